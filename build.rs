@@ -15,6 +15,22 @@ fn main() {
     println!("cargo:rerun-if-changed=wa-bridge/go.mod");
     println!("cargo:rerun-if-changed=wa-bridge/go.sum");
 
+    // The commit being built, reported by /api/version
+    for git_path in [".git/HEAD", ".git/refs/heads"] {
+        if PathBuf::from(git_path).exists() {
+            println!("cargo:rerun-if-changed={}", git_path);
+        }
+    }
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    if let Some(hash) = git_hash.as_deref().map(str::trim).filter(|h| !h.is_empty()) {
+        println!("cargo:rustc-env=WA_GIT_HASH={}", hash);
+    }
+
     // Skip Go build if explicitly disabled
     if env::var("SKIP_GO_BUILD").is_ok() {
         println!("cargo:warning=Skipping Go bridge build (SKIP_GO_BUILD is set)");
@@ -0,0 +1,220 @@
+//! Structured access logging for the web server.
+//!
+//! With `--access-log`, every request is logged under the `access_log` target
+//! with its method, route template, status, latency, auth principal and body
+//! sizes. The UI polls `/api/status` and `/api/qr` constantly, so successful
+//! responses from those are only sampled. Query strings on the auth and OAuth
+//! routes are redacted, and request and response bodies are never logged.
+
+use axum::{
+    body::HttpBody,
+    extract::{MatchedPath, Request, State},
+    http::header,
+    middleware::Next,
+    response::Response,
+};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::info;
+
+use crate::web::AppState;
+
+/// Endpoints the UI polls; only one in this many successful requests is logged
+const POLL_SAMPLE_RATE: u64 = 50;
+
+/// Routes polled by the UI
+const POLLING_PATHS: &[&str] = &["/api/status", "/api/qr"];
+
+/// Path prefixes whose query strings carry passwords, codes or tokens
+const SENSITIVE_PREFIXES: &[&str] = &["/api/auth", "/oauth/"];
+
+/// Placeholder logged instead of a sensitive value
+const REDACTED: &str = "[redacted]";
+
+/// Who made a request, set as a response extension by handlers that
+/// authenticate on their own (e.g. the MCP endpoint)
+#[derive(Debug, Clone)]
+pub struct Principal(pub String);
+
+/// Access log settings and sampling state
+#[derive(Default)]
+pub struct AccessLog {
+    enabled: AtomicBool,
+    polls: AtomicU64,
+}
+
+impl AccessLog {
+    /// Whether requests are logged
+    pub fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Whether this successful polling request is the one in
+    /// `POLL_SAMPLE_RATE` that gets logged
+    fn sample_poll(&self) -> bool {
+        self.polls
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(POLL_SAMPLE_RATE)
+    }
+}
+
+/// Whether a path's query string must not be logged
+fn is_sensitive(path: &str) -> bool {
+    SENSITIVE_PREFIXES
+        .iter()
+        .any(|prefix| path.starts_with(prefix))
+}
+
+/// Middleware logging each request once its response is ready
+pub async fn log_requests(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let start = Instant::now();
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    // Unmatched requests (static files) have no template, so log the path
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|matched| matched.as_str().to_string())
+        .unwrap_or_else(|| path.clone());
+    let query = match request.uri().query() {
+        Some(_) if is_sensitive(&path) => REDACTED.to_string(),
+        Some(query) => query.to_string(),
+        None => String::new(),
+    };
+    let request_bytes = request.body().size_hint().exact().unwrap_or(0);
+    let bearer = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(str::to_string);
+
+    let response = next.run(request).await;
+
+    let status = response.status();
+    if POLLING_PATHS.contains(&route.as_str())
+        && status.is_success()
+        && !state.access_log.sample_poll()
+    {
+        return response;
+    }
+
+    let principal = match response.extensions().get::<Principal>() {
+        Some(Principal(principal)) => principal.clone(),
+        None => match bearer {
+            // Named sessions are told apart, e.g. "web:Ana"
+            Some(token) => match state.auth_tokens.read().await.get(&token) {
+                Some(Some(name)) => format!("web:{}", name),
+                Some(None) => "web".to_string(),
+                None => "anonymous".to_string(),
+            },
+            None => "anonymous".to_string(),
+        },
+    };
+    let response_bytes = response.body().size_hint().exact();
+
+    info!(
+        target: "access_log",
+        method = %method,
+        route = %route,
+        query = %query,
+        status = status.as_u16(),
+        latency_ms = start.elapsed().as_millis() as u64,
+        principal = %principal,
+        request_bytes,
+        response_bytes,
+        "request"
+    );
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::send_guard::LanguageGuardConfig;
+    use crate::storage::test_store;
+    use axum::body::Body;
+    use std::sync::Mutex;
+    use tower::ServiceExt;
+
+    /// Collects formatted log output
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_access_log_redacts_sensitive_queries() {
+        let (store, dir) = test_store();
+        let state = AppState::new(
+            store,
+            dir.path().to_path_buf(),
+            dir.path().to_path_buf(),
+            None,
+            Some("secret".to_string()),
+            None,
+            LanguageGuardConfig::default(),
+        );
+        state.access_log.set_enabled(true);
+
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_env_filter("access_log=info")
+            .with_ansi(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let router = crate::web::create_router(state.clone());
+        let send = |uri: &str| {
+            let router = router.clone();
+            let request = axum::http::Request::builder()
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap();
+            async move { router.oneshot(request).await.unwrap() }
+        };
+
+        send("/api/auth/check?password=hunter2").await;
+        send("/api/contacts/abc@s.whatsapp.net/link?format=qr").await;
+        // Only the first of a run of polls is logged
+        send("/api/status").await;
+        send("/api/status").await;
+
+        let logs = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = logs.lines().collect();
+        assert_eq!(lines.len(), 3, "{}", logs);
+
+        assert!(lines[0].contains("route=/api/auth/check"));
+        assert!(lines[0].contains("query=[redacted]"));
+        assert!(!logs.contains("hunter2"));
+
+        // Other routes log their template and query
+        assert!(lines[1].contains("route=/api/contacts/:contact_id/link"));
+        assert!(lines[1].contains("query=format=qr"));
+        assert!(lines[1].contains("principal=anonymous"));
+
+        assert!(lines[2].contains("route=/api/status"));
+        assert!(lines[2].contains("status=200"));
+    }
+}
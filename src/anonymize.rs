@@ -0,0 +1,280 @@
+//! Pseudonymization for sharing conversation data.
+//!
+//! Every identity is replaced by a placeholder derived from a salted hash of
+//! it, so the same person gets the same placeholder throughout one export
+//! (or for one MCP client, whose salt is kept) but nothing links
+//! placeholders across salts. Known names are also scrubbed from message
+//! text, along with anything that looks like a JID, email address or phone
+//! number.
+
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Names shorter than this aren't scrubbed from text: too many ordinary
+/// words would go with them
+const MIN_NAME_CHARS: usize = 3;
+
+/// Replaces identities with stable placeholders
+pub struct Anonymizer {
+    salt: String,
+    /// JIDs or email addresses, in one pass so JIDs (tried first, as
+    /// "@s.whatsapp.net" looks like a domain) aren't taken for emails
+    address_re: Regex,
+    /// Candidate phone numbers: an optional international prefix then digits
+    /// with the separators people write them with. `is_phone` has the final say.
+    phone_re: Regex,
+    /// Dates written like phone numbers, which aren't scrubbed
+    date_re: Regex,
+    /// Known names, longest first, matched case-insensitively in text
+    names: Option<Regex>,
+    /// Each way a name can appear (lowercased) to the full name it stands for
+    name_keys: HashMap<String, String>,
+}
+
+impl Anonymizer {
+    pub fn new(salt: &str) -> Self {
+        Self {
+            salt: salt.to_string(),
+            address_re: Regex::new(concat!(
+                r"(?P<jid>\b[0-9]{5,}(?::[0-9]+)?@(?:s\.whatsapp\.net|c\.us|g\.us|lid|broadcast|newsletter)\b)",
+                r"|[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}",
+            ))
+            .unwrap(),
+            phone_re: Regex::new(r"(?:\+|\b00|\()?[0-9][0-9 ().\-]{5,20}[0-9]").unwrap(),
+            date_re: Regex::new(
+                r"^(?:[0-9]{4}[./-][0-9]{1,2}[./-][0-9]{1,2}|[0-9]{1,2}[./-][0-9]{1,2}[./-][0-9]{2,4})$",
+            )
+            .unwrap(),
+            names: None,
+            name_keys: HashMap::new(),
+        }
+    }
+
+    /// A fresh salt, for an export nothing else should link to
+    pub fn random() -> Self {
+        Self::new(&uuid::Uuid::new_v4().to_string())
+    }
+
+    /// Also scrub these names (and their first names) from text
+    pub fn with_names<'a>(mut self, names: impl IntoIterator<Item = &'a str>) -> Self {
+        for name in names {
+            let full = name.trim_matches(|c: char| !c.is_alphanumeric());
+            if full.chars().count() < MIN_NAME_CHARS || full.chars().all(|c| !c.is_alphabetic()) {
+                continue;
+            }
+            let key = full.to_lowercase();
+            self.name_keys.entry(key.clone()).or_insert(key.clone());
+            if let Some(first) = full.split_whitespace().next() {
+                if first.len() < full.len() && first.chars().count() >= MIN_NAME_CHARS {
+                    self.name_keys.entry(first.to_lowercase()).or_insert(key);
+                }
+            }
+        }
+
+        let mut variants: Vec<&String> = self.name_keys.keys().collect();
+        variants.sort_by(|a, b| b.chars().count().cmp(&a.chars().count()).then(a.cmp(b)));
+        self.names = (!variants.is_empty()).then(|| {
+            let alternatives: Vec<String> = variants.iter().map(|v| regex::escape(v)).collect();
+            Regex::new(&format!(r"(?i)\b(?:{})\b", alternatives.join("|"))).unwrap()
+        });
+        self
+    }
+
+    fn hash(&self, kind: &str, value: &str) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt.as_bytes());
+        hasher.update([0]);
+        hasher.update(kind.as_bytes());
+        hasher.update([0]);
+        hasher.update(value.as_bytes());
+        hasher.finalize().into()
+    }
+
+    /// "Person XYZ" for a name, whatever its case or surrounding spaces
+    pub fn person(&self, name: &str) -> String {
+        let key = name.trim().to_lowercase();
+        let key = self.name_keys.get(&key).cloned().unwrap_or(key);
+        let hash = self.hash("name", &key);
+        let letters: String = hash[..3].iter().map(|b| (b'A' + b % 26) as char).collect();
+        format!("Person {}", letters)
+    }
+
+    /// "+XXX...123" for a phone number, by its digits
+    pub fn phone(&self, phone: &str) -> String {
+        let digits: String = phone.chars().filter(char::is_ascii_digit).collect();
+        let hash = self.hash("phone", digits.trim_start_matches('0'));
+        format!(
+            "+XXX...{:03}",
+            u16::from_be_bytes([hash[0], hash[1]]) % 1000
+        )
+    }
+
+    /// The same JID with its user part replaced, keeping the server so the
+    /// kind of chat stays visible
+    pub fn jid(&self, jid: &str) -> String {
+        let (user, server) = jid.split_once('@').unwrap_or((jid, ""));
+        let user = user.split(':').next().unwrap_or(user);
+        let hash = self.hash("jid", user);
+        let id: String = hash[..6].iter().map(|b| format!("{:02x}", b)).collect();
+        if server.is_empty() {
+            id
+        } else {
+            format!("{}@{}", id, server)
+        }
+    }
+
+    /// Text with JIDs, email addresses, phone numbers and known names replaced
+    pub fn scrub(&self, text: &str) -> String {
+        let text =
+            self.address_re
+                .replace_all(text, |caps: &regex::Captures| match caps.name("jid") {
+                    Some(jid) => self.jid(jid.as_str()),
+                    None => "[email]".to_string(),
+                });
+        let text = self.phone_re.replace_all(&text, |caps: &regex::Captures| {
+            let found = &caps[0];
+            if self.is_phone(found) {
+                self.phone(found)
+            } else {
+                found.to_string()
+            }
+        });
+        match &self.names {
+            Some(names) => names
+                .replace_all(&text, |caps: &regex::Captures| self.person(&caps[0]))
+                .into_owned(),
+            None => text.into_owned(),
+        }
+    }
+
+    /// Whether a phone-like match is a phone number: 7 to 15 digits, not a
+    /// date, and written like a number rather than a bare count or amount
+    fn is_phone(&self, candidate: &str) -> bool {
+        let digits = candidate.chars().filter(char::is_ascii_digit).count();
+        if !(7..=15).contains(&digits) || self.date_re.is_match(candidate) {
+            return false;
+        }
+        let international = candidate.starts_with('+') || candidate.starts_with("00");
+        let separated = candidate.contains([' ', '-', '.', '(', ')']);
+        international || separated || digits >= 9
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_placeholders_are_stable_per_salt() {
+        let a = Anonymizer::new("salt-a");
+        let b = Anonymizer::new("salt-b");
+
+        assert_eq!(a.person("Maria Lopez"), a.person("  maria LOPEZ "));
+        assert_ne!(a.person("Maria Lopez"), a.person("Juan Perez"));
+        assert_ne!(a.person("Maria Lopez"), b.person("Maria Lopez"));
+        assert!(a.person("Maria Lopez").starts_with("Person "));
+
+        assert_eq!(a.phone("+44 7911 123456"), a.phone("+447911123456"));
+        assert!(a.phone("+44 7911 123456").starts_with("+XXX..."));
+
+        let jid = a.jid("447911123456@s.whatsapp.net");
+        assert_eq!(jid, a.jid("447911123456:12@s.whatsapp.net"));
+        assert!(jid.ends_with("@s.whatsapp.net"));
+        assert!(!jid.contains("447911123456"));
+        assert!(a.jid("120363041234567890@g.us").ends_with("@g.us"));
+        assert_ne!(jid, b.jid("447911123456@s.whatsapp.net"));
+    }
+
+    #[test]
+    fn test_scrubs_international_phone_formats() {
+        let anon = Anonymizer::new("salt");
+        for phone in [
+            "+44 7911 123456",
+            "+1 (202) 555-0143",
+            "+1-202-555-0143",
+            "(202) 555-0143",
+            "0044 20 7946 0958",
+            "+34 612.345.678",
+            "+33 6 12 34 56 78",
+            "+49 30 901820",
+            "+919876543210",
+            "612345678",
+            "07911 123456",
+        ] {
+            let text = format!("call me on {} tonight", phone);
+            let scrubbed = anon.scrub(&text);
+            assert_eq!(
+                scrubbed.matches(char::is_numeric).count(),
+                3,
+                "{} -> {}",
+                text,
+                scrubbed
+            );
+            assert!(
+                scrubbed.starts_with("call me on +XXX..."),
+                "{} -> {}",
+                text,
+                scrubbed
+            );
+            assert!(scrubbed.ends_with(" tonight"), "{} -> {}", text, scrubbed);
+        }
+    }
+
+    #[test]
+    fn test_leaves_numbers_that_arent_phones() {
+        let anon = Anonymizer::new("salt");
+        for text in [
+            "see you on 2024-01-15 at 10:30",
+            "the party is 15/01/2024",
+            "it costs 1500 euros",
+            "order 123456 is late",
+            "flight 3.5 hours",
+        ] {
+            assert_eq!(anon.scrub(text), text);
+        }
+    }
+
+    #[test]
+    fn test_scrubs_jids_and_emails() {
+        let anon = Anonymizer::new("salt");
+        let scrubbed = anon.scrub(
+            "Forwarded from 447911123456@s.whatsapp.net, reply to maria.lopez+work@example.co.uk",
+        );
+        assert_eq!(
+            scrubbed,
+            format!(
+                "Forwarded from {}, reply to [email]",
+                anon.jid("447911123456@s.whatsapp.net")
+            )
+        );
+    }
+
+    #[test]
+    fn test_scrubs_names_mid_sentence() {
+        let anon =
+            Anonymizer::new("salt").with_names(["Maria Lopez", "José", "Jo", "❤️ Mum ❤️", "12345"]);
+        let maria = anon.person("Maria Lopez");
+
+        assert_eq!(
+            anon.scrub("I saw maria lopez yesterday, and Maria said hi to José!"),
+            format!(
+                "I saw {} yesterday, and {} said hi to {}!",
+                maria,
+                maria,
+                anon.person("José")
+            )
+        );
+        // Only whole words: names inside other words stay
+        assert_eq!(anon.scrub("Mariachi band"), "Mariachi band");
+        // Names too short to scrub safely, and emoji around names
+        assert_eq!(
+            anon.scrub("Jo told Mum"),
+            format!("Jo told {}", anon.person("Mum"))
+        );
+        // The full name wins over the first name
+        assert_eq!(anon.scrub("MARIA LOPEZ"), maria);
+        // Without names, only the patterns are scrubbed
+        assert_eq!(Anonymizer::new("salt").scrub("Maria"), "Maria");
+    }
+}
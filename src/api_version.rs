@@ -0,0 +1,190 @@
+//! Which API the web server speaks, and which clients may still use it.
+//!
+//! Every response carries the API version in `X-API-Version`. The web UI
+//! sends the API version it was built against in `X-Client-Version`
+//! ("<major>" or "<major>.<minor>"); the versions seen are counted, and a
+//! client whose major version the server no longer supports can still read
+//! but gets a 409 "update required" for anything that changes state, so a
+//! cached page can't half-work against a newer server.
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderName, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+use crate::bridge::protocol::PROTOCOL_VERSION;
+use crate::web::AppState;
+
+/// Version of the web API, raised when a change breaks existing clients
+pub const API_VERSION: u32 = 1;
+
+/// Oldest client API version still served
+pub const MIN_CLIENT_API_VERSION: u32 = 1;
+
+pub const API_VERSION_HEADER: HeaderName = HeaderName::from_static("x-api-version");
+pub const CLIENT_VERSION_HEADER: HeaderName = HeaderName::from_static("x-client-version");
+
+/// Most distinct client versions counted; any others count as "other"
+const MAX_COUNTED_VERSIONS: usize = 32;
+
+/// Changes that stay allowed for outdated clients, so they can still sign in
+const UNGATED_PREFIXES: &[&str] = &["/api/auth"];
+
+/// What `/api/version` reports
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionInfo {
+    /// The crate version
+    pub version: &'static str,
+    /// Commit the server was built from, if it was built from a git checkout
+    pub git_hash: Option<&'static str>,
+    pub api_version: u32,
+    pub min_client_api_version: u32,
+    /// Version of the protocol spoken with the WhatsApp bridge
+    pub bridge_protocol_version: u32,
+    /// Requests seen per declared client version since startup
+    pub client_versions: BTreeMap<String, u64>,
+}
+
+/// Requests counted per declared client version
+#[derive(Default)]
+pub struct ClientVersions {
+    counts: Mutex<BTreeMap<String, u64>>,
+}
+
+impl ClientVersions {
+    /// Count a request from a client declaring `version`, logging the first
+    /// one from each version
+    pub fn record(&self, version: &str) {
+        let mut counts = self.counts.lock().unwrap();
+        let key = if counts.contains_key(version) || counts.len() < MAX_COUNTED_VERSIONS {
+            version
+        } else {
+            "other"
+        };
+        let count = counts.entry(key.to_string()).or_insert(0);
+        if *count == 0 {
+            info!("First request from a client declaring API version {}", key);
+        }
+        *count += 1;
+    }
+
+    pub fn counts(&self) -> BTreeMap<String, u64> {
+        self.counts.lock().unwrap().clone()
+    }
+}
+
+/// What `/api/version` reports, with the client versions counted so far
+pub fn version_info(clients: &ClientVersions) -> VersionInfo {
+    VersionInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: option_env!("WA_GIT_HASH"),
+        api_version: API_VERSION,
+        min_client_api_version: MIN_CLIENT_API_VERSION,
+        bridge_protocol_version: PROTOCOL_VERSION,
+        client_versions: clients.counts(),
+    }
+}
+
+/// Whether a client declaring `version` is served. Versions that don't
+/// start with a number say nothing and are let through.
+pub fn is_compatible(version: &str) -> bool {
+    let major = version.trim().split('.').next().unwrap_or_default();
+    match major.parse::<u32>() {
+        Ok(major) => (MIN_CLIENT_API_VERSION..=API_VERSION).contains(&major),
+        Err(_) => true,
+    }
+}
+
+/// Count the client's declared version, refuse changes from clients the
+/// server no longer supports, and add the API version to the response
+pub async fn check_client_version(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let client_version = request
+        .headers()
+        .get(CLIENT_VERSION_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let path = request.uri().path();
+    let mutating = !matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) && path.starts_with("/api/")
+        && !UNGATED_PREFIXES
+            .iter()
+            .any(|prefix| path.starts_with(prefix));
+
+    let mut response = match client_version {
+        Some(version) => {
+            state.client_versions.record(&version);
+            if mutating && !is_compatible(&version) {
+                warn!(
+                    "Refused {} {} from a client on API version {} (server is on {})",
+                    request.method(),
+                    path,
+                    version,
+                    API_VERSION
+                );
+                update_required(&version)
+            } else {
+                next.run(request).await
+            }
+        }
+        None => next.run(request).await,
+    };
+    response
+        .headers_mut()
+        .insert(API_VERSION_HEADER, HeaderValue::from(API_VERSION));
+    response
+}
+
+fn update_required(client_version: &str) -> Response {
+    (
+        StatusCode::CONFLICT,
+        Json(serde_json::json!({
+            "error": "This page is out of date with the server; reload it to update",
+            "updateRequired": true,
+            "apiVersion": API_VERSION,
+            "clientVersion": client_version,
+        })),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_version_compatibility() {
+        assert!(is_compatible("1"));
+        assert!(is_compatible("1.7"));
+        assert!(is_compatible(" 1.0.3 "));
+        assert!(!is_compatible("0.9"));
+        assert!(!is_compatible(&(API_VERSION + 1).to_string()));
+        // Nothing to go on
+        assert!(is_compatible("dev"));
+        assert!(is_compatible(""));
+
+        let clients = ClientVersions::default();
+        clients.record("1");
+        clients.record("1");
+        for i in 0..MAX_COUNTED_VERSIONS {
+            clients.record(&format!("garbage-{}", i));
+        }
+        let counts = clients.counts();
+        assert_eq!(counts["1"], 2);
+        assert_eq!(counts["other"], 1);
+        assert_eq!(counts.len(), MAX_COUNTED_VERSIONS + 1);
+    }
+}
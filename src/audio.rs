@@ -0,0 +1,316 @@
+//! Duration and waveform of audio messages.
+//!
+//! The conversation view draws voice notes as a WhatsApp-style waveform
+//! with their length, and the bridge's `duration_seconds` is often missing,
+//! so both are worked out from the audio itself. Codecs symphonia can decode
+//! get a waveform of real sample peaks. Voice notes are Ogg Opus, which it
+//! can demux but not decode: their duration comes from the container and
+//! their waveform from packet sizes (Opus is variable bitrate, so louder
+//! speech takes bigger packets), and they're flagged as metadata-only. Audio
+//! that can't be read at all keeps the bridge's duration, with no waveform.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use tracing::debug;
+
+use crate::storage::StoredMessage;
+
+/// Number of bars in a waveform
+pub const WAVEFORM_BUCKETS: usize = 64;
+
+/// Height of the tallest bar of a waveform
+const WAVEFORM_MAX: u8 = 100;
+
+/// Duration and waveform of an audio message
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioInfo {
+    pub duration_ms: i64,
+    /// `WAVEFORM_BUCKETS` bar heights from 0 to 100, empty if unknown
+    pub waveform: Vec<u8>,
+    /// The samples couldn't be decoded, so the duration is the container's
+    /// (or the bridge's) and the waveform at best an estimate
+    pub metadata_only: bool,
+}
+
+/// Work out the duration and waveform of base64 audio data, or None if it
+/// can't be read
+pub fn analyze(media_data: &str, mime_type: Option<&str>) -> Option<AudioInfo> {
+    // Media sent from the web UI may be a data URL
+    let encoded = media_data
+        .split_once(";base64,")
+        .map_or(media_data, |(_, data)| data);
+    let bytes = STANDARD.decode(encoded.trim()).ok()?;
+
+    let mut hint = Hint::new();
+    if let Some(mime_type) = mime_type {
+        // "audio/ogg; codecs=opus" -> "audio/ogg"
+        hint.mime_type(mime_type.split(';').next().unwrap_or(mime_type).trim());
+    }
+    let source = MediaSourceStream::new(Box::new(Cursor::new(bytes)), Default::default());
+    let probed = match symphonia::default::get_probe().format(
+        &hint,
+        source,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    ) {
+        Ok(probed) => probed,
+        Err(e) => {
+            debug!("Unreadable audio: {}", e);
+            return None;
+        }
+    };
+    let mut format = probed.format;
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)?
+        .clone();
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| debug!("Audio not decodable, using metadata only: {}", e))
+        .ok();
+
+    // One value per packet: its peak sample if decoded, else its size
+    let mut levels = Vec::new();
+    let mut decoded_frames = 0u64;
+    let mut packet_frames = 0u64;
+    let mut samples: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(_)) => break,
+            Err(e) => {
+                debug!("Stopped reading audio: {}", e);
+                break;
+            }
+        };
+        if packet.track_id() != track.id {
+            continue;
+        }
+        packet_frames += packet.dur;
+
+        let Some(active) = decoder.as_mut() else {
+            levels.push(packet.buf().len() as f32);
+            continue;
+        };
+        match active.decode(&packet) {
+            Ok(buffer) => {
+                let spec = *buffer.spec();
+                let frames = buffer.frames();
+                let buf = samples
+                    .get_or_insert_with(|| SampleBuffer::new(buffer.capacity() as u64, spec));
+                if buf.capacity() < frames * spec.channels.count() {
+                    *buf = SampleBuffer::new(buffer.capacity() as u64, spec);
+                }
+                buf.copy_interleaved_ref(buffer);
+                decoded_frames += frames as u64;
+                levels.push(buf.samples().iter().fold(0f32, |peak, s| peak.max(s.abs())));
+            }
+            // A corrupt packet is skipped; the rest may still decode
+            Err(SymphoniaError::DecodeError(e)) => debug!("Skipping audio packet: {}", e),
+            Err(e) => {
+                debug!("Audio decoding failed, using metadata only: {}", e);
+                decoder = None;
+                levels.clear();
+                break;
+            }
+        }
+    }
+
+    let metadata_only = decoder.is_none();
+    let frames = if metadata_only {
+        // The container's frame count leaves out the encoder delay
+        track.codec_params.n_frames.unwrap_or(packet_frames)
+    } else {
+        decoded_frames
+    };
+    let duration_ms = match (track.codec_params.time_base, track.codec_params.sample_rate) {
+        (Some(time_base), _) if metadata_only => {
+            let time = time_base.calc_time(frames);
+            (time.seconds as f64 * 1000.0 + time.frac * 1000.0).round() as i64
+        }
+        (_, Some(rate)) if rate > 0 => (frames as f64 * 1000.0 / rate as f64).round() as i64,
+        _ => return None,
+    };
+    if duration_ms <= 0 {
+        return None;
+    }
+
+    Some(AudioInfo {
+        duration_ms,
+        waveform: waveform(&levels, metadata_only),
+        metadata_only,
+    })
+}
+
+/// Bucket per-packet levels into `WAVEFORM_BUCKETS` bars scaled to 0-100,
+/// taking the loudest packet in each. Packet sizes are measured from the
+/// smallest, which is about what silence costs.
+fn waveform(levels: &[f32], from_sizes: bool) -> Vec<u8> {
+    if levels.is_empty() {
+        return Vec::new();
+    }
+    let floor = if from_sizes {
+        levels.iter().copied().fold(f32::MAX, f32::min)
+    } else {
+        0.0
+    };
+    let peaks: Vec<f32> = (0..WAVEFORM_BUCKETS)
+        .map(|i| {
+            let start = i * levels.len() / WAVEFORM_BUCKETS;
+            let end = ((i + 1) * levels.len() / WAVEFORM_BUCKETS).max(start + 1);
+            levels[start..end.min(levels.len())]
+                .iter()
+                .fold(0f32, |peak, level| peak.max(level - floor))
+        })
+        .collect();
+    let loudest = peaks.iter().copied().fold(0f32, f32::max);
+    peaks
+        .iter()
+        .map(|peak| {
+            if loudest > 0.0 {
+                (peak / loudest * WAVEFORM_MAX as f32).round() as u8
+            } else {
+                0
+            }
+        })
+        .collect()
+}
+
+/// Whether a message's content is audio carrying its media data
+fn audio_media(content: &serde_json::Value) -> Option<&str> {
+    if content.get("type").and_then(|t| t.as_str()) != Some("audio") {
+        return None;
+    }
+    content
+        .get("media_data")
+        .or_else(|| content.get("mediaData"))
+        .and_then(|v| v.as_str())
+}
+
+/// Work out the duration and waveform of an audio message that doesn't have
+/// them yet, decoding on a blocking thread so message ingestion isn't held up
+pub async fn attach(message: &mut StoredMessage) {
+    if message.audio.is_some() {
+        return;
+    }
+    let Some(content) = message.content.as_ref() else {
+        return;
+    };
+    let Some(media_data) = audio_media(content).map(str::to_string) else {
+        return;
+    };
+    let mime_type = content
+        .get("mime_type")
+        .and_then(|m| m.as_str())
+        .map(str::to_string);
+    let bridge_duration = content.get("duration_seconds").and_then(|d| d.as_i64());
+
+    let analyzed = tokio::task::spawn_blocking(move || analyze(&media_data, mime_type.as_deref()))
+        .await
+        .ok()
+        .flatten();
+    message.audio = analyzed.or_else(|| {
+        bridge_duration.map(|seconds| AudioInfo {
+            duration_ms: seconds * 1000,
+            waveform: Vec::new(),
+            metadata_only: true,
+        })
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::text_message;
+
+    const VOICE_NOTE: &[u8] = include_bytes!("../tests/fixtures/voice_note.opus");
+
+    /// A mono 16-bit WAV: `silent_ms` of silence, then a full-scale square wave
+    fn wav(silent_ms: u32, loud_ms: u32) -> Vec<u8> {
+        let rate = 8000u32;
+        let silent = rate * silent_ms / 1000;
+        let total = silent + rate * loud_ms / 1000;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + total * 2).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&rate.to_le_bytes());
+        bytes.extend_from_slice(&(rate * 2).to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&(total * 2).to_le_bytes());
+        for n in 0..total {
+            let sample: i16 = match n {
+                n if n < silent => 0,
+                n if n % 20 < 10 => i16::MAX,
+                _ => -i16::MAX,
+            };
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_opus_voice_note_uses_container_metadata() {
+        let info = analyze(&STANDARD.encode(VOICE_NOTE), Some("audio/ogg; codecs=opus")).unwrap();
+        assert!(info.metadata_only);
+        assert!((info.duration_ms - 3000).abs() <= 1000, "{:?}", info);
+        assert_eq!(info.waveform.len(), WAVEFORM_BUCKETS);
+        // The fixture's first fifth is silent-sized packets
+        assert_eq!(info.waveform[0], 0);
+        assert_eq!(info.waveform.iter().max(), Some(&WAVEFORM_MAX));
+    }
+
+    #[test]
+    fn test_decodable_audio_gets_sample_peaks() {
+        let info = analyze(&STANDARD.encode(wav(1000, 1000)), None).unwrap();
+        assert!(!info.metadata_only);
+        assert!((info.duration_ms - 2000).abs() <= 1000, "{:?}", info);
+        assert_eq!(info.waveform.len(), WAVEFORM_BUCKETS);
+        assert_eq!(info.waveform[0], 0);
+        assert_eq!(info.waveform[WAVEFORM_BUCKETS - 1], WAVEFORM_MAX);
+
+        assert_eq!(analyze("not base64 at all!", None), None);
+        assert_eq!(analyze(&STANDARD.encode(b"OggS garbage"), None), None);
+    }
+
+    #[tokio::test]
+    async fn test_attach_falls_back_to_bridge_duration() {
+        let content = serde_json::json!({
+            "type": "audio",
+            "mime_type": "audio/ogg; codecs=opus",
+            "duration_seconds": 7,
+            "media_data": STANDARD.encode(b"truncated"),
+        });
+        let mut message = StoredMessage {
+            content_type: "Audio".to_string(),
+            content_json: content.to_string(),
+            content: Some(content),
+            original_text: None,
+            ..text_message("voice", "a@s.whatsapp.net", 1, "")
+        };
+        attach(&mut message).await;
+        assert_eq!(
+            message.audio,
+            Some(AudioInfo {
+                duration_ms: 7000,
+                waveform: Vec::new(),
+                metadata_only: true,
+            })
+        );
+    }
+}
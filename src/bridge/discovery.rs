@@ -0,0 +1,414 @@
+//! Finding the wa-bridge binary.
+//!
+//! An explicit `--bridge-path` (or `$WA_BRIDGE_PATH`) is the only candidate
+//! when given. Otherwise the bridge is looked for next to the executable, in
+//! the data directory, in the working directory and its build outputs, then
+//! on `$PATH`, under both its plain name and a platform-suffixed one such as
+//! `wa-bridge-linux-arm64`. Each candidate must be an executable built for
+//! this OS and architecture, checked from its ELF or Mach-O header. When
+//! nothing fits, the error lists every location with why it was rejected.
+
+use std::ffi::OsString;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+/// Base name of the bridge binary
+const BRIDGE_NAME: &str = "wa-bridge";
+
+/// Bytes of the file header needed to identify its platform
+const HEADER_LEN: usize = 20;
+
+/// ELF `e_machine` values
+const EM_386: u16 = 3;
+const EM_ARM: u16 = 40;
+const EM_X86_64: u16 = 62;
+const EM_AARCH64: u16 = 183;
+const EM_RISCV: u16 = 243;
+
+/// Mach-O CPU types
+const CPU_TYPE_X86_64: u32 = 0x0100_0007;
+const CPU_TYPE_ARM64: u32 = 0x0100_000c;
+
+/// Where to look for the bridge
+#[derive(Debug, Clone, Default)]
+pub struct BridgeSearch {
+    /// `--bridge-path` / `$WA_BRIDGE_PATH`; when set nothing else is tried
+    pub explicit: Option<PathBuf>,
+    /// Directory of the running executable
+    pub exe_dir: Option<PathBuf>,
+    pub data_dir: Option<PathBuf>,
+    pub current_dir: Option<PathBuf>,
+    /// Value of `$PATH`
+    pub path_var: Option<OsString>,
+}
+
+/// One place the bridge was looked for
+#[derive(Debug, Clone)]
+pub struct BridgeCandidate {
+    pub path: PathBuf,
+    /// Why this location was tried, e.g. "data directory"
+    pub source: &'static str,
+    /// None if the binary is usable, else why not
+    pub problem: Option<String>,
+}
+
+/// Every location tried, in order; the first usable one is the bridge
+#[derive(Debug, Clone)]
+pub struct BridgeDiscovery {
+    pub candidates: Vec<BridgeCandidate>,
+}
+
+impl BridgeSearch {
+    /// Search the usual places for this process
+    pub fn new(explicit: Option<&Path>, data_dir: Option<&Path>) -> Self {
+        Self {
+            explicit: explicit.map(Path::to_path_buf),
+            exe_dir: std::env::current_exe()
+                .ok()
+                .and_then(|exe| exe.parent().map(Path::to_path_buf)),
+            data_dir: data_dir.map(Path::to_path_buf),
+            current_dir: std::env::current_dir().ok(),
+            path_var: std::env::var_os("PATH"),
+        }
+    }
+
+    /// Candidate paths in search order
+    fn candidates(&self) -> Vec<(PathBuf, &'static str)> {
+        if let Some(explicit) = &self.explicit {
+            return vec![(explicit.clone(), "--bridge-path / WA_BRIDGE_PATH")];
+        }
+
+        let mut dirs: Vec<(PathBuf, &'static str)> = Vec::new();
+        if let Some(dir) = &self.exe_dir {
+            dirs.push((dir.clone(), "next to the executable"));
+        }
+        if let Some(dir) = &self.data_dir {
+            dirs.push((dir.clone(), "data directory"));
+        }
+        if let Some(dir) = &self.current_dir {
+            dirs.push((dir.clone(), "working directory"));
+            for profile in ["debug", "release"] {
+                dirs.push((dir.join("target").join(profile), "build output"));
+            }
+            dirs.push((dir.join(BRIDGE_NAME), "bridge source directory"));
+        }
+        if let Some(path_var) = &self.path_var {
+            dirs.extend(std::env::split_paths(path_var).map(|dir| (dir, "$PATH")));
+        }
+
+        let names = binary_names();
+        let mut candidates: Vec<(PathBuf, &'static str)> = Vec::new();
+        for (dir, source) in dirs {
+            for name in &names {
+                let path = dir.join(name);
+                if !candidates.iter().any(|(seen, _)| *seen == path) {
+                    candidates.push((path, source));
+                }
+            }
+        }
+        candidates
+    }
+
+    /// Check every candidate location
+    pub fn run(&self) -> BridgeDiscovery {
+        BridgeDiscovery {
+            candidates: self
+                .candidates()
+                .into_iter()
+                .map(|(path, source)| BridgeCandidate {
+                    problem: check_binary(&path).err(),
+                    path,
+                    source,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl BridgeDiscovery {
+    /// The bridge to use, if any location had a usable one
+    pub fn found(&self) -> Option<&Path> {
+        self.candidates
+            .iter()
+            .find(|c| c.problem.is_none())
+            .map(|c| c.path.as_path())
+    }
+
+    /// The bridge to use, or an error listing every location tried
+    pub fn into_result(self) -> Result<PathBuf> {
+        if let Some(path) = self.found() {
+            return Ok(path.to_path_buf());
+        }
+        anyhow::bail!(
+            "Could not find a usable wa-bridge binary for {}. Looked in:\n{}\n\
+             Build it with `cd wa-bridge && go build -o wa-bridge .`, or point --bridge-path at it.",
+            platform_suffix(),
+            self.report()
+        )
+    }
+
+    /// One line per location tried, marking the one used
+    pub fn report(&self) -> String {
+        let found = self.found();
+        self.candidates
+            .iter()
+            .map(|c| match &c.problem {
+                None if Some(c.path.as_path()) == found => {
+                    format!("  ✓ {} ({}) - using this", c.path.display(), c.source)
+                }
+                None => format!("  ✓ {} ({})", c.path.display(), c.source),
+                Some(problem) => format!("  ✗ {} ({}): {}", c.path.display(), c.source, problem),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Find the wa-bridge binary, checking `explicit` alone if given
+pub fn find_bridge_binary(explicit: Option<&Path>, data_dir: Option<&Path>) -> Result<PathBuf> {
+    BridgeSearch::new(explicit, data_dir).run().into_result()
+}
+
+/// This platform in Go's GOOS-GOARCH naming, as used by cross-compiled builds
+pub fn platform_suffix() -> String {
+    let os = match std::env::consts::OS {
+        "macos" => "darwin",
+        os => os,
+    };
+    let arch = match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "386",
+        "riscv64" => "riscv64",
+        arch => arch,
+    };
+    format!("{}-{}", os, arch)
+}
+
+/// File names the bridge may have here: plain, then platform-suffixed
+fn binary_names() -> Vec<String> {
+    let suffix = std::env::consts::EXE_SUFFIX;
+    vec![
+        format!("{}{}", BRIDGE_NAME, suffix),
+        format!("{}-{}{}", BRIDGE_NAME, platform_suffix(), suffix),
+    ]
+}
+
+/// Check a file is an executable this machine can run
+fn check_binary(path: &Path) -> Result<(), String> {
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err("not found".to_string()),
+        Err(e) => return Err(format!("can't read it: {}", e)),
+    };
+    if !metadata.is_file() {
+        return Err("not a file".to_string());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 == 0 {
+            return Err(format!(
+                "not executable (run `chmod +x {}`)",
+                path.display()
+            ));
+        }
+    }
+
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    std::fs::File::open(path)
+        .and_then(|file| file.take(HEADER_LEN as u64).read_to_end(&mut header))
+        .map_err(|e| format!("can't read it: {}", e))?;
+    check_platform(&header, std::env::consts::OS, std::env::consts::ARCH)
+}
+
+/// Check an executable's header matches an OS and architecture (Rust's
+/// `std::env::consts` names). Scripts and formats without an architecture
+/// in the header are accepted.
+fn check_platform(header: &[u8], os: &str, arch: &str) -> Result<(), String> {
+    if header.starts_with(b"#!") {
+        return Ok(());
+    }
+
+    if header.starts_with(b"\x7fELF") {
+        if os == "macos" || os == "windows" {
+            return Err(format!("built for Linux, not {}", os));
+        }
+        if header.len() < HEADER_LEN {
+            return Err("truncated ELF header".to_string());
+        }
+        let machine = match header[5] {
+            1 => u16::from_le_bytes([header[18], header[19]]),
+            _ => u16::from_be_bytes([header[18], header[19]]),
+        };
+        let built_for = match machine {
+            EM_X86_64 => "x86_64",
+            EM_AARCH64 => "aarch64",
+            EM_ARM => "arm",
+            EM_386 => "x86",
+            EM_RISCV if header[4] == 2 => "riscv64",
+            _ => {
+                return Err(format!(
+                    "built for an unknown architecture (ELF machine {})",
+                    machine
+                ))
+            }
+        };
+        return if built_for == arch {
+            Ok(())
+        } else {
+            Err(format!("built for {}, not {}", built_for, arch))
+        };
+    }
+
+    if header.len() >= 8 && header[..4] == [0xcf, 0xfa, 0xed, 0xfe] {
+        if os != "macos" {
+            return Err(format!("built for macOS, not {}", os));
+        }
+        let built_for = match u32::from_le_bytes([header[4], header[5], header[6], header[7]]) {
+            CPU_TYPE_X86_64 => "x86_64",
+            CPU_TYPE_ARM64 => "aarch64",
+            cpu => {
+                return Err(format!(
+                    "built for an unknown architecture (Mach-O CPU {:#x})",
+                    cpu
+                ))
+            }
+        };
+        return if built_for == arch {
+            Ok(())
+        } else {
+            Err(format!("built for {}, not {}", built_for, arch))
+        };
+    }
+
+    // Universal macOS binaries contain several architectures
+    if header.starts_with(&[0xca, 0xfe, 0xba, 0xbe]) {
+        return if os == "macos" {
+            Ok(())
+        } else {
+            Err(format!("built for macOS, not {}", os))
+        };
+    }
+
+    if header.starts_with(b"MZ") {
+        return if os == "windows" {
+            Ok(())
+        } else {
+            Err(format!("built for Windows, not {}", os))
+        };
+    }
+
+    Err("not a recognised executable".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An ELF header for a 64-bit little-endian binary of `machine`
+    fn elf(machine: u16) -> Vec<u8> {
+        let mut header = b"\x7fELF\x02\x01\x01".to_vec();
+        header.resize(18, 0);
+        header.extend_from_slice(&machine.to_le_bytes());
+        header
+    }
+
+    #[test]
+    fn test_check_platform() {
+        assert!(check_platform(&elf(EM_X86_64), "linux", "x86_64").is_ok());
+        assert!(check_platform(&elf(EM_AARCH64), "linux", "aarch64").is_ok());
+        assert_eq!(
+            check_platform(&elf(EM_X86_64), "linux", "aarch64"),
+            Err("built for x86_64, not aarch64".to_string())
+        );
+        assert!(check_platform(&elf(EM_AARCH64), "macos", "aarch64").is_err());
+
+        let mut macho = vec![0xcf, 0xfa, 0xed, 0xfe];
+        macho.extend_from_slice(&CPU_TYPE_ARM64.to_le_bytes());
+        assert!(check_platform(&macho, "macos", "aarch64").is_ok());
+        assert!(check_platform(&macho, "macos", "x86_64").is_err());
+        assert!(check_platform(&macho, "linux", "aarch64").is_err());
+
+        assert!(check_platform(b"#!/bin/sh\n", "linux", "arm").is_ok());
+        assert!(check_platform(b"hello", "linux", "x86_64").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_search_order_and_report() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = tempfile::tempdir().unwrap();
+        let [exe_dir, data_dir, work_dir, bin_dir] =
+            ["exe", "data", "work", "bin"].map(|name| root.path().join(name));
+        for dir in [&exe_dir, &data_dir, &work_dir, &bin_dir] {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        let install = |path: &Path, contents: &[u8], mode: u32| {
+            std::fs::write(path, contents).unwrap();
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap();
+        };
+        let search = BridgeSearch {
+            explicit: None,
+            exe_dir: Some(exe_dir.clone()),
+            data_dir: Some(data_dir.clone()),
+            current_dir: Some(work_dir.clone()),
+            path_var: Some(std::env::join_paths([&bin_dir]).unwrap()),
+        };
+        let suffixed = format!("wa-bridge-{}", platform_suffix());
+
+        // Nothing anywhere: every location is listed with its reason
+        let err = search.run().into_result().unwrap_err().to_string();
+        assert!(err.contains(&format!(
+            "{} (next to the executable): not found",
+            exe_dir.join("wa-bridge").display()
+        )));
+        assert!(err.contains(&format!(
+            "{} ($PATH): not found",
+            bin_dir.join(&suffixed).display()
+        )));
+
+        // A platform-suffixed build on $PATH is found
+        install(&bin_dir.join(&suffixed), b"#!/bin/sh\n", 0o755);
+        assert_eq!(
+            search.run().found(),
+            Some(bin_dir.join(&suffixed).as_path())
+        );
+
+        // The data directory comes before $PATH, but only a usable binary counts
+        install(&data_dir.join("wa-bridge"), b"#!/bin/sh\n", 0o644);
+        let discovery = search.run();
+        assert_eq!(discovery.found(), Some(bin_dir.join(&suffixed).as_path()));
+        assert!(discovery
+            .report()
+            .contains("(data directory): not executable"));
+
+        let wrong_arch = if std::env::consts::ARCH == "aarch64" {
+            EM_X86_64
+        } else {
+            EM_AARCH64
+        };
+        install(&data_dir.join("wa-bridge"), &elf(wrong_arch), 0o755);
+        let discovery = search.run();
+        assert_eq!(discovery.found(), Some(bin_dir.join(&suffixed).as_path()));
+        assert!(discovery.report().contains("(data directory): built for"));
+
+        install(&data_dir.join("wa-bridge"), b"#!/bin/sh\n", 0o755);
+        assert_eq!(
+            search.run().found(),
+            Some(data_dir.join("wa-bridge").as_path())
+        );
+
+        // An explicit path is the only one tried
+        let explicit = BridgeSearch {
+            explicit: Some(root.path().join("missing")),
+            ..search.clone()
+        };
+        let discovery = explicit.run();
+        assert_eq!(discovery.candidates.len(), 1);
+        assert!(discovery.found().is_none());
+    }
+}
@@ -1,10 +1,12 @@
 //! Bridge module for communicating with the Go wa-bridge subprocess.
 
+pub mod discovery;
 pub mod process;
 pub mod protocol;
 
-pub use process::{default_data_dir, find_bridge_binary, BridgeConfig, BridgeProcess};
+pub use discovery::{find_bridge_binary, BridgeSearch};
+pub use process::{default_data_dir, saved_session, BridgeConfig, BridgeProcess};
 pub use protocol::{
-    BridgeCommand, BridgeEvent, Chat, ChatPresenceState, ConnectionState, Contact, Message,
-    MessageContent,
+    is_channel_jid, unknown_text_fields, BridgeCommand, BridgeEvent, Chat, ChatPresenceState,
+    ConnectionState, Contact, HistoryDepth, Message, MessageContent,
 };
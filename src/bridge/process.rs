@@ -2,16 +2,80 @@
 //!
 //! Handles spawning the wa-bridge binary and communicating via JSON-lines over stdio.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 
 use anyhow::{Context, Result};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::process::{Child, Command};
 use tokio::sync::mpsc;
 
 use super::protocol::{BridgeCommand, BridgeEvent};
 
+/// Longest stdout line accepted from the bridge; longer lines (typically a
+/// huge media payload) are skipped
+const MAX_LINE_BYTES: usize = 32 * 1024 * 1024;
+
+/// Consecutive unparseable lines before an error event is raised
+const MAX_CONSECUTIVE_PARSE_FAILURES: u32 = 5;
+
+/// How much of an unparseable line to include in the log
+const PARSE_ERROR_PREVIEW_CHARS: usize = 200;
+
+/// Result of reading one line from the bridge
+#[derive(Debug, PartialEq)]
+enum LineRead {
+    /// A complete line is in the buffer
+    Line,
+    /// The line exceeded the limit and was discarded (length in bytes)
+    Oversized(usize),
+    /// The stream ended
+    Eof,
+}
+
+/// Read one line into `buf` (without the newline), discarding it instead of
+/// buffering once it grows past `max_bytes`
+async fn read_line_capped<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+    max_bytes: usize,
+) -> std::io::Result<LineRead> {
+    buf.clear();
+    let mut len = 0;
+
+    loop {
+        let available = reader.fill_buf().await?;
+        if available.is_empty() {
+            return Ok(match len {
+                0 => LineRead::Eof,
+                n if n > max_bytes => LineRead::Oversized(n),
+                _ => LineRead::Line,
+            });
+        }
+
+        let (chunk, used, done) = match available.iter().position(|&b| b == b'\n') {
+            Some(i) => (&available[..i], i + 1, true),
+            None => (available, available.len(), false),
+        };
+
+        len += chunk.len();
+        if len <= max_bytes {
+            buf.extend_from_slice(chunk);
+        } else {
+            buf.clear();
+        }
+        reader.consume(used);
+
+        if done {
+            return Ok(if len > max_bytes {
+                LineRead::Oversized(len)
+            } else {
+                LineRead::Line
+            });
+        }
+    }
+}
+
 /// Manages the Go bridge subprocess
 pub struct BridgeProcess {
     child: Child,
@@ -66,7 +130,7 @@ impl BridgeProcess {
         // Spawn task to read stdout (JSON events)
         let event_tx_clone = event_tx.clone();
         tokio::spawn(async move {
-            Self::read_events(stdout, event_tx_clone).await;
+            Self::read_events(BufReader::new(stdout), event_tx_clone, MAX_LINE_BYTES).await;
         });
 
         // Spawn task to read stderr (logs)
@@ -82,31 +146,68 @@ impl BridgeProcess {
         Ok(Self { child, command_tx })
     }
 
-    /// Read JSON-line events from stdout
-    async fn read_events(stdout: tokio::process::ChildStdout, event_tx: mpsc::Sender<BridgeEvent>) {
-        let reader = BufReader::new(stdout);
-        let mut lines = reader.lines();
-
-        while let Ok(Some(line)) = lines.next_line().await {
-            if line.trim().is_empty() {
-                continue;
-            }
-
-            match serde_json::from_str::<BridgeEvent>(&line) {
-                Ok(event) => {
-                    if event_tx.send(event).await.is_err() {
-                        // Receiver dropped, exit
-                        break;
+    /// Read JSON-line events from stdout.
+    ///
+    /// Bad lines never end the stream: unparseable ones are logged (and raise
+    /// an error event after several in a row) and oversized ones are skipped.
+    async fn read_events<R: AsyncBufRead + Unpin>(
+        mut reader: R,
+        event_tx: mpsc::Sender<BridgeEvent>,
+        max_line_bytes: usize,
+    ) {
+        let mut line = Vec::new();
+        let mut consecutive_failures = 0;
+
+        loop {
+            let event = match read_line_capped(&mut reader, &mut line, max_line_bytes).await {
+                Ok(LineRead::Eof) => break,
+                Ok(LineRead::Oversized(len)) => BridgeEvent::Log {
+                    level: "warn".to_string(),
+                    message: format!("Skipped oversized bridge event ({} bytes)", len),
+                },
+                Ok(LineRead::Line) if line.trim_ascii().is_empty() => continue,
+                Ok(LineRead::Line) => match BridgeEvent::from_json_line(&line) {
+                    Ok(event) => {
+                        consecutive_failures = 0;
+                        event
                     }
-                }
+                    Err(e) => {
+                        consecutive_failures += 1;
+                        if consecutive_failures == MAX_CONSECUTIVE_PARSE_FAILURES {
+                            let error_event = BridgeEvent::Error {
+                                code: "protocol_error".to_string(),
+                                message: format!(
+                                    "{} consecutive bridge events could not be parsed",
+                                    consecutive_failures
+                                ),
+                            };
+                            if event_tx.send(error_event).await.is_err() {
+                                break;
+                            }
+                        }
+
+                        let preview: String = String::from_utf8_lossy(&line)
+                            .chars()
+                            .take(PARSE_ERROR_PREVIEW_CHARS)
+                            .collect();
+                        BridgeEvent::Log {
+                            level: "warn".to_string(),
+                            message: format!(
+                                "Failed to parse bridge event: {} - line: {}",
+                                e, preview
+                            ),
+                        }
+                    }
+                },
                 Err(e) => {
-                    // Log parse error but continue
-                    let log_event = BridgeEvent::Log {
-                        level: "warn".to_string(),
-                        message: format!("Failed to parse bridge event: {} - line: {}", e, line),
-                    };
-                    let _ = event_tx.send(log_event).await;
+                    tracing::error!("Failed to read from bridge: {}", e);
+                    break;
                 }
+            };
+
+            if event_tx.send(event).await.is_err() {
+                // Receiver dropped, exit
+                break;
             }
         }
     }
@@ -200,49 +301,143 @@ impl BridgeProcess {
     }
 }
 
-/// Find the wa-bridge binary
-pub fn find_bridge_binary() -> Result<PathBuf> {
-    // First, check if it's in the same directory as the executable
-    if let Ok(exe_path) = std::env::current_exe() {
-        let dir = exe_path.parent().unwrap_or(std::path::Path::new("."));
-        let bridge_path = dir.join("wa-bridge");
-        if bridge_path.exists() {
-            return Ok(bridge_path);
-        }
+/// Get the default data directory for storing session and config
+pub fn default_data_dir() -> Result<PathBuf> {
+    let dir = dirs::data_dir()
+        .or_else(dirs::home_dir)
+        .context("Could not determine home directory")?
+        .join("whatsapp-translator");
+
+    Ok(dir)
+}
+
+/// The WhatsApp session the bridge saved in `data_dir`, if it has one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedSession {
+    /// The paired phone number (e.g. "+447700900123"), if it can be read
+    pub phone: Option<String>,
+}
+
+/// Look for a paired session in the bridge's session database. The database
+/// is created before pairing, so only a stored device counts.
+pub fn saved_session(data_dir: &Path) -> Option<SavedSession> {
+    let path = data_dir.join("session.db");
+    if !path.exists() {
+        return None;
+    }
+    let conn = match rusqlite::Connection::open_with_flags(
+        &path,
+        rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
+    ) {
+        Ok(conn) => conn,
+        Err(_) => return Some(SavedSession { phone: None }),
+    };
+    match conn.query_row("SELECT jid FROM whatsmeow_device LIMIT 1", [], |row| {
+        row.get::<_, String>(0)
+    }) {
+        Ok(jid) => Some(SavedSession {
+            phone: phone_from_jid(&jid),
+        }),
+        Err(rusqlite::Error::QueryReturnedNoRows) => None,
+        Err(_) => Some(SavedSession { phone: None }),
     }
+}
+
+/// "+447700900123" from a device JID like "447700900123:12@s.whatsapp.net"
+fn phone_from_jid(jid: &str) -> Option<String> {
+    let user = jid.split(['@', ':', '.']).next()?;
+    (!user.is_empty() && user.bytes().all(|b| b.is_ascii_digit())).then(|| format!("+{}", user))
+}
 
-    // Check current directory
-    let current_dir = std::path::Path::new("./wa-bridge");
-    if current_dir.exists() {
-        return Ok(current_dir.to_path_buf());
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_saved_session() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(saved_session(dir.path()), None);
+
+        // Created but never paired
+        let conn = rusqlite::Connection::open(dir.path().join("session.db")).unwrap();
+        conn.execute("CREATE TABLE whatsmeow_device (jid TEXT PRIMARY KEY)", [])
+            .unwrap();
+        assert_eq!(saved_session(dir.path()), None);
+
+        conn.execute(
+            "INSERT INTO whatsmeow_device (jid) VALUES ('447700900123:12@s.whatsapp.net')",
+            [],
+        )
+        .unwrap();
+        assert_eq!(
+            saved_session(dir.path()),
+            Some(SavedSession {
+                phone: Some("+447700900123".to_string())
+            })
+        );
+        assert_eq!(phone_from_jid("lid-user@lid"), None);
     }
 
-    // Check target directory (for development)
-    for profile in ["debug", "release"] {
-        let dev_path = PathBuf::from(format!("target/{}/wa-bridge", profile));
-        if dev_path.exists() {
-            return Ok(dev_path);
+    async fn read_all(input: &[u8], max_line_bytes: usize) -> Vec<BridgeEvent> {
+        let (tx, mut rx) = mpsc::channel(100);
+        BridgeProcess::read_events(input, tx, max_line_bytes).await;
+        let mut events = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            events.push(event);
         }
+        events
     }
 
-    // Check if wa-bridge directory exists and has the binary
-    let wa_bridge_bin = PathBuf::from("wa-bridge/wa-bridge");
-    if wa_bridge_bin.exists() {
-        return Ok(wa_bridge_bin);
+    fn is_warning(event: &BridgeEvent, text: &str) -> bool {
+        matches!(event, BridgeEvent::Log { level, message } if level == "warn" && message.contains(text))
     }
 
-    anyhow::bail!(
-        "Could not find wa-bridge binary. Please ensure it is built and located in \
-         the same directory as the executable, or run `go build` in the wa-bridge directory."
-    )
-}
+    #[tokio::test]
+    async fn test_reader_survives_bad_lines() {
+        let input = [
+            r#"{"type": "qr", "data": "one"}"#,
+            "panic: runtime error: index out of range",
+            r#"{"type": "newsletter_update", "id": 1}"#,
+            "",
+            r#"{"type": "qr", "data": "two"}"#,
+        ]
+        .join("\n");
+
+        let events = read_all(input.as_bytes(), MAX_LINE_BYTES).await;
+        assert_eq!(events.len(), 4);
+        assert!(matches!(&events[0], BridgeEvent::Qr { data } if data == "one"));
+        assert!(is_warning(&events[1], "Failed to parse bridge event"));
+        assert!(matches!(&events[2], BridgeEvent::Unknown { raw } if raw["id"] == 1));
+        assert!(matches!(&events[3], BridgeEvent::Qr { data } if data == "two"));
+    }
 
-/// Get the default data directory for storing session and config
-pub fn default_data_dir() -> Result<PathBuf> {
-    let dir = dirs::data_dir()
-        .or_else(dirs::home_dir)
-        .context("Could not determine home directory")?
-        .join("whatsapp-translator");
+    #[tokio::test]
+    async fn test_reader_reports_consecutive_parse_failures() {
+        let mut input = "not json\n".repeat(MAX_CONSECUTIVE_PARSE_FAILURES as usize * 2);
+        input.push_str(r#"{"type": "qr", "data": "ok"}"#);
+
+        let events = read_all(input.as_bytes(), MAX_LINE_BYTES).await;
+        let errors = events
+            .iter()
+            .filter(|e| matches!(e, BridgeEvent::Error { code, .. } if code == "protocol_error"))
+            .count();
+        assert_eq!(errors, 1);
+        assert!(matches!(events.last(), Some(BridgeEvent::Qr { .. })));
+    }
 
-    Ok(dir)
+    #[tokio::test]
+    async fn test_reader_skips_oversized_lines() {
+        let giant = format!(r#"{{"type": "qr", "data": "{}"}}"#, "x".repeat(10_000));
+        let input = format!("{}\n{}\n", giant, r#"{"type": "qr", "data": "small"}"#);
+
+        let events = read_all(input.as_bytes(), 1024).await;
+        assert_eq!(events.len(), 2);
+        assert!(is_warning(&events[0], "oversized"));
+        assert!(matches!(&events[1], BridgeEvent::Qr { data } if data == "small"));
+
+        // An oversized final line without a newline is skipped too
+        let events = read_all(giant.as_bytes(), 1024).await;
+        assert_eq!(events.len(), 1);
+        assert!(is_warning(&events[0], "oversized"));
+    }
 }
@@ -3,7 +3,11 @@
 //! The Go bridge sends JSON-line messages to stdout, and receives commands via stdin.
 
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Version of this protocol, raised when a change needs a matching bridge
+/// (new commands are announced through `capabilities` instead)
+pub const PROTOCOL_VERSION: u32 = 1;
 
 /// Events sent from Go bridge to Rust CLI (via stdout)
 #[derive(Debug, Clone, Deserialize)]
@@ -17,6 +21,12 @@ pub enum BridgeEvent {
         phone: String,
         name: String,
         platform: Option<String>,
+        /// Our own LID, if the account has one
+        #[serde(default)]
+        lid: Option<String>,
+        /// Commands the bridge understands (None from bridges too old to say)
+        #[serde(default)]
+        capabilities: Option<Vec<String>>,
     },
 
     /// Connection state changed
@@ -43,6 +53,40 @@ pub enum BridgeEvent {
         error: Option<String>,
     },
 
+    /// Our own profile, in answer to a profile command
+    OwnProfile {
+        request_id: i32,
+        #[serde(default)]
+        jid: Option<String>,
+        #[serde(default)]
+        name: Option<String>,
+        /// "About" text
+        #[serde(default)]
+        status: Option<String>,
+        #[serde(default)]
+        error: Option<String>,
+    },
+
+    /// Result of a check number request
+    NumberCheckResult {
+        request_id: i32,
+        phone: String,
+        jid: Option<String>,
+        #[serde(default)]
+        is_on_whatsapp: bool,
+        error: Option<String>,
+    },
+
+    /// Result of a create group request
+    GroupCreated {
+        request_id: i32,
+        /// JID of the new group
+        #[serde(default)]
+        jid: Option<String>,
+        #[serde(default)]
+        error: Option<String>,
+    },
+
     /// Chat presence (typing/recording indicator)
     ChatPresence {
         chat_id: String,
@@ -50,9 +94,49 @@ pub enum BridgeEvent {
         state: ChatPresenceState,
     },
 
+    /// A subscribed contact came online or went offline
+    Presence {
+        jid: String,
+        is_online: bool,
+        /// When they were last online (unix seconds); absent if hidden
+        #[serde(default)]
+        last_seen: Option<i64>,
+    },
+
     /// Chat marked as read from another device
     MarkAsRead { chat_id: String },
 
+    /// How far a history sync has got; the chunk's messages come before it
+    HistorySyncProgress {
+        chats_done: u32,
+        chats_total: u32,
+        messages_done: u32,
+        /// The last chunk has been imported
+        #[serde(default)]
+        complete: bool,
+    },
+
+    /// A chat's settings changed: group subject or description, or the
+    /// disappearing messages timer. Fields that didn't change are left out.
+    ChatMetadata {
+        chat_id: String,
+        /// Group description ("" when it was removed)
+        #[serde(default)]
+        description: Option<String>,
+        /// Disappearing messages timer in seconds (0 = off)
+        #[serde(default)]
+        ephemeral_duration: Option<u32>,
+        /// New group subject
+        #[serde(default)]
+        subject: Option<String>,
+        /// JID of whoever made the change
+        #[serde(default)]
+        subject_changed_by: Option<String>,
+        /// When the change was made (seconds)
+        #[serde(default)]
+        timestamp: Option<i64>,
+    },
+
     /// Error occurred
     Error { code: String, message: String },
 
@@ -61,6 +145,34 @@ pub enum BridgeEvent {
 
     /// Session logged out (need to re-scan QR)
     LoggedOut { reason: String },
+
+    /// Event with a `type` this version doesn't know (e.g. from a newer bridge)
+    #[serde(skip_deserializing)]
+    Unknown { raw: serde_json::Value },
+}
+
+impl BridgeEvent {
+    /// Parse a JSON line from the bridge.
+    /// Events with an unrecognized `type` become [`BridgeEvent::Unknown`].
+    pub fn from_json_line(line: &[u8]) -> serde_json::Result<Self> {
+        let err = match serde_json::from_slice::<Self>(line) {
+            Ok(event) => return Ok(event),
+            Err(e) => e,
+        };
+
+        // Only re-parse to classify the failure
+        let raw: serde_json::Value = serde_json::from_slice(line)?;
+        match raw.get("type").and_then(|t| t.as_str()) {
+            Some(t)
+                if err
+                    .to_string()
+                    .starts_with(&format!("unknown variant `{}`", t)) =>
+            {
+                Ok(BridgeEvent::Unknown { raw })
+            }
+            _ => Err(err),
+        }
+    }
 }
 
 /// Connection states
@@ -74,6 +186,52 @@ pub enum ConnectionState {
     LoggedOut,
 }
 
+/// How far back history syncs import messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum HistoryDepth {
+    /// No history
+    #[serde(rename = "none")]
+    None,
+    /// The last week
+    #[serde(rename = "recent")]
+    Recent,
+    /// The last three months
+    #[serde(rename = "3months")]
+    ThreeMonths,
+    /// Everything the phone sends
+    #[default]
+    #[serde(rename = "full")]
+    Full,
+}
+
+impl HistoryDepth {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Recent => "recent",
+            Self::ThreeMonths => "3months",
+            Self::Full => "full",
+        }
+    }
+}
+
+impl std::str::FromStr for HistoryDepth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "recent" => Ok(Self::Recent),
+            "3months" => Ok(Self::ThreeMonths),
+            "full" => Ok(Self::Full),
+            _ => Err(format!(
+                "unknown history depth '{}' (expected none, recent, 3months or full)",
+                s
+            )),
+        }
+    }
+}
+
 /// Chat presence states (typing indicators)
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
@@ -120,6 +278,10 @@ pub struct Message {
 
     /// Unread count from WhatsApp (only set on first message of history sync conversations)
     pub unread_count: Option<u32>,
+
+    /// JIDs @-mentioned in the message
+    #[serde(default)]
+    pub mentioned_jids: Vec<String>,
 }
 
 /// Contact information
@@ -133,6 +295,14 @@ pub struct Contact {
 
     /// Display name if known
     pub name: Option<String>,
+
+    /// LID alias when the primary JID is a phone JID
+    #[serde(default)]
+    pub lid: Option<String>,
+
+    /// Phone JID alias when the primary JID is a LID
+    #[serde(default)]
+    pub phone_jid: Option<String>,
 }
 
 /// Chat information
@@ -140,7 +310,16 @@ pub struct Contact {
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Chat {
     /// Private one-on-one chat
-    Private { jid: String, name: Option<String> },
+    Private {
+        jid: String,
+        name: Option<String>,
+        /// LID alias when `jid` is a phone JID
+        #[serde(default)]
+        lid: Option<String>,
+        /// Phone JID alias when `jid` is a LID
+        #[serde(default)]
+        phone_jid: Option<String>,
+    },
 
     /// Group chat
     Group {
@@ -155,11 +334,14 @@ pub enum Chat {
 
     /// Status/Stories
     Status { jid: String },
+
+    /// WhatsApp channel (newsletter), read-only for subscribers
+    Channel { jid: String, name: Option<String> },
 }
 
 /// Message content types
 #[derive(Debug, Clone, Deserialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
+#[serde(remote = "Self", tag = "type", rename_all = "snake_case")]
 pub enum MessageContent {
     /// Plain text message
     Text { body: String },
@@ -173,6 +355,9 @@ pub enum MessageContent {
         file_hash: Option<String>,
         /// Base64 encoded image data
         media_data: Option<String>,
+        /// Sent as view once: the recipient may open it a single time
+        #[serde(default)]
+        view_once: bool,
     },
 
     /// Video message
@@ -183,6 +368,9 @@ pub enum MessageContent {
         duration_seconds: Option<u32>,
         /// Base64 encoded video data
         media_data: Option<String>,
+        /// Sent as view once: the recipient may open it a single time
+        #[serde(default)]
+        view_once: bool,
     },
 
     /// Audio message (including voice notes)
@@ -239,13 +427,93 @@ pub enum MessageContent {
         options: Vec<String>,
     },
 
-    /// Unknown or unsupported message type
-    Unknown { raw_type: String },
+    /// Content of a type this version doesn't support (a newer bridge's, or
+    /// the bridge's own "unknown"), kept whole
+    #[serde(skip_deserializing)]
+    Unknown {
+        raw_type: String,
+        /// The content object exactly as the bridge sent it
+        raw: serde_json::Value,
+    },
+}
+
+impl<'de> Deserialize<'de> for MessageContent {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let raw = serde_json::Value::deserialize(deserializer)?;
+        let err = match MessageContent::deserialize(&raw) {
+            Ok(content) => return Ok(content),
+            Err(e) => e,
+        };
+
+        // Like events, only an unrecognized `type` is kept rather than refused
+        match raw.get("type").and_then(|t| t.as_str()) {
+            Some(t)
+                if err
+                    .to_string()
+                    .starts_with(&format!("unknown variant `{}`", t)) =>
+            {
+                let raw_type = match t {
+                    "unknown" => raw.get("raw_type").and_then(|t| t.as_str()).unwrap_or(t),
+                    _ => t,
+                }
+                .to_string();
+                Ok(MessageContent::Unknown { raw_type, raw })
+            }
+            _ => Err(D::Error::custom(err)),
+        }
+    }
+}
+
+/// Keys whose string values are taken for the text of unsupported content
+const TEXT_LIKE_KEYS: &[&str] = &[
+    "body",
+    "text",
+    "caption",
+    "title",
+    "description",
+    "name",
+    "question",
+    "content",
+    "footer",
+];
+
+/// Text-like fields of content this version can't read (strings under keys
+/// such as "body" or "title", at any depth), shallowest first
+pub fn unknown_text_fields(raw: &serde_json::Value) -> Vec<&str> {
+    let mut found: Vec<&str> = Vec::new();
+    let mut level = vec![raw];
+    while !level.is_empty() {
+        let mut next = Vec::new();
+        for value in level {
+            match value {
+                serde_json::Value::Object(map) => {
+                    // In the keys' order, not the object's
+                    let texts = TEXT_LIKE_KEYS
+                        .iter()
+                        .filter_map(|key| map.get(*key)?.as_str())
+                        .map(str::trim);
+                    for text in texts {
+                        if !text.is_empty() && !found.contains(&text) {
+                            found.push(text);
+                        }
+                    }
+                    next.extend(map.values().filter(|v| !v.is_string()));
+                }
+                serde_json::Value::Array(items) => next.extend(items),
+                _ => {}
+            }
+        }
+        level = next;
+    }
+    found
 }
 
 /// Commands sent from Rust CLI to Go bridge (via stdin)
 /// These will be used in future phases for sending messages, etc.
-#[derive(Debug, Clone, Serialize)]
+/// (Also read back from the local command socket, see `command_socket`.)
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum BridgeCommand {
     /// Send a text message
@@ -298,6 +566,32 @@ pub enum BridgeCommand {
     /// Get profile picture for a JID
     GetProfilePicture { request_id: i32, to: String },
 
+    /// Check whether a phone number (international digits) is on WhatsApp
+    CheckNumber { request_id: i32, phone: String },
+
+    /// Create a group (answered with `GroupCreated`)
+    CreateGroup {
+        request_id: i32,
+        subject: String,
+        participant_jids: Vec<String>,
+    },
+
+    /// Receive `Presence` events for a contact until the next reconnect
+    SubscribePresence { jid: String },
+
+    /// Limit how far back history syncs import messages (answered with
+    /// `HistorySyncProgress` events as syncs arrive)
+    SetHistorySync { depth: HistoryDepth },
+
+    /// Get our own profile (answered with `OwnProfile`)
+    GetOwnProfile { request_id: i32 },
+
+    /// Change our name (answered with `OwnProfile`)
+    SetProfileName { request_id: i32, name: String },
+
+    /// Change our "about" text (answered with `OwnProfile`)
+    SetProfileStatus { request_id: i32, text: String },
+
     /// Disconnect and exit
     Disconnect,
 
@@ -313,6 +607,7 @@ impl Chat {
             Chat::Group { jid, .. } => jid,
             Chat::Broadcast { jid } => jid,
             Chat::Status { jid } => jid,
+            Chat::Channel { jid, .. } => jid,
         }
     }
 
@@ -321,13 +616,38 @@ impl Chat {
         matches!(self, Chat::Group { .. })
     }
 
+    /// Check if this is a channel, which can't be posted to
+    pub fn is_channel(&self) -> bool {
+        matches!(self, Chat::Channel { .. })
+    }
+
+    /// Get the (canonical, alternate) JID pair for a private chat whose
+    /// peer is known under both a phone JID and a LID. The phone JID is
+    /// treated as canonical since older history is stored under it.
+    pub fn identity_pair(&self) -> Option<(&str, &str)> {
+        match self {
+            Chat::Private {
+                jid,
+                phone_jid: Some(phone_jid),
+                ..
+            } if phone_jid != jid => Some((phone_jid, jid)),
+            Chat::Private {
+                jid,
+                lid: Some(lid),
+                ..
+            } if lid != jid => Some((jid, lid)),
+            _ => None,
+        }
+    }
+
     /// Get the display name of the chat
     pub fn display_name(&self) -> String {
         match self {
-            Chat::Private { name, jid } => name.clone().unwrap_or_else(|| extract_phone(jid)),
+            Chat::Private { name, jid, .. } => name.clone().unwrap_or_else(|| extract_phone(jid)),
             Chat::Group { name, jid, .. } => name.clone().unwrap_or_else(|| extract_phone(jid)),
             Chat::Broadcast { jid } => format!("Broadcast: {}", extract_phone(jid)),
             Chat::Status { .. } => "Status".to_string(),
+            Chat::Channel { name, .. } => name.clone().unwrap_or_else(|| "Channel".to_string()),
         }
     }
 }
@@ -363,6 +683,11 @@ impl MessageContent {
     }
 }
 
+/// Check whether a JID belongs to a channel (newsletter)
+pub fn is_channel_jid(jid: &str) -> bool {
+    jid.ends_with("@newsletter")
+}
+
 /// Extract phone number from JID
 fn extract_phone(jid: &str) -> String {
     jid.split('@').next().unwrap_or(jid).to_string()
@@ -372,6 +697,53 @@ fn extract_phone(jid: &str) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_unknown_event_type() {
+        let line = br#"{"type": "newsletter_update", "id": 7}"#;
+        match BridgeEvent::from_json_line(line).unwrap() {
+            BridgeEvent::Unknown { raw } => assert_eq!(raw["id"], 7),
+            other => panic!("expected unknown event, got {:?}", other),
+        }
+
+        // A known type with bad fields is still an error
+        assert!(BridgeEvent::from_json_line(br#"{"type": "qr", "data": 5}"#).is_err());
+        assert!(BridgeEvent::from_json_line(br#"{"data": "x"}"#).is_err());
+        assert!(BridgeEvent::from_json_line(b"panic: runtime error").is_err());
+    }
+
+    #[test]
+    fn test_parse_unknown_content() {
+        let raw = serde_json::json!({
+            "type": "order",
+            "order_id": "ord-1",
+            "summary": {"title": "2 items", "footer": "Pay on delivery"},
+            "items": [{"name": "Mug"}, {"name": "Mug"}, {"text": "  "}]
+        });
+        match serde_json::from_value(raw.clone()).unwrap() {
+            MessageContent::Unknown {
+                raw_type,
+                raw: kept,
+            } => {
+                assert_eq!(raw_type, "order");
+                assert_eq!(kept, raw);
+                assert_eq!(
+                    unknown_text_fields(&kept),
+                    ["2 items", "Pay on delivery", "Mug"]
+                );
+            }
+            other => panic!("expected unknown content, got {:?}", other),
+        }
+
+        // The bridge's own fallback names the type in raw_type
+        let content: MessageContent =
+            serde_json::from_str(r#"{"type": "unknown", "raw_type": "nil"}"#).unwrap();
+        assert!(matches!(content, MessageContent::Unknown { raw_type, .. } if raw_type == "nil"));
+
+        // A known type with bad fields is still an error
+        assert!(serde_json::from_str::<MessageContent>(r#"{"type": "text", "body": 5}"#).is_err());
+        assert!(serde_json::from_str::<MessageContent>(r#"{"body": "hi"}"#).is_err());
+    }
+
     #[test]
     fn test_parse_qr_event() {
         let json = r#"{"type": "qr", "data": "2@ABC123"}"#;
@@ -395,4 +767,43 @@ mod tests {
         let event: BridgeEvent = serde_json::from_str(json).unwrap();
         assert!(matches!(event, BridgeEvent::Message(_)));
     }
+
+    #[test]
+    fn test_history_sync_protocol() {
+        let json = r#"{"type": "history_sync_progress", "chats_done": 3, "chats_total": 40, "messages_done": 512}"#;
+        match serde_json::from_str(json).unwrap() {
+            BridgeEvent::HistorySyncProgress {
+                chats_done: 3,
+                chats_total: 40,
+                messages_done: 512,
+                complete: false,
+            } => {}
+            other => panic!("expected history sync progress, got {:?}", other),
+        }
+
+        let cmd = BridgeCommand::SetHistorySync {
+            depth: HistoryDepth::ThreeMonths,
+        };
+        assert_eq!(
+            serde_json::to_value(&cmd).unwrap(),
+            serde_json::json!({"type": "set_history_sync", "depth": "3months"})
+        );
+        assert_eq!("recent".parse(), Ok(HistoryDepth::Recent));
+        assert!("week".parse::<HistoryDepth>().is_err());
+    }
+
+    #[test]
+    fn test_lid_chat_identity_pair() {
+        let json =
+            r#"{"type": "private", "jid": "98765@lid", "phone_jid": "1234567890@s.whatsapp.net"}"#;
+        let chat: Chat = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            chat.identity_pair(),
+            Some(("1234567890@s.whatsapp.net", "98765@lid"))
+        );
+
+        let json = r#"{"type": "private", "jid": "1234567890@s.whatsapp.net"}"#;
+        let chat: Chat = serde_json::from_str(json).unwrap();
+        assert_eq!(chat.identity_pair(), None);
+    }
 }
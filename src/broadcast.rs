@@ -0,0 +1,502 @@
+//! Sending the same text to several chats.
+//!
+//! Not a WhatsApp broadcast list: every recipient gets a message of its own
+//! through the usual pipeline, translated into its chat's language and
+//! stored with the origin `broadcast:<batch_id>`. A batch runs as a
+//! background job, one message every `DISPATCH_INTERVAL` so a burst of
+//! identical messages doesn't look like spam to WhatsApp, and reports each
+//! recipient's outcome as a `broadcast_progress` event as it goes. Batches
+//! are kept in memory.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tracing::{info, warn};
+
+use crate::bridge::{is_channel_jid, BridgeCommand};
+use crate::jobs::{Job, JobContext, JobProgress, JobQueue};
+use crate::sending::{pending_message_id, OutgoingMessage, OutgoingMessageService};
+use crate::storage::MessageStore;
+use crate::web::WebSocketEvent;
+
+/// Most chats a batch can go to
+pub const MAX_RECIPIENTS: usize = 50;
+
+/// Time between two messages of a batch
+pub const DISPATCH_INTERVAL: Duration = Duration::from_millis(1500);
+
+/// Job kind batches run as; one batch is sent at a time
+pub const BROADCAST_JOB: &str = "broadcast";
+
+/// Finished batches kept for lookup
+const MAX_KEPT_BATCHES: usize = 100;
+
+/// What happened to one recipient's message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecipientStatus {
+    Pending,
+    Sent,
+    Failed,
+}
+
+/// One recipient of a batch
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecipientResult {
+    pub contact_id: String,
+    pub status: RecipientStatus,
+    /// ID the sent message is stored under
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<String>,
+    /// The language it was translated to, None if sent as typed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_language: Option<String>,
+    /// Why it wasn't sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A text sent to several chats, in the order given
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BroadcastBatch {
+    pub id: String,
+    /// The background job sending it, which can be cancelled
+    pub job_id: String,
+    pub text: String,
+    pub recipients: Vec<RecipientResult>,
+    /// When it was started and finished (ms)
+    pub created_at: i64,
+    pub finished_at: Option<i64>,
+}
+
+impl BroadcastBatch {
+    fn count(&self, status: RecipientStatus) -> usize {
+        self.recipients
+            .iter()
+            .filter(|recipient| recipient.status == status)
+            .count()
+    }
+}
+
+/// Errors returned by the broadcast API
+#[derive(Debug, Clone, Serialize)]
+pub enum BroadcastError {
+    EmptyText,
+    NoRecipients,
+    TooManyRecipients,
+    NotFound,
+}
+
+impl BroadcastError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BroadcastError::EmptyText => "empty_text",
+            BroadcastError::NoRecipients => "no_recipients",
+            BroadcastError::TooManyRecipients => "too_many_recipients",
+            BroadcastError::NotFound => "not_found",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            BroadcastError::EmptyText => "The message text is empty",
+            BroadcastError::NoRecipients => "No chats to send to",
+            BroadcastError::TooManyRecipients => "A broadcast can go to at most 50 chats",
+            BroadcastError::NotFound => "No broadcast with that ID",
+        }
+    }
+}
+
+/// Origin recorded on a batch's messages
+pub fn origin(batch_id: &str) -> String {
+    format!("broadcast:{}", batch_id)
+}
+
+fn now_ms() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+/// Starts batches and keeps track of them
+pub struct Broadcasts {
+    store: MessageStore,
+    sending: OutgoingMessageService,
+    jobs: JobQueue,
+    broadcast_tx: broadcast::Sender<WebSocketEvent>,
+    batches: Mutex<HashMap<String, BroadcastBatch>>,
+    interval: Duration,
+}
+
+impl Broadcasts {
+    pub fn new(
+        store: MessageStore,
+        sending: OutgoingMessageService,
+        jobs: JobQueue,
+        broadcast_tx: broadcast::Sender<WebSocketEvent>,
+    ) -> Self {
+        Self {
+            store,
+            sending,
+            jobs,
+            broadcast_tx,
+            batches: Mutex::new(HashMap::new()),
+            interval: DISPATCH_INTERVAL,
+        }
+    }
+
+    #[cfg(test)]
+    fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Queue `text` for each of `contact_ids` (duplicates dropped, order
+    /// kept) and return the batch straight away
+    pub fn start(
+        self: &Arc<Self>,
+        command_tx: mpsc::Sender<BridgeCommand>,
+        contact_ids: &[String],
+        text: &str,
+        sent_by: Option<String>,
+    ) -> Result<BroadcastBatch, BroadcastError> {
+        if text.trim().is_empty() {
+            return Err(BroadcastError::EmptyText);
+        }
+        let mut recipients: Vec<String> = Vec::new();
+        for contact_id in contact_ids.iter().map(|id| id.trim()) {
+            if !contact_id.is_empty() && !recipients.iter().any(|r| r == contact_id) {
+                recipients.push(contact_id.to_string());
+            }
+        }
+        if recipients.is_empty() {
+            return Err(BroadcastError::NoRecipients);
+        }
+        if recipients.len() > MAX_RECIPIENTS {
+            return Err(BroadcastError::TooManyRecipients);
+        }
+
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        self.batches.lock().unwrap().insert(
+            id.clone(),
+            BroadcastBatch {
+                id: id.clone(),
+                job_id: String::new(),
+                text: text.to_string(),
+                recipients: recipients
+                    .iter()
+                    .map(|contact_id| RecipientResult {
+                        contact_id: contact_id.clone(),
+                        status: RecipientStatus::Pending,
+                        message_id: None,
+                        target_language: None,
+                        error: None,
+                    })
+                    .collect(),
+                created_at: now_ms(),
+                finished_at: None,
+            },
+        );
+        self.prune();
+        info!("Broadcasting to {} chats (batch {})", recipients.len(), id);
+
+        let job_id = self.jobs.submit(BroadcastJob {
+            broadcasts: self.clone(),
+            batch_id: id.clone(),
+            command_tx,
+            text: text.to_string(),
+            recipients,
+            sent_by,
+        });
+        self.update(&id, |batch| batch.job_id = job_id)
+            .ok_or(BroadcastError::NotFound)
+    }
+
+    /// A batch as it stands
+    pub fn get(&self, id: &str) -> Result<BroadcastBatch, BroadcastError> {
+        self.batches
+            .lock()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .ok_or(BroadcastError::NotFound)
+    }
+
+    /// Change a batch and broadcast it, returning it as changed
+    fn update(&self, id: &str, change: impl FnOnce(&mut BroadcastBatch)) -> Option<BroadcastBatch> {
+        let batch = {
+            let mut batches = self.batches.lock().unwrap();
+            let batch = batches.get_mut(id)?;
+            change(batch);
+            batch.clone()
+        };
+        let _ = self.broadcast_tx.send(WebSocketEvent::BroadcastProgress {
+            batch: batch.clone(),
+        });
+        Some(batch)
+    }
+
+    /// Forget the oldest finished batches beyond `MAX_KEPT_BATCHES`
+    fn prune(&self) {
+        let mut batches = self.batches.lock().unwrap();
+        let mut finished: Vec<(i64, String)> = batches
+            .values()
+            .filter(|batch| batch.finished_at.is_some())
+            .map(|batch| (batch.created_at, batch.id.clone()))
+            .collect();
+        if finished.len() <= MAX_KEPT_BATCHES {
+            return;
+        }
+        finished.sort();
+        for (_, id) in finished.iter().take(finished.len() - MAX_KEPT_BATCHES) {
+            batches.remove(id);
+        }
+    }
+
+    /// Send one recipient's message
+    async fn send_one(
+        &self,
+        command_tx: &mpsc::Sender<BridgeCommand>,
+        message: OutgoingMessage,
+    ) -> RecipientResult {
+        let failed = |contact_id: String, error: String| RecipientResult {
+            contact_id,
+            status: RecipientStatus::Failed,
+            message_id: None,
+            target_language: None,
+            error: Some(error),
+        };
+        if is_channel_jid(&message.contact_id) {
+            return failed(message.contact_id, "Channels are read-only".to_string());
+        }
+        match self.store.get_contact(&message.contact_id) {
+            Ok(Some(_)) => {}
+            Ok(None) => return failed(message.contact_id, "Chat not found".to_string()),
+            Err(e) => return failed(message.contact_id, format!("Failed to get chat: {}", e)),
+        }
+
+        match self.sending.send(command_tx, &message, None).await {
+            Ok((stored, outgoing)) => {
+                // Storing it cleared the chat's draft
+                let _ = self.broadcast_tx.send(WebSocketEvent::DraftUpdated {
+                    contact_id: message.contact_id.clone(),
+                    draft: None,
+                });
+                RecipientResult {
+                    contact_id: message.contact_id,
+                    status: RecipientStatus::Sent,
+                    message_id: Some(stored.id),
+                    target_language: outgoing.target_language,
+                    error: None,
+                }
+            }
+            Err(e) => {
+                warn!("Broadcast to {} failed: {}", message.contact_id, e);
+                failed(message.contact_id, e.to_string())
+            }
+        }
+    }
+}
+
+/// Sends a batch's messages in order, spaced out
+struct BroadcastJob {
+    broadcasts: Arc<Broadcasts>,
+    batch_id: String,
+    command_tx: mpsc::Sender<BridgeCommand>,
+    text: String,
+    recipients: Vec<String>,
+    sent_by: Option<String>,
+}
+
+#[async_trait]
+impl Job for BroadcastJob {
+    fn kind(&self) -> &'static str {
+        BROADCAST_JOB
+    }
+
+    async fn run(self: Box<Self>, ctx: JobContext) -> Result<String> {
+        let total = self.recipients.len();
+        for (index, contact_id) in self.recipients.iter().enumerate() {
+            if index > 0 {
+                tokio::time::sleep(self.broadcasts.interval).await;
+            }
+            let message = OutgoingMessage {
+                id: pending_message_id(),
+                contact_id: contact_id.clone(),
+                text: self.text.clone(),
+                reply: None,
+                origin: origin(&self.batch_id),
+                sent_by: self.sent_by.clone(),
+            };
+            let result = self.broadcasts.send_one(&self.command_tx, message).await;
+            self.broadcasts
+                .update(&self.batch_id, |batch| batch.recipients[index] = result);
+            ctx.progress(JobProgress {
+                done: index as u64 + 1,
+                total: Some(total as u64),
+                detail: None,
+            });
+        }
+
+        let batch = self
+            .broadcasts
+            .update(&self.batch_id, |batch| batch.finished_at = Some(now_ms()))
+            .ok_or_else(|| anyhow::anyhow!("Broadcast {} went missing", self.batch_id))?;
+        Ok(format!(
+            "Sent to {} of {} chats",
+            batch.count(RecipientStatus::Sent),
+            total
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shutdown::ShutdownController;
+    use crate::storage::test_store;
+    use std::time::Instant;
+
+    const INTERVAL: Duration = Duration::from_millis(100);
+
+    fn broadcasts(store: &MessageStore) -> (Arc<Broadcasts>, broadcast::Receiver<WebSocketEvent>) {
+        let (tx, rx) = broadcast::channel(100);
+        let jobs = JobQueue::new(store.clone(), tx.clone(), ShutdownController::default());
+        let sending = OutgoingMessageService::new(store.clone(), None);
+        let broadcasts = Broadcasts::new(store.clone(), sending, jobs, tx).with_interval(INTERVAL);
+        (Arc::new(broadcasts), rx)
+    }
+
+    fn chats(store: &MessageStore, count: usize) -> Vec<String> {
+        (0..count)
+            .map(|i| {
+                let contact_id = format!("3460000000{}@s.whatsapp.net", i);
+                store
+                    .upsert_contact(&contact_id, None, None, Some("private"), 1)
+                    .unwrap();
+                contact_id
+            })
+            .collect()
+    }
+
+    /// The batch once it's finished
+    async fn finished(events: &mut broadcast::Receiver<WebSocketEvent>) -> BroadcastBatch {
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            if let WebSocketEvent::BroadcastProgress { batch } = event {
+                if batch.finished_at.is_some() {
+                    return batch;
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_fans_out_in_order_and_throttles() {
+        let (store, _dir) = test_store();
+        let recipients = chats(&store, 3);
+        let (broadcasts, mut events) = broadcasts(&store);
+        let (tx, mut rx) = mpsc::channel(10);
+
+        // Duplicates are dropped
+        let mut requested = recipients.clone();
+        requested.push(recipients[0].clone());
+        let batch = broadcasts
+            .start(
+                tx,
+                &requested,
+                "Party on Saturday!",
+                Some("Ana".to_string()),
+            )
+            .unwrap();
+        assert_eq!(batch.recipients.len(), 3);
+        assert!(batch
+            .recipients
+            .iter()
+            .all(|r| r.status == RecipientStatus::Pending));
+
+        // One message per recipient, in order, spaced out
+        let mut sent_at = Vec::new();
+        for contact_id in &recipients {
+            match tokio::time::timeout(Duration::from_secs(5), rx.recv()).await {
+                Ok(Some(BridgeCommand::Send { to, text, .. })) => {
+                    assert_eq!(&to, contact_id);
+                    assert_eq!(text, "Party on Saturday!");
+                }
+                other => panic!("expected a send, got {:?}", other.is_ok()),
+            }
+            sent_at.push(Instant::now());
+        }
+        for pair in sent_at.windows(2) {
+            assert!(pair[1] - pair[0] >= INTERVAL - Duration::from_millis(10));
+        }
+
+        let done = finished(&mut events).await;
+        assert_eq!(done.count(RecipientStatus::Sent), 3);
+        assert_eq!(broadcasts.get(&batch.id).unwrap(), done);
+
+        // Stored like any other send, marked with the batch
+        let message_id = done.recipients[1].message_id.as_deref().unwrap();
+        let stored = store.get_message_by_id(message_id).unwrap().unwrap();
+        assert_eq!(stored.contact_id, recipients[1]);
+        assert_eq!(stored.origin, Some(origin(&batch.id)));
+        assert_eq!(stored.sent_by.as_deref(), Some("Ana"));
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_reports_partial_failure() {
+        let (store, _dir) = test_store();
+        let mut recipients = chats(&store, 2);
+        recipients.insert(1, "34699999999@s.whatsapp.net".to_string());
+        recipients.push("120363000000000000@newsletter".to_string());
+        let (broadcasts, mut events) = broadcasts(&store);
+        let (tx, mut rx) = mpsc::channel(10);
+
+        broadcasts.start(tx, &recipients, "Hola", None).unwrap();
+        let done = finished(&mut events).await;
+        let statuses: Vec<_> = done.recipients.iter().map(|r| r.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                RecipientStatus::Sent,
+                RecipientStatus::Failed,
+                RecipientStatus::Sent,
+                RecipientStatus::Failed
+            ]
+        );
+        assert_eq!(done.recipients[1].error.as_deref(), Some("Chat not found"));
+        assert_eq!(
+            done.recipients[3].error.as_deref(),
+            Some("Channels are read-only")
+        );
+        // Only the two known chats were sent to
+        assert!(rx.try_recv().is_ok());
+        assert!(rx.try_recv().is_ok());
+        assert!(rx.try_recv().is_err());
+
+        // Too many recipients, or none, are refused up front
+        let (tx, _rx) = mpsc::channel(1);
+        let many: Vec<String> = (0..=MAX_RECIPIENTS)
+            .map(|i| format!("{}@s.whatsapp.net", i))
+            .collect();
+        assert!(matches!(
+            broadcasts.start(tx.clone(), &many, "Hola", None),
+            Err(BroadcastError::TooManyRecipients)
+        ));
+        assert!(matches!(
+            broadcasts.start(tx.clone(), &[" ".to_string()], "Hola", None),
+            Err(BroadcastError::NoRecipients)
+        ));
+        assert!(matches!(
+            broadcasts.start(tx, &recipients, "  ", None),
+            Err(BroadcastError::EmptyText)
+        ));
+    }
+}
@@ -0,0 +1,200 @@
+//! Find in a single chat: which messages match, and where in their text.
+//!
+//! Matching ignores case and accents ("CAFE" finds "café"), which SQLite's
+//! LIKE can't do beyond ASCII, so the text is compared here. Offsets point
+//! into the text the chat shows for each message, in both bytes and chars,
+//! and always fall on character boundaries.
+
+use serde::Serialize;
+use unicode_normalization::char::{decompose_canonical, is_combining_mark};
+
+/// Where one match sits in a message's text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchRange {
+    pub byte_start: usize,
+    pub byte_end: usize,
+    pub char_start: usize,
+    pub char_end: usize,
+}
+
+/// Which of a message's texts the chat shows, and so was searched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextSource {
+    /// The translation of an incoming message
+    Translated,
+    /// The text as written
+    Original,
+}
+
+/// A message with at least one match
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatSearchHit {
+    pub message_id: String,
+    pub timestamp: i64,
+    pub text_source: TextSource,
+    pub matches: Vec<MatchRange>,
+}
+
+/// The text the chat shows for a message: the translation for translated
+/// incoming messages, otherwise what was written (the body or caption)
+pub fn displayed_text(
+    is_from_me: bool,
+    is_translated: bool,
+    original_text: Option<String>,
+    translated_text: Option<String>,
+    content_json: &str,
+) -> Option<(String, TextSource)> {
+    if is_translated && !is_from_me {
+        if let Some(text) = translated_text {
+            return Some((text, TextSource::Translated));
+        }
+    }
+    if let Some(text) = original_text {
+        return Some((text, TextSource::Original));
+    }
+    let content: serde_json::Value = serde_json::from_str(content_json).ok()?;
+    ["body", "text", "caption"]
+        .iter()
+        .find_map(|key| content.get(key).and_then(|v| v.as_str()))
+        .map(|text| (text.to_string(), TextSource::Original))
+}
+
+/// Lowercase `text` with accents removed, with the index of the character
+/// of `text` each folded character came from
+fn fold(text: &str) -> (Vec<char>, Vec<usize>) {
+    let mut folded = Vec::with_capacity(text.len());
+    let mut origins = Vec::with_capacity(text.len());
+    for (index, c) in text.chars().enumerate() {
+        decompose_canonical(c, |d| {
+            if !is_combining_mark(d) {
+                for lower in d.to_lowercase() {
+                    folded.push(lower);
+                    origins.push(index);
+                }
+            }
+        });
+    }
+    (folded, origins)
+}
+
+/// Whether `query` has anything left to search for once folded
+pub fn is_searchable(query: &str) -> bool {
+    !fold(query.trim()).0.is_empty()
+}
+
+/// Every non-overlapping match of `query` in `text`, ignoring case and accents
+pub fn find_matches(text: &str, query: &str) -> Vec<MatchRange> {
+    let (needle, _) = fold(query.trim());
+    if needle.is_empty() {
+        return Vec::new();
+    }
+    let (haystack, origins) = fold(text);
+
+    // Byte offset of each character, plus the end of the text
+    let mut byte_offsets: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+    let char_count = byte_offsets.len();
+    byte_offsets.push(text.len());
+
+    let mut matches = Vec::new();
+    let mut i = 0;
+    while i + needle.len() <= haystack.len() {
+        if haystack[i..i + needle.len()] != needle[..] {
+            i += 1;
+            continue;
+        }
+        let char_start = origins[i];
+        // End after the last matched character, taking in any accents
+        // written as separate combining marks after it
+        let last = origins[i + needle.len() - 1];
+        let char_end = origins[i + needle.len()..]
+            .iter()
+            .find(|&&origin| origin != last)
+            .copied()
+            .unwrap_or(char_count);
+        matches.push(MatchRange {
+            byte_start: byte_offsets[char_start],
+            byte_end: byte_offsets[char_end],
+            char_start,
+            char_end,
+        });
+        i += needle.len();
+    }
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accented_matches() {
+        let text = "Le CAFÉ est près du café";
+        let matches = find_matches(text, "cafe");
+        assert_eq!(matches.len(), 2);
+        for m in &matches {
+            assert!(text[m.byte_start..m.byte_end]
+                .to_lowercase()
+                .starts_with("caf"));
+        }
+        assert_eq!(
+            matches[0],
+            MatchRange {
+                byte_start: 3,
+                byte_end: 8,
+                char_start: 3,
+                char_end: 7,
+            }
+        );
+        assert_eq!(&text[matches[1].byte_start..matches[1].byte_end], "café");
+        assert_eq!(matches[1].char_end, text.chars().count());
+
+        // An accent in the query, and one written as a combining mark
+        let decomposed = "un cafe\u{301} noir";
+        let matches = find_matches(decomposed, "CAFÉ");
+        assert_eq!(matches.len(), 1);
+        assert_eq!(
+            &decomposed[matches[0].byte_start..matches[0].byte_end],
+            "cafe\u{301}"
+        );
+        assert_eq!((matches[0].char_start, matches[0].char_end), (3, 8));
+
+        assert_eq!(find_matches("Straße, STRASSE", "straße").len(), 1);
+        assert_eq!(find_matches("Ünïcödé", "unicode").len(), 1);
+        assert!(find_matches("café", "  ").is_empty());
+        assert!(!is_searchable("\u{301}"));
+    }
+
+    #[test]
+    fn test_displayed_text() {
+        let content = r#"{"type":"image","caption":"a caption"}"#;
+        assert_eq!(
+            displayed_text(
+                false,
+                true,
+                Some("hola".into()),
+                Some("hello".into()),
+                content
+            ),
+            Some(("hello".to_string(), TextSource::Translated))
+        );
+        // My translated messages show what I typed
+        assert_eq!(
+            displayed_text(
+                true,
+                true,
+                Some("hello".into()),
+                Some("hola".into()),
+                content
+            ),
+            Some(("hello".to_string(), TextSource::Original))
+        );
+        assert_eq!(
+            displayed_text(true, false, None, None, content),
+            Some(("a caption".to_string(), TextSource::Original))
+        );
+        assert_eq!(displayed_text(false, false, None, None, "{}"), None);
+    }
+}
@@ -1,13 +1,21 @@
 //! CLI argument parsing using clap.
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 
+use crate::bridge::HistoryDepth;
+use crate::reports::ReportSchedule;
+use crate::translation::{parse_model_pricing, ModelPricing, ModelUpdate};
+use crate::translation_provider::ProviderKind;
+
 /// WhatsApp Translator - Connect to WhatsApp and display incoming messages
 #[derive(Parser, Debug, Clone)]
 #[command(name = "whatsapp-translator")]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Enable verbose/debug logging
     #[arg(short, long, env = "WA_VERBOSE")]
     pub verbose: bool,
@@ -16,10 +24,21 @@ pub struct Args {
     #[arg(long, env = "WA_JSON")]
     pub json: bool,
 
+    /// In terminal mode, keep messages in the web mode's database: ones
+    /// already stored aren't shown or translated again, and each incoming
+    /// message is shown under its chat's previous one
+    #[arg(long, env = "WA_WITH_STORE")]
+    pub with_store: bool,
+
     /// Clear existing session and scan a new QR code
     #[arg(long, env = "WA_LOGOUT")]
     pub logout: bool,
 
+    /// Check the bridge binary, data directory and (in web mode) web assets
+    /// and port before starting, and stop if any of them is broken
+    #[arg(long, env = "WA_PREFLIGHT")]
+    pub preflight: bool,
+
     /// Custom data directory for session storage
     #[arg(long, value_name = "DIR", env = "WA_DATA_DIR")]
     pub data_dir: Option<PathBuf>,
@@ -36,21 +55,241 @@ pub struct Args {
     #[arg(long, default_value = "3000", env = "WA_PORT")]
     pub port: u16,
 
-    /// Host address to bind web server to (default: 0.0.0.0)
+    /// Address(es) to bind the web server to, comma-separated, e.g.
+    /// "::,0.0.0.0" for IPv6 and IPv4 or "[::1]" (default: 0.0.0.0)
     #[arg(long, default_value = "0.0.0.0", env = "WA_HOST")]
     pub host: String,
 
+    /// Exit if any --host address can't be bound, instead of serving on the rest
+    #[arg(long, env = "WA_REQUIRE_ALL_BINDS")]
+    pub require_all_binds: bool,
+
+    /// PEM certificate (chain) to serve HTTPS with; requires --tls-key.
+    /// Send SIGHUP to reload it after renewal.
+    #[arg(long, value_name = "PATH", env = "WA_TLS_CERT", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key matching --tls-cert
+    #[arg(long, value_name = "PATH", env = "WA_TLS_KEY", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Also listen for plain HTTP on this port, redirecting to HTTPS
+    #[arg(
+        long,
+        value_name = "PORT",
+        env = "WA_TLS_REDIRECT_HTTP",
+        requires = "tls_cert"
+    )]
+    pub tls_redirect_http: Option<u16>,
+
     /// Claude API key for message translation
     #[arg(long, env = "ANTHROPIC_API_KEY")]
     pub claude_api_key: Option<String>,
 
+    /// OpenAI API key, for using OpenAI in --translation-fallback
+    #[arg(long, env = "OPENAI_API_KEY")]
+    pub openai_api_key: Option<String>,
+
+    /// Providers to detect and translate with, in order, e.g.
+    /// anthropic,openai: the next is used while the ones before it are
+    /// failing (default: anthropic only)
+    #[arg(
+        long,
+        value_name = "PROVIDERS",
+        env = "WA_TRANSLATION_FALLBACK",
+        value_delimiter = ','
+    )]
+    pub translation_fallback: Vec<ProviderKind>,
+
     /// Default language for messages (messages in this language won't be translated)
     #[arg(long, default_value = "English", env = "WA_DEFAULT_LANGUAGE")]
     pub default_language: String,
 
-    /// Password to protect the web interface (if not set, no password required)
+    /// Initial password to protect the web interface (if not set, no password
+    /// required). Once saved it's changed with PUT /api/auth/password instead.
     #[arg(long, env = "WA_PASSWORD")]
     pub password: Option<String>,
+
+    /// Log a warning when a single translation API call takes longer than this (ms)
+    #[arg(long, default_value = "5000", env = "WA_SLOW_TRANSLATION_MS")]
+    pub slow_translation_ms: u64,
+
+    /// Model for language detection (default: claude-haiku-4-5)
+    #[arg(long, value_name = "MODEL", env = "WA_MODEL_DETECTION")]
+    pub model_detection: Option<String>,
+
+    /// Model for translating messages (default: claude-sonnet-4-5)
+    #[arg(long, value_name = "MODEL", env = "WA_MODEL_TRANSLATION")]
+    pub model_translation: Option<String>,
+
+    /// Model for AI compose and AI replies (default: claude-opus-4-5)
+    #[arg(long, value_name = "MODEL", env = "WA_MODEL_COMPOSE")]
+    pub model_compose: Option<String>,
+
+    /// Model for reply suggestions (default: claude-haiku-4-5)
+    #[arg(long, value_name = "MODEL", env = "WA_MODEL_SUGGESTION")]
+    pub model_suggestion: Option<String>,
+
+    /// Pricing for a model in USD per million input and output tokens, e.g.
+    /// claude-haiku-4-5=1:5 (repeatable; Haiku, Sonnet and Opus models
+    /// default to their current list prices)
+    #[arg(
+        long,
+        value_name = "MODEL=INPUT:OUTPUT",
+        env = "WA_MODEL_PRICING",
+        value_delimiter = ',',
+        value_parser = parse_model_pricing
+    )]
+    pub model_pricing: Vec<(String, ModelPricing)>,
+
+    /// Switch to read-only mode when free space on the data directory's
+    /// filesystem drops below this (MB)
+    #[arg(long, default_value = "200", env = "WA_MIN_FREE_SPACE_MB")]
+    pub min_free_space_mb: u64,
+
+    /// Send translations and AI-composed messages exactly as the model wrote
+    /// them, without cleaning up quotes, labels, markdown and stray characters
+    #[arg(long, env = "WA_NO_SANITIZE_OUTPUT")]
+    pub no_sanitize_output: bool,
+
+    /// Don't translate posts from WhatsApp channels (newsletters), which are
+    /// often long-form; other chats are still translated
+    #[arg(long, env = "WA_NO_TRANSLATE_CHANNELS")]
+    pub no_translate_channels: bool,
+
+    /// Don't detect the language of recent history sync messages to learn
+    /// each chat's language after pairing
+    #[arg(long, env = "WA_NO_LANGUAGE_SEEDING")]
+    pub no_language_seeding: bool,
+
+    /// Most language detections made per day (UTC) while learning chat
+    /// languages from history
+    #[arg(long, default_value = "500", env = "WA_LANGUAGE_SEEDING_DAILY_CAP")]
+    pub language_seeding_daily_cap: u32,
+
+    /// Most link previews kept in the cache; the oldest are deleted beyond
+    /// this (previews older than a week always are)
+    #[arg(long, default_value = "5000", env = "WA_MAX_LINK_PREVIEWS")]
+    pub max_link_previews: usize,
+
+    /// Size (MB) the database's write-ahead log may grow to before the
+    /// hourly upkeep checkpoints and truncates it
+    #[arg(long, default_value = "64", env = "WA_WAL_SIZE_LIMIT_MB")]
+    pub wal_size_limit_mb: u64,
+
+    /// Reverse geocoding endpoint (Nominatim API) used to find the address
+    /// of shared locations that only have coordinates
+    #[arg(long, default_value = crate::geocode::DEFAULT_GEOCODER_URL, env = "WA_GEOCODER_URL")]
+    pub geocoder_url: String,
+
+    /// Don't look up the address of shared locations
+    #[arg(long, env = "WA_NO_GEOCODING")]
+    pub no_geocoding: bool,
+
+    /// Static map URL for location previews, with {lat}, {lon} and {zoom}
+    /// placeholders (fetched by the server, not the browser)
+    #[arg(long, default_value = crate::geocode::DEFAULT_MAP_TEMPLATE, env = "WA_MAP_THUMBNAIL_TEMPLATE")]
+    pub map_thumbnail_template: String,
+
+    /// Contact push services can reach about our notifications (a mailto:
+    /// or https: URL, sent with each push)
+    #[arg(long, default_value = crate::push::DEFAULT_SUBJECT, env = "WA_PUSH_SUBJECT")]
+    pub push_subject: String,
+
+    /// How far back to import history when a phone is paired: none,
+    /// recent (a week), 3months or full. Saved, so later runs keep it.
+    #[arg(long, value_name = "DEPTH", env = "WA_HISTORY_DEPTH")]
+    pub history_depth: Option<HistoryDepth>,
+
+    /// How long shutdown waits for in-flight requests, sends and
+    /// notifications to finish before exiting anyway (seconds)
+    #[arg(long, default_value_t = crate::shutdown::DEFAULT_DRAIN_SECS, env = "WA_SHUTDOWN_DRAIN_SECS")]
+    pub shutdown_drain_secs: u64,
+
+    /// Store view-once photos and videos permanently like other media,
+    /// instead of keeping them in memory until they're opened once
+    #[arg(long, env = "WA_ARCHIVE_VIEW_ONCE")]
+    pub archive_view_once: bool,
+
+    /// Log every web request (route, status, latency, principal) under the
+    /// `access_log` target; polling endpoints are sampled
+    #[arg(long, env = "WA_ACCESS_LOG")]
+    pub access_log: bool,
+
+    /// Invert the terminal QR code (for dark-background terminals)
+    #[arg(long, env = "WA_QR_INVERT")]
+    pub qr_invert: bool,
+
+    /// Generate quick-reply suggestions for incoming private messages (web mode)
+    #[arg(long, env = "WA_AUTO_SUGGEST_REPLIES")]
+    pub auto_suggest_replies: bool,
+
+    /// Maximum automatic reply suggestions generated per day (UTC)
+    #[arg(long, default_value = "100", env = "WA_SUGGEST_REPLIES_DAILY_CAP")]
+    pub suggest_replies_daily_cap: u32,
+
+    /// Make the MCP send_message tool ask for confirmation when a draft doesn't
+    /// match the chat's language (callers can override per call)
+    #[arg(long, env = "WA_MCP_CONFIRM_LANGUAGE")]
+    pub mcp_confirm_language: bool,
+
+    /// Ask for confirmation in the web UI when a draft doesn't match the chat's language
+    #[arg(long, env = "WA_WEB_CONFIRM_LANGUAGE")]
+    pub web_confirm_language: bool,
+
+    /// Deliver a weekly report (messages, AI spend, top chats, errors, disk)
+    /// on this day and local time, e.g. "mon 09:00" (web mode)
+    #[arg(long, value_name = "DAY HH:MM", env = "WA_WEEKLY_REPORT")]
+    pub weekly_report: Option<ReportSchedule>,
+
+    /// Timezone for report weeks and the delivery time, e.g. "Europe/Paris"
+    #[arg(long, default_value = "UTC", env = "WA_REPORT_TIMEZONE")]
+    pub report_timezone: String,
+
+    /// Post weekly reports as JSON to this URL instead of Saved Messages
+    #[arg(long, value_name = "URL", env = "WA_REPORT_WEBHOOK")]
+    pub report_webhook: Option<String>,
+}
+
+/// Subcommands (the default is to connect and show messages)
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Check the setup (bridge, data directory, database, API key, web
+    /// assets, port) and suggest fixes; exits non-zero if a check fails
+    Doctor,
+    /// Manage the wa-bridge binary
+    Bridge {
+        #[command(subcommand)]
+        action: BridgeAction,
+    },
+    /// Serve the MCP tools over stdin/stdout for clients that spawn their
+    /// servers. Sends go through the bridge of a web instance running on
+    /// the same data directory; without one it's read-only.
+    McpStdio,
+    /// Import a WhatsApp "Export chat" .txt file into a chat's history.
+    /// Importing the same file again adds nothing; nothing is translated.
+    ImportChat {
+        /// The exported .txt file
+        file: PathBuf,
+        /// Chat to import into, e.g. 447911123456@s.whatsapp.net (created
+        /// if it doesn't exist)
+        #[arg(long, value_name = "JID")]
+        contact: String,
+        /// Name for a new chat (default: from the file name)
+        #[arg(long)]
+        name: Option<String>,
+        /// My name as it appears in the export (default: my profile name)
+        #[arg(long, value_name = "NAME")]
+        me: Option<String>,
+    },
+}
+
+/// `bridge` subcommands
+#[derive(Subcommand, Debug, Clone)]
+pub enum BridgeAction {
+    /// Show every location searched for wa-bridge and why each was or
+    /// wasn't usable; exits non-zero if none was
+    Check,
 }
 
 impl Args {
@@ -59,6 +298,17 @@ impl Args {
         Self::parse()
     }
 
+    /// Models and pricing set on the command line, overriding saved settings
+    pub fn model_update(&self) -> ModelUpdate {
+        ModelUpdate {
+            detection: self.model_detection.clone(),
+            translation: self.model_translation.clone(),
+            compose: self.model_compose.clone(),
+            suggestion: self.model_suggestion.clone(),
+            pricing: self.model_pricing.iter().cloned().collect(),
+        }
+    }
+
     /// Check if translation is enabled
     pub fn translation_enabled(&self) -> bool {
         self.claude_api_key.is_some()
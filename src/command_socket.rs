@@ -0,0 +1,311 @@
+//! Local command socket.
+//!
+//! The web server listens on a loopback port so that an `mcp-stdio` process
+//! sharing its data directory can send through its bridge. The port and a
+//! random token are written to `command-socket.json` in the data directory
+//! (readable by its owner only). A client sends `{"token": ...}` as its
+//! first line, then one `BridgeCommand` per line; each line is answered with
+//! `{"ok": true}` or `{"error": ...}`.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::bridge::BridgeCommand;
+use crate::web::AppState;
+
+/// File in the data directory telling clients where to connect
+pub const INFO_FILE: &str = "command-socket.json";
+
+/// Longest line read before a client has authenticated
+const MAX_HELLO_BYTES: u64 = 1024;
+
+/// Longest command line (images are sent base64 encoded)
+const MAX_COMMAND_BYTES: u64 = 32 * 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SocketInfo {
+    port: u16,
+    token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Hello {
+    token: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Reply {
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Reply {
+    fn ok() -> Self {
+        Self {
+            ok: true,
+            error: None,
+        }
+    }
+
+    fn error(message: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            error: Some(message.into()),
+        }
+    }
+
+    fn into_result(self) -> Result<()> {
+        match self.error {
+            Some(error) => Err(anyhow!(error)),
+            None if self.ok => Ok(()),
+            None => Err(anyhow!("Unexpected reply from the command socket")),
+        }
+    }
+}
+
+/// Listen for local clients, forwarding their commands to the bridge
+pub async fn serve(state: Arc<AppState>, data_dir: &Path) -> Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", 0))
+        .await
+        .context("Failed to bind the command socket")?;
+    let info = SocketInfo {
+        port: listener.local_addr()?.port(),
+        token: uuid::Uuid::new_v4().simple().to_string(),
+    };
+    write_info(&data_dir.join(INFO_FILE), &info)?;
+    info!("Command socket listening on 127.0.0.1:{}", info.port);
+
+    let token = Arc::new(info.token);
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Command socket accept failed: {}", e);
+                    continue;
+                }
+            };
+            debug!("Command socket connection from {}", peer);
+            let state = state.clone();
+            let token = token.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_client(stream, &state, &token).await {
+                    debug!("Command socket client {} dropped: {}", peer, e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Write the socket info readable by its owner only
+fn write_info(path: &Path, info: &SocketInfo) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to write {:?}", path))?;
+    std::io::Write::write_all(&mut file, serde_json::to_string(info)?.as_bytes())
+        .with_context(|| format!("Failed to write {:?}", path))?;
+    Ok(())
+}
+
+/// Read one line of at most `max` bytes, None at end of stream
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R, max: u64) -> Result<Option<String>> {
+    let mut line = String::new();
+    let read = reader.take(max).read_line(&mut line).await?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with('\n') {
+        bail!("Line too long");
+    }
+    Ok(Some(line))
+}
+
+async fn write_reply<W: AsyncWrite + Unpin>(writer: &mut W, reply: &Reply) -> Result<()> {
+    let mut line = serde_json::to_string(reply)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await?;
+    Ok(())
+}
+
+async fn handle_client(stream: TcpStream, state: &AppState, token: &str) -> Result<()> {
+    let (reader, mut stream) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let hello = read_line(&mut reader, MAX_HELLO_BYTES)
+        .await?
+        .and_then(|line| serde_json::from_str::<Hello>(&line).ok());
+    if hello.map(|hello| hello.token) != Some(token.to_string()) {
+        write_reply(&mut stream, &Reply::error("Invalid token")).await?;
+        bail!("Invalid token");
+    }
+    write_reply(&mut stream, &Reply::ok()).await?;
+
+    while let Some(line) = read_line(&mut reader, MAX_COMMAND_BYTES).await? {
+        let reply = match serde_json::from_str::<BridgeCommand>(&line) {
+            Ok(cmd) => match state.send_bridge_command(cmd).await {
+                Ok(()) => Reply::ok(),
+                Err(e) => Reply::error(e),
+            },
+            Err(e) => Reply::error(format!("Invalid command: {}", e)),
+        };
+        write_reply(&mut stream, &reply).await?;
+    }
+    Ok(())
+}
+
+/// A connection to a running web instance's command socket
+struct Connection {
+    reader: BufReader<tokio::net::tcp::OwnedReadHalf>,
+    writer: tokio::net::tcp::OwnedWriteHalf,
+}
+
+impl Connection {
+    async fn open(data_dir: &Path) -> Result<Self> {
+        let path = data_dir.join(INFO_FILE);
+        let info = std::fs::read_to_string(&path).with_context(|| {
+            format!(
+                "No running web instance found ({:?} is missing); start one with --web",
+                path
+            )
+        })?;
+        let info: SocketInfo =
+            serde_json::from_str(&info).with_context(|| format!("Invalid {:?}", path))?;
+        let stream = TcpStream::connect(("127.0.0.1", info.port)).await.context(
+            "The web instance isn't running (its command socket refused the connection)",
+        )?;
+        let (reader, writer) = stream.into_split();
+        let mut connection = Self {
+            reader: BufReader::new(reader),
+            writer,
+        };
+        connection
+            .request(&Hello { token: info.token })
+            .await?
+            .into_result()
+            .context("The web instance refused the command socket connection")?;
+        Ok(connection)
+    }
+
+    /// Send one line and read its reply
+    async fn request<T: Serialize>(&mut self, message: &T) -> Result<Reply> {
+        let mut line = serde_json::to_string(message)?;
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).await?;
+        let mut reply = String::new();
+        if self.reader.read_line(&mut reply).await? == 0 {
+            bail!("The web instance closed the command socket");
+        }
+        Ok(serde_json::from_str(&reply)?)
+    }
+}
+
+/// Connect to the web instance running in `data_dir`, returning a sender
+/// whose commands are forwarded to its bridge. If the web instance restarts
+/// the connection is opened again for the next command.
+pub async fn connect(data_dir: &Path) -> Result<mpsc::Sender<BridgeCommand>> {
+    let mut connection = Some(Connection::open(data_dir).await?);
+    let data_dir = data_dir.to_path_buf();
+    let (tx, mut rx) = mpsc::channel::<BridgeCommand>(100);
+    tokio::spawn(async move {
+        while let Some(cmd) = rx.recv().await {
+            if connection.is_none() {
+                connection = Connection::open(&data_dir)
+                    .await
+                    .map_err(|e| warn!("Command not sent: {:#}", e))
+                    .ok();
+            }
+            let Some(conn) = connection.as_mut() else {
+                continue;
+            };
+            match conn.request(&cmd).await {
+                Ok(reply) => {
+                    if let Err(e) = reply.into_result() {
+                        warn!("Command not sent: {}", e);
+                    }
+                }
+                Err(e) => {
+                    warn!("Command not sent: {:#}", e);
+                    connection = None;
+                }
+            }
+        }
+    });
+    Ok(tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::send_guard::LanguageGuardConfig;
+    use crate::storage::test_store;
+
+    #[tokio::test]
+    async fn test_commands_reach_the_bridge_with_the_token() {
+        let (store, data_dir) = test_store();
+        let dir = data_dir.path();
+        let state = AppState::new(
+            store,
+            dir.to_path_buf(),
+            dir.to_path_buf(),
+            None,
+            None,
+            None,
+            LanguageGuardConfig::default(),
+        );
+        let (bridge_tx, mut bridge_rx) = mpsc::channel(10);
+        state.set_command_tx(bridge_tx).await;
+        serve(state.clone(), dir).await.unwrap();
+
+        let tx = connect(dir).await.unwrap();
+        tx.send(BridgeCommand::SubscribePresence {
+            jid: "34600000000@s.whatsapp.net".to_string(),
+        })
+        .await
+        .unwrap();
+        let received = tokio::time::timeout(std::time::Duration::from_secs(5), bridge_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            received,
+            BridgeCommand::SubscribePresence { jid } if jid == "34600000000@s.whatsapp.net"
+        ));
+
+        // A wrong token is turned away
+        let info: SocketInfo =
+            serde_json::from_str(&std::fs::read_to_string(dir.join(INFO_FILE)).unwrap()).unwrap();
+        std::fs::write(
+            dir.join(INFO_FILE),
+            serde_json::to_string(&SocketInfo {
+                port: info.port,
+                token: "wrong".to_string(),
+            })
+            .unwrap(),
+        )
+        .unwrap();
+        let err = connect(dir).await.unwrap_err();
+        assert!(format!("{:#}", err).contains("Invalid token"));
+
+        // Without a web instance there is nothing to connect to
+        std::fs::remove_file(dir.join(INFO_FILE)).unwrap();
+        let err = connect(dir).await.unwrap_err();
+        assert!(err.to_string().contains("No running web instance"));
+    }
+}
@@ -0,0 +1,380 @@
+//! How well the link to WhatsApp is holding up.
+//!
+//! "Connected" is all or nothing, but a link usually degrades (phone
+//! offline, flaky network) before it drops. The score here is worked out
+//! from recent reconnects, how long sends take to be confirmed, sends still
+//! waiting, and the bridge falling silent, and sorted into good, degraded
+//! or poor. [`ConnectionScorer`] is fed timestamped samples and never reads
+//! the clock; [`ConnectionMonitor`] feeds it the current time.
+
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How often the app re-assesses the connection
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Window reconnects and event gaps are counted over
+const HISTORY_MS: i64 = 60 * 60 * 1000;
+
+/// Window send round trips are counted over
+const ROUND_TRIP_WINDOW_MS: i64 = 10 * 60 * 1000;
+
+/// Most round trips kept
+const MAX_ROUND_TRIPS: usize = 20;
+
+/// Most event timestamps kept
+const MAX_EVENTS: usize = 1000;
+
+/// Sends unconfirmed for longer are forgotten
+const MAX_SEND_WAIT_MS: i64 = 10 * 60 * 1000;
+
+/// Points lost per reconnect in the last hour, and at most
+const RECONNECT_PENALTY: u32 = 20;
+const MAX_RECONNECT_PENALTY: u32 = 60;
+
+/// Typical round trip from which points are lost, the one at which the
+/// most are, and how many
+const SLOW_ROUND_TRIP_MS: i64 = 3_000;
+const WORST_ROUND_TRIP_MS: i64 = 30_000;
+const MAX_ROUND_TRIP_PENALTY: u32 = 50;
+
+/// Same for the longest a send has been waiting
+const SLOW_SEND_MS: i64 = 10_000;
+const WORST_SEND_MS: i64 = 60_000;
+const MAX_WAITING_PENALTY: u32 = 60;
+
+/// Gaps needed before silence says anything, how many typical gaps and at
+/// least how long it takes, and the points it costs
+const MIN_GAPS: usize = 10;
+const SILENCE_GAPS: i64 = 10;
+const MIN_SILENCE_MS: i64 = 5 * 60 * 1000;
+const SILENCE_PENALTY: u32 = 25;
+
+/// Lowest scores still counting as good and degraded
+const GOOD_SCORE: u32 = 80;
+const DEGRADED_SCORE: u32 = 50;
+
+/// How the connection is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityClass {
+    Good,
+    Degraded,
+    Poor,
+}
+
+impl QualityClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QualityClass::Good => "good",
+            QualityClass::Degraded => "degraded",
+            QualityClass::Poor => "poor",
+        }
+    }
+
+    fn from_score(score: u32) -> Self {
+        if score >= GOOD_SCORE {
+            QualityClass::Good
+        } else if score >= DEGRADED_SCORE {
+            QualityClass::Degraded
+        } else {
+            QualityClass::Poor
+        }
+    }
+}
+
+/// The connection's score and what it was worked out from
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QualityAssessment {
+    pub class: QualityClass,
+    /// 0 to 100
+    pub score: u32,
+    pub connected: bool,
+    pub reconnects_last_hour: usize,
+    /// Median time for recent sends to be confirmed
+    pub send_round_trip_ms: Option<i64>,
+    /// How long the oldest unconfirmed send has been waiting
+    pub oldest_waiting_send_ms: Option<i64>,
+    /// Time since the bridge last sent anything
+    pub last_event_age_ms: Option<i64>,
+}
+
+/// Rolling connection quality from timestamped (ms) samples
+#[derive(Debug, Default)]
+pub struct ConnectionScorer {
+    connected: bool,
+    /// When the connection dropped, in the last hour
+    drops: VecDeque<i64>,
+    /// When bridge events arrived, in the last hour
+    events: VecDeque<i64>,
+    /// Sends waiting to be confirmed, by ID, and when they went out
+    waiting: HashMap<String, i64>,
+    /// Recent sends: when they were confirmed and how long it took
+    round_trips: VecDeque<(i64, i64)>,
+}
+
+impl ConnectionScorer {
+    /// The bridge sent an event
+    pub fn record_event(&mut self, at: i64) {
+        self.events.push_back(at);
+        if self.events.len() > MAX_EVENTS {
+            self.events.pop_front();
+        }
+    }
+
+    /// The connection came up or went down; going down counts as a reconnect
+    pub fn record_connection(&mut self, connected: bool, at: i64) {
+        if self.connected && !connected {
+            self.drops.push_back(at);
+        }
+        self.connected = connected;
+    }
+
+    /// A send went out; `id` is what its confirmation will name
+    pub fn send_started(&mut self, id: &str, at: i64) {
+        self.waiting.insert(id.to_string(), at);
+    }
+
+    /// WhatsApp confirmed a send (unknown IDs are ignored)
+    pub fn send_confirmed(&mut self, id: &str, at: i64) {
+        if let Some(started) = self.waiting.remove(id) {
+            self.round_trips.push_back((at, (at - started).max(0)));
+            if self.round_trips.len() > MAX_ROUND_TRIPS {
+                self.round_trips.pop_front();
+            }
+        }
+    }
+
+    /// Score the connection as of `now`, forgetting samples too old to count
+    pub fn assess(&mut self, now: i64) -> QualityAssessment {
+        self.drops.retain(|&at| now - at <= HISTORY_MS);
+        self.events.retain(|&at| now - at <= HISTORY_MS);
+        self.waiting
+            .retain(|_, &mut at| now - at <= MAX_SEND_WAIT_MS);
+        self.round_trips
+            .retain(|&(at, _)| now - at <= ROUND_TRIP_WINDOW_MS);
+
+        let reconnects = self.drops.len();
+        let round_trip = median(self.round_trips.iter().map(|&(_, rtt)| rtt).collect());
+        let oldest_waiting = self.waiting.values().map(|&at| now - at).max();
+        let last_event_age = self.events.back().map(|&at| now - at);
+
+        let mut penalty = (reconnects as u32 * RECONNECT_PENALTY).min(MAX_RECONNECT_PENALTY);
+        penalty += round_trip.map_or(0, |rtt| {
+            scaled(
+                rtt,
+                SLOW_ROUND_TRIP_MS,
+                WORST_ROUND_TRIP_MS,
+                MAX_ROUND_TRIP_PENALTY,
+            )
+        });
+        penalty += oldest_waiting.map_or(0, |wait| {
+            scaled(wait, SLOW_SEND_MS, WORST_SEND_MS, MAX_WAITING_PENALTY)
+        });
+        if self.is_silent(now) {
+            penalty += SILENCE_PENALTY;
+        }
+
+        let score = if self.connected {
+            100u32.saturating_sub(penalty)
+        } else {
+            0
+        };
+        QualityAssessment {
+            class: QualityClass::from_score(score),
+            score,
+            connected: self.connected,
+            reconnects_last_hour: reconnects,
+            send_round_trip_ms: round_trip,
+            oldest_waiting_send_ms: oldest_waiting,
+            last_event_age_ms: last_event_age,
+        }
+    }
+
+    /// Whether the bridge has gone quiet for far longer than it usually does
+    fn is_silent(&self, now: i64) -> bool {
+        if self.events.len() <= MIN_GAPS {
+            return false;
+        }
+        let gaps = self
+            .events
+            .iter()
+            .zip(self.events.iter().skip(1))
+            .map(|(a, b)| b - a)
+            .collect();
+        let Some(typical) = median(gaps) else {
+            return false;
+        };
+        let silence = now - self.events.back().copied().unwrap_or(now);
+        silence > (typical * SILENCE_GAPS).max(MIN_SILENCE_MS)
+    }
+}
+
+/// Points lost for `value`: none up to `from`, `max` from `to`, linear between
+fn scaled(value: i64, from: i64, to: i64, max: u32) -> u32 {
+    if value <= from {
+        0
+    } else if value >= to {
+        max
+    } else {
+        ((value - from) * max as i64 / (to - from)) as u32
+    }
+}
+
+fn median(mut values: Vec<i64>) -> Option<i64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_unstable();
+    Some(values[values.len() / 2])
+}
+
+/// Connection quality for the app, on the wall clock
+pub struct ConnectionMonitor {
+    scorer: Mutex<ConnectionScorer>,
+    /// The class last announced
+    announced: Mutex<QualityClass>,
+}
+
+impl Default for ConnectionMonitor {
+    fn default() -> Self {
+        Self {
+            scorer: Mutex::new(ConnectionScorer::default()),
+            announced: Mutex::new(QualityClass::Poor),
+        }
+    }
+}
+
+impl ConnectionMonitor {
+    fn now() -> i64 {
+        chrono::Utc::now().timestamp_millis()
+    }
+
+    pub fn record_event(&self) {
+        self.scorer.lock().unwrap().record_event(Self::now());
+    }
+
+    pub fn record_connection(&self, connected: bool) {
+        self.scorer
+            .lock()
+            .unwrap()
+            .record_connection(connected, Self::now());
+    }
+
+    pub fn send_started(&self, id: &str) {
+        self.scorer.lock().unwrap().send_started(id, Self::now());
+    }
+
+    pub fn send_confirmed(&self, id: &str) {
+        self.scorer.lock().unwrap().send_confirmed(id, Self::now());
+    }
+
+    pub fn assess(&self) -> QualityAssessment {
+        self.scorer.lock().unwrap().assess(Self::now())
+    }
+
+    /// Assess the connection, returning the class announced before and the
+    /// assessment if the class has changed since
+    pub fn check(&self) -> Option<(QualityClass, QualityAssessment)> {
+        let assessment = self.assess();
+        let mut announced = self.announced.lock().unwrap();
+        if assessment.class == *announced {
+            return None;
+        }
+        let previous = std::mem::replace(&mut *announced, assessment.class);
+        Some((previous, assessment))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIN: i64 = 60 * 1000;
+
+    /// An event every 10 seconds from `from` until `to`
+    fn keep_busy(scorer: &mut ConnectionScorer, from: i64, to: i64) {
+        for at in (from..to).step_by(10_000) {
+            scorer.record_event(at);
+        }
+    }
+
+    /// Connected at 0, with events for 20 minutes
+    fn busy_scorer() -> ConnectionScorer {
+        let mut scorer = ConnectionScorer::default();
+        scorer.record_connection(true, 0);
+        keep_busy(&mut scorer, 0, 20 * MIN);
+        scorer
+    }
+
+    #[test]
+    fn test_quality_from_reconnects_and_sends() {
+        let mut scorer = ConnectionScorer::default();
+        assert_eq!(scorer.assess(0).class, QualityClass::Poor);
+
+        let mut scorer = busy_scorer();
+        let now = 20 * MIN;
+        let assessment = scorer.assess(now);
+        assert_eq!(assessment.class, QualityClass::Good);
+        assert_eq!(assessment.score, 100);
+
+        // Quick sends leave it good; slow ones don't
+        scorer.send_started("a", now);
+        scorer.send_confirmed("a", now + 1_000);
+        assert_eq!(scorer.assess(now + 1_000).send_round_trip_ms, Some(1_000));
+        for id in ["b", "c"] {
+            scorer.send_started(id, now);
+            scorer.send_confirmed(id, now + 20_000);
+        }
+        let assessment = scorer.assess(now + 20_000);
+        assert_eq!(assessment.send_round_trip_ms, Some(20_000));
+        assert_eq!(assessment.class, QualityClass::Degraded);
+
+        // Slow round trips age out
+        keep_busy(&mut scorer, now, now + 11 * MIN);
+        let now = now + 11 * MIN;
+        assert_eq!(scorer.assess(now).class, QualityClass::Good);
+
+        // Reconnects count for an hour
+        for i in 0..3 {
+            scorer.record_connection(false, now + i * 1_000);
+            scorer.record_connection(true, now + i * 1_000 + 500);
+        }
+        let assessment = scorer.assess(now + 5_000);
+        assert_eq!(assessment.reconnects_last_hour, 3);
+        assert_eq!(assessment.class, QualityClass::Poor);
+        assert_eq!(scorer.assess(now + 61 * MIN).reconnects_last_hour, 0);
+    }
+
+    #[test]
+    fn test_quality_from_waiting_sends_and_silence() {
+        let mut scorer = busy_scorer();
+        let now = 20 * MIN;
+        scorer.send_started("stuck", now);
+        assert_eq!(scorer.assess(now + 5_000).class, QualityClass::Good);
+        let assessment = scorer.assess(now + 45_000);
+        assert_eq!(assessment.oldest_waiting_send_ms, Some(45_000));
+        assert_eq!(assessment.class, QualityClass::Degraded);
+        assert_eq!(scorer.assess(now + 90_000).class, QualityClass::Poor);
+
+        // A send that's never confirmed is eventually forgotten
+        let mut scorer = busy_scorer();
+        scorer.send_started("lost", now);
+        assert_eq!(scorer.assess(now + 11 * MIN).oldest_waiting_send_ms, None);
+
+        // Silence only counts after a busy spell, and for long enough
+        let mut scorer = busy_scorer();
+        assert_eq!(scorer.assess(now + 4 * MIN).score, 100);
+        let assessment = scorer.assess(now + 6 * MIN);
+        assert_eq!(assessment.score, 75);
+        assert_eq!(assessment.class, QualityClass::Degraded);
+
+        let mut quiet = ConnectionScorer::default();
+        quiet.record_connection(true, 0);
+        quiet.record_event(0);
+        assert_eq!(quiet.assess(50 * MIN).class, QualityClass::Good);
+    }
+}
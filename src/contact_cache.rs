@@ -0,0 +1,175 @@
+//! Read-through cache of contacts for `MessageStore::get_contact`.
+//!
+//! Looking a contact up joins its latest message for the preview, which is
+//! slow enough to dominate the database lock during message bursts. The
+//! store fills the cache on lookups and drops a contact's entry whenever
+//! something that shows up in it changes. Both happen while the store holds
+//! its connection lock, so a lookup can't put back a contact that a write
+//! has just invalidated.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::RwLock;
+
+use crate::storage::StoredContact;
+
+/// Contacts kept before arbitrary ones are evicted
+pub const CONTACT_CACHE_CAPACITY: usize = 2048;
+
+/// How well the cache is doing, for `/api/status`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContactCacheStats {
+    pub entries: usize,
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// Share of lookups served from the cache (0 before any lookup)
+    pub hit_rate: f64,
+}
+
+/// Bounded map of contact ID to contact
+pub struct ContactCache {
+    entries: RwLock<HashMap<String, StoredContact>>,
+    capacity: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    /// SQLite's `data_version` when last checked, see `sync_data_version`
+    data_version: AtomicI64,
+}
+
+impl Default for ContactCache {
+    fn default() -> Self {
+        Self::new(CONTACT_CACHE_CAPACITY)
+    }
+}
+
+impl ContactCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: RwLock::new(HashMap::new()),
+            capacity,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            data_version: AtomicI64::new(0),
+        }
+    }
+
+    /// A copy of the cached contact, if there is one
+    pub fn get(&self, id: &str) -> Option<StoredContact> {
+        let contact = self.entries.read().unwrap().get(id).cloned();
+        let counter = if contact.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        contact
+    }
+
+    /// Cache a contact under its ID, evicting another one if full
+    pub fn insert(&self, contact: StoredContact) {
+        let mut entries = self.entries.write().unwrap();
+        if entries.len() >= self.capacity && !entries.contains_key(&contact.id) {
+            if let Some(evicted) = entries.keys().next().cloned() {
+                entries.remove(&evicted);
+            }
+        }
+        if self.capacity > 0 {
+            entries.insert(contact.id.clone(), contact);
+        }
+    }
+
+    /// Forget a contact
+    pub fn invalidate(&self, id: &str) {
+        self.entries.write().unwrap().remove(id);
+    }
+
+    /// Forget every contact
+    pub fn clear(&self) {
+        self.entries.write().unwrap().clear();
+    }
+
+    /// Clear the cache if another process (`mcp-stdio`) has written to the
+    /// database since the last check. `version` is SQLite's `data_version`,
+    /// which only changes for other connections' commits.
+    pub fn sync_data_version(&self, version: i64) {
+        if self.data_version.swap(version, Ordering::Relaxed) != version {
+            self.clear();
+        }
+    }
+
+    pub fn stats(&self) -> ContactCacheStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let lookups = hits + misses;
+        ContactCacheStats {
+            entries: self.entries.read().unwrap().len(),
+            capacity: self.capacity,
+            hits,
+            misses,
+            hit_rate: if lookups == 0 {
+                0.0
+            } else {
+                hits as f64 / lookups as f64
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contact(id: &str, name: &str) -> StoredContact {
+        StoredContact {
+            id: id.to_string(),
+            name: Some(name.to_string()),
+            phone: None,
+            contact_type: Some("private".to_string()),
+            last_message_time: 0,
+            unread_count: 0,
+            pinned_at: None,
+            last_message_preview: None,
+            auto_translate_outgoing: true,
+            mentions_only: false,
+            created_at: None,
+            updated_at: None,
+            last_seen: None,
+            last_read_timestamp: None,
+            description: None,
+            ephemeral_duration: None,
+            placeholder: false,
+        }
+    }
+
+    #[test]
+    fn test_bounded_with_stats() {
+        let cache = ContactCache::new(2);
+        assert!(cache.get("a").is_none());
+        cache.insert(contact("a", "Alice"));
+        cache.insert(contact("b", "Bob"));
+        assert_eq!(cache.get("a").unwrap().name.as_deref(), Some("Alice"));
+
+        cache.insert(contact("c", "Carol"));
+        let stats = cache.stats();
+        assert_eq!(stats.entries, 2);
+        assert!(cache.get("c").is_some());
+
+        cache.invalidate("c");
+        assert!(cache.get("c").is_none());
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (2, 2));
+        assert_eq!(stats.hit_rate, 0.5);
+
+        // A write from another process drops everything
+        cache.sync_data_version(1);
+        cache.insert(contact("a", "Alice"));
+        cache.sync_data_version(1);
+        assert!(cache.get("a").is_some());
+        cache.sync_data_version(2);
+        assert_eq!(cache.stats().entries, 0);
+    }
+}
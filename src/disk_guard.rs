@@ -0,0 +1,286 @@
+//! Disk space monitoring and write protection.
+//!
+//! SQLite writes that fail halfway because the disk is full leave the
+//! database in an inconsistent state, so free space on the data directory's
+//! filesystem is checked periodically. Below a threshold the store goes
+//! read-only: incoming messages are parked in a bounded in-memory buffer
+//! (without their media) and mutating web endpoints are refused. Once space
+//! is available again the buffer is written out and writes resume.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::storage::StoredMessage;
+
+/// Free-space threshold used unless configured otherwise
+pub const DEFAULT_MIN_FREE_BYTES: u64 = 200 * 1024 * 1024;
+
+/// How often free space is checked
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Most messages held in memory while read-only; the oldest are dropped beyond this
+const BUFFER_CAPACITY: usize = 1000;
+
+/// Writes resume once free space exceeds the threshold by a tenth of it,
+/// so the mode doesn't flap while hovering around the threshold
+const RECOVERY_MARGIN_DIVISOR: u64 = 10;
+
+/// Free bytes available to unprivileged users on the filesystem holding `path`
+#[cfg(unix)]
+#[allow(clippy::useless_conversion)] // statvfs field types vary by platform
+pub fn free_bytes(path: &Path) -> Option<u64> {
+    let stat = nix::sys::statvfs::statvfs(path).ok()?;
+    Some(u64::from(stat.blocks_available()) * u64::from(stat.fragment_size()))
+}
+
+/// Free space can't be measured here, so the store never goes read-only
+#[cfg(not(unix))]
+pub fn free_bytes(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Disk space and write-protection mode, as reported by the API
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiskStatus {
+    /// Free bytes on the data directory's filesystem (None if unknown)
+    pub free_bytes: Option<u64>,
+    /// Threshold below which the store goes read-only
+    pub min_free_bytes: u64,
+    /// Whether writes are suspended
+    pub read_only: bool,
+    /// Messages waiting to be written once space is available
+    pub buffered_messages: usize,
+    /// Writes spilled to disk after database errors, waiting to be replayed
+    pub spilled_writes: usize,
+}
+
+/// A change of write-protection mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    ReadOnly,
+    Writable,
+}
+
+/// A message parked while the store is read-only
+pub struct BufferedMessage {
+    pub message: StoredMessage,
+    /// Whether it counts as unread once written
+    pub unread: bool,
+}
+
+/// Write-protection state shared by all clones of the store
+pub struct WriteProtection {
+    data_dir: PathBuf,
+    min_free_bytes: AtomicU64,
+    /// Last measured free space (u64::MAX if not measured or unknown)
+    free_bytes: AtomicU64,
+    read_only: AtomicBool,
+    buffer: Mutex<VecDeque<BufferedMessage>>,
+    /// Whether a message has been dropped since going read-only (logged once)
+    dropped: AtomicBool,
+}
+
+impl WriteProtection {
+    pub fn new(data_dir: &Path, min_free_bytes: u64) -> Self {
+        Self {
+            data_dir: data_dir.to_path_buf(),
+            min_free_bytes: AtomicU64::new(min_free_bytes),
+            free_bytes: AtomicU64::new(u64::MAX),
+            read_only: AtomicBool::new(false),
+            buffer: Mutex::new(VecDeque::new()),
+            dropped: AtomicBool::new(false),
+        }
+    }
+
+    /// Set the free-space threshold below which writes stop
+    pub fn set_min_free_bytes(&self, bytes: u64) {
+        self.min_free_bytes.store(bytes, Ordering::Relaxed);
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+
+    /// Measure free space and switch mode if needed
+    pub fn check(&self) -> Option<Transition> {
+        self.update(free_bytes(&self.data_dir))
+    }
+
+    /// Record a free-space measurement and switch mode if needed.
+    /// An unknown measurement leaves the mode as it is.
+    pub fn update(&self, free: Option<u64>) -> Option<Transition> {
+        self.free_bytes
+            .store(free.unwrap_or(u64::MAX), Ordering::Relaxed);
+        let free = free?;
+        let min = self.min_free_bytes.load(Ordering::Relaxed);
+
+        if !self.is_read_only() && free < min {
+            self.dropped.store(false, Ordering::Relaxed);
+            self.read_only.store(true, Ordering::Relaxed);
+            warn!(
+                "Only {} MB free in {:?} (minimum {} MB), switching to read-only mode",
+                free / 1024 / 1024,
+                self.data_dir,
+                min / 1024 / 1024
+            );
+            Some(Transition::ReadOnly)
+        } else if self.is_read_only() && free >= min + min / RECOVERY_MARGIN_DIVISOR {
+            self.read_only.store(false, Ordering::Relaxed);
+            info!(
+                "{} MB free in {:?}, leaving read-only mode",
+                free / 1024 / 1024,
+                self.data_dir
+            );
+            Some(Transition::Writable)
+        } else {
+            None
+        }
+    }
+
+    /// Go read-only after repeated database write errors, until the next
+    /// free-space check. Returns false if already read-only.
+    pub fn degrade(&self) -> bool {
+        if self.read_only.swap(true, Ordering::Relaxed) {
+            return false;
+        }
+        self.dropped.store(false, Ordering::Relaxed);
+        true
+    }
+
+    /// Park a message until it can be written. Its media is dropped, and
+    /// beyond the buffer's capacity so is the oldest parked message.
+    pub fn buffer(&self, mut message: StoredMessage, unread: bool) {
+        strip_media(&mut message);
+
+        let mut buffer = self.buffer.lock().unwrap();
+        if buffer.len() >= BUFFER_CAPACITY {
+            buffer.pop_front();
+            if !self.dropped.swap(true, Ordering::Relaxed) {
+                warn!(
+                    "Read-only message buffer is full ({} messages), dropping the oldest",
+                    BUFFER_CAPACITY
+                );
+            }
+        }
+        buffer.push_back(BufferedMessage { message, unread });
+    }
+
+    /// Take all parked messages, oldest first
+    pub fn take_buffered(&self) -> Vec<BufferedMessage> {
+        self.buffer.lock().unwrap().drain(..).collect()
+    }
+
+    pub fn status(&self) -> DiskStatus {
+        let free_bytes = self.free_bytes.load(Ordering::Relaxed);
+        DiskStatus {
+            free_bytes: (free_bytes != u64::MAX).then_some(free_bytes),
+            min_free_bytes: self.min_free_bytes.load(Ordering::Relaxed),
+            read_only: self.is_read_only(),
+            buffered_messages: self.buffer.lock().unwrap().len(),
+            spilled_writes: 0,
+        }
+    }
+}
+
+/// Remove embedded media from a message so it isn't held in memory or stored
+fn strip_media(message: &mut StoredMessage) {
+    let Ok(mut content) = serde_json::from_str::<serde_json::Value>(&message.content_json) else {
+        return;
+    };
+    if let Some(obj) = content.as_object_mut() {
+        let had_media = obj.contains_key("media_data") || obj.contains_key("mediaData");
+        obj.remove("media_data");
+        obj.remove("mediaData");
+        if had_media {
+            message.content_json = content.to_string();
+        }
+    }
+    if let Some(obj) = message.content.as_mut().and_then(|c| c.as_object_mut()) {
+        obj.remove("media_data");
+        obj.remove("mediaData");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::text_message;
+
+    const MB: u64 = 1024 * 1024;
+
+    fn message(id: &str, content_json: &str) -> StoredMessage {
+        StoredMessage {
+            content_type: "Image".to_string(),
+            content_json: content_json.to_string(),
+            content: serde_json::from_str(content_json).ok(),
+            original_text: None,
+            ..text_message(id, "a@s.whatsapp.net", 1, "")
+        }
+    }
+
+    #[test]
+    fn test_mode_switches_with_margin() {
+        let guard = WriteProtection::new(Path::new("/nonexistent"), 200 * MB);
+        assert_eq!(guard.update(Some(500 * MB)), None);
+        assert_eq!(guard.update(Some(150 * MB)), Some(Transition::ReadOnly));
+        assert_eq!(guard.update(Some(100 * MB)), None);
+
+        // Just above the threshold isn't enough to resume writing
+        assert_eq!(guard.update(Some(210 * MB)), None);
+        assert!(guard.is_read_only());
+        assert_eq!(guard.update(None), None);
+        assert!(guard.is_read_only());
+        assert_eq!(guard.status().free_bytes, None);
+
+        assert_eq!(guard.update(Some(230 * MB)), Some(Transition::Writable));
+        let status = guard.status();
+        assert!(!status.read_only);
+        assert_eq!(status.free_bytes, Some(230 * MB));
+    }
+
+    #[test]
+    fn test_buffer_is_bounded_and_drops_media() {
+        let guard = WriteProtection::new(Path::new("/nonexistent"), 200 * MB);
+        guard.buffer(
+            message(
+                "img",
+                r#"{"type":"image","caption":"Plage","media_data":"3q2+7w=="}"#,
+            ),
+            true,
+        );
+        for i in 0..BUFFER_CAPACITY {
+            guard.buffer(
+                message(&format!("m{}", i), r#"{"type":"text","body":"hi"}"#),
+                false,
+            );
+        }
+        assert_eq!(guard.status().buffered_messages, BUFFER_CAPACITY);
+
+        let buffered = guard.take_buffered();
+        assert_eq!(buffered[0].message.id, "m0");
+        assert_eq!(guard.status().buffered_messages, 0);
+
+        let guard = WriteProtection::new(Path::new("/nonexistent"), 200 * MB);
+        guard.buffer(
+            message(
+                "img",
+                r#"{"type":"image","caption":"Plage","media_data":"3q2+7w=="}"#,
+            ),
+            true,
+        );
+        let image = &guard.take_buffered()[0];
+        assert!(image.unread);
+        let content: serde_json::Value = serde_json::from_str(&image.message.content_json).unwrap();
+        assert_eq!(
+            content,
+            serde_json::json!({"type": "image", "caption": "Plage"})
+        );
+        assert!(image.message.content.as_ref().unwrap()["media_data"].is_null());
+    }
+}
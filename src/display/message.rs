@@ -1,6 +1,6 @@
 //! Message display formatting for terminal output.
 
-use crate::bridge::{Chat, Message, MessageContent};
+use crate::bridge::{unknown_text_fields, Chat, Message, MessageContent};
 use crossterm::execute;
 use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor};
 use std::io::{stdout, Write};
@@ -48,13 +48,15 @@ impl MessageDisplay {
         }
     }
 
-    /// Display a message to stdout
-    pub fn display(&self, msg: &Message) -> std::io::Result<()> {
+    /// Display a message to stdout, after a line of context (the chat's
+    /// previous message) if given
+    pub fn display(&self, msg: &Message, context: Option<&str>) -> std::io::Result<()> {
         let mut stdout = stdout();
 
         if self.show_separator {
             self.print_separator(&mut stdout)?;
         }
+        self.print_context(&mut stdout, context)?;
 
         // Timestamp and chat type
         self.print_header(&mut stdout, msg)?;
@@ -79,12 +81,14 @@ impl MessageDisplay {
         msg: &Message,
         translated_text: &str,
         source_language: &str,
+        context: Option<&str>,
     ) -> std::io::Result<()> {
         let mut stdout = stdout();
 
         if self.show_separator {
             self.print_separator(&mut stdout)?;
         }
+        self.print_context(&mut stdout, context)?;
 
         // Timestamp and chat type
         self.print_header(&mut stdout, msg)?;
@@ -145,6 +149,27 @@ impl MessageDisplay {
         Ok(())
     }
 
+    fn print_context(
+        &self,
+        stdout: &mut std::io::Stdout,
+        context: Option<&str>,
+    ) -> std::io::Result<()> {
+        let Some(context) = context else {
+            return Ok(());
+        };
+        execute!(
+            stdout,
+            SetForegroundColor(Color::DarkGrey),
+            SetAttribute(Attribute::Italic),
+            Print("Previously: "),
+            Print(context),
+            SetAttribute(Attribute::Reset),
+            ResetColor
+        )?;
+        println!();
+        Ok(())
+    }
+
     fn print_header(&self, stdout: &mut std::io::Stdout, msg: &Message) -> std::io::Result<()> {
         let timestamp = msg.timestamp.format("%Y-%m-%d %H:%M:%S");
         let chat_type = match &msg.chat {
@@ -152,6 +177,7 @@ impl MessageDisplay {
             Chat::Group { .. } => "Group Chat",
             Chat::Broadcast { .. } => "Broadcast",
             Chat::Status { .. } => "Status",
+            Chat::Channel { .. } => "Channel",
         };
 
         execute!(
@@ -174,8 +200,8 @@ impl MessageDisplay {
             ResetColor
         )?;
 
-        // If group, show group name
-        if let Chat::Group { name, .. } = &msg.chat {
+        // If group or channel, show its name
+        if let Chat::Group { name, .. } | Chat::Channel { name, .. } = &msg.chat {
             if let Some(group_name) = name {
                 execute!(
                     stdout,
@@ -268,9 +294,15 @@ impl MessageDisplay {
                 caption,
                 mime_type,
                 file_size,
+                view_once,
                 ..
             } => {
-                self.print_media_info(stdout, "Image", mime_type, *file_size)?;
+                let kind = if *view_once {
+                    "View-once image"
+                } else {
+                    "Image"
+                };
+                self.print_media_info(stdout, kind, mime_type, *file_size)?;
                 if let Some(cap) = caption {
                     println!();
                     execute!(
@@ -288,9 +320,15 @@ impl MessageDisplay {
                 mime_type,
                 file_size,
                 duration_seconds,
+                view_once,
                 ..
             } => {
-                self.print_media_info(stdout, "Video", mime_type, *file_size)?;
+                let kind = if *view_once {
+                    "View-once video"
+                } else {
+                    "Video"
+                };
+                self.print_media_info(stdout, kind, mime_type, *file_size)?;
                 if let Some(duration) = duration_seconds {
                     execute!(
                         stdout,
@@ -470,13 +508,17 @@ impl MessageDisplay {
                 }
             }
 
-            MessageContent::Unknown { raw_type } => {
+            MessageContent::Unknown { raw_type, raw } => {
                 execute!(
                     stdout,
                     SetForegroundColor(self.colors.media_info),
                     Print(format!("[Unsupported message type: {}]", raw_type)),
                     ResetColor
                 )?;
+                for text in unknown_text_fields(raw) {
+                    println!();
+                    execute!(stdout, Print(text))?;
+                }
             }
         }
 
@@ -2,6 +2,8 @@
 
 pub mod message;
 pub mod qr;
+pub mod status;
 
 pub use message::{print_connected, print_error, print_info, print_warning, MessageDisplay};
-pub use qr::{clear_qr_display, render_qr_code};
+pub use qr::QrDisplay;
+pub use status::{LinkState, SessionStatus, StatusLine};
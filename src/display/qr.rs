@@ -6,6 +6,158 @@ use crossterm::{cursor, execute, terminal};
 use qrcode::QrCode;
 use std::io::{stdout, Write};
 
+/// Quiet zone (light border) around the QR code, in modules
+const QUIET_ZONE: usize = 2;
+
+/// Number of terminal rows used by the instructions above the QR code
+const HEADER_ROWS: usize = 9;
+
+/// Renders the login QR code, re-drawing it in place when it rotates.
+pub struct QrDisplay {
+    /// Draw light modules as blocks (for dark-background terminals)
+    invert: bool,
+    /// Cursor position where the QR code starts (set on first compact render)
+    origin: Option<(u16, u16)>,
+    displayed: bool,
+}
+
+impl QrDisplay {
+    pub fn new(invert: bool) -> Self {
+        Self {
+            invert,
+            origin: None,
+            displayed: false,
+        }
+    }
+
+    /// Whether a QR code is currently on screen
+    pub fn is_displayed(&self) -> bool {
+        self.displayed
+    }
+
+    /// Render a QR code to the terminal.
+    ///
+    /// Uses a compact half-block layout (one column and half a row per module)
+    /// and overwrites the previous code on subsequent calls instead of
+    /// appending. Terminals too small for the compact layout get the previous
+    /// full-screen rendering.
+    pub fn render(&mut self, data: &str) -> Result<()> {
+        let code = QrCode::new(data.as_bytes()).context("Failed to generate QR code")?;
+        let lines = qr_to_half_blocks(&code, self.invert);
+        let qr_width = code.width() + QUIET_ZONE * 2;
+
+        let fits = terminal::size()
+            .map(|(w, h)| w as usize >= qr_width && h as usize > HEADER_ROWS + lines.len())
+            .unwrap_or(false);
+
+        if !fits {
+            self.origin = None;
+            self.displayed = true;
+            return render_qr_code(data);
+        }
+
+        let mut stdout = stdout();
+
+        match self.origin {
+            Some((x, y)) => {
+                execute!(
+                    stdout,
+                    cursor::MoveTo(x, y),
+                    terminal::Clear(terminal::ClearType::FromCursorDown)
+                )?;
+            }
+            None => {
+                execute!(
+                    stdout,
+                    terminal::Clear(terminal::ClearType::All),
+                    cursor::MoveTo(0, 0)
+                )?;
+                print_instructions();
+                stdout.flush()?;
+                self.origin = Some(cursor::position()?);
+            }
+        }
+
+        let term_width = terminal::size().map(|(w, _)| w as usize).unwrap_or(80);
+        let pad_str = " ".repeat(term_width.saturating_sub(qr_width) / 2);
+
+        for line in &lines {
+            print!("{}", pad_str);
+            if self.invert {
+                // Use the terminal's own colors; blocks mark the light modules
+                execute!(stdout, Print(line))?;
+            } else {
+                execute!(
+                    stdout,
+                    SetForegroundColor(Color::Black),
+                    SetBackgroundColor(Color::White),
+                    Print(line),
+                    ResetColor
+                )?;
+            }
+            println!();
+        }
+        println!();
+
+        stdout.flush()?;
+        self.displayed = true;
+        Ok(())
+    }
+
+    /// Clear the QR code display
+    pub fn clear(&mut self) -> Result<()> {
+        self.origin = None;
+        self.displayed = false;
+        clear_qr_display()
+    }
+}
+
+/// Convert a QR code to text using Unicode half-block characters, two module
+/// rows per line and one column per module, including the quiet zone.
+///
+/// Blocks mark dark modules, or light modules when `invert` is set.
+pub fn qr_to_half_blocks(code: &QrCode, invert: bool) -> Vec<String> {
+    let modules = code.to_colors();
+    let size = code.width();
+    let total = size + QUIET_ZONE * 2;
+
+    let is_ink = |x: usize, y: usize| -> bool {
+        let dark = x >= QUIET_ZONE
+            && y >= QUIET_ZONE
+            && x < size + QUIET_ZONE
+            && y < size + QUIET_ZONE
+            && modules[(y - QUIET_ZONE) * size + (x - QUIET_ZONE)] == qrcode::Color::Dark;
+        dark != invert
+    };
+
+    (0..total)
+        .step_by(2)
+        .map(|y| {
+            (0..total)
+                .map(|x| {
+                    let bottom = y + 1 < total && is_ink(x, y + 1);
+                    match (is_ink(x, y), bottom) {
+                        (true, true) => '█',
+                        (true, false) => '▀',
+                        (false, true) => '▄',
+                        (false, false) => ' ',
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Print the linking instructions shown above the QR code
+fn print_instructions() {
+    println!("\n  Scan this QR code with WhatsApp on your phone:\n");
+    println!("  1. Open WhatsApp on your phone");
+    println!("  2. Tap Menu (⋮) or Settings (⚙)");
+    println!("  3. Tap 'Linked Devices'");
+    println!("  4. Tap 'Link a Device'");
+    println!("  5. Point your phone at this screen\n");
+}
+
 /// Render a QR code to the terminal using Unicode block characters.
 ///
 /// Uses the upper half block character (▀) to display two rows of QR modules
@@ -25,12 +177,7 @@ pub fn render_qr_code(data: &str) -> Result<()> {
     )?;
 
     // Print header
-    println!("\n  Scan this QR code with WhatsApp on your phone:\n");
-    println!("  1. Open WhatsApp on your phone");
-    println!("  2. Tap Menu (⋮) or Settings (⚙)");
-    println!("  3. Tap 'Linked Devices'");
-    println!("  4. Tap 'Link a Device'");
-    println!("  5. Point your phone at this screen\n");
+    print_instructions();
 
     // Calculate padding for centering
     let term_width = terminal::size().map(|(w, _)| w as usize).unwrap_or(80);
@@ -177,4 +324,53 @@ mod tests {
         let code = QrCode::new(b"test data").unwrap();
         assert!(code.width() > 0);
     }
+
+    #[test]
+    fn test_half_block_dimensions() {
+        // "HELLO" fits in a version 1 (21x21) code
+        let code = QrCode::new(b"HELLO").unwrap();
+        assert_eq!(code.width(), 21);
+
+        let lines = qr_to_half_blocks(&code, false);
+        assert_eq!(lines.len(), 13);
+        assert!(lines.iter().all(|l| l.chars().count() == 25));
+    }
+
+    #[test]
+    fn test_half_block_finder_pattern() {
+        let code = QrCode::new(b"2@ABC123,def456,ghi789").unwrap();
+        let lines = qr_to_half_blocks(&code, false);
+
+        // Quiet zone rows are blank
+        assert!(lines[0].chars().all(|c| c == ' '));
+
+        // Rows 0-1 of the top-left finder: a full dark row above a dark/light/dark row
+        let finder: String = lines[1].chars().skip(QUIET_ZONE).take(7).collect();
+        assert_eq!(finder, "█▀▀▀▀▀█");
+
+        // Rows 2-3: dark border around the 3x3 centre
+        let finder: String = lines[2].chars().skip(QUIET_ZONE).take(7).collect();
+        assert_eq!(finder, "█ ███ █");
+    }
+
+    #[test]
+    fn test_half_block_invert() {
+        let code = QrCode::new(b"HELLO").unwrap();
+        let normal = qr_to_half_blocks(&code, false);
+        let inverted = qr_to_half_blocks(&code, true);
+
+        let flip = |c: char| match c {
+            '█' => ' ',
+            ' ' => '█',
+            '▀' => '▄',
+            '▄' => '▀',
+            other => other,
+        };
+
+        // Every line except the last (whose bottom half is padding) is the exact complement
+        for (n, i) in normal.iter().zip(&inverted).take(normal.len() - 1) {
+            let flipped: String = n.chars().map(flip).collect();
+            assert_eq!(&flipped, i);
+        }
+    }
 }
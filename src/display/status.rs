@@ -0,0 +1,280 @@
+//! Status line at the bottom of the terminal in terminal mode.
+//!
+//! The line shows the connection state, who is connected, the session's
+//! message counts and translation spend. It is cleared before anything else
+//! is printed and drawn again after, so it always sits below the output.
+//! When stdout isn't a terminal it becomes a plain line per state change.
+
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::{execute, terminal};
+use std::io::{stdout, IsTerminal, Write};
+
+/// Connection state shown in the status line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+    Starting,
+    Connecting,
+    Reconnecting,
+    Connected,
+    Disconnected,
+    LoggedOut,
+}
+
+impl LinkState {
+    fn label(self) -> &'static str {
+        match self {
+            LinkState::Starting => "Starting…",
+            LinkState::Connecting => "Connecting…",
+            LinkState::Reconnecting => "Reconnecting…",
+            LinkState::Connected => "Connected",
+            LinkState::Disconnected => "Disconnected",
+            LinkState::LoggedOut => "Logged out",
+        }
+    }
+
+    fn symbol(self) -> char {
+        match self {
+            LinkState::Connected => '●',
+            LinkState::Disconnected | LinkState::LoggedOut => '✗',
+            _ => '◌',
+        }
+    }
+}
+
+/// What the status line shows
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionStatus {
+    pub state: LinkState,
+    /// My phone number, known once connected (or from a saved session)
+    pub phone: Option<String>,
+    /// My WhatsApp name, known once connected
+    pub name: Option<String>,
+    /// Messages received and sent since startup
+    pub received: u64,
+    pub sent: u64,
+    /// Translation spend since startup (USD), None without a translator
+    pub spent_usd: Option<f64>,
+}
+
+impl SessionStatus {
+    pub fn new(phone: Option<String>, translating: bool) -> Self {
+        Self {
+            state: LinkState::Starting,
+            phone,
+            name: None,
+            received: 0,
+            sent: 0,
+            spent_usd: translating.then_some(0.0),
+        }
+    }
+
+    /// Connected as `name`, with `phone` as WhatsApp reports it
+    pub fn connected(&mut self, phone: &str, name: &str) {
+        self.state = LinkState::Connected;
+        self.phone = Some(match phone.bytes().all(|b| b.is_ascii_digit()) {
+            true if !phone.is_empty() => format!("+{}", phone),
+            _ => phone.to_string(),
+        });
+        self.name = Some(name.to_string()).filter(|name| !name.is_empty());
+    }
+
+    /// The line as text, cut to `width` characters
+    pub fn render(&self, width: usize) -> String {
+        let mut parts = vec![match (&self.name, &self.phone) {
+            (Some(name), Some(phone)) if self.state == LinkState::Connected => {
+                format!(
+                    "{} {} as {} ({})",
+                    self.state.symbol(),
+                    self.state.label(),
+                    name,
+                    phone
+                )
+            }
+            (_, Some(phone)) => {
+                format!("{} {} ({})", self.state.symbol(), self.state.label(), phone)
+            }
+            _ => format!("{} {}", self.state.symbol(), self.state.label()),
+        }];
+        parts.push(format!("{} received, {} sent", self.received, self.sent));
+        if let Some(spent) = self.spent_usd {
+            parts.push(format!("${:.4} spent", spent));
+        }
+
+        let line = parts.join(" │ ");
+        if line.chars().count() <= width {
+            return line;
+        }
+        let mut cut: String = line.chars().take(width.saturating_sub(1)).collect();
+        if width > 0 {
+            cut.push('…');
+        }
+        cut
+    }
+}
+
+/// Where the status goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    /// Redrawn in place on the terminal's last line
+    Line,
+    /// A plain line whenever the connection state changes
+    Plain,
+    /// Nowhere (JSON output)
+    Off,
+}
+
+/// Keeps the status line up to date
+pub struct StatusLine {
+    status: SessionStatus,
+    output: Output,
+    /// Whether the line is on screen now
+    drawn: bool,
+    /// Kept off screen (while a QR code is shown)
+    hidden: bool,
+    /// State last written as a plain line
+    printed: Option<LinkState>,
+}
+
+impl StatusLine {
+    pub fn new(status: SessionStatus) -> Self {
+        let output = if stdout().is_terminal() {
+            Output::Line
+        } else {
+            Output::Plain
+        };
+        Self::with_output(status, output)
+    }
+
+    /// A status line that never prints anything
+    pub fn off(status: SessionStatus) -> Self {
+        Self::with_output(status, Output::Off)
+    }
+
+    fn with_output(status: SessionStatus, output: Output) -> Self {
+        Self {
+            status,
+            output,
+            drawn: false,
+            hidden: false,
+            printed: None,
+        }
+    }
+
+    pub fn status(&self) -> &SessionStatus {
+        &self.status
+    }
+
+    pub fn status_mut(&mut self) -> &mut SessionStatus {
+        &mut self.status
+    }
+
+    /// Keep the line off screen, e.g. while a QR code is shown
+    pub fn set_hidden(&mut self, hidden: bool) {
+        self.hidden = hidden;
+    }
+
+    /// Take the line off screen so other output can be printed
+    pub fn clear(&mut self) {
+        if !self.drawn {
+            return;
+        }
+        let mut stdout = stdout();
+        let _ = execute!(
+            stdout,
+            Print("\r"),
+            terminal::Clear(terminal::ClearType::CurrentLine)
+        );
+        self.drawn = false;
+    }
+
+    /// Show the current status: redraw the line, or print it as a plain
+    /// line if the connection state changed
+    pub fn draw(&mut self) {
+        match self.output {
+            Output::Off => {}
+            Output::Plain => {
+                if self.printed != Some(self.status.state) {
+                    println!("Status: {}", self.status.render(usize::MAX));
+                    self.printed = Some(self.status.state);
+                }
+            }
+            Output::Line => {
+                if self.hidden {
+                    return;
+                }
+                let width = terminal::size().map(|(w, _)| w as usize).unwrap_or(80);
+                let mut stdout = stdout();
+                let _ = execute!(
+                    stdout,
+                    Print("\r"),
+                    terminal::Clear(terminal::ClearType::CurrentLine),
+                    SetAttribute(Attribute::Reverse),
+                    Print(self.status.render(width)),
+                    SetAttribute(Attribute::Reset)
+                );
+                let _ = stdout.flush();
+                self.drawn = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_states() {
+        let mut status = SessionStatus::new(None, false);
+        assert_eq!(status.render(100), "◌ Starting… │ 0 received, 0 sent");
+
+        // A resumed session knows its number before connecting
+        let mut resumed = SessionStatus::new(Some("+447700900123".to_string()), true);
+        resumed.state = LinkState::Reconnecting;
+        assert_eq!(
+            resumed.render(100),
+            "◌ Reconnecting… (+447700900123) │ 0 received, 0 sent │ $0.0000 spent"
+        );
+
+        status.connected("447700900123", "Sam");
+        status.received = 12;
+        status.sent = 3;
+        status.spent_usd = Some(0.01234);
+        assert_eq!(
+            status.render(100),
+            "● Connected as Sam (+447700900123) │ 12 received, 3 sent │ $0.0123 spent"
+        );
+
+        status.state = LinkState::LoggedOut;
+        assert!(status
+            .render(100)
+            .starts_with("✗ Logged out (+447700900123) │"));
+    }
+
+    #[test]
+    fn test_render_fits_width() {
+        let mut status = SessionStatus::new(Some("+447700900123".to_string()), true);
+        status.state = LinkState::Connected;
+        status.name = Some("Sam".to_string());
+
+        let line = status.render(20);
+        assert_eq!(line.chars().count(), 20);
+        assert!(line.ends_with('…'));
+        assert_eq!(status.render(0), "");
+    }
+
+    #[test]
+    fn test_plain_output_only_on_state_changes() {
+        let mut line = StatusLine::with_output(SessionStatus::new(None, false), Output::Plain);
+        line.draw();
+        assert_eq!(line.printed, Some(LinkState::Starting));
+        line.status_mut().received += 1;
+        line.draw();
+        assert_eq!(line.printed, Some(LinkState::Starting));
+        line.status_mut().state = LinkState::Connected;
+        line.draw();
+        assert_eq!(line.printed, Some(LinkState::Connected));
+        // Never drawn in place, so never cleared
+        assert!(!line.drawn);
+    }
+}
@@ -0,0 +1,493 @@
+//! Setup checks for `whatsapp-translator doctor` and `--preflight`.
+//!
+//! Each check looks at one thing a new install commonly gets wrong (bridge
+//! binary, data directory, database, API key, web assets, port) and reports
+//! pass, warn or fail with a hint on how to fix it. `doctor` runs them all;
+//! `--preflight` runs the local ones before starting and stops on failures.
+
+use crossterm::execute;
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor};
+use reqwest::StatusCode;
+use std::io::stdout;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::cli::Args;
+use crate::disk_guard;
+use crate::storage::MessageStore;
+
+/// Anthropic endpoint used to validate the API key. Listing models is free.
+const ANTHROPIC_MODELS_URL: &str = "https://api.anthropic.com/v1/models";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// How long the bridge and API get to answer
+const BRIDGE_VERSION_TIMEOUT: Duration = Duration::from_secs(5);
+const API_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Files the web UI can't work without
+const WEB_ASSETS: [&str; 3] = ["index.html", "app.js", "styles.css"];
+
+/// Outcome of a single check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// A check's outcome, with a hint on how to fix it if it didn't pass
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+    pub hint: Option<String>,
+}
+
+impl CheckResult {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Warn,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Fail,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// Check the bridge binary exists, is executable and answers `--version`
+pub async fn check_bridge(path: Option<&Path>, data_dir: &Path) -> CheckResult {
+    const NAME: &str = "Bridge binary";
+    const BUILD_HINT: &str = "Build it with `cd wa-bridge && go build -o wa-bridge .`, \
+                              or point --bridge-path at it";
+
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => {
+            let discovery = crate::bridge::BridgeSearch::new(None, Some(data_dir)).run();
+            match discovery.found() {
+                Some(path) => path.to_path_buf(),
+                None => {
+                    return CheckResult::fail(
+                        NAME,
+                        format!(
+                            "No usable wa-bridge in {} locations",
+                            discovery.candidates.len()
+                        ),
+                        format!(
+                        "{}; run `whatsapp-translator bridge check` to see why each was rejected",
+                        BUILD_HINT
+                    ),
+                    )
+                }
+            }
+        }
+    };
+    if !path.is_file() {
+        return CheckResult::fail(NAME, format!("{:?} doesn't exist", path), BUILD_HINT);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let executable = std::fs::metadata(&path)
+            .map(|m| m.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false);
+        if !executable {
+            return CheckResult::fail(
+                NAME,
+                format!("{:?} isn't executable", path),
+                format!("Run `chmod +x {}`", path.display()),
+            );
+        }
+    }
+
+    let output = tokio::process::Command::new(&path)
+        .arg("--version")
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    match tokio::time::timeout(BRIDGE_VERSION_TIMEOUT, output).await {
+        Ok(Ok(output)) if output.status.success() => {
+            let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if version.starts_with("wa-bridge") {
+                CheckResult::pass(NAME, format!("{} ({:?})", version, path))
+            } else {
+                CheckResult::warn(
+                    NAME,
+                    format!("{:?} answered --version with {:?}", path, version),
+                    "Check --bridge-path points at wa-bridge",
+                )
+            }
+        }
+        Ok(Ok(_)) => CheckResult::warn(
+            NAME,
+            format!("{:?} doesn't understand --version", path),
+            "It's probably an old build; rebuild it from wa-bridge/",
+        ),
+        Ok(Err(e)) => CheckResult::fail(
+            NAME,
+            format!("Can't run {:?}: {}", path, e),
+            "Rebuild it for this platform from wa-bridge/",
+        ),
+        Err(_) => CheckResult::fail(
+            NAME,
+            format!(
+                "{:?} didn't answer --version within {:?}",
+                path, BRIDGE_VERSION_TIMEOUT
+            ),
+            "Rebuild it from wa-bridge/",
+        ),
+    }
+}
+
+/// Check the data directory can be created and written to, and has room
+pub fn check_data_dir(data_dir: &Path, min_free_bytes: u64) -> CheckResult {
+    const NAME: &str = "Data directory";
+
+    if let Err(e) = std::fs::create_dir_all(data_dir) {
+        return CheckResult::fail(
+            NAME,
+            format!("Can't create {:?}: {}", data_dir, e),
+            "Create it yourself or choose another with --data-dir",
+        );
+    }
+    let probe = data_dir.join(format!(".doctor-{}", uuid::Uuid::new_v4()));
+    if let Err(e) = std::fs::write(&probe, b"ok") {
+        return CheckResult::fail(
+            NAME,
+            format!("Can't write to {:?}: {}", data_dir, e),
+            "Fix its permissions or choose another with --data-dir",
+        );
+    }
+    let _ = std::fs::remove_file(&probe);
+
+    match disk_guard::free_bytes(data_dir) {
+        Some(free) if free < min_free_bytes => CheckResult::warn(
+            NAME,
+            format!(
+                "{:?} has only {} MB free; the store will be read-only",
+                data_dir,
+                free / 1024 / 1024
+            ),
+            "Free some space or lower --min-free-space-mb",
+        ),
+        _ => CheckResult::pass(NAME, format!("{:?} is writable", data_dir)),
+    }
+}
+
+/// Check the message database opens and its schema migrates
+pub fn check_database(data_dir: &Path) -> CheckResult {
+    const NAME: &str = "Database";
+
+    match MessageStore::new(data_dir).and_then(|store| store.get_stats()) {
+        Ok((messages, contacts)) => CheckResult::pass(
+            NAME,
+            format!("{} messages from {} contacts", messages, contacts),
+        ),
+        Err(e) => CheckResult::fail(
+            NAME,
+            format!("{:#}", e),
+            "Check messages.db in the data directory isn't corrupt or locked by another tool",
+        ),
+    }
+}
+
+/// Check the Anthropic API key by listing models, which uses no tokens
+pub async fn check_api_key(api_key: Option<&str>) -> CheckResult {
+    const NAME: &str = "Anthropic API key";
+
+    let Some(api_key) = api_key else {
+        return CheckResult::warn(
+            NAME,
+            "Not set, translation is disabled",
+            "Set ANTHROPIC_API_KEY or pass --claude-api-key",
+        );
+    };
+
+    let response = reqwest::Client::new()
+        .get(ANTHROPIC_MODELS_URL)
+        .header("x-api-key", api_key)
+        .header("anthropic-version", ANTHROPIC_VERSION)
+        .timeout(API_TIMEOUT)
+        .send()
+        .await;
+    match response {
+        Ok(response) => api_key_result(response.status()),
+        Err(e) => CheckResult::warn(
+            NAME,
+            format!("Couldn't reach the API: {}", e),
+            "Check your network connection; the key wasn't verified",
+        ),
+    }
+}
+
+/// Interpret the API's answer to the models request
+fn api_key_result(status: StatusCode) -> CheckResult {
+    const NAME: &str = "Anthropic API key";
+
+    match status {
+        s if s.is_success() => {
+            CheckResult::pass(NAME, "Valid (checked by listing models, no cost)")
+        }
+        StatusCode::UNAUTHORIZED => CheckResult::fail(
+            NAME,
+            "Rejected as invalid",
+            "Create a new key at console.anthropic.com and set ANTHROPIC_API_KEY",
+        ),
+        StatusCode::FORBIDDEN => CheckResult::fail(
+            NAME,
+            "Not allowed to use the API",
+            "Check the key's workspace and permissions in the Anthropic console",
+        ),
+        s => CheckResult::warn(
+            NAME,
+            format!("The API answered {}", s),
+            "Try again later; the key wasn't verified",
+        ),
+    }
+}
+
+/// Check the web UI's files are present
+pub fn check_web_dir(web_dir: Option<&Path>, required: bool) -> CheckResult {
+    const NAME: &str = "Web assets";
+    const HINT: &str = "Run from the repository root, or install web/public next to the executable";
+
+    let missing = |detail: String| {
+        if required {
+            CheckResult::fail(NAME, detail, HINT)
+        } else {
+            CheckResult::warn(NAME, format!("{} (only needed for --web)", detail), HINT)
+        }
+    };
+
+    let Some(web_dir) = web_dir else {
+        return missing("web/public not found".to_string());
+    };
+    let absent: Vec<&str> = WEB_ASSETS
+        .into_iter()
+        .filter(|file| !web_dir.join(file).is_file())
+        .collect();
+    if absent.is_empty() {
+        CheckResult::pass(NAME, format!("{:?}", web_dir))
+    } else {
+        missing(format!("{:?} is missing {}", web_dir, absent.join(", ")))
+    }
+}
+
+/// Check the web server's address can be bound
+pub fn check_port(host: &str, port: u16) -> CheckResult {
+    const NAME: &str = "Web port";
+
+    let addrs = match crate::web::parse_bind_addrs(host, port) {
+        Ok(addrs) => addrs,
+        Err(e) => return CheckResult::fail(
+            NAME,
+            format!("{:#}", e),
+            "Use IP addresses for --host, comma-separated, with IPv6 in brackets if it has a port",
+        ),
+    };
+
+    for addr in &addrs {
+        match crate::web::bind_listener(*addr) {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => return CheckResult::fail(
+                NAME,
+                format!("{} is already in use", addr),
+                "Stop whatever is using it (is another instance running?) or pick one with --port",
+            ),
+            Err(e) => return CheckResult::fail(
+                NAME,
+                format!("Can't bind {}: {}", addr, e),
+                "Check --host is an address of this machine, and ports below 1024 need privileges",
+            ),
+        }
+    }
+    let addrs: Vec<String> = addrs.iter().map(|a| a.to_string()).collect();
+    CheckResult::pass(NAME, format!("{} is free", addrs.join(", ")))
+}
+
+/// Run every check, print the report and return whether none failed
+pub async fn run(args: &Args, data_dir: &Path, web_dir: Option<PathBuf>) -> bool {
+    let results = vec![
+        check_bridge(args.bridge_path.as_deref(), data_dir).await,
+        check_data_dir(data_dir, args.min_free_space_mb * 1024 * 1024),
+        check_database(data_dir),
+        check_api_key(args.claude_api_key.as_deref()).await,
+        check_web_dir(web_dir.as_deref(), args.web),
+        check_port(&args.host, args.port),
+    ];
+    print_report(&results);
+    !has_failures(&results)
+}
+
+/// Run the checks that need no network or database before starting, and
+/// return whether none failed. Only failures and warnings are printed.
+pub async fn preflight(args: &Args, data_dir: &Path, web_dir: Option<PathBuf>) -> bool {
+    let mut results = vec![
+        check_bridge(args.bridge_path.as_deref(), data_dir).await,
+        check_data_dir(data_dir, args.min_free_space_mb * 1024 * 1024),
+    ];
+    if args.web {
+        results.push(check_web_dir(web_dir.as_deref(), true));
+        results.push(check_port(&args.host, args.port));
+    }
+    results.retain(|r| r.status != Status::Pass);
+    print_report(&results);
+    !has_failures(&results)
+}
+
+fn has_failures(results: &[CheckResult]) -> bool {
+    results.iter().any(|r| r.status == Status::Fail)
+}
+
+/// Print one colored line per check, with hints under those that didn't pass
+fn print_report(results: &[CheckResult]) {
+    let mut stdout = stdout();
+    for result in results {
+        let (color, label) = match result.status {
+            Status::Pass => (Color::Green, "✓ PASS"),
+            Status::Warn => (Color::Yellow, "⚠ WARN"),
+            Status::Fail => (Color::Red, "✗ FAIL"),
+        };
+        let _ = execute!(
+            stdout,
+            SetForegroundColor(color),
+            SetAttribute(Attribute::Bold),
+            Print(label),
+            SetAttribute(Attribute::Reset),
+            ResetColor,
+            Print(format!("  {}: {}", result.name, result.detail))
+        );
+        println!();
+        if let Some(hint) = &result.hint {
+            let _ = execute!(
+                stdout,
+                SetForegroundColor(Color::DarkGrey),
+                Print(format!("        → {}", hint)),
+                ResetColor
+            );
+            println!();
+        }
+    }
+
+    let count = |status| results.iter().filter(|r| r.status == status).count();
+    let (warnings, failures) = (count(Status::Warn), count(Status::Fail));
+    if warnings + failures > 0 {
+        println!("{} failed, {} warnings", failures, warnings);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    fn script(dir: &Path, name: &str, body: &str, mode: u32) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+        let path = dir.join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        path
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_check_bridge() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+
+        let bridge = script(dir, "ok", "echo 'wa-bridge 0.1.0'", 0o755);
+        let result = check_bridge(Some(&bridge), dir).await;
+        assert_eq!(result.status, Status::Pass, "{:?}", result);
+        assert!(result.detail.starts_with("wa-bridge 0.1.0"));
+
+        // Go's flag package exits 2 on an unknown flag
+        let old = script(dir, "old", "exit 2", 0o755);
+        assert_eq!(check_bridge(Some(&old), dir).await.status, Status::Warn);
+
+        let not_executable = script(dir, "noexec", "echo 'wa-bridge'", 0o644);
+        let result = check_bridge(Some(&not_executable), dir).await;
+        assert_eq!(result.status, Status::Fail);
+        assert!(result.hint.unwrap().contains("chmod +x"));
+
+        let missing = dir.join("missing");
+        assert_eq!(check_bridge(Some(&missing), dir).await.status, Status::Fail);
+    }
+
+    #[test]
+    fn test_check_data_dir_and_database() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().join("nested");
+        assert_eq!(check_data_dir(&dir, 0).status, Status::Pass);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        assert_eq!(check_data_dir(&dir, u64::MAX).status, Status::Warn);
+
+        let result = check_database(&dir);
+        assert_eq!(result.status, Status::Pass, "{:?}", result);
+        assert_eq!(result.detail, "0 messages from 0 contacts");
+
+        // A file where the directory should be
+        let file = dir.join("messages.db");
+        assert_eq!(check_data_dir(&file, 0).status, Status::Fail);
+    }
+
+    #[test]
+    fn test_api_key_result() {
+        assert_eq!(api_key_result(StatusCode::OK).status, Status::Pass);
+        assert_eq!(
+            api_key_result(StatusCode::UNAUTHORIZED).status,
+            Status::Fail
+        );
+        assert_eq!(api_key_result(StatusCode::FORBIDDEN).status, Status::Fail);
+        assert_eq!(
+            api_key_result(StatusCode::TOO_MANY_REQUESTS).status,
+            Status::Warn
+        );
+    }
+
+    #[test]
+    fn test_check_web_dir() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::write(dir.join("index.html"), "").unwrap();
+
+        let result = check_web_dir(Some(dir), true);
+        assert_eq!(result.status, Status::Fail);
+        assert!(result.detail.contains("app.js, styles.css"));
+        assert_eq!(check_web_dir(None, false).status, Status::Warn);
+
+        std::fs::write(dir.join("app.js"), "").unwrap();
+        std::fs::write(dir.join("styles.css"), "").unwrap();
+        assert_eq!(check_web_dir(Some(dir), true).status, Status::Pass);
+    }
+
+    #[test]
+    fn test_check_port() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let result = check_port("127.0.0.1", port);
+        assert_eq!(result.status, Status::Fail);
+        assert!(result.detail.contains("already in use"));
+
+        drop(listener);
+        assert_eq!(check_port("127.0.0.1", port).status, Status::Pass);
+    }
+}
@@ -0,0 +1,250 @@
+//! Recent errors by subsystem, kept in memory for the UI.
+//!
+//! Each category keeps its last few errors so the UI can say "3 translation
+//! errors in the last hour, last: 529 overloaded" without anyone reading the
+//! logs. Messages are redacted and shortened before they're kept, and nothing
+//! is written to disk.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Errors kept per category
+pub const ERRORS_PER_CATEGORY: usize = 50;
+
+/// Longest message kept, in characters
+const MAX_MESSAGE_CHARS: usize = 300;
+
+/// Window for the counts in `/api/status`
+pub const SUMMARY_WINDOW_MS: i64 = 60 * 60 * 1000;
+
+/// Subsystem an error came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    Bridge,
+    Translation,
+    Storage,
+    /// Web Push deliveries
+    Webhook,
+    Send,
+}
+
+impl ErrorCategory {
+    pub const ALL: [ErrorCategory; 5] = [
+        ErrorCategory::Bridge,
+        ErrorCategory::Translation,
+        ErrorCategory::Storage,
+        ErrorCategory::Webhook,
+        ErrorCategory::Send,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCategory::Bridge => "bridge",
+            ErrorCategory::Translation => "translation",
+            ErrorCategory::Storage => "storage",
+            ErrorCategory::Webhook => "webhook",
+            ErrorCategory::Send => "send",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.as_str() == s)
+    }
+
+    /// Whether the UI should be told straight away: these mean messages
+    /// aren't getting through or being kept
+    pub fn is_high_severity(&self) -> bool {
+        matches!(
+            self,
+            ErrorCategory::Bridge | ErrorCategory::Storage | ErrorCategory::Send
+        )
+    }
+}
+
+/// One recorded error
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorEntry {
+    pub id: u64,
+    pub category: ErrorCategory,
+    /// Unix timestamp in milliseconds
+    pub timestamp: i64,
+    pub message: String,
+}
+
+/// A category's errors in the last hour
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorSummary {
+    pub category: ErrorCategory,
+    pub last_hour: usize,
+    pub last: Option<ErrorEntry>,
+}
+
+/// Bounded per-category buffers of recent errors. Clones share the buffers.
+#[derive(Clone, Default)]
+pub struct ErrorRegistry {
+    entries: Arc<Mutex<HashMap<ErrorCategory, VecDeque<ErrorEntry>>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl ErrorRegistry {
+    /// Keep an error, dropping the category's oldest once it's full
+    pub fn record(&self, category: ErrorCategory, message: impl Display) -> ErrorEntry {
+        self.record_at(category, message, chrono::Utc::now().timestamp_millis())
+    }
+
+    fn record_at(
+        &self,
+        category: ErrorCategory,
+        message: impl Display,
+        timestamp: i64,
+    ) -> ErrorEntry {
+        let entry = ErrorEntry {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed) + 1,
+            category,
+            timestamp,
+            message: redact(&message.to_string()),
+        };
+        let mut entries = self.entries.lock().unwrap();
+        let recent = entries.entry(category).or_default();
+        if recent.len() == ERRORS_PER_CATEGORY {
+            recent.pop_front();
+        }
+        recent.push_back(entry.clone());
+        entry
+    }
+
+    /// Errors newer than `since` (milliseconds, exclusive), newest first,
+    /// from one category or all of them
+    pub fn list(&self, category: Option<ErrorCategory>, since: Option<i64>) -> Vec<ErrorEntry> {
+        let entries = self.entries.lock().unwrap();
+        let mut list: Vec<ErrorEntry> = entries
+            .iter()
+            .filter(|(c, _)| category.is_none_or(|category| **c == category))
+            .flat_map(|(_, recent)| recent.iter())
+            .filter(|e| since.is_none_or(|since| e.timestamp > since))
+            .cloned()
+            .collect();
+        list.sort_by_key(|e| std::cmp::Reverse(e.id));
+        list
+    }
+
+    /// Every category's errors in the hour before `now`
+    pub fn summary(&self, now: i64) -> Vec<ErrorSummary> {
+        let entries = self.entries.lock().unwrap();
+        ErrorCategory::ALL
+            .into_iter()
+            .map(|category| {
+                let recent = entries.get(&category);
+                ErrorSummary {
+                    category,
+                    last_hour: recent.map_or(0, |recent| {
+                        recent
+                            .iter()
+                            .filter(|e| e.timestamp > now - SUMMARY_WINDOW_MS)
+                            .count()
+                    }),
+                    last: recent.and_then(|recent| recent.back().cloned()),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Hide anything that looks like a key or token, and cut long messages
+/// (API error bodies) short
+fn redact(message: &str) -> String {
+    let keys = regex::Regex::new(r"sk-ant-[A-Za-z0-9_\-]+").unwrap();
+    let message = keys.replace_all(message, "[redacted]");
+    let secrets = regex::Regex::new(
+        r#"(?i)(bearer\s+|(?:x-api-key|api[_-]?key|token|password|secret)["']?\s*[:=]\s*["']?)[^\s"',&]+"#,
+    )
+    .unwrap();
+    let message = secrets.replace_all(&message, "${1}[redacted]");
+
+    if message.chars().count() > MAX_MESSAGE_CHARS {
+        let mut short: String = message.chars().take(MAX_MESSAGE_CHARS).collect();
+        short.push('…');
+        short
+    } else {
+        message.into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_buffer_caps_each_category() {
+        let errors = ErrorRegistry::default();
+        for i in 0..ERRORS_PER_CATEGORY + 5 {
+            errors.record_at(ErrorCategory::Translation, format!("error {}", i), i as i64);
+        }
+        errors.record_at(ErrorCategory::Send, "send failed", 1000);
+
+        let translation = errors.list(Some(ErrorCategory::Translation), None);
+        assert_eq!(translation.len(), ERRORS_PER_CATEGORY);
+        // The oldest were dropped
+        assert_eq!(
+            translation.first().unwrap().message,
+            format!("error {}", ERRORS_PER_CATEGORY + 4)
+        );
+        assert_eq!(translation.last().unwrap().message, "error 5");
+        assert_eq!(errors.list(None, None).len(), ERRORS_PER_CATEGORY + 1);
+    }
+
+    #[test]
+    fn test_filters_and_summary() {
+        let errors = ErrorRegistry::default();
+        let hour = SUMMARY_WINDOW_MS;
+        errors.record_at(ErrorCategory::Bridge, "old", 1_000);
+        errors.record_at(ErrorCategory::Bridge, "recent", 1_000 + hour);
+        errors.record_at(ErrorCategory::Webhook, "push failed", 2_000 + hour);
+
+        let bridge = errors.list(Some(ErrorCategory::Bridge), None);
+        assert_eq!(bridge.len(), 2);
+        assert!(bridge.iter().all(|e| e.category == ErrorCategory::Bridge));
+        let since: Vec<String> = errors
+            .list(None, Some(1_000))
+            .into_iter()
+            .map(|e| e.message)
+            .collect();
+        assert_eq!(since, ["push failed", "recent"]);
+        assert!(errors.list(Some(ErrorCategory::Send), None).is_empty());
+
+        let summary = errors.summary(1_500 + hour);
+        let bridge = summary
+            .iter()
+            .find(|s| s.category == ErrorCategory::Bridge)
+            .unwrap();
+        assert_eq!(bridge.last_hour, 1);
+        assert_eq!(bridge.last.as_ref().unwrap().message, "recent");
+        assert_eq!(summary.len(), ErrorCategory::ALL.len());
+        assert_eq!(
+            ErrorCategory::parse("webhook"),
+            Some(ErrorCategory::Webhook)
+        );
+    }
+
+    #[test]
+    fn test_messages_are_redacted() {
+        let errors = ErrorRegistry::default();
+        let entry = errors.record(
+            ErrorCategory::Translation,
+            r#"401 - {"error":"invalid x-api-key: sk-ant-api03-abcDEF_123"} Authorization: Bearer abc.def api_key=hunter2"#,
+        );
+        assert!(!entry.message.contains("abcDEF"));
+        assert!(!entry.message.contains("abc.def"));
+        assert!(!entry.message.contains("hunter2"));
+        assert!(entry.message.contains("Bearer [redacted]"));
+
+        let entry = errors.record(ErrorCategory::Translation, "x".repeat(1000));
+        assert_eq!(entry.message.chars().count(), MAX_MESSAGE_CHARS + 1);
+    }
+}
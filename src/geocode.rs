@@ -0,0 +1,375 @@
+//! Reverse geocoding and map previews for Location messages.
+//!
+//! A shared location only carries coordinates (plus a name and address when
+//! it's a venue). Bare coordinates are looked up with a reverse geocoder,
+//! OpenStreetMap's Nominatim by default, and the address is stored in the
+//! message's content. Lookups are cached by rounded coordinates and kept to
+//! one a second, as Nominatim's usage policy asks.
+//!
+//! Map previews are fetched by the server from a static map service and
+//! passed on through `/api/map-thumb`, so viewers' browsers never contact
+//! the tile server themselves.
+
+use anyhow::{anyhow, Context, Result};
+use reqwest::Client;
+use serde::Deserialize;
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use tracing::debug;
+
+use crate::storage::{MessageStore, StoredMessage};
+
+/// Nominatim's reverse geocoding endpoint
+pub const DEFAULT_GEOCODER_URL: &str = "https://nominatim.openstreetmap.org/reverse";
+
+/// OpenStreetMap static map, with `{lat}`, `{lon}` and `{zoom}` placeholders
+pub const DEFAULT_MAP_TEMPLATE: &str = "https://staticmap.openstreetmap.de/staticmap.php?center={lat},{lon}&zoom={zoom}&size=300x150&markers={lat},{lon},red-pushpin";
+
+/// Zoom level of map previews
+const MAP_ZOOM: u8 = 15;
+
+/// Shortest gap between two geocoder requests
+const MIN_REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Request timeout for geocoding and map previews
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest map preview passed on (2MB)
+const MAX_MAP_SIZE: usize = 2 * 1024 * 1024;
+
+/// Nominatim requires an identifying user agent
+const USER_AGENT: &str = "WhatsAppTranslator/1.0 (+https://github.com/vultuk/whatsapp-translator)";
+
+/// Content key holding the address found for bare coordinates
+pub const ADDRESS_KEY: &str = "resolved_address";
+
+/// Content key holding the map preview's URL (served by `/api/map-thumb`)
+pub const MAP_THUMBNAIL_KEY: &str = "map_thumbnail_url";
+
+/// Where lookups and map previews come from
+#[derive(Debug, Clone)]
+pub struct GeocoderConfig {
+    /// Reverse geocoding endpoint (Nominatim API), or None to not geocode
+    pub geocoder_url: Option<String>,
+    /// Static map URL template
+    pub map_template: String,
+}
+
+impl Default for GeocoderConfig {
+    fn default() -> Self {
+        Self {
+            geocoder_url: Some(DEFAULT_GEOCODER_URL.to_string()),
+            map_template: DEFAULT_MAP_TEMPLATE.to_string(),
+        }
+    }
+}
+
+/// Reverse geocoding response (Nominatim `format=jsonv2`)
+#[derive(Deserialize)]
+struct ReverseResponse {
+    display_name: Option<String>,
+}
+
+/// Reverse geocoder with a cache and rate limit
+pub struct Geocoder {
+    client: Client,
+    config: RwLock<GeocoderConfig>,
+    /// When the last geocoder request was made; held during a request
+    last_request: Mutex<Option<Instant>>,
+}
+
+impl Default for Geocoder {
+    fn default() -> Self {
+        Self {
+            client: Client::builder()
+                .timeout(FETCH_TIMEOUT)
+                .user_agent(USER_AGENT)
+                .build()
+                .unwrap_or_default(),
+            config: RwLock::new(GeocoderConfig::default()),
+            last_request: Mutex::new(None),
+        }
+    }
+}
+
+/// Cache key for coordinates, rounded to about 11 metres
+pub fn cache_key(latitude: f64, longitude: f64) -> String {
+    format!("{:.4},{:.4}", latitude, longitude)
+}
+
+/// Whether coordinates are on the globe
+pub fn valid_coordinates(latitude: f64, longitude: f64) -> bool {
+    (-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude)
+}
+
+/// Coordinates of a location message without an address, if it is one
+fn bare_coordinates(content: &serde_json::Value) -> Option<(f64, f64)> {
+    if content.get("type").and_then(|t| t.as_str()) != Some("location")
+        || content.get("address").is_some()
+        || content.get(ADDRESS_KEY).is_some()
+    {
+        return None;
+    }
+    let latitude = content.get("latitude")?.as_f64()?;
+    let longitude = content.get("longitude")?.as_f64()?;
+    valid_coordinates(latitude, longitude).then_some((latitude, longitude))
+}
+
+/// Add the map preview URL to a location message
+pub fn attach_map_thumbnail(message: &mut StoredMessage) {
+    let Ok(mut content) = serde_json::from_str::<serde_json::Value>(&message.content_json) else {
+        return;
+    };
+    if content.get("type").and_then(|t| t.as_str()) != Some("location") {
+        return;
+    }
+    let (Some(latitude), Some(longitude)) = (
+        content.get("latitude").and_then(|v| v.as_f64()),
+        content.get("longitude").and_then(|v| v.as_f64()),
+    ) else {
+        return;
+    };
+    if !valid_coordinates(latitude, longitude) {
+        return;
+    }
+    content[MAP_THUMBNAIL_KEY] =
+        format!("/api/map-thumb?lat={}&lon={}", latitude, longitude).into();
+    message.content_json = content.to_string();
+    message.content = Some(content);
+}
+
+impl Geocoder {
+    pub fn set_config(&self, config: GeocoderConfig) {
+        *self.config.write().unwrap() = config;
+    }
+
+    fn config(&self) -> GeocoderConfig {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Address of a location message that only has coordinates. None if
+    /// it isn't one, geocoding is off, or nothing was found; errors are for
+    /// failed lookups, which aren't cached
+    pub async fn address_for(
+        &self,
+        store: &MessageStore,
+        message: &StoredMessage,
+    ) -> Result<Option<String>> {
+        let Some((latitude, longitude)) = message.content.as_ref().and_then(bare_coordinates)
+        else {
+            return Ok(None);
+        };
+        self.reverse(store, latitude, longitude).await
+    }
+
+    /// Address at some coordinates, from the cache or the geocoder
+    pub async fn reverse(
+        &self,
+        store: &MessageStore,
+        latitude: f64,
+        longitude: f64,
+    ) -> Result<Option<String>> {
+        let Some(url) = self.config().geocoder_url else {
+            return Ok(None);
+        };
+        let key = cache_key(latitude, longitude);
+        if let Some(cached) = store.get_geocode(&key)? {
+            return Ok(cached);
+        }
+
+        let mut last_request = self.last_request.lock().await;
+        // Another lookup may have filled the cache while this one waited
+        if let Some(cached) = store.get_geocode(&key)? {
+            return Ok(cached);
+        }
+        if let Some(last) = *last_request {
+            tokio::time::sleep_until(last + MIN_REQUEST_INTERVAL).await;
+        }
+        *last_request = Some(Instant::now());
+
+        debug!("Reverse geocoding {}", key);
+        let response = self
+            .client
+            .get(&url)
+            .query(&[
+                ("format", "jsonv2"),
+                ("lat", &latitude.to_string()),
+                ("lon", &longitude.to_string()),
+            ])
+            .send()
+            .await
+            .context("Failed to reach the geocoder")?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Geocoder returned HTTP {}", response.status()));
+        }
+        let address = response
+            .json::<ReverseResponse>()
+            .await
+            .context("Failed to parse the geocoder response")?
+            .display_name
+            .filter(|name| !name.trim().is_empty());
+        // Nowhere in particular (e.g. open sea) is cached too
+        store.save_geocode(&key, address.as_deref())?;
+        Ok(address)
+    }
+
+    /// Fetch the map preview image for some coordinates: the bytes and
+    /// their content type
+    pub async fn map_thumbnail(&self, latitude: f64, longitude: f64) -> Result<(Vec<u8>, String)> {
+        let url = self
+            .config()
+            .map_template
+            .replace("{lat}", &format!("{:.5}", latitude))
+            .replace("{lon}", &format!("{:.5}", longitude))
+            .replace("{zoom}", &MAP_ZOOM.to_string());
+        let response = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .context("Failed to reach the map service")?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Map service returned HTTP {}", response.status()));
+        }
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_string();
+        if !content_type.starts_with("image/") {
+            return Err(anyhow!(
+                "Map service returned {:?}, not an image",
+                content_type
+            ));
+        }
+        let bytes = response
+            .bytes()
+            .await
+            .context("Failed to read the map preview")?;
+        if bytes.len() > MAX_MAP_SIZE {
+            return Err(anyhow!("Map preview too large"));
+        }
+        Ok((bytes.to_vec(), content_type))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{test_store, text_message};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Geocoder that names every place after its coordinates, counting requests
+    async fn spawn_geocoder() -> (String, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let app = axum::Router::new().route(
+            "/reverse",
+            axum::routing::get(
+                move |axum::extract::Query(query): axum::extract::Query<
+                    std::collections::HashMap<String, String>,
+                >| {
+                    let counter = counter.clone();
+                    async move {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        if query["lat"] == "0" {
+                            return axum::Json(serde_json::json!({"error": "Unable to geocode"}));
+                        }
+                        axum::Json(serde_json::json!({
+                            "display_name": format!("Near {}, {}", query["lat"], query["lon"])
+                        }))
+                    }
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{}/reverse", addr), hits)
+    }
+
+    fn location(latitude: f64, longitude: f64) -> StoredMessage {
+        let content = serde_json::json!({
+            "type": "location",
+            "latitude": latitude,
+            "longitude": longitude,
+        });
+        StoredMessage {
+            content_type: "Location".to_string(),
+            content_json: content.to_string(),
+            content: Some(content),
+            original_text: None,
+            ..text_message("loc", "a@s.whatsapp.net", 1, "")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reverse_geocoding_is_cached() {
+        let (store, _dir) = test_store();
+        let (url, hits) = spawn_geocoder().await;
+        let geocoder = Geocoder::default();
+        geocoder.set_config(GeocoderConfig {
+            geocoder_url: Some(url),
+            ..Default::default()
+        });
+
+        let address = geocoder
+            .address_for(&store, &location(48.85837, 2.29448))
+            .await
+            .unwrap();
+        assert_eq!(address.as_deref(), Some("Near 48.85837, 2.29448"));
+        // A few metres away: the same rounded coordinates, so no new lookup
+        let nearby = geocoder.reverse(&store, 48.85841, 2.29451).await.unwrap();
+        assert_eq!(nearby, address);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        // Nothing found is cached too; the second lookup waited its turn
+        let started = Instant::now();
+        assert_eq!(geocoder.reverse(&store, 0.0, 0.0).await.unwrap(), None);
+        assert_eq!(geocoder.reverse(&store, 0.0, 0.0).await.unwrap(), None);
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+        assert!(started.elapsed() >= Duration::from_millis(900));
+
+        // Venues already have an address; other messages aren't locations
+        let mut venue = location(51.5, -0.12);
+        venue.content.as_mut().unwrap()["address"] = "Trafalgar Square".into();
+        assert_eq!(geocoder.address_for(&store, &venue).await.unwrap(), None);
+        let mut text = location(51.5, -0.12);
+        text.content = Some(serde_json::json!({"type": "text", "body": "hi"}));
+        assert_eq!(geocoder.address_for(&store, &text).await.unwrap(), None);
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+        // An unreachable geocoder is an error, and isn't cached
+        geocoder.set_config(GeocoderConfig {
+            geocoder_url: Some("http://127.0.0.1:1/reverse".to_string()),
+            ..Default::default()
+        });
+        assert!(geocoder.reverse(&store, 40.4168, -3.7038).await.is_err());
+        assert_eq!(
+            store.get_geocode(&cache_key(40.4168, -3.7038)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_attach_map_thumbnail() {
+        let mut message = location(48.85837, 2.29448);
+        attach_map_thumbnail(&mut message);
+        assert_eq!(
+            message.content.as_ref().unwrap()[MAP_THUMBNAIL_KEY],
+            "/api/map-thumb?lat=48.85837&lon=2.29448"
+        );
+
+        let mut off_the_globe = location(123.0, 2.0);
+        attach_map_thumbnail(&mut off_the_globe);
+        assert!(off_the_globe
+            .content
+            .unwrap()
+            .get(MAP_THUMBNAIL_KEY)
+            .is_none());
+    }
+}
@@ -0,0 +1,333 @@
+//! Creating WhatsApp groups.
+//!
+//! Participants are given as contact IDs or phone numbers; numbers go
+//! through the same normalization as starting a new chat. The bridge creates
+//! the group and answers with its JID, which becomes a local contact so the
+//! group shows up in the chat list straight away.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicI32, Ordering};
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+use crate::bridge::BridgeCommand;
+use crate::new_chat::normalize_phone;
+use crate::pending::{CommandError, PendingRequests, DEFAULT_COMMAND_TIMEOUT};
+use crate::storage::MessageStore;
+
+/// Most participants a new group can start with
+pub const MAX_PARTICIPANTS: usize = 255;
+
+/// Longest group subject WhatsApp accepts, in characters
+pub const MAX_SUBJECT_CHARS: usize = 100;
+
+/// Errors returned when creating a group
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum GroupError {
+    InvalidSubject,
+    NoParticipants,
+    TooManyParticipants,
+    /// A participant that is neither a contact nor a valid phone number
+    InvalidParticipant(String),
+    BridgeUnavailable,
+    Timeout,
+    /// The bridge's error message
+    Failed(String),
+    StorageError,
+}
+
+impl GroupError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GroupError::InvalidSubject => "invalid_subject",
+            GroupError::NoParticipants => "no_participants",
+            GroupError::TooManyParticipants => "too_many_participants",
+            GroupError::InvalidParticipant(_) => "invalid_participant",
+            GroupError::BridgeUnavailable => "bridge_unavailable",
+            GroupError::Timeout => "timeout",
+            GroupError::Failed(_) => "create_failed",
+            GroupError::StorageError => "storage_error",
+        }
+    }
+
+    pub fn description(&self) -> &str {
+        match self {
+            GroupError::InvalidSubject => "The group name must be 1 to 100 characters",
+            GroupError::NoParticipants => "A group needs at least one participant",
+            GroupError::TooManyParticipants => "A group can start with at most 255 participants",
+            GroupError::InvalidParticipant(_) => {
+                "Participants must be contacts or valid phone numbers"
+            }
+            GroupError::BridgeUnavailable => "WhatsApp is not connected",
+            GroupError::Timeout => "Timed out waiting for WhatsApp to create the group",
+            GroupError::Failed(message) => message,
+            GroupError::StorageError => "Failed to save the group",
+        }
+    }
+}
+
+/// The bridge's answer to a create group command
+#[derive(Debug, Clone)]
+pub struct GroupCreation {
+    pub jid: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Group creations waiting for the bridge
+#[derive(Default)]
+pub struct PendingGroups {
+    requests: PendingRequests<GroupCreation>,
+    next_id: AtomicI32,
+}
+
+impl PendingGroups {
+    /// Handle a group created event from the bridge
+    pub fn complete(&self, request_id: i32, result: GroupCreation) {
+        self.requests.complete(request_id, result);
+    }
+
+    /// Drop creations nobody is waiting for any more
+    pub fn sweep(&self) -> usize {
+        self.requests.sweep()
+    }
+}
+
+/// A newly created group
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedGroup {
+    pub contact_id: String,
+    pub subject: String,
+    pub participants: Vec<String>,
+}
+
+/// Check the subject, returning it trimmed
+fn validate_subject(subject: &str) -> Result<String, GroupError> {
+    let subject = subject.trim();
+    if subject.is_empty() || subject.chars().count() > MAX_SUBJECT_CHARS {
+        return Err(GroupError::InvalidSubject);
+    }
+    Ok(subject.to_string())
+}
+
+/// Turn contact IDs and phone numbers into participant JIDs, without
+/// duplicates. JIDs must be existing private contacts; anything else is
+/// parsed as a phone number (using `region` when it has no country code).
+pub fn resolve_participants(
+    store: &MessageStore,
+    participants: &[String],
+    region: Option<&str>,
+) -> Result<Vec<String>, GroupError> {
+    let mut jids: Vec<String> = Vec::with_capacity(participants.len());
+    for participant in participants {
+        let participant = participant.trim();
+        let jid = if participant.contains('@') {
+            let contact = store.get_contact(participant).map_err(|e| {
+                error!("Failed to look up participant {}: {}", participant, e);
+                GroupError::StorageError
+            })?;
+            match contact {
+                Some(contact) if contact.contact_type.as_deref() != Some("group") => contact.id,
+                _ => return Err(GroupError::InvalidParticipant(participant.to_string())),
+            }
+        } else {
+            let phone = normalize_phone(participant, region)
+                .map_err(|_| GroupError::InvalidParticipant(participant.to_string()))?;
+            format!("{}@s.whatsapp.net", phone)
+        };
+        if !jids.contains(&jid) {
+            jids.push(jid);
+        }
+    }
+
+    match jids.len() {
+        0 => Err(GroupError::NoParticipants),
+        n if n > MAX_PARTICIPANTS => Err(GroupError::TooManyParticipants),
+        _ => Ok(jids),
+    }
+}
+
+/// Create a group with `participants` (contact IDs or phone numbers) and
+/// save it as a contact
+pub async fn create_group(
+    store: &MessageStore,
+    command_tx: Option<&mpsc::Sender<BridgeCommand>>,
+    pending: &PendingGroups,
+    subject: &str,
+    participants: &[String],
+    region: Option<&str>,
+) -> Result<CreatedGroup, GroupError> {
+    let subject = validate_subject(subject)?;
+    let participant_jids = resolve_participants(store, participants, region)?;
+
+    let request_id = pending.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+    let cmd = BridgeCommand::CreateGroup {
+        request_id,
+        subject: subject.clone(),
+        participant_jids: participant_jids.clone(),
+    };
+    let result = pending
+        .requests
+        .send_and_wait(command_tx, request_id, cmd, DEFAULT_COMMAND_TIMEOUT)
+        .await
+        .map_err(|e| match e {
+            CommandError::NotConnected => GroupError::BridgeUnavailable,
+            CommandError::Timeout => GroupError::Timeout,
+        })?;
+
+    let jid = match result {
+        GroupCreation { error: Some(e), .. } => {
+            error!("Bridge failed to create group {:?}: {}", subject, e);
+            return Err(GroupError::Failed(e));
+        }
+        GroupCreation { jid: Some(jid), .. } if !jid.is_empty() => jid,
+        GroupCreation { .. } => {
+            return Err(GroupError::Failed(
+                "WhatsApp didn't return the new group".to_string(),
+            ))
+        }
+    };
+
+    let now = chrono::Utc::now().timestamp_millis();
+    store
+        .upsert_contact(&jid, Some(&subject), None, Some("group"), now)
+        .map_err(|e| {
+            error!("Failed to save group {}: {}", jid, e);
+            GroupError::StorageError
+        })?;
+
+    info!(
+        "Created group {} with {} participants",
+        jid,
+        participant_jids.len()
+    );
+    Ok(CreatedGroup {
+        contact_id: jid,
+        subject,
+        participants: participant_jids,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_store;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_validation() {
+        let (store, _dir) = test_store();
+        store
+            .upsert_contact(
+                "447911123456@s.whatsapp.net",
+                Some("Alice"),
+                Some("447911123456"),
+                Some("private"),
+                1,
+            )
+            .unwrap();
+        store
+            .upsert_contact("123@g.us", Some("Family"), None, Some("group"), 1)
+            .unwrap();
+
+        // Contacts and numbers, with duplicates dropped
+        let participants = vec![
+            "447911123456@s.whatsapp.net".to_string(),
+            "+44 7911 123456".to_string(),
+            "07911 123457".to_string(),
+        ];
+        assert_eq!(
+            resolve_participants(&store, &participants, Some("GB")).unwrap(),
+            ["447911123456@s.whatsapp.net", "447911123457@s.whatsapp.net"]
+        );
+
+        for participant in ["999@s.whatsapp.net", "123@g.us", "not a number"] {
+            assert_eq!(
+                resolve_participants(&store, &[participant.to_string()], None),
+                Err(GroupError::InvalidParticipant(participant.to_string()))
+            );
+        }
+        assert_eq!(
+            resolve_participants(&store, &[], None),
+            Err(GroupError::NoParticipants)
+        );
+        let too_many: Vec<String> = (0..=MAX_PARTICIPANTS)
+            .map(|i| format!("+447911123{:03}", i))
+            .collect();
+        assert_eq!(
+            resolve_participants(&store, &too_many, None),
+            Err(GroupError::TooManyParticipants)
+        );
+
+        assert_eq!(validate_subject("  "), Err(GroupError::InvalidSubject));
+        assert_eq!(
+            validate_subject(&"x".repeat(MAX_SUBJECT_CHARS + 1)),
+            Err(GroupError::InvalidSubject)
+        );
+        assert_eq!(validate_subject(" Trip ").unwrap(), "Trip");
+    }
+
+    #[tokio::test]
+    async fn test_create_group() {
+        let (store, _dir) = test_store();
+        let pending = Arc::new(PendingGroups::default());
+        let (tx, mut rx) = mpsc::channel(4);
+        let participants = vec!["+44 7911 123456".to_string()];
+
+        assert_eq!(
+            create_group(&store, None, &pending, "Trip", &participants, None)
+                .await
+                .unwrap_err(),
+            GroupError::BridgeUnavailable
+        );
+
+        // The bridge's error is passed on
+        let bridge = pending.clone();
+        let answer = tokio::spawn(async move {
+            let Some(BridgeCommand::CreateGroup { request_id, .. }) = rx.recv().await else {
+                panic!("expected a create group command");
+            };
+            bridge.complete(
+                request_id,
+                GroupCreation {
+                    jid: None,
+                    error: Some("rate-overlimit".to_string()),
+                },
+            );
+
+            let Some(BridgeCommand::CreateGroup {
+                request_id,
+                subject,
+                participant_jids,
+            }) = rx.recv().await
+            else {
+                panic!("expected a create group command");
+            };
+            assert_eq!(subject, "Trip");
+            assert_eq!(participant_jids, ["447911123456@s.whatsapp.net"]);
+            bridge.complete(
+                request_id,
+                GroupCreation {
+                    jid: Some("120363000000000001@g.us".to_string()),
+                    error: None,
+                },
+            );
+        });
+        assert_eq!(
+            create_group(&store, Some(&tx), &pending, "Trip", &participants, None)
+                .await
+                .unwrap_err(),
+            GroupError::Failed("rate-overlimit".to_string())
+        );
+
+        let group = create_group(&store, Some(&tx), &pending, " Trip ", &participants, None)
+            .await
+            .unwrap();
+        answer.await.unwrap();
+        assert_eq!(group.contact_id, "120363000000000001@g.us");
+
+        let contact = store.get_contact(&group.contact_id).unwrap().unwrap();
+        assert_eq!(contact.name.as_deref(), Some("Trip"));
+        assert_eq!(contact.contact_type.as_deref(), Some("group"));
+    }
+}
@@ -0,0 +1,135 @@
+//! History sync progress.
+//!
+//! A fresh pairing imports a large history sync. Rather than refreshing a
+//! chat's preview (a window-function query over its messages) and
+//! broadcasting it for every history message, the chats touched are
+//! collected and refreshed once, when the sync completes or the bridge
+//! disconnects.
+//!
+//! History messages are also stored in batches, each written in a single
+//! transaction, instead of one transaction per contact, message and unread
+//! count update.
+
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::storage::StoredMessage;
+
+/// History messages stored together at most
+pub const BATCH_SIZE: usize = 200;
+
+/// Longest a history message waits for its batch to fill
+pub const BATCH_DELAY: Duration = Duration::from_millis(500);
+
+/// How far a history sync has got
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncProgress {
+    pub chats_done: u32,
+    pub chats_total: u32,
+    pub messages_done: u32,
+    pub complete: bool,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Latest progress of the sync running, if any
+    running: Option<SyncProgress>,
+    /// Contacts whose updates are held back until it ends
+    deferred: HashSet<String>,
+}
+
+/// The history sync being imported, if any
+#[derive(Default)]
+pub struct HistorySync {
+    inner: Mutex<Inner>,
+}
+
+impl HistorySync {
+    /// Progress of the sync running, if any
+    pub fn progress(&self) -> Option<SyncProgress> {
+        self.inner.lock().unwrap().running
+    }
+
+    /// Record progress. Once the sync completes, returns the contacts whose
+    /// updates were held back.
+    pub fn update(&self, progress: SyncProgress) -> Option<Vec<String>> {
+        let mut inner = self.inner.lock().unwrap();
+        if progress.complete {
+            inner.running = None;
+            Some(inner.deferred.drain().collect())
+        } else {
+            inner.running = Some(progress);
+            None
+        }
+    }
+
+    /// Hold back a contact's update while a sync runs. False if none is.
+    pub fn defer(&self, contact_id: &str) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.running.is_none() {
+            return false;
+        }
+        if !inner.deferred.contains(contact_id) {
+            inner.deferred.insert(contact_id.to_string());
+        }
+        true
+    }
+
+    /// Give up on the sync (the bridge went away), returning the contacts
+    /// whose updates were held back
+    pub fn abandon(&self) -> Vec<String> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.running = None;
+        inner.deferred.drain().collect()
+    }
+}
+
+/// A history message waiting to be stored
+pub struct BatchedMessage {
+    pub message: StoredMessage,
+    /// The chat's unread count from WhatsApp, if it came with one
+    pub unread_count: Option<u32>,
+}
+
+#[derive(Default)]
+struct Batch {
+    messages: Vec<BatchedMessage>,
+    /// When the oldest message was added
+    started: Option<Instant>,
+}
+
+/// History messages waiting to be stored together
+#[derive(Default)]
+pub struct HistoryBatch {
+    inner: Mutex<Batch>,
+}
+
+impl HistoryBatch {
+    /// Add a message. True once the batch is full and should be stored.
+    pub fn push(&self, message: BatchedMessage) -> bool {
+        let mut batch = self.inner.lock().unwrap();
+        batch.started.get_or_insert_with(Instant::now);
+        batch.messages.push(message);
+        batch.messages.len() >= BATCH_SIZE
+    }
+
+    /// When the batch should be stored if it doesn't fill, if it has
+    /// anything in it
+    pub fn deadline(&self) -> Option<Instant> {
+        self.inner
+            .lock()
+            .unwrap()
+            .started
+            .map(|started| started + BATCH_DELAY)
+    }
+
+    /// Take the messages waiting, oldest first
+    pub fn take(&self) -> Vec<BatchedMessage> {
+        let mut batch = self.inner.lock().unwrap();
+        batch.started = None;
+        std::mem::take(&mut batch.messages)
+    }
+}
@@ -0,0 +1,614 @@
+//! Importing WhatsApp's "Export chat" text files into the archive.
+//!
+//! Exports come in two layouts, depending on the phone they came from:
+//!
+//! ```text
+//! [15/01/2024, 14:32:05] Alice: Are we still on for Friday?   (iPhone)
+//! 15/01/2024, 14:32 - Alice: Are we still on for Friday?      (Android)
+//! ```
+//!
+//! with the date and time written the phone's way. A line that doesn't start
+//! a message continues the one before. Whether dates are day or month first
+//! is worked out from the whole file, and times are taken as local time
+//! here. Each message's ID is derived from the chat, time, sender and text,
+//! so importing the same export again adds nothing. Imported messages are
+//! never translated.
+
+use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use regex::{Captures, Regex};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use tracing::{error, info};
+
+use crate::bridge::MessageContent;
+use crate::storage::{MessageStore, StoredMessage};
+use crate::translation::TranslationStatus;
+
+/// Prefix of imported messages' IDs
+pub const IMPORT_ID_PREFIX: &str = "import_";
+
+/// Words WhatsApp uses for a file left out of an export, in the locales
+/// seen so far ("image omitted", "<Medien ausgelassen>", "imagen omitida"...)
+const OMITTED_WORDS: &[&str] = &[
+    "omitted",
+    "ausgelassen",
+    "weggelassen",
+    "omitido",
+    "omitida",
+    "omis",
+    "omise",
+    "absente",
+    "weggelaten",
+    "oculta",
+    "omesso",
+    "omessa",
+    "omessi",
+];
+
+/// Prefixes of the file names exports are given, before the chat's name
+const FILE_NAME_PREFIXES: &[&str] = &[
+    "WhatsApp Chat with ",
+    "WhatsApp Chat - ",
+    "WhatsApp-Chat mit ",
+    "Chat de WhatsApp con ",
+    "Discussion WhatsApp avec ",
+    "Conversa do WhatsApp com ",
+    "Chat WhatsApp con ",
+];
+
+/// Left-to-right marks: iPhone exports put one before notices and
+/// attachments
+const DIRECTION_MARKS: &[char] = &['\u{feff}', '\u{200e}', '\u{200f}'];
+
+/// A message read from an export
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportEntry {
+    pub time: NaiveDateTime,
+    /// None for WhatsApp's own notices ("Messages and calls are end-to-end
+    /// encrypted", "Alice added Bob")
+    pub sender: Option<String>,
+    /// The message, or the omitted file's name if there was one
+    pub text: String,
+    /// WhatsApp left the file this message carried out of the export
+    pub media_omitted: bool,
+}
+
+/// What an import did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    /// Messages and notices read from the file
+    pub parsed: usize,
+    /// Messages newly stored
+    pub imported: usize,
+    /// Notices, and messages stored by an earlier import
+    pub skipped: usize,
+}
+
+/// Errors returned when importing an export
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum ImportError {
+    InvalidContact,
+    /// Nothing in the file looked like a WhatsApp message
+    NotAnExport,
+    /// The disk is nearly full, so nothing is written
+    ReadOnly,
+    StorageError,
+}
+
+impl ImportError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportError::InvalidContact => "invalid_contact",
+            ImportError::NotAnExport => "not_an_export",
+            ImportError::ReadOnly => "read_only",
+            ImportError::StorageError => "storage_error",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            ImportError::InvalidContact => {
+                "The chat must be a WhatsApp ID, like 447911123456@s.whatsapp.net"
+            }
+            ImportError::NotAnExport => "The file isn't a WhatsApp chat export (.txt)",
+            ImportError::ReadOnly => "The disk is nearly full; free some space and import again",
+            ImportError::StorageError => "Failed to save the imported messages",
+        }
+    }
+}
+
+/// Date and time of a message as written, before the day/month order is known
+struct RawEntry {
+    date: [u32; 3],
+    time: [u32; 3],
+    pm: Option<bool>,
+    rest: String,
+}
+
+/// Read the messages in an export, oldest first
+pub fn parse_export(export: &str) -> Vec<ExportEntry> {
+    const DATE: &str = r"(\d{1,4})[./-](\d{1,2})[./-](\d{1,4})";
+    const TIME: &str = r"(\d{1,2})[:.](\d{2})(?:[:.](\d{2}))?";
+    const AM_PM: &str = r"(?:[\s\u{202f}\u{a0}]*([AaPp])\.?\s?[Mm]\.?)?";
+    let bracketed = Regex::new(&format!(r"^\[{DATE},?\s+{TIME}{AM_PM}\]\s*(.*)$")).unwrap();
+    let dashed = Regex::new(&format!(r"^{DATE},?\s+{TIME}{AM_PM}\s+[-–]\s+(.*)$")).unwrap();
+
+    let mut raw: Vec<RawEntry> = Vec::new();
+    for line in export.lines() {
+        let line = line.trim_start_matches(DIRECTION_MARKS);
+        let header = bracketed.captures(line).or_else(|| dashed.captures(line));
+        match header {
+            Some(caps) => {
+                let number = |i: usize| caps.get(i).map_or(0, |m| m.as_str().parse().unwrap_or(0));
+                raw.push(RawEntry {
+                    date: [number(1), number(2), number(3)],
+                    time: [number(4), number(5), number(6)],
+                    pm: am_pm(&caps),
+                    rest: caps[8].to_string(),
+                });
+            }
+            // Anything before the first message isn't part of one
+            None => {
+                if let Some(entry) = raw.last_mut() {
+                    entry.rest.push('\n');
+                    entry.rest.push_str(line);
+                }
+            }
+        }
+    }
+
+    // Day first unless a date only makes sense month first, or the times are
+    // 12 hour (US style) and nothing says otherwise
+    let dated = || raw.iter().filter(|e| e.date[0] < 1000);
+    let day_first = if dated().any(|e| e.date[0] > 12) {
+        true
+    } else if dated().any(|e| e.date[1] > 12) {
+        false
+    } else {
+        !raw.iter().any(|e| e.pm.is_some())
+    };
+
+    raw.into_iter()
+        .filter_map(|entry| {
+            let time = entry_time(&entry, day_first)?;
+            let (sender, text) = split_sender(&entry.rest);
+            let text = text.trim_end();
+            Some(match omitted_file(text) {
+                Some(file_name) => ExportEntry {
+                    time,
+                    sender,
+                    text: file_name,
+                    media_omitted: true,
+                },
+                // iPhone exports write notices as if the chat sent them,
+                // marked like attachments
+                None if text.starts_with(DIRECTION_MARKS) => ExportEntry {
+                    time,
+                    sender: None,
+                    text: text.trim_start_matches(DIRECTION_MARKS).to_string(),
+                    media_omitted: false,
+                },
+                None => ExportEntry {
+                    time,
+                    sender,
+                    text: text.to_string(),
+                    media_omitted: false,
+                },
+            })
+        })
+        .collect()
+}
+
+/// Whether a header's time is PM, AM or 24 hour (None)
+fn am_pm(caps: &Captures) -> Option<bool> {
+    caps.get(7).map(|m| m.as_str().eq_ignore_ascii_case("p"))
+}
+
+fn entry_time(entry: &RawEntry, day_first: bool) -> Option<NaiveDateTime> {
+    let [a, b, c] = entry.date;
+    let (year, month, day) = if a >= 1000 {
+        (a, b, c)
+    } else if day_first {
+        (c, b, a)
+    } else {
+        (c, a, b)
+    };
+    let year = if year < 100 { 2000 + year } else { year };
+    let date = NaiveDate::from_ymd_opt(year as i32, month, day)?;
+
+    let [hour, minute, second] = entry.time;
+    let hour = match entry.pm {
+        Some(pm) if hour <= 12 => hour % 12 + if pm { 12 } else { 0 },
+        Some(_) => return None,
+        None => hour,
+    };
+    Some(date.and_time(NaiveTime::from_hms_opt(hour, minute, second)?))
+}
+
+/// The sender and text of a message line; notices have no sender
+fn split_sender(rest: &str) -> (Option<String>, &str) {
+    // Only the first line can name the sender
+    let first_line = rest.lines().next().unwrap_or_default();
+    match first_line.split_once(": ") {
+        Some((sender, _)) if !sender.trim().is_empty() => {
+            let text = &rest[sender.len() + 2..];
+            (
+                Some(sender.trim_matches(DIRECTION_MARKS).trim().to_string()),
+                text.trim_start_matches(' '),
+            )
+        }
+        _ => (None, rest),
+    }
+}
+
+/// The file name (possibly empty) if the text is WhatsApp's placeholder for
+/// a file left out of the export
+fn omitted_file(text: &str) -> Option<String> {
+    let text = text.trim_start_matches(DIRECTION_MARKS).trim();
+    if text.contains('\n') {
+        return None;
+    }
+    let is_omitted = |word: &str| {
+        let word = word
+            .trim_matches(|c: char| !c.is_alphanumeric())
+            .to_lowercase();
+        OMITTED_WORDS.contains(&word.as_str())
+    };
+
+    if let Some(inner) = text.strip_prefix('<').and_then(|t| t.strip_suffix('>')) {
+        // "<attached: 00000012-PHOTO-2024-01-16-09-02-00.jpg>"
+        if let Some((label, file_name)) = inner.split_once(':') {
+            if !label.contains(' ') && file_name.contains('.') {
+                return Some(file_name.trim().to_string());
+            }
+        }
+        return inner.split_whitespace().any(is_omitted).then(String::new);
+    }
+
+    // "image omitted", "Bild weggelassen", "imagen omitida"
+    let words: Vec<&str> = text.split_whitespace().collect();
+    (words.len() >= 2 && words.len() <= 3 && is_omitted(words[words.len() - 1])).then(String::new)
+}
+
+/// The chat's name from an export's file name, e.g. "Alice" from
+/// "WhatsApp Chat with Alice.txt"
+pub fn chat_name_from_file_name(file_name: &str) -> Option<String> {
+    let stem = file_name
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or(file_name)
+        .trim_end_matches(".txt");
+    FILE_NAME_PREFIXES
+        .iter()
+        .find_map(|prefix| stem.strip_prefix(prefix))
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+/// Deterministic ID for an imported message. `occurrence` counts identical
+/// messages before it in the same export ("ok", "ok").
+fn import_id(contact_id: &str, entry: &ExportEntry, occurrence: usize) -> String {
+    let mut hasher = Sha256::new();
+    for part in [
+        contact_id,
+        &entry.time.and_utc().timestamp().to_string(),
+        entry.sender.as_deref().unwrap_or_default(),
+        &entry.text,
+        &occurrence.to_string(),
+    ] {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    let hash: String = hasher
+        .finalize()
+        .iter()
+        .take(12)
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("{}{}", IMPORT_ID_PREFIX, hash)
+}
+
+/// Import an export's messages into the chat `contact_id`, creating the
+/// contact (named `name`) if it doesn't exist. Messages from `me` (default:
+/// my profile name) are stored as mine.
+pub fn import_chat_export(
+    store: &MessageStore,
+    export: &str,
+    contact_id: &str,
+    name: Option<&str>,
+    me: Option<&str>,
+) -> Result<ImportReport, ImportError> {
+    let contact_id = contact_id.trim();
+    if !contact_id.contains('@') || contact_id.starts_with('@') {
+        return Err(ImportError::InvalidContact);
+    }
+    let entries = parse_export(export);
+    let Some(last) = entries.last() else {
+        return Err(ImportError::NotAnExport);
+    };
+    if store.is_read_only() {
+        return Err(ImportError::ReadOnly);
+    }
+    let storage_error = |e: anyhow::Error| {
+        error!("Failed to import chat export into {}: {:#}", contact_id, e);
+        ImportError::StorageError
+    };
+
+    let own = store.get_own_profile().map_err(storage_error)?;
+    let me = me.map(str::to_string).or(own.name);
+    let existing = store.get_contact(contact_id).map_err(storage_error)?;
+    let chat_type = match existing.as_ref().and_then(|c| c.contact_type.clone()) {
+        Some(contact_type) => contact_type,
+        None if contact_id.ends_with("@g.us") => "group".to_string(),
+        None => "private".to_string(),
+    };
+    let contact_phone = contact_id
+        .strip_suffix("@s.whatsapp.net")
+        .map(str::to_string);
+    store
+        .upsert_contact(
+            contact_id,
+            existing.is_none().then_some(name).flatten(),
+            contact_phone.as_deref(),
+            Some(&chat_type),
+            timestamp_ms(last.time),
+        )
+        .map_err(storage_error)?;
+
+    let mut seen: HashMap<(i64, Option<&str>, &str), usize> = HashMap::new();
+    let mut messages = Vec::with_capacity(entries.len());
+    for entry in &entries {
+        let Some(sender) = entry.sender.as_deref() else {
+            continue;
+        };
+        let timestamp = timestamp_ms(entry.time);
+        let occurrence = seen
+            .entry((timestamp, entry.sender.as_deref(), &entry.text))
+            .and_modify(|n| *n += 1)
+            .or_insert(0);
+        let is_from_me = me.as_deref() == Some(sender);
+        let body = match (entry.media_omitted, entry.text.is_empty()) {
+            (true, true) => "[Media omitted]".to_string(),
+            (true, false) => format!("[Media omitted: {}]", entry.text),
+            (false, _) => entry.text.clone(),
+        };
+        let content = MessageContent::Text { body: body.clone() };
+        let content_json = serde_json::to_string(&content).unwrap_or_default();
+        messages.push(StoredMessage {
+            id: import_id(contact_id, entry, *occurrence),
+            contact_id: contact_id.to_string(),
+            timestamp,
+            is_from_me,
+            is_forwarded: false,
+            sender_name: Some(sender.to_string()),
+            sender_phone: if is_from_me {
+                own.phone.clone()
+            } else if chat_type == "private" {
+                contact_phone.clone()
+            } else {
+                None
+            },
+            contact_name: None,
+            contact_phone: None,
+            chat_type: chat_type.clone(),
+            content_type: content.type_name().to_string(),
+            content: serde_json::from_str(&content_json).ok(),
+            content_json,
+            original_text: (!is_from_me && !entry.media_omitted).then_some(body),
+            translated_text: None,
+            source_language: None,
+            is_translated: false,
+            origin: None,
+            sent_by: None,
+            mentioned_jids: Vec::new(),
+            mentions_me: false,
+            vocabulary: None,
+            audio: None,
+            sort_key: None,
+            triage: None,
+            translation_status: Some(if is_from_me || entry.media_omitted {
+                TranslationStatus::NotNeeded
+            } else {
+                // Never translated, and left out of retrying untranslated
+                TranslationStatus::SkippedFilter
+            }),
+        });
+    }
+
+    let imported = store.import_messages(&messages).map_err(|e| {
+        if store.is_read_only() {
+            ImportError::ReadOnly
+        } else {
+            storage_error(e)
+        }
+    })?;
+    let report = ImportReport {
+        parsed: entries.len(),
+        imported,
+        skipped: entries.len() - imported,
+    };
+    info!(
+        "Imported chat export into {}: {} parsed, {} imported, {} skipped",
+        contact_id, report.parsed, report.imported, report.skipped
+    );
+    Ok(report)
+}
+
+/// A time from an export, taken as local time here
+fn timestamp_ms(time: NaiveDateTime) -> i64 {
+    Local
+        .from_local_datetime(&time)
+        .earliest()
+        // Skipped by a clock change: near enough
+        .map_or_else(
+            || time.and_utc().timestamp_millis(),
+            |t| t.timestamp_millis(),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_store;
+
+    fn at(date: (i32, u32, u32), time: (u32, u32, u32)) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(date.0, date.1, date.2)
+            .unwrap()
+            .and_hms_opt(time.0, time.1, time.2)
+            .unwrap()
+    }
+
+    fn message(time: NaiveDateTime, sender: &str, text: &str) -> ExportEntry {
+        ExportEntry {
+            time,
+            sender: Some(sender.to_string()),
+            text: text.to_string(),
+            media_omitted: false,
+        }
+    }
+
+    #[test]
+    fn test_parse_ios_export() {
+        let entries = parse_export(include_str!("../tests/fixtures/exports/en_ios.txt"));
+        assert_eq!(entries.len(), 7);
+        assert_eq!(entries[0].sender, None);
+        assert!(entries[0]
+            .text
+            .starts_with("Messages and calls are end-to-end encrypted"));
+        assert_eq!(
+            entries[1],
+            message(
+                at((2024, 1, 15), (14, 32, 5)),
+                "Alice Smith",
+                "Are we still on for Friday?"
+            )
+        );
+        // Lines without a header continue the message
+        assert_eq!(
+            entries[2].text,
+            "Yes!\nI booked the table for 8\nsee you there"
+        );
+        assert!(entries[3].media_omitted);
+        assert_eq!(entries[3].text, "");
+        assert!(entries[4].media_omitted);
+        assert_eq!(entries[4].text, "00000012-PHOTO-2024-01-16-09-02-00.jpg");
+        assert_eq!(entries[5], entries[6]);
+    }
+
+    #[test]
+    fn test_parse_locales() {
+        // Month first with a 12 hour clock
+        let entries = parse_export(include_str!("../tests/fixtures/exports/en_us_android.txt"));
+        assert_eq!(entries.len(), 5);
+        assert_eq!(entries[0].sender, None);
+        assert_eq!(
+            entries[2],
+            message(
+                at((2024, 1, 15), (14, 33, 0)),
+                "Sam",
+                "Yes! 12/25 works too"
+            )
+        );
+        assert_eq!(entries[3].time, at((2024, 1, 15), (23, 59, 0)));
+        assert!(entries[3].media_omitted);
+        assert_eq!(entries[4].time, at((2024, 1, 16), (0, 5, 0)));
+
+        let entries = parse_export(include_str!("../tests/fixtures/exports/de_android.txt"));
+        assert_eq!(entries.len(), 4);
+        assert_eq!(
+            entries[2],
+            message(
+                at((2024, 1, 15), (14, 33, 0)),
+                "Sam",
+                "Ja, um 20:00\nIch habe reserviert"
+            )
+        );
+        assert!(entries[3].media_omitted);
+
+        let entries = parse_export(include_str!("../tests/fixtures/exports/es_ios.txt"));
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries[0],
+            message(
+                at((2024, 1, 15), (14, 32, 5)),
+                "Lucía",
+                "¿Seguimos con lo del viernes?"
+            )
+        );
+        assert!(entries[2].media_omitted);
+
+        // No comma after the date, and a space before the colon
+        let entries = parse_export(include_str!("../tests/fixtures/exports/fr_android.txt"));
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries[0],
+            message(
+                at((2024, 1, 15), (14, 32, 0)),
+                "Camille",
+                "Toujours partants pour vendredi ?"
+            )
+        );
+        assert!(entries[2].media_omitted);
+
+        assert!(parse_export("just some notes\nnot an export").is_empty());
+        assert_eq!(
+            chat_name_from_file_name("/tmp/WhatsApp Chat with Alice Smith.txt").as_deref(),
+            Some("Alice Smith")
+        );
+        assert_eq!(chat_name_from_file_name("notes.txt"), None);
+    }
+
+    #[test]
+    fn test_import_is_idempotent() {
+        let (store, _dir) = test_store();
+        let contact_id = "447911123456@s.whatsapp.net";
+        let export = include_str!("../tests/fixtures/exports/en_ios.txt");
+
+        assert_eq!(
+            import_chat_export(&store, export, "Alice", None, Some("Sam")),
+            Err(ImportError::InvalidContact)
+        );
+        assert_eq!(
+            import_chat_export(&store, "hello", contact_id, None, Some("Sam")),
+            Err(ImportError::NotAnExport)
+        );
+
+        let report =
+            import_chat_export(&store, export, contact_id, Some("Alice"), Some("Sam")).unwrap();
+        assert_eq!(
+            report,
+            ImportReport {
+                parsed: 7,
+                imported: 6,
+                skipped: 1,
+            }
+        );
+        let again =
+            import_chat_export(&store, export, contact_id, Some("Alice"), Some("Sam")).unwrap();
+        assert_eq!((again.imported, again.skipped), (0, 7));
+
+        let contact = store.get_contact(contact_id).unwrap().unwrap();
+        assert_eq!(contact.name.as_deref(), Some("Alice"));
+        assert_eq!(contact.unread_count, 0);
+
+        let messages = store.get_messages(contact_id).unwrap();
+        assert_eq!(messages.len(), 6);
+        assert!(messages.iter().all(|m| m.id.starts_with(IMPORT_ID_PREFIX)));
+        let yes = &messages[1];
+        assert!(yes.is_from_me);
+        assert_eq!(yes.translation_status, Some(TranslationStatus::NotNeeded));
+        let question = &messages[0];
+        assert!(!question.is_from_me);
+        assert_eq!(question.sender_phone.as_deref(), Some("447911123456"));
+        assert_eq!(
+            question.translation_status,
+            Some(TranslationStatus::SkippedFilter)
+        );
+        assert_eq!(
+            messages[3].content.as_ref().unwrap()["body"],
+            "[Media omitted: 00000012-PHOTO-2024-01-16-09-02-00.jpg]"
+        );
+    }
+}
@@ -0,0 +1,551 @@
+//! Long-running background work with progress and cancellation.
+//!
+//! Work that takes a while (translating a chat's history again, exports,
+//! imports) runs as a job: it's queued, runs once a slot for its kind is
+//! free (`DEFAULT_CONCURRENCY` at a time unless set otherwise), reports
+//! progress as it goes and can be cancelled. Every change is broadcast to
+//! web clients as a `job_progress` event. Jobs that are running or queued
+//! are kept in memory; finished ones are saved in the `jobs` table, so
+//! what happened to them can be looked up later.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, watch, Semaphore};
+use tracing::{info, warn};
+
+use crate::shutdown::ShutdownController;
+use crate::storage::MessageStore;
+use crate::web::WebSocketEvent;
+
+/// Jobs of one kind that run at once unless set otherwise
+pub const DEFAULT_CONCURRENCY: usize = 1;
+
+/// Most finished jobs listed
+pub const MAX_LISTED_JOBS: usize = 100;
+
+/// Where a job is in its life
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Done => "done",
+            JobState::Failed => "failed",
+            JobState::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "queued" => Some(JobState::Queued),
+            "running" => Some(JobState::Running),
+            "done" => Some(JobState::Done),
+            "failed" => Some(JobState::Failed),
+            "cancelled" => Some(JobState::Cancelled),
+            _ => None,
+        }
+    }
+}
+
+/// How far a job has got
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobProgress {
+    /// Items done so far
+    pub done: u64,
+    /// Items in all, if known
+    pub total: Option<u64>,
+    /// What it's doing now, if it says
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// A job as reported by the API
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobRecord {
+    pub id: String,
+    pub kind: String,
+    pub state: JobState,
+    pub progress: JobProgress,
+    /// What the job did, once it's done
+    pub result: Option<String>,
+    /// Why it failed or was cancelled
+    pub error: Option<String>,
+    /// When it was queued, started and finished (ms)
+    pub created_at: i64,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+}
+
+/// Errors returned by the jobs API
+#[derive(Debug, Clone, Serialize)]
+pub enum JobError {
+    NotFound,
+    AlreadyFinished,
+    StorageError,
+}
+
+impl JobError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobError::NotFound => "not_found",
+            JobError::AlreadyFinished => "already_finished",
+            JobError::StorageError => "storage_error",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            JobError::NotFound => "No job with that ID",
+            JobError::AlreadyFinished => "The job has already finished",
+            JobError::StorageError => "Failed to get the job",
+        }
+    }
+}
+
+/// A piece of background work
+#[async_trait]
+pub trait Job: Send + 'static {
+    /// What kind of job this is, e.g. "retranslate"; jobs of a kind share a
+    /// concurrency limit
+    fn kind(&self) -> &'static str;
+
+    /// Do the work, reporting progress through `ctx`, and say what was done.
+    /// A cancelled job is dropped at its next await, so it should leave
+    /// things consistent at each one.
+    async fn run(self: Box<Self>, ctx: JobContext) -> Result<String>;
+}
+
+/// Set when a job is cancelled
+#[derive(Clone)]
+pub struct CancelToken(Arc<watch::Sender<bool>>);
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self(Arc::new(watch::channel(false).0))
+    }
+}
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.send_replace(true);
+    }
+
+    /// Resolves once the job is cancelled
+    pub async fn cancelled(&self) {
+        let mut rx = self.0.subscribe();
+        let _ = rx.wait_for(|cancelled| *cancelled).await;
+    }
+}
+
+/// What a running job reports its progress through
+pub struct JobContext {
+    id: String,
+    queue: JobQueue,
+}
+
+impl JobContext {
+    /// Report how far the job has got
+    pub fn progress(&self, progress: JobProgress) {
+        self.queue
+            .update(&self.id, |record| record.progress = progress);
+    }
+}
+
+struct ActiveJob {
+    record: JobRecord,
+    token: CancelToken,
+}
+
+struct Inner {
+    store: MessageStore,
+    broadcast_tx: broadcast::Sender<WebSocketEvent>,
+    shutdown: ShutdownController,
+    active: Mutex<HashMap<String, ActiveJob>>,
+    limits: Mutex<HashMap<&'static str, usize>>,
+    /// Slots per kind, made when a kind's first job is queued
+    slots: Mutex<HashMap<&'static str, Arc<Semaphore>>>,
+}
+
+/// Queued and running jobs
+#[derive(Clone)]
+pub struct JobQueue {
+    inner: Arc<Inner>,
+}
+
+fn now_ms() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+impl JobQueue {
+    pub fn new(
+        store: MessageStore,
+        broadcast_tx: broadcast::Sender<WebSocketEvent>,
+        shutdown: ShutdownController,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                store,
+                broadcast_tx,
+                shutdown,
+                active: Mutex::new(HashMap::new()),
+                limits: Mutex::new(HashMap::new()),
+                slots: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Let `limit` jobs of a kind run at once. Takes effect for a kind
+    /// whose first job hasn't been queued yet.
+    pub fn set_limit(&self, kind: &'static str, limit: usize) {
+        self.inner.limits.lock().unwrap().insert(kind, limit.max(1));
+    }
+
+    fn slots(&self, kind: &'static str) -> Arc<Semaphore> {
+        let limit = self
+            .inner
+            .limits
+            .lock()
+            .unwrap()
+            .get(kind)
+            .copied()
+            .unwrap_or(DEFAULT_CONCURRENCY);
+        self.inner
+            .slots
+            .lock()
+            .unwrap()
+            .entry(kind)
+            .or_insert_with(|| Arc::new(Semaphore::new(limit)))
+            .clone()
+    }
+
+    /// Queue a job, returning its ID. It runs once a slot for its kind is
+    /// free.
+    pub fn submit(&self, job: impl Job) -> String {
+        let kind = job.kind();
+        let id = uuid::Uuid::new_v4().to_string();
+        let token = CancelToken::default();
+        let record = JobRecord {
+            id: id.clone(),
+            kind: kind.to_string(),
+            state: JobState::Queued,
+            progress: JobProgress::default(),
+            result: None,
+            error: None,
+            created_at: now_ms(),
+            started_at: None,
+            finished_at: None,
+        };
+        self.inner.active.lock().unwrap().insert(
+            id.clone(),
+            ActiveJob {
+                record: record.clone(),
+                token: token.clone(),
+            },
+        );
+        self.broadcast(record);
+
+        let queue = self.clone();
+        let slots = self.slots(kind);
+        let job: Box<dyn Job> = Box::new(job);
+        let job_id = id.clone();
+        tokio::spawn(async move {
+            let _work = queue.inner.shutdown.track(kind);
+            let shutdown = queue.inner.shutdown.clone();
+            let _slot = tokio::select! {
+                slot = slots.acquire_owned() => slot,
+                _ = token.cancelled() => return queue.finish(&job_id, Err(None)),
+                _ = shutdown.wait() => {
+                    return queue.finish(&job_id, Err(Some("The server shut down".to_string())))
+                }
+            };
+
+            queue.update(&job_id, |record| {
+                record.state = JobState::Running;
+                record.started_at = Some(now_ms());
+            });
+            let ctx = JobContext {
+                id: job_id.clone(),
+                queue: queue.clone(),
+            };
+            let outcome = tokio::select! {
+                result = job.run(ctx) => Ok(result),
+                _ = token.cancelled() => Err(None),
+                _ = shutdown.wait() => Err(Some("The server shut down".to_string())),
+            };
+            queue.finish(&job_id, outcome);
+        });
+        id
+    }
+
+    /// Change an active job's record and broadcast it
+    fn update(&self, id: &str, change: impl FnOnce(&mut JobRecord)) {
+        let record = {
+            let mut active = self.inner.active.lock().unwrap();
+            let Some(job) = active.get_mut(id) else {
+                return;
+            };
+            change(&mut job.record);
+            job.record.clone()
+        };
+        self.broadcast(record);
+    }
+
+    /// Record how a job ended: with its result, or cancelled (with why, if
+    /// it wasn't asked for)
+    fn finish(&self, id: &str, outcome: Result<Result<String>, Option<String>>) {
+        let Some(ActiveJob { mut record, .. }) = self.inner.active.lock().unwrap().remove(id)
+        else {
+            return;
+        };
+        record.finished_at = Some(now_ms());
+        match outcome {
+            Ok(Ok(result)) => {
+                record.state = JobState::Done;
+                record.result = Some(result);
+            }
+            Ok(Err(e)) => {
+                warn!("Job {} ({}) failed: {:#}", record.id, record.kind, e);
+                record.state = JobState::Failed;
+                record.error = Some(format!("{:#}", e));
+            }
+            Err(reason) => {
+                record.state = JobState::Cancelled;
+                record.error = reason;
+            }
+        }
+        info!(
+            "Job {} ({}) {}",
+            record.id,
+            record.kind,
+            record.state.as_str()
+        );
+        if let Err(e) = self.inner.store.save_job(&record) {
+            warn!("Failed to save job {}: {}", record.id, e);
+        }
+        self.broadcast(record);
+    }
+
+    fn broadcast(&self, job: JobRecord) {
+        let _ = self
+            .inner
+            .broadcast_tx
+            .send(WebSocketEvent::JobProgress { job });
+    }
+
+    /// Cancel a queued or running job, returning its record as it was
+    pub fn cancel(&self, id: &str) -> Result<JobRecord, JobError> {
+        if let Some(job) = self.inner.active.lock().unwrap().get(id) {
+            job.token.cancel();
+            return Ok(job.record.clone());
+        }
+        match self.inner.store.get_job(id) {
+            Ok(Some(_)) => Err(JobError::AlreadyFinished),
+            Ok(None) => Err(JobError::NotFound),
+            Err(e) => {
+                warn!("Failed to get job {}: {}", id, e);
+                Err(JobError::StorageError)
+            }
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Result<JobRecord, JobError> {
+        if let Some(job) = self.inner.active.lock().unwrap().get(id) {
+            return Ok(job.record.clone());
+        }
+        match self.inner.store.get_job(id) {
+            Ok(Some(record)) => Ok(record),
+            Ok(None) => Err(JobError::NotFound),
+            Err(e) => {
+                warn!("Failed to get job {}: {}", id, e);
+                Err(JobError::StorageError)
+            }
+        }
+    }
+
+    /// Active jobs, then the most recently finished ones, newest first
+    pub fn list(&self) -> Result<Vec<JobRecord>, JobError> {
+        let mut jobs: Vec<JobRecord> = self
+            .inner
+            .active
+            .lock()
+            .unwrap()
+            .values()
+            .map(|job| job.record.clone())
+            .collect();
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.created_at));
+        match self.inner.store.get_recent_jobs(MAX_LISTED_JOBS) {
+            Ok(finished) => jobs.extend(finished),
+            Err(e) => {
+                warn!("Failed to list jobs: {}", e);
+                return Err(JobError::StorageError);
+            }
+        }
+        Ok(jobs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_store;
+    use std::time::Duration;
+    use tokio::sync::Notify;
+
+    /// Waits to be let go, then reports each of its steps
+    struct StepJob {
+        steps: u64,
+        go: Arc<Notify>,
+    }
+
+    #[async_trait]
+    impl Job for StepJob {
+        fn kind(&self) -> &'static str {
+            "steps"
+        }
+
+        async fn run(self: Box<Self>, ctx: JobContext) -> Result<String> {
+            for done in 1..=self.steps {
+                self.go.notified().await;
+                ctx.progress(JobProgress {
+                    done,
+                    total: Some(self.steps),
+                    detail: None,
+                });
+            }
+            Ok(format!("{} steps", self.steps))
+        }
+    }
+
+    fn queue() -> (JobQueue, broadcast::Receiver<WebSocketEvent>) {
+        let (store, _dir) = test_store();
+        let (tx, rx) = broadcast::channel(100);
+        (JobQueue::new(store, tx, ShutdownController::default()), rx)
+    }
+
+    /// The next update broadcast for a job
+    async fn next_update(events: &mut broadcast::Receiver<WebSocketEvent>, id: &str) -> JobRecord {
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            if let WebSocketEvent::JobProgress { job } = event {
+                if job.id == id {
+                    return job;
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_job_progress_and_concurrency_limit() {
+        let (queue, mut events) = queue();
+        let first_go = Arc::new(Notify::new());
+        let second_go = Arc::new(Notify::new());
+        let first = queue.submit(StepJob {
+            steps: 2,
+            go: first_go.clone(),
+        });
+        let second = queue.submit(StepJob {
+            steps: 1,
+            go: second_go.clone(),
+        });
+
+        assert_eq!(
+            next_update(&mut events, &first).await.state,
+            JobState::Queued
+        );
+        assert_eq!(
+            next_update(&mut events, &first).await.state,
+            JobState::Running
+        );
+        // One "steps" job at a time: the second waits for the first
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(queue.get(&second).unwrap().state, JobState::Queued);
+
+        first_go.notify_one();
+        let update = next_update(&mut events, &first).await;
+        assert_eq!(
+            update.progress,
+            JobProgress {
+                done: 1,
+                total: Some(2),
+                detail: None
+            }
+        );
+        first_go.notify_one();
+        assert_eq!(next_update(&mut events, &first).await.progress.done, 2);
+        let done = next_update(&mut events, &first).await;
+        assert_eq!(done.state, JobState::Done);
+        assert_eq!(done.result.as_deref(), Some("2 steps"));
+
+        // Now the second runs
+        loop {
+            if next_update(&mut events, &second).await.state == JobState::Running {
+                break;
+            }
+        }
+        second_go.notify_one();
+        loop {
+            if next_update(&mut events, &second).await.state == JobState::Done {
+                break;
+            }
+        }
+
+        // Finished jobs are kept in the database
+        let jobs = queue.list().unwrap();
+        assert_eq!(
+            jobs.iter().map(|job| job.id.as_str()).collect::<Vec<_>>(),
+            [second.as_str(), first.as_str()]
+        );
+        assert_eq!(queue.get(&first).unwrap(), done);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_job() {
+        let (queue, mut events) = queue();
+        queue.set_limit("steps", 2);
+        let go = Arc::new(Notify::new());
+        let running = queue.submit(StepJob {
+            steps: 3,
+            go: go.clone(),
+        });
+        go.notify_one();
+        loop {
+            if next_update(&mut events, &running).await.progress.done == 1 {
+                break;
+            }
+        }
+
+        // Cancelled mid-run, it stops at its next await
+        assert_eq!(queue.cancel(&running).unwrap().state, JobState::Running);
+        let cancelled = next_update(&mut events, &running).await;
+        assert_eq!(cancelled.state, JobState::Cancelled);
+        assert_eq!(cancelled.progress.done, 1);
+        assert_eq!(cancelled.error, None);
+        go.notify_one();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(queue.get(&running).unwrap().progress.done, 1);
+
+        assert!(matches!(
+            queue.cancel(&running),
+            Err(JobError::AlreadyFinished)
+        ));
+        assert!(matches!(queue.cancel("nope"), Err(JobError::NotFound)));
+    }
+}
@@ -0,0 +1,422 @@
+//! Seeding conversation languages from history.
+//!
+//! A chat's language comes from the languages detected on its incoming
+//! messages, but history sync messages are never detected, so after a fresh
+//! pairing outgoing translation has nothing to go on until enough live
+//! messages arrive. Once a history sync has been imported, a background job
+//! detects (without translating) the language of up to `SAMPLE_SIZE` recent
+//! incoming text messages in each chat active in the last `ACTIVE_WINDOW_MS`.
+//!
+//! The job runs once. Detections count against a daily cap; when it's
+//! reached the job waits for the next day (UTC), and how far it got is kept
+//! in the settings table so a restart picks up where it left off.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use crate::storage::MessageStore;
+use crate::translation::{TranslationService, MIN_DETECTION_LEN};
+use crate::web::AppState;
+
+/// Most incoming messages detected per chat
+pub const SAMPLE_SIZE: usize = 10;
+
+/// Chats with messages this recent are seeded (ms)
+pub const ACTIVE_WINDOW_MS: i64 = 60 * 24 * 60 * 60 * 1000;
+
+/// Detections allowed per day (UTC) unless configured otherwise
+pub const DEFAULT_DAILY_CAP: u32 = 500;
+
+/// Operation detections are recorded under in the usage table
+pub const OPERATION: &str = "language_seeding";
+
+/// How long to wait after a run fails or translation is unavailable
+const RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// How far the job has got, as broadcast to web clients
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SeedingProgress {
+    pub contacts_done: usize,
+    pub contacts_total: usize,
+    /// Messages detected in this run
+    pub messages_detected: usize,
+    pub complete: bool,
+}
+
+/// How a run ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeedingOutcome {
+    /// Every active chat has been sampled
+    Complete,
+    /// Today's detections are used up
+    CapReached,
+    /// No translation provider answered
+    Unavailable,
+}
+
+/// Whether seeding runs, its daily cap, and whether it's running now
+pub struct LanguageSeeding {
+    enabled: AtomicBool,
+    daily_cap: AtomicU32,
+    running: AtomicBool,
+}
+
+impl Default for LanguageSeeding {
+    fn default() -> Self {
+        Self {
+            enabled: AtomicBool::new(true),
+            daily_cap: AtomicU32::new(DEFAULT_DAILY_CAP),
+            running: AtomicBool::new(false),
+        }
+    }
+}
+
+impl LanguageSeeding {
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn set_daily_cap(&self, cap: u32) {
+        self.daily_cap.store(cap, Ordering::Relaxed);
+    }
+
+    pub fn daily_cap(&self) -> u32 {
+        self.daily_cap.load(Ordering::Relaxed)
+    }
+}
+
+/// Start of the UTC day `now_ms` falls in (seconds, as usage is recorded)
+fn start_of_day_secs(now_ms: i64) -> i64 {
+    let day_ms = 24 * 60 * 60 * 1000;
+    now_ms.div_euclid(day_ms) * day_ms / 1000
+}
+
+/// Detect the languages of recent incoming messages in active chats,
+/// picking up after the last contact sampled, until every chat is sampled
+/// or `daily_cap` detections have been made today
+pub async fn seed(
+    store: &MessageStore,
+    translator: &TranslationService,
+    daily_cap: u32,
+    now_ms: i64,
+    on_progress: impl Fn(&SeedingProgress),
+) -> Result<SeedingOutcome> {
+    let mut saved = store.get_language_seeding_progress()?;
+    if saved.complete {
+        return Ok(SeedingOutcome::Complete);
+    }
+
+    let contacts = store.get_active_contact_ids(now_ms - ACTIVE_WINDOW_MS)?;
+    let start = match &saved.last_contact {
+        Some(last) => contacts.partition_point(|id| id <= last),
+        None => 0,
+    };
+    let mut progress = SeedingProgress {
+        contacts_done: start,
+        contacts_total: contacts.len(),
+        ..Default::default()
+    };
+    let mut used = store.count_usage_messages_since(OPERATION, start_of_day_secs(now_ms))?;
+
+    for contact_id in &contacts[start..] {
+        let sample =
+            store.get_undetected_language_sample(contact_id, SAMPLE_SIZE, MIN_DETECTION_LEN)?;
+        for (message_id, text) in sample {
+            if used >= daily_cap {
+                debug!("Language seeding cap ({}) reached for today", daily_cap);
+                return Ok(SeedingOutcome::CapReached);
+            }
+            let (language, usage) = translator.detect_text_language(&text).await?;
+            if usage.input_tokens == 0 {
+                // Nothing was asked, so the language is only the default
+                return Ok(SeedingOutcome::Unavailable);
+            }
+            used += 1;
+            if let Err(e) =
+                store.record_usage(Some(contact_id), Some(&message_id), &usage, OPERATION)
+            {
+                warn!("Failed to record usage: {}", e);
+            }
+            store.set_detected_language(&message_id, &language)?;
+            progress.messages_detected += 1;
+        }
+
+        saved.last_contact = Some(contact_id.clone());
+        store.set_language_seeding_progress(&saved)?;
+        progress.contacts_done += 1;
+        on_progress(&progress);
+    }
+
+    saved.complete = true;
+    store.set_language_seeding_progress(&saved)?;
+    progress.complete = true;
+    on_progress(&progress);
+    info!(
+        "Seeded conversation languages: {} messages detected in {} chats",
+        progress.messages_detected, progress.contacts_total
+    );
+    Ok(SeedingOutcome::Complete)
+}
+
+/// How long until the next UTC day starts
+fn until_tomorrow(now_ms: i64) -> Duration {
+    let next_day_ms = start_of_day_secs(now_ms) * 1000 + 24 * 60 * 60 * 1000;
+    Duration::from_millis((next_day_ms - now_ms).max(0) as u64)
+}
+
+/// Start seeding in the background if it's enabled, a history sync has been
+/// imported and seeding hasn't finished or started yet
+pub fn start(state: Arc<AppState>) {
+    if !state.language_seeding.enabled.load(Ordering::Relaxed) {
+        return;
+    }
+    let Some(translator) = state.translator.clone() else {
+        return;
+    };
+    match (
+        state.store.is_history_sync_complete(),
+        state.store.get_language_seeding_progress(),
+    ) {
+        (Ok(true), Ok(progress)) if !progress.complete => {}
+        (Err(e), _) | (_, Err(e)) => {
+            warn!(
+                "Failed to check whether to seed conversation languages: {}",
+                e
+            );
+            return;
+        }
+        _ => return,
+    }
+    if state.language_seeding.running.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    tokio::spawn(async move {
+        info!("Seeding conversation languages from history");
+        loop {
+            let now_ms = chrono::Utc::now().timestamp_millis();
+            // Progress is saved as it goes, so stopping mid-run loses nothing
+            let outcome = tokio::select! {
+                outcome = seed(
+                    &state.store,
+                    &translator,
+                    state.language_seeding.daily_cap(),
+                    now_ms,
+                    |progress| state.broadcast_language_seeding(progress),
+                ) => outcome,
+                _ = state.shutdown.wait() => break,
+            };
+            let delay = match outcome {
+                Ok(SeedingOutcome::Complete) => break,
+                Ok(SeedingOutcome::CapReached) => {
+                    info!("Language seeding paused until tomorrow (daily cap reached)");
+                    until_tomorrow(now_ms)
+                }
+                Ok(SeedingOutcome::Unavailable) => RETRY_DELAY,
+                Err(e) => {
+                    warn!("Language seeding failed: {}", e);
+                    RETRY_DELAY
+                }
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = state.shutdown.wait() => break,
+            }
+        }
+        state
+            .language_seeding
+            .running
+            .store(false, Ordering::SeqCst);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{test_store, text_message};
+    use std::sync::atomic::AtomicUsize;
+
+    const NOW_MS: i64 = 1_760_000_000_000;
+    const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+    async fn setup() -> (
+        MessageStore,
+        tempfile::TempDir,
+        TranslationService,
+        Arc<AtomicUsize>,
+    ) {
+        let (store, dir) = test_store();
+        let (url, hits) = crate::translation::spawn_counting_provider(
+            r#"{"language": "Spanish", "isEnglish": false}"#,
+        )
+        .await;
+        let translator = TranslationService::new("test-key".to_string(), "English".to_string())
+            .with_api_url(&url);
+        (store, dir, translator, hits)
+    }
+
+    #[tokio::test]
+    async fn test_seeding_samples_recent_incoming_messages() {
+        let (store, _dir, translator, hits) = setup().await;
+        let active = "34600000001@s.whatsapp.net";
+        let stale = "34600000002@s.whatsapp.net";
+        for contact in [active, stale] {
+            store
+                .upsert_contact(contact, None, None, Some("private"), 1)
+                .unwrap();
+        }
+        for i in 0..15 {
+            let ts = NOW_MS - DAY_MS + i * 1000;
+            store
+                .add_message(&text_message(
+                    &format!("a{}", i),
+                    active,
+                    ts,
+                    "¿Qué tal estás hoy?",
+                ))
+                .unwrap();
+        }
+        // Too short to detect, and sent by me
+        store
+            .add_message(&text_message("short", active, NOW_MS - 10, "ok"))
+            .unwrap();
+        let mut mine = text_message("mine", active, NOW_MS - 5, "See you tomorrow then");
+        mine.is_from_me = true;
+        store.add_message(&mine).unwrap();
+        store
+            .add_message(&text_message(
+                "old",
+                stale,
+                NOW_MS - 90 * DAY_MS,
+                "Hasta luego amigo",
+            ))
+            .unwrap();
+
+        let updates = std::sync::Mutex::new(Vec::new());
+        let outcome = seed(&store, &translator, 100, NOW_MS, |p| {
+            updates.lock().unwrap().push(p.clone())
+        })
+        .await
+        .unwrap();
+        assert_eq!(outcome, SeedingOutcome::Complete);
+        assert_eq!(hits.load(Ordering::SeqCst), SAMPLE_SIZE);
+
+        let detected = |id: &str| {
+            store
+                .get_message_by_id(id)
+                .unwrap()
+                .unwrap()
+                .source_language
+        };
+        assert_eq!(detected("a14").as_deref(), Some("Spanish"));
+        assert_eq!(detected("a5").as_deref(), Some("Spanish"));
+        assert_eq!(detected("a4"), None);
+        assert_eq!(detected("old"), None);
+        assert!(
+            !store
+                .get_message_by_id("a14")
+                .unwrap()
+                .unwrap()
+                .is_translated
+        );
+        assert_eq!(
+            store
+                .get_cached_conversation_language(active)
+                .unwrap()
+                .as_deref(),
+            Some("Spanish")
+        );
+        assert_eq!(store.get_cached_conversation_language(stale).unwrap(), None);
+
+        let updates = updates.into_inner().unwrap();
+        assert_eq!(
+            updates.last(),
+            Some(&SeedingProgress {
+                contacts_done: 1,
+                contacts_total: 1,
+                messages_detected: SAMPLE_SIZE,
+                complete: true,
+            })
+        );
+
+        // It only runs once
+        seed(&store, &translator, 100, NOW_MS, |_| {})
+            .await
+            .unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), SAMPLE_SIZE);
+    }
+
+    #[tokio::test]
+    async fn test_seeding_stops_at_the_cap_and_resumes() {
+        let (store, _dir, translator, hits) = setup().await;
+        let contacts = [
+            "34600000001@s.whatsapp.net",
+            "34600000002@s.whatsapp.net",
+            "34600000003@s.whatsapp.net",
+        ];
+        for (c, contact) in contacts.iter().enumerate() {
+            store
+                .upsert_contact(contact, None, None, Some("private"), 1)
+                .unwrap();
+            for i in 0..3 {
+                let id = format!("m{}-{}", c, i);
+                store
+                    .add_message(&text_message(
+                        &id,
+                        contact,
+                        NOW_MS - 1000 + i,
+                        "Buenos días a todos",
+                    ))
+                    .unwrap();
+            }
+        }
+
+        let outcome = seed(&store, &translator, 4, NOW_MS, |_| {}).await.unwrap();
+        assert_eq!(outcome, SeedingOutcome::CapReached);
+        assert_eq!(hits.load(Ordering::SeqCst), 4);
+        let progress = store.get_language_seeding_progress().unwrap();
+        assert_eq!(progress.last_contact.as_deref(), Some(contacts[0]));
+        assert!(!progress.complete);
+
+        // Still capped for the rest of the day, even after a restart
+        let outcome = seed(&store, &translator, 4, NOW_MS, |_| {}).await.unwrap();
+        assert_eq!(outcome, SeedingOutcome::CapReached);
+        assert_eq!(hits.load(Ordering::SeqCst), 4);
+
+        // A higher cap picks up where it stopped, without detecting anything twice
+        let last = std::sync::Mutex::new(None);
+        let outcome = seed(&store, &translator, 100, NOW_MS, |p| {
+            *last.lock().unwrap() = Some(p.clone())
+        })
+        .await
+        .unwrap();
+        assert_eq!(outcome, SeedingOutcome::Complete);
+        assert_eq!(hits.load(Ordering::SeqCst), 9);
+        let last = last.into_inner().unwrap().unwrap();
+        assert_eq!((last.contacts_done, last.messages_detected), (3, 5));
+        for contact in contacts {
+            assert_eq!(
+                store
+                    .get_cached_conversation_language(contact)
+                    .unwrap()
+                    .as_deref(),
+                Some("Spanish")
+            );
+        }
+    }
+
+    #[test]
+    fn test_until_tomorrow() {
+        let midnight = NOW_MS - NOW_MS.rem_euclid(DAY_MS);
+        assert_eq!(start_of_day_secs(NOW_MS), midnight / 1000);
+        assert_eq!(
+            until_tomorrow(midnight + 1000),
+            Duration::from_millis((DAY_MS - 1000) as u64)
+        );
+    }
+}
@@ -0,0 +1,155 @@
+//! Application lifecycle for web mode.
+//!
+//! Coordinates `/api/logout` with the bridge restart loop so that a logout
+//! never races a bridge respawn or in-flight event processing:
+//!
+//! - The restart loop holds the state lock while respawning (`Restarting`).
+//! - Bridge events are handled while holding the state lock and are dropped
+//!   once a logout has started (`LoggingOut`).
+//! - Logout asks the restart loop to stop the bridge, waits until it has, and
+//!   only then clears data and returns to `Running`.
+
+use std::time::Duration;
+use tokio::sync::{watch, Mutex, MutexGuard};
+
+/// Lifecycle state of the application
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LifecycleState {
+    /// Normal operation - bridge events are processed
+    Running,
+    /// Logout in progress - bridge events are dropped and no bridge is spawned
+    LoggingOut,
+    /// The restart loop is spawning a new bridge
+    Restarting,
+}
+
+/// Lifecycle state machine shared by the web server and the restart loop
+pub struct Lifecycle {
+    state: Mutex<LifecycleState>,
+    /// Mirror of `state` for tasks waiting on a transition
+    state_tx: watch::Sender<LifecycleState>,
+    /// Set by logout to ask the restart loop to stop the current bridge
+    stop_tx: watch::Sender<bool>,
+    /// Whether a bridge process is currently running
+    bridge_running_tx: watch::Sender<bool>,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(LifecycleState::Running),
+            state_tx: watch::channel(LifecycleState::Running).0,
+            stop_tx: watch::channel(false).0,
+            bridge_running_tx: watch::channel(false).0,
+        }
+    }
+}
+
+impl Lifecycle {
+    /// Lock the lifecycle state. Holding the guard blocks state transitions.
+    pub async fn lock(&self) -> MutexGuard<'_, LifecycleState> {
+        self.state.lock().await
+    }
+
+    fn set(&self, guard: &mut MutexGuard<'_, LifecycleState>, new_state: LifecycleState) {
+        **guard = new_state;
+        self.state_tx.send_replace(new_state);
+    }
+
+    /// Start a logout. Returns false if a logout is already in progress.
+    pub async fn begin_logout(&self) -> bool {
+        let mut state = self.state.lock().await;
+        if *state == LifecycleState::LoggingOut {
+            return false;
+        }
+        self.set(&mut state, LifecycleState::LoggingOut);
+        true
+    }
+
+    /// Finish a logout and let the restart loop spawn a new bridge
+    pub async fn finish_logout(&self) {
+        let mut state = self.state.lock().await;
+        self.set(&mut state, LifecycleState::Running);
+    }
+
+    /// Wait until no logout is in progress, then lock the state for a respawn.
+    /// The state is `Restarting` until [`Lifecycle::bridge_started`] is called.
+    pub async fn begin_restart(&self) -> MutexGuard<'_, LifecycleState> {
+        let mut rx = self.state_tx.subscribe();
+        loop {
+            let mut state = self.state.lock().await;
+            if *state != LifecycleState::LoggingOut {
+                self.set(&mut state, LifecycleState::Restarting);
+                // A stop request only ever applies to the bridge it was made for
+                self.stop_tx.send_replace(false);
+                return state;
+            }
+            drop(state);
+            let _ = rx.wait_for(|s| *s != LifecycleState::LoggingOut).await;
+        }
+    }
+
+    /// Record that a bridge was spawned and return to `Running`
+    pub fn bridge_started(&self, mut guard: MutexGuard<'_, LifecycleState>) {
+        self.bridge_running_tx.send_replace(true);
+        self.set(&mut guard, LifecycleState::Running);
+    }
+
+    /// Record that the bridge process has stopped
+    pub fn bridge_stopped(&self) {
+        self.bridge_running_tx.send_replace(false);
+    }
+
+    /// Whether a bridge process is currently running
+    pub fn is_bridge_running(&self) -> bool {
+        *self.bridge_running_tx.borrow()
+    }
+
+    /// Ask the restart loop to stop the current bridge
+    pub fn request_bridge_stop(&self) {
+        self.stop_tx.send_replace(true);
+    }
+
+    /// Resolves when a bridge stop has been requested
+    pub async fn stop_requested(&self) {
+        let mut rx = self.stop_tx.subscribe();
+        let _ = rx.wait_for(|stop| *stop).await;
+    }
+
+    /// Wait for the bridge to stop. Returns false on timeout.
+    pub async fn wait_for_bridge_stop(&self, timeout: Duration) -> bool {
+        let mut rx = self.bridge_running_tx.subscribe();
+        let stopped = tokio::time::timeout(timeout, rx.wait_for(|running| !*running)).await;
+        stopped.is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_restart_waits_for_logout() {
+        let lifecycle = Arc::new(Lifecycle::default());
+        assert!(lifecycle.begin_logout().await);
+        assert!(!lifecycle.begin_logout().await);
+
+        let restarter = {
+            let lifecycle = lifecycle.clone();
+            tokio::spawn(async move {
+                let guard = lifecycle.begin_restart().await;
+                lifecycle.bridge_started(guard);
+            })
+        };
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!restarter.is_finished());
+        assert!(!lifecycle.is_bridge_running());
+
+        lifecycle.finish_logout().await;
+        restarter.await.unwrap();
+        assert!(lifecycle.is_bridge_running());
+        assert_eq!(*lifecycle.lock().await, LifecycleState::Running);
+    }
+}
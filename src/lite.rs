@@ -0,0 +1,673 @@
+//! Plain HTML version of the web interface, under `/lite`.
+//!
+//! For text browsers and strict script blocking: the chat list, a chat a
+//! page at a time, and a form to send from, rendered on the server with no
+//! JavaScript. Pages come from the same store calls as the JSON API, and
+//! sends go through `send_text` like the UI's. When a web password is set,
+//! the session token from signing in is kept in a cookie.
+
+use axum::{
+    extract::{Form, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use maud::{html, Markup, DOCTYPE};
+use serde::Deserialize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+use crate::bridge::is_channel_jid;
+use crate::chat_search::{displayed_text, TextSource};
+use crate::storage::{
+    ContactCursor, MessageCursor, MessageFilter, MessageStore, StoredContact, StoredMessage,
+};
+use crate::web::{request_cookie, send_text, AppState, SendMessageRequest, SendTextError};
+
+/// Cookie holding the web session token
+pub const SESSION_COOKIE: &str = "wa_session";
+
+const CONTACTS_PER_PAGE: u32 = 100;
+const MESSAGES_PER_PAGE: u32 = 50;
+
+/// Content types with media to link to
+const MEDIA_TYPES: [&str; 6] = [
+    "Image",
+    "Video",
+    "Audio",
+    "Voice Note",
+    "Document",
+    "Sticker",
+];
+
+/// Query parameters for paged pages
+#[derive(Debug, Deserialize)]
+pub struct PageQuery {
+    cursor: Option<String>,
+}
+
+/// Sign in form
+#[derive(Debug, Deserialize)]
+pub struct LoginForm {
+    password: String,
+}
+
+/// Send form
+#[derive(Debug, Deserialize)]
+pub struct SendForm {
+    text: String,
+    /// Set when confirming a send held back by the language guard
+    confirmation_token: Option<String>,
+}
+
+/// Something to tell the user above the send form
+enum Notice {
+    Error(String),
+    /// The language guard held the message back
+    Confirm {
+        text: String,
+        token: String,
+        detected_language: Option<String>,
+        chat_language: Option<String>,
+        text_to_send: String,
+    },
+}
+
+/// Whether the request may see the pages: always when there is no web
+/// password, otherwise with a valid session cookie
+async fn signed_in(state: &AppState, headers: &HeaderMap) -> bool {
+    if !state.auth_required().await {
+        return true;
+    }
+    match request_cookie(headers, SESSION_COOKIE) {
+        Some(token) => state.auth_tokens.read().await.contains_key(&token),
+        None => false,
+    }
+}
+
+fn chat_url(contact_id: &str) -> String {
+    format!("/lite/chat/{}", urlencoding::encode(contact_id))
+}
+
+fn page(title: &str, body: Markup) -> Html<String> {
+    Html(
+        html! {
+            (DOCTYPE)
+            html lang="en" {
+                head {
+                    meta charset="utf-8";
+                    meta name="viewport" content="width=device-width, initial-scale=1";
+                    title { (title) " - WhatsApp Translator" }
+                }
+                body {
+                    a href="#main" { "Skip to content" }
+                    (body)
+                }
+            }
+        }
+        .into_string(),
+    )
+}
+
+fn error_page(status: StatusCode, message: &str) -> Response {
+    (
+        status,
+        page(
+            "Error",
+            html! {
+                main id="main" {
+                    h1 { "Something went wrong" }
+                    p role="alert" { (message) }
+                    p { a href="/lite" { "Back to chats" } }
+                }
+            },
+        ),
+    )
+        .into_response()
+}
+
+/// GET /lite/login
+pub async fn login_page() -> impl IntoResponse {
+    login_form(None)
+}
+
+fn login_form(error: Option<&str>) -> Html<String> {
+    page(
+        "Sign in",
+        html! {
+            main id="main" {
+                h1 { "Sign in" }
+                @if let Some(error) = error {
+                    p role="alert" { (error) }
+                }
+                form method="post" action="/lite/login" {
+                    label for="password" { "Password" }
+                    " "
+                    input type="password" id="password" name="password" required autocomplete="current-password";
+                    " "
+                    button type="submit" { "Sign in" }
+                }
+            }
+        },
+    )
+}
+
+/// POST /lite/login
+pub async fn login(State(state): State<Arc<AppState>>, Form(form): Form<LoginForm>) -> Response {
+    if !state.auth_required().await {
+        return Redirect::to("/lite").into_response();
+    }
+    if !state.check_password(&form.password).await {
+        warn!("Failed authentication attempt (lite)");
+        return (
+            StatusCode::UNAUTHORIZED,
+            login_form(Some("Invalid password")),
+        )
+            .into_response();
+    }
+
+    let token = state.issue_auth_token(None).await;
+    info!("User authenticated successfully (lite)");
+    let cookie = format!(
+        "{}={}; Path=/lite; HttpOnly; SameSite=Strict{}",
+        SESSION_COOKIE,
+        token,
+        if state.serves_https.load(Ordering::Relaxed) {
+            "; Secure"
+        } else {
+            ""
+        }
+    );
+    ([(header::SET_COOKIE, cookie)], Redirect::to("/lite")).into_response()
+}
+
+/// GET /lite: the chat list
+pub async fn contacts_page(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<PageQuery>,
+) -> Response {
+    if !signed_in(&state, &headers).await {
+        return Redirect::to("/lite/login").into_response();
+    }
+    let cursor = match query.cursor.as_deref().map(ContactCursor::decode) {
+        None => None,
+        Some(Some(cursor)) => Some(cursor),
+        Some(None) => return error_page(StatusCode::BAD_REQUEST, "Invalid cursor"),
+    };
+
+    match state.store.get_contacts_page(CONTACTS_PER_PAGE, cursor) {
+        Ok((contacts, next)) => {
+            render_contacts(&contacts, next.map(|c| c.encode()).as_deref()).into_response()
+        }
+        Err(e) => {
+            error!("Failed to get contacts: {}", e);
+            error_page(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get contacts")
+        }
+    }
+}
+
+fn render_contacts(contacts: &[StoredContact], next_cursor: Option<&str>) -> Html<String> {
+    page(
+        "Chats",
+        html! {
+            main id="main" {
+                h1 { "Chats" }
+                @if contacts.is_empty() {
+                    p { "No chats yet." }
+                }
+                ul {
+                    @for contact in contacts {
+                        li {
+                            a href=(chat_url(&contact.id)) { (contact_name(contact)) }
+                            @if contact.unread_count > 0 {
+                                " (" (contact.unread_count) " unread)"
+                            }
+                            @if let Some(preview) = &contact.last_message_preview {
+                                br;
+                                (preview)
+                            }
+                        }
+                    }
+                }
+                @if let Some(cursor) = next_cursor {
+                    nav aria-label="Pages" {
+                        a href={ "/lite?cursor=" (urlencoding::encode(cursor)) } { "More chats" }
+                    }
+                }
+            }
+        },
+    )
+}
+
+fn contact_name(contact: &StoredContact) -> &str {
+    contact
+        .name
+        .as_deref()
+        .or(contact.phone.as_deref())
+        .unwrap_or(&contact.id)
+}
+
+/// GET /lite/chat/:contact_id: a page of a chat, newest first
+pub async fn chat_page(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(contact_id): Path<String>,
+    Query(query): Query<PageQuery>,
+) -> Response {
+    if !signed_in(&state, &headers).await {
+        return Redirect::to("/lite/login").into_response();
+    }
+    let cursor = match query.cursor.as_deref().map(MessageCursor::decode) {
+        None => None,
+        Some(Some(cursor)) => Some(cursor),
+        Some(None) => return error_page(StatusCode::BAD_REQUEST, "Invalid cursor"),
+    };
+    chat_response(&state.store, &contact_id, cursor, None)
+}
+
+fn chat_response(
+    store: &MessageStore,
+    contact_id: &str,
+    cursor: Option<MessageCursor>,
+    notice: Option<Notice>,
+) -> Response {
+    let contact = match store.get_contact(contact_id) {
+        Ok(Some(contact)) => contact,
+        Ok(None) => return error_page(StatusCode::NOT_FOUND, "Chat not found"),
+        Err(e) => {
+            error!("Failed to get contact: {}", e);
+            return error_page(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get chat");
+        }
+    };
+    let is_older_page = cursor.is_some();
+    match store.get_messages_page(
+        &contact.id,
+        MESSAGES_PER_PAGE,
+        cursor,
+        true,
+        MessageFilter::default(),
+    ) {
+        Ok((messages, next)) => render_chat(
+            &contact,
+            &messages,
+            next.map(|c| c.encode()).as_deref(),
+            is_older_page,
+            notice.as_ref(),
+        )
+        .into_response(),
+        Err(e) => {
+            error!("Failed to get messages: {}", e);
+            error_page(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get messages")
+        }
+    }
+}
+
+fn render_chat(
+    contact: &StoredContact,
+    messages: &[StoredMessage],
+    older_cursor: Option<&str>,
+    is_older_page: bool,
+    notice: Option<&Notice>,
+) -> Html<String> {
+    let name = contact_name(contact);
+    let url = chat_url(&contact.id);
+    let typed = match notice {
+        Some(Notice::Error(_)) | None => "",
+        Some(Notice::Confirm { text, .. }) => text,
+    };
+    page(
+        name,
+        html! {
+            nav aria-label="Chats" {
+                a href="/lite" { "All chats" }
+            }
+            main id="main" {
+                h1 { (name) }
+                @if let Some(cursor) = older_cursor {
+                    p {
+                        a href={ (url) "?cursor=" (urlencoding::encode(cursor)) } { "Older messages" }
+                    }
+                }
+                @if messages.is_empty() {
+                    p { "No messages." }
+                }
+                ol {
+                    @for message in messages.iter().filter(|m| m.content_type != "Reaction") {
+                        li { (render_message(contact, message)) }
+                    }
+                }
+                @if is_older_page {
+                    p { a href=(url) { "Newest messages" } }
+                }
+                @if is_channel_jid(&contact.id) {
+                    p { "Channels are read-only." }
+                } @else {
+                    (send_form(&url, typed, notice))
+                }
+            }
+        },
+    )
+}
+
+fn render_message(contact: &StoredContact, message: &StoredMessage) -> Markup {
+    let sender = if message.is_from_me {
+        "You"
+    } else {
+        message
+            .sender_name
+            .as_deref()
+            .or(message.sender_phone.as_deref())
+            .unwrap_or_else(|| contact_name(contact))
+    };
+    let time = chrono::DateTime::from_timestamp_millis(message.timestamp)
+        .map(|t| t.with_timezone(&chrono::Local));
+    let shown = displayed_text(
+        message.is_from_me,
+        message.is_translated,
+        message.original_text.clone(),
+        message.translated_text.clone(),
+        &message.content_json,
+    );
+    // The other side of a translation, for the disclosure
+    let other = match shown.as_ref().map(|(_, source)| source) {
+        Some(TextSource::Translated) => message
+            .original_text
+            .as_deref()
+            .map(|text| ("Original", text)),
+        Some(TextSource::Original) if message.is_from_me && message.is_translated => message
+            .translated_text
+            .as_deref()
+            .map(|text| ("Sent as", text)),
+        _ => None,
+    };
+    html! {
+        p {
+            strong { (sender) }
+            @if let Some(time) = time {
+                " "
+                time datetime=(time.to_rfc3339()) { (time.format("%Y-%m-%d %H:%M")) }
+            }
+        }
+        @if MEDIA_TYPES.contains(&message.content_type.as_str()) {
+            p {
+                a href={ "/api/media/" (urlencoding::encode(&message.id)) } {
+                    (message.content_type) " (download)"
+                }
+            }
+        }
+        @match &shown {
+            Some((text, _)) => p { (text) },
+            None => @if let Some(preview) = MessageStore::generate_message_preview(
+                Some(&message.content_json),
+                Some(&message.content_type),
+                false,
+            ) {
+                p { (preview) }
+            },
+        }
+        @if let Some((label, text)) = other {
+            details {
+                summary { (label) }
+                p { (text) }
+            }
+        }
+    }
+}
+
+fn send_form(url: &str, typed: &str, notice: Option<&Notice>) -> Markup {
+    html! {
+        @match notice {
+            Some(Notice::Error(error)) => p role="alert" { (error) },
+            Some(Notice::Confirm { text, token, detected_language, chat_language, text_to_send }) => {
+                div role="alert" {
+                    p {
+                        "This message seems to be in "
+                        (detected_language.as_deref().unwrap_or("another language"))
+                        " but the chat is in "
+                        (chat_language.as_deref().unwrap_or("an unknown language"))
+                        ". It would be sent as:"
+                    }
+                    blockquote { (text_to_send) }
+                    form method="post" action={ (url) "/send" } {
+                        input type="hidden" name="text" value=(text);
+                        input type="hidden" name="confirmation_token" value=(token);
+                        button type="submit" { "Send anyway" }
+                    }
+                }
+            },
+            None => {},
+        }
+        form method="post" action={ (url) "/send" } {
+            label for="text" { "Message" }
+            br;
+            textarea id="text" name="text" rows="3" cols="60" required { (typed) }
+            br;
+            button type="submit" { "Send" }
+        }
+    }
+}
+
+/// POST /lite/chat/:contact_id/send
+pub async fn send(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(contact_id): Path<String>,
+    Form(form): Form<SendForm>,
+) -> Response {
+    if !signed_in(&state, &headers).await {
+        return Redirect::to("/lite/login").into_response();
+    }
+    let contact_id = match state.store.get_contact(&contact_id) {
+        Ok(Some(contact)) => contact.id,
+        Ok(None) => return error_page(StatusCode::NOT_FOUND, "Chat not found"),
+        Err(e) => {
+            error!("Failed to get contact: {}", e);
+            return error_page(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get chat");
+        }
+    };
+
+    let req = SendMessageRequest {
+        contact_id: contact_id.clone(),
+        text: form.text.clone(),
+        reply_to: None,
+        reply_to_sender: None,
+        reply_to_text: None,
+        confirmation_token: form.confirmation_token.filter(|t| !t.is_empty()),
+        ignore_quiet_hours: false,
+    };
+    // Sessions here sign in without a display name
+    let (status, notice) = match send_text(&state, req, None).await {
+        Ok(_) => return Redirect::to(&chat_url(&contact_id)).into_response(),
+        Err(SendTextError::Rejected(status, error)) => (status, Notice::Error(error)),
+        Err(SendTextError::NeedsConfirmation {
+            token,
+            detected_language,
+            chat_language,
+            text_to_send,
+        }) => (
+            StatusCode::CONFLICT,
+            Notice::Confirm {
+                text: form.text,
+                token,
+                detected_language,
+                chat_language,
+                text_to_send,
+            },
+        ),
+    };
+    let mut response = chat_response(&state.store, &contact_id, None, Some(notice));
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bridge::BridgeCommand;
+    use crate::send_guard::LanguageGuardConfig;
+    use crate::storage::{test_store, text_message};
+    use tokio::sync::mpsc;
+    use tower::ServiceExt;
+
+    fn contact(id: &str, name: &str) -> StoredContact {
+        StoredContact {
+            id: id.to_string(),
+            name: Some(name.to_string()),
+            phone: None,
+            contact_type: Some("private".to_string()),
+            last_message_time: 0,
+            unread_count: 2,
+            pinned_at: None,
+            last_message_preview: Some("\"quoted\" & <b>bold</b>".to_string()),
+            auto_translate_outgoing: true,
+            mentions_only: false,
+            created_at: None,
+            updated_at: None,
+            last_seen: None,
+            last_read_timestamp: None,
+            description: None,
+            ephemeral_duration: None,
+            placeholder: false,
+        }
+    }
+
+    #[test]
+    fn test_html_is_escaped() {
+        let evil = contact("1@s.whatsapp.net", "<script>alert(1)</script>");
+        let Html(list) = render_contacts(std::slice::from_ref(&evil), Some("a&b"));
+        assert!(!list.contains("<script>"));
+        assert!(list.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(list.contains("&quot;quoted&quot; &amp; &lt;b&gt;bold&lt;/b&gt;"));
+        assert!(list.contains("href=\"/lite/chat/1%40s.whatsapp.net\""));
+        assert!(list.contains("/lite?cursor=a%26b"));
+
+        let message = StoredMessage {
+            translated_text: Some("</details><script>x</script>".to_string()),
+            source_language: Some("es".to_string()),
+            is_translated: true,
+            ..text_message(
+                "m1",
+                &evil.id,
+                1_700_000_000_000,
+                "<img src=x onerror=alert(1)>",
+            )
+        };
+        let notice = Notice::Confirm {
+            text: "\"><script>".to_string(),
+            token: "t\"".to_string(),
+            detected_language: Some("<i>".to_string()),
+            chat_language: None,
+            text_to_send: "x".to_string(),
+        };
+        let Html(chat) = render_chat(&evil, &[message], None, false, Some(&notice));
+        assert!(!chat.contains("<script>"));
+        assert!(!chat.contains("<img"));
+        assert!(!chat.contains("<i>"));
+        assert!(chat.contains("&lt;/details&gt;&lt;script&gt;x&lt;/script&gt;"));
+        assert!(
+            chat.contains("<summary>Original</summary><p>&lt;img src=x onerror=alert(1)&gt;</p>")
+        );
+        assert!(chat.contains("value=\"&quot;&gt;&lt;script&gt;\""));
+        assert!(chat.contains("value=\"t&quot;\""));
+    }
+
+    #[tokio::test]
+    async fn test_send_form_round_trip() {
+        let (store, dir) = test_store();
+        let chat = "447911123456@s.whatsapp.net";
+        store
+            .upsert_contact(chat, Some("Alice"), None, Some("private"), 1)
+            .unwrap();
+        let state = AppState::new(
+            store.clone(),
+            dir.path().to_path_buf(),
+            dir.path().to_path_buf(),
+            None,
+            Some("correct horse".to_string()),
+            None,
+            LanguageGuardConfig::default(),
+        );
+        let (tx, mut rx) = mpsc::channel(10);
+        state.set_command_tx(tx).await;
+        *state.connected.write().await = true;
+        let router = crate::web::create_router(state.clone());
+        let request = |method: &str, uri: &str, cookie: Option<&str>, form: &str| {
+            let mut request = axum::http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+            if let Some(cookie) = cookie {
+                request = request.header(header::COOKIE, cookie);
+            }
+            let request = request
+                .body(axum::body::Body::from(form.to_string()))
+                .unwrap();
+            router.clone().oneshot(request)
+        };
+        let send_uri = format!("{}/send", chat_url(chat));
+
+        // Signed out: nothing is sent
+        let response = request("POST", &send_uri, None, "text=hi").await.unwrap();
+        assert_eq!(response.headers()[header::LOCATION], "/lite/login");
+        let response = request("POST", "/lite/login", None, "password=wrong")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = request("POST", "/lite/login", None, "password=correct+horse")
+            .await
+            .unwrap();
+        let cookie = response.headers()[header::SET_COOKIE]
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string();
+        assert!(cookie.starts_with("wa_session="));
+
+        let response = request(
+            "POST",
+            &send_uri,
+            Some(&cookie),
+            "text=Hello+%3Cthere%3E+%26+bye",
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()[header::LOCATION], chat_url(chat));
+
+        // Dispatched and stored like a send from the UI
+        match rx.recv().await.unwrap() {
+            BridgeCommand::Send { to, text, .. } => {
+                assert_eq!(to, chat);
+                assert_eq!(text, "Hello <there> & bye");
+            }
+            other => panic!("expected a send, got {:?}", other),
+        }
+        let (messages, _) = store
+            .get_messages_page(chat, 10, None, true, MessageFilter::default())
+            .unwrap();
+        let sent = messages.last().unwrap();
+        assert!(sent.is_from_me);
+        assert!(sent.content_json.contains("Hello <there> & bye"));
+        assert_eq!(sent.origin.as_deref(), Some("web"));
+
+        let response = request("GET", &chat_url(chat), Some(&cookie), "")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("Hello &lt;there&gt; &amp; bye"));
+
+        // An empty message is reported on the page
+        let response = request("POST", &send_uri, Some(&cookie), "text=")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -3,40 +3,93 @@
 //! This application uses a Go bridge (wa-bridge) that implements the WhatsApp Web protocol
 //! via the whatsmeow library. Communication happens via JSON-lines over stdio.
 
+mod access_log;
+mod anonymize;
+mod api_version;
+mod audio;
 mod bridge;
+mod broadcast;
+mod chat_search;
 mod cli;
+mod command_socket;
+mod connection_quality;
+mod contact_cache;
+mod disk_guard;
 mod display;
+mod doctor;
+mod error_registry;
+mod geocode;
+mod groups;
+mod history_sync;
+mod import;
+mod jobs;
+mod language_seeding;
+mod lifecycle;
 mod link_preview;
+mod lite;
+mod maintenance;
 mod mcp;
+mod mcp_access;
+mod name_search;
+mod new_chat;
+mod notes;
 mod oauth;
+mod password;
+mod pending;
+mod pins;
+mod presence;
+mod push;
+mod reports;
+mod send_guard;
+mod sending;
+mod settings_transfer;
+mod shutdown;
+mod spill;
+mod sticker;
 mod storage;
 mod style_analyzer;
+mod thumbnail;
+mod tls;
+mod transcript;
 mod translation;
+mod translation_provider;
+mod tzinfer;
+mod undo_send;
+mod uploads;
+mod view_once;
 mod web;
 
 use anyhow::{Context, Result};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::signal;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 use tracing::{debug, error, info, warn};
 use tracing_subscriber::EnvFilter;
 
-use bridge::{BridgeConfig, BridgeEvent, BridgeProcess, ConnectionState, Message, MessageContent};
-use cli::Args;
+use bridge::{
+    BridgeCommand, BridgeConfig, BridgeEvent, BridgeProcess, ConnectionState, HistoryDepth,
+    Message, MessageContent,
+};
+use cli::{Args, BridgeAction, Command};
 use display::{
-    clear_qr_display, print_connected, print_error, print_info, print_warning, render_qr_code,
-    MessageDisplay,
+    print_connected, print_error, print_info, print_warning, LinkState, MessageDisplay, QrDisplay,
+    SessionStatus, StatusLine,
 };
-use storage::{MessageStore, StoredMessage};
-use translation::TranslationService;
+use error_registry::ErrorCategory;
+use history_sync::BatchedMessage;
+use storage::{ClaimedPending, ContactChange, ContactUpsert, MessageStore, StoredMessage};
+use translation::{ModelConfig, TranslationService, TranslationStatus};
+use translation_provider::{AnthropicProvider, OpenAiProvider, Provider, ProviderKind};
 use web::AppState;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse_args();
 
-    // Initialize logging
-    init_logging(args.verbose);
+    // Initialize logging (on stderr when stdout carries MCP)
+    let mcp_stdio = matches!(args.command, Some(Command::McpStdio));
+    init_logging(args.verbose, mcp_stdio);
 
     // Determine data directory
     let data_dir = args
@@ -47,20 +100,53 @@ async fn main() -> Result<()> {
 
     info!("Using data directory: {:?}", data_dir);
 
+    if let Some(Command::Doctor) = args.command {
+        let healthy = doctor::run(&args, &data_dir, find_web_dir().ok()).await;
+        std::process::exit(if healthy { 0 } else { 1 });
+    }
+
+    if let Some(Command::Bridge {
+        action: BridgeAction::Check,
+    }) = args.command
+    {
+        let discovery =
+            bridge::BridgeSearch::new(args.bridge_path.as_deref(), Some(&data_dir)).run();
+        println!("{}", discovery.report());
+        match discovery.found() {
+            Some(path) => println!("Using {}", path.display()),
+            None => println!(
+                "No usable wa-bridge for {}. Build it with `cd wa-bridge && go build -o wa-bridge .`",
+                bridge::discovery::platform_suffix()
+            ),
+        }
+        std::process::exit(if discovery.found().is_some() { 0 } else { 1 });
+    }
+
+    if mcp_stdio {
+        return run_mcp_stdio(args, data_dir).await;
+    }
+
+    if let Some(Command::ImportChat {
+        file,
+        contact,
+        name,
+        me,
+    }) = &args.command
+    {
+        return run_import_chat(&data_dir, file, contact, name.as_deref(), me.as_deref());
+    }
+
+    if args.preflight && !doctor::preflight(&args, &data_dir, find_web_dir().ok()).await {
+        anyhow::bail!("Preflight checks failed; run `whatsapp-translator doctor` for details");
+    }
+
     // Handle logout request
     if args.logout {
         handle_logout(&data_dir).await?;
     }
 
     // Find bridge binary
-    let bridge_path = args
-        .bridge_path
-        .clone()
-        .or_else(|| bridge::find_bridge_binary().ok())
-        .context(
-            "Could not find wa-bridge binary. Please build it first:\n\
-             cd wa-bridge && go build -o wa-bridge .",
-        )?;
+    let bridge_path = bridge::find_bridge_binary(args.bridge_path.as_deref(), Some(&data_dir))?;
 
     info!("Using bridge binary: {:?}", bridge_path);
 
@@ -71,37 +157,158 @@ async fn main() -> Result<()> {
         verbose: args.verbose,
     };
 
-    // Initialize translation service if API key provided
-    let translator = args.claude_api_key.as_ref().map(|key| {
-        info!("Translation enabled (target: {})", args.default_language);
-        Arc::new(TranslationService::new(
-            key.clone(),
-            args.default_language.clone(),
-        ))
-    });
+    let translator = translator_from_args(&args)?;
+    if let Some(translator) = &translator {
+        check_api_key(translator, !args.web && !args.json).await;
+    }
 
     if args.web {
         // Web server mode
         run_web_mode(config, args, data_dir, translator).await
     } else {
         // Terminal mode
-        run_terminal_mode(config, args.json, translator).await
+        let store = if args.with_store {
+            let store = MessageStore::new(&data_dir).context("Failed to open message store")?;
+            if let Some(translator) = &translator {
+                apply_saved_models(&store, translator, &args);
+            }
+            Some(store)
+        } else {
+            None
+        };
+        run_terminal_mode(config, args.json, args.qr_invert, translator, store).await
     }
 }
 
+/// Translation service for the API key given, if any
+fn translator_from_args(args: &Args) -> Result<Option<Arc<TranslationService>>> {
+    // Models given on the command line must be usable before anything starts
+    let mut models = ModelConfig::default();
+    models.apply(args.model_update());
+    models.validate()?;
+
+    let Some(key) = &args.claude_api_key else {
+        return Ok(None);
+    };
+    let providers = translation_providers(args, key)?;
+    info!("Translation enabled (target: {})", args.default_language);
+    Ok(Some(Arc::new(
+        TranslationService::new(key.clone(), args.default_language.clone())
+            .with_slow_call_threshold(args.slow_translation_ms)
+            .with_output_sanitizer(!args.no_sanitize_output)
+            .with_channel_translation(!args.no_translate_channels)
+            .with_models(models)
+            .with_providers(providers),
+    )))
+}
+
+/// Check the API key before anything starts, so a bad one doesn't go
+/// unnoticed while messages quietly go untranslated. With `print`, a
+/// rejected key is also printed for the terminal.
+async fn check_api_key(translator: &TranslationService, print: bool) {
+    match translator.check_api_key().await {
+        Ok(true) => debug!("Anthropic API key accepted"),
+        Ok(false) => {
+            if print {
+                if let Some(reason) = translator.unavailable() {
+                    print_error(reason.description());
+                }
+            }
+        }
+        Err(e) => warn!("Couldn't check the Anthropic API key: {:#}", e),
+    }
+}
+
+/// The providers chosen with --translation-fallback, each with its API key
+fn translation_providers(args: &Args, claude_key: &str) -> Result<Vec<Box<dyn Provider>>> {
+    let mut providers: Vec<Box<dyn Provider>> = Vec::new();
+    for (i, kind) in args.translation_fallback.iter().enumerate() {
+        if args.translation_fallback[..i].contains(kind) {
+            anyhow::bail!("{} is in --translation-fallback twice", kind.as_str());
+        }
+        providers.push(match kind {
+            ProviderKind::Anthropic => Box::new(AnthropicProvider::new(claude_key.to_string())),
+            ProviderKind::OpenAi => {
+                let key = args.openai_api_key.clone().context(
+                    "--translation-fallback includes openai but OPENAI_API_KEY is not set",
+                )?;
+                Box::new(OpenAiProvider::new(key))
+            }
+        });
+    }
+    if providers.len() > 1 {
+        let names: Vec<&str> = args
+            .translation_fallback
+            .iter()
+            .map(|k| k.as_str())
+            .collect();
+        info!("Translation providers: {}", names.join(" -> "));
+    }
+    Ok(providers)
+}
+
+/// Use the models saved through the settings API, with command-line choices
+/// on top
+fn apply_saved_models(store: &MessageStore, translator: &TranslationService, args: &Args) {
+    match store.get_model_config() {
+        Ok(Some(mut saved)) => {
+            saved.apply(args.model_update());
+            match saved.validate() {
+                Ok(()) => translator.set_models(saved),
+                Err(e) => warn!("Ignoring saved model settings: {}", e),
+            }
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Failed to load saved model settings: {}", e),
+    }
+}
+
+/// Import a chat export file into the store
+fn run_import_chat(
+    data_dir: &std::path::Path,
+    file: &std::path::Path,
+    contact_id: &str,
+    name: Option<&str>,
+    me: Option<&str>,
+) -> Result<()> {
+    let export = std::fs::read(file).with_context(|| format!("Failed to read {:?}", file))?;
+    let name = name.map(str::to_string).or_else(|| {
+        file.file_name()
+            .and_then(|f| import::chat_name_from_file_name(&f.to_string_lossy()))
+    });
+    let store = MessageStore::new(data_dir).context("Failed to open message store")?;
+    let report = import::import_chat_export(
+        &store,
+        &String::from_utf8_lossy(&export),
+        contact_id,
+        name.as_deref(),
+        me,
+    )
+    .map_err(|e| anyhow::anyhow!(e.description()))?;
+    println!(
+        "Parsed {} messages: {} imported, {} skipped",
+        report.parsed, report.imported, report.skipped
+    );
+    Ok(())
+}
+
 /// Initialize the tracing subscriber for logging
-fn init_logging(verbose: bool) {
+fn init_logging(verbose: bool, to_stderr: bool) {
     let filter = if verbose {
         EnvFilter::new("debug")
     } else {
         EnvFilter::new("info").add_directive("whatsapp_archiver=info".parse().unwrap())
     };
 
-    tracing_subscriber::fmt()
+    let subscriber = tracing_subscriber::fmt()
         .with_env_filter(filter)
         .with_target(false)
-        .without_time()
-        .init();
+        .without_time();
+    if to_stderr {
+        subscriber.with_writer(std::io::stderr).init();
+    } else {
+        subscriber.init();
+    }
 }
 
 /// Handle logout by removing session data
@@ -118,6 +325,49 @@ async fn handle_logout(data_dir: &std::path::Path) -> Result<()> {
     Ok(())
 }
 
+/// Serve MCP over stdin/stdout, sending through a running web instance
+async fn run_mcp_stdio(args: Args, data_dir: std::path::PathBuf) -> Result<()> {
+    let translator = translator_from_args(&args)?;
+    let store =
+        MessageStore::new(&data_dir)?.with_min_free_space(args.min_free_space_mb * 1024 * 1024);
+    if let Some(translator) = &translator {
+        apply_saved_models(&store, translator, &args);
+    }
+
+    let (command_tx, sends_disabled) = match command_socket::connect(&data_dir).await {
+        Ok(tx) => {
+            info!("Sending through the web instance's bridge");
+            (Some(tx), None)
+        }
+        Err(e) => {
+            let reason = format!("{:#}", e);
+            warn!("Serving read-only, sends are disabled: {}", reason);
+            (None, Some(reason))
+        }
+    };
+
+    // The stdio client can be anonymized like an OAuth one, as "stdio"
+    let anonymization_salt = store.get_mcp_anonymization_salt("stdio")?;
+    let access = store.get_mcp_access("stdio")?;
+    let mut server = mcp::WhatsAppMcpServer::new(
+        Arc::new(store),
+        command_tx,
+        translator,
+        Arc::new(new_chat::PendingNumberChecks::default()),
+        Arc::new(send_guard::PendingConfirmations::default()),
+        args.mcp_confirm_language,
+        "stdio".to_string(),
+    )
+    .with_access(access);
+    if let Some(reason) = &sends_disabled {
+        server = server.with_sends_disabled(reason);
+    }
+    if let Some(salt) = anonymization_salt {
+        server = server.with_anonymization(salt);
+    }
+    server.serve_io(rmcp::transport::stdio()).await
+}
+
 /// Run in web server mode
 async fn run_web_mode(
     config: BridgeConfig,
@@ -126,7 +376,15 @@ async fn run_web_mode(
     translator: Option<Arc<TranslationService>>,
 ) -> Result<()> {
     // Initialize message store
-    let store = MessageStore::new(&data_dir)?;
+    let store =
+        MessageStore::new(&data_dir)?.with_min_free_space(args.min_free_space_mb * 1024 * 1024);
+
+    if let Some(translator) = &translator {
+        apply_saved_models(&store, translator, &args);
+    }
+    if let Some(depth) = args.history_depth {
+        store.set_history_depth(depth)?;
+    }
 
     // Find web directory (relative to executable or in project)
     let web_dir = find_web_dir()?;
@@ -139,14 +397,113 @@ async fn run_web_mode(
         data_dir.clone(),
         translator.clone(),
         args.password.clone(),
+        args.auto_suggest_replies
+            .then_some(args.suggest_replies_daily_cap),
+        send_guard::LanguageGuardConfig {
+            mcp_default: args.mcp_confirm_language,
+            web: args.web_confirm_language,
+        },
     );
 
+    if let Some(token) = state.setup_token.read().await.as_deref() {
+        warn!(
+            "No web password is set. Set one with PUT /api/auth/password using setup token {}",
+            token
+        );
+    }
+
+    state.view_once.set_archive(args.archive_view_once);
+    state
+        .language_seeding
+        .set_enabled(!args.no_language_seeding);
+    state
+        .language_seeding
+        .set_daily_cap(args.language_seeding_daily_cap);
+    state.access_log.set_enabled(args.access_log);
+    state
+        .maintenance
+        .set_max_link_previews(args.max_link_previews);
+    state
+        .maintenance
+        .set_wal_limit_bytes(args.wal_size_limit_mb * 1024 * 1024);
+    state.push.set_subject(&args.push_subject);
+    state.reports.set_config(reports::ReportConfig {
+        timezone: tzinfer::parse_timezone(&args.report_timezone).with_context(|| {
+            format!(
+                "Unknown report timezone {} (use a name like Europe/Paris)",
+                args.report_timezone
+            )
+        })?,
+        schedule: args.weekly_report,
+        webhook_url: args.report_webhook.clone(),
+    });
+    state.geocoder.set_config(geocode::GeocoderConfig {
+        geocoder_url: (!args.no_geocoding).then(|| args.geocoder_url.clone()),
+        map_template: args.map_thumbnail_template.clone(),
+    });
+    state.spawn_request_sweeper();
+    state.spawn_connection_monitor();
+    state.spawn_translation_provider_watcher();
+    state.spawn_translation_availability_watcher();
+    if let Err(e) = command_socket::serve(state.clone(), &data_dir).await {
+        warn!("mcp-stdio sends are unavailable: {:#}", e);
+    }
+    maintenance::spawn(state.clone());
+    reports::spawn(state.clone());
+    language_seeding::start(state.clone());
+
+    // Watch free disk space, going read-only while it's low
+    let disk_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(disk_guard::CHECK_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = disk_state.shutdown.wait() => break,
+            }
+            let store = disk_state.store.clone();
+            let transition = tokio::task::spawn_blocking(move || store.check_disk_space())
+                .await
+                .ok()
+                .flatten();
+            if transition.is_some() {
+                disk_state.broadcast_disk_status();
+            }
+        }
+    });
+
+    // Replay writes spilled after disk errors, now and then every minute
+    let spill_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(spill::RECOVERY_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = spill_state.shutdown.wait() => break,
+            }
+            let store = spill_state.store.clone();
+            match tokio::task::spawn_blocking(move || store.recover_spilled()).await {
+                Ok(Ok(0)) | Err(_) => {}
+                Ok(Ok(_)) => spill_state.broadcast_disk_status(),
+                Ok(Err(e)) => warn!("Spilled writes are still waiting: {:#}", e),
+            }
+        }
+    });
+
+    // Check the TLS certificate and key up front so a bad pair stops startup
+    let https = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => Some(tls::HttpsConfig::load(cert, key, args.tls_redirect_http)?),
+        _ => None,
+    };
+
     // Spawn the web server (once, outside the bridge loop)
     let server_state = state.clone();
     let host = args.host.clone();
     let port = args.port;
+    let require_all_binds = args.require_all_binds;
     tokio::spawn(async move {
-        if let Err(e) = web::start_server(server_state, &host, port).await {
+        if let Err(e) = web::start_server(server_state, &host, port, require_all_binds, https).await
+        {
             error!("Web server error: {}", e);
         }
     });
@@ -158,16 +515,24 @@ async fn run_web_mode(
         let _ = shutdown_tx.send(());
     });
 
+    let drain = std::time::Duration::from_secs(args.shutdown_drain_secs);
+    let mut report = None;
+
     // Bridge restart loop - restarts bridge after logout
     loop {
         // Channel for receiving events from the bridge
         let (event_tx, mut event_rx) = mpsc::channel::<BridgeEvent>(100);
 
+        // Wait out any logout in progress; holding the lifecycle lock while
+        // spawning keeps a logout from starting mid-respawn
+        let restart_guard = state.lifecycle.begin_restart().await;
+
         // Spawn the bridge process
         print_info("Starting WhatsApp bridge...");
         let bridge = match BridgeProcess::spawn(config.clone(), event_tx).await {
             Ok(b) => b,
             Err(e) => {
+                drop(restart_guard);
                 print_error(&format!("Failed to start bridge: {}", e));
                 tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
                 continue;
@@ -176,21 +541,47 @@ async fn run_web_mode(
 
         // Pass the bridge's command sender to the app state for sending messages
         state.set_command_tx(bridge.command_sender()).await;
+        state.lifecycle.bridge_started(restart_guard);
 
         // Event loop for this bridge instance
         let should_exit = loop {
+            let batch_deadline = state
+                .history_batch
+                .deadline()
+                .map(tokio::time::Instant::from_std);
             tokio::select! {
                 _ = &mut shutdown_rx => {
                     print_info("Shutting down...");
+                    // Drained while the bridge is still up, so held-back
+                    // sends can go out
+                    report = Some(
+                        drain_web_mode(&state, &store, translator.as_ref(), &mut event_rx, drain)
+                            .await,
+                    );
                     let _ = bridge.shutdown().await;
                     break true; // Exit completely
                 }
 
+                _ = state.lifecycle.stop_requested() => {
+                    info!("Stopping bridge for logout...");
+                    if let Err(e) = bridge.shutdown().await {
+                        warn!("Failed to stop bridge cleanly: {}", e);
+                    }
+                    break false; // Restart bridge once logout completes
+                }
+
+                _ = tokio::time::sleep_until(batch_deadline.unwrap_or_else(tokio::time::Instant::now)),
+                    if batch_deadline.is_some() => {
+                    if let Err(e) = flush_history_batch(&state, &store).await {
+                        report_event_error(&state, e);
+                    }
+                }
+
                 event = event_rx.recv() => {
                     match event {
                         Some(event) => {
-                            if let Err(e) = handle_web_event(event, &state, &store, translator.as_ref()).await {
-                                error!("Error handling event: {}", e);
+                            if let Err(e) = dispatch_web_event(event, &state, &store, translator.as_ref()).await {
+                                report_event_error(&state, e);
                             }
                         }
                         None => {
@@ -203,6 +594,13 @@ async fn run_web_mode(
             }
         };
 
+        if let Err(e) = flush_history_batch(&state, &store).await {
+            report_event_error(&state, e);
+        }
+        state.clear_command_tx().await;
+        state.lifecycle.bridge_stopped();
+        state.abandon_history_sync();
+
         if should_exit {
             break;
         }
@@ -211,9 +609,132 @@ async fn run_web_mode(
         tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
     }
 
+    // Background tasks still hold the store, so write queued usage explicitly
+    store.flush_usage();
+
+    if let Some(report) = report {
+        let unsaved = store.disk_status().buffered_messages;
+        let abandoned: Vec<String> = report
+            .abandoned
+            .iter()
+            .map(|(kind, count)| format!("{} {}", count, kind))
+            .collect();
+        let summary = format!(
+            "Shutdown complete: drained {} in-flight items, abandoned {} ({}), {} messages left unsaved",
+            report.drained,
+            report.abandoned_total(),
+            if abandoned.is_empty() {
+                "none".to_string()
+            } else {
+                abandoned.join(", ")
+            },
+            unsaved
+        );
+        if report.abandoned_total() > 0 || unsaved > 0 {
+            warn!("{}", summary);
+        } else {
+            info!("{}", summary);
+        }
+    }
+
     Ok(())
 }
 
+/// Report a bridge event that couldn't be handled, as a storage error when
+/// the database is what failed
+fn report_event_error(state: &AppState, e: anyhow::Error) {
+    let category = if e.downcast_ref::<rusqlite::Error>().is_some() {
+        ErrorCategory::Storage
+    } else {
+        ErrorCategory::Bridge
+    };
+    state.report_error(category, format!("Error handling event: {}", e));
+}
+
+/// The drain phase of a shutdown: stop taking new work, handle the events
+/// the bridge already delivered, and give in-flight work until `drain` to
+/// finish. Usage is flushed to the database whatever is left.
+async fn drain_web_mode(
+    state: &Arc<AppState>,
+    store: &MessageStore,
+    translator: Option<&Arc<TranslationService>>,
+    event_rx: &mut mpsc::Receiver<BridgeEvent>,
+    drain: std::time::Duration,
+) -> shutdown::ShutdownReport {
+    let deadline = tokio::time::Instant::now() + drain;
+    state.begin_shutdown();
+
+    // Incoming messages already received would be lost, so they're still
+    // stored and translated
+    let mut handled = 0;
+    let events_done = tokio::time::timeout_at(deadline, async {
+        while let Ok(event) = event_rx.try_recv() {
+            if let Err(e) = dispatch_web_event(event, state, store, translator).await {
+                report_event_error(state, e);
+            }
+            handled += 1;
+        }
+        if let Err(e) = flush_history_batch(state, store).await {
+            report_event_error(state, e);
+        }
+    })
+    .await
+    .is_ok();
+
+    let mut report = state
+        .shutdown
+        .drain(deadline.saturating_duration_since(tokio::time::Instant::now()))
+        .await;
+    report.drained += handled;
+    if !events_done {
+        report.abandoned.insert("bridge event", 1 + event_rx.len());
+    }
+    store.flush_usage();
+    report
+}
+
+/// Handle a bridge event in web mode unless a logout is in progress.
+///
+/// The lifecycle lock is held while the event is handled, so a logout waits
+/// for in-flight events and every later event is dropped until it completes.
+/// History messages are only batched here, to be stored by a later event,
+/// once the batch fills, or after `history_sync::BATCH_DELAY`.
+async fn dispatch_web_event(
+    event: BridgeEvent,
+    state: &Arc<AppState>,
+    store: &MessageStore,
+    translator: Option<&Arc<TranslationService>>,
+) -> Result<()> {
+    state.connection.record_event();
+    let lifecycle = state.lifecycle.lock().await;
+    if *lifecycle == lifecycle::LifecycleState::LoggingOut {
+        debug!("Dropping bridge event during logout");
+        return Ok(());
+    }
+
+    // History messages are stored in batches; any other event waits until
+    // the ones before it are stored
+    match event {
+        BridgeEvent::Message(msg) if msg.is_history => {
+            let unread_count = msg.unread_count;
+            let message = prepare_message(msg, state, store, translator).await;
+            if state.history_batch.push(BatchedMessage {
+                message,
+                unread_count,
+            }) {
+                store_history_batch(state, store)?;
+            }
+            Ok(())
+        }
+        event => {
+            if let Err(e) = store_history_batch(state, store) {
+                report_event_error(state, e);
+            }
+            handle_web_event(event, state, store, translator).await
+        }
+    }
+}
+
 /// Handle events in web mode
 async fn handle_web_event(
     event: BridgeEvent,
@@ -227,14 +748,46 @@ async fn handle_web_event(
             state.set_qr_code(data).await;
         }
 
-        BridgeEvent::Connected { phone, name, .. } => {
+        BridgeEvent::Connected {
+            phone,
+            name,
+            lid,
+            capabilities,
+            ..
+        } => {
             info!("Connected as {} ({})", name, phone);
+            state.set_own_jids(&phone, lid.as_deref()).await;
+            *state.bridge_capabilities.write().await = capabilities;
+            let jid = format!("{}@s.whatsapp.net", phone);
+            // My own chat, under either JID, is Saved Messages
+            if let Some(lid) = lid.as_deref() {
+                if let Err(e) = store.link_identity(&web::bare_jid(lid), &jid, "self") {
+                    warn!("Failed to link my LID to {}: {}", jid, e);
+                }
+            }
+            if let Err(e) = store.mark_self_chat(&jid) {
+                error!("Failed to set up Saved Messages: {}", e);
+            }
+            if let Err(e) = state.update_own_profile(|profile| {
+                profile.jid = Some(jid);
+                profile.phone = Some(phone.clone());
+                if !name.is_empty() {
+                    profile.name = Some(name.clone());
+                }
+            }) {
+                error!("Failed to save my profile: {}", e);
+            }
             state.set_connected(true, Some(phone), Some(name)).await;
+            state.refresh_own_profile();
+            // Subscriptions don't survive a reconnect
+            state.resubscribe_presence().await;
+            request_history_sync(state, store).await;
         }
 
         BridgeEvent::ConnectionState { state: conn_state } => match conn_state {
             ConnectionState::Disconnected | ConnectionState::LoggedOut => {
                 state.set_connected(false, None, None).await;
+                state.abandon_history_sync();
             }
             _ => {}
         },
@@ -243,43 +796,47 @@ async fn handle_web_event(
             // Extract unread count before moving msg
             let unread_count = msg.unread_count;
             let is_history = msg.is_history;
+            let mut stored_msg = prepare_message(msg, state, store, translator).await;
+
+            // Live incoming messages count as unread, unless it's a group set
+            // to mentions only and the message doesn't mention me
+            let counts_as_unread = unread_count.is_none()
+                && !stored_msg.is_from_me
+                && !is_history
+                && (stored_msg.mentions_me
+                    || stored_msg.chat_type != "group"
+                    || !store.get_mentions_only(&stored_msg.contact_id)?);
+
+            let persisted =
+                persist_message(store, &mut stored_msg, counts_as_unread, unread_count)?;
+
+            // Attach quick-reply suggestions to live incoming messages (automatic mode)
+            let suggestions = if is_history {
+                None
+            } else {
+                state.auto_reply_suggestions(&stored_msg).await
+            };
 
-            // Process and store the message
-            let stored_msg = process_message(msg, translator, Some(store)).await;
-
-            // Update contact with contact_name (not sender_name!)
-            // contact_name is the chat name (other person for DMs, group name for groups)
-            // sender_name changes based on who sent the message
-            store.upsert_contact(
-                &stored_msg.contact_id,
-                stored_msg.contact_name.as_deref(),
-                stored_msg.contact_phone.as_deref(),
-                Some(&stored_msg.chat_type),
-                stored_msg.timestamp,
+            announce_message(
+                state,
+                store,
+                stored_msg,
+                persisted,
+                is_history,
+                counts_as_unread,
+                suggestions,
             )?;
-
-            // Handle unread counts
-            if let Some(unread) = unread_count {
-                // History sync message with unread count from WhatsApp - use it directly
-                store.set_unread_count(&stored_msg.contact_id, unread)?;
-            } else if !stored_msg.is_from_me && !is_history {
-                // Live incoming message - increment unread
-                store.increment_unread(&stored_msg.contact_id)?;
-            }
-
-            // Store message
-            store.add_message(&stored_msg)?;
-
-            // Broadcast to WebSocket clients
-            state.broadcast_message(stored_msg);
         }
 
         BridgeEvent::Error { code, message } => {
-            error!("Bridge error [{}]: {}", code, message);
+            state.report_error(
+                ErrorCategory::Bridge,
+                format!("Bridge error [{}]: {}", code, message),
+            );
         }
 
         BridgeEvent::Log { level, message } => match level.as_str() {
-            "error" => error!("{}", message),
+            "error" => state.report_error(ErrorCategory::Bridge, message),
             "warn" => warn!("{}", message),
             "info" => info!("{}", message),
             _ => debug!("{}", message),
@@ -290,6 +847,10 @@ async fn handle_web_event(
             state.set_connected(false, None, None).await;
         }
 
+        BridgeEvent::Unknown { raw } => {
+            debug!("Ignoring unknown bridge event: {}", raw);
+        }
+
         BridgeEvent::SendResult {
             request_id,
             success,
@@ -297,15 +858,27 @@ async fn handle_web_event(
             timestamp,
             error,
         } => {
+            let outcome = pending::SendOutcome {
+                success,
+                error: error.clone(),
+            };
+            state.connection.send_confirmed(&request_id.to_string());
+            if !state.pending_sends.complete(request_id, outcome) {
+                debug!("Send result {} arrived with nothing waiting", request_id);
+            }
             if success {
                 debug!(
                     "Message sent successfully: {:?} at {:?}",
                     message_id, timestamp
                 );
             } else {
-                error!(
-                    "Failed to send message (request {}): {:?}",
-                    request_id, error
+                state.report_error(
+                    ErrorCategory::Send,
+                    format!(
+                        "Failed to send message (request {}): {}",
+                        request_id,
+                        error.as_deref().unwrap_or("unknown error")
+                    ),
                 );
             }
             // TODO: Could broadcast send result to WebSocket clients for UI updates
@@ -322,7 +895,72 @@ async fn handle_web_event(
                 debug!("Profile picture error (request {}): {}", request_id, err);
             }
             // Notify the waiting request
-            state.handle_profile_picture_response(request_id, url).await;
+            state.pending_avatars.complete(request_id, url);
+        }
+
+        BridgeEvent::OwnProfile {
+            request_id,
+            jid,
+            name,
+            status,
+            error,
+        } => {
+            let result = match error {
+                Some(err) => {
+                    debug!("Profile error (request {}): {}", request_id, err);
+                    Err(err)
+                }
+                None => state
+                    .update_own_profile(|profile| {
+                        profile.jid = jid.or(profile.jid.take());
+                        profile.name = name.or(profile.name.take());
+                        // Left out if the bridge couldn't fetch it; empty if cleared
+                        if let Some(status) = status {
+                            profile.status = (!status.is_empty()).then_some(status);
+                        }
+                    })
+                    .map(|_| ())
+                    .map_err(|e| {
+                        error!("Failed to save my profile: {}", e);
+                        "Failed to save profile".to_string()
+                    }),
+            };
+            state.pending_profiles.complete(request_id, result);
+        }
+
+        BridgeEvent::NumberCheckResult {
+            request_id,
+            phone,
+            jid,
+            is_on_whatsapp,
+            error,
+        } => {
+            debug!(
+                "Number check for {} (request {}): on WhatsApp = {}",
+                phone, request_id, is_on_whatsapp
+            );
+            state
+                .number_checks
+                .complete(
+                    request_id,
+                    new_chat::NumberCheck {
+                        jid,
+                        is_on_whatsapp,
+                        error,
+                    },
+                )
+                .await;
+        }
+
+        BridgeEvent::GroupCreated {
+            request_id,
+            jid,
+            error,
+        } => {
+            debug!("Group created (request {}): {:?}", request_id, jid);
+            state
+                .pending_groups
+                .complete(request_id, groups::GroupCreation { jid, error });
         }
 
         BridgeEvent::ChatPresence {
@@ -337,34 +975,199 @@ async fn handle_web_event(
             };
             // Log at info level so it's always visible
             info!("Chat presence: {} is {} in {}", user_id, state_str, chat_id);
+            let chat_id = store.resolve_contact_id(&chat_id)?;
             // Broadcast to WebSocket clients
             state.broadcast_typing(chat_id, user_id, state_str.to_string());
         }
 
+        BridgeEvent::Presence {
+            jid,
+            is_online,
+            last_seen,
+        } => {
+            let contact_id = store.resolve_contact_id(&jid)?;
+            let last_seen = last_seen.map(|secs| secs * 1000);
+            if let Some(last_seen) = last_seen {
+                store.set_last_seen(&contact_id, last_seen)?;
+            }
+            let presence = presence::Presence {
+                is_online,
+                last_seen,
+            };
+            if state.presence.update(&contact_id, presence) {
+                debug!("Presence: {} online = {}", contact_id, is_online);
+                state.broadcast_presence(contact_id);
+            }
+        }
+
         BridgeEvent::MarkAsRead { chat_id } => {
             // Chat was marked as read from another device (e.g., user's phone)
             info!("Chat marked as read from another device: {}", chat_id);
-            store.mark_as_read(&chat_id)?;
+            let chat_id = store.resolve_contact_id(&chat_id)?;
+            let last_read_timestamp = store.mark_as_read(&chat_id)?;
             // Broadcast to WebSocket clients so UI updates
-            state.broadcast_mark_as_read(chat_id);
+            state.broadcast_mark_as_read(chat_id, last_read_timestamp);
+        }
+
+        BridgeEvent::ChatMetadata {
+            chat_id,
+            description,
+            ephemeral_duration,
+            subject,
+            subject_changed_by,
+            timestamp,
+        } => {
+            let contact_id = store.resolve_contact_id(&chat_id)?;
+            let chat_type = if contact_id.ends_with("@g.us") {
+                "group"
+            } else {
+                "private"
+            };
+            let subject = subject.as_deref().map(str::trim).filter(|s| !s.is_empty());
+            let contact_change =
+                store.upsert_contact(&contact_id, subject, None, Some(chat_type), 0)?;
+            let metadata_change =
+                store.set_chat_metadata(&contact_id, description.as_deref(), ephemeral_duration)?;
+
+            // Subject and description changes show up in the timeline
+            let actor = subject_changed_by
+                .as_deref()
+                .map(|jid| chat_actor_name(store, jid))
+                .transpose()?;
+            let mut notices = Vec::new();
+            if let (ContactChange::NameChanged { new, .. }, Some(_)) = (&contact_change, subject) {
+                notices.push(match &actor {
+                    Some(actor) => format!("{} changed the subject to \"{}\"", actor, new),
+                    None => format!("The subject was changed to \"{}\"", new),
+                });
+            }
+            if metadata_change.description {
+                let removed = description.as_deref().is_some_and(|d| d.trim().is_empty());
+                let action = if removed { "removed" } else { "changed" };
+                notices.push(match &actor {
+                    Some(actor) => format!("{} {} the group description", actor, action),
+                    None => format!("The group description was {}", action),
+                });
+            }
+            let timestamp = timestamp
+                .map(|secs| secs * 1000)
+                .unwrap_or_else(|| chrono::Utc::now().timestamp_millis());
+            for notice in notices {
+                let mut message =
+                    storage::StoredMessage::system(&contact_id, chat_type, timestamp, &notice);
+                message.sort_key = store.add_message(&message)?;
+                state.broadcast_message(message, None);
+            }
+
+            if contact_change != ContactChange::Unchanged || metadata_change.any() {
+                debug!("Chat metadata changed for {}", contact_id);
+                if let Some(contact) = store.get_contact(&contact_id)? {
+                    state.broadcast_contact_updated(contact);
+                }
+            }
+        }
+
+        BridgeEvent::HistorySyncProgress {
+            chats_done,
+            chats_total,
+            messages_done,
+            complete,
+        } => {
+            debug!(
+                "History sync: {}/{} chats, {} messages",
+                chats_done, chats_total, messages_done
+            );
+            if complete {
+                info!(
+                    "History sync imported: {} messages from {} chats",
+                    messages_done, chats_done
+                );
+                // Not asked for again after a restart
+                if let Err(e) = store.set_history_sync_complete(true) {
+                    error!("Failed to record the history sync: {}", e);
+                }
+                language_seeding::start(state.clone());
+            }
+            state.history_sync_progress(history_sync::SyncProgress {
+                chats_done,
+                chats_total,
+                messages_done,
+                complete,
+            });
         }
     }
 
     Ok(())
 }
 
+/// Tell the bridge how much history to import: the configured depth until
+/// a sync has been imported, then none
+async fn request_history_sync(state: &AppState, store: &MessageStore) {
+    if !state.bridge_supports("set_history_sync").await {
+        return;
+    }
+    let depth = match store.is_history_sync_complete() {
+        Ok(true) => HistoryDepth::None,
+        Ok(false) => store.get_history_depth().unwrap_or_default(),
+        Err(e) => {
+            warn!("Failed to check for an imported history sync: {}", e);
+            return;
+        }
+    };
+    debug!("History sync depth: {}", depth.as_str());
+    if let Err(e) = state
+        .send_bridge_command(BridgeCommand::SetHistorySync { depth })
+        .await
+    {
+        warn!("Failed to set the history sync depth: {}", e);
+    }
+}
+
+/// Who a chat notice says made a change: "You", a contact's name, or the
+/// phone number in their JID
+fn chat_actor_name(store: &MessageStore, jid: &str) -> Result<String> {
+    // Drop the device part of "user:device@server"
+    let (user, server) = jid.split_once('@').unwrap_or((jid, ""));
+    let user = user.split(':').next().unwrap_or(user);
+    let contact = store.get_contact(&format!("{}@{}", user, server))?;
+    Ok(match contact {
+        Some(contact) if contact.contact_type.as_deref() == Some("self") => "You".to_string(),
+        Some(contact) => contact
+            .name
+            .or(contact.phone)
+            .unwrap_or_else(|| user.to_string()),
+        None => user.to_string(),
+    })
+}
+
 /// Process a message, translating if necessary
 async fn process_message(
     msg: Message,
     translator: Option<&Arc<TranslationService>>,
     store: Option<&storage::MessageStore>,
 ) -> StoredMessage {
-    let contact_id = msg.chat.jid().to_string();
+    // Fold LID / phone JID aliases into a single canonical contact
+    let contact_id = match store {
+        Some(store) => {
+            if let Some((canonical, alternate)) = msg.chat.identity_pair() {
+                if let Err(e) = store.link_identity(alternate, canonical, "bridge") {
+                    tracing::warn!("Failed to link {} to {}: {}", alternate, canonical, e);
+                }
+            }
+            store
+                .resolve_contact_id(msg.chat.jid())
+                .unwrap_or_else(|_| msg.chat.jid().to_string())
+        }
+        None => msg.chat.jid().to_string(),
+    };
+    let is_self_chat = store.is_some_and(|s| s.is_self_chat(&contact_id).unwrap_or(false));
     let chat_type = match &msg.chat {
+        _ if is_self_chat => "self",
         bridge::Chat::Private { .. } => "private",
         bridge::Chat::Group { .. } => "group",
         bridge::Chat::Broadcast { .. } => "broadcast",
         bridge::Chat::Status { .. } => "status",
+        bridge::Chat::Channel { .. } => "channel",
     };
 
     // Get conversation settings if we have a store
@@ -373,64 +1176,108 @@ async fn process_message(
         .unwrap_or_default();
 
     // Extract text content for translation (skip history messages)
-    let (original_text, translated_text, source_language, is_translated) =
-        if let Some(translator) = translator {
-            if let Some(text) = extract_text_content(&msg.content) {
-                if !msg.is_from_me && !msg.is_history {
-                    // Only translate incoming messages (not history sync)
-                    let result = translator
-                        .process_text(
-                            &text,
-                            settings.language_override.as_deref(),
-                            settings.translation_style.as_deref(),
-                        )
-                        .await;
-
-                    // Record usage if we have a store and there was actual API usage
-                    if let Some(store) = store {
-                        if result.usage.input_tokens > 0 {
-                            if let Err(e) = store.record_usage(
-                                Some(&contact_id),
-                                Some(&msg.id),
-                                &result.usage,
-                                if result.needs_translation {
-                                    "translate_incoming"
-                                } else {
-                                    "detect_language"
-                                },
-                            ) {
-                                tracing::warn!("Failed to record usage: {}", e);
-                            }
+    let (
+        original_text,
+        translated_text,
+        source_language,
+        is_translated,
+        vocabulary,
+        triage,
+        translation_status,
+    ) = if let Some(translator) = translator {
+        if let Some(text) = extract_text_content(&msg.content) {
+            let skip_channel = msg.chat.is_channel() && !translator.translates_channels();
+            // Group participants filtered out aren't even language-detected
+            let skip_participant = match (store, &msg.chat) {
+                (Some(store), bridge::Chat::Group { .. }) => !store
+                    .translates_participant(&contact_id, &web::bare_jid(&msg.from.jid))
+                    .unwrap_or(true),
+                _ => false,
+            };
+            // Notes to myself aren't translated unless I pick a language
+            let skip_self = is_self_chat && settings.language_override.is_none();
+            if !msg.is_from_me
+                && !msg.is_history
+                && !skip_channel
+                && !skip_participant
+                && !skip_self
+            {
+                // Only translate incoming messages (not history sync)
+                let result = translator
+                    .process_text(
+                        &text,
+                        settings.language_override.as_deref(),
+                        settings.translation_style.as_deref(),
+                        settings.learning_mode,
+                        settings.triage_enabled(chat_type),
+                    )
+                    .await;
+
+                // Record usage if we have a store and there was actual API usage
+                if let Some(store) = store {
+                    if result.usage.input_tokens > 0 {
+                        if let Err(e) = store.record_usage(
+                            Some(&contact_id),
+                            Some(&msg.id),
+                            &result.usage,
+                            if result.needs_translation {
+                                "translate_incoming"
+                            } else {
+                                "detect_language"
+                            },
+                        ) {
+                            tracing::warn!("Failed to record usage: {}", e);
                         }
                     }
-
-                    (
-                        Some(result.original_text),
-                        result.translated_text,
-                        Some(result.source_language),
-                        result.needs_translation,
-                    )
-                } else {
-                    (Some(text), None, None, false)
                 }
+
+                (
+                    Some(result.original_text),
+                    result.translated_text,
+                    Some(result.source_language),
+                    result.needs_translation,
+                    Some(result.vocabulary).filter(|v| !v.is_empty()),
+                    result.triage,
+                    Some(result.status),
+                )
             } else {
-                (None, None, None, false)
+                // History is left for later; a filter skip is for good
+                let status = if msg.is_from_me {
+                    TranslationStatus::NotNeeded
+                } else if skip_channel || skip_participant || skip_self {
+                    TranslationStatus::SkippedFilter
+                } else {
+                    TranslationStatus::Pending
+                };
+                (Some(text), None, None, false, None, None, Some(status))
             }
         } else {
-            (extract_text_content(&msg.content), None, None, false)
+            let status = TranslationStatus::NotNeeded;
+            (None, None, None, false, None, None, Some(status))
+        }
+    } else {
+        let text = extract_text_content(&msg.content);
+        let status = match (&text, msg.is_from_me) {
+            (Some(_), false) => TranslationStatus::Pending,
+            _ => TranslationStatus::NotNeeded,
         };
+        (text, None, None, false, None, None, Some(status))
+    };
 
     // Serialize content to JSON
     let content_json = serde_json::to_string(&msg.content).unwrap_or_default();
     let content: Option<serde_json::Value> = serde_json::from_str(&content_json).ok();
-    let content_type = msg.content.type_name().to_string();
+    let content_type = match &msg.content {
+        MessageContent::Unknown { raw_type, .. } => format!("unknown:{}", raw_type),
+        content => content.type_name().to_string(),
+    };
 
     // Get contact name and phone from chat info
     // For private chats: this is the other person
     // For groups: this is the group name
     let (contact_name, contact_phone) = match &msg.chat {
-        bridge::Chat::Private { name, jid } => {
-            let phone = jid.split('@').next().map(|s| s.to_string());
+        bridge::Chat::Private { name, .. } => {
+            let phone = contact_id.split('@').next().map(|s| s.to_string());
             (name.clone(), phone)
         }
         bridge::Chat::Group { name, .. } => (name.clone(), None),
@@ -445,6 +1292,8 @@ async fn process_message(
             )
         }
         bridge::Chat::Status { .. } => (Some("Status".to_string()), None),
+        // Channels have no phone number to extract
+        bridge::Chat::Channel { name, .. } => (name.clone(), None),
     };
 
     StoredMessage {
@@ -465,7 +1314,299 @@ async fn process_message(
         translated_text,
         source_language,
         is_translated,
+        origin: None,
+        sent_by: None,
+        mentioned_jids: msg.mentioned_jids,
+        mentions_me: false,
+        vocabulary,
+        audio: None,
+        sort_key: None,
+        triage,
+        translation_status,
+    }
+}
+
+/// Turn a message from the bridge into the one stored, with its media
+/// prepared and whether it mentions me
+async fn prepare_message(
+    msg: Message,
+    state: &Arc<AppState>,
+    store: &MessageStore,
+    translator: Option<&Arc<TranslationService>>,
+) -> StoredMessage {
+    let mut stored_msg = process_message(msg, translator, Some(store)).await;
+    if !state.view_once.archive() {
+        // Held in memory only (and not thumbnailed) until it's opened once
+        if let Some((media_data, mime_type)) = view_once::take_payload(&mut stored_msg) {
+            state
+                .view_once
+                .insert(&stored_msg.id, media_data, mime_type);
+        }
+    }
+    thumbnail::attach(&mut stored_msg).await;
+    sticker::attach(&mut stored_msg).await;
+    audio::attach(&mut stored_msg).await;
+    geocode::attach_map_thumbnail(&mut stored_msg);
+    stored_msg.mentions_me =
+        !stored_msg.is_from_me && state.mentions_me(&stored_msg.mentioned_jids).await;
+    stored_msg
+}
+
+/// Tell clients about a message once it's stored
+fn announce_message(
+    state: &Arc<AppState>,
+    store: &MessageStore,
+    mut stored_msg: StoredMessage,
+    persisted: Persisted,
+    is_history: bool,
+    counts_as_unread: bool,
+    suggestions: Option<Vec<String>>,
+) -> Result<()> {
+    let Persisted {
+        written,
+        contact_change,
+        claimed,
+    } = persisted;
+    if written {
+        state.resolve_location(&stored_msg);
+    }
+
+    // Broadcast to WebSocket clients, with images as thumbnails
+    let contact_id = stored_msg.contact_id.clone();
+    match claimed {
+        Some(claimed) => {
+            state.connection.send_confirmed(&claimed.pending_id);
+            let _ = state
+                .broadcast_tx
+                .send(web::WebSocketEvent::MessageIdUpdated {
+                    contact_id: contact_id.clone(),
+                    old_id: claimed.pending_id,
+                    new_id: stored_msg.id,
+                    timestamp: stored_msg.timestamp,
+                    sort_key: claimed.sort_key,
+                });
+        }
+        None => {
+            if counts_as_unread {
+                state.push_notification(&stored_msg);
+            }
+            thumbnail::drop_full_image(&mut stored_msg);
+            state.broadcast_message(stored_msg, suggestions);
+        }
+    }
+
+    // Let clients pick up a new chat or a rename without reloading the
+    // list; chats in a history sync are sent once it's imported
+    let deferred = is_history && state.history_sync.defer(&contact_id);
+    if contact_change != ContactChange::Unchanged {
+        if let ContactChange::NameChanged { old, new } = &contact_change {
+            info!("Contact {} renamed from {:?} to {:?}", contact_id, old, new);
+        }
+        if !deferred {
+            if let Some(contact) = store.get_contact(&contact_id)? {
+                state.broadcast_contact_updated(contact);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Store the history messages waiting in the batch and tell clients about
+/// them, as `handle_web_event` does for each message
+fn store_history_batch(state: &Arc<AppState>, store: &MessageStore) -> Result<()> {
+    let batch = state.history_batch.take();
+    if batch.is_empty() {
+        return Ok(());
+    }
+
+    let count = batch.len();
+    let started = std::time::Instant::now();
+    let stored = persist_batch(store, batch)?;
+    debug!(
+        "Stored {} history messages in {:?}",
+        count,
+        started.elapsed()
+    );
+
+    for (stored_msg, persisted) in stored {
+        announce_message(state, store, stored_msg, persisted, true, false, None)?;
+    }
+    Ok(())
+}
+
+/// Store the waiting history messages, unless a logout is in progress
+async fn flush_history_batch(state: &Arc<AppState>, store: &MessageStore) -> Result<()> {
+    let lifecycle = state.lifecycle.lock().await;
+    if *lifecycle == lifecycle::LifecycleState::LoggingOut {
+        return Ok(());
+    }
+    store_history_batch(state, store)
+}
+
+/// What `persist_message` did with a message
+#[derive(Default)]
+struct Persisted {
+    /// False if it was only buffered because the disk is nearly full
+    written: bool,
+    contact_change: ContactChange,
+    /// The copy stored when I sent it, if this is WhatsApp's copy
+    claimed: Option<ClaimedPending>,
+}
+
+/// Store a processed message from the bridge, updating its contact and
+/// unread count
+fn persist_message(
+    store: &MessageStore,
+    stored_msg: &mut StoredMessage,
+    counts_as_unread: bool,
+    unread_count: Option<u32>,
+) -> Result<Persisted> {
+    if store.is_read_only() {
+        // Disk is nearly full: keep the message in memory until it can be written
+        store.buffer_message(stored_msg, counts_as_unread);
+        return Ok(Persisted::default());
+    }
+
+    // Update contact with contact_name (not sender_name!)
+    // contact_name is the chat name (other person for DMs, group name for groups)
+    // sender_name changes based on who sent the message
+    let contact_change = store.upsert_contact(
+        &stored_msg.contact_id,
+        stored_msg.contact_name.as_deref(),
+        stored_msg.contact_phone.as_deref(),
+        Some(&stored_msg.chat_type),
+        stored_msg.timestamp,
+    )?;
+
+    // Live message: count it as unread. Failing that, the
+    // message itself is still stored (or spilled) below.
+    if counts_as_unread {
+        if let Err(e) = store.increment_unread(&stored_msg.contact_id) {
+            error!("Failed to count message as unread: {}", e);
+        }
+    }
+
+    // Store message, unless it's WhatsApp's copy of one I sent
+    // from here, which takes over the copy stored when it was sent
+    let claimed = store
+        .claim_pending_message(stored_msg)
+        .inspect_err(|e| error!("Failed to match pending message: {}", e))
+        .unwrap_or(None);
+    stored_msg.sort_key = match &claimed {
+        Some(claimed) => Some(claimed.sort_key),
+        None => store.add_message(stored_msg)?,
+    };
+
+    // History sync message with unread count from WhatsApp - use it
+    // directly, once the message is stored so it can be the anchor
+    if let Some(unread) = unread_count {
+        store.set_unread_count(&stored_msg.contact_id, unread)?;
+    }
+
+    Ok(Persisted {
+        written: true,
+        contact_change,
+        claimed,
+    })
+}
+
+/// Store a batch of history messages as `persist_message` does one at a
+/// time (none counts as unread): their contacts in one transaction, the
+/// messages in another, then each chat's unread count from WhatsApp once
+fn persist_batch(
+    store: &MessageStore,
+    batch: Vec<BatchedMessage>,
+) -> Result<Vec<(StoredMessage, Persisted)>> {
+    if store.is_read_only() {
+        // Disk is nearly full: keep the messages in memory until they can be written
+        return Ok(batch
+            .into_iter()
+            .map(|entry| {
+                store.buffer_message(&entry.message, false);
+                (entry.message, Persisted::default())
+            })
+            .collect());
+    }
+
+    let (mut messages, unread_counts): (Vec<_>, Vec<_>) = batch
+        .into_iter()
+        .map(|entry| (entry.message, entry.unread_count))
+        .unzip();
+    let contacts: Vec<_> = messages
+        .iter()
+        .map(|msg| ContactUpsert {
+            id: &msg.contact_id,
+            name: msg.contact_name.as_deref(),
+            phone: msg.contact_phone.as_deref(),
+            contact_type: Some(&msg.chat_type),
+            last_message_time: msg.timestamp,
+        })
+        .collect();
+    let contact_changes = store.upsert_contacts_batch(&contacts)?;
+    let writes = store.add_messages_batch(&messages)?;
+
+    // The last count each chat came with, as set one message at a time
+    let mut unread = HashMap::new();
+    for (msg, count) in messages.iter().zip(&unread_counts) {
+        if let Some(count) = count {
+            unread.insert(msg.contact_id.as_str(), *count);
+        }
+    }
+    for (contact_id, count) in unread {
+        store.set_unread_count(contact_id, count)?;
     }
+
+    for (msg, write) in messages.iter_mut().zip(&writes) {
+        msg.sort_key = write.sort_key;
+    }
+    Ok(messages
+        .into_iter()
+        .zip(contact_changes)
+        .zip(writes)
+        .map(|((msg, contact_change), write)| {
+            let persisted = Persisted {
+                written: true,
+                contact_change,
+                claimed: write.claimed,
+            };
+            (msg, persisted)
+        })
+        .collect())
+}
+
+/// Store a message for terminal mode, returning it with a preview of the
+/// chat's previous message for incoming ones. Returns None if the message
+/// is already stored, having been shown (and translated) before, here or
+/// by a web mode instance sharing the database.
+async fn store_terminal_message(
+    msg: Message,
+    translator: Option<&Arc<TranslationService>>,
+    store: &MessageStore,
+) -> Result<Option<(StoredMessage, Option<String>)>> {
+    if store.get_message_by_id(&msg.id)?.is_some() {
+        debug!("Skipping message {} already in the store", msg.id);
+        return Ok(None);
+    }
+
+    let unread_count = msg.unread_count;
+    let is_history = msg.is_history;
+    let mut stored_msg = process_message(msg, translator, Some(store)).await;
+
+    let context = if stored_msg.is_from_me {
+        None
+    } else {
+        store
+            .get_contact(&stored_msg.contact_id)?
+            .and_then(|contact| contact.last_message_preview)
+    };
+
+    // Without my own JIDs to hand, mentions only groups never count
+    let counts_as_unread = unread_count.is_none()
+        && !stored_msg.is_from_me
+        && !is_history
+        && (stored_msg.chat_type != "group" || !store.get_mentions_only(&stored_msg.contact_id)?);
+    persist_message(store, &mut stored_msg, counts_as_unread, unread_count)?;
+    Ok(Some((stored_msg, context)))
 }
 
 /// Extract text content from a message
@@ -516,20 +1657,40 @@ fn find_web_dir() -> Result<std::path::PathBuf> {
 async fn run_terminal_mode(
     config: BridgeConfig,
     json_output: bool,
+    qr_invert: bool,
     translator: Option<Arc<TranslationService>>,
+    store: Option<MessageStore>,
 ) -> Result<()> {
     // Channel for receiving events from the bridge
     let (event_tx, mut event_rx) = mpsc::channel::<BridgeEvent>(100);
 
-    // Spawn the bridge process
-    print_info("Starting WhatsApp bridge...");
+    // A saved session reconnects by itself; say so rather than imply pairing
+    let saved = bridge::saved_session(&config.data_dir);
+    match saved.as_ref().map(|session| session.phone.as_deref()) {
+        Some(Some(phone)) => print_info(&format!("Resuming session for {}...", phone)),
+        Some(None) => print_info("Resuming saved session..."),
+        None => print_info("Starting WhatsApp bridge..."),
+    }
     let bridge = BridgeProcess::spawn(config, event_tx)
         .await
         .context("Failed to start bridge process")?;
 
     let message_display = MessageDisplay::new();
-    let mut connected = false;
-    let mut qr_displayed = false;
+    let session_status = SessionStatus::new(
+        saved.and_then(|session| session.phone),
+        translator.is_some(),
+    );
+    let mut status = if json_output {
+        StatusLine::off(session_status)
+    } else {
+        StatusLine::new(session_status)
+    };
+    status.draw();
+    let mut qr_display = QrDisplay::new(qr_invert);
+    let mut unavailable = translator
+        .as_ref()
+        .filter(|_| !json_output)
+        .map(|t| t.subscribe_unavailable());
 
     // Handle Ctrl+C for graceful shutdown
     let shutdown = async {
@@ -542,11 +1703,22 @@ async fn run_terminal_mode(
         tokio::select! {
             // Check for shutdown signal
             _ = &mut shutdown => {
+                status.clear();
                 print_info("Shutting down...");
                 bridge.shutdown().await?;
                 break;
             }
 
+            // Say when translation stops working, or works again
+            Ok(()) = changed(&mut unavailable) => {
+                status.clear();
+                match translator.as_ref().and_then(|t| t.unavailable()) {
+                    Some(reason) => print_error(reason.description()),
+                    None => print_info("Translation is working again"),
+                }
+                status.draw();
+            }
+
             // Process events from the bridge
             event = event_rx.recv() => {
                 match event {
@@ -557,18 +1729,25 @@ async fn run_terminal_mode(
                                 println!("{}", json);
                             }
                         } else {
+                            status.clear();
                             handle_terminal_event(
                                 event,
                                 &message_display,
-                                &mut connected,
-                                &mut qr_displayed,
+                                &mut status,
+                                &mut qr_display,
                                 translator.as_ref(),
+                                store.as_ref(),
                             ).await?;
+                            if let Some(translator) = &translator {
+                                status.status_mut().spent_usd = Some(translator.spent_usd());
+                            }
+                            status.draw();
                         }
                     }
                     None => {
                         // Bridge closed the channel
-                        if !connected {
+                        status.clear();
+                        if status.status().state != LinkState::Connected {
                             print_error("Bridge process terminated unexpectedly");
                         }
                         break;
@@ -581,75 +1760,111 @@ async fn run_terminal_mode(
     Ok(())
 }
 
+/// Wait for a change to a watched value; never, without one
+async fn changed<T>(
+    receiver: &mut Option<watch::Receiver<T>>,
+) -> Result<(), watch::error::RecvError> {
+    match receiver {
+        Some(receiver) => receiver.changed().await,
+        None => std::future::pending().await,
+    }
+}
+
 /// Handle a single bridge event in terminal mode
 async fn handle_terminal_event(
     event: BridgeEvent,
     message_display: &MessageDisplay,
-    connected: &mut bool,
-    qr_displayed: &mut bool,
+    status: &mut StatusLine,
+    qr_display: &mut QrDisplay,
     translator: Option<&Arc<TranslationService>>,
+    store: Option<&MessageStore>,
 ) -> Result<()> {
     match event {
         BridgeEvent::Qr { data } => {
             debug!("Received QR code data");
-            render_qr_code(&data)?;
-            *qr_displayed = true;
+            status.set_hidden(true);
+            qr_display.render(&data)?;
         }
 
         BridgeEvent::Connected { phone, name, .. } => {
-            if *qr_displayed {
-                clear_qr_display()?;
-                *qr_displayed = false;
+            if qr_display.is_displayed() {
+                qr_display.clear()?;
             }
+            status.set_hidden(false);
             print_connected(&phone, &name);
-            *connected = true;
+            status.status_mut().connected(&phone, &name);
         }
 
         BridgeEvent::ConnectionState { state } => {
             debug!("Connection state: {:?}", state);
+            // Transient states only show in the status line
+            let status = status.status_mut();
             match state {
-                ConnectionState::Connecting => {
-                    print_info("Connecting to WhatsApp...");
-                }
-                ConnectionState::Connected => {
-                    if !*connected {
-                        print_info("Connected!");
-                    }
-                }
+                ConnectionState::Connecting => status.state = LinkState::Connecting,
+                ConnectionState::Connected => status.state = LinkState::Connected,
                 ConnectionState::Disconnected => {
                     print_warning("Disconnected from WhatsApp");
-                    *connected = false;
-                }
-                ConnectionState::Reconnecting => {
-                    print_info("Reconnecting...");
+                    status.state = LinkState::Disconnected;
                 }
+                ConnectionState::Reconnecting => status.state = LinkState::Reconnecting,
                 ConnectionState::LoggedOut => {
                     print_warning("Logged out from WhatsApp");
-                    *connected = false;
+                    status.state = LinkState::LoggedOut;
                 }
             }
         }
 
         BridgeEvent::Message(msg) => {
+            if !msg.is_history {
+                let status = status.status_mut();
+                if msg.is_from_me {
+                    status.sent += 1;
+                } else {
+                    status.received += 1;
+                }
+            }
+            if let Some(store) = store {
+                match store_terminal_message(msg.clone(), translator, store).await {
+                    Ok(Some((stored, context))) => {
+                        match stored.translated_text.filter(|_| stored.is_translated) {
+                            Some(translated) => message_display.display_with_translation(
+                                &msg,
+                                &translated,
+                                stored.source_language.as_deref().unwrap_or_default(),
+                                context.as_deref(),
+                            )?,
+                            None => message_display.display(&msg, context.as_deref())?,
+                        }
+                        return Ok(());
+                    }
+                    Ok(None) => return Ok(()),
+                    // Still show it, as without a store
+                    Err(e) => error!("Failed to store message {}: {:#}", msg.id, e),
+                }
+            }
+
             // Translate if needed
             if let Some(translator) = translator {
                 if !msg.is_from_me {
                     if let Some(text) = extract_text_content(&msg.content) {
                         // CLI mode doesn't have per-conversation settings
-                        let result = translator.process_text(&text, None, None).await;
+                        let result = translator
+                            .process_text(&text, None, None, false, false)
+                            .await;
                         if result.needs_translation {
                             // Display with translation
                             message_display.display_with_translation(
                                 &msg,
                                 &result.translated_text.unwrap_or(text),
                                 &result.source_language,
+                                None,
                             )?;
                             return Ok(());
                         }
                     }
                 }
             }
-            message_display.display(&msg)?;
+            message_display.display(&msg, None)?;
         }
 
         BridgeEvent::Error { code, message } => {
@@ -667,7 +1882,11 @@ async fn handle_terminal_event(
         BridgeEvent::LoggedOut { reason } => {
             print_warning(&format!("Logged out: {}", reason));
             print_info("Please restart the application to scan a new QR code.");
-            *connected = false;
+            status.status_mut().state = LinkState::LoggedOut;
+        }
+
+        BridgeEvent::Unknown { raw } => {
+            debug!("Ignoring unknown bridge event: {}", raw);
         }
 
         BridgeEvent::SendResult {
@@ -695,15 +1914,54 @@ async fn handle_terminal_event(
             debug!("Ignoring profile picture event in terminal mode");
         }
 
+        BridgeEvent::OwnProfile { .. } => {
+            // My profile is only shown in web mode
+            debug!("Ignoring own profile event in terminal mode");
+        }
+
+        BridgeEvent::NumberCheckResult { .. } => {
+            // Number checks are only used in web mode
+            debug!("Ignoring number check event in terminal mode");
+        }
+
+        BridgeEvent::GroupCreated { .. } => {
+            // Groups are only created in web mode
+            debug!("Ignoring group created event in terminal mode");
+        }
+
         BridgeEvent::ChatPresence { .. } => {
             // Typing indicators are only used in web mode
             debug!("Ignoring chat presence event in terminal mode");
         }
 
+        BridgeEvent::Presence { .. } => {
+            // Online status is only shown in web mode
+            debug!("Ignoring presence event in terminal mode");
+        }
+
         BridgeEvent::MarkAsRead { .. } => {
             // Mark-as-read events are only used in web mode
             debug!("Ignoring mark-as-read event in terminal mode");
         }
+
+        BridgeEvent::ChatMetadata { .. } => {
+            // Chat metadata is only stored in web mode
+            debug!("Ignoring chat metadata event in terminal mode");
+        }
+
+        BridgeEvent::HistorySyncProgress {
+            chats_done,
+            messages_done,
+            complete,
+            ..
+        } => {
+            if complete {
+                print_info(&format!(
+                    "Imported {} history messages from {} chats",
+                    messages_done, chats_done
+                ));
+            }
+        }
     }
 
     Ok(())
@@ -728,6 +1986,8 @@ impl serde::Serialize for BridgeEvent {
                 phone,
                 name,
                 platform,
+                lid,
+                capabilities,
             } => {
                 map.serialize_entry("type", "connected")?;
                 map.serialize_entry("phone", phone)?;
@@ -735,6 +1995,12 @@ impl serde::Serialize for BridgeEvent {
                 if let Some(p) = platform {
                     map.serialize_entry("platform", p)?;
                 }
+                if let Some(l) = lid {
+                    map.serialize_entry("lid", l)?;
+                }
+                if let Some(c) = capabilities {
+                    map.serialize_entry("capabilities", c)?;
+                }
             }
             BridgeEvent::ConnectionState { state } => {
                 map.serialize_entry("type", "connection_state")?;
@@ -758,6 +2024,10 @@ impl serde::Serialize for BridgeEvent {
                 map.serialize_entry("type", "logged_out")?;
                 map.serialize_entry("reason", reason)?;
             }
+            BridgeEvent::Unknown { raw } => {
+                map.serialize_entry("type", "unknown")?;
+                map.serialize_entry("raw", raw)?;
+            }
             BridgeEvent::SendResult {
                 request_id,
                 success,
@@ -798,6 +2068,60 @@ impl serde::Serialize for BridgeEvent {
                     map.serialize_entry("error", err)?;
                 }
             }
+            BridgeEvent::OwnProfile {
+                request_id,
+                jid,
+                name,
+                status,
+                error,
+            } => {
+                map.serialize_entry("type", "own_profile")?;
+                map.serialize_entry("request_id", request_id)?;
+                if let Some(j) = jid {
+                    map.serialize_entry("jid", j)?;
+                }
+                if let Some(n) = name {
+                    map.serialize_entry("name", n)?;
+                }
+                if let Some(s) = status {
+                    map.serialize_entry("status", s)?;
+                }
+                if let Some(err) = error {
+                    map.serialize_entry("error", err)?;
+                }
+            }
+            BridgeEvent::NumberCheckResult {
+                request_id,
+                phone,
+                jid,
+                is_on_whatsapp,
+                error,
+            } => {
+                map.serialize_entry("type", "number_check_result")?;
+                map.serialize_entry("request_id", request_id)?;
+                map.serialize_entry("phone", phone)?;
+                if let Some(j) = jid {
+                    map.serialize_entry("jid", j)?;
+                }
+                map.serialize_entry("is_on_whatsapp", is_on_whatsapp)?;
+                if let Some(err) = error {
+                    map.serialize_entry("error", err)?;
+                }
+            }
+            BridgeEvent::GroupCreated {
+                request_id,
+                jid,
+                error,
+            } => {
+                map.serialize_entry("type", "group_created")?;
+                map.serialize_entry("request_id", request_id)?;
+                if let Some(j) = jid {
+                    map.serialize_entry("jid", j)?;
+                }
+                if let Some(err) = error {
+                    map.serialize_entry("error", err)?;
+                }
+            }
             BridgeEvent::ChatPresence {
                 chat_id,
                 user_id,
@@ -813,10 +2137,60 @@ impl serde::Serialize for BridgeEvent {
                 };
                 map.serialize_entry("state", state_str)?;
             }
+            BridgeEvent::Presence {
+                jid,
+                is_online,
+                last_seen,
+            } => {
+                map.serialize_entry("type", "presence")?;
+                map.serialize_entry("jid", jid)?;
+                map.serialize_entry("is_online", is_online)?;
+                if let Some(t) = last_seen {
+                    map.serialize_entry("last_seen", t)?;
+                }
+            }
             BridgeEvent::MarkAsRead { chat_id } => {
                 map.serialize_entry("type", "mark_as_read")?;
                 map.serialize_entry("chat_id", chat_id)?;
             }
+            BridgeEvent::ChatMetadata {
+                chat_id,
+                description,
+                ephemeral_duration,
+                subject,
+                subject_changed_by,
+                timestamp,
+            } => {
+                map.serialize_entry("type", "chat_metadata")?;
+                map.serialize_entry("chat_id", chat_id)?;
+                if let Some(d) = description {
+                    map.serialize_entry("description", d)?;
+                }
+                if let Some(d) = ephemeral_duration {
+                    map.serialize_entry("ephemeral_duration", d)?;
+                }
+                if let Some(s) = subject {
+                    map.serialize_entry("subject", s)?;
+                }
+                if let Some(by) = subject_changed_by {
+                    map.serialize_entry("subject_changed_by", by)?;
+                }
+                if let Some(t) = timestamp {
+                    map.serialize_entry("timestamp", t)?;
+                }
+            }
+            BridgeEvent::HistorySyncProgress {
+                chats_done,
+                chats_total,
+                messages_done,
+                complete,
+            } => {
+                map.serialize_entry("type", "history_sync_progress")?;
+                map.serialize_entry("chats_done", chats_done)?;
+                map.serialize_entry("chats_total", chats_total)?;
+                map.serialize_entry("messages_done", messages_done)?;
+                map.serialize_entry("complete", complete)?;
+            }
         }
 
         map.end()
@@ -831,7 +2205,7 @@ impl serde::Serialize for bridge::Message {
     {
         use serde::ser::SerializeStruct;
 
-        let mut s = serializer.serialize_struct("Message", 8)?;
+        let mut s = serializer.serialize_struct("Message", 9)?;
         s.serialize_field("id", &self.id)?;
         s.serialize_field("timestamp", &self.timestamp.timestamp())?;
         s.serialize_field("from", &self.from)?;
@@ -840,6 +2214,7 @@ impl serde::Serialize for bridge::Message {
         s.serialize_field("is_from_me", &self.is_from_me)?;
         s.serialize_field("is_forwarded", &self.is_forwarded)?;
         s.serialize_field("push_name", &self.push_name)?;
+        s.serialize_field("mentioned_jids", &self.mentioned_jids)?;
         s.end()
     }
 }
@@ -852,10 +2227,12 @@ impl serde::Serialize for bridge::Contact {
     {
         use serde::ser::SerializeStruct;
 
-        let mut s = serializer.serialize_struct("Contact", 3)?;
+        let mut s = serializer.serialize_struct("Contact", 5)?;
         s.serialize_field("jid", &self.jid)?;
         s.serialize_field("phone", &self.phone)?;
         s.serialize_field("name", &self.name)?;
+        s.serialize_field("lid", &self.lid)?;
+        s.serialize_field("phone_jid", &self.phone_jid)?;
         s.end()
     }
 }
@@ -871,12 +2248,23 @@ impl serde::Serialize for bridge::Chat {
         let mut map = serializer.serialize_map(None)?;
 
         match self {
-            bridge::Chat::Private { jid, name } => {
+            bridge::Chat::Private {
+                jid,
+                name,
+                lid,
+                phone_jid,
+            } => {
                 map.serialize_entry("type", "private")?;
                 map.serialize_entry("jid", jid)?;
                 if let Some(n) = name {
                     map.serialize_entry("name", n)?;
                 }
+                if let Some(l) = lid {
+                    map.serialize_entry("lid", l)?;
+                }
+                if let Some(p) = phone_jid {
+                    map.serialize_entry("phone_jid", p)?;
+                }
             }
             bridge::Chat::Group {
                 jid,
@@ -900,6 +2288,13 @@ impl serde::Serialize for bridge::Chat {
                 map.serialize_entry("type", "status")?;
                 map.serialize_entry("jid", jid)?;
             }
+            bridge::Chat::Channel { jid, name } => {
+                map.serialize_entry("type", "channel")?;
+                map.serialize_entry("jid", jid)?;
+                if let Some(n) = name {
+                    map.serialize_entry("name", n)?;
+                }
+            }
         }
 
         map.end()
@@ -927,6 +2322,7 @@ impl serde::Serialize for bridge::MessageContent {
                 file_size,
                 file_hash,
                 media_data,
+                view_once,
             } => {
                 map.serialize_entry("type", "image")?;
                 map.serialize_entry("mime_type", mime_type)?;
@@ -937,6 +2333,9 @@ impl serde::Serialize for bridge::MessageContent {
                 if let Some(h) = file_hash {
                     map.serialize_entry("file_hash", h)?;
                 }
+                if *view_once {
+                    map.serialize_entry("view_once", &true)?;
+                }
                 if let Some(data) = media_data {
                     map.serialize_entry("media_data", data)?;
                 }
@@ -947,6 +2346,7 @@ impl serde::Serialize for bridge::MessageContent {
                 file_size,
                 duration_seconds,
                 media_data,
+                view_once,
             } => {
                 map.serialize_entry("type", "video")?;
                 map.serialize_entry("mime_type", mime_type)?;
@@ -957,6 +2357,9 @@ impl serde::Serialize for bridge::MessageContent {
                 if let Some(d) = duration_seconds {
                     map.serialize_entry("duration_seconds", d)?;
                 }
+                if *view_once {
+                    map.serialize_entry("view_once", &true)?;
+                }
                 if let Some(data) = media_data {
                     map.serialize_entry("media_data", data)?;
                 }
@@ -1051,7 +2454,16 @@ impl serde::Serialize for bridge::MessageContent {
                 map.serialize_entry("question", question)?;
                 map.serialize_entry("options", options)?;
             }
-            bridge::MessageContent::Unknown { raw_type } => {
+            // Kept as received, so nothing of it is lost
+            bridge::MessageContent::Unknown {
+                raw: serde_json::Value::Object(raw),
+                ..
+            } => {
+                for (key, value) in raw {
+                    map.serialize_entry(key, value)?;
+                }
+            }
+            bridge::MessageContent::Unknown { raw_type, .. } => {
                 map.serialize_entry("type", "unknown")?;
                 map.serialize_entry("raw_type", raw_type)?;
             }
@@ -1060,3 +2472,1333 @@ impl serde::Serialize for bridge::MessageContent {
         map.end()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_store;
+    use crate::web::test_state;
+
+    fn message_event(chat: usize, id: usize) -> BridgeEvent {
+        serde_json::from_value(serde_json::json!({
+            "type": "message",
+            "id": format!("msg-{}-{}", chat, id),
+            "timestamp": 1705689600 + id,
+            "from": {"jid": format!("{}@s.whatsapp.net", chat), "phone": chat.to_string()},
+            "chat": {"type": "private", "jid": format!("{}@s.whatsapp.net", chat)},
+            "content": {"type": "text", "body": "Hello!"},
+            "is_from_me": false,
+            "is_forwarded": false
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_logout_during_event_flood() {
+        let (state, _dir) = test_state(None);
+        let store = state.store.clone();
+
+        let floods: Vec<_> = (0..4)
+            .map(|chat| {
+                let state = state.clone();
+                let store = store.clone();
+                tokio::spawn(async move {
+                    for id in 0..200 {
+                        dispatch_web_event(message_event(chat, id), &state, &store, None).await?;
+                        tokio::task::yield_now().await;
+                    }
+                    anyhow::Ok(())
+                })
+            })
+            .collect();
+
+        let logouts: Vec<_> = (0..3)
+            .map(|n| {
+                let state = state.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(std::time::Duration::from_millis(5 * n)).await;
+                    state.logout().await
+                })
+            })
+            .collect();
+
+        for flood in floods {
+            flood.await.unwrap().unwrap();
+        }
+        let mut succeeded = 0;
+        for logout in logouts {
+            match logout.await.unwrap() {
+                Ok(()) => succeeded += 1,
+                Err(e) => assert!(matches!(e, web::LogoutError::InProgress)),
+            }
+        }
+        assert!(succeeded >= 1);
+        assert_eq!(
+            *state.lifecycle.lock().await,
+            lifecycle::LifecycleState::Running
+        );
+
+        // Every stored message still belongs to a stored contact
+        let (message_count, _) = store.get_stats().unwrap();
+        let reachable: usize = store
+            .get_contacts()
+            .unwrap()
+            .iter()
+            .map(|c| store.get_messages(&c.id).unwrap().len())
+            .sum();
+        assert_eq!(reachable as i64, message_count);
+    }
+
+    fn group_message(id: &str, timestamp: i64, mentioned_jids: &[&str]) -> BridgeEvent {
+        serde_json::from_value(serde_json::json!({
+            "type": "message",
+            "id": id,
+            "timestamp": timestamp,
+            "from": {"jid": "34600000000@s.whatsapp.net", "phone": "34600000000"},
+            "chat": {"type": "group", "jid": "120363000000000000@g.us", "name": "Piso"},
+            "content": {"type": "text", "body": "@Me mira esto"},
+            "is_from_me": false,
+            "is_forwarded": false,
+            "mentioned_jids": mentioned_jids
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_channel_message_serialization() {
+        let msg: Message = serde_json::from_value(serde_json::json!({
+            "id": "post-1",
+            "timestamp": 1705689600,
+            "from": {"jid": "120363012345678901@newsletter", "phone": "120363012345678901"},
+            "chat": {"type": "channel", "jid": "120363012345678901@newsletter", "name": "Le Monde"},
+            "content": {"type": "text", "body": "Édition du soir"},
+            "is_from_me": false,
+            "is_forwarded": false
+        }))
+        .unwrap();
+        assert!(msg.chat.is_channel());
+        assert_eq!(msg.chat.display_name(), "Le Monde");
+        assert_eq!(
+            serde_json::to_value(&msg.chat).unwrap(),
+            serde_json::json!({
+                "type": "channel",
+                "jid": "120363012345678901@newsletter",
+                "name": "Le Monde"
+            })
+        );
+
+        let stored = process_message(msg, None, None).await;
+        assert_eq!(stored.chat_type, "channel");
+        assert_eq!(stored.contact_name.as_deref(), Some("Le Monde"));
+        assert_eq!(stored.contact_phone, None);
+    }
+
+    #[tokio::test]
+    async fn test_group_mentions() {
+        let (state, _dir) = test_state(None);
+        let store = state.store.clone();
+        let group = "120363000000000000@g.us";
+        let unread = |store: &MessageStore| store.get_contact(group).unwrap().unwrap().unread_count;
+
+        let connected = serde_json::from_value(serde_json::json!({
+            "type": "connected",
+            "phone": "447700900000",
+            "name": "Me",
+            "lid": "98765@lid"
+        }))
+        .unwrap();
+        handle_web_event(connected, &state, &store, None)
+            .await
+            .unwrap();
+
+        let mut events = state.broadcast_tx.subscribe();
+        handle_web_event(
+            group_message("g1", 1_700_000_001, &[]),
+            &state,
+            &store,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(unread(&store), 1);
+
+        // In mentions-only mode plain group chatter no longer counts as unread
+        assert!(store.toggle_mentions_only(group).unwrap());
+        handle_web_event(
+            group_message("g2", 1_700_000_002, &["447700900001@s.whatsapp.net"]),
+            &state,
+            &store,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(unread(&store), 1);
+
+        // A mention by LID (with a device part) is still me
+        handle_web_event(
+            group_message("g3", 1_700_000_003, &["98765:12@lid"]),
+            &state,
+            &store,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(unread(&store), 2);
+        handle_web_event(
+            group_message("g4", 1_700_000_004, &["447700900000@s.whatsapp.net"]),
+            &state,
+            &store,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(unread(&store), 3);
+
+        let mut flags = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let web::WebSocketEvent::Message { message, .. } = event {
+                flags.push((message.id, message.mentions_me));
+            }
+        }
+        assert_eq!(
+            flags,
+            [
+                ("g1".to_string(), false),
+                ("g2".to_string(), false),
+                ("g3".to_string(), true),
+                ("g4".to_string(), true)
+            ]
+        );
+
+        let ids = |mentions: Vec<StoredMessage>| -> Vec<String> {
+            mentions.into_iter().map(|m| m.id).collect()
+        };
+        assert_eq!(ids(store.get_mentions(None, 50).unwrap()), ["g4", "g3"]);
+        assert_eq!(
+            ids(store.get_mentions(Some(1_700_000_003_000), 50).unwrap()),
+            ["g4"]
+        );
+        assert_eq!(ids(store.get_mentions(None, 1).unwrap()), ["g4"]);
+
+        let mention = store.get_message_by_id("g3").unwrap().unwrap();
+        assert!(mention.mentions_me);
+        assert_eq!(mention.mentioned_jids, ["98765:12@lid"]);
+    }
+
+    #[tokio::test]
+    async fn test_terminal_store_skips_known_messages() {
+        let (store, _dir) = test_store();
+        let (url, hits) =
+            translation::spawn_counting_provider(r#"{"language": "English", "isEnglish": true}"#)
+                .await;
+        let translator = Arc::new(
+            TranslationService::new("test-key".to_string(), "English".to_string())
+                .with_api_url(&url),
+        );
+        let message = |id: &str, body: &str, is_from_me: bool| -> Message {
+            serde_json::from_value(serde_json::json!({
+                "id": id,
+                "timestamp": 1705689600,
+                "from": {"jid": "447911123456@s.whatsapp.net", "phone": "447911123456"},
+                "chat": {"type": "private", "jid": "447911123456@s.whatsapp.net", "name": "Alice"},
+                "content": {"type": "text", "body": body},
+                "is_from_me": is_from_me,
+                "is_forwarded": false
+            }))
+            .unwrap()
+        };
+        let hits = || hits.load(std::sync::atomic::Ordering::SeqCst);
+
+        let (stored, context) = store_terminal_message(
+            message("m1", "Hello there", false),
+            Some(&translator),
+            &store,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(context, None);
+        assert_eq!(
+            stored.translation_status,
+            Some(TranslationStatus::NotNeeded)
+        );
+        assert_eq!(hits(), 1);
+
+        // Seen before (here or by the web mode): not shown or detected again
+        let again = store_terminal_message(
+            message("m1", "Hello there", false),
+            Some(&translator),
+            &store,
+        )
+        .await
+        .unwrap();
+        assert!(again.is_none());
+        assert_eq!(hits(), 1);
+
+        let (_, context) = store_terminal_message(
+            message("m2", "Are you there?", false),
+            Some(&translator),
+            &store,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(context.as_deref(), Some("Hello there"));
+        assert_eq!(hits(), 2);
+
+        // My own messages get no context line and aren't unread
+        let (_, context) =
+            store_terminal_message(message("m3", "Yes", true), Some(&translator), &store)
+                .await
+                .unwrap()
+                .unwrap();
+        assert_eq!(context, None);
+
+        let contact = store
+            .get_contact("447911123456@s.whatsapp.net")
+            .unwrap()
+            .unwrap();
+        assert_eq!(contact.name.as_deref(), Some("Alice"));
+        assert_eq!(contact.unread_count, 2);
+        let ids: Vec<_> = store
+            .get_messages("447911123456@s.whatsapp.net")
+            .unwrap()
+            .into_iter()
+            .map(|m| m.id)
+            .collect();
+        assert_eq!(ids, ["m1", "m2", "m3"]);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_content_is_stored_losslessly() {
+        let (store, _dir) = test_store();
+        let contents = [
+            serde_json::json!({
+                "type": "order",
+                "order_id": "ord-77",
+                "items": [{"title": "Blue mug", "quantity": 2, "price": {"amount": 1250, "currency": "EUR"}}],
+                "note": null
+            }),
+            serde_json::json!({"type": "catalog_item", "product": {"description": "Handmade"}, "tags": ["a", 1.5, true]}),
+            serde_json::json!({"type": "order", "order_id": "ord-78"}),
+            serde_json::json!({"type": "unknown", "raw_type": "EventMessage", "raw": {"name": "Picnic"}}),
+        ];
+
+        for (i, content) in contents.iter().enumerate() {
+            let line = serde_json::json!({
+                "type": "message",
+                "id": format!("u{}", i),
+                "timestamp": 1705689600 + i as i64,
+                "from": {"jid": "447911123456@s.whatsapp.net", "phone": "447911123456"},
+                "chat": {"type": "private", "jid": "447911123456@s.whatsapp.net"},
+                "content": content,
+                "is_from_me": false,
+                "is_forwarded": false
+            });
+            let BridgeEvent::Message(msg) =
+                BridgeEvent::from_json_line(line.to_string().as_bytes()).unwrap()
+            else {
+                panic!("expected a message");
+            };
+            store_terminal_message(msg, None, &store).await.unwrap();
+        }
+
+        let messages = store.get_messages("447911123456@s.whatsapp.net").unwrap();
+        let types: Vec<_> = messages.iter().map(|m| m.content_type.as_str()).collect();
+        assert_eq!(
+            types,
+            [
+                "unknown:order",
+                "unknown:catalog_item",
+                "unknown:order",
+                "unknown:EventMessage"
+            ]
+        );
+        for (message, content) in messages.iter().zip(&contents) {
+            let stored: serde_json::Value = serde_json::from_str(&message.content_json).unwrap();
+            assert_eq!(&stored, content);
+        }
+
+        // The preview falls back on text-like fields
+        let contact = store
+            .get_contact("447911123456@s.whatsapp.net")
+            .unwrap()
+            .unwrap();
+        assert_eq!(contact.last_message_preview.as_deref(), Some("Picnic"));
+
+        let report = store.get_unknown_content_report().unwrap();
+        let counts: Vec<_> = report
+            .iter()
+            .map(|t| (t.raw_type.as_str(), t.count))
+            .collect();
+        assert_eq!(
+            counts,
+            [("order", 2), ("EventMessage", 1), ("catalog_item", 1)]
+        );
+        assert_eq!(report[0].sample, contents[2]);
+    }
+
+    #[tokio::test]
+    async fn test_group_translation_participants() {
+        use storage::ParticipantTranslationMode::{Always, Never};
+
+        let (store, _dir) = test_store();
+        let group = "120363000000000000@g.us";
+        store
+            .upsert_contact(group, Some("Dacha"), None, Some("group"), 1)
+            .unwrap();
+        let (url, hits) =
+            translation::spawn_counting_provider(r#"{"language": "Russian", "isEnglish": false}"#)
+                .await;
+        let translator = Arc::new(
+            TranslationService::new("test-key".to_string(), "English".to_string())
+                .with_api_url(&url),
+        );
+        let receive = |id: &'static str, phone: &'static str, name: &'static str| {
+            let msg: Message = serde_json::from_value(serde_json::json!({
+                "id": id,
+                "timestamp": 1705689600,
+                "from": {"jid": format!("{}:7@s.whatsapp.net", phone), "phone": phone},
+                "chat": {"type": "group", "jid": group, "name": "Dacha"},
+                "content": {"type": "text", "body": "Привет всем, как дела?"},
+                "is_from_me": false,
+                "is_forwarded": false,
+                "push_name": name
+            }))
+            .unwrap();
+            let (store, translator) = (store.clone(), translator.clone());
+            async move {
+                let stored = process_message(msg, Some(&translator), Some(&store)).await;
+                store.add_message(&stored).unwrap();
+                stored
+            }
+        };
+        let usage = || store.get_conversation_usage(group).unwrap().input_tokens;
+
+        // A participant set to never isn't even language-detected
+        store
+            .set_translation_participant(group, "79000000001@s.whatsapp.net", Some(Never))
+            .unwrap();
+        let skipped = receive("m1", "79000000001", "Boris").await;
+        assert!(!skipped.is_translated);
+        assert_eq!(skipped.source_language, None);
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 0);
+        assert_eq!(usage(), 0);
+
+        // Everyone else follows the group and is translated as usual
+        let translated = receive("m2", "79000000002", "Olga").await;
+        assert!(translated.is_translated);
+        assert_eq!(translated.source_language.as_deref(), Some("Russian"));
+        let calls = hits.load(std::sync::atomic::Ordering::SeqCst);
+        assert!(calls > 0);
+        let translated_usage = usage();
+        assert!(translated_usage > 0);
+
+        // Once someone is set to always, only they are translated
+        store
+            .set_translation_participant(group, "79000000003@s.whatsapp.net", Some(Always))
+            .unwrap();
+        assert!(!receive("m3", "79000000002", "Olga").await.is_translated);
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), calls);
+        assert_eq!(usage(), translated_usage);
+        assert!(receive("m4", "79000000003", "Ivan").await.is_translated);
+
+        // The filter list resolves names from the group's messages
+        let participants = store.get_translation_participants(group).unwrap();
+        let listed: Vec<_> = participants
+            .iter()
+            .map(|p| (p.participant_jid.as_str(), p.mode, p.name.as_deref()))
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                ("79000000001@s.whatsapp.net", Never, Some("Boris")),
+                ("79000000003@s.whatsapp.net", Always, Some("Ivan"))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_self_chat_is_saved_messages() {
+        let (state, _dir) = test_state(None);
+        let store = state.store.clone();
+        let (url, hits) =
+            translation::spawn_counting_provider(r#"{"language": "Spanish", "isEnglish": false}"#)
+                .await;
+        let translator = Arc::new(
+            TranslationService::new("test-key".to_string(), "English".to_string())
+                .with_api_url(&url),
+        );
+        let me = "447700900000@s.whatsapp.net";
+        store
+            .upsert_contact(
+                "34600000000@s.whatsapp.net",
+                Some("Ana"),
+                None,
+                Some("private"),
+                5,
+            )
+            .unwrap();
+        store.toggle_pin("34600000000@s.whatsapp.net").unwrap();
+
+        let connected = serde_json::from_value(serde_json::json!({
+            "type": "connected",
+            "phone": "447700900000",
+            "name": "Me",
+            "lid": "98765:3@lid"
+        }))
+        .unwrap();
+        handle_web_event(connected, &state, &store, Some(&translator))
+            .await
+            .unwrap();
+        assert!(store.is_self_chat(me).unwrap());
+        assert!(store.is_self_chat("98765@lid").unwrap());
+
+        // A note from another device, live and then from history with an
+        // unread count, never shows as unread or gets translated
+        let note = |id: &str, chat: &str, unread: Option<u32>| {
+            serde_json::from_value::<BridgeEvent>(serde_json::json!({
+                "type": "message",
+                "id": id,
+                "timestamp": 1705689600,
+                "from": {"jid": chat, "phone": "447700900000"},
+                "chat": {"type": "private", "jid": chat, "name": "Me"},
+                "content": {"type": "text", "body": "Comprar pan y leche mañana"},
+                "is_from_me": false,
+                "is_forwarded": false,
+                "unread_count": unread
+            }))
+            .unwrap()
+        };
+        for event in [note("n1", "98765@lid", None), note("n2", me, Some(4))] {
+            handle_web_event(event, &state, &store, Some(&translator))
+                .await
+                .unwrap();
+        }
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 0);
+        let stored = store.get_message_by_id("n1").unwrap().unwrap();
+        assert_eq!(stored.contact_id, me);
+        assert_eq!(stored.chat_type, "self");
+        assert!(!stored.is_translated);
+
+        // Listed first, above pinned chats, as Saved Messages
+        let contacts = store.get_contacts().unwrap();
+        assert_eq!(contacts[0].id, me);
+        assert_eq!(
+            contacts[0].name.as_deref(),
+            Some(storage::SAVED_MESSAGES_NAME)
+        );
+        assert_eq!(contacts[0].contact_type.as_deref(), Some("self"));
+        assert_eq!(contacts[0].unread_count, 0);
+        assert!(!contacts[0].auto_translate_outgoing);
+        assert_eq!(contacts[1].id, "34600000000@s.whatsapp.net");
+    }
+
+    #[tokio::test]
+    async fn test_sent_messages_are_stored_once() {
+        let (state, _dir) = test_state(None);
+        let store = state.store.clone();
+        let chat = "34600000000@s.whatsapp.net";
+        store
+            .upsert_contact(chat, Some("Ana"), None, Some("private"), 1)
+            .unwrap();
+
+        // Sent from here: stored straight away under temporary IDs
+        let (tx, _rx) = mpsc::channel(10);
+        let outgoing = |text: &str| sending::OutgoingMessage {
+            id: sending::pending_message_id(),
+            contact_id: chat.to_string(),
+            text: text.to_string(),
+            reply: None,
+            origin: "web".to_string(),
+            sent_by: None,
+        };
+        let (plain, _) = state
+            .sending
+            .send(&tx, &outgoing("See you"), None)
+            .await
+            .unwrap();
+        let (translated, _) = state
+            .sending
+            .send(
+                &tx,
+                &outgoing("Hello"),
+                Some(sending::OutgoingText::confirmed(
+                    "Hola".to_string(),
+                    Some("Spanish".to_string()),
+                )),
+            )
+            .await
+            .unwrap();
+        let image = StoredMessage {
+            id: "pending_img_1".to_string(),
+            timestamp: plain.timestamp,
+            content_type: "Image".to_string(),
+            content_json: serde_json::json!({
+                "type": "image", "mime_type": "image/jpeg", "media_data": "3q2+7w=="
+            })
+            .to_string(),
+            content: None,
+            original_text: None,
+            translated_text: None,
+            is_translated: false,
+            ..plain.clone()
+        };
+        store.add_message(&image).unwrap();
+
+        // WhatsApp's copies come back with the real IDs
+        let mut events = state.broadcast_tx.subscribe();
+        let echo = |id: &str, content: serde_json::Value| {
+            serde_json::from_value::<BridgeEvent>(serde_json::json!({
+                "type": "message",
+                "id": id,
+                "timestamp": chrono::Utc::now().timestamp(),
+                "from": {"jid": "447700900000@s.whatsapp.net", "phone": "447700900000"},
+                "chat": {"type": "private", "jid": chat, "name": "Ana"},
+                "content": content,
+                "is_from_me": true,
+                "is_forwarded": false
+            }))
+            .unwrap()
+        };
+        for event in [
+            echo(
+                "3EB0A",
+                serde_json::json!({"type": "text", "body": "See you"}),
+            ),
+            echo("3EB0B", serde_json::json!({"type": "text", "body": "Hola"})),
+            echo(
+                "3EB0C",
+                serde_json::json!({
+                    "type": "image", "mime_type": "image/jpeg", "file_size": 4,
+                    "file_hash": "5F78C33274E43FA9DE5659265C1D917E25C03722DCB0B8D27DB8D5FEAA813953"
+                }),
+            ),
+            // Sent from the phone: nothing to take over
+            echo(
+                "3EB0D",
+                serde_json::json!({"type": "text", "body": "On my way"}),
+            ),
+        ] {
+            handle_web_event(event, &state, &store, None).await.unwrap();
+        }
+
+        let messages = store.get_messages(chat).unwrap();
+        let ids: Vec<&str> = messages.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["3EB0A", "3EB0B", "3EB0C", "3EB0D"]);
+        let hello = &messages[1];
+        assert_eq!(hello.content.as_ref().unwrap()["body"], "Hello");
+        assert_eq!(hello.translated_text.as_deref(), Some("Hola"));
+        assert_eq!(hello.origin.as_deref(), Some("web"));
+
+        let mut updated = Vec::new();
+        let mut new_messages = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            match event {
+                web::WebSocketEvent::MessageIdUpdated { old_id, new_id, .. } => {
+                    updated.push((old_id, new_id))
+                }
+                web::WebSocketEvent::Message { message, .. } => new_messages.push(message.id),
+                _ => {}
+            }
+        }
+        assert_eq!(
+            updated,
+            [
+                (plain.id, "3EB0A".to_string()),
+                (translated.id, "3EB0B".to_string()),
+                (image.id, "3EB0C".to_string()),
+            ]
+        );
+        assert_eq!(new_messages, ["3EB0D"]);
+    }
+
+    #[tokio::test]
+    async fn test_unread_follows_last_read_timestamp() {
+        let (state, _dir) = test_state(None);
+        let store = state.store.clone();
+        let chat = "34600000000@s.whatsapp.net";
+        let message = |id: &str, secs: i64, from_me: bool, history: bool, unread: Option<u32>| {
+            serde_json::from_value::<BridgeEvent>(serde_json::json!({
+                "type": "message",
+                "id": id,
+                "timestamp": secs,
+                "from": {"jid": chat, "phone": "34600000000"},
+                "chat": {"type": "private", "jid": chat, "name": "Lucía"},
+                "content": {"type": "text", "body": "¿Quedamos mañana?"},
+                "is_from_me": from_me,
+                "is_forwarded": false,
+                "is_history": history,
+                "unread_count": unread
+            }))
+            .unwrap()
+        };
+        let check = |unread: i32, first: Option<&str>| {
+            let contact = store.get_contact(chat).unwrap().unwrap();
+            assert_eq!(contact.unread_count, unread);
+            let first_unread = store.get_first_unread(chat).unwrap();
+            assert_eq!(first_unread.as_ref().map(|f| f.message_id.as_str()), first);
+            if let Some(first_unread) = first_unread {
+                assert!(first_unread.timestamp > contact.last_read_timestamp.unwrap_or(0));
+            }
+        };
+
+        // History sync says the last two incoming messages are unread, in
+        // whatever order the messages arrive
+        for (id, secs, from_me) in [
+            ("h3", 1_700_000_003, false),
+            ("h1", 1_700_000_001, false),
+            ("h4", 1_700_000_004, true),
+            ("h2", 1_700_000_002, false),
+        ] {
+            handle_web_event(
+                message(id, secs, from_me, true, Some(2)),
+                &state,
+                &store,
+                None,
+            )
+            .await
+            .unwrap();
+        }
+        check(2, Some("h2"));
+
+        // Live messages add to it; the first unread stays put
+        handle_web_event(
+            message("l1", 1_700_000_010, false, false, None),
+            &state,
+            &store,
+            None,
+        )
+        .await
+        .unwrap();
+        check(3, Some("h2"));
+
+        // Read on the phone: nothing is unread up to the latest message
+        let mut events = state.broadcast_tx.subscribe();
+        let read = serde_json::from_value(serde_json::json!({
+            "type": "mark_as_read",
+            "chat_id": chat
+        }))
+        .unwrap();
+        handle_web_event(read, &state, &store, None).await.unwrap();
+        check(0, None);
+        let last_read = store
+            .get_contact(chat)
+            .unwrap()
+            .unwrap()
+            .last_read_timestamp;
+        assert_eq!(last_read, Some(1_700_000_010_000));
+        match events.recv().await.unwrap() {
+            web::WebSocketEvent::MarkAsRead {
+                chat_id,
+                last_read_timestamp,
+            } => {
+                assert_eq!(chat_id, chat);
+                assert_eq!(last_read_timestamp, last_read);
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+
+        // The next live message is the first unread
+        handle_web_event(
+            message("l2", 1_700_000_020, false, false, None),
+            &state,
+            &store,
+            None,
+        )
+        .await
+        .unwrap();
+        check(1, Some("l2"));
+
+        // History sync with nothing unread catches up to the latest message
+        handle_web_event(
+            message("h0", 1_700_000_000, false, true, Some(0)),
+            &state,
+            &store,
+            None,
+        )
+        .await
+        .unwrap();
+        check(0, None);
+        assert_eq!(
+            store
+                .get_contact(chat)
+                .unwrap()
+                .unwrap()
+                .last_read_timestamp,
+            Some(1_700_000_020_000)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_in_flight_work() {
+        use tower::ServiceExt;
+
+        let (state, _dir) = test_state(None);
+
+        let store = state.store.clone();
+        let contact = "34600000001@s.whatsapp.net";
+
+        // A slow translation that records its usage when it finishes, and a
+        // push delivery that outlives any deadline
+        let work = state.shutdown.track("translation");
+        let worker_store = store.clone();
+        tokio::spawn(async move {
+            let _work = work;
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            let usage = translation::UsageInfo {
+                input_tokens: 42,
+                output_tokens: 7,
+                cost_usd: 0.001,
+                calls: Vec::new(),
+            };
+            worker_store
+                .record_usage(Some(contact), None, &usage, "translate")
+                .unwrap();
+        });
+        let stuck = state.shutdown.track("push");
+        tokio::spawn(async move {
+            let _stuck = stuck;
+            tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+        });
+
+        // A message the bridge delivered but that wasn't handled yet
+        let (event_tx, mut event_rx) = mpsc::channel(10);
+        event_tx.send(message_event(34600000001, 1)).await.unwrap();
+
+        let router = web::create_router(state.clone());
+        let mut events = state.broadcast_tx.subscribe();
+        let started = std::time::Instant::now();
+        let report = drain_web_mode(
+            &state,
+            &store,
+            None,
+            &mut event_rx,
+            std::time::Duration::from_secs(1),
+        )
+        .await;
+        let elapsed = started.elapsed();
+
+        assert!(elapsed < std::time::Duration::from_secs(2), "{:?}", elapsed);
+        // The translation, the bridge event and the push notification it sent
+        assert_eq!(report.drained, 3);
+        assert_eq!(report.abandoned, [("push", 1)].into_iter().collect());
+        assert_eq!(
+            store.get_conversation_usage(contact).unwrap().input_tokens,
+            42
+        );
+        assert_eq!(store.get_messages(contact).unwrap().len(), 1);
+        assert!(std::iter::from_fn(|| events.try_recv().ok())
+            .any(|event| matches!(event, web::WebSocketEvent::Message { .. })));
+
+        // New API requests are turned away
+        let response = router
+            .clone()
+            .oneshot(
+                axum::http::Request::builder()
+                    .uri("/api/contacts")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            response.status(),
+            axum::http::StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chat_metadata_events() {
+        let (state, _dir) = test_state(None);
+        let store = state.store.clone();
+        let (url, hits) =
+            translation::spawn_counting_provider(r#"{"language": "Spanish", "isEnglish": false}"#)
+                .await;
+        let translator = Arc::new(
+            TranslationService::new("test-key".to_string(), "English".to_string())
+                .with_api_url(&url),
+        );
+        let group = "120363000000000000@g.us";
+        store
+            .upsert_contact(group, Some("Familia"), None, Some("group"), 1_000)
+            .unwrap();
+        store
+            .upsert_contact(
+                "34600000001@s.whatsapp.net",
+                Some("Lucia"),
+                None,
+                None,
+                1_000,
+            )
+            .unwrap();
+
+        let mut events = state.broadcast_tx.subscribe();
+        let metadata = |fields: serde_json::Value| {
+            let mut event = serde_json::json!({"type": "chat_metadata", "chat_id": group});
+            event
+                .as_object_mut()
+                .unwrap()
+                .extend(fields.as_object().unwrap().clone());
+            let event: BridgeEvent = serde_json::from_value(event).unwrap();
+            let (state, store, translator) = (state.clone(), store.clone(), translator.clone());
+            async move {
+                handle_web_event(event, &state, &store, Some(&translator))
+                    .await
+                    .unwrap()
+            }
+        };
+
+        // A subject and description change: stored, announced and noted in the timeline
+        metadata(serde_json::json!({
+            "subject": "Familia García",
+            "description": "Planes para el verano",
+            "ephemeral_duration": 604800,
+            "subject_changed_by": "34600000001:3@s.whatsapp.net",
+            "timestamp": 1_700_000_000
+        }))
+        .await;
+        let contact = store.get_contact(group).unwrap().unwrap();
+        assert_eq!(contact.name.as_deref(), Some("Familia García"));
+        assert_eq!(
+            contact.description.as_deref(),
+            Some("Planes para el verano")
+        );
+        assert_eq!(contact.ephemeral_duration, Some(604800));
+        assert_eq!(contact.unread_count, 0);
+        assert_eq!(contact.last_message_preview, None);
+        assert_eq!(contact.last_message_time, 1_000);
+
+        let broadcast: Vec<_> = std::iter::from_fn(|| events.try_recv().ok()).collect();
+        let notices: Vec<_> = broadcast
+            .iter()
+            .filter_map(|event| match event {
+                web::WebSocketEvent::Message { message, .. } => {
+                    assert_eq!(message.content_type, storage::SYSTEM_CONTENT_TYPE);
+                    assert!(!message.is_translated);
+                    Some(message.content.as_ref().unwrap()["text"].clone())
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            notices,
+            [
+                "Lucia changed the subject to \"Familia García\"",
+                "Lucia changed the group description"
+            ]
+        );
+        assert!(broadcast.iter().any(|event| matches!(
+            event,
+            web::WebSocketEvent::ContactUpdated { contact }
+                if contact.ephemeral_duration == Some(604800)
+        )));
+
+        let stored = store.get_messages(group).unwrap();
+        assert_eq!(stored.len(), 2);
+        assert!(stored.iter().all(|m| m.timestamp == 1_700_000_000_000));
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 0);
+        assert_eq!(store.get_first_unread(group).unwrap(), None);
+
+        // Repeats change nothing; turning the timer off and removing the
+        // description clear them
+        metadata(serde_json::json!({"subject": "Familia García", "ephemeral_duration": 604800}))
+            .await;
+        assert!(events.try_recv().is_err());
+        metadata(serde_json::json!({"description": "", "ephemeral_duration": 0})).await;
+        let contact = store.get_contact(group).unwrap().unwrap();
+        assert_eq!(contact.description, None);
+        assert_eq!(contact.ephemeral_duration, None);
+        let stored = store.get_messages(group).unwrap();
+        assert_eq!(
+            stored.last().unwrap().content.as_ref().unwrap()["text"],
+            "The group description was removed"
+        );
+
+        // A real message after the notices is previewed and counted as unread
+        let message: BridgeEvent = serde_json::from_value(serde_json::json!({
+            "type": "message",
+            "id": "m1",
+            "timestamp": 1_700_000_100,
+            "from": {"jid": "34600000001@s.whatsapp.net", "phone": "34600000001"},
+            "chat": {"type": "group", "jid": group, "name": "Familia García"},
+            "content": {"type": "text", "body": "hola"},
+            "is_from_me": false,
+            "is_forwarded": false
+        }))
+        .unwrap();
+        handle_web_event(message, &state, &store, None)
+            .await
+            .unwrap();
+        metadata(serde_json::json!({"description": "Otra vez"})).await;
+        let contact = store.get_contact(group).unwrap().unwrap();
+        assert_eq!(contact.unread_count, 1);
+        assert_eq!(contact.last_message_preview.as_deref(), Some("hola"));
+        assert_eq!(
+            store.get_first_unread(group).unwrap().unwrap().message_id,
+            "m1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_history_sync_progress_and_deferred_contacts() {
+        let (state, _dir) = test_state(None);
+        let store = state.store.clone();
+        let (tx, mut commands) = mpsc::channel(100);
+        state.set_command_tx(tx).await;
+        store.set_history_depth(HistoryDepth::ThreeMonths).unwrap();
+
+        let connected = || {
+            serde_json::from_value::<BridgeEvent>(serde_json::json!({
+                "type": "connected",
+                "phone": "34611111111",
+                "name": "Me",
+                "capabilities": ["set_history_sync"]
+            }))
+            .unwrap()
+        };
+        let mut requested_depth = || {
+            std::iter::from_fn(|| commands.try_recv().ok()).find_map(|cmd| match cmd {
+                BridgeCommand::SetHistorySync { depth } => Some(depth),
+                _ => None,
+            })
+        };
+        let progress = |chats_done: u32, messages_done: u32, complete: bool| {
+            serde_json::from_value::<BridgeEvent>(serde_json::json!({
+                "type": "history_sync_progress",
+                "chats_done": chats_done,
+                "chats_total": 2,
+                "messages_done": messages_done,
+                "complete": complete
+            }))
+            .unwrap()
+        };
+        let message = |chat: &str, id: &str, history: bool| {
+            serde_json::from_value::<BridgeEvent>(serde_json::json!({
+                "type": "message",
+                "id": id,
+                "timestamp": 1_700_000_000,
+                "from": {"jid": chat, "phone": "0"},
+                "chat": {"type": "private", "jid": chat},
+                "content": {"type": "text", "body": id},
+                "is_from_me": false,
+                "is_forwarded": false,
+                "is_history": history
+            }))
+            .unwrap()
+        };
+
+        // The configured depth is asked for until a sync is imported
+        handle_web_event(connected(), &state, &store, None)
+            .await
+            .unwrap();
+        assert_eq!(requested_depth(), Some(HistoryDepth::ThreeMonths));
+
+        let mut events = state.broadcast_tx.subscribe();
+        let mut drain = || std::iter::from_fn(|| events.try_recv().ok()).collect::<Vec<_>>();
+        let updated_contacts = |events: &[web::WebSocketEvent]| {
+            events
+                .iter()
+                .filter_map(|event| match event {
+                    web::WebSocketEvent::ContactUpdated { contact } => Some(contact.clone()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Progress reaches clients, and new chats in the sync wait for it
+        let first = "34600000001@s.whatsapp.net";
+        let second = "34600000002@s.whatsapp.net";
+        handle_web_event(progress(0, 0, false), &state, &store, None)
+            .await
+            .unwrap();
+        for (chat, id) in [(first, "h1"), (first, "h2"), (second, "h3")] {
+            handle_web_event(message(chat, id, true), &state, &store, None)
+                .await
+                .unwrap();
+        }
+        handle_web_event(progress(1, 3, false), &state, &store, None)
+            .await
+            .unwrap();
+        let during = drain();
+        assert!(updated_contacts(&during).is_empty());
+        assert!(during.iter().any(|event| matches!(
+            event,
+            web::WebSocketEvent::SyncProgress {
+                chats_done: 1,
+                chats_total: 2,
+                messages_done: 3,
+                complete: false
+            }
+        )));
+        assert_eq!(
+            state.history_sync.progress().map(|p| p.messages_done),
+            Some(3)
+        );
+
+        // Live messages aren't held back
+        let live = "34600000003@s.whatsapp.net";
+        handle_web_event(message(live, "l1", false), &state, &store, None)
+            .await
+            .unwrap();
+        let updated = updated_contacts(&drain());
+        assert_eq!(updated.len(), 1);
+        assert_eq!(updated[0].id, live);
+
+        // Completing the sync sends the held back chats with their previews
+        handle_web_event(progress(2, 3, true), &state, &store, None)
+            .await
+            .unwrap();
+        let mut updated = updated_contacts(&drain());
+        updated.sort_by(|a, b| a.id.cmp(&b.id));
+        let previews: Vec<_> = updated
+            .iter()
+            .map(|c| (c.id.as_str(), c.last_message_preview.as_deref()))
+            .collect();
+        assert_eq!(previews, [(first, Some("h2")), (second, Some("h3"))]);
+        assert_eq!(state.history_sync.progress(), None);
+        assert!(store.is_history_sync_complete().unwrap());
+
+        // After a restart it isn't asked for again
+        handle_web_event(connected(), &state, &store, None)
+            .await
+            .unwrap();
+        assert_eq!(requested_depth(), Some(HistoryDepth::None));
+    }
+
+    #[tokio::test]
+    async fn test_history_messages_are_stored_in_batches() {
+        let (state, _dir) = test_state(None);
+        let store = state.store.clone();
+        let message = |chat: usize, id: usize, history: bool| {
+            serde_json::from_value::<BridgeEvent>(serde_json::json!({
+                "type": "message",
+                "id": format!("m{}", id),
+                "timestamp": 1_700_000_000 + id,
+                "from": {"jid": format!("3460000000{}@s.whatsapp.net", chat), "phone": "0"},
+                "chat": {"type": "private", "jid": format!("3460000000{}@s.whatsapp.net", chat)},
+                "content": {"type": "text", "body": "Hola"},
+                "is_from_me": false,
+                "is_forwarded": false,
+                "is_history": history,
+                "unread_count": 2
+            }))
+            .unwrap()
+        };
+        let mut events = state.broadcast_tx.subscribe();
+        let mut broadcast = || {
+            std::iter::from_fn(|| events.try_recv().ok())
+                .filter(|event| matches!(event, web::WebSocketEvent::Message { .. }))
+                .count()
+        };
+
+        // Held until the next event that isn't a history message
+        for id in 0..3 {
+            dispatch_web_event(message(1, id, true), &state, &store, None)
+                .await
+                .unwrap();
+        }
+        assert!(store.get_message_by_id("m0").unwrap().is_none());
+        assert!(state.history_batch.deadline().is_some());
+        assert_eq!(broadcast(), 0);
+
+        dispatch_web_event(message(2, 3, false), &state, &store, None)
+            .await
+            .unwrap();
+        assert!(state.history_batch.deadline().is_none());
+        assert_eq!(broadcast(), 4);
+        let chat = "34600000001@s.whatsapp.net";
+        let messages = store.get_messages(chat).unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(store.get_contact(chat).unwrap().unwrap().unread_count, 2);
+
+        // A full batch is stored straight away
+        for id in 10..10 + history_sync::BATCH_SIZE {
+            dispatch_web_event(message(3, id, true), &state, &store, None)
+                .await
+                .unwrap();
+        }
+        assert!(state.history_batch.deadline().is_none());
+        let chat = "34600000003@s.whatsapp.net";
+        assert_eq!(
+            store.get_messages(chat).unwrap().len(),
+            history_sync::BATCH_SIZE
+        );
+    }
+
+    #[tokio::test]
+    async fn test_message_cost_attribution() {
+        let (state, _dir) = test_state(None);
+        let store = state.store.clone();
+        let (url, _) =
+            translation::spawn_counting_provider(r#"{"language": "Spanish", "isEnglish": false}"#)
+                .await;
+        let translator = Arc::new(
+            TranslationService::new("test-key".to_string(), "English".to_string())
+                .with_api_url(&url),
+        );
+
+        // Incoming: charged to the message's own ID
+        handle_web_event(message_event(7, 1), &state, &store, Some(&translator))
+            .await
+            .unwrap();
+        let costs = store.get_message_costs(&["msg-7-1", "msg-7-2"]).unwrap();
+        assert_eq!(costs.len(), 1);
+        let incoming = &costs["msg-7-1"];
+        assert!(incoming.input_tokens > 0);
+        assert!(incoming.cost_usd > 0.0);
+
+        // Outgoing: charged to the temporary ID, then moved to the real one
+        // when WhatsApp's copy comes back
+        let contact_id = "7@s.whatsapp.net";
+        store
+            .update_conversation_settings(
+                contact_id,
+                &storage::ConversationSettings {
+                    language_override: Some("Spanish".to_string()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let sending = sending::OutgoingMessageService::new(store.clone(), Some(translator.clone()));
+        let message = sending::OutgoingMessage {
+            id: sending::pending_message_id(),
+            contact_id: contact_id.to_string(),
+            text: "See you tomorrow".to_string(),
+            reply: None,
+            origin: "web".to_string(),
+            sent_by: None,
+        };
+        let (tx, _rx) = mpsc::channel(1);
+        let (stored, outgoing) = sending.send(&tx, &message, None).await.unwrap();
+        assert!(outgoing.is_translated());
+        let pending = store.get_message_costs(&[&stored.id]).unwrap();
+        assert_eq!(pending[&stored.id].cost_usd, outgoing.cost_usd);
+
+        let echo: BridgeEvent = serde_json::from_value(serde_json::json!({
+            "type": "message",
+            "id": "msg-7-sent",
+            "timestamp": stored.timestamp / 1000,
+            "from": {"jid": "me@s.whatsapp.net", "phone": "me"},
+            "chat": {"type": "private", "jid": contact_id},
+            "content": {"type": "text", "body": outgoing.text_to_send},
+            "is_from_me": true,
+            "is_forwarded": false
+        }))
+        .unwrap();
+        handle_web_event(echo, &state, &store, Some(&translator))
+            .await
+            .unwrap();
+        let costs = store
+            .get_message_costs(&[&stored.id, "msg-7-sent", "msg-7-1"])
+            .unwrap();
+        assert!(!costs.contains_key(&stored.id));
+        assert_eq!(costs["msg-7-sent"].cost_usd, outgoing.cost_usd);
+        assert_eq!(costs["msg-7-1"].cost_usd, incoming.cost_usd);
+    }
+
+    #[tokio::test]
+    async fn test_translation_status_paths() {
+        use TranslationStatus::*;
+
+        let (store, _dir) = test_store();
+        let group = "120363000000000000@g.us";
+        store
+            .set_translation_participant(
+                group,
+                "79000000009@s.whatsapp.net",
+                Some(storage::ParticipantTranslationMode::Never),
+            )
+            .unwrap();
+        let translator = |url: &str| {
+            Arc::new(
+                TranslationService::new("test-key".to_string(), "English".to_string())
+                    .with_api_url(url),
+            )
+        };
+        let (spanish, _) =
+            translation::spawn_counting_provider(r#"{"language": "Spanish", "isEnglish": false}"#)
+                .await;
+        let (english, _) =
+            translation::spawn_counting_provider(r#"{"language": "English", "isEnglish": true}"#)
+                .await;
+        let spanish = translator(&spanish);
+        let english = translator(&english);
+        let unreachable = translator("http://127.0.0.1:9/v1/messages");
+
+        let status = |translator: Option<Arc<TranslationService>>, fields: serde_json::Value| {
+            let mut msg = serde_json::json!({
+                "id": "m1",
+                "timestamp": 1705689600,
+                "from": {"jid": "34600000000@s.whatsapp.net", "phone": "34600000000"},
+                "chat": {"type": "private", "jid": "34600000000@s.whatsapp.net"},
+                "content": {"type": "text", "body": "¿Vienes a cenar esta noche?"},
+                "is_from_me": false,
+                "is_forwarded": false
+            });
+            msg.as_object_mut()
+                .unwrap()
+                .extend(fields.as_object().unwrap().clone());
+            let msg: Message = serde_json::from_value(msg).unwrap();
+            let store = store.clone();
+            async move {
+                process_message(msg, translator.as_ref(), Some(&store))
+                    .await
+                    .translation_status
+            }
+        };
+
+        assert_eq!(
+            status(Some(spanish.clone()), serde_json::json!({})).await,
+            Some(Translated)
+        );
+        assert_eq!(
+            status(Some(english), serde_json::json!({})).await,
+            Some(NotNeeded)
+        );
+        assert_eq!(
+            status(
+                Some(spanish.clone()),
+                serde_json::json!({"content": {"type": "text", "body": "Si"}})
+            )
+            .await,
+            Some(SkippedShort)
+        );
+        assert_eq!(
+            status(Some(unreachable), serde_json::json!({})).await,
+            Some(Error)
+        );
+        assert_eq!(
+            status(
+                Some(spanish.clone()),
+                serde_json::json!({"is_history": true})
+            )
+            .await,
+            Some(Pending)
+        );
+        assert_eq!(
+            status(
+                Some(spanish.clone()),
+                serde_json::json!({
+                    "from": {"jid": "79000000009@s.whatsapp.net", "phone": "79000000009"},
+                    "chat": {"type": "group", "jid": group, "name": "Dacha"}
+                })
+            )
+            .await,
+            Some(SkippedFilter)
+        );
+        assert_eq!(
+            status(
+                Some(spanish.clone()),
+                serde_json::json!({"is_from_me": true})
+            )
+            .await,
+            Some(NotNeeded)
+        );
+        assert_eq!(
+            status(Some(spanish), serde_json::json!({"content": {"type": "location", "latitude": 1.0, "longitude": 2.0}})).await,
+            Some(NotNeeded)
+        );
+        assert_eq!(status(None, serde_json::json!({})).await, Some(Pending));
+    }
+}
@@ -0,0 +1,443 @@
+//! Periodic cleanup of caches and expired records, and upkeep of the
+//! database.
+//!
+//! Link previews and profile pictures are cached to save fetches, and OAuth
+//! authorizations and tokens and unsent image uploads expire; none of them
+//! are kept forever. A background task removes what's no longer needed, and
+//! the web API can run the same cleanup on demand.
+//!
+//! The same task keeps the database in shape: the write-ahead log is
+//! checkpointed and truncated once it grows past a limit, free pages are
+//! given back daily and the query planner's statistics refreshed weekly.
+//! None of it runs during a history sync.
+
+use anyhow::Result;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use crate::storage::MessageStore;
+use crate::web::{AppState, ProfilePicture};
+
+/// How often the cleanup runs
+pub const INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Longest random delay added to each run, so instances sharing a disk
+/// don't all do their upkeep at once
+pub const MAX_JITTER: Duration = Duration::from_secs(5 * 60);
+
+/// WAL size above which it's checkpointed, unless configured otherwise
+pub const DEFAULT_WAL_LIMIT_BYTES: u64 = 64 * 1024 * 1024;
+
+/// How often free pages are given back, and the query planner's
+/// statistics refreshed (ms)
+pub const VACUUM_INTERVAL_MS: i64 = 24 * 60 * 60 * 1000;
+pub const ANALYZE_INTERVAL_MS: i64 = 7 * 24 * 60 * 60 * 1000;
+
+/// Most free pages given back at a time
+const VACUUM_MAX_PAGES: u32 = 25_000;
+
+/// Link previews older than this are deleted (seconds)
+pub const LINK_PREVIEW_MAX_AGE_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// Most link previews kept unless configured otherwise
+pub const DEFAULT_MAX_LINK_PREVIEWS: usize = 5000;
+
+/// Most profile pictures kept in memory; the least recently used go first
+pub const MAX_AVATAR_CACHE_ENTRIES: usize = 500;
+
+/// What a cleanup removed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CleanupReport {
+    /// Link previews past `LINK_PREVIEW_MAX_AGE_SECS`
+    pub link_previews_expired: usize,
+    /// Link previews beyond the configured maximum, oldest first
+    pub link_previews_evicted: usize,
+    /// Profile pictures beyond `MAX_AVATAR_CACHE_ENTRIES`
+    pub avatars_evicted: usize,
+    /// Expired or used OAuth authorizations, codes and tokens
+    pub oauth_entries_expired: usize,
+    /// Chunked image uploads past `UPLOAD_TTL_MS`
+    pub uploads_expired: usize,
+}
+
+impl CleanupReport {
+    fn total(&self) -> usize {
+        self.link_previews_expired
+            + self.link_previews_evicted
+            + self.avatars_evicted
+            + self.oauth_entries_expired
+            + self.uploads_expired
+    }
+}
+
+/// What the database upkeep did
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageReport {
+    /// The WAL was past its limit and was checkpointed
+    pub wal_checkpointed: bool,
+    /// Bytes of free pages given back, if the vacuum was due
+    pub vacuumed_bytes: Option<u64>,
+    /// The query planner's statistics were refreshed
+    pub analyzed: bool,
+}
+
+/// Cleanup settings
+pub struct Maintenance {
+    max_link_previews: AtomicUsize,
+    wal_limit_bytes: AtomicU64,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Self {
+            max_link_previews: AtomicUsize::new(DEFAULT_MAX_LINK_PREVIEWS),
+            wal_limit_bytes: AtomicU64::new(DEFAULT_WAL_LIMIT_BYTES),
+        }
+    }
+}
+
+impl Maintenance {
+    pub fn max_link_previews(&self) -> usize {
+        self.max_link_previews.load(Ordering::Relaxed)
+    }
+
+    pub fn set_max_link_previews(&self, max: usize) {
+        self.max_link_previews.store(max, Ordering::Relaxed);
+    }
+
+    pub fn wal_limit_bytes(&self) -> u64 {
+        self.wal_limit_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn set_wal_limit_bytes(&self, bytes: u64) {
+        self.wal_limit_bytes.store(bytes, Ordering::Relaxed);
+    }
+}
+
+/// Drop the least recently used profile pictures beyond `max_entries`,
+/// returning how many were dropped
+pub fn evict_least_recently_used(
+    cache: &mut HashMap<String, ProfilePicture>,
+    max_entries: usize,
+) -> usize {
+    let excess = cache.len().saturating_sub(max_entries);
+    if excess == 0 {
+        return 0;
+    }
+
+    let mut by_use: Vec<(i64, String)> = cache
+        .iter()
+        .map(|(jid, picture)| (picture.last_used, jid.clone()))
+        .collect();
+    by_use.sort_unstable();
+    for (_, jid) in by_use.into_iter().take(excess) {
+        cache.remove(&jid);
+    }
+    excess
+}
+
+/// Remove expired and excess cache entries and expired OAuth records.
+/// The database is left alone while the disk is nearly full.
+pub async fn run(state: &AppState) -> Result<CleanupReport> {
+    let mut report = CleanupReport::default();
+
+    if state.store.is_read_only() {
+        debug!("Skipping database cleanup while read-only");
+    } else {
+        let store = state.store.clone();
+        let max_link_previews = state.maintenance.max_link_previews();
+        let fetched_before = chrono::Utc::now().timestamp() - LINK_PREVIEW_MAX_AGE_SECS;
+        let ((expired, evicted), oauth) = tokio::task::spawn_blocking(move || -> Result<_> {
+            let link_previews = store.prune_link_previews(fetched_before, max_link_previews)?;
+            Ok((link_previews, store.oauth_cleanup_expired()?))
+        })
+        .await??;
+        report.link_previews_expired = expired;
+        report.link_previews_evicted = evicted;
+        report.oauth_entries_expired = oauth;
+    }
+
+    report.uploads_expired = state
+        .uploads
+        .remove_expired(chrono::Utc::now().timestamp_millis());
+
+    report.avatars_evicted = evict_least_recently_used(
+        &mut *state.avatar_cache.write().await,
+        MAX_AVATAR_CACHE_ENTRIES,
+    );
+
+    if report.total() > 0 {
+        info!(
+            "Cleanup removed {} expired and {} excess link previews, {} cached avatars, {} OAuth entries and {} uploads",
+            report.link_previews_expired,
+            report.link_previews_evicted,
+            report.avatars_evicted,
+            report.oauth_entries_expired,
+            report.uploads_expired
+        );
+    }
+    Ok(report)
+}
+
+/// Checkpoint the WAL if it's past `wal_limit_bytes`, and vacuum and
+/// analyze the database if they're due at `now` (ms). The vacuum runs
+/// before the checkpoint, as it writes to the WAL.
+pub fn maintain_storage(
+    store: &MessageStore,
+    wal_limit_bytes: u64,
+    now: i64,
+) -> Result<StorageReport> {
+    let mut report = StorageReport::default();
+    let mut times = store.get_storage_maintenance_times()?;
+    let due = |last: Option<i64>, interval: i64| last.is_none_or(|last| now - last >= interval);
+
+    if due(times.last_vacuum, VACUUM_INTERVAL_MS) {
+        report.vacuumed_bytes = Some(store.incremental_vacuum(VACUUM_MAX_PAGES)?);
+        times.last_vacuum = Some(now);
+    }
+    if due(times.last_analyze, ANALYZE_INTERVAL_MS) {
+        store.analyze()?;
+        report.analyzed = true;
+        times.last_analyze = Some(now);
+    }
+    if store.database_sizes()?.wal_bytes > wal_limit_bytes {
+        report.wal_checkpointed = store.checkpoint_wal()?;
+        if report.wal_checkpointed {
+            times.last_checkpoint = Some(now);
+        } else {
+            warn!("WAL checkpoint couldn't finish, another process is reading");
+        }
+    }
+
+    store.set_storage_maintenance_times(&times)?;
+    Ok(report)
+}
+
+/// Database upkeep, unless the disk is nearly full or a history sync is
+/// being imported. None if skipped.
+pub async fn run_storage(state: &AppState) -> Result<Option<StorageReport>> {
+    if state.store.is_read_only() {
+        debug!("Skipping database upkeep while read-only");
+        return Ok(None);
+    }
+    if state.history_sync.progress().is_some() {
+        debug!("Skipping database upkeep during a history sync");
+        return Ok(None);
+    }
+
+    let store = state.store.clone();
+    let wal_limit_bytes = state.maintenance.wal_limit_bytes();
+    let now = chrono::Utc::now().timestamp_millis();
+    let report =
+        tokio::task::spawn_blocking(move || maintain_storage(&store, wal_limit_bytes, now))
+            .await??;
+    if report != StorageReport::default() {
+        info!(
+            "Database upkeep: WAL checkpointed: {}, vacuumed: {:?} bytes, analyzed: {}",
+            report.wal_checkpointed, report.vacuumed_bytes, report.analyzed
+        );
+    }
+    Ok(Some(report))
+}
+
+/// A random delay of up to `MAX_JITTER`
+fn jitter() -> Duration {
+    let mut bytes = [0u8; 8];
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        return Duration::ZERO;
+    }
+    let max_ms = MAX_JITTER.as_millis() as u64;
+    Duration::from_millis(u64::from_le_bytes(bytes) % (max_ms + 1))
+}
+
+/// Run the cleanup and database upkeep every `INTERVAL`, each run delayed
+/// by up to `MAX_JITTER`, until shutdown
+pub fn spawn(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut delay = jitter();
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = state.shutdown.wait() => break,
+            }
+            delay = INTERVAL + jitter();
+            let _work = state.shutdown.track("maintenance");
+            if let Err(e) = run(&state).await {
+                warn!("Cleanup failed: {}", e);
+            }
+            if let Err(e) = run_storage(&state).await {
+                warn!("Database upkeep failed: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{test_store, text_message};
+    use crate::web::test_state;
+
+    #[tokio::test]
+    async fn test_run_cleanup() {
+        let (state, _dir) = test_state(None);
+        let store = state.store.clone();
+        for i in 0..3 {
+            let url = format!("https://example.com/{}", i);
+            store
+                .save_link_preview(&crate::link_preview::LinkPreview::error(url, "404".into()))
+                .unwrap();
+        }
+        state.maintenance.set_max_link_previews(2);
+        {
+            let mut cache = state.avatar_cache.write().await;
+            for i in 0..MAX_AVATAR_CACHE_ENTRIES + 2 {
+                let picture = ProfilePicture {
+                    url: None,
+                    fetched_at: 0,
+                    last_used: i as i64,
+                };
+                cache.insert(format!("{}@s.whatsapp.net", i), picture);
+            }
+        }
+
+        let report = run(&state).await.unwrap();
+        assert_eq!(
+            report,
+            CleanupReport {
+                link_previews_expired: 0,
+                link_previews_evicted: 1,
+                avatars_evicted: 2,
+                oauth_entries_expired: 0,
+                uploads_expired: 0,
+            }
+        );
+        let cache = state.avatar_cache.read().await;
+        assert_eq!(cache.len(), MAX_AVATAR_CACHE_ENTRIES);
+        assert!(!cache.contains_key("1@s.whatsapp.net"));
+        assert!(cache.contains_key("2@s.whatsapp.net"));
+        drop(cache);
+
+        // Nothing left to remove the second time
+        assert_eq!(run(&state).await.unwrap(), CleanupReport::default());
+    }
+
+    #[test]
+    fn test_checkpoint_shrinks_grown_wal() {
+        let (store, _dir) = test_store();
+        assert!(store.database_sizes().unwrap().incremental_vacuum);
+        let chat = "447700900001@s.whatsapp.net";
+        store
+            .upsert_contact(chat, None, None, Some("private"), 1)
+            .unwrap();
+        let body = "a long message ".repeat(100);
+        let messages: Vec<_> = (0..2000)
+            .map(|i| text_message(&format!("m{}", i), chat, 1_700_000_000_000 + i, &body))
+            .collect();
+        store.add_messages_batch(&messages).unwrap();
+
+        let limit = 1024 * 1024;
+        let grown = store.database_sizes().unwrap().wal_bytes;
+        assert!(grown > limit, "WAL only grew to {} bytes", grown);
+
+        let now = 1_700_000_000_000;
+        let report = maintain_storage(&store, limit, now).unwrap();
+        assert!(report.wal_checkpointed);
+        assert!(report.analyzed);
+        assert!(report.vacuumed_bytes.is_some());
+        // Only recording the run is left in it
+        let sizes = store.database_sizes().unwrap();
+        assert!(
+            sizes.wal_bytes < 64 * 1024,
+            "WAL still {} bytes",
+            sizes.wal_bytes
+        );
+        assert!(sizes.database_bytes > limit);
+        assert!(store.checkpoint_wal().unwrap());
+        assert_eq!(store.database_sizes().unwrap().wal_bytes, 0);
+
+        // An hour later nothing is due
+        assert_eq!(
+            maintain_storage(&store, limit, now + 60 * 60 * 1000).unwrap(),
+            StorageReport::default()
+        );
+        let times = store.get_storage_maintenance_times().unwrap();
+        assert_eq!(times.last_checkpoint, Some(now));
+        assert_eq!(times.last_analyze, Some(now));
+        let report = maintain_storage(&store, limit, now + VACUUM_INTERVAL_MS).unwrap();
+        assert_eq!(report.vacuumed_bytes, Some(0));
+        assert!(!report.analyzed);
+    }
+
+    #[test]
+    fn test_existing_database_converted_to_incremental_vacuum() {
+        let dir = tempfile::tempdir().unwrap();
+        // Created before incremental vacuum, with pages freed since
+        let conn = rusqlite::Connection::open(dir.path().join("messages.db")).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE scratch (data BLOB);
+             WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 200)
+             INSERT INTO scratch SELECT zeroblob(4096) FROM n;
+             DROP TABLE scratch;",
+        )
+        .unwrap();
+        drop(conn);
+
+        // Not without room for a copy of the database
+        let store = MessageStore::new(dir.path())
+            .unwrap()
+            .with_min_free_space(u64::MAX / 2);
+        assert_eq!(store.incremental_vacuum(VACUUM_MAX_PAGES).unwrap(), 0);
+        assert!(!store.database_sizes().unwrap().incremental_vacuum);
+        drop(store);
+
+        let store = MessageStore::new(dir.path()).unwrap();
+        let sizes = store.database_sizes().unwrap();
+        assert!(!sizes.incremental_vacuum);
+        assert!(sizes.free_bytes > 0);
+
+        let freed = store.incremental_vacuum(VACUUM_MAX_PAGES).unwrap();
+        assert_eq!(freed, sizes.free_bytes);
+        let sizes = store.database_sizes().unwrap();
+        assert!(sizes.incremental_vacuum);
+        assert_eq!(sizes.free_bytes, 0);
+    }
+
+    #[test]
+    fn test_evict_least_recently_used() {
+        let picture = |last_used: i64| ProfilePicture {
+            url: None,
+            fetched_at: 0,
+            last_used,
+        };
+        let mut cache: HashMap<String, ProfilePicture> = (0..10)
+            .map(|i| (format!("{}@s.whatsapp.net", i), picture(100 + i)))
+            .collect();
+        // Fetched first, but used most recently
+        cache.get_mut("0@s.whatsapp.net").unwrap().last_used = 1000;
+
+        assert_eq!(evict_least_recently_used(&mut cache, 10), 0);
+        assert_eq!(evict_least_recently_used(&mut cache, 7), 3);
+        let mut kept: Vec<&str> = cache.keys().map(|jid| jid.as_str()).collect();
+        kept.sort_unstable();
+        assert_eq!(
+            kept,
+            [
+                "0@s.whatsapp.net",
+                "4@s.whatsapp.net",
+                "5@s.whatsapp.net",
+                "6@s.whatsapp.net",
+                "7@s.whatsapp.net",
+                "8@s.whatsapp.net",
+                "9@s.whatsapp.net",
+            ]
+        );
+    }
+}
@@ -2,23 +2,76 @@
 //!
 //! Exposes WhatsApp functionality to external LLMs via the MCP protocol.
 
-use crate::storage::{MessageStore, StoredContact, StoredMessage};
+use crate::anonymize::Anonymizer;
+use crate::storage::{
+    McpQuota, MessageFilter, MessageStore, StoredContact, StoredMessage, TranslationPair,
+};
 use crate::translation::TranslationService;
 use rmcp::{
     model::{
-        CallToolRequestParam, CallToolResult, Content, Implementation, ListToolsResult,
-        PaginatedRequestParam, ServerCapabilities, ServerInfo, Tool,
+        CallToolRequestParam, CallToolResult, Content, ErrorCode, Implementation, ListToolsResult,
+        PaginatedRequestParam, ResourceContents, ServerCapabilities, ServerInfo, Tool,
     },
     service::RequestContext,
     ErrorData as McpError, RoleServer, ServerHandler,
 };
 use serde::Serialize;
 use serde_json::json;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use tokio::sync::mpsc;
-use tracing::{error, info, warn};
+use tracing::warn;
+
+use crate::bridge::{is_channel_jid, BridgeCommand};
+use crate::broadcast::{Broadcasts, MAX_RECIPIENTS};
+use crate::groups::{create_group, resolve_participants, GroupError, PendingGroups};
+use crate::mcp_access::ChatAccessList;
+use crate::new_chat::{normalize_phone, start_new_chat, PendingNumberChecks};
+use crate::notes::{save_note, Note, NoteError};
+use crate::send_guard::{check_language, PendingConfirmations, PendingSend};
+use crate::sending::{
+    pending_message_id, OutgoingMessage, OutgoingMessageService, OutgoingText, ReplyTo,
+};
+use crate::tzinfer;
+
+/// Maximum number of messages read_messages returns at once
+const MAX_READ_MESSAGES_LIMIT: u64 = 200;
+
+/// Maximum number of translations get_translations returns at once
+const MAX_TRANSLATIONS_LIMIT: u64 = 200;
+
+/// Characters of each message's text read_messages returns by default
+const DEFAULT_MAX_CHARS_PER_MESSAGE: u64 = 500;
+
+/// Largest media read_messages and get_media include in their results (bytes)
+const MAX_INLINE_MEDIA_BYTES: u64 = 512 * 1024;
+
+/// Error code for calls refused because the client used up its daily quota
+const QUOTA_EXCEEDED: ErrorCode = ErrorCode(-32029);
 
-use crate::bridge::BridgeCommand;
+/// Error code for replies to a message from another chat (HTTP 422's counterpart)
+const REPLY_TARGET_IN_OTHER_CHAT: ErrorCode = ErrorCode(-32022);
+
+/// Error code for chats the client's access list excludes (HTTP 403's counterpart)
+const CHAT_ACCESS_DENIED: ErrorCode = ErrorCode(-32023);
+
+/// The message a sent message replies to
+#[derive(Debug)]
+struct ReplyTarget {
+    message_id: String,
+    /// Who sent it (None for my own messages)
+    sender: Option<String>,
+    preview: String,
+}
+
+/// What a tool call did that counts against the client's quota
+#[derive(Debug, Default)]
+struct ToolUsage {
+    /// Whether a message was sent
+    sent: bool,
+    /// Translation and language detection spend (USD)
+    cost_usd: f64,
+}
 
 /// WhatsApp MCP Server handler
 #[derive(Clone)]
@@ -26,6 +79,22 @@ pub struct WhatsAppMcpServer {
     store: Arc<MessageStore>,
     command_tx: Option<mpsc::Sender<BridgeCommand>>,
     translator: Option<Arc<TranslationService>>,
+    number_checks: Arc<PendingNumberChecks>,
+    pending_groups: Arc<PendingGroups>,
+    confirmations: Arc<PendingConfirmations>,
+    /// Default for send_message's `confirm_language` argument
+    confirm_language: bool,
+    /// OAuth client the request was authenticated as
+    client_id: String,
+    sending: OutgoingMessageService,
+    /// Why sends are refused when there is no bridge to send through
+    sends_disabled: Option<String>,
+    /// Salt the client's results are pseudonymized with, if it's anonymized
+    anonymization_salt: Option<String>,
+    /// Chats the client may see, if it's limited
+    access: Option<ChatAccessList>,
+    /// Where send_broadcast queues its batches, None where it can't
+    broadcasts: Option<Arc<Broadcasts>>,
 }
 
 /// Contact information returned by the API
@@ -38,6 +107,26 @@ pub struct ContactInfo {
     pub contact_type: Option<String>,
     pub unread_count: i32,
     pub is_pinned: bool,
+    pub auto_translate_outgoing: bool,
+    /// Group description
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Disappearing messages timer in seconds, if it's on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ephemeral_duration: Option<u32>,
+}
+
+impl ContactInfo {
+    /// Replace the ID, name and phone with placeholders
+    fn anonymize(self, anonymizer: &Anonymizer) -> Self {
+        Self {
+            id: anonymizer.jid(&self.id),
+            name: self.name.map(|name| anonymizer.person(&name)),
+            phone: self.phone.map(|phone| anonymizer.phone(&phone)),
+            description: self.description.map(|d| anonymizer.scrub(&d)),
+            ..self
+        }
+    }
 }
 
 impl From<StoredContact> for ContactInfo {
@@ -49,8 +138,112 @@ impl From<StoredContact> for ContactInfo {
             contact_type: c.contact_type,
             unread_count: c.unread_count,
             is_pinned: c.pinned_at.is_some(),
+            auto_translate_outgoing: c.auto_translate_outgoing,
+            description: c.description,
+            ephemeral_duration: c.ephemeral_duration,
+        }
+    }
+}
+
+/// How read_messages returns media
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MediaMode {
+    /// Left out
+    None,
+    /// Described, to fetch with get_media
+    Metadata,
+    /// Small images included as image content
+    Inline,
+}
+
+impl MediaMode {
+    fn from_arg(args: &serde_json::Value) -> Result<Self, McpError> {
+        match args.get("include_media") {
+            None | Some(serde_json::Value::Null) => Ok(Self::None),
+            Some(value) => match value.as_str() {
+                Some("none") => Ok(Self::None),
+                Some("metadata") => Ok(Self::Metadata),
+                Some("inline") => Ok(Self::Inline),
+                _ => Err(McpError::invalid_params(
+                    "include_media must be \"none\", \"metadata\" or \"inline\"",
+                    None,
+                )),
+            },
+        }
+    }
+}
+
+/// A message's media, as returned by the API
+#[derive(Debug, Serialize)]
+pub struct MediaInfo {
+    /// ID to fetch it with get_media (the message's ID)
+    pub media_id: String,
+    pub mime_type: Option<String>,
+    /// Size of the file, when known
+    pub size_bytes: Option<u64>,
+    /// Whether it's included in the result as its own content item
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub included: bool,
+    /// Why it wasn't included
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(skip)]
+    view_once: bool,
+}
+
+impl MediaInfo {
+    /// The media a message carries, if any
+    fn of(message: &StoredMessage) -> Option<Self> {
+        let parsed;
+        let content = match &message.content {
+            Some(content) => content,
+            None => {
+                parsed = serde_json::from_str::<serde_json::Value>(&message.content_json).ok()?;
+                &parsed
+            }
+        };
+        let has_media = content.get("has_media").and_then(|v| v.as_bool()) == Some(true)
+            || content.get("media_data").is_some()
+            || content.get("mediaData").is_some();
+        if !has_media {
+            return None;
         }
+        Some(Self {
+            media_id: message.id.clone(),
+            mime_type: content
+                .get("mime_type")
+                .or_else(|| content.get("mimeType"))
+                .and_then(|v| v.as_str())
+                .map(String::from),
+            size_bytes: content
+                .get("file_size")
+                .and_then(|v| v.as_u64())
+                .filter(|size| *size > 0),
+            included: false,
+            note: None,
+            view_once: content.get("view_once").and_then(|v| v.as_bool()) == Some(true),
+        })
+    }
+
+    fn is_image(&self) -> bool {
+        self.mime_type
+            .as_deref()
+            .is_some_and(|mime| mime.starts_with("image/"))
     }
+
+    fn too_large(&mut self, size: u64) {
+        self.note = Some(format!(
+            "Too large to include ({} KB, the limit is {} KB)",
+            size.div_ceil(1024),
+            MAX_INLINE_MEDIA_BYTES / 1024
+        ));
+    }
+}
+
+/// Size of the data a base64 string decodes to
+fn base64_decoded_len(data: &str) -> u64 {
+    let padding = data.bytes().rev().take_while(|b| *b == b'=').count();
+    (data.len() / 4 * 3).saturating_sub(padding) as u64
 }
 
 /// Message information returned by the API
@@ -63,6 +256,121 @@ pub struct MessageInfo {
     pub text: Option<String>,
     pub translated_text: Option<String>,
     pub content_type: String,
+    /// What sent an outgoing message (e.g. "web", "mcp:<client_id>")
+    pub origin: Option<String>,
+    /// Whether the text was cut to max_chars_per_message
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub text_truncated: bool,
+    /// Attached media, when asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media: Option<MediaInfo>,
+    /// Current reactions to it, e.g. "👍 from Maria"
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub reactions: Vec<String>,
+    /// For a reaction to a message before the page, that message's ID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reacted_to: Option<String>,
+}
+
+impl MessageInfo {
+    /// Replace the sender with a placeholder and scrub the text
+    fn anonymize(self, anonymizer: &Anonymizer) -> Self {
+        Self {
+            sender_name: self.sender_name.map(|name| anonymizer.person(&name)),
+            text: self.text.map(|text| anonymizer.scrub(&text)),
+            translated_text: self.translated_text.map(|text| anonymizer.scrub(&text)),
+            ..self
+        }
+    }
+
+    /// Cut the text and translation to at most `max_chars` characters
+    fn truncate(mut self, max_chars: usize) -> Self {
+        for text in [&mut self.text, &mut self.translated_text]
+            .into_iter()
+            .flatten()
+        {
+            if let Some((cut, _)) = text.char_indices().nth(max_chars) {
+                text.truncate(cut);
+                text.push('…');
+                self.text_truncated = true;
+            }
+        }
+        self
+    }
+}
+
+/// A page of messages returned by read_messages
+#[derive(Debug, Serialize)]
+pub struct ReadMessagesResult {
+    pub contact_id: String,
+    /// Messages in the chat overall
+    pub total_messages: u64,
+    /// Whether more messages in the requested range weren't returned
+    pub truncated: bool,
+    /// Oldest first
+    pub messages: Vec<MessageInfo>,
+}
+
+/// A chat's pinned messages returned by get_pinned_messages
+#[derive(Debug, Serialize)]
+pub struct PinnedMessagesResult {
+    pub contact_id: String,
+    /// Most recently pinned first
+    pub pinned_messages: Vec<PinnedMessageInfo>,
+}
+
+/// A pinned message returned by get_pinned_messages
+#[derive(Debug, Serialize)]
+pub struct PinnedMessageInfo {
+    #[serde(flatten)]
+    pub message: MessageInfo,
+    /// When it was pinned (Unix milliseconds)
+    pub pinned_at: i64,
+}
+
+/// An original/translation pair returned by get_translations
+#[derive(Debug, Serialize)]
+pub struct TranslationInfo {
+    pub message_id: String,
+    pub timestamp: i64,
+    /// "incoming" or "outgoing"
+    pub direction: &'static str,
+    pub original_text: Option<String>,
+    pub translated_text: Option<String>,
+    pub source_language: Option<String>,
+}
+
+impl TranslationInfo {
+    fn anonymize(self, anonymizer: &Anonymizer) -> Self {
+        Self {
+            original_text: self.original_text.map(|text| anonymizer.scrub(&text)),
+            translated_text: self.translated_text.map(|text| anonymizer.scrub(&text)),
+            ..self
+        }
+    }
+}
+
+impl From<TranslationPair> for TranslationInfo {
+    fn from(t: TranslationPair) -> Self {
+        Self {
+            message_id: t.message_id,
+            timestamp: t.timestamp,
+            direction: t.direction,
+            original_text: t.original_text,
+            translated_text: t.translated_text,
+            source_language: t.source_language,
+        }
+    }
+}
+
+/// A page of translations returned by get_translations
+#[derive(Debug, Serialize)]
+pub struct GetTranslationsResult {
+    pub contact_id: String,
+    /// Oldest first
+    pub translations: Vec<TranslationInfo>,
+    /// Whether the page is full, so older translations may exist
+    pub has_more: bool,
 }
 
 impl From<StoredMessage> for MessageInfo {
@@ -89,8 +397,103 @@ impl From<StoredMessage> for MessageInfo {
             text,
             translated_text: m.translated_text,
             content_type: m.content_type,
+            origin: m.origin,
+            text_truncated: false,
+            media: None,
+            reactions: Vec::new(),
+            reacted_to: None,
+        }
+    }
+}
+
+/// A reaction's emoji and the ID of the message it reacts to
+fn reaction_of(message: &StoredMessage) -> Option<(String, String)> {
+    if message.content_type != "Reaction" {
+        return None;
+    }
+    let content: serde_json::Value = serde_json::from_str(&message.content_json).ok()?;
+    let target = content.get("target_message_id")?.as_str()?;
+    let emoji = content.get("emoji").and_then(|v| v.as_str()).unwrap_or("");
+    Some((emoji.to_string(), target.to_string()))
+}
+
+/// A message in a page with the reactions folded into it
+#[derive(Debug)]
+struct FoldedMessage {
+    message: StoredMessage,
+    /// Current reactions to it, oldest first
+    reactions: Vec<StoredMessage>,
+    /// Set when the message is a reaction to a message before the page: its
+    /// emoji and target
+    earlier_reaction: Option<(String, String)>,
+}
+
+/// Fold the reactions in a page of messages into the messages they react
+/// to. Only each person's latest reaction counts, and an empty emoji takes
+/// it back. A reaction to a message before the page is kept in place, to
+/// be shown as a note.
+fn fold_reactions(messages: Vec<StoredMessage>) -> Vec<FoldedMessage> {
+    let in_page: HashSet<String> = messages
+        .iter()
+        .filter(|m| m.content_type != "Reaction")
+        .map(|m| m.id.clone())
+        .collect();
+
+    // Each person's latest reaction to each message, by position in the page
+    let mut latest = HashMap::new();
+    for (i, message) in messages.iter().enumerate() {
+        if let Some((_, target)) = reaction_of(message) {
+            let reactor = match (message.is_from_me, &message.sender_phone) {
+                (true, _) => "me".to_string(),
+                (false, Some(phone)) => phone.clone(),
+                (false, None) => message.id.clone(),
+            };
+            latest.insert((target, reactor), i);
         }
     }
+    let mut current: Vec<usize> = latest.into_values().collect();
+    current.sort_unstable();
+
+    // Current reactions go under their message if it's in the page, and
+    // otherwise stay as a note; the rest are dropped
+    let mut reactions_to: HashMap<String, Vec<StoredMessage>> = HashMap::new();
+    let mut earlier = HashMap::new();
+    let mut messages: Vec<Option<StoredMessage>> = messages.into_iter().map(Some).collect();
+    for i in current {
+        let Some((emoji, target)) = messages[i].as_ref().and_then(reaction_of) else {
+            continue;
+        };
+        if emoji.is_empty() {
+            continue;
+        }
+        if in_page.contains(&target) {
+            let reaction = messages[i].take().unwrap();
+            reactions_to.entry(target).or_default().push(reaction);
+        } else {
+            earlier.insert(i, (emoji, target));
+        }
+    }
+
+    messages
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, message)| {
+            let message = message?;
+            match earlier.remove(&i) {
+                Some(earlier_reaction) => Some(FoldedMessage {
+                    message,
+                    reactions: Vec::new(),
+                    earlier_reaction: Some(earlier_reaction),
+                }),
+                None if message.content_type == "Reaction" => None,
+                None => Some(FoldedMessage {
+                    reactions: reactions_to.remove(&message.id).unwrap_or_default(),
+                    message,
+                    earlier_reaction: None,
+                }),
+            }
+        })
+        .collect()
 }
 
 impl WhatsAppMcpServer {
@@ -98,14 +501,155 @@ impl WhatsAppMcpServer {
         store: Arc<MessageStore>,
         command_tx: Option<mpsc::Sender<BridgeCommand>>,
         translator: Option<Arc<TranslationService>>,
+        number_checks: Arc<PendingNumberChecks>,
+        confirmations: Arc<PendingConfirmations>,
+        confirm_language: bool,
+        client_id: String,
     ) -> Self {
+        let sending = OutgoingMessageService::new((*store).clone(), translator.clone());
         Self {
             store,
             command_tx,
             translator,
+            number_checks,
+            pending_groups: Arc::default(),
+            confirmations,
+            confirm_language,
+            client_id,
+            sending,
+            sends_disabled: None,
+            anonymization_salt: None,
+            access: None,
+            broadcasts: None,
         }
     }
 
+    /// Wait for group creations in `pending_groups`, which bridge events
+    /// complete
+    pub fn with_pending_groups(mut self, pending_groups: Arc<PendingGroups>) -> Self {
+        self.pending_groups = pending_groups;
+        self
+    }
+
+    /// Queue send_broadcast's batches with `broadcasts`
+    pub fn with_broadcasts(mut self, broadcasts: Arc<Broadcasts>) -> Self {
+        self.broadcasts = Some(broadcasts);
+        self
+    }
+
+    /// Pseudonymize everything the client reads with `salt`, and refuse its
+    /// sends
+    pub fn with_anonymization(mut self, salt: String) -> Self {
+        self.anonymization_salt = Some(salt);
+        self
+    }
+
+    /// Limit the client to the chats `access` allows
+    pub fn with_access(mut self, access: Option<ChatAccessList>) -> Self {
+        self.access = access;
+        self
+    }
+
+    /// Refuse the call if the client's access list excludes the chat
+    fn check_access(&self, contact_id: &str) -> Result<(), McpError> {
+        let Some(access) = &self.access else {
+            return Ok(());
+        };
+        let lookup = |e: anyhow::Error| {
+            McpError::internal_error(format!("Failed to check chat access: {}", e), None)
+        };
+        let allowed = match self.store.get_contact(contact_id).map_err(lookup)? {
+            Some(contact) => access.allows_contact(&contact),
+            None => {
+                let chat = self.store.resolve_contact_id(contact_id).map_err(lookup)?;
+                access.allows(&chat, None)
+            }
+        };
+        if allowed {
+            return Ok(());
+        }
+        Err(McpError::new(
+            CHAT_ACCESS_DENIED,
+            "This chat isn't shared with this client",
+            Some(json!({ "contact_id": contact_id })),
+        ))
+    }
+
+    /// The client's anonymizer, if it's anonymized, knowing every contact's
+    /// name and `names`
+    fn anonymizer<'a>(
+        &self,
+        contacts: &'a [StoredContact],
+        names: impl IntoIterator<Item = &'a str>,
+    ) -> Option<Anonymizer> {
+        let salt = self.anonymization_salt.as_deref()?;
+        Some(
+            Anonymizer::new(salt).with_names(
+                contacts
+                    .iter()
+                    .filter_map(|c| c.name.as_deref())
+                    .chain(names),
+            ),
+        )
+    }
+
+    /// The real contact ID an anonymized client's placeholder stands for
+    fn resolve_anonymized_contact(
+        salt: &str,
+        contact_id: &str,
+        contacts: &[StoredContact],
+    ) -> Result<String, McpError> {
+        let anonymizer = Anonymizer::new(salt);
+        contacts
+            .iter()
+            .find(|c| anonymizer.jid(&c.id) == contact_id)
+            .map(|c| c.id.clone())
+            .ok_or_else(|| {
+                McpError::invalid_params("Unknown contact_id (use an ID from list_contacts)", None)
+            })
+    }
+
+    /// Every contact, for anonymized clients (who see placeholders for
+    /// them) and otherwise nothing
+    fn contacts_for_anonymization(&self) -> Result<Vec<StoredContact>, McpError> {
+        if self.anonymization_salt.is_none() {
+            return Ok(Vec::new());
+        }
+        self.store
+            .get_contacts()
+            .map_err(|e| McpError::internal_error(format!("Failed to get contacts: {}", e), None))
+    }
+
+    /// Refuse sends with `reason` while there is no bridge to send through
+    pub fn with_sends_disabled(mut self, reason: &str) -> Self {
+        self.sends_disabled = Some(reason.to_string());
+        self
+    }
+
+    /// Serve MCP over a byte stream (stdin/stdout for `mcp-stdio`) until
+    /// the client disconnects
+    pub async fn serve_io<T, E, A>(self, transport: T) -> anyhow::Result<()>
+    where
+        T: rmcp::transport::IntoTransport<RoleServer, E, A>,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let service = rmcp::ServiceExt::serve(self, transport).await?;
+        service.waiting().await?;
+        Ok(())
+    }
+
+    /// The bridge's command sender, or why sends aren't possible
+    fn command_sender(&self) -> Result<&mpsc::Sender<BridgeCommand>, McpError> {
+        self.command_tx
+            .as_ref()
+            .ok_or_else(|| match &self.sends_disabled {
+                Some(reason) => {
+                    McpError::internal_error(format!("Sending is disabled: {}", reason), None)
+                }
+                None => McpError::internal_error("WhatsApp bridge not connected", None),
+            })
+    }
+
     fn list_contacts_tool() -> Tool {
         let schema = json!({
             "type": "object",
@@ -130,6 +674,30 @@ impl WhatsAppMcpServer {
         )
     }
 
+    fn resolve_contact_tool() -> Tool {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "name": {
+                    "type": "string",
+                    "description": "Name or part of a name, e.g. 'dmitri' or 'familia'. Case, accents and emoji are ignored and Cyrillic and Greek names match their Latin spelling"
+                },
+                "limit": {
+                    "type": "integer",
+                    "description": "Maximum number of contacts to return (default: 10)",
+                    "minimum": 1,
+                    "maximum": 50
+                }
+            },
+            "required": ["name"]
+        });
+        Tool::new(
+            "resolve_contact",
+            "Find contacts and groups by name, best matches first (names starting with it, then names with a word starting with it, then names containing it). Returns the same fields as list_contacts.",
+            schema.as_object().unwrap().clone(),
+        )
+    }
+
     fn read_messages_tool() -> Tool {
         let schema = json!({
             "type": "object",
@@ -142,14 +710,54 @@ impl WhatsAppMcpServer {
                     "type": "integer",
                     "description": "Maximum number of messages to return (default: 50)",
                     "minimum": 1,
-                    "maximum": 200
+                    "maximum": MAX_READ_MESSAGES_LIMIT
+                },
+                "before": {
+                    "type": "integer",
+                    "description": "Only messages before this timestamp (Unix milliseconds). Pass the oldest timestamp from the previous page to read further back"
+                },
+                "after": {
+                    "type": "integer",
+                    "description": "Only messages after this timestamp (Unix milliseconds). Without `before`, returns the oldest messages after it, to read forward"
+                },
+                "max_chars_per_message": {
+                    "type": "integer",
+                    "description": "Cut each message's text to this many characters, 0 for no limit (default: 500)",
+                    "minimum": 0
+                },
+                "include_media": {
+                    "type": "string",
+                    "description": "Photos, videos, voice notes and documents: 'none' leaves them out, 'metadata' describes them (mime type, size, media_id for get_media), 'inline' also includes images up to 512 KB as image content after the JSON, in message order (default: 'none')",
+                    "enum": ["none", "metadata", "inline"]
+                },
+                "include_raw_reactions": {
+                    "type": "boolean",
+                    "description": "Return reactions as separate Reaction messages, as stored, instead of listing them under the messages they react to (default: false)"
                 }
             },
             "required": ["contact_id"]
         });
         Tool::new(
             "read_messages",
-            "Read messages from a specific WhatsApp contact or group. Returns message history with timestamps, sender info, and message content.",
+            "Read messages from a specific WhatsApp contact or group, most recent first unless paging forward with `after`. Returns a page of messages (oldest first) with timestamps, sender info, and content, plus the chat's total message count and whether more messages in the range exist (`truncated`). Reactions are listed under the messages they react to (`reactions`); a reaction to a message before the page is a short note with `reacted_to`. Set include_media to see attached media.",
+            schema.as_object().unwrap().clone(),
+        )
+    }
+
+    fn get_media_tool() -> Tool {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "media_id": {
+                    "type": "string",
+                    "description": "media_id of a message's media (from read_messages with include_media)"
+                }
+            },
+            "required": ["media_id"]
+        });
+        Tool::new(
+            "get_media",
+            "Get the photo, video, voice note or document attached to a WhatsApp message. Returns its metadata as JSON followed by the file (as image content for images, an embedded resource otherwise) when it's at most 512 KB.",
             schema.as_object().unwrap().clone(),
         )
     }
@@ -162,16 +770,107 @@ impl WhatsAppMcpServer {
                     "type": "string",
                     "description": "Contact or group ID (JID) to send the message to"
                 },
+                "phone": {
+                    "type": "string",
+                    "description": "Phone number with country code (e.g. \"+44 7911 123456\"), as an alternative to contact_id for people who aren't contacts yet"
+                },
                 "text": {
                     "type": "string",
                     "description": "Message text to send"
+                },
+                "confirm_language": {
+                    "type": "boolean",
+                    "description": "Check that the message matches the chat's language before sending. On a mismatch nothing is sent and a confirmation token is returned instead"
+                },
+                "confirmation_token": {
+                    "type": "string",
+                    "description": "Token from a previous confirmation_required result. Sends the confirmed message; contact_id and text must be the same as in that call"
+                },
+                "reply_to_message_id": {
+                    "type": "string",
+                    "description": "ID of a message in the same chat (from read_messages) to reply to, quoting it"
+                },
+                "ignore_quiet_hours": {
+                    "type": "boolean",
+                    "description": "Don't warn when it's likely night for the recipient"
                 }
             },
-            "required": ["contact_id", "text"]
+            "required": ["text"]
         });
         Tool::new(
             "send_message",
-            "Send a text message to a WhatsApp contact or group. The message will be sent through the connected WhatsApp account.",
+            "Send a text message to a WhatsApp contact or group. The message will be sent through the connected WhatsApp account. If the result has status \"confirmation_required\", the message was NOT sent: check the detected and chat languages with the user, then call again with the confirmation_token to send it. A message sent while it's likely night for the recipient (23:00-07:00 their time) is still sent, with a warning giving their local time.",
+            schema.as_object().unwrap().clone(),
+        )
+    }
+
+    fn save_note_tool() -> Tool {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "text": {
+                    "type": "string",
+                    "description": "Note text (the caption if an image is given)"
+                },
+                "image_data": {
+                    "type": "string",
+                    "description": "Base64 encoded image to save instead of plain text"
+                },
+                "mime_type": {
+                    "type": "string",
+                    "description": "MIME type of image_data (default: image/jpeg)"
+                }
+            }
+        });
+        Tool::new(
+            "save_note",
+            "Save a note to the user's own WhatsApp \"Saved Messages\" chat (the chat with their own number), as text or an image with a caption. Returns the stored message.",
+            schema.as_object().unwrap().clone(),
+        )
+    }
+
+    fn get_translations_tool() -> Tool {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "contact_id": {
+                    "type": "string",
+                    "description": "Contact or group ID (JID) to get translations from"
+                },
+                "limit": {
+                    "type": "integer",
+                    "description": "Maximum number of translations to return (default: 50)",
+                    "minimum": 1,
+                    "maximum": MAX_TRANSLATIONS_LIMIT
+                },
+                "before": {
+                    "type": "integer",
+                    "description": "Only translations before this timestamp (Unix milliseconds). Pass the oldest timestamp from the previous page to read further back"
+                }
+            },
+            "required": ["contact_id"]
+        });
+        Tool::new(
+            "get_translations",
+            "Get the translated messages of a WhatsApp conversation as pairs of original text and translation, with the foreign language, direction (incoming or outgoing) and timestamp, oldest first. Useful for reviewing or quizzing vocabulary. For outgoing messages the original is what the user wrote and the translation is what was sent.",
+            schema.as_object().unwrap().clone(),
+        )
+    }
+
+    fn get_pinned_messages_tool() -> Tool {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "contact_id": {
+                    "type": "string",
+                    "description": "Contact or group ID (JID) to get the pinned messages of"
+                }
+            },
+            "required": ["contact_id"]
+        });
+        Tool::new(
+            "get_pinned_messages",
+            "Get the messages the user pinned to the top of a WhatsApp conversation (at most 10, such as addresses or door codes), most recently pinned first.",
             schema.as_object().unwrap().clone(),
         )
     }
@@ -190,11 +889,23 @@ impl WhatsAppMcpServer {
             McpError::internal_error(format!("Failed to get contacts: {}", e), None)
         })?;
 
+        let anonymizer = self.anonymizer(&contacts, []);
         let filtered: Vec<ContactInfo> = contacts
-            .into_iter()
+            .iter()
             .filter(|c| contact_type == "all" || c.contact_type.as_deref() == Some(contact_type))
+            .filter(|c| {
+                self.access
+                    .as_ref()
+                    .is_none_or(|access| access.allows_contact(c))
+            })
             .take(limit)
-            .map(ContactInfo::from)
+            .map(|c| {
+                let info = ContactInfo::from(c.clone());
+                match &anonymizer {
+                    Some(anonymizer) => info.anonymize(anonymizer),
+                    None => info,
+                }
+            })
             .collect();
 
         let json = serde_json::to_string_pretty(&filtered).map_err(|e| {
@@ -204,37 +915,38 @@ impl WhatsAppMcpServer {
         Ok(CallToolResult::success(vec![Content::text(json)]))
     }
 
-    async fn handle_read_messages(
+    async fn handle_resolve_contact(
         &self,
         args: serde_json::Value,
     ) -> Result<CallToolResult, McpError> {
-        let contact_id = args
-            .get("contact_id")
+        let name = args
+            .get("name")
             .and_then(|v| v.as_str())
-            .ok_or_else(|| McpError::invalid_params("contact_id is required", None))?;
-        let limit = args.get("limit").and_then(|v| v.as_u64()).unwrap_or(50) as usize;
+            .ok_or_else(|| McpError::invalid_params("name is required", None))?;
+        let limit = integer_arg(&args, "limit")?.unwrap_or(10).clamp(1, 50) as usize;
 
-        let messages = self.store.get_messages(contact_id).map_err(|e| {
-            McpError::internal_error(format!("Failed to get messages: {}", e), None)
+        let contacts = self.store.search_contacts(name, usize::MAX).map_err(|e| {
+            McpError::internal_error(format!("Failed to search contacts: {}", e), None)
         })?;
-
-        // Get the last N messages
-        let recent: Vec<MessageInfo> = messages
+        let matches: Vec<ContactInfo> = contacts
             .into_iter()
-            .rev()
+            .filter(|c| {
+                self.access
+                    .as_ref()
+                    .is_none_or(|access| access.allows_contact(c))
+            })
             .take(limit)
-            .rev()
-            .map(MessageInfo::from)
+            .map(ContactInfo::from)
             .collect();
 
-        let json = serde_json::to_string_pretty(&recent).map_err(|e| {
-            McpError::internal_error(format!("Failed to serialize messages: {}", e), None)
+        let json = serde_json::to_string_pretty(&matches).map_err(|e| {
+            McpError::internal_error(format!("Failed to serialize contacts: {}", e), None)
         })?;
 
         Ok(CallToolResult::success(vec![Content::text(json)]))
     }
 
-    async fn handle_send_message(
+    async fn handle_read_messages(
         &self,
         args: serde_json::Value,
     ) -> Result<CallToolResult, McpError> {
@@ -242,158 +954,879 @@ impl WhatsAppMcpServer {
             .get("contact_id")
             .and_then(|v| v.as_str())
             .ok_or_else(|| McpError::invalid_params("contact_id is required", None))?;
-        let text = args
-            .get("text")
-            .and_then(|v| v.as_str())
-            .ok_or_else(|| McpError::invalid_params("text is required", None))?;
+        let limit = integer_arg(&args, "limit")?.unwrap_or(50);
+        if !(1..=MAX_READ_MESSAGES_LIMIT as i64).contains(&limit) {
+            return Err(McpError::invalid_params(
+                format!("limit must be between 1 and {}", MAX_READ_MESSAGES_LIMIT),
+                None,
+            ));
+        }
+        let before = integer_arg(&args, "before")?;
+        let after = integer_arg(&args, "after")?;
+        if let (Some(before), Some(after)) = (before, after) {
+            if after >= before {
+                return Err(McpError::invalid_params(
+                    "after must be earlier than before",
+                    None,
+                ));
+            }
+        }
+        let max_chars = integer_arg(&args, "max_chars_per_message")?
+            .unwrap_or(DEFAULT_MAX_CHARS_PER_MESSAGE as i64);
+        let max_chars = usize::try_from(max_chars).map_err(|_| {
+            McpError::invalid_params("max_chars_per_message can't be negative", None)
+        })?;
+        let media_mode = MediaMode::from_arg(&args)?;
+        let raw_reactions = match args.get("include_raw_reactions") {
+            None | Some(serde_json::Value::Null) => false,
+            Some(value) => value.as_bool().ok_or_else(|| {
+                McpError::invalid_params("include_raw_reactions must be a boolean", None)
+            })?,
+        };
 
-        let command_tx = self
-            .command_tx
-            .as_ref()
-            .ok_or_else(|| McpError::internal_error("WhatsApp bridge not connected", None))?;
+        // Anonymized clients only know chats by their placeholders
+        let contacts = self.contacts_for_anonymization()?;
+        let chat = match &self.anonymization_salt {
+            Some(salt) => Self::resolve_anonymized_contact(salt, contact_id, &contacts)?,
+            None => contact_id.to_string(),
+        };
+        self.check_access(&chat)?;
 
-        // Translate the message if needed based on conversation language
-        let (text_to_send, was_translated, target_language) =
-            if let Some(translator) = &self.translator {
-                match self.store.get_conversation_language(contact_id, 10) {
-                    Ok(Some(conv_lang)) => {
-                        info!(
-                            "MCP: Conversation language for {} is {}",
-                            contact_id, conv_lang
-                        );
-                        match translator.translate_to(text, &conv_lang).await {
-                            Ok((translated, usage)) => {
-                                // Record usage if there was actual API usage
-                                if usage.input_tokens > 0 {
-                                    if let Err(e) = self.store.record_usage(
-                                        Some(contact_id),
-                                        None,
-                                        &usage,
-                                        "translate_outgoing_mcp",
-                                    ) {
-                                        warn!("Failed to record usage: {}", e);
-                                    }
-                                }
-
-                                if translated != text {
-                                    info!(
-                                        "MCP: Translated outgoing message to {} (cost: ${:.6})",
-                                        conv_lang, usage.cost_usd
-                                    );
-                                    (translated, true, Some(conv_lang))
-                                } else {
-                                    (text.to_string(), false, None)
-                                }
-                            }
-                            Err(e) => {
-                                error!("MCP: Failed to translate outgoing message: {}", e);
-                                (text.to_string(), false, None)
-                            }
-                        }
-                    }
-                    Ok(None) => (text.to_string(), false, None),
-                    Err(e) => {
-                        error!("MCP: Failed to get conversation language: {}", e);
-                        (text.to_string(), false, None)
-                    }
+        let store_error = |e: anyhow::Error| {
+            McpError::internal_error(format!("Failed to get messages: {}", e), None)
+        };
+        let messages = self
+            .store
+            .get_messages_paginated(
+                &chat,
+                Some(limit as u32),
+                before,
+                after,
+                true,
+                MessageFilter::default(),
+            )
+            .map_err(store_error)?;
+        let total_messages = self
+            .store
+            .count_messages(&chat, None, None)
+            .map_err(store_error)?;
+        let in_range = if before.is_some() || after.is_some() {
+            self.store
+                .count_messages(&chat, before, after)
+                .map_err(store_error)?
+        } else {
+            total_messages
+        };
+
+        let anonymizer = self.anonymizer(
+            &contacts,
+            messages.iter().filter_map(|m| m.sender_name.as_deref()),
+        );
+        let truncated = in_range > messages.len() as u64;
+        let messages = if raw_reactions {
+            messages
+                .into_iter()
+                .map(|message| FoldedMessage {
+                    message,
+                    reactions: Vec::new(),
+                    earlier_reaction: None,
+                })
+                .collect()
+        } else {
+            fold_reactions(messages)
+        };
+        let reactor_name = |reaction: &StoredMessage| {
+            if reaction.is_from_me {
+                return "me".to_string();
+            }
+            match (&anonymizer, &reaction.sender_name, &reaction.sender_phone) {
+                (Some(anonymizer), Some(name), _) => anonymizer.person(name),
+                (None, Some(name), _) => name.clone(),
+                (None, None, Some(phone)) => phone.clone(),
+                _ => "someone".to_string(),
+            }
+        };
+
+        let mut attachments = Vec::new();
+        let mut infos = Vec::with_capacity(messages.len());
+        for FoldedMessage {
+            message: m,
+            reactions,
+            earlier_reaction,
+        } in messages
+        {
+            let reactions = reactions
+                .iter()
+                .filter_map(|reaction| {
+                    let (emoji, _) = reaction_of(reaction)?;
+                    Some(format!("{} from {}", emoji, reactor_name(reaction)))
+                })
+                .collect();
+            let mut media = match media_mode {
+                MediaMode::None => None,
+                MediaMode::Metadata | MediaMode::Inline => MediaInfo::of(&m),
+            };
+            if let (MediaMode::Inline, Some(media)) = (media_mode, &mut media) {
+                if media.is_image() {
+                    attachments.extend(self.load_media(media)?);
+                } else {
+                    media.note = Some("Only images are included inline; use get_media".into());
                 }
-            } else {
-                (text.to_string(), false, None)
+            }
+            let info = match &anonymizer {
+                Some(anonymizer) => MessageInfo::from(m).anonymize(anonymizer),
+                None => MessageInfo::from(m),
             };
+            let info = match max_chars {
+                0 => info,
+                max => info.truncate(max),
+            };
+            let info = match earlier_reaction {
+                Some((emoji, target)) => MessageInfo {
+                    text: Some(format!("Reacted {} to an earlier message", emoji)),
+                    translated_text: None,
+                    reacted_to: Some(target),
+                    ..info
+                },
+                None => info,
+            };
+            infos.push(MessageInfo {
+                media,
+                reactions,
+                ..info
+            });
+        }
+        let result = ReadMessagesResult {
+            contact_id: contact_id.to_string(),
+            total_messages,
+            truncated,
+            messages: infos,
+        };
 
-        // Create the send command
-        let cmd = BridgeCommand::Send {
-            request_id: None,
-            to: contact_id.to_string(),
-            text: text_to_send.clone(),
-            reply_to: None,
-            reply_to_sender: None,
+        let json = serde_json::to_string_pretty(&result).map_err(|e| {
+            McpError::internal_error(format!("Failed to serialize messages: {}", e), None)
+        })?;
+
+        let mut content = vec![Content::text(json)];
+        content.extend(attachments);
+        Ok(CallToolResult::success(content))
+    }
+
+    async fn handle_get_media(&self, args: serde_json::Value) -> Result<CallToolResult, McpError> {
+        let media_id = args
+            .get("media_id")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| McpError::invalid_params("media_id is required", None))?;
+        let message = self
+            .store
+            .get_message_by_id(media_id)
+            .map_err(|e| McpError::internal_error(format!("Failed to get message: {}", e), None))?
+            .ok_or_else(|| McpError::invalid_params("Unknown media_id", None))?;
+        self.check_access(&message.contact_id)?;
+        let mut media = MediaInfo::of(&message)
+            .ok_or_else(|| McpError::invalid_params("That message has no media", None))?;
+        let attachment = self.load_media(&mut media)?;
+
+        let json = serde_json::to_string_pretty(&media).map_err(|e| {
+            McpError::internal_error(format!("Failed to serialize media: {}", e), None)
+        })?;
+        let mut content = vec![Content::text(json)];
+        content.extend(attachment);
+        Ok(CallToolResult::success(content))
+    }
+
+    /// Load media to include in a result: images as image content, anything
+    /// else as an embedded resource. Media over MAX_INLINE_MEDIA_BYTES, and
+    /// media that can't be shared, is only described, with a note saying why.
+    fn load_media(&self, media: &mut MediaInfo) -> Result<Option<Content>, McpError> {
+        if self.anonymization_salt.is_some() {
+            media.note = Some("Media isn't shared with anonymized clients".into());
+            return Ok(None);
+        }
+        if media.view_once {
+            media.note = Some("View-once media isn't shared".into());
+            return Ok(None);
+        }
+        if let Some(size) = media.size_bytes.filter(|s| *s > MAX_INLINE_MEDIA_BYTES) {
+            media.too_large(size);
+            return Ok(None);
+        }
+
+        let Some((data, mime_type)) = self
+            .store
+            .get_message_media(&media.media_id)
+            .map_err(|e| McpError::internal_error(format!("Failed to get media: {}", e), None))?
+        else {
+            media.note = Some("The file wasn't downloaded".into());
+            return Ok(None);
         };
+        let size = base64_decoded_len(&data);
+        media.size_bytes = Some(size);
+        if size > MAX_INLINE_MEDIA_BYTES {
+            media.too_large(size);
+            return Ok(None);
+        }
 
-        command_tx.send(cmd).await.map_err(
-            |e: tokio::sync::mpsc::error::SendError<BridgeCommand>| {
-                McpError::internal_error(format!("Failed to send message: {}", e), None)
-            },
-        )?;
+        if media.mime_type.is_none() {
+            media.mime_type = mime_type;
+        }
+        media.included = true;
+        Ok(Some(match &media.mime_type {
+            Some(mime_type) if media.is_image() => Content::image(data, mime_type.as_str()),
+            mime_type => Content::resource(ResourceContents::BlobResourceContents {
+                uri: format!("whatsapp://media/{}", media.media_id),
+                mime_type: mime_type.clone(),
+                blob: data,
+                meta: None,
+            }),
+        }))
+    }
+
+    async fn handle_get_translations(
+        &self,
+        args: serde_json::Value,
+    ) -> Result<CallToolResult, McpError> {
+        let contact_id = args
+            .get("contact_id")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| McpError::invalid_params("contact_id is required", None))?;
+        let limit = integer_arg(&args, "limit")?.unwrap_or(50);
+        if !(1..=MAX_TRANSLATIONS_LIMIT as i64).contains(&limit) {
+            return Err(McpError::invalid_params(
+                format!("limit must be between 1 and {}", MAX_TRANSLATIONS_LIMIT),
+                None,
+            ));
+        }
+        let before = integer_arg(&args, "before")?;
 
-        // Store the sent message locally
-        let timestamp = chrono::Utc::now().timestamp_millis();
-        let temp_message_id = format!("mcp_pending_{}", timestamp);
+        let contacts = self.contacts_for_anonymization()?;
+        let chat = match &self.anonymization_salt {
+            Some(salt) => Self::resolve_anonymized_contact(salt, contact_id, &contacts)?,
+            None => contact_id.to_string(),
+        };
+        self.check_access(&chat)?;
+        let translations = self
+            .store
+            .get_translations(&chat, Some(limit as u32), before)
+            .map_err(|e| {
+                McpError::internal_error(format!("Failed to get translations: {}", e), None)
+            })?;
 
-        // Get contact info for the recipient
-        let contact_info = self.store.get_contact(contact_id).ok().flatten();
-        let contact_name = contact_info.as_ref().and_then(|c| c.name.clone());
-        let contact_phone = contact_info.as_ref().and_then(|c| c.phone.clone());
-        let chat_type = contact_info
-            .as_ref()
-            .and_then(|c| c.contact_type.clone())
-            .unwrap_or_else(|| "private".to_string());
+        let anonymizer = self.anonymizer(&contacts, []);
+        let result = GetTranslationsResult {
+            contact_id: contact_id.to_string(),
+            has_more: translations.len() as i64 >= limit,
+            translations: translations
+                .into_iter()
+                .map(|t| match &anonymizer {
+                    Some(anonymizer) => TranslationInfo::from(t).anonymize(anonymizer),
+                    None => TranslationInfo::from(t),
+                })
+                .collect(),
+        };
 
-        // Build content JSON - store what the user typed (English)
-        let content = json!({
-            "type": "text",
-            "body": text
-        });
+        let json = serde_json::to_string_pretty(&result).map_err(|e| {
+            McpError::internal_error(format!("Failed to serialize translations: {}", e), None)
+        })?;
+
+        Ok(CallToolResult::success(vec![Content::text(json)]))
+    }
+
+    async fn handle_get_pinned_messages(
+        &self,
+        args: serde_json::Value,
+    ) -> Result<CallToolResult, McpError> {
+        let contact_id = args
+            .get("contact_id")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| McpError::invalid_params("contact_id is required", None))?;
+
+        let contacts = self.contacts_for_anonymization()?;
+        let chat = match &self.anonymization_salt {
+            Some(salt) => Self::resolve_anonymized_contact(salt, contact_id, &contacts)?,
+            None => contact_id.to_string(),
+        };
+        self.check_access(&chat)?;
+        let pins = self.store.get_pinned_messages(&chat).map_err(|e| {
+            McpError::internal_error(format!("Failed to get pinned messages: {}", e), None)
+        })?;
 
-        // Create StoredMessage struct
-        let stored_msg = StoredMessage {
-            id: temp_message_id,
+        let anonymizer = self.anonymizer(
+            &contacts,
+            pins.iter()
+                .filter_map(|pin| pin.message.sender_name.as_deref()),
+        );
+        let result = PinnedMessagesResult {
             contact_id: contact_id.to_string(),
-            timestamp,
-            is_from_me: true,
-            is_forwarded: false,
-            sender_name: None,
-            sender_phone: None,
-            contact_name: contact_name.clone(),
-            contact_phone: contact_phone.clone(),
-            chat_type: chat_type.clone(),
-            content_type: "Text".to_string(),
-            content_json: content.to_string(),
-            content: Some(content),
-            original_text: if was_translated {
-                Some(text.to_string())
-            } else {
-                None
-            },
-            translated_text: if was_translated {
-                Some(text_to_send.clone())
-            } else {
-                None
-            },
-            source_language: target_language.clone(),
-            is_translated: was_translated,
+            pinned_messages: pins
+                .into_iter()
+                .map(|pin| PinnedMessageInfo {
+                    pinned_at: pin.pinned_at,
+                    message: match &anonymizer {
+                        Some(anonymizer) => MessageInfo::from(pin.message).anonymize(anonymizer),
+                        None => MessageInfo::from(pin.message),
+                    },
+                })
+                .collect(),
         };
 
-        // Store the message
-        if let Err(e) = self.store.add_message(&stored_msg) {
-            warn!("MCP: Failed to store sent message: {}", e);
+        let json = serde_json::to_string_pretty(&result).map_err(|e| {
+            McpError::internal_error(format!("Failed to serialize pinned messages: {}", e), None)
+        })?;
+
+        Ok(CallToolResult::success(vec![Content::text(json)]))
+    }
+
+    fn send_broadcast_tool() -> Tool {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "contact_ids": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Contact or group IDs (JIDs) to send the message to, at most 50",
+                    "maxItems": MAX_RECIPIENTS
+                },
+                "text": {
+                    "type": "string",
+                    "description": "Message text to send; each chat gets it translated into its own language"
+                },
+                "confirm": {
+                    "type": "boolean",
+                    "description": "Must be true: confirms the user asked for this text to go to every one of these chats"
+                }
+            },
+            "required": ["contact_ids", "text", "confirm"]
+        });
+        Tool::new(
+            "send_broadcast",
+            "Send the same text to several WhatsApp chats, each as a message of its own. Messages go out in the background, one every 1.5 seconds; the result is the batch with each chat's status, all \"pending\" at first. Only use this when the user asked for the message to go to all of these chats.",
+            schema.as_object().unwrap().clone(),
+        )
+    }
+
+    async fn handle_send_broadcast(
+        &self,
+        args: serde_json::Value,
+        usage: &mut ToolUsage,
+    ) -> Result<CallToolResult, McpError> {
+        if args.get("confirm").and_then(|v| v.as_bool()) != Some(true) {
+            return Err(McpError::invalid_params(
+                "confirm must be true to send a broadcast",
+                None,
+            ));
         }
+        let contact_ids: Vec<String> = args
+            .get("contact_ids")
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| McpError::invalid_params("contact_ids is required", None))?
+            .iter()
+            .map(|id| {
+                id.as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| McpError::invalid_params("contact_ids must be strings", None))
+            })
+            .collect::<Result<_, _>>()?;
+        let text = args
+            .get("text")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| McpError::invalid_params("text is required", None))?;
+        for contact_id in &contact_ids {
+            self.check_access(contact_id)?;
+        }
+
+        let broadcasts = self
+            .broadcasts
+            .as_ref()
+            .ok_or_else(|| McpError::internal_error("Broadcasts aren't available here", None))?;
+        let command_tx = self.command_sender()?;
+        self.check_quota(true)?;
 
-        // Update contact's last message time
-        if let Err(e) = self.store.upsert_contact(
-            contact_id,
-            contact_name.as_deref(),
-            contact_phone.as_deref(),
-            Some(&chat_type),
-            timestamp,
+        let batch = broadcasts
+            .start(
+                command_tx.clone(),
+                &contact_ids,
+                text,
+                Some(self.client_id.clone()),
+            )
+            .map_err(|e| McpError::invalid_params(e.description(), None))?;
+        usage.sent = true;
+
+        let json = serde_json::to_string_pretty(&json!({
+            "status": "queued",
+            "batch": batch,
+        }))
+        .map_err(|e| {
+            McpError::internal_error(format!("Failed to serialize result: {}", e), None)
+        })?;
+        Ok(CallToolResult::success(vec![Content::text(json)]))
+    }
+
+    async fn handle_send_message(
+        &self,
+        args: serde_json::Value,
+        usage: &mut ToolUsage,
+    ) -> Result<CallToolResult, McpError> {
+        let contact_id = match (
+            args.get("contact_id").and_then(|v| v.as_str()),
+            args.get("phone").and_then(|v| v.as_str()),
         ) {
-            warn!("MCP: Failed to update contact: {}", e);
+            (Some(contact_id), _) => contact_id.to_string(),
+            (None, Some(phone)) => {
+                // Before the number is looked up and its contact created
+                if let Ok(phone) = normalize_phone(phone, None) {
+                    self.check_access(&format!("{}@s.whatsapp.net", phone))?;
+                }
+                start_new_chat(
+                    &self.store,
+                    self.command_tx.as_ref(),
+                    &self.number_checks,
+                    phone,
+                    None,
+                    true,
+                )
+                .await
+                .map_err(|e| McpError::invalid_params(e.description(), None))?
+            }
+            (None, None) => {
+                return Err(McpError::invalid_params(
+                    "contact_id or phone is required",
+                    None,
+                ))
+            }
+        };
+        let contact_id = contact_id.as_str();
+        self.check_access(contact_id)?;
+        if is_channel_jid(contact_id) {
+            return Err(McpError::invalid_params(
+                "Channels are read-only and can't be sent to",
+                None,
+            ));
+        }
+        let text = args
+            .get("text")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| McpError::invalid_params("text is required", None))?;
+
+        let reply = match args.get("reply_to_message_id").and_then(|v| v.as_str()) {
+            Some(message_id) => Some(self.reply_target(contact_id, message_id)?),
+            None => None,
+        };
+
+        let command_tx = self.command_sender()?;
+
+        // A confirmation token sends exactly what was previously held back
+        let confirmed = match args.get("confirmation_token").and_then(|v| v.as_str()) {
+            Some(token) => Some(
+                self.confirmations
+                    .take(token, contact_id, text)
+                    .await
+                    .ok_or_else(|| {
+                        McpError::invalid_params(
+                            "Invalid or expired confirmation_token (it must be used with the same contact_id and text)",
+                            None,
+                        )
+                    })?,
+            ),
+            None => None,
+        };
+        let is_confirmed = confirmed.is_some();
+        let confirm_language = args
+            .get("confirm_language")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(self.confirm_language);
+
+        // Contacts set to "send as typed", groups by default and private
+        // chats without a clear language skip translation entirely
+        let auto_translate = self
+            .store
+            .get_outgoing_translation(contact_id)
+            .map(|outgoing| outgoing.enabled)
+            .unwrap_or(true);
+
+        let pays_for_translation =
+            self.translator.is_some() && !is_confirmed && (auto_translate || confirm_language);
+        self.check_quota(pays_for_translation)?;
+
+        let message = OutgoingMessage {
+            id: pending_message_id(),
+            contact_id: contact_id.to_string(),
+            text: text.to_string(),
+            reply: reply.as_ref().map(|reply| ReplyTo {
+                message_id: reply.message_id.clone(),
+                sender: reply.sender.clone(),
+                text: Some(reply.preview.clone()),
+            }),
+            origin: format!("mcp:{}", self.client_id),
+            sent_by: Some(self.client_id.clone()),
+        };
+
+        // Translate the message if needed based on conversation language
+        let outgoing = match confirmed {
+            Some(send) => OutgoingText::confirmed(send.text_to_send, send.target_language),
+            None => self.sending.translate(&message).await,
+        };
+        usage.cost_usd += outgoing.cost_usd;
+
+        // Hold the message back if it doesn't match the chat's language
+        if let (Some(translator), false, true) = (&self.translator, is_confirmed, confirm_language)
+        {
+            let (mismatch, detection_cost) = check_language(
+                &self.store,
+                translator,
+                contact_id,
+                &outgoing.text_to_send,
+                outgoing.target_language.as_deref(),
+                "detect_language_mcp",
+            )
+            .await;
+            usage.cost_usd += detection_cost;
+            if let Some(mismatch) = mismatch {
+                let token = self
+                    .confirmations
+                    .insert(PendingSend::new(
+                        contact_id,
+                        text,
+                        &outgoing.text_to_send,
+                        outgoing.target_language.clone(),
+                    ))
+                    .await;
+                let contact_name = self
+                    .store
+                    .get_contact(contact_id)
+                    .ok()
+                    .flatten()
+                    .and_then(|c| c.name);
+                let result = json!({
+                    "status": "confirmation_required",
+                    "contact_id": contact_id,
+                    "contact_name": contact_name,
+                    "text_to_send": outgoing.text_to_send,
+                    "detected_language": mismatch.detected_language,
+                    "chat_language": mismatch.chat_language,
+                    "confirmation_token": token,
+                    "message": "Message NOT sent: it doesn't match the chat's established language. Confirm with the user, then call send_message again with the same contact_id and text plus this confirmation_token.",
+                });
+                let json = serde_json::to_string_pretty(&result).map_err(|e| {
+                    McpError::internal_error(format!("Failed to serialize result: {}", e), None)
+                })?;
+                return Ok(CallToolResult::success(vec![Content::text(json)]));
+            }
         }
 
-        let response = if was_translated {
+        let (_, outgoing) = self
+            .sending
+            .send(command_tx, &message, Some(outgoing))
+            .await
+            .map_err(|e| McpError::internal_error(e.to_string(), None))?;
+        usage.sent = true;
+
+        let response = if let Some(target_language) = &outgoing.target_language {
             format!(
                 "Message sent to {} (translated to {}): \"{}\" -> \"{}\"",
-                contact_id,
-                target_language.unwrap_or_default(),
-                text,
-                text_to_send
+                contact_id, target_language, text, outgoing.text_to_send
             )
         } else {
             format!("Message sent to {}: \"{}\"", contact_id, text)
         };
+        let response = match reply {
+            Some(reply) => format!("{} (in reply to \"{}\")", response, reply.preview),
+            None => response,
+        };
+        let ignore_quiet_hours = args
+            .get("ignore_quiet_hours")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        let warning = if ignore_quiet_hours {
+            None
+        } else {
+            tzinfer::night_send_warning(&self.store, contact_id)
+        };
+        let response = match warning {
+            Some(warning) => format!("{}\nWarning: {}", response, warning),
+            None => response,
+        };
 
         Ok(CallToolResult::success(vec![Content::text(response)]))
     }
+
+    /// Look up the message a send replies to, which must be in the same chat
+    fn reply_target(&self, contact_id: &str, message_id: &str) -> Result<ReplyTarget, McpError> {
+        let lookup = |e: anyhow::Error| {
+            McpError::internal_error(format!("Failed to look up reply target: {}", e), None)
+        };
+        let target = self
+            .store
+            .get_message_by_id(message_id)
+            .map_err(lookup)?
+            .ok_or_else(|| {
+                McpError::invalid_params(
+                    format!("reply_to_message_id {} not found", message_id),
+                    None,
+                )
+            })?;
+        let chat = self.store.resolve_contact_id(contact_id).map_err(lookup)?;
+        if target.contact_id != chat {
+            return Err(McpError::new(
+                REPLY_TARGET_IN_OTHER_CHAT,
+                "reply_to_message_id is a message in another chat",
+                Some(json!({
+                    "reply_to_message_id": message_id,
+                    "contact_id": contact_id,
+                    "message_contact_id": target.contact_id,
+                })),
+            ));
+        }
+
+        // The bridge quotes other people's messages by their number; my own
+        // messages need no sender
+        let sender = if target.is_from_me {
+            None
+        } else if target.chat_type == "group" {
+            target.sender_phone.clone()
+        } else {
+            target
+                .sender_phone
+                .clone()
+                .or_else(|| Some(target.contact_id.clone()))
+        };
+        let preview = MessageStore::generate_reply_preview(&target);
+        Ok(ReplyTarget {
+            message_id: target.id,
+            sender,
+            preview,
+        })
+    }
+
+    fn create_group_tool() -> Tool {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "subject": {
+                    "type": "string",
+                    "description": "Group name (up to 100 characters)"
+                },
+                "participants": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Contact IDs (from list_contacts) or phone numbers with country code of the people to add (1 to 255)",
+                    "minItems": 1,
+                    "maxItems": 255
+                }
+            },
+            "required": ["subject", "participants"]
+        });
+        Tool::new(
+            "create_group",
+            "Create a WhatsApp group with the given people and return its contact ID, which send_message can then be used with.",
+            schema.as_object().unwrap().clone(),
+        )
+    }
+
+    async fn handle_create_group(
+        &self,
+        args: serde_json::Value,
+    ) -> Result<CallToolResult, McpError> {
+        let subject = args
+            .get("subject")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| McpError::invalid_params("subject is required", None))?;
+        let participants: Vec<String> = args
+            .get("participants")
+            .and_then(|v| v.as_array())
+            .ok_or_else(|| McpError::invalid_params("participants is required", None))?
+            .iter()
+            .map(|v| {
+                v.as_str()
+                    .map(String::from)
+                    .ok_or_else(|| McpError::invalid_params("participants must be strings", None))
+            })
+            .collect::<Result<_, _>>()?;
+        // The client may only add people whose chats it may use
+        for jid in resolve_participants(&self.store, &participants, None).map_err(group_error)? {
+            self.check_access(&jid)?;
+        }
+        let command_tx = self.command_sender()?;
+        self.check_quota(false)?;
+
+        let group = create_group(
+            &self.store,
+            Some(command_tx),
+            &self.pending_groups,
+            subject,
+            &participants,
+            None,
+        )
+        .await
+        .map_err(group_error)?;
+
+        let json = serde_json::to_string_pretty(&json!({
+            "status": "created",
+            "contact_id": group.contact_id,
+            "subject": group.subject,
+            "participants": group.participants,
+        }))
+        .map_err(|e| {
+            McpError::internal_error(format!("Failed to serialize result: {}", e), None)
+        })?;
+        Ok(CallToolResult::success(vec![Content::text(json)]))
+    }
+
+    async fn handle_save_note(
+        &self,
+        args: serde_json::Value,
+        usage: &mut ToolUsage,
+    ) -> Result<CallToolResult, McpError> {
+        let text = args.get("text").and_then(|v| v.as_str()).map(String::from);
+        let note = match args.get("image_data").and_then(|v| v.as_str()) {
+            Some(media_data) => Note::Image {
+                media_data: media_data.to_string(),
+                mime_type: args
+                    .get("mime_type")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("image/jpeg")
+                    .to_string(),
+                caption: text.filter(|t| !t.trim().is_empty()),
+            },
+            None => Note::Text(text.unwrap_or_default()),
+        };
+        self.check_quota(false)?;
+
+        let message = save_note(
+            &self.store,
+            self.command_tx.as_ref(),
+            note,
+            &format!("mcp:{}", self.client_id),
+        )
+        .await
+        .map_err(|e| match e {
+            NoteError::EmptyNote => McpError::invalid_params(e.description(), None),
+            _ => McpError::internal_error(e.description(), None),
+        })?;
+        usage.sent = true;
+
+        let json = serde_json::to_string_pretty(&json!({
+            "status": "saved",
+            "contact_id": message.contact_id,
+            "message": MessageInfo::from(message),
+        }))
+        .map_err(|e| {
+            McpError::internal_error(format!("Failed to serialize result: {}", e), None)
+        })?;
+        Ok(CallToolResult::success(vec![Content::text(json)]))
+    }
+
+    /// Refuse the call if the client has used up today's sends, or its
+    /// translation budget when the call would spend some
+    fn check_quota(&self, pays_for_translation: bool) -> Result<(), McpError> {
+        let quota = self
+            .store
+            .get_mcp_quota(&self.client_id)
+            .map_err(|e| McpError::internal_error(format!("Failed to read quota: {}", e), None))?;
+        if quota == McpQuota::default() {
+            return Ok(());
+        }
+
+        let now = chrono::Utc::now();
+        let start_of_day = now.date_naive().and_hms_opt(0, 0, 0).unwrap().and_utc();
+        let (sends, spent) = self
+            .store
+            .get_mcp_client_usage_since(&self.client_id, start_of_day.timestamp())
+            .map_err(|e| McpError::internal_error(format!("Failed to read usage: {}", e), None))?;
+
+        let exceeded = |quota: &str, limit: serde_json::Value, message: String| {
+            warn!("MCP: Client {} is over quota: {}", self.client_id, message);
+            let resets_at = start_of_day + chrono::Duration::days(1);
+            McpError::new(
+                QUOTA_EXCEEDED,
+                message,
+                Some(json!({
+                    "client_id": self.client_id,
+                    "quota": quota,
+                    "limit": limit,
+                    "resets_at": resets_at.to_rfc3339(),
+                })),
+            )
+        };
+        if let Some(limit) = quota.daily_sends {
+            if sends >= limit {
+                return Err(exceeded(
+                    "daily_sends",
+                    json!(limit),
+                    format!("Daily limit of {} sent messages reached", limit),
+                ));
+            }
+        }
+        if let (Some(budget), true) = (quota.daily_translation_usd, pays_for_translation) {
+            if spent >= budget {
+                return Err(exceeded(
+                    "daily_translation_usd",
+                    json!(budget),
+                    format!("Daily translation budget of ${:.2} used up", budget),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Tools that act on WhatsApp (sending messages, creating groups), which
+    /// anonymized clients can't use
+    const SENDING_TOOLS: [&'static str; 4] = [
+        "send_message",
+        "send_broadcast",
+        "save_note",
+        "create_group",
+    ];
+
+    /// Tools that look chats up by their real names, which anonymized
+    /// clients never see
+    const NAME_LOOKUP_TOOLS: [&'static str; 1] = ["resolve_contact"];
+
+    /// Run a tool, recording the call against the client's usage
+    async fn run_tool(
+        &self,
+        name: &str,
+        args: serde_json::Value,
+    ) -> Result<CallToolResult, McpError> {
+        let mut usage = ToolUsage::default();
+        if self.anonymization_salt.is_some()
+            && (Self::SENDING_TOOLS.contains(&name) || Self::NAME_LOOKUP_TOOLS.contains(&name))
+        {
+            return Err(McpError::invalid_params(
+                format!("{} is disabled for anonymized clients", name),
+                None,
+            ));
+        }
+        let result = match name {
+            "list_contacts" => self.handle_list_contacts(args).await,
+            "resolve_contact" => self.handle_resolve_contact(args).await,
+            "read_messages" => self.handle_read_messages(args).await,
+            "get_media" => self.handle_get_media(args).await,
+            "get_translations" => self.handle_get_translations(args).await,
+            "get_pinned_messages" => self.handle_get_pinned_messages(args).await,
+            "send_message" => self.handle_send_message(args, &mut usage).await,
+            "send_broadcast" => self.handle_send_broadcast(args, &mut usage).await,
+            "save_note" => self.handle_save_note(args, &mut usage).await,
+            "create_group" => self.handle_create_group(args).await,
+            _ => {
+                return Err(McpError::invalid_params(
+                    format!("Unknown tool: {}", name),
+                    None,
+                ))
+            }
+        };
+
+        if let Err(e) = self.store.record_mcp_client_usage(
+            &self.client_id,
+            name,
+            usage.sent,
+            usage.cost_usd,
+            chrono::Utc::now().timestamp(),
+        ) {
+            warn!("MCP: Failed to record client usage: {}", e);
+        }
+        result
+    }
 }
 
 impl ServerHandler for WhatsAppMcpServer {
@@ -410,8 +1843,15 @@ impl ServerHandler for WhatsAppMcpServer {
             },
             instructions: Some(
                 "This MCP server provides access to WhatsApp conversations. \
-                 Use list_contacts to see available chats, read_messages to get message history, \
-                 and send_message to send new messages."
+                 Use list_contacts to see available chats, resolve_contact to find one by name, \
+                 read_messages to get message history, \
+                 get_media to see a message's photo or file, \
+                 get_translations to review original/translated pairs, \
+                 get_pinned_messages for messages pinned in a chat, \
+                 send_message to send new messages, \
+                 send_broadcast to send one text to several chats, \
+                 create_group to start a group with some contacts, \
+                 and save_note to keep a note in the user's Saved Messages."
                     .to_string(),
             ),
         }
@@ -422,11 +1862,25 @@ impl ServerHandler for WhatsAppMcpServer {
         _request: Option<PaginatedRequestParam>,
         _context: RequestContext<RoleServer>,
     ) -> Result<ListToolsResult, McpError> {
-        Ok(ListToolsResult::with_all_items(vec![
+        let mut tools = vec![
             Self::list_contacts_tool(),
+            Self::resolve_contact_tool(),
             Self::read_messages_tool(),
+            Self::get_media_tool(),
+            Self::get_translations_tool(),
+            Self::get_pinned_messages_tool(),
             Self::send_message_tool(),
-        ]))
+            Self::send_broadcast_tool(),
+            Self::save_note_tool(),
+            Self::create_group_tool(),
+        ];
+        if self.anonymization_salt.is_some() {
+            tools.retain(|tool| {
+                !Self::SENDING_TOOLS.contains(&tool.name.as_ref())
+                    && !Self::NAME_LOOKUP_TOOLS.contains(&tool.name.as_ref())
+            });
+        }
+        Ok(ListToolsResult::with_all_items(tools))
     }
 
     async fn call_tool(
@@ -439,14 +1893,1057 @@ impl ServerHandler for WhatsAppMcpServer {
             .map(serde_json::Value::Object)
             .unwrap_or(serde_json::Value::Null);
 
-        match request.name.as_ref() {
-            "list_contacts" => self.handle_list_contacts(args).await,
-            "read_messages" => self.handle_read_messages(args).await,
-            "send_message" => self.handle_send_message(args).await,
-            _ => Err(McpError::invalid_params(
-                format!("Unknown tool: {}", request.name),
+        self.run_tool(request.name.as_ref(), args).await
+    }
+}
+
+/// Read an optional integer argument, rejecting values of any other type
+fn integer_arg(args: &serde_json::Value, name: &str) -> Result<Option<i64>, McpError> {
+    match args.get(name) {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(value) => value
+            .as_i64()
+            .map(Some)
+            .ok_or_else(|| McpError::invalid_params(format!("{} must be an integer", name), None)),
+    }
+}
+
+/// The MCP error for a group that couldn't be created
+fn group_error(e: GroupError) -> McpError {
+    match &e {
+        GroupError::InvalidParticipant(participant) => {
+            McpError::invalid_params(format!("{}: {}", e.description(), participant), None)
+        }
+        GroupError::InvalidSubject
+        | GroupError::NoParticipants
+        | GroupError::TooManyParticipants => {
+            McpError::invalid_params(e.description().to_string(), None)
+        }
+        _ => McpError::internal_error(e.description().to_string(), None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_store;
+    use crate::translation::spawn_counting_provider;
+
+    fn result_text(result: &CallToolResult) -> String {
+        result.content[0].as_text().unwrap().text.clone()
+    }
+
+    fn read_only_server(store: MessageStore) -> WhatsAppMcpServer {
+        WhatsAppMcpServer::new(
+            Arc::new(store),
+            None,
+            None,
+            Arc::new(PendingNumberChecks::default()),
+            Arc::new(PendingConfirmations::default()),
+            false,
+            "test-client".to_string(),
+        )
+    }
+
+    fn text_message(id: &str, contact_id: &str, timestamp: i64, body: &str) -> StoredMessage {
+        StoredMessage {
+            sender_name: Some("Ana".to_string()),
+            chat_type: "group".to_string(),
+            ..crate::storage::text_message(id, contact_id, timestamp, body)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_messages_pages_in_sql() {
+        let (store, _dir) = test_store();
+        let chat = "120363000000000000@g.us";
+        store
+            .upsert_contact(chat, Some("Família"), None, Some("group"), 1)
+            .unwrap();
+        let long = "é".repeat(2000);
+        for i in 0..5000 {
+            let body = if i == 4999 { long.as_str() } else { "Bom dia" };
+            store
+                .add_message(&text_message(&format!("m{}", i), chat, i, body))
+                .unwrap();
+        }
+        let server = read_only_server(store);
+        let read = |args: serde_json::Value| {
+            let server = server.clone();
+            async move {
+                let result = server.handle_read_messages(args).await.unwrap();
+                serde_json::from_str::<serde_json::Value>(&result_text(&result)).unwrap()
+            }
+        };
+
+        // The store hands back only the page, not the whole history
+        let rows = server
+            .store
+            .get_messages_paginated(chat, Some(10), None, None, true, MessageFilter::default())
+            .unwrap();
+        assert_eq!(rows.len(), 10);
+        assert_eq!(rows[0].id, "m4990");
+        let page = read(json!({"contact_id": chat, "limit": 10})).await;
+
+        assert_eq!(page["total_messages"], 5000);
+        assert_eq!(page["truncated"], true);
+        let messages = page["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 10);
+        assert_eq!(messages[0]["id"], "m4990");
+        let last = &messages[9];
+        assert_eq!(last["text_truncated"], true);
+        assert_eq!(last["text"].as_str().unwrap().chars().count(), 501);
+        assert!(messages[0].get("text_truncated").is_none());
+
+        let untruncated =
+            read(json!({"contact_id": chat, "limit": 1, "max_chars_per_message": 0})).await;
+        assert_eq!(untruncated["messages"][0]["text"], long.as_str());
+
+        // Paging back and forward
+        let older = read(json!({"contact_id": chat, "limit": 3, "before": 4990})).await;
+        let ids: Vec<&str> = older["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids, ["m4987", "m4988", "m4989"]);
+        let newer = read(json!({"contact_id": chat, "limit": 3, "after": 10})).await;
+        assert_eq!(newer["messages"][0]["id"], "m11");
+        assert_eq!(newer["truncated"], true);
+        let range = read(json!({"contact_id": chat, "after": 10, "before": 14})).await;
+        assert_eq!(range["messages"].as_array().unwrap().len(), 3);
+        assert_eq!(range["truncated"], false);
+    }
+
+    #[tokio::test]
+    async fn test_read_messages_schema() {
+        let tool = WhatsAppMcpServer::read_messages_tool();
+        let properties = &tool.input_schema["properties"];
+        for (name, kind) in [
+            ("contact_id", "string"),
+            ("limit", "integer"),
+            ("before", "integer"),
+            ("after", "integer"),
+            ("max_chars_per_message", "integer"),
+            ("include_media", "string"),
+            ("include_raw_reactions", "boolean"),
+        ] {
+            assert_eq!(properties[name]["type"], kind, "{}", name);
+        }
+        assert_eq!(properties["limit"]["maximum"], MAX_READ_MESSAGES_LIMIT);
+        assert_eq!(tool.input_schema["required"], json!(["contact_id"]));
+
+        let (store, _dir) = test_store();
+        let server = read_only_server(store);
+        let chat = "a@s.whatsapp.net";
+        for args in [
+            json!({}),
+            json!({"contact_id": chat, "limit": 0}),
+            json!({"contact_id": chat, "limit": 201}),
+            json!({"contact_id": chat, "limit": "ten"}),
+            json!({"contact_id": chat, "before": "yesterday"}),
+            json!({"contact_id": chat, "before": 5, "after": 5}),
+            json!({"contact_id": chat, "max_chars_per_message": -1}),
+        ] {
+            assert!(
+                server.handle_read_messages(args.clone()).await.is_err(),
+                "{}",
+                args
+            );
+        }
+        let empty = server
+            .handle_read_messages(json!({"contact_id": chat}))
+            .await
+            .unwrap();
+        let empty: serde_json::Value = serde_json::from_str(&result_text(&empty)).unwrap();
+        assert_eq!(empty["total_messages"], 0);
+        assert_eq!(empty["truncated"], false);
+    }
+
+    #[tokio::test]
+    async fn test_read_messages_folds_reactions() {
+        let (store, _dir) = test_store();
+        let chat = "120363000000000001@g.us";
+        store
+            .upsert_contact(chat, Some("Cena"), None, Some("group"), 1)
+            .unwrap();
+        let from = |mut message: StoredMessage, name: &str, phone: &str| {
+            message.sender_name = Some(name.to_string());
+            message.sender_phone = Some(phone.to_string());
+            message
+        };
+        let reaction = |id: &str, timestamp: i64, emoji: &str, target: &str| {
+            let mut message = text_message(id, chat, timestamp, "");
+            message.content_type = "Reaction".to_string();
+            message.content_json =
+                json!({"type": "reaction", "emoji": emoji, "target_message_id": target})
+                    .to_string();
+            message.original_text = None;
+            message
+        };
+        let mine = |mut message: StoredMessage| {
+            message.is_from_me = true;
+            message.sender_name = None;
+            message
+        };
+        for message in [
+            from(
+                text_message("m1", chat, 1, "Dinner at 8?"),
+                "Maria",
+                "351911",
+            ),
+            mine(text_message("m2", chat, 2, "Sounds good")),
+            from(
+                text_message("m3", chat, 3, "See you there"),
+                "Maria",
+                "351911",
+            ),
+            from(reaction("r1", 4, "👍", "m2"), "Maria", "351911"),
+            mine(reaction("r2", 5, "❤️", "m3")),
+            // Taken back
+            mine(reaction("r3", 6, "", "m3")),
+            from(reaction("r4", 7, "😂", "m1"), "João", "351922"),
+            // Changed her mind
+            from(reaction("r5", 8, "🙏", "m2"), "Maria", "351911"),
+        ] {
+            store.add_message(&message).unwrap();
+        }
+        let server = read_only_server(store);
+        let read = |args: serde_json::Value| {
+            let server = server.clone();
+            async move {
+                let result = server.handle_read_messages(args).await.unwrap();
+                serde_json::from_str::<serde_json::Value>(&result_text(&result)).unwrap()
+            }
+        };
+
+        let page = read(json!({"contact_id": chat})).await;
+        let messages = page["messages"].as_array().unwrap();
+        let ids: Vec<_> = messages.iter().map(|m| m["id"].as_str().unwrap()).collect();
+        assert_eq!(ids, ["m1", "m2", "m3"]);
+        assert_eq!(messages[0]["reactions"], json!(["😂 from João"]));
+        assert_eq!(messages[1]["reactions"], json!(["🙏 from Maria"]));
+        assert!(messages[2].get("reactions").is_none());
+
+        // The first message is before the page
+        let page = read(json!({"contact_id": chat, "after": 1})).await;
+        let messages = page["messages"].as_array().unwrap();
+        let ids: Vec<_> = messages.iter().map(|m| m["id"].as_str().unwrap()).collect();
+        assert_eq!(ids, ["m2", "m3", "r4"]);
+        assert_eq!(messages[2]["text"], "Reacted 😂 to an earlier message");
+        assert_eq!(messages[2]["reacted_to"], "m1");
+        assert_eq!(messages[2]["sender_name"], "João");
+
+        // As stored, for clients that expect it
+        let raw = read(json!({"contact_id": chat, "include_raw_reactions": true})).await;
+        let messages = raw["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 8);
+        assert!(messages.iter().all(|m| m.get("reactions").is_none()));
+        assert!(server
+            .handle_read_messages(json!({"contact_id": chat, "include_raw_reactions": "yes"}))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_chat_access_lists() {
+        use crate::mcp_access::{AccessMode, ChatAccessList};
+
+        let (store, _dir) = test_store();
+        let work = "120363000000000001@g.us";
+        let family = "120363000000000002@g.us";
+        let mum = "447700900001@s.whatsapp.net";
+        for (chat, name, kind) in [
+            (work, "Work", "group"),
+            (family, "Family", "group"),
+            (mum, "Mum", "private"),
+        ] {
+            store
+                .upsert_contact(chat, Some(name), None, Some(kind), 1)
+                .unwrap();
+            store
+                .add_message(&text_message(&format!("{}-1", name), chat, 1, "Hi"))
+                .unwrap();
+        }
+        let server_for = |client_id: &str| {
+            let access = store.get_mcp_access(client_id).unwrap();
+            read_only_server(store.clone()).with_access(access)
+        };
+        let visible = |server: WhatsAppMcpServer| async move {
+            let result = server.handle_list_contacts(json!({})).await.unwrap();
+            let contacts: serde_json::Value = serde_json::from_str(&result_text(&result)).unwrap();
+            let mut names: Vec<String> = contacts
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|c| c["name"].as_str().unwrap().to_string())
+                .collect();
+            names.sort();
+            names
+        };
+
+        // Every chat is open without a list
+        assert_eq!(
+            visible(server_for("assistant")).await,
+            ["Family", "Mum", "Work"]
+        );
+
+        // Every client is kept out of the family chats; one may only see work
+        let deny = ChatAccessList {
+            mode: AccessMode::Deny,
+            chats: vec![family.to_string(), "tag:private".to_string()],
+        };
+        store.set_mcp_access(None, Some(&deny)).unwrap();
+        let allow = ChatAccessList {
+            mode: AccessMode::Allow,
+            chats: vec![work.to_string()],
+        };
+        store
+            .set_mcp_access(Some("assistant"), Some(&allow))
+            .unwrap();
+        assert_eq!(visible(server_for("other")).await, ["Work"]);
+        let assistant = server_for("assistant");
+        assert_eq!(visible(assistant.clone()).await, ["Work"]);
+
+        let err = assistant
+            .handle_read_messages(json!({"contact_id": family}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, CHAT_ACCESS_DENIED);
+        assert!(assistant
+            .handle_read_messages(json!({"contact_id": work}))
+            .await
+            .is_ok());
+        let err = assistant
+            .handle_get_media(json!({"media_id": "Mum-1"}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, CHAT_ACCESS_DENIED);
+        let err = assistant
+            .handle_send_message(
+                json!({"contact_id": mum, "text": "Hi"}),
+                &mut ToolUsage::default(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, CHAT_ACCESS_DENIED);
+
+        // Numbers and group participants are checked before anything is
+        // looked up or created
+        let err = assistant
+            .handle_send_message(
+                json!({"phone": "+44 7911 123456", "text": "Hi"}),
+                &mut ToolUsage::default(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, CHAT_ACCESS_DENIED);
+        assert!(store
+            .get_contact("447911123456@s.whatsapp.net")
+            .unwrap()
+            .is_none());
+        let err = assistant
+            .handle_create_group(json!({"subject": "Plans", "participants": [mum]}))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, CHAT_ACCESS_DENIED);
+
+        let lists = store.get_mcp_access_lists().unwrap();
+        assert_eq!(lists.default, Some(deny));
+        assert_eq!(lists.clients.get("assistant"), Some(&allow));
+
+        // Dropping the lists opens everything again
+        store.set_mcp_access(None, None).unwrap();
+        store.set_mcp_access(Some("assistant"), None).unwrap();
+        assert_eq!(
+            visible(server_for("assistant")).await,
+            ["Family", "Mum", "Work"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_messages_media() {
+        use base64::{engine::general_purpose::STANDARD, Engine};
+
+        let (store, _dir) = test_store();
+        let chat = "34600000000@s.whatsapp.net";
+        store
+            .upsert_contact(chat, Some("Ana"), None, Some("private"), 1)
+            .unwrap();
+        let small = STANDARD.encode([7u8; 1000]);
+        let large = STANDARD.encode(vec![7u8; MAX_INLINE_MEDIA_BYTES as usize + 1]);
+        let media = |id: &str, timestamp: i64, kind: &str, mime: &str, data: &str| {
+            let mut message = text_message(id, chat, timestamp, "");
+            message.original_text = None;
+            message.content_type = kind.to_string();
+            message.content_json = json!({
+                "type": kind.to_lowercase(),
+                "mime_type": mime,
+                "file_size": 0,
+                "media_data": data,
+            })
+            .to_string();
+            message
+        };
+        store
+            .add_message(&text_message("m1", chat, 1, "Mira"))
+            .unwrap();
+        store
+            .add_message(&media("m2", 2, "Image", "image/jpeg", &small))
+            .unwrap();
+        store
+            .add_message(&media("m3", 3, "Image", "image/png", &large))
+            .unwrap();
+        store
+            .add_message(&media("m4", 4, "Audio", "audio/ogg", &small))
+            .unwrap();
+        let server = read_only_server(store);
+        let read = |include_media: &str| {
+            let server = server.clone();
+            let args = json!({"contact_id": chat, "include_media": include_media});
+            async move {
+                let result = server.handle_read_messages(args).await.unwrap();
+                let page: serde_json::Value = serde_json::from_str(&result_text(&result)).unwrap();
+                (page, result.content)
+            }
+        };
+
+        // none: only text
+        let (page, content) = read("none").await;
+        assert_eq!(content.len(), 1);
+        assert!(page["messages"][1].get("media").is_none());
+
+        // metadata: described, nothing attached
+        let (page, content) = read("metadata").await;
+        assert_eq!(content.len(), 1);
+        assert!(page["messages"][0].get("media").is_none());
+        let described = &page["messages"][1]["media"];
+        assert_eq!(described["media_id"], "m2");
+        assert_eq!(described["mime_type"], "image/jpeg");
+        assert!(described.get("included").is_none());
+
+        // inline: the small image is attached, the rest explained
+        let (page, content) = read("inline").await;
+        assert_eq!(content.len(), 2);
+        let image = content[1].as_image().unwrap();
+        assert_eq!(image.data, small);
+        assert_eq!(image.mime_type, "image/jpeg");
+        let messages = page["messages"].as_array().unwrap();
+        assert_eq!(messages[1]["media"]["included"], true);
+        assert_eq!(messages[1]["media"]["size_bytes"], 1000);
+        assert!(messages[2]["media"].get("included").is_none());
+        assert_eq!(
+            messages[2]["media"]["size_bytes"],
+            MAX_INLINE_MEDIA_BYTES + 1
+        );
+        assert!(messages[2]["media"]["note"]
+            .as_str()
+            .unwrap()
+            .starts_with("Too large"));
+        assert!(messages[3]["media"]["note"]
+            .as_str()
+            .unwrap()
+            .contains("get_media"));
+        assert!(server
+            .handle_read_messages(json!({"contact_id": chat, "include_media": "all"}))
+            .await
+            .is_err());
+
+        // get_media: images as image content, other files as resources,
+        // the same size cap
+        let get = |media_id: &str| server.handle_get_media(json!({ "media_id": media_id }));
+        let audio = get("m4").await.unwrap();
+        let resource = audio.content[1].as_resource().unwrap();
+        match &resource.resource {
+            ResourceContents::BlobResourceContents {
+                uri,
+                mime_type,
+                blob,
+                ..
+            } => {
+                assert_eq!(uri, "whatsapp://media/m4");
+                assert_eq!(mime_type.as_deref(), Some("audio/ogg"));
+                assert_eq!(blob, &small);
+            }
+            other => panic!("expected a blob, got {:?}", other),
+        }
+        assert!(get("m2").await.unwrap().content[1].as_image().is_some());
+        let large = get("m3").await.unwrap();
+        assert_eq!(large.content.len(), 1);
+        let described: serde_json::Value = serde_json::from_str(&result_text(&large)).unwrap();
+        assert!(described["note"].as_str().unwrap().starts_with("Too large"));
+        assert!(get("m1").await.is_err());
+        assert!(get("missing").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_send_message_confirms_language_mismatch() {
+        let (store, _dir) = test_store();
+        let contact_id = "33600000000@s.whatsapp.net";
+        store
+            .upsert_contact(contact_id, Some("Madame Leroy"), None, Some("private"), 1)
+            .unwrap();
+
+        let (url, _) =
+            spawn_counting_provider(r#"{"language": "English", "isEnglish": true}"#).await;
+        let translator = TranslationService::new("test-key".to_string(), "English".to_string())
+            .with_api_url(&url);
+        let (tx, mut rx) = mpsc::channel(10);
+        let server = WhatsAppMcpServer::new(
+            Arc::new(store),
+            Some(tx),
+            Some(Arc::new(translator)),
+            Arc::new(PendingNumberChecks::default()),
+            Arc::new(PendingConfirmations::default()),
+            true,
+            "test-client".to_string(),
+        );
+
+        // No established chat language: nothing is sent without confirmation
+        let args = json!({ "contact_id": contact_id, "text": "Hi, the rent has been paid" });
+        let result = server
+            .handle_send_message(args.clone(), &mut ToolUsage::default())
+            .await
+            .unwrap();
+        let result: serde_json::Value = serde_json::from_str(&result_text(&result)).unwrap();
+        assert_eq!(result["status"], "confirmation_required");
+        assert_eq!(result["detected_language"], "English");
+        assert!(result["chat_language"].is_null());
+        assert!(rx.try_recv().is_err());
+
+        // The token only works for the chat it was issued for
+        let token = result["confirmation_token"].as_str().unwrap();
+        let mut confirmed = args.clone();
+        confirmed["confirmation_token"] = json!(token);
+        let mut wrong_chat = confirmed.clone();
+        wrong_chat["contact_id"] = json!("44700000000@s.whatsapp.net");
+        assert!(server
+            .handle_send_message(wrong_chat, &mut ToolUsage::default())
+            .await
+            .is_err());
+        assert!(server
+            .handle_send_message(confirmed.clone(), &mut ToolUsage::default())
+            .await
+            .is_err());
+
+        let result = server
+            .handle_send_message(args, &mut ToolUsage::default())
+            .await
+            .unwrap();
+        let result: serde_json::Value = serde_json::from_str(&result_text(&result)).unwrap();
+        confirmed["confirmation_token"] = result["confirmation_token"].clone();
+        let result = server
+            .handle_send_message(confirmed, &mut ToolUsage::default())
+            .await
+            .unwrap();
+        assert!(result_text(&result).starts_with("Message sent to"));
+        assert!(matches!(rx.try_recv(), Ok(BridgeCommand::Send { .. })));
+
+        // The stored message records which MCP client sent it
+        let sent = server
+            .store
+            .get_messages_paginated(
+                contact_id,
+                None,
                 None,
-            )),
+                None,
+                true,
+                MessageFilter {
+                    origin: Some("mcp"),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].origin.as_deref(), Some("mcp:test-client"));
+    }
+
+    #[tokio::test]
+    async fn test_send_message_reply() {
+        let (store, _dir) = test_store();
+        let store = Arc::new(store);
+        let group = "120363000000000000@g.us";
+        let other = "33600000000@s.whatsapp.net";
+        store
+            .upsert_contact(group, Some("Piso"), None, Some("group"), 1)
+            .unwrap();
+        store
+            .upsert_contact(other, Some("Madame Leroy"), None, Some("private"), 1)
+            .unwrap();
+        let mut question = text_message("q1", group, 1000, "Who's buying milk?");
+        question.sender_phone = Some("34600000000".to_string());
+        store.add_message(&question).unwrap();
+        store
+            .add_message(&text_message("o1", other, 1000, "Bonjour"))
+            .unwrap();
+
+        let (tx, mut rx) = mpsc::channel(10);
+        let server = WhatsAppMcpServer::new(
+            store.clone(),
+            Some(tx),
+            None,
+            Arc::new(PendingNumberChecks::default()),
+            Arc::new(PendingConfirmations::default()),
+            false,
+            "test-client".to_string(),
+        );
+
+        // A reply quotes the message and its sender
+        let result = server
+            .handle_send_message(
+                json!({"contact_id": group, "text": "Me", "reply_to_message_id": "q1"}),
+                &mut ToolUsage::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            result_text(&result),
+            format!(
+                "Message sent to {}: \"Me\" (in reply to \"Who's buying milk?\")",
+                group
+            )
+        );
+        match rx.try_recv().unwrap() {
+            BridgeCommand::Send {
+                reply_to,
+                reply_to_sender,
+                ..
+            } => {
+                assert_eq!(reply_to.as_deref(), Some("q1"));
+                assert_eq!(reply_to_sender.as_deref(), Some("34600000000"));
+            }
+            other => panic!("unexpected command: {:?}", other),
         }
+        let sent = store
+            .get_messages_paginated(
+                group,
+                None,
+                None,
+                None,
+                true,
+                MessageFilter {
+                    origin: Some("mcp"),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let content: serde_json::Value = serde_json::from_str(&sent[0].content_json).unwrap();
+        assert_eq!(content["reply_to"]["message_id"], "q1");
+        assert_eq!(content["reply_to"]["sender"], "34600000000");
+        assert_eq!(content["reply_to"]["text"], "Who's buying milk?");
+
+        // Messages from other chats, or that don't exist, can't be replied to
+        let err = server
+            .handle_send_message(
+                json!({"contact_id": group, "text": "Oui", "reply_to_message_id": "o1"}),
+                &mut ToolUsage::default(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, REPLY_TARGET_IN_OTHER_CHAT);
+        let err = server
+            .handle_send_message(
+                json!({"contact_id": group, "text": "Me", "reply_to_message_id": "missing"}),
+                &mut ToolUsage::default(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::INVALID_PARAMS);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_client_quotas() {
+        let (store, _dir) = test_store();
+        let store = Arc::new(store);
+        let contact_id = "33600000000@s.whatsapp.net";
+        store
+            .upsert_contact(contact_id, Some("Madame Leroy"), None, Some("private"), 1)
+            .unwrap();
+        store
+            .set_mcp_quota(
+                None,
+                &McpQuota {
+                    daily_sends: Some(100),
+                    daily_translation_usd: Some(0.000001),
+                },
+            )
+            .unwrap();
+        store
+            .set_mcp_quota(
+                Some("agent"),
+                &McpQuota {
+                    daily_sends: Some(2),
+                    daily_translation_usd: None,
+                },
+            )
+            .unwrap();
+
+        // Yesterday's sends don't count against today's limit
+        let start_of_day = chrono::Utc::now()
+            .date_naive()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc()
+            .timestamp();
+        for _ in 0..2 {
+            store
+                .record_mcp_client_usage("agent", "send_message", true, 0.0, start_of_day - 1)
+                .unwrap();
+        }
+
+        let (tx, mut rx) = mpsc::channel(10);
+        let agent = WhatsAppMcpServer::new(
+            store.clone(),
+            Some(tx.clone()),
+            None,
+            Arc::new(PendingNumberChecks::default()),
+            Arc::new(PendingConfirmations::default()),
+            false,
+            "agent".to_string(),
+        );
+        let args = json!({ "contact_id": contact_id, "text": "Bonjour" });
+        for _ in 0..2 {
+            agent.run_tool("send_message", args.clone()).await.unwrap();
+            assert!(rx.try_recv().is_ok());
+        }
+        let err = agent
+            .run_tool("send_message", args.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, QUOTA_EXCEEDED);
+        assert_eq!(err.data.unwrap()["quota"], "daily_sends");
+        assert!(rx.try_recv().is_err());
+        // Reading is never limited
+        agent
+            .run_tool("read_messages", json!({ "contact_id": contact_id }))
+            .await
+            .unwrap();
+
+        // The default translation budget applies to clients without an override
+        let (url, hits) =
+            spawn_counting_provider(r#"{"language": "English", "isEnglish": true}"#).await;
+        let translator = TranslationService::new("test-key".to_string(), "English".to_string())
+            .with_api_url(&url);
+        let other = WhatsAppMcpServer::new(
+            store.clone(),
+            Some(tx),
+            Some(Arc::new(translator)),
+            Arc::new(PendingNumberChecks::default()),
+            Arc::new(PendingConfirmations::default()),
+            true,
+            "other".to_string(),
+        );
+        let result = other.run_tool("send_message", args.clone()).await.unwrap();
+        assert!(result_text(&result).contains("confirmation_required"));
+        let err = other.run_tool("send_message", args).await.unwrap_err();
+        assert_eq!(err.data.unwrap()["quota"], "daily_translation_usd");
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        let clients = store.list_mcp_clients(start_of_day).unwrap();
+        let agent = clients.iter().find(|c| c.client_id == "agent").unwrap();
+        assert_eq!(agent.tool_calls, 6);
+        assert_eq!(agent.sends, 4);
+        assert_eq!(agent.sends_today, 2);
+        assert_eq!(agent.remaining_sends, Some(0));
+        // Limits the override leaves unset fall back to the default
+        assert_eq!(agent.remaining_translation_usd, Some(0.000001));
+        let other = clients.iter().find(|c| c.client_id == "other").unwrap();
+        assert_eq!(other.sends_today, 0);
+        assert!(other.translation_cost_today_usd > 0.0);
+        assert_eq!(other.remaining_translation_usd, Some(0.0));
+        assert_eq!(other.remaining_sends, Some(100));
+    }
+
+    #[tokio::test]
+    async fn test_web_and_mcp_sends_are_stored_alike() {
+        use axum::extract::State;
+        use axum::response::IntoResponse;
+
+        let (store, dir) = test_store();
+        let contact_id = "33600000000@s.whatsapp.net";
+        store
+            .upsert_contact(
+                contact_id,
+                Some("Madame Leroy"),
+                Some("33600000000"),
+                Some("private"),
+                1,
+            )
+            .unwrap();
+        store
+            .update_conversation_settings(
+                contact_id,
+                &crate::storage::ConversationSettings {
+                    language_override: Some("French".to_string()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let mut question = text_message("q1", contact_id, 1000, "Tu viens ce soir ?");
+        question.sender_phone = Some("33600000000".to_string());
+        store.add_message(&question).unwrap();
+        store
+            .update_own_profile(|profile| {
+                profile.name = Some("Sam".to_string());
+                profile.phone = Some("447700900000".to_string());
+            })
+            .unwrap();
+
+        let (url, _) = spawn_counting_provider("J'arrive").await;
+        let translator = Arc::new(
+            TranslationService::new("test-key".to_string(), "English".to_string())
+                .with_api_url(&url),
+        );
+        let (tx, mut rx) = mpsc::channel(10);
+
+        // From the web UI
+        let state = crate::web::AppState::new(
+            store.clone(),
+            dir.path().to_path_buf(),
+            dir.path().to_path_buf(),
+            Some(translator.clone()),
+            None,
+            None,
+            crate::send_guard::LanguageGuardConfig::default(),
+        );
+        state.set_command_tx(tx.clone()).await;
+        *state.connected.write().await = true;
+        let req = crate::web::SendMessageRequest {
+            contact_id: contact_id.to_string(),
+            text: "On my way".to_string(),
+            reply_to: Some("q1".to_string()),
+            reply_to_sender: Some("33600000000".to_string()),
+            reply_to_text: Some("Tu viens ce soir ?".to_string()),
+            confirmation_token: None,
+            ignore_quiet_hours: false,
+        };
+        let response =
+            crate::web::send_message(State(state), axum::http::HeaderMap::new(), axum::Json(req))
+                .await
+                .into_response();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+
+        // From an MCP client
+        let server = WhatsAppMcpServer::new(
+            Arc::new(store.clone()),
+            Some(tx),
+            Some(translator),
+            Arc::new(PendingNumberChecks::default()),
+            Arc::new(PendingConfirmations::default()),
+            false,
+            "test-client".to_string(),
+        );
+        server
+            .handle_send_message(
+                json!({"contact_id": contact_id, "text": "On my way", "reply_to_message_id": "q1"}),
+                &mut ToolUsage::default(),
+            )
+            .await
+            .unwrap();
+
+        // The same command went to the bridge both times
+        let first = format!("{:?}", rx.try_recv().unwrap());
+        assert_eq!(first, format!("{:?}", rx.try_recv().unwrap()));
+        assert!(first.contains("J'arrive"));
+
+        // And the stored rows only differ in ID, time (and so position), origin
+        // and who sent them
+        let sent = store
+            .get_messages_paginated(contact_id, None, None, None, true, MessageFilter::default())
+            .unwrap();
+        let mut rows: Vec<serde_json::Value> = sent
+            .into_iter()
+            .filter(|m| m.is_from_me)
+            .map(|m| serde_json::to_value(m).unwrap())
+            .collect();
+        assert_eq!(rows.len(), 2);
+        let mut origins = Vec::new();
+        for row in &mut rows {
+            let row = row.as_object_mut().unwrap();
+            assert!(row
+                .remove("id")
+                .unwrap()
+                .as_str()
+                .unwrap()
+                .starts_with("pending_"));
+            row.remove("timestamp");
+            row.remove("sortKey");
+            origins.push((row.remove("origin").unwrap(), row.remove("sentBy").unwrap()));
+        }
+        origins.sort_by_key(|(origin, _)| origin.to_string());
+        assert_eq!(
+            origins,
+            [
+                (json!("mcp:test-client"), json!("test-client")),
+                (json!("web"), serde_json::Value::Null)
+            ]
+        );
+        assert_eq!(rows[0], rows[1]);
+        assert_eq!(rows[0]["senderName"], "Sam");
+        assert_eq!(rows[0]["isTranslated"], true);
+        assert_eq!(rows[0]["translatedText"], "J'arrive");
+        assert_eq!(rows[0]["content"]["reply_to"]["text"], "Tu viens ce soir ?");
+    }
+
+    #[tokio::test]
+    async fn test_stdio_handshake_and_read_tool() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let (store, _dir) = test_store();
+        let chat = "34600000000@s.whatsapp.net";
+        store
+            .upsert_contact(chat, Some("Ana"), None, Some("private"), 1)
+            .unwrap();
+        store
+            .add_message(&text_message("m1", chat, 1, "Hola"))
+            .unwrap();
+
+        let server = read_only_server(store).with_sends_disabled("No running web instance found");
+        let (client, server_io) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(server.serve_io(server_io));
+        let (reader, mut writer) = tokio::io::split(client);
+        let mut lines = BufReader::new(reader).lines();
+
+        let initialize = json!({
+            "jsonrpc": "2.0", "id": 1, "method": "initialize",
+            "params": {
+                "protocolVersion": "2025-03-26",
+                "capabilities": {},
+                "clientInfo": {"name": "test", "version": "1"}
+            }
+        });
+        let initialized = json!({"jsonrpc": "2.0", "method": "notifications/initialized"});
+        let list_contacts = json!({
+            "jsonrpc": "2.0", "id": 2, "method": "tools/call",
+            "params": {"name": "list_contacts", "arguments": {}}
+        });
+        let send_message = json!({
+            "jsonrpc": "2.0", "id": 3, "method": "tools/call",
+            "params": {"name": "send_message", "arguments": {"contact_id": chat, "text": "Hi"}}
+        });
+
+        let mut replies = Vec::new();
+        for request in [initialize, initialized, list_contacts, send_message] {
+            let expects_reply = request.get("id").is_some();
+            writer
+                .write_all(format!("{}\n", request).as_bytes())
+                .await
+                .unwrap();
+            if expects_reply {
+                let line =
+                    tokio::time::timeout(std::time::Duration::from_secs(5), lines.next_line())
+                        .await
+                        .unwrap()
+                        .unwrap()
+                        .unwrap();
+                replies.push(serde_json::from_str::<serde_json::Value>(&line).unwrap());
+            }
+        }
+
+        assert_eq!(replies[0]["id"], 1);
+        assert_eq!(
+            replies[0]["result"]["serverInfo"]["name"],
+            "whatsapp-translator"
+        );
+        assert!(replies[0]["result"]["capabilities"]["tools"].is_object());
+
+        assert_eq!(replies[1]["id"], 2);
+        let contacts = replies[1]["result"]["content"][0]["text"].as_str().unwrap();
+        assert!(contacts.contains(chat));
+        assert!(contacts.contains("Ana"));
+
+        // Without a bridge, sends fail saying why
+        assert_eq!(replies[2]["id"], 3);
+        assert!(replies[2]["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("Sending is disabled: No running web instance found"));
+    }
+
+    #[tokio::test]
+    async fn test_anonymized_client_reads_placeholders_only() {
+        let (store, _dir) = test_store();
+        let chat = "34612345678@s.whatsapp.net";
+        store
+            .upsert_contact(
+                chat,
+                Some("Maria Lopez"),
+                Some("34612345678"),
+                Some("private"),
+                1,
+            )
+            .unwrap();
+        store
+            .add_message(&text_message(
+                "m1",
+                chat,
+                1,
+                "Tell Maria to call Ana on +34 612 345 678 or ana@example.com",
+            ))
+            .unwrap();
+        store
+            .set_mcp_client_anonymized("test-client", true)
+            .unwrap();
+        let salt = store
+            .get_mcp_anonymization_salt("test-client")
+            .unwrap()
+            .unwrap();
+        let anonymizer = Anonymizer::new(&salt);
+        let server = read_only_server(store).with_anonymization(salt);
+
+        let result = server.run_tool("list_contacts", json!({})).await.unwrap();
+        let text = result_text(&result);
+        assert!(
+            !text.contains("34612345678") && !text.contains("Maria"),
+            "{}",
+            text
+        );
+        let contacts: serde_json::Value = serde_json::from_str(&text).unwrap();
+        let placeholder = contacts[0]["id"].as_str().unwrap().to_string();
+        assert_eq!(placeholder, anonymizer.jid(chat));
+        assert_eq!(contacts[0]["name"], anonymizer.person("Maria Lopez"));
+
+        // Chats are read by their placeholder, never their real ID
+        let result = server
+            .run_tool("read_messages", json!({"contact_id": placeholder}))
+            .await
+            .unwrap();
+        let page: serde_json::Value = serde_json::from_str(&result_text(&result)).unwrap();
+        let message = &page["messages"][0];
+        assert_eq!(page["contact_id"], placeholder.as_str());
+        assert_eq!(message["sender_name"], anonymizer.person("Ana"));
+        let text = message["text"].as_str().unwrap();
+        assert!(text.starts_with(&format!(
+            "Tell {} to call {} on +XXX...",
+            anonymizer.person("Maria Lopez"),
+            anonymizer.person("Ana")
+        )));
+        assert!(text.ends_with(" or [email]"), "{}", text);
+        assert!(server
+            .run_tool("read_messages", json!({"contact_id": chat}))
+            .await
+            .is_err());
+
+        // Nothing can be sent
+        for tool in ["send_message", "save_note"] {
+            let err = server
+                .run_tool(tool, json!({"contact_id": placeholder, "text": "hi"}))
+                .await
+                .unwrap_err();
+            assert!(err.message.contains("disabled for anonymized clients"));
+        }
+
+        // Lifting the flag drops the salt, so a new one gives new placeholders
+        server
+            .store
+            .set_mcp_client_anonymized("test-client", false)
+            .unwrap();
+        assert!(server
+            .store
+            .get_mcp_anonymization_salt("test-client")
+            .unwrap()
+            .is_none());
     }
 }
@@ -0,0 +1,160 @@
+//! Which chats MCP clients may see.
+//!
+//! A list of chats either allows only the chats on it or denies them. One
+//! list can be set for every client and overridden per OAuth client (the
+//! stdio client is "stdio"). Entries are contact JIDs, or `tag:<type>` for
+//! every chat of a type, e.g. `tag:group`. Without a list every chat is
+//! open.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::storage::StoredContact;
+
+/// Prefix of entries naming every chat of a type
+pub const TAG_PREFIX: &str = "tag:";
+
+/// Whether the chats on a list are the only ones open or the ones closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessMode {
+    Allow,
+    Deny,
+}
+
+/// Chats an MCP client may or may not see
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatAccessList {
+    pub mode: AccessMode,
+    /// Contact JIDs and `tag:<type>` entries
+    #[serde(default)]
+    pub chats: Vec<String>,
+}
+
+impl ChatAccessList {
+    /// Trim the entries and drop blank and repeated ones. Errors with the
+    /// first entry that's neither a JID nor a tag.
+    pub fn normalized(self) -> Result<Self, String> {
+        let mut chats: Vec<String> = Vec::new();
+        for entry in self.chats.iter().map(|entry| entry.trim()) {
+            if entry.is_empty() || chats.iter().any(|chat| chat == entry) {
+                continue;
+            }
+            let valid = match entry.strip_prefix(TAG_PREFIX) {
+                Some(tag) => !tag.is_empty(),
+                None => entry.contains('@'),
+            };
+            if !valid {
+                return Err(format!(
+                    "\"{}\" is neither a chat ID nor a tag (tag:<type>)",
+                    entry
+                ));
+            }
+            chats.push(entry.to_string());
+        }
+        Ok(Self { chats, ..self })
+    }
+
+    /// Whether an entry names the chat
+    fn names(&self, contact_id: &str, contact_type: Option<&str>) -> bool {
+        self.chats
+            .iter()
+            .any(|entry| match entry.strip_prefix(TAG_PREFIX) {
+                Some(tag) => contact_type == Some(tag),
+                None => entry == contact_id,
+            })
+    }
+
+    /// Whether a client with this list may see the chat
+    pub fn allows(&self, contact_id: &str, contact_type: Option<&str>) -> bool {
+        self.names(contact_id, contact_type) == (self.mode == AccessMode::Allow)
+    }
+
+    /// Whether a client with this list may see the contact's chat
+    pub fn allows_contact(&self, contact: &StoredContact) -> bool {
+        self.allows(&contact.id, contact.contact_type.as_deref())
+    }
+}
+
+/// Every access list that's set
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpAccessLists {
+    /// For clients without their own
+    pub default: Option<ChatAccessList>,
+    /// By client ID
+    pub clients: BTreeMap<String, ChatAccessList>,
+}
+
+/// What an access list opens, in words, for the OAuth approval page
+pub fn describe(list: Option<&ChatAccessList>, contacts: &[StoredContact]) -> String {
+    let Some(list) = list else {
+        return "All chats".to_string();
+    };
+    let mut names: Vec<String> = list
+        .chats
+        .iter()
+        .map(|entry| match entry.strip_prefix(TAG_PREFIX) {
+            Some(tag) => format!("every {} chat", tag),
+            None => contacts
+                .iter()
+                .find(|contact| contact.id == *entry)
+                .and_then(|contact| contact.name.clone())
+                .unwrap_or_else(|| entry.clone()),
+        })
+        .collect();
+    names.sort();
+    match (list.mode, names.is_empty()) {
+        (AccessMode::Allow, true) => "No chats".to_string(),
+        (AccessMode::Allow, false) => format!("Only {}", names.join(", ")),
+        (AccessMode::Deny, true) => "All chats".to_string(),
+        (AccessMode::Deny, false) => format!("All chats except {}", names.join(", ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_access_list_modes() {
+        let work = "120363000000000001@g.us";
+        let family = "120363000000000002@g.us";
+        let mum = "447700900001@s.whatsapp.net";
+
+        let allow: ChatAccessList =
+            serde_json::from_value(serde_json::json!({"mode": "allow", "chats": [work]})).unwrap();
+        assert!(allow.allows(work, Some("group")));
+        assert!(!allow.allows(family, Some("group")));
+        assert!(!allow.allows(mum, Some("private")));
+
+        let deny = ChatAccessList {
+            mode: AccessMode::Deny,
+            chats: vec![family.to_string(), "tag:private".to_string()],
+        };
+        assert!(deny.allows(work, Some("group")));
+        assert!(!deny.allows(family, Some("group")));
+        assert!(!deny.allows(mum, Some("private")));
+        // Chats of no known type only match by ID
+        assert!(deny.allows("unknown@s.whatsapp.net", None));
+
+        let tagged = ChatAccessList {
+            mode: AccessMode::Allow,
+            chats: vec![" tag:group ".into(), "".into(), "tag:group".into()],
+        }
+        .normalized()
+        .unwrap();
+        assert_eq!(tagged.chats, ["tag:group"]);
+        assert!(tagged.allows(family, Some("group")));
+        assert!(!tagged.allows(mum, Some("private")));
+
+        for entry in ["Family", "tag:"] {
+            let list = ChatAccessList {
+                mode: AccessMode::Deny,
+                chats: vec![entry.to_string()],
+            };
+            assert!(list.normalized().is_err(), "{}", entry);
+        }
+    }
+}
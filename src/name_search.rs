@@ -0,0 +1,196 @@
+//! Finding contacts by name.
+//!
+//! Names are compared folded: compatibility-decomposed (NFKD), without
+//! accents, emoji or punctuation, lowercased, and with Cyrillic and Greek
+//! letters spelled out in Latin ones, so "dmitri" finds "Дмитрий" and
+//! "familia" finds "Família ❤️". The folded name is stored with the contact
+//! (`name_normalized`); the name itself is kept as it is for display.
+
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// How well a folded name matches a folded query, best first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NameMatch {
+    /// The name starts with the query
+    Prefix,
+    /// One of the name's words starts with the query
+    WordPrefix,
+    /// The query is somewhere inside the name
+    Substring,
+}
+
+/// Latin spelling of a lowercase Cyrillic or Greek letter (accents already
+/// stripped), None for any other character
+fn transliterate(c: char) -> Option<&'static str> {
+    Some(match c {
+        // Cyrillic (Russian, Ukrainian, Belarusian, Bulgarian, Serbian)
+        'а' => "a",
+        'б' => "b",
+        'в' => "v",
+        'г' | 'ґ' => "g",
+        'д' => "d",
+        'е' | 'э' => "e",
+        'є' => "ye",
+        'ж' => "zh",
+        'з' => "z",
+        'и' | 'і' => "i",
+        'ј' => "j",
+        'к' => "k",
+        'л' => "l",
+        'љ' => "lj",
+        'м' => "m",
+        'н' => "n",
+        'њ' => "nj",
+        'о' => "o",
+        'п' => "p",
+        'р' => "r",
+        'с' => "s",
+        'т' => "t",
+        'ћ' => "c",
+        'у' | 'ў' => "u",
+        'ф' => "f",
+        'х' => "kh",
+        'ц' => "ts",
+        'ч' => "ch",
+        'џ' => "dz",
+        'ш' => "sh",
+        'щ' => "shch",
+        'ъ' | 'ь' => "",
+        'ы' => "y",
+        'ю' => "yu",
+        'я' => "ya",
+        // Greek
+        'α' => "a",
+        'β' => "v",
+        'γ' => "g",
+        'δ' => "d",
+        'ε' => "e",
+        'ζ' => "z",
+        'η' | 'ι' => "i",
+        'θ' => "th",
+        'κ' => "k",
+        'λ' => "l",
+        'μ' => "m",
+        'ν' => "n",
+        'ξ' => "x",
+        'ο' | 'ω' => "o",
+        'π' => "p",
+        'ρ' => "r",
+        'σ' | 'ς' => "s",
+        'τ' => "t",
+        'υ' => "y",
+        'φ' => "f",
+        'χ' => "ch",
+        'ψ' => "ps",
+        _ => return None,
+    })
+}
+
+/// Fold a name (or a query) for matching: words of lowercase letters and
+/// digits separated by single spaces
+pub fn normalize_name(name: &str) -> String {
+    let mut folded = String::with_capacity(name.len());
+    let mut pending_space = false;
+    for c in name
+        .nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+    {
+        if !c.is_alphanumeric() {
+            pending_space = true;
+            continue;
+        }
+        if pending_space && !folded.is_empty() {
+            folded.push(' ');
+        }
+        pending_space = false;
+        match transliterate(c) {
+            Some(latin) => folded.push_str(latin),
+            None => folded.push(c),
+        }
+    }
+    folded
+}
+
+/// How a folded name matches a folded query, None if it doesn't
+pub fn match_name(normalized_name: &str, normalized_query: &str) -> Option<NameMatch> {
+    if normalized_query.is_empty() {
+        return None;
+    }
+    if normalized_name.starts_with(normalized_query) {
+        Some(NameMatch::Prefix)
+    } else if normalized_name
+        .split(' ')
+        .any(|word| word.starts_with(normalized_query))
+    {
+        Some(NameMatch::WordPrefix)
+    } else if normalized_name.contains(normalized_query) {
+        Some(NameMatch::Substring)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_multilingual_names() {
+        let cases = [
+            ("Família ❤️", "familia"),
+            ("🎉 Fête de l'école 🎉", "fete de l ecole"),
+            ("Дмитрий", "dmitrii"),
+            ("Ольга Петрова", "olga petrova"),
+            ("Юлія", "yuliya"),
+            ("Γιώργος", "giorgos"),
+            ("ΝΙΚΟΣ", "nikos"),
+            ("Zoë & Jürgen", "zoe jurgen"),
+            ("Ｆｕｌｌｗｉｄｔｈ", "fullwidth"),
+            ("ﬁnance team", "finance team"),
+            ("Ana (work) 2", "ana work 2"),
+            ("山田太郎", "山田太郎"),
+            ("❤️🔥", ""),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(normalize_name(name), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_match_name() {
+        assert_eq!(
+            match_name(&normalize_name("Familia ❤️"), &normalize_name("Famil")),
+            Some(NameMatch::Prefix)
+        );
+        assert_eq!(
+            match_name(&normalize_name("Дмитрий Иванов"), &normalize_name("Dmitri")),
+            Some(NameMatch::Prefix)
+        );
+        assert_eq!(
+            match_name(&normalize_name("Дмитрий Иванов"), &normalize_name("ivanov")),
+            Some(NameMatch::WordPrefix)
+        );
+        assert_eq!(
+            match_name(
+                &normalize_name("Γιώργος Παπαδόπουλος"),
+                &normalize_name("pado")
+            ),
+            Some(NameMatch::Substring)
+        );
+        assert_eq!(
+            match_name(&normalize_name("🏠 Casa Núñez"), &normalize_name("NUÑEZ")),
+            Some(NameMatch::WordPrefix)
+        );
+        assert_eq!(
+            match_name(&normalize_name("Familia ❤️"), &normalize_name("Familie")),
+            None
+        );
+        assert_eq!(
+            match_name(&normalize_name("Familia ❤️"), &normalize_name("❤️")),
+            None
+        );
+        assert!(NameMatch::Prefix < NameMatch::WordPrefix);
+    }
+}
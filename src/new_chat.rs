@@ -0,0 +1,256 @@
+//! Starting conversations with phone numbers that aren't existing contacts.
+//!
+//! Normalizes a raw phone number, optionally asks the bridge whether it is
+//! registered on WhatsApp, and creates the contact row so the regular send
+//! path can be used.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, RwLock};
+use tracing::{error, info};
+
+use crate::bridge::BridgeCommand;
+use crate::storage::MessageStore;
+
+/// How long to wait for the bridge to answer a number check
+const NUMBER_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Errors returned when starting a new chat
+#[derive(Debug, Clone, Serialize)]
+pub enum NewChatError {
+    InvalidNumber,
+    InvalidRegion,
+    NotOnWhatsApp,
+    BridgeUnavailable,
+    CheckFailed,
+    CheckTimeout,
+    StorageError,
+}
+
+impl NewChatError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NewChatError::InvalidNumber => "invalid_number",
+            NewChatError::InvalidRegion => "invalid_region",
+            NewChatError::NotOnWhatsApp => "not_on_whatsapp",
+            NewChatError::BridgeUnavailable => "bridge_unavailable",
+            NewChatError::CheckFailed => "check_failed",
+            NewChatError::CheckTimeout => "check_timeout",
+            NewChatError::StorageError => "storage_error",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            NewChatError::InvalidNumber => "The phone number is not valid",
+            NewChatError::InvalidRegion => "The region must be a two-letter country code",
+            NewChatError::NotOnWhatsApp => "The phone number is not registered on WhatsApp",
+            NewChatError::BridgeUnavailable => "WhatsApp is not connected",
+            NewChatError::CheckFailed => "WhatsApp could not check the number",
+            NewChatError::CheckTimeout => "Timed out waiting for WhatsApp to check the number",
+            NewChatError::StorageError => "Failed to save the contact",
+        }
+    }
+}
+
+/// Result of a bridge number check
+#[derive(Debug, Clone)]
+pub struct NumberCheck {
+    pub jid: Option<String>,
+    pub is_on_whatsapp: bool,
+    pub error: Option<String>,
+}
+
+/// Number checks waiting for a response from the bridge
+#[derive(Default)]
+pub struct PendingNumberChecks {
+    requests: RwLock<HashMap<i32, oneshot::Sender<NumberCheck>>>,
+    next_id: AtomicI32,
+}
+
+impl PendingNumberChecks {
+    /// Handle a number check response from the bridge
+    pub async fn complete(&self, request_id: i32, result: NumberCheck) {
+        let mut pending = self.requests.write().await;
+        if let Some(tx) = pending.remove(&request_id) {
+            let _ = tx.send(result);
+        }
+    }
+
+    /// Ask the bridge whether a number is on WhatsApp and wait for the answer
+    async fn check(
+        &self,
+        command_tx: &mpsc::Sender<BridgeCommand>,
+        phone: &str,
+    ) -> Result<NumberCheck, NewChatError> {
+        let request_id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let (tx, rx) = oneshot::channel();
+        self.requests.write().await.insert(request_id, tx);
+
+        let cmd = BridgeCommand::CheckNumber {
+            request_id,
+            phone: phone.to_string(),
+        };
+
+        if let Err(e) = command_tx.send(cmd).await {
+            error!("Failed to send number check: {}", e);
+            self.requests.write().await.remove(&request_id);
+            return Err(NewChatError::BridgeUnavailable);
+        }
+
+        match tokio::time::timeout(NUMBER_CHECK_TIMEOUT, rx).await {
+            Ok(Ok(result)) => Ok(result),
+            _ => {
+                self.requests.write().await.remove(&request_id);
+                Err(NewChatError::CheckTimeout)
+            }
+        }
+    }
+}
+
+/// Normalize a phone number to international digits (E.164 without the `+`).
+///
+/// Numbers without a leading `+` are parsed using `region` (e.g. "GB") when
+/// given, otherwise they are assumed to already include the country code.
+pub fn normalize_phone(raw: &str, region: Option<&str>) -> Result<String, NewChatError> {
+    let trimmed = raw.trim();
+    if trimmed.is_empty() {
+        return Err(NewChatError::InvalidNumber);
+    }
+
+    let country = match region.map(str::trim).filter(|r| !r.is_empty()) {
+        Some(r) => Some(
+            r.to_uppercase()
+                .parse::<phonenumber::country::Id>()
+                .map_err(|_| NewChatError::InvalidRegion)?,
+        ),
+        None => None,
+    };
+
+    let input = if trimmed.starts_with('+') || country.is_some() {
+        trimmed.to_string()
+    } else {
+        format!("+{}", trimmed.trim_start_matches("00"))
+    };
+
+    let number = phonenumber::parse(country, &input).map_err(|_| NewChatError::InvalidNumber)?;
+    if !phonenumber::is_valid(&number) {
+        return Err(NewChatError::InvalidNumber);
+    }
+
+    Ok(number
+        .format()
+        .mode(phonenumber::Mode::E164)
+        .to_string()
+        .trim_start_matches('+')
+        .to_string())
+}
+
+/// Create (or find) the contact for a phone number and return its contact ID.
+///
+/// When `verify` is set the bridge is asked whether the number is on WhatsApp
+/// first, and the JID it reports is used.
+pub async fn start_new_chat(
+    store: &MessageStore,
+    command_tx: Option<&mpsc::Sender<BridgeCommand>>,
+    checks: &PendingNumberChecks,
+    phone: &str,
+    region: Option<&str>,
+    verify: bool,
+) -> Result<String, NewChatError> {
+    let phone = normalize_phone(phone, region)?;
+    let mut jid = format!("{}@s.whatsapp.net", phone);
+
+    if verify {
+        let command_tx = command_tx.ok_or(NewChatError::BridgeUnavailable)?;
+        let result = checks.check(command_tx, &phone).await?;
+
+        if let Some(e) = result.error {
+            error!("Number check for {} failed: {}", phone, e);
+            return Err(NewChatError::CheckFailed);
+        }
+        if !result.is_on_whatsapp {
+            return Err(NewChatError::NotOnWhatsApp);
+        }
+        if let Some(checked_jid) = result.jid.filter(|j| !j.is_empty()) {
+            jid = checked_jid;
+        }
+    }
+
+    let now = chrono::Utc::now().timestamp_millis();
+    store
+        .upsert_contact(&jid, None, Some(&phone), Some("private"), now)
+        .map_err(|e| {
+            error!("Failed to create contact {}: {}", jid, e);
+            NewChatError::StorageError
+        })?;
+
+    let contact_id = store.resolve_contact_id(&jid).map_err(|e| {
+        error!("Failed to resolve contact {}: {}", jid, e);
+        NewChatError::StorageError
+    })?;
+
+    info!("Started new chat with {}", contact_id);
+    Ok(contact_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_store;
+
+    #[test]
+    fn test_normalize_phone() {
+        assert_eq!(
+            normalize_phone("+44 7911 123456", None).unwrap(),
+            "447911123456"
+        );
+        assert_eq!(
+            normalize_phone("07911 123456", Some("gb")).unwrap(),
+            "447911123456"
+        );
+        assert_eq!(
+            normalize_phone("0044 7911 123456", None).unwrap(),
+            "447911123456"
+        );
+        assert_eq!(normalize_phone("14155552671", None).unwrap(), "14155552671");
+    }
+
+    #[test]
+    fn test_normalize_phone_errors() {
+        assert!(matches!(
+            normalize_phone("", None),
+            Err(NewChatError::InvalidNumber)
+        ));
+        assert!(matches!(
+            normalize_phone("+44 123", None),
+            Err(NewChatError::InvalidNumber)
+        ));
+        assert!(matches!(
+            normalize_phone("07911 123456", Some("Narnia")),
+            Err(NewChatError::InvalidRegion)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_start_new_chat_without_verification() {
+        let (store, _dir) = test_store();
+        let checks = PendingNumberChecks::default();
+
+        let id = start_new_chat(&store, None, &checks, "+44 7911 123456", None, false)
+            .await
+            .unwrap();
+        assert_eq!(id, "447911123456@s.whatsapp.net");
+
+        let contact = store.get_contact(&id).unwrap().unwrap();
+        assert_eq!(contact.phone.as_deref(), Some("447911123456"));
+
+        // Verification needs a connected bridge
+        assert!(matches!(
+            start_new_chat(&store, None, &checks, "+44 7911 123456", None, true).await,
+            Err(NewChatError::BridgeUnavailable)
+        ));
+    }
+}
@@ -0,0 +1,164 @@
+//! Notes saved to "Saved Messages", the chat with my own number.
+//!
+//! A note is sent to the self-chat with the regular send commands and stored
+//! straight away, so the web API and MCP can hand back the stored message.
+
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+use crate::bridge::BridgeCommand;
+use crate::storage::{MessageStore, StoredMessage};
+
+/// What to save as a note
+pub enum Note {
+    Text(String),
+    Image {
+        /// Base64 encoded image data
+        media_data: String,
+        mime_type: String,
+        caption: Option<String>,
+    },
+}
+
+/// Errors returned when saving a note
+#[derive(Debug, Clone, Serialize)]
+pub enum NoteError {
+    EmptyNote,
+    NoSelfChat,
+    BridgeUnavailable,
+    StorageError,
+}
+
+impl NoteError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NoteError::EmptyNote => "empty_note",
+            NoteError::NoSelfChat => "no_self_chat",
+            NoteError::BridgeUnavailable => "bridge_unavailable",
+            NoteError::StorageError => "storage_error",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            NoteError::EmptyNote => "The note needs text or an image",
+            NoteError::NoSelfChat => "Saved Messages is available once WhatsApp has connected",
+            NoteError::BridgeUnavailable => "WhatsApp is not connected",
+            NoteError::StorageError => "Failed to save the note",
+        }
+    }
+}
+
+/// Send a note to my Saved Messages chat and store it
+pub async fn save_note(
+    store: &MessageStore,
+    command_tx: Option<&mpsc::Sender<BridgeCommand>>,
+    note: Note,
+    origin: &str,
+) -> Result<StoredMessage, NoteError> {
+    let empty = match &note {
+        Note::Text(text) => text.trim().is_empty(),
+        Note::Image { media_data, .. } => media_data.is_empty(),
+    };
+    if empty {
+        return Err(NoteError::EmptyNote);
+    }
+    let self_chat = store
+        .get_self_chat_id()
+        .map_err(|e| {
+            error!("Failed to find Saved Messages: {}", e);
+            NoteError::StorageError
+        })?
+        .ok_or(NoteError::NoSelfChat)?;
+    let command_tx = command_tx.ok_or(NoteError::BridgeUnavailable)?;
+
+    let (cmd, content_type, content) = match note {
+        Note::Text(text) => (
+            BridgeCommand::Send {
+                request_id: None,
+                to: self_chat.clone(),
+                text: text.clone(),
+                reply_to: None,
+                reply_to_sender: None,
+            },
+            "Text",
+            serde_json::json!({"type": "text", "body": text}),
+        ),
+        Note::Image {
+            media_data,
+            mime_type,
+            caption,
+        } => (
+            BridgeCommand::SendImage {
+                request_id: None,
+                to: self_chat.clone(),
+                media_data: media_data.clone(),
+                mime_type: mime_type.clone(),
+                caption: caption.clone(),
+                reply_to: None,
+                reply_to_sender: None,
+            },
+            "Image",
+            serde_json::json!({
+                "type": "image",
+                "mime_type": mime_type,
+                "caption": caption,
+                "media_data": media_data
+            }),
+        ),
+    };
+    command_tx.send(cmd).await.map_err(|e| {
+        error!("Failed to send note: {}", e);
+        NoteError::BridgeUnavailable
+    })?;
+
+    let me = store.get_own_profile().unwrap_or_default();
+    let timestamp = chrono::Utc::now().timestamp_millis();
+    let mut stored_msg = StoredMessage {
+        id: format!("pending_note_{}", timestamp),
+        contact_id: self_chat,
+        timestamp,
+        is_from_me: true,
+        is_forwarded: false,
+        sender_name: me.name,
+        sender_phone: me.phone.clone(),
+        contact_name: None,
+        contact_phone: me.phone,
+        chat_type: "self".to_string(),
+        content_type: content_type.to_string(),
+        content_json: content.to_string(),
+        content: Some(content),
+        original_text: None,
+        translated_text: None,
+        source_language: None,
+        is_translated: false,
+        origin: Some(origin.to_string()),
+        sent_by: None,
+        mentioned_jids: Vec::new(),
+        mentions_me: false,
+        vocabulary: None,
+        audio: None,
+        sort_key: None,
+        triage: None,
+        translation_status: None,
+    };
+    crate::thumbnail::attach(&mut stored_msg).await;
+
+    stored_msg.sort_key = store
+        .upsert_contact(
+            &stored_msg.contact_id,
+            None,
+            stored_msg.contact_phone.as_deref(),
+            Some("self"),
+            timestamp,
+        )
+        .and_then(|_| store.add_message(&stored_msg))
+        .map_err(|e| {
+            error!("Failed to store note: {}", e);
+            NoteError::StorageError
+        })?;
+
+    info!("Saved a note ({})", origin);
+    Ok(stored_msg)
+}
@@ -0,0 +1,47 @@
+//! Hashing of the web interface password.
+//!
+//! The password is kept as a salted argon2 hash in PHC string format
+//! (`$argon2id$v=19$...`), so the parameters travel with the hash.
+
+use anyhow::{anyhow, Result};
+use argon2::password_hash::{
+    rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+};
+use argon2::Argon2;
+
+/// Hash a password with a fresh random salt
+pub fn hash(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| anyhow!("Failed to hash password: {}", e))
+}
+
+/// Whether a password matches a hash from `hash` (false if the hash is malformed)
+pub fn verify(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|parsed| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_and_verify() {
+        let first = hash("correct horse").unwrap();
+        assert!(first.starts_with("$argon2id$"));
+        assert!(verify("correct horse", &first));
+        assert!(!verify("correct horse ", &first));
+        assert!(!verify("correct horse", "correct horse"));
+
+        // Salted: the same password hashes differently each time
+        let second = hash("correct horse").unwrap();
+        assert_ne!(first, second);
+        assert!(verify("correct horse", &second));
+    }
+}
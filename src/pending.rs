@@ -0,0 +1,230 @@
+//! Correlation of bridge commands with the responses they trigger.
+//!
+//! Commands expecting an answer carry a request_id, and the bridge event that
+//! echoes it completes the waiter registered under that id. A bridge that
+//! never answers fails the wait with [`CommandError::Timeout`] instead of
+//! leaving it hanging, and a sweeper drops entries whose waiter went away
+//! without cleaning up (e.g. an HTTP request that was cancelled).
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+use tracing::debug;
+
+use crate::bridge::BridgeCommand;
+
+/// How long to wait for the bridge to answer a command
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often requests past their deadline are swept
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Why a command got no answer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandError {
+    /// There is no bridge to send the command to
+    NotConnected,
+    /// The bridge took the command but didn't answer in time
+    Timeout,
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CommandError::NotConnected => "Bridge not connected",
+            CommandError::Timeout => "Bridge did not respond",
+        })
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// The bridge's answer to a send command
+#[derive(Debug, Clone)]
+pub struct SendOutcome {
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+struct Pending<T> {
+    tx: oneshot::Sender<T>,
+    deadline: Instant,
+}
+
+/// Requests waiting for a response of type `T` (request_id -> waiter)
+pub struct PendingRequests<T> {
+    requests: Mutex<HashMap<i32, Pending<T>>>,
+}
+
+impl<T> Default for PendingRequests<T> {
+    fn default() -> Self {
+        Self {
+            requests: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T> PendingRequests<T> {
+    /// Wait for the response to `request_id`, for up to `timeout`
+    pub fn register(&self, request_id: i32, timeout: Duration) -> oneshot::Receiver<T> {
+        let (tx, rx) = oneshot::channel();
+        let deadline = Instant::now() + timeout;
+        self.requests
+            .lock()
+            .unwrap()
+            .insert(request_id, Pending { tx, deadline });
+        rx
+    }
+
+    /// Deliver a response. Returns false if nothing is waiting for it, e.g.
+    /// because it arrived after the request timed out.
+    pub fn complete(&self, request_id: i32, response: T) -> bool {
+        let pending = self.requests.lock().unwrap().remove(&request_id);
+        match pending {
+            Some(pending) => pending.tx.send(response).is_ok(),
+            None => {
+                debug!("No request waiting for response {}", request_id);
+                false
+            }
+        }
+    }
+
+    /// Stop waiting for a request
+    pub fn cancel(&self, request_id: i32) {
+        self.requests.lock().unwrap().remove(&request_id);
+    }
+
+    /// Wait on a registered request, cleaning up if it times out
+    pub async fn wait(
+        &self,
+        request_id: i32,
+        rx: oneshot::Receiver<T>,
+        timeout: Duration,
+    ) -> Result<T, CommandError> {
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(response)) => Ok(response),
+            // Timed out, or swept from under us
+            _ => {
+                self.cancel(request_id);
+                Err(CommandError::Timeout)
+            }
+        }
+    }
+
+    /// Send `command` (which carries `request_id`) and wait for its response
+    pub async fn send_and_wait(
+        &self,
+        command_tx: Option<&mpsc::Sender<BridgeCommand>>,
+        request_id: i32,
+        command: BridgeCommand,
+        timeout: Duration,
+    ) -> Result<T, CommandError> {
+        let command_tx = command_tx.ok_or(CommandError::NotConnected)?;
+        let rx = self.register(request_id, timeout);
+        if command_tx.send(command).await.is_err() {
+            self.cancel(request_id);
+            return Err(CommandError::NotConnected);
+        }
+        self.wait(request_id, rx, timeout).await
+    }
+
+    /// Drop requests past their deadline or no longer waited on. Returns
+    /// how many were dropped.
+    pub fn sweep(&self) -> usize {
+        let now = Instant::now();
+        let mut requests = self.requests.lock().unwrap();
+        let before = requests.len();
+        requests.retain(|_, pending| pending.deadline > now && !pending.tx.is_closed());
+        before - requests.len()
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn picture_request(request_id: i32) -> BridgeCommand {
+        BridgeCommand::GetProfilePicture {
+            request_id,
+            to: "447911123456@s.whatsapp.net".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_and_wait() {
+        let pending = std::sync::Arc::new(PendingRequests::<Option<String>>::default());
+        let (tx, mut rx) = mpsc::channel(4);
+
+        // No bridge is a different failure from one that doesn't answer
+        assert_eq!(
+            pending
+                .send_and_wait(None, 1, picture_request(1), DEFAULT_COMMAND_TIMEOUT)
+                .await,
+            Err(CommandError::NotConnected)
+        );
+
+        // Success: the response with the command's request_id is returned
+        let responder = pending.clone();
+        let answer = tokio::spawn(async move {
+            let Some(BridgeCommand::GetProfilePicture { request_id, .. }) = rx.recv().await else {
+                panic!("expected the command to reach the bridge");
+            };
+            assert!(responder.complete(request_id, Some("https://pps/1".to_string())));
+            rx
+        });
+        assert_eq!(
+            pending
+                .send_and_wait(Some(&tx), 2, picture_request(2), DEFAULT_COMMAND_TIMEOUT)
+                .await,
+            Ok(Some("https://pps/1".to_string()))
+        );
+        let mut rx = answer.await.unwrap();
+
+        // Timeout: the entry is cleaned up and a late response goes nowhere
+        assert_eq!(
+            pending
+                .send_and_wait(Some(&tx), 3, picture_request(3), Duration::from_millis(20))
+                .await,
+            Err(CommandError::Timeout)
+        );
+        assert!(rx.try_recv().is_ok());
+        assert_eq!(pending.len(), 0);
+        assert!(!pending.complete(3, None));
+
+        // A closed command channel means the bridge has gone
+        drop(rx);
+        assert_eq!(
+            pending
+                .send_and_wait(Some(&tx), 4, picture_request(4), DEFAULT_COMMAND_TIMEOUT)
+                .await,
+            Err(CommandError::NotConnected)
+        );
+        assert_eq!(pending.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_sweep_drops_expired_and_abandoned() {
+        let pending = PendingRequests::<SendOutcome>::default();
+        let _expired = pending.register(1, Duration::ZERO);
+        drop(pending.register(2, DEFAULT_COMMAND_TIMEOUT));
+        let _waiting = pending.register(3, DEFAULT_COMMAND_TIMEOUT);
+
+        assert_eq!(pending.sweep(), 2);
+        assert_eq!(pending.len(), 1);
+        assert!(pending.complete(
+            3,
+            SendOutcome {
+                success: true,
+                error: None,
+            }
+        ));
+    }
+}
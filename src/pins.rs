@@ -0,0 +1,170 @@
+//! Messages pinned to the top of their chat.
+//!
+//! Besides pinning whole chats, single messages (an address, a door code)
+//! can be pinned within a chat. Pins are kept only here, not on WhatsApp,
+//! and go when their message is deleted or the chat is cleared.
+
+use serde::Serialize;
+use tracing::error;
+
+use crate::storage::{MessageStore, PinnedMessage};
+
+/// Most messages pinned in one chat
+pub const MAX_PINS_PER_CHAT: usize = 10;
+
+/// Errors returned when pinning or unpinning a message
+#[derive(Debug, Clone, Serialize)]
+pub enum PinError {
+    MessageNotFound,
+    OtherChat,
+    LimitReached,
+    NotPinned,
+    StorageError,
+}
+
+impl PinError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PinError::MessageNotFound => "message_not_found",
+            PinError::OtherChat => "message_in_other_chat",
+            PinError::LimitReached => "pin_limit_reached",
+            PinError::NotPinned => "not_pinned",
+            PinError::StorageError => "storage_error",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            PinError::MessageNotFound => "The message doesn't exist",
+            PinError::OtherChat => "The message is in another chat",
+            PinError::LimitReached => "A chat can have at most 10 pinned messages",
+            PinError::NotPinned => "The message isn't pinned",
+            PinError::StorageError => "Failed to update the pinned messages",
+        }
+    }
+}
+
+fn storage_error(e: anyhow::Error) -> PinError {
+    error!("Failed to update pinned messages: {}", e);
+    PinError::StorageError
+}
+
+/// Pin a message in `contact_id`, the chat it must be in. Returns the chat's
+/// pinned messages.
+pub fn pin_message(
+    store: &MessageStore,
+    contact_id: &str,
+    message_id: &str,
+    pinned_by: &str,
+) -> Result<Vec<PinnedMessage>, PinError> {
+    let message = store
+        .get_message_by_id(message_id)
+        .map_err(storage_error)?
+        .ok_or(PinError::MessageNotFound)?;
+    let chat = store
+        .resolve_contact_id(contact_id)
+        .map_err(storage_error)?;
+    if message.contact_id != chat {
+        return Err(PinError::OtherChat);
+    }
+
+    if !store
+        .pin_message(&chat, message_id, pinned_by, MAX_PINS_PER_CHAT)
+        .map_err(storage_error)?
+    {
+        return Err(PinError::LimitReached);
+    }
+    store.get_pinned_messages(&chat).map_err(storage_error)
+}
+
+/// Unpin a message. Returns its chat and the chat's remaining pins.
+pub fn unpin_message(
+    store: &MessageStore,
+    message_id: &str,
+) -> Result<(String, Vec<PinnedMessage>), PinError> {
+    let chat = store
+        .unpin_message(message_id)
+        .map_err(storage_error)?
+        .ok_or(PinError::NotPinned)?;
+    let pins = store.get_pinned_messages(&chat).map_err(storage_error)?;
+    Ok((chat, pins))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{test_store, text_message};
+
+    #[test]
+    fn test_pin_limit_per_chat() {
+        let (store, _dir) = test_store();
+        let chat = "447700900001@s.whatsapp.net";
+        store
+            .upsert_contact(chat, Some("Landlord"), None, Some("private"), 1)
+            .unwrap();
+        for i in 0..=MAX_PINS_PER_CHAT {
+            store
+                .add_message(&text_message(
+                    &format!("m{}", i),
+                    chat,
+                    1000 + i as i64,
+                    "Door code 4711",
+                ))
+                .unwrap();
+        }
+
+        for i in 0..MAX_PINS_PER_CHAT {
+            pin_message(&store, chat, &format!("m{}", i), "web").unwrap();
+        }
+        // Pinning again is fine; an eleventh pin isn't
+        assert_eq!(pin_message(&store, chat, "m0", "web").unwrap().len(), 10);
+        assert!(matches!(
+            pin_message(&store, chat, "m10", "web"),
+            Err(PinError::LimitReached)
+        ));
+
+        let (unpinned_from, pins) = unpin_message(&store, "m3").unwrap();
+        assert_eq!(unpinned_from, chat);
+        assert_eq!(pins.len(), 9);
+        let pins = pin_message(&store, chat, "m10", "mcp:cli").unwrap();
+        assert_eq!(pins[0].message.id, "m10");
+        assert_eq!(pins[0].pinned_by, "mcp:cli");
+        assert!(matches!(
+            unpin_message(&store, "m3"),
+            Err(PinError::NotPinned)
+        ));
+    }
+
+    #[test]
+    fn test_pin_rejects_other_chat() {
+        let (store, _dir) = test_store();
+        let chat = "447700900001@s.whatsapp.net";
+        let other = "447700900002@s.whatsapp.net";
+        for contact in [chat, other] {
+            store
+                .upsert_contact(contact, None, None, Some("private"), 1)
+                .unwrap();
+        }
+        store
+            .add_message(&text_message("a1", chat, 1000, "Door code 4711"))
+            .unwrap();
+        store
+            .add_message(&text_message("b1", other, 1000, "Door code 4711"))
+            .unwrap();
+
+        assert!(matches!(
+            pin_message(&store, chat, "b1", "web"),
+            Err(PinError::OtherChat)
+        ));
+        assert!(matches!(
+            pin_message(&store, chat, "missing", "web"),
+            Err(PinError::MessageNotFound)
+        ));
+        assert!(store.get_pinned_messages(chat).unwrap().is_empty());
+
+        // Deleting a pinned message takes its pin with it
+        pin_message(&store, chat, "a1", "web").unwrap();
+        store.delete_message(chat, "a1").unwrap();
+        assert!(store.get_pinned_messages(chat).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,263 @@
+//! Online / last-seen presence subscriptions.
+//!
+//! WhatsApp only sends a contact's presence after subscribing to it, and a
+//! subscription lasts until the bridge reconnects. Pinned contacts and chats
+//! opened in the last hour are subscribed, up to `MAX_SUBSCRIPTIONS` at once;
+//! beyond that the least recently opened chat is dropped, pinned ones last.
+//! Contacts who hide their presence just never report being online or a
+//! last-seen time, which is shown as unknown.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Most contacts subscribed to at once
+pub const MAX_SUBSCRIPTIONS: usize = 25;
+
+/// How long an opened (unpinned) chat stays subscribed
+const RECENT_CHAT_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// A contact's latest presence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Presence {
+    pub is_online: bool,
+    /// When they were last online (ms), if they share it
+    pub last_seen: Option<i64>,
+}
+
+/// Subscription counts for `/api/status`
+#[derive(Debug, Clone, Serialize)]
+pub struct PresenceSummary {
+    pub subscribed: usize,
+    pub online: usize,
+    pub limit: usize,
+}
+
+/// Result of watching a contact
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Watch {
+    /// The contact wasn't subscribed yet, so the bridge must be asked to
+    pub subscribe: bool,
+    /// Contact dropped to stay under the limit
+    pub evicted: Option<String>,
+}
+
+struct Subscription {
+    jid: String,
+    pinned: bool,
+    last_used: Instant,
+    presence: Option<Presence>,
+}
+
+/// Contacts whose presence is followed, least recently used first
+#[derive(Default)]
+pub struct PresenceSubscriptions {
+    subscriptions: Mutex<VecDeque<Subscription>>,
+}
+
+/// Whether presence can be subscribed to for a JID (people, not groups)
+pub fn is_person_jid(jid: &str) -> bool {
+    jid.ends_with("@s.whatsapp.net") || jid.ends_with("@lid")
+}
+
+impl PresenceSubscriptions {
+    /// Follow a contact's presence because their chat was opened or pinned
+    pub fn watch(&self, jid: &str, pinned: bool) -> Watch {
+        self.watch_at(jid, pinned, Instant::now())
+    }
+
+    fn watch_at(&self, jid: &str, pinned: bool, now: Instant) -> Watch {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        Self::expire(&mut subscriptions, now);
+
+        if let Some(pos) = subscriptions.iter().position(|s| s.jid == jid) {
+            let mut existing = subscriptions.remove(pos).unwrap();
+            existing.pinned = pinned;
+            existing.last_used = now;
+            subscriptions.push_back(existing);
+            return Watch::default();
+        }
+
+        let mut evicted = None;
+        if subscriptions.len() >= MAX_SUBSCRIPTIONS {
+            let victim = subscriptions.iter().position(|s| !s.pinned).unwrap_or(0);
+            evicted = subscriptions.remove(victim).map(|s| s.jid);
+        }
+        subscriptions.push_back(Subscription {
+            jid: jid.to_string(),
+            pinned,
+            last_used: now,
+            presence: None,
+        });
+        Watch {
+            subscribe: true,
+            evicted,
+        }
+    }
+
+    /// Stop treating a contact as pinned; it expires like an opened chat
+    pub fn unpin(&self, jid: &str) {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        if let Some(subscription) = subscriptions.iter_mut().find(|s| s.jid == jid) {
+            subscription.pinned = false;
+        }
+    }
+
+    /// Drop unpinned chats that haven't been opened recently
+    fn expire(subscriptions: &mut VecDeque<Subscription>, now: Instant) {
+        subscriptions.retain(|s| s.pinned || now.duration_since(s.last_used) < RECENT_CHAT_TTL);
+    }
+
+    /// Record a presence update. Returns false if the contact isn't followed.
+    pub fn update(&self, jid: &str, presence: Presence) -> bool {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        match subscriptions.iter_mut().find(|s| s.jid == jid) {
+            Some(subscription) => {
+                // Keep the last-seen time when an update leaves it out
+                let last_seen = presence
+                    .last_seen
+                    .or(subscription.presence.and_then(|p| p.last_seen));
+                subscription.presence = Some(Presence {
+                    is_online: presence.is_online,
+                    last_seen,
+                });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// A followed contact's latest presence, if any has arrived
+    pub fn get(&self, jid: &str) -> Option<Presence> {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        Self::expire(&mut subscriptions, Instant::now());
+        subscriptions
+            .iter()
+            .find(|s| s.jid == jid)
+            .and_then(|s| s.presence)
+    }
+
+    /// Every followed contact, e.g. to resubscribe after reconnecting
+    pub fn jids(&self) -> Vec<String> {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        Self::expire(&mut subscriptions, Instant::now());
+        subscriptions.iter().map(|s| s.jid.clone()).collect()
+    }
+
+    pub fn summary(&self) -> PresenceSummary {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        Self::expire(&mut subscriptions, Instant::now());
+        PresenceSummary {
+            subscribed: subscriptions.len(),
+            online: subscriptions
+                .iter()
+                .filter(|s| s.presence.is_some_and(|p| p.is_online))
+                .count(),
+            limit: MAX_SUBSCRIPTIONS,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jid(n: usize) -> String {
+        format!("{}@s.whatsapp.net", n)
+    }
+
+    #[test]
+    fn test_least_recently_opened_chat_is_evicted() {
+        let subs = PresenceSubscriptions::default();
+        let start = Instant::now();
+        for n in 0..MAX_SUBSCRIPTIONS {
+            let watch = subs.watch_at(&jid(n), n == 0, start);
+            assert_eq!(
+                watch,
+                Watch {
+                    subscribe: true,
+                    evicted: None
+                }
+            );
+        }
+
+        // Reopening a chat makes it the most recent without resubscribing
+        assert_eq!(subs.watch_at(&jid(1), false, start), Watch::default());
+
+        // The oldest unpinned chat goes first, skipping the pinned one
+        let watch = subs.watch_at(&jid(100), false, start);
+        assert!(watch.subscribe);
+        assert_eq!(watch.evicted, Some(jid(2)));
+        let watch = subs.watch_at(&jid(101), false, start);
+        assert_eq!(watch.evicted, Some(jid(3)));
+
+        let jids = subs.jids();
+        assert_eq!(jids.len(), MAX_SUBSCRIPTIONS);
+        assert!(jids.contains(&jid(0)));
+        assert!(jids.contains(&jid(1)));
+        assert!(!jids.contains(&jid(2)));
+    }
+
+    #[test]
+    fn test_pinned_contacts_evicted_when_all_pinned() {
+        let subs = PresenceSubscriptions::default();
+        let start = Instant::now();
+        for n in 0..MAX_SUBSCRIPTIONS {
+            subs.watch_at(&jid(n), true, start);
+        }
+        let watch = subs.watch_at(&jid(100), true, start);
+        assert_eq!(watch.evicted, Some(jid(0)));
+    }
+
+    #[test]
+    fn test_opened_chats_expire() {
+        let subs = PresenceSubscriptions::default();
+        let start = Instant::now();
+        subs.watch_at(&jid(0), true, start);
+        subs.watch_at(&jid(1), false, start);
+
+        let later = start + RECENT_CHAT_TTL + Duration::from_secs(1);
+        // Only the pinned contact is still followed, so watching again resubscribes
+        assert!(subs.watch_at(&jid(1), false, later).subscribe);
+        assert!(!subs.watch_at(&jid(0), true, later).subscribe);
+    }
+
+    #[test]
+    fn test_update_keeps_last_seen() {
+        let subs = PresenceSubscriptions::default();
+        assert!(!subs.update(
+            &jid(0),
+            Presence {
+                is_online: true,
+                last_seen: None
+            }
+        ));
+
+        subs.watch(&jid(0), false);
+        subs.update(
+            &jid(0),
+            Presence {
+                is_online: false,
+                last_seen: Some(1_000),
+            },
+        );
+        // Coming online (or a hidden last-seen) doesn't forget the last time
+        subs.update(
+            &jid(0),
+            Presence {
+                is_online: true,
+                last_seen: None,
+            },
+        );
+        assert_eq!(
+            subs.get(&jid(0)),
+            Some(Presence {
+                is_online: true,
+                last_seen: Some(1_000)
+            })
+        );
+        assert_eq!(subs.summary().online, 1);
+    }
+}
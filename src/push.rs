@@ -0,0 +1,547 @@
+//! Web Push notifications.
+//!
+//! Browsers subscribe with their vendor's push service and hand us the
+//! endpoint and their keys. Each live incoming message that would count as
+//! unread, unless its chat is muted or it arrives during the quiet hours, is
+//! sent to every subscription: encrypted for the browser (RFC 8291,
+//! `aes128gcm`) and signed with our VAPID key (RFC 8292), which is generated
+//! once and kept in the settings. Subscriptions the push service no longer
+//! knows (404/410) are deleted.
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use reqwest::{Client, StatusCode};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use ring::{aead, agreement, hkdf};
+use serde::Serialize;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+use crate::error_registry::{ErrorCategory, ErrorRegistry};
+use crate::storage::{MessageStore, PushSubscription, StoredMessage};
+
+/// Contact for push services, sent in the VAPID signature
+pub const DEFAULT_SUBJECT: &str = "mailto:whatsapp-translator@localhost";
+
+/// How long a push service keeps a notification for an offline browser
+const TTL_SECS: u32 = 24 * 60 * 60;
+
+/// How long a VAPID signature is valid (push services accept up to 24h)
+const VAPID_LIFETIME_SECS: i64 = 12 * 60 * 60;
+
+/// Record size announced in the encrypted payload's header
+const RECORD_SIZE: u32 = 4096;
+
+/// Request timeout for push services
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Characters of the message text in a notification
+const PREVIEW_CHARS: usize = 200;
+
+/// HKDF output length
+struct Len(usize);
+
+impl hkdf::KeyType for Len {
+    fn len(&self) -> usize {
+        self.0
+    }
+}
+
+fn hkdf_expand(prk: &hkdf::Prk, info: &[&[u8]], len: usize) -> Result<Vec<u8>> {
+    let mut out = vec![0; len];
+    prk.expand(info, Len(len))
+        .and_then(|okm| okm.fill(&mut out))
+        .map_err(|_| anyhow!("HKDF expansion failed"))?;
+    Ok(out)
+}
+
+/// The key push notifications are signed with
+pub struct VapidKey {
+    key_pair: EcdsaKeyPair,
+    rng: SystemRandom,
+}
+
+impl VapidKey {
+    /// A new key, with its PKCS#8 encoding (base64) to store
+    fn generate() -> Result<(Self, String)> {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+            .map_err(|_| anyhow!("Failed to generate a VAPID key"))?;
+        let encoded = STANDARD.encode(pkcs8.as_ref());
+        Ok((Self::from_pkcs8(&encoded)?, encoded))
+    }
+
+    fn from_pkcs8(encoded: &str) -> Result<Self> {
+        let pkcs8 = STANDARD
+            .decode(encoded)
+            .context("Invalid saved VAPID key")?;
+        let rng = SystemRandom::new();
+        let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8, &rng)
+            .map_err(|e| anyhow!("Invalid saved VAPID key: {}", e))?;
+        Ok(Self { key_pair, rng })
+    }
+
+    /// The public key browsers subscribe with (`applicationServerKey`)
+    pub fn public_key(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.key_pair.public_key().as_ref())
+    }
+
+    /// `Authorization` header value for a push to `endpoint`
+    fn authorization(&self, endpoint: &str, subject: &str, now: i64) -> Result<String> {
+        let url = reqwest::Url::parse(endpoint).context("Invalid push endpoint")?;
+        let audience = url.origin().ascii_serialization();
+        let header = URL_SAFE_NO_PAD.encode(r#"{"typ":"JWT","alg":"ES256"}"#);
+        let claims = URL_SAFE_NO_PAD.encode(
+            serde_json::json!({
+                "aud": audience,
+                "exp": now + VAPID_LIFETIME_SECS,
+                "sub": subject,
+            })
+            .to_string(),
+        );
+        let signing_input = format!("{}.{}", header, claims);
+        let signature = self
+            .key_pair
+            .sign(&self.rng, signing_input.as_bytes())
+            .map_err(|_| anyhow!("Failed to sign VAPID token"))?;
+        Ok(format!(
+            "vapid t={}.{}, k={}",
+            signing_input,
+            URL_SAFE_NO_PAD.encode(signature.as_ref()),
+            self.public_key()
+        ))
+    }
+}
+
+/// Decode a base64url key, padded or not
+fn decode_key(key: &str) -> Option<Vec<u8>> {
+    URL_SAFE_NO_PAD.decode(key.trim_end_matches('=')).ok()
+}
+
+/// Whether a subscription has an HTTPS endpoint and keys of the right size
+pub fn valid_subscription(subscription: &PushSubscription) -> bool {
+    reqwest::Url::parse(&subscription.endpoint).is_ok_and(|url| url.scheme() == "https")
+        && decode_key(&subscription.p256dh).is_some_and(|key| key.len() == 65)
+        && decode_key(&subscription.auth).is_some_and(|key| key.len() == 16)
+}
+
+/// Encrypt a payload for a browser's keys (RFC 8291, `aes128gcm`): a single
+/// record after a header carrying the salt and our one-off public key
+pub fn encrypt(payload: &[u8], p256dh: &str, auth: &str) -> Result<Vec<u8>> {
+    let (Some(ua_public), Some(auth_secret)) = (decode_key(p256dh), decode_key(auth)) else {
+        bail!("Invalid subscription keys");
+    };
+    if ua_public.len() != 65 || auth_secret.len() != 16 {
+        bail!("Invalid subscription keys");
+    }
+    // Leave room for the padding delimiter and the tag in one record
+    if payload.len() + 1 + aead::AES_128_GCM.tag_len() > RECORD_SIZE as usize - 86 {
+        bail!("Push payload too large");
+    }
+
+    let rng = SystemRandom::new();
+    let as_private = agreement::EphemeralPrivateKey::generate(&agreement::ECDH_P256, &rng)
+        .map_err(|_| anyhow!("Failed to generate a key"))?;
+    let as_public = as_private
+        .compute_public_key()
+        .map_err(|_| anyhow!("Failed to compute a public key"))?;
+    let as_public = as_public.as_ref().to_vec();
+    let ecdh_secret = agreement::agree_ephemeral(
+        as_private,
+        &agreement::UnparsedPublicKey::new(&agreement::ECDH_P256, &ua_public),
+        |secret| secret.to_vec(),
+    )
+    .map_err(|_| anyhow!("Invalid p256dh key"))?;
+
+    let mut salt = [0u8; 16];
+    rng.fill(&mut salt)
+        .map_err(|_| anyhow!("Failed to generate a salt"))?;
+
+    // The input keying material combines the shared secret with both keys
+    let prk_key = hkdf::Salt::new(hkdf::HKDF_SHA256, &auth_secret).extract(&ecdh_secret);
+    let ikm = hkdf_expand(&prk_key, &[b"WebPush: info\0", &ua_public, &as_public], 32)?;
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, &salt).extract(&ikm);
+    let cek = hkdf_expand(&prk, &[b"Content-Encoding: aes128gcm\0"], 16)?;
+    let nonce = hkdf_expand(&prk, &[b"Content-Encoding: nonce\0"], 12)?;
+
+    let key = aead::LessSafeKey::new(
+        aead::UnboundKey::new(&aead::AES_128_GCM, &cek).map_err(|_| anyhow!("Invalid key"))?,
+    );
+    let mut record = payload.to_vec();
+    record.push(2); // Delimiter of the last (and only) record
+    key.seal_in_place_append_tag(
+        aead::Nonce::try_assume_unique_for_key(&nonce).map_err(|_| anyhow!("Invalid nonce"))?,
+        aead::Aad::empty(),
+        &mut record,
+    )
+    .map_err(|_| anyhow!("Failed to encrypt push payload"))?;
+
+    let mut body = Vec::with_capacity(86 + record.len());
+    body.extend_from_slice(&salt);
+    body.extend_from_slice(&RECORD_SIZE.to_be_bytes());
+    body.push(as_public.len() as u8);
+    body.extend_from_slice(&as_public);
+    body.extend_from_slice(&record);
+    Ok(body)
+}
+
+/// What a notification shows, as read by the service worker
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Notification {
+    pub contact_id: String,
+    pub message_id: String,
+    /// The chat's name (the group, or the contact in private chats)
+    pub chat: Option<String>,
+    /// Who wrote it
+    pub sender: Option<String>,
+    /// The message text, translated if it was
+    pub preview: String,
+    pub timestamp: i64,
+}
+
+impl Notification {
+    pub fn for_message(message: &StoredMessage) -> Self {
+        let preview = message
+            .translated_text
+            .clone()
+            .filter(|_| message.is_translated)
+            .or_else(|| {
+                MessageStore::generate_message_preview(
+                    Some(&message.content_json),
+                    Some(&message.content_type),
+                    false,
+                )
+            })
+            .unwrap_or_default();
+        Self {
+            contact_id: message.contact_id.clone(),
+            message_id: message.id.clone(),
+            chat: message.contact_name.clone(),
+            sender: message.sender_name.clone(),
+            preview: preview.chars().take(PREVIEW_CHARS).collect(),
+            timestamp: message.timestamp,
+        }
+    }
+}
+
+/// Sends push notifications to subscribed browsers
+pub struct PushNotifier {
+    client: Client,
+    /// Contact sent to push services (a `mailto:` or `https:` URL)
+    subject: RwLock<String>,
+    /// Loaded (or generated) on first use
+    vapid_key: Mutex<Option<Arc<VapidKey>>>,
+    /// Failed deliveries, for the UI
+    errors: ErrorRegistry,
+}
+
+impl Default for PushNotifier {
+    fn default() -> Self {
+        Self {
+            client: Client::builder()
+                .timeout(PUSH_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            subject: RwLock::new(DEFAULT_SUBJECT.to_string()),
+            vapid_key: Mutex::new(None),
+            errors: ErrorRegistry::default(),
+        }
+    }
+}
+
+impl PushNotifier {
+    /// Record failed deliveries in `errors`
+    pub fn with_errors(mut self, errors: ErrorRegistry) -> Self {
+        self.errors = errors;
+        self
+    }
+
+    pub fn set_subject(&self, subject: &str) {
+        *self.subject.write().unwrap() = subject.to_string();
+    }
+
+    /// The VAPID key, generated and saved the first time it's needed
+    pub fn vapid_key(&self, store: &MessageStore) -> Result<Arc<VapidKey>> {
+        let mut cached = self.vapid_key.lock().unwrap();
+        if let Some(key) = cached.as_ref() {
+            return Ok(key.clone());
+        }
+        let key = match store.get_vapid_key()? {
+            Some(encoded) => VapidKey::from_pkcs8(&encoded)?,
+            None => {
+                let (key, encoded) = VapidKey::generate()?;
+                store.set_vapid_key(&encoded)?;
+                info!("Generated a VAPID key for push notifications");
+                key
+            }
+        };
+        let key = Arc::new(key);
+        *cached = Some(key.clone());
+        Ok(key)
+    }
+
+    /// Whether a message should be pushed at `now` (server local time): not
+    /// if its chat is muted or it's within the quiet hours
+    pub fn should_notify(
+        store: &MessageStore,
+        message: &StoredMessage,
+        now: chrono::NaiveTime,
+    ) -> Result<bool> {
+        if store.get_conversation_settings(&message.contact_id)?.muted {
+            return Ok(false);
+        }
+        if store
+            .get_quiet_hours()?
+            .is_some_and(|quiet| quiet.contains(now))
+        {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Push a message to every subscribed browser, unless it shouldn't be
+    /// (see `should_notify`). Returns how many accepted it.
+    pub async fn notify(
+        &self,
+        store: &MessageStore,
+        message: &StoredMessage,
+        now: chrono::NaiveTime,
+    ) -> Result<usize> {
+        let subscriptions = store.get_push_subscriptions()?;
+        if subscriptions.is_empty() || !Self::should_notify(store, message, now)? {
+            return Ok(0);
+        }
+        let vapid_key = self.vapid_key(store)?;
+        let payload = serde_json::to_vec(&Notification::for_message(message))?;
+
+        let mut delivered = 0;
+        for subscription in subscriptions {
+            match self.send(&vapid_key, &subscription, &payload).await {
+                Ok(true) => delivered += 1,
+                Ok(false) => {
+                    info!("Push subscription {} expired", subscription.endpoint);
+                    if let Err(e) = store.delete_push_subscription(&subscription.endpoint) {
+                        warn!("Failed to delete push subscription: {}", e);
+                    }
+                }
+                Err(e) => {
+                    warn!("Push to {} failed: {:#}", subscription.endpoint, e);
+                    // The endpoint's path identifies the browser, so only its host is kept
+                    let host = reqwest::Url::parse(&subscription.endpoint)
+                        .ok()
+                        .and_then(|url| url.host_str().map(str::to_string))
+                        .unwrap_or_default();
+                    self.errors.record(
+                        ErrorCategory::Webhook,
+                        format!("Push to {} failed: {:#}", host, e),
+                    );
+                }
+            }
+        }
+        Ok(delivered)
+    }
+
+    /// Post an encrypted payload to a subscription. Returns false if the
+    /// push service says the subscription is gone.
+    async fn send(
+        &self,
+        vapid_key: &VapidKey,
+        subscription: &PushSubscription,
+        payload: &[u8],
+    ) -> Result<bool> {
+        let body = encrypt(payload, &subscription.p256dh, &subscription.auth)?;
+        let subject = self.subject.read().unwrap().clone();
+        let authorization = vapid_key.authorization(
+            &subscription.endpoint,
+            &subject,
+            chrono::Utc::now().timestamp(),
+        )?;
+        let response = self
+            .client
+            .post(&subscription.endpoint)
+            .header("Authorization", authorization)
+            .header("Content-Encoding", "aes128gcm")
+            .header("Content-Type", "application/octet-stream")
+            .header("TTL", TTL_SECS.to_string())
+            .header("Urgency", "normal")
+            .body(body)
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::NOT_FOUND | StatusCode::GONE => Ok(false),
+            status if status.is_success() => {
+                debug!("Pushed to {}", subscription.endpoint);
+                Ok(true)
+            }
+            status => bail!("Push service answered {}", status),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{test_store, text_message, QuietHours};
+    use axum::{extract::State, http::HeaderMap, routing::post, Router};
+    use chrono::NaiveTime;
+
+    /// A browser's keys: the private half is good for one decryption
+    struct Browser {
+        private_key: agreement::EphemeralPrivateKey,
+        subscription: PushSubscription,
+        auth: Vec<u8>,
+    }
+
+    fn browser(endpoint: &str) -> Browser {
+        let rng = SystemRandom::new();
+        let private_key =
+            agreement::EphemeralPrivateKey::generate(&agreement::ECDH_P256, &rng).unwrap();
+        let public_key = private_key.compute_public_key().unwrap();
+        let mut auth = vec![0u8; 16];
+        rng.fill(&mut auth).unwrap();
+        Browser {
+            subscription: PushSubscription {
+                endpoint: endpoint.to_string(),
+                p256dh: URL_SAFE_NO_PAD.encode(public_key.as_ref()),
+                auth: URL_SAFE_NO_PAD.encode(&auth),
+            },
+            private_key,
+            auth,
+        }
+    }
+
+    /// Decrypt like a browser would
+    fn decrypt(browser: Browser, body: &[u8]) -> Vec<u8> {
+        let (salt, rest) = body.split_at(16);
+        let key_len = rest[4] as usize;
+        let as_public = &rest[5..5 + key_len];
+        let mut record = rest[5 + key_len..].to_vec();
+        let ua_public = URL_SAFE_NO_PAD
+            .decode(&browser.subscription.p256dh)
+            .unwrap();
+
+        let ecdh_secret = agreement::agree_ephemeral(
+            browser.private_key,
+            &agreement::UnparsedPublicKey::new(&agreement::ECDH_P256, as_public),
+            |secret| secret.to_vec(),
+        )
+        .unwrap();
+        let prk_key = hkdf::Salt::new(hkdf::HKDF_SHA256, &browser.auth).extract(&ecdh_secret);
+        let ikm = hkdf_expand(&prk_key, &[b"WebPush: info\0", &ua_public, as_public], 32).unwrap();
+        let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, salt).extract(&ikm);
+        let cek = hkdf_expand(&prk, &[b"Content-Encoding: aes128gcm\0"], 16).unwrap();
+        let nonce = hkdf_expand(&prk, &[b"Content-Encoding: nonce\0"], 12).unwrap();
+        let key = aead::LessSafeKey::new(aead::UnboundKey::new(&aead::AES_128_GCM, &cek).unwrap());
+        let plain = key
+            .open_in_place(
+                aead::Nonce::try_assume_unique_for_key(&nonce).unwrap(),
+                aead::Aad::empty(),
+                &mut record,
+            )
+            .unwrap();
+        assert_eq!(plain.last(), Some(&2));
+        plain[..plain.len() - 1].to_vec()
+    }
+
+    type Received = Arc<Mutex<Vec<(HeaderMap, Vec<u8>)>>>;
+
+    /// A push service accepting `/ok` and reporting `/gone` expired
+    async fn spawn_push_service() -> (String, Received) {
+        async fn accept(
+            State(received): State<Received>,
+            headers: HeaderMap,
+            body: axum::body::Bytes,
+        ) -> StatusCode {
+            received.lock().unwrap().push((headers, body.to_vec()));
+            StatusCode::CREATED
+        }
+        let received = Received::default();
+        let app = Router::new()
+            .route("/ok", post(accept))
+            .route("/gone", post(|| async { StatusCode::GONE }))
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, received)
+    }
+
+    fn incoming(contact_id: &str) -> StoredMessage {
+        StoredMessage {
+            sender_name: Some("Ana".to_string()),
+            contact_name: Some("Ana".to_string()),
+            translated_text: Some("Are you coming tonight?".to_string()),
+            source_language: Some("Spanish".to_string()),
+            is_translated: true,
+            ..text_message("m1", contact_id, 1_700_000_000_000, "¿Vienes esta noche?")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_push_delivery_and_suppression() {
+        let (store, _dir) = test_store();
+        let chat = "34600000000@s.whatsapp.net";
+        store
+            .upsert_contact(chat, Some("Ana"), None, Some("private"), 1)
+            .unwrap();
+        let (url, received) = spawn_push_service().await;
+        let phone = browser(&format!("{}/ok", url));
+        let expired = browser(&format!("{}/gone", url));
+        store.save_push_subscription(&phone.subscription).unwrap();
+        store.save_push_subscription(&expired.subscription).unwrap();
+
+        let notifier = PushNotifier::default();
+        let noon = NaiveTime::from_hms_opt(12, 0, 0).unwrap();
+        let message = incoming(chat);
+
+        // Delivered encrypted and signed; the expired subscription is dropped
+        assert_eq!(notifier.notify(&store, &message, noon).await.unwrap(), 1);
+        assert_eq!(
+            store.get_push_subscriptions().unwrap(),
+            std::slice::from_ref(&phone.subscription)
+        );
+        let (headers, body) = received.lock().unwrap().remove(0);
+        assert_eq!(headers["content-encoding"], "aes128gcm");
+        let vapid_key = notifier.vapid_key(&store).unwrap().public_key();
+        let authorization = headers["authorization"].to_str().unwrap();
+        assert!(authorization.starts_with("vapid t="));
+        assert!(authorization.ends_with(&format!(", k={}", vapid_key)));
+        let payload: serde_json::Value = serde_json::from_slice(&decrypt(phone, &body)).unwrap();
+        assert_eq!(payload["contactId"], chat);
+        assert_eq!(payload["sender"], "Ana");
+        assert_eq!(payload["preview"], "Are you coming tonight?");
+
+        // The key is kept across restarts
+        assert_eq!(
+            PushNotifier::default()
+                .vapid_key(&store)
+                .unwrap()
+                .public_key(),
+            vapid_key
+        );
+
+        // Quiet hours (here across midnight) hold notifications back
+        store
+            .set_quiet_hours(Some(&QuietHours {
+                start: NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+                end: NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+            }))
+            .unwrap();
+        let night = NaiveTime::from_hms_opt(23, 30, 0).unwrap();
+        let early = NaiveTime::from_hms_opt(6, 59, 0).unwrap();
+        assert_eq!(notifier.notify(&store, &message, night).await.unwrap(), 0);
+        assert_eq!(notifier.notify(&store, &message, early).await.unwrap(), 0);
+        assert_eq!(notifier.notify(&store, &message, noon).await.unwrap(), 1);
+
+        // So does muting the chat
+        let mut settings = store.get_conversation_settings(chat).unwrap();
+        settings.muted = true;
+        store.update_conversation_settings(chat, &settings).unwrap();
+        assert_eq!(notifier.notify(&store, &message, noon).await.unwrap(), 0);
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,622 @@
+//! Weekly operational reports.
+//!
+//! A report sums up a week (Monday to Sunday in the report timezone):
+//! messages handled, AI spend, the busiest chats, errors and disk space.
+//! Reports are saved so past weeks can be read again, and with a schedule
+//! set the last full week's report is delivered to Saved Messages or posted
+//! to a webhook. A delivery that can't be made (WhatsApp disconnected, the
+//! webhook down) is retried every hour.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use reqwest::Client;
+use serde::Serialize;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use tracing::{error, info, warn};
+
+use crate::disk_guard::DiskStatus;
+use crate::error_registry::{ErrorCategory, ErrorRegistry};
+use crate::notes::{self, Note};
+use crate::storage::{ActivityStats, MessageStore, StoredReport};
+use crate::web::AppState;
+
+/// How often a due report is looked for (and a failed delivery retried)
+pub const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Busiest chats listed in a report
+const TOP_CHATS: usize = 5;
+
+/// Timeout for posting a report to the webhook
+const WEBHOOK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Delivery destination recorded for Saved Messages
+pub const SELF_CHAT_DESTINATION: &str = "self_chat";
+
+/// Errors returned when getting a report
+#[derive(Debug, Clone, Serialize)]
+pub enum ReportError {
+    InvalidWeek,
+    FutureWeek,
+    StorageError,
+}
+
+impl ReportError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReportError::InvalidWeek => "invalid_week",
+            ReportError::FutureWeek => "future_week",
+            ReportError::StorageError => "storage_error",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            ReportError::InvalidWeek => "The week must be an ISO week like 2026-W07",
+            ReportError::FutureWeek => "The week hasn't started yet",
+            ReportError::StorageError => "Failed to get the report",
+        }
+    }
+}
+
+fn storage_error(e: anyhow::Error) -> ReportError {
+    error!("Failed to get report: {}", e);
+    ReportError::StorageError
+}
+
+/// When the weekly report is delivered, e.g. "mon 09:00"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportSchedule {
+    pub weekday: Weekday,
+    pub time: NaiveTime,
+}
+
+impl std::str::FromStr for ReportSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid report schedule '{}' (expected e.g. \"mon 09:00\")",
+                s
+            )
+        };
+        let (day, time) = s.trim().split_once(' ').ok_or_else(invalid)?;
+        Ok(Self {
+            weekday: day.parse().map_err(|_| invalid())?,
+            time: NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// An ISO week: Monday to Sunday
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportWeek {
+    pub year: i32,
+    pub week: u32,
+}
+
+impl ReportWeek {
+    /// Parse "2026-W07" (or "2026-w7")
+    pub fn parse(s: &str) -> Option<Self> {
+        let (year, week) = s.trim().split_once(['W', 'w'])?;
+        let week = Self {
+            year: year.strip_suffix('-').unwrap_or(year).parse().ok()?,
+            week: week.parse().ok()?,
+        };
+        week.monday().map(|_| week)
+    }
+
+    /// The week a day is in
+    pub fn containing(date: NaiveDate) -> Self {
+        let week = date.iso_week();
+        Self {
+            year: week.year(),
+            week: week.week(),
+        }
+    }
+
+    /// The last full week before a day's week
+    pub fn before(date: NaiveDate) -> Self {
+        Self::containing(date - Duration::days(7))
+    }
+
+    fn monday(&self) -> Option<NaiveDate> {
+        NaiveDate::from_isoywd_opt(self.year, self.week, Weekday::Mon)
+    }
+
+    /// Start and end (exclusive) of the week in a timezone
+    pub fn bounds(&self, tz: Tz) -> (DateTime<Utc>, DateTime<Utc>) {
+        let monday = self.monday().expect("validated week");
+        (
+            local_midnight(tz, monday),
+            local_midnight(tz, monday + Duration::days(7)),
+        )
+    }
+}
+
+impl fmt::Display for ReportWeek {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-W{:02}", self.year, self.week)
+    }
+}
+
+/// The start of a day in a timezone (or the first hour that exists, where
+/// the clocks change at midnight)
+fn local_midnight(tz: Tz, date: NaiveDate) -> DateTime<Utc> {
+    (0..3)
+        .find_map(|hour| {
+            tz.from_local_datetime(&date.and_hms_opt(hour, 0, 0)?)
+                .earliest()
+        })
+        .map(|time| time.with_timezone(&Utc))
+        .unwrap_or_else(|| date.and_hms_opt(0, 0, 0).unwrap().and_utc())
+}
+
+/// A category's errors in the week
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorCount {
+    pub category: ErrorCategory,
+    pub count: usize,
+    /// The latest one's message
+    pub last: Option<String>,
+}
+
+/// What a report is made of
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WeeklyStats {
+    #[serde(flatten)]
+    pub activity: ActivityStats,
+    /// Errors by category. Only errors since the last restart are known, and
+    /// at most the latest few of each kind.
+    pub errors: Vec<ErrorCount>,
+    /// Disk space when the report was made
+    pub disk: DiskStatus,
+}
+
+/// Gather a week's stats
+pub fn assemble(
+    store: &MessageStore,
+    errors: &ErrorRegistry,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<WeeklyStats> {
+    let (from, to) = (from.timestamp_millis(), to.timestamp_millis());
+    let activity = store.get_activity_stats(from, to, TOP_CHATS)?;
+    let in_week = errors.list(None, Some(from - 1));
+    let errors = ErrorCategory::ALL
+        .into_iter()
+        .filter_map(|category| {
+            // Newest first
+            let mut entries = in_week
+                .iter()
+                .filter(|e| e.category == category && e.timestamp < to);
+            let last = entries.next()?;
+            Some(ErrorCount {
+                category,
+                count: 1 + entries.count(),
+                last: Some(last.message.clone()),
+            })
+        })
+        .collect();
+    Ok(WeeklyStats {
+        activity,
+        errors,
+        disk: store.disk_status(),
+    })
+}
+
+fn megabytes(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+/// A report as text (WhatsApp-style markdown)
+pub fn render(week: ReportWeek, tz: Tz, stats: &WeeklyStats) -> String {
+    let monday = week.monday().expect("validated week");
+    let sunday = monday + Duration::days(6);
+    let activity = &stats.activity;
+    let mut lines = vec![
+        format!("*Weekly report {}*", week),
+        format!(
+            "{} to {} ({})",
+            monday.format("%a %-d %b"),
+            sunday.format("%a %-d %b %Y"),
+            tz.name()
+        ),
+        String::new(),
+        format!(
+            "*Messages:* {} received, {} sent, {} translated",
+            activity.messages_received, activity.messages_sent, activity.messages_translated
+        ),
+        format!(
+            "*AI usage:* {} calls, {} tokens in, {} out, ${:.2}",
+            activity.translation_calls,
+            activity.input_tokens,
+            activity.output_tokens,
+            activity.translation_cost_usd
+        ),
+    ];
+
+    lines.push(String::new());
+    if activity.top_chats.is_empty() {
+        lines.push("*Top chats:* none".to_string());
+    } else {
+        lines.push("*Top chats:*".to_string());
+        for (i, chat) in activity.top_chats.iter().enumerate() {
+            lines.push(format!(
+                "{}. {} ({} message{})",
+                i + 1,
+                chat.name.as_deref().unwrap_or(&chat.contact_id),
+                chat.messages,
+                if chat.messages == 1 { "" } else { "s" }
+            ));
+        }
+    }
+
+    lines.push(String::new());
+    if stats.errors.is_empty() {
+        lines.push("*Errors:* none".to_string());
+    } else {
+        lines.push("*Errors:*".to_string());
+        for errors in &stats.errors {
+            lines.push(format!(
+                "- {}: {} (last: {})",
+                errors.category.as_str(),
+                errors.count,
+                errors.last.as_deref().unwrap_or("-")
+            ));
+        }
+    }
+
+    lines.push(String::new());
+    let free = stats
+        .disk
+        .free_bytes
+        .map_or_else(|| "unknown".to_string(), megabytes);
+    lines.push(format!(
+        "*Disk:* {} free{}, database {}",
+        free,
+        if stats.disk.read_only {
+            " (read-only, low on space)"
+        } else {
+            ""
+        },
+        megabytes(activity.database_bytes)
+    ));
+    lines.join("\n")
+}
+
+/// Make a week's report. Complete weeks are saved, replacing an earlier
+/// report for the week; the current week's report is only returned.
+pub fn generate(
+    store: &MessageStore,
+    errors: &ErrorRegistry,
+    week: ReportWeek,
+    tz: Tz,
+    now: DateTime<Utc>,
+) -> Result<StoredReport> {
+    let (from, to) = week.bounds(tz);
+    let stats = assemble(store, errors, from, to)?;
+    let report = StoredReport {
+        week: week.to_string(),
+        period_start: from.timestamp_millis(),
+        period_end: to.timestamp_millis(),
+        generated_at: now.timestamp_millis(),
+        markdown: render(week, tz, &stats),
+        stats: serde_json::to_value(&stats)?,
+        delivered_to: None,
+        delivered_at: None,
+    };
+    if to <= now {
+        store.save_report(&report)?;
+    }
+    Ok(report)
+}
+
+/// A week's report: the saved one for a past week, or one made now. The
+/// last full week when no week is given.
+pub fn get_report(
+    store: &MessageStore,
+    errors: &ErrorRegistry,
+    week: Option<&str>,
+    tz: Tz,
+) -> Result<StoredReport, ReportError> {
+    let now = Utc::now();
+    let week = match week {
+        Some(week) => ReportWeek::parse(week).ok_or(ReportError::InvalidWeek)?,
+        None => ReportWeek::before(now.with_timezone(&tz).date_naive()),
+    };
+    let (from, _) = week.bounds(tz);
+    if from > now {
+        return Err(ReportError::FutureWeek);
+    }
+    if let Some(report) = store.get_report(&week.to_string()).map_err(storage_error)? {
+        return Ok(report);
+    }
+    generate(store, errors, week, tz, now).map_err(storage_error)
+}
+
+/// Report timezone, delivery schedule and destination
+#[derive(Debug, Clone)]
+pub struct ReportConfig {
+    pub timezone: Tz,
+    /// When to deliver reports (None: only made on request)
+    pub schedule: Option<ReportSchedule>,
+    /// Post reports here instead of to Saved Messages
+    pub webhook_url: Option<String>,
+}
+
+impl Default for ReportConfig {
+    fn default() -> Self {
+        Self {
+            timezone: Tz::UTC,
+            schedule: None,
+            webhook_url: None,
+        }
+    }
+}
+
+/// Weekly report settings and delivery
+pub struct WeeklyReports {
+    client: Client,
+    config: RwLock<ReportConfig>,
+}
+
+impl Default for WeeklyReports {
+    fn default() -> Self {
+        Self {
+            client: Client::builder()
+                .timeout(WEBHOOK_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            config: RwLock::new(ReportConfig::default()),
+        }
+    }
+}
+
+impl WeeklyReports {
+    pub fn set_config(&self, config: ReportConfig) {
+        *self.config.write().unwrap() = config;
+    }
+
+    pub fn config(&self) -> ReportConfig {
+        self.config.read().unwrap().clone()
+    }
+
+    pub fn timezone(&self) -> Tz {
+        self.config.read().unwrap().timezone
+    }
+
+    /// Post a report to the webhook as JSON
+    async fn post(&self, url: &str, report: &StoredReport) -> Result<()> {
+        let response = self.client.post(url).json(report).send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("webhook answered {}", response.status()));
+        }
+        Ok(())
+    }
+}
+
+/// When a week's report is due: its schedule slot in the following week
+fn due_at(week: ReportWeek, schedule: ReportSchedule, tz: Tz) -> DateTime<Utc> {
+    let (_, next_monday) = week.bounds(tz);
+    let date = next_monday.with_timezone(&tz).date_naive()
+        + Duration::days(schedule.weekday.num_days_from_monday() as i64);
+    tz.from_local_datetime(&date.and_time(schedule.time))
+        .earliest()
+        .map_or(next_monday, |time| time.with_timezone(&Utc))
+}
+
+/// Deliver a report to the webhook, or to Saved Messages. Returns where it went.
+async fn deliver(state: &AppState, report: &StoredReport) -> Result<String> {
+    if let Some(url) = state.reports.config().webhook_url {
+        state.reports.post(&url, report).await?;
+        return Ok(url);
+    }
+
+    let command_tx = if *state.connected.read().await {
+        state.command_tx.read().await.clone()
+    } else {
+        None
+    };
+    let message = notes::save_note(
+        &state.store,
+        command_tx.as_ref(),
+        Note::Text(report.markdown.clone()),
+        "report",
+    )
+    .await
+    .map_err(|e| anyhow!(e.description()))?;
+    state.broadcast_message(message, None);
+    Ok(SELF_CHAT_DESTINATION.to_string())
+}
+
+/// Make and deliver the last full week's report if it's due and hasn't
+/// gone out yet
+pub async fn deliver_due(state: &AppState, now: DateTime<Utc>) -> Result<()> {
+    let config = state.reports.config();
+    let Some(schedule) = config.schedule else {
+        return Ok(());
+    };
+    let tz = config.timezone;
+    let week = ReportWeek::before(now.with_timezone(&tz).date_naive());
+    if now < due_at(week, schedule, tz) {
+        return Ok(());
+    }
+
+    let report = match state.store.get_report(&week.to_string())? {
+        Some(report) if report.delivered_at.is_some() => return Ok(()),
+        Some(report) => report,
+        None => generate(&state.store, &state.errors, week, tz, now)?,
+    };
+    let destination = deliver(state, &report).await?;
+    state
+        .store
+        .mark_report_delivered(&report.week, &destination, now.timestamp_millis())?;
+    info!("Delivered weekly report {} to {}", report.week, destination);
+    Ok(())
+}
+
+/// Look for a due report every `CHECK_INTERVAL` until shutdown
+pub fn spawn(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = state.shutdown.wait() => break,
+            }
+            let _work = state.shutdown.track("report");
+            if let Err(e) = deliver_due(&state, Utc::now()).await {
+                warn!("Weekly report not delivered, retrying in an hour: {:#}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{test_store, text_message, StoredMessage};
+    use crate::translation::UsageInfo;
+
+    fn message(id: &str, contact_id: &str, timestamp: i64, is_from_me: bool) -> StoredMessage {
+        StoredMessage {
+            is_from_me,
+            translated_text: (!is_from_me).then(|| "Hello".to_string()),
+            is_translated: !is_from_me,
+            ..text_message(id, contact_id, timestamp, "Hola")
+        }
+    }
+
+    #[test]
+    fn test_report_weeks() {
+        let week = ReportWeek::parse("2026-W07").unwrap();
+        assert_eq!(
+            week,
+            ReportWeek {
+                year: 2026,
+                week: 7
+            }
+        );
+        assert_eq!(ReportWeek::parse("2026w7"), Some(week));
+        assert_eq!(ReportWeek::parse("2026-W54"), None);
+        assert_eq!(ReportWeek::parse("last week"), None);
+        assert_eq!(week.to_string(), "2026-W07");
+
+        // Monday 9 February to Monday 16 February, local time
+        let tokyo: Tz = "Asia/Tokyo".parse().unwrap();
+        let (from, to) = week.bounds(tokyo);
+        assert_eq!(from.to_rfc3339(), "2026-02-08T15:00:00+00:00");
+        assert_eq!(to.to_rfc3339(), "2026-02-15T15:00:00+00:00");
+
+        // A week the clocks change in is an hour short
+        let london: Tz = "Europe/London".parse().unwrap();
+        let (from, to) = ReportWeek::parse("2026-W13").unwrap().bounds(london);
+        assert_eq!(to - from, Duration::hours(7 * 24 - 1));
+
+        let sunday = NaiveDate::from_ymd_opt(2026, 2, 15).unwrap();
+        assert_eq!(ReportWeek::containing(sunday), week);
+        assert_eq!(ReportWeek::before(sunday + Duration::days(1)), week);
+
+        // Due on the schedule's day of the following week
+        let schedule: ReportSchedule = "tue 09:30".parse().unwrap();
+        assert_eq!(
+            due_at(week, schedule, tokyo).to_rfc3339(),
+            "2026-02-17T00:30:00+00:00"
+        );
+        assert!("09:30".parse::<ReportSchedule>().is_err());
+    }
+
+    #[test]
+    fn test_assemble_weekly_stats() {
+        let (store, _dir) = test_store();
+        let errors = ErrorRegistry::default();
+        let alice = "447700900001@s.whatsapp.net";
+        let bob = "447700900002@s.whatsapp.net";
+        store
+            .upsert_contact(alice, Some("Alice"), None, Some("private"), 1)
+            .unwrap();
+        store
+            .upsert_contact(bob, Some("Bob"), None, Some("private"), 1)
+            .unwrap();
+
+        // This week, so the usage recorded now falls in it
+        let now = Utc::now();
+        let week = ReportWeek::containing(now.date_naive());
+        let (from, to) = week.bounds(Tz::UTC);
+        let start = from.timestamp_millis();
+        for (i, (contact, is_from_me)) in
+            [(alice, false), (alice, false), (alice, true), (bob, false)]
+                .into_iter()
+                .enumerate()
+        {
+            store
+                .add_message(&message(
+                    &format!("m{}", i),
+                    contact,
+                    start + i as i64,
+                    is_from_me,
+                ))
+                .unwrap();
+        }
+        // The week before doesn't count
+        store
+            .add_message(&message("old", bob, start - 1, false))
+            .unwrap();
+        let usage = UsageInfo {
+            input_tokens: 120,
+            output_tokens: 30,
+            cost_usd: 0.25,
+            calls: Vec::new(),
+        };
+        store
+            .record_usage(Some(alice), Some("m0"), &usage, "translate")
+            .unwrap();
+        store
+            .record_usage(Some(bob), Some("m3"), &usage, "translate")
+            .unwrap();
+        errors.record(ErrorCategory::Send, "Failed to send message: timeout");
+
+        let stats = assemble(&store, &errors, from, to).unwrap();
+        let activity = &stats.activity;
+        assert_eq!(activity.messages_received, 3);
+        assert_eq!(activity.messages_sent, 1);
+        assert_eq!(activity.messages_translated, 3);
+        assert_eq!(activity.translation_calls, 2);
+        assert_eq!(activity.input_tokens, 240);
+        assert_eq!(activity.output_tokens, 60);
+        assert!((activity.translation_cost_usd - 0.5).abs() < 1e-9);
+        let top: Vec<_> = activity
+            .top_chats
+            .iter()
+            .map(|c| (c.name.as_deref().unwrap(), c.messages))
+            .collect();
+        assert_eq!(top, [("Alice", 3), ("Bob", 1)]);
+        assert!(activity.database_bytes > 0);
+        assert_eq!(stats.errors.len(), 1);
+        assert_eq!(stats.errors[0].category, ErrorCategory::Send);
+        assert_eq!(stats.errors[0].count, 1);
+
+        let text = render(week, Tz::UTC, &stats);
+        assert!(text.contains("3 received, 1 sent, 3 translated"));
+        assert!(text.contains("1. Alice (3 messages)"));
+        assert!(text.contains("- send: 1"));
+
+        // Only complete weeks are kept
+        generate(&store, &errors, week, Tz::UTC, now).unwrap();
+        assert!(store.list_reports().unwrap().is_empty());
+        let last_week = ReportWeek::before(now.date_naive());
+        let report = get_report(&store, &errors, None, Tz::UTC).unwrap();
+        assert_eq!(report.week, last_week.to_string());
+        assert!(report.markdown.contains("1. Bob (1 message)"));
+        assert_eq!(store.list_reports().unwrap().len(), 1);
+        assert!(matches!(
+            get_report(&store, &errors, Some("2999-W01"), Tz::UTC),
+            Err(ReportError::FutureWeek)
+        ));
+    }
+}
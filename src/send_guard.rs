@@ -0,0 +1,205 @@
+//! Confirmation guard for outgoing messages in the wrong language.
+//!
+//! Before sending, the draft's language is compared with the chat's
+//! established language. On a mismatch (or when the chat has no established
+//! language yet) the send is parked behind a one-time confirmation token
+//! instead of going out, and a follow-up send with that token delivers it.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use tracing::{error, info, warn};
+
+use crate::storage::MessageStore;
+use crate::translation::TranslationService;
+
+/// How long a confirmation token stays valid
+const CONFIRMATION_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Where the language guard is enabled
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LanguageGuardConfig {
+    /// Default for the MCP send_message tool's `confirm_language` argument
+    pub mcp_default: bool,
+    /// Guard sends made through `/api/send`
+    pub web: bool,
+}
+
+/// A draft whose language doesn't match the chat
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LanguageMismatch {
+    /// Language of the text that would be sent (None if detection failed)
+    pub detected_language: Option<String>,
+    /// Established language of the chat (None if it has none yet)
+    pub chat_language: Option<String>,
+}
+
+/// A send waiting for confirmation
+#[derive(Debug, Clone)]
+pub struct PendingSend {
+    pub contact_id: String,
+    /// What the user typed
+    pub text: String,
+    /// What would actually be sent (after outgoing translation)
+    pub text_to_send: String,
+    /// Language the text was translated to, if it was
+    pub target_language: Option<String>,
+    created_at: Instant,
+}
+
+impl PendingSend {
+    pub fn new(
+        contact_id: &str,
+        text: &str,
+        text_to_send: &str,
+        target_language: Option<String>,
+    ) -> Self {
+        Self {
+            contact_id: contact_id.to_string(),
+            text: text.to_string(),
+            text_to_send: text_to_send.to_string(),
+            target_language,
+            created_at: Instant::now(),
+        }
+    }
+
+    fn is_expired(&self) -> bool {
+        self.created_at.elapsed() > CONFIRMATION_TTL
+    }
+}
+
+/// Sends parked behind a confirmation token
+#[derive(Default)]
+pub struct PendingConfirmations {
+    sends: RwLock<HashMap<String, PendingSend>>,
+}
+
+impl PendingConfirmations {
+    /// Park a send and return its confirmation token
+    pub async fn insert(&self, send: PendingSend) -> String {
+        let token = uuid::Uuid::new_v4().to_string();
+        let mut sends = self.sends.write().await;
+        sends.retain(|_, s| !s.is_expired());
+        sends.insert(token.clone(), send);
+        token
+    }
+
+    /// Redeem a confirmation token. Tokens are single-use and only valid for
+    /// the chat and text they were issued for.
+    pub async fn take(&self, token: &str, contact_id: &str, text: &str) -> Option<PendingSend> {
+        let send = self.sends.write().await.remove(token)?;
+        if send.is_expired() || send.contact_id != contact_id || send.text != text {
+            warn!("Rejected confirmation token for {}", contact_id);
+            return None;
+        }
+        Some(send)
+    }
+}
+
+/// The language a chat is established in: the language override if set,
+/// otherwise the language detected from recent messages
+pub fn chat_language(store: &MessageStore, contact_id: &str) -> Option<String> {
+    let settings = store
+        .get_conversation_settings(contact_id)
+        .unwrap_or_default();
+    settings.language_override.or_else(|| {
+        store
+            .get_cached_conversation_language(contact_id)
+            .ok()
+            .flatten()
+    })
+}
+
+/// Check whether the text about to be sent matches the chat's language.
+///
+/// `target_language` is the language the text was translated to before
+/// sending, in which case it matches by construction and no detection is
+/// needed. Returns the mismatch if the send needs confirmation, along with
+/// what language detection cost (USD).
+pub async fn check_language(
+    store: &MessageStore,
+    translator: &TranslationService,
+    contact_id: &str,
+    text_to_send: &str,
+    target_language: Option<&str>,
+    operation: &str,
+) -> (Option<LanguageMismatch>, f64) {
+    let chat_language = chat_language(store, contact_id);
+
+    if let (Some(chat), Some(target)) = (&chat_language, target_language) {
+        if chat.eq_ignore_ascii_case(target) {
+            return (None, 0.0);
+        }
+    }
+
+    let mut cost_usd = 0.0;
+    let detected_language = match translator.detect_text_language(text_to_send).await {
+        Ok((language, usage)) => {
+            cost_usd = usage.cost_usd;
+            if usage.input_tokens > 0 {
+                if let Err(e) = store.record_usage(Some(contact_id), None, &usage, operation) {
+                    warn!("Failed to record usage: {}", e);
+                }
+            }
+            Some(language)
+        }
+        Err(e) => {
+            error!("Failed to detect draft language: {}", e);
+            None
+        }
+    };
+
+    let mismatch = match (&detected_language, &chat_language) {
+        (Some(detected), Some(chat)) if detected.eq_ignore_ascii_case(chat) => None,
+        _ => {
+            info!(
+                "Draft for {} is in {:?} but the chat is in {:?}, asking for confirmation",
+                contact_id, detected_language, chat_language
+            );
+            Some(LanguageMismatch {
+                detected_language,
+                chat_language,
+            })
+        }
+    };
+    (mismatch, cost_usd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_confirmation_tokens_are_single_use() {
+        let pending = PendingConfirmations::default();
+
+        let token = pending
+            .insert(PendingSend::new("a@s.whatsapp.net", "Hi", "Hi", None))
+            .await;
+        assert!(pending
+            .take(&token, "b@s.whatsapp.net", "Hi")
+            .await
+            .is_none());
+
+        // A rejected token is spent too
+        assert!(pending
+            .take(&token, "a@s.whatsapp.net", "Hi")
+            .await
+            .is_none());
+
+        let token = pending
+            .insert(PendingSend::new("a@s.whatsapp.net", "Hi", "Salut", None))
+            .await;
+        let send = pending
+            .take(&token, "a@s.whatsapp.net", "Hi")
+            .await
+            .unwrap();
+        assert_eq!(send.text_to_send, "Salut");
+        assert!(pending
+            .take(&token, "a@s.whatsapp.net", "Hi")
+            .await
+            .is_none());
+    }
+}
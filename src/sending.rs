@@ -0,0 +1,479 @@
+//! Sending text messages.
+//!
+//! Messages typed in the web UI and sent by MCP clients go through the same
+//! steps: translated into the chat's language when it calls for it, handed
+//! to the bridge, and stored straight away under a temporary ID (as what was
+//! typed, with what was actually sent alongside) so they show up before
+//! WhatsApp echoes them back.
+
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+use crate::bridge::BridgeCommand;
+use crate::storage::{MessageStore, StoredMessage};
+use crate::translation::{LearningNote, TranslationService, TranslationStatus, VocabEntry};
+
+/// A text message to send
+#[derive(Debug, Clone)]
+pub struct OutgoingMessage {
+    /// Temporary ID it's stored (and its translation cost recorded) under
+    /// until WhatsApp's copy arrives, see `pending_message_id`
+    pub id: String,
+    pub contact_id: String,
+    /// What the user (or MCP client) typed
+    pub text: String,
+    pub reply: Option<ReplyTo>,
+    /// Where it was sent from ("web", "mcp:<client_id>")
+    pub origin: String,
+    /// Who sent it: the web session's name, or the MCP client
+    pub sent_by: Option<String>,
+}
+
+/// The message an outgoing message replies to
+#[derive(Debug, Clone)]
+pub struct ReplyTo {
+    pub message_id: String,
+    /// Who wrote it, None if it was me
+    pub sender: Option<String>,
+    /// Preview of its text
+    pub text: Option<String>,
+}
+
+/// What actually goes out for an outgoing message
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutgoingText {
+    pub text_to_send: String,
+    /// The language it was translated to, None if sent as typed
+    pub target_language: Option<String>,
+    /// What translating it cost (USD)
+    pub cost_usd: f64,
+    /// Key phrases and their translations, when the chat asks for them.
+    /// Kept with the message, never sent
+    pub learning_notes: Vec<LearningNote>,
+}
+
+impl OutgoingText {
+    /// Sent as typed
+    pub fn as_typed(text: &str) -> Self {
+        Self {
+            text_to_send: text.to_string(),
+            target_language: None,
+            cost_usd: 0.0,
+            learning_notes: Vec::new(),
+        }
+    }
+
+    /// Sent exactly as previously decided (a confirmed send)
+    pub fn confirmed(text_to_send: String, target_language: Option<String>) -> Self {
+        Self {
+            text_to_send,
+            target_language,
+            cost_usd: 0.0,
+            learning_notes: Vec::new(),
+        }
+    }
+
+    pub fn is_translated(&self) -> bool {
+        self.target_language.is_some()
+    }
+
+    /// The learning notes as stored in the message's vocabulary
+    pub fn learning_notes_vocabulary(&self) -> Vec<VocabEntry> {
+        self.learning_notes
+            .iter()
+            .cloned()
+            .map(Into::into)
+            .collect()
+    }
+}
+
+/// A new temporary ID for a message sent from here
+pub fn pending_message_id() -> String {
+    format!("pending_{}", uuid::Uuid::new_v4().simple())
+}
+
+/// Usage operation recorded for translating a message from `origin`
+fn usage_operation(origin: &str) -> &'static str {
+    if origin.starts_with("mcp") {
+        "translate_outgoing_mcp"
+    } else {
+        "translate_outgoing"
+    }
+}
+
+/// Translates, sends and stores outgoing text messages
+#[derive(Clone)]
+pub struct OutgoingMessageService {
+    store: MessageStore,
+    translator: Option<Arc<TranslationService>>,
+}
+
+impl OutgoingMessageService {
+    pub fn new(store: MessageStore, translator: Option<Arc<TranslationService>>) -> Self {
+        Self { store, translator }
+    }
+
+    /// Translate a message into its chat's language when the conversation
+    /// calls for it: a language override always translates, otherwise the
+    /// detected conversation language is used unless the text is already in
+    /// it. Failures fall back to sending as typed. Usage is recorded against
+    /// the message's temporary ID.
+    pub async fn translate(&self, message: &OutgoingMessage) -> OutgoingText {
+        let (contact_id, text) = (message.contact_id.as_str(), message.text.as_str());
+        // Contacts set to "send as typed", groups by default and private chats
+        // without a clear language skip translation entirely
+        if !self
+            .store
+            .get_outgoing_translation(contact_id)
+            .map(|outgoing| outgoing.enabled)
+            .unwrap_or(true)
+        {
+            return OutgoingText::as_typed(text);
+        }
+        let Some(translator) = &self.translator else {
+            return OutgoingText::as_typed(text);
+        };
+
+        // Target language: settings override > auto-detected > none
+        let settings = self
+            .store
+            .get_conversation_settings(contact_id)
+            .unwrap_or_default();
+        let force_translate = settings.language_override.is_some();
+        let target_language = match settings.language_override {
+            Some(language) => Some(language),
+            None => self
+                .store
+                .get_cached_conversation_language(contact_id)
+                .unwrap_or_else(|e| {
+                    error!("Failed to get conversation language: {}", e);
+                    None
+                }),
+        };
+        let Some(target_language) = target_language else {
+            return OutgoingText::as_typed(text);
+        };
+        info!(
+            "Target language for {} is {} (override: {})",
+            contact_id, target_language, force_translate
+        );
+
+        match translator
+            .translate_outgoing(
+                text,
+                &target_language,
+                force_translate,
+                settings.translation_tone.as_ref(),
+                settings.include_learning_notes,
+            )
+            .await
+        {
+            Ok((translated, learning_notes, usage)) => {
+                // Record usage if there was actual API usage
+                if usage.input_tokens > 0 {
+                    if let Err(e) = self.store.record_usage(
+                        Some(contact_id),
+                        Some(&message.id),
+                        &usage,
+                        usage_operation(&message.origin),
+                    ) {
+                        warn!("Failed to record usage: {}", e);
+                    }
+                }
+
+                if translated != text {
+                    info!(
+                        "Translated outgoing message to {} (cost: ${:.6})",
+                        target_language, usage.cost_usd
+                    );
+                    OutgoingText {
+                        text_to_send: translated,
+                        target_language: Some(target_language),
+                        cost_usd: usage.cost_usd,
+                        learning_notes,
+                    }
+                } else {
+                    OutgoingText {
+                        cost_usd: usage.cost_usd,
+                        ..OutgoingText::as_typed(text)
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Failed to translate outgoing message: {}", e);
+                OutgoingText::as_typed(text)
+            }
+        }
+    }
+
+    /// Hand a message to the bridge
+    pub async fn dispatch(
+        &self,
+        command_tx: &mpsc::Sender<BridgeCommand>,
+        message: &OutgoingMessage,
+        outgoing: &OutgoingText,
+    ) -> Result<()> {
+        let cmd = BridgeCommand::Send {
+            request_id: None, // Fire-and-forget; WhatsApp's copy arrives as a message event
+            to: message.contact_id.clone(),
+            text: outgoing.text_to_send.clone(),
+            reply_to: message.reply.as_ref().map(|r| r.message_id.clone()),
+            reply_to_sender: message.reply.as_ref().and_then(|r| r.sender.clone()),
+        };
+        command_tx
+            .send(cmd)
+            .await
+            .map_err(|e| anyhow!("Failed to send message: {}", e))
+    }
+
+    /// Store a sent message under a temporary ID and bump its chat, returning
+    /// the stored message. `outgoing` is None while the translation isn't
+    /// known yet (see `record_translation`).
+    ///
+    /// For translated messages the content body is what was typed (that's
+    /// what is displayed), `translated_text` what was actually sent and
+    /// `source_language` the language it was translated to.
+    pub fn record(
+        &self,
+        message: &OutgoingMessage,
+        outgoing: Option<&OutgoingText>,
+    ) -> StoredMessage {
+        let timestamp = chrono::Utc::now().timestamp_millis();
+        let contact = self.store.get_contact(&message.contact_id).ok().flatten();
+        let me = self.store.get_own_profile().unwrap_or_default();
+        let translated = outgoing.filter(|outgoing| outgoing.is_translated());
+
+        let mut content = serde_json::json!({"type": "text", "body": message.text});
+        if let Some(reply) = &message.reply {
+            content["reply_to"] = serde_json::json!({
+                "message_id": reply.message_id,
+                "sender": reply.sender,
+                "text": reply.text,
+            });
+        }
+
+        let mut stored_msg = StoredMessage {
+            // The actual message ID comes back with WhatsApp's copy
+            id: message.id.clone(),
+            contact_id: message.contact_id.clone(),
+            timestamp,
+            is_from_me: true,
+            is_forwarded: false,
+            sender_name: me.name,
+            sender_phone: me.phone,
+            contact_name: contact.as_ref().and_then(|c| c.name.clone()),
+            contact_phone: contact.as_ref().and_then(|c| c.phone.clone()),
+            chat_type: contact
+                .as_ref()
+                .and_then(|c| c.contact_type.clone())
+                .unwrap_or_else(|| "private".to_string()),
+            content_type: "Text".to_string(),
+            content_json: content.to_string(),
+            content: Some(content),
+            original_text: translated.map(|_| message.text.clone()),
+            translated_text: translated.map(|t| t.text_to_send.clone()),
+            source_language: translated.and_then(|t| t.target_language.clone()),
+            is_translated: translated.is_some(),
+            origin: Some(message.origin.clone()),
+            sent_by: message.sent_by.clone(),
+            mentioned_jids: Vec::new(),
+            mentions_me: false,
+            vocabulary: translated
+                .map(|t| t.learning_notes_vocabulary())
+                .filter(|vocabulary| !vocabulary.is_empty()),
+            audio: None,
+            sort_key: None,
+            triage: None,
+            translation_status: Some(if translated.is_some() {
+                TranslationStatus::Translated
+            } else {
+                TranslationStatus::NotNeeded
+            }),
+        };
+
+        match self.store.add_message(&stored_msg) {
+            Ok(sort_key) => stored_msg.sort_key = sort_key,
+            Err(e) => error!("Failed to store sent message: {}", e),
+        }
+        // Update the chat's last message time (keeping its name and phone)
+        if let Err(e) = self.store.upsert_contact(
+            &stored_msg.contact_id,
+            stored_msg.contact_name.as_deref(),
+            stored_msg.contact_phone.as_deref(),
+            Some(&stored_msg.chat_type),
+            stored_msg.timestamp,
+        ) {
+            error!("Failed to update contact: {}", e);
+        }
+        stored_msg
+    }
+
+    /// Record how a message stored before it was translated went out
+    pub fn record_translation(&self, message_id: &str, text: &str, outgoing: &OutgoingText) {
+        if !outgoing.is_translated() {
+            return;
+        }
+        if let Err(e) = self.store.record_sent_translation(
+            message_id,
+            text,
+            &outgoing.text_to_send,
+            outgoing.target_language.as_deref(),
+            &outgoing.learning_notes_vocabulary(),
+        ) {
+            error!("Failed to store outgoing translation: {}", e);
+        }
+    }
+
+    /// Send a message and store it, translating it first unless what to
+    /// send has already been decided
+    pub async fn send(
+        &self,
+        command_tx: &mpsc::Sender<BridgeCommand>,
+        message: &OutgoingMessage,
+        outgoing: Option<OutgoingText>,
+    ) -> Result<(StoredMessage, OutgoingText)> {
+        let outgoing = match outgoing {
+            Some(outgoing) => outgoing,
+            None => self.translate(message).await,
+        };
+        self.dispatch(command_tx, message, &outgoing).await?;
+        let stored = self.record(message, Some(&outgoing));
+        Ok((stored, outgoing))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{test_store, ConversationSettings};
+    use crate::translation::spawn_counting_provider;
+    use std::sync::atomic::Ordering;
+
+    #[tokio::test]
+    async fn test_outgoing_translation_toggle_skips_translation() {
+        let (store, _dir) = test_store();
+        let contact_id = "34600000000@s.whatsapp.net";
+        store
+            .upsert_contact(contact_id, Some("Ana"), None, Some("private"), 1)
+            .unwrap();
+        store
+            .update_conversation_settings(
+                contact_id,
+                &ConversationSettings {
+                    language_override: Some("Spanish".to_string()),
+                    translation_style: None,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        let (url, hits) = spawn_counting_provider("Hola").await;
+        let translator = TranslationService::new("test-key".to_string(), "English".to_string())
+            .with_api_url(&url);
+        let sending = OutgoingMessageService::new(store.clone(), Some(Arc::new(translator)));
+        let hello = OutgoingMessage {
+            id: pending_message_id(),
+            contact_id: contact_id.to_string(),
+            text: "Hello".to_string(),
+            reply: None,
+            origin: "web".to_string(),
+            sent_by: None,
+        };
+
+        // Sending as typed: no API calls and no usage recorded
+        assert!(!store.toggle_outgoing_translation(contact_id).unwrap());
+        assert!(
+            !store
+                .get_contact(contact_id)
+                .unwrap()
+                .unwrap()
+                .auto_translate_outgoing
+        );
+
+        let outgoing = sending.translate(&hello).await;
+        assert_eq!(outgoing, OutgoingText::as_typed("Hello"));
+        assert!(!outgoing.is_translated());
+        assert_eq!(hits.load(Ordering::SeqCst), 0);
+        assert_eq!(
+            store
+                .get_conversation_usage(contact_id)
+                .unwrap()
+                .input_tokens,
+            0
+        );
+
+        // Turning it back on translates again
+        assert!(store.toggle_outgoing_translation(contact_id).unwrap());
+        let outgoing = sending.translate(&hello).await;
+        assert_eq!(outgoing.text_to_send, "Hola");
+        assert!(outgoing.is_translated());
+        assert_eq!(outgoing.target_language.as_deref(), Some("Spanish"));
+        assert!(hits.load(Ordering::SeqCst) > 0);
+        assert!(
+            store
+                .get_conversation_usage(contact_id)
+                .unwrap()
+                .input_tokens
+                > 0
+        );
+    }
+    #[tokio::test]
+    async fn test_learning_notes_kept_off_the_wire() {
+        let (store, _dir) = test_store();
+        let contact_id = "34600000000@s.whatsapp.net";
+        store
+            .upsert_contact(contact_id, Some("Ana"), None, Some("private"), 1)
+            .unwrap();
+        let mut settings = ConversationSettings {
+            language_override: Some("Spanish".to_string()),
+            ..Default::default()
+        };
+        store
+            .update_conversation_settings(contact_id, &settings)
+            .unwrap();
+
+        let (url, _) = spawn_counting_provider(
+            "Hola amigo\n{\"notes\": [{\"source\": \"friend\", \"target\": \"amigo\"}]}",
+        )
+        .await;
+        let translator = TranslationService::new("test-key".to_string(), "English".to_string())
+            .with_api_url(&url);
+        let sending = OutgoingMessageService::new(store.clone(), Some(Arc::new(translator)));
+        let hello = OutgoingMessage {
+            id: pending_message_id(),
+            contact_id: contact_id.to_string(),
+            text: "Hello friend".to_string(),
+            reply: None,
+            origin: "web".to_string(),
+            sent_by: None,
+        };
+
+        // Off: nothing asked for, the reply is taken as it comes
+        let outgoing = sending.translate(&hello).await;
+        assert!(outgoing.learning_notes.is_empty());
+
+        settings.include_learning_notes = true;
+        store
+            .update_conversation_settings(contact_id, &settings)
+            .unwrap();
+        let outgoing = sending.translate(&hello).await;
+        assert_eq!(outgoing.text_to_send, "Hola amigo");
+        assert_eq!(
+            outgoing.learning_notes,
+            vec![LearningNote {
+                source: "friend".to_string(),
+                target: "amigo".to_string(),
+            }]
+        );
+
+        // Stored with the message as vocabulary
+        let stored = sending.record(&hello, Some(&outgoing));
+        assert_eq!(stored.translated_text.as_deref(), Some("Hola amigo"));
+        let vocabulary = store.get_vocabulary(contact_id).unwrap();
+        assert_eq!(vocabulary.len(), 1);
+        assert_eq!(vocabulary[0].term, "amigo");
+        assert_eq!(vocabulary[0].gloss, "friend");
+    }
+}
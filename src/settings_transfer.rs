@@ -0,0 +1,627 @@
+//! Carrying configuration from one deployment to another.
+//!
+//! An export is one JSON document: the global settings worth carrying over
+//! and each contact's own configuration (language override, translation
+//! style and tone, timezone, translation and notification flags, pinning,
+//! and which group participants are always or never translated). Messages,
+//! secrets such as the web password and push keys, and bookkeeping aren't
+//! in it.
+//!
+//! Importing compares the document with what's here. A setting or contact
+//! field set differently on both sides is a conflict, settled by keeping
+//! ours (`skip`) or taking theirs (`overwrite`); fields still at their
+//! default here are always taken, and nothing is removed. Contacts we don't
+//! have are created as placeholders until WhatsApp tells us about them. The
+//! whole import is one transaction, and a dry run reports the same changes
+//! without making them.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use tracing::error;
+
+use crate::bridge::HistoryDepth;
+use crate::mcp_access::ChatAccessList;
+use crate::storage::{MessageStore, ParticipantTranslationMode, QuietHours, PORTABLE_SETTINGS};
+use crate::translation::{ModelConfig, TranslationTone};
+
+/// Version of the export document
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Contact fields that say who a contact is rather than configure it
+const IDENTITY_FIELDS: &[&str] = &["id", "name", "type"];
+
+/// Global settings and per-contact configuration, as exported
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ConfigExport {
+    pub version: u32,
+    /// When the export was made (ms)
+    #[serde(default)]
+    pub exported_at: Option<i64>,
+    /// Settings by key; JSON settings are embedded as JSON
+    #[serde(default)]
+    pub settings: BTreeMap<String, Value>,
+    #[serde(default)]
+    pub contacts: Vec<ContactConfig>,
+}
+
+/// A contact's own configuration
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct ContactConfig {
+    pub id: String,
+    /// Name and chat type, for creating the contact if it's missing
+    pub name: Option<String>,
+    #[serde(rename = "type")]
+    pub contact_type: Option<String>,
+    pub language_override: Option<String>,
+    pub translation_style: Option<String>,
+    pub translation_tone: Option<TranslationTone>,
+    pub timezone: Option<String>,
+    pub learning_mode: bool,
+    pub include_learning_notes: bool,
+    pub muted: bool,
+    pub triage: Option<bool>,
+    pub mentions_only: bool,
+    /// Whether my messages are translated (None: the default for the chat type)
+    pub auto_translate_outgoing: Option<bool>,
+    pub pinned: bool,
+    /// Group participants whose messages are always or never translated
+    pub translation_participants: BTreeMap<String, ParticipantTranslationMode>,
+}
+
+impl ContactConfig {
+    /// Whether nothing is configured for the contact
+    pub fn is_default(&self) -> bool {
+        config_fields(self) == config_fields(&ContactConfig::default())
+    }
+}
+
+/// The kind of value a portable setting holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingKind {
+    /// "true"; off when unset
+    Flag,
+    /// Whole number of seconds, above zero
+    Seconds,
+    Count,
+    Usd,
+    HistoryDepth,
+    QuietHours,
+    Models,
+    AccessList,
+}
+
+impl SettingKind {
+    /// Check a setting's value, as stored
+    fn check(self, value: &str) -> Result<(), String> {
+        match self {
+            SettingKind::Flag => match value {
+                "true" => Ok(()),
+                _ => Err("expected true".to_string()),
+            },
+            SettingKind::Seconds => match value.parse::<u64>() {
+                Ok(secs) if secs > 0 => Ok(()),
+                _ => Err("expected a number of seconds above 0".to_string()),
+            },
+            SettingKind::Count => value
+                .parse::<u32>()
+                .map(|_| ())
+                .map_err(|_| "expected a whole number".to_string()),
+            SettingKind::Usd => match value.parse::<f64>() {
+                Ok(usd) if usd.is_finite() && usd >= 0.0 => Ok(()),
+                _ => Err("expected an amount in USD".to_string()),
+            },
+            SettingKind::HistoryDepth => value.parse::<HistoryDepth>().map(|_| ()),
+            SettingKind::QuietHours => serde_json::from_str::<QuietHours>(value)
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+            SettingKind::Models => serde_json::from_str::<ModelConfig>(value)
+                .map_err(|e| e.to_string())?
+                .validate()
+                .map_err(|e| e.to_string()),
+            SettingKind::AccessList => serde_json::from_str::<ChatAccessList>(value)
+                .map_err(|e| e.to_string())?
+                .normalized()
+                .map(|_| ()),
+        }
+    }
+}
+
+/// What to do with a setting or contact field set differently here
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictResolution {
+    /// Keep ours
+    #[default]
+    Skip,
+    /// Take theirs
+    Overwrite,
+}
+
+/// What an import did (or would do) to a setting or contact
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportAction {
+    Created,
+    Updated,
+    Unchanged,
+    /// Only conflicting values were imported, and ours were kept
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingImport {
+    pub key: String,
+    pub action: ImportAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContactImport {
+    pub id: String,
+    pub action: ImportAction,
+    /// Fields taken from the import
+    pub changed: Vec<String>,
+    /// Fields set differently here and kept
+    pub conflicts: Vec<String>,
+}
+
+/// What an import did, or with a dry run would have done
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub settings: Vec<SettingImport>,
+    pub contacts: Vec<ContactImport>,
+}
+
+/// Errors returned when importing configuration
+#[derive(Debug, Clone, PartialEq)]
+pub enum SettingsImportError {
+    UnsupportedVersion(u32),
+    UnknownSetting(String),
+    InvalidSetting {
+        key: String,
+        reason: String,
+    },
+    InvalidContact(String),
+    /// The disk is nearly full, so nothing is written
+    ReadOnly,
+    StorageError,
+}
+
+impl SettingsImportError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SettingsImportError::UnsupportedVersion(_) => "unsupported_version",
+            SettingsImportError::UnknownSetting(_) => "unknown_setting",
+            SettingsImportError::InvalidSetting { .. } => "invalid_setting",
+            SettingsImportError::InvalidContact(_) => "invalid_contact",
+            SettingsImportError::ReadOnly => "read_only",
+            SettingsImportError::StorageError => "storage_error",
+        }
+    }
+
+    pub fn description(&self) -> String {
+        match self {
+            SettingsImportError::UnsupportedVersion(version) => format!(
+                "Export version {} isn't supported (expected {})",
+                version, FORMAT_VERSION
+            ),
+            SettingsImportError::UnknownSetting(key) => {
+                format!("Unknown setting \"{}\"", key)
+            }
+            SettingsImportError::InvalidSetting { key, reason } => {
+                format!("Invalid value for setting \"{}\": {}", key, reason)
+            }
+            SettingsImportError::InvalidContact(id) => format!(
+                "Contact \"{}\" must be a WhatsApp ID, like 447911123456@s.whatsapp.net",
+                id
+            ),
+            SettingsImportError::ReadOnly => {
+                "The disk is nearly full; free some space and import again".to_string()
+            }
+            SettingsImportError::StorageError => "Failed to import the settings".to_string(),
+        }
+    }
+}
+
+/// A stored setting as it appears in an export: JSON if it parses as JSON,
+/// otherwise the text
+pub fn export_value(value: &str) -> Value {
+    serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()))
+}
+
+/// An exported setting as it's stored
+fn stored_value(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// A contact's configuration fields by name, without who the contact is
+fn config_fields(config: &ContactConfig) -> serde_json::Map<String, Value> {
+    let mut fields = match serde_json::to_value(config) {
+        Ok(Value::Object(fields)) => fields,
+        _ => serde_json::Map::new(),
+    };
+    for field in IDENTITY_FIELDS {
+        fields.remove(*field);
+    }
+    fields
+}
+
+/// What importing `imported` does to a setting whose value here is `local`
+pub fn merge_setting(
+    local: Option<&str>,
+    imported: &str,
+    on_conflict: ConflictResolution,
+) -> ImportAction {
+    match local {
+        None => ImportAction::Created,
+        Some(local) if export_value(local) == export_value(imported) => ImportAction::Unchanged,
+        Some(_) if on_conflict == ConflictResolution::Overwrite => ImportAction::Updated,
+        Some(_) => ImportAction::Skipped,
+    }
+}
+
+/// A contact's configuration after importing `imported` over `local` (None
+/// if the contact is new), and what changed. The contact keeps its own ID,
+/// name and type.
+pub fn merge_contact(
+    local: Option<&ContactConfig>,
+    imported: &ContactConfig,
+    on_conflict: ConflictResolution,
+) -> (ContactConfig, ContactImport) {
+    let defaults = config_fields(&ContactConfig::default());
+    let base = local.cloned().unwrap_or_else(|| ContactConfig {
+        id: imported.id.clone(),
+        name: imported.name.clone(),
+        contact_type: imported.contact_type.clone(),
+        ..Default::default()
+    });
+
+    let ours = config_fields(&base);
+    let mut merged = ours.clone();
+    let (mut changed, mut conflicts) = (Vec::new(), Vec::new());
+    for (field, theirs) in config_fields(imported) {
+        let mine = &ours[&field];
+        if *mine == theirs {
+            continue;
+        }
+        if *mine == defaults[&field] || on_conflict == ConflictResolution::Overwrite {
+            merged.insert(field.clone(), theirs);
+            changed.push(field);
+        } else {
+            conflicts.push(field);
+        }
+    }
+
+    let merged = ContactConfig {
+        id: base.id.clone(),
+        name: base.name.clone(),
+        contact_type: base.contact_type.clone(),
+        ..serde_json::from_value(Value::Object(merged)).unwrap_or_else(|_| base.clone())
+    };
+    let action = if local.is_none() {
+        ImportAction::Created
+    } else if !changed.is_empty() {
+        ImportAction::Updated
+    } else if !conflicts.is_empty() {
+        ImportAction::Skipped
+    } else {
+        ImportAction::Unchanged
+    };
+    let change = ContactImport {
+        id: base.id,
+        action,
+        changed,
+        conflicts,
+    };
+    (merged, change)
+}
+
+fn storage_error(e: anyhow::Error) -> SettingsImportError {
+    error!("Failed to import settings: {:#}", e);
+    SettingsImportError::StorageError
+}
+
+/// Export the global settings and every configured contact
+pub fn export(store: &MessageStore) -> anyhow::Result<ConfigExport> {
+    let settings = store
+        .get_portable_settings()?
+        .into_iter()
+        .map(|(key, value)| {
+            let value = export_value(&value);
+            (key, value)
+        })
+        .collect();
+    Ok(ConfigExport {
+        version: FORMAT_VERSION,
+        exported_at: Some(chrono::Utc::now().timestamp_millis()),
+        settings,
+        contacts: store.get_contact_configs()?,
+    })
+}
+
+/// Import an export, checking all of it before changing anything
+pub fn import(
+    store: &MessageStore,
+    export: &ConfigExport,
+    on_conflict: ConflictResolution,
+    dry_run: bool,
+) -> Result<ImportReport, SettingsImportError> {
+    if export.version != FORMAT_VERSION {
+        return Err(SettingsImportError::UnsupportedVersion(export.version));
+    }
+
+    let mut settings = Vec::new();
+    for (key, value) in &export.settings {
+        let Some((_, kind)) = PORTABLE_SETTINGS.iter().find(|(name, _)| name == key) else {
+            return Err(SettingsImportError::UnknownSetting(key.clone()));
+        };
+        let value = stored_value(value);
+        kind.check(&value)
+            .map_err(|reason| SettingsImportError::InvalidSetting {
+                key: key.clone(),
+                reason,
+            })?;
+        settings.push((key.clone(), value));
+    }
+
+    let mut contacts = Vec::new();
+    for contact in &export.contacts {
+        let id = contact.id.trim();
+        if !id.contains('@') || id.starts_with('@') {
+            return Err(SettingsImportError::InvalidContact(contact.id.clone()));
+        }
+        let translation_tone = match contact.translation_tone.clone() {
+            Some(tone) => Some(
+                tone.validated()
+                    .ok_or_else(|| SettingsImportError::InvalidContact(contact.id.clone()))?,
+            ),
+            None => None,
+        };
+        contacts.push(ContactConfig {
+            id: id.to_string(),
+            translation_tone,
+            ..contact.clone()
+        });
+    }
+
+    if !dry_run && store.is_read_only() {
+        return Err(SettingsImportError::ReadOnly);
+    }
+    store
+        .import_config(&settings, &contacts, on_conflict, dry_run)
+        .map_err(storage_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_store;
+
+    fn configured_store() -> (MessageStore, tempfile::TempDir) {
+        let (store, dir) = test_store();
+        store
+            .upsert_contact("1@s.whatsapp.net", Some("Ana"), None, Some("private"), 1)
+            .unwrap();
+        store
+            .upsert_contact("2@g.us", Some("Family"), None, Some("group"), 1)
+            .unwrap();
+        store
+            .upsert_contact("3@s.whatsapp.net", Some("Unset"), None, Some("private"), 1)
+            .unwrap();
+
+        let mut settings = store.get_conversation_settings("1@s.whatsapp.net").unwrap();
+        settings.language_override = Some("Spanish".to_string());
+        settings.translation_tone = Some(TranslationTone::Custom("Warm".to_string()));
+        settings.muted = true;
+        store
+            .update_conversation_settings("1@s.whatsapp.net", &settings)
+            .unwrap();
+        store
+            .set_outgoing_translation("1@s.whatsapp.net", Some(false))
+            .unwrap();
+        store.toggle_pin("1@s.whatsapp.net").unwrap();
+        store.toggle_mentions_only("2@g.us").unwrap();
+        store
+            .set_translation_participant(
+                "2@g.us",
+                "9@s.whatsapp.net",
+                Some(ParticipantTranslationMode::Never),
+            )
+            .unwrap();
+
+        store.set_undo_window_secs(30).unwrap();
+        store.set_group_outgoing_translation(true).unwrap();
+        store
+            .set_quiet_hours(Some(&QuietHours {
+                start: chrono::NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+                end: chrono::NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+            }))
+            .unwrap();
+        store.set_vapid_key("secret").unwrap();
+        (store, dir)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (source, _source_dir) = configured_store();
+        let exported = export(&source).unwrap();
+
+        // Only configured contacts and portable settings are exported
+        let ids: Vec<&str> = exported.contacts.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, ["1@s.whatsapp.net", "2@g.us"]);
+        assert_eq!(exported.settings["undo_window_secs"], 30);
+        assert_eq!(exported.settings["push_quiet_hours"]["start"], "22:00:00");
+        assert!(!exported.settings.contains_key("vapid_private_key"));
+
+        // Through JSON into an empty store and back out again
+        let json = serde_json::to_string(&exported).unwrap();
+        let parsed: ConfigExport = serde_json::from_str(&json).unwrap();
+        let (target, _target_dir) = test_store();
+        let report = import(&target, &parsed, ConflictResolution::Skip, false).unwrap();
+        assert!(report
+            .contacts
+            .iter()
+            .all(|c| c.action == ImportAction::Created));
+        assert!(report
+            .settings
+            .iter()
+            .all(|s| s.action == ImportAction::Created));
+
+        let reexported = export(&target).unwrap();
+        assert_eq!(reexported.settings, exported.settings);
+        assert_eq!(reexported.contacts, exported.contacts);
+        assert!(
+            !target
+                .get_outgoing_translation("1@s.whatsapp.net")
+                .unwrap()
+                .enabled
+        );
+        assert_eq!(
+            target.get_quiet_hours().unwrap(),
+            source.get_quiet_hours().unwrap()
+        );
+
+        // Imported contacts are placeholders until WhatsApp sends them
+        let placeholder = |id: &str| target.get_contact(id).unwrap().unwrap().placeholder;
+        assert!(placeholder("1@s.whatsapp.net"));
+        target
+            .upsert_contact("1@s.whatsapp.net", Some("Ana"), None, None, 5)
+            .unwrap();
+        assert!(!placeholder("1@s.whatsapp.net"));
+
+        // Importing again changes nothing
+        let report = import(&target, &parsed, ConflictResolution::Overwrite, false).unwrap();
+        assert!(report
+            .contacts
+            .iter()
+            .all(|c| c.action == ImportAction::Unchanged));
+        assert!(report
+            .settings
+            .iter()
+            .all(|s| s.action == ImportAction::Unchanged));
+    }
+
+    #[test]
+    fn test_conflicts_and_dry_run() {
+        let (store, _dir) = configured_store();
+        let mut exported = export(&store).unwrap();
+        exported
+            .settings
+            .insert("undo_window_secs".to_string(), 60.into());
+        exported
+            .settings
+            .insert("history_depth".to_string(), "recent".into());
+        let ana = &mut exported.contacts[0];
+        ana.language_override = Some("French".to_string());
+        ana.timezone = Some("Europe/Madrid".to_string());
+        exported.contacts.push(ContactConfig {
+            id: "4@s.whatsapp.net".to_string(),
+            name: Some("New".to_string()),
+            muted: true,
+            ..Default::default()
+        });
+
+        // A dry run reports what would change and changes nothing
+        let report = import(&store, &exported, ConflictResolution::Skip, true).unwrap();
+        assert!(report.dry_run);
+        let action = |key: &str| {
+            report
+                .settings
+                .iter()
+                .find(|s| s.key == key)
+                .unwrap()
+                .action
+        };
+        assert_eq!(action("undo_window_secs"), ImportAction::Skipped);
+        assert_eq!(action("history_depth"), ImportAction::Created);
+        assert_eq!(action("push_quiet_hours"), ImportAction::Unchanged);
+        let ana = &report.contacts[0];
+        assert_eq!(ana.action, ImportAction::Updated);
+        assert_eq!(ana.changed, ["timezone"]);
+        assert_eq!(ana.conflicts, ["languageOverride"]);
+        assert_eq!(report.contacts[2].action, ImportAction::Created);
+        assert_eq!(store.get_undo_window_secs().unwrap(), 30);
+        assert!(store.get_contact("4@s.whatsapp.net").unwrap().is_none());
+        assert_eq!(
+            store
+                .get_conversation_settings("1@s.whatsapp.net")
+                .unwrap()
+                .timezone,
+            None
+        );
+
+        // Skipping keeps our values but takes the unset ones
+        import(&store, &exported, ConflictResolution::Skip, false).unwrap();
+        let settings = store.get_conversation_settings("1@s.whatsapp.net").unwrap();
+        assert_eq!(settings.language_override.as_deref(), Some("Spanish"));
+        assert_eq!(settings.timezone.as_deref(), Some("Europe/Madrid"));
+        assert_eq!(store.get_undo_window_secs().unwrap(), 30);
+        assert!(
+            store
+                .get_conversation_settings("4@s.whatsapp.net")
+                .unwrap()
+                .muted
+        );
+
+        // Overwriting takes theirs
+        import(&store, &exported, ConflictResolution::Overwrite, false).unwrap();
+        let settings = store.get_conversation_settings("1@s.whatsapp.net").unwrap();
+        assert_eq!(settings.language_override.as_deref(), Some("French"));
+        assert_eq!(store.get_undo_window_secs().unwrap(), 60);
+    }
+
+    #[test]
+    fn test_invalid_imports_change_nothing() {
+        let (store, _dir) = configured_store();
+        let exported = export(&store).unwrap();
+
+        // Unknown keys anywhere in the document are rejected
+        let mut json = serde_json::to_value(&exported).unwrap();
+        json["contacts"][0]["tags"] = serde_json::json!(["work"]);
+        let error = serde_json::from_value::<ConfigExport>(json).unwrap_err();
+        assert!(error.to_string().contains("unknown field `tags`"));
+
+        let mut unknown = exported.clone();
+        unknown
+            .settings
+            .insert("web_password_hash".to_string(), "x".into());
+        assert_eq!(
+            import(&store, &unknown, ConflictResolution::Overwrite, false),
+            Err(SettingsImportError::UnknownSetting(
+                "web_password_hash".to_string()
+            ))
+        );
+
+        let mut invalid = exported.clone();
+        invalid.contacts[0].muted = false;
+        invalid
+            .settings
+            .insert("undo_window_secs".to_string(), "soon".into());
+        assert!(matches!(
+            import(&store, &invalid, ConflictResolution::Overwrite, false),
+            Err(SettingsImportError::InvalidSetting { .. })
+        ));
+        assert!(
+            store
+                .get_conversation_settings("1@s.whatsapp.net")
+                .unwrap()
+                .muted
+        );
+
+        let mut future = exported;
+        future.version = FORMAT_VERSION + 1;
+        assert_eq!(
+            import(&store, &future, ConflictResolution::Skip, false),
+            Err(SettingsImportError::UnsupportedVersion(FORMAT_VERSION + 1))
+        );
+    }
+}
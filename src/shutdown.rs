@@ -0,0 +1,141 @@
+//! Coordinated shutdown.
+//!
+//! Ctrl+C starts a drain phase before the bridge is stopped: new API
+//! requests are refused with 503, background loops stop, and work already
+//! in flight (requests, held-back sends, push deliveries) gets until the
+//! drain deadline to finish. Whatever is still running then is abandoned
+//! and counted in the final report.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{watch, Notify};
+
+/// How long in-flight work gets to finish by default (seconds)
+pub const DEFAULT_DRAIN_SECS: u64 = 10;
+
+/// Tells background tasks when to stop and waits for in-flight work
+#[derive(Clone)]
+pub struct ShutdownController {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    /// true once shutdown has begun
+    shutting_down: watch::Sender<bool>,
+    /// Work in flight, by kind
+    in_flight: Mutex<BTreeMap<&'static str, usize>>,
+    /// Work that finished after shutdown began
+    drained: Mutex<usize>,
+    /// Woken whenever work finishes
+    finished: Notify,
+}
+
+/// What the drain phase got through
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// Work that finished during the drain phase
+    pub drained: usize,
+    /// Work still running at the deadline, by kind
+    pub abandoned: BTreeMap<&'static str, usize>,
+}
+
+impl ShutdownReport {
+    pub fn abandoned_total(&self) -> usize {
+        self.abandoned.values().sum()
+    }
+}
+
+/// Marks a piece of work as in flight until it's dropped
+pub struct WorkGuard {
+    inner: Arc<Inner>,
+    kind: &'static str,
+}
+
+impl Default for ShutdownController {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                shutting_down: watch::channel(false).0,
+                in_flight: Mutex::new(BTreeMap::new()),
+                drained: Mutex::new(0),
+                finished: Notify::new(),
+            }),
+        }
+    }
+}
+
+impl ShutdownController {
+    pub fn is_shutting_down(&self) -> bool {
+        *self.inner.shutting_down.borrow()
+    }
+
+    /// A receiver that turns true when shutdown begins, for tasks that
+    /// select on it
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.inner.shutting_down.subscribe()
+    }
+
+    /// Resolves once shutdown has begun
+    pub async fn wait(&self) {
+        let mut rx = self.subscribe();
+        let _ = rx.wait_for(|shutting_down| *shutting_down).await;
+    }
+
+    /// Count a piece of work as in flight until the guard is dropped
+    pub fn track(&self, kind: &'static str) -> WorkGuard {
+        *self
+            .inner
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(kind)
+            .or_default() += 1;
+        WorkGuard {
+            inner: Arc::clone(&self.inner),
+            kind,
+        }
+    }
+
+    /// Start shutting down. Returns false if it had already started.
+    pub fn begin(&self) -> bool {
+        !self.inner.shutting_down.send_replace(true)
+    }
+
+    /// Wait for in-flight work to finish, up to `deadline`
+    pub async fn drain(&self, deadline: Duration) -> ShutdownReport {
+        let _ = tokio::time::timeout(deadline, async {
+            loop {
+                let finished = self.inner.finished.notified();
+                if self.inner.in_flight.lock().unwrap().is_empty() {
+                    return;
+                }
+                finished.await;
+            }
+        })
+        .await;
+
+        ShutdownReport {
+            drained: *self.inner.drained.lock().unwrap(),
+            abandoned: self.inner.in_flight.lock().unwrap().clone(),
+        }
+    }
+}
+
+impl Drop for WorkGuard {
+    fn drop(&mut self) {
+        {
+            let mut in_flight = self.inner.in_flight.lock().unwrap();
+            if let Some(count) = in_flight.get_mut(self.kind) {
+                *count -= 1;
+                if *count == 0 {
+                    in_flight.remove(self.kind);
+                }
+            }
+        }
+        if *self.inner.shutting_down.borrow() {
+            *self.inner.drained.lock().unwrap() += 1;
+        }
+        self.inner.finished.notify_waiters();
+    }
+}
@@ -0,0 +1,297 @@
+//! Journal of writes that failed with a disk error.
+//!
+//! A message that reached WhatsApp (or came from it) shouldn't vanish from
+//! history because SQLite hit a transient I/O error. Such writes are appended
+//! to an NDJSON file in the data directory, synced to disk, and replayed into
+//! the database in order once writes work again. Messages are inserted with
+//! `INSERT OR IGNORE`, and usage rows carry the ID of the spilled write, so
+//! replaying one that did get stored is harmless.
+//!
+//! A database that's busy or locked by another connection isn't a disk
+//! error: those writes are tried again a few times and otherwise fail.
+
+use anyhow::{Context, Result};
+use rusqlite::ErrorCode;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use tracing::warn;
+
+use crate::storage::StoredMessage;
+use crate::translation::UsageInfo;
+
+/// Journal file in the data directory
+pub const SPILL_FILE: &str = "spill.ndjson";
+
+/// How often spilled writes are retried
+pub const RECOVERY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Disk errors in a row that put the store in read-only mode
+pub const DEGRADE_AFTER_FAILURES: u32 = 3;
+
+/// Extra attempts at a write that found the database busy or locked
+pub const BUSY_RETRIES: u32 = 3;
+
+/// Wait before each of those attempts
+pub const BUSY_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// A write waiting to be replayed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SpilledWrite {
+    /// A message as `StoredMessage` JSON, with `contentJson` included
+    Message { message: serde_json::Value },
+    Contact {
+        id: String,
+        name: Option<String>,
+        phone: Option<String>,
+        contact_type: Option<String>,
+        last_message_time: i64,
+    },
+    Usage {
+        /// Stored with the rows so they're only inserted once (none in
+        /// journals written before it was added)
+        #[serde(default)]
+        spill_id: Option<String>,
+        contact_id: Option<String>,
+        message_id: Option<String>,
+        usage: UsageInfo,
+        operation: String,
+        timestamp: i64,
+    },
+}
+
+impl SpilledWrite {
+    pub fn message(msg: &StoredMessage) -> Self {
+        let mut message = serde_json::to_value(msg).unwrap_or_default();
+        if let Some(obj) = message.as_object_mut() {
+            // The API form leaves out the raw content and has it parsed instead
+            obj.remove("content");
+            obj.insert("contentJson".to_string(), msg.content_json.clone().into());
+        }
+        SpilledWrite::Message { message }
+    }
+}
+
+/// The SQLite error codes a failed write carries
+fn sqlite_codes(e: &anyhow::Error) -> impl Iterator<Item = ErrorCode> + '_ {
+    e.chain()
+        .filter_map(|cause| match cause.downcast_ref::<rusqlite::Error>() {
+            Some(rusqlite::Error::SqliteFailure(failure, _)) => Some(failure.code),
+            _ => None,
+        })
+}
+
+/// Whether a failed write is worth journaling: the disk or database file
+/// misbehaved, rather than the write itself being wrong
+pub fn is_disk_error(e: &anyhow::Error) -> bool {
+    sqlite_codes(e).any(|code| {
+        matches!(
+            code,
+            ErrorCode::SystemIoFailure
+                | ErrorCode::DiskFull
+                | ErrorCode::ReadOnly
+                | ErrorCode::CannotOpen
+        )
+    })
+}
+
+/// Whether a write failed because another connection held the database
+pub fn is_busy_error(e: &anyhow::Error) -> bool {
+    sqlite_codes(e).any(|code| matches!(code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked))
+}
+
+/// Run a write, trying it again while the database is busy or locked
+pub fn retry_when_busy<T>(mut write: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut retries = 0;
+    loop {
+        match write() {
+            Err(e) if retries < BUSY_RETRIES && is_busy_error(&e) => {
+                retries += 1;
+                warn!(
+                    "Database busy, retrying write ({}/{})",
+                    retries, BUSY_RETRIES
+                );
+                std::thread::sleep(BUSY_RETRY_DELAY);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// The spill file, shared by all clones of the store
+pub struct SpillJournal {
+    path: PathBuf,
+    /// Held while appending or replaying, so a replay sees every append
+    lock: Mutex<()>,
+    depth: AtomicUsize,
+    /// Disk errors since the last successful write
+    failures: AtomicU32,
+}
+
+impl SpillJournal {
+    pub fn open(data_dir: &Path) -> Self {
+        let journal = Self {
+            path: data_dir.join(SPILL_FILE),
+            lock: Mutex::new(()),
+            depth: AtomicUsize::new(0),
+            failures: AtomicU32::new(0),
+        };
+        let depth = journal
+            .read()
+            .map(|writes| writes.len())
+            .unwrap_or_else(|e| {
+                warn!("{:#}", e);
+                0
+            });
+        if depth > 0 {
+            warn!("{} writes are waiting in {:?}", depth, journal.path);
+        }
+        journal.depth.store(depth, Ordering::Relaxed);
+        journal
+    }
+
+    /// Writes waiting to be replayed
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    /// Count a disk error, returning true once there have been enough in a
+    /// row to stop writing
+    pub fn record_failure(&self) -> bool {
+        self.failures.fetch_add(1, Ordering::Relaxed) + 1 >= DEGRADE_AFTER_FAILURES
+    }
+
+    pub fn record_success(&self) {
+        self.failures.store(0, Ordering::Relaxed);
+    }
+
+    /// Append a write and sync it to disk
+    pub fn append(&self, write: &SpilledWrite) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut line = serde_json::to_string(write)?;
+        line.push('\n');
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open {:?}", self.path))?;
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Lock the journal for a replay, returning its writes oldest first
+    pub fn begin_replay(&self) -> Result<(MutexGuard<'_, ()>, Vec<SpilledWrite>)> {
+        let guard = self.lock.lock().unwrap();
+        let writes = self.read()?;
+        Ok((guard, writes))
+    }
+
+    /// Finish a replay, keeping the writes that weren't replayed
+    pub fn finish_replay(
+        &self,
+        _guard: MutexGuard<'_, ()>,
+        remaining: &[SpilledWrite],
+    ) -> Result<()> {
+        if remaining.is_empty() {
+            match std::fs::remove_file(&self.path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Failed to remove {:?}", self.path))
+                }
+            }
+        } else {
+            let tmp = self.path.with_extension("ndjson.tmp");
+            let mut file = std::fs::File::create(&tmp)
+                .with_context(|| format!("Failed to create {:?}", tmp))?;
+            for write in remaining {
+                serde_json::to_writer(&mut file, write)?;
+                file.write_all(b"\n")?;
+            }
+            file.sync_data()?;
+            std::fs::rename(&tmp, &self.path)
+                .with_context(|| format!("Failed to replace {:?}", self.path))?;
+        }
+        self.depth.store(remaining.len(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// The journaled writes, skipping lines that can't be parsed (such as
+    /// one cut short by a crash)
+    fn read(&self) -> Result<Vec<SpilledWrite>> {
+        let file = match std::fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", self.path)),
+        };
+        let mut writes = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line.with_context(|| format!("Failed to read {:?}", self.path))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(write) => writes.push(write),
+                Err(e) => warn!("Skipping unreadable spilled write: {}", e),
+            }
+        }
+        Ok(writes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn sqlite_error(code: i32) -> anyhow::Error {
+        anyhow::Error::from(rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(code),
+            None,
+        ))
+        .context("Failed to write")
+    }
+
+    #[test]
+    fn test_busy_writes_are_retried_not_spilled() {
+        let busy = sqlite_error(rusqlite::ffi::SQLITE_BUSY);
+        assert!(is_busy_error(&busy) && !is_disk_error(&busy));
+        let locked = sqlite_error(rusqlite::ffi::SQLITE_LOCKED);
+        assert!(is_busy_error(&locked) && !is_disk_error(&locked));
+        let io = sqlite_error(rusqlite::ffi::SQLITE_IOERR);
+        assert!(is_disk_error(&io) && !is_busy_error(&io));
+
+        // Busy until the second retry
+        let attempts = Cell::new(0);
+        let result = retry_when_busy(|| {
+            attempts.set(attempts.get() + 1);
+            match attempts.get() {
+                1 | 2 => Err(sqlite_error(rusqlite::ffi::SQLITE_BUSY)),
+                _ => Ok(attempts.get()),
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+
+        // Gives up after the last retry, and doesn't retry other errors
+        attempts.set(0);
+        let result: Result<()> = retry_when_busy(|| {
+            attempts.set(attempts.get() + 1);
+            Err(sqlite_error(rusqlite::ffi::SQLITE_BUSY))
+        });
+        assert!(is_busy_error(&result.unwrap_err()));
+        assert_eq!(attempts.get(), BUSY_RETRIES + 1);
+        attempts.set(0);
+        let result: Result<()> = retry_when_busy(|| {
+            attempts.set(attempts.get() + 1);
+            Err(sqlite_error(rusqlite::ffi::SQLITE_IOERR))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+}
@@ -0,0 +1,253 @@
+//! Sticker formats and previews.
+//!
+//! Stickers are WebP images, still or animated, or Lottie animations (JSON,
+//! gzipped as TGS). The bridge's mime type and `is_animated` flag don't say
+//! which, so the format is read from the bytes and kept with the message.
+//! WebP stickers get a PNG of their first frame for previews; Lottie ones
+//! are handed to the browser as JSON to play and have no server-side preview.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::{codecs::png::PngEncoder, ImageFormat};
+use std::io::Read;
+use tracing::debug;
+
+use crate::storage::StoredMessage;
+use crate::thumbnail;
+
+/// Longest side of a sticker preview, in pixels
+const PREVIEW_SIZE: u32 = 160;
+
+/// Largest Lottie animation unpacked from a TGS payload
+const MAX_LOTTIE_BYTES: u64 = 8 * 1024 * 1024;
+
+/// Content key holding the sticker's format
+pub const FORMAT_KEY: &str = "sticker_format";
+
+/// What a sticker's media actually is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StickerFormat {
+    Webp,
+    AnimatedWebp,
+    Lottie,
+    /// Gzipped Lottie
+    Tgs,
+}
+
+impl StickerFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StickerFormat::Webp => "webp",
+            StickerFormat::AnimatedWebp => "animated_webp",
+            StickerFormat::Lottie => "lottie",
+            StickerFormat::Tgs => "tgs",
+        }
+    }
+
+    /// Content type the media is served with
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            StickerFormat::Webp | StickerFormat::AnimatedWebp => "image/webp",
+            StickerFormat::Lottie => "application/json",
+            StickerFormat::Tgs => "application/x-tgsticker",
+        }
+    }
+
+    pub fn is_animated(&self) -> bool {
+        !matches!(self, StickerFormat::Webp)
+    }
+}
+
+/// The WebP format of `bytes`, from the RIFF header alone
+pub fn detect_webp(bytes: &[u8]) -> Option<StickerFormat> {
+    if bytes.len() < 16 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WEBP" {
+        return None;
+    }
+    // Only the extended format can be animated, flagged in its header
+    let animated = &bytes[12..16] == b"VP8X" && bytes.get(20).is_some_and(|f| f & 0x02 != 0);
+    Some(match animated {
+        true => StickerFormat::AnimatedWebp,
+        false => StickerFormat::Webp,
+    })
+}
+
+/// The format of a sticker's `bytes`, or None if it isn't one we know
+pub fn detect(bytes: &[u8]) -> Option<StickerFormat> {
+    if let Some(format) = detect_webp(bytes) {
+        return Some(format);
+    }
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        return gunzip(bytes)
+            .filter(|json| is_lottie(json))
+            .map(|_| StickerFormat::Tgs);
+    }
+    is_lottie(bytes).then_some(StickerFormat::Lottie)
+}
+
+/// Whether `bytes` are a Lottie animation's JSON
+fn is_lottie(bytes: &[u8]) -> bool {
+    let trimmed = bytes.trim_ascii_start();
+    if !trimmed.starts_with(b"{") {
+        return false;
+    }
+    serde_json::from_slice::<serde_json::Value>(trimmed).is_ok_and(|json| {
+        json.get("v").is_some() && json.get("layers").is_some_and(|l| l.is_array())
+    })
+}
+
+fn gunzip(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut json = Vec::new();
+    flate2::read::GzDecoder::new(bytes)
+        .take(MAX_LOTTIE_BYTES + 1)
+        .read_to_end(&mut json)
+        .ok()?;
+    (json.len() as u64 <= MAX_LOTTIE_BYTES).then_some(json)
+}
+
+/// The Lottie JSON of a Lottie or TGS sticker
+pub fn lottie_json(bytes: &[u8]) -> Option<Vec<u8>> {
+    match detect(bytes)? {
+        StickerFormat::Lottie => Some(bytes.to_vec()),
+        StickerFormat::Tgs => gunzip(bytes),
+        StickerFormat::Webp | StickerFormat::AnimatedWebp => None,
+    }
+}
+
+/// A PNG of a WebP sticker's first frame, shrunk to `PREVIEW_SIZE`
+pub fn first_frame_png(bytes: &[u8]) -> Option<Vec<u8>> {
+    detect_webp(bytes)?;
+    let image = match image::load_from_memory_with_format(bytes, ImageFormat::WebP) {
+        Ok(image) => image,
+        Err(e) => {
+            debug!("Failed to decode sticker: {}", e);
+            return None;
+        }
+    };
+    let preview = if image.width() > PREVIEW_SIZE || image.height() > PREVIEW_SIZE {
+        image.thumbnail(PREVIEW_SIZE, PREVIEW_SIZE)
+    } else {
+        image
+    };
+    let mut png = Vec::new();
+    preview
+        .to_rgba8()
+        .write_with_encoder(PngEncoder::new(&mut png))
+        .ok()?;
+    Some(png)
+}
+
+/// A sticker's format and base64 PNG preview, from its base64 media data
+fn inspect(media_data: &str) -> (Option<StickerFormat>, Option<String>) {
+    let Ok(bytes) = STANDARD.decode(media_data.trim()) else {
+        return (None, None);
+    };
+    let preview = first_frame_png(&bytes).map(|png| STANDARD.encode(png));
+    (detect(&bytes), preview)
+}
+
+/// Record a sticker message's format, correct its mime type and add its
+/// preview as the thumbnail, on a blocking thread
+pub async fn attach(message: &mut StoredMessage) {
+    let Ok(mut content) = serde_json::from_str::<serde_json::Value>(&message.content_json) else {
+        return;
+    };
+    if content.get("type").and_then(|t| t.as_str()) != Some("sticker")
+        || content.get(FORMAT_KEY).is_some()
+    {
+        return;
+    }
+    let Some(media_data) = content
+        .get("media_data")
+        .or_else(|| content.get("mediaData"))
+        .and_then(|v| v.as_str())
+        .map(str::to_string)
+    else {
+        return;
+    };
+
+    let (format, preview) = tokio::task::spawn_blocking(move || inspect(&media_data))
+        .await
+        .unwrap_or((None, None));
+    if let Some(format) = format {
+        content[FORMAT_KEY] = format.as_str().into();
+        content["mime_type"] = format.mime_type().into();
+        content["is_animated"] = format.is_animated().into();
+    }
+    content[thumbnail::CONTENT_KEY] = preview.into();
+    message.content_json = content.to_string();
+    message.content = Some(content);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::text_message;
+
+    const STATIC_WEBP: &[u8] = include_bytes!("../tests/fixtures/stickers/static.webp");
+    const ANIMATED_WEBP: &[u8] = include_bytes!("../tests/fixtures/stickers/animated.webp");
+    const LOTTIE: &[u8] = include_bytes!("../tests/fixtures/stickers/lottie.json");
+    const TGS: &[u8] = include_bytes!("../tests/fixtures/stickers/animated.tgs");
+
+    #[test]
+    fn test_detect_formats() {
+        assert_eq!(detect(STATIC_WEBP), Some(StickerFormat::Webp));
+        assert_eq!(detect(ANIMATED_WEBP), Some(StickerFormat::AnimatedWebp));
+        assert_eq!(detect(LOTTIE), Some(StickerFormat::Lottie));
+        assert_eq!(detect(TGS), Some(StickerFormat::Tgs));
+
+        // Other JSON, gzip and images aren't stickers
+        assert_eq!(detect(br#"{"v": 1}"#), None);
+        assert_eq!(detect(&TGS[..20]), None);
+        assert_eq!(detect(b"\x89PNG\r\n\x1a\n"), None);
+        assert_eq!(detect(&ANIMATED_WEBP[..8]), None);
+        assert_eq!(detect(b""), None);
+
+        // Both Lottie payloads give the same animation
+        assert_eq!(lottie_json(TGS).as_deref(), Some(LOTTIE));
+        assert_eq!(lottie_json(LOTTIE).as_deref(), Some(LOTTIE));
+        assert_eq!(lottie_json(ANIMATED_WEBP), None);
+    }
+
+    #[test]
+    fn test_first_frame_of_static_and_animated_webp() {
+        // The fixtures are 96x96 with a 64x64 square in the middle: red in
+        // the still one, red then blue in the animated one
+        for sticker in [STATIC_WEBP, ANIMATED_WEBP] {
+            let png = first_frame_png(sticker).unwrap();
+            assert_eq!(image::guess_format(&png).unwrap(), ImageFormat::Png);
+            let frame = image::load_from_memory(&png).unwrap().to_rgba8();
+            assert_eq!(frame.dimensions(), (96, 96));
+            // Lossy frames come back within a step or two of the colour
+            let [r, g, b, a] = frame.get_pixel(48, 48).0;
+            assert!(r.abs_diff(220) <= 4 && g.abs_diff(30) <= 4 && b.abs_diff(30) <= 4);
+            assert_eq!(a, 255);
+            // Transparency survives
+            assert_eq!(frame.get_pixel(2, 2).0[3], 0);
+        }
+
+        // Lottie has no server-side preview, and corrupt WebP doesn't panic
+        assert_eq!(first_frame_png(LOTTIE), None);
+        assert_eq!(first_frame_png(&ANIMATED_WEBP[..60]), None);
+    }
+
+    #[tokio::test]
+    async fn test_attach_records_format() {
+        let content = serde_json::json!({
+            "type": "sticker",
+            "mime_type": "image/webp",
+            "is_animated": true,
+            "media_data": STANDARD.encode(TGS),
+        });
+        let mut message = StoredMessage {
+            content_type: "Sticker".to_string(),
+            content_json: content.to_string(),
+            content: Some(content),
+            original_text: None,
+            ..text_message("sticker", "a@s.whatsapp.net", 1, "")
+        };
+        attach(&mut message).await;
+        let content = message.content.unwrap();
+        assert_eq!(content[FORMAT_KEY], "tgs");
+        assert_eq!(content["mime_type"], "application/x-tgsticker");
+        assert!(content[thumbnail::CONTENT_KEY].is_null());
+    }
+}
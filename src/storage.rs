@@ -1,18 +1,40 @@
 //! SQLite storage for messages and contacts.
 
 use anyhow::{Context, Result};
-use rusqlite::{params, Connection};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use rusqlite::{params, Connection, OptionalExtension};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
 use std::sync::{Arc, Mutex};
-use tracing::info;
-
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+use crate::audio::AudioInfo;
+use crate::bridge::{unknown_text_fields, HistoryDepth};
+use crate::chat_search::{self, ChatSearchHit};
+use crate::contact_cache::{ContactCache, ContactCacheStats};
+use crate::disk_guard::{DiskStatus, Transition, WriteProtection, DEFAULT_MIN_FREE_BYTES};
+use crate::jobs::{JobRecord, JobState};
 use crate::link_preview::LinkPreview;
+use crate::mcp_access::{ChatAccessList, McpAccessLists};
+use crate::name_search::{self, NameMatch};
 use crate::oauth::{AccessToken, AuthorizationCode, PendingAuthorization, RefreshToken};
-use crate::translation::UsageInfo;
+use crate::settings_transfer::{
+    self, ConflictResolution, ContactConfig, ImportAction, ImportReport, SettingImport, SettingKind,
+};
+use crate::spill::{is_disk_error, retry_when_busy, SpillJournal, SpilledWrite};
+use crate::translation::{
+    ModelConfig, Tone, TranslationStatus, TranslationTone, Triage, Urgency, UsageInfo, VocabEntry,
+};
 
 /// Stored message with translation info
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct StoredMessage {
     pub id: String,
@@ -52,6 +74,72 @@ pub struct StoredMessage {
     pub source_language: Option<String>,
     #[serde(rename = "isTranslated")]
     pub is_translated: bool,
+    /// What sent an outgoing message: "web", "mcp:<client_id>", "rule:<id>",
+    /// "schedule:<id>" or "api". None for messages that came from WhatsApp.
+    #[serde(default)]
+    pub origin: Option<String>,
+    /// Who sent an outgoing message: the name of the web session it was sent
+    /// from, or the MCP client. Only kept here, never sent to WhatsApp.
+    #[serde(rename = "sentBy", default)]
+    pub sent_by: Option<String>,
+    /// JIDs @-mentioned in the message
+    #[serde(default)]
+    pub mentioned_jids: Vec<String>,
+    /// Whether the message @-mentions me
+    #[serde(default)]
+    pub mentions_me: bool,
+    /// Words picked out in learning mode, only sent while the chat has it on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vocabulary: Option<Vec<VocabEntry>>,
+    /// Duration and waveform of audio messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio: Option<AudioInfo>,
+    /// Position in the conversation, assigned when the message is stored:
+    /// the timestamp in ms * 1000, plus a counter that keeps messages
+    /// stored within the same second (or ms) in arrival order
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort_key: Option<i64>,
+    /// Urgency and tone of an incoming message, if it was triaged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub triage: Option<Triage>,
+    /// Why the message was or wasn't translated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub translation_status: Option<TranslationStatus>,
+}
+
+impl StoredMessage {
+    /// A notice about the chat itself (e.g. the group subject changed), shown
+    /// in the timeline but never translated, previewed or counted as unread
+    pub fn system(contact_id: &str, chat_type: &str, timestamp: i64, text: &str) -> Self {
+        Self {
+            id: format!("system-{}", uuid::Uuid::new_v4()),
+            contact_id: contact_id.to_string(),
+            timestamp,
+            is_from_me: false,
+            is_forwarded: false,
+            sender_name: None,
+            sender_phone: None,
+            contact_name: None,
+            contact_phone: None,
+            chat_type: chat_type.to_string(),
+            content_type: SYSTEM_CONTENT_TYPE.to_string(),
+            content_json: serde_json::json!({ "type": "system", "text": text }).to_string(),
+            content: None,
+            original_text: None,
+            translated_text: None,
+            source_language: None,
+            is_translated: false,
+            origin: None,
+            sent_by: None,
+            mentioned_jids: Vec::new(),
+            mentions_me: false,
+            vocabulary: None,
+            audio: None,
+            sort_key: None,
+            triage: None,
+            translation_status: None,
+        }
+    }
 }
 
 /// Stored contact
@@ -73,6 +161,568 @@ pub struct StoredContact {
     /// Preview of the last message (truncated)
     #[serde(rename = "lastMessagePreview")]
     pub last_message_preview: Option<String>,
+    /// Whether outgoing messages are translated (false = send exactly as
+    /// typed). Private chats without their own setting also need a clear
+    /// conversation language; see `get_outgoing_translation`.
+    pub auto_translate_outgoing: bool,
+    /// Whether only messages that mention me count as unread (groups)
+    pub mentions_only: bool,
+    /// When the contact was first seen (ms)
+    #[serde(default)]
+    pub created_at: Option<i64>,
+    /// When the contact's name, phone or type last changed (ms)
+    #[serde(default)]
+    pub updated_at: Option<i64>,
+    /// When the contact was last seen online (ms), if they share it
+    #[serde(default)]
+    pub last_seen: Option<i64>,
+    /// Timestamp of the last message read (ms); incoming messages after it
+    /// are unread. None if the chat was never read
+    #[serde(default)]
+    pub last_read_timestamp: Option<i64>,
+    /// Group description, if it has one
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Disappearing messages timer in seconds (None = off)
+    #[serde(default)]
+    pub ephemeral_duration: Option<u32>,
+    /// Created by a settings import and not yet seen on WhatsApp
+    #[serde(default)]
+    pub placeholder: bool,
+}
+
+/// The first unread message in a chat, where the UI scrolls to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FirstUnread {
+    pub message_id: String,
+    pub timestamp: i64,
+}
+
+/// Reactions to a message with the same emoji
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactionGroup {
+    pub emoji: String,
+    pub count: usize,
+    /// Whether one of them is my reaction
+    pub from_me: bool,
+    /// Phone numbers of who reacted, "me" for my reaction if my number isn't known
+    pub reactors: Vec<String>,
+}
+
+/// How often an emoji was used
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EmojiCount {
+    pub emoji: String,
+    pub count: u32,
+}
+
+/// Someone who reacts in a chat and how often
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReactorCount {
+    /// Phone number of who reacted
+    pub reactor: String,
+    pub name: Option<String>,
+    pub count: u32,
+}
+
+/// Reactions in a chat since a point in time, counting each person's
+/// current reaction to a message once
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReactionStats {
+    pub since: i64,
+    pub total: u32,
+    /// Every emoji used, most used first
+    pub emojis: Vec<EmojiCount>,
+    /// Who reacts most, not counting me
+    pub top_reactors: Vec<ReactorCount>,
+    /// The emojis I use most
+    pub my_top_reactions: Vec<EmojiCount>,
+}
+
+/// Most entries in the top reactor and my-top lists
+const TOP_REACTIONS_LIMIT: u32 = 5;
+
+/// What `upsert_contact` changed about a contact
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ContactChange {
+    /// The contact didn't exist before
+    Created,
+    /// The name changed (`old` is None if it had no name)
+    NameChanged { old: Option<String>, new: String },
+    /// The phone number or chat type changed
+    DetailsChanged,
+    /// Nothing changed apart from the last message time
+    #[default]
+    Unchanged,
+}
+
+/// A contact update for `upsert_contacts_batch`, as `upsert_contact` takes it
+#[derive(Debug, Clone, Copy)]
+pub struct ContactUpsert<'a> {
+    pub id: &'a str,
+    pub name: Option<&'a str>,
+    pub phone: Option<&'a str>,
+    pub contact_type: Option<&'a str>,
+    pub last_message_time: i64,
+}
+
+/// What `add_messages_batch` did with a message
+#[derive(Debug, Default)]
+pub struct BatchedWrite {
+    /// Its sort key, None if it was already stored (or buffered or spilled)
+    pub sort_key: Option<i64>,
+    /// The copy stored when I sent it, if this was WhatsApp's copy
+    pub claimed: Option<ClaimedPending>,
+}
+
+/// Which of a chat's metadata fields `set_chat_metadata` changed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChatMetadataChange {
+    pub description: bool,
+    pub ephemeral_duration: bool,
+}
+
+impl ChatMetadataChange {
+    pub fn any(&self) -> bool {
+        self.description || self.ephemeral_duration
+    }
+}
+
+/// A translated message's original text and translation
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TranslationPair {
+    pub message_id: String,
+    pub timestamp: i64,
+    /// "incoming" or "outgoing"
+    pub direction: &'static str,
+    /// What was written (for sent messages, what the user typed)
+    pub original_text: Option<String>,
+    /// The translation (for sent messages, what was actually sent)
+    pub translated_text: Option<String>,
+    /// The conversation's foreign language
+    pub source_language: Option<String>,
+}
+
+/// What clearing a conversation deleted
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClearedConversation {
+    pub messages: usize,
+    /// Media blobs no longer used by any message
+    pub media_blobs_freed: usize,
+    /// Translation usage rows (only deleted when asked for)
+    pub usage_records: usize,
+}
+
+/// A locally stored sent message that WhatsApp's copy of it took over
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClaimedPending {
+    /// The temporary ID it was stored under
+    pub pending_id: String,
+    pub sort_key: i64,
+}
+
+/// Where a page of messages stopped: its oldest message's sort key and
+/// rowid, which together are unique and never change. Opaque to clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageCursor {
+    sort_key: i64,
+    rowid: i64,
+}
+
+impl MessageCursor {
+    pub fn encode(&self) -> String {
+        encode_cursor('m', &[self.sort_key, self.rowid])
+    }
+
+    /// None if the token isn't a message cursor
+    pub fn decode(token: &str) -> Option<Self> {
+        let [sort_key, rowid] = decode_cursor('m', token)?;
+        Some(Self { sort_key, rowid })
+    }
+}
+
+/// Where a page of contacts stopped, by the contact list's order: the self
+/// chat, then pinned chats by when they were pinned, then the most recently
+/// active, with the rowid breaking ties. Opaque to clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContactCursor {
+    rank: i64,
+    pinned_at: i64,
+    last_message_time: i64,
+    rowid: i64,
+}
+
+impl ContactCursor {
+    pub fn encode(&self) -> String {
+        encode_cursor(
+            'c',
+            &[
+                self.rank,
+                self.pinned_at,
+                self.last_message_time,
+                self.rowid,
+            ],
+        )
+    }
+
+    /// None if the token isn't a contact cursor
+    pub fn decode(token: &str) -> Option<Self> {
+        let [rank, pinned_at, last_message_time, rowid] = decode_cursor('c', token)?;
+        Some(Self {
+            rank,
+            pinned_at,
+            last_message_time,
+            rowid,
+        })
+    }
+}
+
+/// A page of find-in-chat results
+#[derive(Debug, Default)]
+pub struct ChatSearchPage {
+    pub hits: Vec<ChatSearchHit>,
+    /// Matches in the whole chat, not just this page
+    pub total_matches: usize,
+    pub next_cursor: Option<MessageCursor>,
+}
+
+/// Which of a chat's messages to get: all by default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessageFilter<'a> {
+    /// Sent by this origin ("web", "mcp", "mcp:<client_id>", ...)
+    pub origin: Option<&'a str>,
+    /// Sent by this person, as their web session (or MCP client) was named
+    pub sent_by: Option<&'a str>,
+}
+
+/// Where a page of messages ends: before a timestamp, or a cursor
+#[derive(Debug, Clone, Copy)]
+enum Before {
+    Timestamp(i64),
+    Cursor(MessageCursor),
+}
+
+/// A cursor token: its kind then its values, base64 encoded for URLs
+fn encode_cursor(kind: char, values: &[i64]) -> String {
+    let values: Vec<String> = values.iter().map(i64::to_string).collect();
+    URL_SAFE_NO_PAD.encode(format!("{}{}", kind, values.join(":")))
+}
+
+fn decode_cursor<const N: usize>(kind: char, token: &str) -> Option<[i64; N]> {
+    let text = String::from_utf8(URL_SAFE_NO_PAD.decode(token).ok()?).ok()?;
+    let mut parts = text.strip_prefix(kind)?.split(':');
+    let mut values = [0; N];
+    for value in values.iter_mut() {
+        *value = parts.next()?.parse().ok()?;
+    }
+    parts.next().is_none().then_some(values)
+}
+
+/// A message being written but not yet sent, synced between sessions
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Draft {
+    pub contact_id: String,
+    pub text: String,
+    pub reply_to_message_id: Option<String>,
+    /// When the draft was last edited (Unix milliseconds)
+    pub updated_at: i64,
+}
+
+/// A message pinned to the top of its chat
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PinnedMessage {
+    #[serde(flatten)]
+    pub message: StoredMessage,
+    /// When it was pinned (Unix milliseconds)
+    pub pinned_at: i64,
+    /// Where it was pinned from ("web", or "mcp:<client_id>")
+    pub pinned_by: String,
+}
+
+/// Whether a group participant's messages are translated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParticipantTranslationMode {
+    Always,
+    Never,
+}
+
+impl ParticipantTranslationMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Always => "always",
+            Self::Never => "never",
+        }
+    }
+}
+
+/// A group participant whose messages are always or never translated
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TranslationParticipant {
+    pub participant_jid: String,
+    pub mode: ParticipantTranslationMode,
+    /// Contact name, or the name they last wrote in the group under
+    pub name: Option<String>,
+    pub updated_at: i64,
+}
+
+/// A recorded change to one of a contact's fields
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContactEvent {
+    /// "created", "name", "phone" or "type"
+    pub field: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub changed_at: i64,
+}
+
+/// The contact fields whose changes are tracked
+#[derive(Debug, PartialEq)]
+struct ContactIdentity {
+    name: Option<String>,
+    phone: Option<String>,
+    contact_type: Option<String>,
+}
+
+/// Daily limits on what an MCP client may do (None = unlimited)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpQuota {
+    /// Messages sent per day
+    pub daily_sends: Option<u32>,
+    /// Translation spend per day (USD)
+    pub daily_translation_usd: Option<f64>,
+}
+
+/// A browser's Web Push subscription
+#[derive(Debug, Clone, PartialEq)]
+pub struct PushSubscription {
+    /// The push service URL notifications are posted to
+    pub endpoint: String,
+    /// The browser's P-256 public key (base64url)
+    pub p256dh: String,
+    /// The browser's authentication secret (base64url)
+    pub auth: String,
+}
+
+/// Daily time span (server local time) without push notifications; it
+/// wraps past midnight when `end` is before `start`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuietHours {
+    pub start: chrono::NaiveTime,
+    pub end: chrono::NaiveTime,
+}
+
+impl QuietHours {
+    pub fn contains(&self, time: chrono::NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+/// An MCP client's usage and quota, as listed by the API
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpClientUsage {
+    pub client_id: String,
+    pub tool_calls: i64,
+    pub sends: i64,
+    pub translation_cost_usd: f64,
+    pub sends_today: u32,
+    pub translation_cost_today_usd: f64,
+    /// When the client last called a tool (Unix seconds)
+    pub last_activity: Option<i64>,
+    pub quota: McpQuota,
+    pub remaining_sends: Option<u32>,
+    pub remaining_translation_usd: Option<f64>,
+    /// Whether the client only sees pseudonymized data and can't send
+    pub anonymized: bool,
+}
+
+/// Settings keys for the default MCP quota; per-client overrides append ":<client_id>"
+const MCP_DAILY_SENDS_SETTING: &str = "mcp_daily_sends";
+const MCP_DAILY_TRANSLATION_USD_SETTING: &str = "mcp_daily_translation_usd";
+
+/// Settings key prefix for an anonymized MCP client's pseudonymization salt;
+/// the client's ID is appended, and clients without one see real data
+const MCP_ANONYMIZED_SETTING: &str = "mcp_anonymized";
+
+/// Settings key for the MCP chat access list every client gets (JSON);
+/// `:<client_id>` is appended for a client's own list
+const MCP_ACCESS_SETTING: &str = "mcp_access";
+
+/// Settings key for the models chosen through the settings API (JSON)
+const MODELS_SETTING: &str = "models";
+
+/// Settings key for the web password's argon2 hash
+const WEB_PASSWORD_SETTING: &str = "web_password_hash";
+
+/// Settings key for my own WhatsApp profile (JSON)
+const OWN_PROFILE_SETTING: &str = "own_profile";
+
+/// Settings key for whether outgoing messages in groups are translated by
+/// default ("true"; off if unset)
+const GROUP_OUTGOING_TRANSLATION_SETTING: &str = "translate_outgoing_in_groups";
+
+/// Settings key for the VAPID private key push notifications are signed
+/// with (PKCS#8, base64)
+const VAPID_KEY_SETTING: &str = "vapid_private_key";
+
+/// Settings key for the push notification quiet hours (JSON, unset = none)
+const QUIET_HOURS_SETTING: &str = "push_quiet_hours";
+
+/// Settings key for how long web sends can be undone (seconds, unset = off)
+const UNDO_WINDOW_SETTING: &str = "undo_window_secs";
+
+/// Settings key for how far back history syncs import (unset = full)
+const HISTORY_DEPTH_SETTING: &str = "history_depth";
+
+/// Settings key set once a history sync has been imported in full
+/// ("true"; unset = not yet)
+const HISTORY_SYNC_COMPLETE_SETTING: &str = "history_sync_complete";
+
+/// Settings key for when storage maintenance last ran each step (JSON)
+const STORAGE_MAINTENANCE_SETTING: &str = "storage_maintenance";
+
+/// Settings key for how far seeding conversation languages has got (JSON)
+const LANGUAGE_SEEDING_SETTING: &str = "language_seeding";
+
+/// Settings carried over by a settings export, with the kind of value each
+/// holds. Secrets and bookkeeping stay behind, as do per-client MCP
+/// settings, whose clients are registered separately on each deployment.
+pub const PORTABLE_SETTINGS: &[(&str, SettingKind)] = &[
+    (MODELS_SETTING, SettingKind::Models),
+    (GROUP_OUTGOING_TRANSLATION_SETTING, SettingKind::Flag),
+    (QUIET_HOURS_SETTING, SettingKind::QuietHours),
+    (UNDO_WINDOW_SETTING, SettingKind::Seconds),
+    (HISTORY_DEPTH_SETTING, SettingKind::HistoryDepth),
+    (MCP_DAILY_SENDS_SETTING, SettingKind::Count),
+    (MCP_DAILY_TRANSLATION_USD_SETTING, SettingKind::Usd),
+    (MCP_ACCESS_SETTING, SettingKind::AccessList),
+];
+
+/// Sort key for a message at timestamp ?3 in chat ?2: the timestamp scaled
+/// up, or one past the last key already used within the same second
+const NEXT_SORT_KEY_SQL: &str = "(SELECT MAX(?3 * 1000, COALESCE(MAX(sort_key) + 1, 0))
+     FROM messages
+     WHERE contact_id = ?2
+       AND sort_key >= ?3 * 1000
+       AND sort_key < (?3 + CASE WHEN ?3 % 1000 = 0 THEN 1000 ELSE 1 END) * 1000)";
+
+/// Where a contact sits in the contact list: the self chat first, then
+/// pinned chats, then the rest
+const CONTACT_RANK_SQL: &str =
+    "CASE WHEN c.type = 'self' THEN 0 WHEN c.pinned_at IS NOT NULL THEN 1 ELSE 2 END";
+
+/// How far apart (ms) a locally stored send and WhatsApp's copy of it can be
+const PENDING_MATCH_WINDOW_MS: i64 = 2 * 60 * 1000;
+
+/// How many recent incoming messages the language confidence looks at
+pub const LANGUAGE_CONFIDENCE_WINDOW: u32 = 20;
+
+/// Share of those messages that must be in a private chat's language before
+/// outgoing messages are translated into it
+pub const OUTGOING_TRANSLATION_MIN_CONFIDENCE: f64 = 0.7;
+
+/// A contact's effective outgoing translation setting: the one chosen for
+/// the contact, else the group default, else (in groups) off by the group
+/// default or (in private chats) on when the conversation language is
+/// clear. Listings show private chats as on, pending the language check.
+/// The settings key is `GROUP_OUTGOING_TRANSLATION_SETTING`.
+const OUTGOING_TRANSLATION_SQL: &str = "CASE
+    WHEN c.outgoing_translation_set THEN c.auto_translate_outgoing
+    WHEN c.type = 'self' THEN 0
+    WHEN c.type = 'group' THEN EXISTS(
+        SELECT 1 FROM settings WHERE key = 'translate_outgoing_in_groups' AND value = 'true')
+    ELSE 1
+END";
+
+/// Content type of the notices `StoredMessage::system` makes
+pub const SYSTEM_CONTENT_TYPE: &str = "system";
+
+/// Messages that can be unread: incoming ones other than reactions and
+/// system notices
+const UNREAD_MESSAGE_SQL: &str =
+    "m.is_from_me = 0 AND m.content_type != 'Reaction' AND m.content_type != 'system'";
+
+/// What the chat with my own number is called in the chat list
+pub const SAVED_MESSAGES_NAME: &str = "Saved Messages";
+
+/// My own WhatsApp profile, as last reported by the bridge
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OwnProfile {
+    pub jid: Option<String>,
+    pub phone: Option<String>,
+    pub name: Option<String>,
+    /// "About" text
+    pub status: Option<String>,
+    pub avatar_url: Option<String>,
+    /// When any of it last changed (ms)
+    pub updated_at: Option<i64>,
+}
+
+/// How sure we are of a chat's language, from its recent incoming messages
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LanguageConfidence {
+    /// The conversation language (None if no incoming message has one)
+    pub language: Option<String>,
+    /// Recent incoming messages with a detected language
+    pub sampled: u32,
+    /// How many of them are in `language`
+    pub matching: u32,
+    /// `matching / sampled` (0 with no samples)
+    pub confidence: f64,
+}
+
+/// Why outgoing messages to a contact are or aren't translated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutgoingTranslationReason {
+    /// Turned on or off for this contact
+    ContactSetting,
+    /// The default for groups
+    GroupDefault,
+    /// The chat has a language override
+    LanguageOverride,
+    /// Enough recent incoming messages share the conversation language
+    Confident,
+    /// Too few recent incoming messages share the conversation language
+    NotConfident,
+}
+
+/// Whether outgoing messages to a contact are translated, and why
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutgoingTranslation {
+    pub enabled: bool,
+    pub reason: OutgoingTranslationReason,
+    /// The setting chosen for this contact, if any
+    pub contact_setting: Option<bool>,
+}
+
+/// Media data split out of a message's content, keyed by file hash
+struct ExtractedMedia {
+    hash: String,
+    data: String,
+    mime_type: Option<String>,
+    /// JPEG thumbnail of an image (None for other media, or if it couldn't be made)
+    thumbnail: Option<String>,
+    /// Content JSON with the media data replaced by a `has_media` flag
+    content_json: String,
 }
 
 /// Conversation settings for per-contact customization
@@ -85,6 +735,77 @@ pub struct ConversationSettings {
     /// Style instruction for translations in this conversation
     /// e.g., "formal", "informal", "family", "robotic", "geek"
     pub translation_style: Option<String>,
+    /// Pick out vocabulary from incoming translated messages
+    #[serde(default)]
+    pub learning_mode: bool,
+    /// No push notifications for this conversation
+    #[serde(default)]
+    pub muted: bool,
+    /// Classify incoming messages' urgency and tone (None: every chat but
+    /// groups)
+    #[serde(default)]
+    pub triage: Option<bool>,
+    /// Register for my translated and composed messages; incoming
+    /// translations ignore it
+    #[serde(default)]
+    pub translation_tone: Option<TranslationTone>,
+    /// IANA timezone the contact lives in (None: inferred from their messages)
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// Note a few key phrases of my translated messages and their
+    /// translations, to learn from
+    #[serde(default)]
+    pub include_learning_notes: bool,
+}
+
+impl ConversationSettings {
+    /// Whether incoming messages in a chat of this type are triaged
+    pub fn triage_enabled(&self, chat_type: &str) -> bool {
+        self.triage.unwrap_or(chat_type != "group")
+    }
+}
+
+/// A word from a chat's learning-mode vocabulary with how often it came up
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VocabularyItem {
+    pub term: String,
+    pub gloss: String,
+    /// Messages the term was picked out of
+    pub count: u32,
+    /// Timestamp of the latest of those messages
+    pub last_seen: i64,
+}
+
+/// Sizes of the database and its write-ahead log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DatabaseSizes {
+    pub database_bytes: u64,
+    pub wal_bytes: u64,
+    /// Space in free pages, which an incremental vacuum gives back
+    pub free_bytes: u64,
+    /// Whether the database can be vacuumed incrementally
+    pub incremental_vacuum: bool,
+}
+
+/// When each storage maintenance step last ran (ms)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageMaintenanceTimes {
+    pub last_checkpoint: Option<i64>,
+    pub last_vacuum: Option<i64>,
+    pub last_analyze: Option<i64>,
+}
+
+/// How far seeding conversation languages from history has got
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LanguageSeedingProgress {
+    /// Last contact sampled; contacts are sampled in ID order
+    pub last_contact: Option<String>,
+    /// Every active contact has been sampled
+    pub complete: bool,
 }
 
 /// Style profile for AI reply generation
@@ -103,6 +824,131 @@ pub struct StyleProfile {
     pub updated_at: i64,
 }
 
+/// A possible LID / phone JID duplicate found by the link heuristic
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdentityLinkCandidate {
+    /// The alias JID (usually `...@lid`)
+    pub alt_jid: String,
+    /// The contact the alias would be merged into
+    pub canonical_id: String,
+    pub name: Option<String>,
+    /// True when the match is unambiguous and safe to link automatically
+    pub confident: bool,
+    /// Why the candidate was or wasn't considered confident
+    pub reason: String,
+}
+
+/// Latency summary for a group of API calls
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencyStats {
+    /// Operation or model name
+    pub key: String,
+    pub count: usize,
+    pub p50_ms: i64,
+    pub p95_ms: i64,
+    pub avg_ms: i64,
+    pub max_ms: i64,
+}
+
+impl LatencyStats {
+    /// Summarize latency samples using nearest-rank percentiles
+    fn from_samples(key: String, mut samples: Vec<i64>) -> Self {
+        samples.sort_unstable();
+        let count = samples.len();
+        let percentile = |p: usize| -> i64 {
+            if count == 0 {
+                return 0;
+            }
+            let rank = (p * count).div_ceil(100).max(1);
+            samples[rank - 1]
+        };
+
+        Self {
+            key,
+            count,
+            p50_ms: percentile(50),
+            p95_ms: percentile(95),
+            avg_ms: if count > 0 {
+                samples.iter().sum::<i64>() / count as i64
+            } else {
+                0
+            },
+            max_ms: samples.last().copied().unwrap_or(0),
+        }
+    }
+}
+
+/// API latency broken down by operation, by model and by provider
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PerformanceStats {
+    pub by_operation: Vec<LatencyStats>,
+    pub by_model: Vec<LatencyStats>,
+    pub by_provider: Vec<LatencyStats>,
+}
+
+/// Messages of one unsupported content type (stored as "unknown:<raw_type>")
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnknownContentType {
+    pub raw_type: String,
+    pub count: u64,
+    /// Timestamp (ms) of the latest one
+    pub last_seen: i64,
+    /// The latest one's content, as the bridge sent it
+    pub sample: serde_json::Value,
+}
+
+/// Message and translation activity in a period
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivityStats {
+    pub messages_received: u64,
+    pub messages_sent: u64,
+    /// Incoming messages that were translated
+    pub messages_translated: u64,
+    /// AI calls made (translation, detection, compose, ...)
+    pub translation_calls: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub translation_cost_usd: f64,
+    /// Busiest chats, most messages first
+    pub top_chats: Vec<ChatActivity>,
+    /// Size of the database file
+    pub database_bytes: u64,
+}
+
+/// A chat's message count in a period
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatActivity {
+    pub contact_id: String,
+    pub name: Option<String>,
+    pub messages: u64,
+}
+
+/// A weekly report as generated, and where it was delivered
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredReport {
+    /// ISO week, e.g. "2026-W41"
+    pub week: String,
+    /// Start and end of the week (ms, end exclusive)
+    pub period_start: i64,
+    pub period_end: i64,
+    pub generated_at: i64,
+    pub markdown: String,
+    pub stats: serde_json::Value,
+    /// "self_chat" or the webhook URL, once delivered
+    pub delivered_to: Option<String>,
+    pub delivered_at: Option<i64>,
+}
+
+/// How close two contacts' message activity must be to count as overlapping
+const IDENTITY_ACTIVITY_WINDOW_MS: i64 = 30 * 24 * 60 * 60 * 1000;
+
 impl StyleProfile {
     /// The special contact ID used for the global style profile
     pub const GLOBAL_ID: &'static str = "__global__";
@@ -113,9 +959,177 @@ impl StyleProfile {
     }
 }
 
+/// Usage records queued before the writer falls back to inserting inline
+const USAGE_QUEUE_CAPACITY: usize = 1024;
+/// Number of queued usage records that triggers a write
+const USAGE_BATCH_SIZE: usize = 50;
+/// Longest a queued usage record waits before being written
+const USAGE_BATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Translation usage waiting to be written
+struct UsageRecord {
+    contact_id: Option<String>,
+    message_id: Option<String>,
+    usage: UsageInfo,
+    operation: String,
+    timestamp: i64,
+}
+
+impl UsageRecord {
+    fn spilled(&self) -> SpilledWrite {
+        SpilledWrite::Usage {
+            spill_id: Some(uuid::Uuid::new_v4().to_string()),
+            contact_id: self.contact_id.clone(),
+            message_id: self.message_id.clone(),
+            usage: self.usage.clone(),
+            operation: self.operation.clone(),
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+enum UsageCommand {
+    Record(UsageRecord),
+    /// Write everything queued so far, then acknowledge
+    Flush(SyncSender<()>),
+}
+
+/// Background thread that batches usage inserts into single transactions.
+/// Dropping it writes whatever is still queued before returning.
+struct UsageWriter {
+    tx: Option<SyncSender<UsageCommand>>,
+    handle: Option<std::thread::JoinHandle<()>>,
+}
+
+impl UsageWriter {
+    fn spawn(conn: Arc<Mutex<Connection>>, spill: Arc<SpillJournal>) -> Result<Self> {
+        let (tx, rx) = mpsc::sync_channel(USAGE_QUEUE_CAPACITY);
+        let handle = std::thread::Builder::new()
+            .name("usage-writer".to_string())
+            .spawn(move || Self::run(&conn, &spill, rx))
+            .context("Failed to start usage writer")?;
+
+        Ok(Self {
+            tx: Some(tx),
+            handle: Some(handle),
+        })
+    }
+
+    fn run(conn: &Mutex<Connection>, spill: &SpillJournal, rx: mpsc::Receiver<UsageCommand>) {
+        let mut batch = Vec::new();
+        let mut deadline: Option<Instant> = None;
+
+        loop {
+            let command = match deadline {
+                Some(deadline) => {
+                    rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                }
+                None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            match command {
+                Ok(UsageCommand::Record(record)) => {
+                    batch.push(record);
+                    deadline.get_or_insert_with(|| Instant::now() + USAGE_BATCH_INTERVAL);
+                    if batch.len() < USAGE_BATCH_SIZE {
+                        continue;
+                    }
+                }
+                Ok(UsageCommand::Flush(ack)) => {
+                    Self::write(conn, spill, &mut batch);
+                    deadline = None;
+                    let _ = ack.send(());
+                    continue;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    Self::write(conn, spill, &mut batch);
+                    return;
+                }
+            }
+
+            Self::write(conn, spill, &mut batch);
+            deadline = None;
+        }
+    }
+
+    fn write(conn: &Mutex<Connection>, spill: &SpillJournal, batch: &mut Vec<UsageRecord>) {
+        if batch.is_empty() {
+            return;
+        }
+
+        let result = retry_when_busy(|| {
+            let conn = conn.lock().unwrap();
+            let tx = conn.unchecked_transaction()?;
+            for record in batch.iter() {
+                MessageStore::insert_usage(&tx, record, None)?;
+            }
+            tx.commit()?;
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => {}
+            Err(e) if is_disk_error(&e) => {
+                warn!(
+                    "Failed to write {} usage records, spilling: {}",
+                    batch.len(),
+                    e
+                );
+                for record in batch.iter() {
+                    if let Err(e) = spill.append(&record.spilled()) {
+                        error!("Failed to spill usage record: {:#}", e);
+                    }
+                }
+            }
+            Err(e) => error!("Failed to write {} usage records: {}", batch.len(), e),
+        }
+        batch.clear();
+    }
+
+    /// Queue a record, handing it back if the queue is full or closed
+    fn send(&self, record: UsageRecord) -> std::result::Result<(), UsageRecord> {
+        let Some(tx) = &self.tx else {
+            return Err(record);
+        };
+        match tx.try_send(UsageCommand::Record(record)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(UsageCommand::Record(record)))
+            | Err(TrySendError::Disconnected(UsageCommand::Record(record))) => Err(record),
+            Err(_) => unreachable!("only records are sent here"),
+        }
+    }
+
+    /// Block until everything queued so far has been written
+    fn flush(&self) {
+        let Some(tx) = &self.tx else { return };
+        let (ack_tx, ack_rx) = mpsc::sync_channel(1);
+        if tx.send(UsageCommand::Flush(ack_tx)).is_ok() {
+            let _ = ack_rx.recv();
+        }
+    }
+}
+
+impl Drop for UsageWriter {
+    fn drop(&mut self) {
+        // Closing the channel makes the writer drain the queue and exit
+        self.tx.take();
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                error!("Usage writer panicked");
+            }
+        }
+    }
+}
+
 /// Thread-safe message store backed by SQLite
 pub struct MessageStore {
     conn: Arc<Mutex<Connection>>,
+    usage_writer: Arc<UsageWriter>,
+    write_protection: Arc<WriteProtection>,
+    contact_cache: Arc<ContactCache>,
+    /// Writes that failed with a disk error, waiting to be replayed
+    spill: Arc<SpillJournal>,
 }
 
 impl MessageStore {
@@ -138,11 +1152,23 @@ impl MessageStore {
         )
         .with_context(|| format!("unable to open database file: {:?}", db_path))?;
 
+        // Free pages can be given back a few at a time. This only takes on a
+        // new database; existing ones are converted by storage maintenance
+        conn.execute_batch("PRAGMA auto_vacuum=INCREMENTAL;")?;
         // Enable WAL mode for better performance
         conn.execute_batch("PRAGMA journal_mode=WAL;")?;
+        // A second process (`mcp-stdio`, or terminal mode with
+        // `--with-store`) may share the database
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
 
+        let conn = Arc::new(Mutex::new(conn));
+        let spill = Arc::new(SpillJournal::open(data_dir));
         let store = Self {
-            conn: Arc::new(Mutex::new(conn)),
+            usage_writer: Arc::new(UsageWriter::spawn(Arc::clone(&conn), Arc::clone(&spill))?),
+            write_protection: Arc::new(WriteProtection::new(data_dir, DEFAULT_MIN_FREE_BYTES)),
+            contact_cache: Arc::new(ContactCache::default()),
+            spill,
+            conn,
         };
 
         store.init_schema()?;
@@ -152,32 +1178,212 @@ impl MessageStore {
         Ok(store)
     }
 
-    /// Initialize database schema
-    fn init_schema(&self) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+    /// Set the free space below which the store goes read-only
+    pub fn with_min_free_space(self, bytes: u64) -> Self {
+        self.write_protection.set_min_free_bytes(bytes);
+        self
+    }
 
-        conn.execute_batch(
-            r#"
-            -- Contacts table
-            CREATE TABLE IF NOT EXISTS contacts (
-                id TEXT PRIMARY KEY,
-                name TEXT,
-                phone TEXT,
-                type TEXT,
-                last_message_time INTEGER DEFAULT 0,
-                unread_count INTEGER DEFAULT 0
-            );
+    /// Whether writes are suspended because the disk is nearly full
+    pub fn is_read_only(&self) -> bool {
+        self.write_protection.is_read_only()
+    }
 
-            -- Messages table
-            CREATE TABLE IF NOT EXISTS messages (
-                id TEXT PRIMARY KEY,
-                contact_id TEXT NOT NULL,
-                timestamp INTEGER NOT NULL,
-                is_from_me INTEGER NOT NULL,
-                is_forwarded INTEGER DEFAULT 0,
-                sender_name TEXT,
-                sender_phone TEXT,
-                chat_type TEXT,
+    pub fn disk_status(&self) -> DiskStatus {
+        DiskStatus {
+            spilled_writes: self.spill.depth(),
+            ..self.write_protection.status()
+        }
+    }
+
+    /// Check free disk space, switching to or from read-only mode if needed.
+    /// Messages buffered while read-only are written out on recovery.
+    pub fn check_disk_space(&self) -> Option<Transition> {
+        let transition = self.write_protection.check();
+        if transition == Some(Transition::Writable) {
+            self.flush_buffered_messages();
+            if let Err(e) = self.recover_spilled() {
+                warn!("Spilled writes are still waiting: {:#}", e);
+            }
+        }
+        transition
+    }
+
+    /// Hand back a write's result, unless it failed with a disk error: then
+    /// the write is journaled to be replayed later and `T::default()` is
+    /// returned (as for a message buffered while read-only). Enough disk
+    /// errors in a row make the store read-only.
+    fn spill_on_failure<T: Default>(
+        &self,
+        result: Result<T>,
+        write: impl FnOnce() -> SpilledWrite,
+    ) -> Result<T> {
+        let e = match result {
+            Ok(value) => {
+                self.spill.record_success();
+                return Ok(value);
+            }
+            Err(e) if is_disk_error(&e) => e,
+            Err(e) => return Err(e),
+        };
+
+        if self.spill.record_failure() && self.write_protection.degrade() {
+            warn!("Repeated disk errors, switching to read-only mode");
+        }
+        match self.spill.append(&write()) {
+            Ok(()) => {
+                warn!("Write failed, spilled it to be retried: {:#}", e);
+                Ok(T::default())
+            }
+            Err(spill_error) => {
+                error!("Failed to spill a failed write: {:#}", spill_error);
+                Err(e)
+            }
+        }
+    }
+
+    /// Replay writes spilled after disk errors, oldest first, stopping at
+    /// the first that fails again. Returns how many were written.
+    pub fn recover_spilled(&self) -> Result<usize> {
+        if self.spill.depth() == 0 {
+            return Ok(0);
+        }
+        let (guard, writes) = self.spill.begin_replay()?;
+        let mut written = 0;
+        let mut failure = None;
+        for write in &writes {
+            if let Err(e) = self.replay(write) {
+                failure = Some(e);
+                break;
+            }
+            written += 1;
+        }
+        self.spill.finish_replay(guard, &writes[written..])?;
+
+        if written > 0 {
+            info!("Wrote {} spilled writes", written);
+            self.spill.record_success();
+        }
+        match failure {
+            Some(e) => Err(e.context(format!("{} spilled writes remain", writes.len() - written))),
+            None => Ok(written),
+        }
+    }
+
+    fn replay(&self, write: &SpilledWrite) -> Result<()> {
+        match write {
+            SpilledWrite::Message { message } => {
+                let msg: StoredMessage =
+                    serde_json::from_value(message.clone()).context("Invalid spilled message")?;
+                self.write_message(&msg)?;
+            }
+            SpilledWrite::Contact {
+                id,
+                name,
+                phone,
+                contact_type,
+                last_message_time,
+            } => {
+                self.write_contact(
+                    id,
+                    name.as_deref(),
+                    phone.as_deref(),
+                    contact_type.as_deref(),
+                    *last_message_time,
+                )?;
+            }
+            SpilledWrite::Usage {
+                spill_id,
+                contact_id,
+                message_id,
+                usage,
+                operation,
+                timestamp,
+            } => {
+                let record = UsageRecord {
+                    contact_id: contact_id.clone(),
+                    message_id: message_id.clone(),
+                    usage: usage.clone(),
+                    operation: operation.clone(),
+                    timestamp: *timestamp,
+                };
+                let conn = self.conn.lock().unwrap();
+                Self::insert_usage(&conn, &record, spill_id.as_deref())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Park an incoming message while the store is read-only; it is written
+    /// (and counted as unread if `unread`) once space is available
+    pub fn buffer_message(&self, msg: &StoredMessage, unread: bool) {
+        self.write_protection.buffer(msg.clone(), unread);
+    }
+
+    /// Write out messages buffered while the store was read-only
+    fn flush_buffered_messages(&self) {
+        let buffered = self.write_protection.take_buffered();
+        if buffered.is_empty() {
+            return;
+        }
+
+        let count = buffered.len();
+        let mut failed = 0;
+        for entry in buffered {
+            let msg = &entry.message;
+            let result = self
+                .upsert_contact(
+                    &msg.contact_id,
+                    msg.contact_name.as_deref(),
+                    msg.contact_phone.as_deref(),
+                    Some(&msg.chat_type),
+                    msg.timestamp,
+                )
+                .and_then(|_| self.add_message(msg))
+                .and_then(|_| {
+                    if entry.unread {
+                        self.increment_unread(&msg.contact_id)?;
+                    }
+                    Ok(())
+                });
+            if let Err(e) = result {
+                failed += 1;
+                error!("Failed to write buffered message {}: {}", msg.id, e);
+            }
+        }
+        info!(
+            "Wrote {} messages buffered while read-only ({} failed)",
+            count - failed,
+            failed
+        );
+    }
+
+    /// Initialize database schema
+    fn init_schema(&self) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute_batch(
+            r#"
+            -- Contacts table
+            CREATE TABLE IF NOT EXISTS contacts (
+                id TEXT PRIMARY KEY,
+                name TEXT,
+                phone TEXT,
+                type TEXT,
+                last_message_time INTEGER DEFAULT 0,
+                unread_count INTEGER DEFAULT 0
+            );
+
+            -- Messages table
+            CREATE TABLE IF NOT EXISTS messages (
+                id TEXT PRIMARY KEY,
+                contact_id TEXT NOT NULL,
+                timestamp INTEGER NOT NULL,
+                is_from_me INTEGER NOT NULL,
+                is_forwarded INTEGER DEFAULT 0,
+                sender_name TEXT,
+                sender_phone TEXT,
+                chat_type TEXT,
                 content_type TEXT NOT NULL,
                 content_json TEXT NOT NULL,
                 FOREIGN KEY (contact_id) REFERENCES contacts(id)
@@ -186,6 +1392,7 @@ impl MessageStore {
             -- Indexes
             CREATE INDEX IF NOT EXISTS idx_messages_contact_id ON messages(contact_id);
             CREATE INDEX IF NOT EXISTS idx_messages_timestamp ON messages(timestamp);
+            CREATE INDEX IF NOT EXISTS idx_messages_contact_timestamp ON messages(contact_id, timestamp);
             CREATE INDEX IF NOT EXISTS idx_contacts_last_message ON contacts(last_message_time DESC);
 
             -- Translation usage tracking
@@ -202,6 +1409,7 @@ impl MessageStore {
 
             CREATE INDEX IF NOT EXISTS idx_usage_contact_id ON translation_usage(contact_id);
             CREATE INDEX IF NOT EXISTS idx_usage_timestamp ON translation_usage(timestamp);
+            CREATE INDEX IF NOT EXISTS idx_usage_message_id ON translation_usage(message_id);
 
             -- Link preview cache
             CREATE TABLE IF NOT EXISTS link_previews (
@@ -216,6 +1424,21 @@ impl MessageStore {
 
             CREATE INDEX IF NOT EXISTS idx_link_previews_fetched ON link_previews(fetched_at);
 
+            -- Reverse geocoding cache, keyed by rounded coordinates
+            CREATE TABLE IF NOT EXISTS geocode_cache (
+                coordinates TEXT PRIMARY KEY,
+                address TEXT,
+                fetched_at INTEGER NOT NULL
+            );
+
+            -- Web Push subscriptions, one per browser
+            CREATE TABLE IF NOT EXISTS push_subscriptions (
+                endpoint TEXT PRIMARY KEY,
+                p256dh TEXT NOT NULL,
+                auth TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+
             -- OAuth 2.0 tables for MCP authentication
             
             -- Pending authorization requests (before user approves)
@@ -284,1602 +1507,9057 @@ impl MessageStore {
         // Add conversation settings columns (language_override, translation_style)
         self.migrate_add_conversation_settings_columns(&conn)?;
 
+        // Add identity_links table for LID / phone JID aliases
+        self.migrate_add_identity_links_table(&conn)?;
+
+        // Add latency_ms and model columns to translation_usage
+        self.migrate_add_usage_latency_columns(&conn)?;
+
+        // Add auto_translate_outgoing column to contacts
+        self.migrate_add_auto_translate_outgoing_column(&conn)?;
+
+        // Add media_blobs table and move existing media into it
+        self.migrate_add_media_blobs_table(&conn)?;
+
+        // Add origin column to messages
+        self.migrate_add_message_origin_column(&conn)?;
+
+        // Add mentioned_jids and mentions_me columns to messages
+        self.migrate_add_message_mentions_columns(&conn)?;
+
+        // Add mentions_only column to contacts
+        self.migrate_add_mentions_only_column(&conn)?;
+
+        // Add cached conversation language columns to contacts
+        self.migrate_add_conversation_language_cache(&conn)?;
+
+        // Add created_at / updated_at to contacts and the contact_events table
+        self.migrate_add_contact_audit_columns(&conn)?;
+
+        // Add the settings and mcp_client_usage tables
+        self.migrate_add_mcp_client_usage_table(&conn)?;
+
+        // Add thumbnail column to media_blobs
+        self.migrate_add_media_thumbnail_column(&conn)?;
+
+        // Add partial index over translated messages
+        self.migrate_add_translated_messages_index(&conn)?;
+
+        // Add partial index over reactions
+        self.migrate_add_reactions_index(&conn)?;
+
+        // Bind pending OAuth authorizations to the approval form and browser
+        self.migrate_add_oauth_pending_binding_columns(&conn)?;
+
+        // Add drafts table
+        self.migrate_add_drafts_table(&conn)?;
+
+        // Add last_seen column to contacts
+        self.migrate_add_last_seen_column(&conn)?;
+
+        // Add learning mode setting and extracted vocabulary
+        self.migrate_add_learning_mode_columns(&conn)?;
+
+        // Add audio duration and waveform columns
+        self.migrate_add_audio_columns(&conn)?;
+
+        // Tell chosen outgoing translation settings apart from the defaults
+        self.migrate_add_outgoing_translation_set_column(&conn)?;
+
+        // Add translation_participants table for per-sender group filters
+        self.migrate_add_translation_participants_table(&conn)?;
+
+        // Add last_read_timestamp to contacts, the anchor for unread messages
+        self.migrate_add_last_read_column(&conn)?;
+
+        // Add sort_key to messages, ordering messages with equal timestamps
+        self.migrate_add_sort_key_column(&conn)?;
+
+        // Add muted to contacts, silencing a chat's push notifications
+        self.migrate_add_muted_column(&conn)?;
+
+        // Add urgency/tone to messages and the triage opt-out to contacts
+        self.migrate_add_triage_columns(&conn)?;
+
+        // Add group description and disappearing messages timer to contacts
+        self.migrate_add_chat_metadata_columns(&conn)?;
+
+        // Add translation_status to messages, recording why one wasn't translated
+        self.migrate_add_translation_status_column(&conn)?;
+
+        // Add provider to translation_usage, recording which provider served a call
+        self.migrate_add_usage_provider_column(&conn)?;
+
+        // Add translation_tone to contacts, the register for my messages to them
+        self.migrate_add_translation_tone_column(&conn)?;
+
+        // Add timezone to contacts, the IANA zone the contact lives in
+        self.migrate_add_contact_timezone_column(&conn)?;
+
+        // Add the pinned_messages table, messages pinned to the top of a chat
+        self.migrate_add_pinned_messages_table(&conn)?;
+
+        // Add the reports table, generated weekly reports
+        self.migrate_add_reports_table(&conn)?;
+
+        // Add include_learning_notes to contacts, key phrases with my translated messages
+        self.migrate_add_learning_notes_column(&conn)?;
+
+        // Add name_normalized to contacts, their folded names for search
+        self.migrate_add_name_normalized_column(&conn)?;
+
+        // Add the jobs table, background jobs that have finished
+        self.migrate_add_jobs_table(&conn)?;
+
+        // Add sent_by to messages, who sent an outgoing message from here
+        self.migrate_add_sent_by_column(&conn)?;
+
+        // Add placeholder to contacts, contacts a settings import created
+        self.migrate_add_placeholder_column(&conn)?;
+
+        // Add spill_id to translation_usage, so replayed usage is only counted once
+        self.migrate_add_usage_spill_id_column(&conn)?;
+
         Ok(())
     }
 
-    /// Add style_profiles table for AI reply generation
-    fn migrate_add_style_profiles_table(&self, conn: &Connection) -> Result<()> {
-        // Check if table exists
-        let table_exists: bool = conn
+    /// Add spill_id column to translation_usage table
+    fn migrate_add_usage_spill_id_column(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
             .query_row(
-                "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='style_profiles'",
+                "SELECT COUNT(*) FROM pragma_table_info('translation_usage') WHERE name = 'spill_id'",
                 [],
                 |row| row.get(0),
             )
             .unwrap_or(0)
             > 0;
 
-        if !table_exists {
-            info!("Migrating database: creating style_profiles table...");
+        if !has_column {
+            info!("Migrating database: adding spill_id column to translation_usage...");
             conn.execute_batch(
                 r#"
-                CREATE TABLE style_profiles (
-                    contact_id TEXT PRIMARY KEY,
-                    profile_text TEXT NOT NULL,
-                    sample_messages TEXT NOT NULL,
-                    message_count INTEGER NOT NULL,
-                    updated_at INTEGER NOT NULL
-                );
-
-                CREATE INDEX idx_style_profiles_updated ON style_profiles(updated_at);
+                ALTER TABLE translation_usage ADD COLUMN spill_id TEXT;
+                CREATE UNIQUE INDEX IF NOT EXISTS idx_usage_spill_id ON translation_usage(spill_id);
                 "#,
             )?;
-            info!("Database migration complete: created style_profiles table");
+            info!("Database migration complete: added spill_id column to translation_usage");
         }
 
         Ok(())
     }
 
-    /// Add conversation settings columns to contacts table
-    fn migrate_add_conversation_settings_columns(&self, conn: &Connection) -> Result<()> {
-        // Check if language_override column exists
-        let has_language_override: bool = conn
+    /// Add placeholder column to contacts table
+    fn migrate_add_placeholder_column(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
             .query_row(
-                "SELECT COUNT(*) FROM pragma_table_info('contacts') WHERE name = 'language_override'",
+                "SELECT COUNT(*) FROM pragma_table_info('contacts') WHERE name = 'placeholder'",
                 [],
                 |row| row.get(0),
             )
             .unwrap_or(0)
             > 0;
 
-        if !has_language_override {
-            info!("Migrating database: adding conversation settings columns...");
-            conn.execute("ALTER TABLE contacts ADD COLUMN language_override TEXT", [])?;
-            conn.execute("ALTER TABLE contacts ADD COLUMN translation_style TEXT", [])?;
-            info!("Database migration complete: added conversation settings columns");
+        if !has_column {
+            info!("Migrating database: adding placeholder column to contacts...");
+            conn.execute(
+                "ALTER TABLE contacts ADD COLUMN placeholder INTEGER NOT NULL DEFAULT 0",
+                [],
+            )?;
+            info!("Database migration complete: added placeholder column to contacts");
         }
 
         Ok(())
     }
 
-    /// Add pinned_at column to contacts table
-    fn migrate_add_pinned_column(&self, conn: &Connection) -> Result<()> {
-        // Check if column exists
-        let has_pinned_at: bool = conn
+    /// Add sent_by column to messages table
+    fn migrate_add_sent_by_column(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
             .query_row(
-                "SELECT COUNT(*) FROM pragma_table_info('contacts') WHERE name = 'pinned_at'",
+                "SELECT COUNT(*) FROM pragma_table_info('messages') WHERE name = 'sent_by'",
                 [],
                 |row| row.get(0),
             )
             .unwrap_or(0)
             > 0;
 
-        if !has_pinned_at {
-            info!("Migrating database: adding pinned_at column...");
-            conn.execute("ALTER TABLE contacts ADD COLUMN pinned_at INTEGER", [])?;
-            info!("Database migration complete: added pinned_at column");
+        if !has_column {
+            info!("Migrating database: adding sent_by column to messages...");
+            conn.execute("ALTER TABLE messages ADD COLUMN sent_by TEXT", [])?;
+            info!("Database migration complete: added sent_by column to messages");
         }
 
         Ok(())
     }
 
-    /// Fix contact types based on JID suffix (groups end with @g.us)
-    fn migrate_fix_contact_types(&self, conn: &Connection) -> Result<()> {
-        // Update contacts where type doesn't match the JID suffix
-        // This fixes both NULL types and incorrectly set types
-        let updated = conn.execute(
-            r#"
-            UPDATE contacts 
-            SET type = CASE 
-                WHEN id LIKE '%@g.us' THEN 'group'
-                WHEN id LIKE '%@s.whatsapp.net' THEN 'private'
-                WHEN id LIKE '%@broadcast' THEN 'broadcast'
-                ELSE 'private'
-            END
-            WHERE type IS NULL 
-               OR (id LIKE '%@g.us' AND type != 'group')
-               OR (id LIKE '%@s.whatsapp.net' AND type != 'private')
-               OR (id LIKE '%@broadcast' AND type != 'broadcast')
-            "#,
-            [],
-        )?;
+    /// Add a chat's description and disappearing messages timer to contacts
+    fn migrate_add_chat_metadata_columns(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('contacts') WHERE name = 'description'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
 
-        if updated > 0 {
-            info!(
-                "Fixed contact types for {} contacts based on JID suffix",
-                updated
-            );
+        if !has_column {
+            info!("Migrating database: adding chat metadata columns...");
+            conn.execute_batch(
+                r#"
+                ALTER TABLE contacts ADD COLUMN description TEXT;
+                ALTER TABLE contacts ADD COLUMN ephemeral_duration INTEGER;
+                "#,
+            )?;
+            info!("Database migration complete: added chat metadata columns");
         }
 
         Ok(())
     }
 
-    /// Add translation columns to existing database
-    fn migrate_add_translation_columns(&self, conn: &Connection) -> Result<()> {
-        // Check if columns exist by querying table info
-        let has_original_text: bool = conn
+    /// Add translation_status to messages. Existing messages are marked
+    /// translated or not needed by whether they were translated.
+    /// Add provider column to translation_usage. Earlier calls all went to
+    /// Anthropic.
+    fn migrate_add_usage_provider_column(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
             .query_row(
-                "SELECT COUNT(*) FROM pragma_table_info('messages') WHERE name = 'original_text'",
+                "SELECT COUNT(*) FROM pragma_table_info('translation_usage') WHERE name = 'provider'",
                 [],
                 |row| row.get(0),
             )
             .unwrap_or(0)
             > 0;
 
-        if !has_original_text {
-            tracing::info!("Migrating database: adding translation columns...");
+        if !has_column {
+            info!("Migrating database: adding provider column to translation_usage...");
             conn.execute_batch(
                 r#"
-                ALTER TABLE messages ADD COLUMN original_text TEXT;
-                ALTER TABLE messages ADD COLUMN translated_text TEXT;
-                ALTER TABLE messages ADD COLUMN source_language TEXT;
-                ALTER TABLE messages ADD COLUMN is_translated INTEGER DEFAULT 0;
+                ALTER TABLE translation_usage ADD COLUMN provider TEXT;
+                UPDATE translation_usage SET provider = 'anthropic' WHERE model IS NOT NULL;
                 "#,
             )?;
-            tracing::info!("Database migration complete");
+            info!("Database migration complete: added provider column");
         }
 
         Ok(())
     }
 
-    /// Add or update a contact
-    pub fn upsert_contact(
-        &self,
-        id: &str,
-        name: Option<&str>,
-        phone: Option<&str>,
-        contact_type: Option<&str>,
-        last_message_time: i64,
-    ) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+    fn migrate_add_translation_tone_column(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('contacts') WHERE name = 'translation_tone'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
 
-        conn.execute(
-            r#"
-            INSERT INTO contacts (id, name, phone, type, last_message_time, unread_count)
-            VALUES (?1, ?2, ?3, ?4, ?5, 0)
-            ON CONFLICT(id) DO UPDATE SET
-                name = COALESCE(
-                    CASE WHEN excluded.name IS NOT NULL AND excluded.name != excluded.phone 
-                         THEN excluded.name ELSE NULL END,
-                    contacts.name
-                ),
-                phone = COALESCE(excluded.phone, contacts.phone),
-                type = COALESCE(excluded.type, contacts.type),
-                last_message_time = MAX(contacts.last_message_time, excluded.last_message_time)
-            "#,
-            params![id, name, phone, contact_type, last_message_time],
-        )?;
+        if !has_column {
+            info!("Migrating database: adding translation_tone column to contacts...");
+            conn.execute("ALTER TABLE contacts ADD COLUMN translation_tone TEXT", [])?;
+            info!("Database migration complete: added translation_tone column");
+        }
 
         Ok(())
     }
 
-    /// Increment unread count for a contact
-    pub fn increment_unread(&self, contact_id: &str) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "UPDATE contacts SET unread_count = unread_count + 1 WHERE id = ?",
-            params![contact_id],
-        )?;
-        Ok(())
-    }
+    fn migrate_add_contact_timezone_column(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('contacts') WHERE name = 'timezone'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
 
-    /// Reset unread count for a contact
-    pub fn mark_as_read(&self, contact_id: &str) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "UPDATE contacts SET unread_count = 0 WHERE id = ?",
-            params![contact_id],
-        )?;
-        Ok(())
-    }
+        if !has_column {
+            info!("Migrating database: adding timezone column to contacts...");
+            conn.execute("ALTER TABLE contacts ADD COLUMN timezone TEXT", [])?;
+            info!("Database migration complete: added timezone column");
+        }
 
-    /// Set unread count for a contact (used for history sync)
-    pub fn set_unread_count(&self, contact_id: &str, count: u32) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "UPDATE contacts SET unread_count = ? WHERE id = ?",
-            params![count as i32, contact_id],
-        )?;
         Ok(())
     }
 
-    /// Add a message to the store
-    pub fn add_message(&self, msg: &StoredMessage) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-
-        conn.execute(
-            r#"
-            INSERT OR IGNORE INTO messages 
-            (id, contact_id, timestamp, is_from_me, is_forwarded, sender_name, sender_phone, 
-             chat_type, content_type, content_json, original_text, translated_text, 
-             source_language, is_translated)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
-            "#,
-            params![
-                msg.id,
-                msg.contact_id,
-                msg.timestamp,
-                msg.is_from_me,
-                msg.is_forwarded,
-                msg.sender_name,
-                msg.sender_phone,
-                msg.chat_type,
-                msg.content_type,
-                msg.content_json,
-                msg.original_text,
-                msg.translated_text,
-                msg.source_language,
-                msg.is_translated,
-            ],
-        )?;
+    fn migrate_add_translation_status_column(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('messages') WHERE name = 'translation_status'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
+
+        if !has_column {
+            info!("Migrating database: adding translation_status column...");
+            conn.execute_batch(
+                r#"
+                ALTER TABLE messages ADD COLUMN translation_status TEXT;
+                UPDATE messages SET translation_status =
+                    CASE WHEN is_translated = 1 THEN 'translated' ELSE 'not_needed' END;
+                CREATE INDEX IF NOT EXISTS idx_messages_translation_status
+                    ON messages(contact_id, translation_status);
+                "#,
+            )?;
+            info!("Database migration complete: added translation_status column");
+        }
 
         Ok(())
     }
 
-    /// Update the translation for an existing message
-    pub fn update_message_translation(
-        &self,
-        message_id: &str,
-        translated_text: Option<&str>,
-        source_language: Option<&str>,
-    ) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+    /// Add the muted setting to contacts
+    fn migrate_add_muted_column(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('contacts') WHERE name = 'muted'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
 
-        conn.execute(
-            r#"
-            UPDATE messages 
-            SET translated_text = ?1, source_language = ?2, is_translated = 1
-            WHERE id = ?3
-            "#,
-            params![translated_text, source_language, message_id],
-        )?;
+        if !has_column {
+            info!("Migrating database: adding muted column...");
+            conn.execute(
+                "ALTER TABLE contacts ADD COLUMN muted INTEGER NOT NULL DEFAULT 0",
+                [],
+            )?;
+            info!("Database migration complete: added muted column");
+        }
 
         Ok(())
     }
 
-    /// Get all contacts sorted by pinned status first, then last message time
-    pub fn get_contacts(&self) -> Result<Vec<StoredContact>> {
-        let conn = self.conn.lock().unwrap();
+    /// Add urgency and tone to messages, and the triage setting to contacts
+    fn migrate_add_triage_columns(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('messages') WHERE name = 'urgency'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
 
-        // Use a subquery to get the last message for each contact
-        // The inner subquery ensures we only get one message per contact (the latest by rowid)
-        let mut stmt = conn.prepare(
-            r#"
-            SELECT 
-                c.id, c.name, c.phone, c.type, c.last_message_time, c.unread_count, c.pinned_at,
-                m.content_json, m.content_type, m.is_from_me
-            FROM contacts c
-            LEFT JOIN (
-                SELECT contact_id, content_json, content_type, is_from_me, timestamp,
-                       ROW_NUMBER() OVER (PARTITION BY contact_id ORDER BY timestamp DESC, rowid DESC) as rn
-                FROM messages
-            ) m ON m.contact_id = c.id AND m.rn = 1
-            ORDER BY 
-                CASE WHEN c.pinned_at IS NOT NULL THEN 0 ELSE 1 END,
-                c.pinned_at ASC,
-                c.last_message_time DESC
-            "#,
-        )?;
+        if !has_column {
+            info!("Migrating database: adding triage columns...");
+            conn.execute_batch(
+                r#"
+                ALTER TABLE messages ADD COLUMN urgency TEXT;
+                ALTER TABLE messages ADD COLUMN tone TEXT;
+                ALTER TABLE contacts ADD COLUMN triage INTEGER;
+                CREATE INDEX IF NOT EXISTS idx_messages_urgency ON messages(urgency, timestamp);
+                "#,
+            )?;
+            info!("Database migration complete: added triage columns");
+        }
 
-        let contacts = stmt
-            .query_map([], |row| {
-                let content_json: Option<String> = row.get(7)?;
-                let content_type: Option<String> = row.get(8)?;
-                let is_from_me: Option<bool> = row.get(9)?;
+        Ok(())
+    }
 
-                let preview = Self::generate_message_preview(
-                    content_json.as_deref(),
-                    content_type.as_deref(),
-                    is_from_me.unwrap_or(false),
-                );
+    /// Add sort_key to messages, backfilled from the timestamps with the
+    /// rowid breaking ties
+    fn migrate_add_sort_key_column(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('messages') WHERE name = 'sort_key'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
 
-                Ok(StoredContact {
-                    id: row.get(0)?,
-                    name: row.get(1)?,
-                    phone: row.get(2)?,
-                    contact_type: row.get(3)?,
-                    last_message_time: row.get(4)?,
-                    unread_count: row.get(5)?,
-                    pinned_at: row.get(6)?,
-                    last_message_preview: preview,
-                })
-            })?
-            .filter_map(|r| r.ok())
-            .collect();
+        if !has_column {
+            info!("Migrating database: adding sort_key column...");
+            conn.execute_batch(
+                r#"
+                ALTER TABLE messages ADD COLUMN sort_key INTEGER;
+                UPDATE messages SET sort_key = ranked.sort_key
+                FROM (
+                    SELECT rowid AS message_rowid,
+                           timestamp * 1000 + ROW_NUMBER() OVER (
+                               PARTITION BY contact_id, timestamp ORDER BY rowid
+                           ) - 1 AS sort_key
+                    FROM messages
+                ) AS ranked
+                WHERE messages.rowid = ranked.message_rowid;
+                "#,
+            )?;
+            info!("Database migration complete: added sort_key column");
+        }
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_messages_contact_sort_key ON messages(contact_id, sort_key)",
+            [],
+        )?;
 
-        Ok(contacts)
+        Ok(())
     }
 
-    /// Generate a preview string for a message (matching frontend logic)
-    fn generate_message_preview(
-        content_json: Option<&str>,
-        content_type: Option<&str>,
-        is_from_me: bool,
-    ) -> Option<String> {
-        let prefix = if is_from_me { "You: " } else { "" };
-
-        let content_type = content_type?;
-        let content_json = content_json?;
-
-        let content: serde_json::Value = serde_json::from_str(content_json).ok()?;
+    /// Add last_read_timestamp to contacts, set from the current unread
+    /// counts: just before the last `unread_count` incoming messages, or the
+    /// latest message for chats without unread messages
+    fn migrate_add_last_read_column(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('contacts') WHERE name = 'last_read_timestamp'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
 
-        // Normalize content_type to lowercase for matching
-        let content_type_lower = content_type.to_lowercase();
-        let preview = match content_type_lower.as_str() {
-            "text" => {
-                let body = content
-                    .get("body")
-                    .or_else(|| content.get("text"))
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("");
-                let truncated: String = body.chars().take(50).collect();
-                format!("{}{}", prefix, truncated)
-            }
-            "image" => {
-                let caption = content
-                    .get("caption")
-                    .and_then(|v| v.as_str())
-                    .map(|c| {
-                        let truncated: String = c.chars().take(30).collect();
-                        format!(" {}", truncated)
-                    })
-                    .unwrap_or_default();
-                format!("{}[ Image ]{}", prefix, caption)
-            }
-            "video" => {
-                let caption = content
-                    .get("caption")
-                    .and_then(|v| v.as_str())
-                    .map(|c| {
-                        let truncated: String = c.chars().take(30).collect();
-                        format!(" {}", truncated)
-                    })
-                    .unwrap_or_default();
-                format!("{}[ Video ]{}", prefix, caption)
-            }
-            "audio" => {
-                let is_voice = content
-                    .get("is_voice_note")
-                    .or_else(|| content.get("isVoiceNote"))
-                    .and_then(|v| v.as_bool())
-                    .unwrap_or(false);
-                if is_voice {
-                    format!("{}[ Voice Note ]", prefix)
-                } else {
-                    format!("{}[ Audio ]", prefix)
-                }
-            }
-            "voice note" => format!("{}[ Voice Note ]", prefix),
-            "document" => {
-                let file_name = content
-                    .get("file_name")
-                    .or_else(|| content.get("fileName"))
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("file");
-                format!("{}[ Document: {} ]", prefix, file_name)
-            }
-            "sticker" => format!("{}[ Sticker ]", prefix),
-            "location" => format!("{}[ Location ]", prefix),
-            "contact" => {
-                let name = content.get("name").and_then(|v| v.as_str()).unwrap_or("");
-                format!("{}[ Contact: {} ]", prefix, name)
-            }
-            "reaction" => {
-                let emoji = content.get("emoji").and_then(|v| v.as_str()).unwrap_or("");
-                format!("{}{}", prefix, emoji)
-            }
-            "revoked" => "[ Message deleted ]".to_string(),
-            "poll" => {
-                let question = content
-                    .get("question")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("");
-                format!("{}[ Poll: {} ]", prefix, question)
-            }
-            _ => format!("{}[ Message ]", prefix),
-        };
+        if !has_column {
+            info!("Migrating database: adding last_read_timestamp column...");
+            conn.execute_batch(&format!(
+                r#"
+                ALTER TABLE contacts ADD COLUMN last_read_timestamp INTEGER;
+                UPDATE contacts SET last_read_timestamp = {};
+                "#,
+                Self::last_read_sql("contacts.unread_count", "contacts.id")
+            ))?;
+            info!("Database migration complete: added last_read_timestamp column");
+        }
 
-        Some(preview)
+        Ok(())
     }
 
-    /// Pin or unpin a contact
-    pub fn toggle_pin(&self, contact_id: &str) -> Result<bool> {
-        let conn = self.conn.lock().unwrap();
+    /// Add the translation_participants table: group participants whose
+    /// messages are always or never translated
+    fn migrate_add_translation_participants_table(&self, conn: &Connection) -> Result<()> {
+        conn.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS translation_participants (
+                group_jid TEXT NOT NULL,
+                participant_jid TEXT NOT NULL,
+                mode TEXT NOT NULL CHECK (mode IN ('always', 'never')),
+                updated_at INTEGER NOT NULL,
+                PRIMARY KEY (group_jid, participant_jid)
+            )
+            "#,
+            [],
+        )?;
+        Ok(())
+    }
 
-        // Check if currently pinned
-        let currently_pinned: Option<i64> = conn
+    /// Add outgoing_translation_set to contacts, marking auto_translate_outgoing
+    /// as chosen for the contact rather than the default for its chat type.
+    /// Contacts switched to "send as typed" so far keep that choice.
+    fn migrate_add_outgoing_translation_set_column(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
             .query_row(
-                "SELECT pinned_at FROM contacts WHERE id = ?",
-                params![contact_id],
+                "SELECT COUNT(*) FROM pragma_table_info('contacts') WHERE name = 'outgoing_translation_set'",
+                [],
                 |row| row.get(0),
             )
-            .ok()
-            .flatten();
+            .unwrap_or(0)
+            > 0;
 
-        if currently_pinned.is_some() {
-            // Unpin
-            conn.execute(
-                "UPDATE contacts SET pinned_at = NULL WHERE id = ?",
-                params![contact_id],
-            )?;
-            Ok(false) // Now unpinned
-        } else {
-            // Pin with current timestamp
-            let now = chrono::Utc::now().timestamp_millis();
-            conn.execute(
-                "UPDATE contacts SET pinned_at = ? WHERE id = ?",
-                params![now, contact_id],
+        if !has_column {
+            info!("Migrating database: adding outgoing_translation_set column...");
+            conn.execute_batch(
+                r#"
+                ALTER TABLE contacts ADD COLUMN outgoing_translation_set INTEGER NOT NULL DEFAULT 0;
+                UPDATE contacts SET outgoing_translation_set = 1 WHERE auto_translate_outgoing = 0;
+                "#,
             )?;
-            Ok(true) // Now pinned
+            info!("Database migration complete: added outgoing_translation_set column");
         }
-    }
 
-    /// Get conversation settings for a contact
-    pub fn get_conversation_settings(&self, contact_id: &str) -> Result<ConversationSettings> {
-        let conn = self.conn.lock().unwrap();
+        Ok(())
+    }
 
-        let result = conn.query_row(
-            "SELECT language_override, translation_style FROM contacts WHERE id = ?",
-            params![contact_id],
-            |row| {
-                Ok(ConversationSettings {
-                    language_override: row.get(0)?,
-                    translation_style: row.get(1)?,
-                })
-            },
-        );
+    /// Add the duration and waveform of audio messages
+    fn migrate_add_audio_columns(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('messages') WHERE name = 'audio_duration_ms'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
 
-        match result {
-            Ok(settings) => Ok(settings),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(ConversationSettings::default()),
-            Err(e) => Err(e.into()),
+        if !has_column {
+            info!("Migrating database: adding audio columns...");
+            conn.execute_batch(
+                r#"
+                ALTER TABLE messages ADD COLUMN audio_duration_ms INTEGER;
+                ALTER TABLE messages ADD COLUMN audio_waveform TEXT;
+                ALTER TABLE messages ADD COLUMN audio_metadata_only INTEGER NOT NULL DEFAULT 0;
+                "#,
+            )?;
+            info!("Database migration complete: added audio columns");
         }
+
+        Ok(())
     }
 
-    /// Update conversation settings for a contact
-    pub fn update_conversation_settings(
-        &self,
-        contact_id: &str,
-        settings: &ConversationSettings,
-    ) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+    /// Add last_seen column to contacts, filled in from presence updates
+    fn migrate_add_last_seen_column(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('contacts') WHERE name = 'last_seen'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
+
+        if !has_column {
+            info!("Migrating database: adding last_seen column...");
+            conn.execute("ALTER TABLE contacts ADD COLUMN last_seen INTEGER", [])?;
+            info!("Database migration complete: added last_seen column");
+        }
+
+        Ok(())
+    }
+
+    /// Add the learning_mode setting to contacts and vocab_json to messages
+    fn migrate_add_learning_mode_columns(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('messages') WHERE name = 'vocab_json'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
+
+        if !has_column {
+            info!("Migrating database: adding learning mode columns...");
+            conn.execute_batch(
+                r#"
+                ALTER TABLE contacts ADD COLUMN learning_mode INTEGER NOT NULL DEFAULT 0;
+                ALTER TABLE messages ADD COLUMN vocab_json TEXT;
+                "#,
+            )?;
+            info!("Database migration complete: added learning mode columns");
+        }
+
+        Ok(())
+    }
 
+    /// Add the drafts table, one unsent draft per conversation
+    fn migrate_add_drafts_table(&self, conn: &Connection) -> Result<()> {
         conn.execute(
-            "UPDATE contacts SET language_override = ?, translation_style = ? WHERE id = ?",
-            params![
-                settings.language_override,
-                settings.translation_style,
-                contact_id
-            ],
+            r#"
+            CREATE TABLE IF NOT EXISTS drafts (
+                contact_id TEXT PRIMARY KEY,
+                text TEXT NOT NULL,
+                reply_to_message_id TEXT,
+                updated_at INTEGER NOT NULL
+            )
+            "#,
+            [],
         )?;
+        Ok(())
+    }
 
-        info!(
-            "Updated conversation settings for {}: language={:?}, style={:?}",
-            contact_id, settings.language_override, settings.translation_style
-        );
+    /// Add the pinned_messages table, messages pinned to the top of their chat
+    fn migrate_add_pinned_messages_table(&self, conn: &Connection) -> Result<()> {
+        conn.execute_batch(
+            r#"
+            CREATE TABLE IF NOT EXISTS pinned_messages (
+                message_id TEXT PRIMARY KEY,
+                contact_id TEXT NOT NULL,
+                pinned_at INTEGER NOT NULL,
+                pinned_by TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_pinned_messages_contact
+                ON pinned_messages(contact_id, pinned_at);
+            "#,
+        )?;
+        Ok(())
+    }
 
+    /// Add the reports table, weekly reports kept so past weeks can be read
+    fn migrate_add_reports_table(&self, conn: &Connection) -> Result<()> {
+        conn.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS reports (
+                week TEXT PRIMARY KEY,
+                period_start INTEGER NOT NULL,
+                period_end INTEGER NOT NULL,
+                generated_at INTEGER NOT NULL,
+                markdown TEXT NOT NULL,
+                stats_json TEXT NOT NULL,
+                delivered_to TEXT,
+                delivered_at INTEGER
+            )
+            "#,
+            [],
+        )?;
         Ok(())
     }
 
-    /// Get messages for a specific contact (all messages - for MCP/internal use)
-    pub fn get_messages(&self, contact_id: &str) -> Result<Vec<StoredMessage>> {
-        self.get_messages_paginated(contact_id, None, None, false)
+    /// Add the jobs table, finished background jobs kept for looking back on
+    fn migrate_add_jobs_table(&self, conn: &Connection) -> Result<()> {
+        conn.execute_batch(
+            r#"
+            CREATE TABLE IF NOT EXISTS jobs (
+                id TEXT PRIMARY KEY,
+                kind TEXT NOT NULL,
+                state TEXT NOT NULL,
+                progress_json TEXT NOT NULL,
+                result TEXT,
+                error TEXT,
+                created_at INTEGER NOT NULL,
+                started_at INTEGER,
+                finished_at INTEGER
+            );
+            CREATE INDEX IF NOT EXISTS idx_jobs_created_at ON jobs(created_at);
+            "#,
+        )?;
+        Ok(())
     }
 
-    /// Get media data for a specific message
-    /// Returns the media_data and mime_type for a message
-    pub fn get_message_media(&self, message_id: &str) -> Result<Option<(String, Option<String>)>> {
-        let conn = self.conn.lock().unwrap();
+    fn migrate_add_learning_notes_column(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('contacts') WHERE name = 'include_learning_notes'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
 
-        let result = conn.query_row(
-            "SELECT content_json FROM messages WHERE id = ?",
-            params![message_id],
-            |row| row.get::<_, String>(0),
-        );
+        if !has_column {
+            info!("Migrating database: adding include_learning_notes column to contacts...");
+            conn.execute(
+                "ALTER TABLE contacts ADD COLUMN include_learning_notes INTEGER NOT NULL DEFAULT 0",
+                [],
+            )?;
+            info!("Database migration complete: added include_learning_notes column");
+        }
 
-        match result {
-            Ok(content_json) => {
-                // Parse and extract media_data and mime_type
-                if let Ok(content) = serde_json::from_str::<serde_json::Value>(&content_json) {
-                    let media_data = content
-                        .get("media_data")
-                        .or_else(|| content.get("mediaData"))
-                        .and_then(|v| v.as_str())
-                        .map(|s| s.to_string());
+        Ok(())
+    }
 
-                    let mime_type = content
-                        .get("mime_type")
-                        .or_else(|| content.get("mimeType"))
-                        .and_then(|v| v.as_str())
-                        .map(|s| s.to_string());
+    /// Add contacts' folded names (see `name_search`), filled in for the
+    /// contacts already stored
+    fn migrate_add_name_normalized_column(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('contacts') WHERE name = 'name_normalized'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
 
-                    if let Some(media) = media_data {
-                        return Ok(Some((media, mime_type)));
-                    }
-                }
-                Ok(None)
+        if !has_column {
+            info!("Migrating database: adding name_normalized column to contacts...");
+            let tx = conn.unchecked_transaction()?;
+            tx.execute("ALTER TABLE contacts ADD COLUMN name_normalized TEXT", [])?;
+            let names: Vec<(String, String)> = tx
+                .prepare("SELECT id, name FROM contacts WHERE name IS NOT NULL")?
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+            for (id, name) in &names {
+                Self::store_name_normalized(&tx, id, Some(name))?;
             }
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
+            tx.commit()?;
+            info!(
+                "Database migration complete: added name_normalized column ({} names)",
+                names.len()
+            );
         }
+
+        Ok(())
     }
 
-    /// Strip media_data from content JSON to reduce payload size
-    fn strip_media_from_content(content_json: &str) -> (String, Option<serde_json::Value>) {
-        if let Ok(mut content) = serde_json::from_str::<serde_json::Value>(content_json) {
-            // Check if this content has media_data
-            let has_media =
-                content.get("media_data").is_some() || content.get("mediaData").is_some();
+    /// Index translated messages by conversation for the translation history
+    fn migrate_add_translated_messages_index(&self, conn: &Connection) -> Result<()> {
+        conn.execute(
+            r#"
+            CREATE INDEX IF NOT EXISTS idx_messages_translated
+                ON messages(contact_id, timestamp) WHERE is_translated = 1
+            "#,
+            [],
+        )?;
+        Ok(())
+    }
 
-            if has_media {
-                // Remove media_data from content
-                if let Some(obj) = content.as_object_mut() {
-                    obj.remove("media_data");
-                    obj.remove("mediaData");
-                    // Add a flag to indicate media is available
-                    obj.insert("has_media".to_string(), serde_json::Value::Bool(true));
-                }
-                let stripped_json =
-                    serde_json::to_string(&content).unwrap_or_else(|_| content_json.to_string());
-                return (stripped_json, Some(content));
-            }
+    /// Add csrf_token and browser_binding columns to oauth_pending_auth.
+    /// Requests pending from before have neither and can't be approved.
+    fn migrate_add_oauth_pending_binding_columns(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('oauth_pending_auth') WHERE name = 'csrf_token'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
 
-            (content_json.to_string(), Some(content))
-        } else {
-            (content_json.to_string(), None)
+        if !has_column {
+            info!("Migrating database: adding OAuth approval binding columns...");
+            conn.execute_batch(
+                r#"
+                ALTER TABLE oauth_pending_auth ADD COLUMN csrf_token TEXT NOT NULL DEFAULT '';
+                ALTER TABLE oauth_pending_auth ADD COLUMN browser_binding TEXT NOT NULL DEFAULT '';
+                "#,
+            )?;
+            info!("Database migration complete: added OAuth approval binding columns");
         }
+
+        Ok(())
     }
 
-    /// Get messages for a specific contact with pagination
-    /// - limit: max number of messages to return (default: all)
-    /// - before_timestamp: only get messages before this timestamp (for loading older messages)
-    /// - strip_media: if true, remove media_data from content to reduce payload size
-    /// Returns messages in ascending order by timestamp (oldest first)
-    pub fn get_messages_paginated(
-        &self,
-        contact_id: &str,
-        limit: Option<u32>,
-        before_timestamp: Option<i64>,
-        strip_media: bool,
-    ) -> Result<Vec<StoredMessage>> {
-        let conn = self.conn.lock().unwrap();
+    /// Index reactions by conversation and time for the reaction stats
+    fn migrate_add_reactions_index(&self, conn: &Connection) -> Result<()> {
+        conn.execute(
+            r#"
+            CREATE INDEX IF NOT EXISTS idx_messages_reactions
+                ON messages(contact_id, timestamp) WHERE content_type = 'Reaction'
+            "#,
+            [],
+        )?;
+        Ok(())
+    }
 
-        // First get the contact info to populate contact_name and contact_phone
-        let contact_info: Option<(Option<String>, Option<String>)> = conn
+    /// Add a thumbnail column to media_blobs. Images already stored have no
+    /// thumbnail and are shown with a placeholder.
+    fn migrate_add_media_thumbnail_column(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
             .query_row(
-                "SELECT name, phone FROM contacts WHERE id = ?",
-                params![contact_id],
-                |row| Ok((row.get(0)?, row.get(1)?)),
+                "SELECT COUNT(*) FROM pragma_table_info('media_blobs') WHERE name = 'thumbnail'",
+                [],
+                |row| row.get(0),
             )
-            .ok();
+            .unwrap_or(0)
+            > 0;
 
-        let (contact_name, contact_phone) = contact_info.unwrap_or((None, None));
+        if !has_column {
+            info!("Migrating database: adding thumbnail column to media_blobs...");
+            conn.execute("ALTER TABLE media_blobs ADD COLUMN thumbnail TEXT", [])?;
+            info!("Database migration complete: added thumbnail column");
+        }
 
-        // Build query based on parameters
-        // We select in DESC order to get the most recent N messages, then reverse
-        let query = match (limit, before_timestamp) {
-            (Some(lim), Some(before)) => {
-                format!(
-                    r#"
-                    SELECT id, contact_id, timestamp, is_from_me, is_forwarded, sender_name, 
-                           sender_phone, chat_type, content_type, content_json, original_text,
-                           translated_text, source_language, is_translated
-                    FROM messages 
-                    WHERE contact_id = ? AND timestamp < ?
-                    ORDER BY timestamp DESC
-                    LIMIT {}
-                    "#,
-                    lim
-                )
-            }
-            (Some(lim), None) => {
-                format!(
-                    r#"
-                    SELECT id, contact_id, timestamp, is_from_me, is_forwarded, sender_name, 
-                           sender_phone, chat_type, content_type, content_json, original_text,
-                           translated_text, source_language, is_translated
-                    FROM messages 
-                    WHERE contact_id = ?
-                    ORDER BY timestamp DESC
-                    LIMIT {}
-                    "#,
-                    lim
-                )
-            }
-            (None, Some(before)) => r#"
-                SELECT id, contact_id, timestamp, is_from_me, is_forwarded, sender_name, 
-                       sender_phone, chat_type, content_type, content_json, original_text,
-                       translated_text, source_language, is_translated
-                FROM messages 
-                WHERE contact_id = ? AND timestamp < ?
-                ORDER BY timestamp ASC
-                "#
-            .to_string(),
-            (None, None) => r#"
-                SELECT id, contact_id, timestamp, is_from_me, is_forwarded, sender_name, 
-                       sender_phone, chat_type, content_type, content_json, original_text,
-                       translated_text, source_language, is_translated
-                FROM messages 
-                WHERE contact_id = ?
-                ORDER BY timestamp ASC
-                "#
-            .to_string(),
-        };
+        Ok(())
+    }
 
-        let mut stmt = conn.prepare(&query)?;
+    /// Add the key/value settings table and per-client MCP usage table
+    fn migrate_add_mcp_client_usage_table(&self, conn: &Connection) -> Result<()> {
+        conn.execute_batch(
+            r#"
+            CREATE TABLE IF NOT EXISTS settings (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );
 
-        // Helper to build StoredMessage from row
-        let build_message = |row: &rusqlite::Row,
-                             contact_name: &Option<String>,
-                             contact_phone: &Option<String>,
-                             strip: bool|
-         -> rusqlite::Result<StoredMessage> {
-            let raw_content_json: String = row.get(9)?;
-            let (content_json, content) = if strip {
-                Self::strip_media_from_content(&raw_content_json)
-            } else {
-                (
-                    raw_content_json.clone(),
-                    serde_json::from_str(&raw_content_json).ok(),
-                )
-            };
+            CREATE TABLE IF NOT EXISTS mcp_client_usage (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                client_id TEXT NOT NULL,
+                tool TEXT NOT NULL,
+                sent INTEGER NOT NULL DEFAULT 0,
+                cost_usd REAL NOT NULL DEFAULT 0,
+                timestamp INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_mcp_client_usage_client
+                ON mcp_client_usage(client_id, timestamp);
+            "#,
+        )?;
+        Ok(())
+    }
 
-            Ok(StoredMessage {
-                id: row.get(0)?,
-                contact_id: row.get(1)?,
-                timestamp: row.get(2)?,
-                is_from_me: row.get(3)?,
-                is_forwarded: row.get(4)?,
-                sender_name: row.get(5)?,
-                sender_phone: row.get(6)?,
-                contact_name: contact_name.clone(),
-                contact_phone: contact_phone.clone(),
-                chat_type: row.get(7)?,
-                content_type: row.get(8)?,
-                content_json,
-                content,
-                original_text: row.get(10)?,
-                translated_text: row.get(11)?,
-                source_language: row.get(12)?,
-                is_translated: row.get(13)?,
-            })
-        };
-
-        let messages: Vec<StoredMessage> = if before_timestamp.is_some() {
-            stmt.query_map(params![contact_id, before_timestamp], |row| {
-                build_message(row, &contact_name, &contact_phone, strip_media)
-            })?
-            .filter_map(|r| r.ok())
-            .collect()
-        } else {
-            stmt.query_map(params![contact_id], |row| {
-                build_message(row, &contact_name, &contact_phone, strip_media)
-            })?
-            .filter_map(|r| r.ok())
-            .collect()
-        };
+    /// Add media_blobs table and media_hash column, then dedup stored media
+    fn migrate_add_media_blobs_table(&self, conn: &Connection) -> Result<()> {
+        let has_media_hash: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('messages') WHERE name = 'media_hash'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
 
-        // If we used DESC order with limit, reverse to get chronological order
-        if limit.is_some() {
-            let mut messages = messages;
-            messages.reverse();
-            Ok(messages)
-        } else {
-            Ok(messages)
+        if has_media_hash {
+            return Ok(());
         }
-    }
 
-    /// Get a contact by ID
-    pub fn get_contact(&self, contact_id: &str) -> Result<Option<StoredContact>> {
-        let conn = self.conn.lock().unwrap();
+        info!("Migrating database: adding media_blobs table...");
 
-        let mut stmt = conn.prepare(
+        let tx = conn.unchecked_transaction()?;
+        tx.execute_batch(
             r#"
-            SELECT 
-                c.id, c.name, c.phone, c.type, c.last_message_time, c.unread_count, c.pinned_at,
-                m.content_json, m.content_type, m.is_from_me
-            FROM contacts c
-            LEFT JOIN (
-                SELECT contact_id, content_json, content_type, is_from_me,
-                       ROW_NUMBER() OVER (PARTITION BY contact_id ORDER BY timestamp DESC, rowid DESC) as rn
-                FROM messages
-            ) m ON m.contact_id = c.id AND m.rn = 1
-            WHERE c.id = ?
+            CREATE TABLE IF NOT EXISTS media_blobs (
+                hash TEXT PRIMARY KEY,
+                data TEXT NOT NULL,
+                mime_type TEXT,
+                size INTEGER NOT NULL,
+                ref_count INTEGER NOT NULL DEFAULT 0,
+                created_at INTEGER NOT NULL,
+                thumbnail TEXT
+            );
+
+            ALTER TABLE messages ADD COLUMN media_hash TEXT;
+            CREATE INDEX IF NOT EXISTS idx_messages_media_hash ON messages(media_hash);
             "#,
         )?;
 
-        let contact = stmt
-            .query_row(params![contact_id], |row| {
-                let content_json: Option<String> = row.get(7)?;
-                let content_type: Option<String> = row.get(8)?;
-                let is_from_me: Option<bool> = row.get(9)?;
+        // Move media already embedded in messages into media_blobs
+        let rows: Vec<(String, String)> = {
+            let mut stmt = tx.prepare(
+                r#"
+                SELECT id, content_json FROM messages
+                WHERE content_json LIKE '%"media_data"%' OR content_json LIKE '%"mediaData"%'
+                "#,
+            )?;
+            let rows = stmt
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .filter_map(|r| r.ok())
+                .collect();
+            rows
+        };
 
-                let preview = Self::generate_message_preview(
-                    content_json.as_deref(),
-                    content_type.as_deref(),
-                    is_from_me.unwrap_or(false),
-                );
+        let mut original_bytes: i64 = 0;
+        for (id, content_json) in &rows {
+            if let Some(media) = Self::extract_media(content_json) {
+                original_bytes += media.data.len() as i64;
+                Self::store_media_blob(&tx, &media)?;
+                tx.execute(
+                    "UPDATE messages SET content_json = ?1, media_hash = ?2 WHERE id = ?3",
+                    params![media.content_json, media.hash, id],
+                )?;
+            }
+        }
 
-                Ok(StoredContact {
-                    id: row.get(0)?,
-                    name: row.get(1)?,
-                    phone: row.get(2)?,
-                    contact_type: row.get(3)?,
-                    last_message_time: row.get(4)?,
-                    unread_count: row.get(5)?,
-                    pinned_at: row.get(6)?,
-                    last_message_preview: preview,
-                })
-            })
-            .ok();
+        let stored_bytes: i64 = tx.query_row(
+            "SELECT COALESCE(SUM(size), 0) FROM media_blobs",
+            [],
+            |row| row.get(0),
+        )?;
+        tx.commit()?;
 
-        Ok(contact)
-    }
+        info!(
+            "Database migration complete: moved media from {} messages into media_blobs, reclaimed {} bytes",
+            rows.len(),
+            original_bytes - stored_bytes
+        );
 
-    /// Get database statistics
-    pub fn get_stats(&self) -> Result<(i64, i64)> {
-        let conn = self.conn.lock().unwrap();
+        Ok(())
+    }
 
-        let message_count: i64 =
-            conn.query_row("SELECT COUNT(*) FROM messages", [], |row| row.get(0))?;
+    /// Add origin column to messages table
+    fn migrate_add_message_origin_column(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('messages') WHERE name = 'origin'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
 
-        let contact_count: i64 =
-            conn.query_row("SELECT COUNT(*) FROM contacts", [], |row| row.get(0))?;
+        if !has_column {
+            info!("Migrating database: adding origin column to messages...");
+            conn.execute("ALTER TABLE messages ADD COLUMN origin TEXT", [])?;
+            info!("Database migration complete: added origin column to messages");
+        }
 
-        Ok((message_count, contact_count))
+        Ok(())
     }
 
-    /// Get the predominant language used by a contact in recent messages.
-    /// Returns the most common source_language from the last N incoming messages.
-    pub fn get_conversation_language(
-        &self,
-        contact_id: &str,
-        _limit: usize,
-    ) -> Result<Option<String>> {
-        let conn = self.conn.lock().unwrap();
-
-        // Get the most common source language from recent incoming (not from me) messages
-        let mut stmt = conn.prepare(
-            r#"
-            SELECT source_language, COUNT(*) as cnt
-            FROM messages 
-            WHERE contact_id = ? 
-              AND is_from_me = 0 
-              AND source_language IS NOT NULL
-              AND source_language != ''
-            GROUP BY source_language
-            ORDER BY cnt DESC
-            LIMIT 1
-            "#,
-        )?;
+    /// Add mentioned_jids and mentions_me columns to messages table
+    fn migrate_add_message_mentions_columns(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('messages') WHERE name = 'mentions_me'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
 
-        let language: Option<String> = stmt.query_row(params![contact_id], |row| row.get(0)).ok();
+        if !has_column {
+            info!("Migrating database: adding mention columns to messages...");
+            conn.execute_batch(
+                r#"
+                ALTER TABLE messages ADD COLUMN mentioned_jids TEXT;
+                ALTER TABLE messages ADD COLUMN mentions_me INTEGER NOT NULL DEFAULT 0;
+                CREATE INDEX IF NOT EXISTS idx_messages_mentions_me
+                    ON messages(timestamp) WHERE mentions_me = 1;
+                "#,
+            )?;
+            info!("Database migration complete: added mention columns to messages");
+        }
 
-        Ok(language)
+        Ok(())
     }
 
-    /// Record translation usage for a message
-    pub fn record_usage(
-        &self,
-        contact_id: Option<&str>,
-        message_id: Option<&str>,
-        usage: &UsageInfo,
-        operation: &str,
-    ) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
-
-        conn.execute(
+    /// Add created_at and updated_at columns to contacts, backfilled from
+    /// each contact's earliest message, and the contact_events table
+    fn migrate_add_contact_audit_columns(&self, conn: &Connection) -> Result<()> {
+        conn.execute_batch(
             r#"
-            INSERT INTO translation_usage 
-            (contact_id, message_id, timestamp, input_tokens, output_tokens, cost_usd, operation)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            CREATE TABLE IF NOT EXISTS contact_events (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                contact_id TEXT NOT NULL,
+                field TEXT NOT NULL,
+                old_value TEXT,
+                new_value TEXT,
+                changed_at INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_contact_events_contact
+                ON contact_events(contact_id, changed_at);
             "#,
-            params![
-                contact_id,
-                message_id,
-                timestamp,
-                usage.input_tokens,
-                usage.output_tokens,
-                usage.cost_usd,
-                operation,
-            ],
         )?;
 
+        let has_column: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('contacts') WHERE name = 'created_at'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
+
+        if !has_column {
+            info!("Migrating database: adding contact created_at/updated_at columns...");
+            conn.execute_batch(
+                r#"
+                ALTER TABLE contacts ADD COLUMN created_at INTEGER;
+                ALTER TABLE contacts ADD COLUMN updated_at INTEGER;
+                UPDATE contacts SET created_at = COALESCE(
+                    (SELECT MIN(timestamp) FROM messages WHERE messages.contact_id = contacts.id),
+                    NULLIF(last_message_time, 0)
+                );
+                UPDATE contacts SET updated_at = created_at;
+                "#,
+            )?;
+            info!("Database migration complete: added contact created_at/updated_at columns");
+        }
+
         Ok(())
     }
 
-    /// Get total usage across all conversations
-    pub fn get_global_usage(&self) -> Result<UsageInfo> {
-        let conn = self.conn.lock().unwrap();
+    /// Add conversation_language and language_message_count columns to
+    /// contacts and backfill them from existing messages
+    fn migrate_add_conversation_language_cache(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('contacts') WHERE name = 'conversation_language'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
 
-        let result = conn.query_row(
-            r#"
-            SELECT COALESCE(SUM(input_tokens), 0), 
-                   COALESCE(SUM(output_tokens), 0), 
-                   COALESCE(SUM(cost_usd), 0.0)
-            FROM translation_usage
-            "#,
-            [],
-            |row| {
-                Ok(UsageInfo {
-                    input_tokens: row.get::<_, i64>(0)? as u32,
-                    output_tokens: row.get::<_, i64>(1)? as u32,
-                    cost_usd: row.get(2)?,
-                })
-            },
-        )?;
+        if !has_column {
+            info!("Migrating database: adding conversation language cache...");
+            let tx = conn.unchecked_transaction()?;
+            tx.execute_batch(
+                r#"
+                ALTER TABLE contacts ADD COLUMN conversation_language TEXT;
+                ALTER TABLE contacts ADD COLUMN language_message_count INTEGER;
+                "#,
+            )?;
+            let ids: Vec<String> = tx
+                .prepare("SELECT id FROM contacts")?
+                .query_map([], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()?;
+            for id in &ids {
+                Self::recompute_conversation_language(&tx, id)?;
+            }
+            tx.commit()?;
+            info!(
+                "Database migration complete: cached conversation language for {} contacts",
+                ids.len()
+            );
+        }
 
-        Ok(result)
+        Ok(())
     }
 
-    /// Get usage for a specific conversation
-    pub fn get_conversation_usage(&self, contact_id: &str) -> Result<UsageInfo> {
-        let conn = self.conn.lock().unwrap();
+    /// Add mentions_only column to contacts table
+    fn migrate_add_mentions_only_column(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('contacts') WHERE name = 'mentions_only'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
 
-        let result = conn.query_row(
-            r#"
-            SELECT COALESCE(SUM(input_tokens), 0), 
-                   COALESCE(SUM(output_tokens), 0), 
-                   COALESCE(SUM(cost_usd), 0.0)
-            FROM translation_usage
-            WHERE contact_id = ?
-            "#,
-            params![contact_id],
-            |row| {
-                Ok(UsageInfo {
-                    input_tokens: row.get::<_, i64>(0)? as u32,
-                    output_tokens: row.get::<_, i64>(1)? as u32,
-                    cost_usd: row.get(2)?,
-                })
-            },
-        )?;
+        if !has_column {
+            info!("Migrating database: adding mentions_only column...");
+            conn.execute(
+                "ALTER TABLE contacts ADD COLUMN mentions_only INTEGER NOT NULL DEFAULT 0",
+                [],
+            )?;
+            info!("Database migration complete: added mentions_only column");
+        }
 
-        Ok(result)
+        Ok(())
     }
 
-    /// Get a cached link preview by URL
-    /// Returns None if not cached or if cache is older than max_age_secs
-    pub fn get_link_preview(&self, url: &str, max_age_secs: i64) -> Result<Option<LinkPreview>> {
-        let conn = self.conn.lock().unwrap();
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+    /// Add auto_translate_outgoing column to contacts table
+    fn migrate_add_auto_translate_outgoing_column(&self, conn: &Connection) -> Result<()> {
+        let has_column: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('contacts') WHERE name = 'auto_translate_outgoing'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
 
-        let min_time = now - max_age_secs;
+        if !has_column {
+            info!("Migrating database: adding auto_translate_outgoing column...");
+            conn.execute(
+                "ALTER TABLE contacts ADD COLUMN auto_translate_outgoing INTEGER NOT NULL DEFAULT 1",
+                [],
+            )?;
+            info!("Database migration complete: added auto_translate_outgoing column");
+        }
 
-        let result = conn.query_row(
-            r#"
-            SELECT url, title, description, image_url, site_name, error, fetched_at
-            FROM link_previews
-            WHERE url = ? AND fetched_at > ?
-            "#,
-            params![url, min_time],
-            |row| {
-                Ok(LinkPreview {
-                    url: row.get(0)?,
-                    title: row.get(1)?,
-                    description: row.get(2)?,
-                    image_url: row.get(3)?,
-                    site_name: row.get(4)?,
-                    error: row.get(5)?,
-                })
-            },
-        );
+        Ok(())
+    }
 
-        match result {
-            Ok(preview) => Ok(Some(preview)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
+    /// Add latency_ms and model columns to translation_usage table
+    fn migrate_add_usage_latency_columns(&self, conn: &Connection) -> Result<()> {
+        let has_latency: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('translation_usage') WHERE name = 'latency_ms'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
+
+        if !has_latency {
+            info!("Migrating database: adding latency columns to translation_usage...");
+            conn.execute_batch(
+                r#"
+                ALTER TABLE translation_usage ADD COLUMN latency_ms INTEGER;
+                ALTER TABLE translation_usage ADD COLUMN model TEXT;
+                "#,
+            )?;
+            info!("Database migration complete: added latency columns");
         }
+
+        Ok(())
     }
 
-    /// Save a link preview to cache
-    pub fn save_link_preview(&self, preview: &LinkPreview) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+    /// Add identity_links table mapping alternate JIDs to a canonical contact
+    fn migrate_add_identity_links_table(&self, conn: &Connection) -> Result<()> {
+        let table_exists: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='identity_links'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
 
-        conn.execute(
-            r#"
-            INSERT OR REPLACE INTO link_previews 
-            (url, title, description, image_url, site_name, error, fetched_at)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
-            "#,
-            params![
-                preview.url,
-                preview.title,
-                preview.description,
-                preview.image_url,
-                preview.site_name,
-                preview.error,
-                now,
-            ],
-        )?;
+        if !table_exists {
+            info!("Migrating database: creating identity_links table...");
+            conn.execute_batch(
+                r#"
+                CREATE TABLE identity_links (
+                    alt_jid TEXT PRIMARY KEY,
+                    canonical_id TEXT NOT NULL,
+                    source TEXT NOT NULL,
+                    created_at INTEGER NOT NULL
+                );
+
+                CREATE INDEX idx_identity_links_canonical ON identity_links(canonical_id);
+                "#,
+            )?;
+            info!("Database migration complete: created identity_links table");
+        }
 
         Ok(())
     }
 
-    /// Clear all data from the database (for logout)
-    pub fn clear_all(&self) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+    /// Add style_profiles table for AI reply generation
+    fn migrate_add_style_profiles_table(&self, conn: &Connection) -> Result<()> {
+        // Check if table exists
+        let table_exists: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='style_profiles'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
 
-        conn.execute_batch(
-            r#"
-            DELETE FROM messages;
-            DELETE FROM contacts;
-            DELETE FROM translation_usage;
-            DELETE FROM link_previews;
-            "#,
-        )?;
+        if !table_exists {
+            info!("Migrating database: creating style_profiles table...");
+            conn.execute_batch(
+                r#"
+                CREATE TABLE style_profiles (
+                    contact_id TEXT PRIMARY KEY,
+                    profile_text TEXT NOT NULL,
+                    sample_messages TEXT NOT NULL,
+                    message_count INTEGER NOT NULL,
+                    updated_at INTEGER NOT NULL
+                );
+
+                CREATE INDEX idx_style_profiles_updated ON style_profiles(updated_at);
+                "#,
+            )?;
+            info!("Database migration complete: created style_profiles table");
+        }
 
-        info!("All data cleared from database");
         Ok(())
     }
 
-    // ==================== OAuth 2.0 Methods ====================
+    /// Add conversation settings columns to contacts table
+    fn migrate_add_conversation_settings_columns(&self, conn: &Connection) -> Result<()> {
+        // Check if language_override column exists
+        let has_language_override: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('contacts') WHERE name = 'language_override'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
 
-    /// Clean up expired OAuth entries (call periodically)
-    pub fn oauth_cleanup_expired(&self) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+        if !has_language_override {
+            info!("Migrating database: adding conversation settings columns...");
+            conn.execute("ALTER TABLE contacts ADD COLUMN language_override TEXT", [])?;
+            conn.execute("ALTER TABLE contacts ADD COLUMN translation_style TEXT", [])?;
+            info!("Database migration complete: added conversation settings columns");
+        }
 
-        conn.execute(
-            "DELETE FROM oauth_pending_auth WHERE expires_at < ?",
-            params![now],
-        )?;
-        conn.execute(
-            "DELETE FROM oauth_authorization_codes WHERE expires_at < ? OR used = 1",
-            params![now],
+        Ok(())
+    }
+
+    /// Add pinned_at column to contacts table
+    fn migrate_add_pinned_column(&self, conn: &Connection) -> Result<()> {
+        // Check if column exists
+        let has_pinned_at: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('contacts') WHERE name = 'pinned_at'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
+
+        if !has_pinned_at {
+            info!("Migrating database: adding pinned_at column...");
+            conn.execute("ALTER TABLE contacts ADD COLUMN pinned_at INTEGER", [])?;
+            info!("Database migration complete: added pinned_at column");
+        }
+
+        Ok(())
+    }
+
+    /// Fix contact types based on JID suffix (groups end with @g.us)
+    fn migrate_fix_contact_types(&self, conn: &Connection) -> Result<()> {
+        // Update contacts where type doesn't match the JID suffix
+        // This fixes both NULL types and incorrectly set types
+        let updated = conn.execute(
+            r#"
+            UPDATE contacts 
+            SET type = CASE 
+                WHEN id LIKE '%@g.us' THEN 'group'
+                WHEN id LIKE '%@s.whatsapp.net' THEN 'private'
+                WHEN id LIKE '%@broadcast' THEN 'broadcast'
+                WHEN id LIKE '%@newsletter' THEN 'channel'
+                ELSE 'private'
+            END
+            WHERE type IS NULL 
+               OR (id LIKE '%@g.us' AND type != 'group')
+               OR (id LIKE '%@s.whatsapp.net' AND type NOT IN ('private', 'self'))
+               OR (id LIKE '%@broadcast' AND type != 'broadcast')
+               OR (id LIKE '%@newsletter' AND type != 'channel')
+            "#,
+            [],
         )?;
+
+        // Channels used to arrive as private chats, so existing rows carry
+        // the newsletter ID as a phone number and the wrong chat type
         conn.execute(
-            "DELETE FROM oauth_access_tokens WHERE expires_at < ?",
-            params![now],
+            "UPDATE contacts SET phone = NULL WHERE id LIKE '%@newsletter' AND phone IS NOT NULL",
+            [],
         )?;
         conn.execute(
-            "DELETE FROM oauth_refresh_tokens WHERE expires_at < ?",
-            params![now],
+            "UPDATE messages SET chat_type = 'channel'
+             WHERE contact_id LIKE '%@newsletter' AND chat_type != 'channel'",
+            [],
         )?;
 
+        if updated > 0 {
+            info!(
+                "Fixed contact types for {} contacts based on JID suffix",
+                updated
+            );
+        }
+
         Ok(())
     }
 
-    /// Store a pending authorization request
-    pub fn oauth_store_pending_auth(&self, pending: &PendingAuthorization) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+    /// Add translation columns to existing database
+    fn migrate_add_translation_columns(&self, conn: &Connection) -> Result<()> {
+        // Check if columns exist by querying table info
+        let has_original_text: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('messages') WHERE name = 'original_text'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+            > 0;
 
-        conn.execute(
-            r#"
-            INSERT OR REPLACE INTO oauth_pending_auth 
-            (session_key, client_id, redirect_uri, code_challenge, code_challenge_method, scope, state, created_at, expires_at)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
-            "#,
-            params![
-                pending.session_key,
-                pending.client_id,
-                pending.redirect_uri,
-                pending.code_challenge,
-                pending.code_challenge_method,
-                pending.scope,
-                pending.state,
-                pending.created_at,
-                pending.expires_at,
-            ],
-        )?;
+        if !has_original_text {
+            tracing::info!("Migrating database: adding translation columns...");
+            conn.execute_batch(
+                r#"
+                ALTER TABLE messages ADD COLUMN original_text TEXT;
+                ALTER TABLE messages ADD COLUMN translated_text TEXT;
+                ALTER TABLE messages ADD COLUMN source_language TEXT;
+                ALTER TABLE messages ADD COLUMN is_translated INTEGER DEFAULT 0;
+                "#,
+            )?;
+            tracing::info!("Database migration complete");
+        }
 
         Ok(())
     }
 
-    /// Get and remove a pending authorization
-    pub fn oauth_take_pending_auth(
+    /// Add or update a contact, returning what changed.
+    /// Changes to the name, phone or type are recorded in contact_events.
+    /// After a disk error the update is spilled and reported as unchanged.
+    pub fn upsert_contact(
         &self,
-        session_key: &str,
-    ) -> Result<Option<PendingAuthorization>> {
-        let conn = self.conn.lock().unwrap();
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+        id: &str,
+        name: Option<&str>,
+        phone: Option<&str>,
+        contact_type: Option<&str>,
+        last_message_time: i64,
+    ) -> Result<ContactChange> {
+        let result = retry_when_busy(|| {
+            self.write_contact(id, name, phone, contact_type, last_message_time)
+        });
+        self.spill_on_failure(result, || SpilledWrite::Contact {
+            id: id.to_string(),
+            name: name.map(str::to_string),
+            phone: phone.map(str::to_string),
+            contact_type: contact_type.map(str::to_string),
+            last_message_time,
+        })
+    }
+
+    fn write_contact(
+        &self,
+        id: &str,
+        name: Option<&str>,
+        phone: Option<&str>,
+        contact_type: Option<&str>,
+        last_message_time: i64,
+    ) -> Result<ContactChange> {
+        let conn = self.conn.lock().unwrap();
+        let id = Self::resolve_id(&conn, id);
+        self.contact_cache.invalidate(&id);
+        let now = chrono::Utc::now().timestamp_millis();
+
+        let tx = conn.unchecked_transaction()?;
+        let change = Self::upsert_contact_row(
+            &tx,
+            &ContactUpsert {
+                id: &id,
+                name,
+                phone,
+                contact_type,
+                last_message_time,
+            },
+            now,
+        )?;
+        tx.commit()?;
+
+        Ok(change)
+    }
+
+    /// Add or update contacts in one transaction, returning what changed
+    /// for each. If the batch fails they're written one at a time, so a
+    /// disk error is spilled as with `upsert_contact`.
+    pub fn upsert_contacts_batch(&self, contacts: &[ContactUpsert]) -> Result<Vec<ContactChange>> {
+        let result = (|| {
+            let conn = self.conn.lock().unwrap();
+            let now = chrono::Utc::now().timestamp_millis();
+            let tx = conn.unchecked_transaction()?;
+            let mut changes = Vec::with_capacity(contacts.len());
+            for contact in contacts {
+                let id = Self::resolve_id(&tx, contact.id);
+                self.contact_cache.invalidate(&id);
+                changes.push(Self::upsert_contact_row(
+                    &tx,
+                    &ContactUpsert {
+                        id: &id,
+                        ..*contact
+                    },
+                    now,
+                )?);
+            }
+            tx.commit()?;
+            anyhow::Ok(changes)
+        })();
+
+        match result {
+            Ok(changes) => {
+                self.spill.record_success();
+                Ok(changes)
+            }
+            Err(e) => {
+                warn!(
+                    "Batched contact update failed, retrying one at a time: {:#}",
+                    e
+                );
+                contacts
+                    .iter()
+                    .map(|c| {
+                        self.upsert_contact(
+                            c.id,
+                            c.name,
+                            c.phone,
+                            c.contact_type,
+                            c.last_message_time,
+                        )
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Upsert a contact (under its resolved ID), recording what changed
+    fn upsert_contact_row(
+        tx: &Connection,
+        contact: &ContactUpsert,
+        now: i64,
+    ) -> Result<ContactChange> {
+        let ContactUpsert {
+            id,
+            name,
+            phone,
+            contact_type,
+            last_message_time,
+        } = *contact;
+        let before = Self::contact_identity(tx, id)?;
+        tx.execute(
+            r#"
+            INSERT INTO contacts (id, name, phone, type, last_message_time, unread_count,
+                                  created_at, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, 0, ?6, ?6)
+            ON CONFLICT(id) DO UPDATE SET
+                name = COALESCE(
+                    CASE WHEN excluded.name IS NOT NULL AND excluded.name IS NOT excluded.phone 
+                         THEN excluded.name ELSE NULL END,
+                    contacts.name
+                ),
+                phone = COALESCE(excluded.phone, contacts.phone),
+                type = CASE WHEN contacts.type = 'self' THEN 'self'
+                            ELSE COALESCE(excluded.type, contacts.type) END,
+                last_message_time = MAX(contacts.last_message_time, excluded.last_message_time),
+                placeholder = 0
+            "#,
+            params![id, name, phone, contact_type, last_message_time, now],
+        )?;
+        let after = Self::contact_identity(tx, id)?.context("Contact missing after upsert")?;
+        if before
+            .as_ref()
+            .is_none_or(|before| before.name != after.name)
+        {
+            Self::store_name_normalized(tx, id, after.name.as_deref())?;
+        }
+
+        let change = match &before {
+            None => {
+                Self::record_contact_event(tx, id, "created", None, after.name.as_deref(), now)?;
+                ContactChange::Created
+            }
+            Some(before) if *before == after => ContactChange::Unchanged,
+            Some(before) => {
+                let fields = [
+                    ("name", &before.name, &after.name),
+                    ("phone", &before.phone, &after.phone),
+                    ("type", &before.contact_type, &after.contact_type),
+                ];
+                for (field, old, new) in fields {
+                    if old != new {
+                        Self::record_contact_event(
+                            tx,
+                            id,
+                            field,
+                            old.as_deref(),
+                            new.as_deref(),
+                            now,
+                        )?;
+                    }
+                }
+                tx.execute(
+                    "UPDATE contacts SET updated_at = ?1 WHERE id = ?2",
+                    params![now, id],
+                )?;
+                match (&before.name, &after.name) {
+                    (old, Some(new)) if old.as_ref() != Some(new) => ContactChange::NameChanged {
+                        old: old.clone(),
+                        new: new.clone(),
+                    },
+                    _ => ContactChange::DetailsChanged,
+                }
+            }
+        };
+
+        Ok(change)
+    }
+
+    /// Keep a contact's folded name in step with its name
+    fn store_name_normalized(conn: &Connection, id: &str, name: Option<&str>) -> Result<()> {
+        conn.execute(
+            "UPDATE contacts SET name_normalized = ?1 WHERE id = ?2",
+            params![name.map(name_search::normalize_name), id],
+        )?;
+        Ok(())
+    }
+
+    /// The tracked fields of a contact, if it exists
+    fn contact_identity(conn: &Connection, id: &str) -> Result<Option<ContactIdentity>> {
+        Ok(conn
+            .query_row(
+                "SELECT name, phone, type FROM contacts WHERE id = ?",
+                params![id],
+                |row| {
+                    Ok(ContactIdentity {
+                        name: row.get(0)?,
+                        phone: row.get(1)?,
+                        contact_type: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?)
+    }
+
+    fn record_contact_event(
+        conn: &Connection,
+        contact_id: &str,
+        field: &str,
+        old_value: Option<&str>,
+        new_value: Option<&str>,
+        changed_at: i64,
+    ) -> Result<()> {
+        conn.execute(
+            r#"
+            INSERT INTO contact_events (contact_id, field, old_value, new_value, changed_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            "#,
+            params![contact_id, field, old_value, new_value, changed_at],
+        )?;
+        Ok(())
+    }
+
+    /// Recorded changes to a contact, newest first
+    pub fn get_contact_events(&self, contact_id: &str, limit: u32) -> Result<Vec<ContactEvent>> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT field, old_value, new_value, changed_at
+            FROM contact_events
+            WHERE contact_id = ?1
+            ORDER BY changed_at DESC, id DESC
+            LIMIT ?2
+            "#,
+        )?;
+        let events = stmt
+            .query_map(params![contact_id, limit], |row| {
+                Ok(ContactEvent {
+                    field: row.get(0)?,
+                    old_value: row.get(1)?,
+                    new_value: row.get(2)?,
+                    changed_at: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(events)
+    }
+
+    /// Increment unread count for a contact
+    pub fn increment_unread(&self, contact_id: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+        self.contact_cache.invalidate(&contact_id);
+        conn.execute(
+            "UPDATE contacts SET unread_count = unread_count + 1 WHERE id = ? AND type IS NOT 'self'",
+            params![contact_id],
+        )?;
+        Ok(())
+    }
+
+    /// Mark a contact's chat read up to its latest message. Returns the new
+    /// last read timestamp (None if the contact doesn't exist)
+    pub fn mark_as_read(&self, contact_id: &str) -> Result<Option<i64>> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+        self.contact_cache.invalidate(&contact_id);
+        let last_read = conn
+            .query_row(
+                &format!(
+                    r#"
+                    UPDATE contacts SET
+                        unread_count = 0,
+                        last_read_timestamp = MAX(COALESCE(last_read_timestamp, 0), {})
+                    WHERE id = ?1
+                    RETURNING last_read_timestamp
+                    "#,
+                    Self::last_read_sql("0", "?1")
+                ),
+                params![contact_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(last_read)
+    }
+
+    /// Set unread count for a contact (used for history sync), moving the
+    /// last read timestamp to just before that many incoming messages. Call
+    /// it after storing the message, so the anchor ends up right once the
+    /// whole chat has synced, whatever order its messages arrive in
+    pub fn set_unread_count(&self, contact_id: &str, count: u32) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+        self.contact_cache.invalidate(&contact_id);
+        conn.execute(
+            &format!(
+                "UPDATE contacts SET unread_count = ?1, last_read_timestamp = {} WHERE id = ?2 AND type IS NOT 'self'",
+                Self::last_read_sql("?1", "?2")
+            ),
+            params![count as i32, contact_id],
+        )?;
+        Ok(())
+    }
+
+    /// The first unread message in a contact's chat: the first incoming
+    /// message after the last read timestamp, if the chat has unread messages
+    pub fn get_first_unread(&self, contact_id: &str) -> Result<Option<FirstUnread>> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+        let first = conn
+            .query_row(
+                &format!(
+                    r#"
+                    SELECT m.id, m.timestamp FROM contacts c
+                    JOIN messages m ON m.contact_id = c.id
+                    WHERE c.id = ? AND c.unread_count > 0
+                      AND m.timestamp > COALESCE(c.last_read_timestamp, 0)
+                      AND {UNREAD_MESSAGE_SQL}
+                    ORDER BY m.timestamp ASC, m.rowid ASC
+                    LIMIT 1
+                    "#
+                ),
+                params![contact_id],
+                |row| {
+                    Ok(FirstUnread {
+                        message_id: row.get(0)?,
+                        timestamp: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(first)
+    }
+
+    /// Update a chat's description and disappearing messages timer. None
+    /// leaves a field as it is, while "" or 0 clears it. Returns what changed.
+    pub fn set_chat_metadata(
+        &self,
+        contact_id: &str,
+        description: Option<&str>,
+        ephemeral_duration: Option<u32>,
+    ) -> Result<ChatMetadataChange> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+        self.contact_cache.invalidate(&contact_id);
+        let description = description
+            .map(str::trim)
+            .map(|d| (!d.is_empty()).then_some(d));
+        let ephemeral_duration = ephemeral_duration.map(|d| (d > 0).then_some(d));
+
+        let tx = conn.unchecked_transaction()?;
+        let Some((old_description, old_duration)) = tx
+            .query_row(
+                "SELECT description, ephemeral_duration FROM contacts WHERE id = ?",
+                params![contact_id],
+                |row| {
+                    Ok((
+                        row.get::<_, Option<String>>(0)?,
+                        row.get::<_, Option<u32>>(1)?,
+                    ))
+                },
+            )
+            .optional()?
+        else {
+            return Ok(ChatMetadataChange::default());
+        };
+
+        let change = ChatMetadataChange {
+            description: description.is_some_and(|d| d != old_description.as_deref()),
+            ephemeral_duration: ephemeral_duration.is_some_and(|d| d != old_duration),
+        };
+        if change.description {
+            tx.execute(
+                "UPDATE contacts SET description = ?1 WHERE id = ?2",
+                params![description.flatten(), contact_id],
+            )?;
+        }
+        if change.ephemeral_duration {
+            tx.execute(
+                "UPDATE contacts SET ephemeral_duration = ?1 WHERE id = ?2",
+                params![ephemeral_duration.flatten(), contact_id],
+            )?;
+        }
+        tx.commit()?;
+        Ok(change)
+    }
+
+    /// Record when a contact was last seen online (ms); older times are ignored
+    pub fn set_last_seen(&self, contact_id: &str, last_seen: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+        self.contact_cache.invalidate(&contact_id);
+        conn.execute(
+            "UPDATE contacts SET last_seen = ?1 WHERE id = ?2 AND (last_seen IS NULL OR last_seen < ?1)",
+            params![last_seen, contact_id],
+        )?;
+        Ok(())
+    }
+
+    /// Look up the canonical contact ID for a JID (returns the JID itself if unlinked)
+    fn resolve_id(conn: &Connection, jid: &str) -> String {
+        conn.query_row(
+            "SELECT canonical_id FROM identity_links WHERE alt_jid = ?",
+            params![jid],
+            |row| row.get(0),
+        )
+        .unwrap_or_else(|_| jid.to_string())
+    }
+
+    /// Resolve a JID to its canonical contact ID
+    pub fn resolve_contact_id(&self, jid: &str) -> Result<String> {
+        let conn = self.conn.lock().unwrap();
+        Ok(Self::resolve_id(&conn, jid))
+    }
+
+    /// Link an alternate JID to a canonical contact, merging any existing
+    /// contact row, messages and usage stored under the alternate JID.
+    /// An alias that is already linked keeps its existing link.
+    pub fn link_identity(&self, alt_jid: &str, canonical_id: &str, source: &str) -> Result<()> {
+        self.flush_usage();
+        let conn = self.conn.lock().unwrap();
+        self.contact_cache.clear();
+
+        let canonical_id = Self::resolve_id(&conn, canonical_id);
+        if canonical_id == alt_jid || Self::resolve_id(&conn, alt_jid) != alt_jid {
+            return Ok(());
+        }
+
+        let tx = conn.unchecked_transaction()?;
+        let now = chrono::Utc::now().timestamp_millis();
+
+        tx.execute(
+            "INSERT INTO identity_links (alt_jid, canonical_id, source, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![alt_jid, canonical_id, source, now],
+        )?;
+
+        // Keep links one hop deep so resolve_id never has to follow chains
+        tx.execute(
+            "UPDATE identity_links SET canonical_id = ?1 WHERE canonical_id = ?2",
+            params![canonical_id, alt_jid],
+        )?;
+
+        let phone = canonical_id
+            .strip_suffix("@s.whatsapp.net")
+            .map(|p| p.to_string());
+
+        tx.execute(
+            r#"
+            INSERT INTO contacts (id, name, name_normalized, phone, type, last_message_time, unread_count,
+                                  last_read_timestamp, pinned_at, language_override, translation_style, translation_tone, timezone,
+                                  auto_translate_outgoing, outgoing_translation_set, mentions_only,
+                                  created_at, updated_at)
+            SELECT ?2, name, name_normalized, ?3, type, last_message_time, unread_count, last_read_timestamp,
+                   pinned_at, language_override, translation_style, translation_tone, timezone, auto_translate_outgoing,
+                   outgoing_translation_set, mentions_only, created_at, updated_at
+            FROM contacts WHERE id = ?1
+            ON CONFLICT(id) DO UPDATE SET
+                created_at = MIN(COALESCE(contacts.created_at, excluded.created_at),
+                                 COALESCE(excluded.created_at, contacts.created_at)),
+                name = COALESCE(contacts.name, excluded.name),
+                name_normalized = CASE WHEN contacts.name IS NULL THEN excluded.name_normalized
+                                       ELSE contacts.name_normalized END,
+                phone = COALESCE(contacts.phone, excluded.phone),
+                last_message_time = MAX(contacts.last_message_time, excluded.last_message_time),
+                unread_count = contacts.unread_count + excluded.unread_count,
+                last_read_timestamp = MIN(COALESCE(contacts.last_read_timestamp, excluded.last_read_timestamp),
+                                          COALESCE(excluded.last_read_timestamp, contacts.last_read_timestamp)),
+                pinned_at = COALESCE(contacts.pinned_at, excluded.pinned_at),
+                language_override = COALESCE(contacts.language_override, excluded.language_override),
+                translation_style = COALESCE(contacts.translation_style, excluded.translation_style),
+                translation_tone = COALESCE(contacts.translation_tone, excluded.translation_tone),
+                timezone = COALESCE(contacts.timezone, excluded.timezone),
+                auto_translate_outgoing = MIN(contacts.auto_translate_outgoing, excluded.auto_translate_outgoing),
+                outgoing_translation_set = MAX(contacts.outgoing_translation_set, excluded.outgoing_translation_set),
+                mentions_only = MAX(contacts.mentions_only, excluded.mentions_only)
+            "#,
+            params![alt_jid, canonical_id, phone],
+        )?;
+
+        tx.execute(
+            "UPDATE messages SET contact_id = ?1 WHERE contact_id = ?2",
+            params![canonical_id, alt_jid],
+        )?;
+        Self::invalidate_language_cache(&tx, &canonical_id)?;
+        tx.execute(
+            "UPDATE translation_usage SET contact_id = ?1 WHERE contact_id = ?2",
+            params![canonical_id, alt_jid],
+        )?;
+        tx.execute(
+            "UPDATE contact_events SET contact_id = ?1 WHERE contact_id = ?2",
+            params![canonical_id, alt_jid],
+        )?;
+        tx.execute(
+            "UPDATE pinned_messages SET contact_id = ?1 WHERE contact_id = ?2",
+            params![canonical_id, alt_jid],
+        )?;
+        tx.execute(
+            "UPDATE OR IGNORE style_profiles SET contact_id = ?1 WHERE contact_id = ?2",
+            params![canonical_id, alt_jid],
+        )?;
+        tx.execute(
+            "DELETE FROM style_profiles WHERE contact_id = ?",
+            params![alt_jid],
+        )?;
+        tx.execute(
+            "UPDATE OR IGNORE translation_participants SET participant_jid = ?1 WHERE participant_jid = ?2",
+            params![canonical_id, alt_jid],
+        )?;
+        tx.execute(
+            "DELETE FROM translation_participants WHERE participant_jid = ?",
+            params![alt_jid],
+        )?;
+        tx.execute("DELETE FROM contacts WHERE id = ?", params![alt_jid])?;
+
+        tx.commit()?;
+
+        info!(
+            "Linked identity {} -> {} (source: {})",
+            alt_jid, canonical_id, source
+        );
+
+        Ok(())
+    }
+
+    /// Find unlinked LID contacts that look like duplicates of a phone JID
+    /// contact: same name (case-insensitive) and overlapping message activity.
+    /// Matches that are not unique or lack overlapping activity are returned
+    /// with `confident = false` for manual confirmation.
+    pub fn find_identity_link_candidates(&self) -> Result<Vec<IdentityLinkCandidate>> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn.prepare(
+            r#"
+            WITH activity AS (
+                SELECT contact_id, MIN(timestamp) AS first_ts, MAX(timestamp) AS last_ts
+                FROM messages
+                GROUP BY contact_id
+            )
+            SELECT l.id, p.id, l.name, la.first_ts, la.last_ts, pa.first_ts, pa.last_ts
+            FROM contacts l
+            JOIN contacts p ON LOWER(TRIM(p.name)) = LOWER(TRIM(l.name))
+            LEFT JOIN activity la ON la.contact_id = l.id
+            LEFT JOIN activity pa ON pa.contact_id = p.id
+            WHERE l.id LIKE '%@lid'
+              AND p.id LIKE '%@s.whatsapp.net'
+              AND l.name IS NOT NULL AND TRIM(l.name) != ''
+              AND l.id NOT IN (SELECT alt_jid FROM identity_links)
+            ORDER BY l.id, p.id
+            "#,
+        )?;
+
+        type Activity = (Option<i64>, Option<i64>);
+        let rows: Vec<(String, String, Option<String>, Activity, Activity)> = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    (row.get(3)?, row.get(4)?),
+                    (row.get(5)?, row.get(6)?),
+                ))
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        let candidates = rows
+            .iter()
+            .map(|(lid, phone_jid, name, lid_activity, phone_activity)| {
+                let overlaps = match (lid_activity, phone_activity) {
+                    ((Some(lf), Some(ll)), (Some(pf), Some(pl))) => {
+                        *lf <= pl + IDENTITY_ACTIVITY_WINDOW_MS
+                            && *pf <= ll + IDENTITY_ACTIVITY_WINDOW_MS
+                    }
+                    _ => false,
+                };
+                let unique = rows.iter().filter(|r| &r.0 == lid).count() == 1
+                    && rows.iter().filter(|r| &r.1 == phone_jid).count() == 1;
+
+                let reason = match (unique, overlaps) {
+                    (true, true) => "unique name match with overlapping activity",
+                    (true, false) => "name matches but activity does not overlap",
+                    (false, _) => "name matches more than one contact",
+                };
+
+                IdentityLinkCandidate {
+                    alt_jid: lid.clone(),
+                    canonical_id: phone_jid.clone(),
+                    name: name.clone(),
+                    confident: unique && overlaps,
+                    reason: reason.to_string(),
+                }
+            })
+            .collect();
+
+        Ok(candidates)
+    }
+
+    /// Add a message to the store.
+    /// Media data is kept once per file in media_blobs and referenced by hash.
+    /// While the store is read-only the message is buffered instead, and
+    /// after a disk error it is spilled.
+    ///
+    /// Returns the message's sort key, or None if it was already stored (or
+    /// buffered or spilled). The key goes after everything already stored in
+    /// the chat within the message's timestamp: its second when that's all
+    /// the bridge gave, otherwise its millisecond. Same-second messages thus
+    /// keep the order they arrived in, including around local sends.
+    pub fn add_message(&self, msg: &StoredMessage) -> Result<Option<i64>> {
+        if self.is_read_only() {
+            self.buffer_message(msg, false);
+            return Ok(None);
+        }
+
+        let result = retry_when_busy(|| self.write_message(msg));
+        self.spill_on_failure(result, || SpilledWrite::message(msg))
+    }
+
+    fn write_message(&self, msg: &StoredMessage) -> Result<Option<i64>> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, &msg.contact_id);
+        self.contact_cache.invalidate(&contact_id);
+
+        let tx = conn.unchecked_transaction()?;
+        let sort_key = Self::insert_message(&tx, &contact_id, msg)?;
+        tx.commit()?;
+
+        Ok(sort_key)
+    }
+
+    /// Add messages in one transaction, each taking over the copy stored
+    /// when I sent it if it's WhatsApp's copy of one (as
+    /// `claim_pending_message`). While the store is read-only they're
+    /// buffered; if the batch fails they're written one at a time, so a disk
+    /// error is spilled as with `add_message`.
+    pub fn add_messages_batch(&self, messages: &[StoredMessage]) -> Result<Vec<BatchedWrite>> {
+        if self.is_read_only() {
+            for msg in messages {
+                self.buffer_message(msg, false);
+            }
+            return Ok(messages.iter().map(|_| BatchedWrite::default()).collect());
+        }
+
+        let result = (|| {
+            let conn = self.conn.lock().unwrap();
+            let tx = conn.unchecked_transaction()?;
+            let mut writes = Vec::with_capacity(messages.len());
+            for msg in messages {
+                let contact_id = Self::resolve_id(&tx, &msg.contact_id);
+                self.contact_cache.invalidate(&contact_id);
+                let write = match Self::claim_pending(&tx, &contact_id, msg)? {
+                    Some(claimed) => BatchedWrite {
+                        sort_key: Some(claimed.sort_key),
+                        claimed: Some(claimed),
+                    },
+                    None => BatchedWrite {
+                        sort_key: Self::insert_message(&tx, &contact_id, msg)?,
+                        claimed: None,
+                    },
+                };
+                writes.push(write);
+            }
+            tx.commit()?;
+            anyhow::Ok(writes)
+        })();
+
+        match result {
+            Ok(writes) => {
+                self.spill.record_success();
+                Ok(writes)
+            }
+            Err(e) => {
+                warn!(
+                    "Batched message write failed, retrying one at a time: {:#}",
+                    e
+                );
+                messages
+                    .iter()
+                    .map(|msg| {
+                        let claimed = self
+                            .claim_pending_message(msg)
+                            .inspect_err(|e| error!("Failed to match pending message: {}", e))
+                            .unwrap_or(None);
+                        Ok(match claimed {
+                            Some(claimed) => BatchedWrite {
+                                sort_key: Some(claimed.sort_key),
+                                claimed: Some(claimed),
+                            },
+                            None => BatchedWrite {
+                                sort_key: self.add_message(msg)?,
+                                claimed: None,
+                            },
+                        })
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Messages imported into the archive per transaction, so a long import
+    /// lets live messages in between
+    const IMPORT_CHUNK: usize = 500;
+
+    /// Store imported messages (skipping ones already stored), returning
+    /// how many were new. Unlike `add_message`, a disk error fails the
+    /// import rather than being spilled, since it can simply be run again.
+    pub fn import_messages(&self, messages: &[StoredMessage]) -> Result<usize> {
+        if self.is_read_only() {
+            anyhow::bail!("The disk is nearly full");
+        }
+        let mut imported = 0;
+        for chunk in messages.chunks(Self::IMPORT_CHUNK) {
+            let conn = self.conn.lock().unwrap();
+            let tx = conn.unchecked_transaction()?;
+            for msg in chunk {
+                let contact_id = Self::resolve_id(&tx, &msg.contact_id);
+                self.contact_cache.invalidate(&contact_id);
+                if Self::insert_message(&tx, &contact_id, msg)?.is_some() {
+                    imported += 1;
+                }
+            }
+            tx.commit()?;
+        }
+        Ok(imported)
+    }
+
+    /// Insert a message unless one with its ID is stored, returning its sort
+    /// key if it was inserted
+    fn insert_message(
+        tx: &Connection,
+        contact_id: &str,
+        msg: &StoredMessage,
+    ) -> Result<Option<i64>> {
+        let media = Self::extract_media(&msg.content_json);
+        let content_json = media
+            .as_ref()
+            .map(|m| m.content_json.as_str())
+            .unwrap_or(&msg.content_json);
+
+        let sort_key: Option<i64> = tx
+            .query_row(
+                &format!(
+                    r#"
+            INSERT OR IGNORE INTO messages 
+            (id, contact_id, timestamp, is_from_me, is_forwarded, sender_name, sender_phone, 
+             chat_type, content_type, content_json, original_text, translated_text, 
+             source_language, is_translated, media_hash, origin, mentioned_jids, mentions_me,
+             vocab_json, audio_duration_ms, audio_waveform, audio_metadata_only, urgency, tone,
+             translation_status, sent_by, sort_key)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18,
+                    ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, {})
+            RETURNING sort_key
+            "#,
+                    NEXT_SORT_KEY_SQL
+                ),
+                params![
+                    msg.id,
+                    contact_id,
+                    msg.timestamp,
+                    msg.is_from_me,
+                    msg.is_forwarded,
+                    msg.sender_name,
+                    msg.sender_phone,
+                    msg.chat_type,
+                    msg.content_type,
+                    content_json,
+                    msg.original_text,
+                    msg.translated_text,
+                    msg.source_language,
+                    msg.is_translated,
+                    media.as_ref().map(|m| &m.hash),
+                    msg.origin,
+                    (!msg.mentioned_jids.is_empty())
+                        .then(|| serde_json::to_string(&msg.mentioned_jids).unwrap_or_default()),
+                    msg.mentions_me,
+                    msg.vocabulary
+                        .as_ref()
+                        .map(|v| serde_json::to_string(v).unwrap_or_default()),
+                    msg.audio.as_ref().map(|a| a.duration_ms),
+                    msg.audio
+                        .as_ref()
+                        .map(|a| serde_json::to_string(&a.waveform).unwrap_or_default()),
+                    msg.audio.as_ref().is_some_and(|a| a.metadata_only),
+                    msg.triage.and_then(|t| t.urgency).map(Urgency::as_str),
+                    msg.triage.and_then(|t| t.tone).map(Tone::as_str),
+                    msg.translation_status.map(TranslationStatus::as_str),
+                    msg.sent_by,
+                ],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        // Only a newly inserted message takes a reference on the blob
+        if sort_key.is_some() {
+            if let Some(media) = &media {
+                Self::store_media_blob(tx, media)?;
+            }
+            // A message sent from here (web, MCP, schedule) replaces the draft
+            if msg.is_from_me && msg.origin.is_some() && msg.content_type != "Reaction" {
+                tx.execute(
+                    "DELETE FROM drafts WHERE contact_id = ?",
+                    params![contact_id],
+                )?;
+            }
+            if let (false, Some(language)) = (msg.is_from_me, msg.source_language.as_deref()) {
+                if !language.is_empty() {
+                    Self::count_conversation_language(tx, contact_id, language)?;
+                }
+            }
+        }
+
+        Ok(sort_key)
+    }
+
+    /// Let WhatsApp's copy of a message I sent take over the copy stored when
+    /// it was sent (under a `pending_` ID), instead of storing it twice.
+    ///
+    /// The stored copy matches if it's in the same chat, of the same type,
+    /// within two minutes, and either sent the same text (the translation,
+    /// for translated messages) or the same file. It gets the real ID and
+    /// timestamp, keeping what was typed. Returns None if nothing matched
+    /// (the message should be stored as usual).
+    pub fn claim_pending_message(&self, msg: &StoredMessage) -> Result<Option<ClaimedPending>> {
+        if !msg.is_from_me || self.is_read_only() {
+            return Ok(None);
+        }
+
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, &msg.contact_id);
+        self.contact_cache.invalidate(&contact_id);
+        let tx = conn.unchecked_transaction()?;
+        let claimed = Self::claim_pending(&tx, &contact_id, msg)?;
+        tx.commit()?;
+        Ok(claimed)
+    }
+
+    /// `claim_pending_message` within a transaction, for a message in
+    /// `contact_id` (resolved)
+    fn claim_pending(
+        tx: &Connection,
+        contact_id: &str,
+        msg: &StoredMessage,
+    ) -> Result<Option<ClaimedPending>> {
+        if !msg.is_from_me || msg.id.starts_with("pending_") {
+            return Ok(None);
+        }
+        let content: serde_json::Value =
+            serde_json::from_str(&msg.content_json).unwrap_or_default();
+        let body = content.get("body").and_then(|b| b.as_str());
+        let media_hash = content
+            .get("file_hash")
+            .and_then(|h| h.as_str())
+            .filter(|h| !h.is_empty())
+            .map(|h| h.to_lowercase())
+            .or_else(|| Self::extract_media(&msg.content_json).map(|m| m.hash));
+        if body.is_none() && media_hash.is_none() {
+            return Ok(None);
+        }
+
+        // Already stored (delivered again)
+        let exists: bool = tx.query_row(
+            "SELECT EXISTS(SELECT 1 FROM messages WHERE id = ?)",
+            params![msg.id],
+            |row| row.get(0),
+        )?;
+        if exists {
+            return Ok(None);
+        }
+
+        // The oldest match, so identical messages sent in a row pair up in order
+        let pending_id: Option<String> = tx
+            .query_row(
+                r#"
+                SELECT id FROM messages
+                WHERE contact_id = ?1 AND is_from_me = 1
+                  AND id LIKE 'pending\_%' ESCAPE '\'
+                  AND content_type = ?2
+                  AND timestamp BETWEEN ?3 - ?4 AND ?3 + ?4
+                  AND ((?5 IS NOT NULL AND ?5 = COALESCE(
+                            CASE WHEN is_translated THEN translated_text END,
+                            json_extract(content_json, '$.body')))
+                       OR (?6 IS NOT NULL AND media_hash = ?6))
+                ORDER BY sort_key, rowid
+                LIMIT 1
+                "#,
+                params![
+                    contact_id,
+                    msg.content_type,
+                    msg.timestamp,
+                    PENDING_MATCH_WINDOW_MS,
+                    body,
+                    media_hash
+                ],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(pending_id) = pending_id else {
+            return Ok(None);
+        };
+
+        let sort_key: i64 = tx.query_row(
+            &format!(
+                "UPDATE messages SET id = ?1, timestamp = ?3, sort_key = {}
+                 WHERE id = ?4
+                 RETURNING sort_key",
+                NEXT_SORT_KEY_SQL
+            ),
+            params![msg.id, contact_id, msg.timestamp, pending_id],
+            |row| row.get(0),
+        )?;
+        tx.execute(
+            "UPDATE translation_usage SET message_id = ?1 WHERE message_id = ?2",
+            params![msg.id, pending_id],
+        )?;
+        tx.execute(
+            "UPDATE pinned_messages SET message_id = ?1 WHERE message_id = ?2",
+            params![msg.id, pending_id],
+        )?;
+
+        debug!("Sent message {} is now {}", pending_id, msg.id);
+        Ok(Some(ClaimedPending {
+            pending_id,
+            sort_key,
+        }))
+    }
+
+    /// Replace my reaction to a message with `reaction` (a reaction message),
+    /// or just remove it if None. Returns the emoji it replaced, if any.
+    pub fn set_own_reaction(
+        &self,
+        contact_id: &str,
+        target_message_id: &str,
+        reaction: Option<&StoredMessage>,
+    ) -> Result<Option<String>> {
+        let previous = {
+            let conn = self.conn.lock().unwrap();
+            let contact_id = Self::resolve_id(&conn, contact_id);
+            self.contact_cache.invalidate(&contact_id);
+            let tx = conn.unchecked_transaction()?;
+            let previous: Option<String> = tx
+                .query_row(
+                    r#"
+                    SELECT json_extract(content_json, '$.emoji') FROM messages
+                    WHERE contact_id = ?1 AND is_from_me = 1
+                      AND json_extract(content_json, '$.type') = 'reaction'
+                      AND json_extract(content_json, '$.target_message_id') = ?2
+                    ORDER BY timestamp DESC, rowid DESC LIMIT 1
+                    "#,
+                    params![contact_id, target_message_id],
+                    |row| row.get(0),
+                )
+                .optional()?
+                .flatten();
+            tx.execute(
+                r#"
+                DELETE FROM messages
+                WHERE contact_id = ?1 AND is_from_me = 1
+                  AND json_extract(content_json, '$.type') = 'reaction'
+                  AND json_extract(content_json, '$.target_message_id') = ?2
+                "#,
+                params![contact_id, target_message_id],
+            )?;
+            tx.commit()?;
+            previous.filter(|emoji| !emoji.is_empty())
+        };
+
+        if let Some(reaction) = reaction {
+            self.add_message(reaction)?;
+        }
+        Ok(previous)
+    }
+
+    /// Query over everyone's current reaction to each message since `?1`:
+    /// one row per person and message, with removed reactions dropped.
+    /// `contact_filter` narrows the reaction rows (e.g. to one chat).
+    fn latest_reactions_query(contact_filter: &str, select: &str) -> String {
+        format!(
+            r#"
+            WITH latest AS (
+                SELECT contact_id, emoji, is_from_me, reactor, sender_name
+                FROM (
+                    SELECT contact_id, is_from_me, sender_name,
+                           json_extract(content_json, '$.emoji') AS emoji,
+                           CASE WHEN is_from_me THEN 'me' ELSE COALESCE(sender_phone, id) END
+                               AS reactor,
+                           ROW_NUMBER() OVER (
+                               PARTITION BY contact_id,
+                                   json_extract(content_json, '$.target_message_id'),
+                                   CASE WHEN is_from_me THEN 'me'
+                                        ELSE COALESCE(sender_phone, id) END
+                               ORDER BY timestamp DESC, rowid DESC
+                           ) AS rn
+                    FROM messages
+                    WHERE content_type = 'Reaction' AND timestamp >= ?1 {}
+                )
+                WHERE rn = 1 AND emoji IS NOT NULL AND emoji <> ''
+            )
+            {}
+            "#,
+            contact_filter, select
+        )
+    }
+
+    /// Reaction counts per emoji, top reactors and my most used reactions in
+    /// a chat since `since` (ms)
+    pub fn get_reaction_stats(&self, contact_id: &str, since: i64) -> Result<ReactionStats> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+        let query = |select: &str| Self::latest_reactions_query("AND contact_id = ?2", select);
+        let emoji_count = |row: &rusqlite::Row| {
+            Ok(EmojiCount {
+                emoji: row.get(0)?,
+                count: row.get(1)?,
+            })
+        };
+
+        let emojis = conn
+            .prepare(&query(
+                "SELECT emoji, COUNT(*) AS n FROM latest GROUP BY emoji ORDER BY n DESC, emoji",
+            ))?
+            .query_map(params![since, contact_id], emoji_count)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let top_reactors = conn
+            .prepare(&query(
+                "SELECT reactor, MAX(sender_name), COUNT(*) AS n FROM latest
+                 WHERE NOT is_from_me GROUP BY reactor ORDER BY n DESC, reactor LIMIT ?3",
+            ))?
+            .query_map(params![since, contact_id, TOP_REACTIONS_LIMIT], |row| {
+                Ok(ReactorCount {
+                    reactor: row.get(0)?,
+                    name: row.get(1)?,
+                    count: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let my_top_reactions = conn
+            .prepare(&query(
+                "SELECT emoji, COUNT(*) AS n FROM latest
+                 WHERE is_from_me GROUP BY emoji ORDER BY n DESC, emoji LIMIT ?3",
+            ))?
+            .query_map(params![since, contact_id, TOP_REACTIONS_LIMIT], emoji_count)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(ReactionStats {
+            since,
+            total: emojis.iter().map(|e| e.count).sum(),
+            emojis,
+            top_reactors,
+            my_top_reactions,
+        })
+    }
+
+    /// Each chat's most used reaction since `since` (ms), by contact ID
+    pub fn get_top_reactions(&self, since: i64) -> Result<HashMap<String, String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&Self::latest_reactions_query(
+            "",
+            r#"
+            SELECT contact_id, emoji FROM (
+                SELECT contact_id, emoji,
+                       ROW_NUMBER() OVER (
+                           PARTITION BY contact_id ORDER BY COUNT(*) DESC, emoji
+                       ) AS position
+                FROM latest GROUP BY contact_id, emoji
+            )
+            WHERE position = 1
+            "#,
+        ))?;
+        let top = stmt
+            .query_map(params![since], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<HashMap<_, _>>>()?;
+        Ok(top)
+    }
+
+    /// Current reactions to messages in a conversation, grouped by emoji.
+    /// Only each person's latest reaction counts, and an empty emoji means
+    /// they took it back. Messages without reactions are left out.
+    pub fn get_reactions(
+        &self,
+        contact_id: &str,
+        message_ids: &[&str],
+    ) -> Result<HashMap<String, Vec<ReactionGroup>>> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT json_extract(content_json, '$.target_message_id'),
+                   json_extract(content_json, '$.emoji'), is_from_me, sender_phone, id
+            FROM messages
+            WHERE contact_id = ? AND json_extract(content_json, '$.type') = 'reaction'
+            ORDER BY timestamp ASC, rowid ASC
+            "#,
+        )?;
+        let rows = stmt.query_map(params![contact_id], |row| {
+            Ok((
+                row.get::<_, Option<String>>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, bool>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, String>(4)?,
+            ))
+        })?;
+
+        /// Someone's latest reaction to a message
+        struct Latest {
+            target: String,
+            /// "me" for my reactions, else who reacted
+            reactor: String,
+            emoji: String,
+            from_me: bool,
+            who: String,
+        }
+
+        let mut latest: Vec<Latest> = Vec::new();
+        for row in rows {
+            let (Some(target), emoji, from_me, sender_phone, id) = row? else {
+                continue;
+            };
+            if !message_ids.contains(&target.as_str()) {
+                continue;
+            }
+            let who = sender_phone.unwrap_or_else(|| if from_me { "me".to_string() } else { id });
+            let reaction = Latest {
+                reactor: if from_me {
+                    "me".to_string()
+                } else {
+                    who.clone()
+                },
+                target,
+                emoji: emoji.unwrap_or_default(),
+                from_me,
+                who,
+            };
+            match latest
+                .iter_mut()
+                .find(|l| l.target == reaction.target && l.reactor == reaction.reactor)
+            {
+                Some(existing) => *existing = reaction,
+                None => latest.push(reaction),
+            }
+        }
+
+        let mut reactions: HashMap<String, Vec<ReactionGroup>> = HashMap::new();
+        for reaction in latest.into_iter().filter(|l| !l.emoji.is_empty()) {
+            let groups = reactions.entry(reaction.target).or_default();
+            match groups.iter_mut().find(|g| g.emoji == reaction.emoji) {
+                Some(group) => {
+                    group.count += 1;
+                    group.from_me |= reaction.from_me;
+                    group.reactors.push(reaction.who);
+                }
+                None => groups.push(ReactionGroup {
+                    emoji: reaction.emoji,
+                    count: 1,
+                    from_me: reaction.from_me,
+                    reactors: vec![reaction.who],
+                }),
+            }
+        }
+        Ok(reactions)
+    }
+
+    /// Delete a single message, releasing its media blob.
+    /// Returns false if the message doesn't exist in this conversation.
+    pub fn delete_message(&self, contact_id: &str, message_id: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+        self.contact_cache.invalidate(&contact_id);
+
+        let tx = conn.unchecked_transaction()?;
+        let media_hash: Option<Option<String>> = tx
+            .query_row(
+                "SELECT media_hash FROM messages WHERE id = ? AND contact_id = ?",
+                params![message_id, contact_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        let Some(media_hash) = media_hash else {
+            return Ok(false);
+        };
+
+        tx.execute("DELETE FROM messages WHERE id = ?", params![message_id])?;
+        tx.execute(
+            "DELETE FROM pinned_messages WHERE message_id = ?",
+            params![message_id],
+        )?;
+        if let Some(hash) = media_hash {
+            Self::release_media_blob(&tx, &hash)?;
+        }
+        Self::invalidate_language_cache(&tx, &contact_id)?;
+        tx.commit()?;
+
+        info!("Deleted message {} from {}", message_id, contact_id);
+        Ok(true)
+    }
+
+    /// Delete a conversation's history, keeping the contact (its name, pin,
+    /// settings and change history). Media blobs no other message uses are
+    /// freed; translation usage is only deleted with `include_usage`.
+    /// Returns None if there is no such contact.
+    pub fn clear_conversation(
+        &self,
+        contact_id: &str,
+        include_usage: bool,
+    ) -> Result<Option<ClearedConversation>> {
+        if include_usage {
+            self.flush_usage();
+        }
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+        self.contact_cache.invalidate(&contact_id);
+
+        let exists: bool = conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM contacts WHERE id = ?)",
+            params![contact_id],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Ok(None);
+        }
+
+        let tx = conn.unchecked_transaction()?;
+        let media_refs: Vec<(String, i64)> = {
+            let mut stmt = tx.prepare(
+                r#"
+                SELECT media_hash, COUNT(*) FROM messages
+                WHERE contact_id = ? AND media_hash IS NOT NULL
+                GROUP BY media_hash
+                "#,
+            )?;
+            let rows = stmt.query_map(params![contact_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+
+        let messages = tx.execute(
+            "DELETE FROM messages WHERE contact_id = ?",
+            params![contact_id],
+        )?;
+        tx.execute(
+            "DELETE FROM pinned_messages WHERE contact_id = ?",
+            params![contact_id],
+        )?;
+
+        let mut media_blobs_freed = 0;
+        for (hash, refs) in &media_refs {
+            tx.execute(
+                "UPDATE media_blobs SET ref_count = ref_count - ?1 WHERE hash = ?2",
+                params![refs, hash],
+            )?;
+            media_blobs_freed += tx.execute(
+                "DELETE FROM media_blobs WHERE hash = ? AND ref_count <= 0",
+                params![hash],
+            )?;
+        }
+
+        let usage_records = if include_usage {
+            tx.execute(
+                "DELETE FROM translation_usage WHERE contact_id = ?",
+                params![contact_id],
+            )?
+        } else {
+            0
+        };
+
+        // An empty, valid language cache: new messages count from scratch
+        tx.execute(
+            r#"
+            UPDATE contacts SET
+                last_message_time = 0,
+                unread_count = 0,
+                last_read_timestamp = NULL,
+                conversation_language = NULL,
+                language_message_count = 0
+            WHERE id = ?
+            "#,
+            params![contact_id],
+        )?;
+        tx.commit()?;
+
+        info!(
+            "Cleared {} messages from {} ({} media blobs freed, {} usage records deleted)",
+            messages, contact_id, media_blobs_freed, usage_records
+        );
+        Ok(Some(ClearedConversation {
+            messages,
+            media_blobs_freed,
+            usage_records,
+        }))
+    }
+
+    /// Split embedded media out of a message's content JSON.
+    /// Returns None if the content has no media data.
+    fn extract_media(content_json: &str) -> Option<ExtractedMedia> {
+        let mut content: serde_json::Value = serde_json::from_str(content_json).ok()?;
+        let thumbnail = crate::thumbnail::for_content(&content).flatten();
+        let obj = content.as_object_mut()?;
+
+        let data = obj
+            .remove("media_data")
+            .or_else(|| obj.remove("mediaData"))?
+            .as_str()?
+            .to_string();
+        obj.remove("mediaData");
+
+        // Prefer the hash WhatsApp sends; otherwise hash the decoded file bytes the same way
+        let hash = obj
+            .get("file_hash")
+            .and_then(|v| v.as_str())
+            .filter(|h| !h.is_empty())
+            .map(|h| h.to_lowercase())
+            .unwrap_or_else(|| {
+                let bytes = STANDARD
+                    .decode(&data)
+                    .unwrap_or_else(|_| data.as_bytes().to_vec());
+                Sha256::digest(&bytes)
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect()
+            });
+
+        let mime_type = obj
+            .get("mime_type")
+            .or_else(|| obj.get("mimeType"))
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string());
+
+        obj.remove(crate::thumbnail::CONTENT_KEY);
+        obj.insert("has_media".to_string(), serde_json::Value::Bool(true));
+
+        Some(ExtractedMedia {
+            hash,
+            data,
+            mime_type,
+            thumbnail,
+            content_json: content.to_string(),
+        })
+    }
+
+    /// Store a media blob, or take another reference on it if it's already stored
+    fn store_media_blob(conn: &Connection, media: &ExtractedMedia) -> Result<()> {
+        let now = chrono::Utc::now().timestamp_millis();
+        conn.execute(
+            r#"
+            INSERT INTO media_blobs (hash, data, mime_type, size, ref_count, created_at, thumbnail)
+            VALUES (?1, ?2, ?3, ?4, 1, ?5, ?6)
+            ON CONFLICT(hash) DO UPDATE SET
+                ref_count = media_blobs.ref_count + 1,
+                thumbnail = COALESCE(media_blobs.thumbnail, excluded.thumbnail)
+            "#,
+            params![
+                media.hash,
+                media.data,
+                media.mime_type,
+                media.data.len() as i64,
+                now,
+                media.thumbnail
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Drop a reference on a media blob, deleting it once nothing uses it
+    fn release_media_blob(conn: &Connection, hash: &str) -> Result<()> {
+        conn.execute(
+            "UPDATE media_blobs SET ref_count = ref_count - 1 WHERE hash = ?",
+            params![hash],
+        )?;
+        conn.execute(
+            "DELETE FROM media_blobs WHERE hash = ? AND ref_count <= 0",
+            params![hash],
+        )?;
+        Ok(())
+    }
+
+    /// Put a blob's media data back into stripped content JSON
+    fn restore_media(conn: &Connection, content_json: &str, hash: &str) -> String {
+        let data: Option<String> = conn
+            .query_row(
+                "SELECT data FROM media_blobs WHERE hash = ?",
+                params![hash],
+                |row| row.get(0),
+            )
+            .ok();
+
+        match (
+            data,
+            serde_json::from_str::<serde_json::Value>(content_json),
+        ) {
+            (Some(data), Ok(mut content)) => {
+                if let Some(obj) = content.as_object_mut() {
+                    obj.insert("media_data".to_string(), serde_json::Value::String(data));
+                }
+                content.to_string()
+            }
+            _ => content_json.to_string(),
+        }
+    }
+
+    /// Update the translation for an existing message
+    pub fn update_message_translation(
+        &self,
+        message_id: &str,
+        translated_text: Option<&str>,
+        source_language: Option<&str>,
+    ) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            r#"
+            UPDATE messages 
+            SET translated_text = ?1, source_language = ?2, is_translated = 1,
+                translation_status = 'translated'
+            WHERE id = ?3
+            "#,
+            params![translated_text, source_language, message_id],
+        )?;
+
+        // The message's language may have changed
+        conn.execute(
+            r#"
+            UPDATE contacts SET conversation_language = NULL, language_message_count = NULL
+            WHERE id = (SELECT contact_id FROM messages WHERE id = ?)
+            "#,
+            params![message_id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Record the translation an outgoing message was sent as, once it's
+    /// translated at dispatch (`text` is what was typed), with its learning
+    /// notes if any
+    pub fn record_sent_translation(
+        &self,
+        message_id: &str,
+        text: &str,
+        translated_text: &str,
+        language: Option<&str>,
+        vocabulary: &[VocabEntry],
+    ) -> Result<()> {
+        let vocab_json = if vocabulary.is_empty() {
+            None
+        } else {
+            Some(serde_json::to_string(vocabulary)?)
+        };
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            r#"
+            UPDATE messages
+            SET original_text = ?1, translated_text = ?2, source_language = ?3, is_translated = 1,
+                translation_status = 'translated', vocab_json = COALESCE(?5, vocab_json)
+            WHERE id = ?4
+            "#,
+            params![text, translated_text, language, message_id, vocab_json],
+        )?;
+        Ok(())
+    }
+
+    /// Record why a message was or wasn't translated
+    pub fn set_translation_status(
+        &self,
+        message_id: &str,
+        status: TranslationStatus,
+    ) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE messages SET translation_status = ?1 WHERE id = ?2",
+            params![status.as_str(), message_id],
+        )?;
+        Ok(())
+    }
+
+    /// Count incoming messages by translation status, across all chats or
+    /// in one. Every status is listed, with zero if nothing has it.
+    pub fn get_translation_status_counts(
+        &self,
+        contact_id: Option<&str>,
+    ) -> Result<Vec<(TranslationStatus, u64)>> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = contact_id.map(|id| Self::resolve_id(&conn, id));
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT translation_status, COUNT(*) FROM messages
+            WHERE is_from_me = 0 AND translation_status IS NOT NULL
+              AND (?1 IS NULL OR contact_id = ?1)
+            GROUP BY translation_status
+            "#,
+        )?;
+        let counts = stmt
+            .query_map(params![contact_id], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })?
+            .collect::<rusqlite::Result<HashMap<_, _>>>()?;
+
+        Ok(TranslationStatus::ALL
+            .into_iter()
+            .map(|status| {
+                let count = counts.get(status.as_str()).copied().unwrap_or(0);
+                (status, count as u64)
+            })
+            .collect())
+    }
+
+    /// Unsupported content types seen, most frequent first, each with the
+    /// latest message of that type as a sample
+    pub fn get_unknown_content_report(&self) -> Result<Vec<UnknownContentType>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT substr(m.content_type, 9), COUNT(*), MAX(m.timestamp),
+                   (SELECT s.content_json FROM messages s
+                    WHERE s.content_type = m.content_type
+                    ORDER BY s.timestamp DESC LIMIT 1)
+            FROM messages m
+            WHERE m.content_type LIKE 'unknown:%'
+            GROUP BY m.content_type
+            ORDER BY COUNT(*) DESC, m.content_type
+            "#,
+        )?;
+        let report = stmt
+            .query_map([], |row| {
+                let sample: String = row.get(3)?;
+                Ok(UnknownContentType {
+                    raw_type: row.get(0)?,
+                    count: row.get::<_, i64>(1)? as u64,
+                    last_seen: row.get(2)?,
+                    sample: serde_json::from_str(&sample)
+                        .unwrap_or(serde_json::Value::String(sample)),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(report)
+    }
+
+    /// Mark a chat's incoming messages whose translation failed or never
+    /// happened as pending again, returning them oldest first
+    pub fn requeue_untranslated(&self, contact_id: &str) -> Result<Vec<StoredMessage>> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+        let tx = conn.unchecked_transaction()?;
+        let messages = {
+            let mut stmt = tx.prepare(
+                r#"
+                SELECT m.id, m.contact_id, m.timestamp, m.is_from_me, m.is_forwarded, m.sender_name,
+                       m.sender_phone, m.chat_type, m.content_type, m.content_json, m.original_text,
+                       m.translated_text, m.source_language, m.is_translated,
+                       c.name as contact_name, c.phone as contact_phone, m.origin,
+                       m.mentioned_jids, m.mentions_me, m.translation_status, m.sent_by
+                FROM messages m
+                LEFT JOIN contacts c ON m.contact_id = c.id
+                WHERE m.contact_id = ? AND m.is_from_me = 0
+                  AND m.translation_status IN ('error', 'pending')
+                ORDER BY m.timestamp ASC, m.rowid ASC
+                "#,
+            )?;
+            let rows = stmt.query_map(params![contact_id], |row| {
+                let contact_name: Option<String> = row.get(14)?;
+                let contact_phone: Option<String> = row.get(15)?;
+                Self::row_to_stored_message(row, contact_name, contact_phone)
+            })?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+        tx.execute(
+            r#"
+            UPDATE messages SET translation_status = 'pending'
+            WHERE contact_id = ? AND is_from_me = 0 AND translation_status = 'error'
+            "#,
+            params![contact_id],
+        )?;
+        tx.commit()?;
+        Ok(messages)
+    }
+
+    /// Get all contacts sorted by pinned status first, then last message time
+    pub fn get_contacts(&self) -> Result<Vec<StoredContact>> {
+        Ok(self
+            .query_contacts(None, None)?
+            .into_iter()
+            .map(|(contact, _)| contact)
+            .collect())
+    }
+
+    /// Contacts whose names match a search (see `name_search`), best matches
+    /// first, otherwise in the contact list's order
+    pub fn search_contacts(&self, query: &str, limit: usize) -> Result<Vec<StoredContact>> {
+        let query = name_search::normalize_name(query);
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+        let matches: HashMap<String, NameMatch> = {
+            let conn = self.conn.lock().unwrap();
+            let mut stmt = conn.prepare(
+                "SELECT id, name_normalized FROM contacts WHERE instr(name_normalized, ?) > 0",
+            )?;
+            let rows = stmt.query_map(params![query], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?;
+            let mut matches = HashMap::new();
+            for row in rows {
+                let (id, name) = row?;
+                if let Some(rank) = name_search::match_name(&name, &query) {
+                    matches.insert(id, rank);
+                }
+            }
+            matches
+        };
+        if matches.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut contacts: Vec<StoredContact> = self
+            .get_contacts()?
+            .into_iter()
+            .filter(|contact| matches.contains_key(&contact.id))
+            .collect();
+        // Stable, so equally good matches keep the list's order
+        contacts.sort_by_key(|contact| matches[&contact.id]);
+        contacts.truncate(limit);
+        Ok(contacts)
+    }
+
+    /// Get a page of contacts in the contact list's order, after `cursor`
+    /// if given, with the cursor for the next page if there is one
+    pub fn get_contacts_page(
+        &self,
+        limit: u32,
+        cursor: Option<ContactCursor>,
+    ) -> Result<(Vec<StoredContact>, Option<ContactCursor>)> {
+        let mut contacts = self.query_contacts(Some(limit + 1), cursor)?;
+        let next = if contacts.len() > limit as usize {
+            contacts.truncate(limit as usize);
+            contacts.last().map(|(_, cursor)| *cursor)
+        } else {
+            None
+        };
+        Ok((contacts.into_iter().map(|(c, _)| c).collect(), next))
+    }
+
+    /// Contacts in the contact list's order (see `ContactCursor`), each with
+    /// the cursor for the page after it
+    fn query_contacts(
+        &self,
+        limit: Option<u32>,
+        after: Option<ContactCursor>,
+    ) -> Result<Vec<(StoredContact, ContactCursor)>> {
+        let conn = self.conn.lock().unwrap();
+
+        // Use a subquery to get the last message for each contact
+        // The inner subquery ensures we only get one message per contact (the latest by rowid)
+        let mut stmt = conn.prepare(&format!(
+            r#"
+            SELECT 
+                c.id, c.name, c.phone, c.type, c.last_message_time, c.unread_count, c.pinned_at,
+                m.content_json, m.content_type, m.is_from_me, {},
+                c.mentions_only, c.created_at, c.updated_at, c.last_seen, c.last_read_timestamp,
+                {1} AS rank, COALESCE(c.pinned_at, 0) AS pinned, COALESCE(c.last_message_time, 0) AS active,
+                c.rowid, c.description, c.ephemeral_duration, c.placeholder
+            FROM contacts c
+            LEFT JOIN (
+                SELECT contact_id, content_json, content_type, is_from_me, timestamp,
+                       ROW_NUMBER() OVER (PARTITION BY contact_id ORDER BY sort_key DESC, rowid DESC) as rn
+                FROM messages
+                WHERE content_type != 'system'
+            ) m ON m.contact_id = c.id AND m.rn = 1
+            WHERE c.id NOT IN (SELECT alt_jid FROM identity_links)
+              AND (?1 IS NULL OR ({1}, COALESCE(c.pinned_at, 0), -COALESCE(c.last_message_time, 0), c.rowid)
+                                  > (?1, ?2, -?3, ?4))
+            ORDER BY rank, pinned, active DESC, c.rowid
+            LIMIT ?5
+            "#,
+            OUTGOING_TRANSLATION_SQL,
+            CONTACT_RANK_SQL
+        ))?;
+
+        let contacts = stmt
+            .query_map(
+                params![
+                    after.map(|a| a.rank),
+                    after.map(|a| a.pinned_at),
+                    after.map(|a| a.last_message_time),
+                    after.map(|a| a.rowid),
+                    limit.map_or(-1, i64::from)
+                ],
+                |row| {
+                    let content_json: Option<String> = row.get(7)?;
+                    let content_type: Option<String> = row.get(8)?;
+                    let is_from_me: Option<bool> = row.get(9)?;
+
+                    let preview = Self::generate_message_preview(
+                        content_json.as_deref(),
+                        content_type.as_deref(),
+                        is_from_me.unwrap_or(false),
+                    );
+
+                    let contact_type: Option<String> = row.get(3)?;
+                    let contact = StoredContact {
+                        id: row.get(0)?,
+                        name: Self::contact_display_name(row.get(1)?, contact_type.as_deref()),
+                        phone: row.get(2)?,
+                        contact_type,
+                        last_message_time: row.get(4)?,
+                        unread_count: row.get(5)?,
+                        pinned_at: row.get(6)?,
+                        last_message_preview: preview,
+                        auto_translate_outgoing: row.get(10)?,
+                        mentions_only: row.get(11)?,
+                        created_at: row.get(12)?,
+                        updated_at: row.get(13)?,
+                        last_seen: row.get(14)?,
+                        last_read_timestamp: row.get(15)?,
+                        description: row.get(20)?,
+                        ephemeral_duration: row.get(21)?,
+                        placeholder: row.get(22)?,
+                    };
+                    let cursor = ContactCursor {
+                        rank: row.get(16)?,
+                        pinned_at: row.get(17)?,
+                        last_message_time: row.get(18)?,
+                        rowid: row.get(19)?,
+                    };
+                    Ok((contact, cursor))
+                },
+            )?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(contacts)
+    }
+
+    /// SQL for a contact's last read timestamp (in an UPDATE of contacts)
+    /// when `unread` messages are unread: just before the `unread` latest
+    /// incoming messages (0 if fewer are stored), or the latest message
+    fn last_read_sql(unread: &str, contact_id: &str) -> String {
+        format!(
+            r#"CASE WHEN {unread} > 0 THEN COALESCE((
+                SELECT latest.timestamp - 1 FROM (
+                    SELECT m.timestamp,
+                           ROW_NUMBER() OVER (ORDER BY m.timestamp DESC, m.rowid DESC) AS rn
+                    FROM messages m
+                    WHERE m.contact_id = {contact_id} AND {UNREAD_MESSAGE_SQL}
+                ) latest
+                WHERE latest.rn = {unread}
+            ), 0)
+            ELSE MAX(
+                COALESCE(contacts.last_message_time, 0),
+                COALESCE((SELECT MAX(m.timestamp) FROM messages m WHERE m.contact_id = {contact_id}), 0)
+            ) END"#
+        )
+    }
+
+    /// The name a contact is listed under: my own chat is "Saved Messages"
+    fn contact_display_name(name: Option<String>, contact_type: Option<&str>) -> Option<String> {
+        if contact_type == Some("self") {
+            Some(SAVED_MESSAGES_NAME.to_string())
+        } else {
+            name
+        }
+    }
+
+    /// Make the chat with my own JID the "Saved Messages" chat if it exists
+    /// (it's created by the first note). A previous account's self-chat goes
+    /// back to being private.
+    pub fn mark_self_chat(&self, jid: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        self.contact_cache.clear();
+        let id = Self::resolve_id(&conn, jid);
+        let tx = conn.unchecked_transaction()?;
+        tx.execute(
+            "UPDATE contacts SET type = 'private' WHERE type = 'self' AND id != ?",
+            params![id],
+        )?;
+        tx.execute(
+            "UPDATE contacts SET type = 'self', unread_count = 0 WHERE id = ?",
+            params![id],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn self_chat_id(conn: &Connection) -> Result<Option<String>> {
+        Ok(Self::read_own_profile(conn)?
+            .jid
+            .map(|jid| Self::resolve_id(conn, &jid)))
+    }
+
+    /// My "Saved Messages" chat: the one with my own JID, once connected
+    pub fn get_self_chat_id(&self) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        Self::self_chat_id(&conn)
+    }
+
+    /// Whether a chat is my own "Saved Messages" chat
+    pub fn is_self_chat(&self, contact_id: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+        Ok(Self::self_chat_id(&conn)?.is_some_and(|id| id == contact_id))
+    }
+
+    /// Generate a preview string for a message (matching frontend logic)
+    pub(crate) fn generate_message_preview(
+        content_json: Option<&str>,
+        content_type: Option<&str>,
+        is_from_me: bool,
+    ) -> Option<String> {
+        let prefix = if is_from_me { "You: " } else { "" };
+
+        let content_type = content_type?;
+        let content_json = content_json?;
+
+        let content: serde_json::Value = serde_json::from_str(content_json).ok()?;
+
+        // Normalize content_type to lowercase for matching
+        let content_type_lower = content_type.to_lowercase();
+        let preview = match content_type_lower.as_str() {
+            "text" => {
+                let body = content
+                    .get("body")
+                    .or_else(|| content.get("text"))
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("");
+                let truncated: String = body.chars().take(50).collect();
+                format!("{}{}", prefix, truncated)
+            }
+            "image" => {
+                let caption = content
+                    .get("caption")
+                    .and_then(|v| v.as_str())
+                    .map(|c| {
+                        let truncated: String = c.chars().take(30).collect();
+                        format!(" {}", truncated)
+                    })
+                    .unwrap_or_default();
+                format!("{}[ Image ]{}", prefix, caption)
+            }
+            "video" => {
+                let caption = content
+                    .get("caption")
+                    .and_then(|v| v.as_str())
+                    .map(|c| {
+                        let truncated: String = c.chars().take(30).collect();
+                        format!(" {}", truncated)
+                    })
+                    .unwrap_or_default();
+                format!("{}[ Video ]{}", prefix, caption)
+            }
+            "audio" => {
+                let is_voice = content
+                    .get("is_voice_note")
+                    .or_else(|| content.get("isVoiceNote"))
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false);
+                if is_voice {
+                    format!("{}[ Voice Note ]", prefix)
+                } else {
+                    format!("{}[ Audio ]", prefix)
+                }
+            }
+            "voice note" => format!("{}[ Voice Note ]", prefix),
+            "document" => {
+                let file_name = content
+                    .get("file_name")
+                    .or_else(|| content.get("fileName"))
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("file");
+                format!("{}[ Document: {} ]", prefix, file_name)
+            }
+            "sticker" => format!("{}[ Sticker ]", prefix),
+            "location" => format!("{}[ Location ]", prefix),
+            "contact" => {
+                let name = content.get("name").and_then(|v| v.as_str()).unwrap_or("");
+                format!("{}[ Contact: {} ]", prefix, name)
+            }
+            "reaction" => {
+                let emoji = content.get("emoji").and_then(|v| v.as_str()).unwrap_or("");
+                format!("{}{}", prefix, emoji)
+            }
+            "revoked" => "[ Message deleted ]".to_string(),
+            "poll" => {
+                let question = content
+                    .get("question")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("");
+                format!("{}[ Poll: {} ]", prefix, question)
+            }
+            t if t.starts_with("unknown:") => match unknown_text_fields(&content).first() {
+                Some(text) => {
+                    let truncated: String = text.chars().take(50).collect();
+                    format!("{}{}", prefix, truncated)
+                }
+                None => format!("{}[ Unsupported: {} ]", prefix, &content_type[8..]),
+            },
+            _ => format!("{}[ Message ]", prefix),
+        };
+
+        Some(preview)
+    }
+
+    /// Text standing in for a message quoted by a reply: its text, or for
+    /// media what it is, with its caption or length, e.g. "📷 Photo — 'Look!'"
+    /// or "🎵 Voice note (0:42)"
+    pub(crate) fn generate_reply_preview(message: &StoredMessage) -> String {
+        let content: serde_json::Value =
+            serde_json::from_str(&message.content_json).unwrap_or_default();
+        let text = |key: &str| {
+            content
+                .get(key)
+                .and_then(|v| v.as_str())
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+        };
+        let captioned = |label: String| match text("caption") {
+            Some(caption) => {
+                let truncated: String = caption.chars().take(50).collect();
+                format!("{} — '{}'", label, truncated)
+            }
+            None => label,
+        };
+        // Audio length comes from the decoded audio, else from WhatsApp
+        let duration_ms = message
+            .audio
+            .as_ref()
+            .map(|audio| audio.duration_ms)
+            .or_else(|| {
+                content
+                    .get("duration_seconds")
+                    .and_then(|v| v.as_i64())
+                    .map(|secs| secs * 1000)
+            });
+        let timed = |label: &str| match duration_ms.filter(|&ms| ms > 0) {
+            Some(ms) => {
+                let secs = (ms + 500) / 1000;
+                format!("{} ({}:{:02})", label, secs / 60, secs % 60)
+            }
+            None => label.to_string(),
+        };
+
+        match message.content_type.to_lowercase().as_str() {
+            "image" => captioned("📷 Photo".to_string()),
+            "video" => captioned(timed("🎥 Video")),
+            "audio" => {
+                let is_voice = content
+                    .get("is_voice_note")
+                    .or_else(|| content.get("isVoiceNote"))
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false);
+                timed(if is_voice {
+                    "🎵 Voice note"
+                } else {
+                    "🎵 Audio"
+                })
+            }
+            "voice note" => timed("🎵 Voice note"),
+            "document" => captioned(format!(
+                "📄 {}",
+                text("file_name")
+                    .or_else(|| text("fileName"))
+                    .unwrap_or("Document")
+            )),
+            "sticker" => "Sticker".to_string(),
+            "location" => match text("name").or_else(|| text("address")) {
+                Some(place) => format!("📍 {}", place),
+                None => "📍 Location".to_string(),
+            },
+            "contact" => match text("display_name").or_else(|| text("name")) {
+                Some(name) => format!("👤 {}", name),
+                None => "👤 Contact".to_string(),
+            },
+            "poll" => match text("question") {
+                Some(question) => format!("📊 {}", question),
+                None => "📊 Poll".to_string(),
+            },
+            _ => Self::generate_message_preview(
+                Some(&message.content_json),
+                Some(&message.content_type),
+                false,
+            )
+            .unwrap_or_default(),
+        }
+    }
+
+    /// Pin or unpin a contact
+    pub fn toggle_pin(&self, contact_id: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+        self.contact_cache.invalidate(&contact_id);
+
+        // Check if currently pinned
+        let currently_pinned: Option<i64> = conn
+            .query_row(
+                "SELECT pinned_at FROM contacts WHERE id = ?",
+                params![contact_id],
+                |row| row.get(0),
+            )
+            .ok()
+            .flatten();
+
+        if currently_pinned.is_some() {
+            // Unpin
+            conn.execute(
+                "UPDATE contacts SET pinned_at = NULL WHERE id = ?",
+                params![contact_id],
+            )?;
+            Ok(false) // Now unpinned
+        } else {
+            // Pin with current timestamp
+            let now = chrono::Utc::now().timestamp_millis();
+            conn.execute(
+                "UPDATE contacts SET pinned_at = ? WHERE id = ?",
+                params![now, contact_id],
+            )?;
+            Ok(true) // Now pinned
+        }
+    }
+
+    /// Toggle whether outgoing messages to a contact are translated, setting
+    /// the opposite of what currently applies for the contact.
+    /// Returns the new state.
+    pub fn toggle_outgoing_translation(&self, contact_id: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+        self.contact_cache.invalidate(&contact_id);
+
+        let enabled = !Self::outgoing_translation(&conn, &contact_id)?.enabled;
+        Self::write_outgoing_translation(&conn, &contact_id, Some(enabled))?;
+        Ok(enabled)
+    }
+
+    /// Choose whether outgoing messages to a contact are translated, or go
+    /// back to the default for its chat type with None
+    pub fn set_outgoing_translation(&self, contact_id: &str, enabled: Option<bool>) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+        self.contact_cache.invalidate(&contact_id);
+        Self::write_outgoing_translation(&conn, &contact_id, enabled)
+    }
+
+    fn write_outgoing_translation(
+        conn: &Connection,
+        contact_id: &str,
+        enabled: Option<bool>,
+    ) -> Result<()> {
+        let updated = conn.execute(
+            "UPDATE contacts SET outgoing_translation_set = ?1, auto_translate_outgoing = ?2
+             WHERE id = ?3",
+            params![enabled.is_some(), enabled.unwrap_or(true), contact_id],
+        )?;
+        if updated == 0 {
+            anyhow::bail!("Contact not found: {}", contact_id);
+        }
+
+        info!(
+            "Outgoing translation for {} is now {}",
+            contact_id,
+            match enabled {
+                Some(true) => "on",
+                Some(false) => "off",
+                None => "the default",
+            }
+        );
+
+        Ok(())
+    }
+
+    /// Whether outgoing messages to a contact should be translated, and why.
+    /// Unknown contacts are treated as private chats (or groups, by JID).
+    pub fn get_outgoing_translation(&self, contact_id: &str) -> Result<OutgoingTranslation> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+        Self::outgoing_translation(&conn, &contact_id)
+    }
+
+    fn outgoing_translation(conn: &Connection, contact_id: &str) -> Result<OutgoingTranslation> {
+        let contact: Option<(Option<String>, bool, bool, Option<String>)> = conn
+            .query_row(
+                "SELECT type, outgoing_translation_set, auto_translate_outgoing, language_override
+                 FROM contacts WHERE id = ?",
+                params![contact_id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .optional()?;
+        let (chat_type, is_set, enabled, language_override) =
+            contact.unwrap_or((None, false, true, None));
+        let contact_setting = is_set.then_some(enabled);
+        let is_group = match chat_type.as_deref() {
+            Some(chat_type) => chat_type == "group",
+            None => contact_id.ends_with("@g.us"),
+        };
+
+        let (enabled, reason) = if let Some(enabled) = contact_setting {
+            (enabled, OutgoingTranslationReason::ContactSetting)
+        } else if is_group {
+            let enabled = Self::read_setting(conn, GROUP_OUTGOING_TRANSLATION_SETTING)?
+                .is_some_and(|v| v == "true");
+            (enabled, OutgoingTranslationReason::GroupDefault)
+        } else if language_override.is_some() {
+            (true, OutgoingTranslationReason::LanguageOverride)
+        } else {
+            let confidence = Self::language_confidence(conn, contact_id)?;
+            if confidence.language.is_some()
+                && confidence.confidence >= OUTGOING_TRANSLATION_MIN_CONFIDENCE
+            {
+                (true, OutgoingTranslationReason::Confident)
+            } else {
+                (false, OutgoingTranslationReason::NotConfident)
+            }
+        };
+        Ok(OutgoingTranslation {
+            enabled,
+            reason,
+            contact_setting,
+        })
+    }
+
+    /// How many of a contact's last `LANGUAGE_CONFIDENCE_WINDOW` incoming
+    /// messages with a detected language are in the conversation language
+    pub fn get_language_confidence(&self, contact_id: &str) -> Result<LanguageConfidence> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+        Self::language_confidence(&conn, &contact_id)
+    }
+
+    fn language_confidence(conn: &Connection, contact_id: &str) -> Result<LanguageConfidence> {
+        let language = Self::cached_conversation_language(conn, contact_id)?;
+
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT source_language FROM messages
+            WHERE contact_id = ?1
+              AND is_from_me = 0
+              AND source_language IS NOT NULL
+              AND source_language != ''
+            ORDER BY timestamp DESC, rowid DESC
+            LIMIT ?2
+            "#,
+        )?;
+        let recent: Vec<String> = stmt
+            .query_map(params![contact_id, LANGUAGE_CONFIDENCE_WINDOW], |row| {
+                row.get(0)
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let sampled = recent.len() as u32;
+        let matching = language.as_deref().map_or(0, |language| {
+            recent
+                .iter()
+                .filter(|l| l.eq_ignore_ascii_case(language))
+                .count() as u32
+        });
+        Ok(LanguageConfidence {
+            language,
+            sampled,
+            matching,
+            confidence: if sampled > 0 {
+                matching as f64 / sampled as f64
+            } else {
+                0.0
+            },
+        })
+    }
+
+    /// The web password's hash, None if no password is set
+    pub fn get_web_password_hash(&self) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        Self::read_setting(&conn, WEB_PASSWORD_SETTING)
+    }
+
+    /// Hash and save a new web password, returning the hash
+    pub fn set_web_password(&self, password: &str) -> Result<String> {
+        // Hashing is deliberately slow, so it's done before taking the lock
+        let hash = crate::password::hash(password)?;
+        let conn = self.conn.lock().unwrap();
+        Self::write_setting(&conn, WEB_PASSWORD_SETTING, Some(&hash))?;
+        Ok(hash)
+    }
+
+    /// My own profile as last saved (empty before the first connection)
+    pub fn get_own_profile(&self) -> Result<OwnProfile> {
+        let conn = self.conn.lock().unwrap();
+        Self::read_own_profile(&conn)
+    }
+
+    fn read_own_profile(conn: &Connection) -> Result<OwnProfile> {
+        Ok(Self::read_setting(conn, OWN_PROFILE_SETTING)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default())
+    }
+
+    /// Change my saved profile. Returns the new profile if anything changed.
+    pub fn update_own_profile(
+        &self,
+        update: impl FnOnce(&mut OwnProfile),
+    ) -> Result<Option<OwnProfile>> {
+        let conn = self.conn.lock().unwrap();
+        let current = Self::read_own_profile(&conn)?;
+        let mut profile = current.clone();
+        update(&mut profile);
+        if profile == current {
+            return Ok(None);
+        }
+
+        profile.updated_at = Some(chrono::Utc::now().timestamp_millis());
+        Self::write_setting(
+            &conn,
+            OWN_PROFILE_SETTING,
+            Some(&serde_json::to_string(&profile)?),
+        )?;
+        Ok(Some(profile))
+    }
+
+    /// Whether outgoing messages in groups are translated by default
+    pub fn get_group_outgoing_translation(&self) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(
+            Self::read_setting(&conn, GROUP_OUTGOING_TRANSLATION_SETTING)?
+                .is_some_and(|v| v == "true"),
+        )
+    }
+
+    pub fn set_group_outgoing_translation(&self, enabled: bool) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        self.contact_cache.clear();
+        Self::write_setting(
+            &conn,
+            GROUP_OUTGOING_TRANSLATION_SETTING,
+            enabled.then_some("true"),
+        )
+    }
+
+    /// How long messages sent from the web UI can be undone (seconds, 0 = off)
+    pub fn get_undo_window_secs(&self) -> Result<u64> {
+        let conn = self.conn.lock().unwrap();
+        Ok(Self::read_setting(&conn, UNDO_WINDOW_SETTING)?
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0))
+    }
+
+    pub fn set_undo_window_secs(&self, secs: u64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let value = (secs > 0).then(|| secs.to_string());
+        Self::write_setting(&conn, UNDO_WINDOW_SETTING, value.as_deref())
+    }
+
+    /// How far back history syncs import messages
+    pub fn get_history_depth(&self) -> Result<HistoryDepth> {
+        let conn = self.conn.lock().unwrap();
+        Ok(Self::read_setting(&conn, HISTORY_DEPTH_SETTING)?
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default())
+    }
+
+    pub fn set_history_depth(&self, depth: HistoryDepth) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        Self::write_setting(&conn, HISTORY_DEPTH_SETTING, Some(depth.as_str()))
+    }
+
+    /// Whether a history sync has been imported, so it needn't be again
+    pub fn is_history_sync_complete(&self) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        Ok(Self::read_setting(&conn, HISTORY_SYNC_COMPLETE_SETTING)?.as_deref() == Some("true"))
+    }
+
+    pub fn set_history_sync_complete(&self, complete: bool) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        Self::write_setting(
+            &conn,
+            HISTORY_SYNC_COMPLETE_SETTING,
+            complete.then_some("true"),
+        )
+    }
+
+    /// Toggle whether only messages that mention me count as unread for a
+    /// contact. Returns the new state.
+    pub fn toggle_mentions_only(&self, contact_id: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+        self.contact_cache.invalidate(&contact_id);
+
+        let updated = conn.execute(
+            "UPDATE contacts SET mentions_only = NOT mentions_only WHERE id = ?",
+            params![contact_id],
+        )?;
+        if updated == 0 {
+            anyhow::bail!("Contact not found: {}", contact_id);
+        }
+
+        let enabled: bool = conn.query_row(
+            "SELECT mentions_only FROM contacts WHERE id = ?",
+            params![contact_id],
+            |row| row.get(0),
+        )?;
+
+        info!(
+            "Mentions-only unread for {} is now {}",
+            contact_id,
+            if enabled { "on" } else { "off" }
+        );
+
+        Ok(enabled)
+    }
+
+    /// Whether only messages that mention me count as unread for a contact.
+    /// Unknown contacts default to false.
+    pub fn get_mentions_only(&self, contact_id: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+
+        let result = conn.query_row(
+            "SELECT mentions_only FROM contacts WHERE id = ?",
+            params![contact_id],
+            |row| row.get(0),
+        );
+
+        match result {
+            Ok(enabled) => Ok(enabled),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get conversation settings for a contact
+    pub fn get_conversation_settings(&self, contact_id: &str) -> Result<ConversationSettings> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+
+        let result = conn.query_row(
+            "SELECT language_override, translation_style, learning_mode, muted, triage, translation_tone,
+                    timezone, include_learning_notes
+             FROM contacts WHERE id = ?",
+            params![contact_id],
+            |row| {
+                let tone: Option<String> = row.get(5)?;
+                Ok(ConversationSettings {
+                    language_override: row.get(0)?,
+                    translation_style: row.get(1)?,
+                    learning_mode: row.get(2)?,
+                    muted: row.get(3)?,
+                    triage: row.get(4)?,
+                    translation_tone: tone.and_then(|t| serde_json::from_str(&t).ok()),
+                    timezone: row.get(6)?,
+                    include_learning_notes: row.get(7)?,
+                })
+            },
+        );
+
+        match result {
+            Ok(settings) => Ok(settings),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(ConversationSettings::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Update conversation settings for a contact
+    pub fn update_conversation_settings(
+        &self,
+        contact_id: &str,
+        settings: &ConversationSettings,
+    ) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+
+        conn.execute(
+            "UPDATE contacts SET language_override = ?, translation_style = ?, learning_mode = ?, muted = ?, triage = ?,
+                                 translation_tone = ?, timezone = ?, include_learning_notes = ?
+             WHERE id = ?",
+            params![
+                settings.language_override,
+                settings.translation_style,
+                settings.learning_mode,
+                settings.muted,
+                settings.triage,
+                settings
+                    .translation_tone
+                    .as_ref()
+                    .map(serde_json::to_string)
+                    .transpose()?,
+                settings.timezone,
+                settings.include_learning_notes,
+                contact_id
+            ],
+        )?;
+
+        info!(
+            "Updated conversation settings for {}: language={:?}, style={:?}, learning={}, muted={}, triage={:?}, tone={:?}, timezone={:?}, learning_notes={}",
+            contact_id,
+            settings.language_override,
+            settings.translation_style,
+            settings.learning_mode,
+            settings.muted,
+            settings.triage,
+            settings.translation_tone,
+            settings.timezone,
+            settings.include_learning_notes
+        );
+
+        Ok(())
+    }
+
+    /// The settings a settings export carries that are set, by key
+    pub fn get_portable_settings(&self) -> Result<Vec<(String, String)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut settings = Vec::new();
+        for (key, _) in PORTABLE_SETTINGS {
+            if let Some(value) = Self::read_setting(&conn, key)? {
+                settings.push((key.to_string(), value));
+            }
+        }
+        Ok(settings)
+    }
+
+    /// Every contact with configuration of its own, in ID order
+    pub fn get_contact_configs(&self) -> Result<Vec<ContactConfig>> {
+        let conn = self.conn.lock().unwrap();
+        let ids = conn
+            .prepare(
+                "SELECT id FROM contacts WHERE id NOT IN (SELECT alt_jid FROM identity_links)
+                 ORDER BY id",
+            )?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut configs = Vec::new();
+        for id in ids {
+            if let Some(config) = Self::contact_config(&conn, &id)?.filter(|c| !c.is_default()) {
+                configs.push(config);
+            }
+        }
+        Ok(configs)
+    }
+
+    /// A contact's own configuration, or None if there's no such contact
+    fn contact_config(conn: &Connection, contact_id: &str) -> Result<Option<ContactConfig>> {
+        let config = conn
+            .query_row(
+                "SELECT name, type, language_override, translation_style, translation_tone, timezone,
+                        learning_mode, include_learning_notes, muted, triage, mentions_only,
+                        outgoing_translation_set, auto_translate_outgoing, pinned_at IS NOT NULL
+                 FROM contacts WHERE id = ?",
+                params![contact_id],
+                |row| {
+                    let tone: Option<String> = row.get(4)?;
+                    let outgoing_set: bool = row.get(11)?;
+                    Ok(ContactConfig {
+                        id: contact_id.to_string(),
+                        name: row.get(0)?,
+                        contact_type: row.get(1)?,
+                        language_override: row.get(2)?,
+                        translation_style: row.get(3)?,
+                        translation_tone: tone.and_then(|t| serde_json::from_str(&t).ok()),
+                        timezone: row.get(5)?,
+                        learning_mode: row.get(6)?,
+                        include_learning_notes: row.get(7)?,
+                        muted: row.get(8)?,
+                        triage: row.get(9)?,
+                        mentions_only: row.get(10)?,
+                        auto_translate_outgoing: match outgoing_set {
+                            true => Some(row.get(12)?),
+                            false => None,
+                        },
+                        pinned: row.get(13)?,
+                        translation_participants: Default::default(),
+                    })
+                },
+            )
+            .optional()?;
+        let Some(mut config) = config else {
+            return Ok(None);
+        };
+
+        let mut stmt = conn.prepare(
+            "SELECT participant_jid, mode FROM translation_participants WHERE group_jid = ?",
+        )?;
+        let participants = stmt.query_map(params![contact_id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for participant in participants {
+            let (participant_jid, mode) = participant?;
+            let mode = if mode == "always" {
+                ParticipantTranslationMode::Always
+            } else {
+                ParticipantTranslationMode::Never
+            };
+            config
+                .translation_participants
+                .insert(participant_jid, mode);
+        }
+        Ok(Some(config))
+    }
+
+    /// Replace a contact's own configuration, `previous` being what it was.
+    /// A contact already pinned keeps its place.
+    fn write_contact_config(
+        conn: &Connection,
+        previous: &ContactConfig,
+        config: &ContactConfig,
+        now: i64,
+    ) -> Result<()> {
+        conn.execute(
+            "UPDATE contacts SET language_override = ?1, translation_style = ?2, translation_tone = ?3,
+                                 timezone = ?4, learning_mode = ?5, include_learning_notes = ?6, muted = ?7,
+                                 triage = ?8, mentions_only = ?9, outgoing_translation_set = ?10,
+                                 auto_translate_outgoing = ?11,
+                                 pinned_at = CASE WHEN ?12 THEN COALESCE(pinned_at, ?13) ELSE NULL END
+             WHERE id = ?14",
+            params![
+                config.language_override,
+                config.translation_style,
+                config
+                    .translation_tone
+                    .as_ref()
+                    .map(serde_json::to_string)
+                    .transpose()?,
+                config.timezone,
+                config.learning_mode,
+                config.include_learning_notes,
+                config.muted,
+                config.triage,
+                config.mentions_only,
+                config.auto_translate_outgoing.is_some(),
+                config.auto_translate_outgoing.unwrap_or(true),
+                config.pinned,
+                now,
+                config.id
+            ],
+        )?;
+
+        if config.translation_participants != previous.translation_participants {
+            conn.execute(
+                "DELETE FROM translation_participants WHERE group_jid = ?",
+                params![config.id],
+            )?;
+            for (participant_jid, mode) in &config.translation_participants {
+                conn.execute(
+                    "INSERT OR REPLACE INTO translation_participants (group_jid, participant_jid, mode, updated_at)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![
+                        config.id,
+                        Self::resolve_id(conn, participant_jid),
+                        mode.as_str(),
+                        now
+                    ],
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply checked settings and contact configuration from a settings
+    /// import in one transaction (see `settings_transfer`), or with
+    /// `dry_run` work out what would change and roll it back
+    pub fn import_config(
+        &self,
+        settings: &[(String, String)],
+        contacts: &[ContactConfig],
+        on_conflict: ConflictResolution,
+        dry_run: bool,
+    ) -> Result<ImportReport> {
+        let conn = self.conn.lock().unwrap();
+        let tx = conn.unchecked_transaction()?;
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut report = ImportReport {
+            dry_run,
+            settings: Vec::new(),
+            contacts: Vec::new(),
+        };
+
+        for (key, value) in settings {
+            let local = Self::read_setting(&tx, key)?;
+            let action = settings_transfer::merge_setting(local.as_deref(), value, on_conflict);
+            if matches!(action, ImportAction::Created | ImportAction::Updated) {
+                Self::write_setting(&tx, key, Some(value))?;
+            }
+            report.settings.push(SettingImport {
+                key: key.clone(),
+                action,
+            });
+        }
+
+        for imported in contacts {
+            let id = Self::resolve_id(&tx, &imported.id);
+            let imported = ContactConfig {
+                id: id.clone(),
+                ..imported.clone()
+            };
+            let local = Self::contact_config(&tx, &id)?;
+            let (config, change) =
+                settings_transfer::merge_contact(local.as_ref(), &imported, on_conflict);
+
+            // Contacts we don't have yet stay placeholders until WhatsApp
+            // tells us about them
+            if local.is_none() {
+                Self::upsert_contact_row(
+                    &tx,
+                    &ContactUpsert {
+                        id: &id,
+                        name: imported.name.as_deref(),
+                        phone: id.strip_suffix("@s.whatsapp.net"),
+                        contact_type: imported.contact_type.as_deref(),
+                        last_message_time: 0,
+                    },
+                    now,
+                )?;
+                tx.execute(
+                    "UPDATE contacts SET placeholder = 1 WHERE id = ?",
+                    params![id],
+                )?;
+            }
+            let previous = local.unwrap_or_else(|| ContactConfig {
+                id: id.clone(),
+                ..Default::default()
+            });
+            if config != previous {
+                Self::write_contact_config(&tx, &previous, &config, now)?;
+            }
+            report.contacts.push(change);
+        }
+
+        if dry_run {
+            tx.rollback()?;
+            return Ok(report);
+        }
+        tx.commit()?;
+        self.contact_cache.clear();
+        info!(
+            "Imported settings: {} settings and {} contacts",
+            report.settings.len(),
+            report.contacts.len()
+        );
+        Ok(report)
+    }
+
+    /// Timestamps (ms) of a chat's latest incoming messages, newest first
+    pub fn incoming_timestamps(&self, contact_id: &str, limit: usize) -> Result<Vec<i64>> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT m.timestamp FROM messages m
+             WHERE m.contact_id = ? AND {}
+             ORDER BY m.timestamp DESC LIMIT ?",
+            UNREAD_MESSAGE_SQL
+        ))?;
+        let timestamps = stmt
+            .query_map(params![contact_id, limit as i64], |row| row.get(0))?
+            .collect::<Result<Vec<i64>, _>>()?;
+        Ok(timestamps)
+    }
+
+    /// Vocabulary extracted from a chat in learning mode, most frequent first.
+    /// Terms are counted case-insensitively, keeping the latest gloss.
+    pub fn get_vocabulary(&self, contact_id: &str) -> Result<Vec<VocabularyItem>> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+
+        let mut stmt = conn.prepare(
+            "SELECT vocab_json, timestamp FROM messages
+             WHERE contact_id = ? AND vocab_json IS NOT NULL
+             ORDER BY timestamp ASC",
+        )?;
+        let rows = stmt
+            .query_map(params![contact_id], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })?
+            .filter_map(|r| r.ok());
+
+        let mut items: HashMap<String, VocabularyItem> = HashMap::new();
+        for (json, timestamp) in rows {
+            let Ok(entries) = serde_json::from_str::<Vec<VocabEntry>>(&json) else {
+                continue;
+            };
+            for entry in entries {
+                let item =
+                    items
+                        .entry(entry.term.to_lowercase())
+                        .or_insert_with(|| VocabularyItem {
+                            term: entry.term.clone(),
+                            gloss: String::new(),
+                            count: 0,
+                            last_seen: timestamp,
+                        });
+                item.gloss = entry.gloss;
+                item.count += 1;
+                item.last_seen = timestamp;
+            }
+        }
+
+        let mut items: Vec<VocabularyItem> = items.into_values().collect();
+        items.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then(b.last_seen.cmp(&a.last_seen))
+                .then(a.term.cmp(&b.term))
+        });
+        Ok(items)
+    }
+
+    /// Get all messages for a specific contact (used by tests)
+    #[cfg(test)]
+    pub fn get_messages(&self, contact_id: &str) -> Result<Vec<StoredMessage>> {
+        self.get_messages_paginated(
+            contact_id,
+            None,
+            None,
+            None,
+            false,
+            MessageFilter::default(),
+        )
+    }
+
+    /// Count a contact's messages, optionally only those before and/or after
+    /// a timestamp (milliseconds, exclusive)
+    pub fn count_messages(
+        &self,
+        contact_id: &str,
+        before_timestamp: Option<i64>,
+        after_timestamp: Option<i64>,
+    ) -> Result<u64> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+
+        let count: i64 = conn.query_row(
+            r#"
+            SELECT COUNT(*) FROM messages
+            WHERE contact_id = ?1
+              AND (?2 IS NULL OR timestamp < ?2)
+              AND (?3 IS NULL OR timestamp > ?3)
+            "#,
+            params![contact_id, before_timestamp, after_timestamp],
+            |row| row.get(0),
+        )?;
+
+        Ok(count as u64)
+    }
+
+    /// Get a conversation's translated messages before a timestamp, most
+    /// recent `limit` of them (all if None), in chronological order
+    pub fn get_translations(
+        &self,
+        contact_id: &str,
+        limit: Option<u32>,
+        before_timestamp: Option<i64>,
+    ) -> Result<Vec<TranslationPair>> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT id, timestamp, is_from_me, original_text, translated_text, source_language
+            FROM messages
+            WHERE contact_id = ?1 AND is_translated = 1
+              AND (?2 IS NULL OR timestamp < ?2)
+            ORDER BY timestamp DESC
+            LIMIT ?3
+            "#,
+        )?;
+        let rows = stmt.query_map(
+            params![contact_id, before_timestamp, limit.map_or(-1, i64::from)],
+            |row| {
+                Ok(TranslationPair {
+                    message_id: row.get(0)?,
+                    timestamp: row.get(1)?,
+                    direction: if row.get(2)? { "outgoing" } else { "incoming" },
+                    original_text: row.get(3)?,
+                    translated_text: row.get(4)?,
+                    source_language: row.get(5)?,
+                })
+            },
+        )?;
+
+        let mut translations = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+        translations.reverse();
+        Ok(translations)
+    }
+
+    /// Pin a message to the top of its chat unless the chat already has
+    /// `max_pins` pinned. Returns whether the message is pinned (it may
+    /// have been already).
+    pub fn pin_message(
+        &self,
+        contact_id: &str,
+        message_id: &str,
+        pinned_by: &str,
+        max_pins: usize,
+    ) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+
+        let tx = conn.unchecked_transaction()?;
+        let (already_pinned, pins): (bool, i64) = tx.query_row(
+            r#"
+            SELECT EXISTS(SELECT 1 FROM pinned_messages WHERE message_id = ?2),
+                   (SELECT COUNT(*) FROM pinned_messages WHERE contact_id = ?1)
+            "#,
+            params![contact_id, message_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        if already_pinned {
+            return Ok(true);
+        }
+        if pins as usize >= max_pins {
+            return Ok(false);
+        }
+        tx.execute(
+            "INSERT INTO pinned_messages (message_id, contact_id, pinned_at, pinned_by) VALUES (?, ?, ?, ?)",
+            params![
+                message_id,
+                contact_id,
+                chrono::Utc::now().timestamp_millis(),
+                pinned_by
+            ],
+        )?;
+        tx.commit()?;
+
+        info!("Pinned message {} in {}", message_id, contact_id);
+        Ok(true)
+    }
+
+    /// Unpin a message, returning the chat it was pinned in (None if it
+    /// wasn't pinned)
+    pub fn unpin_message(&self, message_id: &str) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id: Option<String> = conn
+            .query_row(
+                "DELETE FROM pinned_messages WHERE message_id = ? RETURNING contact_id",
+                params![message_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(contact_id) = &contact_id {
+            info!("Unpinned message {} in {}", message_id, contact_id);
+        }
+        Ok(contact_id)
+    }
+
+    /// A chat's pinned messages, the most recently pinned first, looked up
+    /// together. Media data isn't included.
+    pub fn get_pinned_messages(&self, contact_id: &str) -> Result<Vec<PinnedMessage>> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT m.id, m.contact_id, m.timestamp, m.is_from_me, m.is_forwarded, m.sender_name,
+                   m.sender_phone, m.chat_type, m.content_type, m.content_json, m.original_text,
+                   m.translated_text, m.source_language, m.is_translated,
+                   c.name, c.phone, m.origin, m.mentioned_jids, m.mentions_me, m.sort_key,
+                   m.translation_status, p.pinned_at, p.pinned_by, m.sent_by
+            FROM pinned_messages p
+            JOIN messages m ON m.id = p.message_id
+            LEFT JOIN contacts c ON c.id = m.contact_id
+            WHERE p.contact_id = ?
+            ORDER BY p.pinned_at DESC, p.rowid DESC
+            "#,
+        )?;
+        let rows = stmt.query_map(params![contact_id], |row| {
+            Ok(PinnedMessage {
+                message: Self::row_to_stored_message(row, row.get(14)?, row.get(15)?)?,
+                pinned_at: row.get(21)?,
+                pinned_by: row.get(22)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    /// Get the unsent draft for a conversation
+    pub fn get_draft(&self, contact_id: &str) -> Result<Option<Draft>> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+        Ok(Self::query_draft(&conn, &contact_id)?)
+    }
+
+    fn query_draft(conn: &Connection, contact_id: &str) -> rusqlite::Result<Option<Draft>> {
+        conn.query_row(
+            "SELECT contact_id, text, reply_to_message_id, updated_at FROM drafts WHERE contact_id = ?",
+            params![contact_id],
+            |row| {
+                Ok(Draft {
+                    contact_id: row.get(0)?,
+                    text: row.get(1)?,
+                    reply_to_message_id: row.get(2)?,
+                    updated_at: row.get(3)?,
+                })
+            },
+        )
+        .optional()
+    }
+
+    /// Save a conversation's draft, last write wins. A write older than the
+    /// stored draft is rejected: the newer stored draft is returned instead.
+    pub fn save_draft(&self, draft: &Draft) -> Result<Option<Draft>> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, &draft.contact_id);
+        let saved = conn.execute(
+            r#"
+            INSERT INTO drafts (contact_id, text, reply_to_message_id, updated_at)
+            VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT(contact_id) DO UPDATE SET
+                text = excluded.text,
+                reply_to_message_id = excluded.reply_to_message_id,
+                updated_at = excluded.updated_at
+            WHERE excluded.updated_at >= drafts.updated_at
+            "#,
+            params![
+                contact_id,
+                draft.text,
+                draft.reply_to_message_id,
+                draft.updated_at
+            ],
+        )?;
+        if saved > 0 {
+            return Ok(None);
+        }
+        Ok(Self::query_draft(&conn, &contact_id)?)
+    }
+
+    /// Delete a conversation's draft. With `as_of`, a draft saved after that
+    /// time is kept and returned as a conflict.
+    pub fn delete_draft(&self, contact_id: &str, as_of: Option<i64>) -> Result<Option<Draft>> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+        let deleted = conn.execute(
+            "DELETE FROM drafts WHERE contact_id = ?1 AND (?2 IS NULL OR updated_at <= ?2)",
+            params![contact_id, as_of],
+        )?;
+        if deleted > 0 || as_of.is_none() {
+            return Ok(None);
+        }
+        Ok(Self::query_draft(&conn, &contact_id)?)
+    }
+
+    /// A group's participant translation filters, with names resolved
+    pub fn get_translation_participants(
+        &self,
+        group_jid: &str,
+    ) -> Result<Vec<TranslationParticipant>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT p.participant_jid, p.mode, p.updated_at,
+                   COALESCE(c.name, (
+                       SELECT m.sender_name FROM messages m
+                       WHERE m.contact_id = p.group_jid
+                         AND m.sender_phone = substr(p.participant_jid, 1, instr(p.participant_jid, '@') - 1)
+                         AND m.sender_name IS NOT NULL
+                       ORDER BY m.timestamp DESC LIMIT 1
+                   ))
+            FROM translation_participants p
+            LEFT JOIN contacts c ON c.id = p.participant_jid
+            WHERE p.group_jid = ?
+            ORDER BY p.updated_at, p.participant_jid
+            "#,
+        )?;
+        let participants = stmt
+            .query_map(params![group_jid], |row| {
+                let mode: String = row.get(1)?;
+                Ok(TranslationParticipant {
+                    participant_jid: row.get(0)?,
+                    mode: if mode == "always" {
+                        ParticipantTranslationMode::Always
+                    } else {
+                        ParticipantTranslationMode::Never
+                    },
+                    updated_at: row.get(2)?,
+                    name: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(participants)
+    }
+
+    /// Always or never translate a group participant, or clear the filter
+    /// with None
+    pub fn set_translation_participant(
+        &self,
+        group_jid: &str,
+        participant_jid: &str,
+        mode: Option<ParticipantTranslationMode>,
+    ) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let participant_jid = Self::resolve_id(&conn, participant_jid);
+        match mode {
+            Some(mode) => conn.execute(
+                r#"
+                INSERT INTO translation_participants (group_jid, participant_jid, mode, updated_at)
+                VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT(group_jid, participant_jid) DO UPDATE SET
+                    mode = excluded.mode,
+                    updated_at = excluded.updated_at
+                "#,
+                params![
+                    group_jid,
+                    participant_jid,
+                    mode.as_str(),
+                    chrono::Utc::now().timestamp_millis()
+                ],
+            )?,
+            None => conn.execute(
+                "DELETE FROM translation_participants WHERE group_jid = ?1 AND participant_jid = ?2",
+                params![group_jid, participant_jid],
+            )?,
+        };
+        Ok(())
+    }
+
+    /// Whether a group participant's messages are translated. Participants
+    /// set to never aren't; once anyone in the group is set to always, only
+    /// they are; otherwise everyone is.
+    pub fn translates_participant(&self, group_jid: &str, participant_jid: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let participant_jid = Self::resolve_id(&conn, participant_jid);
+        let (mode, any_always): (Option<String>, bool) = conn.query_row(
+            r#"
+            SELECT (SELECT mode FROM translation_participants
+                    WHERE group_jid = ?1 AND participant_jid = ?2),
+                   EXISTS(SELECT 1 FROM translation_participants
+                          WHERE group_jid = ?1 AND mode = 'always')
+            "#,
+            params![group_jid, participant_jid],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok(match mode.as_deref() {
+            Some(mode) => mode == "always",
+            None => !any_always,
+        })
+    }
+
+    /// Whether a message is a view-once photo or video
+    pub fn is_view_once_message(&self, message_id: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let view_once: Option<bool> = conn
+            .query_row(
+                "SELECT json_extract(content_json, '$.view_once') = 1 FROM messages WHERE id = ?",
+                params![message_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
+        Ok(view_once.unwrap_or(false))
+    }
+
+    /// Record when a view-once message's media was opened.
+    /// Returns false if it isn't a view-once message or was already opened.
+    pub fn mark_view_once_viewed(&self, message_id: &str, viewed_at: i64) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            r#"
+            UPDATE messages SET content_json = json_set(content_json, '$.viewed_at', ?2)
+            WHERE id = ?1
+              AND json_extract(content_json, '$.view_once') = 1
+              AND json_extract(content_json, '$.viewed_at') IS NULL
+            "#,
+            params![message_id, viewed_at],
+        )?;
+        Ok(updated > 0)
+    }
+
+    /// Get media data for a specific message
+    /// Returns the media_data and mime_type for a message
+    pub fn get_message_media(&self, message_id: &str) -> Result<Option<(String, Option<String>)>> {
+        let conn = self.conn.lock().unwrap();
+
+        let result = conn.query_row(
+            r#"
+            SELECT m.content_json, b.data
+            FROM messages m
+            LEFT JOIN media_blobs b ON b.hash = m.media_hash
+            WHERE m.id = ?
+            "#,
+            params![message_id],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)),
+        );
+
+        match result {
+            Ok((content_json, blob_data)) => {
+                // Parse and extract media_data and mime_type
+                if let Ok(content) = serde_json::from_str::<serde_json::Value>(&content_json) {
+                    let media_data = blob_data.or_else(|| {
+                        content
+                            .get("media_data")
+                            .or_else(|| content.get("mediaData"))
+                            .and_then(|v| v.as_str())
+                            .map(|s| s.to_string())
+                    });
+
+                    let mime_type = content
+                        .get("mime_type")
+                        .or_else(|| content.get("mimeType"))
+                        .and_then(|v| v.as_str())
+                        .map(|s| s.to_string());
+
+                    if let Some(media) = media_data {
+                        return Ok(Some((media, mime_type)));
+                    }
+                }
+                Ok(None)
+            }
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Strip media_data from content JSON to reduce payload size
+    pub fn strip_media_from_content(content_json: &str) -> (String, Option<serde_json::Value>) {
+        if let Ok(mut content) = serde_json::from_str::<serde_json::Value>(content_json) {
+            // Check if this content has media_data
+            let has_media =
+                content.get("media_data").is_some() || content.get("mediaData").is_some();
+
+            if has_media {
+                // Remove media_data from content
+                if let Some(obj) = content.as_object_mut() {
+                    obj.remove("media_data");
+                    obj.remove("mediaData");
+                    // Add a flag to indicate media is available
+                    obj.insert("has_media".to_string(), serde_json::Value::Bool(true));
+                }
+                let stripped_json =
+                    serde_json::to_string(&content).unwrap_or_else(|_| content_json.to_string());
+                return (stripped_json, Some(content));
+            }
+
+            (content_json.to_string(), Some(content))
+        } else {
+            (content_json.to_string(), None)
+        }
+    }
+
+    /// Get messages for a specific contact with pagination
+    /// - limit: max number of messages to return (default: all)
+    /// - before_timestamp: only get messages before this timestamp (for loading older messages)
+    /// - after_timestamp: only get messages after this timestamp; without a
+    ///   before_timestamp the limit then keeps the oldest of them (for paging forward)
+    /// - strip_media: if true, remove media_data from content to reduce payload size
+    /// - filter: only get messages sent by an origin (e.g. "web", "mcp", "mcp:<client_id>")
+    ///   or by a named web session
+    /// Returns messages in ascending order by timestamp (oldest first)
+    pub fn get_messages_paginated(
+        &self,
+        contact_id: &str,
+        limit: Option<u32>,
+        before_timestamp: Option<i64>,
+        after_timestamp: Option<i64>,
+        strip_media: bool,
+        filter: MessageFilter<'_>,
+    ) -> Result<Vec<StoredMessage>> {
+        Ok(self
+            .query_messages(
+                contact_id,
+                limit,
+                before_timestamp.map(Before::Timestamp),
+                after_timestamp,
+                strip_media,
+                filter,
+            )?
+            .into_iter()
+            .map(|(message, _)| message)
+            .collect())
+    }
+
+    /// Get the latest `limit` messages for a contact before `cursor` if
+    /// given, oldest first, with the cursor for the older page if there is
+    /// one. Messages are in the chat's order (sort key, then rowid), so a
+    /// page boundary never skips or repeats messages sharing a timestamp.
+    pub fn get_messages_page(
+        &self,
+        contact_id: &str,
+        limit: u32,
+        cursor: Option<MessageCursor>,
+        strip_media: bool,
+        filter: MessageFilter<'_>,
+    ) -> Result<(Vec<StoredMessage>, Option<MessageCursor>)> {
+        let mut messages = self.query_messages(
+            contact_id,
+            Some(limit + 1),
+            cursor.map(Before::Cursor),
+            None,
+            strip_media,
+            filter,
+        )?;
+        let next = if messages.len() > limit as usize {
+            messages.remove(0);
+            messages.first().map(|(_, cursor)| *cursor)
+        } else {
+            None
+        };
+        Ok((messages.into_iter().map(|(m, _)| m).collect(), next))
+    }
+
+    /// Messages for `get_messages_paginated` and `get_messages_page`, each
+    /// with the cursor for the messages before it
+    fn query_messages(
+        &self,
+        contact_id: &str,
+        limit: Option<u32>,
+        before: Option<Before>,
+        after_timestamp: Option<i64>,
+        strip_media: bool,
+        filter: MessageFilter<'_>,
+    ) -> Result<Vec<(StoredMessage, MessageCursor)>> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+
+        // First get the contact info to populate contact_name and contact_phone
+        let contact_info: Option<(Option<String>, Option<String>)> = conn
+            .query_row(
+                "SELECT name, phone FROM contacts WHERE id = ?",
+                params![contact_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .ok();
+
+        let (contact_name, contact_phone) = contact_info.unwrap_or((None, None));
+
+        // We select in DESC order to get the most recent N messages, then reverse
+        // (unless paging forward from after_timestamp).
+        // An origin filter matches exactly or by kind ("mcp" matches "mcp:<client_id>").
+        // A sent_by filter matches exactly.
+        let newest_first = limit.is_some() && (after_timestamp.is_none() || before.is_some());
+        let (before_timestamp, before_cursor) = match before {
+            Some(Before::Timestamp(timestamp)) => (Some(timestamp), None),
+            Some(Before::Cursor(cursor)) => (None, Some(cursor)),
+            None => (None, None),
+        };
+        let query = format!(
+            r#"
+            SELECT id, contact_id, timestamp, is_from_me, is_forwarded, sender_name, 
+                   sender_phone, chat_type, content_type, content_json, original_text,
+                   translated_text, source_language, is_translated, media_hash, origin,
+                   mentioned_jids, mentions_me,
+                   (SELECT thumbnail FROM media_blobs WHERE hash = messages.media_hash),
+                   vocab_json, audio_duration_ms, audio_waveform, audio_metadata_only, sort_key,
+                   urgency, tone, rowid, translation_status, sent_by
+            FROM messages 
+            WHERE contact_id = ?1
+              AND (?2 IS NULL OR timestamp < ?2)
+              AND (?3 IS NULL OR origin = ?3 OR substr(origin, 1, length(?3) + 1) = ?3 || ':')
+              AND (?7 IS NULL OR sent_by = ?7)
+              AND (?4 IS NULL OR timestamp > ?4)
+              AND (?5 IS NULL OR (COALESCE(sort_key, 0), rowid) < (?5, ?6))
+            ORDER BY sort_key {0}, rowid {0}
+            {1}
+            "#,
+            if newest_first { "DESC" } else { "ASC" },
+            limit.map(|l| format!("LIMIT {}", l)).unwrap_or_default()
+        );
+
+        let mut stmt = conn.prepare(&query)?;
+
+        // Helper to build StoredMessage from row
+        let build_message = |row: &rusqlite::Row,
+                             contact_name: &Option<String>,
+                             contact_phone: &Option<String>,
+                             strip: bool|
+         -> rusqlite::Result<(StoredMessage, MessageCursor)> {
+            let mut raw_content_json: String = row.get(9)?;
+            let media_hash: Option<String> = row.get(14)?;
+            if let (Some(hash), false) = (&media_hash, strip) {
+                raw_content_json = Self::restore_media(&conn, &raw_content_json, hash);
+            }
+            let (content_json, content) = if strip {
+                let (content_json, mut content) = Self::strip_media_from_content(&raw_content_json);
+                // Stripped images and stickers carry their thumbnail (null
+                // if there isn't one)
+                if let (Some(_), Some(content)) = (&media_hash, content.as_mut()) {
+                    if matches!(
+                        content.get("type").and_then(|t| t.as_str()),
+                        Some("image" | "sticker")
+                    ) {
+                        content[crate::thumbnail::CONTENT_KEY] =
+                            row.get::<_, Option<String>>(18)?.into();
+                    }
+                }
+                (content_json, content)
+            } else {
+                (
+                    raw_content_json.clone(),
+                    serde_json::from_str(&raw_content_json).ok(),
+                )
+            };
+
+            let message = StoredMessage {
+                id: row.get(0)?,
+                contact_id: row.get(1)?,
+                timestamp: row.get(2)?,
+                is_from_me: row.get(3)?,
+                is_forwarded: row.get(4)?,
+                sender_name: row.get(5)?,
+                sender_phone: row.get(6)?,
+                contact_name: contact_name.clone(),
+                contact_phone: contact_phone.clone(),
+                chat_type: row.get(7)?,
+                content_type: row.get(8)?,
+                content_json,
+                content,
+                original_text: row.get(10)?,
+                translated_text: row.get(11)?,
+                source_language: row.get(12)?,
+                is_translated: row.get(13)?,
+                origin: row.get(15)?,
+                sent_by: row.get(28)?,
+                mentioned_jids: Self::mentioned_jids_from_row(row),
+                mentions_me: row.get(17)?,
+                vocabulary: Self::vocabulary_from_row(row),
+                audio: Self::audio_from_row(row),
+                sort_key: row.get(23)?,
+                triage: Self::triage_from_row(row),
+                translation_status: Self::translation_status_from_row(row),
+            };
+            let cursor = MessageCursor {
+                sort_key: message.sort_key.unwrap_or_default(),
+                rowid: row.get(26)?,
+            };
+            Ok((message, cursor))
+        };
+
+        let messages: Vec<(StoredMessage, MessageCursor)> = stmt
+            .query_map(
+                params![
+                    contact_id,
+                    before_timestamp,
+                    filter.origin,
+                    after_timestamp,
+                    before_cursor.map(|c| c.sort_key),
+                    before_cursor.map(|c| c.rowid),
+                    filter.sent_by
+                ],
+                |row| build_message(row, &contact_name, &contact_phone, strip_media),
+            )?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        // If we used DESC order with limit, reverse to get chronological order
+        if newest_first {
+            let mut messages = messages;
+            messages.reverse();
+            Ok(messages)
+        } else {
+            Ok(messages)
+        }
+    }
+
+    /// Find `query` in a chat's messages, newest first: a page of up to
+    /// `limit` matching messages older than `cursor`, the number of matches
+    /// in the whole chat, and the cursor for the next page if there is one
+    pub fn search_chat(
+        &self,
+        contact_id: &str,
+        query: &str,
+        limit: u32,
+        cursor: Option<MessageCursor>,
+    ) -> Result<ChatSearchPage> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+
+        // LIKE only folds ASCII case, so every message with text is matched here
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT id, timestamp, is_from_me, is_translated, original_text, translated_text,
+                   content_json, COALESCE(sort_key, 0), rowid
+            FROM messages
+            WHERE contact_id = ?1 AND content_type != 'Reaction'
+            ORDER BY sort_key DESC, rowid DESC
+            "#,
+        )?;
+        let mut rows = stmt.query(params![contact_id])?;
+
+        let mut page = ChatSearchPage::default();
+        let mut last_cursor = None;
+        while let Some(row) = rows.next()? {
+            let Some((text, text_source)) = chat_search::displayed_text(
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+                row.get(5)?,
+                &row.get::<_, String>(6)?,
+            ) else {
+                continue;
+            };
+            let matches = chat_search::find_matches(&text, query);
+            if matches.is_empty() {
+                continue;
+            }
+            page.total_matches += matches.len();
+
+            let message_cursor = MessageCursor {
+                sort_key: row.get(7)?,
+                rowid: row.get(8)?,
+            };
+            let after_cursor = cursor.is_none_or(|c| {
+                (message_cursor.sort_key, message_cursor.rowid) < (c.sort_key, c.rowid)
+            });
+            if !after_cursor {
+                continue;
+            }
+            if page.hits.len() == limit as usize {
+                page.next_cursor = last_cursor;
+                continue;
+            }
+            page.hits.push(ChatSearchHit {
+                message_id: row.get(0)?,
+                timestamp: row.get(1)?,
+                text_source,
+                matches,
+            });
+            last_cursor = Some(message_cursor);
+        }
+        Ok(page)
+    }
+
+    /// Get messages that mention me across all chats, newest first.
+    /// `since` is a Unix timestamp in milliseconds (exclusive).
+    pub fn get_mentions(&self, since: Option<i64>, limit: u32) -> Result<Vec<StoredMessage>> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT m.id, m.contact_id, m.timestamp, m.is_from_me, m.is_forwarded, m.sender_name,
+                   m.sender_phone, m.chat_type, m.content_type, m.content_json, m.original_text,
+                   m.translated_text, m.source_language, m.is_translated,
+                   c.name as contact_name, c.phone as contact_phone, m.origin,
+                   m.mentioned_jids, m.mentions_me, m.sent_by
+            FROM messages m
+            LEFT JOIN contacts c ON m.contact_id = c.id
+            WHERE m.mentions_me = 1
+              AND (?1 IS NULL OR m.timestamp > ?1)
+            ORDER BY m.timestamp DESC
+            LIMIT ?2
+            "#,
+        )?;
+
+        let messages = stmt
+            .query_map(params![since, limit], |row| {
+                let contact_name: Option<String> = row.get(14)?;
+                let contact_phone: Option<String> = row.get(15)?;
+                Self::row_to_stored_message(row, contact_name, contact_phone)
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(messages)
+    }
+
+    /// Get incoming messages triaged at `urgency` or above across all chats,
+    /// newest first, optionally only those in a tone. `since` is a Unix
+    /// timestamp in milliseconds (exclusive).
+    pub fn get_triaged_messages(
+        &self,
+        urgency: Urgency,
+        tone: Option<Tone>,
+        since: Option<i64>,
+        limit: u32,
+    ) -> Result<Vec<StoredMessage>> {
+        let conn = self.conn.lock().unwrap();
+        let levels: Vec<&str> = [Urgency::Low, Urgency::Normal, Urgency::High]
+            .into_iter()
+            .filter(|level| *level >= urgency)
+            .map(Urgency::as_str)
+            .collect();
+
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT m.id, m.contact_id, m.timestamp, m.is_from_me, m.is_forwarded, m.sender_name,
+                   m.sender_phone, m.chat_type, m.content_type, m.content_json, m.original_text,
+                   m.translated_text, m.source_language, m.is_translated,
+                   c.name as contact_name, c.phone as contact_phone, c.type as contact_type,
+                   m.origin, m.mentioned_jids, m.mentions_me, m.sort_key, m.urgency, m.tone,
+                   m.translation_status, m.sent_by
+            FROM messages m
+            LEFT JOIN contacts c ON m.contact_id = c.id
+            WHERE m.is_from_me = 0
+              AND m.urgency IN (SELECT value FROM json_each(?1))
+              AND (?2 IS NULL OR m.tone = ?2)
+              AND (?3 IS NULL OR m.timestamp > ?3)
+            ORDER BY m.timestamp DESC
+            LIMIT ?4
+            "#,
+        )?;
+
+        let messages = stmt
+            .query_map(
+                params![
+                    serde_json::to_string(&levels)?,
+                    tone.map(Tone::as_str),
+                    since,
+                    limit
+                ],
+                |row| {
+                    let contact_type: Option<String> = row.get(16)?;
+                    let contact_name =
+                        Self::contact_display_name(row.get(14)?, contact_type.as_deref());
+                    Self::row_to_stored_message(row, contact_name, row.get(15)?)
+                },
+            )?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(messages)
+    }
+
+    /// Get a contact by ID
+    pub fn get_contact(&self, contact_id: &str) -> Result<Option<StoredContact>> {
+        let conn = self.conn.lock().unwrap();
+        let data_version: i64 = conn.query_row("PRAGMA data_version", [], |row| row.get(0))?;
+        self.contact_cache.sync_data_version(data_version);
+        if let Some(contact) = self.contact_cache.get(contact_id) {
+            return Ok(Some(contact));
+        }
+        let requested_id = contact_id;
+        let contact_id = Self::resolve_id(&conn, contact_id);
+
+        let mut stmt = conn.prepare(&format!(
+            r#"
+            SELECT 
+                c.id, c.name, c.phone, c.type, c.last_message_time, c.unread_count, c.pinned_at,
+                m.content_json, m.content_type, m.is_from_me, {},
+                c.mentions_only, c.created_at, c.updated_at, c.last_seen, c.last_read_timestamp,
+                c.description, c.ephemeral_duration, c.placeholder
+            FROM contacts c
+            LEFT JOIN (
+                SELECT contact_id, content_json, content_type, is_from_me,
+                       ROW_NUMBER() OVER (PARTITION BY contact_id ORDER BY sort_key DESC, rowid DESC) as rn
+                FROM messages
+                WHERE content_type != 'system'
+            ) m ON m.contact_id = c.id AND m.rn = 1
+            WHERE c.id = ?
+            "#,
+            OUTGOING_TRANSLATION_SQL
+        ))?;
+
+        let contact = stmt
+            .query_row(params![contact_id], |row| {
+                let content_json: Option<String> = row.get(7)?;
+                let content_type: Option<String> = row.get(8)?;
+                let is_from_me: Option<bool> = row.get(9)?;
+
+                let preview = Self::generate_message_preview(
+                    content_json.as_deref(),
+                    content_type.as_deref(),
+                    is_from_me.unwrap_or(false),
+                );
+
+                let contact_type: Option<String> = row.get(3)?;
+                Ok(StoredContact {
+                    id: row.get(0)?,
+                    name: Self::contact_display_name(row.get(1)?, contact_type.as_deref()),
+                    phone: row.get(2)?,
+                    contact_type,
+                    last_message_time: row.get(4)?,
+                    unread_count: row.get(5)?,
+                    pinned_at: row.get(6)?,
+                    last_message_preview: preview,
+                    auto_translate_outgoing: row.get(10)?,
+                    mentions_only: row.get(11)?,
+                    created_at: row.get(12)?,
+                    updated_at: row.get(13)?,
+                    last_seen: row.get(14)?,
+                    last_read_timestamp: row.get(15)?,
+                    description: row.get(16)?,
+                    ephemeral_duration: row.get(17)?,
+                    placeholder: row.get(18)?,
+                })
+            })
+            .ok();
+
+        // Only lookups by the canonical ID are cached, which is the ID
+        // writes invalidate
+        if let Some(contact) = contact.as_ref().filter(|c| c.id == requested_id) {
+            self.contact_cache.insert(contact.clone());
+        }
+        Ok(contact)
+    }
+
+    /// How well `get_contact`'s cache is doing
+    pub fn contact_cache_stats(&self) -> ContactCacheStats {
+        self.contact_cache.stats()
+    }
+
+    /// Sizes of the database files and its free space
+    pub fn database_sizes(&self) -> Result<DatabaseSizes> {
+        let conn = self.conn.lock().unwrap();
+        let file_size = |suffix: &str| {
+            conn.path()
+                .and_then(|path| std::fs::metadata(format!("{}{}", path, suffix)).ok())
+                .map_or(0, |metadata| metadata.len())
+        };
+        let page_size: u64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+        let free_pages: u64 = conn.query_row("PRAGMA freelist_count", [], |row| row.get(0))?;
+        let auto_vacuum: i64 = conn.query_row("PRAGMA auto_vacuum", [], |row| row.get(0))?;
+        Ok(DatabaseSizes {
+            database_bytes: file_size(""),
+            wal_bytes: file_size("-wal"),
+            free_bytes: free_pages * page_size,
+            // 2 is INCREMENTAL
+            incremental_vacuum: auto_vacuum == 2,
+        })
+    }
+
+    /// Copy the write-ahead log into the database and truncate it. False if
+    /// a reader in another process kept it from finishing.
+    pub fn checkpoint_wal(&self) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let busy: i64 = conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| row.get(0))?;
+        Ok(busy == 0)
+    }
+
+    /// Give back up to `max_pages` free pages, returning the bytes freed.
+    /// A database that can't be vacuumed incrementally is converted first
+    /// (see `convert_to_incremental_vacuum`), which gives back all of them.
+    pub fn incremental_vacuum(&self, max_pages: u32) -> Result<u64> {
+        let sizes = self.database_sizes()?;
+        if !sizes.incremental_vacuum {
+            return self.convert_to_incremental_vacuum(&sizes);
+        }
+
+        let conn = self.conn.lock().unwrap();
+        let page_size: u64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+        let free_pages = |conn: &Connection| -> Result<u64> {
+            Ok(conn.query_row("PRAGMA freelist_count", [], |row| row.get(0))?)
+        };
+        let before = free_pages(&conn)?;
+        conn.execute_batch(&format!("PRAGMA incremental_vacuum({});", max_pages))?;
+        Ok(before.saturating_sub(free_pages(&conn)?) * page_size)
+    }
+
+    /// Convert the database to incremental vacuum with a full VACUUM,
+    /// returning the bytes freed. The rewrite needs room for a copy of the
+    /// database, so it's skipped (freeing nothing) unless the disk has that
+    /// on top of the space kept free. It runs on a connection of its own, so
+    /// the shared one isn't held while it does.
+    fn convert_to_incremental_vacuum(&self, sizes: &DatabaseSizes) -> Result<u64> {
+        let path = {
+            let conn = self.conn.lock().unwrap();
+            conn.path()
+                .map(PathBuf::from)
+                .context("The database isn't a file")?
+        };
+        let needed =
+            sizes.database_bytes + sizes.wal_bytes + self.write_protection.status().min_free_bytes;
+        let free = path.parent().and_then(crate::disk_guard::free_bytes);
+        if free.is_none_or(|free| free <= needed) {
+            info!(
+                "Not converting the database to incremental vacuum: {:?} bytes free, {} needed",
+                free, needed
+            );
+            return Ok(0);
+        }
+
+        info!("Converting the database to incremental vacuum...");
+        let conn = Connection::open(&path)?;
+        conn.busy_timeout(std::time::Duration::from_secs(5))?;
+        conn.execute_batch("PRAGMA auto_vacuum=INCREMENTAL; VACUUM;")
+            .context("Failed to convert the database to incremental vacuum")?;
+        info!("Database converted to incremental vacuum");
+        Ok(sizes.free_bytes)
+    }
+
+    /// Refresh the statistics the query planner works from
+    pub fn analyze(&self) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute_batch("ANALYZE;")?;
+        Ok(())
+    }
+
+    pub fn get_storage_maintenance_times(&self) -> Result<StorageMaintenanceTimes> {
+        let conn = self.conn.lock().unwrap();
+        Ok(Self::read_setting(&conn, STORAGE_MAINTENANCE_SETTING)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default())
+    }
+
+    pub fn set_storage_maintenance_times(&self, times: &StorageMaintenanceTimes) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        Self::write_setting(
+            &conn,
+            STORAGE_MAINTENANCE_SETTING,
+            Some(&serde_json::to_string(times)?),
+        )
+    }
+
+    /// Get database statistics
+    pub fn get_stats(&self) -> Result<(i64, i64)> {
+        let conn = self.conn.lock().unwrap();
+
+        let message_count: i64 =
+            conn.query_row("SELECT COUNT(*) FROM messages", [], |row| row.get(0))?;
+
+        let contact_count: i64 =
+            conn.query_row("SELECT COUNT(*) FROM contacts", [], |row| row.get(0))?;
+
+        Ok((message_count, contact_count))
+    }
+
+    /// Messages and AI usage between two timestamps (ms, end exclusive),
+    /// with the `top` busiest chats
+    pub fn get_activity_stats(&self, from: i64, to: i64, top: usize) -> Result<ActivityStats> {
+        self.flush_usage();
+        let conn = self.conn.lock().unwrap();
+
+        let (messages_received, messages_sent, messages_translated) = conn.query_row(
+            r#"
+            SELECT COALESCE(SUM(is_from_me = 0), 0), COALESCE(SUM(is_from_me = 1), 0),
+                   COALESCE(SUM(is_from_me = 0 AND is_translated = 1), 0)
+            FROM messages
+            WHERE timestamp >= ?1 AND timestamp < ?2 AND content_type != 'system'
+            "#,
+            params![from, to],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)? as u64,
+                    row.get::<_, i64>(1)? as u64,
+                    row.get::<_, i64>(2)? as u64,
+                ))
+            },
+        )?;
+
+        // Usage is timestamped in seconds
+        let (translation_calls, input_tokens, output_tokens, translation_cost_usd) = conn
+            .query_row(
+                r#"
+                SELECT COUNT(*), COALESCE(SUM(input_tokens), 0),
+                       COALESCE(SUM(output_tokens), 0), COALESCE(SUM(cost_usd), 0.0)
+                FROM translation_usage
+                WHERE timestamp >= ?1 AND timestamp < ?2
+                "#,
+                params![from.div_euclid(1000), to.div_euclid(1000)],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)? as u64,
+                        row.get::<_, i64>(1)? as u64,
+                        row.get::<_, i64>(2)? as u64,
+                        row.get::<_, f64>(3)?,
+                    ))
+                },
+            )?;
+
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT m.contact_id, c.name, c.type, COUNT(*) AS n
+            FROM messages m
+            LEFT JOIN contacts c ON c.id = m.contact_id
+            WHERE m.timestamp >= ?1 AND m.timestamp < ?2 AND m.content_type != 'system'
+            GROUP BY m.contact_id
+            ORDER BY n DESC, m.contact_id
+            LIMIT ?3
+            "#,
+        )?;
+        let top_chats = stmt
+            .query_map(params![from, to, top as i64], |row| {
+                let contact_type: Option<String> = row.get(2)?;
+                Ok(ChatActivity {
+                    contact_id: row.get(0)?,
+                    name: Self::contact_display_name(row.get(1)?, contact_type.as_deref()),
+                    messages: row.get::<_, i64>(3)? as u64,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let database_bytes: i64 = conn.query_row(
+            "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+            [],
+            |row| row.get(0),
+        )?;
+
+        Ok(ActivityStats {
+            messages_received,
+            messages_sent,
+            messages_translated,
+            translation_calls,
+            input_tokens,
+            output_tokens,
+            translation_cost_usd,
+            top_chats,
+            database_bytes: database_bytes as u64,
+        })
+    }
+
+    /// Save a generated report, replacing an earlier one for the same week
+    /// but keeping where it was delivered
+    pub fn save_report(&self, report: &StoredReport) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            r#"
+            INSERT INTO reports (week, period_start, period_end, generated_at, markdown, stats_json)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+            ON CONFLICT(week) DO UPDATE SET
+                period_start = excluded.period_start,
+                period_end = excluded.period_end,
+                generated_at = excluded.generated_at,
+                markdown = excluded.markdown,
+                stats_json = excluded.stats_json
+            "#,
+            params![
+                report.week,
+                report.period_start,
+                report.period_end,
+                report.generated_at,
+                report.markdown,
+                report.stats.to_string()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Record where a week's report was delivered
+    pub fn mark_report_delivered(&self, week: &str, delivered_to: &str, at: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE reports SET delivered_to = ?2, delivered_at = ?3 WHERE week = ?1",
+            params![week, delivered_to, at],
+        )?;
+        Ok(())
+    }
+
+    fn row_to_report(row: &rusqlite::Row) -> rusqlite::Result<StoredReport> {
+        let stats: String = row.get(5)?;
+        Ok(StoredReport {
+            week: row.get(0)?,
+            period_start: row.get(1)?,
+            period_end: row.get(2)?,
+            generated_at: row.get(3)?,
+            markdown: row.get(4)?,
+            stats: serde_json::from_str(&stats).unwrap_or_default(),
+            delivered_to: row.get(6)?,
+            delivered_at: row.get(7)?,
+        })
+    }
+
+    /// A week's saved report
+    pub fn get_report(&self, week: &str) -> Result<Option<StoredReport>> {
+        let conn = self.conn.lock().unwrap();
+        let report = conn
+            .query_row(
+                r#"
+                SELECT week, period_start, period_end, generated_at, markdown, stats_json,
+                       delivered_to, delivered_at
+                FROM reports WHERE week = ?
+                "#,
+                params![week],
+                Self::row_to_report,
+            )
+            .optional()?;
+        Ok(report)
+    }
+
+    /// Saved reports, latest week first
+    pub fn list_reports(&self) -> Result<Vec<StoredReport>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT week, period_start, period_end, generated_at, markdown, stats_json,
+                   delivered_to, delivered_at
+            FROM reports ORDER BY period_start DESC
+            "#,
+        )?;
+        let reports = stmt
+            .query_map([], Self::row_to_report)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(reports)
+    }
+
+    /// Save a background job's record
+    pub fn save_job(&self, job: &JobRecord) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            r#"
+            INSERT INTO jobs (id, kind, state, progress_json, result, error, created_at,
+                              started_at, finished_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            ON CONFLICT(id) DO UPDATE SET
+                state = excluded.state,
+                progress_json = excluded.progress_json,
+                result = excluded.result,
+                error = excluded.error,
+                started_at = excluded.started_at,
+                finished_at = excluded.finished_at
+            "#,
+            params![
+                job.id,
+                job.kind,
+                job.state.as_str(),
+                serde_json::to_string(&job.progress)?,
+                job.result,
+                job.error,
+                job.created_at,
+                job.started_at,
+                job.finished_at
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn row_to_job(row: &rusqlite::Row) -> rusqlite::Result<JobRecord> {
+        let state: String = row.get(2)?;
+        let progress: String = row.get(3)?;
+        Ok(JobRecord {
+            id: row.get(0)?,
+            kind: row.get(1)?,
+            state: JobState::parse(&state).unwrap_or(JobState::Failed),
+            progress: serde_json::from_str(&progress).unwrap_or_default(),
+            result: row.get(4)?,
+            error: row.get(5)?,
+            created_at: row.get(6)?,
+            started_at: row.get(7)?,
+            finished_at: row.get(8)?,
+        })
+    }
+
+    pub fn get_job(&self, id: &str) -> Result<Option<JobRecord>> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn
+            .query_row(
+                r#"
+                SELECT id, kind, state, progress_json, result, error, created_at,
+                       started_at, finished_at
+                FROM jobs WHERE id = ?
+                "#,
+                params![id],
+                Self::row_to_job,
+            )
+            .optional()?)
+    }
+
+    /// The `limit` most recently queued jobs saved, newest first
+    pub fn get_recent_jobs(&self, limit: usize) -> Result<Vec<JobRecord>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT id, kind, state, progress_json, result, error, created_at,
+                   started_at, finished_at
+            FROM jobs ORDER BY created_at DESC, rowid DESC LIMIT ?
+            "#,
+        )?;
+        let jobs = stmt
+            .query_map(params![limit as i64], Self::row_to_job)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(jobs)
+    }
+
+    /// Get the predominant language of a contact's incoming messages from the
+    /// cache on the contact row, recomputing it if it has been invalidated.
+    pub fn get_cached_conversation_language(&self, contact_id: &str) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+        Self::cached_conversation_language(&conn, &contact_id)
+    }
+
+    fn cached_conversation_language(conn: &Connection, contact_id: &str) -> Result<Option<String>> {
+        let cached: Option<(Option<String>, Option<i64>)> = conn
+            .query_row(
+                "SELECT conversation_language, language_message_count FROM contacts WHERE id = ?",
+                params![contact_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        match cached {
+            Some((language, Some(_))) => Ok(language),
+            _ => Ok(Self::recompute_conversation_language(conn, contact_id)?),
+        }
+    }
+
+    /// Contacts with messages since `since_ms`, in ID order
+    pub fn get_active_contact_ids(&self, since_ms: i64) -> Result<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT contact_id FROM messages WHERE timestamp >= ? ORDER BY contact_id",
+        )?;
+        let ids = stmt
+            .query_map(params![since_ms], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(ids)
+    }
+
+    /// Of a contact's `limit` most recent incoming text messages long enough
+    /// to detect a language in, those with no language yet (ID, text)
+    pub fn get_undetected_language_sample(
+        &self,
+        contact_id: &str,
+        limit: usize,
+        min_len: usize,
+    ) -> Result<Vec<(String, String)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT id, original_text, source_language FROM messages
+            WHERE contact_id = ?1
+              AND is_from_me = 0
+              AND content_type = 'Text'
+              AND length(trim(original_text)) >= ?2
+            ORDER BY timestamp DESC
+            LIMIT ?3
+            "#,
+        )?;
+        let sample = stmt
+            .query_map(params![contact_id, min_len as i64, limit as i64], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(sample
+            .into_iter()
+            .filter(|(_, _, language)| language.as_deref().unwrap_or_default().is_empty())
+            .map(|(id, text, _)| (id, text))
+            .collect())
+    }
+
+    /// Record the language detected for an incoming message that has none,
+    /// without marking it translated
+    pub fn set_detected_language(&self, message_id: &str, language: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let contact_id: Option<String> = conn
+            .query_row(
+                r#"
+                UPDATE messages SET source_language = ?1
+                WHERE id = ?2 AND (source_language IS NULL OR source_language = '')
+                RETURNING contact_id
+                "#,
+                params![language, message_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(contact_id) = contact_id {
+            Self::count_conversation_language(&conn, &contact_id, language)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_language_seeding_progress(&self) -> Result<LanguageSeedingProgress> {
+        let conn = self.conn.lock().unwrap();
+        Ok(Self::read_setting(&conn, LANGUAGE_SEEDING_SETTING)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default())
+    }
+
+    pub fn set_language_seeding_progress(&self, progress: &LanguageSeedingProgress) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        Self::write_setting(
+            &conn,
+            LANGUAGE_SEEDING_SETTING,
+            Some(&serde_json::to_string(progress)?),
+        )
+    }
+
+    /// Find the predominant incoming language (the most common source_language,
+    /// ties going to the language seen most recently) and how many messages use it
+    fn query_conversation_language(
+        conn: &Connection,
+        contact_id: &str,
+    ) -> rusqlite::Result<Option<(String, i64)>> {
+        conn.query_row(
+            r#"
+            SELECT source_language, COUNT(*) as cnt
+            FROM messages 
+            WHERE contact_id = ? 
+              AND is_from_me = 0 
+              AND source_language IS NOT NULL
+              AND source_language != ''
+            GROUP BY source_language
+            ORDER BY cnt DESC, MAX(rowid) DESC
+            LIMIT 1
+            "#,
+            params![contact_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+    }
+
+    /// Recompute a contact's cached conversation language from its messages
+    fn recompute_conversation_language(
+        conn: &Connection,
+        contact_id: &str,
+    ) -> rusqlite::Result<Option<String>> {
+        let (language, count) = match Self::query_conversation_language(conn, contact_id)? {
+            Some((language, count)) => (Some(language), count),
+            None => (None, 0),
+        };
+        conn.execute(
+            "UPDATE contacts SET conversation_language = ?1, language_message_count = ?2 WHERE id = ?3",
+            params![language, count, contact_id],
+        )?;
+        Ok(language)
+    }
+
+    /// Update the cached conversation language for a newly stored incoming
+    /// message in `language`. Only that language's count changes, so it takes
+    /// over if it now has at least as many messages as the cached one (a tie
+    /// goes to the language seen most recently, which it now is).
+    fn count_conversation_language(
+        conn: &Connection,
+        contact_id: &str,
+        language: &str,
+    ) -> rusqlite::Result<()> {
+        let cached: Option<(Option<String>, Option<i64>)> = conn
+            .query_row(
+                "SELECT conversation_language, language_message_count FROM contacts WHERE id = ?",
+                params![contact_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        // No contact row yet, or a stale cache: the next read recomputes it
+        let Some((cached_language, Some(cached_count))) = cached else {
+            return Ok(());
+        };
+
+        if cached_language.as_deref() == Some(language) {
+            conn.execute(
+                "UPDATE contacts SET language_message_count = language_message_count + 1 WHERE id = ?",
+                params![contact_id],
+            )?;
+            return Ok(());
+        }
+
+        let count: i64 = conn.query_row(
+            r#"
+            SELECT COUNT(*) FROM messages
+            WHERE contact_id = ? AND is_from_me = 0 AND source_language = ?
+            "#,
+            params![contact_id, language],
+            |row| row.get(0),
+        )?;
+        if count >= cached_count {
+            conn.execute(
+                "UPDATE contacts SET conversation_language = ?1, language_message_count = ?2 WHERE id = ?3",
+                params![language, count, contact_id],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Mark a contact's cached conversation language as stale
+    fn invalidate_language_cache(conn: &Connection, contact_id: &str) -> rusqlite::Result<()> {
+        conn.execute(
+            "UPDATE contacts SET conversation_language = NULL, language_message_count = NULL WHERE id = ?",
+            params![contact_id],
+        )?;
+        Ok(())
+    }
+
+    /// Record translation usage for a message.
+    /// The record is queued for the background usage writer; if its queue is
+    /// full the record is written inline instead.
+    pub fn record_usage(
+        &self,
+        contact_id: Option<&str>,
+        message_id: Option<&str>,
+        usage: &UsageInfo,
+        operation: &str,
+    ) -> Result<()> {
+        let record = UsageRecord {
+            contact_id: contact_id.map(str::to_string),
+            message_id: message_id.map(str::to_string),
+            usage: usage.clone(),
+            operation: operation.to_string(),
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64,
+        };
+
+        if let Err(record) = self.usage_writer.send(record) {
+            warn!("Usage writer queue is full, recording usage inline");
+            let result = retry_when_busy(|| {
+                let conn = self.conn.lock().unwrap();
+                Ok(Self::insert_usage(&conn, &record, None)?)
+            });
+            self.spill_on_failure(result, || record.spilled())?;
+        }
+
+        Ok(())
+    }
+
+    /// Write queued usage records to the database. Readers of usage call this
+    /// first so their numbers include everything recorded so far.
+    pub fn flush_usage(&self) {
+        self.usage_writer.flush();
+    }
+
+    /// Insert a usage record.
+    /// Each timed API call gets its own row (with model and latency); any
+    /// usage not attributed to a call is stored in an untimed row. Rows of a
+    /// replayed spilled write (`spill_id`) that are already stored are skipped.
+    fn insert_usage(
+        conn: &Connection,
+        record: &UsageRecord,
+        spill_id: Option<&str>,
+    ) -> rusqlite::Result<()> {
+        let usage = &record.usage;
+        let contact_id = record
+            .contact_id
+            .as_deref()
+            .map(|id| Self::resolve_id(conn, id));
+        let row_spill_id = |row: usize| spill_id.map(|id| format!("{}:{}", id, row));
+
+        let mut stmt = conn.prepare_cached(if spill_id.is_some() {
+            r#"
+            INSERT OR IGNORE INTO translation_usage 
+            (contact_id, message_id, timestamp, input_tokens, output_tokens, cost_usd, operation,
+             latency_ms, model, provider, spill_id)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+            "#
+        } else {
+            r#"
+            INSERT INTO translation_usage 
+            (contact_id, message_id, timestamp, input_tokens, output_tokens, cost_usd, operation,
+             latency_ms, model, provider, spill_id)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+            "#
+        })?;
+
+        for (row, call) in usage.calls.iter().enumerate() {
+            stmt.execute(params![
+                contact_id,
+                record.message_id,
+                record.timestamp,
+                call.input_tokens,
+                call.output_tokens,
+                call.cost_usd,
+                record.operation,
+                call.latency_ms as i64,
+                call.model,
+                call.provider.as_str(),
+                row_spill_id(row),
+            ])?;
+        }
+
+        let input_tokens = usage
+            .input_tokens
+            .saturating_sub(usage.calls.iter().map(|c| c.input_tokens).sum());
+        let output_tokens = usage
+            .output_tokens
+            .saturating_sub(usage.calls.iter().map(|c| c.output_tokens).sum());
+        let cost_usd = usage.cost_usd - usage.calls.iter().map(|c| c.cost_usd).sum::<f64>();
+
+        if usage.calls.is_empty() || input_tokens > 0 || output_tokens > 0 || cost_usd > 1e-9 {
+            stmt.execute(params![
+                contact_id,
+                record.message_id,
+                record.timestamp,
+                input_tokens,
+                output_tokens,
+                cost_usd.max(0.0),
+                record.operation,
+                None::<i64>,
+                None::<String>,
+                None::<String>,
+                row_spill_id(usage.calls.len()),
+            ])?;
+        }
+
+        Ok(())
+    }
+
+    /// Get latency percentiles for timed API calls, grouped by operation, by
+    /// model and by provider. Only the most recent `max_rows` calls are
+    /// considered.
+    pub fn get_performance_stats(&self, max_rows: usize) -> Result<PerformanceStats> {
+        self.flush_usage();
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT operation, model, provider, latency_ms
+            FROM translation_usage
+            WHERE latency_ms IS NOT NULL
+            ORDER BY timestamp DESC, id DESC
+            LIMIT ?
+            "#,
+        )?;
+
+        let rows: Vec<(String, Option<String>, Option<String>, i64)> = stmt
+            .query_map(params![max_rows as i64], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        let mut by_operation: std::collections::BTreeMap<String, Vec<i64>> = Default::default();
+        let mut by_model: std::collections::BTreeMap<String, Vec<i64>> = Default::default();
+        let mut by_provider: std::collections::BTreeMap<String, Vec<i64>> = Default::default();
+        for (operation, model, provider, latency_ms) in rows {
+            by_operation.entry(operation).or_default().push(latency_ms);
+            by_model
+                .entry(model.unwrap_or_else(|| "unknown".to_string()))
+                .or_default()
+                .push(latency_ms);
+            by_provider
+                .entry(provider.unwrap_or_else(|| "unknown".to_string()))
+                .or_default()
+                .push(latency_ms);
+        }
+
+        let summarize = |groups: std::collections::BTreeMap<String, Vec<i64>>| {
+            groups
+                .into_iter()
+                .map(|(key, latencies)| LatencyStats::from_samples(key, latencies))
+                .collect()
+        };
+
+        Ok(PerformanceStats {
+            by_operation: summarize(by_operation),
+            by_model: summarize(by_model),
+            by_provider: summarize(by_provider),
+        })
+    }
+
+    /// Count the distinct messages an operation has been recorded for since a
+    /// Unix timestamp (seconds)
+    pub fn count_usage_messages_since(&self, operation: &str, since_secs: i64) -> Result<u32> {
+        self.flush_usage();
+        let conn = self.conn.lock().unwrap();
+
+        let count: i64 = conn.query_row(
+            r#"
+            SELECT COUNT(DISTINCT message_id) FROM translation_usage
+            WHERE operation = ? AND timestamp >= ?
+            "#,
+            params![operation, since_secs],
+            |row| row.get(0),
+        )?;
+
+        Ok(count as u32)
+    }
+
+    /// Get total usage across all conversations
+    pub fn get_global_usage(&self) -> Result<UsageInfo> {
+        self.flush_usage();
+        let conn = self.conn.lock().unwrap();
+
+        let result = conn.query_row(
+            r#"
+            SELECT COALESCE(SUM(input_tokens), 0), 
+                   COALESCE(SUM(output_tokens), 0), 
+                   COALESCE(SUM(cost_usd), 0.0)
+            FROM translation_usage
+            "#,
+            [],
+            |row| {
+                Ok(UsageInfo {
+                    input_tokens: row.get::<_, i64>(0)? as u32,
+                    output_tokens: row.get::<_, i64>(1)? as u32,
+                    cost_usd: row.get(2)?,
+                    calls: Vec::new(),
+                })
+            },
+        )?;
+
+        Ok(result)
+    }
+
+    /// Get usage for a specific conversation
+    pub fn get_conversation_usage(&self, contact_id: &str) -> Result<UsageInfo> {
+        self.flush_usage();
+        let conn = self.conn.lock().unwrap();
+        let contact_id = Self::resolve_id(&conn, contact_id);
+
+        let result = conn.query_row(
+            r#"
+            SELECT COALESCE(SUM(input_tokens), 0), 
+                   COALESCE(SUM(output_tokens), 0), 
+                   COALESCE(SUM(cost_usd), 0.0)
+            FROM translation_usage
+            WHERE contact_id = ?
+            "#,
+            params![contact_id],
+            |row| {
+                Ok(UsageInfo {
+                    input_tokens: row.get::<_, i64>(0)? as u32,
+                    output_tokens: row.get::<_, i64>(1)? as u32,
+                    cost_usd: row.get(2)?,
+                    calls: Vec::new(),
+                })
+            },
+        )?;
+
+        Ok(result)
+    }
+
+    /// Get the summed translation usage of each message, in one query.
+    /// Messages nothing was spent on are left out.
+    pub fn get_message_costs(&self, message_ids: &[&str]) -> Result<HashMap<String, UsageInfo>> {
+        if message_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        self.flush_usage();
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT message_id, SUM(input_tokens), SUM(output_tokens), SUM(cost_usd)
+            FROM translation_usage
+            WHERE message_id IN (SELECT value FROM json_each(?1))
+            GROUP BY message_id
+            "#,
+        )?;
+        let costs = stmt
+            .query_map(params![serde_json::to_string(message_ids)?], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    UsageInfo {
+                        input_tokens: row.get::<_, i64>(1)? as u32,
+                        output_tokens: row.get::<_, i64>(2)? as u32,
+                        cost_usd: row.get(3)?,
+                        calls: Vec::new(),
+                    },
+                ))
+            })?
+            .collect::<rusqlite::Result<HashMap<_, _>>>()?;
+
+        Ok(costs)
+    }
+
+    /// Record an MCP tool call: whether it sent a message and what
+    /// translation it paid for
+    pub fn record_mcp_client_usage(
+        &self,
+        client_id: &str,
+        tool: &str,
+        sent: bool,
+        cost_usd: f64,
+        timestamp: i64,
+    ) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            r#"
+            INSERT INTO mcp_client_usage (client_id, tool, sent, cost_usd, timestamp)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            "#,
+            params![client_id, tool, sent, cost_usd, timestamp],
+        )?;
+        Ok(())
+    }
+
+    /// Messages an MCP client has sent and translation it has paid for
+    /// (USD) since a Unix timestamp (seconds)
+    pub fn get_mcp_client_usage_since(
+        &self,
+        client_id: &str,
+        since_secs: i64,
+    ) -> Result<(u32, f64)> {
+        let conn = self.conn.lock().unwrap();
+        let (sends, cost): (i64, f64) = conn.query_row(
+            r#"
+            SELECT COALESCE(SUM(sent), 0), COALESCE(SUM(cost_usd), 0.0)
+            FROM mcp_client_usage
+            WHERE client_id = ?1 AND timestamp >= ?2
+            "#,
+            params![client_id, since_secs],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok((sends as u32, cost))
+    }
+
+    /// Every MCP client that holds a token or has called a tool, most
+    /// recently active first, with usage since `today_secs` counted as today's
+    pub fn list_mcp_clients(&self, today_secs: i64) -> Result<Vec<McpClientUsage>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            r#"
+            SELECT c.client_id,
+                   COUNT(u.id),
+                   COALESCE(SUM(u.sent), 0),
+                   COALESCE(SUM(u.cost_usd), 0.0),
+                   COALESCE(SUM(CASE WHEN u.timestamp >= ?1 THEN u.sent ELSE 0 END), 0),
+                   COALESCE(SUM(CASE WHEN u.timestamp >= ?1 THEN u.cost_usd ELSE 0.0 END), 0.0),
+                   MAX(u.timestamp)
+            FROM (
+                SELECT client_id FROM mcp_client_usage
+                UNION
+                SELECT client_id FROM oauth_access_tokens
+            ) c
+            LEFT JOIN mcp_client_usage u ON u.client_id = c.client_id
+            GROUP BY c.client_id
+            ORDER BY MAX(u.timestamp) DESC NULLS LAST, c.client_id
+            "#,
+        )?;
+        let rows = stmt
+            .query_map(params![today_secs], |row| {
+                Ok(McpClientUsage {
+                    client_id: row.get(0)?,
+                    tool_calls: row.get(1)?,
+                    sends: row.get(2)?,
+                    translation_cost_usd: row.get(3)?,
+                    sends_today: row.get::<_, i64>(4)? as u32,
+                    translation_cost_today_usd: row.get(5)?,
+                    last_activity: row.get(6)?,
+                    quota: McpQuota::default(),
+                    remaining_sends: None,
+                    remaining_translation_usd: None,
+                    anonymized: false,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        rows.into_iter()
+            .map(|mut client| {
+                let quota = Self::read_mcp_quota(&conn, &client.client_id)?;
+                client.remaining_sends = quota
+                    .daily_sends
+                    .map(|limit| limit.saturating_sub(client.sends_today));
+                client.remaining_translation_usd = quota
+                    .daily_translation_usd
+                    .map(|budget| (budget - client.translation_cost_today_usd).max(0.0));
+                client.quota = quota;
+                client.anonymized = Self::read_setting(
+                    &conn,
+                    &format!("{}:{}", MCP_ANONYMIZED_SETTING, client.client_id),
+                )?
+                .is_some();
+                Ok(client)
+            })
+            .collect()
+    }
+
+    /// The daily quota for an MCP client: its own limits where set, else the defaults
+    pub fn get_mcp_quota(&self, client_id: &str) -> Result<McpQuota> {
+        let conn = self.conn.lock().unwrap();
+        Self::read_mcp_quota(&conn, client_id)
+    }
+
+    /// Set the default MCP quota (`client_id` None) or one client's
+    /// overrides. Unset limits are removed, so a client falls back to the
+    /// default and the default becomes unlimited.
+    pub fn set_mcp_quota(&self, client_id: Option<&str>, quota: &McpQuota) -> Result<()> {
+        let key = |setting: &str| match client_id {
+            Some(client_id) => format!("{}:{}", setting, client_id),
+            None => setting.to_string(),
+        };
+        let conn = self.conn.lock().unwrap();
+        Self::write_setting(
+            &conn,
+            &key(MCP_DAILY_SENDS_SETTING),
+            quota.daily_sends.map(|v| v.to_string()).as_deref(),
+        )?;
+        Self::write_setting(
+            &conn,
+            &key(MCP_DAILY_TRANSLATION_USD_SETTING),
+            quota
+                .daily_translation_usd
+                .map(|v| v.to_string())
+                .as_deref(),
+        )?;
+        Ok(())
+    }
+
+    /// The salt an anonymized MCP client's data is pseudonymized with, or
+    /// None if the client sees real data
+    pub fn get_mcp_anonymization_salt(&self, client_id: &str) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        Self::read_setting(&conn, &format!("{}:{}", MCP_ANONYMIZED_SETTING, client_id))
+    }
+
+    /// Flag an MCP client as anonymized or not. A client keeps its salt
+    /// while flagged, so the placeholders it has seen stay valid.
+    pub fn set_mcp_client_anonymized(&self, client_id: &str, anonymized: bool) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let key = format!("{}:{}", MCP_ANONYMIZED_SETTING, client_id);
+        if !anonymized {
+            return Self::write_setting(&conn, &key, None);
+        }
+        if Self::read_setting(&conn, &key)?.is_none() {
+            Self::write_setting(&conn, &key, Some(&uuid::Uuid::new_v4().to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// The chat access list an MCP client is held to: its own, else the
+    /// default. None if every chat is open to it.
+    pub fn get_mcp_access(&self, client_id: &str) -> Result<Option<ChatAccessList>> {
+        let conn = self.conn.lock().unwrap();
+        let list =
+            match Self::read_setting(&conn, &format!("{}:{}", MCP_ACCESS_SETTING, client_id))? {
+                Some(json) => Some(json),
+                None => Self::read_setting(&conn, MCP_ACCESS_SETTING)?,
+            };
+        list.map(|json| serde_json::from_str(&json).context("Invalid saved MCP access list"))
+            .transpose()
+    }
+
+    /// Every MCP chat access list that's set
+    pub fn get_mcp_access_lists(&self) -> Result<McpAccessLists> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT key, value FROM settings WHERE key LIKE ?1")?;
+        let mut lists = McpAccessLists::default();
+        let rows = stmt.query_map(params![format!("{}%", MCP_ACCESS_SETTING)], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (key, json) = row?;
+            let list = serde_json::from_str(&json).context("Invalid saved MCP access list")?;
+            match key.strip_prefix(MCP_ACCESS_SETTING) {
+                Some("") => lists.default = Some(list),
+                Some(client) => {
+                    if let Some(client_id) = client.strip_prefix(':') {
+                        lists.clients.insert(client_id.to_string(), list);
+                    }
+                }
+                None => {}
+            }
+        }
+        Ok(lists)
+    }
+
+    /// Set the default MCP chat access list (`client_id` None) or one
+    /// client's, or remove it with `list` None
+    pub fn set_mcp_access(
+        &self,
+        client_id: Option<&str>,
+        list: Option<&ChatAccessList>,
+    ) -> Result<()> {
+        let key = match client_id {
+            Some(client_id) => format!("{}:{}", MCP_ACCESS_SETTING, client_id),
+            None => MCP_ACCESS_SETTING.to_string(),
+        };
+        let json = list.map(serde_json::to_string).transpose()?;
+        let conn = self.conn.lock().unwrap();
+        Self::write_setting(&conn, &key, json.as_deref())
+    }
+
+    fn read_mcp_quota(conn: &Connection, client_id: &str) -> Result<McpQuota> {
+        let setting = |name: &str| -> Result<Option<String>> {
+            match Self::read_setting(conn, &format!("{}:{}", name, client_id))? {
+                Some(value) => Ok(Some(value)),
+                None => Self::read_setting(conn, name),
+            }
+        };
+        Ok(McpQuota {
+            daily_sends: setting(MCP_DAILY_SENDS_SETTING)?.and_then(|v| v.parse().ok()),
+            daily_translation_usd: setting(MCP_DAILY_TRANSLATION_USD_SETTING)?
+                .and_then(|v| v.parse().ok()),
+        })
+    }
+
+    /// The models saved through the settings API, if any
+    pub fn get_model_config(&self) -> Result<Option<ModelConfig>> {
+        let conn = self.conn.lock().unwrap();
+        Self::read_setting(&conn, MODELS_SETTING)?
+            .map(|json| serde_json::from_str(&json).context("Invalid saved model settings"))
+            .transpose()
+    }
+
+    pub fn set_model_config(&self, models: &ModelConfig) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        Self::write_setting(&conn, MODELS_SETTING, Some(&serde_json::to_string(models)?))
+    }
+
+    pub fn get_vapid_key(&self) -> Result<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        Self::read_setting(&conn, VAPID_KEY_SETTING)
+    }
+
+    pub fn set_vapid_key(&self, pkcs8_base64: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        Self::write_setting(&conn, VAPID_KEY_SETTING, Some(pkcs8_base64))
+    }
+
+    pub fn get_quiet_hours(&self) -> Result<Option<QuietHours>> {
+        let conn = self.conn.lock().unwrap();
+        Self::read_setting(&conn, QUIET_HOURS_SETTING)?
+            .map(|json| serde_json::from_str(&json).context("Invalid saved quiet hours"))
+            .transpose()
+    }
+
+    pub fn set_quiet_hours(&self, quiet_hours: Option<&QuietHours>) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let value = quiet_hours.map(serde_json::to_string).transpose()?;
+        Self::write_setting(&conn, QUIET_HOURS_SETTING, value.as_deref())
+    }
+
+    /// Save a browser's push subscription, replacing its keys if the
+    /// endpoint is already subscribed
+    pub fn save_push_subscription(&self, subscription: &PushSubscription) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            r#"
+            INSERT INTO push_subscriptions (endpoint, p256dh, auth, created_at)
+            VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT(endpoint) DO UPDATE SET p256dh = excluded.p256dh, auth = excluded.auth
+            "#,
+            params![
+                subscription.endpoint,
+                subscription.p256dh,
+                subscription.auth,
+                chrono::Utc::now().timestamp()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Delete a push subscription. Returns false if there was none.
+    pub fn delete_push_subscription(&self, endpoint: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let deleted = conn.execute(
+            "DELETE FROM push_subscriptions WHERE endpoint = ?",
+            params![endpoint],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    pub fn get_push_subscriptions(&self) -> Result<Vec<PushSubscription>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT endpoint, p256dh, auth FROM push_subscriptions ORDER BY created_at")?;
+        let subscriptions = stmt
+            .query_map([], |row| {
+                Ok(PushSubscription {
+                    endpoint: row.get(0)?,
+                    p256dh: row.get(1)?,
+                    auth: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(subscriptions)
+    }
+
+    fn read_setting(conn: &Connection, key: &str) -> Result<Option<String>> {
+        Ok(conn
+            .query_row(
+                "SELECT value FROM settings WHERE key = ?",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Store a setting, or remove it if `value` is None
+    fn write_setting(conn: &Connection, key: &str, value: Option<&str>) -> Result<()> {
+        match value {
+            Some(value) => conn.execute(
+                "INSERT INTO settings (key, value) VALUES (?1, ?2)
+                 ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+                params![key, value],
+            )?,
+            None => conn.execute("DELETE FROM settings WHERE key = ?", params![key])?,
+        };
+        Ok(())
+    }
+
+    /// Get a cached link preview by URL
+    /// Returns None if not cached or if cache is older than max_age_secs
+    pub fn get_link_preview(&self, url: &str, max_age_secs: i64) -> Result<Option<LinkPreview>> {
+        let conn = self.conn.lock().unwrap();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let min_time = now - max_age_secs;
+
+        let result = conn.query_row(
+            r#"
+            SELECT url, title, description, image_url, site_name, error, fetched_at
+            FROM link_previews
+            WHERE url = ? AND fetched_at > ?
+            "#,
+            params![url, min_time],
+            |row| {
+                Ok(LinkPreview {
+                    url: row.get(0)?,
+                    title: row.get(1)?,
+                    description: row.get(2)?,
+                    image_url: row.get(3)?,
+                    site_name: row.get(4)?,
+                    error: row.get(5)?,
+                })
+            },
+        );
+
+        match result {
+            Ok(preview) => Ok(Some(preview)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Save a link preview to cache
+    pub fn save_link_preview(&self, preview: &LinkPreview) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        conn.execute(
+            r#"
+            INSERT OR REPLACE INTO link_previews 
+            (url, title, description, image_url, site_name, error, fetched_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            "#,
+            params![
+                preview.url,
+                preview.title,
+                preview.description,
+                preview.image_url,
+                preview.site_name,
+                preview.error,
+                now,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Cached address for rounded coordinates: Some(None) if the geocoder
+    /// found nothing there, None if they haven't been looked up
+    pub fn get_geocode(&self, coordinates: &str) -> Result<Option<Option<String>>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT address FROM geocode_cache WHERE coordinates = ?1",
+            params![coordinates],
+            |row| row.get(0),
+        )
+        .optional()
+        .context("Failed to read geocode cache")
+    }
+
+    /// Cache the address found (or not) for rounded coordinates
+    pub fn save_geocode(&self, coordinates: &str, address: Option<&str>) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO geocode_cache (coordinates, address, fetched_at) VALUES (?1, ?2, ?3)",
+            params![coordinates, address, chrono::Utc::now().timestamp()],
+        )
+        .context("Failed to cache geocode")?;
+        Ok(())
+    }
+
+    /// Store the address found for a location message's coordinates
+    pub fn set_resolved_address(&self, message_id: &str, address: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let updated = conn
+            .execute(
+                "UPDATE messages SET content_json = json_set(content_json, '$.resolved_address', ?1) WHERE id = ?2",
+                params![address, message_id],
+            )
+            .context("Failed to store resolved address")?;
+        Ok(updated > 0)
+    }
+
+    /// Delete link previews fetched before `fetched_before` (seconds), then
+    /// the oldest beyond `max_rows`. Returns (expired, evicted)
+    pub fn prune_link_previews(
+        &self,
+        fetched_before: i64,
+        max_rows: usize,
+    ) -> Result<(usize, usize)> {
+        let conn = self.conn.lock().unwrap();
+        let expired = conn.execute(
+            "DELETE FROM link_previews WHERE fetched_at < ?",
+            params![fetched_before],
+        )?;
+        let evicted = conn.execute(
+            r#"
+            DELETE FROM link_previews WHERE url IN (
+                SELECT url FROM link_previews
+                ORDER BY fetched_at DESC, rowid DESC
+                LIMIT -1 OFFSET ?
+            )
+            "#,
+            params![max_rows as i64],
+        )?;
+        Ok((expired, evicted))
+    }
+
+    /// Clear all data from the database (for logout)
+    pub fn clear_all(&self) -> Result<()> {
+        self.flush_usage();
+        let conn = self.conn.lock().unwrap();
+        self.contact_cache.clear();
+
+        conn.execute_batch(
+            r#"
+            DELETE FROM messages;
+            DELETE FROM media_blobs;
+            DELETE FROM contacts;
+            DELETE FROM contact_events;
+            DELETE FROM translation_usage;
+            DELETE FROM link_previews;
+            DELETE FROM drafts;
+            DELETE FROM pinned_messages;
+            DELETE FROM reports;
+            DELETE FROM translation_participants;
+            DELETE FROM push_subscriptions;
+            "#,
+        )?;
+        Self::write_setting(&conn, OWN_PROFILE_SETTING, None)?;
+        // The next pairing brings its own history
+        Self::write_setting(&conn, HISTORY_SYNC_COMPLETE_SETTING, None)?;
+
+        info!("All data cleared from database");
+        Ok(())
+    }
+
+    // ==================== OAuth 2.0 Methods ====================
+
+    /// Clean up expired OAuth entries (call periodically). Returns how many
+    /// were deleted
+    pub fn oauth_cleanup_expired(&self) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let mut deleted = conn.execute(
+            "DELETE FROM oauth_pending_auth WHERE expires_at < ?",
+            params![now],
+        )?;
+        deleted += conn.execute(
+            "DELETE FROM oauth_authorization_codes WHERE expires_at < ? OR used = 1",
+            params![now],
+        )?;
+        deleted += conn.execute(
+            "DELETE FROM oauth_access_tokens WHERE expires_at < ?",
+            params![now],
+        )?;
+        deleted += conn.execute(
+            "DELETE FROM oauth_refresh_tokens WHERE expires_at < ?",
+            params![now],
+        )?;
+
+        Ok(deleted)
+    }
+
+    /// Store a pending authorization request
+    pub fn oauth_store_pending_auth(&self, pending: &PendingAuthorization) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            r#"
+            INSERT OR REPLACE INTO oauth_pending_auth 
+            (session_key, client_id, redirect_uri, code_challenge, code_challenge_method, scope, state, created_at, expires_at,
+             csrf_token, browser_binding)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)
+            "#,
+            params![
+                pending.session_key,
+                pending.client_id,
+                pending.redirect_uri,
+                pending.code_challenge,
+                pending.code_challenge_method,
+                pending.scope,
+                pending.state,
+                pending.created_at,
+                pending.expires_at,
+                pending.csrf_token,
+                pending.browser_binding,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Get and remove a pending authorization
+    pub fn oauth_take_pending_auth(
+        &self,
+        session_key: &str,
+    ) -> Result<Option<PendingAuthorization>> {
+        let conn = self.conn.lock().unwrap();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let result = conn.query_row(
+            r#"
+            SELECT session_key, client_id, redirect_uri, code_challenge, code_challenge_method, 
+                   scope, state, created_at, expires_at, csrf_token, browser_binding
+            FROM oauth_pending_auth 
+            WHERE session_key = ? AND expires_at > ?
+            "#,
+            params![session_key, now],
+            |row| {
+                Ok(PendingAuthorization {
+                    session_key: row.get(0)?,
+                    client_id: row.get(1)?,
+                    redirect_uri: row.get(2)?,
+                    code_challenge: row.get(3)?,
+                    code_challenge_method: row.get(4)?,
+                    scope: row.get(5)?,
+                    state: row.get(6)?,
+                    created_at: row.get(7)?,
+                    expires_at: row.get(8)?,
+                    csrf_token: row.get(9)?,
+                    browser_binding: row.get(10)?,
+                })
+            },
+        );
+
+        match result {
+            Ok(pending) => {
+                // Delete the pending auth after retrieval
+                conn.execute(
+                    "DELETE FROM oauth_pending_auth WHERE session_key = ?",
+                    params![session_key],
+                )?;
+                Ok(Some(pending))
+            }
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get a pending authorization (without removing it)
+    pub fn oauth_get_pending_auth(
+        &self,
+        session_key: &str,
+    ) -> Result<Option<PendingAuthorization>> {
+        let conn = self.conn.lock().unwrap();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let result = conn.query_row(
+            r#"
+            SELECT session_key, client_id, redirect_uri, code_challenge, code_challenge_method, 
+                   scope, state, created_at, expires_at, csrf_token, browser_binding
+            FROM oauth_pending_auth 
+            WHERE session_key = ? AND expires_at > ?
+            "#,
+            params![session_key, now],
+            |row| {
+                Ok(PendingAuthorization {
+                    session_key: row.get(0)?,
+                    client_id: row.get(1)?,
+                    redirect_uri: row.get(2)?,
+                    code_challenge: row.get(3)?,
+                    code_challenge_method: row.get(4)?,
+                    scope: row.get(5)?,
+                    state: row.get(6)?,
+                    created_at: row.get(7)?,
+                    expires_at: row.get(8)?,
+                    csrf_token: row.get(9)?,
+                    browser_binding: row.get(10)?,
+                })
+            },
+        );
+
+        match result {
+            Ok(pending) => Ok(Some(pending)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Store an authorization code
+    pub fn oauth_store_authorization_code(&self, code: &AuthorizationCode) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            r#"
+            INSERT INTO oauth_authorization_codes 
+            (code, client_id, redirect_uri, code_challenge, code_challenge_method, scope, created_at, expires_at, used)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            "#,
+            params![
+                code.code,
+                code.client_id,
+                code.redirect_uri,
+                code.code_challenge,
+                code.code_challenge_method,
+                code.scope,
+                code.created_at,
+                code.expires_at,
+                code.used,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Get an authorization code (and mark it as used)
+    pub fn oauth_use_authorization_code(&self, code: &str) -> Result<Option<AuthorizationCode>> {
+        let conn = self.conn.lock().unwrap();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let result = conn.query_row(
+            r#"
+            SELECT code, client_id, redirect_uri, code_challenge, code_challenge_method, 
+                   scope, created_at, expires_at, used
+            FROM oauth_authorization_codes 
+            WHERE code = ? AND expires_at > ? AND used = 0
+            "#,
+            params![code, now],
+            |row| {
+                Ok(AuthorizationCode {
+                    code: row.get(0)?,
+                    client_id: row.get(1)?,
+                    redirect_uri: row.get(2)?,
+                    code_challenge: row.get(3)?,
+                    code_challenge_method: row.get(4)?,
+                    scope: row.get(5)?,
+                    created_at: row.get(6)?,
+                    expires_at: row.get(7)?,
+                    used: row.get::<_, i32>(8)? != 0,
+                })
+            },
+        );
+
+        match result {
+            Ok(auth_code) => {
+                // Mark as used
+                conn.execute(
+                    "UPDATE oauth_authorization_codes SET used = 1 WHERE code = ?",
+                    params![code],
+                )?;
+                Ok(Some(auth_code))
+            }
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Store an access token
+    pub fn oauth_store_access_token(&self, token: &AccessToken) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            r#"
+            INSERT INTO oauth_access_tokens (token, client_id, scope, created_at, expires_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            "#,
+            params![
+                token.token,
+                token.client_id,
+                token.scope,
+                token.created_at,
+                token.expires_at,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Validate an access token
+    pub fn oauth_validate_access_token(&self, token: &str) -> Result<Option<AccessToken>> {
+        let conn = self.conn.lock().unwrap();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let result = conn.query_row(
+            r#"
+            SELECT token, client_id, scope, created_at, expires_at
+            FROM oauth_access_tokens 
+            WHERE token = ? AND expires_at > ?
+            "#,
+            params![token, now],
+            |row| {
+                Ok(AccessToken {
+                    token: row.get(0)?,
+                    client_id: row.get(1)?,
+                    scope: row.get(2)?,
+                    created_at: row.get(3)?,
+                    expires_at: row.get(4)?,
+                })
+            },
+        );
+
+        match result {
+            Ok(t) => Ok(Some(t)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Store a refresh token
+    pub fn oauth_store_refresh_token(&self, token: &RefreshToken) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            r#"
+            INSERT INTO oauth_refresh_tokens (token, client_id, scope, created_at, expires_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            "#,
+            params![
+                token.token,
+                token.client_id,
+                token.scope,
+                token.created_at,
+                token.expires_at,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Validate and get a refresh token
+    pub fn oauth_get_refresh_token(&self, token: &str) -> Result<Option<RefreshToken>> {
+        let conn = self.conn.lock().unwrap();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
 
         let result = conn.query_row(
             r#"
-            SELECT session_key, client_id, redirect_uri, code_challenge, code_challenge_method, 
-                   scope, state, created_at, expires_at
-            FROM oauth_pending_auth 
-            WHERE session_key = ? AND expires_at > ?
+            SELECT token, client_id, scope, created_at, expires_at
+            FROM oauth_refresh_tokens 
+            WHERE token = ? AND expires_at > ?
+            "#,
+            params![token, now],
+            |row| {
+                Ok(RefreshToken {
+                    token: row.get(0)?,
+                    client_id: row.get(1)?,
+                    scope: row.get(2)?,
+                    created_at: row.get(3)?,
+                    expires_at: row.get(4)?,
+                })
+            },
+        );
+
+        match result {
+            Ok(t) => Ok(Some(t)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Revoke a token (access or refresh)
+    pub fn oauth_revoke_token(&self, token: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            "DELETE FROM oauth_access_tokens WHERE token = ?",
+            params![token],
+        )?;
+        conn.execute(
+            "DELETE FROM oauth_refresh_tokens WHERE token = ?",
+            params![token],
+        )?;
+
+        Ok(())
+    }
+
+    /// Clear all OAuth tokens (for complete logout)
+    pub fn oauth_clear_all(&self) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute_batch(
+            r#"
+            DELETE FROM oauth_pending_auth;
+            DELETE FROM oauth_authorization_codes;
+            DELETE FROM oauth_access_tokens;
+            DELETE FROM oauth_refresh_tokens;
             "#,
-            params![session_key, now],
+        )?;
+
+        info!("All OAuth tokens cleared from database");
+        Ok(())
+    }
+
+    // ========== Style Profile Methods ==========
+
+    /// Get a style profile by contact ID (or "__global__" for global profile)
+    pub fn get_style_profile(&self, contact_id: &str) -> Result<Option<StyleProfile>> {
+        let conn = self.conn.lock().unwrap();
+
+        let result = conn.query_row(
+            "SELECT contact_id, profile_text, sample_messages, message_count, updated_at 
+             FROM style_profiles WHERE contact_id = ?",
+            params![contact_id],
             |row| {
-                Ok(PendingAuthorization {
-                    session_key: row.get(0)?,
-                    client_id: row.get(1)?,
-                    redirect_uri: row.get(2)?,
-                    code_challenge: row.get(3)?,
-                    code_challenge_method: row.get(4)?,
-                    scope: row.get(5)?,
-                    state: row.get(6)?,
-                    created_at: row.get(7)?,
-                    expires_at: row.get(8)?,
+                let sample_messages_json: String = row.get(2)?;
+                let sample_messages: Vec<String> =
+                    serde_json::from_str(&sample_messages_json).unwrap_or_default();
+
+                Ok(StyleProfile {
+                    contact_id: row.get(0)?,
+                    profile_text: row.get(1)?,
+                    sample_messages,
+                    message_count: row.get(3)?,
+                    updated_at: row.get(4)?,
                 })
             },
         );
 
-        match result {
-            Ok(pending) => {
-                // Delete the pending auth after retrieval
-                conn.execute(
-                    "DELETE FROM oauth_pending_auth WHERE session_key = ?",
-                    params![session_key],
-                )?;
-                Ok(Some(pending))
-            }
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
-        }
+        match result {
+            Ok(profile) => Ok(Some(profile)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Save or update a style profile
+    pub fn save_style_profile(&self, profile: &StyleProfile) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+
+        let sample_messages_json = serde_json::to_string(&profile.sample_messages)?;
+
+        conn.execute(
+            r#"
+            INSERT INTO style_profiles (contact_id, profile_text, sample_messages, message_count, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT(contact_id) DO UPDATE SET
+                profile_text = excluded.profile_text,
+                sample_messages = excluded.sample_messages,
+                message_count = excluded.message_count,
+                updated_at = excluded.updated_at
+            "#,
+            params![
+                profile.contact_id,
+                profile.profile_text,
+                sample_messages_json,
+                profile.message_count,
+                profile.updated_at,
+            ],
+        )?;
+
+        info!(
+            "Saved style profile for {} ({} messages analyzed)",
+            profile.contact_id, profile.message_count
+        );
+
+        Ok(())
+    }
+
+    /// Get count of outgoing messages (for determining if style profile needs refresh)
+    pub fn get_outgoing_message_count(&self, contact_id: Option<&str>) -> Result<i32> {
+        let conn = self.conn.lock().unwrap();
+
+        let count: i32 = if let Some(cid) = contact_id {
+            conn.query_row(
+                "SELECT COUNT(*) FROM messages WHERE is_from_me = 1 AND contact_id = ?",
+                params![cid],
+                |row| row.get(0),
+            )?
+        } else {
+            conn.query_row(
+                "SELECT COUNT(*) FROM messages WHERE is_from_me = 1",
+                [],
+                |row| row.get(0),
+            )?
+        };
+
+        Ok(count)
+    }
+
+    /// Get outgoing messages for style analysis
+    /// If contact_id is None, gets messages across all contacts (for global profile)
+    /// Returns messages with text content, ordered by timestamp descending
+    pub fn get_outgoing_messages_for_style(
+        &self,
+        contact_id: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>> {
+        let conn = self.conn.lock().unwrap();
+
+        let query = if contact_id.is_some() {
+            r#"
+            SELECT id, contact_id, timestamp, is_from_me, is_forwarded, sender_name,
+                   sender_phone, chat_type, content_type, content_json, original_text,
+                   translated_text, source_language, is_translated, origin,
+                   mentioned_jids, mentions_me, sent_by
+            FROM messages
+            WHERE is_from_me = 1 
+              AND contact_id = ?
+              AND content_type = 'Text'
+              AND (original_text IS NOT NULL OR content_json LIKE '%"body"%')
+            ORDER BY timestamp DESC
+            LIMIT ?
+            "#
+        } else {
+            r#"
+            SELECT id, contact_id, timestamp, is_from_me, is_forwarded, sender_name,
+                   sender_phone, chat_type, content_type, content_json, original_text,
+                   translated_text, source_language, is_translated, origin,
+                   mentioned_jids, mentions_me, sent_by
+            FROM messages
+            WHERE is_from_me = 1 
+              AND content_type = 'Text'
+              AND (original_text IS NOT NULL OR content_json LIKE '%"body"%')
+            ORDER BY timestamp DESC
+            LIMIT ?
+            "#
+        };
+
+        let mut stmt = conn.prepare(query)?;
+
+        let messages: Vec<StoredMessage> = if let Some(cid) = contact_id {
+            stmt.query_map(params![cid, limit as i64], |row| {
+                Self::row_to_stored_message(row, None, None)
+            })?
+            .filter_map(|r| r.ok())
+            .collect()
+        } else {
+            stmt.query_map(params![limit as i64], |row| {
+                Self::row_to_stored_message(row, None, None)
+            })?
+            .filter_map(|r| r.ok())
+            .collect()
+        };
+
+        Ok(messages)
+    }
+
+    /// Get message exchange pairs for style learning
+    /// Returns pairs of (incoming message, my reply) to show conversation patterns
+    /// This helps AI learn not just what you say, but how you respond to different inputs
+    pub fn get_message_exchange_pairs(
+        &self,
+        contact_id: &str,
+        limit: usize,
+    ) -> Result<Vec<(StoredMessage, StoredMessage)>> {
+        let conn = self.conn.lock().unwrap();
+
+        // Get recent messages for this contact, ordered by timestamp
+        let query = r#"
+            SELECT id, contact_id, timestamp, is_from_me, is_forwarded, sender_name,
+                   sender_phone, chat_type, content_type, content_json, original_text,
+                   translated_text, source_language, is_translated, origin,
+                   mentioned_jids, mentions_me, sent_by
+            FROM messages
+            WHERE contact_id = ?
+              AND content_type = 'Text'
+              AND (original_text IS NOT NULL OR content_json LIKE '%"body"%')
+            ORDER BY timestamp DESC
+            LIMIT ?
+        "#;
+
+        let mut stmt = conn.prepare(query)?;
+        let messages: Vec<StoredMessage> = stmt
+            .query_map(params![contact_id, (limit * 3) as i64], |row| {
+                Self::row_to_stored_message(row, None, None)
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        // Find pairs: look for my messages that follow their messages
+        let mut pairs = Vec::new();
+        let messages_sorted: Vec<_> = messages.into_iter().rev().collect(); // chronological order
+
+        for i in 1..messages_sorted.len() {
+            let prev = &messages_sorted[i - 1];
+            let curr = &messages_sorted[i];
+
+            // If previous message is from them and current is from me, it's an exchange
+            if !prev.is_from_me && curr.is_from_me {
+                pairs.push((prev.clone(), curr.clone()));
+                if pairs.len() >= limit {
+                    break;
+                }
+            }
+        }
+
+        Ok(pairs)
+    }
+
+    /// Helper to convert a row to StoredMessage (used by multiple methods)
+    fn row_to_stored_message(
+        row: &rusqlite::Row,
+        contact_name: Option<String>,
+        contact_phone: Option<String>,
+    ) -> rusqlite::Result<StoredMessage> {
+        let content_json: String = row.get(9)?;
+        let content = serde_json::from_str(&content_json).ok();
+
+        Ok(StoredMessage {
+            id: row.get(0)?,
+            contact_id: row.get(1)?,
+            timestamp: row.get(2)?,
+            is_from_me: row.get::<_, i32>(3)? != 0,
+            is_forwarded: row.get::<_, i32>(4).unwrap_or(0) != 0,
+            sender_name: row.get(5)?,
+            sender_phone: row.get(6)?,
+            contact_name,
+            contact_phone,
+            chat_type: row
+                .get::<_, Option<String>>(7)?
+                .unwrap_or_else(|| "private".to_string()),
+            content_type: row.get(8)?,
+            content_json,
+            content,
+            original_text: row.get(10)?,
+            translated_text: row.get(11)?,
+            source_language: row.get(12)?,
+            is_translated: row.get::<_, i32>(13).unwrap_or(0) != 0,
+            origin: row.get("origin").ok().flatten(),
+            sent_by: row.get("sent_by").ok().flatten(),
+            mentioned_jids: Self::mentioned_jids_from_row(row),
+            mentions_me: row.get("mentions_me").unwrap_or(false),
+            vocabulary: Self::vocabulary_from_row(row),
+            audio: Self::audio_from_row(row),
+            sort_key: row.get("sort_key").ok().flatten(),
+            triage: Self::triage_from_row(row),
+            translation_status: Self::translation_status_from_row(row),
+        })
+    }
+
+    /// Read the translation_status column of a message row, if it was
+    /// selected and set
+    fn translation_status_from_row(row: &rusqlite::Row) -> Option<TranslationStatus> {
+        row.get::<_, Option<String>>("translation_status")
+            .ok()
+            .flatten()
+            .as_deref()
+            .and_then(TranslationStatus::parse)
+    }
+
+    /// Read the urgency and tone columns of a message row, if they were
+    /// selected and set
+    fn triage_from_row(row: &rusqlite::Row) -> Option<Triage> {
+        let column = |name: &str| row.get::<_, Option<String>>(name).ok().flatten();
+        Triage::new(
+            column("urgency").as_deref().and_then(Urgency::parse),
+            column("tone").as_deref().and_then(Tone::parse),
+        )
     }
 
-    /// Get a pending authorization (without removing it)
-    pub fn oauth_get_pending_auth(
-        &self,
-        session_key: &str,
-    ) -> Result<Option<PendingAuthorization>> {
+    /// Parse the vocab_json column of a message row, if it was selected
+    fn vocabulary_from_row(row: &rusqlite::Row) -> Option<Vec<VocabEntry>> {
+        row.get::<_, Option<String>>("vocab_json")
+            .ok()
+            .flatten()
+            .and_then(|json| serde_json::from_str(&json).ok())
+    }
+
+    /// Read the audio columns of a message row, if they were selected and set
+    fn audio_from_row(row: &rusqlite::Row) -> Option<AudioInfo> {
+        let duration_ms = row
+            .get::<_, Option<i64>>("audio_duration_ms")
+            .ok()
+            .flatten()?;
+        Some(AudioInfo {
+            duration_ms,
+            waveform: row
+                .get::<_, Option<String>>("audio_waveform")
+                .ok()
+                .flatten()
+                .and_then(|json| serde_json::from_str(&json).ok())
+                .unwrap_or_default(),
+            metadata_only: row.get("audio_metadata_only").unwrap_or(false),
+        })
+    }
+
+    /// Parse the mentioned_jids JSON column of a message row
+    fn mentioned_jids_from_row(row: &rusqlite::Row) -> Vec<String> {
+        row.get::<_, Option<String>>("mentioned_jids")
+            .ok()
+            .flatten()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    /// Get a specific message by ID
+    pub fn get_message_by_id(&self, message_id: &str) -> Result<Option<StoredMessage>> {
         let conn = self.conn.lock().unwrap();
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
 
         let result = conn.query_row(
             r#"
-            SELECT session_key, client_id, redirect_uri, code_challenge, code_challenge_method, 
-                   scope, state, created_at, expires_at
-            FROM oauth_pending_auth 
-            WHERE session_key = ? AND expires_at > ?
+            SELECT m.id, m.contact_id, m.timestamp, m.is_from_me, m.is_forwarded, m.sender_name,
+                   m.sender_phone, m.chat_type, m.content_type, m.content_json, m.original_text,
+                   m.translated_text, m.source_language, m.is_translated,
+                   c.name as contact_name, c.phone as contact_phone, m.origin,
+                   m.mentioned_jids, m.mentions_me, m.translation_status, m.sent_by
+            FROM messages m
+            LEFT JOIN contacts c ON m.contact_id = c.id
+            WHERE m.id = ?
             "#,
-            params![session_key, now],
+            params![message_id],
             |row| {
-                Ok(PendingAuthorization {
-                    session_key: row.get(0)?,
-                    client_id: row.get(1)?,
-                    redirect_uri: row.get(2)?,
-                    code_challenge: row.get(3)?,
-                    code_challenge_method: row.get(4)?,
-                    scope: row.get(5)?,
-                    state: row.get(6)?,
-                    created_at: row.get(7)?,
-                    expires_at: row.get(8)?,
-                })
+                let contact_name: Option<String> = row.get(14)?;
+                let contact_phone: Option<String> = row.get(15)?;
+                Self::row_to_stored_message(row, contact_name, contact_phone)
             },
         );
 
         match result {
-            Ok(pending) => Ok(Some(pending)),
+            Ok(msg) => Ok(Some(msg)),
             Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
             Err(e) => Err(e.into()),
         }
     }
 
-    /// Store an authorization code
-    pub fn oauth_store_authorization_code(&self, code: &AuthorizationCode) -> Result<()> {
+    /// Get recent messages for a contact (for conversation context)
+    pub fn get_recent_messages(
+        &self,
+        contact_id: &str,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>> {
         let conn = self.conn.lock().unwrap();
 
-        conn.execute(
+        // Get contact info
+        let contact_info: Option<(Option<String>, Option<String>)> = conn
+            .query_row(
+                "SELECT name, phone FROM contacts WHERE id = ?",
+                params![contact_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .ok();
+
+        let (contact_name, contact_phone) = contact_info.unwrap_or((None, None));
+
+        let mut stmt = conn.prepare(
             r#"
-            INSERT INTO oauth_authorization_codes 
-            (code, client_id, redirect_uri, code_challenge, code_challenge_method, scope, created_at, expires_at, used)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            SELECT id, contact_id, timestamp, is_from_me, is_forwarded, sender_name,
+                   sender_phone, chat_type, content_type, content_json, original_text,
+                   translated_text, source_language, is_translated, origin,
+                   mentioned_jids, mentions_me, sort_key, sent_by
+            FROM messages
+            WHERE contact_id = ?
+            ORDER BY sort_key DESC, rowid DESC
+            LIMIT ?
             "#,
-            params![
-                code.code,
-                code.client_id,
-                code.redirect_uri,
-                code.code_challenge,
-                code.code_challenge_method,
-                code.scope,
-                code.created_at,
-                code.expires_at,
-                code.used,
-            ],
         )?;
 
-        Ok(())
+        let rows = stmt.query_map(params![contact_id, limit as i64], |row| {
+            Self::row_to_stored_message(row, contact_name.clone(), contact_phone.clone())
+        })?;
+
+        // Collect and reverse to get chronological order
+        let mut messages: Vec<StoredMessage> = rows.filter_map(|r| r.ok()).collect();
+        messages.reverse();
+        Ok(messages)
     }
+}
 
-    /// Get an authorization code (and mark it as used)
-    pub fn oauth_use_authorization_code(&self, code: &str) -> Result<Option<AuthorizationCode>> {
-        let conn = self.conn.lock().unwrap();
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
+impl Clone for MessageStore {
+    fn clone(&self) -> Self {
+        Self {
+            conn: Arc::clone(&self.conn),
+            usage_writer: Arc::clone(&self.usage_writer),
+            write_protection: Arc::clone(&self.write_protection),
+            contact_cache: Arc::clone(&self.contact_cache),
+            spill: Arc::clone(&self.spill),
+        }
+    }
+}
+
+/// A store in a temporary directory that is removed when the guard drops
+#[cfg(test)]
+pub(crate) fn test_store() -> (MessageStore, tempfile::TempDir) {
+    let dir = tempfile::tempdir().unwrap();
+    let store = MessageStore::new(dir.path()).unwrap();
+    (store, dir)
+}
+
+/// An incoming private text message, for tests to adjust with struct update syntax
+#[cfg(test)]
+pub(crate) fn text_message(
+    id: &str,
+    contact_id: &str,
+    timestamp: i64,
+    body: &str,
+) -> StoredMessage {
+    StoredMessage {
+        id: id.to_string(),
+        contact_id: contact_id.to_string(),
+        timestamp,
+        chat_type: "private".to_string(),
+        content_type: "Text".to_string(),
+        content_json: serde_json::json!({"type": "text", "body": body}).to_string(),
+        original_text: Some(body.to_string()),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_contacts_by_folded_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MessageStore::new(dir.path()).unwrap();
+        let contacts = [
+            ("120363000000000001@g.us", "Familia ❤️", "group"),
+            ("120363000000000002@g.us", "🎉 Família Souza 🎉", "group"),
+            ("79161234567@s.whatsapp.net", "Дмитрий Иванов", "private"),
+            ("306912345678@s.whatsapp.net", "Γιώργος", "private"),
+            ("4915112345678@s.whatsapp.net", "Ana Familienrat", "private"),
+        ];
+        for (i, (id, name, contact_type)) in contacts.iter().enumerate() {
+            store
+                .upsert_contact(id, Some(name), None, Some(contact_type), 1000 + i as i64)
+                .unwrap();
+        }
+        let names = |store: &MessageStore, query: &str| -> Vec<String> {
+            store
+                .search_contacts(query, 10)
+                .unwrap()
+                .into_iter()
+                .map(|c| c.name.unwrap())
+                .collect()
+        };
+
+        // Prefix matches first, then word prefixes, each in the list's order
+        assert_eq!(
+            names(&store, "famil"),
+            ["🎉 Família Souza 🎉", "Familia ❤️", "Ana Familienrat"]
+        );
+        assert_eq!(names(&store, "Dmitri"), ["Дмитрий Иванов"]);
+        assert_eq!(names(&store, "giorg"), ["Γιώργος"]);
+        assert_eq!(names(&store, "ivan"), ["Дмитрий Иванов"]);
+        assert!(names(&store, "❤️").is_empty());
+        assert!(names(&store, "Souzaa").is_empty());
+
+        // A renamed contact is found by its new name only
+        store
+            .upsert_contact("306912345678@s.whatsapp.net", Some("Yorgos"), None, None, 1)
+            .unwrap();
+        assert!(names(&store, "giorg").is_empty());
+        assert_eq!(names(&store, "yorg"), ["Yorgos"]);
+
+        // Contacts stored before the column existed are filled in
+        store
+            .conn
+            .lock()
             .unwrap()
-            .as_secs() as i64;
+            .execute("ALTER TABLE contacts DROP COLUMN name_normalized", [])
+            .unwrap();
+        drop(store);
+        let store = MessageStore::new(dir.path()).unwrap();
+        assert_eq!(names(&store, "dmitri"), ["Дмитрий Иванов"]);
+        assert_eq!(
+            store
+                .get_contact("79161234567@s.whatsapp.net")
+                .unwrap()
+                .unwrap()
+                .name
+                .as_deref(),
+            Some("Дмитрий Иванов")
+        );
+    }
 
-        let result = conn.query_row(
-            r#"
-            SELECT code, client_id, redirect_uri, code_challenge, code_challenge_method, 
-                   scope, created_at, expires_at, used
-            FROM oauth_authorization_codes 
-            WHERE code = ? AND expires_at > ? AND used = 0
-            "#,
-            params![code, now],
-            |row| {
-                Ok(AuthorizationCode {
-                    code: row.get(0)?,
-                    client_id: row.get(1)?,
-                    redirect_uri: row.get(2)?,
-                    code_challenge: row.get(3)?,
-                    code_challenge_method: row.get(4)?,
-                    scope: row.get(5)?,
-                    created_at: row.get(6)?,
-                    expires_at: row.get(7)?,
-                    used: row.get::<_, i32>(8)? != 0,
-                })
-            },
+    #[test]
+    fn test_reply_preview_per_content_type() {
+        let preview = |content_type: &str, content: serde_json::Value| {
+            let mut message = text_message("q1", "447700900001@s.whatsapp.net", 1000, "hi");
+            message.content_type = content_type.to_string();
+            message.content_json = content.to_string();
+            MessageStore::generate_reply_preview(&message)
+        };
+
+        assert_eq!(
+            preview(
+                "Text",
+                serde_json::json!({"type": "text", "body": "See you at 8"})
+            ),
+            "See you at 8"
+        );
+        assert_eq!(
+            preview(
+                "Image",
+                serde_json::json!({"type": "image", "caption": "Our new flat"})
+            ),
+            "📷 Photo — 'Our new flat'"
+        );
+        assert_eq!(
+            preview(
+                "Image",
+                serde_json::json!({"type": "image", "caption": "  "})
+            ),
+            "📷 Photo"
+        );
+        assert_eq!(
+            preview(
+                "Video",
+                serde_json::json!({"type": "video", "duration_seconds": 75, "caption": "Goal!"})
+            ),
+            "🎥 Video (1:15) — 'Goal!'"
+        );
+        assert_eq!(
+            preview(
+                "Audio",
+                serde_json::json!({"type": "audio", "duration_seconds": 42, "is_voice_note": true})
+            ),
+            "🎵 Voice note (0:42)"
+        );
+        assert_eq!(
+            preview(
+                "Audio",
+                serde_json::json!({"type": "audio", "is_voice_note": false})
+            ),
+            "🎵 Audio"
+        );
+        assert_eq!(
+            preview(
+                "Document",
+                serde_json::json!({"type": "document", "file_name": "lease.pdf", "caption": "Signed"})
+            ),
+            "📄 lease.pdf — 'Signed'"
+        );
+        assert_eq!(
+            preview("Sticker", serde_json::json!({"type": "sticker"})),
+            "Sticker"
+        );
+        assert_eq!(
+            preview(
+                "Location",
+                serde_json::json!({"type": "location", "name": "Café Central"})
+            ),
+            "📍 Café Central"
+        );
+        assert_eq!(
+            preview(
+                "Contact",
+                serde_json::json!({"type": "contact", "display_name": "Ana"})
+            ),
+            "👤 Ana"
+        );
+        assert_eq!(
+            preview(
+                "Poll",
+                serde_json::json!({"type": "poll", "question": "Pizza or sushi?"})
+            ),
+            "📊 Pizza or sushi?"
         );
 
-        match result {
-            Ok(auth_code) => {
-                // Mark as used
-                conn.execute(
-                    "UPDATE oauth_authorization_codes SET used = 1 WHERE code = ?",
-                    params![code],
-                )?;
-                Ok(Some(auth_code))
-            }
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
-        }
+        // A decoded voice note's own length wins over WhatsApp's
+        let mut voice = text_message("q2", "447700900001@s.whatsapp.net", 1000, "hi");
+        voice.content_type = "Audio".to_string();
+        voice.content_json =
+            r#"{"type":"audio","duration_seconds":3,"is_voice_note":true}"#.to_string();
+        voice.audio = Some(AudioInfo {
+            duration_ms: 62_400,
+            waveform: Vec::new(),
+            metadata_only: false,
+        });
+        assert_eq!(
+            MessageStore::generate_reply_preview(&voice),
+            "🎵 Voice note (1:02)"
+        );
     }
 
-    /// Store an access token
-    pub fn oauth_store_access_token(&self, token: &AccessToken) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+    #[test]
+    fn test_upsert_contact_reports_changes() {
+        let (store, _dir) = test_store();
+        let chat = "351912345678@s.whatsapp.net";
 
-        conn.execute(
-            r#"
-            INSERT INTO oauth_access_tokens (token, client_id, scope, created_at, expires_at)
-            VALUES (?1, ?2, ?3, ?4, ?5)
-            "#,
-            params![
-                token.token,
-                token.client_id,
-                token.scope,
-                token.created_at,
-                token.expires_at,
-            ],
-        )?;
+        assert_eq!(
+            store
+                .upsert_contact(chat, Some("Ana"), Some("351912345678"), Some("private"), 1)
+                .unwrap(),
+            ContactChange::Created
+        );
+        let created = store.get_contact(chat).unwrap().unwrap();
+        assert!(created.created_at.is_some());
+        assert_eq!(created.created_at, created.updated_at);
+
+        // A new message alone, or one without a name, changes nothing
+        assert_eq!(
+            store
+                .upsert_contact(chat, Some("Ana"), Some("351912345678"), Some("private"), 2)
+                .unwrap(),
+            ContactChange::Unchanged
+        );
+        assert_eq!(
+            store.upsert_contact(chat, None, None, None, 3).unwrap(),
+            ContactChange::Unchanged
+        );
+        assert_eq!(
+            store.get_contact(chat).unwrap().unwrap().last_message_time,
+            3
+        );
 
-        Ok(())
+        assert_eq!(
+            store
+                .upsert_contact(chat, Some("Ana Lima"), None, None, 4)
+                .unwrap(),
+            ContactChange::NameChanged {
+                old: Some("Ana".to_string()),
+                new: "Ana Lima".to_string()
+            }
+        );
+        assert_eq!(
+            store
+                .upsert_contact(chat, None, None, Some("group"), 5)
+                .unwrap(),
+            ContactChange::DetailsChanged
+        );
+
+        let events: Vec<(String, Option<String>, Option<String>)> = store
+            .get_contact_events(chat, 10)
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.field, e.old_value, e.new_value))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (
+                    "type".to_string(),
+                    Some("private".to_string()),
+                    Some("group".to_string())
+                ),
+                (
+                    "name".to_string(),
+                    Some("Ana".to_string()),
+                    Some("Ana Lima".to_string())
+                ),
+                ("created".to_string(), None, Some("Ana".to_string())),
+            ]
+        );
     }
 
-    /// Validate an access token
-    pub fn oauth_validate_access_token(&self, token: &str) -> Result<Option<AccessToken>> {
-        let conn = self.conn.lock().unwrap();
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
+    #[test]
+    fn test_contact_audit_columns_backfill() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MessageStore::new(dir.path()).unwrap();
+        let chat = "a@s.whatsapp.net";
+        store.upsert_contact(chat, None, None, None, 9000).unwrap();
+        store
+            .add_message(&text_message("m1", chat, 5000, "hi"))
+            .unwrap();
+        store
+            .add_message(&text_message("m2", chat, 7000, "hi"))
+            .unwrap();
+        store
+            .upsert_contact("quiet@s.whatsapp.net", None, None, None, 8000)
+            .unwrap();
+
+        // Simulate a database from before the audit columns existed
+        store
+            .conn
+            .lock()
             .unwrap()
-            .as_secs() as i64;
+            .execute_batch(
+                r#"
+                ALTER TABLE contacts DROP COLUMN created_at;
+                ALTER TABLE contacts DROP COLUMN updated_at;
+                "#,
+            )
+            .unwrap();
+        drop(store);
+
+        let store = MessageStore::new(dir.path()).unwrap();
+        let contact = store.get_contact(chat).unwrap().unwrap();
+        assert_eq!(contact.created_at, Some(5000));
+        assert_eq!(contact.updated_at, Some(5000));
+        let quiet = store.get_contact("quiet@s.whatsapp.net").unwrap().unwrap();
+        assert_eq!(quiet.created_at, Some(8000));
+    }
 
-        let result = conn.query_row(
-            r#"
-            SELECT token, client_id, scope, created_at, expires_at
-            FROM oauth_access_tokens 
-            WHERE token = ? AND expires_at > ?
-            "#,
-            params![token, now],
-            |row| {
-                Ok(AccessToken {
-                    token: row.get(0)?,
-                    client_id: row.get(1)?,
-                    scope: row.get(2)?,
-                    created_at: row.get(3)?,
-                    expires_at: row.get(4)?,
-                })
-            },
-        );
+    #[test]
+    fn test_batched_writes_match_single_writes() {
+        let alice = "447700900001@s.whatsapp.net";
+        let bob = "447700900002@s.whatsapp.net";
+        let named = |mut msg: StoredMessage, name: &str| {
+            msg.contact_name = Some(name.to_string());
+            msg
+        };
+        let mut mine = text_message("h3", alice, 1_700_000_003_000, "hi");
+        mine.is_from_me = true;
+        let history = vec![
+            (
+                named(text_message("h1", alice, 1_700_000_001_000, "hi"), "Alice"),
+                Some(1),
+            ),
+            (
+                named(text_message("h2", bob, 1_700_000_002_000, "hi"), "Bob"),
+                None,
+            ),
+            (mine, Some(1)),
+            (
+                named(
+                    text_message("h4", alice, 1_700_000_004_000, "hi"),
+                    "Alice B",
+                ),
+                Some(1),
+            ),
+            // Delivered twice
+            (text_message("h2", bob, 1_700_000_002_000, "hi"), None),
+        ];
+        // Sent from here just before, so WhatsApp's copy (h3) takes it over
+        let store_with_pending = || {
+            let (store, _dir) = test_store();
+            store
+                .upsert_contact(alice, None, None, Some("private"), 1)
+                .unwrap();
+            let mut pending = text_message("pending_1", alice, 1_700_000_002_500, "hi");
+            pending.is_from_me = true;
+            pending.origin = Some("web".to_string());
+            store.add_message(&pending).unwrap();
+            store
+        };
 
-        match result {
-            Ok(t) => Ok(Some(t)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
+        let single = store_with_pending();
+        let mut single_sort_keys = Vec::new();
+        for (msg, unread) in &history {
+            single
+                .upsert_contact(
+                    &msg.contact_id,
+                    msg.contact_name.as_deref(),
+                    None,
+                    Some(&msg.chat_type),
+                    msg.timestamp,
+                )
+                .unwrap();
+            let sort_key = match single.claim_pending_message(msg).unwrap() {
+                Some(claimed) => Some(claimed.sort_key),
+                None => single.add_message(msg).unwrap(),
+            };
+            single_sort_keys.push(sort_key);
+            if let Some(unread) = unread {
+                single.set_unread_count(&msg.contact_id, *unread).unwrap();
+            }
+        }
+
+        let batched = store_with_pending();
+        let contacts: Vec<_> = history
+            .iter()
+            .map(|(msg, _)| ContactUpsert {
+                id: &msg.contact_id,
+                name: msg.contact_name.as_deref(),
+                phone: None,
+                contact_type: Some(&msg.chat_type),
+                last_message_time: msg.timestamp,
+            })
+            .collect();
+        let changes = batched.upsert_contacts_batch(&contacts).unwrap();
+        assert!(matches!(
+            changes[0],
+            ContactChange::NameChanged { old: None, .. }
+        ));
+        assert_eq!(changes[1], ContactChange::Created);
+        assert_eq!(changes[2], ContactChange::Unchanged);
+        assert!(matches!(
+            changes[3],
+            ContactChange::NameChanged { old: Some(_), .. }
+        ));
+        let messages: Vec<_> = history.iter().map(|(msg, _)| msg.clone()).collect();
+        let writes = batched.add_messages_batch(&messages).unwrap();
+        assert_eq!(
+            writes[2].claimed.as_ref().map(|c| c.pending_id.as_str()),
+            Some("pending_1")
+        );
+        let batched_sort_keys: Vec<_> = writes.iter().map(|w| w.sort_key).collect();
+        assert_eq!(batched_sort_keys, single_sort_keys);
+        assert_eq!(batched_sort_keys[4], None);
+        batched.set_unread_count(alice, 1).unwrap();
+
+        let dump = |store: &MessageStore, sql: &str| -> Vec<String> {
+            let conn = store.conn.lock().unwrap();
+            let mut stmt = conn.prepare(sql).unwrap();
+            let columns = stmt.column_count();
+            stmt.query_map([], |row| {
+                (0..columns)
+                    .map(|i| row.get::<_, rusqlite::types::Value>(i))
+                    .collect::<rusqlite::Result<Vec<_>>>()
+                    .map(|values| format!("{:?}", values))
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap()
+        };
+        for sql in [
+            "SELECT * FROM messages ORDER BY id",
+            "SELECT id, name, phone, type, last_message_time, unread_count, last_read_timestamp
+             FROM contacts ORDER BY id",
+            "SELECT contact_id, field, old_value, new_value FROM contact_events ORDER BY id",
+        ] {
+            assert_eq!(dump(&batched, sql), dump(&single, sql), "{}", sql);
         }
     }
 
-    /// Store a refresh token
-    pub fn oauth_store_refresh_token(&self, token: &RefreshToken) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-
-        conn.execute(
-            r#"
-            INSERT INTO oauth_refresh_tokens (token, client_id, scope, created_at, expires_at)
-            VALUES (?1, ?2, ?3, ?4, ?5)
-            "#,
-            params![
-                token.token,
-                token.client_id,
-                token.scope,
-                token.created_at,
-                token.expires_at,
-            ],
-        )?;
+    #[test]
+    fn test_prune_link_previews() {
+        let (store, _dir) = test_store();
+        {
+            let conn = store.conn.lock().unwrap();
+            for (i, fetched_at) in [100, 200, 300, 400, 500, 600].into_iter().enumerate() {
+                conn.execute(
+                    "INSERT INTO link_previews (url, fetched_at) VALUES (?, ?)",
+                    params![format!("https://example.com/{}", i), fetched_at],
+                )
+                .unwrap();
+            }
+        }
 
-        Ok(())
+        // Two are too old; of the other four the oldest beyond three goes
+        assert_eq!(store.prune_link_previews(300, 3).unwrap(), (2, 1));
+        let urls: Vec<String> = {
+            let conn = store.conn.lock().unwrap();
+            let mut stmt = conn
+                .prepare("SELECT url FROM link_previews ORDER BY fetched_at")
+                .unwrap();
+            stmt.query_map([], |row| row.get(0))
+                .unwrap()
+                .collect::<rusqlite::Result<_>>()
+                .unwrap()
+        };
+        assert_eq!(
+            urls,
+            [
+                "https://example.com/3",
+                "https://example.com/4",
+                "https://example.com/5"
+            ]
+        );
+        assert_eq!(store.prune_link_previews(300, 3).unwrap(), (0, 0));
     }
 
-    /// Validate and get a refresh token
-    pub fn oauth_get_refresh_token(&self, token: &str) -> Result<Option<RefreshToken>> {
-        let conn = self.conn.lock().unwrap();
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs() as i64;
+    #[test]
+    fn test_last_read_timestamp_backfill() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MessageStore::new(dir.path()).unwrap();
+        let unread = "a@s.whatsapp.net";
+        let read = "b@s.whatsapp.net";
+        for chat in [unread, read] {
+            store.upsert_contact(chat, None, None, None, 4000).unwrap();
+            for (i, timestamp) in [1000, 2000, 3000].into_iter().enumerate() {
+                let id = format!("{}-{}", chat, i);
+                store
+                    .add_message(&text_message(&id, chat, timestamp, "hi"))
+                    .unwrap();
+            }
+        }
+        let mut mine = text_message("a-mine", unread, 4000, "hi");
+        mine.is_from_me = true;
+        store.add_message(&mine).unwrap();
+        for _ in 0..2 {
+            store.increment_unread(unread).unwrap();
+        }
 
-        let result = conn.query_row(
-            r#"
-            SELECT token, client_id, scope, created_at, expires_at
-            FROM oauth_refresh_tokens 
-            WHERE token = ? AND expires_at > ?
-            "#,
-            params![token, now],
-            |row| {
-                Ok(RefreshToken {
-                    token: row.get(0)?,
-                    client_id: row.get(1)?,
-                    scope: row.get(2)?,
-                    created_at: row.get(3)?,
-                    expires_at: row.get(4)?,
-                })
-            },
+        // Simulate a database from before last_read_timestamp existed
+        store
+            .conn
+            .lock()
+            .unwrap()
+            .execute("ALTER TABLE contacts DROP COLUMN last_read_timestamp", [])
+            .unwrap();
+        drop(store);
+
+        // The last two incoming messages stay unread; my reply doesn't count
+        let store = MessageStore::new(dir.path()).unwrap();
+        let contact = store.get_contact(unread).unwrap().unwrap();
+        assert_eq!(contact.unread_count, 2);
+        assert_eq!(contact.last_read_timestamp, Some(1999));
+        assert_eq!(
+            store.get_first_unread(unread).unwrap(),
+            Some(FirstUnread {
+                message_id: format!("{}-1", unread),
+                timestamp: 2000
+            })
         );
+        let contact = store.get_contact(read).unwrap().unwrap();
+        assert_eq!(contact.last_read_timestamp, Some(4000));
+        assert_eq!(store.get_first_unread(read).unwrap(), None);
+    }
 
-        match result {
-            Ok(t) => Ok(Some(t)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
+    #[test]
+    fn test_same_second_messages_keep_arrival_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MessageStore::new(dir.path()).unwrap();
+        let group = "120363000000000000@g.us";
+        store
+            .upsert_contact(group, None, None, Some("group"), 1)
+            .unwrap();
+        let message = |id: &str, timestamp: i64, is_from_me: bool| {
+            let mut message = text_message(id, group, timestamp, "hi");
+            message.is_from_me = is_from_me;
+            message.content_json = serde_json::json!({"type": "text", "body": id}).to_string();
+            message
+        };
+        let ids = |messages: Vec<StoredMessage>| -> Vec<String> {
+            messages.into_iter().map(|m| m.id).collect()
+        };
+
+        // A burst within one second, whose timestamps only have second
+        // resolution, around a send from here stamped to the millisecond
+        let second = 1_700_000_000_000;
+        let sends = [
+            message("burst-1", second, false),
+            message("mine", second + 437, true),
+            message("burst-2", second, false),
+            message("burst-3", second, false),
+            message("next-second", second + 1000, false),
+        ];
+        let mut keys = Vec::new();
+        for send in &sends {
+            keys.push(store.add_message(send).unwrap().unwrap());
         }
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(keys[0], second * 1000);
+        // Already stored: no new key
+        assert_eq!(store.add_message(&sends[2]).unwrap(), None);
+
+        let expected = ["burst-1", "mine", "burst-2", "burst-3", "next-second"];
+        for _ in 0..3 {
+            let all = store
+                .get_messages_paginated(group, None, None, None, true, MessageFilter::default())
+                .unwrap();
+            assert_eq!(all[1].sort_key, Some(keys[1]));
+            assert_eq!(ids(all), expected);
+            let latest = store
+                .get_messages_paginated(group, Some(3), None, None, true, MessageFilter::default())
+                .unwrap();
+            assert_eq!(ids(latest), expected[2..]);
+            assert_eq!(
+                ids(store.get_recent_messages(group, 4).unwrap()),
+                expected[1..]
+            );
+        }
+        // The preview is the last message stored for the latest second
+        store
+            .conn
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM messages WHERE id = 'next-second'", [])
+            .unwrap();
+        let contact = store.get_contact(group).unwrap().unwrap();
+        assert_eq!(contact.last_message_preview.as_deref(), Some("burst-3"));
+
+        // Existing rows are backfilled in the order they were stored
+        {
+            let conn = store.conn.lock().unwrap();
+            conn.execute_batch(
+                "DROP INDEX idx_messages_contact_sort_key;
+                 ALTER TABLE messages DROP COLUMN sort_key;",
+            )
+            .unwrap();
+        }
+        drop(store);
+        let store = MessageStore::new(dir.path()).unwrap();
+        let all = store
+            .get_messages_paginated(group, None, None, None, true, MessageFilter::default())
+            .unwrap();
+        let backfilled: Vec<(String, Option<i64>)> =
+            all.into_iter().map(|m| (m.id, m.sort_key)).collect();
+        assert_eq!(
+            backfilled,
+            [
+                ("burst-1".to_string(), Some(second * 1000)),
+                ("burst-2".to_string(), Some(second * 1000 + 1)),
+                ("burst-3".to_string(), Some(second * 1000 + 2)),
+                ("mine".to_string(), Some((second + 437) * 1000)),
+            ]
+        );
     }
 
-    /// Revoke a token (access or refresh)
-    pub fn oauth_revoke_token(&self, token: &str) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+    #[test]
+    fn test_link_identity_merges_conversation() {
+        let (store, _dir) = test_store();
+        let phone = "1234567890@s.whatsapp.net";
+        let lid = "98765@lid";
+
+        store
+            .upsert_contact(phone, Some("Ana"), None, Some("private"), 1000)
+            .unwrap();
+        store
+            .upsert_contact(lid, Some("Ana"), None, Some("private"), 2000)
+            .unwrap();
+        store
+            .add_message(&text_message("m1", phone, 1000, "hi"))
+            .unwrap();
+        store
+            .add_message(&text_message("m2", lid, 2000, "hi"))
+            .unwrap();
+
+        store.link_identity(lid, phone, "manual").unwrap();
+
+        assert_eq!(store.resolve_contact_id(lid).unwrap(), phone);
+        assert_eq!(store.get_messages(lid).unwrap().len(), 2);
+        assert_eq!(store.get_messages(phone).unwrap().len(), 2);
+
+        let contacts = store.get_contacts().unwrap();
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[0].id, phone);
+        assert_eq!(contacts[0].last_message_time, 2000);
+
+        // New messages under the alias land on the canonical contact
+        store
+            .add_message(&text_message("m3", lid, 3000, "hi"))
+            .unwrap();
+        assert_eq!(store.get_messages(phone).unwrap().len(), 3);
+    }
 
-        conn.execute(
-            "DELETE FROM oauth_access_tokens WHERE token = ?",
-            params![token],
-        )?;
-        conn.execute(
-            "DELETE FROM oauth_refresh_tokens WHERE token = ?",
-            params![token],
-        )?;
+    #[test]
+    fn test_identity_link_candidates() {
+        let (store, _dir) = test_store();
+        let day = 24 * 60 * 60 * 1000;
+
+        // Unique name, overlapping activity -> confident
+        store
+            .upsert_contact("111@s.whatsapp.net", Some("Ana"), None, None, 0)
+            .unwrap();
+        store
+            .upsert_contact("1@lid", Some("ana "), None, None, 0)
+            .unwrap();
+        store
+            .add_message(&text_message("a1", "111@s.whatsapp.net", day, "hi"))
+            .unwrap();
+        store
+            .add_message(&text_message("a2", "1@lid", 2 * day, "hi"))
+            .unwrap();
+
+        // Two phone contacts share the name -> ambiguous
+        store
+            .upsert_contact("222@s.whatsapp.net", Some("Bo"), None, None, 0)
+            .unwrap();
+        store
+            .upsert_contact("333@s.whatsapp.net", Some("Bo"), None, None, 0)
+            .unwrap();
+        store
+            .upsert_contact("2@lid", Some("Bo"), None, None, 0)
+            .unwrap();
+
+        let candidates = store.find_identity_link_candidates().unwrap();
+        assert_eq!(candidates.len(), 3);
+
+        let ana = candidates.iter().find(|c| c.alt_jid == "1@lid").unwrap();
+        assert!(ana.confident);
+        assert_eq!(ana.canonical_id, "111@s.whatsapp.net");
+        assert!(candidates
+            .iter()
+            .filter(|c| c.alt_jid == "2@lid")
+            .all(|c| !c.confident));
+
+        store
+            .link_identity("1@lid", "111@s.whatsapp.net", "heuristic")
+            .unwrap();
+        assert_eq!(store.find_identity_link_candidates().unwrap().len(), 2);
+    }
 
-        Ok(())
+    fn usage(input_tokens: u32) -> UsageInfo {
+        UsageInfo {
+            input_tokens,
+            output_tokens: 1,
+            cost_usd: 0.001,
+            calls: Vec::new(),
+        }
     }
 
-    /// Clear all OAuth tokens (for complete logout)
-    pub fn oauth_clear_all(&self) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+    fn usage_rows(store: &MessageStore) -> i64 {
+        let conn = store.conn.lock().unwrap();
+        conn.query_row("SELECT COUNT(*) FROM translation_usage", [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    }
 
-        conn.execute_batch(
-            r#"
-            DELETE FROM oauth_pending_auth;
-            DELETE FROM oauth_authorization_codes;
-            DELETE FROM oauth_access_tokens;
-            DELETE FROM oauth_refresh_tokens;
-            "#,
-        )?;
+    #[test]
+    fn test_usage_is_written_in_batches() {
+        let (store, _dir) = test_store();
 
-        info!("All OAuth tokens cleared from database");
-        Ok(())
+        for _ in 0..USAGE_BATCH_SIZE - 1 {
+            store
+                .record_usage(Some("a@s.whatsapp.net"), None, &usage(10), "translate")
+                .unwrap();
+        }
+        assert_eq!(usage_rows(&store), 0);
+
+        // A full batch is written without waiting for the interval
+        store
+            .record_usage(Some("a@s.whatsapp.net"), None, &usage(10), "translate")
+            .unwrap();
+        let deadline = Instant::now() + USAGE_BATCH_INTERVAL / 2;
+        while usage_rows(&store) == 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(usage_rows(&store), USAGE_BATCH_SIZE as i64);
     }
 
-    // ========== Style Profile Methods ==========
-
-    /// Get a style profile by contact ID (or "__global__" for global profile)
-    pub fn get_style_profile(&self, contact_id: &str) -> Result<Option<StyleProfile>> {
-        let conn = self.conn.lock().unwrap();
+    #[test]
+    fn test_usage_reads_flush_queue() {
+        let (store, _dir) = test_store();
+        let contact = "a@s.whatsapp.net";
 
-        let result = conn.query_row(
-            "SELECT contact_id, profile_text, sample_messages, message_count, updated_at 
-             FROM style_profiles WHERE contact_id = ?",
-            params![contact_id],
-            |row| {
-                let sample_messages_json: String = row.get(2)?;
-                let sample_messages: Vec<String> =
-                    serde_json::from_str(&sample_messages_json).unwrap_or_default();
+        store
+            .record_usage(Some(contact), Some("m1"), &usage(10), "translate")
+            .unwrap();
+        store.record_usage(None, None, &usage(5), "detect").unwrap();
 
-                Ok(StyleProfile {
-                    contact_id: row.get(0)?,
-                    profile_text: row.get(1)?,
-                    sample_messages,
-                    message_count: row.get(3)?,
-                    updated_at: row.get(4)?,
-                })
-            },
+        assert_eq!(
+            store.get_conversation_usage(contact).unwrap().input_tokens,
+            10
         );
+        assert_eq!(store.get_global_usage().unwrap().input_tokens, 15);
+    }
 
-        match result {
-            Ok(profile) => Ok(Some(profile)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
+    fn language_message(id: &str, contact_id: &str, language: Option<&str>) -> StoredMessage {
+        StoredMessage {
+            source_language: language.map(str::to_string),
+            ..text_message(id, contact_id, 1, "hi")
         }
     }
 
-    /// Save or update a style profile
-    pub fn save_style_profile(&self, profile: &StyleProfile) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
+    fn cached_language_columns(
+        store: &MessageStore,
+        contact_id: &str,
+    ) -> (Option<String>, Option<i64>) {
+        let conn = store.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT conversation_language, language_message_count FROM contacts WHERE id = ?",
+            params![contact_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap()
+    }
 
-        let sample_messages_json = serde_json::to_string(&profile.sample_messages)?;
+    /// The conversation language computed from scratch
+    fn full_language(store: &MessageStore, contact_id: &str) -> Option<String> {
+        let conn = store.conn.lock().unwrap();
+        MessageStore::query_conversation_language(&conn, contact_id)
+            .unwrap()
+            .map(|(language, _)| language)
+    }
 
-        conn.execute(
-            r#"
-            INSERT INTO style_profiles (contact_id, profile_text, sample_messages, message_count, updated_at)
-            VALUES (?1, ?2, ?3, ?4, ?5)
-            ON CONFLICT(contact_id) DO UPDATE SET
-                profile_text = excluded.profile_text,
-                sample_messages = excluded.sample_messages,
-                message_count = excluded.message_count,
-                updated_at = excluded.updated_at
-            "#,
-            params![
-                profile.contact_id,
-                profile.profile_text,
-                sample_messages_json,
-                profile.message_count,
-                profile.updated_at,
-            ],
-        )?;
+    #[test]
+    fn test_conversation_language_cache_matches_recompute() {
+        let (store, _dir) = test_store();
+        let chats = ["a@s.whatsapp.net", "b@s.whatsapp.net"];
+        for chat in chats {
+            store
+                .upsert_contact(chat, None, None, Some("private"), 1)
+                .unwrap();
+        }
+        let languages = [
+            Some("Spanish"),
+            Some("French"),
+            Some("German"),
+            None,
+            Some(""),
+        ];
+
+        // Mixed-language histories with frequent lead changes and ties
+        let mut seed: u64 = 42;
+        for i in 0..300u32 {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let chat = chats[(seed >> 33) as usize % chats.len()];
+            let language = languages[(seed >> 40) as usize % languages.len()];
+            let mut message = language_message(&format!("m{}", i), chat, language);
+            message.is_from_me = (seed >> 50).is_multiple_of(5);
+            store.add_message(&message).unwrap();
+
+            // Duplicates are ignored and don't count twice
+            if i.is_multiple_of(17) {
+                store.add_message(&message).unwrap();
+            }
 
-        info!(
-            "Saved style profile for {} ({} messages analyzed)",
-            profile.contact_id, profile.message_count
+            for chat in chats {
+                assert_eq!(
+                    store.get_cached_conversation_language(chat).unwrap(),
+                    full_language(&store, chat),
+                    "after message {}",
+                    i
+                );
+            }
+        }
+
+        // The cache is maintained incrementally rather than recomputed
+        assert!(cached_language_columns(&store, chats[0]).1.is_some());
+
+        // Changes outside add_message invalidate it
+        let leader = full_language(&store, chats[0]).unwrap();
+        let messages = store.get_messages(chats[0]).unwrap();
+        for message in messages
+            .iter()
+            .filter(|m| !m.is_from_me && m.source_language.as_deref() == Some(leader.as_str()))
+            .take(3)
+        {
+            store.delete_message(chats[0], &message.id).unwrap();
+            assert_eq!(cached_language_columns(&store, chats[0]), (None, None));
+            assert_eq!(
+                store.get_cached_conversation_language(chats[0]).unwrap(),
+                full_language(&store, chats[0])
+            );
+        }
+
+        for message in messages.iter().filter(|m| !m.is_from_me).take(20) {
+            store
+                .update_message_translation(&message.id, Some("Hi"), Some("Italian"))
+                .unwrap();
+        }
+        assert_eq!(
+            store.get_cached_conversation_language(chats[0]).unwrap(),
+            full_language(&store, chats[0])
         );
 
-        Ok(())
+        MessageStore::invalidate_language_cache(&store.conn.lock().unwrap(), chats[1]).unwrap();
+        assert_eq!(cached_language_columns(&store, chats[1]), (None, None));
+        assert_eq!(
+            store.get_cached_conversation_language(chats[1]).unwrap(),
+            full_language(&store, chats[1])
+        );
     }
 
-    /// Get count of outgoing messages (for determining if style profile needs refresh)
-    pub fn get_outgoing_message_count(&self, contact_id: Option<&str>) -> Result<i32> {
-        let conn = self.conn.lock().unwrap();
+    #[test]
+    fn test_conversation_language_cache_backfill() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MessageStore::new(dir.path()).unwrap();
+        let chat = "a@s.whatsapp.net";
+        store
+            .upsert_contact(chat, None, None, Some("private"), 1)
+            .unwrap();
+        store
+            .upsert_contact("empty@s.whatsapp.net", None, None, Some("private"), 1)
+            .unwrap();
+        for (i, language) in ["French", "Spanish", "French"].iter().enumerate() {
+            store
+                .add_message(&language_message(&format!("m{}", i), chat, Some(language)))
+                .unwrap();
+        }
 
-        let count: i32 = if let Some(cid) = contact_id {
-            conn.query_row(
-                "SELECT COUNT(*) FROM messages WHERE is_from_me = 1 AND contact_id = ?",
-                params![cid],
-                |row| row.get(0),
-            )?
-        } else {
-            conn.query_row(
-                "SELECT COUNT(*) FROM messages WHERE is_from_me = 1",
+        // Simulate a database from before the cache existed
+        store
+            .conn
+            .lock()
+            .unwrap()
+            .execute_batch(
+                r#"
+                ALTER TABLE contacts DROP COLUMN conversation_language;
+                ALTER TABLE contacts DROP COLUMN language_message_count;
+                "#,
+            )
+            .unwrap();
+        drop(store);
+
+        let store = MessageStore::new(dir.path()).unwrap();
+        assert_eq!(
+            cached_language_columns(&store, chat),
+            (Some("French".to_string()), Some(2))
+        );
+        assert_eq!(
+            cached_language_columns(&store, "empty@s.whatsapp.net"),
+            (None, Some(0))
+        );
+    }
+
+    #[test]
+    fn test_channel_contacts_are_reclassified() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MessageStore::new(dir.path()).unwrap();
+        let channel = "120363012345678901@newsletter";
+
+        // Channels used to be stored as private chats with a bogus phone
+        store
+            .upsert_contact(
+                channel,
+                Some("Le Monde"),
+                Some("120363012345678901"),
+                Some("private"),
+                1,
+            )
+            .unwrap();
+        let mut post = text_message("p1", channel, 1, "hi");
+        post.content_json =
+            r#"{"type":"text","body":"Ce que l'on sait de la réforme des retraites, point par point"}"#
+                .to_string();
+        store.add_message(&post).unwrap();
+        drop(store);
+
+        let store = MessageStore::new(dir.path()).unwrap();
+        let contact = store.get_contact(channel).unwrap().unwrap();
+        assert_eq!(contact.contact_type.as_deref(), Some("channel"));
+        assert_eq!(contact.phone, None);
+
+        let contacts = store.get_contacts().unwrap();
+        assert_eq!(
+            contacts[0].last_message_preview.as_deref(),
+            Some("Ce que l'on sait de la réforme des retraites, poin")
+        );
+        let chat_type: String = store
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT chat_type FROM messages WHERE id = 'p1'",
                 [],
                 |row| row.get(0),
-            )?
+            )
+            .unwrap();
+        assert_eq!(chat_type, "channel");
+    }
+
+    #[test]
+    fn test_read_only_buffers_messages_until_recovery() {
+        let (store, _dir) = test_store();
+        let chat = "a@s.whatsapp.net";
+        let count = |store: &MessageStore| -> i64 {
+            store
+                .conn
+                .lock()
+                .unwrap()
+                .query_row("SELECT COUNT(*) FROM messages", [], |row| row.get(0))
+                .unwrap()
         };
 
-        Ok(count)
+        store.write_protection.update(Some(0));
+        assert!(store.is_read_only());
+        let mut incoming = text_message("m1", chat, 1, "hi");
+        incoming.contact_name = Some("Camille".to_string());
+        store.buffer_message(&incoming, true);
+        store
+            .add_message(&text_message("m2", chat, 2, "hi"))
+            .unwrap();
+        assert_eq!(count(&store), 0);
+        assert_eq!(store.disk_status().buffered_messages, 2);
+
+        store.write_protection.update(Some(1 << 40));
+        store.flush_buffered_messages();
+        assert_eq!(count(&store), 2);
+        assert_eq!(store.disk_status().buffered_messages, 0);
+        let contact = store.get_contact(chat).unwrap().unwrap();
+        assert_eq!(contact.name.as_deref(), Some("Camille"));
+        assert_eq!(contact.unread_count, 1);
     }
 
-    /// Get outgoing messages for style analysis
-    /// If contact_id is None, gets messages across all contacts (for global profile)
-    /// Returns messages with text content, ordered by timestamp descending
-    pub fn get_outgoing_messages_for_style(
-        &self,
-        contact_id: Option<&str>,
-        limit: usize,
-    ) -> Result<Vec<StoredMessage>> {
-        let conn = self.conn.lock().unwrap();
+    #[test]
+    fn test_disk_errors_spill_and_recover() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MessageStore::new(dir.path()).unwrap();
+        let chat = "a@s.whatsapp.net";
+        let set_failing = |failing: bool| {
+            // Writes fail with SQLITE_READONLY, as on a filesystem remounted read-only
+            store
+                .conn
+                .lock()
+                .unwrap()
+                .pragma_update(None, "query_only", failing)
+                .unwrap();
+        };
+        let stored_ids = |store: &MessageStore| -> Vec<String> {
+            let conn = store.conn.lock().unwrap();
+            let mut stmt = conn
+                .prepare("SELECT id FROM messages ORDER BY sort_key")
+                .unwrap();
+            stmt.query_map([], |row| row.get(0))
+                .unwrap()
+                .map(|r| r.unwrap())
+                .collect()
+        };
 
-        let query = if contact_id.is_some() {
-            r#"
-            SELECT id, contact_id, timestamp, is_from_me, is_forwarded, sender_name,
-                   sender_phone, chat_type, content_type, content_json, original_text,
-                   translated_text, source_language, is_translated
-            FROM messages
-            WHERE is_from_me = 1 
-              AND contact_id = ?
-              AND content_type = 'Text'
-              AND (original_text IS NOT NULL OR content_json LIKE '%"body"%')
-            ORDER BY timestamp DESC
-            LIMIT ?
-            "#
-        } else {
-            r#"
-            SELECT id, contact_id, timestamp, is_from_me, is_forwarded, sender_name,
-                   sender_phone, chat_type, content_type, content_json, original_text,
-                   translated_text, source_language, is_translated
-            FROM messages
-            WHERE is_from_me = 1 
-              AND content_type = 'Text'
-              AND (original_text IS NOT NULL OR content_json LIKE '%"body"%')
-            ORDER BY timestamp DESC
-            LIMIT ?
-            "#
+        set_failing(true);
+        let change = store
+            .upsert_contact(chat, Some("Camille"), None, Some("private"), 1000)
+            .unwrap();
+        assert_eq!(change, ContactChange::Unchanged);
+        let mut first = text_message("m1", chat, 2000, "hi");
+        first.content_json = r#"{"type":"text","body":"premier"}"#.to_string();
+        assert_eq!(store.add_message(&first).unwrap(), None);
+        store
+            .add_message(&text_message("m2", chat, 2000, "hi"))
+            .unwrap();
+        assert_eq!(store.disk_status().spilled_writes, 3);
+        assert!(stored_ids(&store).is_empty());
+
+        // The third error in a row stops writes altogether
+        assert!(store.is_read_only());
+        store.write_protection.update(Some(1 << 40));
+        let usage = UsageInfo {
+            input_tokens: 7,
+            ..Default::default()
+        };
+        store
+            .record_usage(Some(chat), Some("m1"), &usage, "translate")
+            .unwrap();
+        store.flush_usage();
+        assert_eq!(store.disk_status().spilled_writes, 4);
+
+        // Nothing is lost while the disk still fails
+        assert!(store.recover_spilled().is_err());
+        assert_eq!(store.disk_status().spilled_writes, 4);
+
+        // The journal survives a restart and is replayed in order
+        set_failing(false);
+        let reopened = MessageStore::new(dir.path()).unwrap();
+        assert_eq!(reopened.disk_status().spilled_writes, 4);
+        assert_eq!(reopened.recover_spilled().unwrap(), 4);
+        assert_eq!(reopened.disk_status().spilled_writes, 0);
+        assert!(!dir.path().join(crate::spill::SPILL_FILE).exists());
+        assert_eq!(stored_ids(&reopened), ["m1", "m2"]);
+        let m1 = reopened.get_message_by_id("m1").unwrap().unwrap();
+        assert_eq!(m1.content_json, r#"{"type":"text","body":"premier"}"#);
+        let contact = reopened.get_contact(chat).unwrap().unwrap();
+        assert_eq!(contact.name.as_deref(), Some("Camille"));
+        assert_eq!(reopened.get_global_usage().unwrap().input_tokens, 7);
+
+        // Replaying a message that did get stored changes nothing
+        reopened
+            .spill
+            .append(&SpilledWrite::message(&first))
+            .unwrap();
+        assert_eq!(reopened.recover_spilled().unwrap(), 1);
+        assert_eq!(stored_ids(&reopened), ["m1", "m2"]);
+
+        // So is replaying usage again, as when the journal couldn't be
+        // cleared after its rows were written
+        let spilled = SpilledWrite::Usage {
+            spill_id: Some("usage-1".to_string()),
+            contact_id: Some(chat.to_string()),
+            message_id: Some("m2".to_string()),
+            usage: UsageInfo {
+                input_tokens: 5,
+                ..Default::default()
+            },
+            operation: "translate".to_string(),
+            timestamp: 3,
         };
+        for _ in 0..2 {
+            reopened.spill.append(&spilled).unwrap();
+            assert_eq!(reopened.recover_spilled().unwrap(), 1);
+        }
+        assert_eq!(reopened.get_global_usage().unwrap().input_tokens, 12);
+    }
+
+    #[test]
+    fn test_usage_survives_shutdown() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MessageStore::new(dir.path()).unwrap();
+        let clone = store.clone();
+
+        for i in 0..120 {
+            clone
+                .record_usage(Some("a@s.whatsapp.net"), None, &usage(i), "translate")
+                .unwrap();
+        }
+        drop(clone);
+        drop(store);
+
+        let store = MessageStore::new(dir.path()).unwrap();
+        assert_eq!(usage_rows(&store), 120);
+        assert_eq!(
+            store.get_global_usage().unwrap().input_tokens,
+            (0..120).sum::<u32>()
+        );
+    }
+
+    #[test]
+    fn test_latency_percentiles() {
+        let stats = LatencyStats::from_samples("op".to_string(), (1..=100).rev().collect());
+        assert_eq!(stats.count, 100);
+        assert_eq!(stats.p50_ms, 50);
+        assert_eq!(stats.p95_ms, 95);
+        assert_eq!(stats.max_ms, 100);
+        assert_eq!(stats.avg_ms, 50);
+
+        let single = LatencyStats::from_samples("op".to_string(), vec![42]);
+        assert_eq!((single.p50_ms, single.p95_ms), (42, 42));
+    }
+
+    fn image_message(id: &str, contact_id: &str, file_hash: Option<&str>) -> StoredMessage {
+        let mut content = serde_json::json!({
+            "type": "image",
+            "caption": null,
+            "mime_type": "image/jpeg",
+            "file_size": 4,
+            "media_data": "3q2+7w==",
+        });
+        if let Some(hash) = file_hash {
+            content["file_hash"] = serde_json::json!(hash);
+        }
+        StoredMessage {
+            content_type: "Image".to_string(),
+            content_json: content.to_string(),
+            original_text: None,
+            ..text_message(id, contact_id, 1, "hi")
+        }
+    }
+
+    fn blob_refs(store: &MessageStore) -> Vec<(String, i64)> {
+        let conn = store.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT hash, ref_count FROM media_blobs ORDER BY hash")
+            .unwrap();
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .filter_map(|r| r.ok())
+            .collect();
+        rows
+    }
 
-        let mut stmt = conn.prepare(query)?;
+    #[test]
+    fn test_media_dedup_across_chats() {
+        let (store, _dir) = test_store();
+        let chats = ["1@s.whatsapp.net", "2@s.whatsapp.net", "3@g.us"];
+        for (i, chat) in chats.iter().enumerate() {
+            store.upsert_contact(chat, None, None, None, 1).unwrap();
+            store
+                .add_message(&image_message(&format!("m{}", i), chat, Some("ABCD")))
+                .unwrap();
+        }
+        // Re-delivery of a stored message doesn't take another reference
+        store
+            .add_message(&image_message("m0", chats[0], Some("ABCD")))
+            .unwrap();
+
+        assert_eq!(blob_refs(&store), vec![("abcd".to_string(), 3)]);
+        {
+            let conn = store.conn.lock().unwrap();
+            let embedded: i64 = conn
+                .query_row(
+                    "SELECT COUNT(*) FROM messages WHERE content_json LIKE '%media_data%'",
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(embedded, 0);
+        }
 
-        let messages: Vec<StoredMessage> = if let Some(cid) = contact_id {
-            stmt.query_map(params![cid, limit as i64], |row| {
-                Self::row_to_stored_message(row, None, None)
-            })?
-            .filter_map(|r| r.ok())
-            .collect()
-        } else {
-            stmt.query_map(params![limit as i64], |row| {
-                Self::row_to_stored_message(row, None, None)
-            })?
-            .filter_map(|r| r.ok())
-            .collect()
+        // Media is served from the blob, both lazily and in full message reads
+        let (data, mime) = store.get_message_media("m1").unwrap().unwrap();
+        assert_eq!(data, "3q2+7w==");
+        assert_eq!(mime.as_deref(), Some("image/jpeg"));
+        let full = store.get_messages(chats[1]).unwrap();
+        assert_eq!(full[0].content.as_ref().unwrap()["media_data"], "3q2+7w==");
+        let stripped = store
+            .get_messages_paginated(chats[1], None, None, None, true, MessageFilter::default())
+            .unwrap();
+        let content = stripped[0].content.as_ref().unwrap();
+        assert_eq!(content["has_media"], true);
+        assert!(content.get("media_data").is_none());
+
+        // Deleting one copy keeps the blob for the other chats
+        assert!(!store.delete_message(chats[1], "m0").unwrap());
+        assert!(store.delete_message(chats[1], "m1").unwrap());
+        assert_eq!(blob_refs(&store), vec![("abcd".to_string(), 2)]);
+        assert!(store.get_message_media("m2").unwrap().is_some());
+
+        // The last reference garbage-collects the blob
+        assert!(store.delete_message(chats[0], "m0").unwrap());
+        assert!(store.delete_message(chats[2], "m2").unwrap());
+        assert!(blob_refs(&store).is_empty());
+    }
+
+    #[test]
+    fn test_clear_conversation() {
+        let (store, _dir) = test_store();
+        let chat = "1@s.whatsapp.net";
+        let other = "2@s.whatsapp.net";
+        for contact in [chat, other] {
+            store.upsert_contact(contact, None, None, None, 1).unwrap();
+        }
+        store
+            .add_message(&image_message("shared-1", chat, Some("AAAA")))
+            .unwrap();
+        store
+            .add_message(&image_message("shared-2", chat, Some("AAAA")))
+            .unwrap();
+        store
+            .add_message(&image_message("own", chat, Some("BBBB")))
+            .unwrap();
+        store
+            .add_message(&language_message("es", chat, Some("Spanish")))
+            .unwrap();
+        store
+            .add_message(&image_message("other", other, Some("AAAA")))
+            .unwrap();
+        store
+            .record_usage(Some(chat), None, &usage(10), "translate")
+            .unwrap();
+        store
+            .record_usage(Some(other), None, &usage(10), "translate")
+            .unwrap();
+        store.flush_usage();
+        assert_eq!(
+            store.get_cached_conversation_language(chat).unwrap(),
+            Some("Spanish".to_string())
+        );
+
+        assert!(store
+            .clear_conversation("missing@s.whatsapp.net", false)
+            .unwrap()
+            .is_none());
+        let cleared = store.clear_conversation(chat, false).unwrap().unwrap();
+        assert_eq!(
+            (
+                cleared.messages,
+                cleared.media_blobs_freed,
+                cleared.usage_records
+            ),
+            (4, 1, 0)
+        );
+
+        // No orphaned rows: the shared blob keeps only the other chat's reference
+        assert!(store.get_messages(chat).unwrap().is_empty());
+        assert_eq!(blob_refs(&store), vec![("aaaa".to_string(), 1)]);
+        assert!(store.get_message_media("other").unwrap().is_some());
+        assert_eq!(usage_rows(&store), 2);
+
+        let contact = store.get_contact(chat).unwrap().unwrap();
+        assert_eq!(contact.last_message_time, 0);
+        assert_eq!(contact.unread_count, 0);
+        assert_eq!(contact.last_message_preview, None);
+        assert_eq!(store.get_cached_conversation_language(chat).unwrap(), None);
+
+        // Usage is only deleted when asked for, and only for this chat
+        let cleared = store.clear_conversation(chat, true).unwrap().unwrap();
+        assert_eq!((cleared.messages, cleared.usage_records), (0, 1));
+        assert_eq!(usage_rows(&store), 1);
+
+        // New messages after clearing are stored and counted as usual
+        store.upsert_contact(chat, None, None, None, 5000).unwrap();
+        store
+            .add_message(&language_message("fr", chat, Some("French")))
+            .unwrap();
+        store
+            .add_message(&image_message("again", chat, Some("AAAA")))
+            .unwrap();
+        assert_eq!(store.get_messages(chat).unwrap().len(), 2);
+        assert_eq!(blob_refs(&store), vec![("aaaa".to_string(), 2)]);
+        assert_eq!(
+            store.get_cached_conversation_language(chat).unwrap(),
+            Some("French".to_string())
+        );
+        assert_eq!(
+            store.get_contact(chat).unwrap().unwrap().last_message_time,
+            5000
+        );
+    }
+
+    #[test]
+    fn test_translation_history() {
+        let (store, _dir) = test_store();
+        let chat = "34600000000@s.whatsapp.net";
+        let other = "33600000000@s.whatsapp.net";
+        for contact in [chat, other] {
+            store.upsert_contact(contact, None, None, None, 1).unwrap();
+        }
+        let translated = |id: &str, contact: &str, ts: i64, from_me: bool, pair: (&str, &str)| {
+            let mut message = text_message(id, contact, ts, "hi");
+            message.is_from_me = from_me;
+            message.original_text = Some(pair.0.to_string());
+            message.translated_text = Some(pair.1.to_string());
+            message.source_language = Some("Spanish".to_string());
+            message.is_translated = true;
+            message
         };
+        // Inserted out of order, with untranslated messages and another chat mixed in
+        for message in [
+            translated(
+                "m3",
+                chat,
+                3000,
+                false,
+                ("¿Vienes mañana?", "Are you coming tomorrow?"),
+            ),
+            translated("m1", chat, 1000, false, ("Hola", "Hello")),
+            text_message("plain", chat, 1500, "hi"),
+            translated("m2", chat, 2000, true, ("Thanks!", "¡Gracias!")),
+            translated("f1", other, 2500, false, ("Bonjour", "Hello")),
+            translated("m4", chat, 4000, false, ("Hasta luego", "See you later")),
+        ] {
+            store.add_message(&message).unwrap();
+        }
 
-        Ok(messages)
+        let all = store.get_translations(chat, None, None).unwrap();
+        let ids: Vec<&str> = all.iter().map(|t| t.message_id.as_str()).collect();
+        assert_eq!(ids, ["m1", "m2", "m3", "m4"]);
+        assert_eq!(all[0].direction, "incoming");
+        assert_eq!(all[1].direction, "outgoing");
+        assert_eq!(all[1].original_text.as_deref(), Some("Thanks!"));
+        assert_eq!(all[1].translated_text.as_deref(), Some("¡Gracias!"));
+
+        // The latest page, then the one before it
+        let page = store.get_translations(chat, Some(2), None).unwrap();
+        assert_eq!(page[0].message_id, "m3");
+        assert_eq!(page[1].message_id, "m4");
+        let older = store
+            .get_translations(chat, Some(2), Some(page[0].timestamp))
+            .unwrap();
+        let ids: Vec<&str> = older.iter().map(|t| t.message_id.as_str()).collect();
+        assert_eq!(ids, ["m1", "m2"]);
+
+        let conn = store.conn.lock().unwrap();
+        let plan: Vec<String> = conn
+            .prepare(
+                r#"
+                EXPLAIN QUERY PLAN
+                SELECT id FROM messages
+                WHERE contact_id = ?1 AND is_translated = 1 AND (?2 IS NULL OR timestamp < ?2)
+                ORDER BY timestamp DESC LIMIT ?3
+                "#,
+            )
+            .unwrap()
+            .query_map(params![chat, None::<i64>, 10], |row| row.get(3))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert!(
+            plan.iter()
+                .any(|step| step.contains("idx_messages_translated")),
+            "{:?}",
+            plan
+        );
     }
 
-    /// Get message exchange pairs for style learning
-    /// Returns pairs of (incoming message, my reply) to show conversation patterns
-    /// This helps AI learn not just what you say, but how you respond to different inputs
-    pub fn get_message_exchange_pairs(
-        &self,
-        contact_id: &str,
-        limit: usize,
-    ) -> Result<Vec<(StoredMessage, StoredMessage)>> {
-        let conn = self.conn.lock().unwrap();
+    #[test]
+    fn test_drafts() {
+        let (store, _dir) = test_store();
+        let chat = "34600000000@s.whatsapp.net";
+        store.upsert_contact(chat, None, None, None, 1).unwrap();
+        let draft = |text: &str, updated_at: i64| Draft {
+            contact_id: chat.to_string(),
+            text: text.to_string(),
+            reply_to_message_id: None,
+            updated_at,
+        };
 
-        // Get recent messages for this contact, ordered by timestamp
-        let query = r#"
-            SELECT id, contact_id, timestamp, is_from_me, is_forwarded, sender_name,
-                   sender_phone, chat_type, content_type, content_json, original_text,
-                   translated_text, source_language, is_translated
-            FROM messages
-            WHERE contact_id = ?
-              AND content_type = 'Text'
-              AND (original_text IS NOT NULL OR content_json LIKE '%"body"%')
-            ORDER BY timestamp DESC
-            LIMIT ?
-        "#;
+        assert_eq!(store.save_draft(&draft("Hola, ¿qué", 1000)).unwrap(), None);
+        assert_eq!(
+            store.save_draft(&draft("Hola, ¿qué tal?", 2000)).unwrap(),
+            None
+        );
 
-        let mut stmt = conn.prepare(query)?;
-        let messages: Vec<StoredMessage> = stmt
-            .query_map(params![contact_id, (limit * 3) as i64], |row| {
-                Self::row_to_stored_message(row, None, None)
-            })?
-            .filter_map(|r| r.ok())
-            .collect();
+        // A session editing an older copy is told about the newer draft
+        assert_eq!(
+            store.save_draft(&draft("Hola", 1500)).unwrap(),
+            Some(draft("Hola, ¿qué tal?", 2000))
+        );
+        assert_eq!(
+            store.delete_draft(chat, Some(1500)).unwrap(),
+            Some(draft("Hola, ¿qué tal?", 2000))
+        );
+        assert_eq!(
+            store.get_draft(chat).unwrap(),
+            Some(draft("Hola, ¿qué tal?", 2000))
+        );
 
-        // Find pairs: look for my messages that follow their messages
-        let mut pairs = Vec::new();
-        let messages_sorted: Vec<_> = messages.into_iter().rev().collect(); // chronological order
+        // Receiving a message or storing one from the phone keeps the draft
+        let mut incoming = text_message("in", chat, 2500, "hi");
+        store.add_message(&incoming).unwrap();
+        incoming.id = "phone".to_string();
+        incoming.is_from_me = true;
+        store.add_message(&incoming).unwrap();
+        assert!(store.get_draft(chat).unwrap().is_some());
+
+        // Sending from here clears it
+        let mut sent = text_message("sent", chat, 3000, "hi");
+        sent.is_from_me = true;
+        sent.origin = Some("mcp".to_string());
+        store.add_message(&sent).unwrap();
+        assert_eq!(store.get_draft(chat).unwrap(), None);
+
+        store.save_draft(&draft("Adiós", 4000)).unwrap();
+        store.clear_all().unwrap();
+        assert_eq!(store.get_draft(chat).unwrap(), None);
+    }
 
-        for i in 1..messages_sorted.len() {
-            let prev = &messages_sorted[i - 1];
-            let curr = &messages_sorted[i];
+    #[test]
+    fn test_image_thumbnails_in_listing() {
+        let (store, _dir) = test_store();
+        let chat = "1@s.whatsapp.net";
+        store.upsert_contact(chat, None, None, None, 1).unwrap();
+
+        let photo = image::DynamicImage::ImageRgb8(image::ImageBuffer::from_pixel(
+            800,
+            600,
+            image::Rgb([200, 30, 30]),
+        ));
+        let mut jpeg = std::io::Cursor::new(Vec::new());
+        photo.write_to(&mut jpeg, image::ImageFormat::Jpeg).unwrap();
+        let contents = [
+            (
+                "photo",
+                serde_json::json!({"type": "image", "mime_type": "image/jpeg", "media_data": STANDARD.encode(jpeg.get_ref())}),
+            ),
+            (
+                "webp",
+                serde_json::json!({"type": "image", "mime_type": "image/webp", "media_data": STANDARD.encode(b"RIFF\x00\x00\x00\x00WEBPVP8 ")}),
+            ),
+            (
+                "corrupt",
+                serde_json::json!({"type": "image", "mime_type": "image/jpeg", "media_data": STANDARD.encode(b"\xff\xd8\xff\xe0 truncated")}),
+            ),
+            (
+                "sticker",
+                serde_json::json!({"type": "sticker", "mime_type": "image/webp", "media_data": "UklGRg=="}),
+            ),
+        ];
+        for (i, (id, content)) in contents.iter().enumerate() {
+            let mut msg = text_message(id, chat, i as i64, "hi");
+            msg.content_json = content.to_string();
+            store.add_message(&msg).unwrap();
+        }
 
-            // If previous message is from them and current is from me, it's an exchange
-            if !prev.is_from_me && curr.is_from_me {
-                pairs.push((prev.clone(), curr.clone()));
-                if pairs.len() >= limit {
-                    break;
-                }
+        let messages = store
+            .get_messages_paginated(chat, None, None, None, true, MessageFilter::default())
+            .unwrap();
+        let content = |i: usize| messages[i].content.clone().unwrap();
+
+        let thumbnail = STANDARD
+            .decode(content(0)["thumbnail_data"].as_str().unwrap())
+            .unwrap();
+        let thumbnail = image::load_from_memory(&thumbnail).unwrap();
+        assert_eq!((thumbnail.width(), thumbnail.height()), (320, 240));
+        assert!(content(0).get("media_data").is_none());
+
+        // Unsupported or broken images are flagged with a null thumbnail
+        assert!(content(1)["thumbnail_data"].is_null());
+        assert!(content(2)["thumbnail_data"].is_null());
+        assert_eq!(content(2)["has_media"], true);
+        // Stickers carry their preview too, null when there's none
+        assert!(content(3)["thumbnail_data"].is_null());
+
+        // The full image is still served on demand
+        let (data, _) = store.get_message_media("photo").unwrap().unwrap();
+        assert_eq!(data, STANDARD.encode(jpeg.get_ref()));
+    }
+
+    #[test]
+    fn test_media_migration_dedups_existing_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MessageStore::new(dir.path()).unwrap();
+        store
+            .upsert_contact("1@s.whatsapp.net", None, None, None, 1)
+            .unwrap();
+
+        // Recreate a database from before media_blobs existed, with embedded media
+        {
+            let conn = store.conn.lock().unwrap();
+            conn.execute_batch(
+                r#"
+                DROP TABLE media_blobs;
+                DROP INDEX idx_messages_media_hash;
+                ALTER TABLE messages DROP COLUMN media_hash;
+                "#,
+            )
+            .unwrap();
+            for id in ["a", "b"] {
+                let msg = image_message(id, "1@s.whatsapp.net", None);
+                conn.execute(
+                    "INSERT INTO messages (id, contact_id, timestamp, is_from_me, content_type, content_json) VALUES (?1, ?2, 1, 0, ?3, ?4)",
+                    params![msg.id, msg.contact_id, msg.content_type, msg.content_json],
+                )
+                .unwrap();
             }
         }
+        drop(store);
 
-        Ok(pairs)
+        let store = MessageStore::new(dir.path()).unwrap();
+        // Hash computed from the decoded bytes de ad be ef
+        let hash = "5f78c33274e43fa9de5659265c1d917e25c03722dcb0b8d27db8d5feaa813953";
+        assert_eq!(blob_refs(&store), vec![(hash.to_string(), 2)]);
+        assert_eq!(store.get_message_media("b").unwrap().unwrap().0, "3q2+7w==");
     }
 
-    /// Helper to convert a row to StoredMessage (used by multiple methods)
-    fn row_to_stored_message(
-        row: &rusqlite::Row,
-        contact_name: Option<String>,
-        contact_phone: Option<String>,
-    ) -> rusqlite::Result<StoredMessage> {
-        let content_json: String = row.get(9)?;
-        let content = serde_json::from_str(&content_json).ok();
+    #[test]
+    fn test_search_chat() {
+        use crate::chat_search::TextSource;
+
+        let (store, _dir) = test_store();
+        let chat = "34600000000@s.whatsapp.net";
+        store.upsert_contact(chat, None, None, None, 1).unwrap();
+
+        let mut incoming = text_message("in", chat, 1, "hi");
+        incoming.original_text = Some("¿Dónde está el café?".to_string());
+        incoming.translated_text = Some("Where is the CAFÉ? The cafe!".to_string());
+        incoming.is_translated = true;
+        store.add_message(&incoming).unwrap();
+
+        let mut mine = text_message("mine", chat, 2, "hi");
+        mine.is_from_me = true;
+        mine.original_text = Some("I love the café".to_string());
+        mine.translated_text = Some("Me encanta el café".to_string());
+        mine.is_translated = true;
+        store.add_message(&mine).unwrap();
+
+        let mut untranslated = text_message("plain", chat, 3, "hi");
+        untranslated.original_text = None;
+        untranslated.content_json = r#"{"type":"text","body":"Café con leche"}"#.to_string();
+        store.add_message(&untranslated).unwrap();
+        let other = "34600000001@s.whatsapp.net";
+        store.upsert_contact(other, None, None, None, 1).unwrap();
+        let mut elsewhere = text_message("other", other, 4, "hi");
+        elsewhere.original_text = Some("café".to_string());
+        store.add_message(&elsewhere).unwrap();
+
+        let page = store.search_chat(chat, "Cafe", 50, None).unwrap();
+        let ids: Vec<&str> = page.hits.iter().map(|h| h.message_id.as_str()).collect();
+        assert_eq!(ids, ["plain", "mine", "in"]);
+        assert_eq!(page.total_matches, 4);
+        assert!(page.next_cursor.is_none());
+        let incoming_hit = &page.hits[2];
+        assert_eq!(incoming_hit.text_source, TextSource::Translated);
+        assert_eq!(incoming_hit.matches.len(), 2);
+        assert_eq!(incoming_hit.matches[0].char_start, 13);
+        assert_eq!(page.hits[1].text_source, TextSource::Original);
+
+        // Only the text the chat shows is searched
+        assert!(store
+            .search_chat(chat, "dónde", 50, None)
+            .unwrap()
+            .hits
+            .is_empty());
+        assert!(store
+            .search_chat(chat, "encanta", 50, None)
+            .unwrap()
+            .hits
+            .is_empty());
+        assert_eq!(
+            store
+                .search_chat(chat, "love", 50, None)
+                .unwrap()
+                .hits
+                .len(),
+            1
+        );
 
-        Ok(StoredMessage {
-            id: row.get(0)?,
-            contact_id: row.get(1)?,
-            timestamp: row.get(2)?,
-            is_from_me: row.get::<_, i32>(3)? != 0,
-            is_forwarded: row.get::<_, i32>(4).unwrap_or(0) != 0,
-            sender_name: row.get(5)?,
-            sender_phone: row.get(6)?,
-            contact_name,
-            contact_phone,
-            chat_type: row
-                .get::<_, Option<String>>(7)?
-                .unwrap_or_else(|| "private".to_string()),
-            content_type: row.get(8)?,
-            content_json,
-            content,
-            original_text: row.get(10)?,
-            translated_text: row.get(11)?,
-            source_language: row.get(12)?,
-            is_translated: row.get::<_, i32>(13).unwrap_or(0) != 0,
-        })
+        // Paging keeps the chat-wide total
+        let first = store.search_chat(chat, "cafe", 2, None).unwrap();
+        assert_eq!(first.hits.len(), 2);
+        let second = store
+            .search_chat(chat, "cafe", 2, first.next_cursor)
+            .unwrap();
+        assert_eq!(second.hits.len(), 1);
+        assert_eq!(second.hits[0].message_id, "in");
+        assert_eq!(second.total_matches, 4);
+        assert!(second.next_cursor.is_none());
     }
 
-    /// Get a specific message by ID
-    pub fn get_message_by_id(&self, message_id: &str) -> Result<Option<StoredMessage>> {
-        let conn = self.conn.lock().unwrap();
+    #[test]
+    fn test_translation_status_migration_and_requeue() {
+        use TranslationStatus::*;
+
+        let dir = tempfile::tempdir().unwrap();
+        let store = MessageStore::new(dir.path()).unwrap();
+        let chat = "34600000000@s.whatsapp.net";
+        store.upsert_contact(chat, None, None, None, 1).unwrap();
+        let mut translated = text_message("a", chat, 1, "hi");
+        translated.is_translated = true;
+        store.add_message(&translated).unwrap();
+        store
+            .add_message(&text_message("b", chat, 2, "hi"))
+            .unwrap();
+
+        // Existing rows are backfilled from is_translated
+        {
+            let conn = store.conn.lock().unwrap();
+            conn.execute_batch(
+                r#"
+                DROP INDEX idx_messages_translation_status;
+                ALTER TABLE messages DROP COLUMN translation_status;
+                "#,
+            )
+            .unwrap();
+        }
+        drop(store);
+        let store = MessageStore::new(dir.path()).unwrap();
+        let status = |id: &str| {
+            store
+                .get_message_by_id(id)
+                .unwrap()
+                .unwrap()
+                .translation_status
+        };
+        assert_eq!(status("a"), Some(Translated));
+        assert_eq!(status("b"), Some(NotNeeded));
+
+        for (id, timestamp, translation_status) in
+            [("c", 3, Error), ("d", 4, Pending), ("e", 5, SkippedShort)]
+        {
+            let mut msg = text_message(id, chat, timestamp, "hi");
+            msg.translation_status = Some(translation_status);
+            store.add_message(&msg).unwrap();
+        }
+        let mut mine = text_message("f", chat, 6, "hi");
+        mine.is_from_me = true;
+        mine.translation_status = Some(Error);
+        store.add_message(&mine).unwrap();
 
-        let result = conn.query_row(
-            r#"
-            SELECT m.id, m.contact_id, m.timestamp, m.is_from_me, m.is_forwarded, m.sender_name,
-                   m.sender_phone, m.chat_type, m.content_type, m.content_json, m.original_text,
-                   m.translated_text, m.source_language, m.is_translated,
-                   c.name as contact_name, c.phone as contact_phone
-            FROM messages m
-            LEFT JOIN contacts c ON m.contact_id = c.id
-            WHERE m.id = ?
-            "#,
-            params![message_id],
-            |row| {
-                let contact_name: Option<String> = row.get(14)?;
-                let contact_phone: Option<String> = row.get(15)?;
-                Self::row_to_stored_message(row, contact_name, contact_phone)
-            },
+        let counts: HashMap<_, _> = store
+            .get_translation_status_counts(Some(chat))
+            .unwrap()
+            .into_iter()
+            .collect();
+        assert_eq!(counts.len(), TranslationStatus::ALL.len());
+        assert_eq!(counts[&Error], 1);
+        assert_eq!(counts[&SkippedBudget], 0);
+
+        // Only incoming errors and pending ones are queued again
+        let queued: Vec<_> = store
+            .requeue_untranslated(chat)
+            .unwrap()
+            .into_iter()
+            .map(|m| m.id)
+            .collect();
+        assert_eq!(queued, ["c", "d"]);
+        assert_eq!(status("c"), Some(Pending));
+        assert_eq!(status("f"), Some(Error));
+    }
+
+    #[test]
+    fn test_reactions_keep_each_persons_latest() {
+        let (store, _dir) = test_store();
+        let chat = "34600000000@s.whatsapp.net";
+        let reaction = |id: &str, from: Option<&str>, emoji: &str, timestamp: i64| {
+            let mut msg = text_message(id, chat, timestamp, "hi");
+            msg.is_from_me = from.is_none();
+            msg.sender_phone = from.map(str::to_string);
+            msg.content_type = "Reaction".to_string();
+            msg.content_json = serde_json::json!({
+                "type": "reaction",
+                "emoji": emoji,
+                "target_message_id": "target",
+            })
+            .to_string();
+            msg
+        };
+        store.upsert_contact(chat, None, None, None, 1).unwrap();
+        store
+            .add_message(&text_message("target", chat, 1, "hi"))
+            .unwrap();
+        store
+            .add_message(&reaction("r1", Some("111"), "👍", 2))
+            .unwrap();
+        store
+            .add_message(&reaction("r2", Some("222"), "👍", 3))
+            .unwrap();
+        store
+            .add_message(&reaction("r3", Some("111"), "❤️", 4))
+            .unwrap();
+        store
+            .add_message(&reaction("r4", Some("222"), "", 5))
+            .unwrap();
+
+        let groups = |store: &MessageStore| {
+            store
+                .get_reactions(chat, &["target", "other"])
+                .unwrap()
+                .remove("target")
+                .unwrap_or_default()
+        };
+        assert_eq!(
+            groups(&store),
+            vec![ReactionGroup {
+                emoji: "❤️".to_string(),
+                count: 1,
+                from_me: false,
+                reactors: vec!["111".to_string()],
+            }]
         );
 
-        match result {
-            Ok(msg) => Ok(Some(msg)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
+        // My reaction replaces my previous one
+        assert_eq!(
+            store
+                .set_own_reaction(chat, "target", Some(&reaction("mine", None, "❤️", 6)))
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            store
+                .set_own_reaction(chat, "target", Some(&reaction("mine2", None, "😂", 7)))
+                .unwrap(),
+            Some("❤️".to_string())
+        );
+        let current = groups(&store);
+        assert_eq!(current.len(), 2);
+        assert!(current.iter().any(|g| g.emoji == "😂" && g.from_me));
+
+        assert_eq!(
+            store.set_own_reaction(chat, "target", None).unwrap(),
+            Some("😂".to_string())
+        );
+        assert_eq!(groups(&store).len(), 1);
+    }
+
+    #[test]
+    fn test_vocabulary_counts_terms_across_chat() {
+        let (store, _dir) = test_store();
+        let chat = "34600000000@s.whatsapp.net";
+        let entry = |term: &str, gloss: &str| VocabEntry {
+            term: term.to_string(),
+            gloss: gloss.to_string(),
+        };
+        store.upsert_contact(chat, None, None, None, 1).unwrap();
+        assert!(!store.get_conversation_settings(chat).unwrap().learning_mode);
+
+        let mut first = text_message("m1", chat, 1, "hi");
+        first.vocabulary = Some(vec![entry("playa", "beach"), entry("mañana", "tomorrow")]);
+        let mut second = text_message("m2", chat, 2, "hi");
+        second.vocabulary = Some(vec![entry("Playa", "beach, shore")]);
+        for msg in [&first, &second, &text_message("m3", chat, 3, "hi")] {
+            store.add_message(msg).unwrap();
         }
+
+        let vocabulary = store.get_vocabulary(chat).unwrap();
+        assert_eq!(vocabulary.len(), 2);
+        assert_eq!(vocabulary[0].term, "playa");
+        assert_eq!(vocabulary[0].gloss, "beach, shore");
+        assert_eq!(vocabulary[0].count, 2);
+        assert_eq!(vocabulary[0].last_seen, 2);
+        assert_eq!(vocabulary[1].term, "mañana");
+        assert_eq!(vocabulary[1].count, 1);
+
+        let messages = store
+            .get_messages_paginated(chat, None, None, None, true, MessageFilter::default())
+            .unwrap();
+        assert_eq!(messages[0].vocabulary.as_ref().map(Vec::len), Some(2));
+        assert!(messages[2].vocabulary.is_none());
     }
 
-    /// Get recent messages for a contact (for conversation context)
-    pub fn get_recent_messages(
-        &self,
-        contact_id: &str,
-        limit: usize,
-    ) -> Result<Vec<StoredMessage>> {
-        let conn = self.conn.lock().unwrap();
+    #[test]
+    fn test_outgoing_translation_by_chat_type() {
+        let (store, _dir) = test_store();
+        let chat = "34600000000@s.whatsapp.net";
+        let group = "123456789@g.us";
+        store
+            .upsert_contact(chat, None, None, Some("private"), 1)
+            .unwrap();
+        store
+            .upsert_contact(group, None, None, Some("group"), 1)
+            .unwrap();
+        let incoming = |id: &str, contact: &str, language: &str, ts| {
+            let mut msg = text_message(id, contact, ts, "hi");
+            msg.source_language = Some(language.to_string());
+            store.add_message(&msg).unwrap();
+        };
 
-        // Get contact info
-        let contact_info: Option<(Option<String>, Option<String>)> = conn
-            .query_row(
-                "SELECT name, phone FROM contacts WHERE id = ?",
-                params![contact_id],
-                |row| Ok((row.get(0)?, row.get(1)?)),
+        // Groups are off by default, however Spanish the chat is
+        for ts in 0..20 {
+            incoming(&format!("g{}", ts), group, "Spanish", ts);
+        }
+        let outgoing = store.get_outgoing_translation(group).unwrap();
+        assert!(!outgoing.enabled);
+        assert_eq!(outgoing.reason, OutgoingTranslationReason::GroupDefault);
+        store.set_group_outgoing_translation(true).unwrap();
+        assert!(store.get_outgoing_translation(group).unwrap().enabled);
+        assert!(
+            store
+                .get_contact(group)
+                .unwrap()
+                .unwrap()
+                .auto_translate_outgoing
+        );
+        store.set_group_outgoing_translation(false).unwrap();
+
+        // A contact's own setting wins over the default, until it's cleared
+        store.set_outgoing_translation(group, Some(true)).unwrap();
+        let outgoing = store.get_outgoing_translation(group).unwrap();
+        assert!(outgoing.enabled);
+        assert_eq!(outgoing.reason, OutgoingTranslationReason::ContactSetting);
+        assert_eq!(outgoing.contact_setting, Some(true));
+        assert!(!store.toggle_outgoing_translation(group).unwrap());
+        store.set_outgoing_translation(group, None).unwrap();
+        let outgoing = store.get_outgoing_translation(group).unwrap();
+        assert_eq!(outgoing.reason, OutgoingTranslationReason::GroupDefault);
+        assert_eq!(outgoing.contact_setting, None);
+
+        // Private chats need 70% of the last 20 incoming messages in the
+        // chat's language: 14 Spanish then 6 English is just enough...
+        for ts in 0..14 {
+            incoming(&format!("a{}", ts), chat, "Spanish", 100 + ts);
+        }
+        for ts in 0..6 {
+            incoming(&format!("b{}", ts), chat, "English", 200 + ts);
+        }
+        let confidence = store.get_language_confidence(chat).unwrap();
+        assert_eq!(confidence.language.as_deref(), Some("Spanish"));
+        assert_eq!((confidence.matching, confidence.sampled), (14, 20));
+        let outgoing = store.get_outgoing_translation(chat).unwrap();
+        assert!(outgoing.enabled);
+        assert_eq!(outgoing.reason, OutgoingTranslationReason::Confident);
+
+        // ...and one more English message pushes the oldest Spanish one out
+        // of the window
+        incoming("c0", chat, "English", 300);
+        let confidence = store.get_language_confidence(chat).unwrap();
+        assert_eq!((confidence.matching, confidence.sampled), (13, 20));
+        let outgoing = store.get_outgoing_translation(chat).unwrap();
+        assert!(!outgoing.enabled);
+        assert_eq!(outgoing.reason, OutgoingTranslationReason::NotConfident);
+
+        // A language override always translates
+        store
+            .update_conversation_settings(
+                chat,
+                &ConversationSettings {
+                    language_override: Some("Spanish".to_string()),
+                    ..Default::default()
+                },
             )
-            .ok();
+            .unwrap();
+        let outgoing = store.get_outgoing_translation(chat).unwrap();
+        assert_eq!(outgoing.reason, OutgoingTranslationReason::LanguageOverride);
+    }
 
-        let (contact_name, contact_phone) = contact_info.unwrap_or((None, None));
+    #[test]
+    fn test_reaction_stats_aggregate_current_reactions() {
+        let (store, _dir) = test_store();
+        let chat = "34600000000@s.whatsapp.net";
+        let group = "123456789@g.us";
+        let reaction = |id: &str, chat: &str, target: &str, from: Option<&str>, emoji: &str, ts| {
+            let mut msg = text_message(id, chat, ts, "hi");
+            msg.is_from_me = from.is_none();
+            msg.sender_phone = from.map(str::to_string);
+            msg.sender_name = from.map(|phone| format!("Person {}", phone));
+            msg.content_type = "Reaction".to_string();
+            msg.content_json = serde_json::json!({
+                "type": "reaction",
+                "emoji": emoji,
+                "target_message_id": target,
+            })
+            .to_string();
+            msg
+        };
+        store.upsert_contact(chat, None, None, None, 1).unwrap();
+        store.upsert_contact(group, None, None, None, 1).unwrap();
+        let seeded = [
+            // Too old to count
+            reaction("r0", chat, "a", Some("111"), "😢", 5),
+            reaction("r1", chat, "a", Some("111"), "👍", 10),
+            reaction("r2", chat, "b", Some("111"), "👍", 11),
+            reaction("r3", chat, "a", Some("222"), "❤️", 12),
+            // Replaced, then taken back
+            reaction("r4", chat, "b", Some("222"), "😂", 13),
+            reaction("r5", chat, "b", Some("222"), "❤️", 14),
+            reaction("r6", chat, "c", Some("222"), "😮", 15),
+            reaction("r7", chat, "c", Some("222"), "", 16),
+            reaction("r8", chat, "a", None, "😂", 17),
+            reaction("r9", chat, "b", None, "😂", 18),
+            reaction("r10", chat, "c", None, "❤️", 19),
+            reaction("r11", group, "x", Some("333"), "🙏", 20),
+        ];
+        for msg in &seeded {
+            store.add_message(msg).unwrap();
+        }
 
-        let mut stmt = conn.prepare(
-            r#"
-            SELECT id, contact_id, timestamp, is_from_me, is_forwarded, sender_name,
-                   sender_phone, chat_type, content_type, content_json, original_text,
-                   translated_text, source_language, is_translated
-            FROM messages
-            WHERE contact_id = ?
-            ORDER BY timestamp DESC
-            LIMIT ?
-            "#,
-        )?;
+        let stats = store.get_reaction_stats(chat, 10).unwrap();
+        let counts = |list: &[EmojiCount]| -> Vec<(String, u32)> {
+            list.iter().map(|e| (e.emoji.clone(), e.count)).collect()
+        };
+        assert_eq!(stats.total, 7);
+        assert_eq!(
+            counts(&stats.emojis),
+            vec![
+                ("❤️".to_string(), 3),
+                ("👍".to_string(), 2),
+                ("😂".to_string(), 2)
+            ]
+        );
+        assert_eq!(
+            stats.top_reactors,
+            vec![
+                ReactorCount {
+                    reactor: "111".to_string(),
+                    name: Some("Person 111".to_string()),
+                    count: 2,
+                },
+                ReactorCount {
+                    reactor: "222".to_string(),
+                    name: Some("Person 222".to_string()),
+                    count: 2,
+                },
+            ]
+        );
+        assert_eq!(
+            counts(&stats.my_top_reactions),
+            vec![("😂".to_string(), 2), ("❤️".to_string(), 1)]
+        );
 
-        let rows = stmt.query_map(params![contact_id, limit as i64], |row| {
-            Self::row_to_stored_message(row, contact_name.clone(), contact_phone.clone())
-        })?;
+        // An earlier start takes in the old reaction
+        assert_eq!(store.get_reaction_stats(chat, 0).unwrap().total, 7);
+        assert_eq!(store.get_reaction_stats(chat, 17).unwrap().total, 3);
 
-        // Collect and reverse to get chronological order
-        let mut messages: Vec<StoredMessage> = rows.filter_map(|r| r.ok()).collect();
-        messages.reverse();
-        Ok(messages)
+        let top = store.get_top_reactions(10).unwrap();
+        assert_eq!(top.get(chat).map(String::as_str), Some("❤️"));
+        assert_eq!(top.get(group).map(String::as_str), Some("🙏"));
     }
-}
 
-impl Clone for MessageStore {
-    fn clone(&self) -> Self {
-        Self {
-            conn: Arc::clone(&self.conn),
+    #[test]
+    fn test_triaged_messages_by_urgency_and_tone() {
+        let (store, _dir) = test_store();
+        let chat = "34600000000@s.whatsapp.net";
+        let group = "123456789@g.us";
+        store
+            .upsert_contact(chat, Some("Ana"), None, Some("private"), 1)
+            .unwrap();
+        store
+            .upsert_contact(group, None, None, Some("group"), 1)
+            .unwrap();
+        let tagged = |id: &str, contact: &str, ts, urgency, tone| {
+            let mut msg = text_message(id, contact, ts, "hi");
+            msg.triage = Triage::new(urgency, tone);
+            store.add_message(&msg).unwrap();
+        };
+        tagged("m1", chat, 1, Some(Urgency::High), Some(Tone::Negative));
+        tagged("m2", chat, 2, Some(Urgency::Normal), Some(Tone::Positive));
+        tagged("m3", group, 3, Some(Urgency::High), None);
+        tagged("m4", chat, 4, None, None);
+        let mut mine = text_message("m5", chat, 5, "hi");
+        mine.is_from_me = true;
+        mine.triage = Triage::new(Some(Urgency::High), None);
+        store.add_message(&mine).unwrap();
+
+        let ids = |messages: Vec<StoredMessage>| -> Vec<String> {
+            messages.into_iter().map(|m| m.id).collect()
+        };
+        let high = store
+            .get_triaged_messages(Urgency::High, None, None, 50)
+            .unwrap();
+        assert_eq!(high[1].contact_name.as_deref(), Some("Ana"));
+        assert_eq!(
+            high[1].triage,
+            Some(Triage {
+                urgency: Some(Urgency::High),
+                tone: Some(Tone::Negative),
+            })
+        );
+        assert_eq!(ids(high), vec!["m3", "m1"]);
+        assert_eq!(
+            ids(store
+                .get_triaged_messages(Urgency::Normal, None, None, 50)
+                .unwrap()),
+            vec!["m3", "m2", "m1"]
+        );
+        assert_eq!(
+            ids(store
+                .get_triaged_messages(Urgency::Low, Some(Tone::Negative), None, 50)
+                .unwrap()),
+            vec!["m1"]
+        );
+        assert_eq!(
+            ids(store
+                .get_triaged_messages(Urgency::High, None, Some(1), 50)
+                .unwrap()),
+            vec!["m3"]
+        );
+
+        // Untagged messages round-trip without triage
+        let messages = store
+            .get_messages_paginated(chat, None, None, None, true, MessageFilter::default())
+            .unwrap();
+        assert!(messages.iter().any(|m| m.id == "m4" && m.triage.is_none()));
+
+        // Groups are left out unless switched on, private chats the reverse
+        let settings = store.get_conversation_settings(group).unwrap();
+        assert!(!settings.triage_enabled("group"));
+        store
+            .update_conversation_settings(
+                group,
+                &ConversationSettings {
+                    triage: Some(true),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert!(store
+            .get_conversation_settings(group)
+            .unwrap()
+            .triage_enabled("group"));
+        assert!(store
+            .get_conversation_settings(chat)
+            .unwrap()
+            .triage_enabled("private"));
+    }
+
+    #[test]
+    fn test_cursor_pages_with_duplicate_timestamps() {
+        let (store, _dir) = test_store();
+        let chat = "34600000000@s.whatsapp.net";
+        store.upsert_contact(chat, None, None, None, 1).unwrap();
+        // Seven messages in the same millisecond, then three that also share
+        // a sort key (as rows written before sort keys were unique could)
+        for n in 0..7 {
+            store
+                .add_message(&text_message(&format!("a{}", n), chat, 5000, "hi"))
+                .unwrap();
+        }
+        for n in 0..3 {
+            store
+                .add_message(&text_message(&format!("b{}", n), chat, 9000, "hi"))
+                .unwrap();
+        }
+        store
+            .conn
+            .lock()
+            .unwrap()
+            .execute("UPDATE messages SET sort_key = 1 WHERE id LIKE 'b%'", [])
+            .unwrap();
+        let ids = |messages: &[StoredMessage]| -> Vec<String> {
+            messages.iter().map(|m| m.id.clone()).collect()
+        };
+        let all = ids(&store
+            .get_messages_paginated(chat, None, None, None, true, MessageFilter::default())
+            .unwrap());
+
+        // Walking back a page at a time sees each message once, in order
+        let mut walked = Vec::new();
+        let mut cursor = None;
+        let mut pages = 0;
+        loop {
+            let (page, next) = store
+                .get_messages_page(chat, 3, cursor, true, MessageFilter::default())
+                .unwrap();
+            pages += 1;
+            let mut page = ids(&page);
+            page.extend(walked);
+            walked = page;
+            match next {
+                // Cursors survive the trip through their token
+                Some(next) => cursor = MessageCursor::decode(&next.encode()),
+                None => break,
+            }
         }
+        assert_eq!(walked, all);
+        assert_eq!(pages, 4);
+
+        // A page that ends exactly at the oldest message has no cursor
+        let (page, next) = store
+            .get_messages_page(chat, 10, None, true, MessageFilter::default())
+            .unwrap();
+        assert_eq!(page.len(), 10);
+        assert!(next.is_none());
+
+        // Tokens of the wrong kind or garbage aren't cursors
+        let contact_token = ContactCursor {
+            rank: 2,
+            pinned_at: 0,
+            last_message_time: 5,
+            rowid: 1,
+        }
+        .encode();
+        assert!(ContactCursor::decode(&contact_token).is_some());
+        assert!(MessageCursor::decode(&contact_token).is_none());
+        assert!(MessageCursor::decode("not a cursor").is_none());
+        assert!(MessageCursor::decode(&URL_SAFE_NO_PAD.encode("m1:2:3")).is_none());
+    }
+
+    #[test]
+    fn test_contact_pages_follow_list_order() {
+        let (store, _dir) = test_store();
+        // Five chats active at the same time, one pinned, plus one with no
+        // messages at all
+        for n in 0..5 {
+            store
+                .upsert_contact(
+                    &format!("3460000000{}@s.whatsapp.net", n),
+                    None,
+                    None,
+                    Some("private"),
+                    100,
+                )
+                .unwrap();
+        }
+        store
+            .upsert_contact("34611111111@s.whatsapp.net", None, None, Some("private"), 0)
+            .unwrap();
+        store.toggle_pin("34600000003@s.whatsapp.net").unwrap();
+
+        let all: Vec<String> = store
+            .get_contacts()
+            .unwrap()
+            .into_iter()
+            .map(|c| c.id)
+            .collect();
+        assert_eq!(all.len(), 6);
+        assert_eq!(all[0], "34600000003@s.whatsapp.net");
+        assert_eq!(all[5], "34611111111@s.whatsapp.net");
+
+        let mut walked = Vec::new();
+        let mut cursor = None;
+        loop {
+            let (page, next) = store.get_contacts_page(2, cursor).unwrap();
+            assert!(page.len() <= 2);
+            walked.extend(page.into_iter().map(|c| c.id));
+            match next {
+                Some(next) => cursor = ContactCursor::decode(&next.encode()),
+                None => break,
+            }
+        }
+        assert_eq!(walked, all);
+    }
+
+    #[test]
+    fn test_contact_cache_stays_fresh() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let (store, _dir) = test_store();
+        let chat = "447911123456@s.whatsapp.net";
+        store
+            .upsert_contact(chat, Some("Name 0"), None, None, 1)
+            .unwrap();
+        let mut first = text_message("m0", chat, 1, "hi");
+        first.content_json = r#"{"type":"text","body":"msg 0"}"#.to_string();
+        store.add_message(&first).unwrap();
+
+        // Each reader must see at least the rename and message that had been
+        // written when it started its lookup
+        const RENAMES: usize = 200;
+        let written = AtomicUsize::new(0);
+        let number = |text: &str| -> usize { text.rsplit(' ').next().unwrap().parse().unwrap() };
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| loop {
+                    let done = written.load(Ordering::SeqCst);
+                    let contact = store.get_contact(chat).unwrap().unwrap();
+                    assert!(number(contact.name.as_deref().unwrap()) >= done);
+                    assert!(number(contact.last_message_preview.as_deref().unwrap()) >= done);
+                    if done == RENAMES {
+                        break;
+                    }
+                });
+            }
+            scope.spawn(|| {
+                for i in 1..=RENAMES {
+                    let name = format!("Name {}", i);
+                    store
+                        .upsert_contact(chat, Some(&name), None, None, i as i64)
+                        .unwrap();
+                    let mut msg = text_message(&format!("m{}", i), chat, i as i64 + 1, "hi");
+                    msg.content_json = format!(r#"{{"type":"text","body":"msg {}"}}"#, i);
+                    store.add_message(&msg).unwrap();
+                    if i % 10 == 0 {
+                        store.toggle_pin(chat).unwrap();
+                    }
+                    written.store(i, Ordering::SeqCst);
+                }
+            });
+        });
+
+        let contact = store.get_contact(chat).unwrap().unwrap();
+        assert_eq!(contact.name.as_deref(), Some("Name 200"));
+        assert_eq!(contact.last_message_preview.as_deref(), Some("msg 200"));
+        assert!(contact.pinned_at.is_none());
+        let stats = store.contact_cache_stats();
+        assert!(stats.hits > 0 && stats.misses > 0);
+        assert_eq!(stats.entries, 1);
+
+        // A clone shares the cache, and a write drops the entry
+        store.clone().mark_as_read(chat).unwrap();
+        assert_eq!(store.contact_cache_stats().entries, 0);
     }
 }
@@ -0,0 +1,193 @@
+//! Small JPEG previews of image messages.
+//!
+//! The conversation view shows these inline instead of either the full
+//! image (slow to load as base64) or a "click to load" placeholder. Only
+//! still JPEG and PNG images are thumbnailed; anything else (WebP stickers,
+//! GIFs, corrupt data) gets a null thumbnail and the client falls back to
+//! loading the full media on demand.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::{codecs::jpeg::JpegEncoder, ImageFormat};
+use tracing::debug;
+
+use crate::storage::StoredMessage;
+
+/// Longest side of a thumbnail, in pixels
+const MAX_SIZE: u32 = 320;
+
+/// JPEG quality of thumbnails (1-100)
+const JPEG_QUALITY: u8 = 60;
+
+/// Content key holding the base64 JPEG thumbnail (null if none could be made).
+/// Stickers keep a PNG preview under it (see `sticker`)
+pub const CONTENT_KEY: &str = "thumbnail_data";
+
+/// Make a base64 JPEG thumbnail from base64 image data, or None if the
+/// format isn't supported or the data can't be decoded
+pub fn generate(media_data: &str) -> Option<String> {
+    // Media sent from the web UI may be a data URL
+    let encoded = media_data
+        .split_once(";base64,")
+        .map_or(media_data, |(_, data)| data);
+    let bytes = STANDARD.decode(encoded.trim()).ok()?;
+
+    let format = image::guess_format(&bytes).ok()?;
+    if !matches!(format, ImageFormat::Jpeg | ImageFormat::Png) {
+        debug!("No thumbnail for {:?} image", format);
+        return None;
+    }
+    let image = match image::load_from_memory_with_format(&bytes, format) {
+        Ok(image) => image,
+        Err(e) => {
+            debug!("Failed to decode image for thumbnail: {}", e);
+            return None;
+        }
+    };
+
+    let thumbnail = if image.width() > MAX_SIZE || image.height() > MAX_SIZE {
+        image.thumbnail(MAX_SIZE, MAX_SIZE)
+    } else {
+        image
+    };
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY)
+        .encode_image(&thumbnail.to_rgb8())
+        .ok()?;
+    Some(STANDARD.encode(jpeg))
+}
+
+/// Whether a message's content is an image carrying its media data
+fn image_media(content: &serde_json::Value) -> Option<&str> {
+    if content.get("type").and_then(|t| t.as_str()) != Some("image") {
+        return None;
+    }
+    content
+        .get("media_data")
+        .or_else(|| content.get("mediaData"))
+        .and_then(|v| v.as_str())
+}
+
+/// Add a thumbnail to an image message that doesn't have one yet, decoding
+/// the image on a blocking thread so message ingestion isn't held up
+pub async fn attach(message: &mut StoredMessage) {
+    let Ok(mut content) = serde_json::from_str::<serde_json::Value>(&message.content_json) else {
+        return;
+    };
+    if content.get(CONTENT_KEY).is_some() {
+        return;
+    }
+    let Some(media_data) = image_media(&content).map(str::to_string) else {
+        return;
+    };
+
+    let thumbnail = tokio::task::spawn_blocking(move || generate(&media_data))
+        .await
+        .ok()
+        .flatten();
+    content[CONTENT_KEY] = thumbnail.into();
+    message.content_json = content.to_string();
+    message.content = Some(content);
+}
+
+/// Drop the full data of an image that has a thumbnail, leaving a
+/// `has_media` flag so clients load it on demand
+pub fn drop_full_image(message: &mut StoredMessage) {
+    let Some(content) = message.content.as_mut().and_then(|c| c.as_object_mut()) else {
+        return;
+    };
+    if !content.get(CONTENT_KEY).is_some_and(|t| t.is_string()) {
+        return;
+    }
+    if content.remove("media_data").is_some() | content.remove("mediaData").is_some() {
+        content.insert("has_media".to_string(), true.into());
+        message.content_json = serde_json::Value::Object(content.clone()).to_string();
+    }
+}
+
+/// Thumbnail for content that hasn't been through `attach`: the one already
+/// in it, else one generated in place (Some(None) if it can't be made), or
+/// None if the content isn't an image with media
+pub fn for_content(content: &serde_json::Value) -> Option<Option<String>> {
+    if let Some(existing) = content.get(CONTENT_KEY) {
+        return Some(existing.as_str().map(str::to_string));
+    }
+    image_media(content).map(generate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::text_message;
+    use image::{DynamicImage, ImageBuffer, Rgb};
+
+    fn encode(image: &DynamicImage, format: ImageFormat) -> String {
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        image.write_to(&mut bytes, format).unwrap();
+        STANDARD.encode(bytes.into_inner())
+    }
+
+    fn decode(thumbnail: &str) -> DynamicImage {
+        let bytes = STANDARD.decode(thumbnail).unwrap();
+        assert_eq!(image::guess_format(&bytes).unwrap(), ImageFormat::Jpeg);
+        image::load_from_memory(&bytes).unwrap()
+    }
+
+    #[test]
+    fn test_thumbnails_by_format() {
+        let photo = DynamicImage::ImageRgb8(ImageBuffer::from_fn(1280, 960, |x, y| {
+            Rgb([(x % 256) as u8, (y % 256) as u8, 128])
+        }));
+
+        // Large JPEGs and PNGs shrink to fit, keeping their aspect ratio
+        let thumbnail = decode(&generate(&encode(&photo, ImageFormat::Jpeg)).unwrap());
+        assert_eq!((thumbnail.width(), thumbnail.height()), (320, 240));
+        let tall = photo.rotate90();
+        let thumbnail = decode(&generate(&encode(&tall, ImageFormat::Png)).unwrap());
+        assert_eq!((thumbnail.width(), thumbnail.height()), (240, 320));
+
+        // Small images aren't upscaled; data URLs are accepted
+        let icon = photo.crop_imm(0, 0, 64, 48);
+        let data_url = format!("data:image/png;base64,{}", encode(&icon, ImageFormat::Png));
+        let thumbnail = decode(&generate(&data_url).unwrap());
+        assert_eq!((thumbnail.width(), thumbnail.height()), (64, 48));
+
+        // WebP (stickers) and GIF aren't thumbnailed
+        let webp = STANDARD.encode(b"RIFF\x1a\x00\x00\x00WEBPVP8L\x0d\x00\x00\x00\x2f\x00\x00\x00\x10\x07\x10\x11\x11\x88\x88\xfe\x07\x00");
+        assert_eq!(generate(&webp), None);
+        assert_eq!(generate(&STANDARD.encode(b"GIF89a\x01\x00\x01\x00")), None);
+
+        // Corrupt payloads don't panic
+        let jpeg = STANDARD.decode(encode(&photo, ImageFormat::Jpeg)).unwrap();
+        assert_eq!(generate(&STANDARD.encode(&jpeg[..200])), None);
+        assert_eq!(generate("not base64 at all!"), None);
+        assert_eq!(generate(""), None);
+    }
+
+    #[tokio::test]
+    async fn test_attach_only_touches_images() {
+        let photo = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(400, 400, Rgb([9, 9, 9])));
+        let content = serde_json::json!({
+            "type": "image",
+            "mime_type": "image/jpeg",
+            "media_data": encode(&photo, ImageFormat::Jpeg),
+        });
+        let mut message = StoredMessage {
+            content_type: "Image".to_string(),
+            content_json: content.to_string(),
+            content: Some(content),
+            original_text: None,
+            ..text_message("img", "a@s.whatsapp.net", 1, "")
+        };
+        attach(&mut message).await;
+        let thumbnail = message.content.as_ref().unwrap()[CONTENT_KEY]
+            .as_str()
+            .unwrap();
+        assert_eq!(decode(thumbnail).width(), 320);
+
+        let sticker = serde_json::json!({"type": "sticker", "media_data": "UklGRg=="});
+        message.content_json = sticker.to_string();
+        message.content = Some(sticker.clone());
+        attach(&mut message).await;
+        assert_eq!(message.content, Some(sticker));
+    }
+}
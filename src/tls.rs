@@ -0,0 +1,217 @@
+//! HTTPS for the web server.
+//!
+//! With a certificate and key configured the server terminates TLS itself
+//! (rustls via axum-server) instead of relying on a proxy in front of it.
+//! The pair is checked before the server starts, reloaded on SIGHUP so a
+//! renewed certificate takes effect without a restart, and an optional plain
+//! HTTP listener redirects browsers to the HTTPS port.
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::{
+    extract::{Host, State},
+    http::Uri,
+    response::Redirect,
+    Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::ServerConfig;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{error, info};
+
+/// Certificate, key and redirect settings for serving HTTPS
+#[derive(Clone)]
+pub struct HttpsConfig {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    /// Port for a plain HTTP listener that redirects to HTTPS
+    pub redirect_http_port: Option<u16>,
+    rustls: RustlsConfig,
+}
+
+impl HttpsConfig {
+    /// Load and check the certificate and key
+    pub fn load(
+        cert_path: &Path,
+        key_path: &Path,
+        redirect_http_port: Option<u16>,
+    ) -> Result<Self> {
+        let config = load_server_config(cert_path, key_path)?;
+        Ok(Self {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            redirect_http_port,
+            rustls: RustlsConfig::from_config(config),
+        })
+    }
+
+    pub fn rustls_config(&self) -> RustlsConfig {
+        self.rustls.clone()
+    }
+
+    /// Re-read the certificate and key, keeping the current pair if the new one is unusable
+    pub fn reload(&self) -> Result<()> {
+        let config = load_server_config(&self.cert_path, &self.key_path)?;
+        self.rustls.reload_from_config(config);
+        Ok(())
+    }
+
+    /// Reload the certificate and key whenever the process receives SIGHUP
+    #[cfg(unix)]
+    pub fn reload_on_sighup(&self) {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let https = self.clone();
+        tokio::spawn(async move {
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(hangup) => hangup,
+                Err(e) => {
+                    error!(
+                        "Can't listen for SIGHUP, TLS certificate reload disabled: {}",
+                        e
+                    );
+                    return;
+                }
+            };
+            while hangup.recv().await.is_some() {
+                match https.reload() {
+                    Ok(()) => info!("Reloaded TLS certificate from {:?}", https.cert_path),
+                    Err(e) => error!("Keeping the current TLS certificate: {:#}", e),
+                }
+            }
+        });
+    }
+
+    #[cfg(not(unix))]
+    pub fn reload_on_sighup(&self) {}
+}
+
+/// Build a rustls server config from PEM files, with errors that say which
+/// file is at fault
+fn load_server_config(cert_path: &Path, key_path: &Path) -> Result<Arc<ServerConfig>> {
+    let cert_pem = std::fs::read(cert_path)
+        .with_context(|| format!("Can't read TLS certificate {:?}", cert_path))?;
+    let certs = rustls_pemfile::certs(&mut cert_pem.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("TLS certificate {:?} isn't valid PEM", cert_path))?;
+    if certs.is_empty() {
+        bail!("No certificate found in {:?}", cert_path);
+    }
+
+    let key_pem =
+        std::fs::read(key_path).with_context(|| format!("Can't read TLS key {:?}", key_path))?;
+    let key = rustls_pemfile::private_key(&mut key_pem.as_slice())
+        .with_context(|| format!("TLS key {:?} isn't valid PEM", key_path))?
+        .ok_or_else(|| anyhow!("No private key found in {:?}", key_path))?;
+
+    let mut config =
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| match e {
+                rustls::Error::InconsistentKeys(_) => anyhow!(
+                    "TLS key {:?} doesn't match certificate {:?}",
+                    key_path,
+                    cert_path
+                ),
+                e => anyhow!(
+                    "Can't use TLS certificate {:?} with key {:?}: {}",
+                    cert_path,
+                    key_path,
+                    e
+                ),
+            })?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+/// Serve plain HTTP on `addr`, redirecting every request to the HTTPS port
+pub async fn serve_redirect(addr: SocketAddr, https_port: u16) -> Result<()> {
+    let listener = crate::web::bind_listener(addr)
+        .and_then(tokio::net::TcpListener::from_std)
+        .with_context(|| format!("Can't bind HTTP redirect listener on {}", addr))?;
+    info!(
+        "Redirecting http://{} to HTTPS port {}",
+        listener.local_addr()?,
+        https_port
+    );
+
+    let router = Router::new()
+        .fallback(redirect_to_https)
+        .with_state(https_port);
+    axum::serve(listener, router).await?;
+    Ok(())
+}
+
+async fn redirect_to_https(State(https_port): State<u16>, Host(host): Host, uri: Uri) -> Redirect {
+    let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    Redirect::permanent(&https_url(&host, https_port, path))
+}
+
+/// The HTTPS URL for a request that arrived over plain HTTP
+fn https_url(host: &str, https_port: u16, path: &str) -> String {
+    // Drop the HTTP port, taking care not to split an IPv6 address
+    let hostname = host
+        .rsplit_once(':')
+        .filter(|(name, port)| {
+            !name.is_empty() && !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit())
+        })
+        .map_or(host, |(name, _)| name);
+    if https_port == 443 {
+        format!("https://{}{}", hostname, path)
+    } else {
+        format!("https://{}:{}{}", hostname, https_port, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_pair(dir: &Path, name: &str) -> (PathBuf, PathBuf) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_path = dir.join(format!("{}.crt", name));
+        let key_path = dir.join(format!("{}.key", name));
+        std::fs::write(&cert_path, cert.cert.pem()).unwrap();
+        std::fs::write(&key_path, cert.key_pair.serialize_pem()).unwrap();
+        (cert_path, key_path)
+    }
+
+    #[test]
+    fn test_load_reports_bad_pairs() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert_a, key_a) = write_pair(dir.path(), "a");
+        let (_, key_b) = write_pair(dir.path(), "b");
+
+        let https = HttpsConfig::load(&cert_a, &key_a, None).unwrap();
+        assert!(https.reload().is_ok());
+
+        let err = HttpsConfig::load(&cert_a, &key_b, None).err().unwrap();
+        assert!(err.to_string().contains("doesn't match"), "{:#}", err);
+
+        let missing = dir.path().join("missing.crt");
+        let err = HttpsConfig::load(&missing, &key_a, None).err().unwrap();
+        assert!(err.to_string().contains("Can't read TLS certificate"));
+
+        // The key file given as the certificate has no certificate in it
+        let err = HttpsConfig::load(&key_a, &key_a, None).err().unwrap();
+        assert!(err.to_string().contains("No certificate found"));
+
+        // A failed reload keeps the server running with the old pair
+        std::fs::write(&key_a, "garbage").unwrap();
+        assert!(https.reload().is_err());
+    }
+
+    #[test]
+    fn test_https_url() {
+        assert_eq!(
+            https_url("example.com:8080", 8443, "/api/status?x=1"),
+            "https://example.com:8443/api/status?x=1"
+        );
+        assert_eq!(https_url("example.com", 443, "/"), "https://example.com/");
+        assert_eq!(https_url("[::1]:80", 443, "/ws"), "https://[::1]/ws");
+        assert_eq!(https_url("[::1]", 8443, "/"), "https://[::1]:8443/");
+    }
+}
@@ -0,0 +1,741 @@
+//! Printable PDF transcripts of a chat.
+//!
+//! A transcript lists a chat's messages over a date range (UTC), each with
+//! its time, sender and text, the translation beneath it in grey, and media
+//! as a placeholder with the file's details. The first page starts with the
+//! contact's details and the range covered; every page is numbered.
+//!
+//! Transcripts use the PDF standard fonts, which only cover Western European
+//! characters (Windows-1252); anything else is printed as "?".
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use printpdf::{
+    BuiltinFont, Color, IndirectFontRef, Line, Mm, PdfDocument, PdfLayerReference, Point, Rgb,
+};
+use serde::Serialize;
+use tracing::error;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::name_search::normalize_name;
+use crate::storage::{
+    MessageFilter, MessageStore, StoredContact, StoredMessage, SYSTEM_CONTENT_TYPE,
+};
+
+/// Most messages one transcript holds
+pub const MAX_MESSAGES: u64 = 5000;
+
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 18.0;
+/// Space kept at the bottom of each page for the page number
+const FOOTER_HEIGHT: f32 = 8.0;
+/// Space between messages
+const MESSAGE_GAP: f32 = 2.5;
+
+/// Helvetica's average character width, as a fraction of the font size
+const AVERAGE_CHAR_WIDTH: f32 = 0.55;
+const MM_PER_POINT: f32 = 25.4 / 72.0;
+
+/// Errors returned when exporting a transcript
+#[derive(Debug, Clone, Serialize)]
+pub enum TranscriptError {
+    UnsupportedFormat,
+    InvalidDate,
+    InvalidRange,
+    ContactNotFound,
+    /// The range has this many messages, more than `MAX_MESSAGES`
+    TooManyMessages(u64),
+    StorageError,
+    RenderFailed,
+}
+
+impl TranscriptError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TranscriptError::UnsupportedFormat => "unsupported_format",
+            TranscriptError::InvalidDate => "invalid_date",
+            TranscriptError::InvalidRange => "invalid_range",
+            TranscriptError::ContactNotFound => "contact_not_found",
+            TranscriptError::TooManyMessages(_) => "too_many_messages",
+            TranscriptError::StorageError => "storage_error",
+            TranscriptError::RenderFailed => "render_failed",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            TranscriptError::UnsupportedFormat => "The only export format is pdf",
+            TranscriptError::InvalidDate => "Dates must look like 2026-03-01",
+            TranscriptError::InvalidRange => "The range ends before it starts",
+            TranscriptError::ContactNotFound => "Contact not found",
+            TranscriptError::TooManyMessages(_) => {
+                "The range has too many messages for one transcript; narrow it with from and to"
+            }
+            TranscriptError::StorageError => "Failed to get the chat's messages",
+            TranscriptError::RenderFailed => "Failed to render the transcript",
+        }
+    }
+}
+
+/// A rendered transcript
+pub struct Transcript {
+    /// Suggested file name, e.g. "chat-ana-from-2026-03-01-to-2026-03-31.pdf"
+    pub file_name: String,
+    pub pdf: Vec<u8>,
+}
+
+/// Parse a `from`/`to` date ("YYYY-MM-DD")
+pub fn parse_date(date: Option<&str>) -> Result<Option<NaiveDate>, TranscriptError> {
+    date.filter(|d| !d.is_empty())
+        .map(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").map_err(|_| TranscriptError::InvalidDate))
+        .transpose()
+}
+
+/// Export a chat's messages from the start of `from` to the end of `to`
+/// (UTC, either open-ended) as a PDF transcript, unless there are more than
+/// `max_messages` of them
+pub fn export_pdf(
+    store: &MessageStore,
+    contact_id: &str,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    max_messages: u64,
+    now: DateTime<Utc>,
+) -> Result<Transcript, TranscriptError> {
+    if let (Some(from), Some(to)) = (from, to) {
+        if to < from {
+            return Err(TranscriptError::InvalidRange);
+        }
+    }
+    let storage_error = |e: anyhow::Error| {
+        error!("Failed to export transcript for {}: {}", contact_id, e);
+        TranscriptError::StorageError
+    };
+    let contact = store
+        .get_contact(contact_id)
+        .map_err(storage_error)?
+        .ok_or(TranscriptError::ContactNotFound)?;
+
+    // Both bounds are exclusive
+    let start_ms = |date: NaiveDate| {
+        date.and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc()
+            .timestamp_millis()
+    };
+    let after = from.map(|date| start_ms(date) - 1);
+    let before = to.map(|date| start_ms(date + Duration::days(1)));
+
+    let count = store
+        .count_messages(&contact.id, before, after)
+        .map_err(storage_error)?;
+    if count > max_messages {
+        return Err(TranscriptError::TooManyMessages(count));
+    }
+    let messages = store
+        .get_messages_paginated(
+            &contact.id,
+            None,
+            before,
+            after,
+            true,
+            MessageFilter::default(),
+        )
+        .map_err(storage_error)?;
+
+    let pdf = render(&contact, &messages, from, to, now).map_err(|e| {
+        error!("Failed to render transcript for {}: {}", contact_id, e);
+        TranscriptError::RenderFailed
+    })?;
+
+    let name: String = normalize_name(
+        contact
+            .name
+            .as_deref()
+            .or(contact.phone.as_deref())
+            .unwrap_or_default(),
+    )
+    .chars()
+    .filter(|c| c.is_ascii_alphanumeric() || *c == ' ')
+    .collect::<String>()
+    .trim()
+    .replace(' ', "-");
+    let mut file_name = format!("chat-{}", if name.is_empty() { "export" } else { &name });
+    if let Some(from) = from {
+        file_name.push_str(&format!("-from-{}", from));
+    }
+    if let Some(to) = to {
+        file_name.push_str(&format!("-to-{}", to));
+    }
+    file_name.push_str(".pdf");
+
+    Ok(Transcript { file_name, pdf })
+}
+
+/// How a line of the transcript looks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Style {
+    Title,
+    Detail,
+    Sender,
+    Body,
+    Translation,
+    Notice,
+}
+
+impl Style {
+    fn size(self) -> f32 {
+        match self {
+            Style::Title => 16.0,
+            Style::Detail | Style::Sender | Style::Translation | Style::Notice => 9.0,
+            Style::Body => 10.0,
+        }
+    }
+
+    /// Height of a line (mm)
+    fn line_height(self) -> f32 {
+        self.size() * MM_PER_POINT * 1.35
+    }
+
+    fn grey(self) -> f32 {
+        match self {
+            Style::Translation | Style::Notice => 0.45,
+            Style::Detail | Style::Sender => 0.25,
+            Style::Title | Style::Body => 0.0,
+        }
+    }
+
+    /// Most characters that fit on a line `indent` mm in
+    fn max_chars(self, indent: f32) -> usize {
+        let width = PAGE_WIDTH - 2.0 * MARGIN - indent;
+        (width / (self.size() * MM_PER_POINT * AVERAGE_CHAR_WIDTH)) as usize
+    }
+}
+
+/// A line placed on a page, `y` mm from the bottom
+struct PlacedLine {
+    text: String,
+    style: Style,
+    x: f32,
+    y: f32,
+}
+
+/// Where lines go: filled top to bottom, a page at a time
+struct Layout {
+    pages: Vec<Vec<PlacedLine>>,
+    /// Baseline of the next line on the last page
+    y: f32,
+    /// Separators drawn under the header, per page
+    rules: Vec<(usize, f32)>,
+}
+
+impl Layout {
+    fn new() -> Self {
+        Self {
+            pages: vec![Vec::new()],
+            y: PAGE_HEIGHT - MARGIN,
+            rules: Vec::new(),
+        }
+    }
+
+    fn new_page(&mut self) {
+        self.pages.push(Vec::new());
+        self.y = PAGE_HEIGHT - MARGIN;
+    }
+
+    /// Room left on the page (mm)
+    fn room(&self) -> f32 {
+        self.y - MARGIN - FOOTER_HEIGHT
+    }
+
+    fn push(&mut self, text: String, style: Style, indent: f32) {
+        if self.room() < style.line_height() {
+            self.new_page();
+        }
+        self.y -= style.line_height();
+        self.pages.last_mut().unwrap().push(PlacedLine {
+            text,
+            style,
+            x: MARGIN + indent,
+            y: self.y,
+        });
+    }
+
+    /// Push text wrapped to the page width
+    fn push_wrapped(&mut self, text: &str, style: Style, indent: f32) {
+        for line in wrap(&printable(text), style.max_chars(indent)) {
+            self.push(line, style, indent);
+        }
+    }
+
+    fn gap(&mut self, height: f32) {
+        self.y -= height;
+    }
+}
+
+/// Text the standard fonts can print: characters outside Windows-1252 are
+/// replaced by their compatibility form if it's printable, else "?"
+fn printable(text: &str) -> String {
+    fn is_printable(c: char) -> bool {
+        matches!(c, ' '..='~' | '\u{A0}'..='\u{FF}' | '\n')
+            || "€‚ƒ„…†‡ˆ‰Š‹ŒŽ‘’“”•–—˜™š›œžŸ".contains(c)
+    }
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\t' => out.push(' '),
+            '\r' => {}
+            // Joiners and variation selectors of emoji sequences
+            '\u{200B}'..='\u{200F}' | '\u{FE00}'..='\u{FE0F}' => {}
+            c if is_printable(c) => out.push(c),
+            c => {
+                let folded: String = c.nfkd().collect();
+                if !folded.is_empty() && folded != c.to_string() && folded.chars().all(is_printable)
+                {
+                    out.push_str(&folded);
+                } else if !out.ends_with('?') {
+                    out.push('?');
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Split text into lines of at most `max_chars`, at spaces where possible
+fn wrap(text: &str, max_chars: usize) -> Vec<String> {
+    let max_chars = max_chars.max(1);
+    let mut lines = Vec::new();
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        let mut len = 0;
+        for word in paragraph.split(' ') {
+            let mut word: Vec<char> = word.chars().collect();
+            if len > 0 && len + 1 + word.len() > max_chars {
+                lines.push(std::mem::take(&mut line));
+                len = 0;
+            }
+            // A word longer than a line is broken up
+            while word.len() > max_chars {
+                let rest = word.split_off(max_chars);
+                lines.push(word.into_iter().collect());
+                word = rest;
+            }
+            if len > 0 {
+                line.push(' ');
+                len += 1;
+            }
+            line.extend(word.iter());
+            len += word.len();
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// File size for display, e.g. "1.2 MB"
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        b if b >= 1024 * 1024 => format!("{:.1} MB", b as f64 / (1024.0 * 1024.0)),
+        b if b >= 1024 => format!("{} KB", b / 1024),
+        b => format!("{} bytes", b),
+    }
+}
+
+/// A placeholder for a media message, with what's known about the file
+fn media_placeholder(message: &StoredMessage) -> Option<String> {
+    let content: serde_json::Value =
+        serde_json::from_str(&message.content_json).unwrap_or_default();
+    let text = |key: &str| {
+        content
+            .get(key)
+            .and_then(|v| v.as_str())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+    };
+    let label = match message.content_type.to_lowercase().as_str() {
+        "image" => "Photo",
+        "video" => "Video",
+        "audio" | "voice note" => {
+            if content.get("is_voice_note").and_then(|v| v.as_bool()) == Some(true)
+                || message.content_type.eq_ignore_ascii_case("voice note")
+            {
+                "Voice note"
+            } else {
+                "Audio"
+            }
+        }
+        "document" => "Document",
+        "sticker" => "Sticker",
+        _ => return None,
+    };
+
+    let mut details = Vec::new();
+    if let Some(name) = text("file_name").or_else(|| text("fileName")) {
+        details.push(name.to_string());
+    }
+    if let Some(mime) = text("mime_type").or_else(|| text("mimeType")) {
+        details.push(mime.to_string());
+    }
+    if let Some(size) = content.get("file_size").and_then(|v| v.as_u64()) {
+        if size > 0 {
+            details.push(format_size(size));
+        }
+    }
+    if let Some(secs) = content.get("duration_seconds").and_then(|v| v.as_u64()) {
+        details.push(format!("{}:{:02}", secs / 60, secs % 60));
+    }
+    let mut placeholder = match details.is_empty() {
+        true => format!("[{}]", label),
+        false => format!("[{}: {}]", label, details.join(", ")),
+    };
+    if let Some(caption) = text("caption") {
+        placeholder.push(' ');
+        placeholder.push_str(caption);
+    }
+    Some(placeholder)
+}
+
+fn format_time(timestamp_ms: i64) -> String {
+    DateTime::from_timestamp_millis(timestamp_ms)
+        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default()
+}
+
+/// Lay out the transcript: the contact's details, then each message
+fn layout(
+    contact: &StoredContact,
+    messages: &[StoredMessage],
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    now: DateTime<Utc>,
+) -> Layout {
+    let contact_name = contact
+        .name
+        .clone()
+        .or_else(|| contact.phone.clone())
+        .unwrap_or_else(|| contact.id.clone());
+
+    let mut layout = Layout::new();
+    layout.push_wrapped(
+        &format!("Chat transcript: {}", contact_name),
+        Style::Title,
+        0.0,
+    );
+    layout.gap(2.0);
+    if let Some(phone) = &contact.phone {
+        layout.push_wrapped(
+            &format!("Phone: +{}", phone.trim_start_matches('+')),
+            Style::Detail,
+            0.0,
+        );
+    }
+    layout.push_wrapped(&format!("WhatsApp ID: {}", contact.id), Style::Detail, 0.0);
+    if let Some(kind) = &contact.contact_type {
+        layout.push_wrapped(&format!("Chat type: {}", kind), Style::Detail, 0.0);
+    }
+    let first = messages.first().map(|m| m.timestamp);
+    let last = messages.last().map(|m| m.timestamp);
+    let bound = |date: Option<NaiveDate>, timestamp: Option<i64>| match (date, timestamp) {
+        (Some(date), _) => date.to_string(),
+        (None, Some(ts)) => format_time(ts)[..10].to_string(),
+        (None, None) => "-".to_string(),
+    };
+    layout.push_wrapped(
+        &format!(
+            "Period: {} to {} (times in UTC)",
+            bound(from, first),
+            bound(to, last)
+        ),
+        Style::Detail,
+        0.0,
+    );
+    layout.push_wrapped(&format!("Messages: {}", messages.len()), Style::Detail, 0.0);
+    layout.push_wrapped(
+        &format!("Exported: {} UTC", now.format("%Y-%m-%d %H:%M")),
+        Style::Detail,
+        0.0,
+    );
+    layout.gap(3.0);
+    layout.rules.push((0, layout.y));
+    layout.gap(4.0);
+
+    for message in messages {
+        let time = format_time(message.timestamp);
+        if message.content_type == SYSTEM_CONTENT_TYPE {
+            let content: serde_json::Value =
+                serde_json::from_str(&message.content_json).unwrap_or_default();
+            let text = content
+                .get("text")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default();
+            layout.push_wrapped(&format!("{}  {}", time, text), Style::Notice, 0.0);
+            layout.gap(MESSAGE_GAP);
+            continue;
+        }
+
+        let sender = match message.is_from_me {
+            true => "Me".to_string(),
+            false => message
+                .sender_name
+                .clone()
+                .or_else(|| message.sender_phone.clone().map(|p| format!("+{}", p)))
+                .unwrap_or_else(|| contact_name.clone()),
+        };
+        let body = media_placeholder(message)
+            .or_else(|| message.original_text.clone())
+            .unwrap_or_else(|| format!("[{}]", message.content_type));
+        let translation = message.translated_text.as_deref().filter(|t| {
+            message.is_translated && !t.is_empty() && Some(*t) != message.original_text.as_deref()
+        });
+
+        // Keep a message's header with its first line
+        let needed = Style::Sender.line_height() + Style::Body.line_height();
+        if layout.room() < needed {
+            layout.new_page();
+        }
+        layout.push_wrapped(&format!("{}  {}", time, sender), Style::Sender, 0.0);
+        layout.push_wrapped(&body, Style::Body, 4.0);
+        if let Some(translation) = translation {
+            let label = match message.source_language.as_deref() {
+                Some(language) if !language.is_empty() => format!("Translation ({})", language),
+                _ => "Translation".to_string(),
+            };
+            layout.push_wrapped(
+                &format!("{}: {}", label, translation),
+                Style::Translation,
+                4.0,
+            );
+        }
+        layout.gap(MESSAGE_GAP);
+    }
+
+    if messages.is_empty() {
+        layout.push_wrapped("No messages in this period.", Style::Notice, 0.0);
+    }
+    layout
+}
+
+/// Render a transcript as a PDF
+fn render(
+    contact: &StoredContact,
+    messages: &[StoredMessage],
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    now: DateTime<Utc>,
+) -> anyhow::Result<Vec<u8>> {
+    let layout = layout(contact, messages, from, to, now);
+    let title = printable(&format!(
+        "Chat transcript: {}",
+        contact.name.as_deref().unwrap_or(&contact.id)
+    ));
+    let (doc, first_page, first_layer) =
+        PdfDocument::new(title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Transcript");
+    let regular = doc.add_builtin_font(BuiltinFont::Helvetica)?;
+    let bold = doc.add_builtin_font(BuiltinFont::HelveticaBold)?;
+    let italic = doc.add_builtin_font(BuiltinFont::HelveticaOblique)?;
+    let font = |style: Style| -> &IndirectFontRef {
+        match style {
+            Style::Title | Style::Sender => &bold,
+            Style::Translation | Style::Notice => &italic,
+            Style::Detail | Style::Body => &regular,
+        }
+    };
+    let grey = |level: f32| Color::Rgb(Rgb::new(level, level, level, None));
+
+    let total = layout.pages.len();
+    for (index, lines) in layout.pages.iter().enumerate() {
+        let layer: PdfLayerReference = if index == 0 {
+            doc.get_page(first_page).get_layer(first_layer)
+        } else {
+            let (page, layer) = doc.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Transcript");
+            doc.get_page(page).get_layer(layer)
+        };
+
+        for line in lines {
+            layer.set_fill_color(grey(line.style.grey()));
+            layer.use_text(
+                line.text.as_str(),
+                line.style.size(),
+                Mm(line.x),
+                Mm(line.y),
+                font(line.style),
+            );
+        }
+        for &(_, y) in layout.rules.iter().filter(|(page, _)| *page == index) {
+            layer.set_outline_color(grey(0.7));
+            layer.set_outline_thickness(0.5);
+            layer.add_line(Line {
+                points: vec![
+                    (Point::new(Mm(MARGIN), Mm(y)), false),
+                    (Point::new(Mm(PAGE_WIDTH - MARGIN), Mm(y)), false),
+                ],
+                is_closed: false,
+            });
+        }
+
+        let footer = format!("Page {} of {}", index + 1, total);
+        let width = footer.len() as f32 * 8.0 * MM_PER_POINT * AVERAGE_CHAR_WIDTH;
+        layer.set_fill_color(grey(0.45));
+        layer.use_text(
+            footer,
+            8.0,
+            Mm((PAGE_WIDTH - width) / 2.0),
+            Mm(MARGIN / 2.0),
+            &regular,
+        );
+    }
+
+    Ok(doc.save_to_bytes()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{test_store, text_message};
+    use printpdf::lopdf;
+
+    fn message(
+        contact: &str,
+        id: &str,
+        timestamp: i64,
+        from_me: bool,
+        text: &str,
+    ) -> StoredMessage {
+        StoredMessage {
+            is_from_me: from_me,
+            ..text_message(id, contact, timestamp, text)
+        }
+    }
+
+    #[test]
+    fn test_printable_and_wrap() {
+        assert_eq!(printable("Café “ok” – 5€"), "Café “ok” – 5€");
+        assert_eq!(printable("ﬁne\tday"), "fine day");
+        assert_eq!(printable("Привет ❤️ 👍🏽"), "? ? ?");
+        assert_eq!(
+            wrap("one two three four", 9),
+            vec!["one two", "three", "four"]
+        );
+        assert_eq!(wrap("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+        assert_eq!(wrap("a\n\nb", 10), vec!["a", "", "b"]);
+    }
+
+    #[test]
+    fn test_export_pdf() {
+        let (store, _dir) = test_store();
+        let contact = "34600000001@s.whatsapp.net";
+        store
+            .upsert_contact(
+                contact,
+                Some("Ana García"),
+                Some("34600000001"),
+                Some("private"),
+                1,
+            )
+            .unwrap();
+
+        let day = |d: u32| {
+            NaiveDate::from_ymd_opt(2026, 3, d)
+                .unwrap()
+                .and_hms_opt(12, 0, 0)
+                .unwrap()
+                .and_utc()
+                .timestamp_millis()
+        };
+        let mut hola = message(contact, "m1", day(1), false, "Hola, ¿cómo estás?");
+        hola.translated_text = Some("Hi, how are you?".to_string());
+        hola.source_language = Some("Spanish".to_string());
+        hola.is_translated = true;
+        store.add_message(&hola).unwrap();
+        store
+            .add_message(&message(
+                contact,
+                "m2",
+                day(1) + 60_000,
+                true,
+                "Very well, thanks!",
+            ))
+            .unwrap();
+        let mut photo = message(contact, "m3", day(2), false, "");
+        photo.content_type = "Image".to_string();
+        photo.original_text = None;
+        photo.content_json = serde_json::json!({
+            "type": "image", "caption": "La playa", "mime_type": "image/jpeg", "file_size": 245760
+        })
+        .to_string();
+        store.add_message(&photo).unwrap();
+        let now = DateTime::from_timestamp_millis(day(20)).unwrap();
+
+        let transcript = export_pdf(&store, contact, None, None, MAX_MESSAGES, now).unwrap();
+        assert_eq!(transcript.file_name, "chat-ana-garcia.pdf");
+        assert!(transcript.pdf.starts_with(b"%PDF-"));
+        let pdf = lopdf::Document::load_mem(&transcript.pdf).unwrap();
+        assert_eq!(pdf.get_pages().len(), 1);
+        // lopdf extracts text as if in the standard encoding rather than the
+        // fonts' WinAnsi, so accented letters don't come back
+        let text = pdf.extract_text(&[1]).unwrap();
+        for expected in [
+            "Chat transcript: Ana Garc",
+            "2026-03-01 12:00  Ana Garc",
+            "Translation (Spanish): Hi, how are you?",
+            "2026-03-01 12:01  Me",
+            "[Photo: image/jpeg, 240 KB] La playa",
+            "Page 1 of 1",
+        ] {
+            assert!(text.contains(expected), "{:?} not in {:?}", expected, text);
+        }
+
+        // A range keeps to its days
+        let from = NaiveDate::from_ymd_opt(2026, 3, 2);
+        let transcript = export_pdf(&store, contact, from, from, MAX_MESSAGES, now).unwrap();
+        assert_eq!(
+            transcript.file_name,
+            "chat-ana-garcia-from-2026-03-02-to-2026-03-02.pdf"
+        );
+        let pdf = lopdf::Document::load_mem(&transcript.pdf).unwrap();
+        let text = pdf.extract_text(&[1]).unwrap();
+        assert!(text.contains("Messages: 1"));
+        assert!(!text.contains("Hi, how are you?"));
+
+        // Long chats run over several numbered pages
+        for i in 0..120 {
+            store
+                .add_message(&message(contact, &format!("x{}", i), day(5) + i * 1000, i % 2 == 0, "Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor incididunt ut labore et dolore magna aliqua."))
+                .unwrap();
+        }
+        let transcript = export_pdf(&store, contact, None, None, MAX_MESSAGES, now).unwrap();
+        let pdf = lopdf::Document::load_mem(&transcript.pdf).unwrap();
+        let pages = pdf.get_pages().len() as u32;
+        assert_eq!(pages, 9);
+        let text = pdf.extract_text(&[pages]).unwrap();
+        assert!(text.contains(&format!("Page {} of {}", pages, pages)));
+
+        assert!(matches!(
+            export_pdf(
+                &store,
+                contact,
+                from,
+                NaiveDate::from_ymd_opt(2026, 3, 1),
+                MAX_MESSAGES,
+                now
+            ),
+            Err(TranscriptError::InvalidRange)
+        ));
+        assert!(matches!(
+            export_pdf(
+                &store,
+                "nobody@s.whatsapp.net",
+                None,
+                None,
+                MAX_MESSAGES,
+                now
+            ),
+            Err(TranscriptError::ContactNotFound)
+        ));
+        assert!(matches!(
+            export_pdf(&store, contact, None, None, 100, now),
+            Err(TranscriptError::TooManyMessages(123))
+        ));
+    }
+}
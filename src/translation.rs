@@ -1,20 +1,43 @@
 //! Translation service using Claude API.
 //!
 //! Uses a cheap model (Haiku) for language detection and a better model (Sonnet) for translation.
+//! The models and their pricing can be changed with [`ModelConfig`], including at runtime.
+//! Detection and translation can fall back to other providers while Claude is failing
+//! (see [`crate::translation_provider`]).
 
 use anyhow::{Context, Result};
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
-use tracing::{debug, info, warn};
-
-/// Models to use for translation
-const DETECTION_MODEL: &str = "claude-haiku-4-5";
-const TRANSLATION_MODEL: &str = "claude-sonnet-4-5";
-const AI_COMPOSE_MODEL: &str = "claude-opus-4-5";
-const ANTHROPIC_API_URL: &str = "https://api.anthropic.com/v1/messages";
-const ANTHROPIC_VERSION: &str = "2023-06-01";
-
-/// Pricing per million tokens (as of 2025)
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tracing::{debug, error, info, warn};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::error_registry::{ErrorCategory, ErrorRegistry};
+use crate::translation_provider::{
+    is_retryable, AnthropicProvider, ChainedProvider, ModelRole, Provider, ProviderKind,
+    ANTHROPIC_API_URL, ANTHROPIC_VERSION,
+};
+
+/// Default models for each kind of call
+const DEFAULT_DETECTION_MODEL: &str = "claude-haiku-4-5";
+const DEFAULT_TRANSLATION_MODEL: &str = "claude-sonnet-4-5";
+const DEFAULT_COMPOSE_MODEL: &str = "claude-opus-4-5";
+const DEFAULT_SUGGESTION_MODEL: &str = "claude-haiku-4-5";
+
+/// Texts shorter than this (in bytes) aren't language-detected
+pub(crate) const MIN_DETECTION_LEN: usize = 5;
+
+/// Default latency above which a single API call is logged as slow
+const DEFAULT_SLOW_CALL_THRESHOLD_MS: u64 = 5000;
+
+/// Longest the API key check at startup waits for an answer
+const KEY_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default pricing per million tokens by model family (as of 2025)
 /// Haiku 4.5: $1/M input, $5/M output
 /// Sonnet 4.5: $3/M input, $15/M output
 /// Opus 4.5: $5/M input, $25/M output
@@ -24,12 +47,276 @@ const SONNET_INPUT_COST_PER_M: f64 = 3.0;
 const SONNET_OUTPUT_COST_PER_M: f64 = 15.0;
 const OPUS_INPUT_COST_PER_M: f64 = 5.0;
 const OPUS_OUTPUT_COST_PER_M: f64 = 25.0;
+/// OpenAI fallback models: GPT-4o mini $0.15/M input, $0.60/M output;
+/// GPT-4o $2.50/M input, $10/M output
+const GPT_4O_MINI_INPUT_COST_PER_M: f64 = 0.15;
+const GPT_4O_MINI_OUTPUT_COST_PER_M: f64 = 0.6;
+const GPT_4O_INPUT_COST_PER_M: f64 = 2.5;
+const GPT_4O_OUTPUT_COST_PER_M: f64 = 10.0;
+
+/// Longest model ID accepted
+const MAX_MODEL_ID_LEN: usize = 100;
+
+/// Learning mode skips vocabulary for messages shorter than this (characters)
+pub const MIN_VOCAB_TEXT_CHARS: usize = 20;
+/// Vocabulary entries asked for per message in learning mode
+const MIN_VOCAB_ENTRIES: usize = 3;
+const MAX_VOCAB_ENTRIES: usize = 5;
+/// Learning notes asked for per outgoing message
+const MAX_LEARNING_NOTES: usize = 2;
+
+/// Longest custom translation tone instruction, in characters
+pub const MAX_TONE_CHARS: usize = 200;
+
+/// Register used when translating my messages to a contact, and when
+/// composing messages for them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TranslationTone {
+    Formal,
+    Neutral,
+    Casual,
+    /// My own instruction, e.g. "Polite but warm, she's my landlord"
+    Custom(String),
+}
+
+impl TranslationTone {
+    /// The tone with a custom instruction trimmed, or None if the
+    /// instruction is empty or longer than `MAX_TONE_CHARS`
+    pub fn validated(self) -> Option<Self> {
+        match self {
+            TranslationTone::Custom(text) => {
+                let text = text.trim();
+                (!text.is_empty() && text.chars().count() <= MAX_TONE_CHARS)
+                    .then(|| TranslationTone::Custom(text.to_string()))
+            }
+            tone => Some(tone),
+        }
+    }
+
+    /// Instruction added to the prompt
+    pub fn instruction(&self) -> &str {
+        match self {
+            TranslationTone::Formal => {
+                "Use a formal register appropriate for a professional relationship."
+            }
+            TranslationTone::Neutral => "Use a neutral register, neither formal nor familiar.",
+            TranslationTone::Casual => "Use a casual register, as between friends.",
+            TranslationTone::Custom(text) => text,
+        }
+    }
+}
+
+/// Price of a model in USD per million tokens
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelPricing {
+    pub input_per_m: f64,
+    pub output_per_m: f64,
+}
+
+impl ModelPricing {
+    /// Default pricing for a model, by its family
+    fn for_family(model: &str) -> Option<Self> {
+        let (input_per_m, output_per_m) = if model.contains("haiku") {
+            (HAIKU_INPUT_COST_PER_M, HAIKU_OUTPUT_COST_PER_M)
+        } else if model.contains("sonnet") {
+            (SONNET_INPUT_COST_PER_M, SONNET_OUTPUT_COST_PER_M)
+        } else if model.contains("opus") {
+            (OPUS_INPUT_COST_PER_M, OPUS_OUTPUT_COST_PER_M)
+        } else if model.starts_with("gpt-4o-mini") {
+            (GPT_4O_MINI_INPUT_COST_PER_M, GPT_4O_MINI_OUTPUT_COST_PER_M)
+        } else if model.starts_with("gpt-4o") {
+            (GPT_4O_INPUT_COST_PER_M, GPT_4O_OUTPUT_COST_PER_M)
+        } else {
+            return None;
+        };
+        Some(Self {
+            input_per_m,
+            output_per_m,
+        })
+    }
+
+    fn cost(&self, usage: &ApiUsage) -> f64 {
+        let input_cost = (usage.input_tokens as f64 / 1_000_000.0) * self.input_per_m;
+        let output_cost = (usage.output_tokens as f64 / 1_000_000.0) * self.output_per_m;
+        input_cost + output_cost
+    }
+}
+
+/// Parse a `MODEL=INPUT:OUTPUT` pricing override (USD per million tokens)
+pub fn parse_model_pricing(value: &str) -> Result<(String, ModelPricing), String> {
+    let (model, prices) = value
+        .split_once('=')
+        .ok_or("expected MODEL=INPUT:OUTPUT, e.g. claude-haiku-4-5=1:5")?;
+    let (input, output) = prices
+        .split_once(':')
+        .ok_or("expected prices as INPUT:OUTPUT per million tokens")?;
+    let price = |p: &str| {
+        p.trim()
+            .parse::<f64>()
+            .map_err(|_| format!("invalid price: {}", p))
+    };
+    Ok((
+        model.trim().to_string(),
+        ModelPricing {
+            input_per_m: price(input)?,
+            output_per_m: price(output)?,
+        },
+    ))
+}
+
+/// Which model each kind of call uses, and what models cost
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelConfig {
+    /// Language detection
+    pub detection: String,
+    /// Translating messages
+    pub translation: String,
+    /// AI compose and AI reply
+    pub compose: String,
+    /// Reply suggestions
+    pub suggestion: String,
+    /// Pricing by model ID; other models use their family's default pricing
+    #[serde(default)]
+    pub pricing: BTreeMap<String, ModelPricing>,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        Self {
+            detection: DEFAULT_DETECTION_MODEL.to_string(),
+            translation: DEFAULT_TRANSLATION_MODEL.to_string(),
+            compose: DEFAULT_COMPOSE_MODEL.to_string(),
+            suggestion: DEFAULT_SUGGESTION_MODEL.to_string(),
+            pricing: BTreeMap::new(),
+        }
+    }
+}
+
+/// Changes to a [`ModelConfig`]; unset models are kept
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelUpdate {
+    pub detection: Option<String>,
+    pub translation: Option<String>,
+    pub compose: Option<String>,
+    pub suggestion: Option<String>,
+    /// Pricing to add or replace, by model ID
+    #[serde(default)]
+    pub pricing: BTreeMap<String, ModelPricing>,
+}
+
+impl ModelConfig {
+    /// Apply an update, replacing the models and pricing it sets
+    pub fn apply(&mut self, update: ModelUpdate) {
+        let models = [
+            (&mut self.detection, update.detection),
+            (&mut self.translation, update.translation),
+            (&mut self.compose, update.compose),
+            (&mut self.suggestion, update.suggestion),
+        ];
+        for (model, new) in models {
+            if let Some(new) = new {
+                *model = new.trim().to_string();
+            }
+        }
+        self.pricing.extend(update.pricing);
+    }
+
+    /// What a model costs: its configured pricing, else its family's
+    pub fn pricing_for(&self, model: &str) -> Option<ModelPricing> {
+        self.pricing
+            .get(model)
+            .copied()
+            .or_else(|| ModelPricing::for_family(model))
+    }
+
+    /// Reject model IDs that can't be right and models with unknown pricing
+    pub fn validate(&self) -> Result<()> {
+        for (kind, model) in [
+            ("detection", &self.detection),
+            ("translation", &self.translation),
+            ("compose", &self.compose),
+            ("suggestion", &self.suggestion),
+        ] {
+            let valid_chars = model
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_' | ':' | '@'));
+            if model.is_empty()
+                || model.len() > MAX_MODEL_ID_LEN
+                || !valid_chars
+                || !model.contains("claude")
+            {
+                anyhow::bail!("Invalid {} model ID: {:?}", kind, model);
+            }
+            if self.pricing_for(model).is_none() {
+                anyhow::bail!(
+                    "No pricing known for {} model {}; set it with --model-pricing {}=INPUT:OUTPUT",
+                    kind,
+                    model,
+                    model
+                );
+            }
+        }
+        for (model, pricing) in &self.pricing {
+            let valid = |p: f64| p.is_finite() && p >= 0.0;
+            if !valid(pricing.input_per_m) || !valid(pricing.output_per_m) {
+                anyhow::bail!("Invalid pricing for {}: {:?}", model, pricing);
+            }
+        }
+        Ok(())
+    }
+}
 
 /// Translation service for processing messages
 pub struct TranslationService {
     client: Client,
     api_key: String,
     default_language: String,
+    api_url: String,
+    slow_call_threshold_ms: u64,
+    /// Clean up model output meant to be sent (see [`sanitize_model_output`])
+    sanitize_output: bool,
+    /// Translate incoming channel posts, which tend to be long-form
+    translate_channels: bool,
+    /// Models and pricing, changeable while running
+    models: RwLock<ModelConfig>,
+    /// Failed API calls, for the UI
+    errors: ErrorRegistry,
+    /// Where detection and translation go, in order of preference
+    providers: Vec<ChainedProvider>,
+    /// The provider that served the last detection or translation
+    active_provider: watch::Sender<ProviderKind>,
+    /// Why nothing can be translated, until a call to Claude succeeds
+    unavailable: watch::Sender<Option<TranslationUnavailable>>,
+    /// What API calls have cost since startup, in millionths of a USD
+    spent_micro_usd: AtomicU64,
+}
+
+/// Why translation can't work at all
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TranslationUnavailable {
+    /// Claude rejects the API key
+    InvalidApiKey,
+}
+
+impl TranslationUnavailable {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TranslationUnavailable::InvalidApiKey => "invalid_api_key",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            TranslationUnavailable::InvalidApiKey => {
+                "The Anthropic API key was rejected, so messages aren't being translated. Set a valid ANTHROPIC_API_KEY and restart."
+            }
+        }
+    }
 }
 
 /// Result of processing a message for translation
@@ -45,6 +332,170 @@ pub struct TranslationResult {
     pub source_language: String,
     /// Token usage and cost for this translation
     pub usage: UsageInfo,
+    /// Notable words from the original, when learning mode asked for them
+    pub vocabulary: Vec<VocabEntry>,
+    /// Urgency and tone, when triage asked for them and the model gave them
+    pub triage: Option<Triage>,
+    /// Why it was or wasn't translated
+    pub status: TranslationStatus,
+}
+
+/// Why a message was or wasn't translated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TranslationStatus {
+    Translated,
+    /// Already in the chat's language
+    NotNeeded,
+    /// Too short to detect its language
+    SkippedShort,
+    /// Filtered out: a channel, a participant set to never, notes to myself
+    SkippedFilter,
+    /// Over a spending limit
+    SkippedBudget,
+    /// Detection or translation failed
+    Error,
+    /// Not attempted yet (history sync, no translator configured, re-queued)
+    Pending,
+}
+
+impl TranslationStatus {
+    pub const ALL: [Self; 7] = [
+        Self::Translated,
+        Self::NotNeeded,
+        Self::SkippedShort,
+        Self::SkippedFilter,
+        Self::SkippedBudget,
+        Self::Error,
+        Self::Pending,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Translated => "translated",
+            Self::NotNeeded => "not_needed",
+            Self::SkippedShort => "skipped_short",
+            Self::SkippedFilter => "skipped_filter",
+            Self::SkippedBudget => "skipped_budget",
+            Self::Error => "error",
+            Self::Pending => "pending",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|status| status.as_str() == value)
+    }
+}
+
+/// How soon an incoming message seems to need attention
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Urgency {
+    Low,
+    Normal,
+    High,
+}
+
+/// The tone an incoming message is written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tone {
+    Positive,
+    Neutral,
+    Negative,
+}
+
+impl Urgency {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Normal => "normal",
+            Self::High => "high",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "low" => Some(Self::Low),
+            "normal" | "medium" => Some(Self::Normal),
+            "high" => Some(Self::High),
+            _ => None,
+        }
+    }
+}
+
+impl Tone {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Positive => "positive",
+            Self::Neutral => "neutral",
+            Self::Negative => "negative",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "positive" => Some(Self::Positive),
+            "neutral" => Some(Self::Neutral),
+            "negative" => Some(Self::Negative),
+            _ => None,
+        }
+    }
+}
+
+/// Coarse triage of an incoming message, classified alongside its language.
+/// Either half is missing if the model left it out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Triage {
+    pub urgency: Option<Urgency>,
+    pub tone: Option<Tone>,
+}
+
+impl Triage {
+    /// None if neither half is known
+    pub fn new(urgency: Option<Urgency>, tone: Option<Tone>) -> Option<Self> {
+        (urgency.is_some() || tone.is_some()).then_some(Self { urgency, tone })
+    }
+}
+
+/// A word or phrase from an incoming message with its meaning
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VocabEntry {
+    /// The word or phrase as written in the original
+    pub term: String,
+    /// Short meaning in the translation language
+    pub gloss: String,
+}
+
+/// A phrase from one of my messages and what it was translated to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LearningNote {
+    /// The phrase as I wrote it
+    pub source: String,
+    /// Its translation, as sent
+    pub target: String,
+}
+
+/// Learning notes are stored in a message's vocabulary, the translated
+/// phrase being the term to learn
+impl From<LearningNote> for VocabEntry {
+    fn from(note: LearningNote) -> Self {
+        Self {
+            term: note.target,
+            gloss: note.source,
+        }
+    }
+}
+
+impl From<VocabEntry> for LearningNote {
+    fn from(entry: VocabEntry) -> Self {
+        Self {
+            source: entry.gloss,
+            target: entry.term,
+        }
+    }
 }
 
 /// Claude API request structure
@@ -98,9 +549,9 @@ struct ClaudeResponse {
 }
 
 #[derive(Deserialize, Debug, Clone, Copy, Default)]
-struct ApiUsage {
-    input_tokens: u32,
-    output_tokens: u32,
+pub(crate) struct ApiUsage {
+    pub(crate) input_tokens: u32,
+    pub(crate) output_tokens: u32,
 }
 
 #[derive(Deserialize)]
@@ -109,7 +560,7 @@ struct ContentBlock {
 }
 
 /// Token usage and cost information
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct UsageInfo {
     /// Total input tokens used
     pub input_tokens: u32,
@@ -117,14 +568,99 @@ pub struct UsageInfo {
     pub output_tokens: u32,
     /// Total cost in USD
     pub cost_usd: f64,
+    /// Individual API calls that make up this usage, with their latency
+    pub calls: Vec<ApiCall>,
+}
+
+/// A single timed API call
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiCall {
+    pub provider: ProviderKind,
+    pub model: String,
+    /// Wall-clock time from sending the request to reading the full response
+    pub latency_ms: u64,
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+    pub cost_usd: f64,
+}
+
+impl UsageInfo {
+    /// Usage for a single API call
+    fn from_call(call: ApiCall) -> Self {
+        Self {
+            input_tokens: call.input_tokens,
+            output_tokens: call.output_tokens,
+            cost_usd: call.cost_usd,
+            calls: vec![call],
+        }
+    }
+
+    /// Total wall-clock latency of all API calls
+    pub fn latency_ms(&self) -> u64 {
+        self.calls.iter().map(|c| c.latency_ms).sum()
+    }
+}
+
+/// Raw response from a timed API call
+struct TimedResponse {
+    status: reqwest::StatusCode,
+    body: String,
+    provider: ProviderKind,
+    model: String,
+    latency_ms: u64,
+}
+
+impl TimedResponse {
+    /// Build usage info for this call from the parsed token counts
+    fn usage(&self, usage: &ApiUsage, cost_usd: f64) -> UsageInfo {
+        UsageInfo::from_call(ApiCall {
+            provider: self.provider,
+            model: self.model.clone(),
+            latency_ms: self.latency_ms,
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            cost_usd,
+        })
+    }
 }
 
-/// Language detection result
+/// Language detection result. The triage fields are only asked for with
+/// triage, and taken as loosely as possible so they can't cost the language.
 #[derive(Deserialize)]
 struct LanguageDetection {
     language: String,
     #[serde(rename = "isEnglish")]
     is_english: bool,
+    #[serde(default)]
+    urgency: Option<serde_json::Value>,
+    #[serde(default)]
+    tone: Option<serde_json::Value>,
+}
+
+/// What a language detection call found
+struct Detection {
+    is_default: bool,
+    language: String,
+    triage: Option<Triage>,
+}
+
+/// Parse a detection reply: the language, and triage if it's there
+fn parse_detection(content: &str) -> Option<Detection> {
+    let start = content.find('{')?;
+    let end = content.rfind('}')?;
+    let detection: LanguageDetection = serde_json::from_str(content.get(start..=end)?).ok()?;
+    let field = |value: &Option<serde_json::Value>| value.as_ref()?.as_str().map(str::to_string);
+    let triage = Triage::new(
+        field(&detection.urgency)
+            .as_deref()
+            .and_then(Urgency::parse),
+        field(&detection.tone).as_deref().and_then(Tone::parse),
+    );
+    Some(Detection {
+        is_default: detection.is_english,
+        language: detection.language,
+        triage,
+    })
 }
 
 impl TranslationService {
@@ -136,88 +672,392 @@ impl TranslationService {
         );
         Self {
             client: Client::new(),
+            providers: vec![ChainedProvider::new(Box::new(AnthropicProvider::new(
+                api_key.clone(),
+            )))],
+            active_provider: watch::Sender::new(ProviderKind::Anthropic),
+            unavailable: watch::Sender::new(None),
+            spent_micro_usd: AtomicU64::new(0),
             api_key,
             default_language,
+            api_url: ANTHROPIC_API_URL.to_string(),
+            slow_call_threshold_ms: DEFAULT_SLOW_CALL_THRESHOLD_MS,
+            sanitize_output: true,
+            translate_channels: true,
+            models: RwLock::new(ModelConfig::default()),
+            errors: ErrorRegistry::default(),
         }
     }
 
-    /// Get the API key (for creating other services like StyleAnalyzer)
-    pub fn get_api_key(&self) -> String {
-        self.api_key.clone()
+    /// Where failed API calls are recorded
+    pub fn errors(&self) -> &ErrorRegistry {
+        &self.errors
     }
 
-    /// Calculate cost for Haiku model usage
-    fn calculate_haiku_cost(usage: &ApiUsage) -> f64 {
-        let input_cost = (usage.input_tokens as f64 / 1_000_000.0) * HAIKU_INPUT_COST_PER_M;
-        let output_cost = (usage.output_tokens as f64 / 1_000_000.0) * HAIKU_OUTPUT_COST_PER_M;
-        input_cost + output_cost
+    /// Detect and translate with these providers, trying each in turn when
+    /// the ones before it fail. Compose and suggestions always use Claude.
+    pub fn with_providers(mut self, providers: Vec<Box<dyn Provider>>) -> Self {
+        if let Some(primary) = providers.first() {
+            self.active_provider.send_replace(primary.kind());
+            self.providers = providers.into_iter().map(ChainedProvider::new).collect();
+        }
+        self
     }
 
-    /// Calculate cost for Sonnet model usage
-    fn calculate_sonnet_cost(usage: &ApiUsage) -> f64 {
-        let input_cost = (usage.input_tokens as f64 / 1_000_000.0) * SONNET_INPUT_COST_PER_M;
-        let output_cost = (usage.output_tokens as f64 / 1_000_000.0) * SONNET_OUTPUT_COST_PER_M;
-        input_cost + output_cost
+    /// The provider that served the last detection or translation, and
+    /// whether it is a fallback
+    pub fn active_provider(&self) -> (ProviderKind, bool) {
+        let active = *self.active_provider.borrow();
+        (active, active != self.providers[0].provider.kind())
     }
 
-    /// Calculate cost for Opus model usage
-    fn calculate_opus_cost(usage: &ApiUsage) -> f64 {
-        let input_cost = (usage.input_tokens as f64 / 1_000_000.0) * OPUS_INPUT_COST_PER_M;
-        let output_cost = (usage.output_tokens as f64 / 1_000_000.0) * OPUS_OUTPUT_COST_PER_M;
-        input_cost + output_cost
+    /// Follow changes of provider, e.g. to a fallback while the primary is
+    /// failing
+    pub fn subscribe_provider(&self) -> watch::Receiver<ProviderKind> {
+        self.active_provider.subscribe()
     }
 
-    /// Detect if text is in the default language
-    async fn detect_language(&self, text: &str) -> Result<(bool, String, UsageInfo)> {
-        // Skip very short messages
-        if text.trim().len() < 5 {
-            return Ok((true, self.default_language.clone(), UsageInfo::default()));
+    fn set_active_provider(&self, kind: ProviderKind) {
+        if self.active_provider.send_replace(kind) == kind {
+            return;
+        }
+        if kind == self.providers[0].provider.kind() {
+            info!("Translating with {} again", kind.as_str());
+        } else {
+            warn!(
+                "Translating with fallback provider {}; quality may differ",
+                kind.as_str()
+            );
         }
+    }
 
-        let prompt = format!(
-            r#"Detect the language of this text and respond with ONLY a JSON object in this exact format: {{"language": "Language Name", "isEnglish": true/false}}
+    /// Why nothing can be translated, if that's the case
+    pub fn unavailable(&self) -> Option<TranslationUnavailable> {
+        *self.unavailable.borrow()
+    }
 
-Text: "{}""#,
-            text.chars().take(500).collect::<String>()
-        );
+    /// Follow whether translation is unavailable
+    pub fn subscribe_unavailable(&self) -> watch::Receiver<Option<TranslationUnavailable>> {
+        self.unavailable.subscribe()
+    }
 
-        let request = ClaudeRequest {
-            model: DETECTION_MODEL.to_string(),
-            max_tokens: 100,
-            messages: vec![ClaudeMessage {
-                role: "user".to_string(),
-                content: prompt,
-            }],
+    /// Note what Claude's answer says about the API key: rejected, or
+    /// evidently fine. Anything else says nothing about it.
+    fn note_key_status(&self, status: StatusCode) {
+        let unavailable = if status == StatusCode::UNAUTHORIZED {
+            Some(TranslationUnavailable::InvalidApiKey)
+        } else if status.is_success() {
+            None
+        } else {
+            return;
         };
+        let changed = self
+            .unavailable
+            .send_if_modified(|current| std::mem::replace(current, unavailable) != unavailable);
+        if !changed {
+            return;
+        }
+        match unavailable {
+            Some(reason) => error!("Translation unavailable: {}", reason.description()),
+            None => info!("Anthropic API key accepted, translating again"),
+        }
+    }
 
+    /// Check the API key by listing models, which uses no tokens. Ok(false)
+    /// if Claude rejects it, which makes translation unavailable. Errors
+    /// (network trouble, the API failing) say nothing about the key.
+    pub async fn check_api_key(&self) -> Result<bool> {
+        let url = match self.api_url.strip_suffix("/messages") {
+            Some(base) => format!("{}/models", base),
+            None => self.api_url.clone(),
+        };
         let response = self
             .client
-            .post(ANTHROPIC_API_URL)
+            .get(url)
             .header("x-api-key", &self.api_key)
             .header("anthropic-version", ANTHROPIC_VERSION)
-            .header("content-type", "application/json")
-            .json(&request)
+            .timeout(KEY_CHECK_TIMEOUT)
             .send()
             .await
-            .context("Failed to send language detection request")?;
+            .context("Couldn't reach the Anthropic API")?;
+        let status = response.status();
+        self.note_key_status(status);
+        match status {
+            StatusCode::UNAUTHORIZED => Ok(false),
+            s if s.is_success() => Ok(true),
+            s => anyhow::bail!("The Anthropic API answered {}", s),
+        }
+    }
+
+    /// Use these models and pricing instead of the defaults
+    pub fn with_models(self, models: ModelConfig) -> Self {
+        self.set_models(models);
+        self
+    }
+
+    /// The models and pricing in use
+    pub fn models(&self) -> ModelConfig {
+        self.models.read().unwrap().clone()
+    }
+
+    /// Switch models or pricing; takes effect from the next API call
+    pub fn set_models(&self, models: ModelConfig) {
+        info!(
+            "Models: detection {}, translation {}, compose {}, suggestion {}",
+            models.detection, models.translation, models.compose, models.suggestion
+        );
+        *self.models.write().unwrap() = models;
+    }
+
+    /// Cost of an API call to `model` (zero if its pricing is unknown)
+    fn cost(&self, model: &str, usage: &ApiUsage) -> f64 {
+        let cost = self
+            .models
+            .read()
+            .unwrap()
+            .pricing_for(model)
+            .map(|pricing| pricing.cost(usage))
+            .unwrap_or(0.0);
+        self.spent_micro_usd
+            .fetch_add((cost * 1_000_000.0).round() as u64, Ordering::Relaxed);
+        cost
+    }
+
+    /// What API calls have cost since startup (USD)
+    pub fn spent_usd(&self) -> f64 {
+        self.spent_micro_usd.load(Ordering::Relaxed) as f64 / 1_000_000.0
+    }
+
+    /// Set the latency above which an API call is logged as slow
+    pub fn with_slow_call_threshold(mut self, threshold_ms: u64) -> Self {
+        self.slow_call_threshold_ms = threshold_ms;
+        self
+    }
+
+    /// Enable or disable cleaning up translations and composed messages
+    /// before they are sent
+    pub fn with_output_sanitizer(mut self, enabled: bool) -> Self {
+        self.sanitize_output = enabled;
+        self
+    }
+
+    /// Enable or disable translating incoming channel posts
+    pub fn with_channel_translation(mut self, enabled: bool) -> Self {
+        self.translate_channels = enabled;
+        self
+    }
+
+    /// Whether incoming channel posts should be translated
+    pub fn translates_channels(&self) -> bool {
+        self.translate_channels
+    }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            warn!("Language detection API error: {} - {}", status, body);
-            return Ok((true, self.default_language.clone(), UsageInfo::default()));
+    /// Apply the output sanitizer to text that is about to be sent, if enabled
+    fn clean_output(&self, text: &str) -> String {
+        if self.sanitize_output {
+            sanitize_model_output(text)
+        } else {
+            text.trim().to_string()
         }
+    }
 
-        let claude_response: ClaudeResponse = response
-            .json()
+    /// Point the service at a different API endpoint (used by tests)
+    #[cfg(test)]
+    pub(crate) fn with_api_url(mut self, api_url: &str) -> Self {
+        self.api_url = api_url.to_string();
+        for entry in &mut self.providers {
+            if entry.provider.kind() == ProviderKind::Anthropic {
+                *entry = ChainedProvider::new(Box::new(AnthropicProvider::with_url(
+                    self.api_key.clone(),
+                    api_url,
+                )));
+            }
+        }
+        self
+    }
+
+    /// Use circuit breakers with these settings (used by tests)
+    #[cfg(test)]
+    pub(crate) fn with_circuit_breakers(
+        mut self,
+        threshold: u32,
+        cooldown: std::time::Duration,
+    ) -> Self {
+        for entry in &mut self.providers {
+            entry.breaker = crate::translation_provider::CircuitBreaker::new(threshold, cooldown);
+        }
+        self
+    }
+
+    /// Send a request to the Claude API and read the full response, timing the call
+    async fn post_timed<T: Serialize>(&self, request: &T, model: &str) -> Result<TimedResponse> {
+        let request = self
+            .client
+            .post(&self.api_url)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .header("content-type", "application/json")
+            .json(request);
+        self.send_timed(request, model, ProviderKind::Anthropic)
             .await
-            .context("Failed to parse language detection response")?;
+    }
+
+    /// Send a request to a provider and read the full response, timing the call
+    async fn send_timed(
+        &self,
+        request: reqwest::RequestBuilder,
+        model: &str,
+        provider: ProviderKind,
+    ) -> Result<TimedResponse> {
+        let started = Instant::now();
+
+        let response = request.send().await.inspect_err(|e| {
+            self.errors
+                .record(ErrorCategory::Translation, format!("{}: {}", model, e));
+        })?;
+        let status = response.status();
+        if provider == ProviderKind::Anthropic {
+            self.note_key_status(status);
+        }
+        let body = response.text().await?;
+        if !status.is_success() {
+            self.errors.record(
+                ErrorCategory::Translation,
+                format!("{} returned {}: {}", model, status, body),
+            );
+        }
+
+        let latency_ms = started.elapsed().as_millis() as u64;
+        if latency_ms > self.slow_call_threshold_ms {
+            warn!(
+                "Slow Claude API call: {} took {}ms (threshold {}ms)",
+                model, latency_ms, self.slow_call_threshold_ms
+            );
+        } else {
+            debug!("Claude API call to {} took {}ms", model, latency_ms);
+        }
+
+        Ok(TimedResponse {
+            status,
+            body,
+            provider,
+            model: model.to_string(),
+            latency_ms,
+        })
+    }
+
+    /// Send a detection or translation prompt down the provider chain,
+    /// moving on after retryable failures. Returns the reply text and usage,
+    /// or `None` if the API turned the request down (logged as `what`).
+    async fn complete(
+        &self,
+        role: ModelRole,
+        max_tokens: u32,
+        prompt: &str,
+        what: &str,
+    ) -> Result<Option<(Option<String>, UsageInfo)>> {
+        let models = self.models();
+        let mut last_failure = None;
+        for entry in &self.providers {
+            let provider = &entry.provider;
+            if !entry.breaker.allows() {
+                debug!(
+                    "Skipping {}: circuit breaker open",
+                    provider.kind().as_str()
+                );
+                continue;
+            }
+            let model = provider.model(role, &models);
+            let request = provider.request(&self.client, &model, max_tokens, prompt);
+            match self.send_timed(request, &model, provider.kind()).await {
+                Ok(response) if response.status.is_success() => {
+                    entry.breaker.succeeded();
+                    self.set_active_provider(provider.kind());
+                    let (text, usage) = provider.parse(&response.body)?;
+                    let usage_info = response.usage(&usage, self.cost(&model, &usage));
+                    return Ok(Some((text, usage_info)));
+                }
+                Ok(response) if !is_retryable(response.status) => {
+                    warn!(
+                        "{} API error: {} - {}",
+                        what, response.status, response.body
+                    );
+                    return Ok(None);
+                }
+                failure => {
+                    if entry.breaker.failed() {
+                        warn!(
+                            "{} keeps failing; skipping it for a while",
+                            provider.kind().as_str()
+                        );
+                    }
+                    last_failure = Some(failure);
+                }
+            }
+        }
+
+        match last_failure {
+            Some(Ok(response)) => {
+                warn!(
+                    "{} API error: {} - {}",
+                    what, response.status, response.body
+                );
+                Ok(None)
+            }
+            Some(Err(e)) => Err(e),
+            None => anyhow::bail!("No translation provider available: all are failing"),
+        }
+    }
+
+    /// Get the API key (for creating other services like StyleAnalyzer)
+    pub fn get_api_key(&self) -> String {
+        self.api_key.clone()
+    }
+
+    /// Detect the language of a piece of text (e.g. a draft before sending)
+    pub async fn detect_text_language(&self, text: &str) -> Result<(String, UsageInfo)> {
+        let (detection, usage) = self.detect_language(text, false).await?;
+        Ok((detection.language, usage))
+    }
+
+    /// Detect if text is in the default language. With `triage`, the same
+    /// call also classifies its urgency and tone.
+    async fn detect_language(&self, text: &str, triage: bool) -> Result<(Detection, UsageInfo)> {
+        let fallback = || Detection {
+            is_default: true,
+            language: self.default_language.clone(),
+            triage: None,
+        };
+
+        // Skip very short messages
+        if text.trim().len() < MIN_DETECTION_LEN {
+            return Ok((fallback(), UsageInfo::default()));
+        }
+
+        let text = text.chars().take(500).collect::<String>();
+        let prompt = if triage {
+            format!(
+                r#"Detect the language of this text and classify it for triage. Respond with ONLY a JSON object in this exact format: {{"language": "Language Name", "isEnglish": true/false, "urgency": "low"/"normal"/"high", "tone": "positive"/"neutral"/"negative"}}
+Urgency is high when the sender needs an answer or action soon (an emergency, a deadline, being stuck or upset) and low when nothing needs answering.
+
+Text: "{}""#,
+                text
+            )
+        } else {
+            format!(
+                r#"Detect the language of this text and respond with ONLY a JSON object in this exact format: {{"language": "Language Name", "isEnglish": true/false}}
 
-        // Calculate usage info for Haiku
-        let usage_info = UsageInfo {
-            input_tokens: claude_response.usage.input_tokens,
-            output_tokens: claude_response.usage.output_tokens,
-            cost_usd: Self::calculate_haiku_cost(&claude_response.usage),
+Text: "{}""#,
+                text
+            )
+        };
+
+        let Some((content, usage_info)) = self
+            .complete(ModelRole::Detection, 100, &prompt, "Language detection")
+            .await
+            .context("Language detection request failed")?
+        else {
+            return Ok((fallback(), UsageInfo::default()));
         };
 
         debug!(
@@ -225,38 +1065,35 @@ Text: "{}""#,
             usage_info.input_tokens, usage_info.output_tokens, usage_info.cost_usd
         );
 
-        let content = claude_response
-            .content
-            .first()
-            .and_then(|c| c.text.clone())
-            .unwrap_or_default();
+        let content = content.unwrap_or_default();
 
-        // Parse JSON from response
-        if let Some(start) = content.find('{') {
-            if let Some(end) = content.rfind('}') {
-                let json_str = &content[start..=end];
-                if let Ok(detection) = serde_json::from_str::<LanguageDetection>(json_str) {
-                    debug!(
-                        "Detected language: {} (isEnglish: {})",
-                        detection.language, detection.is_english
-                    );
-                    return Ok((detection.is_english, detection.language, usage_info));
-                }
+        match parse_detection(&content) {
+            Some(detection) => {
+                debug!(
+                    "Detected language: {} (isEnglish: {}, triage: {:?})",
+                    detection.language, detection.is_default, detection.triage
+                );
+                Ok((detection, usage_info))
             }
+            // Fallback: assume default language
+            None => Ok((fallback(), usage_info)),
         }
-
-        // Fallback: assume default language
-        Ok((true, self.default_language.clone(), usage_info))
     }
 
-    /// Translate text to a target language with optional style
+    /// Translate text to a target language with optional style.
+    ///
+    /// With `with_vocabulary`, the same call also picks out a few notable
+    /// words for learning mode. A reply that isn't the expected JSON falls
+    /// back to a plain translation, so vocabulary never costs the message
+    /// its translation.
     async fn translate(
         &self,
         text: &str,
         source_language: &str,
         target_language: Option<&str>,
         translation_style: Option<&str>,
-    ) -> Result<(String, UsageInfo)> {
+        with_vocabulary: bool,
+    ) -> Result<(String, Vec<VocabEntry>, UsageInfo)> {
         let target = target_language.unwrap_or(&self.default_language);
 
         // Build style instruction if provided
@@ -267,52 +1104,83 @@ Text: "{}""#,
             _ => String::new(),
         };
 
-        let prompt = format!(
+        if with_vocabulary {
+            let prompt = format!(
+                r#"Translate the following text (from {source}) to {target}.{style}
+Also pick {min} to {max} notable {source} words or phrases from the text that a learner would want to remember, each with a short {target} gloss.
+Respond with ONLY a JSON object in this form, nothing else:
+{{"translation": "<the translated text>", "vocabulary": [{{"term": "<word or phrase as written>", "gloss": "<short meaning>"}}]}}
+Preserve the original formatting and meaning of the text as closely as possible in the translation.
+
+Text to translate:
+{text}"#,
+                source = source_language,
+                target = target,
+                style = style_instruction,
+                min = MIN_VOCAB_ENTRIES,
+                max = MAX_VOCAB_ENTRIES,
+                text = text
+            );
+            let (reply, usage) = self.request_translation(text, prompt).await?;
+            match parse_translation_with_vocab(&reply) {
+                Some((translated, vocabulary)) => return Ok((translated, vocabulary, usage)),
+                None => {
+                    warn!("Malformed vocabulary response, translating without vocabulary");
+                    let (translated, retry_usage) = self
+                        .request_translation(
+                            text,
+                            Self::translation_prompt(
+                                text,
+                                source_language,
+                                target,
+                                &style_instruction,
+                            ),
+                        )
+                        .await?;
+                    return Ok((
+                        translated,
+                        Vec::new(),
+                        Self::combine_usage(&usage, &retry_usage),
+                    ));
+                }
+            }
+        }
+
+        let (translated, usage) = self
+            .request_translation(
+                text,
+                Self::translation_prompt(text, source_language, target, &style_instruction),
+            )
+            .await?;
+        Ok((translated, Vec::new(), usage))
+    }
+
+    /// Prompt for a plain translation
+    fn translation_prompt(
+        text: &str,
+        source_language: &str,
+        target: &str,
+        style_instruction: &str,
+    ) -> String {
+        format!(
             r#"Translate the following text (from {}) to {}.{}
 Respond with ONLY the translated text, nothing else. Preserve the original formatting and meaning as closely as possible.
 
 Text to translate:
 {}"#,
             source_language, target, style_instruction, text
-        );
-
-        let request = ClaudeRequest {
-            model: TRANSLATION_MODEL.to_string(),
-            max_tokens: 2000,
-            messages: vec![ClaudeMessage {
-                role: "user".to_string(),
-                content: prompt,
-            }],
-        };
+        )
+    }
 
-        let response = self
-            .client
-            .post(ANTHROPIC_API_URL)
-            .header("x-api-key", &self.api_key)
-            .header("anthropic-version", ANTHROPIC_VERSION)
-            .header("content-type", "application/json")
-            .json(&request)
-            .send()
+    /// Send a translation prompt and return the trimmed reply, or `text`
+    /// unchanged if the API rejects the request
+    async fn request_translation(&self, text: &str, prompt: String) -> Result<(String, UsageInfo)> {
+        let Some((translated, usage_info)) = self
+            .complete(ModelRole::Translation, 2000, &prompt, "Translation")
             .await
-            .context("Failed to send translation request")?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            warn!("Translation API error: {} - {}", status, body);
+            .context("Translation request failed")?
+        else {
             return Ok((text.to_string(), UsageInfo::default()));
-        }
-
-        let claude_response: ClaudeResponse = response
-            .json()
-            .await
-            .context("Failed to parse translation response")?;
-
-        // Calculate usage info for Sonnet
-        let usage_info = UsageInfo {
-            input_tokens: claude_response.usage.input_tokens,
-            output_tokens: claude_response.usage.output_tokens,
-            cost_usd: Self::calculate_sonnet_cost(&claude_response.usage),
         };
 
         debug!(
@@ -320,32 +1188,121 @@ Text to translate:
             usage_info.input_tokens, usage_info.output_tokens, usage_info.cost_usd
         );
 
-        let translated = claude_response
-            .content
-            .first()
-            .and_then(|c| c.text.clone())
-            .unwrap_or_else(|| text.to_string());
+        let translated = translated.unwrap_or_else(|| text.to_string());
 
         Ok((translated.trim().to_string(), usage_info))
     }
 
+    /// Prompt for translating one of my messages, in the contact's tone if
+    /// one is set. With `learning_notes` the model also appends a few key
+    /// phrases and their translations on a last line of JSON.
+    fn outgoing_prompt(
+        text: &str,
+        target_language: &str,
+        tone: Option<&TranslationTone>,
+        learning_notes: bool,
+    ) -> String {
+        let (tone, preserve) = match tone {
+            Some(tone) => (
+                format!("\n{}", tone.instruction()),
+                "formatting and meaning",
+            ),
+            None => (String::new(), "formatting, tone, and meaning"),
+        };
+        let (respond, notes) = if learning_notes {
+            (
+                "Respond with the translated text",
+                format!(
+                    r#", then on a last line of its own a JSON object with up to {} key phrases from the text and their translations, for a learner to remember:
+{{"notes": [{{"source": "<phrase as written>", "target": "<its translation>"}}]}}
+Nothing else."#,
+                    MAX_LEARNING_NOTES
+                ),
+            )
+        } else {
+            (
+                "Respond with ONLY the translated text",
+                ", nothing else.".to_string(),
+            )
+        };
+        format!(
+            r#"Translate the following text to {}.{}
+{}{} Preserve the original {} as closely as possible.
+
+Text to translate:
+{}"#,
+            target_language, tone, respond, notes, preserve, text
+        )
+    }
+
+    /// Translate one of my messages, with learning notes if asked for. A
+    /// reply with notes but no translation is asked for again without
+    /// them, so the notes never cost the message its translation.
+    async fn translate_mine(
+        &self,
+        text: &str,
+        target_language: &str,
+        tone: Option<&TranslationTone>,
+        learning_notes: bool,
+    ) -> Result<(String, Vec<LearningNote>, UsageInfo)> {
+        let prompt = Self::outgoing_prompt(text, target_language, tone, learning_notes);
+        let Some((reply, mut usage)) = self
+            .complete(ModelRole::Translation, 2000, &prompt, "Translation")
+            .await
+            .context("Translation request failed")?
+        else {
+            return Ok((text.to_string(), Vec::new(), UsageInfo::default()));
+        };
+
+        let reply = reply.unwrap_or_else(|| text.to_string());
+        let (translated, notes) = if !learning_notes {
+            (reply, Vec::new())
+        } else if let Some((translated, notes)) = split_learning_notes(&reply) {
+            (translated, notes)
+        } else {
+            warn!("Malformed learning notes response, translating without notes");
+            let prompt = Self::outgoing_prompt(text, target_language, tone, false);
+            let Some((reply, retry_usage)) = self
+                .complete(ModelRole::Translation, 2000, &prompt, "Translation")
+                .await
+                .context("Translation request failed")?
+            else {
+                return Ok((text.to_string(), Vec::new(), usage));
+            };
+            usage = Self::combine_usage(&usage, &retry_usage);
+            (reply.unwrap_or_else(|| text.to_string()), Vec::new())
+        };
+
+        debug!(
+            "Outgoing translation usage: {} in, {} out, ${:.6}",
+            usage.input_tokens, usage.output_tokens, usage.cost_usd
+        );
+
+        Ok((self.clean_output(&translated), notes, usage))
+    }
+
     /// Translate text to a specific target language.
     /// Used for translating outgoing messages to match the conversation language.
-    /// Returns (translated_text, usage_info)
+    /// With `learning_notes`, also picks out up to two key phrases and their
+    /// translations; they're never part of the translated text.
+    /// Returns (translated_text, learning_notes, usage_info)
     pub async fn translate_to(
         &self,
         text: &str,
         target_language: &str,
-    ) -> Result<(String, UsageInfo)> {
+        tone: Option<&TranslationTone>,
+        learning_notes: bool,
+    ) -> Result<(String, Vec<LearningNote>, UsageInfo)> {
         let mut total_usage = UsageInfo::default();
 
         // Skip if target is the default language (likely English)
         if target_language.to_lowercase() == self.default_language.to_lowercase() {
-            return Ok((text.to_string(), total_usage));
+            return Ok((text.to_string(), Vec::new(), total_usage));
         }
 
         // First detect if the text is already in the target language
-        let (_is_target_lang, detected_lang, detection_usage) = self.detect_language(text).await?;
+        let (detection, detection_usage) = self.detect_language(text, false).await?;
+        let detected_lang = detection.language;
         total_usage = Self::combine_usage(&total_usage, &detection_usage);
 
         // If the text appears to be in the target language already, skip translation
@@ -354,7 +1311,7 @@ Text to translate:
                 "Text already in target language ({}), skipping translation",
                 target_language
             );
-            return Ok((text.to_string(), total_usage));
+            return Ok((text.to_string(), Vec::new(), total_usage));
         }
 
         info!(
@@ -362,89 +1319,38 @@ Text to translate:
             detected_lang, target_language
         );
 
-        let prompt = format!(
-            r#"Translate the following text to {}.
-Respond with ONLY the translated text, nothing else. Preserve the original formatting, tone, and meaning as closely as possible.
-
-Text to translate:
-{}"#,
-            target_language, text
-        );
-
-        let request = ClaudeRequest {
-            model: TRANSLATION_MODEL.to_string(),
-            max_tokens: 2000,
-            messages: vec![ClaudeMessage {
-                role: "user".to_string(),
-                content: prompt,
-            }],
-        };
-
-        let response = self
-            .client
-            .post(ANTHROPIC_API_URL)
-            .header("x-api-key", &self.api_key)
-            .header("anthropic-version", ANTHROPIC_VERSION)
-            .header("content-type", "application/json")
-            .json(&request)
-            .send()
-            .await
-            .context("Failed to send translation request")?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            warn!("Translation API error: {} - {}", status, body);
-            return Ok((text.to_string(), total_usage));
-        }
-
-        let claude_response: ClaudeResponse = response
-            .json()
-            .await
-            .context("Failed to parse translation response")?;
-
-        // Calculate usage info for Sonnet
-        let translation_usage = UsageInfo {
-            input_tokens: claude_response.usage.input_tokens,
-            output_tokens: claude_response.usage.output_tokens,
-            cost_usd: Self::calculate_sonnet_cost(&claude_response.usage),
-        };
-        total_usage = Self::combine_usage(&total_usage, &translation_usage);
-
-        debug!(
-            "Outgoing translation usage: {} in, {} out, ${:.6}",
-            translation_usage.input_tokens,
-            translation_usage.output_tokens,
-            translation_usage.cost_usd
-        );
-
-        let translated = claude_response
-            .content
-            .first()
-            .and_then(|c| c.text.clone())
-            .unwrap_or_else(|| text.to_string());
-
-        Ok((translated.trim().to_string(), total_usage))
+        let (translated, notes, translation_usage) = self
+            .translate_mine(text, target_language, tone, learning_notes)
+            .await?;
+        Ok((
+            translated,
+            notes,
+            Self::combine_usage(&total_usage, &translation_usage),
+        ))
     }
 
     /// Translate outgoing text to a specific target language.
     /// When force=true, always translates even if text appears to already be in target language.
     /// Used for translating outgoing messages when user has set a language override.
+    /// Learning notes as for `translate_to`.
     pub async fn translate_outgoing(
         &self,
         text: &str,
         target_language: &str,
         force: bool,
-    ) -> Result<(String, UsageInfo)> {
+        tone: Option<&TranslationTone>,
+        learning_notes: bool,
+    ) -> Result<(String, Vec<LearningNote>, UsageInfo)> {
         let mut total_usage = UsageInfo::default();
 
         // If not forcing, skip if target is the default language (likely English)
         if !force && target_language.to_lowercase() == self.default_language.to_lowercase() {
-            return Ok((text.to_string(), total_usage));
+            return Ok((text.to_string(), Vec::new(), total_usage));
         }
 
         // Detect the source language
-        let (_is_target_lang, detected_lang, detection_usage) = self.detect_language(text).await?;
+        let (detection, detection_usage) = self.detect_language(text, false).await?;
+        let detected_lang = detection.language;
         total_usage = Self::combine_usage(&total_usage, &detection_usage);
 
         // If not forcing, skip if text is already in target language
@@ -453,7 +1359,7 @@ Text to translate:
                 "Text already in target language ({}), skipping translation",
                 target_language
             );
-            return Ok((text.to_string(), total_usage));
+            return Ok((text.to_string(), Vec::new(), total_usage));
         }
 
         info!(
@@ -461,68 +1367,14 @@ Text to translate:
             detected_lang, target_language, force
         );
 
-        let prompt = format!(
-            r#"Translate the following text to {}.
-Respond with ONLY the translated text, nothing else. Preserve the original formatting, tone, and meaning as closely as possible.
-
-Text to translate:
-{}"#,
-            target_language, text
-        );
-
-        let request = ClaudeRequest {
-            model: TRANSLATION_MODEL.to_string(),
-            max_tokens: 2000,
-            messages: vec![ClaudeMessage {
-                role: "user".to_string(),
-                content: prompt,
-            }],
-        };
-
-        let response = self
-            .client
-            .post(ANTHROPIC_API_URL)
-            .header("x-api-key", &self.api_key)
-            .header("anthropic-version", ANTHROPIC_VERSION)
-            .header("content-type", "application/json")
-            .json(&request)
-            .send()
-            .await
-            .context("Failed to send translation request")?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
-            warn!("Translation API error: {} - {}", status, body);
-            return Ok((text.to_string(), total_usage));
-        }
-
-        let claude_response: ClaudeResponse = response
-            .json()
-            .await
-            .context("Failed to parse translation response")?;
-
-        let translation_usage = UsageInfo {
-            input_tokens: claude_response.usage.input_tokens,
-            output_tokens: claude_response.usage.output_tokens,
-            cost_usd: Self::calculate_sonnet_cost(&claude_response.usage),
-        };
-        total_usage = Self::combine_usage(&total_usage, &translation_usage);
-
-        debug!(
-            "Outgoing translation usage: {} in, {} out, ${:.6}",
-            translation_usage.input_tokens,
-            translation_usage.output_tokens,
-            translation_usage.cost_usd
-        );
-
-        let translated = claude_response
-            .content
-            .first()
-            .and_then(|c| c.text.clone())
-            .unwrap_or_else(|| text.to_string());
-
-        Ok((translated.trim().to_string(), total_usage))
+        let (translated, notes, translation_usage) = self
+            .translate_mine(text, target_language, tone, learning_notes)
+            .await?;
+        Ok((
+            translated,
+            notes,
+            Self::combine_usage(&total_usage, &translation_usage),
+        ))
     }
 
     /// Combine two usage infos
@@ -531,6 +1383,7 @@ Text to translate:
             input_tokens: a.input_tokens + b.input_tokens,
             output_tokens: a.output_tokens + b.output_tokens,
             cost_usd: a.cost_usd + b.cost_usd,
+            calls: a.calls.iter().chain(&b.calls).cloned().collect(),
         }
     }
 
@@ -540,11 +1393,16 @@ Text to translate:
     /// - text: The text to translate
     /// - language_override: Optional target language override (e.g., "Spanish")
     /// - translation_style: Optional style instruction (e.g., "formal", "casual")
+    /// - learning_mode: Also extract vocabulary, for texts of at least
+    ///   `MIN_VOCAB_TEXT_CHARS` characters
+    /// - triage: Also classify urgency and tone, in the detection call
     pub async fn process_text(
         &self,
         text: &str,
         language_override: Option<&str>,
         translation_style: Option<&str>,
+        learning_mode: bool,
+        triage: bool,
     ) -> TranslationResult {
         let mut total_usage = UsageInfo::default();
 
@@ -558,24 +1416,44 @@ Text to translate:
                 translated_text: None,
                 source_language: target_language.to_string(),
                 usage: total_usage,
+                vocabulary: Vec::new(),
+                triage: None,
+                status: TranslationStatus::SkippedShort,
             };
         }
 
-        // Step 1: Detect language
-        let (is_target_lang, detected_language, detection_usage) =
-            match self.detect_language(text).await {
-                Ok((is_english, lang, usage)) => {
+        // Step 1: Detect language (and triage)
+        let (is_target_lang, detected_language, triage, detection_usage, mut status) =
+            match self.detect_language(text, triage).await {
+                Ok((detection, usage)) => {
                     // Check if detected language matches the target language
                     let is_target = if language_override.is_some() {
-                        lang.to_lowercase() == target_language.to_lowercase()
+                        detection.language.to_lowercase() == target_language.to_lowercase()
+                    } else {
+                        detection.is_default
+                    };
+                    let status = if text.trim().len() < MIN_DETECTION_LEN {
+                        TranslationStatus::SkippedShort
                     } else {
-                        is_english
+                        TranslationStatus::NotNeeded
                     };
-                    (is_target, lang, usage)
+                    (
+                        is_target,
+                        detection.language,
+                        detection.triage,
+                        usage,
+                        status,
+                    )
                 }
                 Err(e) => {
                     warn!("Language detection failed: {}", e);
-                    (true, target_language.to_string(), UsageInfo::default())
+                    (
+                        true,
+                        target_language.to_string(),
+                        None,
+                        UsageInfo::default(),
+                        TranslationStatus::Error,
+                    )
                 }
             };
         total_usage = Self::combine_usage(&total_usage, &detection_usage);
@@ -587,6 +1465,9 @@ Text to translate:
                 translated_text: None,
                 source_language: detected_language,
                 usage: total_usage,
+                vocabulary: Vec::new(),
+                triage,
+                status,
             };
         }
 
@@ -599,34 +1480,46 @@ Text to translate:
                 .map(|s| format!(" (style: {})", s))
                 .unwrap_or_default()
         );
-        let (translated, translation_usage) = match self
+        let with_vocabulary = learning_mode && text.trim().chars().count() >= MIN_VOCAB_TEXT_CHARS;
+        let (translated, vocabulary, translation_usage) = match self
             .translate(
                 text,
                 &detected_language,
                 language_override,
                 translation_style,
+                with_vocabulary,
             )
             .await
         {
-            Ok(result) => result,
+            Ok((translated, vocabulary, usage)) => {
+                status = TranslationStatus::Translated;
+                (Some(translated), vocabulary, usage)
+            }
             Err(e) => {
                 warn!("Translation failed: {}", e);
-                (text.to_string(), UsageInfo::default())
+                status = TranslationStatus::Error;
+                (None, Vec::new(), UsageInfo::default())
             }
         };
         total_usage = Self::combine_usage(&total_usage, &translation_usage);
 
         info!(
-            "Translation complete - total usage: {} in, {} out, ${:.6}",
-            total_usage.input_tokens, total_usage.output_tokens, total_usage.cost_usd
+            "Translation complete - total usage: {} in, {} out, ${:.6}, {}ms",
+            total_usage.input_tokens,
+            total_usage.output_tokens,
+            total_usage.cost_usd,
+            total_usage.latency_ms()
         );
 
         TranslationResult {
-            needs_translation: true,
+            needs_translation: translated.is_some(),
             original_text: text.to_string(),
-            translated_text: Some(translated),
+            translated_text: translated,
             source_language: detected_language,
             usage: total_usage,
+            vocabulary,
+            triage,
+            status,
         }
     }
 
@@ -637,11 +1530,13 @@ Text to translate:
     /// - prompt: The user's instruction for what message to compose
     /// - reply_context: Optional (sender_name, message_text) of the message being replied to
     /// - reply_image: Optional (media_type, base64_data) of an image being replied to
+    /// - tone: Optional register set for the contact the message is for
     pub async fn compose_ai_message(
         &self,
         prompt: &str,
         reply_context: Option<(&str, &str)>,
         reply_image: Option<(&str, &str)>,
+        tone: Option<&TranslationTone>,
     ) -> Result<(String, UsageInfo)> {
         // Validate input length (max 1000 chars for the prompt)
         if prompt.trim().is_empty() {
@@ -677,6 +1572,10 @@ Respond with ONLY the message text, nothing else."#;
         } else {
             format!("{}\n\nUser request: {}", system_prompt, prompt)
         };
+        let text_content = match tone {
+            Some(tone) => format!("{}\n\n{}", text_content, tone.instruction()),
+            None => text_content,
+        };
 
         // Build request - use vision API if image is provided
         let response = if let Some((media_type, base64_data)) = reply_image {
@@ -703,7 +1602,7 @@ Respond with ONLY the message text, nothing else."#;
             }
 
             let request = ClaudeVisionRequest {
-                model: AI_COMPOSE_MODEL.to_string(),
+                model: self.models.read().unwrap().compose.clone(),
                 max_tokens: 300,
                 messages: vec![ClaudeVisionMessage {
                     role: "user".to_string(),
@@ -711,19 +1610,13 @@ Respond with ONLY the message text, nothing else."#;
                 }],
             };
 
-            self.client
-                .post(ANTHROPIC_API_URL)
-                .header("x-api-key", &self.api_key)
-                .header("anthropic-version", ANTHROPIC_VERSION)
-                .header("content-type", "application/json")
-                .json(&request)
-                .send()
+            self.post_timed(&request, &request.model)
                 .await
                 .context("Failed to send AI compose request")?
         } else {
             // Text-only request
             let request = ClaudeRequest {
-                model: AI_COMPOSE_MODEL.to_string(),
+                model: self.models.read().unwrap().compose.clone(),
                 max_tokens: 300,
                 messages: vec![ClaudeMessage {
                     role: "user".to_string(),
@@ -731,41 +1624,31 @@ Respond with ONLY the message text, nothing else."#;
                 }],
             };
 
-            self.client
-                .post(ANTHROPIC_API_URL)
-                .header("x-api-key", &self.api_key)
-                .header("anthropic-version", ANTHROPIC_VERSION)
-                .header("content-type", "application/json")
-                .json(&request)
-                .send()
+            self.post_timed(&request, &request.model)
                 .await
                 .context("Failed to send AI compose request")?
         };
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
+        if !response.status.is_success() {
+            let (status, body) = (response.status, &response.body);
             anyhow::bail!("AI compose API error: {} - {}", status, body);
         }
 
-        let claude_response: ClaudeResponse = response
-            .json()
-            .await
-            .context("Failed to parse AI compose response")?;
+        let claude_response: ClaudeResponse =
+            serde_json::from_str(&response.body).context("Failed to parse AI compose response")?;
 
-        let usage_info = UsageInfo {
-            input_tokens: claude_response.usage.input_tokens,
-            output_tokens: claude_response.usage.output_tokens,
-            cost_usd: Self::calculate_opus_cost(&claude_response.usage),
-        };
+        let usage_info = response.usage(
+            &claude_response.usage,
+            self.cost(&response.model, &claude_response.usage),
+        );
 
-        let composed = claude_response
-            .content
-            .first()
-            .and_then(|c| c.text.clone())
-            .unwrap_or_default()
-            .trim()
-            .to_string();
+        let composed = self.clean_output(
+            &claude_response
+                .content
+                .first()
+                .and_then(|c| c.text.clone())
+                .unwrap_or_default(),
+        );
 
         // Final safety check - truncate if somehow still too long
         let composed = if composed.len() > 500 {
@@ -793,6 +1676,7 @@ Respond with ONLY the message text, nothing else."#;
     /// - global_style: User's overall writing style profile
     /// - contact_style: Optional style specific to this contact
     /// - my_examples: Examples of user's outgoing messages to this contact
+    /// - tone: Optional register set for this contact
     pub async fn compose_styled_reply(
         &self,
         message_to_reply: &crate::storage::StoredMessage,
@@ -800,6 +1684,7 @@ Respond with ONLY the message text, nothing else."#;
         global_style: &crate::storage::StyleProfile,
         contact_style: Option<&crate::storage::StyleProfile>,
         my_examples: &[crate::storage::StoredMessage],
+        tone: Option<&TranslationTone>,
     ) -> Result<(String, UsageInfo)> {
         // Extract text from the message being replied to
         let reply_to_text = message_to_reply
@@ -844,6 +1729,15 @@ Respond with ONLY the message text, nothing else."#;
         } else {
             "## MY STYLE WITH THIS SPECIFIC CONTACT:\nNo specific style data for this contact yet. Use my general style.\n".to_string()
         };
+        // A register I chose for this contact wins over the one in my examples
+        let contact_style_section = match tone {
+            Some(tone) => format!(
+                "{}\n## REGISTER (OVERRIDES THE STYLE ABOVE):\n{}\n",
+                contact_style_section,
+                tone.instruction()
+            ),
+            None => contact_style_section,
+        };
 
         // Build the full prompt - prioritize examples, be aggressive about casual tone
         let prompt = format!(
@@ -892,7 +1786,7 @@ Write my reply (keep it short and casual like my examples):"#,
 
         // Call Claude - use lower max_tokens to encourage shorter replies
         let request = ClaudeRequest {
-            model: AI_COMPOSE_MODEL.to_string(),
+            model: self.models.read().unwrap().compose.clone(),
             max_tokens: 150,
             messages: vec![ClaudeMessage {
                 role: "user".to_string(),
@@ -901,33 +1795,23 @@ Write my reply (keep it short and casual like my examples):"#,
         };
 
         let response = self
-            .client
-            .post(ANTHROPIC_API_URL)
-            .header("x-api-key", &self.api_key)
-            .header("anthropic-version", ANTHROPIC_VERSION)
-            .header("content-type", "application/json")
-            .json(&request)
-            .send()
+            .post_timed(&request, &request.model)
             .await
             .context("Failed to send styled reply request")?;
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let body = response.text().await.unwrap_or_default();
+        if !response.status.is_success() {
+            let (status, body) = (response.status, &response.body);
             warn!("AI reply API error: {} - {}", status, body);
             anyhow::bail!("Styled reply API error: {} - {}", status, body);
         }
 
-        let claude_response: ClaudeResponse = response
-            .json()
-            .await
+        let claude_response: ClaudeResponse = serde_json::from_str(&response.body)
             .context("Failed to parse styled reply response")?;
 
-        let usage_info = UsageInfo {
-            input_tokens: claude_response.usage.input_tokens,
-            output_tokens: claude_response.usage.output_tokens,
-            cost_usd: Self::calculate_opus_cost(&claude_response.usage),
-        };
+        let usage_info = response.usage(
+            &claude_response.usage,
+            self.cost(&response.model, &claude_response.usage),
+        );
 
         let reply = claude_response
             .content
@@ -955,6 +1839,86 @@ Write my reply (keep it short and casual like my examples):"#,
         Ok((reply, usage_info))
     }
 
+    /// Suggest three short quick replies to an incoming message
+    ///
+    /// Parameters:
+    /// - message_text: The incoming message to reply to
+    /// - recent_conversation: The last few messages for context
+    /// - language: Language to write the replies in (None = default language)
+    pub async fn suggest_replies(
+        &self,
+        message_text: &str,
+        recent_conversation: &[crate::storage::StoredMessage],
+        language: Option<&str>,
+    ) -> Result<(Vec<String>, UsageInfo)> {
+        let language = language.unwrap_or(&self.default_language);
+        let conversation_context = Self::format_conversation(recent_conversation);
+
+        let prompt = format!(
+            r#"Suggest three quick replies I could send to the latest WhatsApp message below.
+
+## RECENT CHAT FOR CONTEXT:
+{}
+
+## LATEST MESSAGE:
+"{}"
+
+## RULES:
+1. Write every reply in {}
+2. Each reply is at most 6 words, like a one-tap smart reply
+3. Make the three replies meaningfully different (e.g. yes / no / ask for more)
+4. Respond with ONLY a JSON array of exactly three strings, e.g. ["Sounds good!", "Can't today, sorry", "What time?"]"#,
+            conversation_context,
+            message_text.chars().take(500).collect::<String>(),
+            language
+        );
+
+        let request = ClaudeRequest {
+            model: self.models.read().unwrap().suggestion.clone(),
+            max_tokens: 150,
+            messages: vec![ClaudeMessage {
+                role: "user".to_string(),
+                content: prompt,
+            }],
+        };
+
+        let response = self
+            .post_timed(&request, &request.model)
+            .await
+            .context("Failed to send reply suggestion request")?;
+
+        if !response.status.is_success() {
+            let (status, body) = (response.status, &response.body);
+            anyhow::bail!("Reply suggestion API error: {} - {}", status, body);
+        }
+
+        let claude_response: ClaudeResponse = serde_json::from_str(&response.body)
+            .context("Failed to parse reply suggestion response")?;
+
+        let usage_info = response.usage(
+            &claude_response.usage,
+            self.cost(&response.model, &claude_response.usage),
+        );
+
+        let text = claude_response
+            .content
+            .first()
+            .and_then(|c| c.text.clone())
+            .unwrap_or_default();
+        let suggestions = parse_suggestions(&text);
+
+        info!(
+            "Reply suggestions generated: {} in {}, {} in, {} out, ${:.6}",
+            suggestions.len(),
+            language,
+            usage_info.input_tokens,
+            usage_info.output_tokens,
+            usage_info.cost_usd
+        );
+
+        Ok((suggestions, usage_info))
+    }
+
     /// Format conversation messages for the prompt
     fn format_conversation(messages: &[crate::storage::StoredMessage]) -> String {
         if messages.is_empty() {
@@ -1032,3 +1996,1018 @@ Write my reply (keep it short and casual like my examples):"#,
             .join("\n")
     }
 }
+
+/// Parse reply suggestions from the model output.
+///
+/// Expects a JSON array of strings (optionally inside a code fence); anything
+/// else is split into lines with list markers and quotes removed. At most three
+/// non-empty suggestions are returned.
+fn parse_suggestions(text: &str) -> Vec<String> {
+    let trimmed = text
+        .trim()
+        .trim_start_matches("```json")
+        .trim_start_matches("```")
+        .trim_end_matches("```")
+        .trim();
+
+    let suggestions = match serde_json::from_str::<Vec<String>>(trimmed) {
+        Ok(list) => list,
+        Err(_) => trimmed
+            .lines()
+            .map(|line| {
+                strip_list_marker(line.trim())
+                    .trim_matches(|c: char| matches!(c, '"' | ',' | '[' | ']'))
+                    .trim()
+                    .to_string()
+            })
+            .collect(),
+    };
+
+    suggestions
+        .into_iter()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .take(3)
+        .collect()
+}
+
+/// Parse a learning-mode reply of `{"translation": .., "vocabulary": [..]}`.
+///
+/// A reply that doesn't look like JSON at all is taken as a plain
+/// translation. Returns None if it looks like JSON but has no usable
+/// translation, so the caller can ask again without vocabulary. Vocabulary
+/// entries that aren't a non-empty term and gloss are dropped, and at most
+/// `MAX_VOCAB_ENTRIES` are kept.
+fn parse_translation_with_vocab(reply: &str) -> Option<(String, Vec<VocabEntry>)> {
+    let unfenced = strip_code_fence(reply.trim());
+    if !unfenced.starts_with('{') {
+        return (!unfenced.is_empty()).then(|| (unfenced.to_string(), Vec::new()));
+    }
+
+    let value = serde_json::from_str::<serde_json::Value>(unfenced).ok()?;
+    let translation = value
+        .get("translation")
+        .and_then(|t| t.as_str())
+        .map(str::trim)
+        .filter(|t| !t.is_empty())?
+        .to_string();
+
+    let vocabulary = value
+        .get("vocabulary")
+        .and_then(|v| v.as_array())
+        .map(|entries| {
+            entries
+                .iter()
+                .filter_map(|entry| {
+                    let field = |name: &str| {
+                        entry
+                            .get(name)
+                            .and_then(|f| f.as_str())
+                            .map(str::trim)
+                            .filter(|f| !f.is_empty())
+                            .map(str::to_string)
+                    };
+                    Some(VocabEntry {
+                        term: field("term")?,
+                        gloss: field("gloss")?,
+                    })
+                })
+                .take(MAX_VOCAB_ENTRIES)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Some((translation, vocabulary))
+}
+
+/// Split the learning notes off an outgoing translation: a last line (or
+/// fenced block) of `{"notes": [{"source": .., "target": ..}]}`.
+///
+/// The notes line is dropped from the text even when it isn't valid JSON,
+/// so it's never sent; its notes are then just lost. A reply without one is
+/// all translation. Returns None if nothing but notes came back. Notes that
+/// aren't a non-empty source and target are dropped, and at most
+/// `MAX_LEARNING_NOTES` are kept.
+fn split_learning_notes(reply: &str) -> Option<(String, Vec<LearningNote>)> {
+    let reply = reply.trim();
+    let (text, tail) = match reply
+        .strip_suffix("```")
+        .and_then(|fenced| fenced.rsplit_once("```"))
+    {
+        Some((text, block)) => (text, block.strip_prefix("json").unwrap_or(block)),
+        None => reply.rsplit_once('\n').unwrap_or(("", reply)),
+    };
+    let tail = tail.trim();
+    if !(tail.starts_with('{') && tail.contains("\"notes\"")) {
+        return Some((reply.to_string(), Vec::new()));
+    }
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+
+    let notes = serde_json::from_str::<serde_json::Value>(tail)
+        .ok()
+        .and_then(|value| value.get("notes")?.as_array().cloned())
+        .map(|entries| {
+            entries
+                .iter()
+                .filter_map(|entry| {
+                    let field = |name: &str| {
+                        entry
+                            .get(name)
+                            .and_then(|f| f.as_str())
+                            .map(str::trim)
+                            .filter(|f| !f.is_empty())
+                            .map(str::to_string)
+                    };
+                    Some(LearningNote {
+                        source: field("source")?,
+                        target: field("target")?,
+                    })
+                })
+                .take(MAX_LEARNING_NOTES)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Some((text.to_string(), notes))
+}
+
+/// Remove a leading "1." / "2)" / "-" / "*" / "•" list marker from a line
+fn strip_list_marker(line: &str) -> &str {
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let rest = &line[digits..];
+    if digits > 0 && (rest.starts_with('.') || rest.starts_with(')')) {
+        return rest[1..].trim_start();
+    }
+    line.strip_prefix(['-', '*', '•'])
+        .map(str::trim_start)
+        .unwrap_or(line)
+}
+
+/// Prefixes models sometimes put before the text they were asked for
+const OUTPUT_LABELS: &[&str] = &[
+    "translation",
+    "translated text",
+    "translated message",
+    "here is the translation",
+    "here's the translation",
+];
+
+/// Quote pairs a model may wrap its whole answer in
+const WRAPPING_QUOTES: &[(char, char)] = &[
+    ('"', '"'),
+    ('\'', '\''),
+    ('“', '”'),
+    ('„', '“'),
+    ('„', '”'),
+    ('‘', '’'),
+    ('«', '»'),
+    ('「', '」'),
+];
+
+/// Clean up model output before it is sent as a WhatsApp message.
+///
+/// Strips a surrounding code fence, a "Translation:"-style label and quotes
+/// wrapping the whole text, turns markdown `**bold**` into WhatsApp `*bold*`,
+/// normalizes to NFC, drops zero-width and control characters (keeping
+/// newlines) and collapses runs of whitespace. Returns the trimmed input if
+/// cleaning would leave nothing.
+pub fn sanitize_model_output(text: &str) -> String {
+    let normalized: String = text
+        .replace("\r\n", "\n")
+        .nfc()
+        .filter_map(|c| match c {
+            '\n' => Some(c),
+            '\t' => Some(' '),
+            '\u{200B}' | '\u{2060}' | '\u{FEFF}' | '\u{180E}' => None,
+            c if c.is_control() => None,
+            c => Some(c),
+        })
+        .collect();
+
+    let mut cleaned = strip_code_fence(normalized.trim());
+    cleaned = strip_output_label(cleaned);
+    cleaned = strip_wrapping_quotes(cleaned);
+    let cleaned = markdown_bold_to_whatsapp(cleaned);
+    let cleaned = collapse_whitespace(&cleaned);
+
+    if cleaned.is_empty() {
+        text.trim().to_string()
+    } else {
+        cleaned
+    }
+}
+
+/// Remove a code fence (with optional language tag) around the whole text
+fn strip_code_fence(text: &str) -> &str {
+    let Some(inner) = text.strip_prefix("```").and_then(|t| t.strip_suffix("```")) else {
+        return text;
+    };
+    // The rest of the opening line is a language tag
+    match inner.split_once('\n') {
+        Some((tag, body)) if !tag.trim().contains(' ') => body.trim(),
+        _ => inner.trim(),
+    }
+}
+
+/// Remove a leading "Translation:" style label, optionally in bold
+fn strip_output_label(text: &str) -> &str {
+    let unbolded = text.strip_prefix("**").unwrap_or(text);
+    for label in OUTPUT_LABELS {
+        let Some(head) = unbolded.get(..label.len()) else {
+            continue;
+        };
+        if !head.eq_ignore_ascii_case(label) {
+            continue;
+        }
+        let rest = &unbolded[label.len()..];
+        let rest = rest
+            .strip_prefix(":**")
+            .or_else(|| rest.strip_prefix("**:"))
+            .or_else(|| rest.strip_prefix(':'));
+        if let Some(rest) = rest {
+            return rest.trim_start();
+        }
+    }
+    text
+}
+
+/// Remove quotes wrapping the whole text, unless the same quotes also
+/// appear inside it (then they're part of the message)
+fn strip_wrapping_quotes(text: &str) -> &str {
+    for &(open, close) in WRAPPING_QUOTES {
+        let Some(inner) = text.strip_prefix(open).and_then(|t| t.strip_suffix(close)) else {
+            continue;
+        };
+        if !inner.contains(open) && !inner.contains(close) {
+            return inner.trim();
+        }
+    }
+    text
+}
+
+/// Turn markdown `**bold**` into WhatsApp `*bold*`
+fn markdown_bold_to_whatsapp(text: &str) -> String {
+    let parts: Vec<&str> = text.split("**").collect();
+    // An odd number of markers means they aren't paired; leave them alone
+    if parts.len().is_multiple_of(2) {
+        return text.to_string();
+    }
+    parts.join("*")
+}
+
+/// Collapse runs of spaces, trim line ends and keep at most one blank line
+/// between paragraphs. Other spacing (e.g. French narrow no-break spaces) is
+/// left as is.
+fn collapse_whitespace(text: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let mut collapsed = String::with_capacity(line.len());
+        for c in line.trim().chars() {
+            if c != ' ' || !collapsed.ends_with(' ') {
+                collapsed.push(c);
+            }
+        }
+        let line = collapsed;
+        if line.is_empty() && lines.last().is_none_or(|l| l.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+/// Start a fake Claude API that counts requests and answers every one with
+/// `reply` as the response text
+#[cfg(test)]
+pub(crate) async fn spawn_counting_provider(
+    reply: &'static str,
+) -> (String, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let hits = Arc::new(AtomicUsize::new(0));
+    let counter = hits.clone();
+    let app = axum::Router::new().route(
+        "/v1/messages",
+        axum::routing::post(move || {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                axum::Json(serde_json::json!({
+                    "content": [{"text": reply}],
+                    "usage": {"input_tokens": 10, "output_tokens": 5}
+                }))
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (format!("http://{}/v1/messages", addr), hits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_store;
+    use std::time::Duration;
+
+    #[test]
+    fn test_sanitize_model_output() {
+        let cases = [
+            // Already clean text is left alone
+            ("Hola, ¿qué tal?", "Hola, ¿qué tal?"),
+            ("Line one\nLine two", "Line one\nLine two"),
+            ("Ça va ?", "Ça va ?"),
+            ("👨‍👩‍👧 family", "👨‍👩‍👧 family"),
+            // Wrapping quotes
+            ("\"Hola amigo\"", "Hola amigo"),
+            ("“Bonjour à tous”", "Bonjour à tous"),
+            ("„Guten Morgen“", "Guten Morgen"),
+            ("«Merci beaucoup»", "Merci beaucoup"),
+            ("「こんにちは」", "こんにちは"),
+            ("'Ciao'", "Ciao"),
+            // Quotes that are part of the message stay
+            ("\"Sí\" dijo ella, \"claro\"", "\"Sí\" dijo ella, \"claro\""),
+            ("Dice «hola»", "Dice «hola»"),
+            ("\"Hola", "\"Hola"),
+            // Labels
+            ("Translation: Hola", "Hola"),
+            ("translation:Hola", "Hola"),
+            ("**Translation:** Hola", "Hola"),
+            ("Here's the translation: \"Hola\"", "Hola"),
+            ("Translated text:\nBuenos días", "Buenos días"),
+            ("Translations are hard", "Translations are hard"),
+            // Code fences
+            ("```\nHola\n```", "Hola"),
+            ("```text\nHola\nAdiós\n```", "Hola\nAdiós"),
+            ("```Hola```", "Hola"),
+            // Markdown bold becomes WhatsApp bold
+            ("Es **muy** importante", "Es *muy* importante"),
+            ("2 ** 3", "2 ** 3"),
+            // Zero-width and control characters
+            ("Hola\u{200B}mundo", "Holamundo"),
+            ("\u{FEFF}Hola", "Hola"),
+            ("Ho\u{0007}la\u{2060}", "Hola"),
+            ("Hola\r\nmundo", "Hola\nmundo"),
+            // Whitespace
+            ("Hola    mundo", "Hola mundo"),
+            ("Hola\t\tmundo", "Hola mundo"),
+            ("  Hola  \n  mundo  ", "Hola\nmundo"),
+            ("Uno\n\n\n\nDos", "Uno\n\nDos"),
+            ("Uno\n \nDos", "Uno\n\nDos"),
+            // NFC: decomposed "é" is composed
+            ("Cafe\u{0301}", "Café"),
+            // Combinations
+            (
+                "```\nTranslation: “Nos vemos  mañana\u{200B}”\n```",
+                "Nos vemos mañana",
+            ),
+            ("\n\n\"**Hola**\"\n", "*Hola*"),
+            // Nothing left after cleaning: keep the original
+            ("\"\"", "\"\""),
+            ("\u{200B}", "\u{200B}"),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(sanitize_model_output(input), expected, "input: {:?}", input);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_translate_to_sanitizes_output() {
+        let (url, _) = spawn_counting_provider("Translation: “Hola\u{200B}  amigo”").await;
+
+        let translator = TranslationService::new("test-key".to_string(), "English".to_string())
+            .with_api_url(&url);
+        let (translated, _, _) = translator
+            .translate_to("Hi friend", "Spanish", None, false)
+            .await
+            .unwrap();
+        assert_eq!(translated, "Hola amigo");
+
+        let raw = TranslationService::new("test-key".to_string(), "English".to_string())
+            .with_api_url(&url)
+            .with_output_sanitizer(false);
+        let (translated, _, _) = raw
+            .translate_to("Hi friend", "Spanish", None, false)
+            .await
+            .unwrap();
+        assert_eq!(translated, "Translation: “Hola\u{200B}  amigo”");
+    }
+
+    #[test]
+    fn test_parse_suggestions() {
+        assert_eq!(
+            parse_suggestions(r#"["Sí, claro", "No puedo", "¿A qué hora?"]"#),
+            vec!["Sí, claro", "No puedo", "¿A qué hora?"]
+        );
+        assert_eq!(
+            parse_suggestions("```json\n[\"a\", \"b\", \"c\", \"d\"]\n```"),
+            vec!["a", "b", "c"]
+        );
+        assert_eq!(
+            parse_suggestions("1. Sounds good!\n2) \"Can't today\"\n\n- 10 mins away"),
+            vec!["Sounds good!", "Can't today", "10 mins away"]
+        );
+    }
+
+    #[test]
+    fn test_parse_translation_with_vocab() {
+        let (translation, vocabulary) = parse_translation_with_vocab(
+            "```json\n{\"translation\": \" See you tomorrow \", \"vocabulary\": [\n  {\"term\": \"mañana\", \"gloss\": \"tomorrow\"},\n  {\"term\": \"\", \"gloss\": \"nothing\"},\n  {\"term\": \"nos vemos\"},\n  \"hasta\"\n]}\n```",
+        )
+        .unwrap();
+        assert_eq!(translation, "See you tomorrow");
+        assert_eq!(
+            vocabulary,
+            vec![VocabEntry {
+                term: "mañana".to_string(),
+                gloss: "tomorrow".to_string(),
+            }]
+        );
+
+        // A plain-text reply is the translation
+        assert_eq!(
+            parse_translation_with_vocab("See you tomorrow"),
+            Some(("See you tomorrow".to_string(), Vec::new()))
+        );
+        // Vocabulary that isn't a list is dropped, keeping the translation
+        assert_eq!(
+            parse_translation_with_vocab(r#"{"translation": "Hi", "vocabulary": "hola"}"#),
+            Some(("Hi".to_string(), Vec::new()))
+        );
+        // Broken or translation-less JSON can't be used at all
+        assert_eq!(
+            parse_translation_with_vocab(r#"{"translation": "Hi", "vocabulary": [{"term"#),
+            None
+        );
+        assert_eq!(parse_translation_with_vocab(r#"{"vocabulary": []}"#), None);
+
+        let many: Vec<String> = (0..8)
+            .map(|n| format!(r#"{{"term": "t{}", "gloss": "g{}"}}"#, n, n))
+            .collect();
+        let reply = format!(
+            r#"{{"translation": "x", "vocabulary": [{}]}}"#,
+            many.join(",")
+        );
+        let (_, vocabulary) = parse_translation_with_vocab(&reply).unwrap();
+        assert_eq!(vocabulary.len(), MAX_VOCAB_ENTRIES);
+    }
+
+    #[test]
+    fn test_parse_detection_triage() {
+        let detection = parse_detection(
+            "```json\n{\"language\": \"Spanish\", \"isEnglish\": false, \"urgency\": \"High\", \"tone\": \"negative\"}\n```",
+        )
+        .unwrap();
+        assert_eq!(detection.language, "Spanish");
+        assert!(!detection.is_default);
+        assert_eq!(
+            detection.triage,
+            Some(Triage {
+                urgency: Some(Urgency::High),
+                tone: Some(Tone::Negative),
+            })
+        );
+
+        // Without triage fields the language still comes through
+        let detection = parse_detection(r#"{"language": "French", "isEnglish": false}"#).unwrap();
+        assert_eq!(detection.language, "French");
+        assert_eq!(detection.triage, None);
+
+        // Unknown or non-string values are dropped one by one
+        let detection = parse_detection(
+            r#"{"language": "English", "isEnglish": true, "urgency": "urgent!!", "tone": "positive"}"#,
+        )
+        .unwrap();
+        assert!(detection.is_default);
+        assert_eq!(
+            detection.triage,
+            Some(Triage {
+                urgency: None,
+                tone: Some(Tone::Positive),
+            })
+        );
+        let detection = parse_detection(
+            r#"{"language": "English", "isEnglish": true, "urgency": 3, "tone": null}"#,
+        )
+        .unwrap();
+        assert_eq!(detection.triage, None);
+        assert_eq!(
+            parse_detection(r#"{"language": "English", "isEnglish": true, "urgency": "medium"}"#)
+                .unwrap()
+                .triage,
+            Some(Triage {
+                urgency: Some(Urgency::Normal),
+                tone: None,
+            })
+        );
+
+        // Without a language there's nothing to go on
+        assert!(parse_detection(r#"{"urgency": "high"}"#).is_none());
+        assert!(parse_detection("Spanish").is_none());
+    }
+
+    /// Start a fake Claude API answering with `replies` in turn, recording
+    /// each prompt
+    async fn spawn_scripted_provider(
+        replies: Vec<&'static str>,
+    ) -> (String, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+        use std::sync::{Arc, Mutex};
+
+        let prompts = Arc::new(Mutex::new(Vec::new()));
+        let recorded = prompts.clone();
+        let app = axum::Router::new().route(
+            "/v1/messages",
+            axum::routing::post(move |axum::Json(body): axum::Json<serde_json::Value>| {
+                let recorded = recorded.clone();
+                let replies = replies.clone();
+                async move {
+                    let mut prompts = recorded.lock().unwrap();
+                    prompts.push(body["messages"][0]["content"].as_str().unwrap().to_string());
+                    let reply = replies[(prompts.len() - 1).min(replies.len() - 1)];
+                    axum::Json(serde_json::json!({
+                        "content": [{"text": reply}],
+                        "usage": {"input_tokens": 10, "output_tokens": 5}
+                    }))
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{}/v1/messages", addr), prompts)
+    }
+
+    #[test]
+    fn test_split_learning_notes() {
+        let note = |source: &str, target: &str| LearningNote {
+            source: source.to_string(),
+            target: target.to_string(),
+        };
+        let (text, notes) = split_learning_notes(
+            "¿Nos vemos mañana en la playa?\n{\"notes\": [{\"source\": \"see you\", \"target\": \"nos vemos\"}, {\"source\": \"beach\"}, {\"source\": \"tomorrow\", \"target\": \"mañana\"}, {\"source\": \"at\", \"target\": \"en\"}]}",
+        )
+        .unwrap();
+        assert_eq!(text, "¿Nos vemos mañana en la playa?");
+        assert_eq!(
+            notes,
+            vec![note("see you", "nos vemos"), note("tomorrow", "mañana")]
+        );
+
+        // Fenced notes, and multi-line translations
+        assert_eq!(
+            split_learning_notes("Hola\nQué tal\n```json\n{\"notes\": [{\"source\": \"hi\", \"target\": \"hola\"}]}\n```"),
+            Some(("Hola\nQué tal".to_string(), vec![note("hi", "hola")]))
+        );
+        // No notes line: all translation, even if it ends with other JSON
+        assert_eq!(
+            split_learning_notes("Hola\n{\"a\": 1}"),
+            Some(("Hola\n{\"a\": 1}".to_string(), Vec::new()))
+        );
+        // A broken notes line is still never part of the text
+        assert_eq!(
+            split_learning_notes("Hola\n{\"notes\": [{\"source\""),
+            Some(("Hola".to_string(), Vec::new()))
+        );
+        // Notes and no translation can't be used
+        assert_eq!(split_learning_notes("{\"notes\": []}"), None);
+    }
+
+    #[test]
+    fn test_outgoing_prompt_learning_notes() {
+        let plain = TranslationService::outgoing_prompt("See you", "Spanish", None, false);
+        assert!(plain.contains("Respond with ONLY the translated text, nothing else."));
+        assert!(!plain.contains("notes"));
+
+        let with_notes = TranslationService::outgoing_prompt("See you", "Spanish", None, true);
+        assert!(with_notes.contains("up to 2 key phrases"));
+        assert!(with_notes.contains(r#"{"notes": [{"source": "#));
+        assert!(with_notes.ends_with("Text to translate:\nSee you"));
+    }
+
+    #[tokio::test]
+    async fn test_learning_notes_never_in_translation() {
+        let (url, prompts) = spawn_scripted_provider(vec![
+            r#"{"language": "English", "isEnglish": true}"#,
+            "Nos vemos mañana\n{\"notes\": [{\"source\": \"see you\", \"target\": \"nos vemos\"}]}",
+            r#"{"language": "English", "isEnglish": true}"#,
+            "{\"notes\": [{\"source\": \"see you\", \"target\": \"nos vemos\"}]}",
+            "Nos vemos mañana",
+        ])
+        .await;
+        let service = TranslationService::new("test-key".to_string(), "English".to_string())
+            .with_api_url(&url);
+
+        let (translated, notes, _) = service
+            .translate_to("See you tomorrow", "Spanish", None, true)
+            .await
+            .unwrap();
+        assert_eq!(translated, "Nos vemos mañana");
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].target, "nos vemos");
+
+        // Only notes came back: asked again without them
+        let (translated, notes, usage) = service
+            .translate_outgoing("See you tomorrow", "Spanish", true, None, true)
+            .await
+            .unwrap();
+        assert_eq!(translated, "Nos vemos mañana");
+        assert!(notes.is_empty());
+        assert_eq!(usage.calls.len(), 3);
+
+        let prompts = prompts.lock().unwrap();
+        assert!(prompts[1].contains(r#"{"notes""#));
+        assert!(prompts[3].contains(r#"{"notes""#));
+        assert!(!prompts[4].contains("notes"));
+    }
+
+    #[tokio::test]
+    async fn test_learning_mode_falls_back_to_translation_only() {
+        const DETECTED: &str = r#"{"language": "Spanish", "isEnglish": false}"#;
+        let text = "¿Nos vemos mañana en la playa?";
+
+        // Vocabulary comes back with the translation
+        let (url, prompts) = spawn_scripted_provider(vec![
+            DETECTED,
+            r#"{"translation": "See you at the beach tomorrow?", "vocabulary": [{"term": "playa", "gloss": "beach"}]}"#,
+        ])
+        .await;
+        let service = TranslationService::new("test-key".to_string(), "English".to_string())
+            .with_api_url(&url);
+        let result = service.process_text(text, None, None, true, false).await;
+        assert_eq!(
+            result.translated_text.as_deref(),
+            Some("See you at the beach tomorrow?")
+        );
+        assert_eq!(result.vocabulary[0].term, "playa");
+        assert!(prompts.lock().unwrap()[1].contains("\"vocabulary\""));
+
+        // Malformed JSON is retried as a plain translation
+        let (url, prompts) = spawn_scripted_provider(vec![
+            DETECTED,
+            r#"{"translation": "See you at the beach tomorrow?", "vocabulary": [{"term": "pla"#,
+            "See you at the beach tomorrow?",
+        ])
+        .await;
+        let service = TranslationService::new("test-key".to_string(), "English".to_string())
+            .with_api_url(&url);
+        let result = service.process_text(text, None, None, true, false).await;
+        assert_eq!(
+            result.translated_text.as_deref(),
+            Some("See you at the beach tomorrow?")
+        );
+        assert!(result.vocabulary.is_empty());
+        assert_eq!(result.usage.calls.len(), 3);
+        assert!(!prompts.lock().unwrap()[2].contains("\"vocabulary\""));
+
+        // Short messages skip the vocabulary prompt
+        let (url, prompts) = spawn_scripted_provider(vec![DETECTED, "See you!"]).await;
+        let service = TranslationService::new("test-key".to_string(), "English".to_string())
+            .with_api_url(&url);
+        let result = service
+            .process_text("¡Nos vemos!", None, None, true, false)
+            .await;
+        assert_eq!(result.translated_text.as_deref(), Some("See you!"));
+        assert!(result.vocabulary.is_empty());
+        assert!(!prompts.lock().unwrap()[1].contains("\"vocabulary\""));
+    }
+
+    #[tokio::test]
+    async fn test_tone_only_on_outgoing_prompts() {
+        let formal = TranslationTone::Formal;
+        let (url, prompts) = spawn_scripted_provider(vec![
+            r#"{"language": "English", "isEnglish": true}"#,
+            "¿Podríamos hablar mañana?",
+            r#"{"language": "Spanish", "isEnglish": false}"#,
+            "Can we talk tomorrow?",
+            "Thank you so much for your help",
+        ])
+        .await;
+        let service = TranslationService::new("test-key".to_string(), "English".to_string())
+            .with_api_url(&url);
+
+        service
+            .translate_outgoing(
+                "Can we talk tomorrow?",
+                "Spanish",
+                false,
+                Some(&formal),
+                false,
+            )
+            .await
+            .unwrap();
+        // Incoming translations have no tone to pass
+        service
+            .process_text("¿Podemos hablar mañana?", None, None, false, false)
+            .await;
+        let custom = TranslationTone::Custom("Polite but warm".to_string());
+        service
+            .compose_ai_message("Say thanks", None, None, Some(&custom))
+            .await
+            .unwrap();
+
+        let prompts = prompts.lock().unwrap();
+        assert_eq!(prompts.len(), 5);
+        assert!(prompts[1].contains(formal.instruction()));
+        for incoming in &prompts[2..4] {
+            assert!(!incoming.contains(formal.instruction()));
+            assert!(!incoming.contains("register"));
+        }
+        assert!(prompts[4].contains("Polite but warm"));
+
+        assert_eq!(
+            TranslationTone::Custom("  Polite  ".to_string()).validated(),
+            Some(TranslationTone::Custom("Polite".to_string()))
+        );
+        assert_eq!(TranslationTone::Custom(" ".to_string()).validated(), None);
+        assert_eq!(
+            TranslationTone::Custom("x".repeat(MAX_TONE_CHARS + 1)).validated(),
+            None
+        );
+    }
+
+    /// Start a fake Claude API that answers every request after `delay`
+    async fn spawn_slow_provider(delay: Duration) -> String {
+        let app = axum::Router::new().route(
+            "/v1/messages",
+            axum::routing::post(move || async move {
+                tokio::time::sleep(delay).await;
+                axum::Json(serde_json::json!({
+                    "content": [{"text": "{\"language\": \"French\", \"isEnglish\": false}"}],
+                    "usage": {"input_tokens": 10, "output_tokens": 5}
+                }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}/v1/messages", addr)
+    }
+
+    #[tokio::test]
+    async fn test_cost_follows_configured_pricing() {
+        let url = spawn_slow_provider(Duration::ZERO).await;
+        let service = TranslationService::new("test-key".to_string(), "English".to_string())
+            .with_api_url(&url);
+
+        // Defaults: Haiku detection then Sonnet translation, 10 in and 5 out each
+        let result = service
+            .process_text("Bonjour tout le monde", None, None, false, false)
+            .await;
+        let costs: Vec<f64> = result.usage.calls.iter().map(|c| c.cost_usd).collect();
+        assert!((costs[0] - 0.000035).abs() < 1e-12, "{:?}", costs);
+        assert!((costs[1] - 0.000105).abs() < 1e-12, "{:?}", costs);
+
+        // Translating with Haiku at a custom price takes effect immediately
+        let mut models = service.models();
+        models.apply(ModelUpdate {
+            translation: Some("claude-haiku-4-5".to_string()),
+            pricing: [(
+                "claude-haiku-4-5".to_string(),
+                ModelPricing {
+                    input_per_m: 2.0,
+                    output_per_m: 10.0,
+                },
+            )]
+            .into(),
+            ..Default::default()
+        });
+        service.set_models(models);
+
+        let result = service
+            .process_text("Bonjour tout le monde", None, None, false, false)
+            .await;
+        let models: Vec<&str> = result
+            .usage
+            .calls
+            .iter()
+            .map(|c| c.model.as_str())
+            .collect();
+        assert_eq!(models, vec!["claude-haiku-4-5", "claude-haiku-4-5"]);
+        assert!(result
+            .usage
+            .calls
+            .iter()
+            .all(|c| (c.cost_usd - 0.00007).abs() < 1e-12));
+        assert!((result.usage.cost_usd - 0.00014).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_model_config_validation() {
+        assert!(ModelConfig::default().validate().is_ok());
+
+        let with = |update: ModelUpdate| {
+            let mut models = ModelConfig::default();
+            models.apply(update);
+            models.validate()
+        };
+        let model = |id: &str| ModelUpdate {
+            compose: Some(id.to_string()),
+            ..Default::default()
+        };
+        assert!(with(model("claude-sonnet-4-5-20250929")).is_ok());
+        assert!(with(model("")).is_err());
+        assert!(with(model("gpt-4o")).is_err());
+        assert!(with(model("claude sonnet")).is_err());
+        // Not a known family, so it needs explicit pricing
+        assert!(with(model("claude-next")).is_err());
+        let (id, pricing) = parse_model_pricing("claude-next=4:20").unwrap();
+        assert_eq!(pricing.output_per_m, 20.0);
+        assert!(with(ModelUpdate {
+            pricing: [(id, pricing)].into(),
+            ..model("claude-next")
+        })
+        .is_ok());
+
+        assert!(parse_model_pricing("claude-haiku-4-5").is_err());
+        assert!(parse_model_pricing("claude-haiku-4-5=1").is_err());
+        let (id, negative) = parse_model_pricing("claude-haiku-4-5=-1:5").unwrap();
+        assert!(with(ModelUpdate {
+            pricing: [(id, negative)].into(),
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_latency_recorded_and_aggregated() {
+        let url = spawn_slow_provider(Duration::from_millis(150)).await;
+        let service = TranslationService::new("test-key".to_string(), "English".to_string())
+            .with_api_url(&url)
+            .with_slow_call_threshold(100);
+
+        let result = service
+            .process_text("Bonjour tout le monde", None, None, false, false)
+            .await;
+        assert!(result.needs_translation);
+
+        // One detection call and one translation call, both timed
+        let models: Vec<&str> = result
+            .usage
+            .calls
+            .iter()
+            .map(|c| c.model.as_str())
+            .collect();
+        assert_eq!(
+            models,
+            vec![DEFAULT_DETECTION_MODEL, DEFAULT_TRANSLATION_MODEL]
+        );
+        assert!(result.usage.calls.iter().all(|c| c.latency_ms >= 150));
+        assert!(result.usage.latency_ms() >= 300);
+
+        let (store, _dir) = test_store();
+        store
+            .record_usage(None, Some("m1"), &result.usage, "translate_incoming")
+            .unwrap();
+
+        let stats = store.get_performance_stats(100).unwrap();
+        assert_eq!(stats.by_operation.len(), 1);
+        assert_eq!(stats.by_operation[0].key, "translate_incoming");
+        assert_eq!(stats.by_operation[0].count, 2);
+        assert!(stats.by_operation[0].p50_ms >= 150);
+
+        assert_eq!(stats.by_model.len(), 2);
+        assert!(stats.by_model.iter().all(|m| m.count == 1));
+
+        // Totals are unchanged by splitting usage into per-call rows
+        let global = store.get_global_usage().unwrap();
+        assert_eq!(global.input_tokens, 20);
+        assert_eq!(global.output_tokens, 10);
+    }
+
+    #[tokio::test]
+    async fn test_fallback_provider_chain() {
+        use crate::translation_provider::{OpenAiProvider, ProviderKind};
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        const DETECTED: &str = r#"{"language": "Spanish", "isEnglish": false}"#;
+        let reply = |prompt: &str, translation: &'static str| {
+            if prompt.starts_with("Detect the language") {
+                DETECTED
+            } else {
+                translation
+            }
+        };
+
+        // Claude answers 503 while down; OpenAI always answers
+        let down = Arc::new(AtomicBool::new(true));
+        let claude_hits = Arc::new(AtomicUsize::new(0));
+        let (is_down, hits) = (down.clone(), claude_hits.clone());
+        let app = axum::Router::new()
+            .route(
+                "/v1/messages",
+                axum::routing::post(move |axum::Json(body): axum::Json<serde_json::Value>| {
+                    let (is_down, hits) = (is_down.clone(), hits.clone());
+                    async move {
+                        hits.fetch_add(1, Ordering::SeqCst);
+                        if is_down.load(Ordering::SeqCst) {
+                            return Err(axum::http::StatusCode::SERVICE_UNAVAILABLE);
+                        }
+                        let prompt = body["messages"][0]["content"].as_str().unwrap();
+                        Ok(axum::Json(serde_json::json!({
+                            "content": [{"text": reply(prompt, "Hi, how are you?")}],
+                            "usage": {"input_tokens": 10, "output_tokens": 5}
+                        })))
+                    }
+                }),
+            )
+            .route(
+                "/v1/chat/completions",
+                axum::routing::post(move |axum::Json(body): axum::Json<serde_json::Value>| async move {
+                    let prompt = body["messages"][0]["content"].as_str().unwrap();
+                    axum::Json(serde_json::json!({
+                        "choices": [{"message": {"content": reply(prompt, "Hello, how are you?")}}],
+                        "usage": {"prompt_tokens": 1_000_000, "completion_tokens": 1_000_000}
+                    }))
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let service = TranslationService::new("test-key".to_string(), "English".to_string())
+            .with_providers(vec![
+                Box::new(AnthropicProvider::with_url(
+                    "test-key".to_string(),
+                    &format!("http://{}/v1/messages", addr),
+                )),
+                Box::new(OpenAiProvider::with_url(
+                    "test-key".to_string(),
+                    &format!("http://{}/v1/chat/completions", addr),
+                )),
+            ])
+            .with_circuit_breakers(1, Duration::from_millis(200));
+        let mut changes = service.subscribe_provider();
+        let text = "Hola, ¿cómo estás?";
+
+        let result = service.process_text(text, None, None, false, false).await;
+        assert_eq!(result.status, TranslationStatus::Translated);
+        assert_eq!(
+            result.translated_text.as_deref(),
+            Some("Hello, how are you?")
+        );
+        assert_eq!(service.active_provider(), (ProviderKind::OpenAi, true));
+        assert!(changes.has_changed().unwrap());
+        changes.mark_unchanged();
+
+        // Attributed to OpenAI at OpenAI's prices
+        let calls: Vec<(ProviderKind, &str)> = result
+            .usage
+            .calls
+            .iter()
+            .map(|c| (c.provider, c.model.as_str()))
+            .collect();
+        assert_eq!(
+            calls,
+            [
+                (ProviderKind::OpenAi, "gpt-4o-mini"),
+                (ProviderKind::OpenAi, "gpt-4o")
+            ]
+        );
+        assert!((result.usage.cost_usd - (0.15 + 0.6 + 2.5 + 10.0)).abs() < 1e-9);
+
+        // The open breaker keeps later calls off the primary
+        assert_eq!(claude_hits.load(Ordering::SeqCst), 1);
+        let (translated, _, usage) = service
+            .translate_to("Good morning", "French", None, false)
+            .await
+            .unwrap();
+        assert_eq!(translated, "Hello, how are you?");
+        assert!(usage
+            .calls
+            .iter()
+            .all(|c| c.provider == ProviderKind::OpenAi));
+        assert_eq!(claude_hits.load(Ordering::SeqCst), 1);
+
+        let (store, _dir) = test_store();
+        store
+            .record_usage(None, Some("m1"), &result.usage, "translate_incoming")
+            .unwrap();
+        let stats = store.get_performance_stats(100).unwrap();
+        assert_eq!(stats.by_provider.len(), 1);
+        assert_eq!(stats.by_provider[0].key, "openai");
+        assert_eq!(stats.by_provider[0].count, 2);
+
+        // Back on the primary once it recovers and the cooldown is over
+        down.store(false, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        let result = service.process_text(text, None, None, false, false).await;
+        assert_eq!(result.translated_text.as_deref(), Some("Hi, how are you?"));
+        assert!(result
+            .usage
+            .calls
+            .iter()
+            .all(|c| c.provider == ProviderKind::Anthropic));
+        assert_eq!(service.active_provider(), (ProviderKind::Anthropic, false));
+        assert!(changes.has_changed().unwrap());
+    }
+}
@@ -0,0 +1,363 @@
+//! Providers for language detection and translation, and the fallback chain
+//! between them.
+//!
+//! Detection and translation go to the first provider in the chain that is
+//! working. After a retryable failure (a network error, rate limiting or a
+//! server error) the next one is tried. Each provider has a circuit breaker,
+//! so one that keeps failing is skipped for a while instead of being waited
+//! on for every message.
+
+use anyhow::{Context, Result};
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::translation::{ApiUsage, ModelConfig};
+
+pub(crate) const ANTHROPIC_API_URL: &str = "https://api.anthropic.com/v1/messages";
+pub(crate) const ANTHROPIC_VERSION: &str = "2023-06-01";
+const OPENAI_API_URL: &str = "https://api.openai.com/v1/chat/completions";
+
+/// OpenAI models used in place of the configured Claude models
+const OPENAI_DETECTION_MODEL: &str = "gpt-4o-mini";
+const OPENAI_TRANSLATION_MODEL: &str = "gpt-4o";
+
+/// Consecutive retryable failures that open a provider's circuit breaker
+pub const BREAKER_THRESHOLD: u32 = 3;
+/// How long an open breaker skips its provider before trying it again
+pub const BREAKER_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// A service that can detect languages and translate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    Anthropic,
+    OpenAi,
+}
+
+impl ProviderKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ProviderKind::Anthropic => "anthropic",
+            ProviderKind::OpenAi => "openai",
+        }
+    }
+}
+
+impl FromStr for ProviderKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "anthropic" | "claude" => Ok(ProviderKind::Anthropic),
+            "openai" => Ok(ProviderKind::OpenAi),
+            other => Err(format!(
+                "unknown provider {:?} (expected anthropic or openai)",
+                other
+            )),
+        }
+    }
+}
+
+/// What a call to a provider is for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelRole {
+    Detection,
+    Translation,
+}
+
+/// An API that takes a single prompt and answers with text
+pub trait Provider: Send + Sync {
+    fn kind(&self) -> ProviderKind;
+
+    /// The model to use for `role`, given the configured Claude models
+    fn model(&self, role: ModelRole, models: &ModelConfig) -> String;
+
+    /// Build the request for a prompt
+    fn request(
+        &self,
+        client: &Client,
+        model: &str,
+        max_tokens: u32,
+        prompt: &str,
+    ) -> RequestBuilder;
+
+    /// The reply text and token counts from a successful response
+    fn parse(&self, body: &str) -> Result<(Option<String>, ApiUsage)>;
+}
+
+/// Whether a failed call is worth trying again on another provider
+pub fn is_retryable(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Claude's Messages API
+pub struct AnthropicProvider {
+    api_key: String,
+    api_url: String,
+}
+
+impl AnthropicProvider {
+    pub fn new(api_key: String) -> Self {
+        Self::with_url(api_key, ANTHROPIC_API_URL)
+    }
+
+    pub fn with_url(api_key: String, api_url: &str) -> Self {
+        Self {
+            api_key,
+            api_url: api_url.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PromptRequest<'a> {
+    model: &'a str,
+    max_tokens: u32,
+    messages: [PromptMessage<'a>; 1],
+}
+
+#[derive(Serialize)]
+struct PromptMessage<'a> {
+    role: &'static str,
+    content: &'a str,
+}
+
+impl<'a> PromptRequest<'a> {
+    fn new(model: &'a str, max_tokens: u32, prompt: &'a str) -> Self {
+        Self {
+            model,
+            max_tokens,
+            messages: [PromptMessage {
+                role: "user",
+                content: prompt,
+            }],
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AnthropicResponse {
+    content: Vec<AnthropicContent>,
+    usage: ApiUsage,
+}
+
+#[derive(Deserialize)]
+struct AnthropicContent {
+    text: Option<String>,
+}
+
+impl Provider for AnthropicProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::Anthropic
+    }
+
+    fn model(&self, role: ModelRole, models: &ModelConfig) -> String {
+        match role {
+            ModelRole::Detection => models.detection.clone(),
+            ModelRole::Translation => models.translation.clone(),
+        }
+    }
+
+    fn request(
+        &self,
+        client: &Client,
+        model: &str,
+        max_tokens: u32,
+        prompt: &str,
+    ) -> RequestBuilder {
+        client
+            .post(&self.api_url)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .header("content-type", "application/json")
+            .json(&PromptRequest::new(model, max_tokens, prompt))
+    }
+
+    fn parse(&self, body: &str) -> Result<(Option<String>, ApiUsage)> {
+        let response: AnthropicResponse =
+            serde_json::from_str(body).context("Unexpected Claude API response")?;
+        let text = response.content.into_iter().next().and_then(|c| c.text);
+        Ok((text, response.usage))
+    }
+}
+
+/// OpenAI's Chat Completions API
+pub struct OpenAiProvider {
+    api_key: String,
+    api_url: String,
+}
+
+impl OpenAiProvider {
+    pub fn new(api_key: String) -> Self {
+        Self::with_url(api_key, OPENAI_API_URL)
+    }
+
+    pub fn with_url(api_key: String, api_url: &str) -> Self {
+        Self {
+            api_key,
+            api_url: api_url.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct OpenAiResponse {
+    choices: Vec<OpenAiChoice>,
+    usage: OpenAiUsage,
+}
+
+#[derive(Deserialize)]
+struct OpenAiChoice {
+    message: OpenAiMessage,
+}
+
+#[derive(Deserialize)]
+struct OpenAiMessage {
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiUsage {
+    prompt_tokens: u32,
+    completion_tokens: u32,
+}
+
+impl Provider for OpenAiProvider {
+    fn kind(&self) -> ProviderKind {
+        ProviderKind::OpenAi
+    }
+
+    fn model(&self, role: ModelRole, _models: &ModelConfig) -> String {
+        match role {
+            ModelRole::Detection => OPENAI_DETECTION_MODEL.to_string(),
+            ModelRole::Translation => OPENAI_TRANSLATION_MODEL.to_string(),
+        }
+    }
+
+    fn request(
+        &self,
+        client: &Client,
+        model: &str,
+        max_tokens: u32,
+        prompt: &str,
+    ) -> RequestBuilder {
+        client
+            .post(&self.api_url)
+            .bearer_auth(&self.api_key)
+            .json(&PromptRequest::new(model, max_tokens, prompt))
+    }
+
+    fn parse(&self, body: &str) -> Result<(Option<String>, ApiUsage)> {
+        let response: OpenAiResponse =
+            serde_json::from_str(body).context("Unexpected OpenAI API response")?;
+        let text = response
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|c| c.message.content);
+        Ok((
+            text,
+            ApiUsage {
+                input_tokens: response.usage.prompt_tokens,
+                output_tokens: response.usage.completion_tokens,
+            },
+        ))
+    }
+}
+
+/// Stops calls to a provider after repeated failures. Once the cooldown is
+/// over one call is let through; it closes the breaker if it succeeds and
+/// opens it again if not.
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    failures: AtomicU32,
+    open_until: Mutex<Option<Instant>>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(BREAKER_THRESHOLD, BREAKER_COOLDOWN)
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            failures: AtomicU32::new(0),
+            open_until: Mutex::new(None),
+        }
+    }
+
+    /// Whether the provider may be called
+    pub fn allows(&self) -> bool {
+        self.open_until
+            .lock()
+            .unwrap()
+            .is_none_or(|until| Instant::now() >= until)
+    }
+
+    pub fn succeeded(&self) {
+        self.failures.store(0, Ordering::Relaxed);
+        *self.open_until.lock().unwrap() = None;
+    }
+
+    /// Count a retryable failure, returning true if it opened the breaker
+    pub fn failed(&self) -> bool {
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= self.threshold {
+            *self.open_until.lock().unwrap() = Some(Instant::now() + self.cooldown);
+            return true;
+        }
+        false
+    }
+}
+
+/// A provider in the fallback chain
+pub struct ChainedProvider {
+    pub provider: Box<dyn Provider>,
+    pub breaker: CircuitBreaker,
+}
+
+impl ChainedProvider {
+    pub fn new(provider: Box<dyn Provider>) -> Self {
+        Self {
+            provider,
+            breaker: CircuitBreaker::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circuit_breaker() {
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(50));
+        assert!(!breaker.failed());
+        assert!(breaker.allows());
+        assert!(breaker.failed());
+        assert!(!breaker.allows());
+
+        // Half open after the cooldown; one more failure opens it again
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.allows());
+        assert!(breaker.failed());
+        assert!(!breaker.allows());
+
+        std::thread::sleep(Duration::from_millis(60));
+        breaker.succeeded();
+        assert!(!breaker.failed());
+        assert!(breaker.allows());
+
+        assert_eq!("OpenAI".parse(), Ok(ProviderKind::OpenAi));
+        assert!("gemini".parse::<ProviderKind>().is_err());
+    }
+}
@@ -0,0 +1,213 @@
+//! Where a contact probably is on the clock.
+//!
+//! A contact's timezone is either set in their conversation settings or
+//! inferred from when they write: people rarely message while asleep, so the
+//! quietest stretch of a histogram of their messages' UTC hours is taken to
+//! be their night.
+
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, NaiveTime, Offset, Timelike, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use tracing::error;
+
+use crate::storage::{MessageStore, QuietHours};
+
+/// Local hours a recipient is likely asleep in: from the start hour up to
+/// the end hour
+pub const NIGHT_START_HOUR: u32 = 23;
+pub const NIGHT_END_HOUR: u32 = 7;
+
+/// Incoming messages looked at when inferring a timezone
+const INFERENCE_SAMPLE: usize = 500;
+
+/// Fewer incoming messages say too little about a contact's day
+pub const MIN_INFERENCE_MESSAGES: usize = 30;
+
+/// Most of a contact's messages the quietest stretch may hold for it to
+/// pass as their night (an even spread puts a third there)
+const MAX_NIGHT_SHARE: f64 = 0.1;
+
+/// A contact's local time and whether they're likely asleep
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecipientClock {
+    /// Whether the offset was inferred from their messages rather than set
+    pub timezone_inferred: bool,
+    pub utc_offset_minutes: i32,
+    /// Their local time ("HH:MM")
+    pub local_time: String,
+    pub likely_asleep: bool,
+}
+
+impl RecipientClock {
+    fn at(offset: FixedOffset, inferred: bool, now: DateTime<Utc>) -> Self {
+        let local = now.with_timezone(&offset).time();
+        Self {
+            timezone_inferred: inferred,
+            utc_offset_minutes: offset.local_minus_utc() / 60,
+            local_time: local.format("%H:%M").to_string(),
+            likely_asleep: night().contains(local),
+        }
+    }
+
+    /// Warning for a message sent now, if it arrives in their night
+    pub fn night_warning(&self) -> Option<String> {
+        self.likely_asleep
+            .then(|| format!("recipient local time ~{}", self.local_time))
+    }
+}
+
+/// The hours a recipient is likely asleep in
+fn night() -> QuietHours {
+    QuietHours {
+        start: NaiveTime::from_hms_opt(NIGHT_START_HOUR, 0, 0).unwrap(),
+        end: NaiveTime::from_hms_opt(NIGHT_END_HOUR, 0, 0).unwrap(),
+    }
+}
+
+/// Parse an IANA timezone name such as "Asia/Tokyo"
+pub fn parse_timezone(name: &str) -> Option<Tz> {
+    name.trim().parse().ok()
+}
+
+/// Estimate a contact's UTC offset (minutes, whole hours) from when their
+/// messages arrived: the quietest night-long stretch of the day is taken to
+/// start at `NIGHT_START_HOUR` local time. None without enough messages or
+/// a clearly quiet stretch.
+pub fn infer_utc_offset(timestamps_ms: &[i64]) -> Option<i32> {
+    let mut hours = [0usize; 24];
+    let mut total = 0;
+    for hour in timestamps_ms
+        .iter()
+        .filter_map(|&ts| DateTime::from_timestamp_millis(ts))
+        .map(|time| time.hour() as usize)
+    {
+        hours[hour] += 1;
+        total += 1;
+    }
+    if total < MIN_INFERENCE_MESSAGES {
+        return None;
+    }
+
+    // Messages in the night-long window starting at each UTC hour
+    let night_hours = ((NIGHT_END_HOUR + 24 - NIGHT_START_HOUR) % 24) as usize;
+    let windows: Vec<usize> = (0..24)
+        .map(|start| (0..night_hours).map(|i| hours[(start + i) % 24]).sum())
+        .collect();
+    let quietest = *windows.iter().min()?;
+    if quietest as f64 > total as f64 * MAX_NIGHT_SHARE {
+        return None;
+    }
+
+    // A night longer than the window makes neighbouring windows tie; take
+    // the middle of the longest run of them
+    let quiet = |start: usize| windows[start % 24] == quietest;
+    let mut longest: Option<(usize, usize)> = None;
+    for start in (0..24).filter(|&start| quiet(start) && !quiet(start + 23)) {
+        let len = (0..24).take_while(|&i| quiet(start + i)).count();
+        if longest.is_none_or(|(_, longest_len)| len > longest_len) {
+            longest = Some((start, len));
+        }
+    }
+    let (run_start, len) = longest?;
+    let night_start_utc = ((run_start + len / 2) % 24) as i32;
+
+    // Offsets run from UTC-11 to UTC+12
+    let offset_hours = (NIGHT_START_HOUR as i32 + 35 - night_start_utc) % 24 - 11;
+    Some(offset_hours * 60)
+}
+
+/// A contact's clock: from their set timezone, or inferred from their
+/// messages in private chats. None when neither tells.
+pub fn recipient_clock(
+    store: &MessageStore,
+    contact_id: &str,
+    now: DateTime<Utc>,
+) -> Result<Option<RecipientClock>> {
+    let settings = store.get_conversation_settings(contact_id)?;
+    if let Some(tz) = settings.timezone.as_deref().and_then(parse_timezone) {
+        let offset = now.with_timezone(&tz).offset().fix();
+        return Ok(Some(RecipientClock::at(offset, false, now)));
+    }
+
+    // A group's messages come from everyone in it
+    if contact_id.ends_with("@g.us") {
+        return Ok(None);
+    }
+    let timestamps = store.incoming_timestamps(contact_id, INFERENCE_SAMPLE)?;
+    Ok(infer_utc_offset(&timestamps)
+        .and_then(|minutes| FixedOffset::east_opt(minutes * 60))
+        .map(|offset| RecipientClock::at(offset, true, now)))
+}
+
+/// Warning for sending to a contact now, if it's likely night for them
+pub fn night_send_warning(store: &MessageStore, contact_id: &str) -> Option<String> {
+    match recipient_clock(store, contact_id, Utc::now()) {
+        Ok(clock) => clock.and_then(|clock| clock.night_warning()),
+        Err(e) => {
+            error!("Failed to work out recipient's local time: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// Messages written every day between two local hours at an offset
+    fn active_between(offset_hours: i64, from: i64, to: i64) -> Vec<i64> {
+        let midnight = Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap();
+        (0..10)
+            .flat_map(|day| {
+                (from..to).map(move |hour| {
+                    let local = midnight + chrono::Duration::hours(day * 24 + hour);
+                    (local - chrono::Duration::hours(offset_hours)).timestamp_millis() + 17 * 60_000
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_infer_utc_offset() {
+        // Awake 08:00-22:00 in Tokyo and 07:00-23:00 in New York (winter)
+        assert_eq!(infer_utc_offset(&active_between(9, 8, 22)), Some(9 * 60));
+        assert_eq!(infer_utc_offset(&active_between(-5, 7, 23)), Some(-5 * 60));
+        assert_eq!(infer_utc_offset(&active_between(12, 9, 21)), Some(12 * 60));
+
+        // A short sample, or messages around the clock, tell nothing
+        assert_eq!(infer_utc_offset(&active_between(9, 8, 22)[..20]), None);
+        assert_eq!(infer_utc_offset(&active_between(0, 0, 24)), None);
+
+        // A few late messages don't move the night
+        let mut night_owl = active_between(1, 7, 23);
+        night_owl.extend(&active_between(1, 1, 2)[..3]);
+        assert_eq!(infer_utc_offset(&night_owl), Some(60));
+    }
+
+    #[test]
+    fn test_night_warning_boundaries() {
+        let offset = FixedOffset::east_opt(2 * 3600).unwrap();
+        // The clock when it's h:m at UTC+2
+        let at = |h: i64, m: i64| {
+            let midnight = Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap();
+            let now = midnight + chrono::Duration::minutes(h * 60 + m - 120);
+            RecipientClock::at(offset, true, now)
+        };
+
+        assert_eq!(at(22, 59).night_warning(), None);
+        assert_eq!(
+            at(23, 0).night_warning().as_deref(),
+            Some("recipient local time ~23:00")
+        );
+        assert_eq!(
+            at(3, 12).night_warning().as_deref(),
+            Some("recipient local time ~03:12")
+        );
+        assert!(at(6, 59).likely_asleep);
+        assert!(!at(7, 0).likely_asleep);
+        assert_eq!(at(7, 0).utc_offset_minutes, 120);
+    }
+}
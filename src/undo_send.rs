@@ -0,0 +1,142 @@
+//! Sends held back for an undo window.
+//!
+//! With `undo_window_secs` set, messages sent from the web UI are stored
+//! straight away but only handed to the bridge once the window has passed.
+//! Each chat's held-back messages form a queue, so they go out in the order
+//! they were written, and undoing one takes it out of its queue before it
+//! reaches WhatsApp.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use tokio::time::Instant;
+
+use crate::sending::{OutgoingMessage, OutgoingText};
+
+/// Longest undo window that can be configured (seconds)
+pub const MAX_UNDO_WINDOW_SECS: u64 = 60;
+
+/// A message waiting out its undo window
+#[derive(Debug, Clone)]
+pub struct QueuedSend {
+    /// ID of the locally stored message
+    pub message_id: String,
+    pub message: OutgoingMessage,
+    /// What to send, when it's already settled (a confirmed send); otherwise
+    /// it's translated at dispatch
+    pub outgoing: Option<OutgoingText>,
+    pub dispatch_at: Instant,
+}
+
+/// Held-back sends, queued per chat
+#[derive(Default)]
+pub struct UndoQueue {
+    chats: Mutex<HashMap<String, VecDeque<QueuedSend>>>,
+    /// Held while sends are being dispatched, so a slow translation can't
+    /// let a later message overtake an earlier one
+    dispatching: tokio::sync::Mutex<()>,
+}
+
+impl UndoQueue {
+    pub fn push(&self, send: QueuedSend) {
+        self.chats
+            .lock()
+            .unwrap()
+            .entry(send.message.contact_id.clone())
+            .or_default()
+            .push_back(send);
+    }
+
+    /// Take the sends at the front of a chat's queue that are due by `now`.
+    /// A send stays queued behind an earlier one that isn't due yet.
+    pub fn take_due(&self, contact_id: &str, now: Instant) -> Vec<QueuedSend> {
+        let mut chats = self.chats.lock().unwrap();
+        let Some(queue) = chats.get_mut(contact_id) else {
+            return Vec::new();
+        };
+        let mut due = Vec::new();
+        while queue.front().is_some_and(|send| send.dispatch_at <= now) {
+            due.extend(queue.pop_front());
+        }
+        if queue.is_empty() {
+            chats.remove(contact_id);
+        }
+        due
+    }
+
+    /// Take a send out of its queue. None if there's no such send waiting,
+    /// e.g. because it has already been dispatched
+    pub fn cancel(&self, message_id: &str) -> Option<QueuedSend> {
+        let mut chats = self.chats.lock().unwrap();
+        let (contact_id, index) = chats.iter().find_map(|(contact_id, queue)| {
+            queue
+                .iter()
+                .position(|send| send.message_id == message_id)
+                .map(|index| (contact_id.clone(), index))
+        })?;
+        let queue = chats.get_mut(&contact_id)?;
+        let send = queue.remove(index);
+        if queue.is_empty() {
+            chats.remove(&contact_id);
+        }
+        send
+    }
+
+    /// Chats with sends waiting
+    pub fn contact_ids(&self) -> Vec<String> {
+        self.chats.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Wait for the right to dispatch sends
+    pub async fn lock_dispatch(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.dispatching.lock().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn queued(message_id: &str, contact_id: &str, dispatch_at: Instant) -> QueuedSend {
+        QueuedSend {
+            message_id: message_id.to_string(),
+            message: OutgoingMessage {
+                id: message_id.to_string(),
+                contact_id: contact_id.to_string(),
+                text: "Hola".to_string(),
+                reply: None,
+                origin: "web".to_string(),
+                sent_by: None,
+            },
+            outgoing: None,
+            dispatch_at,
+        }
+    }
+
+    #[test]
+    fn test_queue_order_and_cancel() {
+        let queue = UndoQueue::default();
+        let now = Instant::now();
+        let chat = "34600000000@s.whatsapp.net";
+        queue.push(queued("m1", chat, now + Duration::from_secs(10)));
+        queue.push(queued("m2", chat, now + Duration::from_secs(5)));
+        queue.push(queued("m3", chat, now + Duration::from_secs(20)));
+        queue.push(queued("g1", "120363000000000000@g.us", now));
+
+        // m2 is due but waits behind m1
+        assert!(queue
+            .take_due(chat, now + Duration::from_secs(5))
+            .is_empty());
+        let due = queue.take_due(chat, now + Duration::from_secs(10));
+        let ids: Vec<&str> = due.iter().map(|s| s.message_id.as_str()).collect();
+        assert_eq!(ids, ["m1", "m2"]);
+
+        assert_eq!(queue.cancel("m3").unwrap().message.contact_id, chat);
+        assert!(queue.cancel("m3").is_none());
+        assert!(queue.cancel("m1").is_none());
+        assert!(queue
+            .take_due(chat, now + Duration::from_secs(30))
+            .is_empty());
+        assert_eq!(queue.take_due("120363000000000000@g.us", now).len(), 1);
+    }
+}
@@ -0,0 +1,447 @@
+//! Images uploaded in chunks, for connections too slow or flaky to send a
+//! whole image in one request.
+//!
+//! An upload is started with its size, its chunks are put in any order (and
+//! put again if a request failed), and it's completed with the SHA-256 of
+//! the whole image. Chunks go straight into a file under the data directory,
+//! and which ones arrived can be asked for, so an interrupted upload picks
+//! up where it stopped. A completed upload is sent by passing its ID to
+//! `/api/send-image`. Uploads are removed an hour after they were started.
+
+use base64::engine::general_purpose::STANDARD;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tracing::{error, warn};
+
+/// Size of every chunk but the last
+pub const CHUNK_SIZE: usize = 512 * 1024;
+
+/// Largest image that can be uploaded
+pub const MAX_UPLOAD_BYTES: u64 = 16 * 1024 * 1024;
+
+/// How long an upload is kept after it's started (ms)
+pub const UPLOAD_TTL_MS: i64 = 60 * 60 * 1000;
+
+/// Errors returned by chunked uploads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum UploadError {
+    NotFound,
+    Empty,
+    TooLarge,
+    InvalidChunk,
+    WrongChunkSize,
+    Incomplete,
+    HashMismatch,
+    NotCompleted,
+    StorageError,
+}
+
+impl UploadError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UploadError::NotFound => "upload_not_found",
+            UploadError::Empty => "empty_upload",
+            UploadError::TooLarge => "upload_too_large",
+            UploadError::InvalidChunk => "invalid_chunk",
+            UploadError::WrongChunkSize => "wrong_chunk_size",
+            UploadError::Incomplete => "upload_incomplete",
+            UploadError::HashMismatch => "hash_mismatch",
+            UploadError::NotCompleted => "upload_not_completed",
+            UploadError::StorageError => "storage_error",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            UploadError::NotFound => "The upload doesn't exist or has expired",
+            UploadError::Empty => "An upload needs at least one byte",
+            UploadError::TooLarge => "Uploads can be at most 16MB",
+            UploadError::InvalidChunk => "The upload has no chunk with that number",
+            UploadError::WrongChunkSize => {
+                "Every chunk but the last must be exactly the chunk size"
+            }
+            UploadError::Incomplete => "Some chunks haven't been uploaded yet",
+            UploadError::HashMismatch => {
+                "The uploaded data doesn't match the SHA-256 given; upload it again"
+            }
+            UploadError::NotCompleted => "The upload hasn't been completed",
+            UploadError::StorageError => "Failed to store the upload",
+        }
+    }
+}
+
+fn storage_error(e: std::io::Error) -> UploadError {
+    error!("Failed to store upload: {}", e);
+    UploadError::StorageError
+}
+
+/// Where an upload stands
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadStatus {
+    pub upload_id: String,
+    pub size: u64,
+    pub chunk_size: usize,
+    pub chunk_count: u32,
+    /// Numbers of the chunks received so far
+    pub received: Vec<u32>,
+    /// The whole image arrived and matched its SHA-256
+    pub completed: bool,
+    /// When the upload is removed (ms)
+    pub expires_at: i64,
+}
+
+struct UploadSession {
+    size: u64,
+    expires_at: i64,
+    received: BTreeSet<u32>,
+    /// Hash of the chunks before `hashed_chunks`, which all arrived
+    hasher: Sha256,
+    hashed_chunks: u32,
+    completed: bool,
+}
+
+impl UploadSession {
+    fn chunk_count(&self) -> u32 {
+        self.size.div_ceil(CHUNK_SIZE as u64) as u32
+    }
+
+    /// Length chunk `index` must have
+    fn chunk_len(&self, index: u32) -> usize {
+        let start = index as u64 * CHUNK_SIZE as u64;
+        (self.size - start).min(CHUNK_SIZE as u64) as usize
+    }
+}
+
+/// Uploads in progress, and completed ones waiting to be sent
+pub struct UploadStore {
+    dir: PathBuf,
+    sessions: Mutex<HashMap<String, UploadSession>>,
+}
+
+impl UploadStore {
+    /// Uploads kept in `dir`, created when the first upload starts
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn path(&self, upload_id: &str) -> PathBuf {
+        self.dir.join(format!("{}.part", upload_id))
+    }
+
+    fn status(upload_id: &str, session: &UploadSession) -> UploadStatus {
+        UploadStatus {
+            upload_id: upload_id.to_string(),
+            size: session.size,
+            chunk_size: CHUNK_SIZE,
+            chunk_count: session.chunk_count(),
+            received: session.received.iter().copied().collect(),
+            completed: session.completed,
+            expires_at: session.expires_at,
+        }
+    }
+
+    /// Start an upload of `size` bytes
+    pub fn create(&self, size: u64, now: i64) -> Result<UploadStatus, UploadError> {
+        if size == 0 {
+            return Err(UploadError::Empty);
+        }
+        if size > MAX_UPLOAD_BYTES {
+            return Err(UploadError::TooLarge);
+        }
+        let upload_id = uuid::Uuid::new_v4().simple().to_string();
+        fs::create_dir_all(&self.dir).map_err(storage_error)?;
+        File::create(self.path(&upload_id)).map_err(storage_error)?;
+
+        let session = UploadSession {
+            size,
+            expires_at: now + UPLOAD_TTL_MS,
+            received: BTreeSet::new(),
+            hasher: Sha256::new(),
+            hashed_chunks: 0,
+            completed: false,
+        };
+        let status = Self::status(&upload_id, &session);
+        self.sessions.lock().unwrap().insert(upload_id, session);
+        Ok(status)
+    }
+
+    /// Where an upload stands, e.g. to resume it
+    pub fn get(&self, upload_id: &str, now: i64) -> Result<UploadStatus, UploadError> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get(upload_id)
+            .filter(|session| session.expires_at > now)
+            .ok_or(UploadError::NotFound)?;
+        Ok(Self::status(upload_id, session))
+    }
+
+    /// Write chunk `index` of an upload. A chunk that already arrived is
+    /// left as it is.
+    pub fn put_chunk(
+        &self,
+        upload_id: &str,
+        index: u32,
+        data: &[u8],
+        now: i64,
+    ) -> Result<UploadStatus, UploadError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get_mut(upload_id)
+            .filter(|session| session.expires_at > now)
+            .ok_or(UploadError::NotFound)?;
+        if index >= session.chunk_count() {
+            return Err(UploadError::InvalidChunk);
+        }
+        if data.len() != session.chunk_len(index) {
+            return Err(UploadError::WrongChunkSize);
+        }
+        if session.received.contains(&index) {
+            return Ok(Self::status(upload_id, session));
+        }
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(self.path(upload_id))
+            .map_err(storage_error)?;
+        file.seek(SeekFrom::Start(index as u64 * CHUNK_SIZE as u64))
+            .and_then(|_| file.write_all(data))
+            .map_err(storage_error)?;
+        session.received.insert(index);
+
+        // Hash on while the chunks from the start are all there
+        if index == session.hashed_chunks {
+            session.hasher.update(data);
+            session.hashed_chunks += 1;
+            while session.received.contains(&session.hashed_chunks) {
+                let mut chunk = vec![0; session.chunk_len(session.hashed_chunks)];
+                file.seek(SeekFrom::Start(
+                    session.hashed_chunks as u64 * CHUNK_SIZE as u64,
+                ))
+                .and_then(|_| file.read_exact(&mut chunk))
+                .map_err(storage_error)?;
+                session.hasher.update(&chunk);
+                session.hashed_chunks += 1;
+            }
+        }
+        Ok(Self::status(upload_id, session))
+    }
+
+    /// Finish an upload once every chunk arrived, checking it against the
+    /// SHA-256 (hex) of the whole image. An upload that doesn't match is
+    /// removed.
+    pub fn complete(
+        &self,
+        upload_id: &str,
+        sha256: &str,
+        now: i64,
+    ) -> Result<UploadStatus, UploadError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = sessions
+            .get_mut(upload_id)
+            .filter(|session| session.expires_at > now)
+            .ok_or(UploadError::NotFound)?;
+        if !session.completed {
+            if session.hashed_chunks < session.chunk_count() {
+                return Err(UploadError::Incomplete);
+            }
+            let hash: String = session
+                .hasher
+                .clone()
+                .finalize()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            if !hash.eq_ignore_ascii_case(sha256.trim()) {
+                sessions.remove(upload_id);
+                self.remove_file(upload_id);
+                return Err(UploadError::HashMismatch);
+            }
+            session.completed = true;
+        }
+        Ok(Self::status(upload_id, session))
+    }
+
+    /// The image of a completed upload, base64 encoded as it's sent. The
+    /// file is encoded as it's read rather than loaded whole first, and
+    /// uploads aren't locked while it is; this blocks, so call it from a
+    /// blocking thread.
+    pub fn read_base64(&self, upload_id: &str, now: i64) -> Result<String, UploadError> {
+        {
+            let sessions = self.sessions.lock().unwrap();
+            let session = sessions
+                .get(upload_id)
+                .filter(|session| session.expires_at > now)
+                .ok_or(UploadError::NotFound)?;
+            if !session.completed {
+                return Err(UploadError::NotCompleted);
+            }
+        }
+
+        let mut file = File::open(self.path(upload_id)).map_err(storage_error)?;
+        let mut encoder = base64::write::EncoderStringWriter::new(&STANDARD);
+        std::io::copy(&mut file, &mut encoder).map_err(storage_error)?;
+        Ok(encoder.into_inner())
+    }
+
+    /// Remove an upload, e.g. once it's been sent
+    pub fn remove(&self, upload_id: &str) {
+        if self.sessions.lock().unwrap().remove(upload_id).is_some() {
+            self.remove_file(upload_id);
+        }
+    }
+
+    fn remove_file(&self, upload_id: &str) {
+        if let Err(e) = fs::remove_file(self.path(upload_id)) {
+            warn!("Failed to remove upload {}: {}", upload_id, e);
+        }
+    }
+
+    /// Remove expired uploads, and files left by uploads from before a
+    /// restart. Returns how many uploads were removed.
+    pub fn remove_expired(&self, now: i64) -> usize {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, session| session.expires_at > now);
+
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return 0;
+        };
+        let mut removed = 0;
+        for path in entries.flatten().map(|entry| entry.path()) {
+            let upload_id = path.file_stem().and_then(|stem| stem.to_str());
+            if upload_id.is_some_and(|id| sessions.contains_key(id)) {
+                continue;
+            }
+            match fs::remove_file(&path) {
+                Ok(()) => removed += 1,
+                Err(e) => warn!("Failed to remove upload {:?}: {}", path, e),
+            }
+        }
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+
+    fn test_uploads() -> (UploadStore, tempfile::TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        (UploadStore::new(dir.path().to_path_buf()), dir)
+    }
+
+    fn sha256_hex(data: &[u8]) -> String {
+        Sha256::digest(data)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// An image of `size` bytes that differs from chunk to chunk
+    fn image(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_out_of_order_chunks() {
+        let (uploads, _dir) = test_uploads();
+        let data = image(2 * CHUNK_SIZE + 1000);
+        let upload = uploads.create(data.len() as u64, 0).unwrap();
+        assert_eq!(upload.chunk_count, 3);
+        let id = upload.upload_id;
+        let chunk = |i: usize| &data[i * CHUNK_SIZE..((i + 1) * CHUNK_SIZE).min(data.len())];
+
+        assert_eq!(
+            uploads.put_chunk(&id, 2, chunk(1), 1),
+            Err(UploadError::WrongChunkSize)
+        );
+        assert_eq!(
+            uploads.put_chunk(&id, 3, chunk(2), 1),
+            Err(UploadError::InvalidChunk)
+        );
+        uploads.put_chunk(&id, 2, chunk(2), 1).unwrap();
+        uploads.put_chunk(&id, 0, chunk(0), 2).unwrap();
+        // Resuming: the client asks what's there and sends the rest
+        assert_eq!(uploads.get(&id, 3).unwrap().received, [0, 2]);
+        assert_eq!(
+            uploads.complete(&id, &sha256_hex(&data), 3),
+            Err(UploadError::Incomplete)
+        );
+        assert_eq!(uploads.read_base64(&id, 3), Err(UploadError::NotCompleted));
+        uploads.put_chunk(&id, 1, chunk(1), 4).unwrap();
+        // A chunk sent twice changes nothing
+        uploads.put_chunk(&id, 0, chunk(0), 4).unwrap();
+
+        let done = uploads
+            .complete(&id, &sha256_hex(&data).to_uppercase(), 5)
+            .unwrap();
+        assert!(done.completed);
+        assert_eq!(done.received, [0, 1, 2]);
+        assert_eq!(uploads.read_base64(&id, 6).unwrap(), STANDARD.encode(&data));
+
+        uploads.remove(&id);
+        assert_eq!(uploads.read_base64(&id, 6), Err(UploadError::NotFound));
+        assert!(!uploads.path(&id).exists());
+    }
+
+    #[test]
+    fn test_hash_mismatch_discards_upload() {
+        let (uploads, _dir) = test_uploads();
+        let data = image(1000);
+        let id = uploads.create(1000, 0).unwrap().upload_id;
+        let mut corrupted = data.clone();
+        corrupted[500] ^= 0xff;
+        uploads.put_chunk(&id, 0, &corrupted, 1).unwrap();
+
+        assert_eq!(
+            uploads.complete(&id, &sha256_hex(&data), 2),
+            Err(UploadError::HashMismatch)
+        );
+        assert_eq!(uploads.get(&id, 2), Err(UploadError::NotFound));
+        assert!(!uploads.path(&id).exists());
+
+        assert_eq!(uploads.create(0, 0), Err(UploadError::Empty));
+        assert_eq!(
+            uploads.create(MAX_UPLOAD_BYTES + 1, 0),
+            Err(UploadError::TooLarge)
+        );
+    }
+
+    #[test]
+    fn test_uploads_expire() {
+        let (uploads, _dir) = test_uploads();
+        let data = image(1000);
+        let stale = uploads.create(1000, 0).unwrap().upload_id;
+        let fresh = uploads.create(1000, UPLOAD_TTL_MS / 2).unwrap().upload_id;
+        uploads.put_chunk(&stale, 0, &data, 1).unwrap();
+        uploads.complete(&stale, &sha256_hex(&data), 2).unwrap();
+
+        assert_eq!(
+            uploads
+                .put_chunk(&fresh, 0, &data, UPLOAD_TTL_MS)
+                .map(|s| s.completed),
+            Ok(false)
+        );
+        assert_eq!(
+            uploads.read_base64(&stale, UPLOAD_TTL_MS),
+            Err(UploadError::NotFound)
+        );
+
+        // A file left from before a restart goes too
+        fs::write(uploads.dir.join("orphan.part"), b"partial").unwrap();
+        assert_eq!(uploads.remove_expired(UPLOAD_TTL_MS), 2);
+        assert!(!uploads.path(&stale).exists());
+        assert!(!uploads.dir.join("orphan.part").exists());
+        assert!(uploads.get(&fresh, UPLOAD_TTL_MS).is_ok());
+    }
+}
@@ -0,0 +1,184 @@
+//! View-once photos and videos.
+//!
+//! The sender expects these to be seen once and then gone, so their media is
+//! never written to the database: it's held in memory until it's viewed
+//! (through `/api/media/:id`, which takes it out of the cache) or expires.
+//! The stored message keeps only the metadata, a `view_once` flag and, once
+//! opened, a `viewed_at` timestamp. `--archive-view-once` stores them like
+//! any other media instead.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::storage::StoredMessage;
+
+/// Content key flagging a view-once message
+pub const CONTENT_KEY: &str = "view_once";
+
+/// How long unopened media is kept
+const TTL: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// Most unopened media held at once; the oldest is dropped beyond this
+const MAX_ENTRIES: usize = 50;
+
+/// Media waiting to be viewed
+struct Entry {
+    media_data: String,
+    mime_type: Option<String>,
+    received: Instant,
+}
+
+/// Unopened view-once media, by message ID
+#[derive(Default)]
+pub struct ViewOnceCache {
+    entries: Mutex<HashMap<String, Entry>>,
+    /// Store view-once media permanently like other media
+    archive: AtomicBool,
+}
+
+impl ViewOnceCache {
+    /// Whether view-once media is archived instead of cached
+    pub fn archive(&self) -> bool {
+        self.archive.load(Ordering::Relaxed)
+    }
+
+    pub fn set_archive(&self, archive: bool) {
+        self.archive.store(archive, Ordering::Relaxed);
+    }
+
+    /// Hold a message's media until it's viewed
+    pub fn insert(&self, message_id: &str, media_data: String, mime_type: Option<String>) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.received.elapsed() < TTL);
+        if entries.len() >= MAX_ENTRIES {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.received)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                debug!("View-once cache full, dropping media of {}", oldest);
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            message_id.to_string(),
+            Entry {
+                media_data,
+                mime_type,
+                received: Instant::now(),
+            },
+        );
+    }
+
+    /// Take a message's media for its single view. Returns None if it was
+    /// already viewed, has expired or was never cached.
+    pub fn take(&self, message_id: &str) -> Option<(String, Option<String>)> {
+        let entry = self.entries.lock().unwrap().remove(message_id)?;
+        (entry.received.elapsed() < TTL).then_some((entry.media_data, entry.mime_type))
+    }
+}
+
+/// Whether message content is a view-once photo or video
+pub fn is_view_once(content: &serde_json::Value) -> bool {
+    content.get(CONTENT_KEY).and_then(|v| v.as_bool()) == Some(true)
+}
+
+/// Take the media out of a view-once message so it isn't stored, leaving a
+/// `has_media` flag. Returns the media data and MIME type, or None if the
+/// message isn't view-once or has no media.
+pub fn take_payload(message: &mut StoredMessage) -> Option<(String, Option<String>)> {
+    let mut content: serde_json::Value = serde_json::from_str(&message.content_json).ok()?;
+    if !is_view_once(&content) {
+        return None;
+    }
+    let obj = content.as_object_mut()?;
+    let media_data = obj
+        .remove("media_data")
+        .or_else(|| obj.remove("mediaData"))?
+        .as_str()?
+        .to_string();
+    let mime_type = obj
+        .get("mime_type")
+        .and_then(|v| v.as_str())
+        .map(str::to_string);
+    obj.insert("has_media".to_string(), true.into());
+
+    message.content_json = content.to_string();
+    message.content = Some(content);
+    Some((media_data, mime_type))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::text_message;
+
+    fn message(content: serde_json::Value) -> StoredMessage {
+        StoredMessage {
+            content_type: "Image".to_string(),
+            content_json: content.to_string(),
+            content: Some(content),
+            original_text: None,
+            ..text_message("once", "a@s.whatsapp.net", 1, "")
+        }
+    }
+
+    #[test]
+    fn test_media_is_viewable_once() {
+        let cache = ViewOnceCache::default();
+        cache.insert(
+            "once",
+            "3q2+7w==".to_string(),
+            Some("image/jpeg".to_string()),
+        );
+
+        assert_eq!(
+            cache.take("once"),
+            Some(("3q2+7w==".to_string(), Some("image/jpeg".to_string())))
+        );
+        assert_eq!(cache.take("once"), None);
+        assert_eq!(cache.take("never-cached"), None);
+
+        // Beyond capacity the oldest unopened media is dropped
+        for i in 0..=MAX_ENTRIES {
+            cache.insert(&format!("m{}", i), "AA==".to_string(), None);
+        }
+        assert_eq!(cache.take("m0"), None);
+        assert!(cache.take(&format!("m{}", MAX_ENTRIES)).is_some());
+    }
+
+    #[test]
+    fn test_take_payload() {
+        let mut once = message(serde_json::json!({
+            "type": "image",
+            "mime_type": "image/jpeg",
+            "view_once": true,
+            "media_data": "3q2+7w==",
+        }));
+        assert_eq!(
+            take_payload(&mut once),
+            Some(("3q2+7w==".to_string(), Some("image/jpeg".to_string())))
+        );
+        let stored: serde_json::Value = serde_json::from_str(&once.content_json).unwrap();
+        assert_eq!(
+            stored,
+            serde_json::json!({
+                "type": "image",
+                "mime_type": "image/jpeg",
+                "view_once": true,
+                "has_media": true,
+            })
+        );
+        assert_eq!(once.content, Some(stored));
+        assert_eq!(take_payload(&mut once), None);
+
+        // Ordinary media is left alone
+        let content = serde_json::json!({"type": "image", "media_data": "3q2+7w=="});
+        let mut normal = message(content.clone());
+        assert_eq!(take_payload(&mut normal), None);
+        assert_eq!(normal.content, Some(content));
+    }
+}
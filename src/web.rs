@@ -5,41 +5,182 @@
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        Host, Path, Query, State,
+        DefaultBodyLimit, Host, Multipart, Path, Query, Request, State,
     },
-    http::{header, StatusCode},
-    response::{Html, IntoResponse, Json, Redirect},
-    routing::{get, post},
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Json, Redirect, Response},
+    routing::{delete, get, post, put},
     Form, Router,
 };
 use futures::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::{broadcast, oneshot, RwLock};
+use tokio::sync::{broadcast, RwLock};
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::services::ServeDir;
-use tracing::{error, info, warn};
-
-use crate::bridge::BridgeCommand;
+use tracing::{debug, error, info, warn};
+
+use crate::access_log::{self, AccessLog, Principal};
+use crate::anonymize::Anonymizer;
+use crate::api_version::{self, ClientVersions};
+use crate::bridge::{is_channel_jid, BridgeCommand};
+use crate::broadcast::{BroadcastBatch, BroadcastError, Broadcasts};
+use crate::chat_search::{self, ChatSearchHit};
+use crate::connection_quality::{self, ConnectionMonitor, QualityAssessment, QualityClass};
+use crate::contact_cache::ContactCacheStats;
+use crate::disk_guard::DiskStatus;
+use crate::error_registry::{ErrorCategory, ErrorEntry, ErrorRegistry, ErrorSummary};
+use crate::geocode::{self, Geocoder};
+use crate::groups::{create_group, GroupError, PendingGroups};
+use crate::history_sync::{HistoryBatch, HistorySync, SyncProgress};
+use crate::import::{self, ImportError};
+use crate::jobs::{Job, JobContext, JobError, JobProgress, JobQueue, JobRecord};
+use crate::language_seeding::{LanguageSeeding, SeedingProgress};
+use crate::lifecycle::Lifecycle;
+use crate::maintenance::{self, Maintenance};
 use crate::mcp::WhatsAppMcpServer;
+use crate::mcp_access::{self, ChatAccessList};
+use crate::new_chat::{start_new_chat, NewChatError, PendingNumberChecks};
+use crate::notes::{self, Note, NoteError};
 use crate::oauth::{
-    generate_token, AccessToken, AuthorizationCode, AuthorizeRequest, OAuthError,
-    OAuthErrorResponse, OAuthMetadata, PendingAuthorization, RefreshToken, RevokeRequest,
-    TokenRequest, TokenResponse,
+    constant_time_eq, generate_token, validate_redirect_uri, AccessToken, AuthorizationCode,
+    AuthorizeRateLimit, AuthorizeRequest, OAuthError, OAuthErrorResponse, OAuthMetadata,
+    PendingAuthorization, RefreshToken, RevokeRequest, TokenRequest, TokenResponse, BROWSER_COOKIE,
+};
+use crate::pending::{self, CommandError, PendingRequests, SendOutcome};
+use crate::pins::{self, PinError};
+use crate::presence::{self, Presence, PresenceSubscriptions, PresenceSummary};
+use crate::push::PushNotifier;
+use crate::reports::{self, ReportError, WeeklyReports};
+use crate::send_guard::{check_language, LanguageGuardConfig, PendingConfirmations, PendingSend};
+use crate::sending::{
+    pending_message_id, OutgoingMessage, OutgoingMessageService, OutgoingText, ReplyTo,
+};
+use crate::settings_transfer::{self, ConfigExport, ConflictResolution, SettingsImportError};
+use crate::shutdown::ShutdownController;
+use crate::storage::{
+    ContactCursor, ConversationSettings, Draft, FirstUnread, LanguageConfidence, McpQuota,
+    MessageCursor, MessageFilter, MessageStore, OutgoingTranslation, OwnProfile,
+    ParticipantTranslationMode, PinnedMessage, PushSubscription, QuietHours, ReactionGroup,
+    StoredContact, StoredMessage, TranslationPair, TranslationParticipant,
+};
+use crate::tls::HttpsConfig;
+use crate::transcript::{self, TranscriptError};
+use crate::translation::{
+    LearningNote, ModelConfig, ModelUpdate, Tone, TranslationService, TranslationStatus,
+    TranslationTone, TranslationUnavailable, Urgency,
 };
-use crate::storage::{MessageStore, StoredMessage};
-use crate::translation::TranslationService;
+use crate::translation_provider::ProviderKind;
+use crate::tzinfer::{self, RecipientClock};
+use crate::undo_send::{QueuedSend, UndoQueue, MAX_UNDO_WINDOW_SECS};
+use crate::uploads::{UploadError, UploadStore, CHUNK_SIZE};
+use crate::view_once::ViewOnceCache;
 use tokio::sync::mpsc;
 
+/// How long logout waits for the bridge to exit by itself after the logout command
+const LOGOUT_BRIDGE_GRACE: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// How long logout waits for the restart loop to stop the bridge
+const LOGOUT_BRIDGE_STOP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Reasons a logout can fail
+#[derive(Debug)]
+pub enum LogoutError {
+    /// Another logout is still running
+    InProgress,
+    /// Clearing the message store failed
+    ClearFailed(String),
+}
+
+/// How long to wait for the bridge to return a profile picture
+const AVATAR_REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How long a cached profile picture stays valid, in seconds
+const AVATAR_CACHE_TTL_SECS: i64 = 3600;
+
+/// Most JIDs accepted by a single batch avatar request
+const MAX_AVATAR_BATCH: usize = 100;
+
+/// Background avatar fetches allowed to wait on the bridge at once
+const AVATAR_FETCH_CONCURRENCY: usize = 4;
+
 /// Profile picture cache entry
 #[derive(Debug, Clone)]
 pub struct ProfilePicture {
     pub url: Option<String>,
     pub fetched_at: i64,
+    /// When the entry was last fetched or read, for evicting the least
+    /// recently used
+    pub last_used: i64,
+}
+
+/// What the cache knows about a JID's avatar.
+/// Serializes as the URL, `null` (no picture) or `"unknown"` (not fetched yet).
+#[derive(Debug, Clone, PartialEq)]
+enum AvatarLookup {
+    Cached(Option<String>),
+    Unknown,
+}
+
+impl Serialize for AvatarLookup {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            AvatarLookup::Cached(url) => url.serialize(serializer),
+            AvatarLookup::Unknown => serializer.serialize_str("unknown"),
+        }
+    }
+}
+
+/// Split requested JIDs into cache results and the (deduplicated) JIDs that
+/// still need fetching
+fn partition_avatars(
+    cache: &mut HashMap<String, ProfilePicture>,
+    jids: &[String],
+    now: i64,
+) -> (HashMap<String, AvatarLookup>, Vec<String>) {
+    let mut avatars = HashMap::new();
+    let mut unknown = Vec::new();
+
+    for jid in jids {
+        if avatars.contains_key(jid) {
+            continue;
+        }
+        match cache.get_mut(jid) {
+            Some(cached) if now - cached.fetched_at < AVATAR_CACHE_TTL_SECS => {
+                cached.last_used = now;
+                avatars.insert(jid.clone(), AvatarLookup::Cached(cached.url.clone()));
+            }
+            _ => {
+                avatars.insert(jid.clone(), AvatarLookup::Unknown);
+                unknown.push(jid.clone());
+            }
+        }
+    }
+
+    (avatars, unknown)
+}
+
+/// Strip the device part from a JID ("123:4@lid" -> "123@lid")
+pub(crate) fn bare_jid(jid: &str) -> String {
+    match jid.split_once('@') {
+        Some((user, server)) => {
+            let user = user.split_once(':').map_or(user, |(user, _)| user);
+            format!("{}@{}", user, server)
+        }
+        None => jid.to_string(),
+    }
+}
+
+/// Whether any of the mentioned JIDs is one of my own
+fn mentions_any(own_jids: &[String], mentioned_jids: &[String]) -> bool {
+    mentioned_jids
+        .iter()
+        .any(|jid| own_jids.contains(&bare_jid(jid)))
 }
 
 /// Shared application state
@@ -48,6 +189,8 @@ pub struct AppState {
     pub connected: RwLock<bool>,
     pub phone: RwLock<Option<String>>,
     pub name: RwLock<Option<String>>,
+    /// My own JIDs (phone JID and LID, without device), for spotting mentions
+    pub own_jids: RwLock<Vec<String>>,
     pub qr_code: RwLock<Option<String>>,
     pub broadcast_tx: broadcast::Sender<WebSocketEvent>,
     pub web_dir: PathBuf,
@@ -56,14 +199,90 @@ pub struct AppState {
     pub translator: Option<Arc<TranslationService>>,
     /// Cache of profile pictures (JID -> ProfilePicture)
     pub avatar_cache: RwLock<HashMap<String, ProfilePicture>>,
-    /// Pending profile picture requests (request_id -> sender)
-    pub pending_avatar_requests: RwLock<HashMap<i32, oneshot::Sender<Option<String>>>>,
+    /// Profile picture requests waiting for the bridge
+    pub pending_avatars: PendingRequests<Option<String>>,
+    /// Sends waiting for the bridge's send result
+    pub pending_sends: PendingRequests<SendOutcome>,
+    /// Profile commands waiting for the bridge (Err is the bridge's error)
+    pub pending_profiles: PendingRequests<Result<(), String>>,
+    /// Commands the connected bridge understands (None if it didn't say)
+    pub bridge_capabilities: RwLock<Option<Vec<String>>>,
+    /// JIDs with a background avatar fetch queued or running
+    pub avatar_fetches: RwLock<std::collections::HashSet<String>>,
+    /// Limits concurrent background avatar fetches
+    pub avatar_fetch_limit: tokio::sync::Semaphore,
     /// Request ID counter
     pub request_id_counter: AtomicI32,
-    /// Password for web interface (None = no password required)
-    pub password: Option<String>,
-    /// Valid auth tokens (simple session management)
-    pub auth_tokens: RwLock<std::collections::HashSet<String>>,
+    /// Pending number check requests for starting new chats
+    pub number_checks: Arc<PendingNumberChecks>,
+    /// Group creations waiting for the bridge
+    pub pending_groups: Arc<PendingGroups>,
+    /// Daily cap on automatic reply suggestions (None = automatic mode off)
+    pub auto_suggest_daily_cap: Option<u32>,
+    /// Where sends are checked against the chat's language
+    pub language_guard: LanguageGuardConfig,
+    /// Sends held back until the user confirms them
+    pub confirmations: Arc<PendingConfirmations>,
+    /// Logout / bridge restart coordination
+    pub lifecycle: Lifecycle,
+    /// Argon2 hash of the web password (None = no password required),
+    /// saved in the settings table
+    pub password_hash: RwLock<Option<String>>,
+    /// One-time token for setting the first password, printed at startup
+    /// while none is set
+    pub setup_token: RwLock<Option<String>>,
+    /// Whether the server terminates TLS itself
+    pub serves_https: AtomicBool,
+    /// Recent errors by subsystem, see `report_error`
+    pub errors: ErrorRegistry,
+    /// Addresses the web server is listening on
+    pub bound_addrs: RwLock<Vec<SocketAddr>>,
+    /// Unopened view-once media, kept out of the database
+    pub view_once: ViewOnceCache,
+    /// Request logging, off unless `--access-log` is set
+    pub access_log: AccessLog,
+    /// Cache and expired record cleanup settings
+    pub maintenance: Maintenance,
+    /// Seeding conversation languages from history sync messages
+    pub language_seeding: LanguageSeeding,
+    /// Translates, sends and stores outgoing text messages
+    pub sending: OutgoingMessageService,
+    /// Web sends waiting out the undo window
+    pub undo_queue: UndoQueue,
+    /// Requests counted per API version clients declare
+    pub client_versions: ClientVersions,
+    /// Images uploaded in chunks, until they're sent
+    pub uploads: UploadStore,
+    /// Stop signal for background tasks and the work in flight, for a
+    /// graceful shutdown
+    pub shutdown: ShutdownController,
+    /// Long-running background jobs, with progress and cancellation
+    pub jobs: JobQueue,
+    /// Texts sent to several chats at once
+    pub broadcasts: Arc<Broadcasts>,
+    /// Reverse geocoding and map previews for shared locations
+    pub geocoder: Geocoder,
+    /// Web Push notifications to subscribed browsers
+    pub push: PushNotifier,
+    /// History sync being imported, and the contact updates it holds back
+    pub history_sync: HistorySync,
+    /// History messages waiting to be stored together
+    pub history_batch: HistoryBatch,
+    /// WebSocket clients that fell behind the broadcast channel
+    pub broadcast_lag: BroadcastLag,
+    /// How well the link to WhatsApp is holding up
+    pub connection: ConnectionMonitor,
+    /// Weekly report settings and delivery
+    pub reports: WeeklyReports,
+    /// Contacts whose online status is followed
+    pub presence: PresenceSubscriptions,
+    /// Reactions sent but not yet confirmed by the bridge (request_id -> reaction)
+    pub pending_reactions: RwLock<HashMap<i32, PendingReaction>>,
+    /// Recent OAuth authorization attempts per client
+    pub oauth_rate_limit: AuthorizeRateLimit,
+    /// Valid auth tokens (simple session management), with the display name
+    /// each session was given, if any
+    pub auth_tokens: RwLock<HashMap<String, Option<String>>>,
 }
 
 /// Events sent to WebSocket clients
@@ -85,6 +304,9 @@ pub enum WebSocketEvent {
     Disconnected,
     Message {
         message: StoredMessage,
+        /// Quick-reply suggestions (automatic mode only)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        suggestions: Option<Vec<String>>,
     },
     Typing {
         chat_id: String,
@@ -93,10 +315,173 @@ pub enum WebSocketEvent {
     },
     MarkAsRead {
         chat_id: String,
+        /// Incoming messages after this timestamp (ms) are still unread
+        last_read_timestamp: Option<i64>,
+    },
+    /// Result of a background avatar fetch
+    Avatar {
+        jid: String,
+        url: Option<String>,
     },
     Error {
         error: String,
     },
+    /// A contact's name, phone or type changed, or it was just created
+    ContactUpdated {
+        contact: StoredContact,
+    },
+    /// My own name, about text or avatar changed
+    ProfileUpdated {
+        profile: OwnProfile,
+    },
+    /// A message was removed before it was sent (an undone send)
+    MessageRemoved {
+        contact_id: String,
+        message_id: String,
+    },
+    /// Something went wrong that stops messages getting through or being
+    /// kept (see `ErrorCategory::is_high_severity`)
+    ErrorOccurred {
+        error: ErrorEntry,
+    },
+    /// The connection's quality class changed
+    ConnectionQuality {
+        previous: QualityClass,
+        quality: QualityAssessment,
+    },
+    /// A message was translated again, after its translation failed or
+    /// never happened
+    MessageTranslated {
+        contact_id: String,
+        message_id: String,
+        translated_text: Option<String>,
+        source_language: Option<String>,
+        translation_status: TranslationStatus,
+    },
+    /// A message I sent was stored under a temporary ID, and WhatsApp's
+    /// copy of it has arrived with the real one
+    MessageIdUpdated {
+        contact_id: String,
+        old_id: String,
+        new_id: String,
+        timestamp: i64,
+        sort_key: i64,
+    },
+    /// The address of a shared location's coordinates was found
+    LocationResolved {
+        contact_id: String,
+        message_id: String,
+        address: String,
+    },
+    /// A conversation's history was cleared (the contact is kept)
+    ConversationCleared {
+        contact_id: String,
+    },
+    /// A message was pinned or unpinned; the chat's pins, most recently
+    /// pinned first
+    PinsUpdated {
+        contact_id: String,
+        pinned_messages: Vec<PinnedMessage>,
+    },
+    /// A conversation's draft was saved, or deleted (None)
+    DraftUpdated {
+        contact_id: String,
+        draft: Option<Draft>,
+    },
+    /// Free disk space crossed the read-only threshold
+    DiskSpace {
+        read_only: bool,
+        free_bytes: Option<u64>,
+        min_free_bytes: u64,
+    },
+    /// I reacted to a message (shown before WhatsApp confirms it)
+    ReactionAdded {
+        contact_id: String,
+        message_id: String,
+        emoji: String,
+        reactions: Vec<ReactionGroup>,
+    },
+    /// I took back a reaction, or one failed to send
+    ReactionRemoved {
+        contact_id: String,
+        message_id: String,
+        reactions: Vec<ReactionGroup>,
+    },
+    /// A followed contact came online or went offline
+    Presence {
+        contact_id: String,
+        is_online: bool,
+        last_seen: Option<i64>,
+    },
+    /// How far the history sync being imported has got
+    SyncProgress {
+        chats_done: u32,
+        chats_total: u32,
+        messages_done: u32,
+        complete: bool,
+    },
+    /// How far seeding conversation languages from history has got
+    LanguageSeeding {
+        contacts_done: usize,
+        contacts_total: usize,
+        messages_detected: usize,
+        complete: bool,
+    },
+    /// This client fell behind and missed events (or too much changed to
+    /// send, with `missed` 0); it should refetch state over the REST API
+    Resync {
+        missed: u64,
+    },
+    /// Translation moved to a fallback provider (quality may differ), or
+    /// back to the primary
+    TranslationProvider {
+        provider: ProviderKind,
+        fallback: bool,
+    },
+    /// Translation stopped working (e.g. the API key was rejected), or
+    /// works again, with `unavailable` None
+    TranslationAvailability {
+        unavailable: Option<TranslationUnavailableStatus>,
+    },
+    /// A background job was queued, made progress or finished
+    JobProgress {
+        job: JobRecord,
+    },
+    /// A broadcast was started or one of its messages was sent (or failed)
+    BroadcastProgress {
+        batch: BroadcastBatch,
+    },
+    /// The server is shutting down; the socket closes after this
+    ShuttingDown,
+}
+
+/// Most contact updates broadcast at the end of a history sync; beyond this
+/// clients are told to refetch instead of overflowing the broadcast channel
+const MAX_DEFERRED_CONTACT_UPDATES: usize = 50;
+
+/// Largest serialized event sent to a WebSocket client; bigger events are
+/// replaced by a `payload_truncated` marker
+const MAX_WS_EVENT_BYTES: usize = 256 * 1024;
+
+/// Counts of WebSocket clients falling behind the broadcast channel
+#[derive(Default)]
+pub struct BroadcastLag {
+    /// Times a client lagged
+    lagged: AtomicU64,
+    /// Events skipped by lagging clients
+    missed: AtomicU64,
+}
+
+impl BroadcastLag {
+    fn record(&self, missed: u64) {
+        let lagged = self.lagged.fetch_add(1, Ordering::Relaxed) + 1;
+        let total = self.missed.fetch_add(missed, Ordering::Relaxed) + missed;
+        warn!(
+            "WebSocket client fell behind and missed {} events, sending resync \
+             ({} lagged clients, {} missed events so far)",
+            missed, lagged, total
+        );
+    }
 }
 
 /// API status response
@@ -105,6 +490,52 @@ struct StatusResponse {
     connected: bool,
     phone: Option<String>,
     name: Option<String>,
+    disk: DiskStatus,
+    /// Models in use (None without translation)
+    models: Option<ModelConfig>,
+    /// Online status subscriptions
+    presence: PresenceSummary,
+    /// History sync being imported, if any
+    history_sync: Option<SyncProgress>,
+    /// Addresses the web server is listening on
+    bound_addrs: Vec<SocketAddr>,
+    /// Largest request bodies accepted, in bytes
+    body_limits: BodyLimits,
+    /// Errors in the last hour by subsystem, with the latest of each
+    errors: Vec<ErrorSummary>,
+    /// Hit rate and size of the contact cache
+    contact_cache: ContactCacheStats,
+    /// Provider serving detection and translation (None without translation)
+    translation_provider: Option<TranslationProviderStatus>,
+    /// Why nothing is being translated, with a message to show, if that's
+    /// the case
+    translation_unavailable: Option<TranslationUnavailableStatus>,
+    /// How well the link to WhatsApp is holding up
+    connection_quality: QualityAssessment,
+}
+
+/// Which provider is translating, and whether it's a fallback
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TranslationProviderStatus {
+    provider: ProviderKind,
+    fallback: bool,
+}
+
+/// Why translation is unavailable, with a message for a banner
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct TranslationUnavailableStatus {
+    reason: &'static str,
+    message: &'static str,
+}
+
+impl From<TranslationUnavailable> for TranslationUnavailableStatus {
+    fn from(reason: TranslationUnavailable) -> Self {
+        Self {
+            reason: reason.as_str(),
+            message: reason.description(),
+        }
+    }
 }
 
 /// API QR response
@@ -113,6 +544,14 @@ struct QrResponse {
     qr: Option<String>,
 }
 
+/// Send the same text to several chats
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendBroadcastRequest {
+    pub contact_ids: Vec<String>,
+    pub text: String,
+}
+
 /// Send message request
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -125,6 +564,11 @@ pub struct SendMessageRequest {
     pub reply_to_sender: Option<String>,
     /// Text preview of the replied message (for storage)
     pub reply_to_text: Option<String>,
+    /// Token from a previous language confirmation prompt
+    pub confirmation_token: Option<String>,
+    /// Don't warn when it's likely night for the recipient
+    #[serde(default, alias = "ignore_quiet_hours")]
+    pub ignore_quiet_hours: bool,
 }
 
 /// Send message response
@@ -139,6 +583,27 @@ pub struct SendMessageResponse {
     pub translated_text: Option<String>,
     /// The target language (if translated)
     pub source_language: Option<String>,
+    /// Until when (ms) the send can be undone, if an undo window is set. The
+    /// message is translated when it's dispatched, after this
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub undoable_until: Option<i64>,
+    /// Position of the message in its conversation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort_key: Option<i64>,
+    /// Sent while the recipient is likely asleep, e.g. "recipient local
+    /// time ~03:12"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+    /// The connection's quality class: anything but good means the
+    /// message may be delayed
+    pub connection_quality: QualityClass,
+    /// Key phrases of the message and their translations, if the chat asks
+    /// for them (not part of what was sent)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub learning_notes: Vec<LearningNote>,
+    /// Text standing in for the quoted message, if it's a reply
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_preview: Option<String>,
 }
 
 /// Send image request
@@ -147,7 +612,10 @@ pub struct SendMessageResponse {
 pub struct SendImageRequest {
     pub contact_id: String,
     /// Base64 encoded image data
+    #[serde(default)]
     pub media_data: String,
+    /// A completed chunked upload to send instead of `media_data`
+    pub upload_id: Option<String>,
     pub mime_type: String,
     pub caption: Option<String>,
     /// Message ID to reply to (optional)
@@ -156,12 +624,35 @@ pub struct SendImageRequest {
     pub reply_to_sender: Option<String>,
 }
 
+/// Stored content of an image sent from the web UI, borrowing the media
+#[derive(Serialize)]
+struct SentImageContent<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    mime_type: &'a str,
+    caption: Option<&'a str>,
+    media_data: &'a str,
+    thumbnail_data: Option<&'a str>,
+}
+
+/// Save note request: text, or an image with an optional caption
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SaveNoteRequest {
+    pub text: Option<String>,
+    /// Base64 encoded image data
+    pub media_data: Option<String>,
+    pub mime_type: Option<String>,
+    pub caption: Option<String>,
+}
+
 /// Send image response
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SendImageResponse {
     pub message_id: String,
     pub timestamp: i64,
+    pub connection_quality: QualityClass,
 }
 
 /// Send reaction request
@@ -179,6 +670,20 @@ pub struct SendReactionRequest {
 #[serde(rename_all = "camelCase")]
 pub struct SendReactionResponse {
     pub success: bool,
+    /// The message's reactions, including this one
+    pub reactions: Vec<ReactionGroup>,
+    pub connection_quality: QualityClass,
+}
+
+/// A reaction stored and broadcast before the bridge confirms it
+#[derive(Debug, Clone)]
+pub struct PendingReaction {
+    pub contact_id: String,
+    pub message_id: String,
+    /// Empty when removing my reaction
+    pub emoji: String,
+    /// My reaction before this one, restored if sending fails
+    pub previous: Option<String>,
 }
 
 /// Translate message request
@@ -198,6 +703,9 @@ pub struct TranslateMessageResponse {
     pub translated_text: Option<String>,
     pub source_language: Option<String>,
     pub error: Option<String>,
+    /// What translating the message has cost so far
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub translation_cost_usd: Option<f64>,
 }
 
 /// AI compose request
@@ -213,6 +721,8 @@ pub struct AiComposeRequest {
     pub reply_to_image: Option<String>,
     /// Optional: mime type of the image (e.g., "image/jpeg")
     pub reply_to_image_type: Option<String>,
+    /// Optional: the chat the message is for, to use its translation tone
+    pub contact_id: Option<String>,
 }
 
 /// AI compose response
@@ -233,6 +743,23 @@ pub struct AiReplyRequest {
     pub message_id: String,
 }
 
+/// Reply suggestions request
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SuggestRepliesRequest {
+    pub message_id: String,
+}
+
+/// Reply suggestions response
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SuggestRepliesResponse {
+    pub success: bool,
+    pub suggestions: Vec<String>,
+    pub error: Option<String>,
+    pub cost_usd: Option<f64>,
+}
+
 /// AI styled reply response
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -254,6 +781,27 @@ pub struct AuthCheckResponse {
 #[derive(Deserialize)]
 pub struct AuthRequest {
     pub password: String,
+    /// Display name for the session, recorded on the messages it sends
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// Request to name the current web session
+#[derive(Deserialize)]
+pub struct SessionNameRequest {
+    /// None (or blank) clears the name
+    pub name: Option<String>,
+}
+
+/// Request to set or change the web password
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangePasswordRequest {
+    /// Required once a password is set
+    pub current_password: Option<String>,
+    /// Required to set the first password
+    pub setup_token: Option<String>,
+    pub new_password: String,
 }
 
 /// Auth response
@@ -273,6 +821,76 @@ pub struct UpdateConversationSettingsRequest {
     pub language_override: Option<String>,
     /// Style instruction for translations (plain text, e.g., "formal", "casual")
     pub translation_style: Option<String>,
+    /// Extract vocabulary from incoming messages (left unchanged if omitted)
+    pub learning_mode: Option<bool>,
+    /// No push notifications for this conversation (left unchanged if omitted)
+    pub muted: Option<bool>,
+    /// Whether my messages are translated (left unchanged if omitted)
+    pub outgoing_translation: Option<OutgoingTranslationChoice>,
+    /// Whether incoming messages are triaged (left unchanged if omitted)
+    pub triage: Option<TriageChoice>,
+    /// Register for my messages to this contact (left unchanged if omitted)
+    pub translation_tone: Option<TranslationToneChoice>,
+    /// IANA timezone the contact lives in; empty goes back to inferring it
+    /// (left unchanged if omitted)
+    pub timezone: Option<String>,
+    /// Note key phrases of my translated messages (left unchanged if omitted)
+    pub include_learning_notes: Option<bool>,
+}
+
+/// Translation tone chosen for a contact
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TranslationToneChoice {
+    /// No instruction: the model follows the message as typed
+    Default,
+    Formal,
+    Neutral,
+    Casual,
+    Custom(String),
+}
+
+impl TranslationToneChoice {
+    fn setting(self) -> Option<TranslationTone> {
+        match self {
+            Self::Default => None,
+            Self::Formal => Some(TranslationTone::Formal),
+            Self::Neutral => Some(TranslationTone::Neutral),
+            Self::Casual => Some(TranslationTone::Casual),
+            Self::Custom(text) => Some(TranslationTone::Custom(text)),
+        }
+    }
+}
+
+/// Triage chosen for a contact
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TriageChoice {
+    /// On in every chat but groups
+    Default,
+    On,
+    Off,
+}
+
+impl TriageChoice {
+    fn setting(self) -> Option<bool> {
+        match self {
+            Self::Default => None,
+            Self::On => Some(true),
+            Self::Off => Some(false),
+        }
+    }
+}
+
+/// Outgoing translation chosen for a contact
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutgoingTranslationChoice {
+    /// Off in groups (unless turned on for all groups); in private chats, on
+    /// once the conversation language is clear
+    Default,
+    On,
+    Off,
 }
 
 /// Conversation settings response
@@ -281,6 +899,121 @@ pub struct UpdateConversationSettingsRequest {
 pub struct ConversationSettingsResponse {
     pub language_override: Option<String>,
     pub translation_style: Option<String>,
+    pub learning_mode: bool,
+    pub include_learning_notes: bool,
+    pub muted: bool,
+    /// Triage chosen for the chat (None: the default)
+    pub triage: Option<bool>,
+    /// Whether incoming messages are triaged, by choice or by default
+    pub triage_enabled: bool,
+    /// Register for my translated and composed messages (None: not set)
+    pub translation_tone: Option<TranslationTone>,
+    /// IANA timezone set for the contact (None: inferred, if possible)
+    pub timezone: Option<String>,
+    /// The contact's local time, from the set or inferred timezone
+    #[serde(flatten)]
+    pub recipient_clock: Option<RecipientClock>,
+    pub outgoing_translation: Option<OutgoingTranslation>,
+    /// How consistently recent incoming messages use the conversation language
+    pub language_confidence: Option<LanguageConfidence>,
+    /// Participants always or never translated (groups only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub translation_participants: Option<Vec<TranslationParticipant>>,
+}
+
+/// Defaults for outgoing translation across chats
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutgoingTranslationSettings {
+    /// Translate my messages in groups unless turned off for the group
+    pub translate_groups: bool,
+}
+
+/// How long web sends can be undone
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UndoWindowSettings {
+    /// Seconds a send is held back before going to WhatsApp (0 = off)
+    pub undo_window_secs: u64,
+}
+
+/// A browser's push subscription (`PushSubscription.toJSON()`)
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PushSubscribeRequest {
+    pub endpoint: String,
+    pub keys: PushSubscriptionKeys,
+}
+
+#[derive(Deserialize)]
+pub struct PushSubscriptionKeys {
+    pub p256dh: String,
+    pub auth: String,
+}
+
+/// Push subscription to remove
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PushUnsubscribeRequest {
+    pub endpoint: String,
+}
+
+/// When push notifications are held back
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuietHoursSettings {
+    /// Daily span (server local time, "HH:MM") without notifications, or
+    /// None for none
+    pub quiet_hours: Option<QuietHours>,
+}
+
+/// Create group request
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateGroupRequest {
+    /// Group name
+    pub subject: String,
+    /// Contact IDs or phone numbers
+    pub participants: Vec<String>,
+    /// Two-letter region used for numbers without a country code (e.g. "GB")
+    pub region: Option<String>,
+}
+
+/// New chat request
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewChatRequest {
+    /// Phone number, ideally with country code (e.g. "+44 7911 123456")
+    pub phone: String,
+    /// Two-letter region used for numbers without a country code (e.g. "GB")
+    pub region: Option<String>,
+    /// Check the number is on WhatsApp before creating the contact (default: true)
+    #[serde(default = "default_true")]
+    pub verify: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Link identities request (all fields optional)
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkIdentitiesRequest {
+    /// Only report candidates, don't link anything
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Ambiguous candidates the user has confirmed should be linked
+    #[serde(default)]
+    pub confirm: Vec<IdentityLinkConfirmation>,
+}
+
+/// A manually confirmed identity link
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdentityLinkConfirmation {
+    pub alt_jid: String,
+    pub canonical_id: String,
 }
 
 impl AppState {
@@ -290,14 +1023,55 @@ impl AppState {
         data_dir: PathBuf,
         translator: Option<Arc<TranslationService>>,
         password: Option<String>,
+        auto_suggest_daily_cap: Option<u32>,
+        language_guard: LanguageGuardConfig,
     ) -> Arc<Self> {
         let (broadcast_tx, _) = broadcast::channel(100);
 
+        // `--password` only seeds the first password; after that the saved
+        // hash wins, so a password changed at runtime survives restarts
+        let password_hash = match store.get_web_password_hash() {
+            Ok(Some(hash)) => {
+                if password.is_some() {
+                    info!("Using the saved web password; --password only sets the first one");
+                }
+                Some(hash)
+            }
+            Ok(None) => password.and_then(|password| {
+                store
+                    .set_web_password(&password)
+                    .map_err(|e| error!("Failed to save the web password: {}", e))
+                    .ok()
+            }),
+            Err(e) => {
+                error!("Failed to read the web password: {}", e);
+                password.and_then(|password| crate::password::hash(&password).ok())
+            }
+        };
+        let setup_token = password_hash.is_none().then(generate_token);
+        let sending = OutgoingMessageService::new(store.clone(), translator.clone());
+        // Shared with the translator and push notifier, which record their own
+        let errors = translator
+            .as_ref()
+            .map(|t| t.errors().clone())
+            .unwrap_or_default();
+        let uploads = UploadStore::new(data_dir.join("uploads"));
+        let shutdown = ShutdownController::default();
+        let jobs = JobQueue::new(store.clone(), broadcast_tx.clone(), shutdown.clone());
+        jobs.set_limit(RETRANSLATE_JOB, RETRANSLATE_CONCURRENCY);
+        let broadcasts = Arc::new(Broadcasts::new(
+            store.clone(),
+            sending.clone(),
+            jobs.clone(),
+            broadcast_tx.clone(),
+        ));
+
         Arc::new(Self {
             store,
             connected: RwLock::new(false),
             phone: RwLock::new(None),
             name: RwLock::new(None),
+            own_jids: RwLock::new(Vec::new()),
             qr_code: RwLock::new(None),
             broadcast_tx,
             web_dir,
@@ -305,18 +1079,184 @@ impl AppState {
             command_tx: RwLock::new(None),
             translator,
             avatar_cache: RwLock::new(HashMap::new()),
-            pending_avatar_requests: RwLock::new(HashMap::new()),
+            pending_avatars: PendingRequests::default(),
+            pending_sends: PendingRequests::default(),
+            pending_profiles: PendingRequests::default(),
+            bridge_capabilities: RwLock::new(None),
+            avatar_fetches: RwLock::new(std::collections::HashSet::new()),
+            avatar_fetch_limit: tokio::sync::Semaphore::new(AVATAR_FETCH_CONCURRENCY),
             request_id_counter: AtomicI32::new(1),
-            password,
-            auth_tokens: RwLock::new(std::collections::HashSet::new()),
+            number_checks: Arc::new(PendingNumberChecks::default()),
+            pending_groups: Arc::new(PendingGroups::default()),
+            auto_suggest_daily_cap,
+            language_guard,
+            confirmations: Arc::new(PendingConfirmations::default()),
+            lifecycle: Lifecycle::default(),
+            password_hash: RwLock::new(password_hash),
+            setup_token: RwLock::new(setup_token),
+            serves_https: AtomicBool::new(false),
+            bound_addrs: RwLock::new(Vec::new()),
+            view_once: ViewOnceCache::default(),
+            access_log: AccessLog::default(),
+            maintenance: Maintenance::default(),
+            language_seeding: LanguageSeeding::default(),
+            sending,
+            undo_queue: UndoQueue::default(),
+            client_versions: ClientVersions::default(),
+            uploads,
+            shutdown,
+            jobs,
+            broadcasts,
+            geocoder: Geocoder::default(),
+            push: PushNotifier::default().with_errors(errors.clone()),
+            errors,
+            history_sync: HistorySync::default(),
+            history_batch: HistoryBatch::default(),
+            broadcast_lag: BroadcastLag::default(),
+            connection: ConnectionMonitor::default(),
+            reports: WeeklyReports::default(),
+            presence: PresenceSubscriptions::default(),
+            pending_reactions: RwLock::new(HashMap::new()),
+            oauth_rate_limit: AuthorizeRateLimit::default(),
+            auth_tokens: RwLock::new(HashMap::new()),
         })
     }
 
+    /// Whether the web interface needs a password
+    pub async fn auth_required(&self) -> bool {
+        self.password_hash.read().await.is_some()
+    }
+
+    /// Check a password against the saved hash (false if none is set).
+    /// Argon2 is deliberately slow, so it runs on a blocking thread.
+    pub async fn check_password(&self, password: &str) -> bool {
+        let Some(hash) = self.password_hash.read().await.clone() else {
+            return false;
+        };
+        let password = password.to_string();
+        tokio::task::spawn_blocking(move || crate::password::verify(&password, &hash))
+            .await
+            .unwrap_or(false)
+    }
+
+    /// Start a web session named `name`, returning its token
+    pub(crate) async fn issue_auth_token(&self, name: Option<String>) -> String {
+        let token = generate_token();
+        self.auth_tokens.write().await.insert(token.clone(), name);
+        token
+    }
+
+    /// Display name of the session a request's bearer token belongs to
+    pub(crate) async fn session_name(&self, headers: &HeaderMap) -> Option<String> {
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "))?;
+        self.auth_tokens.read().await.get(token).cloned().flatten()
+    }
+
+    /// Save a new web password and sign out every session. Returns the token
+    /// of a new session for whoever changed it.
+    pub async fn change_password(&self, new_password: &str) -> anyhow::Result<String> {
+        let store = self.store.clone();
+        let new_password = new_password.to_string();
+        let hash =
+            tokio::task::spawn_blocking(move || store.set_web_password(&new_password)).await??;
+        *self.password_hash.write().await = Some(hash);
+        *self.setup_token.write().await = None;
+        self.auth_tokens.write().await.clear();
+        info!("Web password changed; all sessions signed out");
+        Ok(self.issue_auth_token(None).await)
+    }
+
     /// Set the bridge command sender
     pub async fn set_command_tx(&self, tx: mpsc::Sender<BridgeCommand>) {
         *self.command_tx.write().await = Some(tx);
     }
 
+    /// Forget the command sender of a bridge that has stopped
+    pub async fn clear_command_tx(&self) {
+        *self.command_tx.write().await = None;
+    }
+
+    /// Log out of WhatsApp and clear all local data.
+    ///
+    /// Bridge events are dropped from the moment the logout starts, and data is
+    /// only cleared once the restart loop has stopped the bridge, so no new
+    /// bridge or late event can write into the store mid-logout.
+    pub async fn logout(&self) -> Result<(), LogoutError> {
+        if !self.lifecycle.begin_logout().await {
+            return Err(LogoutError::InProgress);
+        }
+        info!("Logout requested - clearing all data");
+
+        let result = self.logout_inner().await;
+        self.lifecycle.finish_logout().await;
+
+        if result.is_ok() {
+            info!("Logout complete - all data cleared");
+        }
+        result
+    }
+
+    async fn logout_inner(&self) -> Result<(), LogoutError> {
+        // 1. Send logout command to bridge (this will notify WhatsApp and clear the session)
+        //    and give it time to exit on its own
+        let command_tx = self.command_tx.read().await.clone();
+        if let Some(tx) = command_tx {
+            if let Err(e) = tx.send(BridgeCommand::Logout).await {
+                warn!("Failed to send logout command to bridge: {}", e);
+            } else {
+                self.lifecycle
+                    .wait_for_bridge_stop(LOGOUT_BRIDGE_GRACE)
+                    .await;
+            }
+        }
+
+        // 2. Make sure the bridge has stopped before touching its session or our data
+        if self.lifecycle.is_bridge_running() {
+            self.lifecycle.request_bridge_stop();
+            if !self
+                .lifecycle
+                .wait_for_bridge_stop(LOGOUT_BRIDGE_STOP_TIMEOUT)
+                .await
+            {
+                warn!("Timed out waiting for bridge to stop during logout");
+            }
+        }
+        self.clear_command_tx().await;
+
+        // 3. Clear the message store (contacts, messages, usage)
+        //    History messages not yet stored go with it
+        self.history_batch.take();
+        if let Err(e) = self.store.clear_all() {
+            error!("Failed to clear message store: {}", e);
+            return Err(LogoutError::ClearFailed(e.to_string()));
+        }
+
+        // 4. Clear the session database file (cleanup after WhatsApp logout)
+        let session_db = self.data_dir.join("session.db");
+        if session_db.exists() {
+            if let Err(e) = std::fs::remove_file(&session_db) {
+                warn!("Failed to remove session database: {}", e);
+            }
+        }
+
+        // 5. Clear auth tokens
+        self.auth_tokens.write().await.clear();
+
+        // 6. Reset connection state
+        *self.connected.write().await = false;
+        *self.phone.write().await = None;
+        *self.name.write().await = None;
+        *self.qr_code.write().await = None;
+
+        // 7. Clear avatar cache
+        self.avatar_cache.write().await.clear();
+
+        Ok(())
+    }
+
     /// Send a command to the bridge
     pub async fn send_bridge_command(&self, cmd: BridgeCommand) -> Result<(), String> {
         let tx = self.command_tx.read().await;
@@ -327,6 +1267,19 @@ impl AppState {
         }
     }
 
+    /// Log an error and keep it for the UI, telling clients straight away
+    /// about the ones that mean messages aren't getting through
+    pub fn report_error(&self, category: ErrorCategory, message: impl std::fmt::Display) {
+        let message = message.to_string();
+        error!("{}", message);
+        let entry = self.errors.record(category, message);
+        if category.is_high_severity() {
+            let _ = self
+                .broadcast_tx
+                .send(WebSocketEvent::ErrorOccurred { error: entry });
+        }
+    }
+
     /// Update connection status
     pub async fn set_connected(
         &self,
@@ -335,6 +1288,7 @@ impl AppState {
         name: Option<String>,
     ) {
         *self.connected.write().await = connected;
+        self.connection.record_connection(connected);
         *self.phone.write().await = phone.clone();
         *self.name.write().await = name.clone();
 
@@ -349,6 +1303,76 @@ impl AppState {
         }
     }
 
+    /// Remember my own JIDs from the Connected event
+    pub async fn set_own_jids(&self, phone: &str, lid: Option<&str>) {
+        let mut jids = vec![format!("{}@s.whatsapp.net", phone)];
+        jids.extend(lid.map(bare_jid));
+        *self.own_jids.write().await = jids;
+    }
+
+    /// Whether the connected bridge understands a command type
+    pub async fn bridge_supports(&self, command: &str) -> bool {
+        self.bridge_capabilities
+            .read()
+            .await
+            .as_ref()
+            .is_some_and(|capabilities| capabilities.iter().any(|c| c == command))
+    }
+
+    /// Change my saved profile, letting WebSocket clients know if it changed
+    pub fn update_own_profile(
+        &self,
+        update: impl FnOnce(&mut OwnProfile),
+    ) -> anyhow::Result<OwnProfile> {
+        match self.store.update_own_profile(update)? {
+            Some(profile) => {
+                let _ = self.broadcast_tx.send(WebSocketEvent::ProfileUpdated {
+                    profile: profile.clone(),
+                });
+                Ok(profile)
+            }
+            None => self.store.get_own_profile(),
+        }
+    }
+
+    /// Ask the bridge for my about text and avatar after connecting
+    pub fn refresh_own_profile(self: &Arc<Self>) {
+        let state = self.clone();
+        tokio::spawn(async move {
+            if state.bridge_supports("get_own_profile").await {
+                // Answered through the OwnProfile event, which saves it
+                let request_id = state.next_request_id();
+                let _ = state
+                    .send_command_and_wait(
+                        &state.pending_profiles,
+                        request_id,
+                        BridgeCommand::GetOwnProfile { request_id },
+                        pending::DEFAULT_COMMAND_TIMEOUT,
+                    )
+                    .await;
+            }
+            state.refresh_own_avatar().await;
+        });
+    }
+
+    /// Fetch my avatar through the profile picture flow, keeping the saved
+    /// one if the fetch fails
+    async fn refresh_own_avatar(&self) {
+        let Ok(OwnProfile { jid: Some(jid), .. }) = self.store.get_own_profile() else {
+            return;
+        };
+        if let Some(url) = self.get_profile_picture(&jid).await {
+            if let Err(e) = self.update_own_profile(|profile| profile.avatar_url = Some(url)) {
+                error!("Failed to save my avatar: {}", e);
+            }
+        }
+    }
+
+    /// Whether any of the mentioned JIDs is me
+    pub async fn mentions_me(&self, mentioned_jids: &[String]) -> bool {
+        mentions_any(&self.own_jids.read().await, mentioned_jids)
+    }
+
     /// Set QR code
     pub async fn set_qr_code(&self, qr: String) {
         *self.qr_code.write().await = Some(qr.clone());
@@ -356,8 +1380,56 @@ impl AppState {
     }
 
     /// Broadcast a new message
-    pub fn broadcast_message(&self, message: StoredMessage) {
-        let _ = self.broadcast_tx.send(WebSocketEvent::Message { message });
+    pub fn broadcast_message(&self, mut message: StoredMessage, suggestions: Option<Vec<String>>) {
+        // Media is fetched through /api/media; inlined it can be megabytes
+        let (content_json, content) = MessageStore::strip_media_from_content(&message.content_json);
+        message.content_json = content_json;
+        message.content = content;
+        let _ = self.broadcast_tx.send(WebSocketEvent::Message {
+            message,
+            suggestions,
+        });
+    }
+
+    /// Generate quick-reply suggestions for a live incoming private message
+    /// when automatic mode is on and today's cap hasn't been reached
+    pub async fn auto_reply_suggestions(&self, message: &StoredMessage) -> Option<Vec<String>> {
+        let cap = self.auto_suggest_daily_cap?;
+        let translator = self.translator.as_ref()?;
+        if message.is_from_me || message.chat_type != "private" || message.original_text.is_none() {
+            return None;
+        }
+
+        let start_of_day = chrono::Utc::now()
+            .date_naive()
+            .and_hms_opt(0, 0, 0)?
+            .and_utc()
+            .timestamp();
+        match self
+            .store
+            .count_usage_messages_since(AUTO_SUGGEST_OPERATION, start_of_day)
+        {
+            Ok(count) if count >= cap => {
+                debug!("Daily reply suggestion cap ({}) reached", cap);
+                return None;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!("Failed to count reply suggestions: {}", e);
+                return None;
+            }
+        }
+
+        match generate_reply_suggestions(&self.store, translator, message, AUTO_SUGGEST_OPERATION)
+            .await
+        {
+            Ok((suggestions, _)) if !suggestions.is_empty() => Some(suggestions),
+            Ok(_) => None,
+            Err(e) => {
+                warn!("Failed to generate reply suggestions: {}", e);
+                None
+            }
+        }
     }
 
     /// Broadcast a typing indicator
@@ -379,25 +1451,445 @@ impl AppState {
     }
 
     /// Broadcast a mark-as-read event (chat was read from another device)
-    pub fn broadcast_mark_as_read(&self, chat_id: String) {
-        let _ = self
-            .broadcast_tx
-            .send(WebSocketEvent::MarkAsRead { chat_id });
+    pub fn broadcast_mark_as_read(&self, chat_id: String, last_read_timestamp: Option<i64>) {
+        let _ = self.broadcast_tx.send(WebSocketEvent::MarkAsRead {
+            chat_id,
+            last_read_timestamp,
+        });
     }
 
-    /// Get next request ID
-    pub fn next_request_id(&self) -> i32 {
-        self.request_id_counter.fetch_add(1, Ordering::SeqCst)
+    /// Look up the address of a stored location message that only has
+    /// coordinates, in the background; failures leave the coordinates as
+    /// they are
+    pub fn resolve_location(self: &Arc<Self>, message: &StoredMessage) {
+        if message.content_type != "Location" {
+            return;
+        }
+        let state = self.clone();
+        let message = message.clone();
+        tokio::spawn(async move {
+            let address = match state.geocoder.address_for(&state.store, &message).await {
+                Ok(Some(address)) => address,
+                Ok(None) => return,
+                Err(e) => {
+                    debug!("Failed to geocode location {}: {}", message.id, e);
+                    return;
+                }
+            };
+            match state.store.set_resolved_address(&message.id, &address) {
+                Ok(true) => {
+                    let _ = state.broadcast_tx.send(WebSocketEvent::LocationResolved {
+                        contact_id: message.contact_id,
+                        message_id: message.id,
+                        address,
+                    });
+                }
+                Ok(false) => {}
+                Err(e) => warn!("Failed to store address of {}: {}", message.id, e),
+            }
+        });
     }
 
-    /// Request a profile picture and wait for the response
-    pub async fn get_profile_picture(&self, jid: &str) -> Option<String> {
+    /// Push a new incoming message to subscribed browsers in the background
+    pub fn push_notification(self: &Arc<Self>, message: &StoredMessage) {
+        let state = self.clone();
+        let message = message.clone();
+        let work = self.shutdown.track("push");
+        tokio::spawn(async move {
+            let _work = work;
+            let now = chrono::Local::now().time();
+            if let Err(e) = state.push.notify(&state.store, &message, now).await {
+                warn!("Failed to push message {}: {:#}", message.id, e);
+                state.errors.record(
+                    ErrorCategory::Webhook,
+                    format!("Failed to push message {}: {:#}", message.id, e),
+                );
+            }
+        });
+    }
+
+    /// A message's current reactions
+    pub fn reactions_for(&self, contact_id: &str, message_id: &str) -> Vec<ReactionGroup> {
+        match self.store.get_reactions(contact_id, &[message_id]) {
+            Ok(mut reactions) => reactions.remove(message_id).unwrap_or_default(),
+            Err(e) => {
+                error!("Failed to get reactions for {}: {}", message_id, e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Store my reaction to a message (empty emoji removes it) and tell every
+    /// client. Returns the emoji it replaced.
+    async fn apply_own_reaction(
+        &self,
+        contact_id: &str,
+        message_id: &str,
+        emoji: &str,
+        reaction_id: &str,
+    ) -> Result<Option<String>, String> {
+        let reaction = if emoji.is_empty() {
+            None
+        } else {
+            let content = serde_json::json!({
+                "type": "reaction",
+                "emoji": emoji,
+                "target_message_id": message_id,
+            });
+            Some(StoredMessage {
+                id: reaction_id.to_string(),
+                contact_id: contact_id.to_string(),
+                timestamp: chrono::Utc::now().timestamp_millis(),
+                is_from_me: true,
+                is_forwarded: false,
+                sender_name: self.name.read().await.clone(),
+                sender_phone: self.phone.read().await.clone(),
+                contact_name: None,
+                contact_phone: None,
+                chat_type: if contact_id.ends_with("@g.us") {
+                    "group".to_string()
+                } else {
+                    "private".to_string()
+                },
+                content_type: "Reaction".to_string(),
+                content_json: content.to_string(),
+                content: Some(content),
+                original_text: None,
+                translated_text: None,
+                source_language: None,
+                is_translated: false,
+                origin: Some("web".to_string()),
+                sent_by: None,
+                mentioned_jids: Vec::new(),
+                mentions_me: false,
+                vocabulary: None,
+                audio: None,
+                sort_key: None,
+                triage: None,
+                translation_status: None,
+            })
+        };
+        let previous = self
+            .store
+            .set_own_reaction(contact_id, message_id, reaction.as_ref())
+            .map_err(|e| e.to_string())?;
+        self.broadcast_reactions(contact_id, message_id, emoji);
+        Ok(previous)
+    }
+
+    /// Broadcast a message's reactions after I added (or removed, with an
+    /// empty emoji) mine
+    fn broadcast_reactions(&self, contact_id: &str, message_id: &str, emoji: &str) {
+        let reactions = self.reactions_for(contact_id, message_id);
+        let event = if emoji.is_empty() {
+            WebSocketEvent::ReactionRemoved {
+                contact_id: contact_id.to_string(),
+                message_id: message_id.to_string(),
+                reactions,
+            }
+        } else {
+            WebSocketEvent::ReactionAdded {
+                contact_id: contact_id.to_string(),
+                message_id: message_id.to_string(),
+                emoji: emoji.to_string(),
+                reactions,
+            }
+        };
+        let _ = self.broadcast_tx.send(event);
+    }
+
+    /// Translate a message that was queued again, storing and announcing
+    /// the outcome
+    async fn retranslate(
+        &self,
+        translator: &TranslationService,
+        settings: &ConversationSettings,
+        message: StoredMessage,
+    ) {
+        let text = message.original_text.clone().or_else(|| {
+            let content = message.content.as_ref()?;
+            content
+                .get("body")
+                .or_else(|| content.get("caption"))
+                .and_then(|v| v.as_str())
+                .map(String::from)
+        });
+        let Some(text) = text else {
+            if let Err(e) = self
+                .store
+                .set_translation_status(&message.id, TranslationStatus::NotNeeded)
+            {
+                warn!("Failed to update translation status: {}", e);
+            }
+            return;
+        };
+
+        let result = translator
+            .process_text(
+                &text,
+                settings.language_override.as_deref(),
+                settings.translation_style.as_deref(),
+                false,
+                false,
+            )
+            .await;
+        if result.usage.input_tokens > 0 {
+            if let Err(e) = self.store.record_usage(
+                Some(&message.contact_id),
+                Some(&message.id),
+                &result.usage,
+                "retranslate",
+            ) {
+                warn!("Failed to record usage: {}", e);
+            }
+        }
+
+        let stored = if result.needs_translation {
+            self.store.update_message_translation(
+                &message.id,
+                result.translated_text.as_deref(),
+                Some(&result.source_language),
+            )
+        } else {
+            self.store
+                .set_translation_status(&message.id, result.status)
+        };
+        if let Err(e) = stored {
+            warn!("Failed to store retranslation of {}: {}", message.id, e);
+            return;
+        }
+
+        let _ = self.broadcast_tx.send(WebSocketEvent::MessageTranslated {
+            contact_id: message.contact_id,
+            message_id: message.id,
+            source_language: result.needs_translation.then_some(result.source_language),
+            translated_text: result.translated_text,
+            translation_status: result.status,
+        });
+    }
+
+    /// Settle a reaction once the bridge reports whether it was sent. If it
+    /// wasn't, my previous reaction is put back and clients are told.
+    /// Returns false if the request wasn't a reaction.
+    pub async fn reconcile_reaction(&self, request_id: i32, success: bool) -> bool {
+        let Some(pending) = self.pending_reactions.write().await.remove(&request_id) else {
+            return false;
+        };
+        if success {
+            return true;
+        }
+
+        warn!(
+            "Reaction {:?} to {} in {} failed to send, reverting",
+            pending.emoji, pending.message_id, pending.contact_id
+        );
+        let restore = pending.previous.unwrap_or_default();
+        let reaction_id = format!("reverted_reaction_{}", request_id);
+        if let Err(e) = self
+            .apply_own_reaction(
+                &pending.contact_id,
+                &pending.message_id,
+                &restore,
+                &reaction_id,
+            )
+            .await
+        {
+            error!("Failed to revert reaction: {}", e);
+        }
+        true
+    }
+
+    /// Broadcast a followed contact's latest presence
+    pub fn broadcast_presence(&self, contact_id: String) {
+        if let Some(presence) = self.presence.get(&contact_id) {
+            let _ = self.broadcast_tx.send(WebSocketEvent::Presence {
+                contact_id,
+                is_online: presence.is_online,
+                last_seen: presence.last_seen,
+            });
+        }
+    }
+
+    /// Follow a contact's online status, subscribing through the bridge if
+    /// they weren't followed already. Groups are ignored.
+    pub async fn watch_presence(&self, contact_id: &str, pinned: bool) {
+        if !presence::is_person_jid(contact_id) {
+            return;
+        }
+        let watch = self.presence.watch(contact_id, pinned);
+        if let Some(evicted) = watch.evicted {
+            debug!(
+                "Presence subscriptions full, no longer following {}",
+                evicted
+            );
+        }
+        if watch.subscribe {
+            self.subscribe_presence(contact_id).await;
+        }
+    }
+
+    async fn subscribe_presence(&self, jid: &str) {
+        let cmd = BridgeCommand::SubscribePresence {
+            jid: jid.to_string(),
+        };
+        if let Err(e) = self.send_bridge_command(cmd).await {
+            debug!("Failed to subscribe to presence of {}: {}", jid, e);
+        }
+    }
+
+    /// Follow pinned contacts and resend every subscription, which the
+    /// bridge loses when it reconnects
+    pub async fn resubscribe_presence(&self) {
+        match self.store.get_contacts() {
+            Ok(contacts) => {
+                for contact in contacts.iter().filter(|c| c.pinned_at.is_some()) {
+                    if presence::is_person_jid(&contact.id) {
+                        self.presence.watch(&contact.id, true);
+                    }
+                }
+            }
+            Err(e) => error!("Failed to load pinned contacts for presence: {}", e),
+        }
+        for jid in self.presence.jids() {
+            self.subscribe_presence(&jid).await;
+        }
+    }
+
+    /// Broadcast a contact whose details changed
+    pub fn broadcast_contact_updated(&self, contact: StoredContact) {
+        let _ = self
+            .broadcast_tx
+            .send(WebSocketEvent::ContactUpdated { contact });
+    }
+
+    /// Record history sync progress and pass it on to WebSocket clients.
+    /// Once the sync completes the contacts it touched are sent, read in
+    /// one query.
+    pub fn history_sync_progress(&self, progress: SyncProgress) {
+        let _ = self.broadcast_tx.send(WebSocketEvent::SyncProgress {
+            chats_done: progress.chats_done,
+            chats_total: progress.chats_total,
+            messages_done: progress.messages_done,
+            complete: progress.complete,
+        });
+        if let Some(deferred) = self.history_sync.update(progress) {
+            self.broadcast_deferred_contacts(deferred);
+        }
+    }
+
+    /// Broadcast how far seeding conversation languages has got
+    pub fn broadcast_language_seeding(&self, progress: &SeedingProgress) {
+        let _ = self.broadcast_tx.send(WebSocketEvent::LanguageSeeding {
+            contacts_done: progress.contacts_done,
+            contacts_total: progress.contacts_total,
+            messages_detected: progress.messages_detected,
+            complete: progress.complete,
+        });
+    }
+
+    /// Stop waiting for a history sync the bridge won't finish, sending
+    /// the contact updates it held back
+    pub fn abandon_history_sync(&self) {
+        self.broadcast_deferred_contacts(self.history_sync.abandon());
+    }
+
+    /// Broadcast contacts whose updates were held back during a history
+    /// sync, or have clients refetch them all if there are too many
+    pub fn broadcast_deferred_contacts(&self, contact_ids: Vec<String>) {
+        if contact_ids.is_empty() {
+            return;
+        }
+        if contact_ids.len() > MAX_DEFERRED_CONTACT_UPDATES {
+            let _ = self.broadcast_tx.send(WebSocketEvent::Resync { missed: 0 });
+            return;
+        }
+        let contact_ids: HashSet<String> = contact_ids.into_iter().collect();
+        match self.store.get_contacts() {
+            Ok(contacts) => {
+                for contact in contacts {
+                    if contact_ids.contains(&contact.id) {
+                        self.broadcast_contact_updated(contact);
+                    }
+                }
+            }
+            Err(e) => error!("Failed to load contacts after history sync: {}", e),
+        }
+    }
+
+    /// Tell clients whenever translation switches provider
+    pub fn spawn_translation_provider_watcher(self: &Arc<Self>) {
+        let Some(translator) = self.translator.clone() else {
+            return;
+        };
+        let state = self.clone();
+        let mut changes = translator.subscribe_provider();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    changed = changes.changed() => {
+                        if changed.is_err() {
+                            break;
+                        }
+                    }
+                    _ = state.shutdown.wait() => break,
+                }
+                let (provider, fallback) = translator.active_provider();
+                let _ = state
+                    .broadcast_tx
+                    .send(WebSocketEvent::TranslationProvider { provider, fallback });
+            }
+        });
+    }
+
+    /// Tell clients whenever translation becomes unavailable, or works again
+    pub fn spawn_translation_availability_watcher(self: &Arc<Self>) {
+        let Some(translator) = self.translator.clone() else {
+            return;
+        };
+        let state = self.clone();
+        let mut changes = translator.subscribe_unavailable();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    changed = changes.changed() => {
+                        if changed.is_err() {
+                            break;
+                        }
+                    }
+                    _ = state.shutdown.wait() => break,
+                }
+                let unavailable = translator.unavailable().map(Into::into);
+                let _ = state
+                    .broadcast_tx
+                    .send(WebSocketEvent::TranslationAvailability { unavailable });
+            }
+        });
+    }
+
+    /// Broadcast the store's disk space and write-protection mode
+    pub fn broadcast_disk_status(&self) {
+        let disk = self.store.disk_status();
+        let _ = self.broadcast_tx.send(WebSocketEvent::DiskSpace {
+            read_only: disk.read_only,
+            free_bytes: disk.free_bytes,
+            min_free_bytes: disk.min_free_bytes,
+        });
+    }
+
+    /// Get next request ID
+    pub fn next_request_id(&self) -> i32 {
+        self.request_id_counter.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Request a profile picture and wait for the response
+    pub async fn get_profile_picture(&self, jid: &str) -> Option<String> {
         // Check cache first (valid for 1 hour)
         let now = chrono::Utc::now().timestamp();
         {
-            let cache = self.avatar_cache.read().await;
-            if let Some(cached) = cache.get(jid) {
-                if now - cached.fetched_at < 3600 {
+            let mut cache = self.avatar_cache.write().await;
+            if let Some(cached) = cache.get_mut(jid) {
+                if now - cached.fetched_at < AVATAR_CACHE_TTL_SECS {
+                    cached.last_used = now;
                     return cached.url.clone();
                 }
             }
@@ -405,31 +1897,21 @@ impl AppState {
 
         // Not in cache or expired, request from bridge
         let request_id = self.next_request_id();
-        let (tx, rx) = oneshot::channel();
-
-        // Register pending request
-        {
-            let mut pending = self.pending_avatar_requests.write().await;
-            pending.insert(request_id, tx);
-        }
-
-        // Send command to bridge
         let cmd = BridgeCommand::GetProfilePicture {
             request_id,
             to: jid.to_string(),
         };
 
-        if let Err(e) = self.send_bridge_command(cmd).await {
-            error!("Failed to request profile picture: {}", e);
-            // Clean up pending request
-            let mut pending = self.pending_avatar_requests.write().await;
-            pending.remove(&request_id);
-            return None;
-        }
-
-        // Wait for response with timeout
-        match tokio::time::timeout(std::time::Duration::from_secs(10), rx).await {
-            Ok(Ok(url)) => {
+        match self
+            .send_command_and_wait(
+                &self.pending_avatars,
+                request_id,
+                cmd,
+                AVATAR_REQUEST_TIMEOUT,
+            )
+            .await
+        {
+            Ok(url) => {
                 // Cache the result
                 let mut cache = self.avatar_cache.write().await;
                 cache.insert(
@@ -437,25 +1919,204 @@ impl AppState {
                     ProfilePicture {
                         url: url.clone(),
                         fetched_at: now,
+                        last_used: now,
                     },
                 );
                 url
             }
-            _ => {
-                // Timeout or error, clean up
-                let mut pending = self.pending_avatar_requests.write().await;
-                pending.remove(&request_id);
+            Err(e) => {
+                debug!("Profile picture request for {} failed: {}", jid, e);
                 None
             }
         }
     }
 
-    /// Handle profile picture response from bridge
-    pub async fn handle_profile_picture_response(&self, request_id: i32, url: Option<String>) {
-        let mut pending = self.pending_avatar_requests.write().await;
-        if let Some(tx) = pending.remove(&request_id) {
-            let _ = tx.send(url);
+    /// Fetch avatars in the background and push each result to WebSocket clients.
+    /// JIDs that already have a fetch queued are skipped.
+    pub async fn queue_avatar_fetches(self: &Arc<Self>, jids: Vec<String>) {
+        let mut in_flight = self.avatar_fetches.write().await;
+        for jid in jids {
+            if !in_flight.insert(jid.clone()) {
+                continue;
+            }
+
+            let state = self.clone();
+            tokio::spawn(async move {
+                let url = match state.avatar_fetch_limit.acquire().await {
+                    Ok(_permit) => state.get_profile_picture(&jid).await,
+                    Err(_) => None,
+                };
+                state.avatar_fetches.write().await.remove(&jid);
+                let _ = state.broadcast_tx.send(WebSocketEvent::Avatar { jid, url });
+            });
+        }
+    }
+
+    /// Send a command carrying `request_id` and wait for the bridge's
+    /// response to it, failing if there's no bridge or it doesn't answer
+    pub async fn send_command_and_wait<T>(
+        &self,
+        pending: &PendingRequests<T>,
+        request_id: i32,
+        cmd: BridgeCommand,
+        timeout: std::time::Duration,
+    ) -> Result<T, CommandError> {
+        let command_tx = self.command_tx.read().await.clone();
+        pending
+            .send_and_wait(command_tx.as_ref(), request_id, cmd, timeout)
+            .await
+    }
+
+    /// Hold a send back until its undo window has passed, then dispatch it
+    /// along with anything else due in its chat
+    pub fn queue_undoable_send(self: &Arc<Self>, send: QueuedSend) {
+        let contact_id = send.message.contact_id.clone();
+        let dispatch_at = send.dispatch_at;
+        self.undo_queue.push(send);
+
+        let state = self.clone();
+        tokio::spawn(async move {
+            // On shutdown every held-back send goes out at once, see `begin_shutdown`
+            tokio::select! {
+                _ = tokio::time::sleep_until(dispatch_at) => {}
+                _ = state.shutdown.wait() => return,
+            }
+            let _work = state.shutdown.track("send");
+            state
+                .dispatch_due_sends(&contact_id, tokio::time::Instant::now())
+                .await;
+        });
+    }
+
+    /// Translate and send a chat's held-back messages due by `now`. A send
+    /// that fails is removed again, as if it was undone.
+    async fn dispatch_due_sends(&self, contact_id: &str, now: tokio::time::Instant) {
+        let _dispatching = self.undo_queue.lock_dispatch().await;
+        let due = self.undo_queue.take_due(contact_id, now);
+        for send in due {
+            let message = send.message;
+            let outgoing = match send.outgoing {
+                Some(outgoing) => outgoing,
+                None => {
+                    let outgoing = self.sending.translate(&message).await;
+                    self.sending
+                        .record_translation(&send.message_id, &message.text, &outgoing);
+                    outgoing
+                }
+            };
+            debug!(
+                "Undo window passed for {}, sending (translated to {:?})",
+                send.message_id, outgoing.target_language
+            );
+
+            let sent = match self.command_tx.read().await.clone() {
+                Some(command_tx) => {
+                    self.sending
+                        .dispatch(&command_tx, &message, &outgoing)
+                        .await
+                }
+                None => Err(anyhow::anyhow!("Bridge not connected")),
+            };
+            match sent {
+                Ok(()) => self.connection.send_started(&send.message_id),
+                Err(e) => {
+                    self.report_error(
+                        ErrorCategory::Send,
+                        format!("Failed to send message {}: {}", send.message_id, e),
+                    );
+                    if let Err(e) = self
+                        .store
+                        .delete_message(&message.contact_id, &send.message_id)
+                    {
+                        error!("Failed to remove unsent message: {}", e);
+                    }
+                    let _ = self.broadcast_tx.send(WebSocketEvent::MessageRemoved {
+                        contact_id: message.contact_id,
+                        message_id: send.message_id,
+                    });
+                    let _ = self.broadcast_tx.send(WebSocketEvent::Error {
+                        error: format!("Failed to send message: {}", e),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Start shutting down: new API requests are refused, background loops
+    /// and WebSocket clients are told to stop, and sends still in their undo
+    /// window go out now instead of being lost
+    pub fn begin_shutdown(self: &Arc<Self>) {
+        if !self.shutdown.begin() {
+            return;
+        }
+        let contact_ids = self.undo_queue.contact_ids();
+        if contact_ids.is_empty() {
+            return;
         }
+        let state = self.clone();
+        let work = self.shutdown.track("send");
+        tokio::spawn(async move {
+            let _work = work;
+            let everything =
+                tokio::time::Instant::now() + std::time::Duration::from_secs(MAX_UNDO_WINDOW_SECS);
+            for contact_id in contact_ids {
+                state.dispatch_due_sends(&contact_id, everything).await;
+            }
+        });
+    }
+
+    /// Periodically drop requests the bridge never answered
+    pub fn spawn_request_sweeper(self: &Arc<Self>) {
+        let state = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(pending::SWEEP_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = state.shutdown.wait() => break,
+                }
+                let swept = state.pending_avatars.sweep()
+                    + state.pending_sends.sweep()
+                    + state.pending_profiles.sweep()
+                    + state.pending_groups.sweep();
+                if swept > 0 {
+                    debug!("Dropped {} expired bridge requests", swept);
+                }
+            }
+        });
+    }
+
+    /// Periodically re-assess the connection, telling clients when its
+    /// quality class changes
+    pub fn spawn_connection_monitor(self: &Arc<Self>) {
+        let state = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(connection_quality::CHECK_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = state.shutdown.wait() => break,
+                }
+                let Some((previous, quality)) = state.connection.check() else {
+                    continue;
+                };
+                let message = format!(
+                    "Connection quality {} -> {} (score {}, {} reconnects in the last hour)",
+                    previous.as_str(),
+                    quality.class.as_str(),
+                    quality.score,
+                    quality.reconnects_last_hour
+                );
+                if quality.class == QualityClass::Good {
+                    info!("{}", message);
+                } else {
+                    warn!("{}", message);
+                }
+                let _ = state
+                    .broadcast_tx
+                    .send(WebSocketEvent::ConnectionQuality { previous, quality });
+            }
+        });
     }
 }
 
@@ -468,8 +2129,41 @@ pub fn create_router(state: Arc<AppState>) -> Router {
 
     // Serve static files from the web directory
     let serve_dir = ServeDir::new(&state.web_dir);
-
-    Router::new()
+    let write_guard = middleware::from_fn_with_state(state.clone(), reject_writes_when_read_only);
+    let shutdown_guard =
+        middleware::from_fn_with_state(state.clone(), reject_requests_when_shutting_down);
+    let version_check =
+        middleware::from_fn_with_state(state.clone(), api_version::check_client_version);
+
+    // What MCP clients may see and do is only managed from a signed-in
+    // session, and the settings that include it only move through one
+    let signed_in = Router::new()
+        .route("/api/mcp/clients", get(list_mcp_clients))
+        .route("/api/mcp/quota", put(update_default_mcp_quota))
+        .route(
+            "/api/mcp/access",
+            get(get_mcp_access).put(update_default_mcp_access),
+        )
+        .route(
+            "/api/mcp/clients/:client_id/access",
+            put(update_mcp_client_access),
+        )
+        .route(
+            "/api/mcp/clients/:client_id/quota",
+            put(update_mcp_client_quota),
+        )
+        .route(
+            "/api/mcp/clients/:client_id/anonymized",
+            put(update_mcp_client_anonymized),
+        )
+        .route("/api/settings/export", get(export_settings))
+        .route("/api/settings/import", post(import_settings))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_web_session,
+        ));
+
+    let router = Router::new()
         // OAuth 2.0 routes for MCP authentication
         .route(
             "/.well-known/oauth-authorization-server",
@@ -487,58 +2181,371 @@ pub fn create_router(state: Arc<AppState>) -> Router {
         // Auth routes (no auth required)
         .route("/api/auth/check", get(auth_check))
         .route("/api/auth", post(auth_login))
+        .route("/api/auth/password", put(change_password))
+        .route("/api/session/name", put(set_session_name))
         .route("/api/logout", post(logout))
+        .route("/readyz", get(readyz))
+        // Script-free pages, signed in with a cookie of their own
+        .route("/lite", get(crate::lite::contacts_page))
+        .route(
+            "/lite/login",
+            get(crate::lite::login_page).post(crate::lite::login),
+        )
+        .route("/lite/chat/:contact_id", get(crate::lite::chat_page))
+        .route("/lite/chat/:contact_id/send", post(crate::lite::send))
         // API routes
         .route("/api/status", get(get_status))
+        .route("/api/version", get(get_version))
+        .route("/api/profile", get(get_profile).put(update_profile))
         .route("/api/contacts", get(get_contacts))
+        .route("/api/contacts/new-chat", post(new_chat))
+        .route("/api/groups", post(create_group_handler))
         .route("/api/contacts/:contact_id/pin", post(toggle_pin))
+        .route("/api/contacts/:contact_id/link", get(get_contact_link))
+        .route(
+            "/api/contacts/:contact_id/first-unread",
+            get(get_first_unread),
+        )
+        .route(
+            "/api/contacts/:contact_id/history",
+            get(get_contact_history),
+        )
+        .route(
+            "/api/contacts/:contact_id/outgoing-translation",
+            post(toggle_outgoing_translation),
+        )
+        .route(
+            "/api/contacts/:contact_id/mentions-only",
+            post(toggle_mentions_only),
+        )
+        .route(
+            "/api/contacts/:contact_id/retranslate-errors",
+            post(retranslate_errors),
+        )
         .route(
             "/api/contacts/:contact_id/settings",
             get(get_conversation_settings).put(update_conversation_settings),
         )
-        .route("/api/messages/:contact_id", get(get_messages))
+        .route(
+            "/api/contacts/:contact_id/translations",
+            get(get_translations),
+        )
+        .route("/api/contacts/:contact_id/vocabulary", get(get_vocabulary))
+        .route(
+            "/api/contacts/:contact_id/reaction-stats",
+            get(get_reaction_stats),
+        )
+        .route(
+            "/api/contacts/:contact_id/draft",
+            get(get_draft).put(save_draft).delete(delete_draft),
+        )
+        .route(
+            "/api/groups/:group_jid/translation-participants",
+            get(get_translation_participants).put(set_translation_participant),
+        )
+        .route(
+            "/api/groups/:group_jid/translation-participants/:participant_jid",
+            delete(delete_translation_participant),
+        )
+        .route(
+            "/api/messages/:contact_id",
+            get(get_messages).delete(clear_conversation),
+        )
+        .route("/api/mentions", get(get_mentions))
+        .route("/api/triage", get(get_triage))
+        .route(
+            "/api/messages/:contact_id/:message_id",
+            delete(delete_message),
+        )
         .route("/api/media/:message_id", get(get_media))
         .route("/api/avatar/:jid", get(get_avatar))
+        .route("/api/avatars", post(get_avatars))
         .route("/api/qr", get(get_qr))
         .route("/api/send", post(send_message))
-        .route("/api/send-image", post(send_image))
+        .route("/api/send-broadcast", post(send_broadcast))
+        .route("/api/broadcasts/:batch_id", get(get_broadcast))
+        // The message ID takes the place of the chat in the route below
+        .route("/api/messages/:contact_id/undo", post(undo_send))
+        .route("/api/messages/:contact_id/search", get(search_chat))
+        // Here too the message ID takes the place of the chat
+        .route(
+            "/api/messages/:contact_id/pin",
+            post(pin_message).delete(unpin_message),
+        )
+        .route(
+            "/api/send-image",
+            post(send_image)
+                .layer(DefaultBodyLimit::max(MEDIA_BODY_LIMIT))
+                .layer(middleware::from_fn_with_state(
+                    MEDIA_BODY_LIMIT,
+                    explain_body_limit,
+                )),
+        )
+        .route("/api/uploads", post(create_upload))
+        .route("/api/uploads/:upload_id", get(get_upload))
+        .route(
+            "/api/uploads/:upload_id/chunks/:index",
+            put(put_upload_chunk).layer(DefaultBodyLimit::max(CHUNK_SIZE)),
+        )
+        .route("/api/uploads/:upload_id/complete", post(complete_upload))
+        .route(
+            "/api/notes",
+            post(save_note)
+                .layer(DefaultBodyLimit::max(MEDIA_BODY_LIMIT))
+                .layer(middleware::from_fn_with_state(
+                    MEDIA_BODY_LIMIT,
+                    explain_body_limit,
+                )),
+        )
         .route("/api/react", post(send_reaction))
-        .route("/api/ai-compose", post(ai_compose))
+        .route(
+            "/api/import/chat-export",
+            post(import_chat_export)
+                .layer(DefaultBodyLimit::max(MEDIA_BODY_LIMIT))
+                .layer(middleware::from_fn_with_state(
+                    MEDIA_BODY_LIMIT,
+                    explain_body_limit,
+                )),
+        )
+        // Can carry the image being replied to
+        .route(
+            "/api/ai-compose",
+            post(ai_compose)
+                .layer(DefaultBodyLimit::max(MEDIA_BODY_LIMIT))
+                .layer(middleware::from_fn_with_state(
+                    MEDIA_BODY_LIMIT,
+                    explain_body_limit,
+                )),
+        )
         .route("/api/ai-reply", post(ai_reply))
+        .route("/api/suggest-replies", post(suggest_replies))
         .route("/api/translate", post(translate_message))
         .route("/api/stats", get(get_stats))
+        .route("/api/stats/translation", get(get_translation_stats))
+        .route("/api/errors", get(get_errors))
+        .route(
+            "/api/unknown-content-report",
+            get(get_unknown_content_report),
+        )
+        .route("/api/reports", get(list_reports))
+        .route("/api/jobs", get(list_jobs))
+        .route("/api/jobs/:job_id", get(get_job).delete(cancel_job))
+        .route("/api/reports/weekly", get(get_weekly_report))
+        .route("/api/export/:contact_id", get(export_chat))
         .route("/api/usage", get(get_global_usage))
+        .route("/api/usage/performance", get(get_usage_performance))
         .route("/api/usage/:contact_id", get(get_conversation_usage))
+        .route(
+            "/api/settings/models",
+            get(get_model_settings).put(update_model_settings),
+        )
+        .route(
+            "/api/settings/outgoing-translation",
+            get(get_outgoing_translation_settings).put(update_outgoing_translation_settings),
+        )
+        .route(
+            "/api/settings/undo-window",
+            get(get_undo_window_settings).put(update_undo_window_settings),
+        )
+        .route(
+            "/api/settings/quiet-hours",
+            get(get_quiet_hours_settings).put(update_quiet_hours_settings),
+        )
+        .route("/api/push/vapid-public-key", get(get_vapid_public_key))
+        .route(
+            "/api/push/subscribe",
+            post(subscribe_push).delete(unsubscribe_push),
+        )
+        .merge(signed_in)
         .route("/api/link-preview", get(get_link_preview))
+        .route("/api/map-thumb", get(get_map_thumbnail))
+        .route("/api/maintenance/link-identities", post(link_identities))
+        .route("/api/maintenance/cleanup", post(run_cleanup))
         // WebSocket
         .route("/ws", get(websocket_handler))
         // MCP (Model Context Protocol) endpoint - HTTP transport
-        .route("/mcp", post(mcp_handler))
+        .route(
+            "/mcp",
+            post(mcp_handler)
+                .layer(DefaultBodyLimit::max(MCP_BODY_LIMIT))
+                .layer(middleware::from_fn_with_state(
+                    MCP_BODY_LIMIT,
+                    explain_body_limit,
+                )),
+        )
         // Serve static files
         .fallback_service(serve_dir)
-        .layer(cors)
-        .with_state(state)
-}
+        // Routes above with their own limit override this one
+        .layer(DefaultBodyLimit::max(TEXT_BODY_LIMIT))
+        .layer(middleware::from_fn_with_state(
+            TEXT_BODY_LIMIT,
+            explain_body_limit,
+        ))
+        .layer(write_guard)
+        .layer(shutdown_guard)
+        .layer(version_check)
+        .layer(cors);
+
+    // Outermost, so the logged latency covers the other layers too
+    let router = if state.access_log.enabled() {
+        router.layer(middleware::from_fn_with_state(
+            state.clone(),
+            access_log::log_requests,
+        ))
+    } else {
+        router
+    };
 
-/// Start the web server
-pub async fn start_server(state: Arc<AppState>, host: &str, port: u16) -> anyhow::Result<()> {
-    let addr: SocketAddr = format!("{}:{}", host, port).parse()?;
-    let router = create_router(state);
+    router.with_state(state)
+}
 
-    info!("Web server running at http://{}", addr);
+/// Parse `--host`: one or more comma-separated addresses such as `0.0.0.0`,
+/// `::` or `[::1]`, each optionally with its own port (`[::1]:8080`)
+pub fn parse_bind_addrs(hosts: &str, port: u16) -> anyhow::Result<Vec<SocketAddr>> {
+    let mut addrs = Vec::new();
+    for host in hosts.split(',').map(str::trim).filter(|h| !h.is_empty()) {
+        let addr = match host.parse::<SocketAddr>() {
+            Ok(addr) => addr,
+            Err(_) => {
+                let ip = host
+                    .strip_prefix('[')
+                    .and_then(|h| h.strip_suffix(']'))
+                    .unwrap_or(host);
+                let ip: IpAddr = ip
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid bind address: {}", host))?;
+                SocketAddr::new(ip, port)
+            }
+        };
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+    if addrs.is_empty() {
+        anyhow::bail!("No bind address given");
+    }
+    Ok(addrs)
+}
 
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, router).await?;
+/// Bind a listening socket. IPv6 sockets are IPv6-only so `::` doesn't
+/// claim the IPv4 port as well and clash with a separate `0.0.0.0`.
+pub fn bind_listener(addr: SocketAddr) -> std::io::Result<std::net::TcpListener> {
+    use socket2::{Domain, Protocol, Socket, Type};
 
-    Ok(())
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(socket.into())
 }
 
-// Auth Handlers
-
-/// Check if authentication is required
+/// Bind every address. Fails if none could be bound, or with `require_all`
+/// if any couldn't; otherwise failures are logged and skipped. With port 0
+/// the remaining addresses reuse the port picked for the first one.
+pub fn bind_all(
+    addrs: &[SocketAddr],
+    require_all: bool,
+) -> anyhow::Result<Vec<std::net::TcpListener>> {
+    let mut listeners: Vec<std::net::TcpListener> = Vec::new();
+    let mut failures = Vec::new();
+    for &addr in addrs {
+        let mut addr = addr;
+        if addr.port() == 0 {
+            if let Some(first) = listeners.first().and_then(|l| l.local_addr().ok()) {
+                addr.set_port(first.port());
+            }
+        }
+        match bind_listener(addr) {
+            Ok(listener) => listeners.push(listener),
+            Err(e) => failures.push(format!("{} ({})", addr, e)),
+        }
+    }
+
+    if listeners.is_empty() || (require_all && !failures.is_empty()) {
+        anyhow::bail!("Failed to bind {}", failures.join(", "));
+    }
+    if !failures.is_empty() {
+        warn!(
+            "Failed to bind {}; serving on the rest",
+            failures.join(", ")
+        );
+    }
+    Ok(listeners)
+}
+
+/// Start the web server on every `--host` address, over HTTPS when a
+/// certificate is configured
+pub async fn start_server(
+    state: Arc<AppState>,
+    hosts: &str,
+    port: u16,
+    require_all_binds: bool,
+    https: Option<HttpsConfig>,
+) -> anyhow::Result<()> {
+    let addrs = parse_bind_addrs(hosts, port)?;
+    let listeners = bind_all(&addrs, require_all_binds)?;
+    let local_addrs = listeners
+        .iter()
+        .map(|l| l.local_addr())
+        .collect::<std::io::Result<Vec<_>>>()?;
+    *state.bound_addrs.write().await = local_addrs.clone();
+
+    let scheme = if https.is_some() { "https" } else { "http" };
+    // Log the bound addresses so port 0 (any free port) can be used
+    for addr in &local_addrs {
+        info!("Web server running at {}://{}", scheme, addr);
+    }
+
+    let Some(https) = https else {
+        let router = create_router(state);
+        let servers = listeners.into_iter().map(|listener| {
+            let router = router.clone();
+            async move {
+                let listener = tokio::net::TcpListener::from_std(listener)?;
+                axum::serve(listener, router).await?;
+                anyhow::Ok(())
+            }
+        });
+        futures::future::try_join_all(servers).await?;
+        return Ok(());
+    };
+
+    state.serves_https.store(true, Ordering::Relaxed);
+    let router = create_router(state);
+
+    if let Some(redirect_port) = https.redirect_http_port {
+        for addr in &local_addrs {
+            let redirect_addr = SocketAddr::new(addr.ip(), redirect_port);
+            let https_port = addr.port();
+            tokio::spawn(async move {
+                if let Err(e) = crate::tls::serve_redirect(redirect_addr, https_port).await {
+                    error!("HTTP redirect server error on {}: {:#}", redirect_addr, e);
+                }
+            });
+        }
+    }
+    https.reload_on_sighup();
+
+    let servers = listeners.into_iter().map(|listener| {
+        axum_server::from_tcp_rustls(listener, https.rustls_config())
+            .serve(router.clone().into_make_service())
+    });
+    futures::future::try_join_all(servers).await?;
+
+    Ok(())
+}
+
+// Auth Handlers
+
+/// Check if authentication is required
 async fn auth_check(State(state): State<Arc<AppState>>) -> Json<AuthCheckResponse> {
     Json(AuthCheckResponse {
-        required: state.password.is_some(),
+        required: state.auth_required().await,
     })
 }
 
@@ -548,33 +2555,21 @@ async fn auth_login(
     Json(req): Json<AuthRequest>,
 ) -> impl IntoResponse {
     // If no password is set, auth is not required
-    let Some(expected_password) = &state.password else {
+    if !state.auth_required().await {
         return Json(AuthResponse {
             success: true,
             token: None,
             error: None,
         })
         .into_response();
-    };
-
-    // Check password
-    if req.password == *expected_password {
-        // Generate a simple token (hash of password + timestamp for uniqueness)
-        use std::collections::hash_map::DefaultHasher;
-        use std::hash::{Hash, Hasher};
-
-        let mut hasher = DefaultHasher::new();
-        expected_password.hash(&mut hasher);
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_nanos()
-            .hash(&mut hasher);
-        let token = format!("{:x}", hasher.finish());
-
-        // Store the token
-        state.auth_tokens.write().await.insert(token.clone());
+    }
 
+    let name = match session_display_name(req.name.as_deref()) {
+        Ok(name) => name,
+        Err(error) => return password_change_error(StatusCode::BAD_REQUEST, error),
+    };
+    if state.check_password(&req.password).await {
+        let token = state.issue_auth_token(name).await;
         info!("User authenticated successfully");
         Json(AuthResponse {
             success: true,
@@ -593,181 +2588,169 @@ async fn auth_login(
     }
 }
 
-/// Verify auth token from request header
-async fn verify_auth(state: &Arc<AppState>, auth_header: Option<&str>) -> bool {
-    // If no password is set, no auth required
-    if state.password.is_none() {
-        return true;
-    }
+/// Longest display name a web session can have
+const MAX_SESSION_NAME_CHARS: usize = 40;
 
-    // Check for valid token in header
-    if let Some(header) = auth_header {
-        if let Some(token) = header.strip_prefix("Bearer ") {
-            return state.auth_tokens.read().await.contains(token);
+/// A session display name as given, trimmed; blank means none
+fn session_display_name(name: Option<&str>) -> Result<Option<String>, &'static str> {
+    match name.map(str::trim).filter(|name| !name.is_empty()) {
+        Some(name) if name.chars().count() > MAX_SESSION_NAME_CHARS => {
+            Err("The session name is too long")
         }
+        name => Ok(name.map(str::to_string)),
     }
-
-    false
 }
 
-/// Logout - clear all data and session
-async fn logout(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    info!("Logout requested - clearing all data");
-
-    // 1. Clear the message store (contacts, messages, usage)
-    if let Err(e) = state.store.clear_all() {
-        error!("Failed to clear message store: {}", e);
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
+/// Name (or rename) the calling web session. Messages it sends from then on
+/// record the name as their sender.
+async fn set_session_name(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(req): Json<SessionNameRequest>,
+) -> impl IntoResponse {
+    let name = match session_display_name(req.name.as_deref()) {
+        Ok(name) => name,
+        Err(error) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({ "success": false, "error": error })),
+            )
+                .into_response()
+        }
+    };
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+    let mut sessions = state.auth_tokens.write().await;
+    match token.and_then(|token| sessions.get_mut(token)) {
+        Some(session) => {
+            info!("Web session named {:?}", name);
+            *session = name.clone();
+            Json(serde_json::json!({ "success": true, "name": name })).into_response()
+        }
+        None => (
+            StatusCode::UNAUTHORIZED,
             Json(serde_json::json!({
                 "success": false,
-                "error": format!("Failed to clear data: {}", e)
+                "error": "Only signed-in sessions can be named"
             })),
         )
-            .into_response();
-    }
-
-    // 2. Send logout command to bridge (this will notify WhatsApp and clear the session)
-    if let Some(tx) = state.command_tx.read().await.as_ref() {
-        if let Err(e) = tx.send(BridgeCommand::Logout).await {
-            warn!("Failed to send logout command to bridge: {}", e);
-        } else {
-            // Give the bridge time to send logout signal to WhatsApp
-            // before we delete the session file
-            tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-        }
-    }
-
-    // 3. Clear the session database file (cleanup after WhatsApp logout)
-    let session_db = state.data_dir.join("session.db");
-    if session_db.exists() {
-        if let Err(e) = std::fs::remove_file(&session_db) {
-            warn!("Failed to remove session database: {}", e);
-        }
+            .into_response(),
     }
-
-    // 4. Clear auth tokens
-    state.auth_tokens.write().await.clear();
-
-    // 5. Reset connection state
-    *state.connected.write().await = false;
-    *state.phone.write().await = None;
-    *state.name.write().await = None;
-    *state.qr_code.write().await = None;
-
-    // 6. Clear avatar cache
-    state.avatar_cache.write().await.clear();
-
-    info!("Logout complete - all data cleared");
-
-    Json(serde_json::json!({
-        "success": true,
-        "message": "Logged out successfully. Please refresh the page."
-    }))
-    .into_response()
 }
 
-// API Handlers
-
-async fn get_status(State(state): State<Arc<AppState>>) -> Json<StatusResponse> {
-    Json(StatusResponse {
-        connected: *state.connected.read().await,
-        phone: state.phone.read().await.clone(),
-        name: state.name.read().await.clone(),
-    })
-}
+/// Shortest web password accepted when setting one
+const MIN_PASSWORD_CHARS: usize = 8;
 
-async fn get_contacts(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    match state.store.get_contacts() {
-        Ok(contacts) => Json(contacts).into_response(),
-        Err(e) => {
-            error!("Failed to get contacts: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to get contacts").into_response()
-        }
-    }
+fn password_change_error(status: StatusCode, error: &str) -> Response {
+    (
+        status,
+        Json(AuthResponse {
+            success: false,
+            token: None,
+            error: Some(error.to_string()),
+        }),
+    )
+        .into_response()
 }
 
-/// Toggle pin status for a contact
-async fn toggle_pin(
+/// Set or change the web password. Changing it needs the current password;
+/// the first one needs the setup token printed at startup. Every existing
+/// session is signed out and a token for a new one is returned.
+async fn change_password(
     State(state): State<Arc<AppState>>,
-    Path(contact_id): Path<String>,
+    Json(req): Json<ChangePasswordRequest>,
 ) -> impl IntoResponse {
-    let contact_id = urlencoding::decode(&contact_id)
-        .map(|s| s.into_owned())
-        .unwrap_or(contact_id);
+    if req.new_password.chars().count() < MIN_PASSWORD_CHARS {
+        return password_change_error(
+            StatusCode::BAD_REQUEST,
+            &format!(
+                "The new password must be at least {} characters",
+                MIN_PASSWORD_CHARS
+            ),
+        );
+    }
 
-    match state.store.toggle_pin(&contact_id) {
-        Ok(is_pinned) => Json(serde_json::json!({
-            "success": true,
-            "pinned": is_pinned
-        }))
-        .into_response(),
-        Err(e) => {
-            error!("Failed to toggle pin: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to toggle pin").into_response()
+    if state.auth_required().await {
+        let current = req.current_password.as_deref().unwrap_or_default();
+        if !state.check_password(current).await {
+            warn!("Rejected web password change: wrong current password");
+            return password_change_error(
+                StatusCode::FORBIDDEN,
+                "The current password is incorrect",
+            );
+        }
+    } else {
+        let setup_token = state.setup_token.read().await.clone();
+        let valid = match (&setup_token, &req.setup_token) {
+            (Some(expected), Some(given)) => constant_time_eq(expected, given),
+            _ => false,
+        };
+        if !valid {
+            warn!("Rejected first web password: invalid setup token");
+            return password_change_error(StatusCode::FORBIDDEN, "Invalid setup token");
         }
     }
-}
-
-/// Get conversation settings for a contact
-async fn get_conversation_settings(
-    State(state): State<Arc<AppState>>,
-    Path(contact_id): Path<String>,
-) -> impl IntoResponse {
-    let contact_id = urlencoding::decode(&contact_id)
-        .map(|s| s.into_owned())
-        .unwrap_or(contact_id);
 
-    match state.store.get_conversation_settings(&contact_id) {
-        Ok(settings) => Json(ConversationSettingsResponse {
-            language_override: settings.language_override,
-            translation_style: settings.translation_style,
+    match state.change_password(&req.new_password).await {
+        Ok(token) => Json(AuthResponse {
+            success: true,
+            token: Some(token),
+            error: None,
         })
         .into_response(),
         Err(e) => {
-            error!("Failed to get conversation settings: {}", e);
-            (
+            error!("Failed to change web password: {}", e);
+            password_change_error(
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to get conversation settings",
+                "Failed to change the password",
             )
-                .into_response()
         }
     }
 }
 
-/// Update conversation settings for a contact
-async fn update_conversation_settings(
-    State(state): State<Arc<AppState>>,
-    Path(contact_id): Path<String>,
-    Json(req): Json<UpdateConversationSettingsRequest>,
-) -> impl IntoResponse {
-    let contact_id = urlencoding::decode(&contact_id)
-        .map(|s| s.into_owned())
-        .unwrap_or(contact_id);
+/// Verify auth token from request header
+async fn verify_auth(state: &Arc<AppState>, auth_header: Option<&str>) -> bool {
+    // If no password is set, no auth required
+    if !state.auth_required().await {
+        return true;
+    }
 
-    // Convert empty strings to None
-    let settings = crate::storage::ConversationSettings {
-        language_override: req.language_override.filter(|s| !s.trim().is_empty()),
-        translation_style: req.translation_style.filter(|s| !s.trim().is_empty()),
-    };
+    // Check for valid token in header
+    if let Some(header) = auth_header {
+        if let Some(token) = header.strip_prefix("Bearer ") {
+            return state.auth_tokens.read().await.contains_key(token);
+        }
+    }
 
-    match state
-        .store
-        .update_conversation_settings(&contact_id, &settings)
-    {
+    false
+}
+
+/// Logout - clear all data and session
+async fn logout(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.logout().await {
         Ok(()) => Json(serde_json::json!({
             "success": true,
-            "languageOverride": settings.language_override,
-            "translationStyle": settings.translation_style
+            "message": "Logged out successfully. Please refresh the page."
         }))
         .into_response(),
         Err(e) => {
-            error!("Failed to update conversation settings: {}", e);
+            let (status, error) = match e {
+                LogoutError::InProgress => (
+                    StatusCode::CONFLICT,
+                    "Logout already in progress".to_string(),
+                ),
+                LogoutError::ClearFailed(e) => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to clear data: {}", e),
+                ),
+            };
             (
-                StatusCode::INTERNAL_SERVER_ERROR,
+                status,
                 Json(serde_json::json!({
                     "success": false,
-                    "error": format!("Failed to update settings: {}", e)
+                    "error": error
                 })),
             )
                 .into_response()
@@ -775,826 +2758,1096 @@ async fn update_conversation_settings(
     }
 }
 
-/// Query parameters for messages pagination
-#[derive(Debug, Deserialize)]
-struct MessagesQuery {
-    /// Maximum number of messages to return (default: 50 for initial load)
-    limit: Option<u32>,
-    /// Only get messages before this timestamp (for loading older messages)
-    before: Option<i64>,
+// API Handlers
+
+async fn get_status(State(state): State<Arc<AppState>>) -> Json<StatusResponse> {
+    Json(StatusResponse {
+        connected: *state.connected.read().await,
+        phone: state.phone.read().await.clone(),
+        name: state.name.read().await.clone(),
+        disk: state.store.disk_status(),
+        models: state.translator.as_ref().map(|t| t.models()),
+        presence: state.presence.summary(),
+        history_sync: state.history_sync.progress(),
+        bound_addrs: state.bound_addrs.read().await.clone(),
+        body_limits: BodyLimits {
+            media: MEDIA_BODY_LIMIT,
+            mcp: MCP_BODY_LIMIT,
+            default: TEXT_BODY_LIMIT,
+        },
+        errors: state.errors.summary(chrono::Utc::now().timestamp_millis()),
+        contact_cache: state.store.contact_cache_stats(),
+        translation_provider: state.translator.as_ref().map(|t| {
+            let (provider, fallback) = t.active_provider();
+            TranslationProviderStatus { provider, fallback }
+        }),
+        translation_unavailable: state
+            .translator
+            .as_ref()
+            .and_then(|t| t.unavailable())
+            .map(Into::into),
+        connection_quality: state.connection.assess(),
+    })
 }
 
-/// Response for paginated messages
-#[derive(Serialize)]
+/// Longest name WhatsApp accepts
+const MAX_PROFILE_NAME_CHARS: usize = 25;
+
+/// Longest "about" text WhatsApp accepts
+const MAX_PROFILE_STATUS_CHARS: usize = 139;
+
+/// Profile update request; fields left out are unchanged
+#[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct MessagesResponse {
-    messages: Vec<StoredMessage>,
-    has_more: bool,
+pub struct UpdateProfileRequest {
+    pub name: Option<String>,
+    /// "About" text (empty to clear it)
+    pub status: Option<String>,
 }
 
-async fn get_messages(
-    State(state): State<Arc<AppState>>,
-    Path(contact_id): Path<String>,
-    Query(params): Query<MessagesQuery>,
-) -> impl IntoResponse {
-    let contact_id = urlencoding::decode(&contact_id)
-        .map(|s| s.into_owned())
-        .unwrap_or(contact_id);
-
-    // Default to 50 messages for initial load, unless explicitly requesting all (limit=0)
-    let limit = match params.limit {
-        Some(0) => None, // 0 means all messages (for backwards compatibility / MCP)
-        Some(n) => Some(n),
-        None => Some(50), // Default to 50 for lazy loading
-    };
+fn profile_error(status: StatusCode, error: &str) -> Response {
+    (status, Json(serde_json::json!({ "error": error }))).into_response()
+}
 
-    // Strip media_data from messages to reduce payload (media loaded on demand via /api/media)
-    match state
-        .store
-        .get_messages_paginated(&contact_id, limit, params.before, true)
-    {
-        Ok(messages) => {
-            // Check if there are more messages (we got a full page)
-            let has_more = limit.map(|l| messages.len() >= l as usize).unwrap_or(false);
-            Json(MessagesResponse { messages, has_more }).into_response()
-        }
+/// Get my own profile, refreshing the avatar while connected
+async fn get_profile(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    if *state.connected.read().await {
+        state.refresh_own_avatar().await;
+    }
+    match state.store.get_own_profile() {
+        Ok(profile) => Json(profile).into_response(),
         Err(e) => {
-            error!("Failed to get messages: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to get messages").into_response()
+            error!("Failed to get profile: {}", e);
+            profile_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get profile")
         }
     }
 }
 
-/// Get media data for a specific message (lazy loaded)
-async fn get_media(
+/// Change my name and/or about text through the bridge
+async fn update_profile(
     State(state): State<Arc<AppState>>,
-    Path(message_id): Path<String>,
+    Json(req): Json<UpdateProfileRequest>,
 ) -> impl IntoResponse {
-    let message_id = urlencoding::decode(&message_id)
-        .map(|s| s.into_owned())
-        .unwrap_or(message_id);
-
-    match state.store.get_message_media(&message_id) {
-        Ok(Some((media_data, mime_type))) => {
-            // Return the base64 media data and mime type
-            Json(serde_json::json!({
-                "media_data": media_data,
-                "mime_type": mime_type
-            }))
-            .into_response()
-        }
-        Ok(None) => (StatusCode::NOT_FOUND, "Media not found").into_response(),
-        Err(e) => {
-            error!("Failed to get media: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to get media").into_response()
-        }
+    let name = req.name.map(|n| n.trim().to_string());
+    let status = req.status.map(|s| s.trim().to_string());
+    if name.is_none() && status.is_none() {
+        return profile_error(StatusCode::BAD_REQUEST, "Nothing to update");
     }
-}
-
-async fn get_qr(State(state): State<Arc<AppState>>) -> Json<QrResponse> {
-    Json(QrResponse {
-        qr: state.qr_code.read().await.clone(),
-    })
-}
-
-/// Avatar response
-#[derive(Serialize)]
-struct AvatarResponse {
-    url: Option<String>,
-}
-
-async fn get_avatar(
-    State(state): State<Arc<AppState>>,
-    Path(jid): Path<String>,
-) -> impl IntoResponse {
-    let jid = urlencoding::decode(&jid)
-        .map(|s| s.into_owned())
-        .unwrap_or(jid);
-
-    // Check if connected
-    if !*state.connected.read().await {
-        return Json(AvatarResponse { url: None }).into_response();
+    if name
+        .as_ref()
+        .is_some_and(|n| n.is_empty() || n.chars().count() > MAX_PROFILE_NAME_CHARS)
+    {
+        return profile_error(
+            StatusCode::BAD_REQUEST,
+            &format!("Name must be 1-{} characters", MAX_PROFILE_NAME_CHARS),
+        );
     }
-
-    let url = state.get_profile_picture(&jid).await;
-    Json(AvatarResponse { url }).into_response()
-}
-
-async fn send_message(
-    State(state): State<Arc<AppState>>,
-    Json(req): Json<SendMessageRequest>,
-) -> impl IntoResponse {
-    // Validate input
-    if req.contact_id.is_empty() || req.text.is_empty() {
-        return (
+    if status
+        .as_ref()
+        .is_some_and(|s| s.chars().count() > MAX_PROFILE_STATUS_CHARS)
+    {
+        return profile_error(
             StatusCode::BAD_REQUEST,
-            Json(serde_json::json!({
-                "error": "contact_id and text are required"
-            })),
-        )
-            .into_response();
+            &format!(
+                "About must be at most {} characters",
+                MAX_PROFILE_STATUS_CHARS
+            ),
+        );
     }
 
-    // Check if connected
     if !*state.connected.read().await {
-        return (
-            StatusCode::SERVICE_UNAVAILABLE,
-            Json(serde_json::json!({
-                "error": "Not connected to WhatsApp"
-            })),
-        )
-            .into_response();
+        return profile_error(StatusCode::SERVICE_UNAVAILABLE, "WhatsApp not connected");
+    }
+    let mut commands = Vec::new();
+    if let Some(name) = name {
+        commands.push(("set_profile_name", name));
+    }
+    if let Some(status) = status {
+        commands.push(("set_profile_status", status));
+    }
+    for (command, _) in &commands {
+        if !state.bridge_supports(command).await {
+            return profile_error(
+                StatusCode::NOT_IMPLEMENTED,
+                "The bridge doesn't support profile updates; rebuild wa-bridge",
+            );
+        }
     }
 
-    // Determine the text to send - translate if needed based on conversation settings or language
-    let (text_to_send, _original_text, was_translated, target_language) =
-        if let Some(translator) = &state.translator {
-            // First check for language override in conversation settings
-            let settings = state
-                .store
-                .get_conversation_settings(&req.contact_id)
-                .unwrap_or_default();
-
-            // Determine target language: settings override > auto-detected > none
-            let target_lang = if let Some(ref lang_override) = settings.language_override {
-                // User explicitly set a language override - ALWAYS use it
-                Some(lang_override.clone())
-            } else {
-                // Fall back to auto-detected conversation language
-                state
-                    .store
-                    .get_conversation_language(&req.contact_id, 10)
-                    .ok()
-                    .flatten()
-            };
-
-            if let Some(conv_lang) = target_lang {
-                info!(
-                    "Target language for {} is {} (override: {})",
-                    req.contact_id,
-                    conv_lang,
-                    settings.language_override.is_some()
-                );
-
-                // If there's a language override, always translate (even English -> other)
-                // Otherwise, use the normal translate_to which skips if already in target
-                let force_translate = settings.language_override.is_some();
-
-                match translator
-                    .translate_outgoing(&req.text, &conv_lang, force_translate)
-                    .await
-                {
-                    Ok((translated, usage)) => {
-                        // Record usage if there was actual API usage
-                        if usage.input_tokens > 0 {
-                            if let Err(e) = state.store.record_usage(
-                                Some(&req.contact_id),
-                                None, // No message ID for outgoing yet
-                                &usage,
-                                "translate_outgoing",
-                            ) {
-                                warn!("Failed to record usage: {}", e);
-                            }
-                        }
-
-                        if translated != req.text {
-                            info!(
-                                "Translated outgoing message to {} (cost: ${:.6})",
-                                conv_lang, usage.cost_usd
-                            );
-                            (translated, Some(req.text.clone()), true, Some(conv_lang))
-                        } else {
-                            (req.text.clone(), None, false, None)
-                        }
-                    }
-                    Err(e) => {
-                        error!("Failed to translate outgoing message: {}", e);
-                        (req.text.clone(), None, false, None)
-                    }
-                }
-            } else {
-                // No target language set or detected
-                (req.text.clone(), None, false, None)
+    for (command, value) in commands {
+        let request_id = state.next_request_id();
+        let cmd = if command == "set_profile_name" {
+            BridgeCommand::SetProfileName {
+                request_id,
+                name: value,
             }
         } else {
-            (req.text.clone(), None, false, None)
+            BridgeCommand::SetProfileStatus {
+                request_id,
+                text: value,
+            }
         };
+        match state
+            .send_command_and_wait(
+                &state.pending_profiles,
+                request_id,
+                cmd,
+                pending::DEFAULT_COMMAND_TIMEOUT,
+            )
+            .await
+        {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                error!("Bridge failed to update profile: {}", e);
+                return profile_error(StatusCode::BAD_GATEWAY, &e);
+            }
+            Err(CommandError::NotConnected) => {
+                return profile_error(StatusCode::SERVICE_UNAVAILABLE, "Bridge not connected");
+            }
+            Err(CommandError::Timeout) => {
+                return profile_error(StatusCode::GATEWAY_TIMEOUT, "Bridge did not respond");
+            }
+        }
+    }
 
-    // Send the message via bridge
-    let cmd = BridgeCommand::Send {
-        request_id: None, // We don't track request IDs for now, response is fire-and-forget
-        to: req.contact_id.clone(),
-        text: text_to_send.clone(),
-        reply_to: req.reply_to.clone(),
-        reply_to_sender: req.reply_to_sender.clone(),
-    };
-
-    if let Err(e) = state.send_bridge_command(cmd).await {
-        error!("Failed to send message: {}", e);
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(serde_json::json!({
-                "error": format!("Failed to send message: {}", e)
-            })),
-        )
-            .into_response();
+    // The bridge's answers have been saved (and broadcast) by now
+    match state.store.get_own_profile() {
+        Ok(profile) => Json(profile).into_response(),
+        Err(e) => {
+            error!("Failed to get profile: {}", e);
+            profile_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to get profile")
+        }
     }
+}
 
-    // Generate a temporary message ID and timestamp for immediate response
-    // The actual message ID will come back via the bridge's send_result event
-    let timestamp = chrono::Utc::now().timestamp_millis();
-    let temp_message_id = format!("pending_{}", timestamp);
-
-    // Store the sent message locally
-    // For outgoing translated messages:
-    // - content.body = what user typed (English) - THIS IS DISPLAYED
-    // - original_text = what user typed (English) - same as content for consistency
-    // - translated_text = what was actually sent (foreign language) - SHOWN IN TOOLTIP
-    // - source_language = the language we translated TO (e.g., "French")
-    // Get contact info for the recipient
-    let contact_info = state.store.get_contact(&req.contact_id).ok().flatten();
-    let contact_name = contact_info.as_ref().and_then(|c| c.name.clone());
-    let contact_phone = contact_info.as_ref().and_then(|c| c.phone.clone());
-    let chat_type = contact_info
-        .as_ref()
-        .and_then(|c| c.contact_type.clone())
-        .unwrap_or_else(|| "private".to_string());
+/// Readiness probe: not ready while the store is read-only
+/// Server, API and bridge protocol versions, and the client versions seen
+async fn get_version(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(api_version::version_info(&state.client_versions))
+}
 
-    let stored_msg = StoredMessage {
-        id: temp_message_id.clone(),
-        contact_id: req.contact_id.clone(),
-        timestamp,
-        is_from_me: true,
-        is_forwarded: false,
-        sender_name: state.name.read().await.clone(),
-        sender_phone: state.phone.read().await.clone(),
-        contact_name,
-        contact_phone,
-        chat_type,
-        content_type: "Text".to_string(),
-        // Store English (what user typed) as the content for display
-        content_json: serde_json::json!({"type": "text", "body": req.text.clone()}).to_string(),
-        content: Some(serde_json::json!({"type": "text", "body": req.text.clone()})),
-        original_text: if was_translated {
-            Some(req.text.clone())
-        } else {
-            None
-        },
-        // Store the translated text (what was actually sent) for the tooltip
-        translated_text: if was_translated {
-            Some(text_to_send.clone())
-        } else {
-            None
-        },
-        source_language: target_language.clone(), // The language we translated TO
-        is_translated: was_translated,
+async fn readyz(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let disk = state.store.disk_status();
+    let status = if disk.read_only {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
     };
+    (
+        status,
+        Json(serde_json::json!({
+            "ready": !disk.read_only,
+            "disk": disk,
+        })),
+    )
+}
 
-    // Store the message (don't broadcast - frontend already displays it optimistically)
-    if let Err(e) = state.store.add_message(&stored_msg) {
-        error!("Failed to store sent message: {}", e);
-    }
-
-    // Update contact's last message time (preserve contact name/phone)
-    if let Err(e) = state.store.upsert_contact(
-        &stored_msg.contact_id,
-        stored_msg.contact_name.as_deref(),
-        stored_msg.contact_phone.as_deref(),
-        Some(&stored_msg.chat_type),
-        stored_msg.timestamp,
-    ) {
-        error!("Failed to update contact: {}", e);
-    }
+/// Largest request body for routes taking media (base64, so about 22MB of
+/// image), for MCP requests, and for everything else
+pub const MEDIA_BODY_LIMIT: usize = 30 * 1024 * 1024;
+pub const MCP_BODY_LIMIT: usize = 1024 * 1024;
+pub const TEXT_BODY_LIMIT: usize = 64 * 1024;
 
-    // Note: We don't broadcast sent messages - the frontend displays them immediately.
-    // The message is stored in the DB so it will appear when the conversation is reloaded.
+/// Request body limits, as reported by `/api/status`
+#[derive(Serialize)]
+struct BodyLimits {
+    media: usize,
+    mcp: usize,
+    default: usize,
+}
 
-    Json(SendMessageResponse {
-        message_id: temp_message_id,
-        timestamp,
-        is_translated: was_translated,
-        translated_text: if was_translated {
-            Some(text_to_send)
-        } else {
-            None
-        },
-        source_language: target_language,
-    })
-    .into_response()
+/// Replace the plain-text 413 of a body over its route's limit with a JSON
+/// error giving the limit
+async fn explain_body_limit(State(limit): State<usize>, request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|t| t.as_bytes().starts_with(b"application/json"));
+    if response.status() != StatusCode::PAYLOAD_TOO_LARGE || is_json {
+        return response;
+    }
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        Json(serde_json::json!({
+            "error": format!("Request body is larger than the {} byte limit", limit),
+            "limitBytes": limit,
+        })),
+    )
+        .into_response()
 }
 
-async fn send_image(
+/// POST endpoints that stay available while the store is read-only
+const READ_ONLY_ALLOWED_PATHS: &[&str] = &[
+    "/api/auth",
+    "/api/session/name",
+    "/api/logout",
+    "/api/avatars",
+];
+
+/// Refuse API requests that would write to the store while disk space is low
+async fn reject_writes_when_read_only(
     State(state): State<Arc<AppState>>,
-    Json(req): Json<SendImageRequest>,
-) -> impl IntoResponse {
-    // Validate input
-    if req.contact_id.is_empty() || req.media_data.is_empty() {
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    let mutating = !matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) && path.starts_with("/api/")
+        && !READ_ONLY_ALLOWED_PATHS.contains(&path);
+
+    if mutating && state.store.is_read_only() {
         return (
-            StatusCode::BAD_REQUEST,
+            StatusCode::INSUFFICIENT_STORAGE,
             Json(serde_json::json!({
-                "error": "contact_id and media_data are required"
+                "error": "Disk space is low, so changes are disabled until space is freed",
+                "readOnly": true,
             })),
         )
             .into_response();
     }
 
-    // Check if connected
-    if !*state.connected.read().await {
+    next.run(request).await
+}
+
+/// Refuse requests without a signed-in web session (any request, until a
+/// password is set)
+async fn require_web_session(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let auth_header = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok());
+    if !verify_auth(&state, auth_header).await {
         return (
-            StatusCode::SERVICE_UNAVAILABLE,
+            StatusCode::UNAUTHORIZED,
             Json(serde_json::json!({
-                "error": "Not connected to WhatsApp"
+                "success": false,
+                "error": "Sign in to change these settings"
             })),
         )
             .into_response();
     }
 
-    // Send the image via bridge
-    let cmd = BridgeCommand::SendImage {
-        request_id: None,
-        to: req.contact_id.clone(),
-        media_data: req.media_data.clone(),
-        mime_type: req.mime_type.clone(),
-        caption: req.caption.clone(),
-        reply_to: req.reply_to.clone(),
-        reply_to_sender: req.reply_to_sender.clone(),
-    };
+    next.run(request).await
+}
 
-    if let Err(e) = state.send_bridge_command(cmd).await {
-        error!("Failed to send image: {}", e);
+/// Refuse new API requests once shutdown has begun, and count the others
+/// as in flight so shutdown waits for them
+async fn reject_requests_when_shutting_down(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    if !path.starts_with("/api/") && path != "/mcp" {
+        return next.run(request).await;
+    }
+    if state.shutdown.is_shutting_down() {
         return (
-            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::SERVICE_UNAVAILABLE,
             Json(serde_json::json!({
-                "error": format!("Failed to send image: {}", e)
+                "error": "The server is shutting down",
+                "shuttingDown": true,
             })),
         )
             .into_response();
     }
 
-    // Generate a temporary message ID and timestamp for immediate response
-    let timestamp = chrono::Utc::now().timestamp_millis();
-    let temp_message_id = format!("pending_img_{}", timestamp);
+    let _work = state.shutdown.track("request");
+    next.run(request).await
+}
 
-    // Get contact info for the recipient
-    let contact_info = state.store.get_contact(&req.contact_id).ok().flatten();
-    let contact_name = contact_info.as_ref().and_then(|c| c.name.clone());
-    let contact_phone = contact_info.as_ref().and_then(|c| c.phone.clone());
-    let chat_type = contact_info
-        .as_ref()
-        .and_then(|c| c.contact_type.clone())
-        .unwrap_or_else(|| "private".to_string());
+/// A contact with its live online status, if followed, and the chat's most
+/// used reaction
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ContactListEntry {
+    #[serde(flatten)]
+    contact: StoredContact,
+    presence: Option<Presence>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_reaction: Option<String>,
+}
 
-    // Store the sent image message locally
-    let stored_msg = crate::storage::StoredMessage {
-        id: temp_message_id.clone(),
-        contact_id: req.contact_id.clone(),
-        timestamp,
-        is_from_me: true,
-        is_forwarded: false,
-        sender_name: state.name.read().await.clone(),
-        sender_phone: state.phone.read().await.clone(),
-        contact_name,
-        contact_phone,
-        chat_type,
-        content_type: "Image".to_string(),
-        content_json: serde_json::json!({
-            "type": "image",
-            "mime_type": req.mime_type,
-            "caption": req.caption,
-            "media_data": req.media_data
-        })
-        .to_string(),
-        content: Some(serde_json::json!({
-            "type": "image",
-            "mime_type": req.mime_type,
-            "caption": req.caption,
-            "media_data": req.media_data
-        })),
-        original_text: None,
-        translated_text: None,
-        source_language: None,
-        is_translated: false,
-    };
+/// Query parameters for paging through the contact list
+#[derive(Debug, Deserialize)]
+struct ContactsQuery {
+    /// Where the previous page stopped (its `nextCursor`)
+    cursor: Option<String>,
+    /// Maximum number of contacts to return (default: 100)
+    limit: Option<u32>,
+    /// Only contacts whose names match, ignoring case, accents and emoji
+    /// (best matches first, not paged)
+    search: Option<String>,
+}
 
-    // Store the message
-    if let Err(e) = state.store.add_message(&stored_msg) {
-        error!("Failed to store sent image: {}", e);
-    }
+/// Maximum number of contacts returned in a page
+const MAX_CONTACTS_LIMIT: u32 = 500;
 
-    // Update contact's last message time (preserve contact name/phone)
-    if let Err(e) = state.store.upsert_contact(
-        &stored_msg.contact_id,
-        stored_msg.contact_name.as_deref(),
-        stored_msg.contact_phone.as_deref(),
-        Some(&stored_msg.chat_type),
-        stored_msg.timestamp,
-    ) {
-        error!("Failed to update contact: {}", e);
-    }
+/// A page of the contact list. Pages follow the list's order (the self chat,
+/// pinned chats by when they were pinned, then the most recently active), so
+/// walking the cursors visits every contact once while the list is unchanged.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ContactsPage {
+    contacts: Vec<ContactListEntry>,
+    has_more: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+}
 
-    Json(SendImageResponse {
-        message_id: temp_message_id,
-        timestamp,
-    })
-    .into_response()
+/// How far back reaction stats look by default
+const REACTION_STATS_DEFAULT_DAYS: i64 = 90;
+
+/// Start of the default reaction stats window (ms)
+fn default_reaction_stats_since() -> i64 {
+    chrono::Utc::now().timestamp_millis() - REACTION_STATS_DEFAULT_DAYS * 24 * 60 * 60 * 1000
 }
 
-async fn send_reaction(
+async fn get_contacts(
     State(state): State<Arc<AppState>>,
-    Json(req): Json<SendReactionRequest>,
+    Query(params): Query<ContactsQuery>,
 ) -> impl IntoResponse {
-    // Validate input
-    if req.contact_id.is_empty() || req.message_id.is_empty() {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(serde_json::json!({
-                "error": "contact_id and message_id are required"
-            })),
-        )
-            .into_response();
+    let cursor = match params.cursor.as_deref() {
+        None => None,
+        Some(token) => match ContactCursor::decode(token) {
+            Some(cursor) => Some(cursor),
+            None => return invalid_cursor(),
+        },
+    };
+    let search = params
+        .search
+        .as_deref()
+        .map(str::trim)
+        .filter(|s| !s.is_empty());
+    // Without paging parameters the whole list comes back, as it always has
+    let paged = search.is_none() && (params.cursor.is_some() || params.limit.is_some());
+    let page = if let Some(search) = search {
+        let limit = params
+            .limit
+            .unwrap_or(MAX_CONTACTS_LIMIT)
+            .clamp(1, MAX_CONTACTS_LIMIT);
+        state
+            .store
+            .search_contacts(search, limit as usize)
+            .map(|contacts| (contacts, None))
+    } else if paged {
+        let limit = params.limit.unwrap_or(100).clamp(1, MAX_CONTACTS_LIMIT);
+        state
+            .store
+            .get_contacts_page(limit, cursor)
+            .map(|(contacts, next)| (contacts, next.map(|c| c.encode())))
+    } else {
+        state.store.get_contacts().map(|contacts| (contacts, None))
+    };
+
+    match page {
+        Ok((contacts, next_cursor)) => {
+            let mut top_reactions = state
+                .store
+                .get_top_reactions(default_reaction_stats_since())
+                .unwrap_or_else(|e| {
+                    error!("Failed to get top reactions: {}", e);
+                    HashMap::new()
+                });
+            let contacts: Vec<ContactListEntry> = contacts
+                .into_iter()
+                .map(|contact| ContactListEntry {
+                    presence: state.presence.get(&contact.id),
+                    top_reaction: top_reactions.remove(&contact.id),
+                    contact,
+                })
+                .collect();
+            if paged {
+                Json(ContactsPage {
+                    contacts,
+                    has_more: next_cursor.is_some(),
+                    next_cursor,
+                })
+                .into_response()
+            } else {
+                Json(contacts).into_response()
+            }
+        }
+        Err(e) => {
+            error!("Failed to get contacts: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to get contacts").into_response()
+        }
     }
+}
 
-    // Check if connected
-    if !*state.connected.read().await {
-        return (
-            StatusCode::SERVICE_UNAVAILABLE,
-            Json(serde_json::json!({
-                "error": "Not connected to WhatsApp"
-            })),
-        )
-            .into_response();
+/// Query parameters for reaction stats
+#[derive(Debug, Deserialize)]
+struct ReactionStatsQuery {
+    /// Only count reactions from this timestamp on (default: 90 days ago)
+    since: Option<i64>,
+}
+
+/// Get reaction counts per emoji, top reactors and my most used reactions
+async fn get_reaction_stats(
+    State(state): State<Arc<AppState>>,
+    Path(contact_id): Path<String>,
+    Query(params): Query<ReactionStatsQuery>,
+) -> impl IntoResponse {
+    let contact_id = urlencoding::decode(&contact_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(contact_id);
+    let since = params.since.unwrap_or_else(default_reaction_stats_since);
+
+    match state.store.get_reaction_stats(&contact_id, since) {
+        Ok(stats) => Json(stats).into_response(),
+        Err(e) => {
+            error!("Failed to get reaction stats: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error": "Failed to get reaction stats"})),
+            )
+                .into_response()
+        }
     }
+}
 
-    // Send the reaction via bridge
-    let cmd = BridgeCommand::SendReaction {
-        request_id: None,
-        to: req.contact_id.clone(),
-        message_id: req.message_id.clone(),
-        sender_jid: req.sender_jid.clone(),
-        emoji: req.emoji.clone(),
-    };
+/// Toggle pin status for a contact
+async fn toggle_pin(
+    State(state): State<Arc<AppState>>,
+    Path(contact_id): Path<String>,
+) -> impl IntoResponse {
+    let contact_id = urlencoding::decode(&contact_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(contact_id);
 
-    if let Err(e) = state.send_bridge_command(cmd).await {
-        error!("Failed to send reaction: {}", e);
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
+    match state.store.toggle_pin(&contact_id) {
+        Ok(is_pinned) => {
+            if is_pinned {
+                state.watch_presence(&contact_id, true).await;
+            } else {
+                state.presence.unpin(&contact_id);
+            }
             Json(serde_json::json!({
-                "error": format!("Failed to send reaction: {}", e)
-            })),
-        )
-            .into_response();
+                "success": true,
+                "pinned": is_pinned
+            }))
+            .into_response()
+        }
+        Err(e) => {
+            error!("Failed to toggle pin: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to toggle pin").into_response()
+        }
     }
-
-    Json(SendReactionResponse { success: true }).into_response()
 }
 
-/// Translate a message manually
-async fn translate_message(
+/// Toggle whether outgoing messages to a contact are translated
+async fn toggle_outgoing_translation(
     State(state): State<Arc<AppState>>,
-    Json(req): Json<TranslateMessageRequest>,
+    Path(contact_id): Path<String>,
 ) -> impl IntoResponse {
-    // Check if translation service is available
-    let translator = match &state.translator {
-        Some(t) => t,
-        None => {
-            return Json(TranslateMessageResponse {
-                success: false,
-                translated_text: None,
-                source_language: None,
-                error: Some("Translation service not configured".to_string()),
-            })
-            .into_response();
+    let contact_id = urlencoding::decode(&contact_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(contact_id);
+
+    match state.store.toggle_outgoing_translation(&contact_id) {
+        Ok(enabled) => Json(serde_json::json!({
+            "success": true,
+            "autoTranslateOutgoing": enabled
+        }))
+        .into_response(),
+        Err(e) => {
+            error!("Failed to toggle outgoing translation: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to toggle outgoing translation",
+            )
+                .into_response()
         }
-    };
+    }
+}
 
-    // Get conversation settings for this contact
-    let settings = state
-        .store
-        .get_conversation_settings(&req.contact_id)
-        .unwrap_or_default();
+const RETRANSLATE_JOB: &str = "retranslate";
 
-    // Call the translation service with conversation settings
-    let result = translator
-        .process_text(
-            &req.text,
-            settings.language_override.as_deref(),
-            settings.translation_style.as_deref(),
-        )
-        .await;
+/// Chats whose messages are translated again at once
+const RETRANSLATE_CONCURRENCY: usize = 2;
 
-    // Record usage if there was API usage
-    if result.usage.input_tokens > 0 {
-        if let Err(e) = state.store.record_usage(
-            Some(&req.contact_id),
-            Some(&req.message_id),
-            &result.usage,
-            "manual_translate",
-        ) {
-            warn!("Failed to record translation usage: {}", e);
-        }
+/// Translates a chat's queued messages again, one at a time
+struct RetranslateJob {
+    state: Arc<AppState>,
+    translator: Arc<TranslationService>,
+    contact_id: String,
+    messages: Vec<StoredMessage>,
+}
+
+#[async_trait::async_trait]
+impl Job for RetranslateJob {
+    fn kind(&self) -> &'static str {
+        RETRANSLATE_JOB
     }
 
-    // Update the message in the database with the translation
-    if result.needs_translation {
-        if let Err(e) = state.store.update_message_translation(
-            &req.message_id,
-            result.translated_text.as_deref(),
-            Some(&result.source_language),
-        ) {
-            warn!("Failed to update message translation in DB: {}", e);
+    async fn run(self: Box<Self>, ctx: JobContext) -> anyhow::Result<String> {
+        let settings = self
+            .state
+            .store
+            .get_conversation_settings(&self.contact_id)
+            .unwrap_or_default();
+        let total = self.messages.len() as u64;
+        for (done, message) in self.messages.into_iter().enumerate() {
+            self.state
+                .retranslate(&self.translator, &settings, message)
+                .await;
+            ctx.progress(JobProgress {
+                done: done as u64 + 1,
+                total: Some(total),
+                detail: None,
+            });
         }
+        Ok(format!(
+            "Translated {} messages in {} again",
+            total, self.contact_id
+        ))
     }
-
-    Json(TranslateMessageResponse {
-        success: true,
-        translated_text: result.translated_text,
-        source_language: Some(result.source_language),
-        error: None,
-    })
-    .into_response()
 }
 
-/// AI compose endpoint - generates a message using Claude
-async fn ai_compose(
+/// Translate a chat's incoming messages again where translating failed or
+/// never happened. They're marked pending and translated by a background
+/// job, each one announced as it's done.
+async fn retranslate_errors(
     State(state): State<Arc<AppState>>,
-    Json(req): Json<AiComposeRequest>,
+    Path(contact_id): Path<String>,
 ) -> impl IntoResponse {
-    // Check if translation service is available (it has the API key)
-    let translator = match &state.translator {
-        Some(t) => t,
-        None => {
-            return Json(AiComposeResponse {
-                success: false,
-                message: None,
-                error: Some("AI service not configured (missing API key)".to_string()),
-                cost_usd: None,
-            })
+    let contact_id = urlencoding::decode(&contact_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(contact_id);
+    let Some(translator) = state.translator.clone() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "error": "Translation service not configured" })),
+        )
             .into_response();
-        }
     };
 
-    // Build reply context if provided
-    let reply_context = match (&req.reply_to_sender, &req.reply_to_text) {
-        (Some(sender), Some(text)) => Some((sender.as_str(), text.as_str())),
-        (None, Some(text)) => Some(("Someone", text.as_str())),
-        _ => None,
+    let messages = match state.store.requeue_untranslated(&contact_id) {
+        Ok(messages) => messages,
+        Err(e) => {
+            error!("Failed to queue messages for translation: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to queue messages" })),
+            )
+                .into_response();
+        }
     };
+    let queued = messages.len();
+    info!("Retranslating {} messages in {}", queued, contact_id);
 
-    // Build image context if provided
-    let reply_image = match (&req.reply_to_image_type, &req.reply_to_image) {
-        (Some(mime_type), Some(data)) => Some((mime_type.as_str(), data.as_str())),
-        _ => None,
-    };
+    let job_id = (queued > 0).then(|| {
+        state.jobs.submit(RetranslateJob {
+            state: state.clone(),
+            translator,
+            contact_id,
+            messages,
+        })
+    });
 
-    // Call the AI compose method (using Opus 4.5)
-    match translator
-        .compose_ai_message(&req.prompt, reply_context, reply_image)
-        .await
-    {
-        Ok((message, usage)) => {
-            info!(
-                "AI composed message ({} chars), cost: ${:.6}",
-                message.len(),
-                usage.cost_usd
-            );
+    (
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({ "success": true, "queued": queued, "jobId": job_id })),
+    )
+        .into_response()
+}
 
-            // Record usage
-            if let Err(e) = state.store.record_usage(
-                None,
-                None,
-                &crate::translation::UsageInfo {
-                    input_tokens: usage.input_tokens,
-                    output_tokens: usage.output_tokens,
-                    cost_usd: usage.cost_usd,
-                },
-                "ai_compose",
-            ) {
-                warn!("Failed to record AI compose usage: {}", e);
-            }
+/// Toggle whether only messages that mention me count as unread for a group
+async fn toggle_mentions_only(
+    State(state): State<Arc<AppState>>,
+    Path(contact_id): Path<String>,
+) -> impl IntoResponse {
+    let contact_id = urlencoding::decode(&contact_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(contact_id);
 
-            Json(AiComposeResponse {
-                success: true,
-                message: Some(message),
-                error: None,
-                cost_usd: Some(usage.cost_usd),
-            })
-            .into_response()
-        }
+    match state.store.toggle_mentions_only(&contact_id) {
+        Ok(enabled) => Json(serde_json::json!({
+            "success": true,
+            "mentionsOnly": enabled
+        }))
+        .into_response(),
         Err(e) => {
-            error!("AI compose failed: {}", e);
-            Json(AiComposeResponse {
-                success: false,
-                message: None,
-                error: Some(format!("Failed to compose message: {}", e)),
-                cost_usd: None,
-            })
-            .into_response()
+            error!("Failed to toggle mentions only: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to toggle mentions only",
+            )
+                .into_response()
         }
     }
 }
 
-/// AI styled reply endpoint - generates a reply that sounds like the user
-async fn ai_reply(
-    State(state): State<Arc<AppState>>,
-    Json(req): Json<AiReplyRequest>,
-) -> impl IntoResponse {
-    // Check if translation service is available (it has the API key)
-    let translator = match &state.translator {
-        Some(t) => t,
-        None => {
-            return Json(AiReplyResponse {
-                success: false,
-                reply_text: None,
-                error: Some("AI service not configured (missing API key)".to_string()),
-                cost_usd: None,
-            })
-            .into_response();
+/// Query parameters for contact link generation
+#[derive(Deserialize)]
+struct ContactLinkQuery {
+    /// Optional text to prefill in the chat
+    text: Option<String>,
+    /// Translate the text into the conversation language first
+    #[serde(default)]
+    translate: bool,
+}
+
+/// Click-to-chat links for a phone number
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ChatLinks {
+    wa_me_url: String,
+    whatsapp_url: String,
+}
+
+/// Build the wa.me and whatsapp:// links for a phone number (digits only)
+fn build_chat_links(phone: &str, text: Option<&str>) -> ChatLinks {
+    match text.filter(|t| !t.is_empty()) {
+        Some(text) => {
+            let encoded = urlencoding::encode(text);
+            ChatLinks {
+                wa_me_url: format!("https://wa.me/{}?text={}", phone, encoded),
+                whatsapp_url: format!("whatsapp://send?phone={}&text={}", phone, encoded),
+            }
         }
-    };
+        None => ChatLinks {
+            wa_me_url: format!("https://wa.me/{}", phone),
+            whatsapp_url: format!("whatsapp://send?phone={}", phone),
+        },
+    }
+}
 
-    // Get the message being replied to
-    let message = match state.store.get_message_by_id(&req.message_id) {
-        Ok(Some(m)) => m,
+/// The international phone number (digits only) for a private contact, if known
+fn contact_link_phone(contact: &crate::storage::StoredContact) -> Option<String> {
+    let phone = contact
+        .phone
+        .clone()
+        .or_else(|| {
+            contact
+                .id
+                .strip_suffix("@s.whatsapp.net")
+                .map(|p| p.to_string())
+        })?
+        .trim_start_matches('+')
+        .to_string();
+
+    let valid = (7..=15).contains(&phone.len()) && phone.chars().all(|c| c.is_ascii_digit());
+    valid.then_some(phone)
+}
+
+/// Generate wa.me and whatsapp:// click-to-chat links for a contact,
+/// optionally with prefilled (and translated) text
+/// Where a chat's unread messages start
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FirstUnreadResponse {
+    unread_count: i32,
+    last_read_timestamp: Option<i64>,
+    /// None when the chat has no unread messages
+    first_unread: Option<FirstUnread>,
+}
+
+/// The first unread message of a chat, for jumping to it
+async fn get_first_unread(
+    State(state): State<Arc<AppState>>,
+    Path(contact_id): Path<String>,
+) -> impl IntoResponse {
+    let contact_id = urlencoding::decode(&contact_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(contact_id);
+
+    let contact = match state.store.get_contact(&contact_id) {
+        Ok(Some(c)) => c,
         Ok(None) => {
-            return Json(AiReplyResponse {
-                success: false,
-                reply_text: None,
-                error: Some("Message not found".to_string()),
-                cost_usd: None,
-            })
-            .into_response();
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({ "error": "Contact not found" })),
+            )
+                .into_response();
         }
         Err(e) => {
-            error!("Failed to get message: {}", e);
-            return Json(AiReplyResponse {
-                success: false,
-                reply_text: None,
-                error: Some(format!("Failed to get message: {}", e)),
-                cost_usd: None,
-            })
-            .into_response();
+            error!("Failed to get contact: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to get contact" })),
+            )
+                .into_response();
         }
     };
 
-    // Get recent conversation for context (last 20 messages)
-    let recent_conversation = match state.store.get_recent_messages(&req.contact_id, 20) {
-        Ok(msgs) => msgs,
+    match state.store.get_first_unread(&contact.id) {
+        Ok(first_unread) => Json(FirstUnreadResponse {
+            unread_count: contact.unread_count,
+            last_read_timestamp: contact.last_read_timestamp,
+            first_unread,
+        })
+        .into_response(),
         Err(e) => {
-            warn!("Failed to get recent messages: {}", e);
-            vec![]
+            error!("Failed to get first unread message: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to get first unread message" })),
+            )
+                .into_response()
         }
-    };
+    }
+}
 
-    // Create style analyzer
-    let api_key = translator.get_api_key();
-    let style_analyzer = crate::style_analyzer::StyleAnalyzer::new(api_key);
+async fn get_contact_link(
+    State(state): State<Arc<AppState>>,
+    Path(contact_id): Path<String>,
+    Query(query): Query<ContactLinkQuery>,
+) -> impl IntoResponse {
+    let contact_id = urlencoding::decode(&contact_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(contact_id);
 
-    // Get or create global style profile
-    let (global_style, global_usage) = match style_analyzer
-        .get_or_create_profile(&state.store, None)
-        .await
-    {
-        Ok(result) => result,
-        Err(e) => {
-            error!("Failed to get global style profile: {}", e);
-            return Json(AiReplyResponse {
-                success: false,
-                reply_text: None,
-                error: Some(format!("Failed to analyze writing style: {}", e)),
-                cost_usd: None,
-            })
-            .into_response();
+    let contact = match state.store.get_contact(&contact_id) {
+        Ok(Some(c)) => c,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({ "error": "Contact not found" })),
+            )
+                .into_response();
         }
-    };
-
-    // Get or create per-contact style profile
-    let (contact_style, contact_usage) = match style_analyzer
-        .get_or_create_profile(&state.store, Some(&req.contact_id))
-        .await
-    {
-        Ok(result) => result,
         Err(e) => {
-            warn!("Failed to get contact style profile: {}", e);
-            // Continue without contact-specific style
-            (
-                crate::storage::StyleProfile {
-                    contact_id: req.contact_id.clone(),
-                    profile_text: "No contact-specific style data available.".to_string(),
-                    sample_messages: vec![],
-                    message_count: 0,
-                    updated_at: 0,
-                },
-                None,
+            error!("Failed to get contact: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to get contact" })),
             )
+                .into_response();
         }
     };
 
-    // Get examples of user's messages to this contact (more examples = better style matching)
-    let my_examples = match state
-        .store
-        .get_outgoing_messages_for_style(Some(&req.contact_id), 20)
-    {
-        Ok(msgs) => msgs,
-        Err(e) => {
-            warn!("Failed to get example messages: {}", e);
-            vec![]
-        }
-    };
+    if contact.contact_type.as_deref() == Some("group") || contact.id.ends_with("@g.us") {
+        return (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({
+                "error": "Invite links for groups are not available yet"
+            })),
+        )
+            .into_response();
+    }
 
-    // Generate the styled reply
-    match translator
-        .compose_styled_reply(
-            &message,
-            &recent_conversation,
-            &global_style,
-            Some(&contact_style),
-            &my_examples,
+    if contact.contact_type.as_deref() == Some("channel") || is_channel_jid(&contact.id) {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({
+                "error": "Channels have no chat link"
+            })),
         )
-        .await
-    {
-        Ok((reply_text, usage)) => {
-            // Calculate total cost (including any style analysis)
-            let mut total_cost = usage.cost_usd;
-            if let Some(gu) = &global_usage {
-                total_cost += gu.cost_usd;
-            }
-            if let Some(cu) = &contact_usage {
-                total_cost += cu.cost_usd;
-            }
+            .into_response();
+    }
 
-            info!(
-                "AI reply generated ({} chars), cost: ${:.6}",
-                reply_text.len(),
-                total_cost
-            );
+    let Some(phone) = contact_link_phone(&contact) else {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({
+                "error": "Contact has no resolvable phone number"
+            })),
+        )
+            .into_response();
+    };
 
-            // Record usage
-            if let Err(e) = state.store.record_usage(
-                Some(&req.contact_id),
-                Some(&req.message_id),
-                &crate::translation::UsageInfo {
-                    input_tokens: usage.input_tokens,
-                    output_tokens: usage.output_tokens,
-                    cost_usd: total_cost,
-                },
-                "ai_styled_reply",
-            ) {
-                warn!("Failed to record AI reply usage: {}", e);
-            }
+    // Translate the prefilled text into the conversation language if requested
+    let mut text = query.text.unwrap_or_default();
+    let mut target_language = None;
+    let mut cost_usd = 0.0;
+    if let (true, false, Some(translator)) = (query.translate, text.is_empty(), &state.translator) {
+        let settings = state
+            .store
+            .get_conversation_settings(&contact.id)
+            .unwrap_or_default();
+        let force = settings.language_override.is_some();
+        let language = settings.language_override.or_else(|| {
+            state
+                .store
+                .get_cached_conversation_language(&contact.id)
+                .ok()
+                .flatten()
+        });
 
-            Json(AiReplyResponse {
-                success: true,
-                reply_text: Some(reply_text),
-                error: None,
-                cost_usd: Some(total_cost),
-            })
-            .into_response()
-        }
-        Err(e) => {
-            error!("AI reply generation failed: {}", e);
-            Json(AiReplyResponse {
-                success: false,
-                reply_text: None,
-                error: Some(format!("Failed to generate reply: {}", e)),
-                cost_usd: None,
-            })
-            .into_response()
+        if let Some(language) = language {
+            match translator
+                .translate_outgoing(
+                    &text,
+                    &language,
+                    force,
+                    settings.translation_tone.as_ref(),
+                    false,
+                )
+                .await
+            {
+                Ok((translated, _, usage)) => {
+                    if usage.input_tokens > 0 {
+                        if let Err(e) = state.store.record_usage(
+                            Some(&contact.id),
+                            None,
+                            &usage,
+                            "translate_link",
+                        ) {
+                            warn!("Failed to record usage: {}", e);
+                        }
+                    }
+                    cost_usd = usage.cost_usd;
+                    if translated != text {
+                        text = translated;
+                        target_language = Some(language);
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to translate link text: {}", e);
+                }
+            }
         }
     }
+
+    let links = build_chat_links(&phone, Some(&text));
+
+    Json(serde_json::json!({
+        "success": true,
+        "phone": phone,
+        "text": text,
+        "targetLanguage": target_language,
+        "costUsd": cost_usd,
+        "waMeUrl": links.wa_me_url,
+        "whatsappUrl": links.whatsapp_url,
+    }))
+    .into_response()
 }
 
-async fn get_stats(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    match state.store.get_stats() {
-        Ok((messages, contacts)) => Json(serde_json::json!({
-            "messageCount": messages,
-            "contactCount": contacts,
+/// Start a chat with a phone number that may not be an existing contact.
+/// Returns the contact ID (JID) to use with /api/send.
+async fn new_chat(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<NewChatRequest>,
+) -> impl IntoResponse {
+    let command_tx = state.command_tx.read().await.clone();
+
+    match start_new_chat(
+        &state.store,
+        command_tx.as_ref(),
+        &state.number_checks,
+        &req.phone,
+        req.region.as_deref(),
+        req.verify,
+    )
+    .await
+    {
+        Ok(contact_id) => Json(serde_json::json!({
+            "success": true,
+            "contactId": contact_id,
         }))
         .into_response(),
         Err(e) => {
-            error!("Failed to get stats: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to get stats").into_response()
+            let status = match e {
+                NewChatError::InvalidNumber | NewChatError::InvalidRegion => {
+                    StatusCode::BAD_REQUEST
+                }
+                NewChatError::NotOnWhatsApp => StatusCode::NOT_FOUND,
+                NewChatError::BridgeUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+                NewChatError::CheckTimeout => StatusCode::GATEWAY_TIMEOUT,
+                NewChatError::CheckFailed => StatusCode::BAD_GATEWAY,
+                NewChatError::StorageError => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            (
+                status,
+                Json(serde_json::json!({
+                    "success": false,
+                    "error": e.as_str(),
+                    "errorDescription": e.description(),
+                })),
+            )
+                .into_response()
         }
     }
 }
 
-/// Get global translation usage/cost
-async fn get_global_usage(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    match state.store.get_global_usage() {
-        Ok(usage) => Json(serde_json::json!({
-            "inputTokens": usage.input_tokens,
-            "outputTokens": usage.output_tokens,
-            "costUsd": usage.cost_usd,
-        }))
-        .into_response(),
-        Err(e) => {
-            error!("Failed to get global usage: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to get usage").into_response()
+/// Create a WhatsApp group
+async fn create_group_handler(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<CreateGroupRequest>,
+) -> impl IntoResponse {
+    let command_tx = state.command_tx.read().await.clone();
+    if command_tx.is_some() && !state.bridge_supports("create_group").await {
+        return (
+            StatusCode::NOT_IMPLEMENTED,
+            Json(serde_json::json!({
+                "success": false,
+                "error": "unsupported",
+                "errorDescription": "The bridge doesn't support creating groups; rebuild wa-bridge",
+            })),
+        )
+            .into_response();
+    }
+
+    match create_group(
+        &state.store,
+        command_tx.as_ref(),
+        &state.pending_groups,
+        &req.subject,
+        &req.participants,
+        req.region.as_deref(),
+    )
+    .await
+    {
+        Ok(group) => {
+            if let Ok(Some(contact)) = state.store.get_contact(&group.contact_id) {
+                state.broadcast_contact_updated(contact);
+            }
+            Json(serde_json::json!({
+                "success": true,
+                "contactId": group.contact_id,
+                "subject": group.subject,
+                "participants": group.participants,
+            }))
+            .into_response()
+        }
+        Err(e) => {
+            let status = match e {
+                GroupError::InvalidSubject
+                | GroupError::NoParticipants
+                | GroupError::TooManyParticipants
+                | GroupError::InvalidParticipant(_) => StatusCode::BAD_REQUEST,
+                GroupError::BridgeUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+                GroupError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+                GroupError::Failed(_) => StatusCode::BAD_GATEWAY,
+                GroupError::StorageError => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            let mut body = serde_json::json!({
+                "success": false,
+                "error": e.as_str(),
+                "errorDescription": e.description(),
+            });
+            if let GroupError::InvalidParticipant(participant) = &e {
+                body["participant"] = participant.as_str().into();
+            }
+            (status, Json(body)).into_response()
         }
     }
 }
 
-/// Get translation usage/cost for a specific conversation
-async fn get_conversation_usage(
+/// Import a WhatsApp "Export chat" .txt file into a chat's history.
+///
+/// Multipart fields: `file` (the export), `contactId` (the chat, created if
+/// it doesn't exist), and optionally `name` for a new chat (default: from
+/// the file name) and `me`, my name in the export (default: my profile name).
+async fn import_chat_export(
+    State(state): State<Arc<AppState>>,
+    mut multipart: Multipart,
+) -> impl IntoResponse {
+    let invalid = |description: String| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "success": false,
+                "error": "invalid_request",
+                "errorDescription": description,
+            })),
+        )
+            .into_response()
+    };
+
+    let (mut export, mut file_name, mut contact_id, mut name, mut me) =
+        (None, None, None, None, None);
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return invalid(format!("Invalid upload: {}", e)),
+        };
+        let field_name = field.name().unwrap_or_default().to_string();
+        if field_name == "file" {
+            file_name = field.file_name().map(str::to_string);
+            match field.bytes().await {
+                Ok(bytes) => export = Some(String::from_utf8_lossy(&bytes).into_owned()),
+                Err(e) => return invalid(format!("Invalid upload: {}", e)),
+            }
+            continue;
+        }
+        let value = match field.text().await {
+            Ok(value) => Some(value).filter(|v| !v.trim().is_empty()),
+            Err(e) => return invalid(format!("Invalid upload: {}", e)),
+        };
+        match field_name.as_str() {
+            "contactId" => contact_id = value,
+            "name" => name = value,
+            "me" => me = value,
+            _ => {}
+        }
+    }
+    let Some(export) = export else {
+        return invalid("No export file was uploaded".to_string());
+    };
+    let Some(contact_id) = contact_id else {
+        return invalid("contactId is required".to_string());
+    };
+    let name = name.or_else(|| {
+        file_name
+            .as_deref()
+            .and_then(import::chat_name_from_file_name)
+    });
+
+    let store = state.store.clone();
+    let imported = {
+        let contact_id = contact_id.clone();
+        tokio::task::spawn_blocking(move || {
+            import::import_chat_export(&store, &export, &contact_id, name.as_deref(), me.as_deref())
+        })
+        .await
+        .unwrap_or_else(|e| {
+            error!("Chat export import panicked: {}", e);
+            Err(ImportError::StorageError)
+        })
+    };
+
+    match imported {
+        Ok(report) => {
+            if let Ok(Some(contact)) = state.store.get_contact(&contact_id) {
+                state.broadcast_contact_updated(contact);
+            }
+            Json(serde_json::json!({
+                "success": true,
+                "contactId": contact_id,
+                "report": report,
+            }))
+            .into_response()
+        }
+        Err(e) => {
+            let status = match e {
+                ImportError::InvalidContact | ImportError::NotAnExport => StatusCode::BAD_REQUEST,
+                ImportError::ReadOnly => StatusCode::INSUFFICIENT_STORAGE,
+                ImportError::StorageError => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            (
+                status,
+                Json(serde_json::json!({
+                    "success": false,
+                    "error": e.as_str(),
+                    "errorDescription": e.description(),
+                })),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Chat type of a contact as stored on its messages
+fn chat_type(contact_id: &str) -> &'static str {
+    if contact_id.ends_with("@g.us") {
+        "group"
+    } else {
+        "private"
+    }
+}
+
+/// Get conversation settings for a contact
+async fn get_conversation_settings(
     State(state): State<Arc<AppState>>,
     Path(contact_id): Path<String>,
 ) -> impl IntoResponse {
@@ -1602,786 +3855,6600 @@ async fn get_conversation_usage(
         .map(|s| s.into_owned())
         .unwrap_or(contact_id);
 
-    match state.store.get_conversation_usage(&contact_id) {
-        Ok(usage) => Json(serde_json::json!({
-            "inputTokens": usage.input_tokens,
-            "outputTokens": usage.output_tokens,
-            "costUsd": usage.cost_usd,
+    match state.store.get_conversation_settings(&contact_id) {
+        Ok(settings) => Json(ConversationSettingsResponse {
+            triage_enabled: settings.triage_enabled(chat_type(&contact_id)),
+            language_override: settings.language_override,
+            translation_style: settings.translation_style,
+            learning_mode: settings.learning_mode,
+            include_learning_notes: settings.include_learning_notes,
+            muted: settings.muted,
+            triage: settings.triage,
+            translation_tone: settings.translation_tone,
+            recipient_clock: tzinfer::recipient_clock(
+                &state.store,
+                &contact_id,
+                chrono::Utc::now(),
+            )
+            .map_err(|e| error!("Failed to get recipient's local time: {}", e))
+            .ok()
+            .flatten(),
+            timezone: settings.timezone,
+            outgoing_translation: state
+                .store
+                .get_outgoing_translation(&contact_id)
+                .map_err(|e| error!("Failed to get outgoing translation: {}", e))
+                .ok(),
+            language_confidence: state
+                .store
+                .get_language_confidence(&contact_id)
+                .map_err(|e| error!("Failed to get language confidence: {}", e))
+                .ok(),
+            translation_participants: contact_id
+                .ends_with("@g.us")
+                .then(|| {
+                    state
+                        .store
+                        .get_translation_participants(&contact_id)
+                        .map_err(|e| error!("Failed to get translation participants: {}", e))
+                        .ok()
+                })
+                .flatten(),
+        })
+        .into_response(),
+        Err(e) => {
+            error!("Failed to get conversation settings: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to get conversation settings",
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Update conversation settings for a contact
+async fn update_conversation_settings(
+    State(state): State<Arc<AppState>>,
+    Path(contact_id): Path<String>,
+    Json(req): Json<UpdateConversationSettingsRequest>,
+) -> impl IntoResponse {
+    let contact_id = urlencoding::decode(&contact_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(contact_id);
+
+    let current = state
+        .store
+        .get_conversation_settings(&contact_id)
+        .unwrap_or_default();
+
+    let translation_tone = match req.translation_tone.map(TranslationToneChoice::setting) {
+        None => current.translation_tone,
+        Some(None) => None,
+        Some(Some(tone)) => match tone.validated() {
+            Some(tone) => Some(tone),
+            None => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({
+                        "success": false,
+                        "error": format!(
+                            "A custom tone must be 1 to {} characters",
+                            crate::translation::MAX_TONE_CHARS
+                        )
+                    })),
+                )
+                    .into_response()
+            }
+        },
+    };
+
+    let timezone = match req.timezone.as_deref().map(str::trim) {
+        None => current.timezone,
+        Some("") => None,
+        Some(name) => match tzinfer::parse_timezone(name) {
+            Some(tz) => Some(tz.name().to_string()),
+            None => return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "success": false,
+                    "error": format!("Unknown timezone: {} (use a name like Europe/Paris)", name)
+                })),
+            )
+                .into_response(),
+        },
+    };
+
+    // Convert empty strings to None
+    let settings = crate::storage::ConversationSettings {
+        language_override: req.language_override.filter(|s| !s.trim().is_empty()),
+        translation_style: req.translation_style.filter(|s| !s.trim().is_empty()),
+        learning_mode: req.learning_mode.unwrap_or(current.learning_mode),
+        muted: req.muted.unwrap_or(current.muted),
+        triage: req.triage.map_or(current.triage, TriageChoice::setting),
+        translation_tone,
+        timezone,
+        include_learning_notes: req
+            .include_learning_notes
+            .unwrap_or(current.include_learning_notes),
+    };
+
+    let updated = state
+        .store
+        .update_conversation_settings(&contact_id, &settings)
+        .and_then(|()| match req.outgoing_translation {
+            Some(choice) => state.store.set_outgoing_translation(
+                &contact_id,
+                match choice {
+                    OutgoingTranslationChoice::Default => None,
+                    OutgoingTranslationChoice::On => Some(true),
+                    OutgoingTranslationChoice::Off => Some(false),
+                },
+            ),
+            None => Ok(()),
+        })
+        .and_then(|()| state.store.get_outgoing_translation(&contact_id));
+
+    match updated {
+        Ok(outgoing) => Json(serde_json::json!({
+            "success": true,
+            "languageOverride": settings.language_override,
+            "translationStyle": settings.translation_style,
+            "learningMode": settings.learning_mode,
+            "includeLearningNotes": settings.include_learning_notes,
+            "triageEnabled": settings.triage_enabled(chat_type(&contact_id)),
+            "translationTone": settings.translation_tone,
+            "timezone": settings.timezone,
+            "outgoingTranslation": outgoing
         }))
         .into_response(),
         Err(e) => {
-            error!("Failed to get conversation usage: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to get usage").into_response()
+            error!("Failed to update conversation settings: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "success": false,
+                    "error": format!("Failed to update settings: {}", e)
+                })),
+            )
+                .into_response()
         }
     }
 }
 
-/// Query parameters for link preview
+/// Get a conversation's unsent draft
+async fn get_draft(
+    State(state): State<Arc<AppState>>,
+    Path(contact_id): Path<String>,
+) -> impl IntoResponse {
+    let contact_id = urlencoding::decode(&contact_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(contact_id);
+
+    match state.store.get_draft(&contact_id) {
+        Ok(draft) => Json(serde_json::json!({ "draft": draft })).into_response(),
+        Err(e) => {
+            error!("Failed to get draft: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to get draft" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Request body for saving a draft
 #[derive(Deserialize)]
-struct LinkPreviewQuery {
-    url: String,
+#[serde(rename_all = "camelCase")]
+struct SaveDraftRequest {
+    text: String,
+    reply_to_message_id: Option<String>,
+    /// When the draft was edited (Unix milliseconds); older than the stored
+    /// draft is rejected
+    updated_at: i64,
 }
 
-/// Get link preview for a URL (with caching)
-async fn get_link_preview(
+/// Query parameters for deleting a draft
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DeleteDraftQuery {
+    /// Only delete a draft saved at or before this time
+    updated_at: Option<i64>,
+}
+
+/// 409 response for a stale draft write, carrying the newer draft
+fn draft_conflict(newer: Draft) -> Response {
+    (
+        StatusCode::CONFLICT,
+        Json(serde_json::json!({
+            "error": "A newer draft was saved from another session",
+            "draft": newer,
+        })),
+    )
+        .into_response()
+}
+
+/// Save a conversation's draft (last write wins)
+async fn save_draft(
     State(state): State<Arc<AppState>>,
-    Query(query): Query<LinkPreviewQuery>,
+    Path(contact_id): Path<String>,
+    Json(req): Json<SaveDraftRequest>,
 ) -> impl IntoResponse {
-    use crate::link_preview;
+    let contact_id = urlencoding::decode(&contact_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(contact_id);
 
-    let url = query.url;
+    let draft = Draft {
+        contact_id: contact_id.clone(),
+        text: req.text,
+        reply_to_message_id: req.reply_to_message_id.filter(|id| !id.is_empty()),
+        updated_at: req.updated_at,
+    };
+    match state.store.save_draft(&draft) {
+        Ok(None) => {
+            let _ = state.broadcast_tx.send(WebSocketEvent::DraftUpdated {
+                contact_id,
+                draft: Some(draft.clone()),
+            });
+            Json(draft).into_response()
+        }
+        Ok(Some(newer)) => draft_conflict(newer),
+        Err(e) => {
+            error!("Failed to save draft: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to save draft" })),
+            )
+                .into_response()
+        }
+    }
+}
 
-    // Cache duration: 24 hours for successful fetches, 1 hour for errors
-    let cache_duration = 24 * 60 * 60; // 24 hours
+/// Delete a conversation's draft
+async fn delete_draft(
+    State(state): State<Arc<AppState>>,
+    Path(contact_id): Path<String>,
+    Query(query): Query<DeleteDraftQuery>,
+) -> impl IntoResponse {
+    let contact_id = urlencoding::decode(&contact_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(contact_id);
 
-    // Check cache first
-    match state.store.get_link_preview(&url, cache_duration) {
-        Ok(Some(preview)) => {
-            return Json(preview).into_response();
-        }
+    match state.store.delete_draft(&contact_id, query.updated_at) {
         Ok(None) => {
-            // Not in cache, need to fetch
+            let _ = state.broadcast_tx.send(WebSocketEvent::DraftUpdated {
+                contact_id,
+                draft: None,
+            });
+            Json(serde_json::json!({ "success": true })).into_response()
         }
+        Ok(Some(newer)) => draft_conflict(newer),
         Err(e) => {
-            warn!("Failed to check link preview cache: {}", e);
-            // Continue to fetch
+            error!("Failed to delete draft: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to delete draft" })),
+            )
+                .into_response()
         }
     }
+}
 
-    // Fetch the preview
-    match link_preview::fetch_link_preview(&url).await {
-        Ok(preview) => {
-            // Cache the result
-            if let Err(e) = state.store.save_link_preview(&preview) {
-                warn!("Failed to cache link preview: {}", e);
-            }
-            Json(preview).into_response()
+/// Request body for filtering a group participant's translations
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetTranslationParticipantRequest {
+    participant_jid: String,
+    /// None clears the filter, so they follow the group
+    mode: Option<ParticipantTranslationMode>,
+}
+
+fn translation_participants_response(state: &AppState, group_jid: &str) -> Response {
+    match state.store.get_translation_participants(group_jid) {
+        Ok(participants) => {
+            Json(serde_json::json!({ "participants": participants })).into_response()
         }
         Err(e) => {
-            error!("Failed to fetch link preview for {}: {}", url, e);
-            // Return error preview
-            let error_preview =
-                link_preview::LinkPreview::error(url, format!("Failed to fetch: {}", e));
-            // Cache the error for a shorter duration (by saving it)
-            let _ = state.store.save_link_preview(&error_preview);
-            Json(error_preview).into_response()
+            error!("Failed to get translation participants: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to get translation participants" })),
+            )
+                .into_response()
         }
     }
 }
 
-// ==================== OAuth 2.0 Handlers ====================
-
-/// Get base URL from request (for OAuth metadata)
-fn get_base_url(host: &str, is_https: bool) -> String {
-    let scheme = if is_https { "https" } else { "http" };
-    format!("{}://{}", scheme, host)
+fn not_a_group() -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(serde_json::json!({ "error": "Not a group" })),
+    )
+        .into_response()
 }
 
-/// OAuth 2.0 Authorization Server Metadata (RFC 8414)
-async fn oauth_metadata(Host(host): Host) -> impl IntoResponse {
-    // Assume HTTPS in production (Railway sets this)
-    let is_https = !host.contains("localhost") && !host.contains("127.0.0.1");
-    let base_url = get_base_url(&host, is_https);
+/// Get a group's participant translation filters
+async fn get_translation_participants(
+    State(state): State<Arc<AppState>>,
+    Path(group_jid): Path<String>,
+) -> impl IntoResponse {
+    let group_jid = urlencoding::decode(&group_jid)
+        .map(|s| s.into_owned())
+        .unwrap_or(group_jid);
+    if !group_jid.ends_with("@g.us") {
+        return not_a_group();
+    }
 
-    // Extended metadata with Dynamic Client Registration support
-    Json(serde_json::json!({
-        "issuer": base_url,
-        "authorization_endpoint": format!("{}/oauth/authorize", base_url),
-        "token_endpoint": format!("{}/oauth/token", base_url),
-        "registration_endpoint": format!("{}/oauth/register", base_url),
-        "revocation_endpoint": format!("{}/oauth/revoke", base_url),
-        "response_types_supported": ["code"],
-        "grant_types_supported": ["authorization_code", "refresh_token"],
-        "code_challenge_methods_supported": ["S256"],
-        "token_endpoint_auth_methods_supported": ["none"],
-        "scopes_supported": ["mcp"],
-        // MCP-specific fields
-        "service_documentation": format!("{}/docs", base_url),
-    }))
-}
-
-/// OAuth 2.0 Protected Resource Metadata (RFC 9728)
-/// This tells MCP clients which authorization server to use
-async fn oauth_protected_resource_metadata(Host(host): Host) -> impl IntoResponse {
-    let is_https = !host.contains("localhost") && !host.contains("127.0.0.1");
-    let base_url = get_base_url(&host, is_https);
-
-    Json(serde_json::json!({
-        "resource": format!("{}/mcp", base_url),
-        "authorization_servers": [base_url],
-        "scopes_supported": ["mcp"],
-        "bearer_methods_supported": ["header"]
-    }))
-}
-
-/// Dynamic Client Registration request (RFC 7591)
-#[derive(Debug, Deserialize)]
-struct ClientRegistrationRequest {
-    redirect_uris: Vec<String>,
-    client_name: Option<String>,
-    client_uri: Option<String>,
-    scope: Option<String>,
-    grant_types: Option<Vec<String>>,
-    response_types: Option<Vec<String>>,
-    token_endpoint_auth_method: Option<String>,
-}
-
-/// Dynamic Client Registration endpoint (RFC 7591)
-/// Allows MCP clients like Claude.ai to register before starting OAuth flow
-async fn oauth_register(Json(req): Json<ClientRegistrationRequest>) -> impl IntoResponse {
-    // Generate a client_id for this registration
-    let client_id = format!("client_{}", generate_token()[..16].to_string());
-
-    // For public clients (like Claude.ai), we don't issue a client_secret
-    // The client will use PKCE for security instead
-
-    info!(
-        "OAuth client registered: {} ({:?}) with redirect_uris: {:?}",
-        client_id, req.client_name, req.redirect_uris
-    );
-
-    // Return the registration response
-    (
-        StatusCode::CREATED,
-        Json(serde_json::json!({
-            "client_id": client_id,
-            "client_name": req.client_name,
-            "redirect_uris": req.redirect_uris,
-            "grant_types": req.grant_types.unwrap_or_else(|| vec!["authorization_code".to_string(), "refresh_token".to_string()]),
-            "response_types": req.response_types.unwrap_or_else(|| vec!["code".to_string()]),
-            "token_endpoint_auth_method": "none",
-            "scope": req.scope.unwrap_or_else(|| "mcp".to_string()),
-        })),
-    )
+    translation_participants_response(&state, &group_jid)
 }
 
-/// OAuth Authorization endpoint - shows approval page
-async fn oauth_authorize(
+/// Always or never translate a group participant, or clear the filter
+async fn set_translation_participant(
     State(state): State<Arc<AppState>>,
-    Host(host): Host,
-    Query(params): Query<AuthorizeRequest>,
+    Path(group_jid): Path<String>,
+    Json(req): Json<SetTranslationParticipantRequest>,
 ) -> impl IntoResponse {
-    // Validate request
-    if params.response_type != "code" {
+    let group_jid = urlencoding::decode(&group_jid)
+        .map(|s| s.into_owned())
+        .unwrap_or(group_jid);
+    if !group_jid.ends_with("@g.us") {
+        return not_a_group();
+    }
+    let participant_jid = bare_jid(req.participant_jid.trim());
+    if !participant_jid.contains('@') {
         return (
             StatusCode::BAD_REQUEST,
-            Html(format!(
-                r#"<html><body><h1>Error</h1><p>Unsupported response_type: {}</p></body></html>"#,
-                params.response_type
-            )),
+            Json(serde_json::json!({ "error": "Invalid participant JID" })),
         )
             .into_response();
     }
 
-    if params.code_challenge_method != "S256" {
+    if let Err(e) = state
+        .store
+        .set_translation_participant(&group_jid, &participant_jid, req.mode)
+    {
+        error!("Failed to set translation participant: {}", e);
         return (
-            StatusCode::BAD_REQUEST,
-            Html(r#"<html><body><h1>Error</h1><p>Only S256 code_challenge_method is supported (PKCE required)</p></body></html>"#.to_string()),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": "Failed to set translation participant" })),
         )
             .into_response();
     }
+    translation_participants_response(&state, &group_jid)
+}
 
-    // Generate a session key for this authorization request
-    let session_key = generate_token();
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as i64;
-
-    let pending = PendingAuthorization {
-        session_key: session_key.clone(),
-        client_id: params.client_id.clone(),
-        redirect_uri: params.redirect_uri.clone(),
-        code_challenge: params.code_challenge.clone(),
-        code_challenge_method: params.code_challenge_method.clone(),
-        scope: params.scope.clone().unwrap_or_else(|| "mcp".to_string()),
-        state: params.state.clone(),
-        created_at: now,
-        expires_at: now + 600, // 10 minutes
-    };
+/// Clear a group participant's translation filter
+async fn delete_translation_participant(
+    State(state): State<Arc<AppState>>,
+    Path((group_jid, participant_jid)): Path<(String, String)>,
+) -> impl IntoResponse {
+    let group_jid = urlencoding::decode(&group_jid)
+        .map(|s| s.into_owned())
+        .unwrap_or(group_jid);
+    let participant_jid = urlencoding::decode(&participant_jid)
+        .map(|s| s.into_owned())
+        .unwrap_or(participant_jid);
+    if !group_jid.ends_with("@g.us") {
+        return not_a_group();
+    }
 
-    // Store pending authorization
-    if let Err(e) = state.store.oauth_store_pending_auth(&pending) {
-        error!("Failed to store pending auth: {}", e);
+    if let Err(e) =
+        state
+            .store
+            .set_translation_participant(&group_jid, &bare_jid(&participant_jid), None)
+    {
+        error!("Failed to clear translation participant: {}", e);
         return (
             StatusCode::INTERNAL_SERVER_ERROR,
-            Html(
-                "<html><body><h1>Error</h1><p>Internal server error</p></body></html>".to_string(),
-            ),
+            Json(serde_json::json!({ "error": "Failed to clear translation participant" })),
         )
             .into_response();
     }
+    translation_participants_response(&state, &group_jid)
+}
 
-    // Check if password auth is required
-    let requires_password = state.password.is_some();
-
-    // Show approval page
-    let is_https = !host.contains("localhost") && !host.contains("127.0.0.1");
-    let base_url = get_base_url(&host, is_https);
-
-    let html = format!(
-        r#"<!DOCTYPE html>
-<html>
-<head>
-    <title>Authorize MCP Client</title>
-    <meta name="viewport" content="width=device-width, initial-scale=1">
-    <style>
-        body {{ font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif; 
-               max-width: 500px; margin: 50px auto; padding: 20px; background: #f5f5f5; }}
-        .card {{ background: white; border-radius: 12px; padding: 30px; box-shadow: 0 2px 10px rgba(0,0,0,0.1); }}
-        h1 {{ color: #333; margin-top: 0; }}
-        .client-info {{ background: #f0f0f0; padding: 15px; border-radius: 8px; margin: 20px 0; }}
-        .scope {{ color: #666; font-size: 0.9em; }}
-        .warning {{ color: #e67e22; font-size: 0.9em; margin: 15px 0; }}
-        .buttons {{ display: flex; gap: 10px; margin-top: 20px; }}
-        button {{ flex: 1; padding: 12px 24px; border: none; border-radius: 8px; font-size: 16px; cursor: pointer; }}
-        .approve {{ background: #25D366; color: white; }}
-        .approve:hover {{ background: #1da851; }}
-        .deny {{ background: #e74c3c; color: white; }}
-        .deny:hover {{ background: #c0392b; }}
-        input {{ width: 100%; padding: 12px; margin: 10px 0; border: 1px solid #ddd; border-radius: 8px; box-sizing: border-box; }}
-        label {{ display: block; margin-top: 15px; color: #666; }}
-    </style>
-</head>
-<body>
-    <div class="card">
-        <h1>🔐 Authorize MCP Client</h1>
-        <p>An application is requesting access to your WhatsApp Translator:</p>
-        
-        <div class="client-info">
-            <strong>Client ID:</strong> {client_id}<br>
-            <strong>Redirect URI:</strong> {redirect_uri}
-        </div>
-        
-        <div class="scope">
-            <strong>Requested permissions:</strong> {scope}<br>
-            This will allow the application to read your contacts, messages, and send messages on your behalf.
-        </div>
-        
-        <p class="warning">⚠️ Only authorize applications you trust!</p>
-        
-        <form method="POST" action="{base_url}/oauth/approve">
-            <input type="hidden" name="session_key" value="{session_key}">
-            {password_field}
-            <div class="buttons">
-                <button type="submit" name="approved" value="true" class="approve">✓ Authorize</button>
-                <button type="submit" name="approved" value="false" class="deny">✗ Deny</button>
-            </div>
-        </form>
-    </div>
-</body>
-</html>"#,
-        client_id = html_escape(&params.client_id),
-        redirect_uri = html_escape(&params.redirect_uri),
-        scope = html_escape(&params.scope.clone().unwrap_or_else(|| "mcp".to_string())),
-        base_url = base_url,
-        session_key = session_key,
-        password_field = if requires_password {
-            r#"<label for="password">Enter your password to authorize:</label>
-            <input type="password" name="password" id="password" placeholder="Password" required>"#
-        } else {
-            ""
-        }
-    );
+/// Query parameters for messages pagination
+#[derive(Debug, Deserialize)]
+struct MessagesQuery {
+    /// Maximum number of messages to return (default: 50 for initial load)
+    limit: Option<u32>,
+    /// Where the previous page stopped (its `nextCursor`)
+    cursor: Option<String>,
+    /// Only get messages before this timestamp. Superseded by `cursor`,
+    /// which doesn't skip messages sharing the boundary's timestamp.
+    before: Option<i64>,
+    /// Only get messages sent by this origin ("web", "mcp", "mcp:<client_id>", ...)
+    origin: Option<String>,
+    /// Only get messages sent by this named web session (or MCP client)
+    sent_by: Option<String>,
+    /// Add what translating each message cost
+    #[serde(default)]
+    include_costs: bool,
+}
 
-    Html(html).into_response()
+/// Response for paginated messages
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MessagesResponse<M = StoredMessage> {
+    messages: Vec<M>,
+    has_more: bool,
+    /// Cursor for the next page, when paging by cursor
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+    /// The chat's pinned messages (with its latest page only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pinned_messages: Option<Vec<PinnedMessage>>,
 }
 
-/// Simple HTML escaping
-fn html_escape(s: &str) -> String {
-    s.replace('&', "&amp;")
-        .replace('<', "&lt;")
-        .replace('>', "&gt;")
-        .replace('"', "&quot;")
-        .replace('\'', "&#x27;")
+/// Response for a 400 on a cursor that can't be decoded
+fn invalid_cursor() -> axum::response::Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(serde_json::json!({ "error": "Invalid cursor" })),
+    )
+        .into_response()
 }
 
-/// OAuth approval form data
-#[derive(Debug, Deserialize)]
-struct OAuthApprovalForm {
-    session_key: String,
-    approved: String,
-    password: Option<String>,
+/// A message with the reactions to it
+#[derive(Serialize)]
+struct MessageWithReactions {
+    #[serde(flatten)]
+    message: StoredMessage,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    reactions: Vec<ReactionGroup>,
+    /// What translating the message cost, when asked for
+    #[serde(rename = "translationCostUsd", skip_serializing_if = "Option::is_none")]
+    translation_cost_usd: Option<f64>,
 }
 
-/// Handle OAuth approval form submission
-async fn oauth_approve(
+async fn get_messages(
     State(state): State<Arc<AppState>>,
-    Form(form): Form<OAuthApprovalForm>,
+    Path(contact_id): Path<String>,
+    Query(params): Query<MessagesQuery>,
 ) -> impl IntoResponse {
-    // Get the pending authorization
-    let pending = match state.store.oauth_take_pending_auth(&form.session_key) {
-        Ok(Some(p)) => p,
-        Ok(None) => {
-            return (
-                StatusCode::BAD_REQUEST,
-                Html("<html><body><h1>Error</h1><p>Invalid or expired authorization request</p></body></html>".to_string()),
-            )
-                .into_response();
-        }
-        Err(e) => {
-            error!("Failed to get pending auth: {}", e);
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Html(
-                    "<html><body><h1>Error</h1><p>Internal server error</p></body></html>"
-                        .to_string(),
-                ),
-            )
-                .into_response();
-        }
+    let contact_id = urlencoding::decode(&contact_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(contact_id);
+
+    // Default to 50 messages for initial load, unless explicitly requesting all (limit=0)
+    let limit = match params.limit {
+        Some(0) => None, // 0 means all messages (for backwards compatibility / MCP)
+        Some(n) => Some(n),
+        None => Some(50), // Default to 50 for lazy loading
+    };
+    let cursor = match params.cursor.as_deref() {
+        None => None,
+        Some(token) => match MessageCursor::decode(token) {
+            Some(cursor) => Some(cursor),
+            None => return invalid_cursor(),
+        },
     };
 
-    // Check if user denied
-    if form.approved != "true" {
-        let redirect_url = build_error_redirect(
-            &pending.redirect_uri,
-            OAuthError::AccessDenied,
-            pending.state.as_deref(),
-        );
-        return Redirect::to(&redirect_url).into_response();
+    // Opening a chat follows the contact's online status for a while
+    let latest_page = params.before.is_none() && cursor.is_none();
+    if latest_page {
+        let pinned =
+            matches!(state.store.get_contact(&contact_id), Ok(Some(c)) if c.pinned_at.is_some());
+        state.watch_presence(&contact_id, pinned).await;
     }
 
-    // Verify password if required
-    if let Some(expected_password) = &state.password {
-        match &form.password {
-            Some(password) if password == expected_password => {}
-            _ => {
-                let redirect_url = build_error_redirect(
-                    &pending.redirect_uri,
-                    OAuthError::AccessDenied,
-                    pending.state.as_deref(),
-                );
-                return Redirect::to(&redirect_url).into_response();
-            }
+    let filter = MessageFilter {
+        origin: params.origin.as_deref(),
+        sent_by: params.sent_by.as_deref(),
+    };
+    // Strip media_data from messages to reduce payload (media loaded on demand via /api/media).
+    // Pages follow the cursor; only older clients still page by timestamp.
+    let page = match limit {
+        Some(limit) if cursor.is_some() || params.before.is_none() => state
+            .store
+            .get_messages_page(&contact_id, limit, cursor, true, filter)
+            .map(|(messages, next)| (messages, next.is_some(), next.map(|c| c.encode()))),
+        _ => state
+            .store
+            .get_messages_paginated(&contact_id, limit, params.before, None, true, filter)
+            .map(|messages| {
+                // Check if there are more messages (we got a full page)
+                let has_more = limit.is_some_and(|l| messages.len() >= l as usize);
+                (messages, has_more, None)
+            }),
+    };
+    match page {
+        Ok((messages, has_more, next_cursor)) => {
+            // Reactions are shown on the messages they react to
+            let ids: Vec<&str> = messages.iter().map(|m| m.id.as_str()).collect();
+            let mut reactions = state
+                .store
+                .get_reactions(&contact_id, &ids)
+                .unwrap_or_else(|e| {
+                    error!("Failed to get reactions: {}", e);
+                    HashMap::new()
+                });
+            // Costs for the whole page come from one lookup
+            let costs = if params.include_costs {
+                match state.store.get_message_costs(&ids) {
+                    Ok(costs) => Some(costs),
+                    Err(e) => {
+                        error!("Failed to get message costs: {}", e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            // Vocabulary is only shown while the chat is in learning mode
+            let learning_mode = state
+                .store
+                .get_conversation_settings(&contact_id)
+                .map(|s| s.learning_mode)
+                .unwrap_or_default();
+            let messages: Vec<MessageWithReactions> = messages
+                .into_iter()
+                .filter(|m| m.content_type != "Reaction")
+                .map(|mut message| {
+                    if !learning_mode {
+                        message.vocabulary = None;
+                    }
+                    MessageWithReactions {
+                        reactions: reactions.remove(&message.id).unwrap_or_default(),
+                        translation_cost_usd: costs.as_ref().map(|costs| {
+                            costs.get(&message.id).map_or(0.0, |usage| usage.cost_usd)
+                        }),
+                        message,
+                    }
+                })
+                .collect();
+            // Pins come with the latest page, looked up together
+            let pinned_messages = latest_page
+                .then(|| {
+                    state
+                        .store
+                        .get_pinned_messages(&contact_id)
+                        .map_err(|e| error!("Failed to get pinned messages: {}", e))
+                        .ok()
+                })
+                .flatten();
+            Json(MessagesResponse {
+                messages,
+                has_more,
+                next_cursor,
+                pinned_messages,
+            })
+            .into_response()
+        }
+        Err(e) => {
+            error!("Failed to get messages: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to get messages").into_response()
         }
     }
+}
 
-    // Generate authorization code
-    let code = generate_token();
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as i64;
+/// Find-in-chat query
+#[derive(Deserialize)]
+struct ChatSearchQuery {
+    q: String,
+    /// Matching messages per page (default 50)
+    limit: Option<u32>,
+    /// Where the previous page stopped (its `nextCursor`)
+    cursor: Option<String>,
+}
 
-    let auth_code = AuthorizationCode {
-        code: code.clone(),
-        client_id: pending.client_id,
-        redirect_uri: pending.redirect_uri.clone(),
-        code_challenge: pending.code_challenge,
-        code_challenge_method: pending.code_challenge_method,
-        scope: pending.scope,
-        created_at: now,
-        expires_at: now + 300, // 5 minutes
-        used: false,
+/// Find-in-chat results, newest message first
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ChatSearchResponse {
+    hits: Vec<ChatSearchHit>,
+    /// Matches in the whole chat
+    total_matches: usize,
+    has_more: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+}
+
+/// Recent errors query
+#[derive(Deserialize)]
+struct ErrorsQuery {
+    /// "bridge", "translation", "storage", "webhook" or "send"; all if left out
+    category: Option<String>,
+    /// Only errors after this Unix timestamp in milliseconds
+    since: Option<i64>,
+}
+
+/// Recent errors kept in memory, newest first
+async fn get_errors(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ErrorsQuery>,
+) -> impl IntoResponse {
+    let category = match params.category.as_deref() {
+        None => None,
+        Some(name) => match ErrorCategory::parse(name) {
+            Some(category) => Some(category),
+            None => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({
+                        "error": format!("Unknown error category: {}", name)
+                    })),
+                )
+                    .into_response()
+            }
+        },
     };
+    Json(serde_json::json!({
+        "errors": state.errors.list(category, params.since),
+    }))
+    .into_response()
+}
 
-    // Store the authorization code
-    if let Err(e) = state.store.oauth_store_authorization_code(&auth_code) {
-        error!("Failed to store authorization code: {}", e);
-        let redirect_url = build_error_redirect(
-            &pending.redirect_uri,
-            OAuthError::ServerError,
-            pending.state.as_deref(),
-        );
-        return Redirect::to(&redirect_url).into_response();
-    }
+/// Most matching messages returned at once
+const MAX_CHAT_SEARCH_LIMIT: u32 = 200;
 
-    info!(
-        "OAuth authorization granted for client: {}",
-        auth_code.client_id
-    );
+/// Find text in one chat, with where each match sits in the shown text
+async fn search_chat(
+    State(state): State<Arc<AppState>>,
+    Path(contact_id): Path<String>,
+    Query(params): Query<ChatSearchQuery>,
+) -> impl IntoResponse {
+    let contact_id = urlencoding::decode(&contact_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(contact_id);
 
-    // Redirect back to client with authorization code
-    let mut redirect_url = format!("{}?code={}", pending.redirect_uri, code);
-    if let Some(state_param) = pending.state {
-        redirect_url.push_str(&format!("&state={}", urlencoding::encode(&state_param)));
+    if !chat_search::is_searchable(&params.q) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": "Search text is empty" })),
+        )
+            .into_response();
     }
+    let cursor = match params.cursor.as_deref() {
+        None => None,
+        Some(token) => match MessageCursor::decode(token) {
+            Some(cursor) => Some(cursor),
+            None => return invalid_cursor(),
+        },
+    };
+    let limit = params.limit.unwrap_or(50).clamp(1, MAX_CHAT_SEARCH_LIMIT);
 
-    Redirect::to(&redirect_url).into_response()
+    match state
+        .store
+        .search_chat(&contact_id, &params.q, limit, cursor)
+    {
+        Ok(page) => Json(ChatSearchResponse {
+            hits: page.hits,
+            total_matches: page.total_matches,
+            has_more: page.next_cursor.is_some(),
+            next_cursor: page.next_cursor.map(|c| c.encode()),
+        })
+        .into_response(),
+        Err(e) => {
+            error!("Failed to search chat: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to search chat" })),
+            )
+                .into_response()
+        }
+    }
 }
 
-/// Build error redirect URL
-fn build_error_redirect(redirect_uri: &str, error: OAuthError, state: Option<&str>) -> String {
-    let mut url = format!(
-        "{}?error={}&error_description={}",
-        redirect_uri,
-        error.as_str(),
-        urlencoding::encode(error.description())
-    );
-    if let Some(s) = state {
-        url.push_str(&format!("&state={}", urlencoding::encode(s)));
+/// Get the vocabulary picked out of a chat in learning mode, with how often
+/// each term came up
+async fn get_vocabulary(
+    State(state): State<Arc<AppState>>,
+    Path(contact_id): Path<String>,
+) -> impl IntoResponse {
+    let contact_id = urlencoding::decode(&contact_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(contact_id);
+
+    match state.store.get_vocabulary(&contact_id) {
+        Ok(vocabulary) => Json(vocabulary).into_response(),
+        Err(e) => {
+            error!("Failed to get vocabulary: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error": "Failed to get vocabulary"})),
+            )
+                .into_response()
+        }
     }
-    url
 }
 
-/// OAuth Token endpoint - exchange code for tokens or refresh tokens
-async fn oauth_token(
+/// Maximum number of mentions returned at once
+const MAX_MENTIONS_LIMIT: u32 = 200;
+
+/// Maximum number of triaged messages returned at once
+const MAX_TRIAGE_LIMIT: u32 = 200;
+
+/// Maximum number of contact history entries returned at once
+const MAX_CONTACT_HISTORY_LIMIT: u32 = 200;
+
+/// Query parameters for a contact's change history
+#[derive(Debug, Deserialize)]
+struct ContactHistoryQuery {
+    /// Maximum number of changes to return (default: 50)
+    limit: Option<u32>,
+}
+
+/// Get the recorded changes to a contact's name, phone and type, newest first
+async fn get_contact_history(
     State(state): State<Arc<AppState>>,
-    Form(req): Form<TokenRequest>,
+    Path(contact_id): Path<String>,
+    Query(params): Query<ContactHistoryQuery>,
 ) -> impl IntoResponse {
-    match req.grant_type.as_str() {
-        "authorization_code" => handle_authorization_code_grant(state, req).await,
-        "refresh_token" => handle_refresh_token_grant(state, req).await,
-        _ => {
-            let error = OAuthErrorResponse::from(OAuthError::UnsupportedGrantType);
-            (StatusCode::BAD_REQUEST, Json(error)).into_response()
+    let contact_id = urlencoding::decode(&contact_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(contact_id);
+    let limit = params
+        .limit
+        .unwrap_or(50)
+        .clamp(1, MAX_CONTACT_HISTORY_LIMIT);
+
+    match state.store.get_contact_events(&contact_id, limit) {
+        Ok(events) => Json(events).into_response(),
+        Err(e) => {
+            error!("Failed to get contact history: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to get contact history",
+            )
+                .into_response()
         }
     }
 }
 
-async fn handle_authorization_code_grant(
-    state: Arc<AppState>,
-    req: TokenRequest,
-) -> axum::response::Response {
-    // Validate required parameters
-    let code = match &req.code {
-        Some(c) => c,
-        None => {
-            let error = OAuthErrorResponse::from(OAuthError::InvalidRequest);
-            return (StatusCode::BAD_REQUEST, Json(error)).into_response();
-        }
-    };
+/// Query parameters for a conversation's translation history
+#[derive(Debug, Deserialize)]
+struct TranslationsQuery {
+    /// Maximum number of translations to return (default: 50, 0 for all;
+    /// CSV exports default to all)
+    limit: Option<u32>,
+    /// Only get translations before this timestamp (for loading older ones)
+    before: Option<i64>,
+    /// "json" (default) or "csv"
+    format: Option<String>,
+    /// Scrub names, numbers and addresses from the text, with placeholders
+    /// that are stable within this export only
+    #[serde(default)]
+    anonymize: bool,
+}
 
-    let code_verifier = match &req.code_verifier {
-        Some(v) => v,
-        None => {
-            let error = OAuthErrorResponse::from(OAuthError::InvalidRequest);
-            return (StatusCode::BAD_REQUEST, Json(error)).into_response();
-        }
-    };
+/// Response for a page of translation history
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TranslationsResponse {
+    translations: Vec<TranslationPair>,
+    has_more: bool,
+}
 
-    let redirect_uri = match &req.redirect_uri {
-        Some(r) => r,
-        None => {
-            let error = OAuthErrorResponse::from(OAuthError::InvalidRequest);
-            return (StatusCode::BAD_REQUEST, Json(error)).into_response();
+/// Get a conversation's translated messages as original/translation pairs,
+/// oldest first, as JSON or as CSV for flashcard tools
+async fn get_translations(
+    State(state): State<Arc<AppState>>,
+    Path(contact_id): Path<String>,
+    Query(params): Query<TranslationsQuery>,
+) -> impl IntoResponse {
+    let contact_id = urlencoding::decode(&contact_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(contact_id);
+    let csv = match params.format.as_deref() {
+        None | Some("json") => false,
+        Some("csv") => true,
+        Some(other) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "error": format!("Unknown format {:?}, expected json or csv", other)
+                })),
+            )
+                .into_response()
         }
     };
+    let limit = match params.limit {
+        Some(0) => None,
+        Some(n) => Some(n),
+        None if csv => None,
+        None => Some(50),
+    };
 
-    // Get and validate the authorization code
-    let auth_code = match state.store.oauth_use_authorization_code(code) {
-        Ok(Some(c)) => c,
-        Ok(None) => {
-            let error = OAuthErrorResponse::from(OAuthError::InvalidGrant);
-            return (StatusCode::BAD_REQUEST, Json(error)).into_response();
-        }
+    let translations = match state
+        .store
+        .get_translations(&contact_id, limit, params.before)
+        .and_then(|translations| match params.anonymize {
+            true => anonymize_translations(&state, translations),
+            false => Ok(translations),
+        }) {
+        Ok(translations) => translations,
         Err(e) => {
-            error!("Failed to get authorization code: {}", e);
-            let error = OAuthErrorResponse::from(OAuthError::ServerError);
-            return (StatusCode::INTERNAL_SERVER_ERROR, Json(error)).into_response();
+            error!("Failed to get translations: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to get translations" })),
+            )
+                .into_response();
         }
     };
 
-    // Verify redirect_uri matches
-    if auth_code.redirect_uri != *redirect_uri {
-        let error = OAuthErrorResponse::from(OAuthError::InvalidGrant);
-        return (StatusCode::BAD_REQUEST, Json(error)).into_response();
-    }
-
-    // Verify PKCE
-    if !auth_code.verify_pkce(code_verifier) {
-        let error = OAuthErrorResponse::from(OAuthError::InvalidGrant);
-        return (StatusCode::BAD_REQUEST, Json(error)).into_response();
+    if csv {
+        let filename = format!(
+            "attachment; filename=\"translations-{}.csv\"",
+            match params.anonymize {
+                true => "anonymized",
+                false => contact_id.split('@').next().unwrap_or("chat"),
+            }
+        );
+        return (
+            [
+                (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+                (header::CONTENT_DISPOSITION, filename),
+            ],
+            translations_csv(&translations),
+        )
+            .into_response();
     }
 
-    // Generate tokens
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as i64;
-
-    let access_token_str = generate_token();
-    let refresh_token_str = generate_token();
+    let has_more = limit.is_some_and(|l| translations.len() >= l as usize);
+    Json(TranslationsResponse {
+        translations,
+        has_more,
+    })
+    .into_response()
+}
 
-    let access_token = AccessToken {
-        token: access_token_str.clone(),
-        client_id: auth_code.client_id.clone(),
-        scope: auth_code.scope.clone(),
-        created_at: now,
-        expires_at: now + 3600, // 1 hour
-    };
+/// Translations with every contact's name, and any numbers or addresses,
+/// scrubbed from their text under a fresh salt
+fn anonymize_translations(
+    state: &AppState,
+    translations: Vec<TranslationPair>,
+) -> anyhow::Result<Vec<TranslationPair>> {
+    let contacts = state.store.get_contacts()?;
+    let anonymizer =
+        Anonymizer::random().with_names(contacts.iter().filter_map(|c| c.name.as_deref()));
+    Ok(translations
+        .into_iter()
+        .map(|t| TranslationPair {
+            original_text: t.original_text.map(|text| anonymizer.scrub(&text)),
+            translated_text: t.translated_text.map(|text| anonymizer.scrub(&text)),
+            ..t
+        })
+        .collect())
+}
 
-    let refresh_token = RefreshToken {
-        token: refresh_token_str.clone(),
-        client_id: auth_code.client_id.clone(),
-        scope: auth_code.scope.clone(),
-        created_at: now,
-        expires_at: now + 30 * 24 * 3600, // 30 days
+/// Translations as CSV with columns original, translation, language, date
+fn translations_csv(translations: &[TranslationPair]) -> String {
+    let field = |value: &str| {
+        if value.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
     };
 
-    // Store tokens
-    if let Err(e) = state.store.oauth_store_access_token(&access_token) {
-        error!("Failed to store access token: {}", e);
-        let error = OAuthErrorResponse::from(OAuthError::ServerError);
-        return (StatusCode::INTERNAL_SERVER_ERROR, Json(error)).into_response();
+    let mut csv = String::from("original,translation,language,date\r\n");
+    for t in translations {
+        let date = chrono::DateTime::from_timestamp_millis(t.timestamp)
+            .map(|d| d.format("%Y-%m-%d").to_string())
+            .unwrap_or_default();
+        csv.push_str(&format!(
+            "{},{},{},{}\r\n",
+            field(t.original_text.as_deref().unwrap_or("")),
+            field(t.translated_text.as_deref().unwrap_or("")),
+            field(t.source_language.as_deref().unwrap_or("")),
+            date
+        ));
     }
+    csv
+}
 
-    if let Err(e) = state.store.oauth_store_refresh_token(&refresh_token) {
-        error!("Failed to store refresh token: {}", e);
-        let error = OAuthErrorResponse::from(OAuthError::ServerError);
-        return (StatusCode::INTERNAL_SERVER_ERROR, Json(error)).into_response();
+/// Query parameters for the mentions view
+#[derive(Debug, Deserialize)]
+struct MentionsQuery {
+    /// Only get mentions after this timestamp (milliseconds)
+    since: Option<i64>,
+    /// Maximum number of mentions to return (default: 50)
+    limit: Option<u32>,
+}
+
+/// Get messages that mention me across all chats, newest first
+async fn get_mentions(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<MentionsQuery>,
+) -> impl IntoResponse {
+    let limit = params.limit.unwrap_or(50).clamp(1, MAX_MENTIONS_LIMIT);
+
+    match state.store.get_mentions(params.since, limit) {
+        Ok(messages) => {
+            let has_more = messages.len() >= limit as usize;
+            Json(MessagesResponse {
+                messages,
+                has_more,
+                next_cursor: None,
+                pinned_messages: None,
+            })
+            .into_response()
+        }
+        Err(e) => {
+            error!("Failed to get mentions: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to get mentions").into_response()
+        }
     }
+}
 
-    info!("OAuth tokens issued for client: {}", auth_code.client_id);
+/// Query parameters for the triage view
+#[derive(Debug, Deserialize)]
+struct TriageQuery {
+    /// Lowest urgency to include: low, normal or high (default: high)
+    urgency: Option<String>,
+    /// Only include messages with this tone
+    tone: Option<String>,
+    /// Only get messages after this timestamp (milliseconds)
+    since: Option<i64>,
+    /// Maximum number of messages to return (default: 50)
+    limit: Option<u32>,
+}
 
-    let response = TokenResponse {
-        access_token: access_token_str,
-        token_type: "Bearer".to_string(),
-        expires_in: 3600,
-        refresh_token: refresh_token_str,
-        scope: auth_code.scope,
+/// Get incoming messages tagged at or above an urgency, newest first
+async fn get_triage(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<TriageQuery>,
+) -> impl IntoResponse {
+    let urgency = match params.urgency.as_deref() {
+        None => Urgency::High,
+        Some(value) => match Urgency::parse(value) {
+            Some(urgency) => urgency,
+            None => return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "error": format!("Unknown urgency {:?}, expected low, normal or high", value)
+                })),
+            )
+                .into_response(),
+        },
+    };
+    let tone = match params.tone.as_deref() {
+        None => None,
+        Some(value) => match Tone::parse(value) {
+            Some(tone) => Some(tone),
+            None => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(serde_json::json!({
+                        "error": format!("Unknown tone {:?}, expected positive, neutral or negative", value)
+                    })),
+                )
+                    .into_response()
+            }
+        },
     };
+    let limit = params.limit.unwrap_or(50).clamp(1, MAX_TRIAGE_LIMIT);
 
-    (
-        StatusCode::OK,
-        [(header::CACHE_CONTROL, "no-store")],
-        Json(response),
-    )
-        .into_response()
+    match state
+        .store
+        .get_triaged_messages(urgency, tone, params.since, limit)
+    {
+        Ok(messages) => {
+            let has_more = messages.len() >= limit as usize;
+            Json(MessagesResponse {
+                messages,
+                has_more,
+                next_cursor: None,
+                pinned_messages: None,
+            })
+            .into_response()
+        }
+        Err(e) => {
+            error!("Failed to get triaged messages: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to get triaged messages" })),
+            )
+                .into_response()
+        }
+    }
 }
 
-async fn handle_refresh_token_grant(
-    state: Arc<AppState>,
-    req: TokenRequest,
-) -> axum::response::Response {
-    let refresh_token_str = match &req.refresh_token {
-        Some(r) => r,
-        None => {
-            let error = OAuthErrorResponse::from(OAuthError::InvalidRequest);
-            return (StatusCode::BAD_REQUEST, Json(error)).into_response();
+/// Media query: `format=lottie` asks for a Lottie sticker's animation JSON
+#[derive(Deserialize)]
+struct MediaQuery {
+    format: Option<String>,
+}
+
+/// Get media data for a specific message (lazy loaded)
+async fn get_media(
+    State(state): State<Arc<AppState>>,
+    Path(message_id): Path<String>,
+    Query(query): Query<MediaQuery>,
+) -> impl IntoResponse {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    let message_id = urlencoding::decode(&message_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(message_id);
+
+    // View-once media is served a single time, then only its metadata remains
+    if let Some((media_data, mime_type)) = state.view_once.take(&message_id) {
+        let viewed_at = chrono::Utc::now().timestamp_millis();
+        if let Err(e) = state.store.mark_view_once_viewed(&message_id, viewed_at) {
+            error!("Failed to mark view-once message as viewed: {}", e);
         }
-    };
+        return Json(serde_json::json!({
+            "media_data": media_data,
+            "mime_type": mime_type,
+            "view_once": true
+        }))
+        .into_response();
+    }
 
-    // Validate refresh token
-    let refresh_token = match state.store.oauth_get_refresh_token(refresh_token_str) {
-        Ok(Some(t)) => t,
-        Ok(None) => {
-            let error = OAuthErrorResponse::from(OAuthError::InvalidGrant);
-            return (StatusCode::BAD_REQUEST, Json(error)).into_response();
+    match state.store.get_message_media(&message_id) {
+        Ok(Some((media_data, _))) if query.format.as_deref() == Some("lottie") => {
+            let bytes = STANDARD.decode(media_data.trim()).unwrap_or_default();
+            match crate::sticker::lottie_json(&bytes) {
+                Some(json) => ([(header::CONTENT_TYPE, "application/json")], json).into_response(),
+                None => (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "Media isn't a Lottie animation",
+                )
+                    .into_response(),
+            }
+        }
+        Ok(Some((media_data, mime_type))) => {
+            // WebP stickers stored before their format was detected may be
+            // labelled with the wrong type; the header says which they are
+            let header_bytes = STANDARD
+                .decode(media_data.get(..32).unwrap_or_default())
+                .unwrap_or_default();
+            let mime_type = match crate::sticker::detect_webp(&header_bytes) {
+                Some(format) => Some(format.mime_type().to_string()),
+                None => mime_type,
+            };
+            // Return the base64 media data and mime type
+            Json(serde_json::json!({
+                "media_data": media_data,
+                "mime_type": mime_type
+            }))
+            .into_response()
+        }
+        Ok(None)
+            if state
+                .store
+                .is_view_once_message(&message_id)
+                .unwrap_or(false) =>
+        {
+            (
+                StatusCode::GONE,
+                "View-once media was already opened or has expired",
+            )
+                .into_response()
         }
+        Ok(None) => (StatusCode::NOT_FOUND, "Media not found").into_response(),
         Err(e) => {
-            error!("Failed to get refresh token: {}", e);
-            let error = OAuthErrorResponse::from(OAuthError::ServerError);
-            return (StatusCode::INTERNAL_SERVER_ERROR, Json(error)).into_response();
+            error!("Failed to get media: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to get media").into_response()
         }
-    };
+    }
+}
 
-    // Generate new access token
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as i64;
+/// Delete a message from local storage (does not revoke it on WhatsApp)
+async fn delete_message(
+    State(state): State<Arc<AppState>>,
+    Path((contact_id, message_id)): Path<(String, String)>,
+) -> impl IntoResponse {
+    let contact_id = urlencoding::decode(&contact_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(contact_id);
+    let message_id = urlencoding::decode(&message_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(message_id);
 
-    let access_token_str = generate_token();
+    match state.store.delete_message(&contact_id, &message_id) {
+        Ok(true) => Json(serde_json::json!({ "success": true })).into_response(),
+        Ok(false) => (StatusCode::NOT_FOUND, "Message not found").into_response(),
+        Err(e) => {
+            error!("Failed to delete message: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to delete message",
+            )
+                .into_response()
+        }
+    }
+}
 
-    let access_token = AccessToken {
-        token: access_token_str.clone(),
-        client_id: refresh_token.client_id.clone(),
-        scope: refresh_token.scope.clone(),
-        created_at: now,
-        expires_at: now + 3600, // 1 hour
+/// Pin request: the chat the message is pinned in
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PinMessageRequest {
+    pub contact_id: String,
+}
+
+/// Response for a failed pin or unpin
+fn pin_error(e: PinError) -> axum::response::Response {
+    let status = match e {
+        PinError::MessageNotFound | PinError::NotPinned => StatusCode::NOT_FOUND,
+        PinError::OtherChat => StatusCode::BAD_REQUEST,
+        PinError::LimitReached => StatusCode::CONFLICT,
+        PinError::StorageError => StatusCode::INTERNAL_SERVER_ERROR,
     };
+    (
+        status,
+        Json(serde_json::json!({
+            "success": false,
+            "error": e.as_str(),
+            "errorDescription": e.description(),
+        })),
+    )
+        .into_response()
+}
 
-    // Store new access token
-    if let Err(e) = state.store.oauth_store_access_token(&access_token) {
-        error!("Failed to store access token: {}", e);
-        let error = OAuthErrorResponse::from(OAuthError::ServerError);
-        return (StatusCode::INTERNAL_SERVER_ERROR, Json(error)).into_response();
+/// Pin a message to the top of its chat
+async fn pin_message(
+    State(state): State<Arc<AppState>>,
+    Path(message_id): Path<String>,
+    Json(req): Json<PinMessageRequest>,
+) -> impl IntoResponse {
+    let message_id = urlencoding::decode(&message_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(message_id);
+
+    match pins::pin_message(&state.store, &req.contact_id, &message_id, "web") {
+        Ok(pinned_messages) => {
+            let contact_id = state
+                .store
+                .resolve_contact_id(&req.contact_id)
+                .unwrap_or(req.contact_id);
+            let _ = state.broadcast_tx.send(WebSocketEvent::PinsUpdated {
+                contact_id,
+                pinned_messages: pinned_messages.clone(),
+            });
+            Json(serde_json::json!({
+                "success": true,
+                "pinnedMessages": pinned_messages,
+            }))
+            .into_response()
+        }
+        Err(e) => pin_error(e),
     }
+}
 
-    info!(
-        "OAuth access token refreshed for client: {}",
-        refresh_token.client_id
-    );
+/// Unpin a message
+async fn unpin_message(
+    State(state): State<Arc<AppState>>,
+    Path(message_id): Path<String>,
+) -> impl IntoResponse {
+    let message_id = urlencoding::decode(&message_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(message_id);
 
-    let response = TokenResponse {
-        access_token: access_token_str,
-        token_type: "Bearer".to_string(),
-        expires_in: 3600,
-        refresh_token: refresh_token_str.clone(),
-        scope: refresh_token.scope,
+    match pins::unpin_message(&state.store, &message_id) {
+        Ok((contact_id, pinned_messages)) => {
+            let _ = state.broadcast_tx.send(WebSocketEvent::PinsUpdated {
+                contact_id,
+                pinned_messages: pinned_messages.clone(),
+            });
+            Json(serde_json::json!({
+                "success": true,
+                "pinnedMessages": pinned_messages,
+            }))
+            .into_response()
+        }
+        Err(e) => pin_error(e),
+    }
+}
+
+/// Take back a send that is still in its undo window: it's never sent and
+/// the stored message is removed. 410 once it has gone to WhatsApp
+async fn undo_send(
+    State(state): State<Arc<AppState>>,
+    Path(message_id): Path<String>,
+) -> impl IntoResponse {
+    let message_id = urlencoding::decode(&message_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(message_id);
+
+    let Some(send) = state.undo_queue.cancel(&message_id) else {
+        return match state.store.get_message_by_id(&message_id) {
+            Ok(Some(message)) if message.is_from_me => (
+                StatusCode::GONE,
+                Json(serde_json::json!({ "error": "The message has already been sent" })),
+            )
+                .into_response(),
+            Ok(_) => (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({ "error": "Message not found" })),
+            )
+                .into_response(),
+            Err(e) => {
+                error!("Failed to get message: {}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(serde_json::json!({ "error": "Failed to get message" })),
+                )
+                    .into_response()
+            }
+        };
     };
 
-    (
-        StatusCode::OK,
-        [(header::CACHE_CONTROL, "no-store")],
-        Json(response),
-    )
-        .into_response()
+    if let Err(e) = state
+        .store
+        .delete_message(&send.message.contact_id, &message_id)
+    {
+        error!("Failed to delete undone message: {}", e);
+    }
+    info!(
+        "Undid send of {} to {}",
+        message_id, send.message.contact_id
+    );
+    let _ = state.broadcast_tx.send(WebSocketEvent::MessageRemoved {
+        contact_id: send.message.contact_id,
+        message_id,
+    });
+    Json(serde_json::json!({ "success": true })).into_response()
+}
+
+/// Query parameters for clearing a conversation
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ClearConversationQuery {
+    /// Must be true; guards against clearing a chat by accident
+    #[serde(default)]
+    confirm: bool,
+    /// Also delete the conversation's translation usage
+    #[serde(default)]
+    include_usage: bool,
 }
 
-/// OAuth Token revocation endpoint
-async fn oauth_revoke(
-    State(state): State<Arc<AppState>>,
-    Form(req): Form<RevokeRequest>,
-) -> impl IntoResponse {
-    // Revoke the token (we don't care if it exists or not per RFC 7009)
-    if let Err(e) = state.store.oauth_revoke_token(&req.token) {
-        error!("Failed to revoke token: {}", e);
-        // Still return 200 per RFC 7009
+/// Delete a conversation's history from local storage, keeping the contact
+async fn clear_conversation(
+    State(state): State<Arc<AppState>>,
+    Path(contact_id): Path<String>,
+    Query(query): Query<ClearConversationQuery>,
+) -> impl IntoResponse {
+    let contact_id = urlencoding::decode(&contact_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(contact_id);
+
+    if !query.confirm {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": "Clearing a conversation requires confirm=true" })),
+        )
+            .into_response();
+    }
+
+    let contact_id = state
+        .store
+        .resolve_contact_id(&contact_id)
+        .unwrap_or(contact_id);
+    match state
+        .store
+        .clear_conversation(&contact_id, query.include_usage)
+    {
+        Ok(Some(cleared)) => {
+            let _ = state
+                .broadcast_tx
+                .send(WebSocketEvent::ConversationCleared { contact_id });
+            Json(cleared).into_response()
+        }
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": "Contact not found" })),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Failed to clear conversation: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to clear conversation" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+async fn get_qr(State(state): State<Arc<AppState>>) -> Json<QrResponse> {
+    Json(QrResponse {
+        qr: state.qr_code.read().await.clone(),
+    })
+}
+
+/// Avatar response
+#[derive(Serialize)]
+struct AvatarResponse {
+    url: Option<String>,
+}
+
+async fn get_avatar(
+    State(state): State<Arc<AppState>>,
+    Path(jid): Path<String>,
+) -> impl IntoResponse {
+    let jid = urlencoding::decode(&jid)
+        .map(|s| s.into_owned())
+        .unwrap_or(jid);
+
+    // Check if connected
+    if !*state.connected.read().await {
+        return Json(AvatarResponse { url: None }).into_response();
+    }
+
+    let url = state.get_profile_picture(&jid).await;
+    Json(AvatarResponse { url }).into_response()
+}
+
+/// Batch avatar request
+#[derive(Deserialize)]
+struct AvatarBatchRequest {
+    jids: Vec<String>,
+}
+
+/// Batch avatar response
+#[derive(Serialize)]
+struct AvatarBatchResponse {
+    avatars: HashMap<String, AvatarLookup>,
+}
+
+/// Return cached avatars for a list of JIDs immediately. Unknown ones are
+/// fetched in the background and delivered as `avatar` WebSocket events.
+async fn get_avatars(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<AvatarBatchRequest>,
+) -> impl IntoResponse {
+    if req.jids.len() > MAX_AVATAR_BATCH {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": format!("At most {} JIDs per request", MAX_AVATAR_BATCH)
+            })),
+        )
+            .into_response();
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let (avatars, unknown) = {
+        let mut cache = state.avatar_cache.write().await;
+        partition_avatars(&mut cache, &req.jids, now)
+    };
+
+    // Fetching needs the bridge; unknowns stay unknown while disconnected
+    if !unknown.is_empty() && *state.connected.read().await {
+        state.queue_avatar_fetches(unknown).await;
+    }
+
+    Json(AvatarBatchResponse { avatars }).into_response()
+}
+
+pub(crate) async fn send_message(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(req): Json<SendMessageRequest>,
+) -> impl IntoResponse {
+    let sent_by = state.session_name(&headers).await;
+    match send_text(&state, req, sent_by).await {
+        Ok(sent) => Json(sent).into_response(),
+        Err(SendTextError::Rejected(status, error)) => {
+            (status, Json(serde_json::json!({ "error": error }))).into_response()
+        }
+        Err(SendTextError::NeedsConfirmation {
+            token,
+            detected_language,
+            chat_language,
+            text_to_send,
+        }) => (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({
+                "error": "The message doesn't match the chat's language",
+                "confirmationRequired": true,
+                "confirmationToken": token,
+                "detectedLanguage": detected_language,
+                "chatLanguage": chat_language,
+                "textToSend": text_to_send,
+            })),
+        )
+            .into_response(),
+    }
+}
+
+/// Why `send_text` didn't send a message
+pub(crate) enum SendTextError {
+    /// Refused, with the status and error to report
+    Rejected(StatusCode, String),
+    /// Held back because it doesn't match the chat's language; sending again
+    /// with the token sends it anyway
+    NeedsConfirmation {
+        token: String,
+        detected_language: Option<String>,
+        chat_language: Option<String>,
+        text_to_send: String,
+    },
+}
+
+/// Send a text message typed in the web UI (the JSON API or `/lite`) by
+/// the session named `sent_by`: the language guard, the undo window,
+/// translation and the bridge
+pub(crate) async fn send_text(
+    state: &Arc<AppState>,
+    req: SendMessageRequest,
+    sent_by: Option<String>,
+) -> Result<SendMessageResponse, SendTextError> {
+    let rejected =
+        |status: StatusCode, error: &str| SendTextError::Rejected(status, error.to_string());
+    if req.contact_id.is_empty() || req.text.is_empty() {
+        return Err(rejected(
+            StatusCode::BAD_REQUEST,
+            "contact_id and text are required",
+        ));
+    }
+
+    // Channels are read-only for subscribers
+    if is_channel_jid(&req.contact_id) {
+        return Err(rejected(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Channels are read-only",
+        ));
+    }
+
+    // Check if connected
+    if !*state.connected.read().await {
+        return Err(rejected(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Not connected to WhatsApp",
+        ));
+    }
+
+    // A confirmation token sends exactly what was previously held back
+    let confirmed = match &req.confirmation_token {
+        Some(token) => match state
+            .confirmations
+            .take(token, &req.contact_id, &req.text)
+            .await
+        {
+            Some(send) => Some(send),
+            None => {
+                return Err(rejected(
+                    StatusCode::BAD_REQUEST,
+                    "Invalid or expired confirmation token",
+                ));
+            }
+        },
+        None => None,
+    };
+    let is_confirmed = confirmed.is_some();
+
+    // With an undo window the message is translated when it's dispatched, so
+    // an undone message costs nothing. The language guard needs the
+    // translation up front though.
+    let undo_window_secs = state.store.get_undo_window_secs().unwrap_or_else(|e| {
+        error!("Failed to get undo window: {}", e);
+        0
+    });
+    let translate_at_dispatch = undo_window_secs > 0
+        && !is_confirmed
+        && !(state.translator.is_some() && state.language_guard.web);
+
+    let message = OutgoingMessage {
+        id: pending_message_id(),
+        contact_id: req.contact_id.clone(),
+        text: req.text.clone(),
+        reply: req.reply_to.clone().map(|message_id| ReplyTo {
+            text: reply_preview(&state.store, &message_id).or_else(|| req.reply_to_text.clone()),
+            message_id,
+            sender: req.reply_to_sender.clone(),
+        }),
+        origin: "web".to_string(),
+        sent_by,
+    };
+    let reply_preview = message.reply.as_ref().and_then(|reply| reply.text.clone());
+
+    // Determine the text to send - translate if needed based on conversation settings or language
+    let outgoing = match confirmed {
+        Some(send) => Some(OutgoingText::confirmed(
+            send.text_to_send,
+            send.target_language,
+        )),
+        None if translate_at_dispatch => None,
+        None => Some(state.sending.translate(&message).await),
+    };
+
+    // Hold the message back if it doesn't match the chat's language
+    if let (Some(translator), false, true, Some(outgoing)) = (
+        &state.translator,
+        is_confirmed,
+        state.language_guard.web,
+        &outgoing,
+    ) {
+        if let (Some(mismatch), _) = check_language(
+            &state.store,
+            translator,
+            &req.contact_id,
+            &outgoing.text_to_send,
+            outgoing.target_language.as_deref(),
+            "detect_language_send",
+        )
+        .await
+        {
+            let token = state
+                .confirmations
+                .insert(PendingSend::new(
+                    &req.contact_id,
+                    &req.text,
+                    &outgoing.text_to_send,
+                    outgoing.target_language.clone(),
+                ))
+                .await;
+            return Err(SendTextError::NeedsConfirmation {
+                token,
+                detected_language: mismatch.detected_language,
+                chat_language: mismatch.chat_language,
+                text_to_send: outgoing.text_to_send.clone(),
+            });
+        }
+    }
+
+    // Send the message via bridge, or once the undo window has passed
+    let (stored_msg, undoable_until) = if undo_window_secs > 0 {
+        let stored_msg = state.sending.record(&message, outgoing.as_ref());
+        state.queue_undoable_send(QueuedSend {
+            message_id: stored_msg.id.clone(),
+            message,
+            outgoing: outgoing.clone(),
+            dispatch_at: tokio::time::Instant::now()
+                + std::time::Duration::from_secs(undo_window_secs),
+        });
+        let undoable_until = stored_msg.timestamp + undo_window_secs as i64 * 1000;
+        (stored_msg, Some(undoable_until))
+    } else {
+        let sent = match state.command_tx.read().await.clone() {
+            Some(command_tx) => state.sending.send(&command_tx, &message, outgoing).await,
+            None => Err(anyhow::anyhow!("Bridge not connected")),
+        };
+        match sent {
+            Ok((stored_msg, _)) => {
+                state.connection.send_started(&stored_msg.id);
+                (stored_msg, None)
+            }
+            Err(e) => {
+                state.report_error(
+                    ErrorCategory::Send,
+                    format!("Failed to send message: {}", e),
+                );
+                return Err(SendTextError::Rejected(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to send message: {}", e),
+                ));
+            }
+        }
+    };
+
+    // Storing the sent message cleared the chat's draft; let other sessions know
+    let _ = state.broadcast_tx.send(WebSocketEvent::DraftUpdated {
+        contact_id: req.contact_id.clone(),
+        draft: None,
+    });
+
+    // Note: We don't broadcast sent messages - the frontend displays them immediately.
+    // The message is stored in the DB so it will appear when the conversation is reloaded.
+
+    let warning = if req.ignore_quiet_hours {
+        None
+    } else {
+        tzinfer::night_send_warning(&state.store, &req.contact_id)
+    };
+
+    Ok(SendMessageResponse {
+        message_id: stored_msg.id,
+        timestamp: stored_msg.timestamp,
+        is_translated: stored_msg.is_translated,
+        translated_text: stored_msg.translated_text,
+        source_language: stored_msg.source_language,
+        undoable_until,
+        sort_key: stored_msg.sort_key,
+        warning,
+        connection_quality: state.connection.assess().class,
+        learning_notes: stored_msg
+            .vocabulary
+            .unwrap_or_default()
+            .into_iter()
+            .map(Into::into)
+            .collect(),
+        reply_preview,
+    })
+}
+
+/// Text standing in for a stored message quoted by a reply, None if it isn't
+/// stored
+fn reply_preview(store: &MessageStore, message_id: &str) -> Option<String> {
+    match store.get_message_by_id(message_id) {
+        Ok(message) => message.map(|message| MessageStore::generate_reply_preview(&message)),
+        Err(e) => {
+            error!("Failed to look up replied message: {}", e);
+            None
+        }
+    }
+}
+
+/// Start a chunked upload request
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateUploadRequest {
+    /// Size of the whole image in bytes
+    size: u64,
+}
+
+/// Complete a chunked upload request
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CompleteUploadRequest {
+    /// SHA-256 of the whole image, hex encoded
+    sha256: String,
+}
+
+fn upload_error(e: UploadError) -> axum::response::Response {
+    let status = match e {
+        UploadError::NotFound => StatusCode::NOT_FOUND,
+        UploadError::Empty | UploadError::InvalidChunk | UploadError::WrongChunkSize => {
+            StatusCode::BAD_REQUEST
+        }
+        UploadError::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+        UploadError::Incomplete | UploadError::NotCompleted => StatusCode::CONFLICT,
+        UploadError::HashMismatch => StatusCode::UNPROCESSABLE_ENTITY,
+        UploadError::StorageError => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (
+        status,
+        Json(serde_json::json!({
+            "success": false,
+            "error": e.as_str(),
+            "errorDescription": e.description(),
+        })),
+    )
+        .into_response()
+}
+
+/// Start a chunked image upload, returning its ID and chunk size
+async fn create_upload(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<CreateUploadRequest>,
+) -> impl IntoResponse {
+    match state
+        .uploads
+        .create(req.size, chrono::Utc::now().timestamp_millis())
+    {
+        Ok(upload) => (StatusCode::CREATED, Json(upload)).into_response(),
+        Err(e) => upload_error(e),
+    }
+}
+
+/// Where an upload stands, with the chunks received so far
+async fn get_upload(
+    State(state): State<Arc<AppState>>,
+    Path(upload_id): Path<String>,
+) -> impl IntoResponse {
+    match state
+        .uploads
+        .get(&upload_id, chrono::Utc::now().timestamp_millis())
+    {
+        Ok(upload) => Json(upload).into_response(),
+        Err(e) => upload_error(e),
+    }
+}
+
+/// Store one chunk of an upload (the raw bytes), in any order
+async fn put_upload_chunk(
+    State(state): State<Arc<AppState>>,
+    Path((upload_id, index)): Path<(String, u32)>,
+    body: axum::body::Bytes,
+) -> impl IntoResponse {
+    match state.uploads.put_chunk(
+        &upload_id,
+        index,
+        &body,
+        chrono::Utc::now().timestamp_millis(),
+    ) {
+        Ok(upload) => Json(upload).into_response(),
+        Err(e) => upload_error(e),
+    }
+}
+
+/// Finish an upload, checking the image against its SHA-256
+async fn complete_upload(
+    State(state): State<Arc<AppState>>,
+    Path(upload_id): Path<String>,
+    Json(req): Json<CompleteUploadRequest>,
+) -> impl IntoResponse {
+    match state.uploads.complete(
+        &upload_id,
+        &req.sha256,
+        chrono::Utc::now().timestamp_millis(),
+    ) {
+        Ok(upload) => Json(upload).into_response(),
+        Err(e) => upload_error(e),
+    }
+}
+
+async fn send_image(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(mut req): Json<SendImageRequest>,
+) -> impl IntoResponse {
+    // Validate input
+    if req.contact_id.is_empty() || req.media_data.is_empty() == req.upload_id.is_none() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": "contact_id and either media_data or upload_id are required"
+            })),
+        )
+            .into_response();
+    }
+
+    // Channels are read-only for subscribers
+    if is_channel_jid(&req.contact_id) {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({
+                "error": "Channels are read-only"
+            })),
+        )
+            .into_response();
+    }
+
+    // Check if connected
+    if !*state.connected.read().await {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({
+                "error": "Not connected to WhatsApp"
+            })),
+        )
+            .into_response();
+    }
+
+    if let Some(upload_id) = req.upload_id.clone() {
+        let now = chrono::Utc::now().timestamp_millis();
+        let uploads_state = state.clone();
+        let read =
+            tokio::task::spawn_blocking(move || uploads_state.uploads.read_base64(&upload_id, now))
+                .await
+                .unwrap_or(Err(crate::uploads::UploadError::StorageError));
+        match read {
+            Ok(data) => req.media_data = data,
+            Err(e) => return upload_error(e),
+        }
+    } else if decoded_media_len(&req.media_data).is_none() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": "media_data isn't valid base64"
+            })),
+        )
+            .into_response();
+    }
+
+    let SendImageRequest {
+        contact_id,
+        media_data,
+        upload_id,
+        mime_type,
+        caption,
+        reply_to,
+        reply_to_sender,
+    } = req;
+
+    // The thumbnail is made from a shared handle on the media, and the stored
+    // content is serialized from borrows of it, so the image isn't copied
+    // again before it's moved into the bridge command
+    let media_data = Arc::new(media_data);
+    let thumbnail_source = media_data.clone();
+    let thumbnail =
+        tokio::task::spawn_blocking(move || crate::thumbnail::generate(&thumbnail_source))
+            .await
+            .ok()
+            .flatten();
+    let media_data = Arc::try_unwrap(media_data).unwrap_or_else(|shared| (*shared).clone());
+    let content_json = serde_json::to_string(&SentImageContent {
+        kind: "image",
+        mime_type: &mime_type,
+        caption: caption.as_deref(),
+        media_data: &media_data,
+        thumbnail_data: thumbnail.as_deref(),
+    })
+    .unwrap_or_default();
+
+    // Send the image via bridge
+    let cmd = BridgeCommand::SendImage {
+        request_id: None,
+        to: contact_id.clone(),
+        media_data,
+        mime_type,
+        caption,
+        reply_to,
+        reply_to_sender,
+    };
+
+    if let Err(e) = state.send_bridge_command(cmd).await {
+        state.report_error(ErrorCategory::Send, format!("Failed to send image: {}", e));
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "error": format!("Failed to send image: {}", e)
+            })),
+        )
+            .into_response();
+    }
+    if let Some(upload_id) = upload_id {
+        state.uploads.remove(&upload_id);
+    }
+
+    // Generate a temporary message ID and timestamp for immediate response
+    let timestamp = chrono::Utc::now().timestamp_millis();
+    let temp_message_id = format!("pending_img_{}", timestamp);
+
+    // Get contact info for the recipient
+    let contact_info = state.store.get_contact(&contact_id).ok().flatten();
+    let contact_name = contact_info.as_ref().and_then(|c| c.name.clone());
+    let contact_phone = contact_info.as_ref().and_then(|c| c.phone.clone());
+    let chat_type = contact_info
+        .as_ref()
+        .and_then(|c| c.contact_type.clone())
+        .unwrap_or_else(|| "private".to_string());
+
+    // Store the sent image message locally
+    let stored_msg = crate::storage::StoredMessage {
+        id: temp_message_id.clone(),
+        contact_id: contact_id.clone(),
+        timestamp,
+        is_from_me: true,
+        is_forwarded: false,
+        sender_name: state.name.read().await.clone(),
+        sender_phone: state.phone.read().await.clone(),
+        contact_name,
+        contact_phone,
+        chat_type,
+        content_type: "Image".to_string(),
+        content_json,
+        content: None,
+        original_text: None,
+        translated_text: None,
+        source_language: None,
+        is_translated: false,
+        origin: Some("web".to_string()),
+        sent_by: state.session_name(&headers).await,
+        mentioned_jids: Vec::new(),
+        mentions_me: false,
+        vocabulary: None,
+        audio: None,
+        sort_key: None,
+        triage: None,
+        translation_status: None,
+    };
+
+    // Store the message
+    if let Err(e) = state.store.add_message(&stored_msg) {
+        state.report_error(
+            ErrorCategory::Storage,
+            format!("Failed to store sent image: {}", e),
+        );
+    }
+
+    // Storing the sent message cleared the chat's draft; let other sessions know
+    let _ = state.broadcast_tx.send(WebSocketEvent::DraftUpdated {
+        contact_id,
+        draft: None,
+    });
+
+    // Update contact's last message time (preserve contact name/phone)
+    if let Err(e) = state.store.upsert_contact(
+        &stored_msg.contact_id,
+        stored_msg.contact_name.as_deref(),
+        stored_msg.contact_phone.as_deref(),
+        Some(&stored_msg.chat_type),
+        stored_msg.timestamp,
+    ) {
+        error!("Failed to update contact: {}", e);
+    }
+
+    Json(SendImageResponse {
+        message_id: temp_message_id,
+        timestamp,
+        connection_quality: state.connection.assess().class,
+    })
+    .into_response()
+}
+
+/// Size of the data a base64 string decodes to, or None if it isn't valid
+/// base64. Decoded in small chunks rather than into a copy of the media.
+fn decoded_media_len(data: &str) -> Option<u64> {
+    use base64::engine::general_purpose::STANDARD;
+
+    let mut decoder = base64::read::DecoderReader::new(data.as_bytes(), &STANDARD);
+    std::io::copy(&mut decoder, &mut std::io::sink()).ok()
+}
+
+/// Save a note to my Saved Messages chat, returning the stored message
+async fn save_note(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<SaveNoteRequest>,
+) -> impl IntoResponse {
+    if req
+        .media_data
+        .as_deref()
+        .is_some_and(|data| decoded_media_len(data).is_none())
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "success": false,
+                "error": "media_data isn't valid base64",
+            })),
+        )
+            .into_response();
+    }
+
+    let note = match req.media_data {
+        Some(media_data) => Note::Image {
+            media_data,
+            mime_type: req.mime_type.unwrap_or_else(|| "image/jpeg".to_string()),
+            caption: req.caption.or(req.text).filter(|c| !c.trim().is_empty()),
+        },
+        None => Note::Text(req.text.unwrap_or_default()),
+    };
+
+    // Checked up front like sends, so a disconnected bridge is always a 503
+    let result = if *state.connected.read().await {
+        let command_tx = state.command_tx.read().await.clone();
+        notes::save_note(&state.store, command_tx.as_ref(), note, "web").await
+    } else {
+        Err(NoteError::BridgeUnavailable)
+    };
+
+    match result {
+        Ok(message) => {
+            state.broadcast_message(message.clone(), None);
+            let (content_json, content) =
+                MessageStore::strip_media_from_content(&message.content_json);
+            Json(serde_json::json!({
+                "success": true,
+                "message": StoredMessage {
+                    content_json,
+                    content,
+                    ..message
+                },
+            }))
+            .into_response()
+        }
+        Err(e) => {
+            let status = match e {
+                NoteError::EmptyNote => StatusCode::BAD_REQUEST,
+                NoteError::NoSelfChat | NoteError::BridgeUnavailable => {
+                    StatusCode::SERVICE_UNAVAILABLE
+                }
+                NoteError::StorageError => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            (
+                status,
+                Json(serde_json::json!({
+                    "success": false,
+                    "error": e.as_str(),
+                    "errorDescription": e.description(),
+                })),
+            )
+                .into_response()
+        }
+    }
+}
+
+async fn send_reaction(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<SendReactionRequest>,
+) -> impl IntoResponse {
+    // Validate input
+    if req.contact_id.is_empty() || req.message_id.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": "contact_id and message_id are required"
+            })),
+        )
+            .into_response();
+    }
+
+    // Channels are read-only for subscribers
+    if is_channel_jid(&req.contact_id) {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({
+                "error": "Channels are read-only"
+            })),
+        )
+            .into_response();
+    }
+
+    // Check if connected
+    if !*state.connected.read().await {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({
+                "error": "Not connected to WhatsApp"
+            })),
+        )
+            .into_response();
+    }
+
+    // Store and show it straight away; reconciled when the bridge reports back
+    let request_id = state.next_request_id();
+    let reaction_id = format!("pending_reaction_{}", request_id);
+    let previous = match state
+        .apply_own_reaction(&req.contact_id, &req.message_id, &req.emoji, &reaction_id)
+        .await
+    {
+        Ok(previous) => previous,
+        Err(e) => {
+            state.report_error(
+                ErrorCategory::Storage,
+                format!("Failed to store reaction: {}", e),
+            );
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "error": format!("Failed to store reaction: {}", e)
+                })),
+            )
+                .into_response();
+        }
+    };
+    state.pending_reactions.write().await.insert(
+        request_id,
+        PendingReaction {
+            contact_id: req.contact_id.clone(),
+            message_id: req.message_id.clone(),
+            emoji: req.emoji.clone(),
+            previous,
+        },
+    );
+
+    // Send the reaction via bridge (an empty emoji removes it)
+    let cmd = BridgeCommand::SendReaction {
+        request_id: Some(request_id),
+        to: req.contact_id.clone(),
+        message_id: req.message_id.clone(),
+        sender_jid: req.sender_jid.clone(),
+        emoji: req.emoji.clone(),
+    };
+
+    let result = state
+        .pending_sends
+        .register(request_id, pending::DEFAULT_COMMAND_TIMEOUT);
+    if let Err(e) = state.send_bridge_command(cmd).await {
+        state.report_error(
+            ErrorCategory::Send,
+            format!("Failed to send reaction: {}", e),
+        );
+        state.pending_sends.cancel(request_id);
+        state.reconcile_reaction(request_id, false).await;
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "error": format!("Failed to send reaction: {}", e)
+            })),
+        )
+            .into_response();
+    }
+
+    state.connection.send_started(&request_id.to_string());
+
+    // Keep it if WhatsApp took it, otherwise put the previous reaction back
+    let waiter = state.clone();
+    tokio::spawn(async move {
+        let success = match waiter
+            .pending_sends
+            .wait(request_id, result, pending::DEFAULT_COMMAND_TIMEOUT)
+            .await
+        {
+            Ok(outcome) => {
+                if let Some(e) = outcome.error {
+                    warn!("Reaction {} failed: {}", request_id, e);
+                }
+                outcome.success
+            }
+            Err(e) => {
+                warn!("Reaction {} not confirmed: {}", request_id, e);
+                false
+            }
+        };
+        waiter.reconcile_reaction(request_id, success).await;
+    });
+
+    Json(SendReactionResponse {
+        success: true,
+        reactions: state.reactions_for(&req.contact_id, &req.message_id),
+        connection_quality: state.connection.assess().class,
+    })
+    .into_response()
+}
+
+/// Translate a message manually
+async fn translate_message(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<TranslateMessageRequest>,
+) -> impl IntoResponse {
+    // Check if translation service is available
+    let translator = match &state.translator {
+        Some(t) => t,
+        None => {
+            return Json(TranslateMessageResponse {
+                success: false,
+                translated_text: None,
+                source_language: None,
+                error: Some("Translation service not configured".to_string()),
+                translation_cost_usd: None,
+            })
+            .into_response();
+        }
+    };
+
+    // Get conversation settings for this contact
+    let settings = state
+        .store
+        .get_conversation_settings(&req.contact_id)
+        .unwrap_or_default();
+
+    // Call the translation service with conversation settings
+    let result = translator
+        .process_text(
+            &req.text,
+            settings.language_override.as_deref(),
+            settings.translation_style.as_deref(),
+            false,
+            false,
+        )
+        .await;
+
+    // Record usage if there was API usage
+    if result.usage.input_tokens > 0 {
+        if let Err(e) = state.store.record_usage(
+            Some(&req.contact_id),
+            Some(&req.message_id),
+            &result.usage,
+            "manual_translate",
+        ) {
+            warn!("Failed to record translation usage: {}", e);
+        }
+    }
+
+    // Update the message in the database with the translation
+    if result.needs_translation {
+        if let Err(e) = state.store.update_message_translation(
+            &req.message_id,
+            result.translated_text.as_deref(),
+            Some(&result.source_language),
+        ) {
+            warn!("Failed to update message translation in DB: {}", e);
+        }
+    } else if let Err(e) = state
+        .store
+        .set_translation_status(&req.message_id, result.status)
+    {
+        warn!("Failed to update message translation status in DB: {}", e);
+    }
+
+    // Includes earlier translations of the same message
+    let translation_cost_usd = match state.store.get_message_costs(&[&req.message_id]) {
+        Ok(costs) => Some(
+            costs
+                .get(&req.message_id)
+                .map_or(result.usage.cost_usd, |usage| usage.cost_usd),
+        ),
+        Err(e) => {
+            warn!("Failed to get message cost: {}", e);
+            Some(result.usage.cost_usd)
+        }
+    };
+
+    Json(TranslateMessageResponse {
+        success: true,
+        translated_text: result.translated_text,
+        source_language: Some(result.source_language),
+        error: None,
+        translation_cost_usd,
+    })
+    .into_response()
+}
+
+/// AI compose endpoint - generates a message using Claude
+async fn ai_compose(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<AiComposeRequest>,
+) -> impl IntoResponse {
+    // Check if translation service is available (it has the API key)
+    let translator = match &state.translator {
+        Some(t) => t,
+        None => {
+            return Json(AiComposeResponse {
+                success: false,
+                message: None,
+                error: Some("AI service not configured (missing API key)".to_string()),
+                cost_usd: None,
+            })
+            .into_response();
+        }
+    };
+
+    // Build reply context if provided
+    let reply_context = match (&req.reply_to_sender, &req.reply_to_text) {
+        (Some(sender), Some(text)) => Some((sender.as_str(), text.as_str())),
+        (None, Some(text)) => Some(("Someone", text.as_str())),
+        _ => None,
+    };
+
+    // Build image context if provided
+    let reply_image = match (&req.reply_to_image_type, &req.reply_to_image) {
+        (Some(mime_type), Some(data)) => Some((mime_type.as_str(), data.as_str())),
+        _ => None,
+    };
+
+    let tone = req
+        .contact_id
+        .as_deref()
+        .and_then(|contact_id| state.store.get_conversation_settings(contact_id).ok())
+        .and_then(|settings| settings.translation_tone);
+
+    // Call the AI compose method (using Opus 4.5)
+    match translator
+        .compose_ai_message(&req.prompt, reply_context, reply_image, tone.as_ref())
+        .await
+    {
+        Ok((message, usage)) => {
+            info!(
+                "AI composed message ({} chars), cost: ${:.6}",
+                message.len(),
+                usage.cost_usd
+            );
+
+            // Record usage
+            if let Err(e) =
+                state
+                    .store
+                    .record_usage(req.contact_id.as_deref(), None, &usage, "ai_compose")
+            {
+                warn!("Failed to record AI compose usage: {}", e);
+            }
+
+            Json(AiComposeResponse {
+                success: true,
+                message: Some(message),
+                error: None,
+                cost_usd: Some(usage.cost_usd),
+            })
+            .into_response()
+        }
+        Err(e) => {
+            error!("AI compose failed: {}", e);
+            Json(AiComposeResponse {
+                success: false,
+                message: None,
+                error: Some(format!("Failed to compose message: {}", e)),
+                cost_usd: None,
+            })
+            .into_response()
+        }
+    }
+}
+
+/// AI styled reply endpoint - generates a reply that sounds like the user
+/// Usage operation recorded for automatically generated reply suggestions
+const AUTO_SUGGEST_OPERATION: &str = "suggest_replies_auto";
+
+/// Generate quick-reply suggestions for a message in the conversation's language
+/// and record the usage under `operation`
+async fn generate_reply_suggestions(
+    store: &MessageStore,
+    translator: &TranslationService,
+    message: &StoredMessage,
+    operation: &str,
+) -> anyhow::Result<(Vec<String>, crate::translation::UsageInfo)> {
+    let text = message
+        .original_text
+        .clone()
+        .or_else(|| {
+            message
+                .content
+                .as_ref()
+                .and_then(|c| c.get("body").and_then(|v| v.as_str().map(String::from)))
+        })
+        .ok_or_else(|| anyhow::anyhow!("Message has no text to reply to"))?;
+
+    // Reply in the conversation's language: override > this message > recent messages
+    let settings = store
+        .get_conversation_settings(&message.contact_id)
+        .unwrap_or_default();
+    let language = settings
+        .language_override
+        .or_else(|| {
+            message
+                .is_translated
+                .then(|| message.source_language.clone())
+                .flatten()
+        })
+        .or_else(|| {
+            store
+                .get_cached_conversation_language(&message.contact_id)
+                .ok()
+                .flatten()
+        });
+
+    let recent = store
+        .get_recent_messages(&message.contact_id, 6)
+        .unwrap_or_default();
+
+    let (suggestions, usage) = translator
+        .suggest_replies(&text, &recent, language.as_deref())
+        .await?;
+
+    if usage.input_tokens > 0 {
+        if let Err(e) = store.record_usage(
+            Some(&message.contact_id),
+            Some(&message.id),
+            &usage,
+            operation,
+        ) {
+            warn!("Failed to record usage: {}", e);
+        }
+    }
+
+    Ok((suggestions, usage))
+}
+
+/// Generate three quick-reply suggestions for a received message
+async fn suggest_replies(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<SuggestRepliesRequest>,
+) -> impl IntoResponse {
+    let translator = match &state.translator {
+        Some(t) => t,
+        None => {
+            return Json(SuggestRepliesResponse {
+                success: false,
+                suggestions: vec![],
+                error: Some("AI service not configured (missing API key)".to_string()),
+                cost_usd: None,
+            })
+            .into_response();
+        }
+    };
+
+    let message = match state.store.get_message_by_id(&req.message_id) {
+        Ok(Some(m)) => m,
+        Ok(None) => {
+            return Json(SuggestRepliesResponse {
+                success: false,
+                suggestions: vec![],
+                error: Some("Message not found".to_string()),
+                cost_usd: None,
+            })
+            .into_response();
+        }
+        Err(e) => {
+            error!("Failed to get message: {}", e);
+            return Json(SuggestRepliesResponse {
+                success: false,
+                suggestions: vec![],
+                error: Some(format!("Failed to get message: {}", e)),
+                cost_usd: None,
+            })
+            .into_response();
+        }
+    };
+
+    match generate_reply_suggestions(&state.store, translator, &message, "suggest_replies").await {
+        Ok((suggestions, usage)) => Json(SuggestRepliesResponse {
+            success: true,
+            suggestions,
+            error: None,
+            cost_usd: Some(usage.cost_usd),
+        })
+        .into_response(),
+        Err(e) => {
+            error!("Failed to generate reply suggestions: {}", e);
+            Json(SuggestRepliesResponse {
+                success: false,
+                suggestions: vec![],
+                error: Some(format!("Failed to generate suggestions: {}", e)),
+                cost_usd: None,
+            })
+            .into_response()
+        }
+    }
+}
+
+async fn ai_reply(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<AiReplyRequest>,
+) -> impl IntoResponse {
+    // Check if translation service is available (it has the API key)
+    let translator = match &state.translator {
+        Some(t) => t,
+        None => {
+            return Json(AiReplyResponse {
+                success: false,
+                reply_text: None,
+                error: Some("AI service not configured (missing API key)".to_string()),
+                cost_usd: None,
+            })
+            .into_response();
+        }
+    };
+
+    // Get the message being replied to
+    let message = match state.store.get_message_by_id(&req.message_id) {
+        Ok(Some(m)) => m,
+        Ok(None) => {
+            return Json(AiReplyResponse {
+                success: false,
+                reply_text: None,
+                error: Some("Message not found".to_string()),
+                cost_usd: None,
+            })
+            .into_response();
+        }
+        Err(e) => {
+            error!("Failed to get message: {}", e);
+            return Json(AiReplyResponse {
+                success: false,
+                reply_text: None,
+                error: Some(format!("Failed to get message: {}", e)),
+                cost_usd: None,
+            })
+            .into_response();
+        }
+    };
+
+    // Get recent conversation for context (last 20 messages)
+    let recent_conversation = match state.store.get_recent_messages(&req.contact_id, 20) {
+        Ok(msgs) => msgs,
+        Err(e) => {
+            warn!("Failed to get recent messages: {}", e);
+            vec![]
+        }
+    };
+
+    // Create style analyzer
+    let api_key = translator.get_api_key();
+    let style_analyzer = crate::style_analyzer::StyleAnalyzer::new(api_key);
+
+    // Get or create global style profile
+    let (global_style, global_usage) = match style_analyzer
+        .get_or_create_profile(&state.store, None)
+        .await
+    {
+        Ok(result) => result,
+        Err(e) => {
+            error!("Failed to get global style profile: {}", e);
+            return Json(AiReplyResponse {
+                success: false,
+                reply_text: None,
+                error: Some(format!("Failed to analyze writing style: {}", e)),
+                cost_usd: None,
+            })
+            .into_response();
+        }
+    };
+
+    // Get or create per-contact style profile
+    let (contact_style, contact_usage) = match style_analyzer
+        .get_or_create_profile(&state.store, Some(&req.contact_id))
+        .await
+    {
+        Ok(result) => result,
+        Err(e) => {
+            warn!("Failed to get contact style profile: {}", e);
+            // Continue without contact-specific style
+            (
+                crate::storage::StyleProfile {
+                    contact_id: req.contact_id.clone(),
+                    profile_text: "No contact-specific style data available.".to_string(),
+                    sample_messages: vec![],
+                    message_count: 0,
+                    updated_at: 0,
+                },
+                None,
+            )
+        }
+    };
+
+    // Get examples of user's messages to this contact (more examples = better style matching)
+    let my_examples = match state
+        .store
+        .get_outgoing_messages_for_style(Some(&req.contact_id), 20)
+    {
+        Ok(msgs) => msgs,
+        Err(e) => {
+            warn!("Failed to get example messages: {}", e);
+            vec![]
+        }
+    };
+
+    let tone = state
+        .store
+        .get_conversation_settings(&req.contact_id)
+        .map_err(|e| warn!("Failed to get conversation settings: {}", e))
+        .ok()
+        .and_then(|settings| settings.translation_tone);
+
+    // Generate the styled reply
+    match translator
+        .compose_styled_reply(
+            &message,
+            &recent_conversation,
+            &global_style,
+            Some(&contact_style),
+            &my_examples,
+            tone.as_ref(),
+        )
+        .await
+    {
+        Ok((reply_text, usage)) => {
+            // Calculate total cost (including any style analysis)
+            let mut total_cost = usage.cost_usd;
+            if let Some(gu) = &global_usage {
+                total_cost += gu.cost_usd;
+            }
+            if let Some(cu) = &contact_usage {
+                total_cost += cu.cost_usd;
+            }
+
+            info!(
+                "AI reply generated ({} chars), cost: ${:.6}",
+                reply_text.len(),
+                total_cost
+            );
+
+            // Record usage
+            if let Err(e) = state.store.record_usage(
+                Some(&req.contact_id),
+                Some(&req.message_id),
+                &crate::translation::UsageInfo {
+                    input_tokens: usage.input_tokens,
+                    output_tokens: usage.output_tokens,
+                    cost_usd: total_cost,
+                    calls: usage.calls.clone(),
+                },
+                "ai_styled_reply",
+            ) {
+                warn!("Failed to record AI reply usage: {}", e);
+            }
+
+            Json(AiReplyResponse {
+                success: true,
+                reply_text: Some(reply_text),
+                error: None,
+                cost_usd: Some(total_cost),
+            })
+            .into_response()
+        }
+        Err(e) => {
+            error!("AI reply generation failed: {}", e);
+            Json(AiReplyResponse {
+                success: false,
+                reply_text: None,
+                error: Some(format!("Failed to generate reply: {}", e)),
+                cost_usd: None,
+            })
+            .into_response()
+        }
+    }
+}
+
+async fn get_stats(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.store.get_stats() {
+        Ok((messages, contacts)) => Json(serde_json::json!({
+            "messageCount": messages,
+            "contactCount": contacts,
+            "database": state
+                .store
+                .database_sizes()
+                .map_err(|e| error!("Failed to get database sizes: {}", e))
+                .ok(),
+            "maintenance": state
+                .store
+                .get_storage_maintenance_times()
+                .map_err(|e| error!("Failed to get maintenance times: {}", e))
+                .ok(),
+        }))
+        .into_response(),
+        Err(e) => {
+            error!("Failed to get stats: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to get stats").into_response()
+        }
+    }
+}
+
+/// Query parameters for translation status counts
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TranslationStatsQuery {
+    /// Only count messages in this chat
+    contact_id: Option<String>,
+}
+
+/// Count incoming messages by why they were or weren't translated
+async fn get_translation_stats(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<TranslationStatsQuery>,
+) -> impl IntoResponse {
+    match state
+        .store
+        .get_translation_status_counts(query.contact_id.as_deref())
+    {
+        Ok(counts) => {
+            let total: u64 = counts.iter().map(|(_, count)| count).sum();
+            let counts: serde_json::Map<String, serde_json::Value> = counts
+                .into_iter()
+                .map(|(status, count)| (status.as_str().to_string(), count.into()))
+                .collect();
+            Json(serde_json::json!({
+                "counts": counts,
+                "total": total,
+            }))
+            .into_response()
+        }
+        Err(e) => {
+            error!("Failed to get translation stats: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to get translation stats",
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Query parameters for a weekly report
+#[derive(Debug, Deserialize)]
+struct WeeklyReportQuery {
+    /// ISO week, e.g. "2026-W07" (default: the last full week)
+    week: Option<String>,
+}
+
+fn report_error(e: ReportError) -> Response {
+    let status = match e {
+        ReportError::InvalidWeek | ReportError::FutureWeek => StatusCode::BAD_REQUEST,
+        ReportError::StorageError => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (
+        status,
+        Json(serde_json::json!({
+            "success": false,
+            "error": e.as_str(),
+            "errorDescription": e.description(),
+        })),
+    )
+        .into_response()
+}
+
+/// Get a week's operational report: the saved one for a past week, or one
+/// made now
+async fn get_weekly_report(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<WeeklyReportQuery>,
+) -> impl IntoResponse {
+    let tz = state.reports.timezone();
+    let store = state.store.clone();
+    let errors = state.errors.clone();
+    let report = tokio::task::spawn_blocking(move || {
+        reports::get_report(&store, &errors, query.week.as_deref(), tz)
+    })
+    .await
+    .unwrap_or(Err(ReportError::StorageError));
+    match report {
+        Ok(report) => Json(report).into_response(),
+        Err(e) => report_error(e),
+    }
+}
+
+/// Query parameters for a chat export
+#[derive(Debug, Deserialize)]
+struct ExportQuery {
+    /// Only "pdf" (the default)
+    format: Option<String>,
+    /// First and last day exported (UTC, "YYYY-MM-DD"; default: all)
+    from: Option<String>,
+    to: Option<String>,
+}
+
+fn transcript_error(e: TranscriptError) -> Response {
+    let status = match e {
+        TranscriptError::UnsupportedFormat
+        | TranscriptError::InvalidDate
+        | TranscriptError::InvalidRange => StatusCode::BAD_REQUEST,
+        TranscriptError::ContactNotFound => StatusCode::NOT_FOUND,
+        TranscriptError::TooManyMessages(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        TranscriptError::StorageError | TranscriptError::RenderFailed => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    };
+    let mut body = serde_json::json!({
+        "success": false,
+        "error": e.as_str(),
+        "errorDescription": e.description(),
+    });
+    if let TranscriptError::TooManyMessages(count) = e {
+        body["messageCount"] = count.into();
+        body["maxMessages"] = transcript::MAX_MESSAGES.into();
+    }
+    (status, Json(body)).into_response()
+}
+
+/// Export a chat as a printable PDF transcript
+async fn export_chat(
+    State(state): State<Arc<AppState>>,
+    Path(contact_id): Path<String>,
+    Query(query): Query<ExportQuery>,
+) -> Response {
+    if !query
+        .format
+        .as_deref()
+        .unwrap_or("pdf")
+        .eq_ignore_ascii_case("pdf")
+    {
+        return transcript_error(TranscriptError::UnsupportedFormat);
+    }
+    let (from, to) = match (
+        transcript::parse_date(query.from.as_deref()),
+        transcript::parse_date(query.to.as_deref()),
+    ) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(e), _) | (_, Err(e)) => return transcript_error(e),
+    };
+
+    let store = state.store.clone();
+    let exported = tokio::task::spawn_blocking(move || {
+        transcript::export_pdf(
+            &store,
+            &contact_id,
+            from,
+            to,
+            transcript::MAX_MESSAGES,
+            chrono::Utc::now(),
+        )
+    })
+    .await
+    .unwrap_or(Err(TranscriptError::RenderFailed));
+    match exported {
+        Ok(export) => (
+            [
+                (header::CONTENT_TYPE, "application/pdf".to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{}\"", export.file_name),
+                ),
+            ],
+            export.pdf,
+        )
+            .into_response(),
+        Err(e) => transcript_error(e),
+    }
+}
+
+fn broadcast_error(e: BroadcastError) -> Response {
+    let status = match e {
+        BroadcastError::NotFound => StatusCode::NOT_FOUND,
+        _ => StatusCode::BAD_REQUEST,
+    };
+    (
+        status,
+        Json(serde_json::json!({
+            "success": false,
+            "error": e.as_str(),
+            "errorDescription": e.description(),
+        })),
+    )
+        .into_response()
+}
+
+/// Queue a text for each of several chats; it is sent in the background,
+/// one chat at a time, and the batch comes back straight away
+async fn send_broadcast(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(req): Json<SendBroadcastRequest>,
+) -> Response {
+    let command_tx = match state.command_tx.read().await.clone() {
+        Some(command_tx) if *state.connected.read().await => command_tx,
+        _ => {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(serde_json::json!({ "error": "Not connected to WhatsApp" })),
+            )
+                .into_response();
+        }
+    };
+    let sent_by = state.session_name(&headers).await;
+    match state
+        .broadcasts
+        .start(command_tx, &req.contact_ids, &req.text, sent_by)
+    {
+        Ok(batch) => (StatusCode::ACCEPTED, Json(batch)).into_response(),
+        Err(e) => broadcast_error(e),
+    }
+}
+
+/// A broadcast with each recipient's outcome so far
+async fn get_broadcast(
+    State(state): State<Arc<AppState>>,
+    Path(batch_id): Path<String>,
+) -> Response {
+    match state.broadcasts.get(&batch_id) {
+        Ok(batch) => Json(batch).into_response(),
+        Err(e) => broadcast_error(e),
+    }
+}
+
+fn job_error(e: JobError) -> Response {
+    let status = match e {
+        JobError::NotFound => StatusCode::NOT_FOUND,
+        JobError::AlreadyFinished => StatusCode::CONFLICT,
+        JobError::StorageError => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (
+        status,
+        Json(serde_json::json!({
+            "success": false,
+            "error": e.as_str(),
+            "errorDescription": e.description(),
+        })),
+    )
+        .into_response()
+}
+
+/// List background jobs: those queued or running, then recently finished
+async fn list_jobs(State(state): State<Arc<AppState>>) -> Response {
+    match state.jobs.list() {
+        Ok(jobs) => Json(serde_json::json!({ "jobs": jobs })).into_response(),
+        Err(e) => job_error(e),
+    }
+}
+
+async fn get_job(State(state): State<Arc<AppState>>, Path(job_id): Path<String>) -> Response {
+    match state.jobs.get(&job_id) {
+        Ok(job) => Json(job).into_response(),
+        Err(e) => job_error(e),
+    }
+}
+
+/// Cancel a queued or running job. It's cancelled once it next waits on
+/// something, announced with a `job_progress` event.
+async fn cancel_job(State(state): State<Arc<AppState>>, Path(job_id): Path<String>) -> Response {
+    match state.jobs.cancel(&job_id) {
+        Ok(job) => (StatusCode::ACCEPTED, Json(job)).into_response(),
+        Err(e) => job_error(e),
+    }
+}
+
+/// List the saved weekly reports, latest first
+async fn list_reports(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.store.list_reports() {
+        Ok(reports) => Json(serde_json::json!({ "reports": reports })).into_response(),
+        Err(e) => {
+            error!("Failed to list reports: {}", e);
+            report_error(ReportError::StorageError)
+        }
+    }
+}
+
+/// List the unsupported content types received, with counts and a sample
+/// payload each
+async fn get_unknown_content_report(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.store.get_unknown_content_report() {
+        Ok(types) => {
+            let total: u64 = types.iter().map(|t| t.count).sum();
+            Json(serde_json::json!({
+                "types": types,
+                "total": total,
+            }))
+            .into_response()
+        }
+        Err(e) => {
+            error!("Failed to get unknown content report: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to get unknown content report",
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Get global translation usage/cost
+async fn get_global_usage(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.store.get_global_usage() {
+        Ok(usage) => Json(serde_json::json!({
+            "inputTokens": usage.input_tokens,
+            "outputTokens": usage.output_tokens,
+            "costUsd": usage.cost_usd,
+        }))
+        .into_response(),
+        Err(e) => {
+            error!("Failed to get global usage: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to get usage").into_response()
+        }
+    }
+}
+
+/// List MCP clients with their usage, last activity and remaining quota
+async fn list_mcp_clients(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let start_of_day = chrono::Utc::now()
+        .date_naive()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc()
+        .timestamp();
+    match state.store.list_mcp_clients(start_of_day) {
+        Ok(clients) => Json(clients).into_response(),
+        Err(e) => {
+            error!("Failed to list MCP clients: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error": "Failed to list MCP clients"})),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Set the daily quota every MCP client gets unless overridden
+async fn update_default_mcp_quota(
+    State(state): State<Arc<AppState>>,
+    Json(quota): Json<McpQuota>,
+) -> impl IntoResponse {
+    save_mcp_quota(&state, None, quota)
+}
+
+/// Override the daily quota for one MCP client
+async fn update_mcp_client_quota(
+    State(state): State<Arc<AppState>>,
+    Path(client_id): Path<String>,
+    Json(quota): Json<McpQuota>,
+) -> impl IntoResponse {
+    save_mcp_quota(&state, Some(&client_id), quota)
+}
+
+/// Request to flag an MCP client as anonymized
+#[derive(Debug, Deserialize)]
+struct McpClientAnonymizedRequest {
+    anonymized: bool,
+}
+
+/// Flag an MCP client as anonymized (pseudonymized reads, no sends) or not
+async fn update_mcp_client_anonymized(
+    State(state): State<Arc<AppState>>,
+    Path(client_id): Path<String>,
+    Json(req): Json<McpClientAnonymizedRequest>,
+) -> impl IntoResponse {
+    match state
+        .store
+        .set_mcp_client_anonymized(&client_id, req.anonymized)
+    {
+        Ok(()) => {
+            info!("MCP client {} anonymized: {}", client_id, req.anonymized);
+            Json(serde_json::json!({ "anonymized": req.anonymized })).into_response()
+        }
+        Err(e) => {
+            error!("Failed to save MCP client anonymization: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error": "Failed to save MCP client anonymization"})),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// The chat access lists MCP clients are held to
+async fn get_mcp_access(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.store.get_mcp_access_lists() {
+        Ok(lists) => Json(lists).into_response(),
+        Err(e) => {
+            error!("Failed to get MCP access lists: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error": "Failed to get MCP access lists"})),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Set the chats every MCP client may see unless it has its own list (null
+/// opens every chat)
+async fn update_default_mcp_access(
+    State(state): State<Arc<AppState>>,
+    Json(list): Json<Option<ChatAccessList>>,
+) -> impl IntoResponse {
+    save_mcp_access(&state, None, list)
+}
+
+/// Set the chats one MCP client may see (null for the default list)
+async fn update_mcp_client_access(
+    State(state): State<Arc<AppState>>,
+    Path(client_id): Path<String>,
+    Json(list): Json<Option<ChatAccessList>>,
+) -> impl IntoResponse {
+    save_mcp_access(&state, Some(&client_id), list)
+}
+
+fn save_mcp_access(
+    state: &AppState,
+    client_id: Option<&str>,
+    list: Option<ChatAccessList>,
+) -> Response {
+    let list = match list.map(ChatAccessList::normalized).transpose() {
+        Ok(list) => list,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({ "error": e })),
+            )
+                .into_response()
+        }
+    };
+
+    // Chats are matched under their canonical IDs
+    let list = list
+        .map(|mut list| -> anyhow::Result<ChatAccessList> {
+            for entry in &mut list.chats {
+                if !entry.starts_with(mcp_access::TAG_PREFIX) {
+                    *entry = state.store.resolve_contact_id(entry)?;
+                }
+            }
+            Ok(list)
+        })
+        .transpose()
+        .and_then(|list| {
+            state.store.set_mcp_access(client_id, list.as_ref())?;
+            Ok(list)
+        });
+    match list {
+        Ok(list) => {
+            info!(
+                "MCP chat access for {} set to {:?}",
+                client_id.unwrap_or("all clients"),
+                list
+            );
+            Json(list).into_response()
+        }
+        Err(e) => {
+            error!("Failed to save MCP access list: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error": "Failed to save MCP access list"})),
+            )
+                .into_response()
+        }
+    }
+}
+
+fn save_mcp_quota(state: &AppState, client_id: Option<&str>, quota: McpQuota) -> Response {
+    if quota.daily_translation_usd.is_some_and(|usd| usd < 0.0) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": "dailyTranslationUsd can't be negative"})),
+        )
+            .into_response();
+    }
+
+    match state.store.set_mcp_quota(client_id, &quota) {
+        Ok(()) => {
+            info!(
+                "MCP quota for {} set to {:?}",
+                client_id.unwrap_or("all clients"),
+                quota
+            );
+            Json(quota).into_response()
+        }
+        Err(e) => {
+            error!("Failed to save MCP quota: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({"error": "Failed to save MCP quota"})),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Get the models and pricing in use
+async fn get_model_settings(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match &state.translator {
+        Some(translator) => Json(translator.models()).into_response(),
+        None => translation_not_configured(),
+    }
+}
+
+/// Change models or pricing; applies to the next API call and is saved for
+/// restarts (models given on the command line still win at startup)
+async fn update_model_settings(
+    State(state): State<Arc<AppState>>,
+    Json(update): Json<ModelUpdate>,
+) -> impl IntoResponse {
+    let Some(translator) = &state.translator else {
+        return translation_not_configured();
+    };
+
+    let mut models = translator.models();
+    models.apply(update);
+    if let Err(e) = models.validate() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": e.to_string() })),
+        )
+            .into_response();
+    }
+
+    if let Err(e) = state.store.set_model_config(&models) {
+        error!("Failed to save model settings: {}", e);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": "Failed to save model settings" })),
+        )
+            .into_response();
+    }
+    translator.set_models(models.clone());
+    Json(models).into_response()
+}
+
+/// Get the outgoing translation defaults
+async fn get_outgoing_translation_settings(
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    match state.store.get_group_outgoing_translation() {
+        Ok(translate_groups) => {
+            Json(OutgoingTranslationSettings { translate_groups }).into_response()
+        }
+        Err(e) => {
+            error!("Failed to get outgoing translation settings: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to get outgoing translation settings" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Change the outgoing translation defaults; contacts with their own
+/// setting keep it
+async fn update_outgoing_translation_settings(
+    State(state): State<Arc<AppState>>,
+    Json(settings): Json<OutgoingTranslationSettings>,
+) -> impl IntoResponse {
+    match state
+        .store
+        .set_group_outgoing_translation(settings.translate_groups)
+    {
+        Ok(()) => Json(settings).into_response(),
+        Err(e) => {
+            error!("Failed to save outgoing translation settings: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(
+                    serde_json::json!({ "error": "Failed to save outgoing translation settings" }),
+                ),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Get the undo window for web sends
+async fn get_undo_window_settings(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.store.get_undo_window_secs() {
+        Ok(undo_window_secs) => Json(UndoWindowSettings { undo_window_secs }).into_response(),
+        Err(e) => {
+            error!("Failed to get undo window: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to get undo window" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Change the undo window; messages already held back keep theirs
+async fn update_undo_window_settings(
+    State(state): State<Arc<AppState>>,
+    Json(settings): Json<UndoWindowSettings>,
+) -> impl IntoResponse {
+    if settings.undo_window_secs > MAX_UNDO_WINDOW_SECS {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": format!("The undo window can be at most {} seconds", MAX_UNDO_WINDOW_SECS)
+            })),
+        )
+            .into_response();
+    }
+    match state.store.set_undo_window_secs(settings.undo_window_secs) {
+        Ok(()) => Json(settings).into_response(),
+        Err(e) => {
+            error!("Failed to save undo window: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to save undo window" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// The public key browsers subscribe to push notifications with
+async fn get_vapid_public_key(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.push.vapid_key(&state.store) {
+        Ok(key) => Json(serde_json::json!({ "publicKey": key.public_key() })).into_response(),
+        Err(e) => {
+            error!("Failed to get VAPID key: {:#}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to get VAPID key" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Subscribe a browser to push notifications
+async fn subscribe_push(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<PushSubscribeRequest>,
+) -> impl IntoResponse {
+    let subscription = PushSubscription {
+        endpoint: req.endpoint,
+        p256dh: req.keys.p256dh,
+        auth: req.keys.auth,
+    };
+    if !crate::push::valid_subscription(&subscription) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": "Invalid push subscription" })),
+        )
+            .into_response();
+    }
+
+    match state.store.save_push_subscription(&subscription) {
+        Ok(()) => StatusCode::CREATED.into_response(),
+        Err(e) => {
+            error!("Failed to save push subscription: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to save push subscription" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Unsubscribe a browser from push notifications
+async fn unsubscribe_push(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<PushUnsubscribeRequest>,
+) -> impl IntoResponse {
+    match state.store.delete_push_subscription(&req.endpoint) {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": "Not subscribed" })),
+        )
+            .into_response(),
+        Err(e) => {
+            error!("Failed to delete push subscription: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to delete push subscription" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+async fn get_quiet_hours_settings(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.store.get_quiet_hours() {
+        Ok(quiet_hours) => Json(QuietHoursSettings { quiet_hours }).into_response(),
+        Err(e) => {
+            error!("Failed to get quiet hours: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to get quiet hours" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Change the quiet hours (null turns them off)
+async fn update_quiet_hours_settings(
+    State(state): State<Arc<AppState>>,
+    Json(settings): Json<QuietHoursSettings>,
+) -> impl IntoResponse {
+    match state.store.set_quiet_hours(settings.quiet_hours.as_ref()) {
+        Ok(()) => Json(settings).into_response(),
+        Err(e) => {
+            error!("Failed to save quiet hours: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to save quiet hours" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Export the global settings and every contact's own configuration
+async fn export_settings(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match settings_transfer::export(&state.store) {
+        Ok(export) => Json(export).into_response(),
+        Err(e) => {
+            error!("Failed to export settings: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to export settings" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Query parameters for a settings import
+#[derive(Debug, Deserialize)]
+struct SettingsImportQuery {
+    /// Report what would change without changing it
+    #[serde(default)]
+    dry_run: bool,
+    /// What to do with values set differently here: skip (default) or overwrite
+    #[serde(default)]
+    on_conflict: ConflictResolution,
+}
+
+/// Import a settings export (see `settings_transfer`)
+async fn import_settings(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<SettingsImportQuery>,
+    body: axum::body::Bytes,
+) -> impl IntoResponse {
+    // Parsed here rather than by the extractor so unknown keys are
+    // reported like every other import error
+    let export: ConfigExport = match serde_json::from_slice(&body) {
+        Ok(export) => export,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "success": false,
+                    "error": "invalid_request",
+                    "errorDescription": format!("Invalid settings export: {}", e),
+                })),
+            )
+                .into_response()
+        }
+    };
+
+    match settings_transfer::import(&state.store, &export, query.on_conflict, query.dry_run) {
+        Ok(report) => {
+            // Saved models apply to the next API call, as when set directly
+            if !query.dry_run {
+                if let (Some(translator), Ok(Some(models))) =
+                    (&state.translator, state.store.get_model_config())
+                {
+                    translator.set_models(models);
+                }
+            }
+            Json(serde_json::json!({
+                "success": true,
+                "report": report,
+            }))
+            .into_response()
+        }
+        Err(e) => {
+            let status = match e {
+                SettingsImportError::UnsupportedVersion(_)
+                | SettingsImportError::UnknownSetting(_)
+                | SettingsImportError::InvalidSetting { .. }
+                | SettingsImportError::InvalidContact(_) => StatusCode::BAD_REQUEST,
+                SettingsImportError::ReadOnly => StatusCode::INSUFFICIENT_STORAGE,
+                SettingsImportError::StorageError => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            (
+                status,
+                Json(serde_json::json!({
+                    "success": false,
+                    "error": e.as_str(),
+                    "errorDescription": e.description(),
+                })),
+            )
+                .into_response()
+        }
+    }
+}
+
+fn translation_not_configured() -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(serde_json::json!({ "error": "Translation service not configured" })),
+    )
+        .into_response()
+}
+
+/// Get translation usage/cost for a specific conversation
+async fn get_conversation_usage(
+    State(state): State<Arc<AppState>>,
+    Path(contact_id): Path<String>,
+) -> impl IntoResponse {
+    let contact_id = urlencoding::decode(&contact_id)
+        .map(|s| s.into_owned())
+        .unwrap_or(contact_id);
+
+    match state.store.get_conversation_usage(&contact_id) {
+        Ok(usage) => Json(serde_json::json!({
+            "inputTokens": usage.input_tokens,
+            "outputTokens": usage.output_tokens,
+            "costUsd": usage.cost_usd,
+        }))
+        .into_response(),
+        Err(e) => {
+            error!("Failed to get conversation usage: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to get usage").into_response()
+        }
+    }
+}
+
+/// Maximum number of timed API calls considered for performance stats
+const PERFORMANCE_SAMPLE_LIMIT: usize = 10_000;
+
+/// Get API latency percentiles per operation and per model
+async fn get_usage_performance(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.store.get_performance_stats(PERFORMANCE_SAMPLE_LIMIT) {
+        Ok(stats) => Json(stats).into_response(),
+        Err(e) => {
+            error!("Failed to get performance stats: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to get performance stats",
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Query parameters for link preview
+#[derive(Deserialize)]
+struct LinkPreviewQuery {
+    url: String,
+}
+
+/// Get link preview for a URL (with caching)
+async fn get_link_preview(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<LinkPreviewQuery>,
+) -> impl IntoResponse {
+    use crate::link_preview;
+
+    let url = query.url;
+
+    // Cache duration: 24 hours for successful fetches, 1 hour for errors
+    let cache_duration = 24 * 60 * 60; // 24 hours
+
+    // Check cache first
+    match state.store.get_link_preview(&url, cache_duration) {
+        Ok(Some(preview)) => {
+            return Json(preview).into_response();
+        }
+        Ok(None) => {
+            // Not in cache, need to fetch
+        }
+        Err(e) => {
+            warn!("Failed to check link preview cache: {}", e);
+            // Continue to fetch
+        }
+    }
+
+    // Fetch the preview
+    match link_preview::fetch_link_preview(&url).await {
+        Ok(preview) => {
+            // Cache the result
+            if let Err(e) = state.store.save_link_preview(&preview) {
+                warn!("Failed to cache link preview: {}", e);
+            }
+            Json(preview).into_response()
+        }
+        Err(e) => {
+            error!("Failed to fetch link preview for {}: {}", url, e);
+            // Return error preview
+            let error_preview =
+                link_preview::LinkPreview::error(url, format!("Failed to fetch: {}", e));
+            // Cache the error for a shorter duration (by saving it)
+            let _ = state.store.save_link_preview(&error_preview);
+            Json(error_preview).into_response()
+        }
+    }
+}
+
+/// Query parameters for a map preview
+#[derive(Deserialize)]
+struct MapThumbnailQuery {
+    lat: f64,
+    lon: f64,
+}
+
+/// Map preview of a shared location, fetched from the map service by the
+/// server so viewers don't contact it directly
+async fn get_map_thumbnail(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<MapThumbnailQuery>,
+) -> impl IntoResponse {
+    if !geocode::valid_coordinates(query.lat, query.lon) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": "Coordinates out of range"})),
+        )
+            .into_response();
+    }
+
+    match state.geocoder.map_thumbnail(query.lat, query.lon).await {
+        Ok((bytes, content_type)) => (
+            [
+                (header::CONTENT_TYPE, content_type),
+                (header::CACHE_CONTROL, "private, max-age=86400".to_string()),
+            ],
+            bytes,
+        )
+            .into_response(),
+        Err(e) => {
+            warn!("Failed to fetch map preview: {}", e);
+            (
+                StatusCode::BAD_GATEWAY,
+                Json(serde_json::json!({"error": "Map preview unavailable"})),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Run the periodic cache and expired record cleanup now
+async fn run_cleanup(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match maintenance::run(&state).await {
+        Ok(report) => Json(report).into_response(),
+        Err(e) => {
+            error!("Cleanup failed: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Cleanup failed" })),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Link contacts that appear under both a phone JID and a LID.
+/// Confirmed pairs from the request are linked first, then unambiguous
+/// heuristic matches; anything ambiguous is returned for manual confirmation.
+async fn link_identities(
+    State(state): State<Arc<AppState>>,
+    body: Option<Json<LinkIdentitiesRequest>>,
+) -> impl IntoResponse {
+    let req = body.map(|Json(req)| req).unwrap_or_default();
+    let mut linked = Vec::new();
+
+    if !req.dry_run {
+        for confirmation in &req.confirm {
+            if let Err(e) = state.store.link_identity(
+                &confirmation.alt_jid,
+                &confirmation.canonical_id,
+                "manual",
+            ) {
+                error!("Failed to link identity {}: {}", confirmation.alt_jid, e);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(serde_json::json!({ "error": "Failed to link identities" })),
+                )
+                    .into_response();
+            }
+            linked.push(serde_json::json!({
+                "altJid": confirmation.alt_jid,
+                "canonicalId": confirmation.canonical_id,
+                "source": "manual",
+            }));
+        }
+    }
+
+    let candidates = match state.store.find_identity_link_candidates() {
+        Ok(candidates) => candidates,
+        Err(e) => {
+            error!("Failed to find identity link candidates: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": "Failed to find candidates" })),
+            )
+                .into_response();
+        }
+    };
+
+    let (confident, ambiguous): (Vec<_>, Vec<_>) =
+        candidates.into_iter().partition(|c| c.confident);
+
+    if req.dry_run {
+        return Json(serde_json::json!({
+            "dryRun": true,
+            "linked": [],
+            "wouldLink": confident,
+            "ambiguous": ambiguous,
+        }))
+        .into_response();
+    }
+
+    for candidate in &confident {
+        match state
+            .store
+            .link_identity(&candidate.alt_jid, &candidate.canonical_id, "heuristic")
+        {
+            Ok(()) => linked.push(serde_json::json!({
+                "altJid": candidate.alt_jid,
+                "canonicalId": candidate.canonical_id,
+                "source": "heuristic",
+            })),
+            Err(e) => warn!("Failed to link identity {}: {}", candidate.alt_jid, e),
+        }
+    }
+
+    info!(
+        "Linked {} identities, {} ambiguous candidates left for review",
+        linked.len(),
+        ambiguous.len()
+    );
+
+    Json(serde_json::json!({
+        "dryRun": false,
+        "linked": linked,
+        "ambiguous": ambiguous,
+    }))
+    .into_response()
+}
+
+// ==================== OAuth 2.0 Handlers ====================
+
+/// Get base URL from request (for OAuth metadata). The scheme is HTTPS when
+/// this server terminates TLS, or when a proxy in front of it says the client
+/// connected over HTTPS.
+fn get_base_url(state: &AppState, headers: &HeaderMap, host: &str) -> String {
+    let forwarded_https = headers
+        .get("x-forwarded-proto")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .is_some_and(|proto| proto.trim().eq_ignore_ascii_case("https"));
+    let scheme = if state.serves_https.load(Ordering::Relaxed) || forwarded_https {
+        "https"
+    } else {
+        "http"
+    };
+    format!("{}://{}", scheme, host)
+}
+
+/// OAuth 2.0 Authorization Server Metadata (RFC 8414)
+async fn oauth_metadata(
+    State(state): State<Arc<AppState>>,
+    Host(host): Host,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let base_url = get_base_url(&state, &headers, &host);
+
+    // Extended metadata with Dynamic Client Registration support
+    Json(serde_json::json!({
+        "issuer": base_url,
+        "authorization_endpoint": format!("{}/oauth/authorize", base_url),
+        "token_endpoint": format!("{}/oauth/token", base_url),
+        "registration_endpoint": format!("{}/oauth/register", base_url),
+        "revocation_endpoint": format!("{}/oauth/revoke", base_url),
+        "response_types_supported": ["code"],
+        "grant_types_supported": ["authorization_code", "refresh_token"],
+        "code_challenge_methods_supported": ["S256"],
+        "token_endpoint_auth_methods_supported": ["none"],
+        "scopes_supported": ["mcp"],
+        // MCP-specific fields
+        "service_documentation": format!("{}/docs", base_url),
+    }))
+}
+
+/// OAuth 2.0 Protected Resource Metadata (RFC 9728)
+/// This tells MCP clients which authorization server to use
+async fn oauth_protected_resource_metadata(
+    State(state): State<Arc<AppState>>,
+    Host(host): Host,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let base_url = get_base_url(&state, &headers, &host);
+
+    Json(serde_json::json!({
+        "resource": format!("{}/mcp", base_url),
+        "authorization_servers": [base_url],
+        "scopes_supported": ["mcp"],
+        "bearer_methods_supported": ["header"]
+    }))
+}
+
+/// Dynamic Client Registration request (RFC 7591)
+#[derive(Debug, Deserialize)]
+struct ClientRegistrationRequest {
+    redirect_uris: Vec<String>,
+    client_name: Option<String>,
+    client_uri: Option<String>,
+    scope: Option<String>,
+    grant_types: Option<Vec<String>>,
+    response_types: Option<Vec<String>>,
+    token_endpoint_auth_method: Option<String>,
+    /// Register the client as anonymized: it only ever sees pseudonymized
+    /// data and can't send. A client can restrict itself this way, but only
+    /// the owner can lift it.
+    #[serde(default)]
+    anonymized: bool,
+}
+
+/// Dynamic Client Registration endpoint (RFC 7591)
+/// Allows MCP clients like Claude.ai to register before starting OAuth flow
+async fn oauth_register(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<ClientRegistrationRequest>,
+) -> impl IntoResponse {
+    // Refuse redirect URIs that could send an approval somewhere unsafe
+    for uri in &req.redirect_uris {
+        if let Err(reason) = validate_redirect_uri(uri) {
+            warn!("Rejected OAuth client registration for {}: {}", uri, reason);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "error": "invalid_redirect_uri",
+                    "error_description": format!("Redirect URI {} {}", uri, reason),
+                })),
+            );
+        }
+    }
+
+    // Generate a client_id for this registration
+    let client_id = format!("client_{}", generate_token()[..16].to_string());
+
+    // For public clients (like Claude.ai), we don't issue a client_secret
+    // The client will use PKCE for security instead
+
+    info!(
+        "OAuth client registered: {} ({:?}) with redirect_uris: {:?}",
+        client_id, req.client_name, req.redirect_uris
+    );
+    if req.anonymized {
+        if let Err(e) = state.store.set_mcp_client_anonymized(&client_id, true) {
+            error!("Failed to register anonymized OAuth client: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "error": "server_error",
+                    "error_description": "Failed to register the client",
+                })),
+            );
+        }
+    }
+
+    // Return the registration response
+    (
+        StatusCode::CREATED,
+        Json(serde_json::json!({
+            "client_id": client_id,
+            "client_name": req.client_name,
+            "redirect_uris": req.redirect_uris,
+            "grant_types": req.grant_types.unwrap_or_else(|| vec!["authorization_code".to_string(), "refresh_token".to_string()]),
+            "response_types": req.response_types.unwrap_or_else(|| vec!["code".to_string()]),
+            "token_endpoint_auth_method": "none",
+            "scope": req.scope.unwrap_or_else(|| "mcp".to_string()),
+            "anonymized": req.anonymized,
+        })),
+    )
+}
+
+/// OAuth Authorization endpoint - shows approval page
+async fn oauth_authorize(
+    State(state): State<Arc<AppState>>,
+    Host(host): Host,
+    headers: HeaderMap,
+    Query(params): Query<AuthorizeRequest>,
+) -> impl IntoResponse {
+    // Validate request
+    if params.response_type != "code" {
+        return (
+            StatusCode::BAD_REQUEST,
+            Html(format!(
+                r#"<html><body><h1>Error</h1><p>Unsupported response_type: {}</p></body></html>"#,
+                html_escape(&params.response_type)
+            )),
+        )
+            .into_response();
+    }
+
+    if params.code_challenge_method != "S256" {
+        return (
+            StatusCode::BAD_REQUEST,
+            Html(r#"<html><body><h1>Error</h1><p>Only S256 code_challenge_method is supported (PKCE required)</p></body></html>"#.to_string()),
+        )
+            .into_response();
+    }
+
+    // Never redirect to an unsafe URI, not even with an error
+    if let Err(reason) = validate_redirect_uri(&params.redirect_uri) {
+        warn!(
+            "Rejected OAuth authorization for {} with redirect URI {}: {}",
+            params.client_id, params.redirect_uri, reason
+        );
+        return (
+            StatusCode::BAD_REQUEST,
+            Html(format!(
+                r#"<html><body><h1>Error</h1><p>Invalid redirect_uri: {}</p></body></html>"#,
+                html_escape(reason)
+            )),
+        )
+            .into_response();
+    }
+
+    if !state.oauth_rate_limit.allow(&params.client_id) {
+        warn!(
+            "Too many OAuth authorization attempts for {}",
+            params.client_id
+        );
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            Html("<html><body><h1>Error</h1><p>Too many authorization attempts, try again later</p></body></html>".to_string()),
+        )
+            .into_response();
+    }
+
+    // Generate a session key for this authorization request, a CSRF token
+    // for the form, and a cookie binding it to this browser (reused so
+    // several pending approvals in one browser all work)
+    let session_key = generate_token();
+    let csrf_token = generate_token();
+    let browser_binding = browser_cookie(&headers).unwrap_or_else(generate_token);
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    let pending = PendingAuthorization {
+        session_key: session_key.clone(),
+        client_id: params.client_id.clone(),
+        redirect_uri: params.redirect_uri.clone(),
+        code_challenge: params.code_challenge.clone(),
+        code_challenge_method: params.code_challenge_method.clone(),
+        scope: params.scope.clone().unwrap_or_else(|| "mcp".to_string()),
+        state: params.state.clone(),
+        created_at: now,
+        expires_at: now + 600, // 10 minutes
+        csrf_token: csrf_token.clone(),
+        browser_binding: browser_binding.clone(),
+    };
+
+    // Store pending authorization
+    if let Err(e) = state.store.oauth_store_pending_auth(&pending) {
+        error!("Failed to store pending auth: {}", e);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Html(
+                "<html><body><h1>Error</h1><p>Internal server error</p></body></html>".to_string(),
+            ),
+        )
+            .into_response();
+    }
+
+    // Check if password auth is required
+    let requires_password = state.auth_required().await;
+
+    // Show approval page
+    let base_url = get_base_url(&state, &headers, &host);
+
+    // Which chats the client will see, by its access list
+    let shared_chats = match (
+        state.store.get_mcp_access(&params.client_id),
+        state.store.get_contacts(),
+    ) {
+        (Ok(list), Ok(contacts)) => mcp_access::describe(list.as_ref(), &contacts),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("Failed to describe MCP chat access: {}", e);
+            "Couldn't be checked".to_string()
+        }
+    };
+
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>Authorize MCP Client</title>
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <style>
+        body {{ font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif; 
+               max-width: 500px; margin: 50px auto; padding: 20px; background: #f5f5f5; }}
+        .card {{ background: white; border-radius: 12px; padding: 30px; box-shadow: 0 2px 10px rgba(0,0,0,0.1); }}
+        h1 {{ color: #333; margin-top: 0; }}
+        .client-info {{ background: #f0f0f0; padding: 15px; border-radius: 8px; margin: 20px 0; }}
+        .scope {{ color: #666; font-size: 0.9em; }}
+        .warning {{ color: #e67e22; font-size: 0.9em; margin: 15px 0; }}
+        .buttons {{ display: flex; gap: 10px; margin-top: 20px; }}
+        button {{ flex: 1; padding: 12px 24px; border: none; border-radius: 8px; font-size: 16px; cursor: pointer; }}
+        .approve {{ background: #25D366; color: white; }}
+        .approve:hover {{ background: #1da851; }}
+        .deny {{ background: #e74c3c; color: white; }}
+        .deny:hover {{ background: #c0392b; }}
+        input {{ width: 100%; padding: 12px; margin: 10px 0; border: 1px solid #ddd; border-radius: 8px; box-sizing: border-box; }}
+        label {{ display: block; margin-top: 15px; color: #666; }}
+    </style>
+</head>
+<body>
+    <div class="card">
+        <h1>🔐 Authorize MCP Client</h1>
+        <p>An application is requesting access to your WhatsApp Translator:</p>
+        
+        <div class="client-info">
+            <strong>Client ID:</strong> {client_id}<br>
+            <strong>Redirect URI:</strong> {redirect_uri}
+        </div>
+        
+        <div class="scope">
+            <strong>Requested permissions:</strong> {scope}<br>
+            <strong>Chats shared:</strong> {shared_chats}<br>
+            This will allow the application to read your contacts, messages, and send messages on your behalf.
+        </div>
+        
+        <p class="warning">⚠️ Only authorize applications you trust!</p>
+        
+        <form method="POST" action="{base_url}/oauth/approve">
+            <input type="hidden" name="session_key" value="{session_key}">
+            <input type="hidden" name="csrf_token" value="{csrf_token}">
+            {password_field}
+            <div class="buttons">
+                <button type="submit" name="approved" value="true" class="approve">✓ Authorize</button>
+                <button type="submit" name="approved" value="false" class="deny">✗ Deny</button>
+            </div>
+        </form>
+    </div>
+</body>
+</html>"#,
+        client_id = html_escape(&params.client_id),
+        redirect_uri = html_escape(&params.redirect_uri),
+        scope = html_escape(&params.scope.clone().unwrap_or_else(|| "mcp".to_string())),
+        shared_chats = html_escape(&shared_chats),
+        base_url = base_url,
+        session_key = session_key,
+        csrf_token = csrf_token,
+        password_field = if requires_password {
+            r#"<label for="password">Enter your password to authorize:</label>
+            <input type="password" name="password" id="password" placeholder="Password" required>"#
+        } else {
+            ""
+        }
+    );
+
+    let cookie = format!(
+        "{}={}; Path=/oauth; Max-Age=600; HttpOnly; SameSite=Strict{}",
+        BROWSER_COOKIE,
+        browser_binding,
+        if base_url.starts_with("https://") {
+            "; Secure"
+        } else {
+            ""
+        }
+    );
+    (
+        [
+            (header::SET_COOKIE, cookie),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+        ],
+        Html(html),
+    )
+        .into_response()
+}
+
+/// The OAuth browser cookie sent with a request, if any
+fn browser_cookie(headers: &HeaderMap) -> Option<String> {
+    request_cookie(headers, BROWSER_COOKIE)
+}
+
+/// A non-empty cookie sent with a request
+pub(crate) fn request_cookie(headers: &HeaderMap, cookie: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, value)| *name == cookie && !value.is_empty())
+        .map(|(_, value)| value.to_string())
+}
+
+/// Simple HTML escaping
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#x27;")
+}
+
+/// OAuth approval form data
+#[derive(Debug, Deserialize)]
+struct OAuthApprovalForm {
+    session_key: String,
+    #[serde(default)]
+    csrf_token: String,
+    approved: String,
+    password: Option<String>,
+}
+
+/// Handle OAuth approval form submission
+async fn oauth_approve(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Form(form): Form<OAuthApprovalForm>,
+) -> impl IntoResponse {
+    let expired = || {
+        (
+            StatusCode::BAD_REQUEST,
+            Html("<html><body><h1>Error</h1><p>Invalid or expired authorization request</p></body></html>".to_string()),
+        )
+            .into_response()
+    };
+    let internal_error = || {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Html(
+                "<html><body><h1>Error</h1><p>Internal server error</p></body></html>".to_string(),
+            ),
+        )
+            .into_response()
+    };
+
+    // Get the pending authorization, leaving it in place until the form is
+    // verified so a forged post can't use up the real one
+    let pending = match state.store.oauth_get_pending_auth(&form.session_key) {
+        Ok(Some(p)) => p,
+        Ok(None) => return expired(),
+        Err(e) => {
+            error!("Failed to get pending auth: {}", e);
+            return internal_error();
+        }
+    };
+
+    // The form must come from the approval page, shown in this browser
+    let browser = browser_cookie(&headers).unwrap_or_default();
+    if pending.csrf_token.is_empty()
+        || !constant_time_eq(&form.csrf_token, &pending.csrf_token)
+        || !constant_time_eq(&browser, &pending.browser_binding)
+    {
+        warn!(
+            "Rejected forged OAuth approval for client {}",
+            pending.client_id
+        );
+        return (
+            StatusCode::FORBIDDEN,
+            Html("<html><body><h1>Error</h1><p>This approval could not be verified. Start again from the application.</p></body></html>".to_string()),
+        )
+            .into_response();
+    }
+
+    // Each approval is only used once
+    match state.store.oauth_take_pending_auth(&form.session_key) {
+        Ok(Some(_)) => {}
+        Ok(None) => return expired(),
+        Err(e) => {
+            error!("Failed to take pending auth: {}", e);
+            return internal_error();
+        }
+    }
+
+    // Check if user denied
+    if form.approved != "true" {
+        let redirect_url = build_error_redirect(
+            &pending.redirect_uri,
+            OAuthError::AccessDenied,
+            pending.state.as_deref(),
+        );
+        return Redirect::to(&redirect_url).into_response();
+    }
+
+    // Verify password if required
+    if state.auth_required().await {
+        match &form.password {
+            Some(password) if state.check_password(password).await => {}
+            _ => {
+                let redirect_url = build_error_redirect(
+                    &pending.redirect_uri,
+                    OAuthError::AccessDenied,
+                    pending.state.as_deref(),
+                );
+                return Redirect::to(&redirect_url).into_response();
+            }
+        }
+    }
+
+    // Generate authorization code
+    let code = generate_token();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    let auth_code = AuthorizationCode {
+        code: code.clone(),
+        client_id: pending.client_id,
+        redirect_uri: pending.redirect_uri.clone(),
+        code_challenge: pending.code_challenge,
+        code_challenge_method: pending.code_challenge_method,
+        scope: pending.scope,
+        created_at: now,
+        expires_at: now + 300, // 5 minutes
+        used: false,
+    };
+
+    // Store the authorization code
+    if let Err(e) = state.store.oauth_store_authorization_code(&auth_code) {
+        error!("Failed to store authorization code: {}", e);
+        let redirect_url = build_error_redirect(
+            &pending.redirect_uri,
+            OAuthError::ServerError,
+            pending.state.as_deref(),
+        );
+        return Redirect::to(&redirect_url).into_response();
+    }
+
+    info!(
+        "OAuth authorization granted for client: {}",
+        auth_code.client_id
+    );
+
+    // Redirect back to client with authorization code
+    let mut redirect_url = format!(
+        "{}{}code={}",
+        pending.redirect_uri,
+        query_separator(&pending.redirect_uri),
+        code
+    );
+    if let Some(state_param) = pending.state {
+        redirect_url.push_str(&format!("&state={}", urlencoding::encode(&state_param)));
+    }
+
+    Redirect::to(&redirect_url).into_response()
+}
+
+/// What joins parameters onto a redirect URI that may already have a query
+fn query_separator(redirect_uri: &str) -> char {
+    if redirect_uri.contains('?') {
+        '&'
+    } else {
+        '?'
+    }
+}
+
+/// Build error redirect URL
+fn build_error_redirect(redirect_uri: &str, error: OAuthError, state: Option<&str>) -> String {
+    let mut url = format!(
+        "{}{}error={}&error_description={}",
+        redirect_uri,
+        query_separator(redirect_uri),
+        error.as_str(),
+        urlencoding::encode(error.description())
+    );
+    if let Some(s) = state {
+        url.push_str(&format!("&state={}", urlencoding::encode(s)));
+    }
+    url
+}
+
+/// OAuth Token endpoint - exchange code for tokens or refresh tokens
+async fn oauth_token(
+    State(state): State<Arc<AppState>>,
+    Form(req): Form<TokenRequest>,
+) -> impl IntoResponse {
+    match req.grant_type.as_str() {
+        "authorization_code" => handle_authorization_code_grant(state, req).await,
+        "refresh_token" => handle_refresh_token_grant(state, req).await,
+        _ => {
+            let error = OAuthErrorResponse::from(OAuthError::UnsupportedGrantType);
+            (StatusCode::BAD_REQUEST, Json(error)).into_response()
+        }
+    }
+}
+
+async fn handle_authorization_code_grant(
+    state: Arc<AppState>,
+    req: TokenRequest,
+) -> axum::response::Response {
+    // Validate required parameters
+    let code = match &req.code {
+        Some(c) => c,
+        None => {
+            let error = OAuthErrorResponse::from(OAuthError::InvalidRequest);
+            return (StatusCode::BAD_REQUEST, Json(error)).into_response();
+        }
+    };
+
+    let code_verifier = match &req.code_verifier {
+        Some(v) => v,
+        None => {
+            let error = OAuthErrorResponse::from(OAuthError::InvalidRequest);
+            return (StatusCode::BAD_REQUEST, Json(error)).into_response();
+        }
+    };
+
+    let redirect_uri = match &req.redirect_uri {
+        Some(r) => r,
+        None => {
+            let error = OAuthErrorResponse::from(OAuthError::InvalidRequest);
+            return (StatusCode::BAD_REQUEST, Json(error)).into_response();
+        }
+    };
+
+    // Get and validate the authorization code
+    let auth_code = match state.store.oauth_use_authorization_code(code) {
+        Ok(Some(c)) => c,
+        Ok(None) => {
+            let error = OAuthErrorResponse::from(OAuthError::InvalidGrant);
+            return (StatusCode::BAD_REQUEST, Json(error)).into_response();
+        }
+        Err(e) => {
+            error!("Failed to get authorization code: {}", e);
+            let error = OAuthErrorResponse::from(OAuthError::ServerError);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(error)).into_response();
+        }
+    };
+
+    // Verify redirect_uri matches
+    if auth_code.redirect_uri != *redirect_uri {
+        let error = OAuthErrorResponse::from(OAuthError::InvalidGrant);
+        return (StatusCode::BAD_REQUEST, Json(error)).into_response();
+    }
+
+    // Verify PKCE
+    if !auth_code.verify_pkce(code_verifier) {
+        let error = OAuthErrorResponse::from(OAuthError::InvalidGrant);
+        return (StatusCode::BAD_REQUEST, Json(error)).into_response();
+    }
+
+    // Generate tokens
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    let access_token_str = generate_token();
+    let refresh_token_str = generate_token();
+
+    let access_token = AccessToken {
+        token: access_token_str.clone(),
+        client_id: auth_code.client_id.clone(),
+        scope: auth_code.scope.clone(),
+        created_at: now,
+        expires_at: now + 3600, // 1 hour
+    };
+
+    let refresh_token = RefreshToken {
+        token: refresh_token_str.clone(),
+        client_id: auth_code.client_id.clone(),
+        scope: auth_code.scope.clone(),
+        created_at: now,
+        expires_at: now + 30 * 24 * 3600, // 30 days
+    };
+
+    // Store tokens
+    if let Err(e) = state.store.oauth_store_access_token(&access_token) {
+        error!("Failed to store access token: {}", e);
+        let error = OAuthErrorResponse::from(OAuthError::ServerError);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(error)).into_response();
+    }
+
+    if let Err(e) = state.store.oauth_store_refresh_token(&refresh_token) {
+        error!("Failed to store refresh token: {}", e);
+        let error = OAuthErrorResponse::from(OAuthError::ServerError);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(error)).into_response();
+    }
+
+    info!("OAuth tokens issued for client: {}", auth_code.client_id);
+
+    let response = TokenResponse {
+        access_token: access_token_str,
+        token_type: "Bearer".to_string(),
+        expires_in: 3600,
+        refresh_token: refresh_token_str,
+        scope: auth_code.scope,
+    };
+
+    (
+        StatusCode::OK,
+        [(header::CACHE_CONTROL, "no-store")],
+        Json(response),
+    )
+        .into_response()
+}
+
+async fn handle_refresh_token_grant(
+    state: Arc<AppState>,
+    req: TokenRequest,
+) -> axum::response::Response {
+    let refresh_token_str = match &req.refresh_token {
+        Some(r) => r,
+        None => {
+            let error = OAuthErrorResponse::from(OAuthError::InvalidRequest);
+            return (StatusCode::BAD_REQUEST, Json(error)).into_response();
+        }
+    };
+
+    // Validate refresh token
+    let refresh_token = match state.store.oauth_get_refresh_token(refresh_token_str) {
+        Ok(Some(t)) => t,
+        Ok(None) => {
+            let error = OAuthErrorResponse::from(OAuthError::InvalidGrant);
+            return (StatusCode::BAD_REQUEST, Json(error)).into_response();
+        }
+        Err(e) => {
+            error!("Failed to get refresh token: {}", e);
+            let error = OAuthErrorResponse::from(OAuthError::ServerError);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(error)).into_response();
+        }
+    };
+
+    // Generate new access token
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    let access_token_str = generate_token();
+
+    let access_token = AccessToken {
+        token: access_token_str.clone(),
+        client_id: refresh_token.client_id.clone(),
+        scope: refresh_token.scope.clone(),
+        created_at: now,
+        expires_at: now + 3600, // 1 hour
+    };
+
+    // Store new access token
+    if let Err(e) = state.store.oauth_store_access_token(&access_token) {
+        error!("Failed to store access token: {}", e);
+        let error = OAuthErrorResponse::from(OAuthError::ServerError);
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(error)).into_response();
+    }
+
+    info!(
+        "OAuth access token refreshed for client: {}",
+        refresh_token.client_id
+    );
+
+    let response = TokenResponse {
+        access_token: access_token_str,
+        token_type: "Bearer".to_string(),
+        expires_in: 3600,
+        refresh_token: refresh_token_str.clone(),
+        scope: refresh_token.scope,
+    };
+
+    (
+        StatusCode::OK,
+        [(header::CACHE_CONTROL, "no-store")],
+        Json(response),
+    )
+        .into_response()
+}
+
+/// OAuth Token revocation endpoint
+async fn oauth_revoke(
+    State(state): State<Arc<AppState>>,
+    Form(req): Form<RevokeRequest>,
+) -> impl IntoResponse {
+    // Revoke the token (we don't care if it exists or not per RFC 7009)
+    if let Err(e) = state.store.oauth_revoke_token(&req.token) {
+        error!("Failed to revoke token: {}", e);
+        // Still return 200 per RFC 7009
+    }
+
+    StatusCode::OK
+}
+
+// WebSocket handler
+
+async fn websocket_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    ws.on_upgrade(|socket| handle_websocket(socket, state))
+}
+
+async fn handle_websocket(socket: WebSocket, state: Arc<AppState>) {
+    let (mut sender, mut receiver) = socket.split();
+
+    // Subscribe to broadcast events
+    let mut rx = state.broadcast_tx.subscribe();
+
+    // Send current status
+    let status = WebSocketEvent::Status {
+        connected: *state.connected.read().await,
+        phone: state.phone.read().await.clone(),
+        name: state.name.read().await.clone(),
+    };
+
+    if let Ok(json) = serde_json::to_string(&status) {
+        let _ = sender.send(Message::Text(json)).await;
+    }
+
+    // Warn about low disk space straight away
+    let disk = state.store.disk_status();
+    if disk.read_only {
+        let event = WebSocketEvent::DiskSpace {
+            read_only: true,
+            free_bytes: disk.free_bytes,
+            min_free_bytes: disk.min_free_bytes,
+        };
+        if let Ok(json) = serde_json::to_string(&event) {
+            let _ = sender.send(Message::Text(json)).await;
+        }
+    }
+
+    // Send current QR if available
+    if let Some(qr) = state.qr_code.read().await.clone() {
+        let event = WebSocketEvent::Qr { data: qr };
+        if let Ok(json) = serde_json::to_string(&event) {
+            let _ = sender.send(Message::Text(json)).await;
+        }
+    }
+
+    // Handle incoming messages and broadcast events
+    loop {
+        tokio::select! {
+            // Tell the client the server is going away, then close
+            _ = state.shutdown.wait() => {
+                if let Ok(json) = serde_json::to_string(&WebSocketEvent::ShuttingDown) {
+                    let _ = sender.send(Message::Text(json)).await;
+                }
+                let _ = sender.send(Message::Close(None)).await;
+                break;
+            }
+
+            // Broadcast events to client
+            event = next_event_json(&mut rx, &state) => {
+                let Some(json) = event else { break };
+                if sender.send(Message::Text(json)).await.is_err() {
+                    break;
+                }
+            }
+
+            // Handle client messages (for future use)
+            msg = receiver.next() => {
+                match msg {
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(Message::Ping(data))) => {
+                        let _ = sender.send(Message::Pong(data)).await;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// Receive the next broadcast event as JSON for a WebSocket client. A client
+/// that fell behind gets a resync event in place of the events it missed.
+/// Returns None once the channel is closed.
+async fn next_event_json(
+    rx: &mut broadcast::Receiver<WebSocketEvent>,
+    state: &AppState,
+) -> Option<String> {
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                state.broadcast_lag.record(missed);
+                WebSocketEvent::Resync { missed }
+            }
+            Err(broadcast::error::RecvError::Closed) => return None,
+        };
+        match serde_json::to_string(&event) {
+            Ok(json) => return Some(limit_event_size(json)),
+            Err(e) => error!("Failed to serialize WebSocket event: {}", e),
+        }
+    }
+}
+
+/// Replace an oversized event with a marker naming its type and chat, so the
+/// client refetches it over the REST API
+fn limit_event_size(json: String) -> String {
+    if json.len() <= MAX_WS_EVENT_BYTES {
+        return json;
+    }
+    let event: serde_json::Value = serde_json::from_str(&json).unwrap_or_default();
+    let event_type = event.get("type").cloned().unwrap_or_default();
+    let contact_id = event
+        .get("contact_id")
+        .or_else(|| event.pointer("/message/contactId"))
+        .or_else(|| event.pointer("/contact/id"))
+        .cloned();
+    warn!(
+        "Truncating {} byte WebSocket {} event",
+        json.len(),
+        event_type
+    );
+    serde_json::json!({
+        "type": event_type,
+        "payload_truncated": true,
+        "contact_id": contact_id,
+    })
+    .to_string()
+}
+
+// MCP (Model Context Protocol) HTTP handler
+// Uses Streamable HTTP transport (POST for requests, SSE for responses)
+
+use rmcp::transport::streamable_http_server::{
+    session::local::LocalSessionManager, StreamableHttpServerConfig, StreamableHttpService,
+};
+
+fn create_mcp_service(
+    server: WhatsAppMcpServer,
+) -> StreamableHttpService<WhatsAppMcpServer, LocalSessionManager> {
+    let session_manager = Arc::new(LocalSessionManager::default());
+    let config = StreamableHttpServerConfig {
+        stateful_mode: false, // Stateless mode - simpler, no session management needed
+        ..Default::default()
+    };
+
+    // A fresh copy of the server for each request
+    StreamableHttpService::new(move || Ok(server.clone()), session_manager, config)
+}
+
+async fn mcp_handler(
+    State(state): State<Arc<AppState>>,
+    Host(host): Host,
+    parts: axum::http::request::Parts,
+    // Read through the extractor so the route's body limit applies
+    body: axum::body::Bytes,
+) -> impl IntoResponse {
+    let request = Request::from_parts(parts, axum::body::Body::from(body));
+    // Check OAuth Bearer token authentication
+    let auth_header = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok());
+
+    // The OAuth client the validated token was issued to
+    let client_id = if let Some(header) = auth_header {
+        if let Some(token) = header.strip_prefix("Bearer ") {
+            // Validate the OAuth access token
+            match state.store.oauth_validate_access_token(token) {
+                Ok(Some(access_token)) => {
+                    info!("MCP authenticated via OAuth token");
+                    Some(access_token.client_id)
+                }
+                Ok(None) => {
+                    info!("MCP request with invalid/expired OAuth token");
+                    None
+                }
+                Err(e) => {
+                    error!("Failed to validate OAuth token: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        }
+    } else {
+        None
+    };
+
+    let Some(client_id) = client_id else {
+        // Build the resource_metadata URL for the WWW-Authenticate header
+        let base_url = get_base_url(&state, request.headers(), &host);
+        let resource_metadata_url = format!("{}/.well-known/oauth-protected-resource", base_url);
+
+        // Return 401 with WWW-Authenticate header per RFC 6750 and RFC 9728
+        // The resource_metadata parameter tells MCP clients where to find OAuth config
+        let www_authenticate = format!("Bearer resource_metadata=\"{}\"", resource_metadata_url);
+
+        return (
+            StatusCode::UNAUTHORIZED,
+            [
+                (header::WWW_AUTHENTICATE.as_str(), www_authenticate.as_str()),
+                (header::CONTENT_TYPE.as_str(), "application/json"),
+            ],
+            Json(serde_json::json!({
+                "error": "unauthorized",
+                "error_description": "OAuth Bearer token required. Complete the OAuth flow to get a token."
+            })),
+        )
+            .into_response();
+    };
+
+    // Read the command_tx asynchronously before creating the service
+    let command_tx = state.command_tx.read().await.clone();
+    let store = Arc::new(state.store.clone());
+    let translator = state.translator.clone();
+
+    let number_checks = state.number_checks.clone();
+    let confirmations = state.confirmations.clone();
+
+    // Fail closed: a client that may be anonymized mustn't see real data
+    let anonymization_salt = match state.store.get_mcp_anonymization_salt(&client_id) {
+        Ok(salt) => salt,
+        Err(e) => {
+            error!(
+                "Failed to check whether MCP client {} is anonymized: {}",
+                client_id, e
+            );
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let access = match state.store.get_mcp_access(&client_id) {
+        Ok(access) => access,
+        Err(e) => {
+            error!(
+                "Failed to get the chat access list of MCP client {}: {}",
+                client_id, e
+            );
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let principal = Principal(format!("mcp:{}", client_id));
+    let mut server = WhatsAppMcpServer::new(
+        store,
+        command_tx,
+        translator,
+        number_checks,
+        confirmations,
+        state.language_guard.mcp_default,
+        client_id,
+    )
+    .with_pending_groups(state.pending_groups.clone())
+    .with_broadcasts(state.broadcasts.clone())
+    .with_access(access);
+    if let Some(salt) = anonymization_salt {
+        server = server.with_anonymization(salt);
+    }
+    let service = create_mcp_service(server);
+    // StreamableHttpService has an async handle method we can call directly
+    let mut response = service.handle(request).await.into_response();
+    response.extensions_mut().insert(principal);
+    response
+}
+
+/// App state over a store in a temporary directory that is removed when
+/// the guard drops
+#[cfg(test)]
+pub(crate) fn test_state(
+    translator: Option<Arc<TranslationService>>,
+) -> (Arc<AppState>, tempfile::TempDir) {
+    let (store, dir) = crate::storage::test_store();
+    let state = AppState::new(
+        store,
+        dir.path().to_path_buf(),
+        dir.path().to_path_buf(),
+        translator,
+        None,
+        None,
+        LanguageGuardConfig::default(),
+    );
+    (state, dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{text_message, ConversationSettings};
+    use crate::translation::spawn_counting_provider;
+    use std::sync::atomic::Ordering;
+
+    /// App state over an empty store in a temporary directory, which is
+    /// removed when the returned `TempDir` is dropped
+    #[test]
+    fn test_parse_bind_addrs() {
+        let addrs =
+            parse_bind_addrs(":: , 0.0.0.0,[::1],[::1]:8080,127.0.0.1:9000,::", 3000).unwrap();
+        let expected: Vec<SocketAddr> = [
+            "[::]:3000",
+            "0.0.0.0:3000",
+            "[::1]:3000",
+            "[::1]:8080",
+            "127.0.0.1:9000",
+        ]
+        .iter()
+        .map(|a| a.parse().unwrap())
+        .collect();
+        assert_eq!(addrs, expected);
+
+        assert!(parse_bind_addrs("localhost", 3000).is_err());
+        assert!(parse_bind_addrs("[::1", 3000).is_err());
+        assert!(parse_bind_addrs(" , ", 3000).is_err());
+    }
+
+    #[test]
+    fn test_bind_all_dual_stack() {
+        let addrs = parse_bind_addrs("127.0.0.1,[::1]", 0).unwrap();
+        let listeners = bind_all(&addrs, true).unwrap();
+        let bound: Vec<SocketAddr> = listeners.iter().map(|l| l.local_addr().unwrap()).collect();
+        assert_eq!(bound.len(), 2);
+        assert!(bound[0].is_ipv4() && bound[1].is_ipv6());
+        // Port 0 picks one port for every address
+        assert_eq!(bound[0].port(), bound[1].port());
+        for addr in &bound {
+            std::net::TcpStream::connect(addr).unwrap();
+        }
+
+        // A taken address fails only when every bind is required
+        let taken = [bound[0], "127.0.0.2:0".parse().unwrap()];
+        assert!(bind_all(&taken, true).is_err());
+        assert_eq!(bind_all(&taken, false).unwrap().len(), 1);
+        assert!(bind_all(&taken[..1], false).is_err());
+    }
+
+    #[test]
+    fn test_mentions_match_bare_jids() {
+        let own = vec![
+            "447700900000@s.whatsapp.net".to_string(),
+            bare_jid("98765:3@lid"),
+        ];
+        let mentioned =
+            |jids: &[&str]| -> Vec<String> { jids.iter().map(|j| j.to_string()).collect() };
+
+        assert!(mentions_any(&own, &mentioned(&["98765@lid"])));
+        assert!(mentions_any(
+            &own,
+            &mentioned(&["1@s.whatsapp.net", "447700900000:7@s.whatsapp.net"])
+        ));
+        assert!(!mentions_any(&own, &mentioned(&["447700900000@lid"])));
+        assert!(!mentions_any(&own, &[]));
+    }
+
+    #[test]
+    fn test_translations_csv() {
+        let pair = |original: &str, translated: Option<&str>| TranslationPair {
+            message_id: "m1".to_string(),
+            timestamp: 1_705_689_600_000,
+            direction: "incoming",
+            original_text: Some(original.to_string()),
+            translated_text: translated.map(str::to_string),
+            source_language: Some("Spanish".to_string()),
+        };
+        let csv = translations_csv(&[
+            pair("Hola", Some("Hello")),
+            pair("Dijo \"sí\", claro\nvale", Some("He said \"yes\", sure")),
+            pair("Vale", None),
+        ]);
+        assert_eq!(
+            csv,
+            "original,translation,language,date\r\n\
+             Hola,Hello,Spanish,2024-01-19\r\n\
+             \"Dijo \"\"sí\"\", claro\nvale\",\"He said \"\"yes\"\", sure\",Spanish,2024-01-19\r\n\
+             Vale,,Spanish,2024-01-19\r\n"
+        );
+    }
+
+    #[test]
+    fn test_chat_links_encode_text() {
+        let links = build_chat_links("447911123456", Some("Hi 👋\nSee you at 5 & bring 🍕?"));
+        assert_eq!(
+            links.wa_me_url,
+            "https://wa.me/447911123456?text=Hi%20%F0%9F%91%8B%0ASee%20you%20at%205%20%26%20bring%20%F0%9F%8D%95%3F"
+        );
+        assert_eq!(
+            links.whatsapp_url,
+            "whatsapp://send?phone=447911123456&text=Hi%20%F0%9F%91%8B%0ASee%20you%20at%205%20%26%20bring%20%F0%9F%8D%95%3F"
+        );
+
+        let links = build_chat_links("447911123456", Some(""));
+        assert_eq!(links.wa_me_url, "https://wa.me/447911123456");
+        assert_eq!(links.whatsapp_url, "whatsapp://send?phone=447911123456");
+    }
+
+    #[test]
+    fn test_partition_avatars() {
+        let now = 10_000;
+        let mut cache = HashMap::from([
+            (
+                "a@s.whatsapp.net".to_string(),
+                ProfilePicture {
+                    url: Some("https://pps.whatsapp.net/a.jpg".to_string()),
+                    fetched_at: now - 60,
+                    last_used: now - 60,
+                },
+            ),
+            (
+                "b@s.whatsapp.net".to_string(),
+                ProfilePicture {
+                    url: None,
+                    fetched_at: now - 60,
+                    last_used: now - 60,
+                },
+            ),
+            (
+                "expired@s.whatsapp.net".to_string(),
+                ProfilePicture {
+                    url: Some("https://pps.whatsapp.net/old.jpg".to_string()),
+                    fetched_at: now - AVATAR_CACHE_TTL_SECS,
+                    last_used: now - AVATAR_CACHE_TTL_SECS,
+                },
+            ),
+        ]);
+        let jids: Vec<String> = [
+            "a@s.whatsapp.net",
+            "b@s.whatsapp.net",
+            "expired@s.whatsapp.net",
+            "new@s.whatsapp.net",
+            "new@s.whatsapp.net",
+        ]
+        .iter()
+        .map(|j| j.to_string())
+        .collect();
+
+        let (avatars, unknown) = partition_avatars(&mut cache, &jids, now);
+        assert_eq!(
+            unknown,
+            vec!["expired@s.whatsapp.net", "new@s.whatsapp.net"]
+        );
+        assert_eq!(avatars.len(), 4);
+        // Hits count as used; expired entries wait to be refetched
+        assert_eq!(cache["a@s.whatsapp.net"].last_used, now);
+        assert_eq!(
+            cache["expired@s.whatsapp.net"].last_used,
+            now - AVATAR_CACHE_TTL_SECS
+        );
+        assert_eq!(
+            serde_json::to_value(&avatars).unwrap(),
+            serde_json::json!({
+                "a@s.whatsapp.net": "https://pps.whatsapp.net/a.jpg",
+                "b@s.whatsapp.net": null,
+                "expired@s.whatsapp.net": "unknown",
+                "new@s.whatsapp.net": "unknown",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_language_guard() {
+        let contact_id = "33600000000@s.whatsapp.net";
+        let guarded_state = |detected: &'static str| async move {
+            let dir = tempfile::tempdir().unwrap();
+            let store = MessageStore::new(dir.path()).unwrap();
+            store
+                .upsert_contact(contact_id, Some("Madame Leroy"), None, Some("private"), 1)
+                .unwrap();
+            store
+                .update_conversation_settings(
+                    contact_id,
+                    &ConversationSettings {
+                        language_override: Some("French".to_string()),
+                        translation_style: None,
+                        ..Default::default()
+                    },
+                )
+                .unwrap();
+            // Send as typed so the draft's own language is what goes out
+            store.toggle_outgoing_translation(contact_id).unwrap();
+
+            let (url, hits) = spawn_counting_provider(detected).await;
+            let translator = TranslationService::new("test-key".to_string(), "English".to_string())
+                .with_api_url(&url);
+            let state = AppState::new(
+                store,
+                dir.path().to_path_buf(),
+                dir.path().to_path_buf(),
+                Some(Arc::new(translator)),
+                None,
+                None,
+                LanguageGuardConfig {
+                    mcp_default: false,
+                    web: true,
+                },
+            );
+            let (tx, rx) = mpsc::channel(10);
+            state.set_command_tx(tx).await;
+            *state.connected.write().await = true;
+            (state, rx, hits, dir)
+        };
+        let send = |state: Arc<AppState>, text: &str, token: Option<String>| {
+            let req = SendMessageRequest {
+                contact_id: contact_id.to_string(),
+                text: text.to_string(),
+                reply_to: None,
+                reply_to_sender: None,
+                reply_to_text: None,
+                confirmation_token: token,
+                ignore_quiet_hours: false,
+            };
+            async move {
+                send_message(State(state), HeaderMap::new(), Json(req))
+                    .await
+                    .into_response()
+            }
+        };
+        let body = |response: axum::response::Response| async move {
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        };
+
+        // Matching language: sent straight away
+        let (state, mut rx, _, _dir) =
+            guarded_state(r#"{"language": "French", "isEnglish": false}"#).await;
+        let response = send(state, "Bonjour madame, le loyer est payé", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(rx.try_recv().is_ok());
+
+        // English draft to a French chat: held back until confirmed
+        let (state, mut rx, hits, _dir) =
+            guarded_state(r#"{"language": "English", "isEnglish": true}"#).await;
+        let text = "Hi, the rent has been paid";
+        let response = send(state.clone(), text, None).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let result = body(response).await;
+        assert_eq!(result["detectedLanguage"], "English");
+        assert_eq!(result["chatLanguage"], "French");
+        assert!(rx.try_recv().is_err());
+
+        let token = result["confirmationToken"].as_str().unwrap().to_string();
+        let response = send(state.clone(), text, Some(token.clone())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        match rx.try_recv() {
+            Ok(BridgeCommand::Send { text: sent, .. }) => assert_eq!(sent, text),
+            _ => panic!("expected the confirmed message to be sent"),
+        }
+        // Confirming doesn't detect again, and the token is spent
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        let response = send(state.clone(), text, Some(token)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Sends from the web UI are recorded as such
+        let sent = state
+            .store
+            .get_messages_paginated(
+                contact_id,
+                None,
+                None,
+                None,
+                true,
+                MessageFilter {
+                    origin: Some("web"),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].origin.as_deref(), Some("web"));
+        assert!(state
+            .store
+            .get_messages_paginated(
+                contact_id,
+                None,
+                None,
+                None,
+                true,
+                MessageFilter {
+                    origin: Some("mcp"),
+                    ..Default::default()
+                }
+            )
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_session_names_attribute_sends() {
+        let (state, _dir) = test_state(None);
+        let contact_id = "34600000000@s.whatsapp.net";
+        state
+            .store
+            .upsert_contact(contact_id, Some("Maria"), None, Some("private"), 1)
+            .unwrap();
+        state.store.toggle_outgoing_translation(contact_id).unwrap();
+        let (tx, mut rx) = mpsc::channel(10);
+        state.set_command_tx(tx).await;
+        *state.connected.write().await = true;
+        state.change_password("a shared password").await.unwrap();
+
+        let body = |response: axum::response::Response| async move {
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        };
+        let login = |name: Option<&str>| {
+            let req = AuthRequest {
+                password: "a shared password".to_string(),
+                name: name.map(str::to_string),
+            };
+            let state = state.clone();
+            async move {
+                let response = auth_login(State(state), Json(req)).await.into_response();
+                let mut headers = HeaderMap::new();
+                let token = body(response).await["token"].as_str().unwrap().to_string();
+                headers.insert(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", token).parse().unwrap(),
+                );
+                headers
+            }
+        };
+        let send = |headers: HeaderMap, text: &str| {
+            let req = SendMessageRequest {
+                contact_id: contact_id.to_string(),
+                text: text.to_string(),
+                reply_to: None,
+                reply_to_sender: None,
+                reply_to_text: None,
+                confirmation_token: None,
+                ignore_quiet_hours: false,
+            };
+            let state = state.clone();
+            async move {
+                let response = send_message(State(state), headers, Json(req))
+                    .await
+                    .into_response();
+                assert_eq!(response.status(), StatusCode::OK);
+            }
+        };
+
+        // Two people signed in at once, and a session without a name
+        let ana = login(Some(" Ana ")).await;
+        let ben = login(Some("Ben")).await;
+        let unnamed = login(None).await;
+        send(ana.clone(), "Hola, soy Ana").await;
+        send(ben.clone(), "Hola, soy Ben").await;
+        send(unnamed.clone(), "Hola").await;
+        for _ in 0..3 {
+            assert!(rx.try_recv().is_ok());
+        }
+
+        let messages = |sent_by: Option<&str>| {
+            let params = MessagesQuery {
+                limit: Some(0),
+                cursor: None,
+                before: None,
+                origin: None,
+                sent_by: sent_by.map(str::to_string),
+                include_costs: false,
+            };
+            let state = state.clone();
+            async move {
+                let response =
+                    get_messages(State(state), Path(contact_id.to_string()), Query(params))
+                        .await
+                        .into_response();
+                body(response).await["messages"].as_array().unwrap().clone()
+            }
+        };
+        let all = messages(None).await;
+        let sent_by: Vec<_> = all.iter().map(|m| m["sentBy"].clone()).collect();
+        assert_eq!(
+            sent_by,
+            [
+                serde_json::json!("Ana"),
+                serde_json::json!("Ben"),
+                serde_json::Value::Null
+            ]
+        );
+        let by_ana = messages(Some("Ana")).await;
+        assert_eq!(by_ana.len(), 1);
+        assert_eq!(by_ana[0]["content"]["body"], "Hola, soy Ana");
+
+        // Naming a session later attributes what it sends from then on
+        let rename = |headers: HeaderMap, name: &str| {
+            let req = SessionNameRequest {
+                name: Some(name.to_string()),
+            };
+            let state = state.clone();
+            async move {
+                set_session_name(State(state), headers, Json(req))
+                    .await
+                    .into_response()
+                    .status()
+            }
+        };
+        assert_eq!(rename(unnamed.clone(), "Carla").await, StatusCode::OK);
+        assert_eq!(
+            rename(unnamed.clone(), &"x".repeat(MAX_SESSION_NAME_CHARS + 1)).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            rename(HeaderMap::new(), "Nobody").await,
+            StatusCode::UNAUTHORIZED
+        );
+        send(unnamed, "Hola otra vez").await;
+        assert_eq!(messages(Some("Carla")).await.len(), 1);
+        assert_eq!(messages(Some("Ben")).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_view_once_media_served_once() {
+        let (state, _dir) = test_state(None);
+        let contact_id = "34600000000@s.whatsapp.net";
+        state
+            .store
+            .upsert_contact(contact_id, None, None, Some("private"), 1)
+            .unwrap();
+        let content = serde_json::json!({
+            "type": "image",
+            "mime_type": "image/jpeg",
+            "view_once": true,
+            "has_media": true,
+        });
+        state
+            .store
+            .add_message(&StoredMessage {
+                content_type: "Image".to_string(),
+                content_json: content.to_string(),
+                content: Some(content),
+                original_text: None,
+                ..text_message("once", contact_id, 1, "")
+            })
+            .unwrap();
+        state.view_once.insert(
+            "once",
+            "3q2+7w==".to_string(),
+            Some("image/jpeg".to_string()),
+        );
+
+        let fetch = |id: &'static str| {
+            let state = state.clone();
+            async move {
+                get_media(
+                    State(state),
+                    Path(id.to_string()),
+                    Query(MediaQuery { format: None }),
+                )
+                .await
+                .into_response()
+            }
+        };
+
+        let first = fetch("once").await;
+        assert_eq!(first.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(first.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["media_data"], "3q2+7w==");
+
+        // The payload is gone and the message remembers it was opened
+        assert_eq!(fetch("once").await.status(), StatusCode::GONE);
+        let stored = state.store.get_message_by_id("once").unwrap().unwrap();
+        let stored: serde_json::Value = serde_json::from_str(&stored.content_json).unwrap();
+        assert!(stored["viewed_at"].as_i64().is_some());
+        assert_eq!(fetch("missing").await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_api_key_reported() {
+        use std::sync::atomic::AtomicU16;
+
+        // Claude answering every call with this status
+        let status = Arc::new(AtomicU16::new(500));
+        let answer = |status: Arc<AtomicU16>| {
+            move || {
+                let status = StatusCode::from_u16(status.load(Ordering::SeqCst)).unwrap();
+                async move {
+                    let body = serde_json::json!({
+                        "content": [{"text": r#"{"language": "French", "isEnglish": false}"#}],
+                        "usage": {"input_tokens": 10, "output_tokens": 5}
+                    });
+                    (status, Json(body))
+                }
+            }
+        };
+        let app = Router::new()
+            .route("/v1/models", get(answer(status.clone())))
+            .route("/v1/messages", post(answer(status.clone())));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let translator = Arc::new(
+            TranslationService::new("sk-typo".to_string(), "English".to_string())
+                .with_api_url(&format!("http://{}/v1/messages", addr)),
+        );
+        let (state, _dir) = test_state(Some(translator.clone()));
+        state.spawn_translation_availability_watcher();
+        let events = state.broadcast_tx.subscribe();
+        let status_json = || {
+            let state = state.clone();
+            async move { serde_json::to_value(get_status(State(state)).await.0).unwrap() }
+        };
+        let next_event = || {
+            let mut events = events.resubscribe();
+            async move {
+                let event = tokio::time::timeout(std::time::Duration::from_secs(5), events.recv())
+                    .await
+                    .unwrap()
+                    .unwrap();
+                serde_json::to_value(event).unwrap()
+            }
+        };
+
+        // The API failing says nothing about the key
+        assert!(translator.check_api_key().await.is_err());
+        assert!(status_json().await["translation_unavailable"].is_null());
+
+        status.store(401, Ordering::SeqCst);
+        let event = next_event();
+        assert!(!translator.check_api_key().await.unwrap());
+        let event = event.await;
+        assert_eq!(event["type"], "translation_availability");
+        assert_eq!(event["unavailable"]["reason"], "invalid_api_key");
+        let reported = status_json().await;
+        assert_eq!(
+            reported["translation_unavailable"]["reason"],
+            "invalid_api_key"
+        );
+        assert!(reported["translation_unavailable"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("ANTHROPIC_API_KEY"));
+
+        // Messages still come through, untranslated
+        let result = translator
+            .process_text("Bonjour tout le monde", None, None, false, false)
+            .await;
+        assert!(result.translated_text.is_none());
+        assert_eq!(
+            translator.unavailable(),
+            Some(TranslationUnavailable::InvalidApiKey)
+        );
+
+        // A call that gets through clears it
+        status.store(200, Ordering::SeqCst);
+        let event = next_event();
+        translator
+            .process_text("Bonjour tout le monde", None, None, false, false)
+            .await;
+        assert!(event.await["unavailable"].is_null());
+        assert!(status_json().await["translation_unavailable"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_stale_draft_write_conflicts() {
+        let (state, _dir) = test_state(None);
+        let mut events = state.broadcast_tx.subscribe();
+        let contact_id = "34600000000@s.whatsapp.net";
+        let save = |text: &str, updated_at: i64| {
+            let state = state.clone();
+            let req = SaveDraftRequest {
+                text: text.to_string(),
+                reply_to_message_id: None,
+                updated_at,
+            };
+            async move {
+                save_draft(State(state), Path(contact_id.to_string()), Json(req))
+                    .await
+                    .into_response()
+            }
+        };
+
+        assert_eq!(save("Hola, ¿qué tal?", 2000).await.status(), StatusCode::OK);
+        assert!(matches!(
+            events.try_recv(),
+            Ok(WebSocketEvent::DraftUpdated { draft: Some(_), .. })
+        ));
+
+        // The stale write is rejected with the newer draft, and not broadcast
+        let stale = save("Hola", 1000).await;
+        assert_eq!(stale.status(), StatusCode::CONFLICT);
+        let body = axum::body::to_bytes(stale.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["draft"]["text"], "Hola, ¿qué tal?");
+        assert_eq!(body["draft"]["updatedAt"], 2000);
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_translation_tone_setting() {
+        let (state, _dir) = test_state(None);
+        let contact_id = "447911123456@s.whatsapp.net";
+        state
+            .store
+            .upsert_contact(contact_id, Some("Boss"), None, Some("private"), 1)
+            .unwrap();
+        let update = |body: serde_json::Value| {
+            let state = state.clone();
+            let req: UpdateConversationSettingsRequest = serde_json::from_value(body).unwrap();
+            async move {
+                update_conversation_settings(State(state), Path(contact_id.to_string()), Json(req))
+                    .await
+                    .into_response()
+                    .status()
+            }
+        };
+        let tone = || async {
+            let response = get_conversation_settings(State(state.clone()), Path(contact_id.into()))
+                .await
+                .into_response();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()["translationTone"].clone()
+        };
+
+        assert_eq!(tone().await, serde_json::Value::Null);
+        let custom = serde_json::json!({"translationTone": {"custom": " Formal, she's my boss "}});
+        assert_eq!(update(custom).await, StatusCode::OK);
+        assert_eq!(
+            tone().await,
+            serde_json::json!({"custom": "Formal, she's my boss"})
+        );
+
+        // Left alone when omitted; a bad custom tone changes nothing
+        assert_eq!(
+            update(serde_json::json!({"muted": true})).await,
+            StatusCode::OK
+        );
+        let too_long = serde_json::json!({"translationTone": {"custom": "x".repeat(201)}});
+        assert_eq!(update(too_long).await, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            tone().await,
+            serde_json::json!({"custom": "Formal, she's my boss"})
+        );
+
+        for (choice, expected) in [("formal", "formal"), ("default", "null")] {
+            assert_eq!(
+                update(serde_json::json!({ "translationTone": choice })).await,
+                StatusCode::OK
+            );
+            assert_eq!(tone().await.to_string().trim_matches('"'), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timezone_setting() {
+        let (state, _dir) = test_state(None);
+        let contact_id = "818012345678@s.whatsapp.net";
+        state
+            .store
+            .upsert_contact(contact_id, Some("Yuki"), None, Some("private"), 1)
+            .unwrap();
+        let update = |body: serde_json::Value| {
+            let state = state.clone();
+            let req: UpdateConversationSettingsRequest = serde_json::from_value(body).unwrap();
+            async move {
+                update_conversation_settings(State(state), Path(contact_id.to_string()), Json(req))
+                    .await
+                    .into_response()
+                    .status()
+            }
+        };
+        let settings = || async {
+            let response = get_conversation_settings(State(state.clone()), Path(contact_id.into()))
+                .await
+                .into_response();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        };
+
+        // Nothing to infer from yet
+        let unset = settings().await;
+        assert_eq!(unset["timezone"], serde_json::Value::Null);
+        assert!(unset.get("localTime").is_none());
+
+        assert_eq!(
+            update(serde_json::json!({"timezone": " Asia/Tokyo "})).await,
+            StatusCode::OK
+        );
+        let set = settings().await;
+        assert_eq!(set["timezone"], "Asia/Tokyo");
+        assert_eq!(set["timezoneInferred"], false);
+        assert_eq!(set["utcOffsetMinutes"], 540);
+        assert_eq!(set["localTime"].as_str().unwrap().len(), 5);
+        assert!(set["likelyAsleep"].is_boolean());
+
+        // An unknown zone changes nothing; empty goes back to inferring
+        assert_eq!(
+            update(serde_json::json!({"timezone": "Mars/Olympus"})).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            update(serde_json::json!({"muted": true})).await,
+            StatusCode::OK
+        );
+        assert_eq!(settings().await["timezone"], "Asia/Tokyo");
+        assert_eq!(
+            update(serde_json::json!({"timezone": ""})).await,
+            StatusCode::OK
+        );
+        assert_eq!(settings().await["timezone"], serde_json::Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_lagging_websocket_client_gets_resync() {
+        let (state, _dir) = test_state(None);
+        let mut rx = state.broadcast_tx.subscribe();
+
+        // Flood the channel well past its capacity without reading
+        for i in 0..300 {
+            let _ = state
+                .broadcast_tx
+                .send(WebSocketEvent::ConversationCleared {
+                    contact_id: format!("{}@s.whatsapp.net", i),
+                });
+        }
+
+        let resync: serde_json::Value =
+            serde_json::from_str(&next_event_json(&mut rx, &state).await.unwrap()).unwrap();
+        assert_eq!(resync["type"], "resync");
+        let missed = resync["missed"].as_u64().unwrap();
+        assert!(missed >= 100, "missed {}", missed);
+        assert_eq!(state.broadcast_lag.lagged.load(Ordering::Relaxed), 1);
+        assert_eq!(state.broadcast_lag.missed.load(Ordering::Relaxed), missed);
+
+        // Then the events still in the channel follow
+        let next: serde_json::Value =
+            serde_json::from_str(&next_event_json(&mut rx, &state).await.unwrap()).unwrap();
+        assert_eq!(
+            next["contact_id"],
+            format!("{}@s.whatsapp.net", missed).as_str()
+        );
+
+        // Media is stripped before broadcasting, and anything still too big
+        // becomes a marker
+        let message = |content: serde_json::Value| StoredMessage {
+            content_type: "Video".to_string(),
+            content_json: content.to_string(),
+            content: Some(content),
+            original_text: None,
+            ..text_message("big", "34600000000@s.whatsapp.net", 1, "")
+        };
+        let mut rx = state.broadcast_tx.subscribe();
+        state.broadcast_message(
+            message(serde_json::json!({
+                "type": "video",
+                "media_data": "A".repeat(2 * MAX_WS_EVENT_BYTES),
+            })),
+            None,
+        );
+        let json = next_event_json(&mut rx, &state).await.unwrap();
+        assert!(json.len() < 4096);
+        let event: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(event["message"]["content"]["has_media"], true);
+        assert!(event["message"]["content"].get("media_data").is_none());
+
+        state.broadcast_message(
+            message(serde_json::json!({
+                "type": "text",
+                "body": "a".repeat(2 * MAX_WS_EVENT_BYTES),
+            })),
+            None,
+        );
+        let event: serde_json::Value =
+            serde_json::from_str(&next_event_json(&mut rx, &state).await.unwrap()).unwrap();
+        assert_eq!(
+            event,
+            serde_json::json!({
+                "type": "message",
+                "payload_truncated": true,
+                "contact_id": "34600000000@s.whatsapp.net",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_auto_reply_suggestions_daily_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MessageStore::new(dir.path()).unwrap();
+        let (url, hits) = spawn_counting_provider(r#"["Sí", "No", "¿Cuándo?"]"#).await;
+        let translator = TranslationService::new("test-key".to_string(), "English".to_string())
+            .with_api_url(&url);
+        let state = AppState::new(
+            store,
+            dir.path().to_path_buf(),
+            dir.path().to_path_buf(),
+            Some(Arc::new(translator)),
+            None,
+            Some(1),
+            LanguageGuardConfig::default(),
+        );
+
+        let message = |id: &str, chat_type: &str| StoredMessage {
+            chat_type: chat_type.to_string(),
+            translated_text: Some("Are you coming tomorrow?".to_string()),
+            source_language: Some("Spanish".to_string()),
+            is_translated: true,
+            ..text_message(id, "34600000000@s.whatsapp.net", 1, "¿Vienes mañana?")
+        };
+
+        // Groups never get automatic suggestions
+        assert!(state
+            .auto_reply_suggestions(&message("g1", "group"))
+            .await
+            .is_none());
+
+        let suggestions = state
+            .auto_reply_suggestions(&message("m1", "private"))
+            .await
+            .unwrap();
+        assert_eq!(suggestions, vec!["Sí", "No", "¿Cuándo?"]);
+
+        // The cap of one per day is now used up
+        assert!(state
+            .auto_reply_suggestions(&message("m2", "private"))
+            .await
+            .is_none());
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
     }
 
-    StatusCode::OK
-}
+    #[tokio::test]
+    async fn test_reaction_add_replace_remove() {
+        let (state, _dir) = test_state(None);
+        let contact_id = "34600000000@s.whatsapp.net";
+        state
+            .store
+            .upsert_contact(contact_id, None, None, Some("private"), 1)
+            .unwrap();
+        state
+            .store
+            .add_message(&StoredMessage {
+                original_text: None,
+                ..text_message("target", contact_id, 1, "hola")
+            })
+            .unwrap();
+        let (tx, mut rx) = mpsc::channel(10);
+        state.set_command_tx(tx).await;
+        *state.connected.write().await = true;
+        let mut events = state.broadcast_tx.subscribe();
+
+        let react = |emoji: &str| {
+            let state = state.clone();
+            let req = SendReactionRequest {
+                contact_id: contact_id.to_string(),
+                message_id: "target".to_string(),
+                sender_jid: None,
+                emoji: emoji.to_string(),
+            };
+            async move {
+                let response = send_reaction(State(state), Json(req)).await.into_response();
+                assert_eq!(response.status(), StatusCode::OK);
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+            }
+        };
+        let sent_request_id = |rx: &mut mpsc::Receiver<BridgeCommand>| match rx.try_recv() {
+            Ok(BridgeCommand::SendReaction {
+                request_id: Some(id),
+                ..
+            }) => id,
+            other => panic!("expected a reaction command, got {:?}", other),
+        };
 
-// WebSocket handler
+        // Add: stored, broadcast and returned straight away
+        let body = react("👍").await;
+        assert_eq!(body["reactions"][0]["emoji"], "👍");
+        assert_eq!(body["reactions"][0]["fromMe"], true);
+        assert!(matches!(
+            events.try_recv(),
+            Ok(WebSocketEvent::ReactionAdded { ref emoji, .. }) if emoji == "👍"
+        ));
+        let first = sent_request_id(&mut rx);
+        assert!(state.reconcile_reaction(first, true).await);
+
+        // Replace: only the new emoji is left
+        let body = react("❤️").await;
+        assert_eq!(body["reactions"].as_array().unwrap().len(), 1);
+        assert_eq!(body["reactions"][0]["emoji"], "❤️");
+        events.try_recv().unwrap();
+
+        // A failed send is retracted, putting the previous reaction back
+        let failed = sent_request_id(&mut rx);
+        assert!(state.reconcile_reaction(failed, false).await);
+        match events.try_recv() {
+            Ok(WebSocketEvent::ReactionAdded {
+                emoji, reactions, ..
+            }) => {
+                assert_eq!(emoji, "👍");
+                assert_eq!(reactions.len(), 1);
+            }
+            other => panic!("expected the previous reaction back, got {:?}", other),
+        }
+        assert!(!state.reconcile_reaction(failed, false).await);
+
+        // Remove: an empty emoji goes to WhatsApp and nothing is left here
+        let body = react("").await;
+        assert!(body["reactions"].as_array().unwrap().is_empty());
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(BridgeCommand::SendReaction { ref emoji, .. }) if emoji.is_empty()
+        ));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(WebSocketEvent::ReactionRemoved { ref reactions, .. }) if reactions.is_empty()
+        ));
+
+        // A failed removal is retracted too, putting the reaction back
+        let failed_removal = state.request_id_counter.load(Ordering::SeqCst) - 1;
+        state.reconcile_reaction(failed_removal, false).await;
+        assert_eq!(state.reactions_for(contact_id, "target")[0].emoji, "👍");
+    }
 
-async fn websocket_handler(
-    ws: WebSocketUpgrade,
-    State(state): State<Arc<AppState>>,
-) -> impl IntoResponse {
-    ws.on_upgrade(|socket| handle_websocket(socket, state))
-}
+    #[tokio::test]
+    async fn test_body_limits() {
+        use tower::ServiceExt;
+
+        let (state, _dir) = test_state(None);
+        let contact_id = "34600000000@s.whatsapp.net";
+        state
+            .store
+            .upsert_contact(contact_id, None, None, Some("private"), 1)
+            .unwrap();
+        let (tx, mut rx) = mpsc::channel(10);
+        state.set_command_tx(tx).await;
+        *state.connected.write().await = true;
+        let router = create_router(state);
+        let post = |uri: &str, body: String| {
+            let router = router.clone();
+            let request = axum::http::Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header(header::HOST, "localhost:3000")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(axum::body::Body::from(body))
+                .unwrap();
+            async move {
+                let response = router.oneshot(request).await.unwrap();
+                let status = response.status();
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (
+                    status,
+                    serde_json::from_slice::<serde_json::Value>(&bytes).ok(),
+                )
+            }
+        };
+        let image_body = |media_data: &str| {
+            format!(
+                r#"{{"contactId":"{}","mediaData":"{}","mimeType":"image/png"}}"#,
+                contact_id, media_data
+            )
+        };
 
-async fn handle_websocket(socket: WebSocket, state: Arc<AppState>) {
-    let (mut sender, mut receiver) = socket.split();
+        // The largest image that fits goes through
+        let overhead = image_body("").len();
+        let fits = "A".repeat((MEDIA_BODY_LIMIT - overhead) / 4 * 4);
+        let (status, _) = post("/api/send-image", image_body(&fits)).await;
+        assert_eq!(status, StatusCode::OK);
+        match rx.try_recv() {
+            Ok(BridgeCommand::SendImage { media_data, .. }) => {
+                assert_eq!(media_data.len(), fits.len())
+            }
+            other => panic!("Expected an image send, got {:?}", other.is_ok()),
+        }
 
-    // Subscribe to broadcast events
-    let mut rx = state.broadcast_tx.subscribe();
+        let too_big = format!("{}AAAA", fits);
+        let (status, body) = post("/api/send-image", image_body(&too_big)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body.unwrap()["limitBytes"], MEDIA_BODY_LIMIT);
+        assert!(rx.try_recv().is_err());
 
-    // Send current status
-    let status = WebSocketEvent::Status {
-        connected: *state.connected.read().await,
-        phone: state.phone.read().await.clone(),
-        name: state.name.read().await.clone(),
-    };
+        let (status, _) = post("/api/send-image", image_body("not base64!")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
 
-    if let Ok(json) = serde_json::to_string(&status) {
-        let _ = sender.send(Message::Text(json)).await;
+        // Text routes have a much smaller limit, and MCP its own
+        let text = "a".repeat(TEXT_BODY_LIMIT);
+        let (status, body) = post(
+            "/api/translate",
+            format!(r#"{{"text":"{}","messageId":"m1"}}"#, text),
+        )
+        .await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body.unwrap()["limitBytes"], TEXT_BODY_LIMIT);
+
+        let (status, body) = post("/mcp", "a".repeat(MCP_BODY_LIMIT + 1)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body.unwrap()["limitBytes"], MCP_BODY_LIMIT);
     }
 
-    // Send current QR if available
-    if let Some(qr) = state.qr_code.read().await.clone() {
-        let event = WebSocketEvent::Qr { data: qr };
-        if let Ok(json) = serde_json::to_string(&event) {
-            let _ = sender.send(Message::Text(json)).await;
+    #[tokio::test]
+    async fn test_export_chat_pdf() {
+        use tower::ServiceExt;
+
+        let (state, _dir) = test_state(None);
+        let contact_id = "34600000000@s.whatsapp.net";
+        state
+            .store
+            .upsert_contact(contact_id, Some("Ana"), None, Some("private"), 1)
+            .unwrap();
+        let router = create_router(state);
+        let get = |uri: String| {
+            let request = axum::http::Request::builder()
+                .uri(uri)
+                .header(header::HOST, "localhost:3000")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            router.clone().oneshot(request)
+        };
+
+        let response = get(format!(
+            "/api/export/{}?format=pdf&from=2026-03-01&to=2026-03-31",
+            contact_id
+        ))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/pdf");
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"chat-ana-from-2026-03-01-to-2026-03-31.pdf\""
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.starts_with(b"%PDF-"));
+
+        for (uri, status, error) in [
+            (
+                format!("/api/export/{}?format=csv", contact_id),
+                StatusCode::BAD_REQUEST,
+                "unsupported_format",
+            ),
+            (
+                format!("/api/export/{}?from=March", contact_id),
+                StatusCode::BAD_REQUEST,
+                "invalid_date",
+            ),
+            (
+                "/api/export/nobody@s.whatsapp.net".to_string(),
+                StatusCode::NOT_FOUND,
+                "contact_not_found",
+            ),
+        ] {
+            let response = get(uri).await.unwrap();
+            assert_eq!(response.status(), status);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["error"], error);
         }
     }
 
-    // Handle incoming messages and broadcast events
-    loop {
-        tokio::select! {
-            // Broadcast events to client
-            Ok(event) = rx.recv() => {
-                if let Ok(json) = serde_json::to_string(&event) {
-                    if sender.send(Message::Text(json)).await.is_err() {
-                        break;
-                    }
-                }
+    #[tokio::test]
+    async fn test_client_version_gate() {
+        use crate::api_version::{API_VERSION, API_VERSION_HEADER, CLIENT_VERSION_HEADER};
+        use tower::ServiceExt;
+
+        let (state, _dir) = test_state(None);
+        let contact_id = "34600000000@s.whatsapp.net";
+        state
+            .store
+            .upsert_contact(contact_id, Some("Ana"), None, Some("private"), 1)
+            .unwrap();
+        let router = create_router(state.clone());
+        let send = |method: &str, uri: &str, client_version: Option<String>| {
+            let router = router.clone();
+            let mut request = axum::http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::HOST, "localhost:3000");
+            if let Some(version) = client_version {
+                request = request.header(CLIENT_VERSION_HEADER, version);
             }
+            let request = request.body(axum::body::Body::empty()).unwrap();
+            async move { router.oneshot(request).await.unwrap() }
+        };
+        let pin = format!("/api/contacts/{}/pin", contact_id);
+        let outdated = (API_VERSION + 1).to_string();
+
+        // Every response says which API version the server is on
+        for (method, uri) in [("GET", "/api/version"), ("GET", "/no-such-file.js")] {
+            let response = send(method, uri, None).await;
+            assert_eq!(
+                response.headers()[API_VERSION_HEADER],
+                API_VERSION.to_string().as_str(),
+                "{}",
+                uri
+            );
+        }
 
-            // Handle client messages (for future use)
-            msg = receiver.next() => {
-                match msg {
-                    Some(Ok(Message::Close(_))) | None => break,
-                    Some(Ok(Message::Ping(data))) => {
-                        let _ = sender.send(Message::Pong(data)).await;
-                    }
-                    _ => {}
-                }
+        // Current clients and clients that don't say can make changes
+        let response = send("POST", &pin, Some(format!("{}.2", API_VERSION))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send("POST", &pin, None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // An outdated client may read, but is told to update before changing anything
+        let response = send("POST", &pin, Some(outdated.clone())).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(
+            response.headers()[API_VERSION_HEADER],
+            API_VERSION.to_string().as_str()
+        );
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["updateRequired"], true);
+        assert!(state
+            .store
+            .get_contact(contact_id)
+            .unwrap()
+            .unwrap()
+            .pinned_at
+            .is_none());
+
+        let response = send("GET", "/api/version", Some(outdated.clone())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let version: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(version["apiVersion"], API_VERSION);
+        assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(version["clientVersions"][&outdated], 2);
+        assert_eq!(version["clientVersions"][format!("{}.2", API_VERSION)], 1);
+    }
+
+    #[tokio::test]
+    async fn test_oauth_approval_is_bound_to_form_and_browser() {
+        use tower::ServiceExt;
+
+        let (state, _dir) = test_state(None);
+        let router = create_router(state);
+        let send = |request: axum::http::Request<axum::body::Body>| {
+            let router = router.clone();
+            async move { router.oneshot(request).await.unwrap() }
+        };
+        let authorize = |redirect_uri: &str| {
+            axum::http::Request::builder()
+                .uri(format!(
+                    "/oauth/authorize?response_type=code&client_id=client_test&redirect_uri={}\
+                     &state=xyz&code_challenge=abc&code_challenge_method=S256",
+                    urlencoding::encode(redirect_uri)
+                ))
+                .header(header::HOST, "localhost:3000")
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+        let approve = |session_key: &str, csrf_token: &str, cookie: Option<&str>| {
+            let mut request = axum::http::Request::builder()
+                .method("POST")
+                .uri("/oauth/approve")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+            if let Some(cookie) = cookie {
+                request = request.header(header::COOKIE, cookie);
             }
+            request
+                .body(axum::body::Body::from(format!(
+                    "session_key={}&csrf_token={}&approved=true",
+                    session_key, csrf_token
+                )))
+                .unwrap()
+        };
+        // Start an approval, returning the form's session key and CSRF token
+        // and the cookie set for the browser
+        let start = || async {
+            let response = send(authorize("https://claude.ai/api/mcp/auth_callback")).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let cookie = response
+                .headers()
+                .get(header::SET_COOKIE)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string();
+            assert!(cookie.contains("HttpOnly") && cookie.contains("SameSite=Strict"));
+            let cookie = cookie.split(';').next().unwrap().to_string();
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let html = String::from_utf8(bytes.to_vec()).unwrap();
+            let field = |name: &str| {
+                let marker = format!(r#"name="{}" value=""#, name);
+                let start = html.find(&marker).unwrap() + marker.len();
+                html[start..].split('"').next().unwrap().to_string()
+            };
+            (field("session_key"), field("csrf_token"), cookie)
+        };
+
+        // Happy path: redirected back with a code and the client's state
+        let (session_key, csrf_token, cookie) = start().await;
+        let response = send(approve(&session_key, &csrf_token, Some(&cookie))).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        assert!(location.starts_with("https://claude.ai/api/mcp/auth_callback?code="));
+        assert!(location.ends_with("&state=xyz"));
+
+        // Forged: a post from another browser (no cookie) or without the
+        // form's token. Neither uses up the real approval
+        let (session_key, csrf_token, cookie) = start().await;
+        let response = send(approve(&session_key, &csrf_token, None)).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = send(approve(&session_key, &csrf_token, Some(&cookie))).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        let response = send(approve(&session_key, &csrf_token, Some(&cookie))).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let (session_key, _, cookie) = start().await;
+        let response = send(approve(&session_key, "guessed", Some(&cookie))).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let (session_key, csrf_token, _) = start().await;
+        let response = send(approve(
+            &session_key,
+            &csrf_token,
+            Some(&format!("{}=someone-else", BROWSER_COOKIE)),
+        ))
+        .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // Request parameters shown on error pages are escaped
+        let response = send(
+            axum::http::Request::builder()
+                .uri(
+                    "/oauth/authorize?response_type=%3Cscript%3Ealert(1)%3C/script%3E\
+                      &client_id=client_test&redirect_uri=https://claude.ai/cb\
+                      &code_challenge=abc&code_challenge_method=S256",
+                )
+                .header(header::HOST, "localhost:3000")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let html = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(html.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(!html.contains("<script>"));
+
+        // Unsafe redirect URIs never get an approval page
+        for uri in [
+            "javascript:alert(1)",
+            "data:text/html,<script>alert(1)</script>",
+            "http://evil.example.com/cb",
+        ] {
+            let response = send(authorize(uri)).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+            assert!(response.headers().get(header::SET_COOKIE).is_none());
+        }
+
+        // Rejected registrations too
+        let response = send(
+            axum::http::Request::builder()
+                .method("POST")
+                .uri("/oauth/register")
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(axum::body::Body::from(
+                    r#"{"redirect_uris": ["https://claude.ai/cb", "javascript:alert(1)"]}"#,
+                ))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Each client only gets so many attempts
+        let mut statuses = Vec::new();
+        for _ in 0..10 {
+            let response = send(authorize("https://claude.ai/api/mcp/auth_callback")).await;
+            statuses.push(response.status());
         }
+        assert_eq!(statuses[0], StatusCode::OK);
+        assert_eq!(statuses.last(), Some(&StatusCode::TOO_MANY_REQUESTS));
     }
-}
 
-// MCP (Model Context Protocol) HTTP handler
-// Uses Streamable HTTP transport (POST for requests, SSE for responses)
+    #[tokio::test]
+    async fn test_own_profile_updates() {
+        let (state, _dir) = test_state(None);
+        let mut rx = state.broadcast_tx.subscribe();
 
-use rmcp::transport::streamable_http_server::{
-    session::local::LocalSessionManager, StreamableHttpServerConfig, StreamableHttpService,
-};
+        // Only a real change is saved and broadcast
+        assert_eq!(
+            state.store.get_own_profile().unwrap(),
+            OwnProfile::default()
+        );
+        let profile = state
+            .update_own_profile(|p| p.name = Some("Ana".to_string()))
+            .unwrap();
+        assert_eq!(profile.name.as_deref(), Some("Ana"));
+        assert!(profile.updated_at.is_some());
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(WebSocketEvent::ProfileUpdated { .. })
+        ));
+        state
+            .update_own_profile(|p| p.name = Some("Ana".to_string()))
+            .unwrap();
+        assert!(rx.try_recv().is_err());
+        assert_eq!(state.store.get_own_profile().unwrap(), profile);
+
+        let put = |body: serde_json::Value| {
+            let state = state.clone();
+            async move {
+                update_profile(State(state), Json(serde_json::from_value(body).unwrap()))
+                    .await
+                    .into_response()
+                    .status()
+            }
+        };
+        let name = serde_json::json!({ "name": "Ana María" });
+        assert_eq!(put(serde_json::json!({})).await, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            put(serde_json::json!({ "name": "  " })).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(put(name.clone()).await, StatusCode::SERVICE_UNAVAILABLE);
+
+        // A bridge that predates profile commands is told apart from a
+        // disconnected one
+        state.set_connected(true, None, None).await;
+        *state.bridge_capabilities.write().await = Some(vec!["send_text".to_string()]);
+        assert_eq!(put(name).await, StatusCode::NOT_IMPLEMENTED);
+
+        state.store.clear_all().unwrap();
+        assert_eq!(
+            state.store.get_own_profile().unwrap(),
+            OwnProfile::default()
+        );
+    }
 
-fn create_mcp_service(
-    store: Arc<MessageStore>,
-    command_tx: Option<mpsc::Sender<BridgeCommand>>,
-    translator: Option<Arc<TranslationService>>,
-) -> StreamableHttpService<WhatsAppMcpServer, LocalSessionManager> {
-    let session_manager = Arc::new(LocalSessionManager::default());
-    let config = StreamableHttpServerConfig {
-        stateful_mode: false, // Stateless mode - simpler, no session management needed
-        ..Default::default()
-    };
+    #[tokio::test]
+    async fn test_undo_send() {
+        let (url, hits) = spawn_counting_provider("Bonjour à tous").await;
+        let translator = TranslationService::new("test-key".to_string(), "English".to_string())
+            .with_api_url(&url);
+        let (state, _dir) = test_state(Some(Arc::new(translator)));
+        let contact_id = "33600000000@s.whatsapp.net";
+        state
+            .store
+            .upsert_contact(contact_id, Some("Madame Leroy"), None, Some("private"), 1)
+            .unwrap();
+        state
+            .store
+            .update_conversation_settings(
+                contact_id,
+                &ConversationSettings {
+                    language_override: Some("French".to_string()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let (tx, mut rx) = mpsc::channel(10);
+        state.set_command_tx(tx).await;
+        *state.connected.write().await = true;
+
+        let window = |secs: u64| {
+            let state = state.clone();
+            async move {
+                update_undo_window_settings(
+                    State(state),
+                    Json(UndoWindowSettings {
+                        undo_window_secs: secs,
+                    }),
+                )
+                .await
+                .into_response()
+                .status()
+            }
+        };
+        assert_eq!(
+            window(MAX_UNDO_WINDOW_SECS + 1).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(window(1).await, StatusCode::OK);
+
+        let send = || {
+            let req = SendMessageRequest {
+                contact_id: contact_id.to_string(),
+                text: "Hello everyone".to_string(),
+                reply_to: None,
+                reply_to_sender: None,
+                reply_to_text: None,
+                confirmation_token: None,
+                ignore_quiet_hours: false,
+            };
+            let state = state.clone();
+            async move {
+                let response = send_message(State(state), HeaderMap::new(), Json(req))
+                    .await
+                    .into_response();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+            }
+        };
+        let undo = |message_id: String| {
+            let state = state.clone();
+            async move {
+                undo_send(State(state), Path(message_id))
+                    .await
+                    .into_response()
+                    .status()
+            }
+        };
+        let wait_out_window = || tokio::time::sleep(std::time::Duration::from_millis(1500));
+
+        // Undone within the window: never sent, never translated, not stored
+        let mut events = state.broadcast_tx.subscribe();
+        let body = send().await;
+        let message_id = body["messageId"].as_str().unwrap().to_string();
+        assert_eq!(
+            body["undoableUntil"],
+            body["timestamp"].as_i64().unwrap() + 1000
+        );
+        assert_eq!(body["isTranslated"], false);
+        assert!(state
+            .store
+            .get_message_by_id(&message_id)
+            .unwrap()
+            .is_some());
+        assert_eq!(undo(message_id.clone()).await, StatusCode::OK);
+        assert!(state
+            .store
+            .get_message_by_id(&message_id)
+            .unwrap()
+            .is_none());
+        loop {
+            match events.recv().await.unwrap() {
+                WebSocketEvent::MessageRemoved {
+                    contact_id: removed_from,
+                    message_id: removed,
+                } => {
+                    assert_eq!(removed_from, contact_id);
+                    assert_eq!(removed, message_id);
+                    break;
+                }
+                _ => continue,
+            }
+        }
+        wait_out_window().await;
+        assert!(rx.try_recv().is_err());
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 0);
+
+        // After the window it's translated, sent and can't be undone
+        let message_id = send().await["messageId"].as_str().unwrap().to_string();
+        wait_out_window().await;
+        match rx.try_recv().unwrap() {
+            BridgeCommand::Send { to, text, .. } => {
+                assert_eq!(to, contact_id);
+                assert_eq!(text, "Bonjour à tous");
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+        assert!(hits.load(std::sync::atomic::Ordering::SeqCst) > 0);
+        let sent = state.store.get_message_by_id(&message_id).unwrap().unwrap();
+        assert!(sent.is_translated);
+        assert_eq!(sent.original_text.as_deref(), Some("Hello everyone"));
+        assert_eq!(sent.translated_text.as_deref(), Some("Bonjour à tous"));
+        assert_eq!(sent.source_language.as_deref(), Some("French"));
+        assert_eq!(undo(message_id).await, StatusCode::GONE);
+        assert_eq!(undo("pending_0".to_string()).await, StatusCode::NOT_FOUND);
+    }
 
-    StreamableHttpService::new(
-        move || {
-            // Create a new MCP server instance for each request
-            Ok(WhatsAppMcpServer::new(
-                store.clone(),
-                command_tx.clone(),
-                translator.clone(),
-            ))
-        },
-        session_manager,
-        config,
-    )
-}
+    #[tokio::test]
+    async fn test_manual_translation_cost() {
+        let (url, _) =
+            spawn_counting_provider(r#"{"language": "Spanish", "isEnglish": false}"#).await;
+        let translator = TranslationService::new("test-key".to_string(), "English".to_string())
+            .with_api_url(&url);
+        let (state, _dir) = test_state(Some(Arc::new(translator)));
+        let contact_id = "34600000000@s.whatsapp.net";
+        state
+            .store
+            .upsert_contact(contact_id, None, None, Some("private"), 1)
+            .unwrap();
+        for (id, timestamp) in [("m1", 1), ("m2", 2)] {
+            state
+                .store
+                .add_message(&StoredMessage {
+                    original_text: None,
+                    ..text_message(id, contact_id, timestamp, "hola")
+                })
+                .unwrap();
+        }
+        let body = |response: axum::response::Response| async move {
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        };
+        let translate = || {
+            let req = TranslateMessageRequest {
+                text: "hola, ¿qué tal?".to_string(),
+                message_id: "m1".to_string(),
+                contact_id: contact_id.to_string(),
+            };
+            let state = state.clone();
+            async move {
+                translate_message(State(state), Json(req))
+                    .await
+                    .into_response()
+            }
+        };
+        let messages = |include_costs: bool| {
+            let state = state.clone();
+            async move {
+                get_messages(
+                    State(state),
+                    Path(contact_id.to_string()),
+                    Query(MessagesQuery {
+                        limit: None,
+                        cursor: None,
+                        before: None,
+                        origin: None,
+                        sent_by: None,
+                        include_costs,
+                    }),
+                )
+                .await
+                .into_response()
+            }
+        };
 
-async fn mcp_handler(
-    State(state): State<Arc<AppState>>,
-    Host(host): Host,
-    request: axum::http::Request<axum::body::Body>,
-) -> impl IntoResponse {
-    // Check OAuth Bearer token authentication
-    let auth_header = request
-        .headers()
-        .get(header::AUTHORIZATION)
-        .and_then(|h| h.to_str().ok());
+        // Translating again adds to what the message has cost
+        let first = body(translate().await).await["translationCostUsd"]
+            .as_f64()
+            .unwrap();
+        assert!(first > 0.0);
+        let total = body(translate().await).await["translationCostUsd"]
+            .as_f64()
+            .unwrap();
+        assert!((total - 2.0 * first).abs() < 1e-9);
+
+        // Costs are only listed when asked for, and untranslated messages cost nothing
+        let listed = body(messages(false).await).await;
+        assert!(listed["messages"][0].get("translationCostUsd").is_none());
+        let listed = body(messages(true).await).await;
+        let costs: HashMap<&str, f64> = listed["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| {
+                (
+                    m["id"].as_str().unwrap(),
+                    m["translationCostUsd"].as_f64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(costs, HashMap::from([("m1", total), ("m2", 0.0)]));
+    }
 
-    let is_authenticated = if let Some(header) = auth_header {
-        if let Some(token) = header.strip_prefix("Bearer ") {
-            // Validate the OAuth access token
-            match state.store.oauth_validate_access_token(token) {
-                Ok(Some(_)) => {
-                    info!("MCP authenticated via OAuth token");
-                    true
-                }
-                Ok(None) => {
-                    info!("MCP request with invalid/expired OAuth token");
-                    false
+    #[tokio::test]
+    async fn test_retranslate_errors() {
+        let (url, _) =
+            spawn_counting_provider(r#"{"language": "Spanish", "isEnglish": false}"#).await;
+        let translator = TranslationService::new("test-key".to_string(), "English".to_string())
+            .with_api_url(&url);
+        let (state, _dir) = test_state(Some(Arc::new(translator)));
+        let contact_id = "34600000000@s.whatsapp.net";
+        state
+            .store
+            .upsert_contact(contact_id, None, None, Some("private"), 1)
+            .unwrap();
+        for (id, status) in [
+            ("failed", TranslationStatus::Error),
+            ("history", TranslationStatus::Pending),
+            ("short", TranslationStatus::SkippedShort),
+        ] {
+            state
+                .store
+                .add_message(&StoredMessage {
+                    translation_status: Some(status),
+                    ..text_message(id, contact_id, 1, "¿Vienes a cenar?")
+                })
+                .unwrap();
+        }
+        let body = |response: axum::response::Response| async move {
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        };
+        let stats = || {
+            let state = state.clone();
+            async move {
+                let query = TranslationStatsQuery {
+                    contact_id: Some(contact_id.to_string()),
+                };
+                body(
+                    get_translation_stats(State(state), Query(query))
+                        .await
+                        .into_response(),
+                )
+                .await
+            }
+        };
+        let counts = stats().await;
+        assert_eq!(counts["counts"]["error"], 1);
+        assert_eq!(counts["counts"]["pending"], 1);
+        assert_eq!(counts["total"], 3);
+
+        // Only the failed and never-translated messages are translated again
+        let mut events = state.broadcast_tx.subscribe();
+        let response = retranslate_errors(State(state.clone()), Path(contact_id.to_string()))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let queued = body(response).await;
+        assert_eq!(queued["queued"], 2);
+        let job_id = queued["jobId"].as_str().unwrap().to_string();
+
+        // It runs as a job, reporting each message as it's done
+        let mut translated = Vec::new();
+        let mut progress = Vec::new();
+        let job = loop {
+            match events.recv().await.unwrap() {
+                WebSocketEvent::MessageTranslated {
+                    message_id,
+                    translation_status,
+                    ..
+                } => {
+                    assert_eq!(translation_status, TranslationStatus::Translated);
+                    translated.push(message_id);
                 }
-                Err(e) => {
-                    error!("Failed to validate OAuth token: {}", e);
-                    false
+                WebSocketEvent::JobProgress { job } if job.id == job_id => {
+                    if job.state == crate::jobs::JobState::Done {
+                        break job;
+                    }
+                    progress.push((job.state, job.progress.done));
                 }
+                _ => {}
             }
-        } else {
-            false
+        };
+        assert_eq!(translated, ["failed", "history"]);
+        use crate::jobs::JobState::{Queued, Running};
+        assert_eq!(
+            progress,
+            [(Queued, 0), (Running, 0), (Running, 1), (Running, 2)]
+        );
+        assert_eq!(job.kind, "retranslate");
+        assert_eq!(job.progress.total, Some(2));
+        assert_eq!(state.jobs.get(&job_id).unwrap(), job);
+        let counts = stats().await;
+        assert_eq!(counts["counts"]["translated"], 2);
+        assert_eq!(counts["counts"]["skipped_short"], 1);
+        assert!(
+            state
+                .store
+                .get_message_by_id("failed")
+                .unwrap()
+                .unwrap()
+                .is_translated
+        );
+    }
+
+    #[tokio::test]
+    async fn test_save_note() {
+        let (state, _dir) = test_state(None);
+        let save = |body: serde_json::Value| {
+            let state = state.clone();
+            async move {
+                let response = save_note(State(state), Json(serde_json::from_value(body).unwrap()))
+                    .await
+                    .into_response();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (
+                    status,
+                    serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+                )
+            }
+        };
+        let text = serde_json::json!({ "text": "Buy bread" });
+
+        // Disconnected is a 503 like any send
+        let (status, body) = save(text.clone()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["error"], "bridge_unavailable");
+
+        let me = "447700900000@s.whatsapp.net";
+        state
+            .update_own_profile(|p| p.jid = Some(me.to_string()))
+            .unwrap();
+        let (tx, mut rx) = mpsc::channel(4);
+        state.set_command_tx(tx).await;
+        state
+            .set_connected(true, Some("447700900000".to_string()), None)
+            .await;
+
+        assert_eq!(
+            save(serde_json::json!({ "text": "  " })).await.0,
+            StatusCode::BAD_REQUEST
+        );
+        let (status, body) = save(text).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["message"]["contactId"], me);
+        assert_eq!(body["message"]["chatType"], "self");
+        assert_eq!(body["message"]["origin"], "web");
+        match rx.try_recv().unwrap() {
+            BridgeCommand::Send { to, text, .. } => {
+                assert_eq!(to, me);
+                assert_eq!(text, "Buy bread");
+            }
+            other => panic!("unexpected command {:?}", other),
         }
-    } else {
-        false
-    };
+        let id = body["message"]["id"].as_str().unwrap();
+        assert!(state.store.get_message_by_id(id).unwrap().is_some());
+    }
 
-    if !is_authenticated {
-        // Build the resource_metadata URL for the WWW-Authenticate header
-        let is_https = !host.contains("localhost") && !host.contains("127.0.0.1");
-        let base_url = get_base_url(&host, is_https);
-        let resource_metadata_url = format!("{}/.well-known/oauth-protected-resource", base_url);
+    #[tokio::test]
+    async fn test_mcp_access_needs_sign_in() {
+        use tower::ServiceExt;
+
+        let (state, _dir) = test_state(None);
+        let setup_token = state.setup_token.read().await.clone().unwrap();
+        let response = change_password(
+            State(state.clone()),
+            Json(
+                serde_json::from_value(serde_json::json!({
+                    "setupToken": setup_token,
+                    "newPassword": "a good password"
+                }))
+                .unwrap(),
+            ),
+        )
+        .await
+        .into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let token = body["token"].as_str().unwrap().to_string();
+
+        let router = create_router(state.clone());
+        let send = |method: &str, uri: &str, token: Option<&str>| {
+            let router = router.clone();
+            let mut request = axum::http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::HOST, "localhost:3000")
+                .header(header::CONTENT_TYPE, "application/json");
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+            let body = match method {
+                "GET" => axum::body::Body::empty(),
+                _ if uri.ends_with("/anonymized") => r#"{"anonymized": false}"#.into(),
+                _ if uri.ends_with("/quota") => r#"{"dailySends": 5}"#.into(),
+                _ if uri.starts_with("/api/settings") => r#"{"version": 1}"#.into(),
+                _ => r#"{"mode": "allow", "chats": ["1@s.whatsapp.net"]}"#.into(),
+            };
+            let request = request.body(body).unwrap();
+            async move { router.oneshot(request).await.unwrap() }
+        };
+        let routes = [
+            ("GET", "/api/mcp/clients"),
+            ("PUT", "/api/mcp/quota"),
+            ("GET", "/api/mcp/access"),
+            ("PUT", "/api/mcp/access"),
+            ("PUT", "/api/mcp/clients/client/access"),
+            ("PUT", "/api/mcp/clients/client/quota"),
+            ("PUT", "/api/mcp/clients/client/anonymized"),
+            // The MCP access list is one of the portable settings
+            ("GET", "/api/settings/export"),
+            ("POST", "/api/settings/import?dry_run=true"),
+        ];
+
+        // Without a session nothing is shown or changed
+        for (method, uri) in routes {
+            let response = send(method, uri, None).await;
+            assert_eq!(
+                response.status(),
+                StatusCode::UNAUTHORIZED,
+                "{} {}",
+                method,
+                uri
+            );
+        }
+        assert!(state.store.get_mcp_access("client").unwrap().is_none());
 
-        // Return 401 with WWW-Authenticate header per RFC 6750 and RFC 9728
-        // The resource_metadata parameter tells MCP clients where to find OAuth config
-        let www_authenticate = format!("Bearer resource_metadata=\"{}\"", resource_metadata_url);
+        // A signed-in session can
+        for (method, uri) in routes {
+            let response = send(method, uri, Some(&token)).await;
+            assert_eq!(response.status(), StatusCode::OK, "{} {}", method, uri);
+        }
+        assert!(state.store.get_mcp_access("client").unwrap().is_some());
+    }
 
-        return (
-            StatusCode::UNAUTHORIZED,
-            [
-                (header::WWW_AUTHENTICATE.as_str(), www_authenticate.as_str()),
-                (header::CONTENT_TYPE.as_str(), "application/json"),
-            ],
-            Json(serde_json::json!({
-                "error": "unauthorized",
-                "error_description": "OAuth Bearer token required. Complete the OAuth flow to get a token."
-            })),
+    #[tokio::test]
+    async fn test_web_password_set_and_change() {
+        let (state, dir) = test_state(None);
+        let change = |body: serde_json::Value| {
+            let state = state.clone();
+            async move {
+                let response =
+                    change_password(State(state), Json(serde_json::from_value(body).unwrap()))
+                        .await
+                        .into_response();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                (status, body["token"].as_str().map(str::to_string))
+            }
+        };
+        let bearer = |token: &str| format!("Bearer {}", token);
+
+        // The first password needs the setup token
+        assert!(!state.auth_required().await);
+        let setup_token = state.setup_token.read().await.clone().unwrap();
+        assert_eq!(
+            change(serde_json::json!({ "setupToken": "guess", "newPassword": "first password" }))
+                .await
+                .0,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            change(serde_json::json!({ "setupToken": setup_token, "newPassword": "short" }))
+                .await
+                .0,
+            StatusCode::BAD_REQUEST
+        );
+        let (status, first_token) = change(
+            serde_json::json!({ "setupToken": setup_token, "newPassword": "first password" }),
         )
-            .into_response();
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let first_token = first_token.unwrap();
+        assert!(state.setup_token.read().await.is_none());
+        assert!(verify_auth(&state, Some(&bearer(&first_token))).await);
+
+        // Saved as a salted hash, never the password itself
+        let hash = state.store.get_web_password_hash().unwrap().unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(!hash.contains("first password"));
+        assert!(state.check_password("first password").await);
+
+        // Changing it needs the right current password
+        assert_eq!(
+            change(serde_json::json!({ "newPassword": "second password" }))
+                .await
+                .0,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            change(serde_json::json!({
+                "currentPassword": "wrong password",
+                "newPassword": "second password"
+            }))
+            .await
+            .0,
+            StatusCode::FORBIDDEN
+        );
+        assert!(verify_auth(&state, Some(&bearer(&first_token))).await);
+        let (status, second_token) = change(serde_json::json!({
+            "currentPassword": "first password",
+            "newPassword": "second password"
+        }))
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Every earlier session is signed out
+        assert!(!verify_auth(&state, Some(&bearer(&first_token))).await);
+        assert!(verify_auth(&state, Some(&bearer(&second_token.unwrap()))).await);
+        assert!(!state.check_password("first password").await);
+        assert!(state.check_password("second password").await);
+
+        // After a restart the saved password wins over --password
+        let restarted = AppState::new(
+            state.store.clone(),
+            dir.path().to_path_buf(),
+            dir.path().to_path_buf(),
+            None,
+            Some("cli password".to_string()),
+            None,
+            LanguageGuardConfig::default(),
+        );
+        assert!(restarted.setup_token.read().await.is_none());
+        assert!(restarted.check_password("second password").await);
+        assert!(!restarted.check_password("cli password").await);
     }
-
-    // Read the command_tx asynchronously before creating the service
-    let command_tx = state.command_tx.read().await.clone();
-    let store = Arc::new(state.store.clone());
-    let translator = state.translator.clone();
-
-    let service = create_mcp_service(store, command_tx, translator);
-    // StreamableHttpService has an async handle method we can call directly
-    service.handle(request).await.into_response()
 }
@@ -0,0 +1,76 @@
+//! The web server over HTTPS with a self-signed certificate.
+
+mod support;
+
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use support::{next_event, TestApp};
+
+/// Write a self-signed certificate for localhost, returning its cert and key paths
+fn self_signed(dir: &Path, name: &str) -> (PathBuf, PathBuf) {
+    let cert =
+        rcgen::generate_simple_self_signed(vec!["localhost".to_string(), "127.0.0.1".to_string()])
+            .unwrap();
+    let cert_path = dir.join(format!("{}.crt", name));
+    let key_path = dir.join(format!("{}.key", name));
+    std::fs::write(&cert_path, cert.cert.pem()).unwrap();
+    std::fs::write(&key_path, cert.key_pair.serialize_pem()).unwrap();
+    (cert_path, key_path)
+}
+
+#[tokio::test]
+async fn test_https_and_wss() {
+    let dir = tempfile::tempdir().unwrap();
+    let (cert, key) = self_signed(dir.path(), "server");
+    let scenario = json!([{"emit": {"type": "connected", "phone": "447700900000", "name": "Me"}}]);
+    let app = TestApp::spawn_with_args(
+        &scenario,
+        &[
+            "--tls-cert".as_ref(),
+            cert.as_ref(),
+            "--tls-key".as_ref(),
+            key.as_ref(),
+        ],
+    )
+    .await;
+    assert!(app.base_url.starts_with("https://"), "{}", app.base_url);
+
+    app.wait_for("/api/status", |s| s["connected"] == true)
+        .await;
+
+    // OAuth metadata advertises the scheme actually served, even on 127.0.0.1
+    let metadata = app.get("/.well-known/oauth-authorization-server").await;
+    let issuer = metadata["issuer"].as_str().unwrap();
+    assert!(issuer.starts_with("https://127.0.0.1:"), "{}", issuer);
+
+    let mut ws = app.websocket().await;
+    let status = next_event(&mut ws, "status").await;
+    assert_eq!(status["connected"], true);
+}
+
+#[test]
+fn test_mismatched_key_stops_startup() {
+    let dir = tempfile::tempdir().unwrap();
+    let (cert, _) = self_signed(dir.path(), "a");
+    let (_, other_key) = self_signed(dir.path(), "b");
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_whatsapp-translator"))
+        .args(["--web", "--host", "127.0.0.1", "--port", "0"])
+        .arg("--data-dir")
+        .arg(dir.path())
+        .arg("--bridge-path")
+        .arg(env!("CARGO_BIN_EXE_fake-bridge"))
+        .arg("--tls-cert")
+        .arg(&cert)
+        .arg("--tls-key")
+        .arg(&other_key)
+        .env("NO_COLOR", "1")
+        .env_remove("ANTHROPIC_API_KEY")
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("doesn't match certificate"), "{}", stderr);
+}
@@ -0,0 +1,92 @@
+//! Fake wa-bridge for integration tests.
+//!
+//! Speaks the bridge's JSON-lines protocol over stdio without a WhatsApp
+//! session. The scenario file named by `FAKE_BRIDGE_SCENARIO` is a JSON array
+//! of steps, run in order:
+//!
+//! - `{"emit": <event>}` writes an event to stdout
+//! - `{"await_command": "<type>"}` waits until a command of that type arrives
+//! - `{"sleep_ms": <n>}` pauses
+//!
+//! Send commands are always answered with a successful `send_result` echoing
+//! the request ID. The process exits when stdin closes or on `disconnect`.
+//! `--version` is answered like the real bridge, without a scenario.
+
+use serde_json::{json, Value};
+use std::io::BufRead;
+use std::sync::mpsc;
+use std::time::Duration;
+
+fn emit(event: &Value) {
+    // stdout is line-buffered, so each event is written whole
+    println!("{}", event);
+}
+
+/// Answer a command from the app, if it expects a response
+fn respond(command: &Value, sent: &mut u32) {
+    match command["type"].as_str() {
+        Some("send") | Some("send_image") | Some("send_reaction") => {
+            *sent += 1;
+            emit(&json!({
+                "type": "send_result",
+                "request_id": command["request_id"].as_i64().unwrap_or(0),
+                "success": true,
+                "message_id": format!("fake-sent-{}", sent),
+                "timestamp": 1_700_000_000 + *sent as i64,
+            }));
+        }
+        Some("get_profile_picture") => emit(&json!({
+            "type": "profile_picture",
+            "request_id": command["request_id"],
+            "jid": command["to"],
+            "url": null,
+        })),
+        Some("disconnect") => std::process::exit(0),
+        _ => {}
+    }
+}
+
+fn main() {
+    if std::env::args().any(|arg| arg == "--version") {
+        println!("wa-bridge fake");
+        return;
+    }
+
+    let path = std::env::var("FAKE_BRIDGE_SCENARIO").expect("FAKE_BRIDGE_SCENARIO is not set");
+    let scenario = std::fs::read_to_string(&path).expect("Failed to read scenario");
+    let steps: Vec<Value> = serde_json::from_str(&scenario).expect("Invalid scenario");
+
+    // Commands are answered as they arrive; their types are passed on for `await_command`
+    let (command_tx, command_rx) = mpsc::channel::<String>();
+    std::thread::spawn(move || {
+        let mut sent = 0;
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            let Ok(command) = serde_json::from_str::<Value>(&line) else {
+                eprintln!("fake-bridge: unparseable command: {}", line);
+                continue;
+            };
+            respond(&command, &mut sent);
+            let command_type = command["type"].as_str().unwrap_or_default().to_string();
+            let _ = command_tx.send(command_type);
+        }
+        std::process::exit(0);
+    });
+
+    for step in steps {
+        if let Some(event) = step.get("emit") {
+            emit(event);
+        } else if let Some(wanted) = step.get("await_command").and_then(|c| c.as_str()) {
+            while command_rx.recv().expect("stdin closed") != wanted {}
+        } else if let Some(ms) = step.get("sleep_ms").and_then(|m| m.as_u64()) {
+            std::thread::sleep(Duration::from_millis(ms));
+        } else {
+            panic!("Unknown scenario step: {}", step);
+        }
+    }
+
+    // Stay alive like the real bridge until the app disconnects
+    loop {
+        std::thread::park();
+    }
+}
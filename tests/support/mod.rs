@@ -0,0 +1,182 @@
+//! Integration test harness.
+//!
+//! Runs the real binary in web mode against the fake bridge
+//! (`tests/support/fake_bridge.rs`) with a temporary data directory.
+
+// Each test binary uses a different part of the harness
+#![allow(dead_code)]
+
+use futures::StreamExt;
+use serde_json::{json, Value};
+use std::ffi::OsStr;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::process::{Child, Command};
+use tokio_tungstenite::{Connector, MaybeTlsStream, WebSocketStream};
+
+/// How long to wait for the app to start or reach an expected state
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+pub type WebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// A running app wired to the fake bridge
+pub struct TestApp {
+    pub base_url: String,
+    pub data_dir: tempfile::TempDir,
+    client: reqwest::Client,
+    _child: Child,
+}
+
+impl TestApp {
+    /// Start the app with the fake bridge playing `scenario`
+    pub async fn spawn(scenario: &Value) -> Self {
+        Self::spawn_with_args(scenario, &[]).await
+    }
+
+    /// Start the app with extra command-line arguments
+    pub async fn spawn_with_args(scenario: &Value, args: &[&OsStr]) -> Self {
+        let data_dir = tempfile::tempdir().unwrap();
+        let scenario_path = data_dir.path().join("scenario.json");
+        std::fs::write(&scenario_path, scenario.to_string()).unwrap();
+
+        let mut child = Command::new(env!("CARGO_BIN_EXE_whatsapp-translator"))
+            .args(["--web", "--host", "127.0.0.1", "--port", "0"])
+            .arg("--data-dir")
+            .arg(data_dir.path())
+            .arg("--bridge-path")
+            .arg(env!("CARGO_BIN_EXE_fake-bridge"))
+            .args(args)
+            .env("FAKE_BRIDGE_SCENARIO", &scenario_path)
+            .env("NO_COLOR", "1")
+            .env_remove("ANTHROPIC_API_KEY")
+            .env_remove("WA_PASSWORD")
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .expect("Failed to start app");
+
+        // The app logs the address it bound to; keep draining its output after that
+        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+        let base_url = tokio::time::timeout(TIMEOUT, async {
+            while let Some(line) = lines.next_line().await.unwrap() {
+                if let Some((_, url)) = line.split_once("Web server running at ") {
+                    return url.trim().to_string();
+                }
+            }
+            panic!("App exited before starting the web server");
+        })
+        .await
+        .expect("Timed out waiting for the web server");
+        tokio::spawn(async move {
+            while let Ok(Some(line)) = lines.next_line().await {
+                eprintln!("[app] {}", line);
+            }
+        });
+
+        Self {
+            base_url,
+            data_dir,
+            // Self-signed certificates are used when testing HTTPS
+            client: reqwest::Client::builder()
+                .danger_accept_invalid_certs(true)
+                .build()
+                .unwrap(),
+            _child: child,
+        }
+    }
+
+    pub async fn get(&self, path: &str) -> Value {
+        let response = self
+            .client
+            .get(format!("{}{}", self.base_url, path))
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success(), "GET {} failed", path);
+        response.json().await.unwrap()
+    }
+
+    pub async fn post(&self, path: &str, body: &Value) -> (reqwest::StatusCode, Value) {
+        let response = self
+            .client
+            .post(format!("{}{}", self.base_url, path))
+            .json(body)
+            .send()
+            .await
+            .unwrap();
+        (response.status(), response.json().await.unwrap())
+    }
+
+    /// Poll a GET endpoint until `done` accepts its response
+    pub async fn wait_for(&self, path: &str, done: impl Fn(&Value) -> bool) -> Value {
+        tokio::time::timeout(TIMEOUT, async {
+            loop {
+                let value = self.get(path).await;
+                if done(&value) {
+                    return value;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("Timed out waiting on {}", path))
+    }
+
+    pub async fn websocket(&self) -> WebSocket {
+        let url = format!("{}/ws", self.base_url.replacen("http", "ws", 1));
+        let tls = native_tls::TlsConnector::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        tokio_tungstenite::connect_async_tls_with_config(
+            url,
+            None,
+            false,
+            Some(Connector::NativeTls(tls)),
+        )
+        .await
+        .unwrap()
+        .0
+    }
+
+    /// Open the app's message database
+    pub fn db(&self) -> rusqlite::Connection {
+        rusqlite::Connection::open(self.data_dir.path().join("messages.db")).unwrap()
+    }
+}
+
+/// Wait for the next WebSocket event of the given type
+pub async fn next_event(ws: &mut WebSocket, event_type: &str) -> Value {
+    tokio::time::timeout(TIMEOUT, async {
+        while let Some(message) = ws.next().await {
+            if let Ok(text) = message.unwrap().into_text() {
+                let event: Value = serde_json::from_str(&text).unwrap();
+                if event["type"] == event_type {
+                    return event;
+                }
+            }
+        }
+        panic!("WebSocket closed while waiting for {}", event_type);
+    })
+    .await
+    .unwrap_or_else(|_| panic!("Timed out waiting for a {} event", event_type))
+}
+
+/// A bridge `message` event
+pub fn message(id: &str, chat_jid: &str, name: &str, timestamp: i64, content: Value) -> Value {
+    let phone = chat_jid.split('@').next().unwrap();
+    json!({
+        "type": "message",
+        "id": id,
+        "timestamp": timestamp,
+        "from": {"jid": chat_jid, "phone": phone, "name": name},
+        "chat": {"type": "private", "jid": chat_jid, "name": name},
+        "content": content,
+        "is_from_me": false,
+        "is_forwarded": false,
+        "push_name": name,
+    })
+}
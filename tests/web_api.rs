@@ -0,0 +1,124 @@
+//! End-to-end tests of the web API against the fake bridge.
+
+mod support;
+
+use serde_json::{json, Value};
+use support::{message, next_event, TestApp};
+
+const CAMILLE: &str = "33612345678@s.whatsapp.net";
+const TOMAS: &str = "420601234567@s.whatsapp.net";
+
+/// One message of each content type from Camille, then a reply and typing
+/// indicator once the app sends something
+fn scenario() -> Value {
+    let contents = [
+        json!({"type": "text", "body": "Salut !"}),
+        json!({"type": "image", "caption": "Le chat", "mime_type": "image/jpeg", "file_size": 4, "media_data": "3q2+7w=="}),
+        json!({"type": "video", "caption": null, "mime_type": "video/mp4", "file_size": 4, "duration_seconds": 3, "media_data": "AAAAAA=="}),
+        json!({"type": "audio", "mime_type": "audio/ogg", "file_size": 4, "duration_seconds": 2, "is_voice_note": true, "media_data": "T2dnUw=="}),
+        json!({"type": "document", "caption": null, "mime_type": "application/pdf", "file_name": "bail.pdf", "file_size": 4, "media_data": "JVBERg=="}),
+        json!({"type": "sticker", "mime_type": "image/webp", "is_animated": false, "media_data": "UklGRg=="}),
+        json!({"type": "location", "latitude": 48.8566, "longitude": 2.3522, "name": "Paris", "address": null}),
+        json!({"type": "contact", "display_name": "Agence", "vcard": "BEGIN:VCARD\nEND:VCARD"}),
+        json!({"type": "poll", "question": "Quand ?", "options": ["Lundi", "Mardi"]}),
+    ];
+
+    let mut steps =
+        vec![json!({"emit": {"type": "connected", "phone": "447700900000", "name": "Me"}})];
+    for (i, content) in contents.into_iter().enumerate() {
+        let event = message(
+            &format!("c{}", i),
+            CAMILLE,
+            "Camille",
+            1_700_000_000 + i as i64,
+            content,
+        );
+        steps.push(json!({ "emit": event }));
+    }
+    steps.push(json!({"emit": message("t0", TOMAS, "Tomáš", 1_700_000_100, json!({"type": "text", "body": "Ahoj"}))}));
+
+    steps.push(json!({"await_command": "send"}));
+    steps.push(json!({"emit": message("reply", CAMILLE, "Camille", 1_700_000_200, json!({"type": "text", "body": "Parfait"}))}));
+    steps.push(json!({"emit": {"type": "chat_presence", "chat_id": CAMILLE, "user_id": CAMILLE, "state": "typing"}}));
+    Value::Array(steps)
+}
+
+#[tokio::test]
+async fn test_web_api_end_to_end() {
+    let app = TestApp::spawn(&scenario()).await;
+
+    app.wait_for("/api/status", |s| s["connected"] == true)
+        .await;
+
+    let contacts = app
+        .wait_for("/api/contacts", |c| {
+            c.as_array().is_some_and(|c| c.len() == 2)
+        })
+        .await;
+    let names: Vec<&str> = contacts
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|c| c["name"].as_str())
+        .collect();
+    assert!(names.contains(&"Camille") && names.contains(&"Tomáš"));
+
+    // Every content type is stored, oldest first, with media stripped from the listing
+    let path = format!("/api/messages/{}?limit=0", CAMILLE);
+    let messages = app
+        .wait_for(&path, |m| {
+            m["messages"].as_array().is_some_and(|m| m.len() == 9)
+        })
+        .await;
+    let types: Vec<&str> = messages["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| m["contentType"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        types,
+        [
+            "Text",
+            "Image",
+            "Video",
+            "Voice Note",
+            "Document",
+            "Sticker",
+            "Location",
+            "Contact",
+            "Poll"
+        ]
+    );
+    assert!(messages["messages"][1]["content"]
+        .get("media_data")
+        .is_none());
+
+    // Sending goes through the bridge, which answers with a reply and a typing indicator
+    let mut ws = app.websocket().await;
+    let (status, sent) = app
+        .post(
+            "/api/send",
+            &json!({"contactId": CAMILLE, "text": "J'arrive"}),
+        )
+        .await;
+    assert!(status.is_success(), "send failed: {}", sent);
+
+    let event = next_event(&mut ws, "message").await;
+    assert_eq!(event["message"]["id"], "reply");
+    assert_eq!(event["message"]["contactId"], CAMILLE);
+    let event = next_event(&mut ws, "typing").await;
+    assert_eq!(event["chat_id"], CAMILLE);
+    assert_eq!(event["state"], "typing");
+
+    let (body, origin): (String, Option<String>) = app
+        .db()
+        .query_row(
+            "SELECT content_json, origin FROM messages WHERE is_from_me = 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap();
+    assert!(body.contains("J'arrive"));
+    assert_eq!(origin.as_deref(), Some("web"));
+}
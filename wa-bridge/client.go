@@ -7,10 +7,12 @@ import (
 	"fmt"
 	"os"
 	"strings"
+	"sync"
 	"time"
 
 	"github.com/rs/zerolog"
 	"go.mau.fi/whatsmeow"
+	"go.mau.fi/whatsmeow/appstate"
 	"go.mau.fi/whatsmeow/proto/waE2E"
 	"go.mau.fi/whatsmeow/proto/waHistorySync"
 	"go.mau.fi/whatsmeow/store"
@@ -28,8 +30,32 @@ type Client struct {
 	container *sqlstore.Container
 	verbose   bool
 	ctx       context.Context
+
+	// Presence subscriptions need us marked available once per connection
+	presenceMu    sync.Mutex
+	sentAvailable bool
+
+	// History sync depth, set by the CLI once connected, and progress
+	// across the sync's chunks
+	historyMu        sync.Mutex
+	historyReady     chan struct{}
+	historyReadyOnce sync.Once
+	historyDisabled  bool
+	historyCutoff    time.Time // Zero imports everything
+	historyChats     int
+	historyChatsDone int
+	historyMessages  int
+	historyIdle      *time.Timer
 }
 
+// historyDepthWait is how long a history sync waits for the CLI to set the
+// depth (it's sent as soon as we're connected)
+const historyDepthWait = 10 * time.Second
+
+// historyIdleTimeout ends a sync whose chunks stop arriving before one
+// reports 100%
+const historyIdleTimeout = 2 * time.Minute
+
 // stderrLogger creates a logger that writes to stderr (not stdout)
 // This prevents log output from mixing with our JSON protocol on stdout
 func stderrLogger(module string, verbose bool) waLog.Logger {
@@ -75,6 +101,8 @@ func NewClient(ctx context.Context, dataDir string, verbose bool) (*Client, erro
 		container: container,
 		verbose:   verbose,
 		ctx:       ctx,
+
+		historyReady: make(chan struct{}),
 	}
 
 	// Register event handler
@@ -155,6 +183,9 @@ func (c *Client) handleEvent(evt interface{}) {
 		c.sendConnectedEvent()
 
 	case *events.Disconnected:
+		c.presenceMu.Lock()
+		c.sentAvailable = false
+		c.presenceMu.Unlock()
 		SendEvent(NewConnectionStateEvent("disconnected"))
 
 	case *events.LoggedOut:
@@ -177,10 +208,16 @@ func (c *Client) handleEvent(evt interface{}) {
 		}
 
 	case *events.Presence:
-		// Online/offline status - could be useful in future
+		// Online/offline status of a subscribed contact. LastSeen is zero
+		// when they hide it.
+		var lastSeen int64
+		if !v.LastSeen.IsZero() {
+			lastSeen = v.LastSeen.Unix()
+		}
 		if c.verbose {
-			SendEvent(NewLogEvent("debug", fmt.Sprintf("Presence: %s is %s", v.From, v.Unavailable)))
+			SendEvent(NewLogEvent("debug", fmt.Sprintf("Presence: %s unavailable=%v", v.From, v.Unavailable)))
 		}
+		SendEvent(NewPresenceEvent(v.From.ToNonAD().String(), !v.Unavailable, lastSeen))
 
 	case *events.ChatPresence:
 		// Typing/recording indicators
@@ -223,6 +260,10 @@ func (c *Client) handleEvent(evt interface{}) {
 			SendEvent(NewLogEvent("debug", fmt.Sprintf("Chat marked as read: %s", v.JID.String())))
 		}
 
+	case *events.GroupInfo:
+		// Group subject, description or disappearing messages timer changed
+		c.handleGroupInfo(v)
+
 	case *events.OfflineSyncCompleted:
 		// Offline sync completed - all pending messages delivered
 		SendEvent(NewLogEvent("info", "Offline sync completed"))
@@ -234,11 +275,16 @@ func (c *Client) sendConnectedEvent() {
 	phone := ""
 	name := ""
 	platform := ""
+	lid := ""
 
 	if c.client.Store.ID != nil {
 		phone = c.client.Store.ID.User
 	}
 
+	if !c.client.Store.LID.IsEmpty() {
+		lid = c.client.Store.LID.ToNonAD().String()
+	}
+
 	if c.client.Store.PushName != "" {
 		name = c.client.Store.PushName
 	}
@@ -247,7 +293,29 @@ func (c *Client) sendConnectedEvent() {
 		platform = c.client.Store.Platform
 	}
 
-	SendEvent(NewConnectedEvent(phone, name, platform))
+	SendEvent(NewConnectedEvent(phone, name, platform, lid))
+}
+
+// mentionedJIDs returns the JIDs @-mentioned in a message, from the context
+// info of whichever message type carries it
+func mentionedJIDs(m *waE2E.Message) []string {
+	if m == nil {
+		return nil
+	}
+
+	var contextInfo *waE2E.ContextInfo
+	switch {
+	case m.ExtendedTextMessage != nil:
+		contextInfo = m.ExtendedTextMessage.GetContextInfo()
+	case m.ImageMessage != nil:
+		contextInfo = m.ImageMessage.GetContextInfo()
+	case m.VideoMessage != nil:
+		contextInfo = m.VideoMessage.GetContextInfo()
+	case m.DocumentMessage != nil:
+		contextInfo = m.DocumentMessage.GetContextInfo()
+	}
+
+	return contextInfo.GetMentionedJID()
 }
 
 // handleMessage processes incoming messages
@@ -279,6 +347,21 @@ func (c *Client) handleMessage(evt *events.Message) {
 
 	// Set message content (with media download)
 	msg.Content = c.buildMessageContent(evt.Message)
+	msg.MentionedJIDs = mentionedJIDs(evt.Message)
+	if evt.IsViewOnce || evt.IsViewOnceV2 || evt.IsViewOnceV2Extension {
+		if msg.Content.Type == "image" || msg.Content.Type == "video" {
+			msg.Content.ViewOnce = true
+		}
+	}
+
+	// Disappearing messages turned on or off in a private chat
+	if pm := evt.Message.GetProtocolMessage(); pm != nil && pm.GetType() == waE2E.ProtocolMessage_EPHEMERAL_SETTING {
+		metadata := NewChatMetadataEvent(evt.Info.Chat.ToNonAD().String(), evt.Info.Timestamp.Unix())
+		duration := pm.GetEphemeralExpiration()
+		metadata.EphemeralDuration = &duration
+		metadata.SubjectChangedBy = evt.Info.Sender.ToNonAD().String()
+		SendEvent(metadata)
+	}
 
 	// Skip protocol messages and unknown types - these shouldn't be displayed
 	if msg.Content.Type == "protocol" || msg.Content.Type == "unknown" {
@@ -294,160 +377,276 @@ func (c *Client) handleMessage(evt *events.Message) {
 	SendEvent(NewMessageEvent(msg))
 }
 
-// processHistorySync processes historical messages from WhatsApp history sync
+// handleGroupInfo passes on changes to a group's subject, description and
+// disappearing messages timer
+func (c *Client) handleGroupInfo(evt *events.GroupInfo) {
+	if evt.Name == nil && evt.Topic == nil && evt.Ephemeral == nil {
+		return
+	}
+	metadata := NewChatMetadataEvent(evt.JID.ToNonAD().String(), evt.Timestamp.Unix())
+	if evt.Sender != nil {
+		metadata.SubjectChangedBy = evt.Sender.ToNonAD().String()
+	}
+	if evt.Name != nil {
+		subject := evt.Name.Name
+		metadata.Subject = &subject
+	}
+	if evt.Topic != nil {
+		description := evt.Topic.Topic
+		if evt.Topic.TopicDeleted {
+			description = ""
+		}
+		metadata.Description = &description
+	}
+	if evt.Ephemeral != nil {
+		var duration uint32
+		if evt.Ephemeral.IsEphemeral {
+			duration = evt.Ephemeral.DisappearingTimer
+		}
+		metadata.EphemeralDuration = &duration
+	}
+	SendEvent(metadata)
+}
+
+// SetHistorySync sets how far back history syncs import messages: "none",
+// "recent" (a week), "3months" or "full"
+func (c *Client) SetHistorySync(depth string) error {
+	var disabled bool
+	var cutoff time.Time
+	switch depth {
+	case "none":
+		disabled = true
+	case "recent":
+		cutoff = time.Now().AddDate(0, 0, -7)
+	case "3months":
+		cutoff = time.Now().AddDate(0, -3, 0)
+	case "full":
+	default:
+		return fmt.Errorf("unknown history depth %q", depth)
+	}
+
+	c.historyMu.Lock()
+	c.historyDisabled = disabled
+	c.historyCutoff = cutoff
+	c.historyMu.Unlock()
+	c.historyReadyOnce.Do(func() { close(c.historyReady) })
+	return nil
+}
+
+// processHistorySync processes historical messages from WhatsApp history sync,
+// reporting progress after each conversation
 func (c *Client) processHistorySync(data *waHistorySync.HistorySync) {
 	if data == nil {
 		return
 	}
 
-	totalMessages := 0
+	// The CLI says how much history it wants once we're connected
+	select {
+	case <-c.historyReady:
+	case <-time.After(historyDepthWait):
+	}
+
+	c.historyMu.Lock()
+	defer c.historyMu.Unlock()
+	if c.historyDisabled {
+		SendEvent(NewLogEvent("info", fmt.Sprintf("Skipping history sync: %d conversations", len(data.Conversations))))
+		return
+	}
+
+	c.historyChats += len(data.Conversations)
 	for _, conv := range data.Conversations {
-		if conv == nil || conv.ID == nil {
-			continue
-		}
+		c.historyMessages += c.importHistoryConversation(conv, c.historyCutoff)
+		c.historyChatsDone++
+		SendEvent(NewHistorySyncProgressEvent(c.historyChatsDone, c.historyChats, c.historyMessages, false))
+	}
 
-		chatJID := *conv.ID
+	if data.GetProgress() >= 100 {
+		c.finishHistorySync()
+		return
+	}
+	if c.historyIdle != nil {
+		c.historyIdle.Stop()
+	}
+	c.historyIdle = time.AfterFunc(historyIdleTimeout, func() {
+		c.historyMu.Lock()
+		defer c.historyMu.Unlock()
+		c.finishHistorySync()
+	})
+}
 
-		// Get unread count from conversation (only set on first message)
-		var convUnreadCount *uint32
-		if conv.UnreadCount != nil {
-			convUnreadCount = conv.UnreadCount
-		}
-		firstMessageInConv := true
+// finishHistorySync reports the sync complete and starts counting afresh.
+// Must be called with historyMu held.
+func (c *Client) finishHistorySync() {
+	if c.historyIdle != nil {
+		c.historyIdle.Stop()
+		c.historyIdle = nil
+	}
+	if c.historyChats == 0 {
+		return
+	}
+	SendEvent(NewHistorySyncProgressEvent(c.historyChatsDone, c.historyChats, c.historyMessages, true))
+	SendEvent(NewLogEvent("info", fmt.Sprintf("History sync complete: imported %d messages", c.historyMessages)))
+	c.historyChats, c.historyChatsDone, c.historyMessages = 0, 0, 0
+}
 
-		for _, historyMsg := range conv.Messages {
-			if historyMsg == nil || historyMsg.Message == nil {
-				continue
-			}
+// importHistoryConversation sends a history sync conversation's messages
+// newer than cutoff (all if zero), returning how many were sent
+func (c *Client) importHistoryConversation(conv *waHistorySync.Conversation, cutoff time.Time) int {
+	if conv == nil || conv.ID == nil {
+		return 0
+	}
 
-			webMsg := historyMsg.Message
-			if webMsg.Key == nil {
-				continue
-			}
+	imported := 0
+	chatJID := *conv.ID
 
-			// Parse the message
-			msg := Message{
-				ID:        webMsg.Key.GetID(),
-				Timestamp: time.Unix(int64(webMsg.GetMessageTimestamp()), 0).Unix(),
-				IsFromMe:  webMsg.Key.GetFromMe(),
-			}
+	// Get unread count from conversation (only set on first message)
+	var convUnreadCount *uint32
+	if conv.UnreadCount != nil {
+		convUnreadCount = conv.UnreadCount
+	}
+	firstMessageInConv := true
 
-			// Determine sender and chat
-			if webMsg.Key.GetFromMe() {
-				// Outgoing message - sender is me
-				if c.client.Store.ID != nil {
-					msg.From = Contact{
-						JID:   c.client.Store.ID.String(),
-						Phone: c.client.Store.ID.User,
-					}
-				}
-			} else {
-				// Incoming message
-				var senderJID types.JID
-				if webMsg.Key.Participant != nil && *webMsg.Key.Participant != "" {
-					// Group message - participant is sender
-					senderJID, _ = types.ParseJID(*webMsg.Key.Participant)
-				} else if webMsg.Key.RemoteJID != nil {
-					// Private message - remote JID is sender
-					senderJID, _ = types.ParseJID(*webMsg.Key.RemoteJID)
-				}
+	for _, historyMsg := range conv.Messages {
+		if historyMsg == nil || historyMsg.Message == nil {
+			continue
+		}
+
+		webMsg := historyMsg.Message
+		if webMsg.Key == nil {
+			continue
+		}
+		if !cutoff.IsZero() && int64(webMsg.GetMessageTimestamp()) < cutoff.Unix() {
+			continue
+		}
 
-				// Build contact from JID
-				msg.From = c.buildContact(senderJID)
+		// Parse the message
+		msg := Message{
+			ID:        webMsg.Key.GetID(),
+			Timestamp: time.Unix(int64(webMsg.GetMessageTimestamp()), 0).Unix(),
+			IsFromMe:  webMsg.Key.GetFromMe(),
+		}
 
-				// If we have a push name from the message, use it as the contact name
-				// This is more reliable for history messages where contact store might not have the info
-				if webMsg.PushName != nil && *webMsg.PushName != "" {
-					msg.From.Name = *webMsg.PushName
+		// Determine sender and chat
+		if webMsg.Key.GetFromMe() {
+			// Outgoing message - sender is me
+			if c.client.Store.ID != nil {
+				msg.From = Contact{
+					JID:   c.client.Store.ID.String(),
+					Phone: c.client.Store.ID.User,
 				}
 			}
-
-			// Set push name if available
-			if webMsg.PushName != nil {
-				msg.PushName = *webMsg.PushName
+		} else {
+			// Incoming message
+			var senderJID types.JID
+			if webMsg.Key.Participant != nil && *webMsg.Key.Participant != "" {
+				// Group message - participant is sender
+				senderJID, _ = types.ParseJID(*webMsg.Key.Participant)
+			} else if webMsg.Key.RemoteJID != nil {
+				// Private message - remote JID is sender
+				senderJID, _ = types.ParseJID(*webMsg.Key.RemoteJID)
 			}
 
-			// Build chat info
-			chatJIDParsed, err := types.ParseJID(chatJID)
-			if err != nil {
-				continue
+			// Build contact from JID
+			msg.From = c.buildContact(senderJID)
+
+			// If we have a push name from the message, use it as the contact name
+			// This is more reliable for history messages where contact store might not have the info
+			if webMsg.PushName != nil && *webMsg.PushName != "" {
+				msg.From.Name = *webMsg.PushName
 			}
+		}
 
-			// Determine if it's a group
-			isGroup := strings.HasSuffix(chatJID, "@g.us")
+		// Set push name if available
+		if webMsg.PushName != nil {
+			msg.PushName = *webMsg.PushName
+		}
 
-			msg.Chat = Chat{
-				JID:  chatJID,
-				Type: "private",
-			}
+		// Build chat info
+		chatJIDParsed, err := types.ParseJID(chatJID)
+		if err != nil {
+			continue
+		}
 
-			if isGroup {
-				msg.Chat.Type = "group"
-				// First try to get name from the conversation object (most reliable for history)
-				if conv.Name != nil && *conv.Name != "" {
-					msg.Chat.Name = *conv.Name
-				} else if conv.DisplayName != nil && *conv.DisplayName != "" {
-					msg.Chat.Name = *conv.DisplayName
-				} else {
-					// Fallback to GetGroupInfo (may fail for left/archived groups)
-					groupInfo, err := c.client.GetGroupInfo(c.ctx, chatJIDParsed)
-					if err == nil {
-						msg.Chat.Name = groupInfo.Name
-						count := len(groupInfo.Participants)
-						msg.Chat.ParticipantCount = &count
-					}
+		// Determine if it's a group
+		isGroup := strings.HasSuffix(chatJID, "@g.us")
+
+		msg.Chat = Chat{
+			JID:  chatJID,
+			Type: "private",
+		}
+
+		if chatJIDParsed.Server == types.NewsletterServer {
+			msg.Chat.Type = "channel"
+			if conv.Name != nil && *conv.Name != "" {
+				msg.Chat.Name = *conv.Name
+			}
+		} else if isGroup {
+			msg.Chat.Type = "group"
+			// First try to get name from the conversation object (most reliable for history)
+			if conv.Name != nil && *conv.Name != "" {
+				msg.Chat.Name = *conv.Name
+			} else if conv.DisplayName != nil && *conv.DisplayName != "" {
+				msg.Chat.Name = *conv.DisplayName
+			} else {
+				// Fallback to GetGroupInfo (may fail for left/archived groups)
+				groupInfo, err := c.client.GetGroupInfo(c.ctx, chatJIDParsed)
+				if err == nil {
+					msg.Chat.Name = groupInfo.Name
+					count := len(groupInfo.Participants)
+					msg.Chat.ParticipantCount = &count
 				}
+			}
+		} else {
+			// Private chat - first try conversation DisplayName/Name
+			if conv.DisplayName != nil && *conv.DisplayName != "" {
+				msg.Chat.Name = *conv.DisplayName
+			} else if conv.Name != nil && *conv.Name != "" {
+				msg.Chat.Name = *conv.Name
 			} else {
-				// Private chat - first try conversation DisplayName/Name
-				if conv.DisplayName != nil && *conv.DisplayName != "" {
-					msg.Chat.Name = *conv.DisplayName
-				} else if conv.Name != nil && *conv.Name != "" {
-					msg.Chat.Name = *conv.Name
-				} else {
-					// Fallback to contact store
-					contactInfo, err := c.client.Store.Contacts.GetContact(c.ctx, chatJIDParsed)
-					if err == nil && contactInfo.Found {
-						if contactInfo.FullName != "" {
-							msg.Chat.Name = contactInfo.FullName
-						} else if contactInfo.PushName != "" {
-							msg.Chat.Name = contactInfo.PushName
-						}
+				// Fallback to contact store
+				contactInfo, err := c.client.Store.Contacts.GetContact(c.ctx, chatJIDParsed)
+				if err == nil && contactInfo.Found {
+					if contactInfo.FullName != "" {
+						msg.Chat.Name = contactInfo.FullName
+					} else if contactInfo.PushName != "" {
+						msg.Chat.Name = contactInfo.PushName
 					}
 				}
 			}
+		}
 
-			// Parse message content
-			if webMsg.Message == nil {
-				continue
-			}
-
-			// The message is wrapped in a WebMessageInfo, need to unwrap
-			waMessage := webMsg.Message
-			msg.Content = c.buildMessageContent(waMessage)
+		// Parse message content
+		if webMsg.Message == nil {
+			continue
+		}
 
-			// Skip protocol/unknown messages
-			if msg.Content.Type == "protocol" || msg.Content.Type == "unknown" {
-				continue
-			}
+		// The message is wrapped in a WebMessageInfo, need to unwrap
+		waMessage := webMsg.Message
+		msg.Content = c.buildMessageContent(waMessage)
+		msg.MentionedJIDs = mentionedJIDs(waMessage)
 
-			// Don't download media for history (too slow) - just send metadata
-			// Users can see what media exists but won't have the actual data
+		// Skip protocol/unknown messages
+		if msg.Content.Type == "protocol" || msg.Content.Type == "unknown" {
+			continue
+		}
 
-			// Mark as history message (no translation)
-			msg.IsHistory = true
+		// Don't download media for history (too slow) - just send metadata
+		// Users can see what media exists but won't have the actual data
 
-			// Set unread count on first message of each conversation
-			if firstMessageInConv && convUnreadCount != nil {
-				msg.UnreadCount = convUnreadCount
-				firstMessageInConv = false
-			}
+		// Mark as history message (no translation)
+		msg.IsHistory = true
 
-			SendEvent(NewMessageEvent(msg))
-			totalMessages++
+		// Set unread count on first message of each conversation
+		if firstMessageInConv && convUnreadCount != nil {
+			msg.UnreadCount = convUnreadCount
+			firstMessageInConv = false
 		}
+
+		SendEvent(NewMessageEvent(msg))
+		imported++
 	}
 
-	SendEvent(NewLogEvent("info", fmt.Sprintf("History sync complete: imported %d messages", totalMessages)))
+	return imported
 }
 
 // downloadMediaForMessage downloads media data and adds it to the content
@@ -541,6 +740,13 @@ func (c *Client) buildContact(jid types.JID) Contact {
 		contact.Phone = jid.User
 	}
 
+	contact.LID, contact.PhoneJID = c.alternateJIDs(jid)
+	if contact.Phone == "" && contact.PhoneJID != "" {
+		if pn, err := types.ParseJID(contact.PhoneJID); err == nil {
+			contact.Phone = pn.User
+		}
+	}
+
 	// Try to get contact name from store
 	contactInfo, err := c.client.Store.Contacts.GetContact(c.ctx, jid)
 	if err == nil && contactInfo.Found {
@@ -556,6 +762,24 @@ func (c *Client) buildContact(jid types.JID) Contact {
 	return contact
 }
 
+// alternateJIDs looks up the other identifier for a user JID: the LID for a
+// phone JID, or the phone JID for a LID. Empty strings if unknown.
+func (c *Client) alternateJIDs(jid types.JID) (lid string, phoneJID string) {
+	switch jid.Server {
+	case types.HiddenUserServer:
+		pn, err := c.client.Store.LIDs.GetPNForLID(c.ctx, jid)
+		if err == nil && !pn.IsEmpty() {
+			phoneJID = pn.String()
+		}
+	case types.DefaultUserServer:
+		alt, err := c.client.Store.LIDs.GetLIDForPN(c.ctx, jid)
+		if err == nil && !alt.IsEmpty() {
+			lid = alt.String()
+		}
+	}
+	return lid, phoneJID
+}
+
 // buildChat creates a Chat from message info
 func (c *Client) buildChat(info types.MessageInfo) Chat {
 	chat := Chat{
@@ -571,12 +795,19 @@ func (c *Client) buildChat(info types.MessageInfo) Chat {
 			count := len(groupInfo.Participants)
 			chat.ParticipantCount = &count
 		}
+	} else if info.Chat.Server == types.NewsletterServer {
+		chat.Type = "channel"
+		newsletterInfo, err := c.client.GetNewsletterInfo(c.ctx, info.Chat)
+		if err == nil && newsletterInfo != nil {
+			chat.Name = newsletterInfo.ThreadMeta.Name.Text
+		}
 	} else if strings.HasSuffix(info.Chat.Server, "broadcast") {
 		chat.Type = "broadcast"
 	} else if info.Chat.Server == "status@broadcast" {
 		chat.Type = "status"
 	} else {
 		chat.Type = "private"
+		chat.LID, chat.PhoneJID = c.alternateJIDs(info.Chat)
 		// For private chats, get the contact name from the chat JID (the other person)
 		contactInfo, err := c.client.Store.Contacts.GetContact(c.ctx, info.Chat)
 		if err == nil && contactInfo.Found {
@@ -625,6 +856,7 @@ func (c *Client) buildMessageContent(msg *waE2E.Message) MessageContent {
 			Type:     "image",
 			MimeType: getString(msg.ImageMessage.Mimetype),
 			FileSize: getUint64(msg.ImageMessage.FileLength),
+			ViewOnce: msg.ImageMessage.GetViewOnce(),
 		}
 		if msg.ImageMessage.Caption != nil {
 			content.Caption = *msg.ImageMessage.Caption
@@ -641,6 +873,7 @@ func (c *Client) buildMessageContent(msg *waE2E.Message) MessageContent {
 			Type:     "video",
 			MimeType: getString(msg.VideoMessage.Mimetype),
 			FileSize: getUint64(msg.VideoMessage.FileLength),
+			ViewOnce: msg.VideoMessage.GetViewOnce(),
 		}
 		if msg.VideoMessage.Caption != nil {
 			content.Caption = *msg.VideoMessage.Caption
@@ -958,6 +1191,102 @@ func (c *Client) SendReaction(ctx context.Context, chatJIDStr string, targetMess
 	return resp.ID, resp.Timestamp.Unix(), nil
 }
 
+// CheckNumber checks whether a phone number (international format, digits only)
+// is registered on WhatsApp, returning its JID if so
+func (c *Client) CheckNumber(ctx context.Context, phone string) (string, bool, error) {
+	results, err := c.client.IsOnWhatsApp(ctx, []string{"+" + phone})
+	if err != nil {
+		return "", false, fmt.Errorf("failed to check number: %w", err)
+	}
+
+	for _, result := range results {
+		if result.IsIn {
+			return result.JID.String(), true, nil
+		}
+	}
+
+	return "", false, nil
+}
+
+// CreateGroup creates a group with the given participants and returns its JID
+func (c *Client) CreateGroup(ctx context.Context, subject string, participantJIDs []string) (string, error) {
+	participants := make([]types.JID, 0, len(participantJIDs))
+	for _, jidStr := range participantJIDs {
+		jid, err := types.ParseJID(jidStr)
+		if err != nil {
+			return "", fmt.Errorf("invalid participant %s: %w", jidStr, err)
+		}
+		participants = append(participants, jid)
+	}
+
+	info, err := c.client.CreateGroup(ctx, whatsmeow.ReqCreateGroup{
+		Name:         subject,
+		Participants: participants,
+	})
+	if err != nil {
+		return "", fmt.Errorf("failed to create group: %w", err)
+	}
+	return info.JID.String(), nil
+}
+
+// SubscribePresence asks for a contact's online/offline updates, which
+// arrive as *events.Presence until the connection drops. WhatsApp only sends
+// them while we're marked available, so that's done before the first one.
+func (c *Client) SubscribePresence(ctx context.Context, jidStr string) error {
+	jid, err := types.ParseJID(jidStr)
+	if err != nil {
+		return fmt.Errorf("invalid JID: %w", err)
+	}
+
+	c.presenceMu.Lock()
+	defer c.presenceMu.Unlock()
+	if !c.sentAvailable {
+		if err := c.client.SendPresence(ctx, types.PresenceAvailable); err != nil {
+			return fmt.Errorf("failed to mark available: %w", err)
+		}
+		c.sentAvailable = true
+	}
+
+	if err := c.client.SubscribePresence(ctx, jid); err != nil {
+		return fmt.Errorf("failed to subscribe to presence: %w", err)
+	}
+	return nil
+}
+
+// GetOwnProfile returns our own JID, push name and about text. The JID is
+// empty if we're not logged in; the about text is nil if it couldn't be fetched.
+func (c *Client) GetOwnProfile(ctx context.Context) (string, string, *string, error) {
+	if c.client.Store.ID == nil {
+		return "", "", nil, fmt.Errorf("not logged in")
+	}
+	own := c.client.Store.ID.ToNonAD()
+	name := c.client.Store.PushName
+
+	info, err := c.client.GetUserInfo(ctx, []types.JID{own})
+	if err != nil {
+		return own.String(), name, nil, fmt.Errorf("failed to get about text: %w", err)
+	}
+	status := info[own].Status
+	return own.String(), name, &status, nil
+}
+
+// SetProfileName changes our push name (the name contacts see)
+func (c *Client) SetProfileName(ctx context.Context, name string) error {
+	if err := c.client.SendAppState(ctx, appstate.BuildSettingPushName(name)); err != nil {
+		return fmt.Errorf("failed to set name: %w", err)
+	}
+	c.client.Store.PushName = name
+	return nil
+}
+
+// SetProfileStatus changes our about text
+func (c *Client) SetProfileStatus(ctx context.Context, text string) error {
+	if err := c.client.SetStatusMessage(ctx, text); err != nil {
+		return fmt.Errorf("failed to set about: %w", err)
+	}
+	return nil
+}
+
 // GetProfilePicture fetches the profile picture URL for a JID
 func (c *Client) GetProfilePicture(ctx context.Context, jidStr string) (string, string, error) {
 	// Parse the JID
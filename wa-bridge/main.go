@@ -14,12 +14,22 @@ import (
 	"syscall"
 )
 
+// Version is reported by --version, which `whatsapp-translator doctor` uses
+// to check the binary runs
+const Version = "0.1.0"
+
 func main() {
 	// Parse command line arguments
 	dataDir := flag.String("data-dir", "", "Directory for storing session data")
 	verbose := flag.Bool("verbose", false, "Enable verbose logging")
+	version := flag.Bool("version", false, "Print the version and exit")
 	flag.Parse()
 
+	if *version {
+		fmt.Printf("wa-bridge %s\n", Version)
+		return
+	}
+
 	if *dataDir == "" {
 		SendEvent(NewErrorEvent("config", "data-dir is required"))
 		os.Exit(1)
@@ -163,6 +173,19 @@ func handleCommand(ctx context.Context, client *Client, cmd Command, cancel cont
 			SendEvent(NewProfilePictureEvent(cmd.RequestID, cmd.To, url, id, ""))
 		}
 
+	case "check_number":
+		if cmd.Phone == "" {
+			SendEvent(NewNumberCheckResultEvent(cmd.RequestID, "", "", false, "missing 'phone' field"))
+			return
+		}
+
+		jid, onWhatsApp, err := client.CheckNumber(ctx, cmd.Phone)
+		if err != nil {
+			SendEvent(NewNumberCheckResultEvent(cmd.RequestID, cmd.Phone, "", false, err.Error()))
+		} else {
+			SendEvent(NewNumberCheckResultEvent(cmd.RequestID, cmd.Phone, jid, onWhatsApp, ""))
+		}
+
 	case "send_image":
 		if cmd.To == "" || cmd.MediaData == "" {
 			SendEvent(NewSendResultEvent(cmd.RequestID, false, "", 0, "missing 'to' or 'media_data' field"))
@@ -189,7 +212,73 @@ func handleCommand(ctx context.Context, client *Client, cmd Command, cancel cont
 			SendEvent(NewSendResultEvent(cmd.RequestID, true, messageID, timestamp, ""))
 		}
 
+	case "subscribe_presence":
+		if cmd.JID == "" {
+			SendEvent(NewLogEvent("warn", "subscribe_presence: missing 'jid' field"))
+			return
+		}
+
+		// Contacts hiding their presence just never report it, so a failure
+		// here only means no online status for them
+		if err := client.SubscribePresence(ctx, cmd.JID); err != nil {
+			SendEvent(NewLogEvent("warn", fmt.Sprintf("Failed to subscribe to presence of %s: %v", cmd.JID, err)))
+		}
+
+	case "set_history_sync":
+		if err := client.SetHistorySync(cmd.Depth); err != nil {
+			SendEvent(NewLogEvent("warn", fmt.Sprintf("set_history_sync: %v", err)))
+		}
+
+	case "get_own_profile":
+		sendOwnProfile(ctx, client, cmd.RequestID)
+
+	case "set_profile_name":
+		if cmd.Name == "" {
+			SendEvent(NewOwnProfileEvent(cmd.RequestID, "", "", nil, "missing 'name' field"))
+			return
+		}
+
+		if err := client.SetProfileName(ctx, cmd.Name); err != nil {
+			SendEvent(NewOwnProfileEvent(cmd.RequestID, "", "", nil, err.Error()))
+			return
+		}
+		sendOwnProfile(ctx, client, cmd.RequestID)
+
+	case "set_profile_status":
+		if err := client.SetProfileStatus(ctx, cmd.Text); err != nil {
+			SendEvent(NewOwnProfileEvent(cmd.RequestID, "", "", nil, err.Error()))
+			return
+		}
+		sendOwnProfile(ctx, client, cmd.RequestID)
+
+	case "create_group":
+		if cmd.Subject == "" || len(cmd.ParticipantJIDs) == 0 {
+			SendEvent(NewGroupCreatedEvent(cmd.RequestID, "", "missing 'subject' or 'participant_jids' field"))
+			return
+		}
+
+		jid, err := client.CreateGroup(ctx, cmd.Subject, cmd.ParticipantJIDs)
+		if err != nil {
+			SendEvent(NewGroupCreatedEvent(cmd.RequestID, "", err.Error()))
+		} else {
+			SendEvent(NewGroupCreatedEvent(cmd.RequestID, jid, ""))
+		}
+
 	default:
 		SendEvent(NewLogEvent("warn", fmt.Sprintf("Unknown command type: %s", cmd.Type)))
 	}
 }
+
+// sendOwnProfile answers a profile command with our current profile. Failing
+// to fetch the about text leaves it out rather than failing the command.
+func sendOwnProfile(ctx context.Context, client *Client, requestID int) {
+	jid, name, status, err := client.GetOwnProfile(ctx)
+	if jid == "" {
+		SendEvent(NewOwnProfileEvent(requestID, "", "", nil, err.Error()))
+		return
+	}
+	if err != nil {
+		SendEvent(NewLogEvent("warn", err.Error()))
+	}
+	SendEvent(NewOwnProfileEvent(requestID, jid, name, status, ""))
+}
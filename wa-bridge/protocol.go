@@ -21,6 +21,10 @@ type ConnectedEvent struct {
 	Phone    string `json:"phone"`
 	Name     string `json:"name"`
 	Platform string `json:"platform,omitempty"`
+	LID      string `json:"lid,omitempty"` // Our own LID, used to recognize mentions in LID groups
+	// Commands this bridge understands, so the CLI can tell an old bridge
+	// from one that failed
+	Capabilities []string `json:"capabilities"`
 }
 
 // ConnectionStateEvent is sent when connection state changes
@@ -66,31 +70,36 @@ type LoggedOutEvent struct {
 
 // Message represents a WhatsApp message with full metadata
 type Message struct {
-	ID          string         `json:"id"`
-	Timestamp   int64          `json:"timestamp"`
-	From        Contact        `json:"from"`
-	Chat        Chat           `json:"chat"`
-	Content     MessageContent `json:"content"`
-	IsFromMe    bool           `json:"is_from_me"`
-	IsForwarded bool           `json:"is_forwarded"`
-	IsHistory   bool           `json:"is_history,omitempty"` // True for history sync messages (no translation)
-	PushName    string         `json:"push_name,omitempty"`
-	UnreadCount *uint32        `json:"unread_count,omitempty"` // Unread count from WhatsApp (history sync only)
+	ID            string         `json:"id"`
+	Timestamp     int64          `json:"timestamp"`
+	From          Contact        `json:"from"`
+	Chat          Chat           `json:"chat"`
+	Content       MessageContent `json:"content"`
+	IsFromMe      bool           `json:"is_from_me"`
+	IsForwarded   bool           `json:"is_forwarded"`
+	IsHistory     bool           `json:"is_history,omitempty"` // True for history sync messages (no translation)
+	PushName      string         `json:"push_name,omitempty"`
+	UnreadCount   *uint32        `json:"unread_count,omitempty"`   // Unread count from WhatsApp (history sync only)
+	MentionedJIDs []string       `json:"mentioned_jids,omitempty"` // JIDs @-mentioned in the message
 }
 
 // Contact represents a WhatsApp contact
 type Contact struct {
-	JID   string `json:"jid"`
-	Phone string `json:"phone"`
-	Name  string `json:"name,omitempty"`
+	JID      string `json:"jid"`
+	Phone    string `json:"phone"`
+	Name     string `json:"name,omitempty"`
+	LID      string `json:"lid,omitempty"`       // LID alias when JID is a phone JID
+	PhoneJID string `json:"phone_jid,omitempty"` // Phone JID alias when JID is a LID
 }
 
-// Chat represents a chat (private, group, broadcast, or status)
+// Chat represents a chat (private, group, broadcast, status, or channel)
 type Chat struct {
-	Type             string `json:"type"` // "private", "group", "broadcast", "status"
+	Type             string `json:"type"` // "private", "group", "broadcast", "status", "channel"
 	JID              string `json:"jid"`
 	Name             string `json:"name,omitempty"`
 	ParticipantCount *int   `json:"participant_count,omitempty"`
+	LID              string `json:"lid,omitempty"`       // Private chats: LID alias
+	PhoneJID         string `json:"phone_jid,omitempty"` // Private chats: phone JID alias
 }
 
 // MessageContent represents the content of a message
@@ -106,6 +115,7 @@ type MessageContent struct {
 	DurationSeconds *uint32  `json:"duration_seconds,omitempty"`
 	IsVoiceNote     bool     `json:"is_voice_note,omitempty"`
 	IsAnimated      bool     `json:"is_animated,omitempty"`
+	ViewOnce        bool     `json:"view_once,omitempty"` // View-once photo or video
 	Latitude        *float64 `json:"latitude,omitempty"`
 	Longitude       *float64 `json:"longitude,omitempty"`
 	LocationName    string   `json:"name,omitempty"`
@@ -139,6 +149,36 @@ type ProfilePictureEvent struct {
 	Error     string `json:"error,omitempty"`
 }
 
+// OwnProfileEvent is sent with our own profile, in response to
+// get_own_profile, set_profile_name and set_profile_status
+type OwnProfileEvent struct {
+	Type      string `json:"type"`
+	RequestID int    `json:"request_id"`
+	JID       string `json:"jid,omitempty"`
+	Name      string `json:"name,omitempty"`
+	// About text: nil if it couldn't be fetched, empty if not set
+	Status *string `json:"status,omitempty"`
+	Error  string  `json:"error,omitempty"`
+}
+
+// NumberCheckResultEvent is sent in response to a check_number command
+type NumberCheckResultEvent struct {
+	Type         string `json:"type"`
+	RequestID    int    `json:"request_id"`
+	Phone        string `json:"phone"`
+	JID          string `json:"jid,omitempty"`
+	IsOnWhatsApp bool   `json:"is_on_whatsapp"`
+	Error        string `json:"error,omitempty"`
+}
+
+// GroupCreatedEvent is sent in response to a create_group command
+type GroupCreatedEvent struct {
+	Type      string `json:"type"`
+	RequestID int    `json:"request_id"`
+	JID       string `json:"jid,omitempty"`
+	Error     string `json:"error,omitempty"`
+}
+
 // ChatPresenceEvent is sent when someone starts/stops typing
 type ChatPresenceEvent struct {
 	Type   string `json:"type"`
@@ -147,6 +187,14 @@ type ChatPresenceEvent struct {
 	State  string `json:"state"`   // "typing", "paused", or "recording"
 }
 
+// PresenceEvent is sent when a subscribed contact comes online or goes offline
+type PresenceEvent struct {
+	Type     string `json:"type"`
+	JID      string `json:"jid"`
+	IsOnline bool   `json:"is_online"`
+	LastSeen int64  `json:"last_seen,omitempty"` // Unix seconds; omitted when hidden by privacy settings
+}
+
 // Command types received from Rust CLI (via stdin)
 
 // Command represents a command from the Rust CLI
@@ -167,6 +215,34 @@ type Command struct {
 	ReplyTo       string `json:"reply_to,omitempty"`        // Message ID to reply to
 	ReplyToSender string `json:"reply_to_sender,omitempty"` // JID of the sender of the replied message
 	ReplyToText   string `json:"reply_to_text,omitempty"`   // Text preview of the replied message (optional)
+	// For check_number command
+	Phone string `json:"phone,omitempty"` // Phone number in international format, digits only
+	// For subscribe_presence command
+	JID string `json:"jid,omitempty"`
+	// For set_profile_name command (set_profile_status uses Text)
+	Name string `json:"name,omitempty"`
+	// For set_history_sync command: "none", "recent", "3months" or "full"
+	Depth string `json:"depth,omitempty"`
+	// For create_group command (the group name is Subject)
+	Subject         string   `json:"subject,omitempty"`
+	ParticipantJIDs []string `json:"participant_jids,omitempty"`
+}
+
+// SupportedCommands lists the command types handleCommand understands
+var SupportedCommands = []string{
+	"disconnect",
+	"logout",
+	"send",
+	"get_profile_picture",
+	"check_number",
+	"send_image",
+	"send_reaction",
+	"subscribe_presence",
+	"get_own_profile",
+	"set_profile_name",
+	"set_profile_status",
+	"set_history_sync",
+	"create_group",
 }
 
 // Helper functions to create events
@@ -175,12 +251,14 @@ func NewQREvent(data string) QREvent {
 	return QREvent{Type: "qr", Data: data}
 }
 
-func NewConnectedEvent(phone, name, platform string) ConnectedEvent {
+func NewConnectedEvent(phone, name, platform, lid string) ConnectedEvent {
 	return ConnectedEvent{
-		Type:     "connected",
-		Phone:    phone,
-		Name:     name,
-		Platform: platform,
+		Type:         "connected",
+		Phone:        phone,
+		Name:         name,
+		Platform:     platform,
+		LID:          lid,
+		Capabilities: SupportedCommands,
 	}
 }
 
@@ -204,6 +282,9 @@ func NewMessageEvent(msg Message) map[string]interface{} {
 	if msg.UnreadCount != nil {
 		event["unread_count"] = *msg.UnreadCount
 	}
+	if len(msg.MentionedJIDs) > 0 {
+		event["mentioned_jids"] = msg.MentionedJIDs
+	}
 	return event
 }
 
@@ -241,6 +322,37 @@ func NewProfilePictureEvent(requestID int, jid, url, id, errMsg string) ProfileP
 	}
 }
 
+func NewOwnProfileEvent(requestID int, jid, name string, status *string, errMsg string) OwnProfileEvent {
+	return OwnProfileEvent{
+		Type:      "own_profile",
+		RequestID: requestID,
+		JID:       jid,
+		Name:      name,
+		Status:    status,
+		Error:     errMsg,
+	}
+}
+
+func NewNumberCheckResultEvent(requestID int, phone, jid string, isOnWhatsApp bool, errMsg string) NumberCheckResultEvent {
+	return NumberCheckResultEvent{
+		Type:         "number_check_result",
+		RequestID:    requestID,
+		Phone:        phone,
+		JID:          jid,
+		IsOnWhatsApp: isOnWhatsApp,
+		Error:        errMsg,
+	}
+}
+
+func NewGroupCreatedEvent(requestID int, jid, errMsg string) GroupCreatedEvent {
+	return GroupCreatedEvent{
+		Type:      "group_created",
+		RequestID: requestID,
+		JID:       jid,
+		Error:     errMsg,
+	}
+}
+
 func NewChatPresenceEvent(chatID, userID, state string) ChatPresenceEvent {
 	return ChatPresenceEvent{
 		Type:   "chat_presence",
@@ -250,6 +362,15 @@ func NewChatPresenceEvent(chatID, userID, state string) ChatPresenceEvent {
 	}
 }
 
+func NewPresenceEvent(jid string, isOnline bool, lastSeen int64) PresenceEvent {
+	return PresenceEvent{
+		Type:     "presence",
+		JID:      jid,
+		IsOnline: isOnline,
+		LastSeen: lastSeen,
+	}
+}
+
 // MarkAsReadEvent is sent when a chat is marked as read from another device
 type MarkAsReadEvent struct {
 	Type   string `json:"type"`
@@ -263,6 +384,45 @@ func NewMarkAsReadEvent(chatID string) MarkAsReadEvent {
 	}
 }
 
+// HistorySyncProgressEvent is sent as history sync chats are imported
+type HistorySyncProgressEvent struct {
+	Type         string `json:"type"`
+	ChatsDone    int    `json:"chats_done"`
+	ChatsTotal   int    `json:"chats_total"`
+	MessagesDone int    `json:"messages_done"`
+	Complete     bool   `json:"complete"`
+}
+
+func NewHistorySyncProgressEvent(chatsDone, chatsTotal, messagesDone int, complete bool) HistorySyncProgressEvent {
+	return HistorySyncProgressEvent{
+		Type:         "history_sync_progress",
+		ChatsDone:    chatsDone,
+		ChatsTotal:   chatsTotal,
+		MessagesDone: messagesDone,
+		Complete:     complete,
+	}
+}
+
+// ChatMetadataEvent is sent when a chat's subject, description or
+// disappearing messages timer changes. Fields that didn't change are left out.
+type ChatMetadataEvent struct {
+	Type              string  `json:"type"`
+	ChatID            string  `json:"chat_id"`
+	Description       *string `json:"description,omitempty"`
+	EphemeralDuration *uint32 `json:"ephemeral_duration,omitempty"`
+	Subject           *string `json:"subject,omitempty"`
+	SubjectChangedBy  string  `json:"subject_changed_by,omitempty"`
+	Timestamp         int64   `json:"timestamp,omitempty"`
+}
+
+func NewChatMetadataEvent(chatID string, timestamp int64) ChatMetadataEvent {
+	return ChatMetadataEvent{
+		Type:      "chat_metadata",
+		ChatID:    chatID,
+		Timestamp: timestamp,
+	}
+}
+
 // SendEvent marshals an event to JSON and prints it to stdout
 func SendEvent(event interface{}) {
 	data, err := json.Marshal(event)
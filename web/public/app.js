@@ -1,5 +1,35 @@
 // WhatsApp Translator Web Client
 
+// API version this page was built against. The server says which version
+// it's on in every response, and refuses changes from pages it no longer
+// supports
+const CLIENT_API_VERSION = '1';
+
+const unversionedFetch = window.fetch.bind(window);
+window.fetch = async (input, init = {}) => {
+  const url = typeof input === 'string' ? input : input.url;
+  if (!url.startsWith('/api/')) return unversionedFetch(input, init);
+  const headers = new Headers(init.headers || {});
+  headers.set('X-Client-Version', CLIENT_API_VERSION);
+  const response = await unversionedFetch(input, { ...init, headers });
+  const serverVersion = response.headers.get('X-API-Version');
+  if (serverVersion && serverVersion.split('.')[0] !== CLIENT_API_VERSION.split('.')[0]) {
+    showUpdateRequired();
+  }
+  return response;
+};
+
+// Ask for a reload once the server has moved on to another API version
+function showUpdateRequired() {
+  if (document.getElementById('update-required')) return;
+  const banner = document.createElement('div');
+  banner.id = 'update-required';
+  banner.className = 'disk-warning update-warning';
+  banner.textContent = 'A newer version of this app is available. Click to reload.';
+  banner.addEventListener('click', () => location.reload());
+  document.body.prepend(banner);
+}
+
 class WhatsAppClient {
   constructor() {
     this.ws = null;
@@ -8,7 +38,9 @@ class WhatsAppClient {
     this.currentContactId = null;
     this.messages = new Map();
     this.messagesHasMore = new Map(); // contactId -> boolean (whether more messages exist)
+    this.messagesCursor = new Map(); // contactId -> cursor for the next older page
     this.messagesLoading = new Map(); // contactId -> boolean (whether currently loading)
+    this.pinnedMessages = new Map(); // contactId -> pinned messages, most recently pinned first
     this.avatarCache = new Map(); // JID -> URL
     this.avatarFetching = new Set(); // JIDs currently being fetched
     this.globalUsage = { inputTokens: 0, outputTokens: 0, costUsd: 0 };
@@ -17,6 +49,8 @@ class WhatsAppClient {
     this.typingState = new Map(); // chatId -> { userId, state, timestamp }
     this.typingTimeouts = new Map(); // chatId -> timeoutId (auto-clear after 10s)
     this.replyingTo = null; // { messageId, senderJid, senderName, text, isFromMe }
+    this.draftSaveTimer = null; // Pending debounced draft save
+    this.draftUpdatedAt = 0; // When the current chat's draft was last edited or synced
     this.authToken = localStorage.getItem('wa_auth_token'); // Auth token for API requests
     this.recentEmojis = JSON.parse(localStorage.getItem('wa_recent_emojis') || '[]');
     this.currentEmojiCategory = 'recent';
@@ -152,6 +186,8 @@ class WhatsAppClient {
     
     const password = input?.value;
     if (!password) return;
+    // Shown on the messages this session sends, for instances shared by several people
+    const name = document.getElementById('session-name-input')?.value.trim() || undefined;
 
     submit.disabled = true;
     error?.classList.add('hidden');
@@ -160,7 +196,7 @@ class WhatsAppClient {
       const response = await fetch('/api/auth', {
         method: 'POST',
         headers: { 'Content-Type': 'application/json' },
-        body: JSON.stringify({ password })
+        body: JSON.stringify({ password, name })
       });
 
       const result = await response.json();
@@ -290,6 +326,12 @@ class WhatsAppClient {
 
   // Handle incoming WebSocket messages
   handleMessage(data) {
+    // Too big to send over the WebSocket; fetch it over REST instead
+    if (data.payload_truncated) {
+      this.resync(data.contact_id);
+      return;
+    }
+    
     switch (data.type) {
       case 'status':
         this.handleStatus(data);
@@ -307,29 +349,512 @@ class WhatsAppClient {
         this.handleDisconnected();
         break;
       
+      case 'connection_quality':
+        this.showConnectionQuality(data.quality.class);
+        break;
+      
       case 'message':
         this.handleNewMessage(data.message);
         break;
       
+      case 'message_id_updated':
+        this.handleMessageIdUpdated(data);
+        break;
+      
+      case 'location_resolved':
+        this.handleLocationResolved(data.contact_id, data.message_id, data.address);
+        break;
+      
       case 'typing':
         this.handleTyping(data);
         break;
       
       case 'mark_as_read':
-        this.handleMarkAsRead(data.chat_id);
+        this.handleMarkAsRead(data.chat_id, data.last_read_timestamp);
+        break;
+      
+      case 'avatar':
+        this.handleAvatar(data.jid, data.url);
         break;
       
       case 'error':
         console.error('Error:', data.error);
         break;
+      
+      case 'disk_space':
+        this.handleDiskSpace(data);
+        break;
+      
+      case 'translation_availability':
+        this.handleTranslationAvailability(data.unavailable);
+        break;
+      
+      case 'contact_updated':
+        this.handleContactUpdated(data.contact);
+        break;
+      
+      case 'conversation_cleared':
+        this.handleConversationCleared(data.contact_id);
+        break;
+      
+      case 'message_removed':
+        this.handleMessageRemoved(data.contact_id, data.message_id);
+        break;
+      
+      case 'message_translated':
+        this.handleMessageTranslated(data);
+        break;
+      
+      case 'draft_updated':
+        this.handleDraftUpdated(data.contact_id, data.draft);
+        break;
+      
+      case 'pins_updated':
+        this.setPinnedMessages(data.contact_id, data.pinned_messages);
+        break;
+      
+      case 'reaction_added':
+      case 'reaction_removed':
+        this.handleReactionsUpdated(data.contact_id, data.message_id, data.reactions);
+        break;
+      
+      case 'presence':
+        this.handlePresence(data.contact_id, data.is_online, data.last_seen);
+        break;
+      
+      case 'sync_progress':
+        this.handleSyncProgress(data);
+        break;
+
+      case 'language_seeding':
+        this.handleLanguageSeeding(data);
+        break;
+      
+      case 'resync':
+        if (data.missed) console.warn(`Missed ${data.missed} events, refetching`);
+        this.resync();
+        break;
+
+      case 'shutting_down':
+        // The socket closes next and reconnects once the server is back
+        console.log('Server is shutting down');
+        this.showConnecting();
+        break;
+    }
+  }
+
+  // Apply a contact's new name, phone or type. Unread count and last message
+  // time are tracked locally from message events, so they're left alone.
+  handleContactUpdated(updated) {
+    const contact = this.contacts.find(c => c.id === updated.id);
+    if (!contact) {
+      this.contacts.push(updated);
+    } else {
+      const { unreadCount, lastMessageTime, lastMessagePreview, ...details } = updated;
+      Object.assign(contact, details);
+    }
+    
+    if (this.currentContactId === updated.id) {
+      document.getElementById('chat-name').textContent = updated.name || updated.phone || 'Unknown';
+      this.updateChatDetails();
+    }
+    this.renderContacts();
+  }
+
+  // A followed contact came online or went offline
+  handlePresence(contactId, isOnline, lastSeen) {
+    const contact = this.contacts.find(c => c.id === contactId);
+    if (!contact) return;
+    contact.presence = { isOnline, lastSeen };
+    if (lastSeen && (!contact.lastSeen || lastSeen > contact.lastSeen)) {
+      contact.lastSeen = lastSeen;
+    }
+    if (this.currentContactId === contactId) {
+      this.updatePresenceStatus();
+    }
+    this.renderContacts();
+  }
+
+  // Show "online" or "last seen ..." under the open chat's name. Contacts
+  // who hide their presence show nothing.
+  updatePresenceStatus() {
+    const el = document.getElementById('chat-presence');
+    if (!el) return;
+    const contact = this.contacts.find(c => c.id === this.currentContactId);
+    const lastSeen = contact?.presence?.lastSeen || contact?.lastSeen;
+    if (contact?.presence?.isOnline) {
+      el.textContent = 'online';
+    } else if (lastSeen) {
+      el.textContent = `last seen ${this.formatTime(lastSeen)}`;
+    } else {
+      el.textContent = '';
+    }
+    el.classList.toggle('hidden', !el.textContent);
+  }
+
+  // Show the open chat's disappearing messages timer under its name, with
+  // the group description as the tooltip
+  updateChatDetails() {
+    const el = document.getElementById('chat-details');
+    if (!el) return;
+    const contact = this.contacts.find(c => c.id === this.currentContactId);
+    const duration = contact?.ephemeral_duration;
+    el.textContent = duration ? `Messages disappear after ${this.formatDuration(duration)}` : '';
+    el.title = contact?.description || '';
+    el.classList.toggle('hidden', !el.textContent);
+    document.getElementById('chat-name').title = contact?.description || '';
+  }
+
+  // A disappearing messages timer as words, e.g. "7 days"
+  formatDuration(seconds) {
+    const units = [['day', 86400], ['hour', 3600], ['minute', 60]];
+    for (const [unit, size] of units) {
+      if (seconds >= size) {
+        const count = Math.round(seconds / size);
+        return `${count} ${unit}${count === 1 ? '' : 's'}`;
+      }
+    }
+    return `${seconds} seconds`;
+  }
+
+  // Empty a conversation whose history was cleared (here or in another tab)
+  // Refetch contacts and the open chat after missing events. With a contact
+  // ID, the open chat is only refetched if it's that one.
+  async resync(contactId = null) {
+    await this.loadContacts();
+    if (this.currentContactId && (!contactId || contactId === this.currentContactId)) {
+      await this.loadMessages(this.currentContactId);
+    }
+  }
+
+  // Apply a draft saved or cleared in another session, unless this session
+  // has newer unsaved typing
+  handleDraftUpdated(contactId, draft) {
+    if (contactId !== this.currentContactId || this.draftSaveTimer) return;
+    if (draft && draft.updatedAt <= this.draftUpdatedAt) return;
+    this.applyDraft(draft);
+  }
+
+  // Keep a chat's pinned messages and show them if it's open
+  setPinnedMessages(contactId, pins) {
+    this.pinnedMessages.set(contactId, pins);
+    if (contactId === this.currentContactId) {
+      this.renderPinnedMessages();
+    }
+  }
+
+  isPinned(messageId) {
+    const pins = this.pinnedMessages.get(this.currentContactId) || [];
+    return pins.some(pin => pin.id === messageId);
+  }
+
+  // Show the open chat's pinned messages above its messages
+  renderPinnedMessages() {
+    const bar = document.getElementById('pinned-messages');
+    if (!bar) return;
+    const pins = this.pinnedMessages.get(this.currentContactId) || [];
+    bar.classList.toggle('hidden', pins.length === 0);
+    bar.innerHTML = pins.map(pin => `
+      <div class="pinned-message" onclick="app.scrollToMessage('${pin.id}')">
+        <span class="pinned-message-text">📌 ${this.escapeHtml(this.getMessagePreview(pin))}</span>
+        <button class="pinned-message-unpin" onclick="event.stopPropagation(); app.togglePinMessage('${pin.id}')" title="Unpin">×</button>
+      </div>
+    `).join('');
+    document.querySelectorAll('.pin-btn').forEach(btn => {
+      const messageId = btn.closest('.message')?.dataset.messageId;
+      btn.classList.toggle('active', this.isPinned(messageId));
+    });
+  }
+
+  // Scroll to a message if it's loaded
+  scrollToMessage(messageId) {
+    const el = document.querySelector(`.message[data-message-id="${CSS.escape(messageId)}"]`);
+    el?.scrollIntoView({ behavior: 'smooth', block: 'center' });
+  }
+
+  // Pin a message to the top of the open chat, or unpin it
+  async togglePinMessage(messageId) {
+    const contactId = this.currentContactId;
+    if (!contactId) return;
+    const pinned = this.isPinned(messageId);
+    try {
+      const response = await fetch(`/api/messages/${encodeURIComponent(messageId)}/pin`, {
+        method: pinned ? 'DELETE' : 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: pinned ? undefined : JSON.stringify({ contactId })
+      });
+      const result = await response.json();
+      if (!response.ok) {
+        throw new Error(result.errorDescription || 'Failed to update pins');
+      }
+      this.setPinnedMessages(contactId, result.pinnedMessages);
+    } catch (err) {
+      console.error('Failed to pin message:', err);
+      alert(err.message);
+    }
+  }
+
+  // Put a draft (or nothing) in the message input
+  applyDraft(draft) {
+    const input = document.getElementById('message-input');
+    input.value = draft ? draft.text : '';
+    this.draftUpdatedAt = draft ? draft.updatedAt : 0;
+    this.updateSendButton();
+    this.autoResizeTextarea(input);
+  }
+
+  // Load the chat's draft from the server
+  async loadDraft(contactId) {
+    try {
+      const response = await fetch(`/api/contacts/${encodeURIComponent(contactId)}/draft`);
+      if (!response.ok) return;
+      const { draft } = await response.json();
+      if (contactId === this.currentContactId) {
+        this.applyDraft(draft);
+      }
+    } catch (err) {
+      console.error('Failed to load draft:', err);
+    }
+  }
+
+  // Save the draft shortly after typing stops
+  scheduleDraftSave() {
+    const contactId = this.currentContactId;
+    if (!contactId) return;
+    clearTimeout(this.draftSaveTimer);
+    this.draftUpdatedAt = Date.now();
+    this.draftSaveTimer = setTimeout(() => this.flushDraftSave(contactId), 800);
+  }
+
+  // Save a pending draft now (e.g. before switching chats)
+  flushDraftSave(contactId = this.currentContactId) {
+    if (!this.draftSaveTimer || !contactId) return;
+    clearTimeout(this.draftSaveTimer);
+    this.draftSaveTimer = null;
+    this.saveDraft(contactId, document.getElementById('message-input').value, this.draftUpdatedAt);
+  }
+
+  // Drop a pending draft save (the message was sent)
+  cancelDraftSave() {
+    clearTimeout(this.draftSaveTimer);
+    this.draftSaveTimer = null;
+  }
+
+  async saveDraft(contactId, text, updatedAt) {
+    const url = `/api/contacts/${encodeURIComponent(contactId)}/draft`;
+    try {
+      const response = text.trim()
+        ? await fetch(url, {
+            method: 'PUT',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify({
+              text,
+              replyToMessageId: this.replyingTo ? this.replyingTo.messageId : null,
+              updatedAt
+            })
+          })
+        : await fetch(`${url}?updatedAt=${updatedAt}`, { method: 'DELETE' });
+      
+      // Another session saved a newer draft; show that one
+      if (response.status === 409) {
+        const { draft } = await response.json();
+        if (contactId === this.currentContactId && !this.draftSaveTimer) {
+          this.applyDraft(draft);
+        }
+      }
+    } catch (err) {
+      console.error('Failed to save draft:', err);
+    }
+  }
+
+  handleConversationCleared(contactId) {
+    this.messages.set(contactId, []);
+    this.messagesHasMore.set(contactId, false);
+    this.messagesCursor.delete(contactId);
+    this.setPinnedMessages(contactId, []);
+    
+    const contact = this.contacts.find(c => c.id === contactId);
+    if (contact) {
+      contact.lastMessagePreview = null;
+      contact.unreadCount = 0;
+    }
+    
+    if (this.currentContactId === contactId) {
+      this.renderMessages([]);
+    }
+    this.renderContacts();
+  }
+
+  // Handle a message removed before it was sent (an undone send)
+  handleMessageRemoved(contactId, messageId) {
+    const messages = this.messages.get(contactId);
+    if (!messages) return;
+    const remaining = messages.filter(m => m.id !== messageId);
+    this.messages.set(contactId, remaining);
+    if (this.currentContactId === contactId) {
+      this.renderMessages(remaining);
+    }
+  }
+
+  // A message whose translation failed or never happened was translated again
+  handleMessageTranslated({ contact_id, message_id, translated_text, source_language, translation_status }) {
+    const messages = this.messages.get(contact_id);
+    const message = messages?.find(m => m.id === message_id);
+    if (!message) return;
+    message.translation_status = translation_status;
+    if (translation_status === 'translated') {
+      message.translatedText = translated_text;
+      message.sourceLanguage = source_language;
+      message.isTranslated = true;
+    }
+    if (this.currentContactId === contact_id) {
+      this.renderMessages(messages);
+    }
+  }
+
+  // A message I sent got its real ID once WhatsApp's copy arrived
+  handleMessageIdUpdated({ contact_id, old_id, new_id, timestamp, sort_key }) {
+    const messages = this.messages.get(contact_id);
+    const message = messages?.find(m => m.id === old_id);
+    if (!message) return;
+    message.id = new_id;
+    message.timestamp = timestamp;
+    message.sortKey = sort_key;
+    messages.sort((a, b) => this.messageOrder(a) - this.messageOrder(b));
+    if (this.currentContactId === contact_id) {
+      this.renderMessages(messages);
+    }
+  }
+
+  // Clear a conversation's history after asking for confirmation
+  async clearConversation(contactId) {
+    const contact = this.contacts.find(c => c.id === contactId);
+    const name = contact?.name || contact?.phone || 'this conversation';
+    if (!confirm(`Clear all messages with ${name}? This can't be undone.`)) {
+      return;
+    }
+    
+    try {
+      const response = await fetch(`/api/messages/${encodeURIComponent(contactId)}?confirm=true`, {
+        method: 'DELETE'
+      });
+      
+      if (!response.ok) {
+        throw new Error('Failed to clear conversation');
+      }
+      
+      this.handleConversationCleared(contactId);
+    } catch (err) {
+      console.error('Failed to clear conversation:', err);
+    }
+  }
+
+  // Download a conversation's original/translation pairs as CSV
+  async exportTranslations(contactId) {
+    try {
+      const response = await fetch(`/api/contacts/${encodeURIComponent(contactId)}/translations?format=csv`, {
+        headers: this.getAuthHeaders()
+      });
+      
+      if (!response.ok) {
+        throw new Error('Failed to export translations');
+      }
+      
+      const url = URL.createObjectURL(await response.blob());
+      const link = document.createElement('a');
+      link.href = url;
+      link.download = `translations-${contactId.split('@')[0]}.csv`;
+      link.click();
+      URL.revokeObjectURL(url);
+    } catch (err) {
+      console.error('Failed to export translations:', err);
+    }
+  }
+
+  // Show or hide the low disk space warning
+  handleDiskSpace(data) {
+    let banner = document.getElementById('disk-warning');
+    if (!data.read_only) {
+      banner?.remove();
+      return;
+    }
+    if (!banner) {
+      banner = document.createElement('div');
+      banner.id = 'disk-warning';
+      banner.className = 'disk-warning';
+      document.body.prepend(banner);
+    }
+    const freeMb = data.free_bytes != null ? Math.floor(data.free_bytes / 1024 / 1024) : null;
+    banner.textContent = freeMb != null
+      ? `Low disk space (${freeMb} MB free): read-only until space is freed. New messages are kept in memory.`
+      : 'Low disk space: read-only until space is freed. New messages are kept in memory.';
+  }
+
+  // Show why nothing is being translated (e.g. a rejected API key), or hide
+  // the warning once translation works again
+  handleTranslationAvailability(unavailable) {
+    let banner = document.getElementById('translation-warning');
+    if (!unavailable) {
+      banner?.remove();
+      return;
+    }
+    if (!banner) {
+      banner = document.createElement('div');
+      banner.id = 'translation-warning';
+      banner.className = 'disk-warning translation-warning';
+      document.body.prepend(banner);
+    }
+    banner.textContent = unavailable.message;
+  }
+
+  // Show how far the history sync has got, hiding it once complete
+  handleSyncProgress(data) {
+    let banner = document.getElementById('sync-progress');
+    if (data.complete) {
+      banner?.remove();
+      return;
+    }
+    if (!banner) {
+      banner = document.createElement('div');
+      banner.id = 'sync-progress';
+      banner.className = 'sync-progress';
+      banner.innerHTML = '<span class="sync-progress-text"></span><div class="sync-progress-bar"><div></div></div>';
+      document.body.append(banner);
+    }
+    const percent = data.chats_total ? Math.round(data.chats_done * 100 / data.chats_total) : 0;
+    banner.querySelector('.sync-progress-text').textContent =
+      `Importing history: ${data.chats_done} of ${data.chats_total} chats, ${data.messages_done} messages`;
+    banner.querySelector('.sync-progress-bar > div').style.width = `${percent}%`;
+  }
+
+  // Show how far learning chat languages from history has got
+  handleLanguageSeeding(data) {
+    let banner = document.getElementById('language-seeding');
+    if (data.complete) {
+      banner?.remove();
+      return;
+    }
+    if (!banner) {
+      banner = document.createElement('div');
+      banner.id = 'language-seeding';
+      banner.className = 'sync-progress';
+      banner.innerHTML = '<span class="sync-progress-text"></span><div class="sync-progress-bar"><div></div></div>';
+      document.body.append(banner);
     }
+    const percent = data.contacts_total ? Math.round(data.contacts_done * 100 / data.contacts_total) : 0;
+    banner.querySelector('.sync-progress-text').textContent =
+      `Learning chat languages: ${data.contacts_done} of ${data.contacts_total} chats`;
+    banner.querySelector('.sync-progress-bar > div').style.width = `${percent}%`;
   }
 
   // Handle mark-as-read event from another device
-  handleMarkAsRead(chatId) {
+  handleMarkAsRead(chatId, lastReadTimestamp) {
     const contact = this.contacts.find(c => c.id === chatId);
     if (contact) {
       contact.unreadCount = 0;
+      if (lastReadTimestamp != null) {
+        contact.lastReadTimestamp = lastReadTimestamp;
+      }
       this.renderContacts();
     }
   }
@@ -440,6 +965,7 @@ class WhatsAppClient {
     // Update status indicator
     const statusDot = document.querySelector('.status-dot');
     statusDot.classList.add('connected');
+    this.fetchConnectionQuality();
     
     // Load contacts
     this.loadContacts();
@@ -449,8 +975,44 @@ class WhatsAppClient {
   }
 
   // Handle disconnected state
+  // Show how well the connection is holding up next to "Connected"
+  showConnectionQuality(quality) {
+    this.connectionQuality = quality;
+    const indicator = document.getElementById('status-indicator');
+    const statusDot = indicator.querySelector('.status-dot');
+    statusDot.classList.toggle('degraded', quality === 'degraded');
+    statusDot.classList.toggle('poor', quality === 'poor');
+    const labels = { good: 'Connected', degraded: 'Connection degraded', poor: 'Connection poor' };
+    indicator.querySelector('span:last-child').textContent = labels[quality] || 'Connected';
+  }
+
+  async fetchConnectionQuality() {
+    try {
+      const response = await fetch('/api/status');
+      const status = await response.json();
+      if (status.connection_quality) {
+        this.showConnectionQuality(status.connection_quality.class);
+      }
+      this.handleTranslationAvailability(status.translation_unavailable);
+    } catch (err) {
+      console.error('Failed to fetch connection quality:', err);
+    }
+  }
+
+  // Sent while the connection is shaky: say it may take a while
+  warnPossibleDelay(quality) {
+    if (!quality || quality === 'good') return;
+    this.showConnectionQuality(quality);
+    const indicator = document.getElementById('status-indicator');
+    indicator.querySelector('span:last-child').textContent =
+      'Message may be delayed — phone appears offline';
+    clearTimeout(this.delayWarningTimer);
+    this.delayWarningTimer = setTimeout(() => this.showConnectionQuality(this.connectionQuality), 8000);
+  }
+
   handleDisconnected() {
     this.connected = false;
+    document.getElementById('sync-progress')?.remove();
     
     const statusDot = document.querySelector('.status-dot');
     statusDot.classList.remove('connected');
@@ -480,11 +1042,13 @@ class WhatsAppClient {
     const messages = this.messages.get(message.contactId);
     if (!messages.some(m => m.id === message.id)) {
       messages.push(message);
-      messages.sort((a, b) => a.timestamp - b.timestamp);
+      messages.sort((a, b) => this.messageOrder(a) - this.messageOrder(b));
     }
     
-    // Update contact in list
-    this.updateContactInList(message);
+    // Update contact in list (notices about the chat aren't messages in it)
+    if (message.contentType !== 'system') {
+      this.updateContactInList(message);
+    }
     
     // If this contact is currently selected, show the message
     if (this.currentContactId === message.contactId) {
@@ -506,6 +1070,23 @@ class WhatsAppClient {
     }
   }
 
+  // Position of a message in its conversation: the server's sort key, which
+  // keeps same-second messages in the order they arrived
+  messageOrder(message) {
+    return message.sortKey ?? message.timestamp * 1000;
+  }
+
+  // The address of a shared location's coordinates was found
+  handleLocationResolved(contactId, messageId, address) {
+    const messages = this.messages.get(contactId) || [];
+    const message = messages.find(m => m.id === messageId);
+    if (!message || !message.content) return;
+    message.content.resolved_address = address;
+    if (this.currentContactId === contactId) {
+      this.renderMessages(messages);
+    }
+  }
+
   // Handle incoming reaction message
   handleReactionMessage(reactionMsg) {
     const contactId = reactionMsg.contactId;
@@ -655,8 +1236,7 @@ class WhatsAppClient {
       // Restore the original preview
       const contact = this.contacts.find(c => c.id === chatId);
       if (contact) {
-        const messages = this.messages.get(chatId) || [];
-        const lastMessage = messages[messages.length - 1];
+        const lastMessage = this.lastPreviewMessage(chatId);
         const preview = lastMessage ? this.getMessagePreview(lastMessage) : '';
         previewEl.textContent = preview;
       }
@@ -689,7 +1269,10 @@ class WhatsAppClient {
     }
     
     // Increment unread if not from me and not currently viewing
-    if (!message.isFromMe && this.currentContactId !== message.contactId) {
+    // (groups set to mentions only count just the messages that mention me)
+    const countsAsUnread = contact.type !== 'self'
+      && (!contact.mentionsOnly || message.chatType !== 'group' || message.mentionsMe);
+    if (!message.isFromMe && this.currentContactId !== message.contactId && countsAsUnread) {
       contact.unreadCount = (contact.unreadCount || 0) + 1;
     }
     
@@ -711,9 +1294,7 @@ class WhatsAppClient {
       this.renderContacts();
       
       // Fetch avatars for all contacts in the background
-      this.contacts.forEach(contact => {
-        this.fetchAvatar(contact.id);
-      });
+      this.fetchAvatars(this.contacts.map(contact => contact.id));
     } catch (err) {
       console.error('Failed to load contacts:', err);
     }
@@ -744,6 +1325,47 @@ class WhatsAppClient {
     }
   }
 
+  // Fetch avatars for many contacts at once. Cached ones come back
+  // immediately; the rest arrive later as 'avatar' WebSocket events.
+  async fetchAvatars(jids) {
+    const wanted = jids.filter(jid => !this.avatarCache.has(jid) && !this.avatarFetching.has(jid));
+    
+    for (let i = 0; i < wanted.length; i += 100) {
+      const batch = wanted.slice(i, i + 100);
+      batch.forEach(jid => this.avatarFetching.add(jid));
+      
+      try {
+        const response = await fetch('/api/avatars', {
+          method: 'POST',
+          headers: { 'Content-Type': 'application/json' },
+          body: JSON.stringify({ jids: batch })
+        });
+        const data = await response.json();
+        
+        for (const [jid, url] of Object.entries(data.avatars || {})) {
+          if (url !== 'unknown') {
+            this.handleAvatar(jid, url);
+          } else if (!this.connected) {
+            // Nothing is fetched while disconnected; allow a retry later
+            this.avatarFetching.delete(jid);
+          }
+        }
+      } catch (err) {
+        console.error('Failed to fetch avatars:', err);
+        batch.forEach(jid => this.avatarFetching.delete(jid));
+      }
+    }
+  }
+
+  // Handle an avatar result (from a batch response or a WebSocket event)
+  handleAvatar(jid, url) {
+    this.avatarFetching.delete(jid);
+    this.avatarCache.set(jid, url);
+    if (url) {
+      this.updateAvatarDisplay(jid, url);
+    }
+  }
+
   // Update avatar display for a specific JID
   updateAvatarDisplay(jid, url) {
     const initial = this.getInitial(jid);
@@ -783,9 +1405,11 @@ class WhatsAppClient {
       return;
     }
     
-    // Contacts are already sorted by the backend (pinned first, then by last message time)
+    // Contacts are already sorted by the backend (Saved Messages, pinned, then by last message time)
     // But we'll sort locally too to ensure proper ordering when updates happen
     const sorted = [...this.contacts].sort((a, b) => {
+      // Saved Messages (my own chat) always on top
+      if ((a.type === 'self') !== (b.type === 'self')) return a.type === 'self' ? -1 : 1;
       // Pinned items first
       const aPinned = a.pinnedAt != null;
       const bPinned = b.pinnedAt != null;
@@ -799,13 +1423,17 @@ class WhatsAppClient {
     
     container.innerHTML = sorted.map(contact => {
       const isGroup = contact.type === 'group';
+      const isChannel = contact.type === 'channel';
       const isPinned = contact.pinnedAt != null;
+      const isOnline = contact.presence?.isOnline === true;
       // Better display name logic
       let displayName = contact.name;
       if (!displayName && contact.phone) {
         displayName = '+' + contact.phone;
       } else if (!displayName && isGroup) {
         displayName = 'Group Chat';
+      } else if (!displayName && isChannel) {
+        displayName = 'Channel';
       } else if (!displayName) {
         // Extract phone from JID if available
         const phoneFromJid = contact.id?.split('@')[0];
@@ -818,9 +1446,11 @@ class WhatsAppClient {
         `<span class="unread-badge">${contact.unreadCount}</span>` : '';
       
       // Get last message preview - prefer cached messages, fall back to contact.lastMessagePreview
-      const messages = this.messages.get(contact.id) || [];
-      const lastMessage = messages[messages.length - 1];
+      const lastMessage = this.lastPreviewMessage(contact.id);
       let preview = '';
+      // Stickers show their first frame next to the preview text
+      const previewThumb = lastMessage && lastMessage.content && lastMessage.content.type === 'sticker'
+        ? lastMessage.content.thumbnail_data : null;
       
       if (lastMessage) {
         // Use locally cached message for preview
@@ -845,6 +1475,8 @@ class WhatsAppClient {
       
       // Group indicator (fold mark in corner)
       const groupIndicator = isGroup ? '<div class="group-indicator"></div>' : '';
+      const channelIndicator = isChannel ? '<div class="group-indicator channel-indicator"></div>' : '';
+      const onlineIndicator = isOnline ? '<div class="online-indicator" title="Online"></div>' : '';
       
       // Pin button (shows on hover, filled when pinned)
       const pinButton = `
@@ -858,11 +1490,13 @@ class WhatsAppClient {
       `;
       
       return `
-        <div class="contact-item ${isActive ? 'active' : ''} ${isGroup ? 'is-group' : ''} ${isPinned ? 'is-pinned' : ''}" data-contact-id="${contact.id}">
+        <div class="contact-item ${isActive ? 'active' : ''} ${isGroup ? 'is-group' : ''} ${isChannel ? 'is-channel' : ''} ${isPinned ? 'is-pinned' : ''} ${isOnline ? 'is-online' : ''}" data-contact-id="${contact.id}">
           <div class="avatar-container">
             <div class="avatar">
               ${avatarContent}
               ${groupIndicator}
+              ${channelIndicator}
+              ${onlineIndicator}
             </div>
             ${pinButton}
           </div>
@@ -872,7 +1506,9 @@ class WhatsAppClient {
               <span class="contact-time">${time}</span>
             </div>
             <div class="contact-preview">
+              ${previewThumb ? `<img class="preview-sticker" src="data:image/png;base64,${previewThumb}" alt="">` : ''}
               <span class="preview-text">${this.escapeHtml(preview)}</span>
+              ${contact.topReaction ? `<span class="contact-top-reaction" title="Most used reaction">${this.escapeHtml(contact.topReaction)}</span>` : ''}
               ${unread}
             </div>
           </div>
@@ -881,6 +1517,12 @@ class WhatsAppClient {
     }).join('');
   }
 
+  // The latest cached message a chat's preview shows: system notices are skipped
+  lastPreviewMessage(contactId) {
+    const messages = this.messages.get(contactId) || [];
+    return messages.findLast(m => m.contentType !== 'system');
+  }
+
   // Get message preview text
   getMessagePreview(message) {
     const prefix = message.isFromMe ? 'You: ' : '';
@@ -909,15 +1551,42 @@ class WhatsAppClient {
         return '[ Message deleted ]';
       case 'poll':
         return prefix + '[ Poll: ' + content.question + ' ]';
-      default:
-        return prefix + '[ Message ]';
+      default: {
+        const text = this.unknownContentText(content)[0];
+        return prefix + (text ? text.substring(0, 50) : '[ Message ]');
+      }
+    }
+  }
+
+  // Text-like fields of content the app doesn't support, shallowest first
+  // (mirrors the server's fallback)
+  unknownContentText(content) {
+    const keys = ['body', 'text', 'caption', 'title', 'description', 'name', 'question', 'content', 'footer'];
+    const found = [];
+    let level = [content];
+    while (level.length) {
+      const next = [];
+      for (const value of level) {
+        if (!value || typeof value !== 'object') continue;
+        if (!Array.isArray(value)) {
+          for (const key of keys) {
+            const text = typeof value[key] === 'string' ? value[key].trim() : '';
+            if (text && !found.includes(text)) found.push(text);
+          }
+        }
+        next.push(...Object.values(value).filter(child => typeof child !== 'string'));
+      }
+      level = next;
     }
+    return found;
   }
 
   // Select a contact
   async selectContact(contactId) {
     try {
+      this.flushDraftSave();
       this.currentContactId = contactId;
+      this.renderPinnedMessages();
       
       // Clear any pending reply from previous chat
       this.clearReply();
@@ -943,7 +1612,11 @@ class WhatsAppClient {
       // Update chat header
       if (contact) {
         document.getElementById('chat-name').textContent = contact.name || contact.phone || 'Unknown';
-        document.getElementById('chat-phone').textContent = contact.phone ? '+' + contact.phone : '';
+        document.getElementById('chat-phone').textContent = contact.phone
+          ? '+' + contact.phone
+          : (contact.type === 'channel' ? 'Channel' : '');
+        this.updatePresenceStatus();
+        this.updateChatDetails();
         
         const initial = (contact.name || contact.phone || '?').charAt(0).toUpperCase();
         // Get avatar container - it's the .avatar element in .chat-header
@@ -961,7 +1634,9 @@ class WhatsAppClient {
         }
       }
       
-      // Load messages
+      // Load messages and the unsent draft
+      this.applyDraft(null);
+      this.loadDraft(contactId);
       await this.loadMessages(contactId);
       
       // Load conversation usage
@@ -970,9 +1645,13 @@ class WhatsAppClient {
       // Re-render contacts to update active state and unread
       this.renderContacts();
       
+      // Channels are read-only, so there's nothing to compose
+      const isChannel = contact?.type === 'channel';
+      document.querySelector('.message-input-area')?.classList.toggle('hidden', isChannel);
+
       // Update send button state and focus input (only on desktop)
       this.updateSendButton();
-      if (!this.isMobile()) {
+      if (!isChannel && !this.isMobile()) {
         document.getElementById('message-input').focus();
       }
     } catch (err) {
@@ -990,10 +1669,13 @@ class WhatsAppClient {
       // Handle both old format (array) and new format (object with messages/hasMore)
       const messages = Array.isArray(data) ? data : data.messages;
       const hasMore = Array.isArray(data) ? false : data.hasMore;
+      messages.forEach(m => this.applyReactionGroups(m, m.reactions));
       
       this.messages.set(contactId, messages);
       this.messagesHasMore.set(contactId, hasMore);
+      this.messagesCursor.set(contactId, data.nextCursor || null);
       this.renderMessages(messages);
+      this.setPinnedMessages(contactId, data.pinnedMessages || []);
       
       // Set up scroll handler for infinite scroll
       this.setupScrollHandler();
@@ -1013,26 +1695,32 @@ class WhatsAppClient {
     const existingMessages = this.messages.get(contactId) || [];
     if (existingMessages.length === 0) return;
     
-    // Get the oldest message's timestamp
-    const oldestTimestamp = existingMessages[0].timestamp;
+    // Carry on from the previous page's cursor, falling back to the oldest
+    // message's timestamp
+    const cursor = this.messagesCursor.get(contactId);
+    const from = cursor
+      ? `cursor=${encodeURIComponent(cursor)}`
+      : `before=${existingMessages[0].timestamp}`;
     
     try {
       this.messagesLoading.set(contactId, true);
       this.showLoadingIndicator();
       
       const response = await fetch(
-        `/api/messages/${encodeURIComponent(contactId)}?before=${oldestTimestamp}&limit=50`
+        `/api/messages/${encodeURIComponent(contactId)}?${from}&limit=50`
       );
       const data = await response.json();
       
       const olderMessages = Array.isArray(data) ? data : data.messages;
       const hasMore = Array.isArray(data) ? false : data.hasMore;
+      olderMessages.forEach(m => this.applyReactionGroups(m, m.reactions));
       
       if (olderMessages.length > 0) {
         // Prepend older messages
         const allMessages = [...olderMessages, ...existingMessages];
         this.messages.set(contactId, allMessages);
         this.messagesHasMore.set(contactId, hasMore);
+        this.messagesCursor.set(contactId, data.nextCursor || null);
         
         // Re-render and maintain scroll position
         this.prependMessages(olderMessages);
@@ -1180,8 +1868,25 @@ class WhatsAppClient {
     });
   }
 
+  // Reply context kept with a sent message's content, for messages loaded
+  // from the server
+  storedReplyContext(message) {
+    const reply = message.content && message.content.reply_to;
+    if (!reply) return null;
+    let senderName = 'You';
+    if (reply.sender) {
+      const jid = reply.sender.includes('@') ? reply.sender : reply.sender + '@s.whatsapp.net';
+      const contact = this.contacts.find(c => c.id === jid);
+      senderName = (contact && contact.name) || reply.sender;
+    }
+    return { senderName, text: reply.text || '' };
+  }
+
   // Render a single message
   renderMessage(message) {
+    if (message.contentType === 'system') {
+      return `<div class="message-system" data-message-id="${message.id}">${this.escapeHtml(message.content?.text || '')}</div>`;
+    }
     const isOutgoing = message.isFromMe || message.is_from_me;
     const isTranslated = message.isTranslated || message.is_translated;
     const time = this.formatMessageTime(message.timestamp);
@@ -1270,6 +1975,13 @@ class WhatsAppClient {
       </button>
     ` : '';
     
+    // Pin button (pinned messages are listed above the chat)
+    const pinButton = `
+      <button class="message-action-btn pin-btn${this.isPinned(message.id) ? ' active' : ''}" onclick="event.stopPropagation(); app.togglePinMessage('${messageId}')" title="Pin or unpin">
+        <svg viewBox="0 0 24 24"><path fill="currentColor" d="M16 12V4h1V2H7v2h1v8l-2 2v2h5.2v6h1.6v-6H18v-2l-2-2z"/></svg>
+      </button>
+    `;
+    
     // Reaction button with quick emoji picker
     const reactionButton = `
       <div class="reaction-button-container">
@@ -1289,8 +2001,8 @@ class WhatsAppClient {
     
     // Quoted message (reply context)
     let quotedMessage = '';
-    if (message.replyContext) {
-      const ctx = message.replyContext;
+    const ctx = message.replyContext || this.storedReplyContext(message);
+    if (ctx) {
       quotedMessage = `
         <div class="quoted-message">
           <div class="quoted-sender">${this.escapeHtml(ctx.senderName || 'Unknown')}</div>
@@ -1299,23 +2011,30 @@ class WhatsAppClient {
       `;
     }
     
+    // Learning mode: original text and vocabulary under the translation;
+    // learning notes under my translated messages
+    const vocabularyHtml = isOutgoing ? this.renderLearningNotes(message) : this.renderVocabulary(message);
+    
     // Reactions display
-    const reactionsHtml = this.renderReactions(message.reactions);
+    const reactionsHtml = this.renderReactions(message.reactions, message.myReaction);
     
     return `
-      <div class="message ${isOutgoing ? 'outgoing' : 'incoming'}" data-message-id="${messageId}">
+      <div class="message ${isOutgoing ? 'outgoing' : 'incoming'}${message.mentionsMe ? ' mentions-me' : ''}" data-message-id="${messageId}">
         ${forwarded}
         ${sender}
         ${quotedMessage}
         ${content}
+        ${vocabularyHtml}
         ${reactionsHtml}
         <div class="message-footer">
+          ${isOutgoing && message.sentBy ? `<span class="message-sent-by">${this.escapeHtml(message.sentBy)}</span>` : ''}
           <span class="message-time">${time}</span>
           ${translationIndicator}
           <div class="message-actions">
             ${translateButton}
             ${replyButton}
             ${aiReplyButton}
+            ${pinButton}
             ${reactionButton}
           </div>
         </div>
@@ -1371,9 +2090,13 @@ class WhatsAppClient {
         // Check if we have the actual image data or if it needs to be lazy loaded
         const mediaData = content.media_data || content.mediaData;
         const hasMedia = content.has_media || content.hasMedia;
+        const thumbnailData = content.thumbnail_data;
         const messageId = message.id;
         const mimeType = content.mime_type || content.mimeType || 'image/jpeg';
         
+        if (content.view_once && !mediaData) {
+          return this.renderViewOnceMedia(message, content, 'image', mimeType, displayCaption);
+        }
         if (mediaData) {
           const imgSrc = mediaData.startsWith('data:') ? mediaData : `data:${mimeType};base64,${mediaData}`;
           return `
@@ -1382,6 +2105,16 @@ class WhatsAppClient {
             </div>
             ${displayCaption ? `<div class="message-caption">${this.escapeHtml(displayCaption)}</div>` : ''}
           `;
+        } else if (hasMedia && thumbnailData) {
+          // Show the thumbnail until the full image is requested
+          return `
+            <div class="message-image lazy-media" data-message-id="${messageId}" data-mime-type="${mimeType}" data-media-type="image">
+              <div class="media-placeholder thumbnail-placeholder" onclick="app.loadMedia('${messageId}', this)">
+                <img src="data:image/jpeg;base64,${thumbnailData}" alt="Image">
+              </div>
+            </div>
+            ${displayCaption ? `<div class="message-caption">${this.escapeHtml(displayCaption)}</div>` : ''}
+          `;
         } else if (hasMedia) {
           // Media needs to be lazy loaded - show placeholder
           return `
@@ -1409,6 +2142,9 @@ class WhatsAppClient {
         const videoMsgId = message.id;
         const videoMime = content.mime_type || content.mimeType || 'video/mp4';
         
+        if (content.view_once && !videoData) {
+          return this.renderViewOnceMedia(message, content, 'video', videoMime, displayCaption);
+        }
         if (videoData) {
           const videoSrc = videoData.startsWith('data:') ? videoData : `data:${videoMime};base64,${videoData}`;
           return `
@@ -1447,6 +2183,9 @@ class WhatsAppClient {
         const audioMsgId = message.id;
         const audioMime = content.mime_type || content.mimeType || 'audio/ogg';
         const isVoiceNote = content.is_voice_note || content.isVoiceNote;
+        // Measured from the audio when possible; the bridge's duration is often missing
+        const audioSeconds = message.audio ? Math.round(message.audio.durationMs / 1000) : content.duration_seconds;
+        const audioWaveform = this.renderWaveform(message.audio, audioSeconds);
         
         if (audioData) {
           const audioSrc = audioData.startsWith('data:') ? audioData : `data:${audioMime};base64,${audioData}`;
@@ -1456,13 +2195,14 @@ class WhatsAppClient {
                 <source src="${audioSrc}" type="${audioMime}">
                 Your browser does not support audio playback.
               </audio>
+              ${audioWaveform}
               ${isVoiceNote ? '<span class="voice-note-label">Voice Note</span>' : ''}
             </div>
           `;
         } else if (audioHasMedia) {
           // Media needs to be lazy loaded - show placeholder
           const audioType = isVoiceNote ? 'voice note' : 'audio';
-          const durationText = content.duration_seconds ? this.formatDuration(content.duration_seconds) : '';
+          const durationText = audioSeconds ? this.formatDuration(audioSeconds) : '';
           return `
             <div class="message-audio lazy-media ${isVoiceNote ? 'voice-note' : ''}" data-message-id="${audioMsgId}" data-mime-type="${audioMime}" data-media-type="audio">
               <div class="media-placeholder" onclick="app.loadMedia('${audioMsgId}', this)">
@@ -1471,11 +2211,12 @@ class WhatsAppClient {
                 </svg>
                 <span>Click to load ${audioType}${durationText ? ` (${durationText})` : ''}</span>
               </div>
+              ${audioWaveform}
             </div>
           `;
         } else {
           const audioType = isVoiceNote ? 'Voice Note' : 'Audio';
-          return `<div class="message-media audio">[ ${audioType} ]${content.duration_seconds ? ' - ' + this.formatDuration(content.duration_seconds) : ''}</div>`;
+          return `<div class="message-media audio">[ ${audioType} ]${audioSeconds ? ' - ' + this.formatDuration(audioSeconds) : ''}</div>${audioWaveform}`;
         }
       
       case 'document':
@@ -1531,14 +2272,36 @@ class WhatsAppClient {
         const stickerMsgId = message.id;
         const stickerMime = content.mime_type || content.mimeType || 'image/webp';
         const isAnimated = content.is_animated || content.isAnimated;
+        const stickerFormat = content.sticker_format || '';
+        const isLottie = stickerFormat === 'lottie' || stickerFormat === 'tgs';
+        const stickerThumb = content.thumbnail_data;
         
-        if (stickerData) {
+        if (isLottie && (stickerData || stickerHasMedia)) {
+          // Lottie stickers play in a player fed from the media endpoint
+          return `
+            <div class="message-sticker lazy-media" data-message-id="${stickerMsgId}" data-media-type="sticker" data-sticker-format="${stickerFormat}">
+              <div class="media-placeholder sticker-placeholder" onclick="app.loadMedia('${stickerMsgId}', this)">
+                <svg viewBox="0 0 24 24" width="48" height="48">
+                  <path fill="currentColor" d="M8 5v14l11-7z"/>
+                </svg>
+                <span>Animated sticker</span>
+              </div>
+            </div>
+          `;
+        } else if (stickerData) {
           const stickerSrc = stickerData.startsWith('data:') ? stickerData : `data:${stickerMime};base64,${stickerData}`;
           return `
             <div class="message-sticker">
               <img src="${stickerSrc}" alt="Sticker" loading="lazy">
             </div>
           `;
+        } else if (stickerHasMedia && stickerThumb) {
+          // The first frame stands in until the sticker is loaded
+          return `
+            <div class="message-sticker lazy-media" data-message-id="${stickerMsgId}" data-mime-type="${stickerMime}" data-media-type="sticker">
+              <img class="sticker-preview" src="data:image/png;base64,${stickerThumb}" alt="Sticker" title="${isAnimated ? 'Click to play' : ''}" onclick="app.loadMedia('${stickerMsgId}', this)">
+            </div>
+          `;
         } else if (stickerHasMedia) {
           // Media needs to be lazy loaded - show placeholder
           return `
@@ -1558,16 +2321,19 @@ class WhatsAppClient {
       case 'location':
         const lat = content.latitude;
         const lng = content.longitude;
-        const locName = content.name || content.address || 'Shared Location';
-        const locAddress = content.address && content.address !== content.name ? content.address : '';
+        const locFullAddress = content.address || content.resolved_address;
+        const locName = content.name || locFullAddress || 'Shared Location';
+        const locAddress = locFullAddress && locFullAddress !== locName ? locFullAddress : '';
         const mapsUrl = lat && lng ? `https://www.google.com/maps?q=${lat},${lng}` : null;
+        // Proxied by the server so the map service never sees the viewer
+        const mapThumbUrl = content.map_thumbnail_url || `/api/map-thumb?lat=${lat}&lon=${lng}`;
         
         if (mapsUrl) {
           return `
             <div class="message-location">
               <a href="${mapsUrl}" target="_blank" rel="noopener" class="location-link">
                 <div class="location-preview">
-                  <img src="https://maps.googleapis.com/maps/api/staticmap?center=${lat},${lng}&zoom=15&size=300x150&markers=color:red%7C${lat},${lng}&key=" 
+                  <img src="${this.escapeHtml(mapThumbUrl)}" loading="lazy" 
                        onerror="this.style.display='none';this.nextElementSibling.style.display='flex';"
                        alt="Map">
                   <div class="location-placeholder" style="display:none;">
@@ -1606,8 +2372,12 @@ class WhatsAppClient {
           </div>
         `;
       
-      default:
-        return `<div class="message-media">[ ${content.rawType || 'Unknown message type'} ]</div>`;
+      default: {
+        const rawType = content.raw_type || content.type || 'Unknown message type';
+        const texts = this.unknownContentText(content)
+          .map(t => `<div class="message-text">${this.escapeHtml(t)}</div>`).join('');
+        return `<div class="message-media">[ ${this.escapeHtml(rawType)} ]</div>${texts}`;
+      }
     }
   }
 
@@ -1661,6 +2431,8 @@ class WhatsAppClient {
     
     const sendButton = document.getElementById('send-button');
     sendButton.disabled = true;
+    // Sending clears the draft on the server
+    this.cancelDraftSave();
     
     // Capture reply state before clearing
     const replyTo = this.replyingTo ? this.replyingTo.messageId : null;
@@ -1680,13 +2452,30 @@ class WhatsAppClient {
         }
       }
       
-      const response = await fetch('/api/send', {
+      let response = await fetch('/api/send', {
         method: 'POST',
         headers: { 'Content-Type': 'application/json' },
         body: JSON.stringify(requestBody)
       });
       
-      const result = await response.json();
+      let result = await response.json();
+      
+      // The server holds back drafts that don't match the chat's language
+      if (response.status === 409 && result.confirmationRequired) {
+        const detected = result.detectedLanguage || 'an unknown language';
+        const prompt = result.chatLanguage
+          ? `This message looks like ${detected}, but this chat is in ${result.chatLanguage}. Send it anyway?`
+          : `This chat has no established language yet and the message looks like ${detected}. Send it anyway?`;
+        if (!confirm(prompt)) return;
+        
+        requestBody.confirmationToken = result.confirmationToken;
+        response = await fetch('/api/send', {
+          method: 'POST',
+          headers: { 'Content-Type': 'application/json' },
+          body: JSON.stringify(requestBody)
+        });
+        result = await response.json();
+      }
       
       if (!response.ok) {
         throw new Error(result.error || 'Failed to send message');
@@ -1714,8 +2503,13 @@ class WhatsAppClient {
         originalText: result.isTranslated ? text : null,  // What user typed (English)
         translatedText: result.translatedText || null,     // What was sent (foreign language)
         sourceLanguage: result.sourceLanguage || null,     // Target language
-        // Include reply context if this was a reply
-        replyContext: replyContext
+        vocabulary: (result.learningNotes || []).map(note => ({ term: note.target, gloss: note.source })),
+        sortKey: result.sortKey,
+        // Include reply context if this was a reply, with the server's
+        // stand-in text for quoted media
+        replyContext: replyContext && result.replyPreview
+          ? { ...replyContext, text: result.replyPreview }
+          : replyContext
       };
       
       // Add to local store and display
@@ -1739,6 +2533,14 @@ class WhatsAppClient {
         this.fetchConversationUsage(this.currentContactId);
       }
       
+      this.warnPossibleDelay(result.connectionQuality);
+      
+      // Sent anyway, but it's likely night for them
+      if (result.warning) {
+        const undo = result.undoableUntil ? ' You can still undo it.' : '';
+        alert(`Sent, but it's probably the middle of the night for them (${result.warning}).${undo}`);
+      }
+      
     } catch (err) {
       console.error('Failed to send message:', err);
       alert('Failed to send message: ' + err.message);
@@ -1861,7 +2663,7 @@ class WhatsAppClient {
     });
   }
 
-  // Send a reaction to a message
+  // Send a reaction to a message. Picking my current reaction again removes it.
   async sendReaction(messageId, contactId, senderJid, emoji) {
     // Close any open reaction pickers
     document.querySelectorAll('.reaction-picker.show').forEach(el => el.classList.remove('show'));
@@ -1871,6 +2673,11 @@ class WhatsAppClient {
       return;
     }
 
+    const message = (this.messages.get(contactId) || []).find(m => m.id === messageId);
+    if (message && message.myReaction === emoji) {
+      emoji = '';
+    }
+
     try {
       const response = await fetch('/api/react', {
         method: 'POST',
@@ -1889,47 +2696,39 @@ class WhatsAppClient {
         throw new Error(result.error || 'Failed to send reaction');
       }
 
-      // Update local message with the reaction
-      const messages = this.messages.get(contactId);
-      if (messages) {
-        const message = messages.find(m => m.id === messageId);
-        if (message) {
-          // Initialize reactions map if needed
-          if (!message.reactions) {
-            message.reactions = {};
-          }
-          
-          // Get my phone number for tracking who reacted
-          const myPhone = document.getElementById('user-phone')?.textContent?.replace('+', '') || 'me';
-          
-          // Remove my previous reaction (if any)
-          for (const [existingEmoji, reactors] of Object.entries(message.reactions)) {
-            message.reactions[existingEmoji] = reactors.filter(r => r !== myPhone);
-            if (message.reactions[existingEmoji].length === 0) {
-              delete message.reactions[existingEmoji];
-            }
-          }
-          
-          // Add new reaction (empty emoji means remove)
-          if (emoji) {
-            if (!message.reactions[emoji]) {
-              message.reactions[emoji] = [];
-            }
-            message.reactions[emoji].push(myPhone);
-          }
-          
-          // Update the message display
-          this.updateMessageReactions(messageId);
-        }
-      }
-      
-      console.log('Reaction sent successfully');
+      // The server stored it and returns the message's reactions; other
+      // sessions get the same through a reaction_added/removed event
+      this.handleReactionsUpdated(contactId, messageId, result.reactions);
     } catch (err) {
       console.error('Failed to send reaction:', err);
       alert('Failed to send reaction: ' + err.message);
     }
   }
 
+  // Replace a message's reactions with the server's grouped list
+  handleReactionsUpdated(contactId, messageId, groups) {
+    const message = (this.messages.get(contactId) || []).find(m => m.id === messageId);
+    if (!message) return;
+    this.applyReactionGroups(message, groups);
+    if (contactId === this.currentContactId) {
+      this.updateMessageReactions(messageId);
+    }
+  }
+
+  // Turn the server's reaction groups into the emoji -> reactors map used
+  // for display, remembering which one is mine
+  applyReactionGroups(message, groups) {
+    if (!Array.isArray(groups)) return;
+    message.reactions = {};
+    message.myReaction = null;
+    for (const group of groups) {
+      message.reactions[group.emoji] = group.reactors;
+      if (group.fromMe) {
+        message.myReaction = group.emoji;
+      }
+    }
+  }
+
   // Translate a message manually
   async translateMessage(messageId) {
     const messages = this.messages.get(this.currentContactId);
@@ -2006,7 +2805,7 @@ class WhatsAppClient {
     }
     
     // Build reactions HTML
-    const reactionsHtml = this.renderReactions(message.reactions);
+    const reactionsHtml = this.renderReactions(message.reactions, message.myReaction);
     if (reactionsHtml) {
       // Insert before message-footer
       const footer = messageEl.querySelector('.message-footer');
@@ -2017,14 +2816,49 @@ class WhatsAppClient {
   }
 
   // Render reactions for a message
-  renderReactions(reactions) {
+  // Render the original text and picked-out words of a learning-mode message
+  renderVocabulary(message) {
+    const vocabulary = message.vocabulary;
+    if (!vocabulary || vocabulary.length === 0) return '';
+    
+    const original = message.originalText || message.original_text || '';
+    const items = vocabulary
+      .map(entry => `<span class="vocab-item"><span class="vocab-term">${this.escapeHtml(entry.term)}</span> ${this.escapeHtml(entry.gloss)}</span>`)
+      .join('');
+    
+    return `
+      <div class="message-learning">
+        ${original ? `<div class="message-original">${this.escapeHtml(original)}</div>` : ''}
+        <div class="message-vocabulary">${items}</div>
+      </div>
+    `;
+  }
+
+  // Render the key phrases of one of my translated messages
+  renderLearningNotes(message) {
+    const notes = message.vocabulary;
+    if (!notes || notes.length === 0) return '';
+
+    const items = notes
+      .map(entry => `<span class="vocab-item"><span class="vocab-term">${this.escapeHtml(entry.term)}</span> ${this.escapeHtml(entry.gloss)}</span>`)
+      .join('');
+
+    return `
+      <div class="message-learning">
+        <div class="message-vocabulary">${items}</div>
+      </div>
+    `;
+  }
+
+  renderReactions(reactions, myReaction = null) {
     if (!reactions || Object.keys(reactions).length === 0) return '';
     
     const reactionItems = Object.entries(reactions)
       .filter(([emoji, reactors]) => reactors.length > 0)
       .map(([emoji, reactors]) => {
         const count = reactors.length > 1 ? `<span class="reaction-count">${reactors.length}</span>` : '';
-        return `<span class="reaction-item">${emoji}${count}</span>`;
+        const mine = emoji === myReaction ? ' mine' : '';
+        return `<span class="reaction-item${mine}">${emoji}${count}</span>`;
       })
       .join('');
     
@@ -2170,7 +3004,7 @@ class WhatsAppClient {
     
     try {
       // Build request with optional reply context
-      const requestBody = { prompt };
+      const requestBody = { prompt, contactId: this.currentContactId };
       
       if (this.replyingTo) {
         requestBody.replyToText = this.replyingTo.text;
@@ -2319,6 +3153,75 @@ class WhatsAppClient {
     }
   }
 
+  // Register the service worker and show the notifications toggle where
+  // Web Push is supported
+  async setupPushNotifications() {
+    const button = document.getElementById('notifications-button');
+    if (!button || !('serviceWorker' in navigator) || !('PushManager' in window)) return;
+
+    try {
+      this.pushRegistration = await navigator.serviceWorker.register('/sw.js');
+    } catch (err) {
+      console.warn('Service worker registration failed:', err);
+      return;
+    }
+    navigator.serviceWorker.addEventListener('message', (event) => {
+      if (event.data?.type === 'open_chat' && event.data.contactId) {
+        this.selectContact(event.data.contactId);
+      }
+    });
+    // Opened from a notification while no window was open
+    const chat = new URLSearchParams(location.search).get('chat');
+    if (chat) {
+      history.replaceState(null, '', '/');
+      this.selectContact(chat);
+    }
+
+    const subscription = await this.pushRegistration.pushManager.getSubscription();
+    button.classList.toggle('subscribed', !!subscription);
+    button.classList.remove('hidden');
+    button.addEventListener('click', () => this.togglePushNotifications());
+  }
+
+  // Subscribe this device to push notifications, or unsubscribe it
+  async togglePushNotifications() {
+    const button = document.getElementById('notifications-button');
+    const pushManager = this.pushRegistration.pushManager;
+    try {
+      const existing = await pushManager.getSubscription();
+      if (existing) {
+        await fetch('/api/push/subscribe', {
+          method: 'DELETE',
+          headers: { 'Content-Type': 'application/json', ...this.getAuthHeaders() },
+          body: JSON.stringify({ endpoint: existing.endpoint })
+        });
+        await existing.unsubscribe();
+        button.classList.remove('subscribed');
+        return;
+      }
+
+      if (await Notification.requestPermission() !== 'granted') return;
+      const response = await fetch('/api/push/vapid-public-key', { headers: this.getAuthHeaders() });
+      const { publicKey } = await response.json();
+      const padded = (publicKey + '='.repeat((4 - publicKey.length % 4) % 4))
+        .replace(/-/g, '+').replace(/_/g, '/');
+      const subscription = await pushManager.subscribe({
+        userVisibleOnly: true,
+        applicationServerKey: Uint8Array.from(atob(padded), c => c.charCodeAt(0))
+      });
+      const saved = await fetch('/api/push/subscribe', {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json', ...this.getAuthHeaders() },
+        body: JSON.stringify(subscription.toJSON())
+      });
+      if (!saved.ok) throw new Error('Failed to save subscription');
+      button.classList.add('subscribed');
+    } catch (err) {
+      console.error('Failed to change push notifications:', err);
+      alert('Failed to change notifications. Please try again.');
+    }
+  }
+
   // Auto-resize textarea (expands up to max-height)
   autoResizeTextarea(textarea) {
     textarea.style.height = 'auto';
@@ -2332,6 +3235,9 @@ class WhatsAppClient {
       this.handleLogout();
     });
 
+    // Push notifications on this device
+    this.setupPushNotifications();
+
     // Contact click
     document.getElementById('contacts-list').addEventListener('click', (e) => {
       const contactItem = e.target.closest('.contact-item');
@@ -2362,6 +3268,7 @@ class WhatsAppClient {
     input.addEventListener('input', () => {
       this.updateSendButton();
       this.autoResizeTextarea(input);
+      this.scheduleDraftSave();
     });
 
     // Send on Cmd+Enter (Mac) or Ctrl+Enter (Windows/Linux)
@@ -2511,6 +3418,10 @@ class WhatsAppClient {
       } else if (action === 'settings') {
         this.currentContactId = contactId;
         this.openSettingsModal();
+      } else if (action === 'clear') {
+        this.clearConversation(contactId);
+      } else if (action === 'export-translations') {
+        this.exportTranslations(contactId);
       }
 
       this.hideContactContextMenu();
@@ -2540,6 +3451,11 @@ class WhatsAppClient {
       this.saveConversationSettings();
     });
 
+    document.getElementById('translation-tone')?.addEventListener('change', (e) => {
+      document.getElementById('translation-tone-custom')
+        ?.classList.toggle('hidden', e.target.value !== 'custom');
+    });
+
     // Close modal on Escape key
     document.addEventListener('keydown', (e) => {
       if (e.key === 'Escape') {
@@ -2645,6 +3561,21 @@ class WhatsAppClient {
     return `${mins}:${secs.toString().padStart(2, '0')}`;
   }
 
+  // Waveform bars and duration of an audio message, if the server measured them
+  renderWaveform(audio, seconds) {
+    if (!audio || !audio.waveform || audio.waveform.length === 0) return '';
+    const bars = audio.waveform
+      .map(height => `<span style="height: ${Math.max(height, 4)}%"></span>`)
+      .join('');
+    const title = audio.metadataOnly ? 'Waveform estimated from the audio stream' : '';
+    return `
+      <div class="audio-waveform ${audio.metadataOnly ? 'estimated' : ''}" title="${title}">
+        <div class="audio-waveform-bars">${bars}</div>
+        ${seconds ? `<span class="audio-duration">${this.formatDuration(seconds)}</span>` : ''}
+      </div>
+    `;
+  }
+
   // Format cost for display
   formatCost(costUsd) {
     if (costUsd < 0.01) {
@@ -2842,6 +3773,28 @@ class WhatsAppClient {
     }
   }
 
+  // Render a view-once photo or video: openable once while the bridge's
+  // in-memory copy lasts, then just a note that it was opened
+  renderViewOnceMedia(message, content, mediaType, mimeType, displayCaption) {
+    const label = mediaType === 'video' ? 'video' : 'photo';
+    const caption = displayCaption ? `<div class="message-caption">${this.escapeHtml(displayCaption)}</div>` : '';
+    if (content.has_media && !content.viewed_at) {
+      return `
+        <div class="message-${mediaType} lazy-media view-once" data-message-id="${message.id}" data-mime-type="${mimeType}" data-media-type="${mediaType}" data-view-once="true">
+          <div class="media-placeholder view-once-placeholder" onclick="app.loadMedia('${message.id}', this)">
+            <span class="view-once-icon">1</span>
+            <span>View once ${label} · Click to open</span>
+          </div>
+        </div>
+        ${caption}
+      `;
+    }
+    return `
+      <div class="message-media view-once-opened"><span class="view-once-icon">1</span> Opened</div>
+      ${caption}
+    `;
+  }
+
   // Load media on demand (lazy loading)
   async loadMedia(messageId, placeholderEl) {
     // Find the lazy-media container
@@ -2855,6 +3808,7 @@ class WhatsAppClient {
     const mediaType = container.dataset.mediaType;
     const mimeType = container.dataset.mimeType;
     const fileName = container.dataset.fileName;
+    const isViewOnce = container.dataset.viewOnce === 'true';
 
     // Show loading state
     placeholderEl.classList.add('loading');
@@ -2864,9 +3818,20 @@ class WhatsAppClient {
     `;
 
     try {
+      if (container.dataset.stickerFormat === 'lottie' || container.dataset.stickerFormat === 'tgs') {
+        await this.playLottieSticker(messageId, container);
+        return;
+      }
+
       // Fetch media from the API
       const response = await fetch(`/api/media/${encodeURIComponent(messageId)}`);
       
+      if (isViewOnce && response.status === 410) {
+        // Already opened (here or elsewhere) or expired
+        this.markViewOnceOpened(messageId);
+        container.outerHTML = '<div class="message-media view-once-opened"><span class="view-once-icon">1</span> Opened</div>';
+        return;
+      }
       if (!response.ok) {
         throw new Error('Failed to load media');
       }
@@ -2938,8 +3903,13 @@ class WhatsAppClient {
       container.classList.remove('lazy-media');
       container.innerHTML = mediaHtml;
 
-      // Also update the message cache so re-renders show the media
-      this.updateMessageMediaCache(messageId, data.media_data, actualMimeType);
+      if (isViewOnce) {
+        // Never cache view-once media; a re-render shows it as opened
+        this.markViewOnceOpened(messageId);
+      } else {
+        // Also update the message cache so re-renders show the media
+        this.updateMessageMediaCache(messageId, data.media_data, actualMimeType);
+      }
 
     } catch (err) {
       console.error('Failed to load media:', err);
@@ -2957,6 +3927,56 @@ class WhatsAppClient {
     }
   }
 
+  // Play a Lottie sticker's animation in place of its placeholder
+  async playLottieSticker(messageId, container) {
+    const response = await fetch(`/api/media/${encodeURIComponent(messageId)}?format=lottie`);
+    if (!response.ok) {
+      throw new Error('Failed to load sticker');
+    }
+    const animationData = await response.json();
+    await this.loadLottiePlayer();
+
+    container.classList.remove('lazy-media');
+    container.innerHTML = '<div class="lottie-player"></div>';
+    window.lottie.loadAnimation({
+      container: container.firstElementChild,
+      renderer: 'svg',
+      loop: true,
+      autoplay: true,
+      animationData,
+    });
+  }
+
+  // Load the Lottie player the first time a Lottie sticker is played
+  loadLottiePlayer() {
+    if (window.lottie) {
+      return Promise.resolve();
+    }
+    if (!this.lottieLoading) {
+      this.lottieLoading = new Promise((resolve, reject) => {
+        const script = document.createElement('script');
+        script.src = 'https://unpkg.com/lottie-web@5.12.2/build/player/lottie_light.min.js';
+        script.onload = resolve;
+        script.onerror = () => {
+          this.lottieLoading = null;
+          reject(new Error('Failed to load the Lottie player'));
+        };
+        document.head.appendChild(script);
+      });
+    }
+    return this.lottieLoading;
+  }
+
+  // Record in the message cache that a view-once message has been opened
+  markViewOnceOpened(messageId) {
+    if (!this.currentContactId) return;
+    const messages = this.messages.get(this.currentContactId);
+    const message = messages && messages.find(m => m.id === messageId);
+    if (message && message.content) {
+      message.content.viewed_at = Date.now();
+    }
+  }
+
   // Update message cache with loaded media data
   updateMessageMediaCache(messageId, mediaData, mimeType) {
     // Find the message in the current contact's messages
@@ -3095,6 +4115,26 @@ class WhatsAppClient {
       // Populate form fields
       document.getElementById('language-override').value = settings.languageOverride || '';
       document.getElementById('translation-style').value = settings.translationStyle || '';
+      document.getElementById('learning-mode').checked = !!settings.learningMode;
+      document.getElementById('learning-notes').checked = !!settings.includeLearningNotes;
+      document.getElementById('muted').checked = !!settings.muted;
+      document.getElementById('triage').checked = !!settings.triageEnabled;
+      const outgoing = settings.outgoingTranslation;
+      const contactSetting = outgoing ? outgoing.contactSetting : null;
+      document.getElementById('outgoing-translation').value =
+        contactSetting === true ? 'on' : contactSetting === false ? 'off' : 'default';
+      document.getElementById('outgoing-translation-hint').textContent =
+        this.describeOutgoingTranslation(outgoing, settings.languageConfidence);
+      // A custom tone comes as {"custom": "..."}, the others as their name
+      const tone = settings.translationTone;
+      const customTone = tone && typeof tone === 'object' ? tone.custom : '';
+      document.getElementById('translation-tone').value =
+        customTone ? 'custom' : tone || 'default';
+      const customToneInput = document.getElementById('translation-tone-custom');
+      customToneInput.value = customTone || '';
+      customToneInput.classList.toggle('hidden', !customTone);
+      document.getElementById('timezone').value = settings.timezone || '';
+      document.getElementById('timezone-hint').textContent = this.describeRecipientClock(settings);
 
       // Show modal
       modal.classList.remove('hidden');
@@ -3104,6 +4144,37 @@ class WhatsAppClient {
     }
   }
 
+  // Explain whether my messages to this chat are translated right now
+  describeOutgoingTranslation(outgoing, confidence) {
+    if (!outgoing) return '';
+    const state = outgoing.enabled ? 'Currently translated' : 'Currently sent as typed';
+    switch (outgoing.reason) {
+      case 'contact_setting':
+        return `${state}: set for this chat.`;
+      case 'group_default':
+        return `${state}: the default for groups.`;
+      case 'language_override':
+        return `${state}: this chat has a language override.`;
+      default: {
+        if (!confidence || !confidence.language) {
+          return `${state}: no language detected in this chat yet.`;
+        }
+        const percent = Math.round(confidence.confidence * 100);
+        return `${state}: ${confidence.matching} of the last ${confidence.sampled} messages (${percent}%) are in ${confidence.language}; 70% is needed.`;
+      }
+    }
+  }
+
+  // Say what time it is for the contact, and where that comes from
+  describeRecipientClock(settings) {
+    if (!settings.localTime) {
+      return 'Not enough messages from them yet to guess their timezone.';
+    }
+    const source = settings.timezoneInferred ? 'guessed from when they write' : 'from the timezone set here';
+    const asleep = settings.likelyAsleep ? ' They\'re probably asleep.' : '';
+    return `It's ${settings.localTime} for them (${source}).${asleep}`;
+  }
+
   // Close settings modal
   closeSettingsModal() {
     const modal = document.getElementById('settings-modal');
@@ -3118,6 +4189,21 @@ class WhatsAppClient {
 
     const languageOverride = document.getElementById('language-override')?.value?.trim() || null;
     const translationStyle = document.getElementById('translation-style')?.value?.trim() || null;
+    const learningMode = !!document.getElementById('learning-mode')?.checked;
+    const includeLearningNotes = !!document.getElementById('learning-notes')?.checked;
+    const muted = !!document.getElementById('muted')?.checked;
+    // Matching the default for this kind of chat goes back to following it
+    const triageChecked = !!document.getElementById('triage')?.checked;
+    const triage = triageChecked === !this.currentContactId.endsWith('@g.us')
+      ? 'default'
+      : triageChecked ? 'on' : 'off';
+    const outgoingTranslation = document.getElementById('outgoing-translation')?.value || 'default';
+    let translationTone = document.getElementById('translation-tone')?.value || 'default';
+    if (translationTone === 'custom') {
+      const custom = document.getElementById('translation-tone-custom')?.value?.trim();
+      translationTone = custom ? { custom } : 'default';
+    }
+    const timezone = document.getElementById('timezone')?.value?.trim() || '';
 
     try {
       const response = await fetch(`/api/contacts/${encodeURIComponent(this.currentContactId)}/settings`, {
@@ -3128,12 +4214,20 @@ class WhatsAppClient {
         },
         body: JSON.stringify({
           languageOverride: languageOverride || null,
-          translationStyle: translationStyle || null
+          translationStyle: translationStyle || null,
+          learningMode,
+          includeLearningNotes,
+          muted,
+          triage,
+          outgoingTranslation,
+          translationTone,
+          timezone
         })
       });
 
       if (!response.ok) {
-        throw new Error('Failed to save settings');
+        const error = await response.json().catch(() => ({}));
+        throw new Error(error.error || 'Failed to save settings');
       }
 
       const result = await response.json();
@@ -3146,7 +4240,7 @@ class WhatsAppClient {
       // You could add a toast notification here
     } catch (err) {
       console.error('Failed to save conversation settings:', err);
-      alert('Failed to save settings. Please try again.');
+      alert(`${err.message}. Please try again.`);
     }
   }
 }
@@ -0,0 +1,34 @@
+// Service worker showing Web Push notifications for incoming messages
+
+self.addEventListener('push', (event) => {
+  if (!event.data) return;
+  const message = event.data.json();
+  const chat = message.chat || message.sender || 'WhatsApp';
+  const title = message.sender && message.sender !== chat ? `${message.sender} in ${chat}` : chat;
+  event.waitUntil(
+    self.registration.showNotification(title, {
+      body: message.preview,
+      tag: message.contactId,
+      renotify: true,
+      timestamp: message.timestamp,
+      data: { contactId: message.contactId }
+    })
+  );
+});
+
+// Open (or focus) the app on the notification's chat
+self.addEventListener('notificationclick', (event) => {
+  event.notification.close();
+  const contactId = event.notification.data?.contactId;
+  const url = contactId ? `/?chat=${encodeURIComponent(contactId)}` : '/';
+  event.waitUntil(
+    self.clients.matchAll({ type: 'window', includeUncontrolled: true }).then((windows) => {
+      const open = windows.find((w) => new URL(w.url).origin === self.location.origin);
+      if (open) {
+        open.postMessage({ type: 'open_chat', contactId });
+        return open.focus();
+      }
+      return self.clients.openWindow(url);
+    })
+  );
+});